@@ -0,0 +1,111 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::Write;
+
+use logger::{error, IncMetric, METRICS};
+use micro_http::{Request, Response};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use utils::time::{get_time_us, ClockType};
+
+/// Number of leading bytes of the SHA-256 of the request body kept in the audit log.
+const BODY_HASH_LEN: usize = 8;
+
+/// Records a line for every request served by the API server, holding its method, path,
+/// truncated body hash, response status and latency.
+pub struct AuditLog {
+    writer: Box<dyn Write + Send>,
+}
+
+impl AuditLog {
+    /// Creates an audit log writing its JSON lines to `writer`.
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        AuditLog { writer }
+    }
+
+    /// Appends the line describing `request`, which got `response` after `latency_us`.
+    pub(crate) fn record(&mut self, request: &Request, response: &Response, latency_us: u64) {
+        // The body itself isn't recorded since it may hold secrets, e.g. in MMDS.
+        let body_sha256 = request.body.as_ref().map(|body| {
+            Sha256::digest(body.raw())
+                .iter()
+                .take(BODY_HASH_LEN)
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        });
+        let line = json!({
+            "timestamp_us": get_time_us(ClockType::Real),
+            "method": format!("{:?}", request.method()),
+            "path": request.uri().get_abs_path(),
+            "body_sha256": body_sha256,
+            "status": String::from_utf8_lossy(response.status().raw()),
+            "latency_us": latency_us,
+        });
+
+        if let Err(e) = writeln!(self.writer, "{}", line).and_then(|()| self.writer.flush()) {
+            METRICS.api_server.audit_log_fails.inc();
+            error!("Failed to write to the audit log: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use micro_http::{StatusCode, Version};
+    use serde_json::Value;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let buffer = SharedBuffer::default();
+        let mut audit_log = AuditLog::new(Box::new(buffer.clone()));
+
+        let request = Request::try_from(
+            b"PUT /drives/rootfs HTTP/1.1\r\n\
+              Content-Type: application/json\r\n\
+              Content-Length: 2\r\n\r\n{}",
+            None,
+        )
+        .unwrap();
+        let response = Response::new(Version::Http11, StatusCode::NoContent);
+        audit_log.record(&request, &response, 42);
+
+        let request = Request::try_from(b"GET /machine-config HTTP/1.1\r\n\r\n", None).unwrap();
+        let response = Response::new(Version::Http11, StatusCode::BadRequest);
+        audit_log.record(&request, &response, 7);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["method"], "Put");
+        assert_eq!(lines[0]["path"], "/drives/rootfs");
+        // The first bytes of the SHA-256 of "{}".
+        assert_eq!(lines[0]["body_sha256"], "44136fa355b3678a");
+        assert_eq!(lines[0]["status"], "204");
+        assert_eq!(lines[0]["latency_us"], 42);
+        assert!(lines[0]["timestamp_us"].as_u64().unwrap() > 0);
+        assert_eq!(lines[1]["method"], "Get");
+        assert_eq!(lines[1]["body_sha256"], Value::Null);
+        assert_eq!(lines[1]["status"], "400");
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use logger::{debug, IncMetric, METRICS};
+use micro_http::{Body, MediaType, Method, Request, Response, StatusCode, Version};
+use sha2::{Digest, Sha256};
+use utils::time::{get_time_us, ClockType};
+
+/// Header identifying the attempts of a mutating request, so that its retries get the
+/// response of the first attempt instead of being applied again.
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Maximum length of an idempotency key.
+const MAX_KEY_LEN: usize = 255;
+/// Time during which the responses are replayed.
+const RETENTION_US: u64 = 5 * 60 * 1_000_000;
+/// Maximum number of cached responses, the oldest one being evicted to make room.
+const MAX_ENTRIES: usize = 128;
+
+// Response served for a key, along with what identifies the request it was served for.
+struct CachedResponse {
+    request_digest: Vec<u8>,
+    status: StatusCode,
+    body: Option<Body>,
+    content_type: MediaType,
+    served_at_us: u64,
+}
+
+/// Outcome of looking up the response served for the idempotency key of a request.
+pub(crate) enum Lookup {
+    /// The request isn't idempotent, it has no key or isn't a PUT or a PATCH.
+    NotApplicable,
+    /// The request was already served, with the returned response.
+    Replay(Response),
+    /// The request needs to be served, then its response stored under the returned key.
+    Serve(String),
+    /// The request can't be served.
+    Reject(Response),
+}
+
+/// Responses served for the PUT and PATCH requests carrying an `Idempotency-Key` header.
+#[derive(Default)]
+pub(crate) struct IdempotencyCache {
+    entries: HashMap<String, CachedResponse>,
+}
+
+impl IdempotencyCache {
+    /// Looks up the response served for the idempotency key of `request`.
+    pub(crate) fn lookup(&mut self, request: &Request) -> Lookup {
+        self.lookup_at(request, get_time_us(ClockType::Monotonic))
+    }
+
+    /// Stores `response`, served for `request` with idempotency `key`.
+    pub(crate) fn store(&mut self, key: String, request: &Request, response: &Response) {
+        self.store_at(key, request, response, get_time_us(ClockType::Monotonic))
+    }
+
+    fn lookup_at(&mut self, request: &Request, now_us: u64) -> Lookup {
+        if !matches!(request.method(), Method::Put | Method::Patch) {
+            return Lookup::NotApplicable;
+        }
+        let key = match request
+            .headers
+            .custom_entries()
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(IDEMPOTENCY_KEY_HEADER))
+        {
+            Some((_, key)) => key.trim(),
+            None => return Lookup::NotApplicable,
+        };
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Lookup::Reject(fault(format!(
+                "Invalid {} header: the key must have between 1 and {} characters.",
+                IDEMPOTENCY_KEY_HEADER, MAX_KEY_LEN
+            )));
+        }
+
+        self.evict_expired(now_us);
+        match self.entries.get(key) {
+            None => Lookup::Serve(key.to_string()),
+            Some(cached) if cached.request_digest == request_digest(request) => {
+                METRICS.api_server.idempotent_replays_count.inc();
+                debug!("Replaying the response for the idempotency key {}.", key);
+                let mut response = Response::new(Version::Http11, cached.status);
+                if let Some(body) = cached.body.as_ref() {
+                    response.set_body(body.clone());
+                }
+                response.set_content_type(cached.content_type);
+                Lookup::Replay(response)
+            }
+            Some(_) => Lookup::Reject(fault(format!(
+                "The {} header was already used for a different request.",
+                IDEMPOTENCY_KEY_HEADER
+            ))),
+        }
+    }
+
+    fn store_at(&mut self, key: String, request: &Request, response: &Response, now_us: u64) {
+        if self.entries.len() >= MAX_ENTRIES {
+            let oldest_key = self
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.served_at_us)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest_key) = oldest_key {
+                self.entries.remove(&oldest_key);
+            }
+        }
+        self.entries.insert(
+            key,
+            CachedResponse {
+                request_digest: request_digest(request),
+                status: response.status(),
+                body: response.body(),
+                content_type: response.content_type(),
+                served_at_us: now_us,
+            },
+        );
+    }
+
+    fn evict_expired(&mut self, now_us: u64) {
+        self.entries
+            .retain(|_, cached| now_us.saturating_sub(cached.served_at_us) < RETENTION_US);
+    }
+}
+
+// Identifies a request by its method, URI and body.
+fn request_digest(request: &Request) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{:?} {}\n",
+        request.method(),
+        request.uri().get_abs_path()
+    ));
+    if let Some(body) = request.body.as_ref() {
+        hasher.update(body.raw());
+    }
+    hasher.finalize().to_vec()
+}
+
+fn fault(msg: String) -> Response {
+    crate::ApiServer::json_response(
+        StatusCode::BadRequest,
+        serde_json::json!({ "fault_message": msg }).to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, key: Option<&str>, body: &str) -> Request {
+        let key_header = key
+            .map(|key| format!("Idempotency-Key: {}\r\n", key))
+            .unwrap_or_default();
+        let raw = format!(
+            "{} /network-interfaces/eth0 HTTP/1.1\r\n{}Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            method,
+            key_header,
+            body.len(),
+            body
+        );
+        Request::try_from(raw.as_bytes(), None).unwrap()
+    }
+
+    #[test]
+    fn test_idempotency_cache() {
+        let mut cache = IdempotencyCache::default();
+        let response = fault("The MAC address is already in use.".to_string());
+
+        // Requests without a key, or which don't mutate anything, aren't cached.
+        assert!(matches!(
+            cache.lookup_at(&request("PUT", None, "{}"), 0),
+            Lookup::NotApplicable
+        ));
+        assert!(matches!(
+            cache.lookup_at(
+                &Request::try_from(b"GET / HTTP/1.1\r\nIdempotency-Key: a\r\n\r\n", None).unwrap(),
+                0
+            ),
+            Lookup::NotApplicable
+        ));
+        assert!(matches!(
+            cache.lookup_at(&request("PUT", Some(&"a".repeat(256)), "{}"), 0),
+            Lookup::Reject(_)
+        ));
+
+        let first = request("PUT", Some("retry-1"), "{}");
+        let key = match cache.lookup_at(&first, 0) {
+            Lookup::Serve(key) => key,
+            _ => panic!("Test failed."),
+        };
+        assert_eq!(key, "retry-1");
+        cache.store_at(key, &first, &response, 0);
+
+        // A retry gets the same response.
+        match cache.lookup_at(&request("PUT", Some("retry-1"), "{}"), 1) {
+            Lookup::Replay(replayed) => {
+                assert_eq!(replayed.status(), StatusCode::BadRequest);
+                assert_eq!(replayed.body(), response.body());
+            }
+            _ => panic!("Test failed."),
+        }
+        // A different request can't reuse the key.
+        assert!(matches!(
+            cache.lookup_at(&request("PATCH", Some("retry-1"), "{}"), 1),
+            Lookup::Reject(_)
+        ));
+        assert!(matches!(
+            cache.lookup_at(&request("PUT", Some("retry-1"), "{ }"), 1),
+            Lookup::Reject(_)
+        ));
+        // The response is only replayed for a while.
+        assert!(matches!(
+            cache.lookup_at(&request("PUT", Some("retry-1"), "{}"), RETENTION_US),
+            Lookup::Serve(_)
+        ));
+
+        // The oldest response is evicted when the cache is full.
+        for index in 0..=MAX_ENTRIES {
+            let key = format!("key-{}", index);
+            cache.store_at(
+                key.clone(),
+                &request("PUT", Some(&key), "{}"),
+                &response,
+                index as u64,
+            );
+        }
+        assert_eq!(cache.entries.len(), MAX_ENTRIES);
+        assert!(!cache.entries.contains_key("key-0"));
+        assert!(cache.entries.contains_key(&format!("key-{}", MAX_ENTRIES)));
+    }
+}
@@ -6,27 +6,43 @@
 //! and responding to the user.
 //! It is constructed on top of an HTTP Server that uses Unix Domain Sockets and `EPOLL` to
 //! handle multiple connections on the same thread.
+mod audit;
+mod idempotency;
+mod operations;
 mod parsed_request;
 mod request;
+mod socket;
+mod vsock_forwarder;
 
+use std::os::unix::net::UnixDatagram;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::{fmt, io};
 
 use logger::{
-    debug, error, info, update_metric_with_elapsed_time, warn, ProcessTimeReporter, METRICS,
+    debug, error, info, update_metric_with_elapsed_time, warn, IncMetric, ProcessTimeReporter,
+    SharedStoreMetric, METRICS,
 };
+use micro_http::MediaType;
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, ServerError, ServerRequest,
     ServerResponse, StatusCode, Version,
 };
+use rate_limiter::{BucketReduction, TokenBucket};
 use seccompiler::BpfProgramRef;
 use serde_json::json;
 use utils::eventfd::EventFd;
+use vmm::health::HEALTH;
+use vmm::lifecycle_events::LIFECYCLE_EVENTS;
 use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
 use vmm::vmm_config::snapshot::SnapshotType;
 
+pub use crate::audit::AuditLog;
+use crate::idempotency::{IdempotencyCache, Lookup};
+use crate::operations::Operations;
 use crate::parsed_request::{ParsedRequest, RequestAction};
+pub use crate::socket::SocketPermissions;
+pub use crate::vsock_forwarder::VsockForwarder;
 
 /// Shorthand type for a request containing a boxed VmmAction.
 pub type ApiRequest = Box<VmmAction>;
@@ -72,6 +88,20 @@ pub struct ApiServer {
     to_vmm_fd: EventFd,
     /// If this flag is set, the API thread will go down.
     shutdown_flag: bool,
+    /// Optional log where every served request is recorded.
+    audit_log: Option<AuditLog>,
+    /// Permissions given to the socket when binding to it.
+    socket_permissions: SocketPermissions,
+    /// Whether the metrics can be scraped with `GET /metrics`.
+    metrics_endpoint: bool,
+    /// Responses replayed for the retries of requests with an `Idempotency-Key`.
+    idempotency_cache: IdempotencyCache,
+    /// Optional limit on the rate of the requests forwarded to the VMM.
+    request_rate_limiter: Option<TokenBucket>,
+    /// Requests forwarded to the VMM without waiting for their outcome.
+    operations: Operations,
+    /// Optional sd_notify socket, told when the API is up.
+    ready_notifier: Option<UnixDatagram>,
 }
 
 impl ApiServer {
@@ -88,9 +118,43 @@ impl ApiServer {
             vmm_response_receiver,
             to_vmm_fd,
             shutdown_flag: false,
+            audit_log: None,
+            socket_permissions: SocketPermissions::default(),
+            metrics_endpoint: false,
+            idempotency_cache: IdempotencyCache::default(),
+            request_rate_limiter: None,
+            operations: Operations::default(),
+            ready_notifier: None,
         }
     }
 
+    /// Rejects the requests forwarded to the VMM beyond `requests_per_second`, so that a
+    /// flood of requests can't keep the VMM thread busy. A limit of 0 disables it.
+    pub fn set_request_rate_limit(&mut self, requests_per_second: u64) {
+        self.request_rate_limiter = TokenBucket::new(requests_per_second, 0, 1000);
+    }
+
+    /// Serves the metrics in the Prometheus text exposition format on `GET /metrics`.
+    pub fn enable_metrics_endpoint(&mut self) {
+        self.metrics_endpoint = true;
+    }
+
+    /// Gives `permissions` to the socket created by `bind_and_run`.
+    pub fn set_socket_permissions(&mut self, permissions: SocketPermissions) {
+        self.socket_permissions = permissions;
+    }
+
+    /// Sends `READY=1` through `notifier`, following the sd_notify protocol, once
+    /// `bind_and_run` is bound to its socket.
+    pub fn set_ready_notifier(&mut self, notifier: UnixDatagram) {
+        self.ready_notifier = Some(notifier);
+    }
+
+    /// Records every request served from now on in `audit_log`.
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.audit_log = Some(audit_log);
+    }
+
     /// Starts the HTTP Server by binding to the socket path provided as
     /// an argument.
     ///
@@ -170,7 +234,7 @@ impl ApiServer {
         api_payload_limit: usize,
         socket_ready: mpsc::Sender<bool>,
     ) -> Result<()> {
-        let mut server = HttpServer::new(path).unwrap_or_else(|e| {
+        let mut server = socket::bind(&path, self.socket_permissions).unwrap_or_else(|e| {
             error!("Error creating the HTTP server: {}", e);
             std::process::exit(vmm::FcExitCode::GenericError as i32);
         });
@@ -180,6 +244,12 @@ impl ApiServer {
         socket_ready
             .send(true)
             .expect("No one to signal that the socket path is ready!");
+        // Also tell the service manager, if any. The socket is closed afterwards.
+        if let Some(notifier) = self.ready_notifier.take() {
+            if let Err(e) = notifier.send(b"READY=1") {
+                warn!("Failed to notify the service manager: {}", e);
+            }
+        }
         // Set the api payload size limit.
         server.set_payload_max_size(api_payload_limit);
 
@@ -245,12 +315,47 @@ impl ApiServer {
         request: &Request,
         request_processing_start_us: u64,
     ) -> Response {
+        let response = match self.idempotency_cache.lookup(request) {
+            Lookup::NotApplicable => self.serve_request(request, request_processing_start_us),
+            Lookup::Serve(key) => {
+                let response = self.serve_request(request, request_processing_start_us);
+                self.idempotency_cache.store(key, request, &response);
+                response
+            }
+            Lookup::Replay(response) | Lookup::Reject(response) => response,
+        };
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            let latency_us = utils::time::get_time_us(utils::time::ClockType::Monotonic)
+                - request_processing_start_us;
+            audit_log.record(request, &response, latency_us);
+        }
+        response
+    }
+
+    fn serve_request(&mut self, request: &Request, request_processing_start_us: u64) -> Response {
         match ParsedRequest::try_from_request(request).map(|r| r.into_parts()) {
             Ok((req_action, mut parsing_info)) => {
                 let mut response = match req_action {
+                    RequestAction::Sync(_) | RequestAction::Async(_) if !self.request_allowed() => {
+                        METRICS.api_server.throttled_requests_count.inc();
+                        let msg = "Too many API requests, retry later.";
+                        warn!("{}", msg);
+                        ApiServer::json_response(
+                            StatusCode::BadRequest,
+                            ApiServer::json_fault_message(msg),
+                        )
+                    }
                     RequestAction::Sync(vmm_action) => {
                         self.serve_vmm_action_request(vmm_action, request_processing_start_us)
                     }
+                    RequestAction::Async(vmm_action) => self.serve_async_request(vmm_action),
+                    RequestAction::GetOperation(id) => self.serve_operation_request(&id),
+                    RequestAction::ScrapeMetrics => self.serve_metrics_request(),
+                    RequestAction::CheckHealth => self.serve_health_request(false),
+                    RequestAction::CheckReadiness => self.serve_health_request(true),
+                    RequestAction::GetLifecycleEvents(since) => {
+                        self.serve_lifecycle_events_request(since)
+                    }
                     RequestAction::ShutdownInternal => {
                         self.shutdown_flag = true;
                         Response::new(Version::Http11, StatusCode::NoContent)
@@ -269,34 +374,80 @@ impl ApiServer {
         }
     }
 
+    // The requests served by the API thread alone aren't limited, they can't reach the VMM
+    // thread.
+    fn request_allowed(&mut self) -> bool {
+        match self.request_rate_limiter.as_mut() {
+            Some(bucket) => bucket.reduce(1) == BucketReduction::Success,
+            None => true,
+        }
+    }
+
+    fn serve_metrics_request(&self) -> Response {
+        let result = if self.metrics_endpoint {
+            METRICS
+                .scrape()
+                .map_err(|e| (StatusCode::InternalServerError, e.to_string()))
+        } else {
+            Err((
+                StatusCode::BadRequest,
+                "The metrics endpoint is disabled. Start Firecracker with --metrics-endpoint to \
+                 enable it."
+                    .to_string(),
+            ))
+        };
+
+        match result {
+            Ok(metrics) => {
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                response.set_content_type(MediaType::PlainText);
+                response.set_body(Body::new(metrics));
+                response
+            }
+            Err((status, msg)) => {
+                error!("Failed to scrape the metrics: {}", msg);
+                ApiServer::json_response(status, ApiServer::json_fault_message(msg))
+            }
+        }
+    }
+
+    // Answers without involving the VMM thread, which keeps the checks working when it is stuck.
+    fn serve_health_request(&self, readiness: bool) -> Response {
+        let report = HEALTH.report();
+        let passed = if readiness {
+            report.is_ready()
+        } else {
+            report.is_healthy()
+        };
+        let status = if passed {
+            StatusCode::OK
+        } else {
+            StatusCode::InternalServerError
+        };
+        ApiServer::json_response(
+            status,
+            serde_json::to_string(&report).expect("Failed to serialize the health report"),
+        )
+    }
+
+    // Returns right away with the events recorded after `since`, clients poll again with the
+    // `next_since` cursor of the response.
+    fn serve_lifecycle_events_request(&self, since: u64) -> Response {
+        ApiServer::json_response(
+            StatusCode::OK,
+            serde_json::to_string(&LIFECYCLE_EVENTS.since(since))
+                .expect("Failed to serialize the lifecycle events"),
+        )
+    }
+
     fn serve_vmm_action_request(
         &mut self,
         vmm_action: Box<VmmAction>,
         request_processing_start_us: u64,
     ) -> Response {
-        let metric_with_action = match *vmm_action {
-            VmmAction::CreateSnapshot(ref params) => match params.snapshot_type {
-                SnapshotType::Full => Some((
-                    &METRICS.latencies_us.full_create_snapshot,
-                    "create full snapshot",
-                )),
-                SnapshotType::Diff => Some((
-                    &METRICS.latencies_us.diff_create_snapshot,
-                    "create diff snapshot",
-                )),
-            },
-            VmmAction::LoadSnapshot(_) => {
-                Some((&METRICS.latencies_us.load_snapshot, "load snapshot"))
-            }
-            VmmAction::Pause => Some((&METRICS.latencies_us.pause_vm, "pause vm")),
-            VmmAction::Resume => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
-            _ => None,
-        };
+        let metric_with_action = latency_metric(&vmm_action);
 
-        self.api_request_sender
-            .send(vmm_action)
-            .expect("Failed to send VMM message");
-        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
+        self.send_to_vmm(vmm_action);
         let vmm_outcome = *(self.vmm_response_receiver.recv().expect("VMM disconnected"));
         let response = ParsedRequest::convert_to_response(&vmm_outcome);
 
@@ -310,6 +461,49 @@ impl ApiServer {
         response
     }
 
+    // Answers with the ID of the operation, whose outcome gets recorded when the VMM responds.
+    fn serve_async_request(&mut self, vmm_action: Box<VmmAction>) -> Response {
+        let (action, metric) = match latency_metric(&vmm_action) {
+            Some((metric, action)) => (action, Some(metric)),
+            None => ("operation", None),
+        };
+        self.send_to_vmm(vmm_action);
+        let id = self.operations.start(action, metric);
+        ApiServer::json_response(StatusCode::OK, json!({ "operation_id": id }).to_string())
+    }
+
+    fn serve_operation_request(&mut self, id: &str) -> Response {
+        // Record the outcome of the pending operation, if the VMM is done with it.
+        if self.operations.is_pending() {
+            if let Ok(outcome) = self.vmm_response_receiver.try_recv() {
+                self.operations.complete(&outcome);
+            }
+        }
+        match self.operations.status(id) {
+            Some(status) => ApiServer::json_response(
+                StatusCode::OK,
+                serde_json::to_string(&status).expect("Failed to serialize the operation status"),
+            ),
+            None => ApiServer::json_response(
+                StatusCode::NotFound,
+                ApiServer::json_fault_message(format!("Unknown operation: {}.", id)),
+            ),
+        }
+    }
+
+    // The VMM handles one request at a time, so the pending operation has to complete first,
+    // for its response not to be mistaken for the one of `vmm_action`.
+    fn send_to_vmm(&mut self, vmm_action: Box<VmmAction>) {
+        if self.operations.is_pending() {
+            let outcome = *(self.vmm_response_receiver.recv().expect("VMM disconnected"));
+            self.operations.complete(&outcome);
+        }
+        self.api_request_sender
+            .send(vmm_action)
+            .expect("Failed to send VMM message");
+        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
+    }
+
     /// An HTTP response which also includes a body.
     pub(crate) fn json_response<T: Into<String>>(status: StatusCode, body: T) -> Response {
         let mut response = Response::new(Version::Http11, status);
@@ -322,6 +516,35 @@ impl ApiServer {
     }
 }
 
+// Returns the metric measuring the latency of `vmm_action`, along with its description.
+fn latency_metric(vmm_action: &VmmAction) -> Option<(&'static SharedStoreMetric, &'static str)> {
+    match *vmm_action {
+        VmmAction::CreateSnapshot(ref params) => match params.snapshot_type {
+            SnapshotType::Full => Some((
+                &METRICS.latencies_us.full_create_snapshot,
+                "create full snapshot",
+            )),
+            SnapshotType::Diff => Some((
+                &METRICS.latencies_us.diff_create_snapshot,
+                "create diff snapshot",
+            )),
+        },
+        VmmAction::LoadSnapshot(_) => Some((&METRICS.latencies_us.load_snapshot, "load snapshot")),
+        VmmAction::MergeSnapshot(_) => {
+            Some((&METRICS.latencies_us.merge_snapshot, "merge snapshot"))
+        }
+        VmmAction::Pause => Some((&METRICS.latencies_us.pause_vm, "pause vm")),
+        VmmAction::ReceiveMigration(_) => {
+            Some((&METRICS.latencies_us.receive_migration, "receive migration"))
+        }
+        VmmAction::Resume => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
+        VmmAction::SendMigration(_) => {
+            Some((&METRICS.latencies_us.send_migration, "send migration"))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Write};
@@ -400,7 +623,12 @@ mod tests {
             Box::new(VmmAction::CreateSnapshot(CreateSnapshotParams {
                 snapshot_type: SnapshotType::Diff,
                 snapshot_path: PathBuf::new(),
+                snapshot_fd: None,
                 mem_file_path: PathBuf::new(),
+                mem_file_fd: None,
+                compression: None,
+                mem_writer_threads: None,
+                exclude_mmds: false,
                 version: None,
             })),
             start_time_us,
@@ -414,7 +642,12 @@ mod tests {
             Box::new(VmmAction::CreateSnapshot(CreateSnapshotParams {
                 snapshot_type: SnapshotType::Diff,
                 snapshot_path: PathBuf::new(),
+                snapshot_fd: None,
                 mem_file_path: PathBuf::new(),
+                mem_file_fd: None,
+                compression: None,
+                mem_writer_threads: None,
+                exclude_mmds: false,
                 version: None,
             })),
             start_time_us,
@@ -424,6 +657,166 @@ mod tests {
         assert_eq!(METRICS.latencies_us.full_create_snapshot.fetch(), 0);
     }
 
+    #[test]
+    fn test_serve_async_request() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let mut send = |raw: &[u8]| {
+            sender.write_all(raw).unwrap();
+            assert!(connection.try_read().is_ok());
+            connection.pop_parsed_request().unwrap()
+        };
+        let body = "{ \"snapshot_path\": \"foo\", \"mem_backend\": { \"backend_path\": \"bar\", \
+                    \"backend_type\": \"File\" } }";
+        let load_snapshot = format!(
+            "PUT /snapshot/load HTTP/1.1\r\n\
+             Prefer: respond-async\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let body_of = |response: &Response| -> serde_json::Value {
+            serde_json::from_slice(response.body().unwrap().raw()).unwrap()
+        };
+
+        // The operation ID is returned before the VMM is done.
+        let response = api_server.handle_request(&send(load_snapshot.as_bytes()), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+        let id = body_of(&response)["operation_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(from_api.try_recv().is_ok());
+
+        let get_operation = format!("GET /operations/{} HTTP/1.1\r\n\r\n", id);
+        let response = api_server.handle_request(&send(get_operation.as_bytes()), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_of(&response)["state"], "running");
+
+        to_api
+            .send(Box::new(Err(VmmActionError::OperationNotSupportedPreBoot)))
+            .unwrap();
+        let response = api_server.handle_request(&send(get_operation.as_bytes()), 0);
+        assert_eq!(body_of(&response)["state"], "failed");
+        assert_eq!(
+            body_of(&response)["fault_message"],
+            VmmActionError::OperationNotSupportedPreBoot.to_string()
+        );
+
+        // A synchronous request waits for the pending operation to complete.
+        let response = api_server.handle_request(&send(load_snapshot.as_bytes()), 0);
+        let id = body_of(&response)["operation_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        to_api
+            .send(Box::new(Err(VmmActionError::OperationNotSupportedPreBoot)))
+            .unwrap();
+        to_api
+            .send(Box::new(Ok(VmmData::InstanceInformation(
+                InstanceInfo::default(),
+            ))))
+            .unwrap();
+        let response = api_server.handle_request(&send(b"GET / HTTP/1.1\r\n\r\n"), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+        let get_operation = format!("GET /operations/{} HTTP/1.1\r\n\r\n", id);
+        let response = api_server.handle_request(&send(get_operation.as_bytes()), 0);
+        assert_eq!(body_of(&response)["state"], "failed");
+
+        let response =
+            api_server.handle_request(&send(b"GET /operations/op_42 HTTP/1.1\r\n\r\n"), 0);
+        assert_eq!(response.status(), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_serve_metrics_request() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let mut get_metrics = || {
+            sender.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+            assert!(connection.try_read().is_ok());
+            connection.pop_parsed_request().unwrap()
+        };
+
+        // The endpoint is disabled by default.
+        let response = api_server.handle_request(&get_metrics(), 0);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+
+        api_server.enable_metrics_endpoint();
+        let response = api_server.handle_request(&get_metrics(), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.content_type(), MediaType::PlainText);
+        let body = String::from_utf8(response.body().unwrap().raw().to_vec()).unwrap();
+        assert!(body.contains("# TYPE firecracker_get_api_requests_metrics_count_total counter\n"));
+    }
+
+    #[test]
+    fn test_serve_health_request() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let mut get = |path: &str| {
+            sender
+                .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+                .unwrap();
+            assert!(connection.try_read().is_ok());
+            connection.pop_parsed_request().unwrap()
+        };
+
+        // The VMM thread isn't involved, and the microVM isn't running.
+        let response = api_server.handle_request(&get("/healthz"), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(response.body().unwrap().raw().to_vec()).unwrap();
+        assert_eq!(
+            body,
+            "{\"event_loop\":true,\"vcpus\":true,\"running\":false}"
+        );
+        let response = api_server.handle_request(&get("/readyz"), 0);
+        assert_eq!(response.status(), StatusCode::InternalServerError);
+    }
+
+    #[test]
+    fn test_serve_lifecycle_events_request() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let mut get = |path: &str| {
+            sender
+                .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+                .unwrap();
+            assert!(connection.try_read().is_ok());
+            connection.pop_parsed_request().unwrap()
+        };
+
+        // No microVM was started, so nothing was recorded.
+        let response = api_server.handle_request(&get("/events?since=0"), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(response.body().unwrap().raw().to_vec()).unwrap();
+        assert_eq!(body, "{\"events\":[],\"next_since\":0,\"missed\":0}");
+
+        let response = api_server.handle_request(&get("/events?since=x"), 0);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+    }
+
     #[test]
     fn test_handle_request() {
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -476,6 +869,83 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BadRequest);
     }
 
+    #[test]
+    fn test_handle_idempotent_request() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let mut put_actions = || {
+            sender
+                .write_all(
+                    b"PUT /actions HTTP/1.1\r\n\
+                    Idempotency-Key: flush-1\r\n\
+                    Content-Type: application/json\r\n\
+                    Content-Length: 33\r\n\r\n{ \
+                    \"action_type\": \"FlushMetrics\" \
+                    }",
+                )
+                .unwrap();
+            assert!(connection.try_read().is_ok());
+            connection.pop_parsed_request().unwrap()
+        };
+
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        let response = api_server.handle_request(&put_actions(), 0);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert!(from_api.try_recv().is_ok());
+
+        // The retry gets the same response, without reaching the VMM.
+        let response = api_server.handle_request(&put_actions(), 0);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert!(from_api.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_request_rate_limit() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+        api_server.set_request_rate_limit(1);
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let mut get = |path: &str| {
+            sender
+                .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+                .unwrap();
+            assert!(connection.try_read().is_ok());
+            connection.pop_parsed_request().unwrap()
+        };
+
+        to_api
+            .send(Box::new(Ok(VmmData::InstanceInformation(
+                InstanceInfo::default(),
+            ))))
+            .unwrap();
+        let response = api_server.handle_request(&get("/"), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(from_api.try_recv().is_ok());
+
+        // The next request is rejected without reaching the VMM.
+        let throttled_count = METRICS.api_server.throttled_requests_count.count();
+        let response = api_server.handle_request(&get("/"), 0);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert!(from_api.try_recv().is_err());
+        assert_eq!(
+            METRICS.api_server.throttled_requests_count.count(),
+            throttled_count + 1
+        );
+
+        // The requests served by the API thread alone aren't limited.
+        let response = api_server.handle_request(&get("/healthz"), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
     #[test]
     fn test_bind_and_run() {
         let mut tmp_socket = TempFile::new().unwrap();
@@ -524,6 +994,43 @@ mod tests {
         assert!(sock.read(&mut buf[..]).unwrap() > 0);
     }
 
+    #[test]
+    fn test_bind_and_run_with_ready_notifier() {
+        let mut tmp_socket = TempFile::new().unwrap();
+        tmp_socket.remove().unwrap();
+        let path_to_socket = tmp_socket.as_path().to_str().unwrap().to_owned();
+
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let seccomp_filters = get_filters(SeccompConfig::Advanced).unwrap();
+        let (socket_ready_sender, socket_ready_receiver) = channel();
+        let (notifier, service_manager) = UnixDatagram::pair().unwrap();
+
+        thread::Builder::new()
+            .name("fc_api_test".to_owned())
+            .spawn(move || {
+                let mut api_server =
+                    ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+                api_server.set_ready_notifier(notifier);
+                api_server
+                    .bind_and_run(
+                        PathBuf::from(path_to_socket),
+                        ProcessTimeReporter::new(Some(1), Some(1), Some(1)),
+                        seccomp_filters.get("api").unwrap(),
+                        vmm::HTTP_MAX_PAYLOAD_SIZE,
+                        socket_ready_sender,
+                    )
+                    .unwrap();
+            })
+            .unwrap();
+
+        socket_ready_receiver.recv().unwrap();
+        let mut buf = [0u8; 16];
+        let len = service_manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+    }
+
     #[test]
     fn test_bind_and_run_with_limit() {
         let mut tmp_socket = TempFile::new().unwrap();
@@ -0,0 +1,183 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::VecDeque;
+
+use logger::{info, update_metric_with_elapsed_time, SharedStoreMetric};
+use serde::Serialize;
+use utils::time::{get_time_us, ClockType};
+use vmm::rpc_interface::{VmmActionError, VmmData};
+
+/// Number of completed operations whose outcome is kept.
+const MAX_COMPLETED_OPERATIONS: usize = 16;
+
+/// Status of an asynchronous operation.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OperationState {
+    /// The VMM is carrying out the operation.
+    Running,
+    /// The operation completed successfully.
+    Succeeded,
+    /// The operation failed.
+    Failed,
+}
+
+/// Description of an asynchronous operation, returned by `GET /operations/{id}`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct OperationStatus {
+    /// The ID of the operation.
+    pub id: String,
+    /// What the operation does.
+    pub action: &'static str,
+    /// The status of the operation.
+    pub state: OperationState,
+    /// The time spent on the operation so far, or until it completed.
+    pub elapsed_us: u64,
+    /// Why the operation failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fault_message: Option<String>,
+}
+
+// The operation currently carried out by the VMM.
+struct PendingOperation {
+    id: String,
+    action: &'static str,
+    started_us: u64,
+    latency_metric: Option<&'static SharedStoreMetric>,
+}
+
+/// Tracks the actions forwarded to the VMM without waiting for their outcome.
+///
+/// The VMM handles one request at a time, so there is at most one operation pending, whose
+/// outcome is the next response of the VMM.
+#[derive(Default)]
+pub(crate) struct Operations {
+    next_id: u64,
+    pending: Option<PendingOperation>,
+    completed: VecDeque<OperationStatus>,
+}
+
+impl Operations {
+    /// Whether an operation is waiting for the response of the VMM.
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Records the start of the `action` operation, and returns its ID. The `latency_metric`
+    /// gets the duration of the operation once it succeeds.
+    pub(crate) fn start(
+        &mut self,
+        action: &'static str,
+        latency_metric: Option<&'static SharedStoreMetric>,
+    ) -> String {
+        self.next_id += 1;
+        let id = format!("op_{}", self.next_id);
+        self.pending = Some(PendingOperation {
+            id: id.clone(),
+            action,
+            started_us: get_time_us(ClockType::Monotonic),
+            latency_metric,
+        });
+        id
+    }
+
+    /// Records the `outcome` of the pending operation.
+    pub(crate) fn complete(&mut self, outcome: &Result<VmmData, VmmActionError>) {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let (state, fault_message) = match outcome {
+            Ok(_) => (OperationState::Succeeded, None),
+            Err(e) => (OperationState::Failed, Some(e.to_string())),
+        };
+        let elapsed_us = match pending.latency_metric {
+            Some(metric) if state == OperationState::Succeeded => {
+                update_metric_with_elapsed_time(metric, pending.started_us)
+            }
+            _ => get_time_us(ClockType::Monotonic) - pending.started_us,
+        };
+        info!(
+            "The '{}' operation {} completed in {} us.",
+            pending.action, pending.id, elapsed_us
+        );
+
+        if self.completed.len() == MAX_COMPLETED_OPERATIONS {
+            self.completed.pop_front();
+        }
+        self.completed.push_back(OperationStatus {
+            id: pending.id,
+            action: pending.action,
+            state,
+            elapsed_us,
+            fault_message,
+        });
+    }
+
+    /// Returns the status of the operation with `id`, if it is pending or among the last
+    /// completed ones.
+    pub(crate) fn status(&self, id: &str) -> Option<OperationStatus> {
+        if let Some(pending) = self.pending.as_ref().filter(|pending| pending.id == id) {
+            return Some(OperationStatus {
+                id: pending.id.clone(),
+                action: pending.action,
+                state: OperationState::Running,
+                elapsed_us: get_time_us(ClockType::Monotonic) - pending.started_us,
+                fault_message: None,
+            });
+        }
+        self.completed
+            .iter()
+            .find(|completed| completed.id == id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operations() {
+        let mut operations = Operations::default();
+        assert!(!operations.is_pending());
+        assert_eq!(operations.status("op_1"), None);
+
+        let id = operations.start("create snapshot", None);
+        assert_eq!(id, "op_1");
+        assert!(operations.is_pending());
+        let status = operations.status(&id).unwrap();
+        assert_eq!(status.action, "create snapshot");
+        assert_eq!(status.state, OperationState::Running);
+
+        operations.complete(&Ok(VmmData::Empty));
+        assert!(!operations.is_pending());
+        assert_eq!(
+            operations.status(&id).unwrap().state,
+            OperationState::Succeeded
+        );
+
+        let id = operations.start("load snapshot", None);
+        assert_eq!(id, "op_2");
+        operations.complete(&Err(VmmActionError::OperationNotSupportedPreBoot));
+        let status = operations.status(&id).unwrap();
+        assert_eq!(status.state, OperationState::Failed);
+        assert_eq!(
+            status.fault_message.unwrap(),
+            VmmActionError::OperationNotSupportedPreBoot.to_string()
+        );
+        assert_eq!(
+            serde_json::to_value(operations.status("op_1").unwrap()).unwrap()["state"],
+            "succeeded"
+        );
+
+        // Only the last operations are kept.
+        for _ in 0..MAX_COMPLETED_OPERATIONS {
+            operations.start("create snapshot", None);
+            operations.complete(&Ok(VmmData::Empty));
+        }
+        assert_eq!(operations.status("op_2"), None);
+        assert!(operations.status("op_3").is_some());
+    }
+}
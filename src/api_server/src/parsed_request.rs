@@ -9,24 +9,54 @@ use vmm::rpc_interface::{VmmAction, VmmActionError};
 
 use super::VmmData;
 use crate::request::actions::parse_put_actions;
-use crate::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
+use crate::request::balloon::{
+    parse_delete_balloon, parse_get_balloon, parse_patch_balloon, parse_put_balloon,
+};
 use crate::request::boot_source::parse_put_boot_source;
-use crate::request::drive::{parse_patch_drive, parse_put_drive};
+use crate::request::drive::{
+    parse_delete_drive, parse_patch_drive, parse_put_drive, parse_put_drive_trace,
+};
+use crate::request::events::parse_get_events;
+use crate::request::health::{parse_get_healthz, parse_get_readyz};
 use crate::request::instance_info::parse_get_instance_info;
 use crate::request::logger::parse_put_logger;
 use crate::request::machine_configuration::{
     parse_get_machine_config, parse_patch_machine_config, parse_put_machine_config,
 };
-use crate::request::metrics::parse_put_metrics;
+use crate::request::memory_hotplug::{
+    parse_get_memory_hotplug, parse_patch_memory_hotplug, parse_put_memory_hotplug,
+};
+use crate::request::metrics::{parse_get_metrics, parse_put_metrics};
+use crate::request::migration::parse_put_migration;
 use crate::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
-use crate::request::net::{parse_patch_net, parse_put_net};
-use crate::request::snapshot::{parse_patch_vm_state, parse_put_snapshot};
+use crate::request::net::{
+    parse_delete_net, parse_get_net, parse_patch_net, parse_put_net, parse_put_net_capture,
+};
+use crate::request::operations::parse_get_operation;
+use crate::request::pmem::parse_put_pmem;
+use crate::request::rate_limiter_group::parse_put_rate_limiter_group;
+use crate::request::snapshot::{
+    parse_delete_snapshot, parse_get_snapshot_info, parse_patch_vm_state, parse_put_snapshot,
+};
 use crate::request::version::parse_get_version;
-use crate::request::vsock::parse_put_vsock;
+use crate::request::vm_config::parse_put_vm_config;
+use crate::request::vsock::{parse_get_vsock, parse_put_vsock, parse_put_vsock_connections};
 use crate::ApiServer;
 
 pub(crate) enum RequestAction {
     Sync(Box<VmmAction>),
+    // Forwarded to the VMM without waiting for its outcome, which is polled with
+    // `GetOperation`.
+    Async(Box<VmmAction>),
+    // Served by the API thread, which keeps track of the asynchronous operations.
+    GetOperation(String),
+    // Served by the API thread, which renders the metrics without involving the VMM.
+    ScrapeMetrics,
+    // Served by the API thread, which must keep answering when the VMM thread is stuck.
+    CheckHealth,
+    CheckReadiness,
+    // Served by the API thread from the log of lifecycle events, after the given cursor.
+    GetLifecycleEvents(u64),
     ShutdownInternal, // !!! not an API, used by shutdown to thread::join the API thread
 }
 
@@ -78,6 +108,36 @@ impl ParsedRequest {
             request.body.as_ref(),
         ));
 
+        // The query string only holds the `validate_only` parameter, which doesn't change the
+        // action, except for `GET /snapshot/info` which describes the snapshot at its `path` and
+        // `GET /events` which returns the events recorded `since` a cursor.
+        let (request_path, query) = match request_uri.find('?') {
+            Some(index) => (&request_uri[..index], Some(&request_uri[index + 1..])),
+            None => (request_uri.as_str(), None),
+        };
+        if matches!(request.method(), Method::Get)
+            && request.body.is_none()
+            && request_path.trim_end_matches('/') == "/snapshot/info"
+        {
+            return parse_get_snapshot_info(query);
+        }
+        if matches!(request.method(), Method::Get)
+            && request.body.is_none()
+            && request_path.trim_end_matches('/') == "/events"
+        {
+            return parse_get_events(query);
+        }
+        let mut parsed_request = Self::parse_action(request, request_path)?;
+        if parse_dry_run_header(request)? | parse_validate_only(query)? {
+            parsed_request = parsed_request.into_dry_run()?;
+        }
+        if parse_prefer_header(request)? {
+            parsed_request = parsed_request.into_async()?;
+        }
+        Ok(parsed_request)
+    }
+
+    fn parse_action(request: &Request, request_uri: &str) -> Result<ParsedRequest, Error> {
         // Split request uri by '/' by doing:
         // 1. Trim starting '/' characters
         // 2. Splitting by '/'
@@ -94,39 +154,81 @@ impl ParsedRequest {
         match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
             (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.get(1)),
+            (Method::Get, "healthz", None) => parse_get_healthz(),
             (Method::Get, "version", None) => parse_get_version(),
             (Method::Get, "vm", None) if path_tokens.get(1) == Some(&"config") => {
                 Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig))
             }
+            (Method::Get, "vm", None) if path_tokens.get(1) == Some(&"dirty-rate") => {
+                Ok(ParsedRequest::new_sync(VmmAction::GetDirtyRate))
+            }
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
+            (Method::Get, "memory-hotplug", None) => parse_get_memory_hotplug(),
+            (Method::Get, "metrics", None) => parse_get_metrics(),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "readyz", None) => parse_get_readyz(),
+            (Method::Get, "network-interfaces", None) => {
+                parse_get_net(path_tokens.get(1), path_tokens.get(2))
+            }
+            (Method::Get, "operations", None) => parse_get_operation(path_tokens.get(1)),
+            (Method::Get, "vsock", None) => parse_get_vsock(&path_tokens[1..]),
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
-            (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
+            (Method::Put, "balloon", Some(body)) => parse_put_balloon(body, path_tokens.get(1)),
             (Method::Put, "boot-source", Some(body)) => parse_put_boot_source(body),
-            (Method::Put, "drives", Some(body)) => parse_put_drive(body, path_tokens.get(1)),
+            (Method::Put, "drives", Some(body)) if path_tokens.get(2) == Some(&"trace") => {
+                parse_put_drive_trace(body, path_tokens.get(1))
+            }
+            (Method::Put, "drives", Some(body)) => {
+                parse_put_drive(body, path_tokens.get(1), &request.files)
+            }
             (Method::Put, "logger", Some(body)) => parse_put_logger(body),
             (Method::Put, "machine-config", Some(body)) => parse_put_machine_config(body),
+            (Method::Put, "memory-hotplug", Some(body)) => parse_put_memory_hotplug(body),
             (Method::Put, "metrics", Some(body)) => parse_put_metrics(body),
+            (Method::Put, "migration", Some(body)) => parse_put_migration(body, path_tokens.get(1)),
             (Method::Put, "mmds", Some(body)) => parse_put_mmds(body, path_tokens.get(1)),
+            (Method::Put, "network-interfaces", Some(body))
+                if path_tokens.get(2) == Some(&"capture") =>
+            {
+                parse_put_net_capture(body, path_tokens.get(1))
+            }
             (Method::Put, "network-interfaces", Some(body)) => {
-                parse_put_net(body, path_tokens.get(1))
+                parse_put_net(body, path_tokens.get(1), &request.files)
+            }
+            (Method::Put, "pmem", Some(body)) => parse_put_pmem(body, path_tokens.get(1)),
+            (Method::Put, "rate-limiter-groups", Some(body)) => {
+                parse_put_rate_limiter_group(body, path_tokens.get(1))
             }
             (Method::Put, "shutdown-internal", None) => {
                 Ok(ParsedRequest::new(RequestAction::ShutdownInternal))
             }
-            (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.get(1)),
-            (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
+            (Method::Put, "snapshot", Some(body)) => {
+                parse_put_snapshot(body, path_tokens.get(1), &request.files)
+            }
+            (Method::Put, "vm", Some(body)) if path_tokens.get(1) == Some(&"config") => {
+                parse_put_vm_config(body)
+            }
+            (Method::Put, "vsock", Some(body)) if path_tokens.last() == Some(&"connections") => {
+                parse_put_vsock_connections(body, &path_tokens[1..])
+            }
+            (Method::Put, "vsock", Some(body)) => parse_put_vsock(body, path_tokens.get(1)),
             (Method::Put, _, None) => method_to_error(Method::Put),
             (Method::Patch, "balloon", Some(body)) => parse_patch_balloon(body, path_tokens.get(1)),
             (Method::Patch, "drives", Some(body)) => parse_patch_drive(body, path_tokens.get(1)),
             (Method::Patch, "machine-config", Some(body)) => parse_patch_machine_config(body),
+            (Method::Patch, "memory-hotplug", Some(body)) => parse_patch_memory_hotplug(body),
             (Method::Patch, "mmds", Some(body)) => parse_patch_mmds(body),
             (Method::Patch, "network-interfaces", Some(body)) => {
                 parse_patch_net(body, path_tokens.get(1))
             }
             (Method::Patch, "vm", Some(body)) => parse_patch_vm_state(body),
             (Method::Patch, _, None) => method_to_error(Method::Patch),
+            (Method::Delete, "balloon", None) => parse_delete_balloon(path_tokens.get(1)),
+            (Method::Delete, "drives", None) => parse_delete_drive(path_tokens.get(1)),
+            (Method::Delete, "network-interfaces", None) => parse_delete_net(path_tokens.get(1)),
+            (Method::Delete, "snapshot", None) => parse_delete_snapshot(path_tokens.get(1)),
+            (Method::Delete, _, Some(_)) => method_to_error(Method::Delete),
             (method, unknown_uri, _) => {
                 Err(Error::InvalidPathMethod(unknown_uri.to_string(), method))
             }
@@ -166,16 +268,26 @@ impl ParsedRequest {
                 VmmData::MachineConfiguration(vm_config) => {
                     Self::success_response_with_data(vm_config)
                 }
+                VmmData::MemoryHotplugStatus(status) => Self::success_response_with_data(status),
                 VmmData::MmdsValue(value) => Self::success_response_with_mmds_value(value),
+                VmmData::NetworkInterfaceStats(stats) => Self::success_response_with_data(stats),
                 VmmData::BalloonConfig(balloon_config) => {
                     Self::success_response_with_data(balloon_config)
                 }
                 VmmData::BalloonStats(stats) => Self::success_response_with_data(stats),
+                VmmData::DirtyRate(dirty_rate) => Self::success_response_with_data(dirty_rate),
                 VmmData::InstanceInformation(info) => Self::success_response_with_data(info),
                 VmmData::VmmVersion(version) => Self::success_response_with_data(
                     &serde_json::json!({ "firecracker_version": version.as_str() }),
                 ),
                 VmmData::FullVmConfig(config) => Self::success_response_with_data(config),
+                VmmData::VsockConnections(conns) => {
+                    Self::success_response_with_data(&serde_json::json!({ "connections": conns }))
+                }
+                VmmData::DryRun => {
+                    Self::success_response_with_data(&serde_json::json!({ "dry_run": true }))
+                }
+                VmmData::SnapshotInfo(info) => Self::success_response_with_data(info),
             },
             Err(vmm_action_error) => {
                 let mut response = match vmm_action_error {
@@ -206,6 +318,141 @@ impl ParsedRequest {
     pub(crate) fn new_sync(vmm_action: VmmAction) -> ParsedRequest {
         ParsedRequest::new(RequestAction::Sync(Box::new(vmm_action)))
     }
+
+    // Wraps the parsed action so that it only gets validated by the VMM.
+    fn into_dry_run(self) -> Result<ParsedRequest, Error> {
+        match self.action {
+            RequestAction::Sync(vmm_action) => Ok(ParsedRequest {
+                action: RequestAction::Sync(Box::new(VmmAction::DryRun(vmm_action))),
+                parsing_info: self.parsing_info,
+            }),
+            RequestAction::Async(_)
+            | RequestAction::GetOperation(_)
+            | RequestAction::ScrapeMetrics
+            | RequestAction::CheckHealth
+            | RequestAction::CheckReadiness
+            | RequestAction::GetLifecycleEvents(_)
+            | RequestAction::ShutdownInternal => Err(Error::Generic(
+                StatusCode::BadRequest,
+                "Dry runs are not supported for this request.".to_string(),
+            )),
+        }
+    }
+
+    // Makes the parsed action return an operation ID right away, instead of its outcome. Only
+    // the actions which may take seconds are supported.
+    fn into_async(self) -> Result<ParsedRequest, Error> {
+        match self.action {
+            RequestAction::Sync(vmm_action)
+                if matches!(
+                    *vmm_action,
+                    VmmAction::CreateSnapshot(_)
+                        | VmmAction::LoadSnapshot(_)
+                        | VmmAction::MergeSnapshot(_)
+                        | VmmAction::ReceiveMigration(_)
+                        | VmmAction::SendMigration(_)
+                ) =>
+            {
+                Ok(ParsedRequest {
+                    action: RequestAction::Async(vmm_action),
+                    parsing_info: self.parsing_info,
+                })
+            }
+            _ => Err(Error::Generic(
+                StatusCode::BadRequest,
+                "Only the snapshot creation, loading and merging requests and the migration \
+                 requests can be served asynchronously."
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+/// Header asking for a request to be validated without being applied.
+const DRY_RUN_HEADER: &str = "X-Dry-Run";
+
+/// Returns whether the `X-Dry-Run` header of `request` is set to `true`.
+fn parse_dry_run_header(request: &Request) -> Result<bool, Error> {
+    let value = request
+        .headers
+        .custom_entries()
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(DRY_RUN_HEADER))
+        .map(|(_, value)| value.trim());
+
+    match value {
+        None => Ok(false),
+        Some(value) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Some(value) if value.eq_ignore_ascii_case("false") => Ok(false),
+        Some(value) => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!(
+                "Invalid value for the {} header: {}. Expected `true` or `false`.",
+                DRY_RUN_HEADER, value
+            ),
+        )),
+    }
+}
+
+/// Header asking for a request to be answered before being carried out.
+const PREFER_HEADER: &str = "Prefer";
+/// Value of the `Prefer` header asking for an asynchronous request, from RFC 7240.
+const RESPOND_ASYNC: &str = "respond-async";
+
+/// Returns whether the `Prefer` header of `request` asks for an asynchronous request.
+fn parse_prefer_header(request: &Request) -> Result<bool, Error> {
+    let value = request
+        .headers
+        .custom_entries()
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(PREFER_HEADER))
+        .map(|(_, value)| value.trim());
+
+    match value {
+        None => Ok(false),
+        Some(value) if value.eq_ignore_ascii_case(RESPOND_ASYNC) => Ok(true),
+        Some(value) => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!(
+                "Invalid value for the {} header: {}. Expected `{}`.",
+                PREFER_HEADER, value, RESPOND_ASYNC
+            ),
+        )),
+    }
+}
+
+/// Query parameter asking for a request to be validated without being applied, like the
+/// `X-Dry-Run` header.
+const VALIDATE_ONLY_PARAM: &str = "validate_only";
+
+/// Returns whether the `validate_only` parameter of the `query` string is set to `true`.
+fn parse_validate_only(query: Option<&str>) -> Result<bool, Error> {
+    let mut validate_only = false;
+    for param in query.unwrap_or_default().split_terminator('&') {
+        let mut parts = param.splitn(2, '=');
+        let (name, value) = (parts.next().unwrap_or_default(), parts.next());
+        validate_only = match (name, value) {
+            (VALIDATE_ONLY_PARAM, Some("true")) => true,
+            (VALIDATE_ONLY_PARAM, Some("false")) => false,
+            (VALIDATE_ONLY_PARAM, _) => {
+                return Err(Error::Generic(
+                    StatusCode::BadRequest,
+                    format!(
+                        "Invalid value for the {} parameter: {}. Expected `true` or `false`.",
+                        VALIDATE_ONLY_PARAM,
+                        value.unwrap_or_default()
+                    ),
+                ))
+            }
+            _ => {
+                return Err(Error::Generic(
+                    StatusCode::BadRequest,
+                    format!("Unknown query parameter: {}.", name),
+                ))
+            }
+        };
+    }
+    Ok(validate_only)
 }
 
 /// Helper function for writing the received API requests to the log.
@@ -252,6 +499,10 @@ pub(crate) fn method_to_error(method: Method) -> Result<ParsedRequest, Error> {
             StatusCode::BadRequest,
             "Empty PATCH request.".to_string(),
         )),
+        Method::Delete => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "DELETE request cannot have a body.".to_string(),
+        )),
     }
 }
 
@@ -335,6 +586,10 @@ pub(crate) mod tests {
     use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonStats};
     use vmm::vmm_config::instance_info::InstanceInfo;
     use vmm::vmm_config::machine_config::VmConfig;
+    use vmm::vmm_config::memory_hotplug::MemoryHotplugStatus;
+    use vmm::vmm_config::net::NetStats;
+    use vmm::vmm_config::snapshot::{DirtyRate, SnapshotDeviceInfo, SnapshotInfo};
+    use vmm::vmm_config::vsock::VsockConnectionInfo;
 
     use super::*;
 
@@ -572,6 +827,10 @@ pub(crate) mod tests {
                 VmmData::BalloonStats(stats) => {
                     http_response(&serde_json::to_string(stats).unwrap(), 200)
                 }
+                VmmData::DirtyRate(dirty_rate) => {
+                    http_response(&serde_json::to_string(dirty_rate).unwrap(), 200)
+                }
+                VmmData::DryRun => http_response(r#"{"dry_run":true}"#, 200),
                 VmmData::Empty => http_response("", 204),
                 VmmData::FullVmConfig(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
@@ -579,16 +838,29 @@ pub(crate) mod tests {
                 VmmData::MachineConfiguration(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
                 }
+                VmmData::MemoryHotplugStatus(status) => {
+                    http_response(&serde_json::to_string(status).unwrap(), 200)
+                }
                 VmmData::MmdsValue(value) => {
                     http_response(&serde_json::to_string(value).unwrap(), 200)
                 }
+                VmmData::NetworkInterfaceStats(stats) => {
+                    http_response(&serde_json::to_string(stats).unwrap(), 200)
+                }
                 VmmData::InstanceInformation(info) => {
                     http_response(&serde_json::to_string(info).unwrap(), 200)
                 }
+                VmmData::SnapshotInfo(info) => {
+                    http_response(&serde_json::to_string(info).unwrap(), 200)
+                }
                 VmmData::VmmVersion(version) => http_response(
                     &serde_json::json!({ "firecracker_version": version.as_str() }).to_string(),
                     200,
                 ),
+                VmmData::VsockConnections(conns) => http_response(
+                    &serde_json::json!({ "connections": conns }).to_string(),
+                    200,
+                ),
             };
             let response = ParsedRequest::convert_to_response(&data);
             assert!(response.write_all(&mut buf).is_ok());
@@ -601,11 +873,50 @@ pub(crate) mod tests {
             swap_out: Some(1),
             ..Default::default()
         }));
+        verify_ok_response_with(VmmData::DirtyRate(DirtyRate {
+            dirty_pages: 512,
+            interval_ms: 1000,
+            dirty_pages_per_sec: 512,
+        }));
+        verify_ok_response_with(VmmData::DryRun);
         verify_ok_response_with(VmmData::Empty);
         verify_ok_response_with(VmmData::FullVmConfig(VmmConfig::default()));
         verify_ok_response_with(VmmData::MachineConfiguration(VmConfig::default()));
+        verify_ok_response_with(VmmData::MemoryHotplugStatus(MemoryHotplugStatus {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+            plugged_size_mib: 256,
+            requested_size_mib: 512,
+        }));
         verify_ok_response_with(VmmData::MmdsValue(serde_json::from_str("{}").unwrap()));
+        verify_ok_response_with(VmmData::NetworkInterfaceStats(NetStats {
+            rx_bytes: 1,
+            tx_packets: 1,
+            ..Default::default()
+        }));
         verify_ok_response_with(VmmData::InstanceInformation(InstanceInfo::default()));
+        verify_ok_response_with(VmmData::SnapshotInfo(SnapshotInfo {
+            firecracker_version: Some("1.1.0".to_string()),
+            data_version: 2,
+            vcpu_count: 2,
+            mem_size_mib: 128,
+            devices: vec![SnapshotDeviceInfo {
+                id: "root".to_string(),
+                device_type: "block",
+            }],
+            checksum: "0123456789abcdef".to_string(),
+        }));
+        verify_ok_response_with(VmmData::VsockConnections(vec![VsockConnectionInfo {
+            state: "Established",
+            local_port: 1024,
+            peer_port: 52,
+            rx_bytes: 1,
+            tx_bytes: 1,
+            tx_buf_len: 0,
+            tx_buf_size: 65536,
+            peer_buf_len: 1,
+            peer_buf_alloc: 65536,
+        }]));
         verify_ok_response_with(VmmData::VmmVersion(String::default()));
 
         // Error.
@@ -667,6 +978,34 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_memory_hotplug() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/memory-hotplug", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        let body = "{ \"total_size_mib\": 1024 }";
+        sender
+            .write_all(http_request("PUT", "/memory-hotplug", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        let body = "{ \"requested_size_mib\": 512 }";
+        sender
+            .write_all(http_request("PATCH", "/memory-hotplug", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_get_mmds() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -679,6 +1018,75 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_netif_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/network-interfaces/string/stats", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_vsock_connections() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vsock/connections", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_snapshot_info() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/snapshot/info?path=/tmp/vm.snap", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req)
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::DescribeSnapshot(
+                std::path::PathBuf::from("/tmp/vm.snap")
+            ))));
+    }
+
+    #[test]
+    fn test_try_from_get_events() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/events?since=3", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req).unwrap().into_parts() {
+            (RequestAction::GetLifecycleEvents(3), _) => (),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_get_dirty_rate() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/dirty-rate", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req)
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::GetDirtyRate)));
+    }
+
     #[test]
     fn test_try_from_get_version() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -691,6 +1099,20 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_health() {
+        for path in ["/healthz", "/readyz"].iter() {
+            let (mut sender, receiver) = UnixStream::pair().unwrap();
+            let mut connection = HttpConnection::new(receiver);
+            sender
+                .write_all(http_request("GET", path, None).as_bytes())
+                .unwrap();
+            assert!(connection.try_read().is_ok());
+            let req = connection.pop_parsed_request().unwrap();
+            assert!(ParsedRequest::try_from_request(&req).is_ok());
+        }
+    }
+
     #[test]
     fn test_try_from_put_actions() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -748,6 +1170,23 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_drive_trace() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"drive_id\": \"string\", \"state\": \"Started\", \"path_on_host\": \
+                    \"/tmp/string.jsonl\" }";
+        sender
+            .write_all(http_request("PUT", "/drives/string/trace", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match vmm_action_from_request(ParsedRequest::try_from_request(&req).unwrap()) {
+            VmmAction::SetDriveTrace(config) => assert_eq!(config.drive_id, "string"),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_try_from_put_logger() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -838,6 +1277,25 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_netif_capture() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"iface_id\": \"string\", \"state\": \"Started\", \"path_on_host\": \
+                    \"/tmp/string.pcap\" }";
+        sender
+            .write_all(
+                http_request("PUT", "/network-interfaces/string/capture", Some(&body)).as_bytes(),
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match vmm_action_from_request(ParsedRequest::try_from_request(&req).unwrap()) {
+            VmmAction::SetNetworkCapture(config) => assert_eq!(config.iface_id, "string"),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_try_from_put_snapshot() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -911,6 +1369,41 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        let body = "{ \"guest_cid\": 4, \"uds_path\": \"string\" }";
+        sender
+            .write_all(http_request("PUT", "/vsock/data", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match vmm_action_from_request(ParsedRequest::try_from_request(&req).unwrap()) {
+            VmmAction::SetVsockDevice(cfg) => assert_eq!(cfg.vsock_id.as_deref(), Some("data")),
+            _ => panic!("Test failed."),
+        }
+
+        let body = "{ \"port\": 52, \"count\": 4 }";
+        sender
+            .write_all(http_request("PUT", "/vsock/data/connections", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match vmm_action_from_request(ParsedRequest::try_from_request(&req).unwrap()) {
+            VmmAction::PoolVsockConnections(id, _) => assert_eq!(id, "data"),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_put_pmem() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"pmem_id\": \"string\", \"path_on_host\": \"string\" }";
+        sender
+            .write_all(http_request("PUT", "/pmem/string", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
     #[test]
@@ -994,4 +1487,225 @@ pub(crate) mod tests {
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
+
+    #[test]
+    fn test_try_from_balloon_policy() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"min_available_mib\": 64, \"max_available_mib\": 128, \"step_mib\": 16 }";
+        sender
+            .write_all(http_request("PUT", "/balloon/policy", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("DELETE", "/balloon/policy", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_delete_drive() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("DELETE", "/drives/string", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        // The request can't have a body.
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"drive_id\": \"string\" }";
+        sender
+            .write_all(http_request("DELETE", "/drives/string", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
+    #[test]
+    fn test_try_from_delete_netif() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("DELETE", "/network-interfaces/string", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        // The request can't have a body.
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"iface_id\": \"string\" }";
+        sender
+            .write_all(http_request("DELETE", "/network-interfaces/string", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
+    #[test]
+    fn test_try_from_delete_snapshot_schedule() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("DELETE", "/snapshot/schedule", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req)
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::CancelSnapshotSchedule)));
+    }
+
+    #[test]
+    fn test_try_from_dry_run() {
+        let parse = |dry_run_header: Option<&str>| {
+            let (mut sender, receiver) = UnixStream::pair().unwrap();
+            let mut connection = HttpConnection::new(receiver);
+            let body = "{ \"iface_id\": \"string\" }";
+            let header = dry_run_header
+                .map(|value| format!("X-Dry-Run: {}\r\n", value))
+                .unwrap_or_default();
+            let request = format!(
+                "PATCH /network-interfaces/string HTTP/1.1\r\nContent-Type: \
+                 application/json\r\n{}Content-Length: {}\r\n\r\n{}",
+                header,
+                body.len(),
+                body
+            );
+            sender.write_all(request.as_bytes()).unwrap();
+            assert!(connection.try_read().is_ok());
+            let req = connection.pop_parsed_request().unwrap();
+            ParsedRequest::try_from_request(&req)
+        };
+
+        let action = vmm_action_from_request(parse(None).unwrap());
+        assert!(vmm_action_from_request(parse(Some("false")).unwrap()) == action);
+        assert!(
+            vmm_action_from_request(parse(Some("true")).unwrap())
+                == VmmAction::DryRun(Box::new(action))
+        );
+
+        match parse(Some("yes")) {
+            Err(Error::Generic(StatusCode::BadRequest, msg)) => assert_eq!(
+                msg,
+                "Invalid value for the X-Dry-Run header: yes. Expected `true` or `false`."
+            ),
+            _ => panic!("Unexpected result"),
+        }
+
+        // Internal requests can't be dry run.
+        let req = ParsedRequest::new(RequestAction::ShutdownInternal);
+        assert!(req.into_dry_run().is_err());
+    }
+
+    #[test]
+    fn test_try_from_respond_async() {
+        let parse = |path: &str, body: &str, prefer_header: &str| {
+            let (mut sender, receiver) = UnixStream::pair().unwrap();
+            let mut connection = HttpConnection::new(receiver);
+            let request = format!(
+                "PUT {} HTTP/1.1\r\nContent-Type: application/json\r\nPrefer: {}\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                path,
+                prefer_header,
+                body.len(),
+                body
+            );
+            sender.write_all(request.as_bytes()).unwrap();
+            assert!(connection.try_read().is_ok());
+            let req = connection.pop_parsed_request().unwrap();
+            ParsedRequest::try_from_request(&req)
+        };
+
+        let body = "{ \"snapshot_path\": \"foo\", \"mem_file_path\": \"bar\" }";
+        match parse("/snapshot/create", body, "respond-async")
+            .unwrap()
+            .into_parts()
+        {
+            (RequestAction::Async(action), _) => {
+                assert!(matches!(*action, VmmAction::CreateSnapshot(_)))
+            }
+            _ => panic!("Unexpected result"),
+        }
+
+        match parse("/snapshot/create", body, "wait=10") {
+            Err(Error::Generic(StatusCode::BadRequest, msg)) => assert_eq!(
+                msg,
+                "Invalid value for the Prefer header: wait=10. Expected `respond-async`."
+            ),
+            _ => panic!("Unexpected result"),
+        }
+        // Only the actions which may take long are supported.
+        match parse(
+            "/actions",
+            "{ \"action_type\": \"FlushMetrics\" }",
+            "respond-async",
+        ) {
+            Err(Error::Generic(StatusCode::BadRequest, msg)) => assert_eq!(
+                msg,
+                "Only the snapshot creation, loading and merging requests and the migration \
+                 requests can be served asynchronously."
+            ),
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_try_from_validate_only() {
+        let parse = |uri: &str| {
+            let (mut sender, receiver) = UnixStream::pair().unwrap();
+            let mut connection = HttpConnection::new(receiver);
+            let body = "{ \"iface_id\": \"string\" }";
+            sender
+                .write_all(http_request("PATCH", uri, Some(&body)).as_bytes())
+                .unwrap();
+            assert!(connection.try_read().is_ok());
+            let req = connection.pop_parsed_request().unwrap();
+            ParsedRequest::try_from_request(&req)
+        };
+
+        let action = vmm_action_from_request(parse("/network-interfaces/string").unwrap());
+        assert!(
+            vmm_action_from_request(
+                parse("/network-interfaces/string?validate_only=false").unwrap()
+            ) == action
+        );
+        assert!(
+            vmm_action_from_request(
+                parse("/network-interfaces/string?validate_only=true").unwrap()
+            ) == VmmAction::DryRun(Box::new(action))
+        );
+
+        match parse("/network-interfaces/string?validate_only=yes") {
+            Err(Error::Generic(StatusCode::BadRequest, msg)) => assert_eq!(
+                msg,
+                "Invalid value for the validate_only parameter: yes. Expected `true` or `false`."
+            ),
+            _ => panic!("Unexpected result"),
+        }
+        match parse("/network-interfaces/string?foo=bar") {
+            Err(Error::Generic(StatusCode::BadRequest, msg)) => {
+                assert_eq!(msg, "Unknown query parameter: foo.")
+            }
+            _ => panic!("Unexpected result"),
+        }
+
+        // Internal requests can't be validated either.
+        let req = ParsedRequest::new(RequestAction::ShutdownInternal);
+        assert!(req.into_dry_run().is_err());
+    }
 }
@@ -1,14 +1,15 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(target_arch = "x86_64")]
+use std::time::Duration;
+
 use logger::{IncMetric, METRICS};
 use serde::{Deserialize, Serialize};
 
 use super::super::VmmAction;
 use crate::parsed_request::{Error, ParsedRequest};
-use crate::request::Body;
-#[cfg(target_arch = "aarch64")]
-use crate::request::StatusCode;
+use crate::request::{Body, StatusCode};
 
 // The names of the members from this enum must precisely correspond (as a string) to the possible
 // values of "action_type" from the json request body. This is useful to get a strongly typed
@@ -18,6 +19,8 @@ enum ActionType {
     FlushMetrics,
     InstanceStart,
     SendCtrlAltDel,
+    #[serde(rename = "SendACPIShutdown")]
+    SendAcpiShutdown,
 }
 
 // The model of the json body from a sync request. We use Serde to transform each associated
@@ -26,6 +29,10 @@ enum ActionType {
 #[serde(deny_unknown_fields)]
 struct ActionBody {
     action_type: ActionType,
+    // Seconds after which the microVM is stopped if the guest didn't power off, for
+    // `SendACPIShutdown`.
+    #[serde(default)]
+    timeout_s: Option<u64>,
 }
 
 pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
@@ -35,6 +42,15 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
         Error::SerdeJson(e)
     })?;
 
+    let is_shutdown = matches!(action_body.action_type, ActionType::SendAcpiShutdown);
+    if action_body.timeout_s.is_some() && !is_shutdown {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The timeout_s field is only supported by SendACPIShutdown.".to_string(),
+        ));
+    }
+
     match action_body.action_type {
         ActionType::FlushMetrics => Ok(ParsedRequest::new_sync(VmmAction::FlushMetrics)),
         ActionType::InstanceStart => Ok(ParsedRequest::new_sync(VmmAction::StartMicroVm)),
@@ -49,6 +65,28 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
             #[cfg(target_arch = "x86_64")]
             Ok(ParsedRequest::new_sync(VmmAction::SendCtrlAltDel))
         }
+        ActionType::SendAcpiShutdown => {
+            // ACPI is only supported on x86_64.
+            #[cfg(target_arch = "aarch64")]
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                "SendACPIShutdown is not supported on aarch64.".to_string(),
+            ));
+
+            #[cfg(target_arch = "x86_64")]
+            {
+                if action_body.timeout_s == Some(0) {
+                    METRICS.put_api_requests.actions_fails.inc();
+                    return Err(Error::Generic(
+                        StatusCode::BadRequest,
+                        "The shutdown timeout must be greater than 0.".to_string(),
+                    ));
+                }
+                Ok(ParsedRequest::new_sync(VmmAction::SendAcpiShutdown(
+                    action_body.timeout_s.map(Duration::from_secs),
+                )))
+            }
+        }
     }
 }
 
@@ -93,6 +131,56 @@ mod tests {
             assert!(result.is_err());
         }
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let json = r#"{
+                "action_type": "SendACPIShutdown"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::SendAcpiShutdown(None));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.unwrap().eq(&req));
+
+            let json = r#"{
+                "action_type": "SendACPIShutdown",
+                "timeout_s": 30
+            }"#;
+
+            let req: ParsedRequest =
+                ParsedRequest::new_sync(VmmAction::SendAcpiShutdown(Some(Duration::from_secs(30))));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.unwrap().eq(&req));
+
+            let json = r#"{
+                "action_type": "SendACPIShutdown",
+                "timeout_s": 0
+            }"#;
+
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_err());
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let json = r#"{
+                "action_type": "SendACPIShutdown"
+            }"#;
+
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_err());
+        }
+
+        {
+            // The timeout only applies to the shutdown.
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "timeout_s": 30
+            }"#;
+
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_err());
+        }
+
         {
             let json = r#"{
                 "action_type": "FlushMetrics"
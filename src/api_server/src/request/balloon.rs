@@ -3,7 +3,7 @@
 
 use micro_http::StatusCode;
 use vmm::vmm_config::balloon::{
-    BalloonDeviceConfig, BalloonUpdateConfig, BalloonUpdateStatsConfig,
+    BalloonDeviceConfig, BalloonPolicy, BalloonUpdateConfig, BalloonUpdateStatsConfig,
 };
 
 use super::super::VmmAction;
@@ -23,10 +23,38 @@ pub(crate) fn parse_get_balloon(path_second_token: Option<&&str>) -> Result<Pars
     }
 }
 
-pub(crate) fn parse_put_balloon(body: &Body) -> Result<ParsedRequest, Error> {
-    Ok(ParsedRequest::new_sync(VmmAction::SetBalloonDevice(
-        serde_json::from_slice::<BalloonDeviceConfig>(body.raw()).map_err(Error::SerdeJson)?,
-    )))
+pub(crate) fn parse_put_balloon(
+    body: &Body,
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"policy") => Ok(ParsedRequest::new_sync(VmmAction::SetBalloonPolicy(
+            serde_json::from_slice::<BalloonPolicy>(body.raw()).map_err(Error::SerdeJson)?,
+        ))),
+        Some(path) => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!("Unrecognized PUT request path `{}`.", *path),
+        )),
+        None => Ok(ParsedRequest::new_sync(VmmAction::SetBalloonDevice(
+            serde_json::from_slice::<BalloonDeviceConfig>(body.raw()).map_err(Error::SerdeJson)?,
+        ))),
+    }
+}
+
+pub(crate) fn parse_delete_balloon(
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"policy") => Ok(ParsedRequest::new_sync(VmmAction::RemoveBalloonPolicy)),
+        Some(path) => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!("Unrecognized DELETE request path `{}`.", *path),
+        )),
+        None => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The balloon device can't be removed.".to_string(),
+        )),
+    }
 }
 
 pub(crate) fn parse_patch_balloon(
@@ -150,14 +178,14 @@ mod tests {
 
     #[test]
     fn test_parse_put_balloon_request() {
-        assert!(parse_put_balloon(&Body::new("invalid_payload")).is_err());
+        assert!(parse_put_balloon(&Body::new("invalid_payload"), None).is_err());
 
         // PUT with invalid fields.
         let body = r#"{
                 "amount_mib": "bar",
                 "is_read_only": false
               }"#;
-        assert!(parse_put_balloon(&Body::new(body)).is_err());
+        assert!(parse_put_balloon(&Body::new(body), None).is_err());
 
         // PUT with valid input fields.
         let body = r#"{
@@ -165,6 +193,49 @@ mod tests {
                 "deflate_on_oom": true,
                 "stats_polling_interval_s": 0
             }"#;
-        assert!(parse_put_balloon(&Body::new(body)).is_ok());
+        assert!(parse_put_balloon(&Body::new(body), None).is_ok());
+
+        // PUT with free page reporting.
+        let body = r#"{
+                "amount_mib": 0,
+                "deflate_on_oom": false,
+                "free_page_reporting": true
+            }"#;
+        match vmm_action_from_request(parse_put_balloon(&Body::new(body), None).unwrap()) {
+            VmmAction::SetBalloonDevice(cfg) => assert!(cfg.free_page_reporting),
+            _ => panic!("Test failed."),
+        }
+
+        // PUT on the policy.
+        let body = r#"{
+                "min_available_mib": 64,
+                "max_available_mib": 128,
+                "step_mib": 16
+            }"#;
+        assert!(parse_put_balloon(&Body::new(body), Some(&"unrelated")).is_err());
+        match vmm_action_from_request(parse_put_balloon(&Body::new(body), Some(&"policy")).unwrap())
+        {
+            VmmAction::SetBalloonPolicy(policy) => assert_eq!(
+                policy,
+                BalloonPolicy {
+                    min_available_mib: 64,
+                    max_available_mib: 128,
+                    step_mib: 16,
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+        let body = r#"{ "min_available_mib": 64, "step_mib": 16 }"#;
+        assert!(parse_put_balloon(&Body::new(body), Some(&"policy")).is_err());
+    }
+
+    #[test]
+    fn test_parse_delete_balloon_request() {
+        assert!(parse_delete_balloon(None).is_err());
+        assert!(parse_delete_balloon(Some(&"statistics")).is_err());
+        match vmm_action_from_request(parse_delete_balloon(Some(&"policy")).unwrap()) {
+            VmmAction::RemoveBalloonPolicy => (),
+            _ => panic!("Test failed."),
+        }
     }
 }
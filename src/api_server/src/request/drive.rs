@@ -1,8 +1,11 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0<Paste>
 
+use std::fs::File;
+use std::os::unix::io::IntoRawFd;
+
 use logger::{IncMetric, METRICS};
-use vmm::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig};
+use vmm::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveTraceConfig};
 
 use super::super::VmmAction;
 use crate::parsed_request::{checked_id, Error, ParsedRequest};
@@ -11,6 +14,7 @@ use crate::request::{Body, StatusCode};
 pub(crate) fn parse_put_drive(
     body: &Body,
     id_from_path: Option<&&str>,
+    files: &[File],
 ) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.drive_count.inc();
     let id = if let Some(id) = id_from_path {
@@ -20,22 +24,87 @@ pub(crate) fn parse_put_drive(
         return Err(Error::EmptyID);
     };
 
-    let device_cfg = serde_json::from_slice::<BlockDeviceConfig>(body.raw()).map_err(|e| {
+    let mut device_cfg = serde_json::from_slice::<BlockDeviceConfig>(body.raw()).map_err(|e| {
         METRICS.put_api_requests.drive_fails.inc();
         Error::SerdeJson(e)
     })?;
 
     if id != device_cfg.drive_id {
         METRICS.put_api_requests.drive_fails.inc();
-        Err(Error::Generic(
+        return Err(Error::Generic(
             StatusCode::BadRequest,
             "The id from the path does not match the id from the body!".to_string(),
-        ))
+        ));
+    }
+
+    // A backing file sent along with the request (SCM_RIGHTS) stands for `fd`. The received
+    // file is closed with the request, so the device gets a duplicate of it.
+    match files {
+        [] => (),
+        [file] if device_cfg.fd.is_none() => {
+            let disk_file = file.try_clone().map_err(|e| {
+                METRICS.put_api_requests.drive_fails.inc();
+                Error::Generic(
+                    StatusCode::InternalServerError,
+                    format!("Cannot duplicate the backing file descriptor: {}", e),
+                )
+            })?;
+            device_cfg.fd = Some(disk_file.into_raw_fd());
+        }
+        _ => {
+            METRICS.put_api_requests.drive_fails.inc();
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                "A single backing file descriptor can be sent with the request, and only when \
+                 fd is not set."
+                    .to_string(),
+            ));
+        }
+    }
+
+    Ok(ParsedRequest::new_sync(VmmAction::InsertBlockDevice(
+        device_cfg,
+    )))
+}
+
+pub(crate) fn parse_put_drive_trace(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.drive_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
     } else {
-        Ok(ParsedRequest::new_sync(VmmAction::InsertBlockDevice(
-            device_cfg,
-        )))
+        METRICS.put_api_requests.drive_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let trace = serde_json::from_slice::<DriveTraceConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.drive_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+    if id != trace.drive_id {
+        METRICS.put_api_requests.drive_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
     }
+    Ok(ParsedRequest::new_sync(VmmAction::SetDriveTrace(trace)))
+}
+
+pub(crate) fn parse_delete_drive(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.delete_api_requests.drive_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.delete_api_requests.drive_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    Ok(ParsedRequest::new_sync(VmmAction::RemoveBlockDevice(
+        id.to_string(),
+    )))
 }
 
 pub(crate) fn parse_patch_drive(
@@ -66,15 +135,24 @@ pub(crate) fn parse_patch_drive(
 
     // Validate request - we need to have at least one parameter set:
     // - path_on_host
+    // - size_bytes
+    // - is_read_only
     // - rate_limiter
+    // - read_rate_limiter
+    // - write_rate_limiter
     if block_device_update_cfg.path_on_host.is_none()
+        && block_device_update_cfg.size_bytes.is_none()
+        && block_device_update_cfg.is_read_only.is_none()
         && block_device_update_cfg.rate_limiter.is_none()
+        && block_device_update_cfg.read_rate_limiter.is_none()
+        && block_device_update_cfg.write_rate_limiter.is_none()
     {
         METRICS.patch_api_requests.drive_fails.inc();
         return Err(Error::Generic(
             StatusCode::BadRequest,
             String::from(
-                "Please specify at least one property to patch: path_on_host, rate_limiter.",
+                "Please specify at least one property to patch: path_on_host, size_bytes, \
+                 is_read_only, rate_limiter, read_rate_limiter, write_rate_limiter.",
             ),
         ));
     }
@@ -86,6 +164,10 @@ pub(crate) fn parse_patch_drive(
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    use vmm::vmm_config::drive::{FileEngineType, ImageFormat};
+
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
@@ -210,19 +292,75 @@ mod tests {
         }"#;
         // Validate that parse_patch_drive fails for invalid rate limiter cfg.
         assert!(parse_patch_drive(&Body::new(body), Some(&"foo")).is_err());
+
+        let body = r#"{
+            "drive_id": "foo",
+            "write_rate_limiter": {
+                "bandwidth": {
+                    "size": 5000,
+                    "refill_time": 100
+                }
+            }
+        }"#;
+        // Validate that updating just the rate limiter of the writes works.
+        #[allow(clippy::match_wild_err_arm)]
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateBlockDevice(cfg) => {
+                assert!(cfg.rate_limiter.is_none());
+                assert!(cfg.read_rate_limiter.is_none());
+                assert_eq!(
+                    cfg.write_rate_limiter.unwrap().bandwidth.unwrap().size,
+                    5000
+                );
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        let body = r#"{
+            "drive_id": "foo",
+            "size_bytes": 1048576
+        }"#;
+        // Validate that resizing the drive works on its own.
+        #[allow(clippy::match_wild_err_arm)]
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateBlockDevice(cfg) => {
+                assert_eq!(cfg.size_bytes, Some(1_048_576));
+                assert!(cfg.path_on_host.is_none());
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        let body = r#"{
+            "drive_id": "foo",
+            "size_bytes": -1
+        }"#;
+        assert!(parse_patch_drive(&Body::new(body), Some(&"foo")).is_err());
+
+        let body = r#"{
+            "drive_id": "foo",
+            "is_read_only": true
+        }"#;
+        // Validate that toggling the read-only mode works on its own.
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateBlockDevice(cfg) => {
+                assert_eq!(cfg.is_read_only, Some(true));
+                assert!(cfg.size_bytes.is_none());
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
     }
 
     #[test]
     fn test_parse_put_drive_request() {
-        assert!(parse_put_drive(&Body::new("invalid_payload"), None).is_err());
-        assert!(parse_put_drive(&Body::new("invalid_payload"), Some(&"id")).is_err());
+        assert!(parse_put_drive(&Body::new("invalid_payload"), None, &[]).is_err());
+        assert!(parse_put_drive(&Body::new("invalid_payload"), Some(&"id"), &[]).is_err());
 
         // PUT with invalid fields.
         let body = r#"{
                 "drive_id": "bar",
                 "is_read_only": false
               }"#;
-        assert!(parse_put_drive(&Body::new(body), Some(&"2")).is_err());
+        assert!(parse_put_drive(&Body::new(body), Some(&"2"), &[]).is_err());
 
         // PUT with missing all optional fields.
         let body = r#"{
@@ -231,10 +369,10 @@ mod tests {
             "is_root_device": true,
             "is_read_only": true
         }"#;
-        assert!(parse_put_drive(&Body::new(body), Some(&"1000")).is_ok());
+        assert!(parse_put_drive(&Body::new(body), Some(&"1000"), &[]).is_ok());
 
         // PUT with invalid types on fields. Adding a drive_id as number instead of string.
-        assert!(parse_put_drive(&Body::new(body), Some(&"foo")).is_err());
+        assert!(parse_put_drive(&Body::new(body), Some(&"foo"), &[]).is_err());
 
         // PUT with the complete configuration.
         let body = r#"{
@@ -258,6 +396,161 @@ mod tests {
                     }
                 }
             }"#;
-        assert!(parse_put_drive(&Body::new(body), Some(&"1000")).is_ok());
+        assert!(parse_put_drive(&Body::new(body), Some(&"1000"), &[]).is_ok());
+
+        // `file_engine` is an alias of `io_engine`.
+        let body = r#"{
+            "drive_id": "1000",
+            "path_on_host": "dummy",
+            "is_root_device": true,
+            "is_read_only": true,
+            "file_engine": "Async"
+        }"#;
+        match vmm_action_from_request(
+            parse_put_drive(&Body::new(body), Some(&"1000"), &[]).unwrap(),
+        ) {
+            VmmAction::InsertBlockDevice(config) => {
+                assert_eq!(config.file_engine_type, FileEngineType::Async)
+            }
+            _ => panic!("Test failed."),
+        }
+        let body = r#"{
+            "drive_id": "1000",
+            "path_on_host": "dummy",
+            "is_root_device": true,
+            "is_read_only": true,
+            "io_engine": "Async",
+            "file_engine": "Async"
+        }"#;
+        assert!(parse_put_drive(&Body::new(body), Some(&"1000"), &[]).is_err());
+
+        // PUT with a qcow2 image.
+        let body = r#"{
+            "drive_id": "1000",
+            "path_on_host": "dummy",
+            "is_root_device": false,
+            "is_read_only": false,
+            "format": "qcow2"
+        }"#;
+        match vmm_action_from_request(
+            parse_put_drive(&Body::new(body), Some(&"1000"), &[]).unwrap(),
+        ) {
+            VmmAction::InsertBlockDevice(config) => {
+                assert_eq!(config.image_format, ImageFormat::Qcow2)
+            }
+            _ => panic!("Test failed."),
+        }
+        let body = r#"{
+            "drive_id": "1000",
+            "path_on_host": "dummy",
+            "is_root_device": false,
+            "is_read_only": false,
+            "format": "vmdk"
+        }"#;
+        assert!(parse_put_drive(&Body::new(body), Some(&"1000"), &[]).is_err());
+
+        // PUT with a base image and an overlay.
+        let body = r#"{
+            "drive_id": "1000",
+            "base_image": "base",
+            "overlay": "overlay",
+            "is_root_device": true,
+            "is_read_only": false
+        }"#;
+        match vmm_action_from_request(
+            parse_put_drive(&Body::new(body), Some(&"1000"), &[]).unwrap(),
+        ) {
+            VmmAction::InsertBlockDevice(config) => {
+                assert_eq!(config.path_on_host, "base");
+                assert_eq!(config.overlay, Some("overlay".to_string()));
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_put_drive_request_with_fd() {
+        let body = r#"{
+            "drive_id": "1000",
+            "is_root_device": false,
+            "is_read_only": true
+        }"#;
+        let files = [File::open("/dev/null").unwrap()];
+
+        // The file sent with the request is used as the backing file descriptor.
+        match vmm_action_from_request(
+            parse_put_drive(&Body::new(body), Some(&"1000"), &files).unwrap(),
+        ) {
+            VmmAction::InsertBlockDevice(config) => {
+                assert!(config.path_on_host.is_empty());
+                let fd = config.fd.unwrap();
+                assert_ne!(fd, files[0].as_raw_fd());
+                // Close the duplicate.
+                drop(unsafe { File::from_raw_fd(fd) });
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // A file can't be sent when `fd` is set.
+        let body = r#"{
+            "drive_id": "1000",
+            "fd": 42,
+            "is_root_device": false,
+            "is_read_only": true
+        }"#;
+        match vmm_action_from_request(
+            parse_put_drive(&Body::new(body), Some(&"1000"), &[]).unwrap(),
+        ) {
+            VmmAction::InsertBlockDevice(config) => assert_eq!(config.fd, Some(42)),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_put_drive(&Body::new(body), Some(&"1000"), &files).is_err());
+        // Only a single file can be sent.
+        let files = [
+            File::open("/dev/null").unwrap(),
+            File::open("/dev/null").unwrap(),
+        ];
+        let body = r#"{"drive_id": "1000", "is_root_device": false, "is_read_only": true}"#;
+        assert!(parse_put_drive(&Body::new(body), Some(&"1000"), &files).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_drive_trace_request() {
+        let body = r#"{
+                "drive_id": "foo",
+                "state": "Started",
+                "path_on_host": "/tmp/foo.jsonl",
+                "format": "Binary",
+                "max_records_per_sec": 100
+        }"#;
+        // The id from the path must match the id from the body.
+        assert!(parse_put_drive_trace(&Body::new(body), Some(&"bar")).is_err());
+        // The `id_from_path` cannot be None.
+        assert!(parse_put_drive_trace(&Body::new(body), None).is_err());
+
+        let expected_config = serde_json::from_str::<DriveTraceConfig>(body).unwrap();
+        match vmm_action_from_request(
+            parse_put_drive_trace(&Body::new(body), Some(&"foo")).unwrap(),
+        ) {
+            VmmAction::SetDriveTrace(config) => assert_eq!(config, expected_config),
+            _ => panic!("Test failed."),
+        }
+
+        // Invalid format.
+        let body = r#"{"drive_id": "foo", "state": "Started", "format": "Csv"}"#;
+        assert!(parse_put_drive_trace(&Body::new(body), Some(&"foo")).is_err());
+    }
+
+    #[test]
+    fn test_parse_delete_drive_request() {
+        // The `id_from_path` cannot be None.
+        assert!(parse_delete_drive(None).is_err());
+        // The id must be valid.
+        assert!(parse_delete_drive(Some(&"foo-bar")).is_err());
+
+        match vmm_action_from_request(parse_delete_drive(Some(&"foo")).unwrap()) {
+            VmmAction::RemoveBlockDevice(id) => assert_eq!(id, "foo"),
+            _ => panic!("Test failed."),
+        }
     }
 }
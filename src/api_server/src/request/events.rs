@@ -0,0 +1,59 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+
+use super::StatusCode;
+use crate::parsed_request::{Error, ParsedRequest, RequestAction};
+
+/// Name of the query parameter holding the cursor the events are polled from.
+const EVENTS_SINCE_PARAM: &str = "since";
+
+pub(crate) fn parse_get_events(query: Option<&str>) -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.events_count.inc();
+    let mut since = 0;
+    for param in query.unwrap_or_default().split_terminator('&') {
+        let mut parts = param.splitn(2, '=');
+        match (parts.next().unwrap_or_default(), parts.next()) {
+            (EVENTS_SINCE_PARAM, Some(value)) => {
+                since = value.parse::<u64>().map_err(|_| {
+                    Error::Generic(
+                        StatusCode::BadRequest,
+                        format!(
+                            "Invalid value for the {} parameter: {}.",
+                            EVENTS_SINCE_PARAM, value
+                        ),
+                    )
+                })?;
+            }
+            (name, _) => {
+                return Err(Error::Generic(
+                    StatusCode::BadRequest,
+                    format!("Unknown or empty query parameter: {}.", name),
+                ))
+            }
+        }
+    }
+    Ok(ParsedRequest::new(RequestAction::GetLifecycleEvents(since)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_events() {
+        match parse_get_events(None).unwrap().into_parts() {
+            (RequestAction::GetLifecycleEvents(0), _) => (),
+            _ => panic!("Test failed."),
+        }
+        match parse_get_events(Some("since=42")).unwrap().into_parts() {
+            (RequestAction::GetLifecycleEvents(42), _) => (),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_events(Some("since=")).is_err());
+        assert!(parse_get_events(Some("since=-1")).is_err());
+        assert!(parse_get_events(Some("since")).is_err());
+        assert!(parse_get_events(Some("since=1&validate_only=true")).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+
+use crate::parsed_request::{Error, ParsedRequest, RequestAction};
+
+pub(crate) fn parse_get_healthz() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.health_count.inc();
+    Ok(ParsedRequest::new(RequestAction::CheckHealth))
+}
+
+pub(crate) fn parse_get_readyz() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.health_count.inc();
+    Ok(ParsedRequest::new(RequestAction::CheckReadiness))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_health_requests() {
+        match parse_get_healthz().unwrap().into_parts() {
+            (RequestAction::CheckHealth, _) => (),
+            _ => panic!("Test failed."),
+        }
+        match parse_get_readyz().unwrap().into_parts() {
+            (RequestAction::CheckReadiness, _) => (),
+            _ => panic!("Test failed."),
+        }
+    }
+}
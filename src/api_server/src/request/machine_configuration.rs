@@ -45,7 +45,7 @@ pub(crate) fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, E
 
 #[cfg(test)]
 mod tests {
-    use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
+    use vmm::vmm_config::machine_config::{CpuFeaturesTemplate, GuestMemoryBackend};
 
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
@@ -84,6 +84,7 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(CpuFeaturesTemplate::None),
             track_dirty_pages: Some(false),
+            mem_backend: Some(GuestMemoryBackend::Anonymous),
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -103,6 +104,7 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(CpuFeaturesTemplate::None),
             track_dirty_pages: Some(true),
+            mem_backend: Some(GuestMemoryBackend::Anonymous),
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -128,6 +130,7 @@ mod tests {
                 smt: Some(false),
                 cpu_template: Some(CpuFeaturesTemplate::T2),
                 track_dirty_pages: Some(true),
+                mem_backend: Some(GuestMemoryBackend::Anonymous),
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -157,6 +160,7 @@ mod tests {
                 smt: Some(true),
                 cpu_template: Some(CpuFeaturesTemplate::None),
                 track_dirty_pages: Some(true),
+                mem_backend: Some(GuestMemoryBackend::Anonymous),
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -169,6 +173,34 @@ mod tests {
         {
             assert!(parse_put_machine_config(&Body::new(body)).is_err());
         }
+
+        // 6. Test that the guest memory can be backed by huge pages.
+        let body = r#"{
+            "vcpu_count": 2,
+            "mem_size_mib": 1024,
+            "mem_backend": {
+                "type": "hugetlbfs",
+                "path": "/dev/hugepages"
+            }
+          }"#;
+        match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateVmConfiguration(config) => assert_eq!(
+                config.mem_backend,
+                Some(GuestMemoryBackend::Hugetlbfs {
+                    path: std::path::PathBuf::from("/dev/hugepages")
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+            "vcpu_count": 2,
+            "mem_size_mib": 1024,
+            "mem_backend": {
+                "type": "hugetlbfs"
+            }
+          }"#;
+        assert!(parse_put_machine_config(&Body::new(body)).is_err());
     }
 
     #[test]
@@ -205,6 +237,11 @@ mod tests {
               }"#;
         assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
 
+        let body = r#"{
+                "mem_backend": {"type": "anonymous"}
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
         // 3. Check to see if an empty body returns an error.
         let body = r#"{}"#;
         assert!(parse_patch_machine_config(&Body::new(body)).is_err());
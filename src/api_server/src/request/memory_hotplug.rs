@@ -0,0 +1,109 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+use vmm::vmm_config::memory_hotplug::{MemoryHotplugConfig, MemoryHotplugSizeUpdate};
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_get_memory_hotplug() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetMemoryHotplugStatus))
+}
+
+pub(crate) fn parse_put_memory_hotplug(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.memory_hotplug_count.inc();
+    let config = serde_json::from_slice::<MemoryHotplugConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.memory_hotplug_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+
+    Ok(ParsedRequest::new_sync(VmmAction::SetMemoryHotplug(config)))
+}
+
+pub(crate) fn parse_patch_memory_hotplug(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.patch_api_requests.memory_hotplug_count.inc();
+    let update = serde_json::from_slice::<MemoryHotplugSizeUpdate>(body.raw()).map_err(|e| {
+        METRICS.patch_api_requests.memory_hotplug_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateMemoryHotplug(
+        update,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_memory_hotplug_request() {
+        assert!(
+            vmm_action_from_request(parse_get_memory_hotplug().unwrap())
+                == VmmAction::GetMemoryHotplugStatus
+        );
+    }
+
+    #[test]
+    fn test_parse_put_memory_hotplug_request() {
+        assert!(parse_put_memory_hotplug(&Body::new("invalid_payload")).is_err());
+        assert!(METRICS.put_api_requests.memory_hotplug_fails.count() > 0);
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "total_size_mib": 1024,
+                "requested_size_mib": 512
+              }"#;
+        assert!(parse_put_memory_hotplug(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "total_size_mib": 1024
+              }"#;
+        let expected_config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        };
+        assert!(
+            vmm_action_from_request(parse_put_memory_hotplug(&Body::new(body)).unwrap())
+                == VmmAction::SetMemoryHotplug(expected_config)
+        );
+
+        let body = r#"{
+                "total_size_mib": 1024,
+                "block_size_mib": 128
+              }"#;
+        let expected_config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 128,
+        };
+        assert!(
+            vmm_action_from_request(parse_put_memory_hotplug(&Body::new(body)).unwrap())
+                == VmmAction::SetMemoryHotplug(expected_config)
+        );
+    }
+
+    #[test]
+    fn test_parse_patch_memory_hotplug_request() {
+        assert!(parse_patch_memory_hotplug(&Body::new("invalid_payload")).is_err());
+        assert!(METRICS.patch_api_requests.memory_hotplug_fails.count() > 0);
+
+        // The total size can't be updated.
+        let body = r#"{
+                "total_size_mib": 1024
+              }"#;
+        assert!(parse_patch_memory_hotplug(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "requested_size_mib": 512
+              }"#;
+        assert!(
+            vmm_action_from_request(parse_patch_memory_hotplug(&Body::new(body)).unwrap())
+                == VmmAction::UpdateMemoryHotplug(MemoryHotplugSizeUpdate {
+                    requested_size_mib: 512
+                })
+        );
+    }
+}
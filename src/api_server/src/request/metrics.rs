@@ -5,9 +5,14 @@ use logger::{IncMetric, METRICS};
 use vmm::vmm_config::metrics::MetricsConfig;
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
+use crate::parsed_request::{Error, ParsedRequest, RequestAction};
 use crate::request::Body;
 
+pub(crate) fn parse_get_metrics() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.metrics_count.inc();
+    Ok(ParsedRequest::new(RequestAction::ScrapeMetrics))
+}
+
 pub(crate) fn parse_put_metrics(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.metrics_count.inc();
     Ok(ParsedRequest::new_sync(VmmAction::ConfigureMetrics(
@@ -25,6 +30,14 @@ mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_metrics_request() {
+        match parse_get_metrics().unwrap().into_parts() {
+            (RequestAction::ScrapeMetrics, _) => (),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_parse_put_metrics_request() {
         let body = r#"{
@@ -0,0 +1,103 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+use vmm::vmm_config::migration::{MigrationReceiveConfig, MigrationSendConfig};
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::{Body, Method, StatusCode};
+
+pub(crate) fn parse_put_migration(
+    body: &Body,
+    direction_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.migration_count.inc();
+    let action = match direction_from_path {
+        Some(&"send") => {
+            serde_json::from_slice::<MigrationSendConfig>(body.raw()).map(VmmAction::SendMigration)
+        }
+        Some(&"receive") => serde_json::from_slice::<MigrationReceiveConfig>(body.raw())
+            .map(VmmAction::ReceiveMigration),
+        Some(&direction) => {
+            METRICS.put_api_requests.migration_fails.inc();
+            return Err(Error::InvalidPathMethod(
+                format!("/migration/{}", direction),
+                Method::Put,
+            ));
+        }
+        None => {
+            METRICS.put_api_requests.migration_fails.inc();
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                "Missing migration direction.".to_string(),
+            ));
+        }
+    };
+    action.map(ParsedRequest::new_sync).map_err(|e| {
+        METRICS.put_api_requests.migration_fails.inc();
+        Error::SerdeJson(e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_migration_request() {
+        let body = r#"{
+                "destination": "192.168.0.2:7070",
+                "max_dirty_pages": 256
+              }"#;
+        let expected_config = MigrationSendConfig {
+            destination: "192.168.0.2:7070".parse().unwrap(),
+            max_iterations: 10,
+            max_dirty_pages: 256,
+        };
+        match vmm_action_from_request(parse_put_migration(&Body::new(body), Some(&"send")).unwrap())
+        {
+            VmmAction::SendMigration(config) => assert_eq!(config, expected_config),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "listen_address": "0.0.0.0:7070",
+                "resume_vm": true
+              }"#;
+        let expected_config = MigrationReceiveConfig {
+            listen_address: "0.0.0.0:7070".parse().unwrap(),
+            track_dirty_pages: false,
+            resume_vm: true,
+        };
+        match vmm_action_from_request(
+            parse_put_migration(&Body::new(body), Some(&"receive")).unwrap(),
+        ) {
+            VmmAction::ReceiveMigration(config) => assert_eq!(config, expected_config),
+            _ => panic!("Test failed."),
+        }
+
+        // The fields of one direction aren't accepted by the other.
+        assert!(parse_put_migration(&Body::new(body), Some(&"send")).is_err());
+        assert!(parse_put_migration(&Body::new("{}"), Some(&"receive")).is_err());
+        assert!(parse_put_migration(&Body::new(body), Some(&"invalid")).is_err());
+        assert!(parse_put_migration(&Body::new(body), None).is_err());
+
+        // Host names aren't resolved.
+        let body = r#"{
+                "destination": "localhost:7070"
+              }"#;
+        assert!(matches!(
+            parse_put_migration(&Body::new(body), Some(&"send")),
+            Err(Error::SerdeJson(_))
+        ));
+        let body = r#"{
+                "listen_address": "localhost:7070"
+              }"#;
+        assert!(matches!(
+            parse_put_migration(&Body::new(body), Some(&"receive")),
+            Err(Error::SerdeJson(_))
+        ));
+    }
+}
@@ -4,6 +4,7 @@
 use logger::{IncMetric, METRICS};
 use micro_http::StatusCode;
 use mmds::data_store::MmdsVersion;
+use serde_json::Value;
 use vmm::rpc_interface::VmmAction;
 use vmm::vmm_config::mmds::MmdsConfig;
 
@@ -48,6 +49,12 @@ pub(crate) fn parse_put_mmds(
             })?,
         ))),
         Some(&"config") => parse_put_mmds_config(body),
+        Some(&"identity") => Ok(ParsedRequest::new_sync(VmmAction::SetMmdsIdentity(
+            serde_json::from_slice(body.raw()).map_err(|e| {
+                METRICS.put_api_requests.mmds_fails.inc();
+                Error::SerdeJson(e)
+            })?,
+        ))),
         Some(&unrecognized) => {
             METRICS.put_api_requests.mmds_fails.inc();
             Err(Error::Generic(
@@ -60,18 +67,33 @@ pub(crate) fn parse_put_mmds(
 
 pub(crate) fn parse_patch_mmds(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.patch_api_requests.mmds_count.inc();
-    Ok(ParsedRequest::new_sync(VmmAction::PatchMMDS(
-        serde_json::from_slice(body.raw()).map_err(|e| {
+    let value: Value = serde_json::from_slice(body.raw()).map_err(|e| {
+        METRICS.patch_api_requests.mmds_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+
+    // An array body is a JSON Patch document, anything else is a JSON Merge Patch.
+    if value.is_array() {
+        let operations = serde_json::from_value(value).map_err(|e| {
             METRICS.patch_api_requests.mmds_fails.inc();
             Error::SerdeJson(e)
-        })?,
-    )))
+        })?;
+        return Ok(ParsedRequest::new_sync(VmmAction::PatchMMDSOperations(
+            operations,
+        )));
+    }
+    Ok(ParsedRequest::new_sync(VmmAction::PatchMMDS(value)))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
+    use mmds::data_store::PatchOperation;
+    use vmm::vmm_config::mmds::MmdsIdentityConfig;
+
     use super::*;
-    use crate::parsed_request::tests::depr_action_from_req;
+    use crate::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
 
     #[test]
     fn test_parse_get_mmds_request() {
@@ -104,6 +126,19 @@ mod tests {
               }"#;
         assert!(parse_put_mmds(&Body::new(body), Some(&config_path)).is_ok());
 
+        let body = r#"{
+                "version": "V2",
+                "ipv6_address": "fd00:ec2::254",
+                "network_interfaces": []
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&config_path)).is_ok());
+
+        let body = r#"{
+                "ipv6_address": "169.254.170.2",
+                "network_interfaces": []
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&config_path)).is_err());
+
         let body = r#"{
                 "version": "foo",
                 "ipv4_address": "169.254.170.2",
@@ -128,6 +163,21 @@ mod tests {
         assert!(parse_put_mmds(&Body::new(invalid_config_body), Some(&config_path)).is_err());
         assert!(parse_put_mmds(&Body::new(body), Some(&"invalid_path")).is_err());
         assert!(parse_put_mmds(&Body::new(invalid_body), Some(&config_path)).is_err());
+
+        // Test `identity` path.
+        let body = r#"{
+                "signing_key_path": "/path/to/key"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_mmds(&Body::new(body), Some(&"identity")).unwrap()),
+            VmmAction::SetMmdsIdentity(MmdsIdentityConfig {
+                signing_key_path: PathBuf::from("/path/to/key"),
+            })
+        );
+        let body = r#"{
+                "signing_key": "foo"
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&"identity")).is_err());
     }
 
     #[test]
@@ -173,5 +223,25 @@ mod tests {
         assert!(METRICS.patch_api_requests.mmds_count.count() > 0);
         assert!(parse_patch_mmds(&Body::new("invalid_body")).is_err());
         assert!(METRICS.patch_api_requests.mmds_fails.count() > 0);
+
+        // An array body is parsed as a JSON Patch document.
+        let body = r#"[
+                {"op": "test", "path": "/foo", "value": "bar"},
+                {"op": "remove", "path": "/foo"}
+              ]"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_mmds(&Body::new(body)).unwrap()),
+            VmmAction::PatchMMDSOperations(vec![
+                PatchOperation::Test {
+                    path: "/foo".to_string(),
+                    value: Value::String("bar".to_string()),
+                },
+                PatchOperation::Remove {
+                    path: "/foo".to_string(),
+                },
+            ])
+        );
+        let body = r#"[{"op": "move", "from": "/foo", "path": "/bar"}]"#;
+        assert!(parse_patch_mmds(&Body::new(body)).is_err());
     }
 }
@@ -5,14 +5,22 @@ pub mod actions;
 pub mod balloon;
 pub mod boot_source;
 pub mod drive;
+pub mod events;
+pub mod health;
 pub mod instance_info;
 pub mod logger;
 pub mod machine_configuration;
+pub mod memory_hotplug;
 pub mod metrics;
+pub mod migration;
 pub mod mmds;
 pub mod net;
+pub mod operations;
+pub mod pmem;
+pub mod rate_limiter_group;
 pub mod snapshot;
 pub mod version;
+pub mod vm_config;
 pub mod vsock;
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, StatusCode, Version,
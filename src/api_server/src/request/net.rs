@@ -1,16 +1,47 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::fs::File;
+use std::os::unix::io::IntoRawFd;
+
 use logger::{IncMetric, METRICS};
-use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
+use vmm::vmm_config::net::{
+    NetworkCaptureConfig, NetworkInterfaceConfig, NetworkInterfaceUpdateConfig,
+};
 
 use super::super::VmmAction;
 use crate::parsed_request::{checked_id, Error, ParsedRequest};
 use crate::request::{Body, StatusCode};
 
+pub(crate) fn parse_get_net(
+    id_from_path: Option<&&str>,
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    match path_second_token {
+        Some(&"stats") => Ok(ParsedRequest::new_sync(
+            VmmAction::GetNetworkInterfaceStats(id.to_string()),
+        )),
+        Some(token) => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!("Unrecognized GET request path `{}`.", token),
+        )),
+        None => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Network interfaces only expose their statistics through GET requests.".to_string(),
+        )),
+    }
+}
+
 pub(crate) fn parse_put_net(
     body: &Body,
     id_from_path: Option<&&str>,
+    files: &[File],
 ) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.network_count.inc();
     let id = if let Some(id) = id_from_path {
@@ -20,10 +51,34 @@ pub(crate) fn parse_put_net(
         return Err(Error::EmptyID);
     };
 
-    let netif = serde_json::from_slice::<NetworkInterfaceConfig>(body.raw()).map_err(|e| {
+    let mut netif = serde_json::from_slice::<NetworkInterfaceConfig>(body.raw()).map_err(|e| {
         METRICS.put_api_requests.network_fails.inc();
         Error::SerdeJson(e)
     })?;
+    // A TAP file descriptor sent along with the request (SCM_RIGHTS) stands for `tap_fd`. The
+    // received file is closed with the request, so the device gets a duplicate of it.
+    match files {
+        [] => (),
+        [file] if netif.tap_fd.is_none() => {
+            let tap_file = file.try_clone().map_err(|e| {
+                METRICS.put_api_requests.network_fails.inc();
+                Error::Generic(
+                    StatusCode::InternalServerError,
+                    format!("Cannot duplicate the TAP file descriptor: {}", e),
+                )
+            })?;
+            netif.tap_fd = Some(tap_file.into_raw_fd());
+        }
+        _ => {
+            METRICS.put_api_requests.network_fails.inc();
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                "A single TAP file descriptor can be sent with the request, and only when \
+                 tap_fd is not set."
+                    .to_string(),
+            ));
+        }
+    }
     if id != netif.iface_id.as_str() {
         METRICS.put_api_requests.network_fails.inc();
         return Err(Error::Generic(
@@ -40,6 +95,38 @@ pub(crate) fn parse_put_net(
     )))
 }
 
+pub(crate) fn parse_put_net_capture(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.network_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.network_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let capture = serde_json::from_slice::<NetworkCaptureConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.network_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+    if id != capture.iface_id.as_str() {
+        METRICS.put_api_requests.network_fails.inc();
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!(
+                "The id from the path [{}] does not match the id from the body [{}]!",
+                id,
+                capture.iface_id.as_str()
+            ),
+        ));
+    }
+    Ok(ParsedRequest::new_sync(VmmAction::SetNetworkCapture(
+        capture,
+    )))
+}
+
 pub(crate) fn parse_patch_net(
     body: &Body,
     id_from_path: Option<&&str>,
@@ -73,11 +160,43 @@ pub(crate) fn parse_patch_net(
     )))
 }
 
+pub(crate) fn parse_delete_net(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.delete_api_requests.network_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.delete_api_requests.network_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    Ok(ParsedRequest::new_sync(VmmAction::RemoveNetworkDevice(
+        id.to_string(),
+    )))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_net_request() {
+        // The `id_from_path` cannot be None.
+        assert!(parse_get_net(None, Some(&"stats")).is_err());
+        // The id must be valid.
+        assert!(parse_get_net(Some(&"foo-bar"), Some(&"stats")).is_err());
+        // Only the statistics can be read.
+        assert!(parse_get_net(Some(&"foo"), None).is_err());
+        assert!(parse_get_net(Some(&"foo"), Some(&"config")).is_err());
+
+        match vmm_action_from_request(parse_get_net(Some(&"foo"), Some(&"stats")).unwrap()) {
+            VmmAction::GetNetworkInterfaceStats(id) => assert_eq!(id, "foo"),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_parse_put_net_request() {
         let body = r#"{
@@ -86,18 +205,44 @@ mod tests {
                 "guest_mac": "12:34:56:78:9A:BC"
               }"#;
         // 1. Exercise infamous "The id from the path does not match id from the body!".
-        assert!(parse_put_net(&Body::new(body), Some(&"bar")).is_err());
+        assert!(parse_put_net(&Body::new(body), Some(&"bar"), &[]).is_err());
         // 2. The `id_from_path` cannot be None.
-        assert!(parse_put_net(&Body::new(body), None).is_err());
+        assert!(parse_put_net(&Body::new(body), None, &[]).is_err());
 
         // 3. Success case.
         let netif_clone = serde_json::from_str::<NetworkInterfaceConfig>(body).unwrap();
-        match vmm_action_from_request(parse_put_net(&Body::new(body), Some(&"foo")).unwrap()) {
+        match vmm_action_from_request(parse_put_net(&Body::new(body), Some(&"foo"), &[]).unwrap()) {
             VmmAction::InsertNetworkDevice(netif) => assert_eq!(netif, netif_clone),
             _ => panic!("Test failed."),
         }
 
-        // 4. Serde error for invalid field (bytes instead of bandwidth).
+        // 4. The guest MAC address can be updated.
+        let body = r#"{
+                "iface_id": "foo",
+                "guest_mac": "12:34:56:78:9a:bc"
+        }"#;
+        match vmm_action_from_request(parse_patch_net(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateNetworkInterface(netif) => {
+                assert_eq!(netif.guest_mac.unwrap().to_string(), "12:34:56:78:9a:bc");
+                assert!(netif.rx_rate_limiter.is_none());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // The link state can be updated.
+        let body = r#"{
+                "iface_id": "foo",
+                "link_up": false
+        }"#;
+        match vmm_action_from_request(parse_patch_net(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateNetworkInterface(netif) => {
+                assert_eq!(netif.link_up, Some(false));
+                assert!(netif.guest_mac.is_none());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 5. Serde error for invalid field (bytes instead of bandwidth).
         let body = r#"
         {
             "iface_id": "foo",
@@ -115,7 +260,42 @@ mod tests {
             }
         }"#;
 
-        assert!(parse_put_net(&Body::new(body), Some(&"foo")).is_err());
+        assert!(parse_put_net(&Body::new(body), Some(&"foo"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_net_request_with_fd() {
+        let body = r#"{
+                "iface_id": "foo"
+              }"#;
+        let files = [File::open("/dev/null").unwrap()];
+
+        // The file sent with the request is used as the TAP file descriptor.
+        match vmm_action_from_request(
+            parse_put_net(&Body::new(body), Some(&"foo"), &files).unwrap(),
+        ) {
+            VmmAction::InsertNetworkDevice(netif) => {
+                assert!(netif.host_dev_name.is_empty());
+                let tap_fd = netif.tap_fd.unwrap();
+                assert_ne!(tap_fd, files[0].as_raw_fd());
+                // Close the duplicate.
+                drop(unsafe { File::from_raw_fd(tap_fd) });
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // A file can't be sent when `tap_fd` is set.
+        let body = r#"{
+                "iface_id": "foo",
+                "tap_fd": 42
+              }"#;
+        assert!(parse_put_net(&Body::new(body), Some(&"foo"), &files).is_err());
+        // Only a single file can be sent.
+        let files = [
+            File::open("/dev/null").unwrap(),
+            File::open("/dev/null").unwrap(),
+        ];
+        assert!(parse_put_net(&Body::new(r#"{"iface_id": "foo"}"#), Some(&"foo"), &files).is_err());
     }
 
     #[test]
@@ -158,4 +338,44 @@ mod tests {
         }"#;
         assert!(parse_patch_net(&Body::new(body), Some(&"foo")).is_err());
     }
+
+    #[test]
+    fn test_parse_put_net_capture_request() {
+        let body = r#"{
+                "iface_id": "foo",
+                "state": "Started",
+                "path_on_host": "/tmp/foo.pcap",
+                "max_file_size_mib": 16,
+                "file_count": 4
+        }"#;
+        // The id from the path must match the id from the body.
+        assert!(parse_put_net_capture(&Body::new(body), Some(&"bar")).is_err());
+        // The `id_from_path` cannot be None.
+        assert!(parse_put_net_capture(&Body::new(body), None).is_err());
+
+        let expected_config = serde_json::from_str::<NetworkCaptureConfig>(body).unwrap();
+        match vmm_action_from_request(
+            parse_put_net_capture(&Body::new(body), Some(&"foo")).unwrap(),
+        ) {
+            VmmAction::SetNetworkCapture(config) => assert_eq!(config, expected_config),
+            _ => panic!("Test failed."),
+        }
+
+        // Invalid state.
+        let body = r#"{"iface_id": "foo", "state": "Paused"}"#;
+        assert!(parse_put_net_capture(&Body::new(body), Some(&"foo")).is_err());
+    }
+
+    #[test]
+    fn test_parse_delete_net_request() {
+        // The `id_from_path` cannot be None.
+        assert!(parse_delete_net(None).is_err());
+        // The id must be valid.
+        assert!(parse_delete_net(Some(&"foo-bar")).is_err());
+
+        match vmm_action_from_request(parse_delete_net(Some(&"foo")).unwrap()) {
+            VmmAction::RemoveNetworkDevice(id) => assert_eq!(id, "foo"),
+            _ => panic!("Test failed."),
+        }
+    }
 }
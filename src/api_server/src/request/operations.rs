@@ -0,0 +1,32 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+
+use crate::parsed_request::{checked_id, Error, ParsedRequest, RequestAction};
+
+pub(crate) fn parse_get_operation(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.operations_count.inc();
+    let id = match id_from_path {
+        Some(id) => checked_id(id)?,
+        None => return Err(Error::EmptyID),
+    };
+    Ok(ParsedRequest::new(RequestAction::GetOperation(
+        id.to_string(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_operation_request() {
+        match parse_get_operation(Some(&"op_1")).unwrap().into_parts() {
+            (RequestAction::GetOperation(id), _) => assert_eq!(id, "op_1"),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_operation(None).is_err());
+        assert!(parse_get_operation(Some(&"op-1")).is_err());
+    }
+}
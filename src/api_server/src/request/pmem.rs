@@ -0,0 +1,97 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+use vmm::vmm_config::pmem::PmemConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{checked_id, Error, ParsedRequest};
+use crate::request::{Body, StatusCode};
+
+pub(crate) fn parse_put_pmem(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.pmem_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.pmem_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let pmem_cfg = serde_json::from_slice::<PmemConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.pmem_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+
+    if id != pmem_cfg.pmem_id {
+        METRICS.put_api_requests.pmem_fails.inc();
+        Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ))
+    } else {
+        Ok(ParsedRequest::new_sync(VmmAction::InsertPmemDevice(
+            pmem_cfg,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_pmem_request() {
+        assert!(parse_put_pmem(&Body::new("invalid_payload"), None).is_err());
+        assert!(parse_put_pmem(&Body::new("invalid_payload"), Some(&"id")).is_err());
+
+        // PUT with invalid fields.
+        let body = r#"{
+                "pmem_id": "bar",
+                "path_on_host": "/foo/bar",
+                "is_root_device": false
+              }"#;
+        assert!(parse_put_pmem(&Body::new(body), Some(&"bar")).is_err());
+
+        // PUT with missing path_on_host field.
+        let body = r#"{
+                "pmem_id": "bar"
+              }"#;
+        assert!(parse_put_pmem(&Body::new(body), Some(&"bar")).is_err());
+
+        let body = r#"{
+                "pmem_id": "bar",
+                "path_on_host": "/foo/bar"
+              }"#;
+        // Must fail since the pmem id differs from id_from_path (bar vs foo).
+        assert!(parse_put_pmem(&Body::new(body), Some(&"foo")).is_err());
+
+        let expected_config = PmemConfig {
+            pmem_id: "bar".to_string(),
+            path_on_host: "/foo/bar".to_string(),
+            is_read_only: false,
+        };
+        assert!(
+            vmm_action_from_request(parse_put_pmem(&Body::new(body), Some(&"bar")).unwrap())
+                == VmmAction::InsertPmemDevice(expected_config)
+        );
+
+        let body = r#"{
+                "pmem_id": "bar",
+                "path_on_host": "/foo/bar",
+                "is_read_only": true
+              }"#;
+        let expected_config = PmemConfig {
+            pmem_id: "bar".to_string(),
+            path_on_host: "/foo/bar".to_string(),
+            is_read_only: true,
+        };
+        assert!(
+            vmm_action_from_request(parse_put_pmem(&Body::new(body), Some(&"bar")).unwrap())
+                == VmmAction::InsertPmemDevice(expected_config)
+        );
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+use vmm::vmm_config::rate_limiter_group::RateLimiterGroupConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{checked_id, Error, ParsedRequest};
+use crate::request::{Body, StatusCode};
+
+pub(crate) fn parse_put_rate_limiter_group(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.rate_limiter_group_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.rate_limiter_group_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    let group_cfg = serde_json::from_slice::<RateLimiterGroupConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.rate_limiter_group_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+
+    if id != group_cfg.group_id {
+        METRICS.put_api_requests.rate_limiter_group_fails.inc();
+        Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The id from the path does not match the id from the body!".to_string(),
+        ))
+    } else {
+        Ok(ParsedRequest::new_sync(VmmAction::SetRateLimiterGroup(
+            group_cfg,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::vmm_config::TokenBucketConfig;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_rate_limiter_group_request() {
+        let body = r#"{
+            "group_id": "tenant-a",
+            "bandwidth": {
+                "size": 1000,
+                "refill_time": 100
+            }
+        }"#;
+        assert!(parse_put_rate_limiter_group(&Body::new(body), None).is_err());
+        assert!(parse_put_rate_limiter_group(&Body::new(body), Some(&"tenant-b")).is_err());
+
+        let expected_config = RateLimiterGroupConfig {
+            group_id: "tenant-a".to_string(),
+            bandwidth: Some(TokenBucketConfig {
+                size: 1000,
+                one_time_burst: None,
+                refill_time: 100,
+            }),
+            ops: None,
+        };
+        assert_eq!(
+            vmm_action_from_request(
+                parse_put_rate_limiter_group(&Body::new(body), Some(&"tenant-a")).unwrap()
+            ),
+            VmmAction::SetRateLimiterGroup(expected_config)
+        );
+
+        let body = r#"{
+            "group_id": "tenant-a",
+            "invalid_field": false
+        }"#;
+        assert!(parse_put_rate_limiter_group(&Body::new(body), Some(&"tenant-a")).is_err());
+    }
+}
@@ -1,11 +1,17 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashSet;
+use std::fs::File;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
 use logger::{IncMetric, METRICS};
 use serde::de::Error as DeserializeError;
+use utils::validators::validate_instance_id;
 use vmm::vmm_config::snapshot::{
     CreateSnapshotParams, LoadSnapshotConfig, LoadSnapshotParams, MemBackendConfig, MemBackendType,
-    Vm, VmState,
+    MergeSnapshotParams, ResumeMode, SnapshotScheduleConfig, Vm, VmState, MAX_SCHEDULE_SLOTS,
 };
 
 use super::super::VmmAction;
@@ -21,18 +27,46 @@ pub const MISSING_FIELD: &str =
 /// Only specifying one of them is allowed.
 pub const TOO_MANY_FIELDS: &str =
     "too many fields: either `mem_backend` or `mem_file_path` exclusively is required";
+/// None or both of the `snapshot_path` and `snapshot_fd` fields have been specified.
+pub const SNAPSHOT_SOURCE: &str = "either `snapshot_path` or `snapshot_fd` exclusively is required";
+/// None or both of the `mem_file_path` and `mem_file_fd` fields have been specified when
+/// creating a snapshot, or `mem_file_fd` has been specified along with another memory source
+/// when loading one.
+pub const MEM_FILE_SOURCE: &str =
+    "either `mem_file_path` (or `mem_backend`) or `mem_file_fd` exclusively is required";
+/// The same file descriptor has been specified for the microVM state and the guest memory.
+pub const SAME_FD: &str = "`snapshot_fd` and `mem_file_fd` must be different file descriptors";
+/// The same network interface or drive has been overridden several times.
+pub const DUPLICATE_OVERRIDE: &str = "each network interface and drive can only be overridden once";
+/// The same network interface of a clone has been given several MAC addresses.
+pub const DUPLICATE_GUEST_MAC: &str =
+    "each network interface of a clone can only be given one guest MAC address";
+/// A lazy resume has been asked for along with the `Uffd` memory backend.
+pub const LAZY_RESUME_BACKEND: &str =
+    "the `lazy` resume mode is only supported by the `File` memory backend";
+/// Guest memory backed by hugetlbfs has been asked for along with a memory source which doesn't
+/// copy the memory in it.
+pub const HUGETLBFS_RESUME: &str = "guest memory backed by hugetlbfs is only supported by the \
+                                    `File` memory backend along with the `eager` resume mode";
+/// The interval of a snapshot schedule is zero.
+pub const SCHEDULE_INTERVAL: &str = "`interval_s` must be greater than 0";
+/// A snapshot schedule rotates through too few or too many slots.
+pub const SCHEDULE_SLOTS: &str = "`slots` must be between 2 and 64";
 
 pub(crate) fn parse_put_snapshot(
     body: &Body,
     request_type_from_path: Option<&&str>,
+    files: &[File],
 ) -> Result<ParsedRequest, Error> {
     match request_type_from_path {
         Some(&request_type) => match request_type {
-            "create" => Ok(ParsedRequest::new_sync(VmmAction::CreateSnapshot(
-                serde_json::from_slice::<CreateSnapshotParams>(body.raw())
+            "create" => parse_put_snapshot_create(body, files),
+            "load" => parse_put_snapshot_load(body, files),
+            "schedule" => parse_put_snapshot_schedule(body),
+            "merge" => Ok(ParsedRequest::new_sync(VmmAction::MergeSnapshot(
+                serde_json::from_slice::<MergeSnapshotParams>(body.raw())
                     .map_err(Error::SerdeJson)?,
             ))),
-            "load" => parse_put_snapshot_load(body),
             _ => Err(Error::InvalidPathMethod(
                 format!("/snapshot/{}", request_type),
                 Method::Put,
@@ -45,6 +79,90 @@ pub(crate) fn parse_put_snapshot(
     }
 }
 
+pub(crate) fn parse_delete_snapshot(
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"schedule") => Ok(ParsedRequest::new_sync(VmmAction::CancelSnapshotSchedule)),
+        Some(path) => Err(Error::Generic(
+            StatusCode::BadRequest,
+            format!("Unrecognized DELETE request path `{}`.", *path),
+        )),
+        None => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Missing snapshot operation type.".to_string(),
+        )),
+    }
+}
+
+fn parse_put_snapshot_schedule(body: &Body) -> Result<ParsedRequest, Error> {
+    let config =
+        serde_json::from_slice::<SnapshotScheduleConfig>(body.raw()).map_err(Error::SerdeJson)?;
+    if config.interval_s == 0 {
+        return Err(Error::SerdeJson(serde_json::Error::custom(
+            SCHEDULE_INTERVAL,
+        )));
+    }
+    if config.slots < 2 || config.slots > MAX_SCHEDULE_SLOTS {
+        return Err(Error::SerdeJson(serde_json::Error::custom(SCHEDULE_SLOTS)));
+    }
+    Ok(ParsedRequest::new_sync(VmmAction::ScheduleSnapshots(
+        config,
+    )))
+}
+
+/// Name of the query parameter holding the path of the snapshot to describe.
+const SNAPSHOT_INFO_PATH_PARAM: &str = "path";
+
+pub(crate) fn parse_get_snapshot_info(query: Option<&str>) -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.snapshot_info_count.inc();
+    let mut snapshot_path = None;
+    for param in query.unwrap_or_default().split_terminator('&') {
+        let mut parts = param.splitn(2, '=');
+        match (parts.next().unwrap_or_default(), parts.next()) {
+            (SNAPSHOT_INFO_PATH_PARAM, Some(value)) if !value.is_empty() => {
+                snapshot_path = Some(percent_decode(value).ok_or_else(|| {
+                    Error::Generic(
+                        StatusCode::BadRequest,
+                        format!("Invalid snapshot path: {}.", value),
+                    )
+                })?);
+            }
+            (name, _) => {
+                return Err(Error::Generic(
+                    StatusCode::BadRequest,
+                    format!("Unknown or empty query parameter: {}.", name),
+                ))
+            }
+        }
+    }
+    let snapshot_path = snapshot_path.ok_or_else(|| {
+        Error::Generic(
+            StatusCode::BadRequest,
+            format!("Missing the {} query parameter.", SNAPSHOT_INFO_PATH_PARAM),
+        )
+    })?;
+    Ok(ParsedRequest::new_sync(VmmAction::DescribeSnapshot(
+        PathBuf::from(snapshot_path),
+    )))
+}
+
+// Decodes the `%XX` escapes of a query parameter value, returns `None` if one is malformed or
+// the decoded value isn't UTF-8.
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
 pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, Error> {
     let vm = serde_json::from_slice::<Vm>(body.raw()).map_err(Error::SerdeJson)?;
 
@@ -54,17 +172,111 @@ pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, Error>
     }
 }
 
-fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
-    let snapshot_config =
+// Files sent along with the request (SCM_RIGHTS) stand for `snapshot_fd` and `mem_file_fd`, in
+// this order. The received files are closed with the request, so the VMM gets duplicates of
+// them.
+fn snapshot_fds_from_files(
+    files: &[File],
+    snapshot_fd: &mut Option<RawFd>,
+    mem_file_fd: &mut Option<RawFd>,
+) -> Result<(), Error> {
+    match files {
+        [] => Ok(()),
+        [snapshot_file, mem_file] if snapshot_fd.is_none() && mem_file_fd.is_none() => {
+            let duplicate = |file: &File| {
+                file.try_clone().map(File::into_raw_fd).map_err(|e| {
+                    Error::Generic(
+                        StatusCode::InternalServerError,
+                        format!("Cannot duplicate the snapshot file descriptor: {}", e),
+                    )
+                })
+            };
+            *snapshot_fd = Some(duplicate(snapshot_file)?);
+            *mem_file_fd = Some(duplicate(mem_file)?);
+            Ok(())
+        }
+        _ => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "The microVM state and guest memory file descriptors can be sent with the request, \
+             and only when snapshot_fd and mem_file_fd are not set."
+                .to_string(),
+        )),
+    }
+}
+
+// Ensures that the microVM state comes from either a path or a file descriptor, and that the
+// guest memory doesn't come from the same file descriptor.
+fn check_snapshot_fds(
+    snapshot_path: &Path,
+    snapshot_fd: Option<RawFd>,
+    mem_file_fd: Option<RawFd>,
+) -> Result<(), Error> {
+    if snapshot_path.as_os_str().is_empty() == snapshot_fd.is_none() {
+        return Err(Error::SerdeJson(serde_json::Error::custom(SNAPSHOT_SOURCE)));
+    }
+    if snapshot_fd.is_some() && snapshot_fd == mem_file_fd {
+        return Err(Error::SerdeJson(serde_json::Error::custom(SAME_FD)));
+    }
+    Ok(())
+}
+
+fn parse_put_snapshot_create(body: &Body, files: &[File]) -> Result<ParsedRequest, Error> {
+    let mut snapshot_params =
+        serde_json::from_slice::<CreateSnapshotParams>(body.raw()).map_err(Error::SerdeJson)?;
+
+    snapshot_fds_from_files(
+        files,
+        &mut snapshot_params.snapshot_fd,
+        &mut snapshot_params.mem_file_fd,
+    )?;
+    check_snapshot_fds(
+        &snapshot_params.snapshot_path,
+        snapshot_params.snapshot_fd,
+        snapshot_params.mem_file_fd,
+    )?;
+    if snapshot_params.mem_file_path.as_os_str().is_empty() == snapshot_params.mem_file_fd.is_none()
+    {
+        return Err(Error::SerdeJson(serde_json::Error::custom(MEM_FILE_SOURCE)));
+    }
+
+    Ok(ParsedRequest::new_sync(VmmAction::CreateSnapshot(
+        snapshot_params,
+    )))
+}
+
+fn parse_put_snapshot_load(body: &Body, files: &[File]) -> Result<ParsedRequest, Error> {
+    let mut snapshot_config =
         serde_json::from_slice::<LoadSnapshotConfig>(body.raw()).map_err(Error::SerdeJson)?;
 
-    match (&snapshot_config.mem_backend, &snapshot_config.mem_file_path) {
+    snapshot_fds_from_files(
+        files,
+        &mut snapshot_config.snapshot_fd,
+        &mut snapshot_config.mem_file_fd,
+    )?;
+    check_snapshot_fds(
+        &snapshot_config.snapshot_path,
+        snapshot_config.snapshot_fd,
+        snapshot_config.mem_file_fd,
+    )?;
+
+    match (
+        &snapshot_config.mem_backend,
+        &snapshot_config.mem_file_path,
+        snapshot_config.mem_file_fd,
+    ) {
+        // Ensure `mem_file_fd` is not present along with another source of guest memory.
+        (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => {
+            return Err(Error::SerdeJson(serde_json::Error::custom(MEM_FILE_SOURCE)))
+        }
         // Ensure `mem_file_path` and `mem_backend` fields are not present at the same time.
-        (Some(_), Some(_)) => {
+        (Some(_), Some(_), None) => {
             return Err(Error::SerdeJson(serde_json::Error::custom(TOO_MANY_FIELDS)))
         }
-        // Ensure that one of `mem_file_path` or `mem_backend` fields is always specified.
-        (None, None) => return Err(Error::SerdeJson(serde_json::Error::custom(MISSING_FIELD))),
+        // Ensure that one of `mem_file_path`, `mem_backend` or `mem_file_fd` fields is always
+        // specified.
+        (None, None, None) => {
+            return Err(Error::SerdeJson(serde_json::Error::custom(MISSING_FIELD)))
+        }
         _ => {}
     }
 
@@ -77,25 +289,80 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
         deprecation_message = Some(LOAD_DEPRECATION_MESSAGE);
     }
 
-    // If `mem_file_path` is specified instead of `mem_backend`, we construct the
-    // `MemBackendConfig` object from the path specified, with `File` as backend type.
+    // If `mem_file_path` or `mem_file_fd` is specified instead of `mem_backend`, we construct
+    // the `MemBackendConfig` object from the path specified, if any, with `File` as backend
+    // type.
     let mem_backend = match snapshot_config.mem_backend {
         Some(backend_cfg) => backend_cfg,
-        None => {
-            MemBackendConfig {
-                // This is safe to unwrap() because we ensure above that one of the two:
-                // either `mem_file_path` or `mem_backend` field is always specified.
-                backend_path: snapshot_config.mem_file_path.unwrap(),
-                backend_type: MemBackendType::File,
-            }
-        }
+        None => MemBackendConfig {
+            backend_path: snapshot_config.mem_file_path.unwrap_or_default(),
+            backend_type: MemBackendType::File,
+        },
     };
+    if snapshot_config.resume_mode == ResumeMode::Lazy
+        && mem_backend.backend_type != MemBackendType::File
+    {
+        return Err(Error::SerdeJson(serde_json::Error::custom(
+            LAZY_RESUME_BACKEND,
+        )));
+    }
+    if !snapshot_config.guest_mem_backend.is_anonymous()
+        && (snapshot_config.resume_mode != ResumeMode::Eager
+            || mem_backend.backend_type != MemBackendType::File)
+    {
+        return Err(Error::SerdeJson(serde_json::Error::custom(
+            HUGETLBFS_RESUME,
+        )));
+    }
+
+    let mut iface_ids = HashSet::new();
+    let mut drive_ids = HashSet::new();
+    if !snapshot_config
+        .network_overrides
+        .iter()
+        .all(|net| iface_ids.insert(net.iface_id.as_str()))
+        || !snapshot_config
+            .drive_overrides
+            .iter()
+            .all(|drive| drive_ids.insert(drive.drive_id.as_str()))
+    {
+        return Err(Error::SerdeJson(serde_json::Error::custom(
+            DUPLICATE_OVERRIDE,
+        )));
+    }
+    if let Some(clone) = snapshot_config.clone.as_ref() {
+        validate_instance_id(&clone.instance_id).map_err(|e| {
+            Error::SerdeJson(serde_json::Error::custom(format!(
+                "invalid clone instance ID: {}",
+                e
+            )))
+        })?;
+        let mut iface_ids = HashSet::new();
+        if !clone
+            .guest_macs
+            .iter()
+            .all(|mac| iface_ids.insert(mac.iface_id.as_str()))
+        {
+            return Err(Error::SerdeJson(serde_json::Error::custom(
+                DUPLICATE_GUEST_MAC,
+            )));
+        }
+    }
 
     let snapshot_params = LoadSnapshotParams {
         snapshot_path: snapshot_config.snapshot_path,
+        snapshot_fd: snapshot_config.snapshot_fd,
         mem_backend,
+        mem_file_fd: snapshot_config.mem_file_fd,
         enable_diff_snapshots: snapshot_config.enable_diff_snapshots,
         resume_vm: snapshot_config.resume_vm,
+        resume_mode: snapshot_config.resume_mode,
+        network_overrides: snapshot_config.network_overrides,
+        drive_overrides: snapshot_config.drive_overrides,
+        skip_verify: snapshot_config.skip_verify,
+        guest_mem_backend: snapshot_config.guest_mem_backend,
+        clone: snapshot_config.clone,
+        mmds_data: snapshot_config.mmds_data,
     };
 
     // Construct the `ParsedRequest` object.
@@ -111,7 +378,10 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
 
 #[cfg(test)]
 mod tests {
-    use vmm::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
+    use vmm::vmm_config::machine_config::GuestMemoryBackend;
+    use vmm::vmm_config::snapshot::{
+        DriveOverride, MemBackendConfig, MemBackendType, NetworkOverride, DEFAULT_SCHEDULE_SLOTS,
+    };
 
     use super::*;
     use crate::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
@@ -120,7 +390,7 @@ mod tests {
     fn test_parse_put_snapshot() {
         use std::path::PathBuf;
 
-        use vmm::vmm_config::snapshot::SnapshotType;
+        use vmm::vmm_config::snapshot::{SnapshotCompression, SnapshotType};
 
         let mut body = r#"{
                 "snapshot_type": "Diff",
@@ -132,12 +402,17 @@ mod tests {
         let mut expected_cfg = CreateSnapshotParams {
             snapshot_type: SnapshotType::Diff,
             snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
             mem_file_path: PathBuf::from("bar"),
+            mem_file_fd: None,
+            compression: None,
+            mem_writer_threads: None,
+            exclude_mmds: false,
             version: Some(String::from("0.23.0")),
         };
 
         match vmm_action_from_request(
-            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
         ) {
             VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
             _ => panic!("Test failed."),
@@ -151,12 +426,67 @@ mod tests {
         expected_cfg = CreateSnapshotParams {
             snapshot_type: SnapshotType::Full,
             snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
+            mem_file_path: PathBuf::from("bar"),
+            mem_file_fd: None,
+            compression: None,
+            mem_writer_threads: None,
+            exclude_mmds: false,
+            version: None,
+        };
+
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "compression": "lz4"
+              }"#;
+
+        expected_cfg = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
             mem_file_path: PathBuf::from("bar"),
+            mem_file_fd: None,
+            compression: Some(SnapshotCompression::Lz4),
+            mem_writer_threads: None,
+            exclude_mmds: false,
             version: None,
         };
 
         match vmm_action_from_request(
-            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mem_writer_threads": 8
+              }"#;
+
+        expected_cfg = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
+            mem_file_path: PathBuf::from("bar"),
+            mem_file_fd: None,
+            compression: None,
+            mem_writer_threads: Some(8),
+            exclude_mmds: false,
+            version: None,
+        };
+
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
         ) {
             VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
             _ => panic!("Test failed."),
@@ -167,7 +497,7 @@ mod tests {
                 "mem_file_path": "bar"
               }"#;
 
-        assert!(parse_put_snapshot(&Body::new(invalid_body), Some(&"create")).is_err());
+        assert!(parse_put_snapshot(&Body::new(invalid_body), Some(&"create"), &[]).is_err());
 
         body = r#"{
                 "snapshot_path": "foo",
@@ -179,15 +509,24 @@ mod tests {
 
         let mut expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
                 backend_type: MemBackendType::File,
             },
+            mem_file_fd: None,
             enable_diff_snapshots: false,
             resume_vm: false,
+            resume_mode: ResumeMode::Eager,
+            network_overrides: vec![],
+            drive_overrides: vec![],
+            skip_verify: false,
+            guest_mem_backend: GuestMemoryBackend::Anonymous,
+            clone: None,
+            mmds_data: None,
         };
 
-        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap();
+        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap();
         assert!(parsed_request
             .parsing_info()
             .take_deprecation_message()
@@ -209,15 +548,24 @@ mod tests {
 
         expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
                 backend_type: MemBackendType::File,
             },
+            mem_file_fd: None,
             enable_diff_snapshots: true,
             resume_vm: false,
+            resume_mode: ResumeMode::Eager,
+            network_overrides: vec![],
+            drive_overrides: vec![],
+            skip_verify: false,
+            guest_mem_backend: GuestMemoryBackend::Anonymous,
+            clone: None,
+            mmds_data: None,
         };
 
-        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap();
+        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap();
         assert!(parsed_request
             .parsing_info()
             .take_deprecation_message()
@@ -238,15 +586,24 @@ mod tests {
 
         expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
                 backend_type: MemBackendType::Uffd,
             },
+            mem_file_fd: None,
             enable_diff_snapshots: false,
             resume_vm: true,
+            resume_mode: ResumeMode::Eager,
+            network_overrides: vec![],
+            drive_overrides: vec![],
+            skip_verify: false,
+            guest_mem_backend: GuestMemoryBackend::Anonymous,
+            clone: None,
+            mmds_data: None,
         };
 
-        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap();
+        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap();
         assert!(parsed_request
             .parsing_info()
             .take_deprecation_message()
@@ -264,15 +621,24 @@ mod tests {
 
         expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
                 backend_type: MemBackendType::File,
             },
+            mem_file_fd: None,
             enable_diff_snapshots: false,
             resume_vm: true,
+            resume_mode: ResumeMode::Eager,
+            network_overrides: vec![],
+            drive_overrides: vec![],
+            skip_verify: false,
+            guest_mem_backend: GuestMemoryBackend::Anonymous,
+            clone: None,
+            mmds_data: None,
         };
 
-        let parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap();
+        let parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap();
         match depr_action_from_req(parsed_request, Some(LOAD_DEPRECATION_MESSAGE.to_string())) {
             VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
             _ => panic!("Test failed."),
@@ -286,7 +652,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -302,7 +668,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -320,7 +686,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -332,7 +698,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -347,16 +713,475 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
-            "An error occurred when deserializing the json body of a request: missing field \
-             `snapshot_path` at line 6 column 15."
+            Error::SerdeJson(serde_json::Error::custom(SNAPSHOT_SOURCE.to_string())).to_string()
+        );
+
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"invalid"), &[]).is_err());
+        assert!(parse_put_snapshot(&Body::new(body), None, &[]).is_err());
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "output_mem_file_path": "baz"
+              }"#;
+        let expected_cfg = MergeSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            output_mem_file_path: PathBuf::from("baz"),
+        };
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"merge"), &[]).unwrap(),
+        ) {
+            VmmAction::MergeSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_put_snapshot(
+            &Body::new(r#"{ "snapshot_path": "foo" }"#),
+            Some(&"merge"),
+            &[]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_lazy() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "resume_mode": "lazy"
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg.resume_mode, ResumeMode::Lazy),
+            _ => panic!("Test failed."),
+        }
+
+        // The UFFD backend has its own page fault handler.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "Uffd"
+                },
+                "resume_mode": "lazy"
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(LAZY_RESUME_BACKEND)).to_string()
+        );
+
+        // Only an eager resume from a file copies the guest memory in huge pages.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "guest_mem_backend": {
+                    "type": "hugetlbfs",
+                    "path": "/dev/hugepages"
+                }
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                cfg.guest_mem_backend,
+                GuestMemoryBackend::Hugetlbfs {
+                    path: PathBuf::from("/dev/hugepages")
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "resume_mode": "lazy",
+                "guest_mem_backend": {
+                    "type": "hugetlbfs",
+                    "path": "/dev/hugepages"
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(HUGETLBFS_RESUME)).to_string()
+        );
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "resume_mode": "later"
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_clone() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "clone": {
+                    "instance_id": "clone-1",
+                    "guest_macs": [
+                        {
+                            "iface_id": "eth0",
+                            "guest_mac": "06:00:ac:10:00:03"
+                        }
+                    ]
+                }
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => {
+                let clone = cfg.clone.unwrap();
+                assert_eq!(clone.instance_id, "clone-1");
+                assert_eq!(clone.guest_macs[0].iface_id, "eth0");
+                assert_eq!(
+                    clone.guest_macs[0].guest_mac.to_string(),
+                    "06:00:ac:10:00:03"
+                );
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // The ID of a clone follows the rules of the `--id` argument.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "clone": { "instance_id": "clone_1" }
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).is_err());
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "clone": {
+                    "instance_id": "clone-1",
+                    "guest_macs": [
+                        { "iface_id": "eth0", "guest_mac": "06:00:ac:10:00:03" },
+                        { "iface_id": "eth0", "guest_mac": "06:00:ac:10:00:04" }
+                    ]
+                }
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(DUPLICATE_GUEST_MAC)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_skip_verify() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "skip_verify": true
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => assert!(cfg.skip_verify),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_overrides() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "network_overrides": [
+                    {
+                        "iface_id": "eth0",
+                        "host_dev_name": "vmtap01"
+                    }
+                ],
+                "drive_overrides": [
+                    {
+                        "drive_id": "rootfs",
+                        "path_on_host": "/srv/images/rootfs.ext4"
+                    }
+                ]
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert_eq!(
+                    cfg.network_overrides,
+                    vec![NetworkOverride {
+                        iface_id: "eth0".to_string(),
+                        host_dev_name: "vmtap01".to_string(),
+                    }]
+                );
+                assert_eq!(
+                    cfg.drive_overrides,
+                    vec![DriveOverride {
+                        drive_id: "rootfs".to_string(),
+                        path_on_host: "/srv/images/rootfs.ext4".to_string(),
+                    }]
+                );
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "network_overrides": [
+                    {
+                        "iface_id": "eth0",
+                        "host_dev_name": "vmtap01"
+                    },
+                    {
+                        "iface_id": "eth0",
+                        "host_dev_name": "vmtap02"
+                    }
+                ]
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(DUPLICATE_OVERRIDE)).to_string()
+        );
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "drive_overrides": [
+                    {
+                        "drive_id": "rootfs"
+                    }
+                ]
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_with_fds() {
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        let body = r#"{
+                "snapshot_fd": 42,
+                "mem_file_fd": 43
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => {
+                assert!(cfg.snapshot_path.as_os_str().is_empty());
+                assert_eq!(cfg.snapshot_fd, Some(42));
+                assert!(cfg.mem_file_path.as_os_str().is_empty());
+                assert_eq!(cfg.mem_file_fd, Some(43));
+            }
+            _ => panic!("Test failed."),
+        }
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert_eq!(cfg.snapshot_fd, Some(42));
+                assert_eq!(cfg.mem_file_fd, Some(43));
+                assert_eq!(cfg.mem_backend.backend_type, MemBackendType::File);
+                assert!(cfg.mem_backend.backend_path.as_os_str().is_empty());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // The files sent with the request are used as the file descriptors.
+        let files = [
+            File::open("/dev/null").unwrap(),
+            File::open("/dev/null").unwrap(),
+        ];
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new("{}"), Some(&"create"), &files).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => {
+                let (snapshot_fd, mem_file_fd) =
+                    (cfg.snapshot_fd.unwrap(), cfg.mem_file_fd.unwrap());
+                assert_ne!(snapshot_fd, files[0].as_raw_fd());
+                assert_ne!(mem_file_fd, files[1].as_raw_fd());
+                // Close the duplicates.
+                drop(unsafe { File::from_raw_fd(snapshot_fd) });
+                drop(unsafe { File::from_raw_fd(mem_file_fd) });
+            }
+            _ => panic!("Test failed."),
+        }
+        // Files can't be sent when the file descriptors are set, and both are needed.
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"create"), &files).is_err());
+        assert!(parse_put_snapshot(&Body::new("{}"), Some(&"load"), &files[..1]).is_err());
+
+        let source_error =
+            |msg: &str| Error::SerdeJson(serde_json::Error::custom(msg.to_string())).to_string();
+        for (request_type, body, msg) in [
+            (
+                "create",
+                r#"{ "snapshot_path": "foo", "snapshot_fd": 42, "mem_file_path": "bar" }"#,
+                SNAPSHOT_SOURCE,
+            ),
+            ("create", r#"{ "snapshot_path": "foo" }"#, MEM_FILE_SOURCE),
+            (
+                "create",
+                r#"{ "snapshot_fd": 42, "mem_file_path": "bar", "mem_file_fd": 43 }"#,
+                MEM_FILE_SOURCE,
+            ),
+            (
+                "create",
+                r#"{ "snapshot_fd": 42, "mem_file_fd": 42 }"#,
+                SAME_FD,
+            ),
+            (
+                "load",
+                r#"{ "snapshot_fd": 42, "mem_file_path": "bar", "mem_file_fd": 43 }"#,
+                MEM_FILE_SOURCE,
+            ),
+            (
+                "load",
+                r#"{
+                    "snapshot_path": "foo",
+                    "mem_backend": { "backend_path": "bar", "backend_type": "Uffd" },
+                    "mem_file_fd": 43
+                }"#,
+                MEM_FILE_SOURCE,
+            ),
+            (
+                "load",
+                r#"{ "snapshot_fd": 42, "mem_file_fd": 42 }"#,
+                SAME_FD,
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                parse_put_snapshot(&Body::new(*body), Some(request_type), &[])
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                source_error(msg)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_mmds() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "exclude_mmds": true
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(params) => assert!(params.exclude_mmds),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mmds_data": {
+                    "latest": {
+                        "meta-data": {
+                            "token": "fresh"
+                        }
+                    }
+                }
+              }"#;
+        match depr_action_from_req(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+            Some(LOAD_DEPRECATION_MESSAGE.to_string()),
+        ) {
+            VmmAction::LoadSnapshot(params) => assert_eq!(
+                params.mmds_data,
+                Some(serde_json::json!({"latest": {"meta-data": {"token": "fresh"}}}))
+            ),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_snapshot_schedule() {
+        let body = r#"{
+                "interval_s": 300,
+                "snapshot_dir": "/srv/snapshots",
+                "max_pause_ms": 100
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"schedule"), &[])
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::ScheduleSnapshots(
+                SnapshotScheduleConfig {
+                    interval_s: 300,
+                    snapshot_dir: PathBuf::from("/srv/snapshots"),
+                    slots: DEFAULT_SCHEDULE_SLOTS,
+                    max_pause_ms: Some(100),
+                }
+            ))));
+
+        let body = r#"{
+                "interval_s": 0,
+                "snapshot_dir": "/srv/snapshots"
+              }"#;
+        assert_eq!(
+            parse_put_snapshot(&Body::new(body), Some(&"schedule"), &[])
+                .err()
+                .unwrap()
+                .to_string(),
+            Error::SerdeJson(serde_json::Error::custom(SCHEDULE_INTERVAL)).to_string()
+        );
+
+        for slots in &[1, MAX_SCHEDULE_SLOTS + 1] {
+            let body = format!(
+                r#"{{
+                    "interval_s": 300,
+                    "snapshot_dir": "/srv/snapshots",
+                    "slots": {}
+                  }}"#,
+                slots
+            );
+            assert_eq!(
+                parse_put_snapshot(&Body::new(body), Some(&"schedule"), &[])
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                Error::SerdeJson(serde_json::Error::custom(SCHEDULE_SLOTS)).to_string()
+            );
+        }
+
+        assert!(parse_delete_snapshot(Some(&"schedule"))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::CancelSnapshotSchedule)));
+        assert!(parse_delete_snapshot(Some(&"create")).is_err());
+        assert!(parse_delete_snapshot(None).is_err());
+    }
+
+    #[test]
+    fn test_parse_get_snapshot_info() {
+        assert!(
+            parse_get_snapshot_info(Some("path=/srv/snapshots/vm%201.snap"))
+                .unwrap()
+                .eq(&ParsedRequest::new_sync(VmmAction::DescribeSnapshot(
+                    PathBuf::from("/srv/snapshots/vm 1.snap")
+                )))
         );
 
-        assert!(parse_put_snapshot(&Body::new(body), Some(&"invalid")).is_err());
-        assert!(parse_put_snapshot(&Body::new(body), None).is_err());
+        assert!(parse_get_snapshot_info(None).is_err());
+        assert!(parse_get_snapshot_info(Some("path=")).is_err());
+        assert!(parse_get_snapshot_info(Some("path=/snap%2")).is_err());
+        assert!(parse_get_snapshot_info(Some("path=/snap%zz")).is_err());
+        assert!(parse_get_snapshot_info(Some("path=/snap&validate_only=true")).is_err());
     }
 
     #[test]
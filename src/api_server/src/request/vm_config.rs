@@ -0,0 +1,46 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+use vmm::resources::VmmConfig;
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_vm_config(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.vm_config_count.inc();
+    let vmm_config = serde_json::from_slice::<VmmConfig>(body.raw()).map_err(|e| {
+        METRICS.put_api_requests.vm_config_fails.inc();
+        Error::SerdeJson(e)
+    })?;
+
+    Ok(ParsedRequest::new_sync(VmmAction::PutFullVmConfig(
+        Box::new(vmm_config),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_vm_config_request() {
+        let body = r#"{
+            "boot-source": {
+                "kernel_image_path": "/foo/bar"
+            },
+            "drives": []
+        }"#;
+        let expected_config = serde_json::from_str::<VmmConfig>(body).unwrap();
+        assert!(
+            vmm_action_from_request(parse_put_vm_config(&Body::new(body)).unwrap())
+                == VmmAction::PutFullVmConfig(Box::new(expected_config))
+        );
+
+        // The boot source is mandatory, as in the configuration file.
+        assert!(parse_put_vm_config(&Body::new(r#"{"drives": []}"#)).is_err());
+        assert!(parse_put_vm_config(&Body::new("invalid_body")).is_err());
+    }
+}
@@ -2,23 +2,97 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use logger::{IncMetric, METRICS};
-use vmm::vmm_config::vsock::VsockDeviceConfig;
+use vmm::vmm_config::vsock::{VsockConnectionPoolConfig, VsockDeviceConfig, VSOCK_DEV_ID};
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
-use crate::request::Body;
+use crate::parsed_request::{checked_id, Error, ParsedRequest};
+use crate::request::{Body, StatusCode};
 
-pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, Error> {
+// The connections of the default device are at `/vsock/connections`, the ones of the other
+// devices at `/vsock/{id}/connections`.
+fn connections_device_id<'a>(path_tokens: &[&'a str]) -> Option<&'a str> {
+    match *path_tokens {
+        ["connections"] => Some(VSOCK_DEV_ID),
+        [id, "connections"] => Some(id),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_get_vsock(path_tokens: &[&str]) -> Result<ParsedRequest, Error> {
+    let id = connections_device_id(path_tokens).ok_or_else(|| {
+        Error::Generic(
+            StatusCode::BadRequest,
+            "Vsock devices only expose their connections through GET requests.".to_string(),
+        )
+    })?;
+
+    Ok(ParsedRequest::new_sync(VmmAction::GetVsockConnections(
+        checked_id(id)?.to_string(),
+    )))
+}
+
+pub(crate) fn parse_put_vsock_connections(
+    body: &Body,
+    path_tokens: &[&str],
+) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.vsock_count.inc();
+    let id = match connections_device_id(path_tokens).map(checked_id) {
+        Some(Ok(id)) => id,
+        Some(Err(e)) => {
+            METRICS.put_api_requests.vsock_fails.inc();
+            return Err(e);
+        }
+        None => {
+            METRICS.put_api_requests.vsock_fails.inc();
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                "Connections are pooled with PUT /vsock/connections or \
+                 PUT /vsock/{id}/connections."
+                    .to_string(),
+            ));
+        }
+    };
+    let pool_cfg =
+        serde_json::from_slice::<VsockConnectionPoolConfig>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.vsock_fails.inc();
+            Error::SerdeJson(e)
+        })?;
+
+    Ok(ParsedRequest::new_sync(VmmAction::PoolVsockConnections(
+        id.to_string(),
+        pool_cfg,
+    )))
+}
+
+pub(crate) fn parse_put_vsock(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.vsock_count.inc();
-    let vsock_cfg = serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(|e| {
+    let mut vsock_cfg = serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(|e| {
         METRICS.put_api_requests.vsock_fails.inc();
         Error::SerdeJson(e)
     })?;
 
-    // Check for the presence of deprecated `vsock_id` field.
     let mut deprecation_message = None;
-    if vsock_cfg.vsock_id.is_some() {
-        // vsock_id field in request is deprecated.
+    if let Some(id) = id_from_path {
+        // Devices other than the default one are identified by the path.
+        let id = checked_id(id)?;
+        if vsock_cfg
+            .vsock_id
+            .as_deref()
+            .map_or(false, |vsock_id| vsock_id != id)
+        {
+            METRICS.put_api_requests.vsock_fails.inc();
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                "The id from the path does not match the id from the body!".to_string(),
+            ));
+        }
+        vsock_cfg.vsock_id = Some(id.to_string());
+    } else if vsock_cfg.vsock_id.take().is_some() {
+        // vsock_id field in request is deprecated, and ignored: the request configures the
+        // default device.
         METRICS.deprecated_api.deprecated_http_api_calls.inc();
         deprecation_message = Some("PUT /vsock: vsock_id field is deprecated.");
     }
@@ -36,7 +110,58 @@ pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parsed_request::tests::depr_action_from_req;
+    use crate::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
+
+    #[test]
+    fn test_parse_get_vsock_request() {
+        match vmm_action_from_request(parse_get_vsock(&["connections"]).unwrap()) {
+            VmmAction::GetVsockConnections(id) => assert_eq!(id, VSOCK_DEV_ID),
+            _ => panic!("Test failed."),
+        }
+        match vmm_action_from_request(parse_get_vsock(&["data", "connections"]).unwrap()) {
+            VmmAction::GetVsockConnections(id) => assert_eq!(id, "data"),
+            _ => panic!("Test failed."),
+        }
+
+        // Only the connections can be read.
+        assert!(parse_get_vsock(&[]).is_err());
+        assert!(parse_get_vsock(&["data"]).is_err());
+        assert!(parse_get_vsock(&["data", "config"]).is_err());
+        // The id must be valid.
+        assert!(parse_get_vsock(&["foo-bar", "connections"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_vsock_connections_request() {
+        let body = r#"{
+                "port": 52,
+                "count": 8
+              }"#;
+        match vmm_action_from_request(
+            parse_put_vsock_connections(&Body::new(body), &["connections"]).unwrap(),
+        ) {
+            VmmAction::PoolVsockConnections(id, cfg) => {
+                assert_eq!(id, VSOCK_DEV_ID);
+                assert_eq!(cfg, VsockConnectionPoolConfig { port: 52, count: 8 });
+            }
+            _ => panic!("Test failed."),
+        }
+        match vmm_action_from_request(
+            parse_put_vsock_connections(&Body::new(body), &["data", "connections"]).unwrap(),
+        ) {
+            VmmAction::PoolVsockConnections(id, _) => assert_eq!(id, "data"),
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_put_vsock_connections(&Body::new(body), &["a", "b", "connections"]).is_err());
+        assert!(
+            parse_put_vsock_connections(&Body::new(body), &["foo-bar", "connections"]).is_err()
+        );
+        let body = r#"{
+                "port": 52
+              }"#;
+        assert!(parse_put_vsock_connections(&Body::new(body), &["connections"]).is_err());
+    }
 
     #[test]
     fn test_parse_put_vsock_request() {
@@ -44,13 +169,45 @@ mod tests {
                 "guest_cid": 42,
                 "uds_path": "vsock.sock"
               }"#;
-        assert!(parse_put_vsock(&Body::new(body)).is_ok());
+        assert!(parse_put_vsock(&Body::new(body), None).is_ok());
+
+        // The unix socket isn't used by the vhost datapath.
+        let body = r#"{
+                "guest_cid": 42,
+                "backend": "vhost"
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body), None).is_ok());
 
         let body = r#"{
                 "guest_cid": 42,
                 "invalid_field": false
               }"#;
-        assert!(parse_put_vsock(&Body::new(body)).is_err());
+        assert!(parse_put_vsock(&Body::new(body), None).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_vsock_with_id_request() {
+        let body = r#"{
+                "guest_cid": 42,
+                "uds_path": "vsock.sock"
+              }"#;
+        match vmm_action_from_request(parse_put_vsock(&Body::new(body), Some(&"data")).unwrap()) {
+            VmmAction::SetVsockDevice(cfg) => assert_eq!(cfg.vsock_id.as_deref(), Some("data")),
+            _ => panic!("Test failed."),
+        }
+
+        // The id from the body, if any, must match the one from the path.
+        let body = r#"{
+                "vsock_id": "data",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock"
+              }"#;
+        let (_, mut parsing_info) = parse_put_vsock(&Body::new(body), Some(&"data"))
+            .unwrap()
+            .into_parts();
+        assert!(parsing_info.take_deprecation_message().is_none());
+        assert!(parse_put_vsock(&Body::new(body), Some(&"control")).is_err());
+        assert!(parse_put_vsock(&Body::new(body), Some(&"invalid id")).is_err());
     }
 
     #[test]
@@ -60,16 +217,22 @@ mod tests {
                 "guest_cid": 42,
                 "uds_path": "vsock.sock"
               }"#;
-        depr_action_from_req(
-            parse_put_vsock(&Body::new(body)).unwrap(),
+        // The deprecated id is ignored, the request configures the default device.
+        match depr_action_from_req(
+            parse_put_vsock(&Body::new(body), None).unwrap(),
             Some("PUT /vsock: vsock_id field is deprecated.".to_string()),
-        );
+        ) {
+            VmmAction::SetVsockDevice(cfg) => assert_eq!(cfg.vsock_id, None),
+            _ => panic!("Test failed."),
+        }
 
         let body = r#"{
                 "guest_cid": 42,
                 "uds_path": "vsock.sock"
               }"#;
-        let (_, mut parsing_info) = parse_put_vsock(&Body::new(body)).unwrap().into_parts();
+        let (_, mut parsing_info) = parse_put_vsock(&Body::new(body), None)
+            .unwrap()
+            .into_parts();
         assert!(!parsing_info.take_deprecation_message().is_some());
     }
 }
@@ -0,0 +1,122 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::{CString, OsString};
+use std::fs::{self, Permissions};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
+
+use micro_http::{HttpServer, ServerError};
+
+/// Permissions given to the API socket before clients can reach it through its path.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SocketPermissions {
+    /// File mode bits of the socket.
+    pub mode: Option<u32>,
+    /// User and group IDs owning the socket.
+    pub owner: Option<(u32, u32)>,
+}
+
+/// Errors encountered when creating the API socket.
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// Failed to create the HTTP server.
+    Server(ServerError),
+    /// Failed to apply the permissions to the socket, or to move it to its path.
+    Permissions(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Server(err) => write!(f, "{}", err),
+            Error::Permissions(err) => {
+                write!(f, "Failed to set the permissions of the socket: {}", err)
+            }
+        }
+    }
+}
+
+/// Creates the HTTP server listening on `path`.
+///
+/// When `permissions` are requested, the socket is first bound to a temporary path next to
+/// `path`, and only linked to `path` once they're applied, so that clients can never connect
+/// to it with the default ones.
+pub(crate) fn bind(path: &Path, permissions: SocketPermissions) -> Result<HttpServer, Error> {
+    if permissions == SocketPermissions::default() {
+        return HttpServer::new(path).map_err(Error::Server);
+    }
+
+    let mut tmp_path = OsString::from(path);
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let server = HttpServer::new(&tmp_path).map_err(Error::Server)?;
+    // Linking fails if `path` already exists, as binding to it does.
+    let result =
+        apply_permissions(&tmp_path, permissions).and_then(|()| fs::hard_link(&tmp_path, path));
+    // The socket is either reachable through `path`, or not at all.
+    let _ = fs::remove_file(&tmp_path);
+    result.map(|()| server).map_err(Error::Permissions)
+}
+
+fn apply_permissions(path: &Path, permissions: SocketPermissions) -> io::Result<()> {
+    if let Some((uid, gid)) = permissions.owner {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // Safe because `c_path` is a valid C string, and we check the return value.
+        if unsafe { libc::chown(c_path.as_ptr(), uid, gid) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if let Some(mode) = permissions.mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_bind_with_permissions() {
+        let mut tmp_socket = TempFile::new().unwrap();
+        tmp_socket.remove().unwrap();
+        let path = tmp_socket.as_path().to_path_buf();
+
+        // Safe because these calls can't fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let permissions = SocketPermissions {
+            mode: Some(0o600),
+            owner: Some((uid, gid)),
+        };
+        let _server = bind(&path, permissions).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+        assert_eq!((metadata.uid(), metadata.gid()), (uid, gid));
+        let mut tmp_path = OsString::from(&path);
+        tmp_path.push(".tmp");
+        assert!(!Path::new(&tmp_path).exists());
+
+        // The socket isn't replaced if its path is taken.
+        assert!(matches!(
+            bind(&path, permissions),
+            Err(Error::Permissions(ref e)) if e.kind() == io::ErrorKind::AlreadyExists
+        ));
+        assert!(!Path::new(&tmp_path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = Error::Permissions(io::Error::from_raw_os_error(libc::EPERM));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use logger::{debug, error, warn, IncMetric, METRICS};
+use seccompiler::BpfProgramRef;
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+
+// Maximum number of pending connections on the vsock port.
+const LISTEN_BACKLOG: libc::c_int = 16;
+// Size of the buffer used for relaying bytes between the two ends of a connection.
+const RELAY_BUFFER_SIZE: usize = 4096;
+const EPOLL_EVENTS_LEN: usize = 32;
+
+/// Relays the connections accepted on a vsock port to the API socket, so that the API can be
+/// driven by peers which don't share a filesystem with Firecracker, e.g. a management VM.
+///
+/// The API server keeps seeing regular connections on its unix socket, so the requests
+/// received over vsock are handled exactly like the local ones.
+pub struct VsockForwarder {
+    listener: File,
+    api_socket_path: PathBuf,
+    epoll: Epoll,
+    // Both ends of the relayed connections, by file descriptor.
+    streams: HashMap<RawFd, File>,
+    // The other end of each relayed connection.
+    peers: HashMap<RawFd, RawFd>,
+    // Optional limit on the number of connections relayed at once.
+    max_connections: Option<usize>,
+}
+
+impl VsockForwarder {
+    /// Listens on `port` for vsock connections from any CID, which are relayed to the API
+    /// socket at `api_socket_path`.
+    pub fn bind(port: u32, api_socket_path: PathBuf) -> io::Result<Self> {
+        // Safe because we check the return value.
+        let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safe because we just created the socket and nothing else owns it.
+        let listener = unsafe { File::from_raw_fd(fd) };
+
+        // Safe because all the fields of the address are plain integers.
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        addr.svm_port = port;
+        addr.svm_cid = libc::VMADDR_CID_ANY;
+        // Safe because `addr` is a valid vsock address whose size we pass along, and we check
+        // the return values.
+        if unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        } < 0
+            || unsafe { libc::listen(fd, LISTEN_BACKLOG) } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let epoll = Epoll::new()?;
+        epoll.ctl(
+            ControlOperation::Add,
+            fd,
+            EpollEvent::new(EventSet::IN, fd as u64),
+        )?;
+
+        Ok(VsockForwarder {
+            listener,
+            api_socket_path,
+            epoll,
+            streams: HashMap::new(),
+            peers: HashMap::new(),
+            max_connections: None,
+        })
+    }
+
+    /// Closes the connections accepted while `max_connections` are already relayed.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = Some(max_connections);
+    }
+
+    /// Applies `seccomp_filter` on the current thread, then relays the connections until an
+    /// unrecoverable error occurs.
+    pub fn run(&mut self, seccomp_filter: BpfProgramRef) -> io::Result<()> {
+        if let Err(e) = seccompiler::apply_filter(seccomp_filter) {
+            panic!(
+                "Failed to set the requested seccomp filters on the API vsock thread: {}",
+                e
+            );
+        }
+
+        let mut events = vec![EpollEvent::new(EventSet::empty(), 0); EPOLL_EVENTS_LEN];
+        loop {
+            let count = match self.epoll.wait(-1, &mut events[..]) {
+                Ok(count) => count,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            for event in events.iter().take(count) {
+                let fd = event.data() as RawFd;
+                if fd == self.listener.as_raw_fd() {
+                    self.accept();
+                } else {
+                    self.relay(fd);
+                }
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        // Safe because the listener is a valid socket, and we check the return value.
+        let fd = unsafe {
+            libc::accept4(
+                self.listener.as_raw_fd(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                libc::SOCK_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            error!(
+                "Failed to accept a vsock API connection: {}",
+                io::Error::last_os_error()
+            );
+            return;
+        }
+        // Safe because we just accepted the connection and nothing else owns it.
+        let vsock_stream = unsafe { File::from_raw_fd(fd) };
+
+        // Both ends of each connection are tracked.
+        if let Some(max_connections) = self.max_connections {
+            if self.streams.len() / 2 >= max_connections {
+                METRICS.api_server.vsock_rejected_connections_count.inc();
+                warn!(
+                    "Closing a vsock API connection, {} connections are already relayed.",
+                    max_connections
+                );
+                return;
+            }
+        }
+
+        let api_stream = match UnixStream::connect(&self.api_socket_path) {
+            // Both ends are only read from and written to, so they can be handled alike.
+            // Safe because the fd is released by the stream.
+            Ok(stream) => unsafe { File::from_raw_fd(stream.into_raw_fd()) },
+            Err(e) => {
+                error!("Failed to connect to the API socket: {}", e);
+                return;
+            }
+        };
+
+        for stream in [&vsock_stream, &api_stream].iter() {
+            let fd = stream.as_raw_fd();
+            if let Err(e) = self.epoll.ctl(
+                ControlOperation::Add,
+                fd,
+                EpollEvent::new(EventSet::IN, fd as u64),
+            ) {
+                error!("Failed to register a vsock API connection: {}", e);
+                // Closing the streams also removes them from the epoll set.
+                return;
+            }
+        }
+
+        debug!("Accepted a vsock API connection.");
+        let (vsock_fd, api_fd) = (vsock_stream.as_raw_fd(), api_stream.as_raw_fd());
+        self.peers.insert(vsock_fd, api_fd);
+        self.peers.insert(api_fd, vsock_fd);
+        self.streams.insert(vsock_fd, vsock_stream);
+        self.streams.insert(api_fd, api_stream);
+    }
+
+    fn relay(&mut self, fd: RawFd) {
+        let peer_fd = match self.peers.get(&fd) {
+            Some(peer_fd) => *peer_fd,
+            // Already closed while handling a previous event.
+            None => return,
+        };
+
+        let mut buf = [0u8; RELAY_BUFFER_SIZE];
+        // It is safe to unwrap because both ends of a connection are inserted and removed
+        // together.
+        let result = match self.streams.get_mut(&fd).unwrap().read(&mut buf) {
+            Ok(0) => Err(None),
+            // Both sockets are blocking, so a slow reader holds back the other connections.
+            // The API server drains its connections promptly, and the replies are small.
+            Ok(len) => self
+                .streams
+                .get_mut(&peer_fd)
+                .unwrap()
+                .write_all(&buf[..len])
+                .map_err(Some),
+            Err(e) => Err(Some(e)),
+        };
+
+        if let Err(maybe_err) = result {
+            if let Some(e) = maybe_err {
+                error!("Failed to relay a vsock API connection: {}", e);
+            }
+            // Dropping the streams closes them and removes them from the epoll set.
+            for fd in [fd, peer_fd].iter() {
+                self.peers.remove(fd);
+                self.streams.remove(fd);
+            }
+            debug!("Closed a vsock API connection.");
+        }
+    }
+}
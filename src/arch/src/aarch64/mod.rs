@@ -33,6 +33,9 @@ pub enum Error {
 pub const MMIO_MEM_START: u64 = layout::MAPPED_IO_START;
 /// The size of the memory area reserved for MMIO devices.
 pub const MMIO_MEM_SIZE: u64 = layout::DRAM_MEM_START - layout::MAPPED_IO_START; //>> 1GB
+/// The end of the guest memory, which is also the end of the 40-bit IPA space KVM gives to VMs
+/// by default.
+pub const GUEST_MEM_END: u64 = layout::DRAM_MEM_START + layout::DRAM_MEM_MAX_SIZE;
 
 /// Returns a Vec of the valid memory addresses for aarch64.
 /// See [`layout`](layout) module for a drawing of the specific memory model for this platform.
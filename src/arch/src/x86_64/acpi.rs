@@ -0,0 +1,497 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writes the minimal set of ACPI tables letting the guest be asked to power off, and power off.
+//!
+//! The platform is described as hardware-reduced, so there are no fixed feature registers. The
+//! DSDT holds a power button, whose presses are notified by a Generic Event Device (GED), and
+//! the `\_S5` soft-off state, entered by writing to the sleep control register of the FADT.
+use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+
+use super::layout::RSDP_START;
+use super::mptable::{APIC_DEFAULT_PHYS_BASE, IO_APIC_DEFAULT_PHYS_BASE};
+use super::Error;
+
+const OEM_ID: &[u8; 6] = b"FIRECK";
+const OEM_TABLE_ID: &[u8; 8] = b"FCMVMTBL";
+const OEM_REVISION: u32 = 0;
+const CREATOR_ID: &[u8; 4] = b"FCAT";
+const CREATOR_REVISION: u32 = 0;
+
+const HEADER_LEN: usize = 36;
+const CHECKSUM_OFFSET: usize = 9;
+const RSDP_LEN: usize = 36;
+// The RSDP checksum only covers the fields of its first revision.
+const RSDP_V1_LEN: usize = 20;
+
+// Offsets and flags of the FADT fields, from the ACPI 6.0 specification.
+const FADT_LEN: usize = 276;
+const FADT_DSDT_OFFSET: usize = 40;
+const FADT_IAPC_BOOT_ARCH_OFFSET: usize = 109;
+const FADT_FLAGS_OFFSET: usize = 112;
+const FADT_X_DSDT_OFFSET: usize = 140;
+const FADT_SLEEP_CONTROL_REG_OFFSET: usize = 244;
+const FADT_SLEEP_STATUS_REG_OFFSET: usize = 256;
+// The i8042 keyboard controller is kept, for Ctrl+Alt+Del.
+const IAPC_BOOT_ARCH_8042: u16 = 1 << 1;
+const IAPC_BOOT_ARCH_VGA_NOT_PRESENT: u16 = 1 << 2;
+// There are no fixed feature power and sleep buttons.
+const FADT_FLAGS_PWR_BUTTON: u32 = 1 << 4;
+const FADT_FLAGS_SLP_BUTTON: u32 = 1 << 5;
+const FADT_FLAGS_HW_REDUCED_ACPI: u32 = 1 << 20;
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_LOCAL_APIC_ENABLED: u32 = 1;
+
+// Sleep type of the soft-off state, written by the guest to the sleep control register.
+const S5_SLEEP_TYPE: u8 = 5;
+// Notification of the power button being pressed.
+const POWER_BUTTON_PRESSED: u8 = 0x80;
+
+/// Writes the ACPI tables in `guest_mem`, starting with the RSDP at `RSDP_START`.
+///
+/// # Arguments
+///
+/// * `guest_mem` - The memory to be used by the guest.
+/// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `ged_irq` - Interrupt raised by the Generic Event Device when the power button is pressed.
+/// * `sleep_control_addr` - Address of the sleep control register, a single byte.
+/// * `sleep_status_addr` - Address of the sleep status register, a single byte.
+pub fn setup_acpi_tables(
+    guest_mem: &GuestMemoryMmap,
+    num_cpus: u8,
+    ged_irq: u32,
+    sleep_control_addr: u64,
+    sleep_status_addr: u64,
+) -> crate::Result<()> {
+    let mut tables = Vec::new();
+    let mut next_addr = RSDP_START + RSDP_LEN as u64;
+    let mut place = |table: Vec<u8>| {
+        let addr = next_addr;
+        // Keep the tables 8 bytes aligned.
+        next_addr += (table.len() as u64 + 7) & !7;
+        tables.push((addr, table));
+        addr
+    };
+
+    let dsdt_addr = place(dsdt(ged_irq));
+    let fadt_addr = place(fadt(dsdt_addr, sleep_control_addr, sleep_status_addr));
+    let madt_addr = place(madt(num_cpus));
+    let xsdt_addr = place(xsdt(&[fadt_addr, madt_addr]));
+    tables.push((RSDP_START, rsdp(xsdt_addr)));
+
+    for (addr, table) in tables.iter() {
+        guest_mem
+            .write_slice(table, GuestAddress(*addr))
+            .map_err(|_| Error::AcpiTablesSetup)?;
+    }
+    Ok(())
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    0u8.wrapping_sub(bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)))
+}
+
+// Prepends the common header to the `body` of a table.
+fn table(signature: &[u8; 4], revision: u8, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(signature);
+    bytes.extend_from_slice(&((HEADER_LEN + body.len()) as u32).to_le_bytes());
+    bytes.push(revision);
+    // The checksum is computed once the table is complete.
+    bytes.push(0);
+    bytes.extend_from_slice(OEM_ID);
+    bytes.extend_from_slice(OEM_TABLE_ID);
+    bytes.extend_from_slice(&OEM_REVISION.to_le_bytes());
+    bytes.extend_from_slice(CREATOR_ID);
+    bytes.extend_from_slice(&CREATOR_REVISION.to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes[CHECKSUM_OFFSET] = checksum(&bytes);
+    bytes
+}
+
+fn rsdp(xsdt_addr: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(RSDP_LEN);
+    bytes.extend_from_slice(b"RSD PTR ");
+    bytes.push(0);
+    bytes.extend_from_slice(OEM_ID);
+    bytes.push(2);
+    // There is no RSDT, only the XSDT.
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&(RSDP_LEN as u32).to_le_bytes());
+    bytes.extend_from_slice(&xsdt_addr.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes[8] = checksum(&bytes[..RSDP_V1_LEN]);
+    bytes[32] = checksum(&bytes);
+    bytes
+}
+
+fn xsdt(table_addrs: &[u64]) -> Vec<u8> {
+    let body: Vec<u8> = table_addrs
+        .iter()
+        .flat_map(|addr| addr.to_le_bytes().to_vec())
+        .collect();
+    table(b"XSDT", 1, &body)
+}
+
+// Generic Address Structure of a byte wide register in memory.
+fn byte_register(addr: u64) -> Vec<u8> {
+    // System memory address space, 8 bits wide, at offset 0, accessed by bytes.
+    let mut bytes = vec![0, 8, 0, 1];
+    bytes.extend_from_slice(&addr.to_le_bytes());
+    bytes
+}
+
+fn fadt(dsdt_addr: u64, sleep_control_addr: u64, sleep_status_addr: u64) -> Vec<u8> {
+    let mut body = vec![0; FADT_LEN - HEADER_LEN];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        let start = offset - HEADER_LEN;
+        body[start..start + bytes.len()].copy_from_slice(bytes);
+    };
+
+    put(FADT_DSDT_OFFSET, &(dsdt_addr as u32).to_le_bytes());
+    put(
+        FADT_IAPC_BOOT_ARCH_OFFSET,
+        &(IAPC_BOOT_ARCH_8042 | IAPC_BOOT_ARCH_VGA_NOT_PRESENT).to_le_bytes(),
+    );
+    put(
+        FADT_FLAGS_OFFSET,
+        &(FADT_FLAGS_PWR_BUTTON | FADT_FLAGS_SLP_BUTTON | FADT_FLAGS_HW_REDUCED_ACPI).to_le_bytes(),
+    );
+    put(FADT_X_DSDT_OFFSET, &dsdt_addr.to_le_bytes());
+    put(
+        FADT_SLEEP_CONTROL_REG_OFFSET,
+        &byte_register(sleep_control_addr),
+    );
+    put(
+        FADT_SLEEP_STATUS_REG_OFFSET,
+        &byte_register(sleep_status_addr),
+    );
+    table(b"FACP", 6, &body)
+}
+
+fn madt(num_cpus: u8) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&APIC_DEFAULT_PHYS_BASE.to_le_bytes());
+    // There are no 8259 PICs.
+    body.extend_from_slice(&0u32.to_le_bytes());
+    for cpu_id in 0..num_cpus {
+        body.extend_from_slice(&[MADT_LOCAL_APIC, 8, cpu_id, cpu_id]);
+        body.extend_from_slice(&MADT_LOCAL_APIC_ENABLED.to_le_bytes());
+    }
+    // Same ID as in the MP table.
+    body.extend_from_slice(&[MADT_IO_APIC, 12, num_cpus + 1, 0]);
+    body.extend_from_slice(&IO_APIC_DEFAULT_PHYS_BASE.to_le_bytes());
+    // The global system interrupts are those of the IO APIC.
+    body.extend_from_slice(&0u32.to_le_bytes());
+    table(b"APIC", 5, &body)
+}
+
+fn dsdt(ged_irq: u32) -> Vec<u8> {
+    let mut body = aml::scope(
+        "\\_SB_",
+        &[
+            aml::device(
+                "PWRB",
+                &[
+                    aml::name("_HID", &aml::eisa_id("PNP0C0C")),
+                    aml::name("_UID", &aml::integer(0)),
+                ],
+            ),
+            aml::device(
+                "GED0",
+                &[
+                    aml::name("_HID", &aml::string("ACPI0013")),
+                    aml::name("_UID", &aml::integer(0)),
+                    aml::name("_CRS", &aml::buffer(&aml::interrupt(ged_irq))),
+                    // The power button is the only event.
+                    aml::method(
+                        "_EVT",
+                        1,
+                        &[aml::notify("\\_SB_.PWRB", u32::from(POWER_BUTTON_PRESSED))],
+                    ),
+                ],
+            ),
+        ],
+    );
+    body.extend(aml::name(
+        "\\_S5_",
+        &aml::package(&[
+            aml::integer(u32::from(S5_SLEEP_TYPE)),
+            aml::integer(u32::from(S5_SLEEP_TYPE)),
+        ]),
+    ));
+    table(b"DSDT", 2, &body)
+}
+
+// Encodings of the few AML objects used by the DSDT, from chapter 20 of the ACPI specification.
+mod aml {
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+    const NAME_OP: u8 = 0x08;
+    const BYTE_PREFIX: u8 = 0x0a;
+    const WORD_PREFIX: u8 = 0x0b;
+    const DWORD_PREFIX: u8 = 0x0c;
+    const STRING_PREFIX: u8 = 0x0d;
+    const SCOPE_OP: u8 = 0x10;
+    const BUFFER_OP: u8 = 0x11;
+    const PACKAGE_OP: u8 = 0x12;
+    const METHOD_OP: u8 = 0x14;
+    const DUAL_NAME_PREFIX: u8 = 0x2e;
+    const MULTI_NAME_PREFIX: u8 = 0x2f;
+    const EXT_OP_PREFIX: u8 = 0x5b;
+    const ROOT_CHAR: u8 = 0x5c;
+    const DEVICE_OP: u8 = 0x82;
+    const NOTIFY_OP: u8 = 0x86;
+
+    const EXTENDED_INTERRUPT: u8 = 0x89;
+    // Consumed, edge triggered, active high and exclusive.
+    const INTERRUPT_FLAGS: u8 = 0b0011;
+    const END_TAG: u8 = 0x79;
+
+    // Encodes the length of a package holding `len` bytes. The encoding counts its own bytes.
+    pub(super) fn pkg_length(len: usize) -> Vec<u8> {
+        if len + 1 < 1 << 6 {
+            return vec![(len + 1) as u8];
+        }
+        // The lead byte holds the number of the following bytes along with the low 4 bits.
+        let extra_bytes = (1..=3)
+            .find(|extra| len + 1 + extra < 1 << (4 + 8 * extra))
+            .expect("AML package too large");
+        let total = len + 1 + extra_bytes;
+        let mut bytes = vec![((extra_bytes << 6) | (total & 0xf)) as u8];
+        for i in 0..extra_bytes {
+            bytes.push((total >> (4 + 8 * i)) as u8);
+        }
+        bytes
+    }
+
+    // Encodes an absolute path (starting with `\`) or a relative one, made of 4 character
+    // segments separated by dots.
+    pub(super) fn name_string(path: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let path = match path.strip_prefix('\\') {
+            Some(path) => {
+                bytes.push(ROOT_CHAR);
+                path
+            }
+            None => path,
+        };
+        let segments: Vec<&str> = path.split('.').collect();
+        match segments.len() {
+            1 => (),
+            2 => bytes.push(DUAL_NAME_PREFIX),
+            count => bytes.extend_from_slice(&[MULTI_NAME_PREFIX, count as u8]),
+        }
+        for segment in segments {
+            assert_eq!(segment.len(), 4, "Invalid AML name segment: {}", segment);
+            bytes.extend_from_slice(segment.as_bytes());
+        }
+        bytes
+    }
+
+    fn with_pkg_length(opcode: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut bytes = opcode.to_vec();
+        bytes.extend(pkg_length(contents.len()));
+        bytes.extend_from_slice(contents);
+        bytes
+    }
+
+    fn named_contents(path: &str, objects: &[Vec<u8>]) -> Vec<u8> {
+        let mut contents = name_string(path);
+        contents.extend(objects.concat());
+        contents
+    }
+
+    pub(super) fn integer(value: u32) -> Vec<u8> {
+        match value {
+            0 => vec![ZERO_OP],
+            1 => vec![ONE_OP],
+            2..=0xff => vec![BYTE_PREFIX, value as u8],
+            0x100..=0xffff => {
+                let mut bytes = vec![WORD_PREFIX];
+                bytes.extend_from_slice(&(value as u16).to_le_bytes());
+                bytes
+            }
+            _ => {
+                let mut bytes = vec![DWORD_PREFIX];
+                bytes.extend_from_slice(&value.to_le_bytes());
+                bytes
+            }
+        }
+    }
+
+    pub(super) fn string(value: &str) -> Vec<u8> {
+        let mut bytes = vec![STRING_PREFIX];
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    // Compresses a 7 character PNP ID, e.g. PNP0C0C, into an integer.
+    pub(super) fn eisa_id(id: &str) -> Vec<u8> {
+        let id = id.as_bytes();
+        let vendor = id[..3]
+            .iter()
+            .fold(0u16, |vendor, c| (vendor << 5) | u16::from(c - b'@'));
+        let product = u16::from_str_radix(std::str::from_utf8(&id[3..]).unwrap(), 16)
+            .expect("Invalid EISA ID");
+        let mut bytes = vendor.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&product.to_be_bytes());
+        integer(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(super) fn buffer(data: &[u8]) -> Vec<u8> {
+        let mut contents = integer(data.len() as u32);
+        contents.extend_from_slice(data);
+        with_pkg_length(&[BUFFER_OP], &contents)
+    }
+
+    pub(super) fn package(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut contents = vec![elements.len() as u8];
+        contents.extend(elements.concat());
+        with_pkg_length(&[PACKAGE_OP], &contents)
+    }
+
+    // Resource template holding a single interrupt.
+    pub(super) fn interrupt(irq: u32) -> Vec<u8> {
+        let mut bytes = vec![EXTENDED_INTERRUPT, 6, 0, INTERRUPT_FLAGS, 1];
+        bytes.extend_from_slice(&irq.to_le_bytes());
+        // A null checksum is always valid.
+        bytes.extend_from_slice(&[END_TAG, 0]);
+        bytes
+    }
+
+    pub(super) fn name(path: &str, value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![NAME_OP];
+        bytes.extend(name_string(path));
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    pub(super) fn scope(path: &str, objects: &[Vec<u8>]) -> Vec<u8> {
+        with_pkg_length(&[SCOPE_OP], &named_contents(path, objects))
+    }
+
+    pub(super) fn device(path: &str, objects: &[Vec<u8>]) -> Vec<u8> {
+        with_pkg_length(&[EXT_OP_PREFIX, DEVICE_OP], &named_contents(path, objects))
+    }
+
+    pub(super) fn method(path: &str, arg_count: u8, statements: &[Vec<u8>]) -> Vec<u8> {
+        let mut contents = name_string(path);
+        // Not serialized, at sync level 0.
+        contents.push(arg_count & 0x7);
+        contents.extend(statements.concat());
+        with_pkg_length(&[METHOD_OP], &contents)
+    }
+
+    pub(super) fn notify(path: &str, value: u32) -> Vec<u8> {
+        let mut bytes = vec![NOTIFY_OP];
+        bytes.extend(name_string(path));
+        bytes.extend(integer(value));
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_table(mem: &GuestMemoryMmap, addr: u64) -> Vec<u8> {
+        let len: u32 = mem.read_obj(GuestAddress(addr + 4)).unwrap();
+        let mut bytes = vec![0; len as usize];
+        mem.read_slice(&mut bytes, GuestAddress(addr)).unwrap();
+        assert_eq!(checksum(&bytes), 0);
+        bytes
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+        let mut value = [0; 8];
+        value.copy_from_slice(&bytes[offset..offset + 8]);
+        u64::from_le_bytes(value)
+    }
+
+    #[test]
+    fn test_setup_acpi_tables() {
+        let mem =
+            vm_memory::test_utils::create_anon_guest_memory(&[(GuestAddress(0), 0x10_0000)], false)
+                .unwrap();
+        setup_acpi_tables(&mem, 2, 5, 0xd000_0000, 0xd000_0001).unwrap();
+
+        let mut rsdp = vec![0; RSDP_LEN];
+        mem.read_slice(&mut rsdp, GuestAddress(RSDP_START)).unwrap();
+        assert_eq!(&rsdp[..8], b"RSD PTR ");
+        assert_eq!(checksum(&rsdp[..RSDP_V1_LEN]), 0);
+        assert_eq!(checksum(&rsdp), 0);
+
+        let xsdt = read_table(&mem, read_u64(&rsdp, 24));
+        assert_eq!(&xsdt[..4], b"XSDT");
+        assert_eq!(xsdt.len(), HEADER_LEN + 16);
+
+        let fadt = read_table(&mem, read_u64(&xsdt, HEADER_LEN));
+        assert_eq!(&fadt[..4], b"FACP");
+        assert_eq!(fadt.len(), FADT_LEN);
+        assert_ne!(fadt[FADT_FLAGS_OFFSET + 2] & 0x10, 0);
+        assert_eq!(
+            read_u64(&fadt, FADT_SLEEP_CONTROL_REG_OFFSET + 4),
+            0xd000_0000
+        );
+        assert_eq!(
+            read_u64(&fadt, FADT_SLEEP_STATUS_REG_OFFSET + 4),
+            0xd000_0001
+        );
+
+        let madt = read_table(&mem, read_u64(&xsdt, HEADER_LEN + 8));
+        assert_eq!(&madt[..4], b"APIC");
+        assert_eq!(madt.len(), HEADER_LEN + 8 + 2 * 8 + 12);
+
+        let dsdt = read_table(&mem, read_u64(&fadt, FADT_X_DSDT_OFFSET));
+        assert_eq!(&dsdt[..4], b"DSDT");
+        let aml = &dsdt[HEADER_LEN..];
+        // The interrupt of the GED.
+        assert!(aml
+            .windows(9)
+            .any(|window| window == [0x89, 6, 0, 3, 1, 5, 0, 0, 0]));
+        // The sleep type of \_S5.
+        assert!(aml.ends_with(&[0x12, 0x06, 0x02, 0x0a, 0x05, 0x0a, 0x05]));
+
+        // The tables need to fit before the kernel.
+        let small_mem = vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), RSDP_START as usize)],
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            setup_acpi_tables(&small_mem, 1, 5, 0, 0),
+            Err(Error::AcpiTablesSetup)
+        );
+    }
+
+    #[test]
+    fn test_aml() {
+        assert_eq!(aml::pkg_length(0x3e), [0x3f]);
+        assert_eq!(aml::pkg_length(0x3f), [0x41, 0x04]);
+        assert_eq!(aml::pkg_length(0xffd), [0x4f, 0xff]);
+        assert_eq!(aml::pkg_length(0xffe), [0x81, 0x00, 0x01]);
+
+        assert_eq!(aml::name_string("PWRB"), b"PWRB");
+        assert_eq!(aml::name_string("\\_SB_.PWRB"), b"\\\x2e_SB_PWRB");
+        assert_eq!(
+            aml::name_string("\\_SB_.PCI0.FOO_"),
+            b"\\\x2f\x03_SB_PCI0FOO_"
+        );
+
+        assert_eq!(aml::integer(0), [0x00]);
+        assert_eq!(aml::integer(0x80), [0x0a, 0x80]);
+        assert_eq!(aml::integer(0x1234), [0x0b, 0x34, 0x12]);
+        assert_eq!(aml::eisa_id("PNP0C0C"), [0x0c, 0x41, 0xd0, 0x0c, 0x0c]);
+        assert_eq!(aml::string("ACPI0013"), b"\x0dACPI0013\x00");
+
+        // Name(_S5, Package() { 5 })
+        assert_eq!(
+            aml::name("_S5_", &aml::package(&[aml::integer(5)])),
+            [0x08, b'_', b'S', b'5', b'_', 0x12, 0x04, 0x01, 0x0a, 0x05]
+        );
+    }
+}
@@ -15,6 +15,9 @@ pub const CMDLINE_START: u64 = 0x20000;
 /// Kernel command line start address maximum size.
 pub const CMDLINE_MAX_SIZE: usize = 0x10000;
 
+/// Address of the ACPI RSDP, in the BIOS area searched by the guest.
+pub const RSDP_START: u64 = 0x000e_0000;
+
 /// Start of the high memory.
 pub const HIMEM_START: u64 = 0x0010_0000; // 1 MB.
 
@@ -5,6 +5,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+/// Minimal ACPI tables, for powering off the guest.
+pub mod acpi;
 mod gdt;
 /// Contains logic for setting up Advanced Programmable Interrupt Controller (local version).
 pub mod interrupts;
@@ -37,6 +39,8 @@ pub enum Error {
     ZeroPageSetup,
     /// Failed to compute initrd address.
     InitrdAddress,
+    /// Error writing the ACPI tables to memory.
+    AcpiTablesSetup,
 }
 
 // Where BIOS/VGA magic would live on a real PC.
@@ -48,6 +52,9 @@ pub const MEM_32BIT_GAP_SIZE: u64 = 768 << 20;
 pub const MMIO_MEM_START: u64 = FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE;
 /// The size of the memory area reserved for MMIO devices.
 pub const MMIO_MEM_SIZE: u64 = MEM_32BIT_GAP_SIZE;
+/// The end of the guest memory, 1TiB. This keeps the guest physical addresses within 40 bits,
+/// which all the supported hosts can map.
+pub const GUEST_MEM_END: u64 = 1 << 40;
 
 /// Returns a Vec of the valid memory addresses.
 /// These should be used to configure the GuestMemoryMmap structure for the platform.
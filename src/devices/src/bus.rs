@@ -134,6 +134,12 @@ impl Bus {
         Ok(())
     }
 
+    /// Removes the device registered at `base`, returning it if there was one.
+    pub fn remove(&mut self, base: u64) -> Option<Arc<Mutex<dyn BusDevice>>> {
+        // The length doesn't take part in the comparison of ranges.
+        self.devices.remove(&BusRange(base, 0))
+    }
+
     /// Reads data from the device that owns the range containing `addr` and puts it into `data`.
     ///
     /// Returns true on success, otherwise `data` is untouched.
@@ -215,6 +221,26 @@ mod tests {
         assert!(bus.insert(dummy, 0x0, 0x10).is_ok());
     }
 
+    #[test]
+    fn bus_remove() {
+        let mut bus = Bus::new();
+        let dummy = Arc::new(Mutex::new(DummyDevice));
+        assert!(bus.insert(dummy.clone(), 0x10, 0x10).is_ok());
+        assert!(bus.insert(dummy, 0x20, 0x10).is_ok());
+
+        // Only devices registered at the exact base address are removed.
+        assert!(bus.remove(0x11).is_none());
+        assert!(bus.remove(0x10).is_some());
+        assert!(bus.remove(0x10).is_none());
+        assert!(bus.get_device(0x10).is_none());
+        assert!(bus.get_device(0x20).is_some());
+
+        // The range can be reused.
+        assert!(bus
+            .insert(Arc::new(Mutex::new(DummyDevice)), 0x10, 0x10)
+            .is_ok());
+    }
+
     #[test]
     fn bus_read_write() {
         let mut bus = Bus::new();
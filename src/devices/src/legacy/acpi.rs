@@ -0,0 +1,137 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use logger::{error, info, warn};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+use utils::eventfd::EventFd;
+
+use crate::bus::BusDevice;
+
+/// Offset of the sleep control register, described by the FADT.
+pub const SLEEP_CONTROL_OFFSET: u64 = 0;
+/// Offset of the sleep status register, described by the FADT.
+pub const SLEEP_STATUS_OFFSET: u64 = 1;
+
+// Bit of the sleep control register entering the requested sleep state. The soft-off state is
+// the only one declared to the guest, so this powers the microVM off.
+const SLEEP_ENABLE: u8 = 1 << 5;
+
+/// Hardware-reduced ACPI platform device.
+///
+/// It presses the power button, by raising the interrupt of the Generic Event Device, and stops
+/// the microVM when the guest enters the soft-off state through the sleep control register, or
+/// when it doesn't do so in time after the button was pressed.
+pub struct AcpiDevice {
+    // Raises the interrupt of the Generic Event Device.
+    interrupt_evt: EventFd,
+    // Stops the microVM.
+    exit_evt: EventFd,
+    // Expires when the guest didn't power off in time.
+    shutdown_timer: TimerFd,
+}
+
+impl AcpiDevice {
+    /// Creates the device, writing to `exit_evt` for stopping the microVM.
+    pub fn new(exit_evt: EventFd) -> io::Result<AcpiDevice> {
+        Ok(AcpiDevice {
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            exit_evt,
+            shutdown_timer: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
+        })
+    }
+
+    /// Returns the event raising the interrupt of the Generic Event Device.
+    pub fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    /// Presses the power button, and stops the microVM if the guest isn't powered off within
+    /// `timeout`.
+    pub fn press_power_button(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.interrupt_evt.write(1)?;
+        if let Some(timeout) = timeout {
+            self.shutdown_timer
+                .set_state(TimerState::Oneshot(timeout), SetTimeFlags::Default);
+        }
+        Ok(())
+    }
+
+    fn power_off(&self) {
+        if let Err(e) = self.exit_evt.write(1) {
+            error!("Failed to signal the microVM power off: {}", e);
+        }
+    }
+}
+
+impl BusDevice for AcpiDevice {
+    fn read(&mut self, _offset: u64, data: &mut [u8]) {
+        // The microVM never wakes up, so there is nothing to report in the sleep status register.
+        for byte in data.iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if offset == SLEEP_CONTROL_OFFSET && data.len() == 1 && data[0] & SLEEP_ENABLE != 0 {
+            info!("The guest powered off.");
+            self.power_off();
+        }
+    }
+}
+
+impl MutEventSubscriber for AcpiDevice {
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        if event.fd() == self.shutdown_timer.as_raw_fd() && event.event_set() == EventSet::IN {
+            // Consume the expiration of the timer.
+            self.shutdown_timer.read();
+            warn!("The guest didn't power off in time, stopping the microVM.");
+            self.power_off();
+        } else {
+            error!("Spurious EventManager event for handler: AcpiDevice");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(e) = ops.add(Events::new(&self.shutdown_timer, EventSet::IN)) {
+            error!("Failed to register the ACPI shutdown timer: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acpi_device() {
+        let exit_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut acpi = AcpiDevice::new(exit_evt.try_clone().unwrap()).unwrap();
+
+        acpi.press_power_button(None).unwrap();
+        assert_eq!(acpi.interrupt_evt().read().unwrap(), 1);
+        assert_eq!(acpi.shutdown_timer.get_state(), TimerState::Disarmed);
+
+        // Only entering a sleep state powers off.
+        let mut data = [0xff];
+        acpi.read(SLEEP_STATUS_OFFSET, &mut data);
+        assert_eq!(data, [0]);
+        acpi.write(SLEEP_CONTROL_OFFSET, &[5 << 2]);
+        acpi.write(SLEEP_STATUS_OFFSET, &[SLEEP_ENABLE]);
+        assert!(exit_evt.read().is_err());
+        acpi.write(SLEEP_CONTROL_OFFSET, &[(5 << 2) | SLEEP_ENABLE]);
+        assert_eq!(exit_evt.read().unwrap(), 1);
+
+        acpi.press_power_button(Some(Duration::from_secs(10)))
+            .unwrap();
+        assert!(matches!(
+            acpi.shutdown_timer.get_state(),
+            TimerState::Oneshot(_)
+        ));
+    }
+}
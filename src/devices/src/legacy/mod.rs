@@ -5,6 +5,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+#[cfg(target_arch = "x86_64")]
+mod acpi;
 mod i8042;
 #[cfg(target_arch = "aarch64")]
 mod rtc_pl031;
@@ -16,6 +18,8 @@ use std::ops::Deref;
 use utils::eventfd::EventFd;
 use vm_superio::Trigger;
 
+#[cfg(target_arch = "x86_64")]
+pub use self::acpi::{AcpiDevice, SLEEP_CONTROL_OFFSET, SLEEP_STATUS_OFFSET};
 pub use self::i8042::{Error as I8042DeviceError, I8042Device};
 #[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::RTCDevice;
@@ -30,6 +30,16 @@ pub(crate) fn report_balloon_event_fail(err: virtio::balloon::Error) {
     METRICS.balloon.event_fails.inc();
 }
 
+pub(crate) fn report_mem_event_fail(err: virtio::mem::Error) {
+    error!("{:?}", err);
+    METRICS.memory_hotplug.event_fails.inc();
+}
+
+pub(crate) fn report_pmem_event_fail(err: virtio::pmem::Error) {
+    error!("{:?}", err);
+    METRICS.pmem.event_fails.inc();
+}
+
 #[derive(Debug)]
 pub enum Error {
     /// Failed to read from the TAP device.
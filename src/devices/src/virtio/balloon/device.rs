@@ -18,8 +18,9 @@ use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
 use super::super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BALLOON};
 use super::utils::{compact_page_frame_numbers, remove_range};
 use super::{
-    BALLOON_DEV_ID, DEFLATE_INDEX, INFLATE_INDEX, MAX_PAGES_IN_DESC, MAX_PAGE_COMPACT_BUFFER,
-    MIB_TO_4K_PAGES, NUM_QUEUES, QUEUE_SIZES, STATS_INDEX, VIRTIO_BALLOON_F_DEFLATE_ON_OOM,
+    BalloonPolicy, BALLOON_DEV_ID, DEFLATE_INDEX, INFLATE_INDEX, MAX_PAGES_IN_DESC,
+    MAX_PAGE_COMPACT_BUFFER, MIB_TO_4K_PAGES, NUM_QUEUES, QUEUE_SIZES, REPORTING_INDEX,
+    STATS_INDEX, VIRTIO_BALLOON_F_DEFLATE_ON_OOM, VIRTIO_BALLOON_F_REPORTING,
     VIRTIO_BALLOON_F_STATS_VQ, VIRTIO_BALLOON_PFN_SHIFT, VIRTIO_BALLOON_S_AVAIL,
     VIRTIO_BALLOON_S_CACHES, VIRTIO_BALLOON_S_HTLB_PGALLOC, VIRTIO_BALLOON_S_HTLB_PGFAIL,
     VIRTIO_BALLOON_S_MAJFLT, VIRTIO_BALLOON_S_MEMFREE, VIRTIO_BALLOON_S_MEMTOT,
@@ -69,6 +70,7 @@ pub struct BalloonConfig {
     pub amount_mib: u32,
     pub deflate_on_oom: bool,
     pub stats_polling_interval_s: u16,
+    pub free_page_reporting: bool,
 }
 
 // BalloonStats holds statistics returned from the stats_queue.
@@ -146,6 +148,8 @@ pub struct Balloon {
     // it is acknowledged after the stats queue is processed.
     pub(crate) stats_desc_index: Option<u16>,
     pub(crate) latest_stats: BalloonStats,
+    // Adjusts the target size when new statistics are received, if set.
+    pub(crate) policy: Option<BalloonPolicy>,
     // A buffer used as pfn accumulator during descriptor processing.
     pub(crate) pfn_buffer: [u32; MAX_PAGE_COMPACT_BUFFER],
 }
@@ -155,6 +159,7 @@ impl Balloon {
         amount_mib: u32,
         deflate_on_oom: bool,
         stats_polling_interval_s: u16,
+        free_page_reporting: bool,
         restored: bool,
     ) -> Result<Balloon, BalloonError> {
         let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
@@ -167,16 +172,24 @@ impl Balloon {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
         }
 
+        if free_page_reporting {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
+        }
+
         let queue_evts = [
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
+            EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
         ];
 
         let mut queues: Vec<Queue> = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
 
-        // The VirtIO specification states that the statistics queue should
-        // not be present at all if the statistics are not enabled.
+        // The VirtIO specification states that the statistics and the free page
+        // reporting queues should not be present at all if they are not enabled.
+        if !free_page_reporting {
+            let _ = queues.remove(REPORTING_INDEX);
+        }
         if stats_polling_interval_s == 0 {
             let _ = queues.remove(STATS_INDEX);
         }
@@ -201,6 +214,7 @@ impl Balloon {
             stats_timer,
             stats_desc_index: None,
             latest_stats: BalloonStats::default(),
+            policy: None,
             pfn_buffer: [0u32; MAX_PAGE_COMPACT_BUFFER],
         })
     }
@@ -226,6 +240,15 @@ impl Balloon {
         self.process_stats_queue()
     }
 
+    pub(crate) fn process_reporting_queue_event(&mut self) -> Result<(), BalloonError> {
+        if let Some(index) = self.reporting_index() {
+            self.queue_evts[index]
+                .read()
+                .map_err(BalloonError::EventFd)?;
+        }
+        self.process_reporting_queue()
+    }
+
     pub(crate) fn process_stats_timer_event(&mut self) -> Result<(), BalloonError> {
         self.stats_timer.read();
         self.trigger_stats_update()
@@ -348,6 +371,7 @@ impl Balloon {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
         METRICS.balloon.stats_updates_count.inc();
+        let mut stats_updated = false;
 
         while let Some(head) = self.queues[STATS_INDEX].pop(mem) {
             if let Some(prev_stats_desc) = self.stats_desc_index {
@@ -377,11 +401,94 @@ impl Balloon {
             }
 
             self.stats_desc_index = Some(head.index);
+            stats_updated = true;
+        }
+
+        if stats_updated {
+            self.apply_policy()?;
         }
 
         Ok(())
     }
 
+    // Updates the target size as the policy requires, from the latest statistics.
+    fn apply_policy(&mut self) -> Result<(), BalloonError> {
+        let policy = match self.policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let available_bytes = match self
+            .latest_stats
+            .available_memory
+            .or(self.latest_stats.free_memory)
+        {
+            Some(available_bytes) => available_bytes,
+            None => return Ok(()),
+        };
+
+        // The pages the balloon is yet to take or give back will change the available memory.
+        let target_mib = self.size_mb();
+        let available_mib = (available_bytes >> 20) as i64
+            + i64::from(pages_to_mib(self.config_space.actual_pages))
+            - i64::from(target_mib);
+        let available_mib = available_mib.clamp(0, i64::from(u32::MAX)) as u32;
+
+        match policy.next_target_mib(target_mib, available_mib) {
+            Some(amount_mib) => {
+                self.config_space.num_pages = mib_to_pages(amount_mib)?;
+                METRICS.balloon.policy_updates.inc();
+                self.irq_trigger
+                    .trigger_irq(IrqType::Config)
+                    .map_err(BalloonError::InterruptError)
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn process_reporting_queue(&mut self) -> Result<(), BalloonError> {
+        let index = match self.reporting_index() {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        METRICS.balloon.free_page_report_count.inc();
+
+        let queue = &mut self.queues[index];
+        let mut needs_interrupt = false;
+
+        // Each descriptor of a chain holds a range of free pages, which the guest won't
+        // touch until the chain is returned.
+        while let Some(head) = queue.pop(mem) {
+            let head_index = head.index;
+            let mut desc = Some(head);
+            while let Some(range) = desc {
+                match remove_range(mem, (range.addr, u64::from(range.len)), self.restored) {
+                    Ok(()) => METRICS
+                        .balloon
+                        .free_page_report_freed
+                        .add(range.len as usize),
+                    Err(e) => {
+                        error!("Error removing reported memory range: {:?}", e);
+                        METRICS.balloon.free_page_report_fails.inc();
+                    }
+                }
+                desc = range.next_descriptor();
+            }
+
+            queue
+                .add_used(mem, head_index, 0)
+                .map_err(BalloonError::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
     pub(crate) fn signal_used_queue(&self) -> Result<(), BalloonError> {
         self.irq_trigger.trigger_irq(IrqType::Vring).map_err(|e| {
             METRICS.balloon.event_fails.inc();
@@ -393,6 +500,7 @@ impl Balloon {
     pub fn process_virtio_queues(&mut self) {
         let _ = self.process_inflate();
         let _ = self.process_deflate_queue();
+        let _ = self.process_reporting_queue();
     }
 
     pub fn id(&self) -> &str {
@@ -416,26 +524,39 @@ impl Balloon {
         }
     }
 
+    /// Checks that the balloon target can be set to `amount_mib`, without updating it.
+    pub fn validate_size(&self, amount_mib: u32) -> Result<(), BalloonError> {
+        if !self.is_activated() {
+            return Err(BalloonError::DeviceNotActive);
+        }
+        mib_to_pages(amount_mib).map(|_| ())
+    }
+
     pub fn update_size(&mut self, amount_mib: u32) -> Result<(), BalloonError> {
-        if self.is_activated() {
-            self.config_space.num_pages = mib_to_pages(amount_mib)?;
-            self.irq_trigger
-                .trigger_irq(IrqType::Config)
-                .map_err(BalloonError::InterruptError)
-        } else {
-            Err(BalloonError::DeviceNotActive)
+        self.validate_size(amount_mib)?;
+        self.config_space.num_pages = mib_to_pages(amount_mib)?;
+        self.irq_trigger
+            .trigger_irq(IrqType::Config)
+            .map_err(BalloonError::InterruptError)
+    }
+
+    /// Checks that the statistics polling interval can be set to `interval_s`, without
+    /// updating it.
+    pub fn validate_stats_polling_interval(&self, interval_s: u16) -> Result<(), BalloonError> {
+        if self.stats_polling_interval_s != interval_s
+            && (self.stats_polling_interval_s == 0 || interval_s == 0)
+        {
+            return Err(BalloonError::StatisticsStateChange);
         }
+        Ok(())
     }
 
     pub fn update_stats_polling_interval(&mut self, interval_s: u16) -> Result<(), BalloonError> {
+        self.validate_stats_polling_interval(interval_s)?;
         if self.stats_polling_interval_s == interval_s {
             return Ok(());
         }
 
-        if self.stats_polling_interval_s == 0 || interval_s == 0 {
-            return Err(BalloonError::StatisticsStateChange);
-        }
-
         self.trigger_stats_update()?;
 
         self.stats_polling_interval_s = interval_s;
@@ -464,6 +585,10 @@ impl Balloon {
         self.avail_features & (1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM) != 0
     }
 
+    pub fn free_page_reporting(&self) -> bool {
+        self.avail_features & (1u64 << VIRTIO_BALLOON_F_REPORTING) != 0
+    }
+
     pub fn stats_polling_interval_s(&self) -> u16 {
         self.stats_polling_interval_s
     }
@@ -485,13 +610,41 @@ impl Balloon {
             amount_mib: self.size_mb(),
             deflate_on_oom: self.deflate_on_oom(),
             stats_polling_interval_s: self.stats_polling_interval_s(),
+            free_page_reporting: self.free_page_reporting(),
+        }
+    }
+
+    pub fn policy(&self) -> Option<BalloonPolicy> {
+        self.policy
+    }
+
+    /// Sets the policy adjusting the target size from the statistics, or removes it.
+    pub fn set_policy(&mut self, policy: Option<BalloonPolicy>) -> Result<(), BalloonError> {
+        if let Some(policy) = policy.as_ref() {
+            if !self.stats_enabled() {
+                return Err(BalloonError::StatisticsDisabled);
+            }
+            if !policy.is_valid() {
+                return Err(BalloonError::InvalidPolicy);
+            }
         }
+        self.policy = policy;
+        Ok(())
     }
 
     pub(crate) fn stats_enabled(&self) -> bool {
         self.stats_polling_interval_s > 0
     }
 
+    // The free page reporting queue comes right after the statistics one, if present.
+    pub(crate) fn reporting_index(&self) -> Option<usize> {
+        match (self.free_page_reporting(), self.stats_enabled()) {
+            (false, _) => None,
+            (true, true) => Some(REPORTING_INDEX),
+            (true, false) => Some(STATS_INDEX),
+        }
+    }
+
     pub(crate) fn set_stats_desc_index(&mut self, stats_desc_index: Option<u16>) {
         self.stats_desc_index = stats_desc_index;
     }
@@ -682,36 +835,55 @@ pub(crate) mod tests {
         // Test all feature combinations.
         for deflate_on_oom in vec![true, false].iter() {
             for stats_interval in vec![0, 1].iter() {
-                let mut balloon = Balloon::new(0, *deflate_on_oom, *stats_interval, false).unwrap();
-                assert_eq!(balloon.device_type(), TYPE_BALLOON);
-
-                let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
-                    | ((if *deflate_on_oom { 1 } else { 0 }) << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
-                    | ((*stats_interval as u64) << VIRTIO_BALLOON_F_STATS_VQ);
-
-                assert_eq!(balloon.avail_features_by_page(0), features as u32);
-                assert_eq!(balloon.avail_features_by_page(1), (features >> 32) as u32);
-                for i in 2..10 {
-                    assert_eq!(balloon.avail_features_by_page(i), 0u32);
-                }
+                for free_page_reporting in vec![true, false].iter() {
+                    let mut balloon = Balloon::new(
+                        0,
+                        *deflate_on_oom,
+                        *stats_interval,
+                        *free_page_reporting,
+                        false,
+                    )
+                    .unwrap();
+                    assert_eq!(balloon.device_type(), TYPE_BALLOON);
+
+                    let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
+                        | ((if *deflate_on_oom { 1 } else { 0 })
+                            << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
+                        | ((*stats_interval as u64) << VIRTIO_BALLOON_F_STATS_VQ)
+                        | ((if *free_page_reporting { 1 } else { 0 })
+                            << VIRTIO_BALLOON_F_REPORTING);
+
+                    assert_eq!(balloon.avail_features_by_page(0), features as u32);
+                    assert_eq!(balloon.avail_features_by_page(1), (features >> 32) as u32);
+                    for i in 2..10 {
+                        assert_eq!(balloon.avail_features_by_page(i), 0u32);
+                    }
 
-                for i in 0..10 {
-                    balloon.ack_features_by_page(i, u32::MAX);
+                    for i in 0..10 {
+                        balloon.ack_features_by_page(i, u32::MAX);
+                    }
+                    // Only present features should be acknowledged.
+                    assert_eq!(balloon.acked_features, features);
+
+                    // The queues which are not enabled are not present.
+                    let num_queues = NUM_QUEUES
+                        - (*stats_interval == 0) as usize
+                        - !*free_page_reporting as usize;
+                    assert_eq!(balloon.queues().len(), num_queues);
                 }
-                // Only present features should be acknowledged.
-                assert_eq!(balloon.acked_features, features);
             }
         }
     }
 
     #[test]
     fn test_virtio_read_config() {
-        let balloon = Balloon::new(0x10, true, 0, false).unwrap();
+        let balloon = Balloon::new(0x10, true, 0, false, false).unwrap();
 
         let cfg = BalloonConfig {
             amount_mib: 16,
             deflate_on_oom: true,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
         assert_eq!(balloon.config(), cfg);
 
@@ -737,7 +909,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_virtio_write_config() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
 
         let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
             [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
@@ -757,7 +929,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_invalid_request() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         // Only initialize the inflate queue to demonstrate invalid request handling.
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
@@ -816,7 +988,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_inflate() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(INFLATE_INDEX, infq.create_queue());
@@ -874,7 +1046,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_deflate() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         let defq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(DEFLATE_INDEX, defq.create_queue());
@@ -910,7 +1082,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_stats() {
-        let mut balloon = Balloon::new(0, true, 1, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 1, false, false).unwrap();
         let mem = default_mem();
         let statsq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(STATS_INDEX, statsq.create_queue());
@@ -989,9 +1161,124 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_free_page_reporting() {
+        let mut balloon = Balloon::new(0, true, 1, true, false).unwrap();
+        let mem = default_mem();
+        let repq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        balloon.set_queue(REPORTING_INDEX, repq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        // Fill the second and third pages with non-zero bytes.
+        for i in 0..0x2000 {
+            assert!(mem.write_obj::<u8>(1, GuestAddress(0x1000 + i)).is_ok());
+        }
+
+        // Error case: forgot to trigger the reporting event queue.
+        {
+            set_request(&repq, 0, 0x1000, 0x1000, VIRTQ_DESC_F_WRITE);
+            check_metric_after_block!(
+                METRICS.balloon.event_fails,
+                1,
+                balloon
+                    .process_reporting_queue_event()
+                    .unwrap_or_else(report_balloon_event_fail)
+            );
+            // Verify that nothing got processed.
+            assert_eq!(repq.used.idx.get(), 0);
+        }
+
+        // Happy case: both reported pages are released.
+        {
+            repq.dtable[0].set(0x1000, 0x1000, VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT, 1);
+            repq.dtable[1].set(0x2000, 0x1000, VIRTQ_DESC_F_WRITE, 0);
+            check_metric_after_block!(
+                METRICS.balloon.free_page_report_freed,
+                0x2000,
+                invoke_handler_for_queue_event(&mut balloon, REPORTING_INDEX)
+            );
+            check_request_completion(&repq, 0);
+
+            for i in 0..0x2000 {
+                assert_eq!(mem.read_obj::<u8>(GuestAddress(0x1000 + i)).unwrap(), 0);
+            }
+        }
+
+        // Without the statistics, the reporting queue takes the place of the statistics one.
+        let balloon = Balloon::new(0, true, 0, true, false).unwrap();
+        assert_eq!(balloon.reporting_index(), Some(STATS_INDEX));
+        let balloon = Balloon::new(0, true, 1, false, false).unwrap();
+        assert_eq!(balloon.reporting_index(), None);
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = BalloonPolicy {
+            min_available_mib: 64,
+            max_available_mib: 128,
+            step_mib: 16,
+        };
+
+        // The policy needs the statistics.
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
+        assert_eq!(
+            format!("{:?}", balloon.set_policy(Some(policy))),
+            "Err(StatisticsDisabled)"
+        );
+
+        let mut balloon = Balloon::new(0, true, 1, false, false).unwrap();
+        let invalid_policy = BalloonPolicy {
+            step_mib: 0,
+            ..policy
+        };
+        assert_eq!(
+            format!("{:?}", balloon.set_policy(Some(invalid_policy))),
+            "Err(InvalidPolicy)"
+        );
+        balloon.set_policy(Some(policy)).unwrap();
+        assert_eq!(balloon.policy(), Some(policy));
+
+        let mem = default_mem();
+        let statsq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        balloon.set_queue(STATS_INDEX, statsq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        // Sends the `idx`-th statistics buffer, and returns the resulting target size.
+        let report_available_mib = |balloon: &mut Balloon, idx: usize, available_mib: u64| {
+            let page_addr = 0x100;
+            let stat = BalloonStat {
+                tag: VIRTIO_BALLOON_S_AVAIL,
+                val: available_mib << 20,
+            };
+            mem.write_obj::<BalloonStat>(stat, GuestAddress(page_addr))
+                .unwrap();
+            set_request(&statsq, idx, page_addr, SIZE_OF_STAT as u32, 0);
+            balloon.queue_events()[STATS_INDEX].write(1).unwrap();
+            balloon.process_stats_queue_event().unwrap();
+            // Give the descriptor back, as the timer would.
+            balloon.stats_desc_index = None;
+            balloon.size_mb()
+        };
+
+        // The balloon inflates by one step when the guest has plenty of memory.
+        check_metric_after_block!(METRICS.balloon.policy_updates, 1, {
+            assert_eq!(report_available_mib(&mut balloon, 0, 1024), 16);
+        });
+        // The balloon didn't reach its target yet, so the memory it is about to take is
+        // accounted for.
+        assert_eq!(report_available_mib(&mut balloon, 1, 136), 16);
+        assert_eq!(report_available_mib(&mut balloon, 2, 1024), 32);
+        // The balloon deflates when the guest runs low on memory.
+        assert_eq!(report_available_mib(&mut balloon, 3, 32), 16);
+
+        // Without a policy, the target size is left to the user.
+        balloon.set_policy(None).unwrap();
+        assert_eq!(report_available_mib(&mut balloon, 4, 1024), 16);
+    }
+
     #[test]
     fn test_process_balloon_queues() {
-        let mut balloon = Balloon::new(0x10, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0x10, true, 0, false, false).unwrap();
         let mem = default_mem();
         balloon.activate(mem).unwrap();
         balloon.process_virtio_queues()
@@ -999,16 +1286,21 @@ pub(crate) mod tests {
 
     #[test]
     fn test_update_stats_interval() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         balloon.activate(mem).unwrap();
+        assert_eq!(
+            format!("{:?}", balloon.validate_stats_polling_interval(1)),
+            "Err(StatisticsStateChange)"
+        );
         assert_eq!(
             format!("{:?}", balloon.update_stats_polling_interval(1)),
             "Err(StatisticsStateChange)"
         );
+        assert!(balloon.validate_stats_polling_interval(0).is_ok());
         assert!(balloon.update_stats_polling_interval(0).is_ok());
 
-        let mut balloon = Balloon::new(0, true, 1, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 1, false, false).unwrap();
         let mem = default_mem();
         balloon.activate(mem).unwrap();
         assert_eq!(
@@ -1021,8 +1313,9 @@ pub(crate) mod tests {
 
     #[test]
     fn test_num_pages() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         // Assert that we can't update an inactive device.
+        assert!(balloon.validate_size(1).is_err());
         assert!(balloon.update_size(1).is_err());
         // Switch the state to active.
         balloon.device_state = DeviceState::Activated(
@@ -1040,6 +1333,10 @@ pub(crate) mod tests {
         balloon.update_actual_pages(0x1234);
         balloon.update_num_pages(0x100);
         assert_eq!(balloon.num_pages(), 0x100);
+        // Validating the size doesn't update it.
+        assert!(balloon.validate_size(u32::MAX).is_err());
+        assert!(balloon.validate_size(16).is_ok());
+        assert_eq!(balloon.num_pages(), 0x100);
         assert!(balloon.update_size(16).is_ok());
 
         let mut actual_config = vec![0; CONFIG_SPACE_SIZE];
@@ -4,6 +4,7 @@
 pub mod device;
 pub mod event_handler;
 pub mod persist;
+pub mod policy;
 pub mod test_utils;
 mod utils;
 
@@ -11,14 +12,15 @@ use vm_memory::GuestMemoryError;
 
 pub use self::device::{Balloon, BalloonConfig, BalloonStats};
 pub use self::event_handler::*;
+pub use self::policy::BalloonPolicy;
 
 /// Device ID used in MMIO device identification.
 /// Because Balloon is unique per-vm, this ID can be hardcoded.
 pub const BALLOON_DEV_ID: &str = "balloon";
 pub const CONFIG_SPACE_SIZE: usize = 8;
 pub const QUEUE_SIZE: u16 = 256;
-pub const NUM_QUEUES: usize = 3;
-pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE];
+pub const NUM_QUEUES: usize = 4;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE];
 // Number of 4K pages in a MiB.
 pub const MIB_TO_4K_PAGES: u32 = 256;
 // The maximum number of pages that can be received in a single descriptor.
@@ -34,10 +36,14 @@ pub const INFLATE_INDEX: usize = 0;
 pub const DEFLATE_INDEX: usize = 1;
 // The index of the deflate queue from Balloon device queues/queues_evts vector.
 pub const STATS_INDEX: usize = 2;
+// The index of the free page reporting queue from Balloon device queues/queues_evts vector,
+// when the statistics queue is present. Otherwise, it takes the place of the statistics queue.
+pub const REPORTING_INDEX: usize = 3;
 
 // The feature bitmap for virtio balloon.
 const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1; // Enable statistics.
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2; // Deflate balloon on OOM.
+const VIRTIO_BALLOON_F_REPORTING: u32 = 5; // Free page reporting.
 
 // The statistics tags.
 const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
@@ -65,6 +71,8 @@ pub enum Error {
     GuestMemory(GuestMemoryError),
     /// Received error while sending an interrupt.
     InterruptError(std::io::Error),
+    /// The balloon policy thresholds are not ordered or its step is 0.
+    InvalidPolicy,
     /// Guest gave us a malformed descriptor.
     MalformedDescriptor,
     /// Guest gave us a malformed payload.
@@ -86,6 +86,14 @@ pub struct BalloonState {
     virtio_state: VirtioDeviceState,
 }
 
+impl BalloonState {
+    /// Lists the fields of the state this implementation of the device can't be restored from.
+    pub fn incompatible_fields(&self) -> Vec<String> {
+        self.virtio_state
+            .incompatible_fields(TYPE_BALLOON, QUEUE_SIZE)
+    }
+}
+
 pub struct BalloonConstructorArgs {
     pub mem: GuestMemoryMmap,
 }
@@ -112,9 +120,18 @@ impl Persist<'_> for Balloon {
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
+        // The free page reporting queue is only present when the feature is offered.
+        let free_page_reporting =
+            state.virtio_state.avail_features & (1u64 << VIRTIO_BALLOON_F_REPORTING) != 0;
         // We can safely create the balloon with arbitrary flags and
         // num_pages because we will overwrite them after.
-        let mut balloon = Balloon::new(0, false, state.stats_polling_interval_s, true)?;
+        let mut balloon = Balloon::new(
+            0,
+            false,
+            state.stats_polling_interval_s,
+            free_page_reporting,
+            true,
+        )?;
 
         let mut num_queues = NUM_QUEUES;
         // As per the virtio 1.1 specification, the statistics queue
@@ -122,6 +139,9 @@ impl Persist<'_> for Balloon {
         if state.stats_polling_interval_s == 0 {
             num_queues -= 1;
         }
+        if !free_page_reporting {
+            num_queues -= 1;
+        }
         balloon.queues = state
             .virtio_state
             .build_queues_checked(&constructor_args.mem, TYPE_BALLOON, num_queues, QUEUE_SIZE)
@@ -174,7 +194,7 @@ mod tests {
         let version_map = VersionMap::new();
 
         // Create and save the balloon device.
-        let balloon = Balloon::new(0x42, false, 2, false).unwrap();
+        let balloon = Balloon::new(0x42, false, 2, false, false).unwrap();
 
         <Balloon as Persist>::save(&balloon)
             .serialize(&mut mem.as_mut_slice(), &version_map, 1)
@@ -207,4 +227,30 @@ mod tests {
         assert_eq!(restored_balloon.stats_desc_index, balloon.stats_desc_index);
         assert_eq!(restored_balloon.latest_stats, balloon.latest_stats);
     }
+
+    #[test]
+    fn test_persistence_free_page_reporting() {
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        for stats_polling_interval_s in [0, 1].iter() {
+            let balloon = Balloon::new(0, false, *stats_polling_interval_s, true, false).unwrap();
+            <Balloon as Persist>::save(&balloon)
+                .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+                .unwrap();
+
+            let restored_balloon = Balloon::restore(
+                BalloonConstructorArgs { mem: default_mem() },
+                &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+            )
+            .unwrap();
+
+            assert!(restored_balloon.free_page_reporting());
+            assert_eq!(restored_balloon.queues(), balloon.queues());
+            assert_eq!(
+                restored_balloon.reporting_index(),
+                balloon.reporting_index()
+            );
+        }
+    }
 }
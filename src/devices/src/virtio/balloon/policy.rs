@@ -0,0 +1,95 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Adjusts the target size of the balloon from the statistics of the guest, so that the memory
+//! available in the guest stays between two thresholds.
+
+use std::cmp;
+
+use serde::{Deserialize, Serialize};
+
+/// Thresholds on the memory available in the guest, which the balloon policy keeps it between.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalloonPolicy {
+    /// The balloon deflates when the guest has less available memory than this, in MiB.
+    pub min_available_mib: u32,
+    /// The balloon inflates when the guest has more available memory than this, in MiB.
+    pub max_available_mib: u32,
+    /// The largest change of the balloon target size in a single update, in MiB.
+    pub step_mib: u32,
+}
+
+impl BalloonPolicy {
+    /// Checks that the thresholds are ordered and that the policy can change the target size.
+    pub fn is_valid(&self) -> bool {
+        self.step_mib > 0 && self.min_available_mib <= self.max_available_mib
+    }
+
+    /// Computes the balloon target size, in MiB, bringing the memory available in the guest
+    /// back between the thresholds. `available_mib` accounts for the target size not reached
+    /// yet by the balloon. Returns `None` when the target size doesn't change.
+    pub fn next_target_mib(&self, target_mib: u32, available_mib: u32) -> Option<u32> {
+        let next_target_mib = if available_mib < self.min_available_mib {
+            let missing_mib = self.min_available_mib - available_mib;
+            target_mib.saturating_sub(cmp::min(self.step_mib, missing_mib))
+        } else if available_mib > self.max_available_mib {
+            let excess_mib = available_mib - self.max_available_mib;
+            target_mib.saturating_add(cmp::min(self.step_mib, excess_mib))
+        } else {
+            target_mib
+        };
+
+        if next_target_mib != target_mib {
+            Some(next_target_mib)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_validity() {
+        let mut policy = BalloonPolicy {
+            min_available_mib: 64,
+            max_available_mib: 128,
+            step_mib: 16,
+        };
+        assert!(policy.is_valid());
+
+        policy.step_mib = 0;
+        assert!(!policy.is_valid());
+
+        policy.step_mib = 16;
+        policy.min_available_mib = 256;
+        assert!(!policy.is_valid());
+    }
+
+    #[test]
+    fn test_next_target() {
+        let policy = BalloonPolicy {
+            min_available_mib: 64,
+            max_available_mib: 128,
+            step_mib: 16,
+        };
+
+        // Between the thresholds, the target size is left as is.
+        assert_eq!(policy.next_target_mib(100, 64), None);
+        assert_eq!(policy.next_target_mib(100, 100), None);
+        assert_eq!(policy.next_target_mib(100, 128), None);
+
+        // The balloon inflates by at most one step, without going below the upper threshold.
+        assert_eq!(policy.next_target_mib(100, 1024), Some(116));
+        assert_eq!(policy.next_target_mib(100, 132), Some(104));
+
+        // The balloon deflates by at most one step, without going above the lower threshold.
+        assert_eq!(policy.next_target_mib(100, 0), Some(84));
+        assert_eq!(policy.next_target_mib(100, 60), Some(96));
+        assert_eq!(policy.next_target_mib(8, 0), Some(0));
+        assert_eq!(policy.next_target_mib(0, 0), None);
+    }
+}
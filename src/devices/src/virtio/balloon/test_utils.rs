@@ -8,7 +8,8 @@ use std::u32;
 use crate::virtio::test_utils::VirtQueue;
 #[cfg(test)]
 use crate::virtio::{
-    balloon::NUM_QUEUES, Balloon, IrqType, DEFLATE_INDEX, INFLATE_INDEX, STATS_INDEX,
+    balloon::NUM_QUEUES, Balloon, IrqType, DEFLATE_INDEX, INFLATE_INDEX, REPORTING_INDEX,
+    STATS_INDEX,
 };
 
 #[cfg(test)]
@@ -21,6 +22,7 @@ pub fn invoke_handler_for_queue_event(b: &mut Balloon, queue_index: usize) {
         INFLATE_INDEX => b.process_inflate_queue_event().unwrap(),
         DEFLATE_INDEX => b.process_deflate_queue_event().unwrap(),
         STATS_INDEX => b.process_stats_queue_event().unwrap(),
+        REPORTING_INDEX => b.process_reporting_queue_event().unwrap(),
         _ => unreachable!(),
     };
     // Validate the queue operation finished successfully.
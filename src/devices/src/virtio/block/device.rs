@@ -9,19 +9,24 @@ use std::convert::From;
 use std::fs::{File, OpenOptions};
 use std::io::{Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::path::PathBuf;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use std::{cmp, result};
 
-use block_io::FileEngine;
-use logger::{error, warn, IncMetric, METRICS};
-use rate_limiter::{BucketUpdate, RateLimiter};
+use block_io::nbd::{is_nbd_uri, NbdUri};
+use block_io::{FileEngine, NbdFileEngine, OverlayFileEngine, Qcow2FileEngine};
+use logger::{error, warn, IncMetric, LatencyHistogram, METRICS};
+use rate_limiter::{BucketUpdate, RateLimiter, RateLimiterGroup};
 use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 use utils::kernel_version::{min_kernel_version_for_io_uring, KernelVersion};
 use virtio_gen::virtio_blk::{
-    VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_BLK_ID_BYTES, VIRTIO_F_VERSION_1,
+    VIRTIO_BLK_F_BLK_SIZE, VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_GEOMETRY,
+    VIRTIO_BLK_F_MQ, VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_SIZE_MAX,
+    VIRTIO_BLK_F_TOPOLOGY, VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_BLK_ID_BYTES, VIRTIO_F_VERSION_1,
 };
 use virtio_gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use vm_memory::GuestMemoryMmap;
@@ -29,9 +34,28 @@ use vm_memory::GuestMemoryMmap;
 use super::super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BLOCK};
 use super::io::async_io;
 use super::request::*;
-use super::{io as block_io, Error, CONFIG_SPACE_SIZE, QUEUE_SIZES, SECTOR_SHIFT, SECTOR_SIZE};
+use super::{
+    io as block_io, Error, TraceRecord, TraceWriter, CONFIG_SPACE_SIZE, DISCARD_CONFIG_OFFSET,
+    NUM_QUEUES_CONFIG_OFFSET, QUEUE_SIZE, SECTOR_SHIFT, SECTOR_SIZE,
+};
+use crate::virtio::net::vhost::{Error as VhostError, VhostBackend};
+use crate::virtio::vhost_user::{VhostUser, VHOST_USER_PROTOCOL_F_CONFIG};
 use crate::virtio::{IrqTrigger, IrqType};
 
+// The features of vhost-user backends exposed to the guest. The device model only reads the
+// configuration space from the backend, and has a single queue.
+const VHOST_USER_FEATURES: u64 = (1 << VIRTIO_F_VERSION_1)
+    | (1 << VIRTIO_RING_F_EVENT_IDX)
+    | (1 << VIRTIO_BLK_F_SIZE_MAX)
+    | (1 << VIRTIO_BLK_F_SEG_MAX)
+    | (1 << VIRTIO_BLK_F_GEOMETRY)
+    | (1 << VIRTIO_BLK_F_RO)
+    | (1 << VIRTIO_BLK_F_BLK_SIZE)
+    | (1 << VIRTIO_BLK_F_FLUSH)
+    | (1 << VIRTIO_BLK_F_TOPOLOGY)
+    | (1 << VIRTIO_BLK_F_DISCARD)
+    | (1 << VIRTIO_BLK_F_WRITE_ZEROES);
+
 /// Configuration options for disk caching.
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum CacheType {
@@ -42,6 +66,10 @@ pub enum CacheType {
     /// flush requests coming from the guest will be performed using
     /// `fsync`.
     Writeback,
+    /// Flushing mechanic will not be advertised to the guest driver, since
+    /// the backing file is opened with `O_DSYNC`: write requests are only
+    /// completed once the data reached the host persistent storage.
+    Writethrough,
 }
 
 impl Default for CacheType {
@@ -73,10 +101,27 @@ impl FileEngineType {
     }
 }
 
+/// The format of the image backing a block device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// The image holds the content of the disk as is.
+    Raw,
+    /// The image is in the QEMU copy-on-write format, version 2 or 3.
+    Qcow2,
+}
+
+impl Default for ImageFormat {
+    fn default() -> Self {
+        Self::Raw
+    }
+}
+
 /// Helper object for setting up all `Block` fields derived from its backing file.
 pub(crate) struct DiskProperties {
     cache_type: CacheType,
     file_path: String,
+    overlay_path: Option<String>,
     file_engine: FileEngine<PendingRequest>,
     nsectors: u64,
     image_id: [u8; VIRTIO_BLK_ID_BYTES as usize],
@@ -88,16 +133,208 @@ impl DiskProperties {
         is_disk_read_only: bool,
         cache_type: CacheType,
         file_engine_type: FileEngineType,
+        image_format: ImageFormat,
+        overlay_path: Option<String>,
     ) -> result::Result<Self, Error> {
-        let mut disk_image = OpenOptions::new()
-            .read(true)
-            .write(!is_disk_read_only)
-            .open(PathBuf::from(&disk_image_path))
-            .map_err(Error::BackingFile)?;
-        let disk_size = disk_image
-            .seek(SeekFrom::End(0))
-            .map_err(Error::BackingFile)? as u64;
+        if let Some(overlay_path) = overlay_path {
+            return Self::new_with_overlay(
+                disk_image_path,
+                overlay_path,
+                cache_type,
+                file_engine_type,
+                image_format,
+            );
+        }
+        if is_nbd_uri(&disk_image_path) {
+            return Self::new_with_nbd(
+                disk_image_path,
+                is_disk_read_only,
+                cache_type,
+                file_engine_type,
+                image_format,
+            );
+        }
 
+        let disk_image = Self::open_file(&disk_image_path, is_disk_read_only, cache_type)?;
+        Self::from_image(
+            disk_image,
+            disk_image_path,
+            is_disk_read_only,
+            cache_type,
+            file_engine_type,
+            image_format,
+        )
+    }
+
+    /// Sets up a disk whose backing file was opened by the caller, which hands over the
+    /// ownership of `disk_fd`. The access mode of the file has to match the one of the drive.
+    pub fn new_with_fd(
+        disk_fd: RawFd,
+        is_disk_read_only: bool,
+        cache_type: CacheType,
+        file_engine_type: FileEngineType,
+        image_format: ImageFormat,
+    ) -> result::Result<Self, Error> {
+        // This is safe since the ownership of the file descriptor is handed over to us.
+        let disk_image = unsafe { File::from_raw_fd(disk_fd) };
+        // This is safe since the file descriptor is valid for the lifetime of `disk_image`.
+        let flags = unsafe { libc::fcntl(disk_image.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 {
+            return Err(Error::BackingFile(std::io::Error::last_os_error()));
+        }
+        if !is_disk_read_only && flags & libc::O_ACCMODE == libc::O_RDONLY {
+            return Err(Error::InvalidDiskFd("it isn't open for writing"));
+        }
+        if cache_type == CacheType::Writethrough && flags & libc::O_DSYNC != libc::O_DSYNC {
+            return Err(Error::InvalidDiskFd(
+                "it isn't open with O_DSYNC, required by the Writethrough cache type",
+            ));
+        }
+
+        // The file has no path to refer to it by.
+        Self::from_image(
+            disk_image,
+            String::new(),
+            is_disk_read_only,
+            cache_type,
+            file_engine_type,
+            image_format,
+        )
+    }
+
+    fn from_image(
+        mut disk_image: File,
+        disk_image_path: String,
+        is_disk_read_only: bool,
+        cache_type: CacheType,
+        file_engine_type: FileEngineType,
+        image_format: ImageFormat,
+    ) -> result::Result<Self, Error> {
+        let image_id = Self::build_disk_image_id(&disk_image);
+        let (file_engine, disk_size) = match image_format {
+            ImageFormat::Raw => {
+                let disk_size = disk_image
+                    .seek(SeekFrom::End(0))
+                    .map_err(Error::BackingFile)? as u64;
+                let file_engine = FileEngine::from_file(disk_image, file_engine_type)
+                    .map_err(Error::FileEngine)?;
+                (file_engine, disk_size)
+            }
+            // The qcow2 images are only accessed synchronously.
+            ImageFormat::Qcow2 if file_engine_type == FileEngineType::Async => {
+                return Err(Error::FileEngine(block_io::Error::UnsupportedEngine(
+                    file_engine_type,
+                )));
+            }
+            ImageFormat::Qcow2 => {
+                let engine = Qcow2FileEngine::from_file(disk_image, is_disk_read_only)
+                    .map_err(|err| Error::FileEngine(block_io::Error::Qcow2(err)))?;
+                let disk_size = engine.virtual_size();
+                (FileEngine::Qcow2(engine), disk_size)
+            }
+        };
+
+        Ok(Self::from_parts(
+            cache_type,
+            disk_image_path,
+            None,
+            file_engine,
+            disk_size,
+            image_id,
+        ))
+    }
+
+    // Sets up a disk whose writes land in the sparse file at `overlay_path`, which is created if
+    // needed, while the chunks the guest didn't write to are read from the raw base image at
+    // `base_image_path`.
+    fn new_with_overlay(
+        base_image_path: String,
+        overlay_path: String,
+        cache_type: CacheType,
+        file_engine_type: FileEngineType,
+        image_format: ImageFormat,
+    ) -> result::Result<Self, Error> {
+        if image_format != ImageFormat::Raw {
+            return Err(Error::FileEngine(block_io::Error::Overlay(
+                block_io::overlay::Error::UnsupportedFeature("non raw base images"),
+            )));
+        }
+        if file_engine_type == FileEngineType::Async {
+            return Err(Error::FileEngine(block_io::Error::UnsupportedEngine(
+                file_engine_type,
+            )));
+        }
+
+        let base_image = Self::open_file(&base_image_path, true, cache_type)?;
+        let overlay = Self::open_overlay(&overlay_path, cache_type)?;
+        // The overlay is specific to the drive, unlike the base image.
+        let image_id = Self::build_disk_image_id(&overlay);
+        let engine = OverlayFileEngine::from_files(base_image, overlay)
+            .map_err(|err| Error::FileEngine(block_io::Error::Overlay(err)))?;
+        let disk_size = engine.size();
+
+        Ok(Self::from_parts(
+            cache_type,
+            base_image_path,
+            Some(overlay_path),
+            FileEngine::Overlay(engine),
+            disk_size,
+            image_id,
+        ))
+    }
+
+    // Sets up a disk whose image is exported by the NBD server designated by `uri`.
+    fn new_with_nbd(
+        uri: String,
+        is_disk_read_only: bool,
+        cache_type: CacheType,
+        file_engine_type: FileEngineType,
+        image_format: ImageFormat,
+    ) -> result::Result<Self, Error> {
+        if image_format != ImageFormat::Raw {
+            return Err(Error::FileEngine(block_io::Error::Nbd(
+                block_io::nbd::Error::UnsupportedFeature("non raw images"),
+            )));
+        }
+        if file_engine_type == FileEngineType::Async {
+            return Err(Error::FileEngine(block_io::Error::UnsupportedEngine(
+                file_engine_type,
+            )));
+        }
+
+        let nbd_uri =
+            NbdUri::parse(&uri).map_err(|err| Error::FileEngine(block_io::Error::Nbd(err)))?;
+        let engine = NbdFileEngine::connect(
+            &nbd_uri,
+            is_disk_read_only,
+            cache_type == CacheType::Writethrough,
+        )
+        .map_err(|err| Error::FileEngine(block_io::Error::Nbd(err)))?;
+        // The socket doesn't identify the disk, unlike the name of the export.
+        let mut image_id = [0; VIRTIO_BLK_ID_BYTES as usize];
+        let export = nbd_uri.export.as_bytes();
+        let bytes_to_copy = cmp::min(export.len(), VIRTIO_BLK_ID_BYTES as usize);
+        image_id[..bytes_to_copy].copy_from_slice(&export[..bytes_to_copy]);
+        let disk_size = engine.size();
+
+        Ok(Self::from_parts(
+            cache_type,
+            uri,
+            None,
+            FileEngine::Nbd(engine),
+            disk_size,
+            image_id,
+        ))
+    }
+
+    fn from_parts(
+        cache_type: CacheType,
+        file_path: String,
+        overlay_path: Option<String>,
+        file_engine: FileEngine<PendingRequest>,
+        disk_size: u64,
+        image_id: [u8; VIRTIO_BLK_ID_BYTES as usize],
+    ) -> Self {
         // We only support disk size, which uses the first two words of the configuration space.
         // If the image is not a multiple of the sector size, the tail bits are not exposed.
         if disk_size % SECTOR_SIZE != 0 {
@@ -108,14 +345,46 @@ impl DiskProperties {
             );
         }
 
-        Ok(Self {
+        Self {
             cache_type,
             nsectors: disk_size >> SECTOR_SHIFT,
-            image_id: Self::build_disk_image_id(&disk_image),
-            file_path: disk_image_path,
-            file_engine: FileEngine::from_file(disk_image, file_engine_type)
-                .map_err(Error::FileEngine)?,
-        })
+            image_id,
+            file_path,
+            overlay_path,
+            file_engine,
+        }
+    }
+
+    fn open_file(
+        disk_image_path: &str,
+        is_disk_read_only: bool,
+        cache_type: CacheType,
+    ) -> result::Result<File, Error> {
+        Self::open_options(cache_type)
+            .read(true)
+            .write(!is_disk_read_only)
+            .open(PathBuf::from(disk_image_path))
+            .map_err(Error::BackingFile)
+    }
+
+    fn open_overlay(overlay_path: &str, cache_type: CacheType) -> result::Result<File, Error> {
+        Self::open_options(cache_type)
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(PathBuf::from(overlay_path))
+            .map_err(Error::BackingFile)
+    }
+
+    // With the Writethrough cache type, the writes only return once the data reached the
+    // storage. The page cache is still used, since the buffers of the guest aren't guaranteed
+    // to meet the alignment requirements of `O_DIRECT`.
+    fn open_options(cache_type: CacheType) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        if cache_type == CacheType::Writethrough {
+            options.custom_flags(libc::O_DSYNC);
+        }
+        options
     }
 
     pub fn file_engine(&self) -> &FileEngine<PendingRequest> {
@@ -126,7 +395,6 @@ impl DiskProperties {
         &mut self.file_engine
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         &self.file_engine.file()
     }
@@ -135,10 +403,29 @@ impl DiskProperties {
         self.nsectors
     }
 
+    pub fn image_format(&self) -> ImageFormat {
+        match self.file_engine {
+            FileEngine::Qcow2(_) => ImageFormat::Qcow2,
+            FileEngine::Async(_)
+            | FileEngine::Sync(_)
+            | FileEngine::Overlay(_)
+            | FileEngine::Nbd(_)
+            | FileEngine::VhostUser(_) => ImageFormat::Raw,
+        }
+    }
+
     pub fn image_id(&self) -> &[u8] {
         &self.image_id
     }
 
+    // Replaces the ID of the disk image by `id`, truncated to the bytes the guest can read.
+    fn set_image_id(&mut self, id: &str) {
+        let id = id.as_bytes();
+        let bytes_to_copy = cmp::min(id.len(), VIRTIO_BLK_ID_BYTES as usize);
+        self.image_id = [0; VIRTIO_BLK_ID_BYTES as usize];
+        self.image_id[..bytes_to_copy].copy_from_slice(&id[..bytes_to_copy]);
+    }
+
     fn build_device_id(disk_file: &File) -> result::Result<String, Error> {
         let blk_metadata = disk_file.metadata().map_err(Error::GetFileMetadata)?;
         // This is how kvmtool does it.
@@ -168,20 +455,36 @@ impl DiskProperties {
         default_id
     }
 
-    /// Backing file path.
+    /// Backing file path, which is the one of the base image for disks with an overlay, and
+    /// the one of the socket of the backend for disks served by a vhost-user backend.
     pub fn file_path(&self) -> &String {
         &self.file_path
     }
 
+    /// Path of the file holding the writes to the base image, if any.
+    pub fn overlay_path(&self) -> Option<&String> {
+        self.overlay_path.as_ref()
+    }
+
     /// Provides vec containing the virtio block configuration space
     /// buffer. The config space is populated with the disk size based
-    /// on the backing file size.
-    pub fn virtio_block_config_space(&self) -> Vec<u8> {
+    /// on the backing file size, with the number of queues of the device,
+    /// and with the discard and write zeroes limits.
+    pub fn virtio_block_config_space(&self, num_queues: u16) -> Vec<u8> {
         // The config space is little endian.
         let mut config = Vec::with_capacity(CONFIG_SPACE_SIZE);
-        for i in 0..CONFIG_SPACE_SIZE {
-            config.push((self.nsectors >> (8 * i)) as u8);
+        config.extend_from_slice(&self.nsectors.to_le_bytes());
+        // The geometry, block size, topology and writeback fields are left out.
+        config.resize(NUM_QUEUES_CONFIG_OFFSET, 0);
+        config.extend_from_slice(&num_queues.to_le_bytes());
+        // A single segment of any length is accepted, at any sector, by both discard
+        // and write zeroes requests.
+        let limits: [u32; 5] = [u32::MAX, 1, 1, u32::MAX, 1];
+        for limit in limits.iter() {
+            config.extend_from_slice(&limit.to_le_bytes());
         }
+        // `write_zeroes_may_unmap`, followed by the padding.
+        config.extend_from_slice(&[1, 0, 0, 0]);
         config
     }
 
@@ -200,26 +503,46 @@ pub struct Block {
     pub(crate) acked_features: u64,
     config_space: Vec<u8>,
     pub(crate) activate_evt: EventFd,
+    // Signalled when the device is unplugged, so that it stops processing events.
+    pub(crate) unplug_evt: EventFd,
+
+    // When set, a vhost-user backend serves the requests instead of the device model.
+    pub(crate) vhost_user: Option<VhostUser>,
+    // Signalled by the vhost-user backend when it uses buffers of the queue.
+    pub(crate) vhost_call_evt: EventFd,
 
     // Transport related fields.
     pub(crate) queues: Vec<Queue>,
-    pub(crate) queue_evts: [EventFd; 1],
+    pub(crate) queue_evts: Vec<EventFd>,
     pub(crate) device_state: DeviceState,
     pub(crate) irq_trigger: IrqTrigger,
 
     // Implementation specific fields.
     pub(crate) id: String,
     pub(crate) partuuid: Option<String>,
+    // Reported to the guest as the ID of the device instead of the one of the disk image.
+    pub(crate) serial: Option<String>,
     pub(crate) root_device: bool,
     pub(crate) rate_limiter: RateLimiter,
+    // Limit the reads and the writes respectively, on top of `rate_limiter`.
+    pub(crate) read_rate_limiter: RateLimiter,
+    pub(crate) write_rate_limiter: RateLimiter,
     is_io_engine_throttled: bool,
+    // Service time of the requests, reported in the metrics of the drive.
+    pub(crate) latency_us: Arc<LatencyHistogram>,
+    // Records the completed requests to a trace file when set.
+    pub(crate) trace: Option<TraceWriter>,
 }
 
 macro_rules! unwrap_async_file_engine_or_return {
     ($file_engine: expr) => {
         match $file_engine {
             FileEngine::Async(engine) => engine,
-            FileEngine::Sync(_) => {
+            FileEngine::Sync(_)
+            | FileEngine::Qcow2(_)
+            | FileEngine::Overlay(_)
+            | FileEngine::Nbd(_)
+            | FileEngine::VhostUser(_) => {
                 error!("The block device doesn't use an async IO engine");
                 return;
             }
@@ -240,15 +563,84 @@ impl Block {
         is_disk_read_only: bool,
         is_disk_root: bool,
         rate_limiter: RateLimiter,
+        read_rate_limiter: RateLimiter,
+        write_rate_limiter: RateLimiter,
         file_engine_type: FileEngineType,
+        image_format: ImageFormat,
+        overlay_path: Option<String>,
+        num_queues: u16,
     ) -> result::Result<Block, Error> {
         let disk_properties = DiskProperties::new(
             disk_image_path,
             is_disk_read_only,
             cache_type,
             file_engine_type,
+            image_format,
+            overlay_path,
         )?;
 
+        Self::with_disk(
+            id,
+            partuuid,
+            is_disk_read_only,
+            is_disk_root,
+            rate_limiter,
+            read_rate_limiter,
+            write_rate_limiter,
+            disk_properties,
+            num_queues,
+        )
+    }
+
+    /// Create a new virtio block device backed by the file behind `disk_fd`, which was opened by
+    /// the caller and is owned by the device from now on.
+    pub fn new_with_fd(
+        id: String,
+        partuuid: Option<String>,
+        cache_type: CacheType,
+        disk_fd: RawFd,
+        is_disk_read_only: bool,
+        is_disk_root: bool,
+        rate_limiter: RateLimiter,
+        read_rate_limiter: RateLimiter,
+        write_rate_limiter: RateLimiter,
+        file_engine_type: FileEngineType,
+        image_format: ImageFormat,
+        num_queues: u16,
+    ) -> result::Result<Block, Error> {
+        let disk_properties = DiskProperties::new_with_fd(
+            disk_fd,
+            is_disk_read_only,
+            cache_type,
+            file_engine_type,
+            image_format,
+        )?;
+
+        Self::with_disk(
+            id,
+            partuuid,
+            is_disk_read_only,
+            is_disk_root,
+            rate_limiter,
+            read_rate_limiter,
+            write_rate_limiter,
+            disk_properties,
+            num_queues,
+        )
+    }
+
+    fn with_disk(
+        id: String,
+        partuuid: Option<String>,
+        is_disk_read_only: bool,
+        is_disk_root: bool,
+        rate_limiter: RateLimiter,
+        read_rate_limiter: RateLimiter,
+        write_rate_limiter: RateLimiter,
+        disk_properties: DiskProperties,
+        num_queues: u16,
+    ) -> result::Result<Block, Error> {
+        let cache_type = disk_properties.cache_type();
         let mut avail_features = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_RING_F_EVENT_IDX);
 
         if cache_type == CacheType::Writeback {
@@ -257,18 +649,125 @@ impl Block {
 
         if is_disk_read_only {
             avail_features |= 1u64 << VIRTIO_BLK_F_RO;
+        } else if disk_properties.file_engine().supports_write_zeroes() {
+            avail_features |= (1u64 << VIRTIO_BLK_F_DISCARD) | (1u64 << VIRTIO_BLK_F_WRITE_ZEROES);
         };
 
-        let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?];
+        if num_queues > 1 {
+            avail_features |= 1u64 << VIRTIO_BLK_F_MQ;
+        }
+
+        let config_space = disk_properties.virtio_block_config_space(num_queues);
+        Self::from_parts(
+            id,
+            partuuid,
+            is_disk_root,
+            rate_limiter,
+            read_rate_limiter,
+            write_rate_limiter,
+            disk_properties,
+            avail_features,
+            config_space,
+            num_queues,
+        )
+    }
+
+    /// Create a new virtio block device whose requests are served by the vhost-user backend
+    /// listening on the unix socket at `socket_path`. The guest memory has to be shared.
+    pub fn new_with_vhost_user(
+        id: String,
+        partuuid: Option<String>,
+        cache_type: CacheType,
+        socket_path: String,
+        is_disk_read_only: bool,
+        is_disk_root: bool,
+    ) -> result::Result<Block, Error> {
+        let vhost_user = VhostUser::connect(&socket_path, 1 << VHOST_USER_PROTOCOL_F_CONFIG)
+            .map_err(Error::VhostUser)?;
+        // The layout of the configuration space depends on the features, so it's taken as is
+        // from the backend.
+        let config_space = vhost_user
+            .get_config(CONFIG_SPACE_SIZE)
+            .map_err(Error::VhostUser)?;
+        let mut capacity = [0u8; 8];
+        capacity.copy_from_slice(&config_space[..8]);
+
+        let mut avail_features = vhost_user.features() & VHOST_USER_FEATURES;
+        // Without the flush feature, the backend has to complete the writes once they are
+        // durable.
+        if cache_type != CacheType::Writeback {
+            avail_features &= !(1u64 << VIRTIO_BLK_F_FLUSH);
+        }
+        // The backend can't be asked to reject writes, so it has to serve a read-only disk
+        // for read-only drives.
+        let mut missing_features = (1u64 << VIRTIO_F_VERSION_1) & !avail_features;
+        if is_disk_read_only {
+            missing_features |= (1u64 << VIRTIO_BLK_F_RO) & !avail_features;
+        }
+        if missing_features != 0 {
+            return Err(Error::VhostUser(VhostError::UnsupportedFeatures(
+                missing_features,
+            )));
+        }
+
+        let socket = vhost_user.try_clone_socket().map_err(Error::VhostUser)?;
+        // This is safe since the file descriptor is taken over from `socket`.
+        let socket = unsafe { File::from_raw_fd(socket.into_raw_fd()) };
+        // The device ID is provided by the backend.
+        let disk_properties = DiskProperties::from_parts(
+            cache_type,
+            socket_path,
+            None,
+            FileEngine::VhostUser(socket),
+            u64::from_le_bytes(capacity) << SECTOR_SHIFT,
+            [0; VIRTIO_BLK_ID_BYTES as usize],
+        );
+
+        let mut block = Self::from_parts(
+            id,
+            partuuid,
+            is_disk_root,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            disk_properties,
+            avail_features,
+            config_space,
+            1,
+        )?;
+        block.vhost_user = Some(vhost_user);
+        Ok(block)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_parts(
+        id: String,
+        partuuid: Option<String>,
+        is_disk_root: bool,
+        rate_limiter: RateLimiter,
+        read_rate_limiter: RateLimiter,
+        write_rate_limiter: RateLimiter,
+        disk_properties: DiskProperties,
+        avail_features: u64,
+        config_space: Vec<u8>,
+        num_queues: u16,
+    ) -> result::Result<Block, Error> {
+        let queue_evts = (0..num_queues)
+            .map(|_| EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd))
+            .collect::<result::Result<Vec<_>, _>>()?;
 
-        let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+        let queues = (0..num_queues).map(|_| Queue::new(QUEUE_SIZE)).collect();
 
         Ok(Block {
+            latency_us: METRICS.block_latencies_us.register(&id),
             id,
             root_device: is_disk_root,
             partuuid,
+            serial: None,
             rate_limiter,
-            config_space: disk_properties.virtio_block_config_space(),
+            read_rate_limiter,
+            write_rate_limiter,
+            config_space,
             disk: disk_properties,
             avail_features,
             acked_features: 0u64,
@@ -277,13 +776,17 @@ impl Block {
             device_state: DeviceState::Inactive,
             irq_trigger: IrqTrigger::new().map_err(Error::IrqTrigger)?,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            unplug_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            vhost_user: None,
+            vhost_call_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
             is_io_engine_throttled: false,
+            trace: None,
         })
     }
 
-    pub(crate) fn process_queue_event(&mut self) {
+    pub(crate) fn process_queue_event(&mut self, queue_index: usize) {
         METRICS.block.queue_event_count.inc();
-        if let Err(e) = self.queue_evts[0].read() {
+        if let Err(e) = self.queue_evts[queue_index].read() {
             error!("Failed to get queue event: {:?}", e);
             METRICS.block.event_fails.inc();
         } else if self.rate_limiter.is_blocked() {
@@ -291,21 +794,37 @@ impl Block {
         } else if self.is_io_engine_throttled {
             METRICS.block.io_engine_throttled_events.inc();
         } else {
-            self.process_virtio_queues();
+            self.process_queue(queue_index);
         }
     }
 
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
-        self.process_queue(0);
+        for queue_index in 0..self.queues.len() {
+            self.process_queue(queue_index);
+        }
     }
 
     pub(crate) fn process_rate_limiter_event(&mut self) {
         METRICS.block.rate_limiter_event_count.inc();
         // Upon rate limiter event, call the rate limiter handler
-        // and restart processing the queue.
+        // and restart processing the queues, which share the rate limiter.
         if self.rate_limiter.event_handler().is_ok() {
-            self.process_queue(0);
+            self.process_virtio_queues();
+        }
+    }
+
+    pub(crate) fn process_read_rate_limiter_event(&mut self) {
+        METRICS.block.rate_limiter_event_count.inc();
+        if self.read_rate_limiter.event_handler().is_ok() {
+            self.process_virtio_queues();
+        }
+    }
+
+    pub(crate) fn process_write_rate_limiter_event(&mut self) {
+        METRICS.block.rate_limiter_event_count.inc();
+        if self.write_rate_limiter.event_handler().is_ok() {
+            self.process_virtio_queues();
         }
     }
 
@@ -331,13 +850,19 @@ impl Block {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
+        // The disk may have been made read-only while the driver still sends writes.
+        let is_read_only = self.is_read_only();
         let queue = &mut self.queues[queue_index];
         let mut used_any = false;
 
         while let Some(head) = queue.pop_or_enable_notification(mem) {
             let processing_result = match Request::parse(&head, mem, self.disk.nsectors()) {
                 Ok(request) => {
-                    if request.rate_limit(&mut self.rate_limiter) {
+                    if request.rate_limit(
+                        &mut self.rate_limiter,
+                        &mut self.read_rate_limiter,
+                        &mut self.write_rate_limiter,
+                    ) {
                         // Stop processing the queue and return this descriptor chain to the
                         // avail ring, for later processing.
                         queue.undo_pop();
@@ -346,7 +871,25 @@ impl Block {
                     }
 
                     used_any = true;
-                    request.process(&mut self.disk, head.index, mem)
+                    let start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+                    let mut trace_record = request.trace_record(0);
+                    let res = if request.is_write() && is_read_only {
+                        ProcessingResult::Executed(request.reject_read_only(
+                            queue_index as u16,
+                            head.index,
+                            mem,
+                        ))
+                    } else {
+                        request.process(&mut self.disk, queue_index as u16, head.index, mem)
+                    };
+                    // The submitted requests are accounted for once they complete.
+                    if let ProcessingResult::Executed(_) = res {
+                        trace_record.latency_us =
+                            utils::time::get_time_us(utils::time::ClockType::Monotonic) - start_us;
+                        self.latency_us.record(trace_record.latency_us);
+                        Self::trace_request(&mut self.trace, &trace_record);
+                    }
+                    res
                 }
                 Err(e) => {
                     error!("Failed to parse available descriptor chain: {:?}", e);
@@ -393,7 +936,6 @@ impl Block {
 
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
-        let queue = &mut self.queues[0];
 
         loop {
             match engine.pop(mem) {
@@ -415,10 +957,15 @@ impl Block {
                             ))),
                         ),
                     };
+                    let queue_index = pending.queue_index();
+                    let latency_us = utils::time::get_time_us(utils::time::ClockType::Monotonic)
+                        - pending.start_us();
+                    self.latency_us.record(latency_us);
+                    Self::trace_request(&mut self.trace, &pending.trace_record(latency_us));
                     let finished = pending.finish(mem, res);
 
                     Self::add_used_descriptor(
-                        queue,
+                        &mut self.queues[queue_index],
                         finished.desc_idx,
                         finished.num_bytes_to_mem,
                         mem,
@@ -441,20 +988,28 @@ impl Block {
 
         if self.is_io_engine_throttled {
             self.is_io_engine_throttled = false;
-            self.process_queue(0);
+            self.process_virtio_queues();
         }
     }
 
     /// Update the backing file and the config space of the block device.
     pub fn update_disk_image(&mut self, disk_image_path: String) -> result::Result<(), Error> {
+        self.check_no_vhost_user("updating the backing file")?;
         let disk_properties = DiskProperties::new(
             disk_image_path,
             self.is_read_only(),
             self.cache_type(),
             self.file_engine_type(),
+            self.image_format(),
+            self.overlay_path().cloned(),
         )?;
         self.disk = disk_properties;
-        self.config_space = self.disk.virtio_block_config_space();
+        if let Some(serial) = &self.serial {
+            self.disk.set_image_id(serial);
+        }
+        self.config_space = self
+            .disk
+            .virtio_block_config_space(self.queues.len() as u16);
 
         // Kick the driver to pick up the changes.
         self.irq_trigger.trigger_irq(IrqType::Config).unwrap();
@@ -463,11 +1018,155 @@ impl Block {
         Ok(())
     }
 
+    /// Checks that the disk image at `disk_image_path` could replace the current one, without
+    /// updating the device.
+    pub fn validate_disk_image(&self, disk_image_path: &str) -> result::Result<(), Error> {
+        self.check_no_vhost_user("updating the backing file")?;
+        // The export is only reached when the backing file is updated.
+        if is_nbd_uri(disk_image_path) {
+            return NbdUri::parse(disk_image_path)
+                .map(|_| ())
+                .map_err(|err| Error::FileEngine(block_io::Error::Nbd(err)));
+        }
+        // Base images are only read.
+        let is_read_only = self.is_read_only() || self.overlay_path().is_some();
+        DiskProperties::open_file(disk_image_path, is_read_only, self.cache_type()).map(|_| ())
+    }
+
+    /// Grows the backing file to `size_bytes` if it's smaller, and lets the driver know about
+    /// the new capacity of the disk. A file which has been grown on the host past `size_bytes`
+    /// keeps its size, which is the one reported to the guest.
+    pub fn resize(&mut self, size_bytes: u64) -> result::Result<(), Error> {
+        self.validate_resize(size_bytes)?;
+        let file = self.disk.file();
+        let file_size = file.metadata().map_err(Error::GetFileMetadata)?.len();
+        if file_size < size_bytes {
+            file.set_len(size_bytes).map_err(Error::BackingFile)?;
+        }
+        self.disk.nsectors = cmp::max(file_size, size_bytes) >> SECTOR_SHIFT;
+        self.config_space = self
+            .disk
+            .virtio_block_config_space(self.queues.len() as u16);
+
+        // Kick the driver to pick up the new capacity.
+        self.irq_trigger
+            .trigger_irq(IrqType::Config)
+            .map_err(Error::IrqTrigger)?;
+
+        METRICS.block.update_count.inc();
+        Ok(())
+    }
+
+    /// Checks that the disk could be resized to `size_bytes`. Disks can only grow, by whole
+    /// sectors.
+    pub fn validate_resize(&self, size_bytes: u64) -> result::Result<(), Error> {
+        if self.image_format() == ImageFormat::Qcow2 {
+            return Err(Error::FileEngine(block_io::Error::Qcow2(
+                block_io::qcow2::Error::UnsupportedFeature("resizing"),
+            )));
+        }
+        if self.overlay_path().is_some() {
+            return Err(Error::FileEngine(block_io::Error::Overlay(
+                block_io::overlay::Error::UnsupportedFeature("resizing"),
+            )));
+        }
+        self.check_no_vhost_user("resizing")?;
+        // The size of the export is set by the server.
+        if let FileEngine::Nbd(_) = self.disk.file_engine() {
+            return Err(Error::FileEngine(block_io::Error::Nbd(
+                block_io::nbd::Error::UnsupportedFeature("resizing"),
+            )));
+        }
+        if size_bytes % SECTOR_SIZE != 0 || size_bytes < self.disk.nsectors << SECTOR_SHIFT {
+            return Err(Error::InvalidDiskSize(size_bytes));
+        }
+        Ok(())
+    }
+
+    /// Makes the disk read-only or read-write, reopening the backing file in the new mode. The
+    /// driver negotiated the features of the device on activation, so the writes it keeps
+    /// sending to a disk made read-only are failed with an I/O error. The requests in flight
+    /// complete before the backing file is reopened.
+    pub fn set_read_only(&mut self, read_only: bool) -> result::Result<(), Error> {
+        self.validate_read_only()?;
+        if read_only == self.is_read_only() {
+            return Ok(());
+        }
+
+        self.prepare_save();
+        let disk_properties = DiskProperties::new(
+            self.file_path().clone(),
+            read_only,
+            self.cache_type(),
+            self.file_engine_type(),
+            self.image_format(),
+            None,
+        )?;
+        self.disk = disk_properties;
+        if let Some(serial) = &self.serial {
+            self.disk.set_image_id(serial);
+        }
+        if read_only {
+            self.avail_features |= 1u64 << VIRTIO_BLK_F_RO;
+        } else {
+            self.avail_features &= !(1u64 << VIRTIO_BLK_F_RO);
+        }
+
+        METRICS.block.update_count.inc();
+        Ok(())
+    }
+
+    /// Checks that the disk could be made read-only or read-write. The root device, the disks
+    /// with an overlay and the ones handed over as a file descriptor keep their mode.
+    pub fn validate_read_only(&self) -> result::Result<(), Error> {
+        self.check_no_vhost_user("changing the read-only mode")?;
+        if self.overlay_path().is_some() {
+            return Err(Error::FileEngine(block_io::Error::Overlay(
+                block_io::overlay::Error::UnsupportedFeature("read-only mode"),
+            )));
+        }
+        if self.root_device {
+            return Err(Error::UnsupportedReadOnlyToggle("the root device"));
+        }
+        if self.has_disk_fd() {
+            return Err(Error::UnsupportedReadOnlyToggle(
+                "drives opened from a file descriptor",
+            ));
+        }
+        Ok(())
+    }
+
+    // Rejects the `operation` on drives served by a vhost-user backend, whose disk is out of
+    // reach of the device model.
+    fn check_no_vhost_user(&self, operation: &'static str) -> result::Result<(), Error> {
+        if self.vhost_user.is_some() {
+            return Err(Error::FileEngine(block_io::Error::VhostUser(operation)));
+        }
+        Ok(())
+    }
+
     /// Updates the parameters for the rate limiter
     pub fn update_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
         self.rate_limiter.update_buckets(bytes, ops);
     }
 
+    /// Updates the parameters for the rate limiter of the reads.
+    pub fn update_read_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
+        self.read_rate_limiter.update_buckets(bytes, ops);
+    }
+
+    /// Updates the parameters for the rate limiter of the writes, which also limits the discard
+    /// and write zeroes requests.
+    pub fn update_write_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
+        self.write_rate_limiter.update_buckets(bytes, ops);
+    }
+
+    /// Makes the rate limiter a member of `group`, so that the I/O of this device is also
+    /// limited by the buckets shared with the other members.
+    pub fn set_rate_limiter_group(&mut self, group: Option<RateLimiterGroup>) {
+        self.rate_limiter.set_group(group);
+    }
+
     /// Provides the ID of this block device.
     pub fn id(&self) -> &String {
         &self.id
@@ -478,11 +1177,76 @@ impl Block {
         self.disk.file_path()
     }
 
+    /// Specifies if the backing file of this block device was handed over as a file descriptor,
+    /// in which case it has no path.
+    pub fn has_disk_fd(&self) -> bool {
+        self.vhost_user.is_none() && self.disk.file_path().is_empty()
+    }
+
+    /// Provides the path of the file holding the writes of this block device, if the backing file
+    /// is a base image shared with other devices.
+    pub fn overlay_path(&self) -> Option<&String> {
+        self.disk.overlay_path()
+    }
+
+    /// Provides the path of the socket of the vhost-user backend serving this block device, if
+    /// any.
+    pub fn vhost_user_socket(&self) -> Option<&String> {
+        self.vhost_user.as_ref().map(|_| self.disk.file_path())
+    }
+
     /// Provides the PARTUUID of this block device.
     pub fn partuuid(&self) -> Option<&String> {
         self.partuuid.as_ref()
     }
 
+    /// Provides the serial reported to the guest as the ID of this block device, if it
+    /// replaces the one derived from the disk image.
+    pub fn serial(&self) -> Option<&String> {
+        self.serial.as_ref()
+    }
+
+    /// Sets the serial reported to the guest as the ID of this block device, which keeps the
+    /// identity of the disk stable across updates of the backing file and snapshot restores.
+    /// Only the first `VIRTIO_BLK_ID_BYTES` bytes are visible to the guest.
+    pub fn set_serial(&mut self, serial: Option<String>) {
+        if let Some(serial) = &serial {
+            self.disk.set_image_id(serial);
+        }
+        self.serial = serial;
+    }
+
+    /// Starts recording the requests served by the device to `trace`, or stops when `None`.
+    /// The requests served by a vhost-user backend don't go through Firecracker and can't be
+    /// traced.
+    pub fn set_trace(&mut self, trace: Option<TraceWriter>) -> result::Result<(), Error> {
+        if trace.is_some() && self.vhost_user.is_some() {
+            return Err(Error::VhostUserTrace);
+        }
+        self.trace = trace;
+        Ok(())
+    }
+
+    /// Returns true if the requests served by the device are being traced.
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    // Appends a completed request to the trace if there's one. The trace stops on the first
+    // failure.
+    fn trace_request(trace: &mut Option<TraceWriter>, record: &TraceRecord) {
+        if let Some(writer) = trace.as_mut() {
+            if let Err(e) = writer.write_record(record) {
+                error!(
+                    "Failed to trace block request to {:?}, stopping trace: {:?}",
+                    writer.path(),
+                    e
+                );
+                *trace = None;
+            }
+        }
+    }
+
     /// Specifies if this block device is read only.
     pub fn is_read_only(&self) -> bool {
         self.avail_features & (1u64 << VIRTIO_BLK_F_RO) != 0
@@ -503,13 +1267,31 @@ impl Block {
         &self.rate_limiter
     }
 
+    /// Provides non-mutable reference to the rate limiter of the reads of this device.
+    pub fn read_rate_limiter(&self) -> &RateLimiter {
+        &self.read_rate_limiter
+    }
+
+    /// Provides non-mutable reference to the rate limiter of the writes of this device.
+    pub fn write_rate_limiter(&self) -> &RateLimiter {
+        &self.write_rate_limiter
+    }
+
     pub fn file_engine_type(&self) -> FileEngineType {
         match self.disk.file_engine() {
-            FileEngine::Sync(_) => FileEngineType::Sync,
+            FileEngine::Sync(_)
+            | FileEngine::Qcow2(_)
+            | FileEngine::Overlay(_)
+            | FileEngine::Nbd(_)
+            | FileEngine::VhostUser(_) => FileEngineType::Sync,
             FileEngine::Async(_) => FileEngineType::Async,
         }
     }
 
+    pub fn image_format(&self) -> ImageFormat {
+        self.disk.image_format()
+    }
+
     fn drain_and_flush(&mut self, discard: bool) {
         if let Err(e) = self.disk.file_engine_mut().drain_and_flush(discard) {
             error!("Failed to drain ops and flush block data: {:?}", e);
@@ -526,6 +1308,48 @@ impl Block {
             self.process_async_completion_queue();
         }
     }
+
+    // Hands the ring over to the vhost-user backend. Needs to run on the VMM thread, after
+    // activation.
+    pub(crate) fn start_vhost_user(&self) -> result::Result<(), VhostError> {
+        let vhost_user = match self.vhost_user.as_ref() {
+            Some(vhost_user) => vhost_user,
+            None => return Ok(()),
+        };
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+
+        vhost_user.set_features(self.acked_features & vhost_user.features())?;
+        vhost_user.set_mem_table(mem)?;
+        vhost_user.set_vring(
+            0,
+            &self.queues[0],
+            mem,
+            &self.queue_evts[0],
+            &self.vhost_call_evt,
+        )?;
+        vhost_user.start_vring(0, -1)
+    }
+
+    /// Relays to the guest the notification the vhost-user backend sent for the queue.
+    pub fn process_vhost_call_event(&mut self) {
+        if let Err(e) = self.vhost_call_evt.read() {
+            error!("Failed to get vhost call event: {:?}", e);
+            METRICS.block.event_fails.inc();
+            return;
+        }
+        if let Err(e) = self.irq_trigger.trigger_irq(IrqType::Vring) {
+            error!("Failed to signal used queue: {:?}", e);
+            METRICS.block.event_fails.inc();
+        }
+    }
+
+    /// Completes the in-flight requests, flushes the backing file and asks the device to stop
+    /// processing events. The device must have been detached from the guest beforehand.
+    pub fn unplug(&mut self) -> std::io::Result<()> {
+        self.prepare_save();
+        self.unplug_evt.write(1)
+    }
 }
 
 impl VirtioDevice for Block {
@@ -616,7 +1440,8 @@ impl VirtioDevice for Block {
 impl Drop for Block {
     fn drop(&mut self) {
         match self.disk.cache_type {
-            CacheType::Unsafe => {
+            // The completed writes of Writethrough disks are already on the storage.
+            CacheType::Unsafe | CacheType::Writethrough => {
                 if let Err(e) = self.disk.file_engine_mut().drain(true) {
                     error!("Failed to drain ops on drop: {:?}", e);
                 }
@@ -633,6 +1458,7 @@ pub(crate) mod tests {
     use std::fs::metadata;
     use std::io::Read;
     use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
     use std::time::Duration;
     use std::{thread, u32};
 
@@ -643,13 +1469,19 @@ pub(crate) mod tests {
 
     use super::*;
     use crate::check_metric_after_block;
+    use crate::virtio::block::io::nbd::tests::spawn_unix_server;
+    use crate::virtio::block::io::overlay::tests::{create_base, BASE_SIZE};
+    use crate::virtio::block::io::qcow2::tests::{create_image, VIRTUAL_SIZE};
     use crate::virtio::block::test_utils::{
-        default_block, default_engine_type_for_kv, set_queue, set_rate_limiter,
-        simulate_async_completion_event, simulate_queue_and_async_completion_events,
-        simulate_queue_event,
+        default_block, default_block_with_path, default_engine_type_for_kv, set_queue,
+        set_rate_limiter, simulate_async_completion_event,
+        simulate_queue_and_async_completion_events, simulate_queue_event,
     };
+    use crate::virtio::block::TraceFormat;
     use crate::virtio::queue::tests::*;
     use crate::virtio::test_utils::{default_mem, initialize_virtqueue, VirtQueue};
+    use crate::virtio::vhost_user::tests::spawn_backend;
+    use crate::virtio::vhost_user::VHOST_USER_F_PROTOCOL_FEATURES;
     use crate::virtio::IO_URING_NUM_ENTRIES;
 
     #[test]
@@ -664,16 +1496,29 @@ pub(crate) mod tests {
             true,
             CacheType::Unsafe,
             default_engine_type_for_kv(),
+            ImageFormat::Raw,
+            None,
         )
         .unwrap();
 
         assert_eq!(size, SECTOR_SIZE * num_sectors);
         assert_eq!(disk_properties.nsectors, num_sectors);
-        let cfg = disk_properties.virtio_block_config_space();
+        let cfg = disk_properties.virtio_block_config_space(4);
         assert_eq!(cfg.len(), CONFIG_SPACE_SIZE);
-        for (i, byte) in cfg.iter().enumerate() {
+        for (i, byte) in cfg[..8].iter().enumerate() {
             assert_eq!(*byte, (num_sectors >> (8 * i)) as u8);
         }
+        assert!(cfg[8..NUM_QUEUES_CONFIG_OFFSET]
+            .iter()
+            .all(|byte| *byte == 0));
+        assert_eq!(cfg[NUM_QUEUES_CONFIG_OFFSET..DISCARD_CONFIG_OFFSET], [4, 0]);
+        assert_eq!(
+            cfg[DISCARD_CONFIG_OFFSET..],
+            [
+                0xff, 0xff, 0xff, 0xff, 1, 0, 0, 0, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 1, 0, 0, 0,
+                1, 0, 0, 0
+            ]
+        );
         // Testing `backing_file.virtio_block_disk_image_id()` implies
         // duplicating that logic in tests, so skipping it.
 
@@ -682,6 +1527,8 @@ pub(crate) mod tests {
             true,
             CacheType::Unsafe,
             default_engine_type_for_kv(),
+            ImageFormat::Raw,
+            None,
         )
         .is_err());
     }
@@ -692,7 +1539,10 @@ pub(crate) mod tests {
 
         assert_eq!(block.device_type(), TYPE_BLOCK);
 
-        let features: u64 = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_RING_F_EVENT_IDX);
+        let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
+            | (1u64 << VIRTIO_RING_F_EVENT_IDX)
+            | (1u64 << VIRTIO_BLK_F_DISCARD)
+            | (1u64 << VIRTIO_BLK_F_WRITE_ZEROES);
 
         assert_eq!(block.avail_features_by_page(0), features as u32);
         assert_eq!(block.avail_features_by_page(1), (features >> 32) as u32);
@@ -711,18 +1561,16 @@ pub(crate) mod tests {
     fn test_virtio_read_config() {
         let block = default_block(default_engine_type_for_kv());
 
-        let mut actual_config_space = [0u8; CONFIG_SPACE_SIZE];
+        let mut actual_config_space = [0u8; 8];
         block.read_config(0, &mut actual_config_space);
-        // This will read the number of sectors.
+        // This will read the number of sectors, the first field of the config space.
         // The block's backing file size is 0x1000, so there are 8 (4096/512) sectors.
         // The config space is little endian.
-        let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let expected_config_space: [u8; 8] = [0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
         assert_eq!(actual_config_space, expected_config_space);
 
         // Invalid read.
-        let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
+        let expected_config_space: [u8; 8] = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
         actual_config_space = expected_config_space;
         block.read_config(CONFIG_SPACE_SIZE as u64 + 1, &mut actual_config_space);
 
@@ -734,11 +1582,10 @@ pub(crate) mod tests {
     fn test_virtio_write_config() {
         let mut block = default_block(default_engine_type_for_kv());
 
-        let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
-            [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let expected_config_space: [u8; 8] = [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
         block.write_config(0, &expected_config_space);
 
-        let mut actual_config_space = [0u8; CONFIG_SPACE_SIZE];
+        let mut actual_config_space = [0u8; 8];
         block.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
 
@@ -752,7 +1599,7 @@ pub(crate) mod tests {
 
         // Invalid write.
         let new_config_space = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
-        block.write_config(5, &new_config_space);
+        block.write_config(CONFIG_SPACE_SIZE as u64 - 3, &new_config_space);
         // Make sure nothing got written.
         block.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
@@ -1295,6 +2142,134 @@ pub(crate) mod tests {
             assert_eq!(vq.used.ring[0].get().len, 1);
             assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
         }
+
+        // The service time of the requests is recorded. The histogram is shared with the other
+        // tests using the same drive ID.
+        assert!(block.latency_us.count() >= 2);
+    }
+
+    #[test]
+    fn test_trace() {
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let path = dir.as_path().join("trace.jsonl");
+        let mut block = default_block(default_engine_type_for_kv());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        block
+            .set_trace(Some(
+                TraceWriter::new(&path, TraceFormat::Jsonl, 100).unwrap(),
+            ))
+            .unwrap();
+        assert!(block.is_tracing());
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+            .unwrap();
+        simulate_queue_and_async_completion_events(&mut block, true);
+        assert_eq!(vq.used.idx.get(), 1);
+
+        // The read of the whole disk is recorded once it completes.
+        let trace = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("\"type\":\"In\",\"offset\":0,\"len\":4096,"));
+
+        block.set_trace(None).unwrap();
+        assert!(!block.is_tracing());
+    }
+
+    #[test]
+    fn test_multi_queue() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = Block::new(
+            "multi-queue".to_string(),
+            None,
+            CacheType::Unsafe,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            default_engine_type_for_kv(),
+            ImageFormat::Raw,
+            None,
+            4,
+        )
+        .unwrap();
+        assert_eq!(block.queues().len(), 4);
+        assert_eq!(block.queue_events().len(), 4);
+        assert_ne!(block.avail_features() & (1u64 << VIRTIO_BLK_F_MQ), 0);
+        let mut num_queues = [0u8; 2];
+        block.read_config(NUM_QUEUES_CONFIG_OFFSET as u64, &mut num_queues);
+        assert_eq!(u16::from_le_bytes(num_queues), 4);
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let idle_vq = VirtQueue::new(GuestAddress(0x800), &mem, 16);
+        set_queue(&mut block, 0, idle_vq.create_queue());
+        set_queue(&mut block, 2, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(vq.dtable[0].addr.get()))
+            .unwrap();
+
+        // The request completes on the queue it was submitted to.
+        block.queue_evts[2].write(1).unwrap();
+        block.process_queue_event(2);
+        simulate_async_completion_event(&mut block, true);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(vq.used.ring[0].get().id, 0);
+        assert_eq!(
+            mem.read_obj::<u32>(GuestAddress(vq.dtable[2].addr.get()))
+                .unwrap(),
+            VIRTIO_BLK_S_OK
+        );
+        assert_eq!(idle_vq.used.idx.get(), 0);
+
+        // A single queue doesn't need the multi-queue feature.
+        let block = default_block(default_engine_type_for_kv());
+        assert_eq!(block.queues().len(), 1);
+        assert_eq!(block.avail_features() & (1u64 << VIRTIO_BLK_F_MQ), 0);
+    }
+
+    #[test]
+    fn test_writethrough() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = Block::new(
+            "writethrough".to_string(),
+            None,
+            CacheType::Writethrough,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            default_engine_type_for_kv(),
+            ImageFormat::Raw,
+            None,
+            1,
+        )
+        .unwrap();
+
+        // The writes are synchronous, so there is nothing to flush.
+        assert_eq!(block.cache_type(), CacheType::Writethrough);
+        assert_eq!(block.avail_features() & (1u64 << VIRTIO_BLK_F_FLUSH), 0);
+        // Safe because the file descriptor is valid and the return value is checked.
+        let flags = unsafe { libc::fcntl(block.disk.file().as_raw_fd(), libc::F_GETFL) };
+        assert!(flags >= 0);
+        assert_ne!(flags & libc::O_DSYNC, 0);
+
+        // The page cache of the other cache types is flushed on demand.
+        let block = default_block(default_engine_type_for_kv());
+        let flags = unsafe { libc::fcntl(block.disk.file().as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_DSYNC, 0);
     }
 
     #[test]
@@ -1650,6 +2625,80 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_read_write_rate_limiters() {
+        let mut block = default_block(default_engine_type_for_kv());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+
+        // Create ops rate limiter for the writes that allows only 10 ops/s with bucket size of
+        // 1 ops.
+        let mut rl = RateLimiter::new(0, 0, 0, 1, 0, 100).unwrap();
+        // Use up the budget.
+        assert!(rl.consume(1, TokenType::Ops));
+        block.write_rate_limiter = rl;
+
+        // Reads aren't limited by the rate limiter of the writes.
+        {
+            mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+                .unwrap();
+            vq.dtable[1].len.set(512);
+
+            check_metric_after_block!(
+                &METRICS.block.rate_limiter_throttled_events,
+                0,
+                simulate_queue_and_async_completion_events(&mut block, true)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        }
+
+        // Following write procedure should fail because of write ops rate limiting.
+        {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+                .unwrap();
+            // Make data read only.
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+
+            check_metric_after_block!(
+                &METRICS.block.rate_limiter_throttled_events,
+                1,
+                simulate_queue_event(&mut block, Some(false))
+            );
+            // Only the limiter of the writes is blocked.
+            assert!(block.write_rate_limiter.is_blocked());
+            assert!(!block.rate_limiter.is_blocked());
+            assert!(!block.read_rate_limiter.is_blocked());
+            // Make sure the data is still queued for processing.
+            assert_eq!(vq.used.idx.get(), 0);
+        }
+
+        // Wait for 100ms to give the rate-limiter timer a chance to replenish.
+        // Wait for an extra 50ms to make sure the timerfd event makes its way from the kernel.
+        thread::sleep(Duration::from_millis(150));
+
+        // Following write procedure should succeed because ops budget should now be available.
+        {
+            block.process_write_rate_limiter_event();
+            assert!(!block.write_rate_limiter.is_blocked());
+            // Complete async IO ops if needed
+            simulate_async_completion_event(&mut block, true);
+
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        }
+    }
+
     #[test]
     fn test_update_disk_image() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1662,6 +2711,8 @@ pub(crate) mod tests {
         id[..cmp::min(part_id.len(), VIRTIO_BLK_ID_BYTES as usize)]
             .clone_from_slice(&part_id[..cmp::min(part_id.len(), VIRTIO_BLK_ID_BYTES as usize)]);
 
+        assert!(block.validate_disk_image("/invalid/path").is_err());
+        block.validate_disk_image(path.to_str().unwrap()).unwrap();
         block
             .update_disk_image(String::from(path.to_str().unwrap()))
             .unwrap();
@@ -1672,4 +2723,446 @@ pub(crate) mod tests {
         );
         assert_eq!(block.disk.image_id, id.as_slice());
     }
+
+    #[test]
+    fn test_serial() {
+        let mut block = default_block(default_engine_type_for_kv());
+        assert_eq!(block.serial(), None);
+
+        // The serial is truncated to the bytes the guest can read.
+        let serial = "0123456789abcdefghijklmnop".to_string();
+        block.set_serial(Some(serial.clone()));
+        assert_eq!(block.serial(), Some(&serial));
+        assert_eq!(block.disk.image_id(), &serial.as_bytes()[..20]);
+
+        // The serial outlives the backing file.
+        let f = TempFile::new().unwrap();
+        block
+            .update_disk_image(f.as_path().to_str().unwrap().to_string())
+            .unwrap();
+        assert_eq!(block.disk.image_id(), &serial.as_bytes()[..20]);
+    }
+
+    #[test]
+    fn test_set_read_only() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = default_block_with_path(
+            f.as_path().to_str().unwrap().to_string(),
+            default_engine_type_for_kv(),
+        );
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        block.validate_read_only().unwrap();
+        block.set_read_only(true).unwrap();
+        assert!(block.is_read_only());
+        // The backing file is reopened read-only.
+        let flags = unsafe { libc::fcntl(block.disk.file().as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_ACCMODE, libc::O_RDONLY);
+
+        // The writes of the driver are rejected.
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+            .unwrap();
+        simulate_queue_event(&mut block, Some(true));
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(vq.used.ring[0].get().len, 1);
+        assert_eq!(
+            mem.read_obj::<u8>(status_addr).unwrap(),
+            VIRTIO_BLK_S_IOERR as u8
+        );
+
+        block.set_read_only(false).unwrap();
+        assert!(!block.is_read_only());
+        let flags = unsafe { libc::fcntl(block.disk.file().as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_ACCMODE, libc::O_RDWR);
+
+        // The root device keeps its mode.
+        block.root_device = true;
+        assert!(matches!(
+            block.set_read_only(true),
+            Err(Error::UnsupportedReadOnlyToggle(_))
+        ));
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut block = default_block(default_engine_type_for_kv());
+        let size = block.disk.file().metadata().unwrap().len();
+        assert_eq!(block.disk.nsectors(), size >> SECTOR_SHIFT);
+
+        // Shrinking and partial sectors are rejected.
+        assert!(matches!(
+            block.resize(size - SECTOR_SIZE),
+            Err(Error::InvalidDiskSize(_))
+        ));
+        assert!(matches!(
+            block.validate_resize(size + 1),
+            Err(Error::InvalidDiskSize(_))
+        ));
+        block.validate_resize(size).unwrap();
+        assert_eq!(block.disk.nsectors(), size >> SECTOR_SHIFT);
+
+        // Growing the disk grows its backing file.
+        block.resize(2 * size).unwrap();
+        assert_eq!(block.disk.file().metadata().unwrap().len(), 2 * size);
+        assert_eq!(block.disk.nsectors(), (2 * size) >> SECTOR_SHIFT);
+        assert_eq!(
+            block.config_space[..8],
+            ((2 * size) >> SECTOR_SHIFT).to_le_bytes()
+        );
+        assert!(block.irq_trigger.has_pending_irq(IrqType::Config));
+
+        // A backing file grown on the host keeps its size.
+        block.disk.file().set_len(4 * size).unwrap();
+        block.resize(3 * size).unwrap();
+        assert_eq!(block.disk.file().metadata().unwrap().len(), 4 * size);
+        assert_eq!(block.disk.nsectors(), (4 * size) >> SECTOR_SHIFT);
+    }
+
+    #[test]
+    fn test_qcow2_block() {
+        let image = create_image(4);
+        let path = image.as_path().to_str().unwrap().to_string();
+        let new_block = |file_engine_type| {
+            Block::new(
+                "test".to_string(),
+                None,
+                CacheType::Unsafe,
+                path.clone(),
+                false,
+                false,
+                RateLimiter::default(),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                file_engine_type,
+                ImageFormat::Qcow2,
+                None,
+                1,
+            )
+        };
+
+        assert!(matches!(
+            new_block(FileEngineType::Async),
+            Err(Error::FileEngine(block_io::Error::UnsupportedEngine(
+                FileEngineType::Async
+            )))
+        ));
+
+        let mut block = new_block(FileEngineType::Sync).unwrap();
+        assert_eq!(block.image_format(), ImageFormat::Qcow2);
+        assert_eq!(block.file_engine_type(), FileEngineType::Sync);
+        // The guest sees the virtual size of the image, rather than the size of the file.
+        assert_eq!(block.disk.nsectors(), VIRTUAL_SIZE >> SECTOR_SHIFT);
+        assert_eq!(
+            block.config_space[..8],
+            (VIRTUAL_SIZE >> SECTOR_SHIFT).to_le_bytes()
+        );
+        assert_eq!(
+            block.avail_features(),
+            (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_RING_F_EVENT_IDX)
+        );
+        assert!(block.resize(2 * VIRTUAL_SIZE).is_err());
+
+        // Raw images are rejected.
+        let raw = TempFile::new().unwrap();
+        raw.as_file().set_len(0x1000).unwrap();
+        assert!(Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            raw.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            FileEngineType::Sync,
+            ImageFormat::Qcow2,
+            None,
+            1,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_overlay_block() {
+        let base = create_base();
+        let base_path = base.as_path().to_str().unwrap().to_string();
+        let mut overlay = TempFile::new().unwrap();
+        let overlay_path = overlay.as_path().to_str().unwrap().to_string();
+        // The overlay is created along with the device.
+        overlay.remove().unwrap();
+        let new_block = |file_engine_type, image_format| {
+            Block::new(
+                "test".to_string(),
+                None,
+                CacheType::Unsafe,
+                base_path.clone(),
+                false,
+                false,
+                RateLimiter::default(),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                file_engine_type,
+                image_format,
+                Some(overlay_path.clone()),
+                1,
+            )
+        };
+
+        assert!(matches!(
+            new_block(FileEngineType::Async, ImageFormat::Raw),
+            Err(Error::FileEngine(block_io::Error::UnsupportedEngine(
+                FileEngineType::Async
+            )))
+        ));
+        assert!(matches!(
+            new_block(FileEngineType::Sync, ImageFormat::Qcow2),
+            Err(Error::FileEngine(block_io::Error::Overlay(_)))
+        ));
+
+        let mut block = new_block(FileEngineType::Sync, ImageFormat::Raw).unwrap();
+        assert_eq!(block.file_path(), &base_path);
+        assert_eq!(block.overlay_path(), Some(&overlay_path));
+        assert_eq!(block.disk.nsectors(), BASE_SIZE >> SECTOR_SHIFT);
+        assert_eq!(block.disk.file().metadata().unwrap().len(), 0);
+        assert!(!block.is_read_only());
+        assert_eq!(
+            block.avail_features(),
+            (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_RING_F_EVENT_IDX)
+        );
+        assert!(block.resize(2 * BASE_SIZE).is_err());
+
+        // The base image can be replaced, as long as it's readable, and the overlay is kept.
+        let new_base = create_base();
+        let new_base_path = new_base.as_path().to_str().unwrap().to_string();
+        block.validate_disk_image(&new_base_path).unwrap();
+        block.update_disk_image(new_base_path.clone()).unwrap();
+        assert_eq!(block.file_path(), &new_base_path);
+        assert_eq!(block.overlay_path(), Some(&overlay_path));
+    }
+
+    #[test]
+    fn test_nbd_block() {
+        let path = "/tmp/fc-nbd-blk.sock";
+        let uri = format!("nbd+unix:///export?socket={}", path);
+        let export_size = 16 << 10;
+        let server = spawn_unix_server(path, vec![0; export_size as usize]);
+        let new_block = |file_engine_type, image_format| {
+            Block::new(
+                "test".to_string(),
+                None,
+                CacheType::Writeback,
+                uri.clone(),
+                false,
+                false,
+                RateLimiter::default(),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                file_engine_type,
+                image_format,
+                None,
+                1,
+            )
+        };
+
+        // The configuration is checked before connecting to the server.
+        assert!(matches!(
+            new_block(FileEngineType::Async, ImageFormat::Raw),
+            Err(Error::FileEngine(block_io::Error::UnsupportedEngine(
+                FileEngineType::Async
+            )))
+        ));
+        assert!(matches!(
+            new_block(FileEngineType::Sync, ImageFormat::Qcow2),
+            Err(Error::FileEngine(block_io::Error::Nbd(_)))
+        ));
+
+        let mut block = new_block(FileEngineType::Sync, ImageFormat::Raw).unwrap();
+        assert_eq!(block.file_path(), &uri);
+        assert_eq!(block.disk.nsectors(), export_size >> SECTOR_SHIFT);
+        // The export name identifies the disk.
+        assert_eq!(&block.disk.image_id()[..6], b"export");
+        assert_eq!(
+            block.avail_features(),
+            (1u64 << VIRTIO_F_VERSION_1)
+                | (1u64 << VIRTIO_RING_F_EVENT_IDX)
+                | (1u64 << VIRTIO_BLK_F_FLUSH)
+                | (1u64 << VIRTIO_BLK_F_DISCARD)
+                | (1u64 << VIRTIO_BLK_F_WRITE_ZEROES)
+        );
+        assert!(block.resize(2 * export_size).is_err());
+        assert!(block.validate_disk_image("nbd:///export").is_err());
+        block.validate_disk_image("nbd://10.0.0.1/export").unwrap();
+
+        // The connection is closed along with the device.
+        drop(block);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_block_with_fd() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let open = |write: bool, custom_flags: i32| {
+            OpenOptions::new()
+                .read(true)
+                .write(write)
+                .custom_flags(custom_flags)
+                .open(f.as_path())
+                .unwrap()
+                .into_raw_fd()
+        };
+        let new_block = |cache_type, disk_fd, is_disk_read_only| {
+            Block::new_with_fd(
+                "test".to_string(),
+                None,
+                cache_type,
+                disk_fd,
+                is_disk_read_only,
+                false,
+                RateLimiter::default(),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                FileEngineType::Sync,
+                ImageFormat::Raw,
+                1,
+            )
+        };
+
+        // The access mode of the file must allow the writes of the drive.
+        assert!(matches!(
+            new_block(CacheType::Unsafe, open(false, 0), false),
+            Err(Error::InvalidDiskFd(_))
+        ));
+        // The writes of a Writethrough drive must be synchronous.
+        assert!(matches!(
+            new_block(CacheType::Writethrough, open(true, 0), false),
+            Err(Error::InvalidDiskFd(_))
+        ));
+        new_block(CacheType::Writethrough, open(true, libc::O_DSYNC), false).unwrap();
+
+        let block = new_block(CacheType::Unsafe, open(false, 0), true).unwrap();
+        assert!(block.has_disk_fd());
+        assert!(block.file_path().is_empty());
+        assert!(block.is_read_only());
+        assert_eq!(block.disk.nsectors(), 0x1000 >> SECTOR_SHIFT);
+        assert_eq!(
+            block.disk.file().metadata().unwrap().st_ino(),
+            metadata(f.as_path()).unwrap().st_ino()
+        );
+
+        let block = default_block(default_engine_type_for_kv());
+        assert!(!block.has_disk_fd());
+    }
+
+    #[test]
+    fn test_vhost_user_block() {
+        let path = "/tmp/fc-vhost-user-blk.sock";
+        let features = (1 << VHOST_USER_F_PROTOCOL_FEATURES)
+            | (1 << VIRTIO_F_VERSION_1)
+            | (1 << VIRTIO_BLK_F_FLUSH)
+            | (1 << virtio_gen::virtio_blk::VIRTIO_BLK_F_MQ);
+        // A 1MiB disk.
+        let mut config = vec![0u8; CONFIG_SPACE_SIZE];
+        config[..8].copy_from_slice(&2048u64.to_le_bytes());
+        let backend = spawn_backend(path, features, config.clone());
+
+        let mut block = Block::new_with_vhost_user(
+            "vhost-user".to_string(),
+            None,
+            CacheType::Writeback,
+            path.to_string(),
+            false,
+            false,
+        )
+        .unwrap();
+        // There is a single queue, and the configuration space comes from the backend.
+        assert_eq!(
+            block.avail_features(),
+            (1 << VIRTIO_F_VERSION_1) | (1 << VIRTIO_BLK_F_FLUSH)
+        );
+        let mut capacity = [0u8; 8];
+        block.read_config(0, &mut capacity);
+        assert_eq!(capacity, 2048u64.to_le_bytes());
+        assert_eq!(block.disk.nsectors(), 2048);
+        assert_eq!(block.vhost_user_socket(), Some(&path.to_string()));
+        assert_eq!(block.file_path(), path);
+        assert_eq!(block.file_engine_type(), FileEngineType::Sync);
+        assert!(!block.is_read_only());
+
+        // The disk is out of reach of the device model.
+        assert!(matches!(
+            block.resize(1 << 21),
+            Err(Error::FileEngine(block_io::Error::VhostUser("resizing")))
+        ));
+        assert!(matches!(
+            block.update_disk_image(path.to_string()),
+            Err(Error::FileEngine(block_io::Error::VhostUser(_)))
+        ));
+        let trace_path = utils::tempfile::TempFile::new().unwrap();
+        assert!(matches!(
+            block.set_trace(Some(
+                TraceWriter::new(trace_path.as_path(), TraceFormat::Jsonl, 1).unwrap()
+            )),
+            Err(Error::VhostUserTrace)
+        ));
+        drop(block);
+        backend.join().unwrap();
+
+        // Read-only drives need a read-only disk.
+        let backend = spawn_backend(path, features, config.clone());
+        assert!(matches!(
+            Block::new_with_vhost_user(
+                "vhost-user".to_string(),
+                None,
+                CacheType::Unsafe,
+                path.to_string(),
+                true,
+                false,
+            ),
+            Err(Error::VhostUser(VhostError::UnsupportedFeatures(missing)))
+                if missing == 1 << VIRTIO_BLK_F_RO
+        ));
+        backend.join().unwrap();
+
+        // Flushes are only advertised with the Writeback cache type.
+        let backend = spawn_backend(path, features | (1 << VIRTIO_BLK_F_RO), config);
+        let block = Block::new_with_vhost_user(
+            "vhost-user".to_string(),
+            None,
+            CacheType::Unsafe,
+            path.to_string(),
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            block.avail_features(),
+            (1 << VIRTIO_F_VERSION_1) | (1 << VIRTIO_BLK_F_RO)
+        );
+        assert!(block.is_read_only());
+        drop(block);
+        backend.join().unwrap();
+
+        // Nobody listens on the socket.
+        assert!(matches!(
+            Block::new_with_vhost_user(
+                "vhost-user".to_string(),
+                None,
+                CacheType::Unsafe,
+                "/tmp/fc-vhost-user-nonexistent.sock".to_string(),
+                false,
+                false,
+            ),
+            Err(Error::VhostUser(VhostError::VhostUserConnect(_)))
+        ));
+    }
 }
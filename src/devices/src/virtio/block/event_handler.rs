@@ -3,7 +3,7 @@
 use std::os::unix::io::AsRawFd;
 
 use event_manager::{EventOps, Events, MutEventSubscriber};
-use logger::{debug, error, warn};
+use logger::{debug, error, warn, IncMetric, METRICS};
 use utils::epoll::EventSet;
 
 use super::io::FileEngine;
@@ -12,12 +12,28 @@ use crate::virtio::VirtioDevice;
 
 impl Block {
     fn register_runtime_events(&self, ops: &mut EventOps) {
-        if let Err(e) = ops.add(Events::new(&self.queue_evts[0], EventSet::IN)) {
-            error!("Failed to register queue event: {}", e);
+        if self.vhost_user.is_some() {
+            // The queue events are serviced by the vhost-user backend, which notifies us
+            // when the queue has to be signalled.
+            if let Err(e) = ops.add(Events::new(&self.vhost_call_evt, EventSet::IN)) {
+                error!("Failed to register vhost call event: {}", e);
+            }
+            return;
+        }
+        for queue_evt in self.queue_evts.iter() {
+            if let Err(e) = ops.add(Events::new(queue_evt, EventSet::IN)) {
+                error!("Failed to register queue event: {}", e);
+            }
         }
         if let Err(e) = ops.add(Events::new(&self.rate_limiter, EventSet::IN)) {
             error!("Failed to register ratelimiter event: {}", e);
         }
+        if let Err(e) = ops.add(Events::new(&self.read_rate_limiter, EventSet::IN)) {
+            error!("Failed to register read ratelimiter event: {}", e);
+        }
+        if let Err(e) = ops.add(Events::new(&self.write_rate_limiter, EventSet::IN)) {
+            error!("Failed to register write ratelimiter event: {}", e);
+        }
         if let FileEngine::Async(engine) = self.disk.file_engine() {
             if let Err(e) = ops.add(Events::new(engine.completion_evt(), EventSet::IN)) {
                 error!("Failed to register IO engine completion event: {}", e);
@@ -31,6 +47,42 @@ impl Block {
         }
     }
 
+    fn register_unplug_event(&self, ops: &mut EventOps) {
+        if let Err(e) = ops.add(Events::new(&self.unplug_evt, EventSet::IN)) {
+            error!("Failed to register unplug event: {}", e);
+        }
+    }
+
+    fn process_unplug_event(&self, ops: &mut EventOps) {
+        debug!("block: unplug event");
+        if let Err(e) = self.unplug_evt.read() {
+            error!("Failed to consume block unplug event: {:?}", e);
+        }
+        // Depending on how far the activation went, either the activation event or the
+        // runtime events are registered, so failing to remove the others is expected.
+        let _ = ops.remove(Events::new(&self.activate_evt, EventSet::IN));
+        for queue_evt in self.queue_evts.iter() {
+            let _ = ops.remove(Events::new(queue_evt, EventSet::IN));
+        }
+        let _ = ops.remove(Events::new(&self.rate_limiter, EventSet::IN));
+        let _ = ops.remove(Events::new(&self.read_rate_limiter, EventSet::IN));
+        let _ = ops.remove(Events::new(&self.write_rate_limiter, EventSet::IN));
+        if let FileEngine::Async(engine) = self.disk.file_engine() {
+            let _ = ops.remove(Events::new(engine.completion_evt(), EventSet::IN));
+        }
+        let _ = ops.remove(Events::new(&self.vhost_call_evt, EventSet::IN));
+        if let Err(e) = ops.remove(Events::new(&self.unplug_evt, EventSet::IN)) {
+            error!("Failed to un-register unplug event: {}", e);
+        }
+        // Stops the vhost-user backend. The device stays registered with the event manager,
+        // without any event left to process.
+        if let Some(vhost_user) = self.vhost_user.as_ref() {
+            if let Err(e) = vhost_user.disconnect() {
+                error!("Failed to disconnect from the vhost-user backend: {:?}", e);
+            }
+        }
+    }
+
     fn process_activate_event(&self, ops: &mut EventOps) {
         debug!("block: activate event");
         if let Err(e) = self.activate_evt.read() {
@@ -40,6 +92,10 @@ impl Block {
         if let Err(e) = ops.remove(Events::new(&self.activate_evt, EventSet::IN)) {
             error!("Failed to un-register activate event: {}", e);
         }
+        if let Err(e) = self.start_vhost_user() {
+            error!("Failed to start the vhost-user backend: {:?}", e);
+            METRICS.block.event_fails.inc();
+        }
     }
 }
 
@@ -60,20 +116,41 @@ impl MutEventSubscriber for Block {
             return;
         }
 
+        if source == self.unplug_evt.as_raw_fd() {
+            return self.process_unplug_event(ops);
+        }
+
         if self.is_activated() {
-            let queue_evt = self.queue_evts[0].as_raw_fd();
+            // Each queue has an event of its own.
+            if let Some(queue_index) = self
+                .queue_evts
+                .iter()
+                .position(|queue_evt| queue_evt.as_raw_fd() == source)
+            {
+                return self.process_queue_event(queue_index);
+            }
+
             let rate_limiter_evt = self.rate_limiter.as_raw_fd();
+            let read_rate_limiter_evt = self.read_rate_limiter.as_raw_fd();
+            let write_rate_limiter_evt = self.write_rate_limiter.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
+            let vhost_call_fd = self.vhost_call_evt.as_raw_fd();
             let maybe_completion_fd = match self.disk.file_engine() {
                 FileEngine::Async(engine) => Some(engine.completion_evt().as_raw_fd()),
-                FileEngine::Sync(_) => None,
+                FileEngine::Sync(_)
+                | FileEngine::Qcow2(_)
+                | FileEngine::Overlay(_)
+                | FileEngine::Nbd(_)
+                | FileEngine::VhostUser(_) => None,
             };
 
             // Looks better than C style if/else if/else.
             match source {
-                _ if queue_evt == source => self.process_queue_event(),
                 _ if rate_limiter_evt == source => self.process_rate_limiter_event(),
+                _ if read_rate_limiter_evt == source => self.process_read_rate_limiter_event(),
+                _ if write_rate_limiter_evt == source => self.process_write_rate_limiter_event(),
                 _ if activate_fd == source => self.process_activate_event(ops),
+                _ if vhost_call_fd == source => self.process_vhost_call_event(),
                 _ if maybe_completion_fd == Some(source) => self.process_async_completion_event(),
                 _ => warn!("Block: Spurious event received: {:?}", source),
             }
@@ -95,6 +172,7 @@ impl MutEventSubscriber for Block {
         } else {
             self.register_activate_event(ops);
         }
+        self.register_unplug_event(ops);
     }
 }
 
@@ -103,7 +181,7 @@ pub mod tests {
     use std::sync::{Arc, Mutex};
 
     use event_manager::{EventManager, SubscriberOps};
-    use virtio_gen::virtio_blk::{VIRTIO_BLK_S_OK, VIRTIO_BLK_T_OUT};
+    use virtio_gen::virtio_blk::{VIRTIO_BLK_S_OK, VIRTIO_BLK_T_OUT, VIRTIO_F_VERSION_1};
     use vm_memory::{Bytes, GuestAddress};
 
     use super::*;
@@ -111,8 +189,16 @@ pub mod tests {
     use crate::virtio::block::test_utils::{
         default_block, set_queue, simulate_async_completion_event,
     };
+    use crate::virtio::block::{CacheType, CONFIG_SPACE_SIZE};
     use crate::virtio::queue::tests::*;
     use crate::virtio::test_utils::{default_mem, initialize_virtqueue, VirtQueue};
+    use crate::virtio::vhost_user::tests::spawn_backend;
+    use crate::virtio::vhost_user::{
+        VHOST_USER_F_PROTOCOL_FEATURES, VHOST_USER_SET_FEATURES, VHOST_USER_SET_MEM_TABLE,
+        VHOST_USER_SET_VRING_ADDR, VHOST_USER_SET_VRING_BASE, VHOST_USER_SET_VRING_CALL,
+        VHOST_USER_SET_VRING_ENABLE, VHOST_USER_SET_VRING_KICK, VHOST_USER_SET_VRING_NUM,
+    };
+    use crate::virtio::IrqType;
 
     #[test]
     fn test_event_handler() {
@@ -166,4 +252,98 @@ pub mod tests {
         assert_eq!(vq.used.ring[0].get().len, 1);
         assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
     }
+
+    #[test]
+    fn test_unplug_event() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut block = default_block(FileEngineType::default());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        initialize_virtqueue(&vq);
+
+        let block = Arc::new(Mutex::new(block));
+        let _id = event_manager.add_subscriber(block.clone());
+        block.lock().unwrap().activate(mem.clone()).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        block.lock().unwrap().unplug().unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // Queue events aren't processed anymore.
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+            .unwrap();
+        block.lock().unwrap().queue_evts[0].write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+        assert_eq!(vq.used.idx.get(), 0);
+    }
+
+    #[test]
+    fn test_vhost_user_events() {
+        let path = "/tmp/fc-vhost-user-blk-events.sock";
+        let features = (1 << VHOST_USER_F_PROTOCOL_FEATURES) | (1 << VIRTIO_F_VERSION_1);
+        let backend = spawn_backend(path, features, vec![0u8; CONFIG_SPACE_SIZE]);
+
+        let mut event_manager = EventManager::new().unwrap();
+        let mut block = Block::new_with_vhost_user(
+            "vhost-user".to_string(),
+            None,
+            CacheType::Unsafe,
+            path.to_string(),
+            false,
+            false,
+        )
+        .unwrap();
+        let mem =
+            vm_memory::create_shared_guest_memory(&[(GuestAddress(0), 0x10000)], false).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+
+        let block = Arc::new(Mutex::new(block));
+        let _id = event_manager.add_subscriber(block.clone());
+        // The ring is handed over to the backend on activation.
+        block.lock().unwrap().activate(mem).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // The queue events are serviced by the backend.
+        block.lock().unwrap().queue_evts[0].write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // The notifications of the backend are relayed to the guest.
+        block.lock().unwrap().vhost_call_evt.write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        assert!(block
+            .lock()
+            .unwrap()
+            .irq_trigger
+            .has_pending_irq(IrqType::Vring));
+
+        // Unplugging the device disconnects it from the backend.
+        block.lock().unwrap().unplug().unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        let messages = backend.join().unwrap();
+        let requests: Vec<u32> = messages.iter().map(|message| message.request).collect();
+        assert_eq!(
+            requests[5..],
+            [
+                VHOST_USER_SET_FEATURES,
+                VHOST_USER_SET_MEM_TABLE,
+                VHOST_USER_SET_VRING_NUM,
+                VHOST_USER_SET_VRING_BASE,
+                VHOST_USER_SET_VRING_ADDR,
+                VHOST_USER_SET_VRING_KICK,
+                VHOST_USER_SET_VRING_CALL,
+                VHOST_USER_SET_VRING_ENABLE,
+            ]
+        );
+    }
 }
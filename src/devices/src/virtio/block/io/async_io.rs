@@ -76,6 +76,7 @@ impl<T> AsyncFileEngine<T> {
                 Restriction::AllowOpCode(OpCode::Read),
                 Restriction::AllowOpCode(OpCode::Write),
                 Restriction::AllowOpCode(OpCode::Fsync),
+                Restriction::AllowOpCode(OpCode::Fallocate),
             ],
             Some(completion_evt.as_raw_fd()),
         )
@@ -89,7 +90,6 @@ impl<T> AsyncFileEngine<T> {
         })
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         &self.file
     }
@@ -185,6 +185,32 @@ impl<T> AsyncFileEngine<T> {
         })
     }
 
+    pub fn push_fallocate(
+        &mut self,
+        mode: u32,
+        offset: u64,
+        len: u64,
+        user_data: T,
+    ) -> Result<(), UserDataError<T, Error>> {
+        let wrapped_user_data = WrappedUserData::new(user_data);
+
+        // Safe because we trust that the host kernel will pass us back a completed entry with this
+        // same `user_data`, so that the value will not be leaked.
+        unsafe {
+            self.ring.push(Operation::fallocate(
+                0,
+                mode,
+                offset,
+                len,
+                wrapped_user_data,
+            ))
+        }
+        .map_err(|err_tuple| UserDataError {
+            user_data: err_tuple.1.user_data,
+            error: Error::IoUring(err_tuple.0),
+        })
+    }
+
     pub fn kick_submission_queue(&mut self) -> Result<(), Error> {
         self.ring.submit().map(|_| ()).map_err(Error::IoUring)
     }
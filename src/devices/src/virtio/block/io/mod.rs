@@ -2,6 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod async_io;
+pub mod nbd;
+pub mod overlay;
+pub mod qcow2;
 pub mod sync_io;
 
 use std::fs::File;
@@ -9,6 +12,9 @@ use std::fs::File;
 use vm_memory::{GuestAddress, GuestMemoryMmap};
 
 pub use self::async_io::AsyncFileEngine;
+pub use self::nbd::NbdFileEngine;
+pub use self::overlay::OverlayFileEngine;
+pub use self::qcow2::Qcow2FileEngine;
 pub use self::sync_io::SyncFileEngine;
 use crate::virtio::block::device::FileEngineType;
 
@@ -28,6 +34,11 @@ pub enum FileEngineOk<T> {
 pub enum Error {
     Sync(sync_io::Error),
     Async(async_io::Error),
+    Qcow2(qcow2::Error),
+    Overlay(overlay::Error),
+    Nbd(nbd::Error),
+    // The operation isn't supported by drives served by a vhost-user backend.
+    VhostUser(&'static str),
     UnsupportedEngine(FileEngineType),
     GetKernelVersion(utils::kernel_version::Error),
 }
@@ -51,6 +62,12 @@ pub enum FileEngine<T> {
     #[allow(unused)]
     Async(AsyncFileEngine<T>),
     Sync(SyncFileEngine),
+    Qcow2(Qcow2FileEngine),
+    Overlay(OverlayFileEngine),
+    Nbd(NbdFileEngine),
+    // Placeholder for drives whose requests are served by a vhost-user backend, without going
+    // through the device model. Holds the socket connected to the backend.
+    VhostUser(File),
 }
 
 impl<T> FileEngine<T> {
@@ -69,11 +86,14 @@ impl<T> FileEngine<T> {
         }
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         match self {
             FileEngine::Async(engine) => engine.file(),
             FileEngine::Sync(engine) => engine.file(),
+            FileEngine::Qcow2(engine) => engine.file(),
+            FileEngine::Overlay(engine) => engine.file(),
+            FileEngine::Nbd(engine) => engine.file(),
+            FileEngine::VhostUser(socket) => socket,
         }
     }
 
@@ -102,6 +122,31 @@ impl<T> FileEngine<T> {
                     error: Error::Sync(e),
                 }),
             },
+            FileEngine::Qcow2(engine) => match engine.read(offset, mem, addr, count) {
+                Ok(count) => Ok(FileEngineOk::Executed(UserDataOk { user_data, count })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Qcow2(e),
+                }),
+            },
+            FileEngine::Overlay(engine) => match engine.read(offset, mem, addr, count) {
+                Ok(count) => Ok(FileEngineOk::Executed(UserDataOk { user_data, count })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Overlay(e),
+                }),
+            },
+            FileEngine::Nbd(engine) => match engine.read(offset, mem, addr, count) {
+                Ok(count) => Ok(FileEngineOk::Executed(UserDataOk { user_data, count })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Nbd(e),
+                }),
+            },
+            FileEngine::VhostUser(_) => Err(UserDataError {
+                user_data,
+                error: Error::VhostUser("reads"),
+            }),
         }
     }
 
@@ -130,6 +175,31 @@ impl<T> FileEngine<T> {
                     error: Error::Sync(e),
                 }),
             },
+            FileEngine::Qcow2(engine) => match engine.write(offset, mem, addr, count) {
+                Ok(count) => Ok(FileEngineOk::Executed(UserDataOk { user_data, count })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Qcow2(e),
+                }),
+            },
+            FileEngine::Overlay(engine) => match engine.write(offset, mem, addr, count) {
+                Ok(count) => Ok(FileEngineOk::Executed(UserDataOk { user_data, count })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Overlay(e),
+                }),
+            },
+            FileEngine::Nbd(engine) => match engine.write(offset, mem, addr, count) {
+                Ok(count) => Ok(FileEngineOk::Executed(UserDataOk { user_data, count })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Nbd(e),
+                }),
+            },
+            FileEngine::VhostUser(_) => Err(UserDataError {
+                user_data,
+                error: Error::VhostUser("writes"),
+            }),
         }
     }
 
@@ -152,13 +222,116 @@ impl<T> FileEngine<T> {
                     error: Error::Sync(e),
                 }),
             },
+            FileEngine::Qcow2(engine) => match engine.flush() {
+                Ok(_) => Ok(FileEngineOk::Executed(UserDataOk {
+                    user_data,
+                    count: 0,
+                })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Qcow2(e),
+                }),
+            },
+            FileEngine::Overlay(engine) => match engine.flush() {
+                Ok(_) => Ok(FileEngineOk::Executed(UserDataOk {
+                    user_data,
+                    count: 0,
+                })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Overlay(e),
+                }),
+            },
+            FileEngine::Nbd(engine) => match engine.flush() {
+                Ok(_) => Ok(FileEngineOk::Executed(UserDataOk {
+                    user_data,
+                    count: 0,
+                })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Nbd(e),
+                }),
+            },
+            FileEngine::VhostUser(_) => Err(UserDataError {
+                user_data,
+                error: Error::VhostUser("flushes"),
+            }),
+        }
+    }
+
+    pub fn fallocate(
+        &mut self,
+        mode: u32,
+        offset: u64,
+        len: u64,
+        user_data: T,
+    ) -> Result<FileEngineOk<T>, UserDataError<T, Error>> {
+        match self {
+            FileEngine::Async(engine) => {
+                match engine.push_fallocate(mode, offset, len, user_data) {
+                    Ok(_) => Ok(FileEngineOk::Submitted),
+                    Err(e) => Err(UserDataError {
+                        user_data: e.user_data,
+                        error: Error::Async(e.error),
+                    }),
+                }
+            }
+            FileEngine::Sync(engine) => match engine.fallocate(mode, offset, len) {
+                Ok(_) => Ok(FileEngineOk::Executed(UserDataOk {
+                    user_data,
+                    count: 0,
+                })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Sync(e),
+                }),
+            },
+            // The clusters of qcow2 images are never freed.
+            FileEngine::Qcow2(_engine) => Err(UserDataError {
+                user_data,
+                error: Error::Qcow2(qcow2::Error::UnsupportedFeature("fallocate")),
+            }),
+            // Holes punched in the overlay would read as zeroes only until it's reopened.
+            FileEngine::Overlay(_engine) => Err(UserDataError {
+                user_data,
+                error: Error::Overlay(overlay::Error::UnsupportedFeature("fallocate")),
+            }),
+            FileEngine::Nbd(engine) => match engine.write_zeroes(mode, offset, len) {
+                Ok(_) => Ok(FileEngineOk::Executed(UserDataOk {
+                    user_data,
+                    count: 0,
+                })),
+                Err(e) => Err(UserDataError {
+                    user_data,
+                    error: Error::Nbd(e),
+                }),
+            },
+            FileEngine::VhostUser(_) => Err(UserDataError {
+                user_data,
+                error: Error::VhostUser("fallocate"),
+            }),
+        }
+    }
+
+    // Whether the engine can discard sectors and write zeroes to them.
+    pub fn supports_write_zeroes(&self) -> bool {
+        match self {
+            FileEngine::Async(_) | FileEngine::Sync(_) => true,
+            FileEngine::Nbd(engine) => engine.supports_write_zeroes(),
+            // The clusters of qcow2 images can't be freed or zeroed, and the holes punched in an
+            // overlay would expose the base image.
+            FileEngine::Qcow2(_) | FileEngine::Overlay(_) | FileEngine::VhostUser(_) => false,
         }
     }
 
     pub fn drain(&mut self, discard: bool) -> Result<(), Error> {
         match self {
             FileEngine::Async(engine) => engine.drain(discard).map_err(Error::Async),
-            FileEngine::Sync(_engine) => Ok(()),
+            FileEngine::Sync(_)
+            | FileEngine::Qcow2(_)
+            | FileEngine::Overlay(_)
+            | FileEngine::Nbd(_)
+            | FileEngine::VhostUser(_) => Ok(()),
         }
     }
 
@@ -166,6 +339,11 @@ impl<T> FileEngine<T> {
         match self {
             FileEngine::Async(engine) => engine.drain_and_flush(discard).map_err(Error::Async),
             FileEngine::Sync(engine) => engine.flush().map_err(Error::Sync),
+            FileEngine::Qcow2(engine) => engine.flush().map_err(Error::Qcow2),
+            FileEngine::Overlay(engine) => engine.flush().map_err(Error::Overlay),
+            FileEngine::Nbd(engine) => engine.flush().map_err(Error::Nbd),
+            // The backend flushes the disk on its own.
+            FileEngine::VhostUser(_) => Ok(()),
         }
     }
 }
@@ -185,6 +363,7 @@ pub mod tests {
     use crate::virtio::block::request::PendingRequest;
 
     const FILE_LEN: u32 = 1024;
+    const PUNCH_HOLE_MODE: u32 = (libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE) as u32;
     // 2 pages of memory should be enough to test read/write ops and also dirty tracking.
     const MEM_LEN: usize = 8192;
 
@@ -275,6 +454,8 @@ pub mod tests {
         assert_err!(res, Error::Sync(sync_io::Error::Seek(_e)));
         let res = engine.flush(());
         assert_err!(res, Error::Sync(sync_io::Error::SyncAll(_e)));
+        let res = engine.fallocate(PUNCH_HOLE_MODE, 0, 1, ());
+        assert_err!(res, Error::Sync(sync_io::Error::Fallocate(_e)));
 
         // Create backing file.
         let file = TempFile::new().unwrap().into_file();
@@ -337,6 +518,17 @@ pub mod tests {
         mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
         assert_eq!(buf, data.as_slice());
 
+        // Punch a hole, which reads back as zeroes
+        assert_sync_execution!(engine.fallocate(PUNCH_HOLE_MODE, 0, 100, ()), 0);
+        let mem = create_mem();
+        assert_sync_execution!(
+            engine.read(0, &mem, GuestAddress(0), FILE_LEN, ()),
+            FILE_LEN
+        );
+        mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
+        assert_eq!(buf[..100], [0u8; 100]);
+        assert_eq!(buf[100..], data[100..]);
+
         // Check other ops
         assert!(engine.flush(()).is_ok());
         assert!(engine.drain(true).is_ok());
@@ -399,6 +591,16 @@ pub mod tests {
         check_dirty_mem(&mem, addr, FILE_LEN);
         check_clean_mem(&mem, GuestAddress(4096), 4096);
 
+        // Punch a hole, which reads back as zeroes
+        assert_queued!(engine.fallocate(PUNCH_HOLE_MODE, 0, 100, ()));
+        assert_async_execution(&mem, &mut engine, 0);
+        let mem = create_mem();
+        assert_queued!(engine.read(0, &mem, addr, FILE_LEN, ()));
+        assert_async_execution(&mem, &mut engine, FILE_LEN as u32);
+        mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
+        assert_eq!(buf[..100], [0u8; 100]);
+        assert_eq!(buf[100..], data[100..]);
+
         // Check other ops
         assert_queued!(engine.flush(()));
         assert_async_execution(&mem, &mut engine, 0);
@@ -0,0 +1,662 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A synchronous client of the network block device (NBD) protocol, for drives whose image is
+//! exported by an NBD server, over TCP or a unix domain socket. The export is negotiated with the
+//! fixed newstyle handshake, then the requests are sent one at a time, each one waiting for its
+//! simple reply.
+//!
+//! The exports are designated by URIs of the form `nbd://host[:port]/export`, or
+//! `nbd+unix:///export?socket=path` for servers listening on a unix domain socket.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream;
+use std::result::Result;
+
+use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap};
+
+const TCP_SCHEME: &str = "nbd://";
+const UNIX_SCHEME: &str = "nbd+unix://";
+const DEFAULT_PORT: u16 = 10809;
+
+// Handshake.
+const NBD_MAGIC: u64 = 0x4e42_444d_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054;
+const NBD_OPT_REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const NBD_FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const NBD_FLAG_NO_ZEROES: u16 = 1 << 1;
+const NBD_OPT_EXPORT_NAME: u32 = 1;
+const NBD_OPT_GO: u32 = 7;
+const NBD_REP_ACK: u32 = 1;
+const NBD_REP_INFO: u32 = 3;
+const NBD_REP_FLAG_ERROR: u32 = 1 << 31;
+const NBD_REP_ERR_UNSUP: u32 = NBD_REP_FLAG_ERROR | 1;
+const NBD_INFO_EXPORT: u16 = 0;
+// The replies to the options we send are small, anything larger is bogus.
+const MAX_OPTION_REPLY_LEN: u32 = 4096;
+
+// Transmission.
+const NBD_FLAG_READ_ONLY: u16 = 1 << 1;
+const NBD_FLAG_SEND_FLUSH: u16 = 1 << 2;
+const NBD_FLAG_SEND_FUA: u16 = 1 << 3;
+const NBD_FLAG_SEND_WRITE_ZEROES: u16 = 1 << 6;
+const NBD_REQUEST_MAGIC: u32 = 0x2560_9513;
+const NBD_SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+const NBD_CMD_READ: u16 = 0;
+const NBD_CMD_WRITE: u16 = 1;
+const NBD_CMD_DISC: u16 = 2;
+const NBD_CMD_FLUSH: u16 = 3;
+const NBD_CMD_WRITE_ZEROES: u16 = 6;
+const NBD_CMD_FLAG_FUA: u16 = 1 << 0;
+const NBD_CMD_FLAG_NO_HOLE: u16 = 1 << 1;
+// Servers aren't required to accept larger requests, so the larger guest requests are split.
+const MAX_REQUEST_LEN: u32 = 32 << 20;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The server can't be reached.
+    Connect(io::Error),
+    /// The server rejected the export, with the given NBD error.
+    ExportRejected(u32),
+    /// The server doesn't follow the fixed newstyle handshake.
+    Handshake(&'static str),
+    /// The URI isn't of the form `nbd://host[:port]/export` or `nbd+unix:///export?socket=path`.
+    InvalidUri(String),
+    /// The reply of the server doesn't match the request.
+    InvalidReply,
+    /// The connection to the server failed.
+    Io(io::Error),
+    /// The drive is read-write, but the export is read-only.
+    ReadOnlyExport,
+    /// The server failed the request, with the given errno.
+    Request(u32),
+    Transfer(GuestMemoryError),
+    UnsupportedFeature(&'static str),
+}
+
+/// Whether `path` designates an NBD export rather than a host file.
+pub fn is_nbd_uri(path: &str) -> bool {
+    path.starts_with(TCP_SCHEME) || path.starts_with(UNIX_SCHEME)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NbdAddress {
+    /// The `host:port` the server listens on.
+    Tcp(String),
+    /// The path of the unix domain socket the server listens on.
+    Unix(String),
+}
+
+/// The location of an NBD export.
+#[derive(Debug, PartialEq)]
+pub struct NbdUri {
+    pub address: NbdAddress,
+    pub export: String,
+}
+
+impl NbdUri {
+    pub fn parse(uri: &str) -> Result<NbdUri, Error> {
+        let invalid = || Error::InvalidUri(uri.to_string());
+
+        // The authority is empty, and the socket is passed in the query.
+        if let Some(rest) = uri.strip_prefix(UNIX_SCHEME) {
+            let (export, query) = rest
+                .strip_prefix('/')
+                .and_then(|rest| rest.split_once('?'))
+                .ok_or_else(invalid)?;
+            let socket = query
+                .strip_prefix("socket=")
+                .filter(|socket| !socket.is_empty())
+                .ok_or_else(invalid)?;
+            return Ok(NbdUri {
+                address: NbdAddress::Unix(socket.to_string()),
+                export: export.to_string(),
+            });
+        }
+
+        let rest = uri.strip_prefix(TCP_SCHEME).ok_or_else(invalid)?;
+        let (authority, export) = rest.split_once('/').unwrap_or((rest, ""));
+        if authority.is_empty() {
+            return Err(invalid());
+        }
+        // IPv6 addresses are enclosed in brackets, e.g. `[::1]:10809`.
+        let has_port = authority
+            .rfind(':')
+            .map_or(false, |index| !authority[index..].contains(']'));
+        let address = if has_port {
+            authority.to_string()
+        } else {
+            format!("{}:{}", authority, DEFAULT_PORT)
+        };
+        Ok(NbdUri {
+            address: NbdAddress::Tcp(address),
+            export: export.to_string(),
+        })
+    }
+}
+
+pub struct NbdFileEngine {
+    // The connection to the server, handled as a file like the backing files of the other engines.
+    socket: File,
+    size: u64,
+    flags: u16,
+    // With the Writethrough cache type, the writes are only completed once the server
+    // persisted them.
+    writethrough: bool,
+    handle: u64,
+}
+
+unsafe impl Send for NbdFileEngine {}
+
+impl NbdFileEngine {
+    pub fn connect(
+        uri: &NbdUri,
+        is_read_only: bool,
+        writethrough: bool,
+    ) -> Result<NbdFileEngine, Error> {
+        let socket = match &uri.address {
+            NbdAddress::Tcp(address) => {
+                let stream = TcpStream::connect(address.as_str()).map_err(Error::Connect)?;
+                // The header and the data of the requests are sent separately, the latter
+                // mustn't wait for the former to be acknowledged.
+                stream.set_nodelay(true).map_err(Error::Connect)?;
+                // Safe because the stream gives up the ownership of the descriptor.
+                unsafe { File::from_raw_fd(stream.into_raw_fd()) }
+            }
+            NbdAddress::Unix(path) => {
+                let stream = UnixStream::connect(path).map_err(Error::Connect)?;
+                // Safe because the stream gives up the ownership of the descriptor.
+                unsafe { File::from_raw_fd(stream.into_raw_fd()) }
+            }
+        };
+        Self::from_socket(socket, &uri.export, is_read_only, writethrough)
+    }
+
+    fn from_socket(
+        socket: File,
+        export: &str,
+        is_read_only: bool,
+        writethrough: bool,
+    ) -> Result<NbdFileEngine, Error> {
+        let mut engine = NbdFileEngine {
+            socket,
+            size: 0,
+            flags: 0,
+            writethrough,
+            handle: 0,
+        };
+        engine.negotiate(export)?;
+        if !is_read_only && engine.flags & NBD_FLAG_READ_ONLY != 0 {
+            return Err(Error::ReadOnlyExport);
+        }
+        Ok(engine)
+    }
+
+    /// The connection to the server.
+    pub fn file(&self) -> &File {
+        &self.socket
+    }
+
+    /// The size of the export.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether the server can write zeroes, which is how both the discard and the write zeroes
+    /// requests are served.
+    pub fn supports_write_zeroes(&self) -> bool {
+        self.flags & NBD_FLAG_SEND_WRITE_ZEROES != 0
+    }
+
+    pub fn read(
+        &mut self,
+        offset: u64,
+        mem: &GuestMemoryMmap,
+        addr: GuestAddress,
+        count: u32,
+    ) -> Result<u32, Error> {
+        // The data follows the reply, and has to be received whatever happens, so the guest
+        // memory is checked beforehand.
+        if !mem.check_range(addr, count as usize) {
+            return Err(Error::Transfer(GuestMemoryError::InvalidGuestAddress(addr)));
+        }
+        let mut done = 0;
+        while done < count {
+            let len = cmp::min(count - done, MAX_REQUEST_LEN);
+            let handle = self.send_request(NBD_CMD_READ, 0, offset + u64::from(done), len)?;
+            self.read_reply(handle)?;
+            mem.read_exact_from(
+                addr.unchecked_add(u64::from(done)),
+                &mut self.socket,
+                len as usize,
+            )
+            .map_err(Error::Transfer)?;
+            done += len;
+        }
+        Ok(count)
+    }
+
+    pub fn write(
+        &mut self,
+        offset: u64,
+        mem: &GuestMemoryMmap,
+        addr: GuestAddress,
+        count: u32,
+    ) -> Result<u32, Error> {
+        // The data follows the request, and has to be sent whatever happens, so the guest memory
+        // is checked beforehand.
+        if !mem.check_range(addr, count as usize) {
+            return Err(Error::Transfer(GuestMemoryError::InvalidGuestAddress(addr)));
+        }
+        let fua = self.writethrough && self.flags & NBD_FLAG_SEND_FUA != 0;
+        let flags = if fua { NBD_CMD_FLAG_FUA } else { 0 };
+        let mut done = 0;
+        while done < count {
+            let len = cmp::min(count - done, MAX_REQUEST_LEN);
+            let handle = self.send_request(NBD_CMD_WRITE, flags, offset + u64::from(done), len)?;
+            mem.write_all_to(
+                addr.unchecked_add(u64::from(done)),
+                &mut self.socket,
+                len as usize,
+            )
+            .map_err(Error::Transfer)?;
+            self.read_reply(handle)?;
+            done += len;
+        }
+        // Without FUA, the writes are persisted by flushing the whole export.
+        if self.writethrough && !fua {
+            self.flush()?;
+        }
+        Ok(count)
+    }
+
+    pub fn write_zeroes(&mut self, mode: u32, offset: u64, len: u64) -> Result<(), Error> {
+        if !self.supports_write_zeroes() {
+            return Err(Error::UnsupportedFeature("write zeroes"));
+        }
+        // The range reads back as zeroes either way, punching holes only lets the server free
+        // the space it used.
+        let flags = if mode & libc::FALLOC_FL_PUNCH_HOLE as u32 != 0 {
+            0
+        } else {
+            NBD_CMD_FLAG_NO_HOLE
+        };
+        let mut done = 0;
+        while done < len {
+            let chunk = cmp::min(len - done, u64::from(MAX_REQUEST_LEN));
+            let handle =
+                self.send_request(NBD_CMD_WRITE_ZEROES, flags, offset + done, chunk as u32)?;
+            self.read_reply(handle)?;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        // The server persists the writes before completing them.
+        if self.flags & NBD_FLAG_SEND_FLUSH == 0 {
+            return Ok(());
+        }
+        let handle = self.send_request(NBD_CMD_FLUSH, 0, 0, 0)?;
+        self.read_reply(handle)
+    }
+
+    fn negotiate(&mut self, export: &str) -> Result<(), Error> {
+        if self.read_u64()? != NBD_MAGIC || self.read_u64()? != IHAVEOPT {
+            return Err(Error::Handshake("unsupported handshake"));
+        }
+        let server_flags = self.read_u16()?;
+        if server_flags & NBD_FLAG_FIXED_NEWSTYLE == 0 {
+            return Err(Error::Handshake("unsupported handshake"));
+        }
+        let client_flags = server_flags & (NBD_FLAG_FIXED_NEWSTYLE | NBD_FLAG_NO_ZEROES);
+        self.send(&u32::from(client_flags).to_be_bytes())?;
+
+        // No information is requested, the server sends the size and the flags of the export
+        // anyway.
+        let mut data = Vec::with_capacity(6 + export.len());
+        data.extend_from_slice(&(export.len() as u32).to_be_bytes());
+        data.extend_from_slice(export.as_bytes());
+        data.extend_from_slice(&0u16.to_be_bytes());
+        self.send_option(NBD_OPT_GO, &data)?;
+
+        let mut has_export_info = false;
+        loop {
+            let (reply_type, reply) = self.read_option_reply(NBD_OPT_GO)?;
+            match reply_type {
+                NBD_REP_ACK if has_export_info => return Ok(()),
+                NBD_REP_ACK => return Err(Error::Handshake("missing export information")),
+                NBD_REP_INFO
+                    if reply.len() >= 12 && reply[..2] == NBD_INFO_EXPORT.to_be_bytes() =>
+                {
+                    let mut size = [0u8; 8];
+                    size.copy_from_slice(&reply[2..10]);
+                    self.size = u64::from_be_bytes(size);
+                    self.flags = u16::from_be_bytes([reply[10], reply[11]]);
+                    has_export_info = true;
+                }
+                // Older servers only know the option which directly selects the export.
+                NBD_REP_ERR_UNSUP => return self.select_export(export, server_flags),
+                _ if reply_type & NBD_REP_FLAG_ERROR != 0 => {
+                    return Err(Error::ExportRejected(reply_type))
+                }
+                // The other information about the export isn't used.
+                _ => (),
+            }
+        }
+    }
+
+    // Selects the export without `NBD_OPT_GO`, in which case the server closes the connection
+    // instead of reporting errors.
+    fn select_export(&mut self, export: &str, server_flags: u16) -> Result<(), Error> {
+        self.send_option(NBD_OPT_EXPORT_NAME, export.as_bytes())?;
+        self.size = self.read_u64()?;
+        self.flags = self.read_u16()?;
+        if server_flags & NBD_FLAG_NO_ZEROES == 0 {
+            let mut zeroes = [0u8; 124];
+            self.socket.read_exact(&mut zeroes).map_err(Error::Io)?;
+        }
+        Ok(())
+    }
+
+    fn send_option(&mut self, option: u32, data: &[u8]) -> Result<(), Error> {
+        let mut buf = Vec::with_capacity(16 + data.len());
+        buf.extend_from_slice(&IHAVEOPT.to_be_bytes());
+        buf.extend_from_slice(&option.to_be_bytes());
+        buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        buf.extend_from_slice(data);
+        self.send(&buf)
+    }
+
+    fn read_option_reply(&mut self, option: u32) -> Result<(u32, Vec<u8>), Error> {
+        if self.read_u64()? != NBD_OPT_REPLY_MAGIC || self.read_u32()? != option {
+            return Err(Error::Handshake("invalid option reply"));
+        }
+        let reply_type = self.read_u32()?;
+        let len = self.read_u32()?;
+        if len > MAX_OPTION_REPLY_LEN {
+            return Err(Error::Handshake("invalid option reply"));
+        }
+        let mut data = vec![0u8; len as usize];
+        self.socket.read_exact(&mut data).map_err(Error::Io)?;
+        Ok((reply_type, data))
+    }
+
+    fn send_request(
+        &mut self,
+        command: u16,
+        flags: u16,
+        offset: u64,
+        len: u32,
+    ) -> Result<u64, Error> {
+        self.handle = self.handle.wrapping_add(1);
+        let mut buf = Vec::with_capacity(28);
+        buf.extend_from_slice(&NBD_REQUEST_MAGIC.to_be_bytes());
+        buf.extend_from_slice(&flags.to_be_bytes());
+        buf.extend_from_slice(&command.to_be_bytes());
+        buf.extend_from_slice(&self.handle.to_be_bytes());
+        buf.extend_from_slice(&offset.to_be_bytes());
+        buf.extend_from_slice(&len.to_be_bytes());
+        self.send(&buf)?;
+        Ok(self.handle)
+    }
+
+    // Waits for the reply to the request with the given handle. The data of the replies to
+    // reads is left to the caller.
+    fn read_reply(&mut self, handle: u64) -> Result<(), Error> {
+        if self.read_u32()? != NBD_SIMPLE_REPLY_MAGIC {
+            return Err(Error::InvalidReply);
+        }
+        let error = self.read_u32()?;
+        if self.read_u64()? != handle {
+            return Err(Error::InvalidReply);
+        }
+        if error != 0 {
+            return Err(Error::Request(error));
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.socket.write_all(buf).map_err(Error::Io)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        let mut buf = [0u8; 2];
+        self.socket.read_exact(&mut buf).map_err(Error::Io)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let mut buf = [0u8; 4];
+        self.socket.read_exact(&mut buf).map_err(Error::Io)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        let mut buf = [0u8; 8];
+        self.socket.read_exact(&mut buf).map_err(Error::Io)?;
+        Ok(u64::from_be_bytes(buf))
+    }
+}
+
+impl Drop for NbdFileEngine {
+    fn drop(&mut self) {
+        // Lets the server know that the connection is closed on purpose. The server doesn't
+        // reply, and the connection may be broken already.
+        let _ = self.send_request(NBD_CMD_DISC, 0, 0, 0);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    const EXPORT_SIZE: usize = 16 << 10;
+    const MEM_LEN: usize = 0x1000;
+
+    // Serves an export held in memory over `socket`, until the client disconnects. Returns the
+    // content of the export.
+    fn serve(mut socket: UnixStream, flags: u16, mut export: Vec<u8>) -> Vec<u8> {
+        let read_exact = |socket: &mut UnixStream, len: usize| {
+            let mut buf = vec![0u8; len];
+            socket.read_exact(&mut buf).unwrap();
+            buf
+        };
+        socket.write_all(&NBD_MAGIC.to_be_bytes()).unwrap();
+        socket.write_all(&IHAVEOPT.to_be_bytes()).unwrap();
+        socket
+            .write_all(&(NBD_FLAG_FIXED_NEWSTYLE | NBD_FLAG_NO_ZEROES).to_be_bytes())
+            .unwrap();
+        read_exact(&mut socket, 4);
+
+        // NBD_OPT_GO.
+        let header = read_exact(&mut socket, 16);
+        assert_eq!(header[12..16], [0, 0, 0, 12]);
+        assert_eq!(&read_exact(&mut socket, 12)[4..10], b"export");
+        let mut info = Vec::new();
+        info.extend_from_slice(&NBD_INFO_EXPORT.to_be_bytes());
+        info.extend_from_slice(&(export.len() as u64).to_be_bytes());
+        info.extend_from_slice(&flags.to_be_bytes());
+        for (reply_type, data) in [(NBD_REP_INFO, info), (NBD_REP_ACK, Vec::new())] {
+            socket
+                .write_all(&NBD_OPT_REPLY_MAGIC.to_be_bytes())
+                .unwrap();
+            socket.write_all(&NBD_OPT_GO.to_be_bytes()).unwrap();
+            socket.write_all(&reply_type.to_be_bytes()).unwrap();
+            socket
+                .write_all(&(data.len() as u32).to_be_bytes())
+                .unwrap();
+            socket.write_all(&data).unwrap();
+        }
+
+        loop {
+            let request = read_exact(&mut socket, 28);
+            let command = u16::from_be_bytes([request[6], request[7]]);
+            let mut offset = [0u8; 8];
+            offset.copy_from_slice(&request[16..24]);
+            let offset = u64::from_be_bytes(offset) as usize;
+            let len = u32::from_be_bytes([request[24], request[25], request[26], request[27]]);
+            let range = offset..offset + len as usize;
+            let mut data = Vec::new();
+            match command {
+                NBD_CMD_DISC => return export,
+                NBD_CMD_READ => data.extend_from_slice(&export[range]),
+                NBD_CMD_WRITE => {
+                    export[range].copy_from_slice(&read_exact(&mut socket, len as usize))
+                }
+                NBD_CMD_WRITE_ZEROES => export[range].iter_mut().for_each(|byte| *byte = 0),
+                NBD_CMD_FLUSH => (),
+                _ => panic!("Unexpected command {}", command),
+            }
+            socket
+                .write_all(&NBD_SIMPLE_REPLY_MAGIC.to_be_bytes())
+                .unwrap();
+            socket.write_all(&0u32.to_be_bytes()).unwrap();
+            socket.write_all(&request[8..16]).unwrap();
+            socket.write_all(&data).unwrap();
+        }
+    }
+
+    fn spawn_server(flags: u16, export: Vec<u8>) -> (File, JoinHandle<Vec<u8>>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || serve(server, flags, export));
+        // Safe because the stream gives up the ownership of the descriptor.
+        let socket = unsafe { File::from_raw_fd(client.into_raw_fd()) };
+        (socket, handle)
+    }
+
+    // Serves an export held in memory to the first client of the unix domain socket at `path`.
+    pub(crate) fn spawn_unix_server(path: &str, export: Vec<u8>) -> JoinHandle<Vec<u8>> {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            serve(
+                socket,
+                NBD_FLAG_SEND_FLUSH | NBD_FLAG_SEND_WRITE_ZEROES,
+                export,
+            )
+        })
+    }
+
+    #[test]
+    fn test_parse_uri() {
+        assert_eq!(
+            NbdUri::parse("nbd://storage.local/rootfs").unwrap(),
+            NbdUri {
+                address: NbdAddress::Tcp("storage.local:10809".to_string()),
+                export: "rootfs".to_string(),
+            }
+        );
+        assert_eq!(
+            NbdUri::parse("nbd://10.0.0.1:1234").unwrap(),
+            NbdUri {
+                address: NbdAddress::Tcp("10.0.0.1:1234".to_string()),
+                export: String::new(),
+            }
+        );
+        assert_eq!(
+            NbdUri::parse("nbd://[::1]/rootfs").unwrap().address,
+            NbdAddress::Tcp("[::1]:10809".to_string())
+        );
+        assert_eq!(
+            NbdUri::parse("nbd://[::1]:1234/rootfs").unwrap().address,
+            NbdAddress::Tcp("[::1]:1234".to_string())
+        );
+        assert_eq!(
+            NbdUri::parse("nbd+unix:///rootfs?socket=/run/nbd.sock").unwrap(),
+            NbdUri {
+                address: NbdAddress::Unix("/run/nbd.sock".to_string()),
+                export: "rootfs".to_string(),
+            }
+        );
+
+        for uri in [
+            "nbd:///rootfs",
+            "nbd+unix:///rootfs",
+            "nbd+unix:///rootfs?socket=",
+            "nbd+unix://host/rootfs?socket=/run/nbd.sock",
+            "/var/lib/rootfs.ext4",
+        ] {
+            assert!(matches!(NbdUri::parse(uri), Err(Error::InvalidUri(_))));
+        }
+
+        assert!(is_nbd_uri("nbd://storage.local/rootfs"));
+        assert!(is_nbd_uri("nbd+unix:///rootfs?socket=/run/nbd.sock"));
+        assert!(!is_nbd_uri("/var/lib/rootfs.ext4"));
+    }
+
+    #[test]
+    fn test_requests() {
+        let flags = NBD_FLAG_SEND_FLUSH | NBD_FLAG_SEND_WRITE_ZEROES;
+        let (socket, server) = spawn_server(flags, vec![0xff; EXPORT_SIZE]);
+        let mut engine = NbdFileEngine::from_socket(socket, "export", false, false).unwrap();
+        assert_eq!(engine.size(), EXPORT_SIZE as u64);
+        assert!(engine.supports_write_zeroes());
+
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_LEN)]).unwrap();
+        let data: Vec<u8> = (0..MEM_LEN).map(|i| i as u8).collect();
+        mem.write_slice(&data, GuestAddress(0)).unwrap();
+        assert_eq!(
+            engine
+                .write(0x1000, &mem, GuestAddress(0), MEM_LEN as u32)
+                .unwrap(),
+            MEM_LEN as u32
+        );
+        engine.write_zeroes(0, 0x1000, 0x100).unwrap();
+        engine.flush().unwrap();
+
+        // The data is read back.
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_LEN)]).unwrap();
+        assert_eq!(
+            engine
+                .read(0x1000, &mem, GuestAddress(0), MEM_LEN as u32)
+                .unwrap(),
+            MEM_LEN as u32
+        );
+        let mut buf = vec![0u8; MEM_LEN];
+        mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
+        assert_eq!(buf[..0x100], [0; 0x100]);
+        assert_eq!(buf[0x100..], data[0x100..]);
+
+        // The requests beyond the guest memory aren't sent.
+        assert!(matches!(
+            engine.read(0, &mem, GuestAddress(0x800), MEM_LEN as u32),
+            Err(Error::Transfer(_))
+        ));
+
+        drop(engine);
+        let export = server.join().unwrap();
+        assert_eq!(export[..0x1000], [0xff; 0x1000]);
+        assert_eq!(export[0x1000..0x1100], [0; 0x100]);
+        assert_eq!(export[0x1100..0x2000], data[0x100..]);
+    }
+
+    #[test]
+    fn test_read_only_export() {
+        let (socket, server) = spawn_server(NBD_FLAG_READ_ONLY, vec![0; EXPORT_SIZE]);
+        assert!(matches!(
+            NbdFileEngine::from_socket(socket, "export", false, false),
+            Err(Error::ReadOnlyExport)
+        ));
+        server.join().unwrap();
+
+        let (socket, server) = spawn_server(NBD_FLAG_READ_ONLY, vec![0; EXPORT_SIZE]);
+        let mut engine = NbdFileEngine::from_socket(socket, "export", true, false).unwrap();
+        assert!(!engine.supports_write_zeroes());
+        assert!(matches!(
+            engine.write_zeroes(0, 0, 0x100),
+            Err(Error::UnsupportedFeature(_))
+        ));
+        drop(engine);
+        server.join().unwrap();
+    }
+}
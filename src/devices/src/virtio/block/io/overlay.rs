@@ -0,0 +1,363 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A synchronous engine for drives made of a read-only base image, which may be shared by many
+//! microVMs, and a sparse overlay holding the writes of the guest. The disk is split in chunks,
+//! which are copied from the base image to the overlay the first time the guest writes to them,
+//! and read from the overlay from then on.
+//!
+//! The chunks already copied to an existing overlay are found through its data extents, so the
+//! overlay must be kept on a filesystem which supports `SEEK_DATA` and doesn't turn the chunks
+//! filled with zeroes into holes.
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::result::Result;
+
+use vm_memory::{Address, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+
+// The granularity of the copies to the overlay, a multiple of the block size of the usual host
+// filesystems.
+const CHUNK_SIZE: u64 = 64 << 10;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A chunk of the base image can't be copied to the overlay.
+    CopyChunk(io::Error),
+    /// The data extents of the overlay can't be looked up.
+    Extents(io::Error),
+    Flush(io::Error),
+    Metadata(io::Error),
+    /// The overlay is larger than the base image, so it wasn't created on top of it.
+    OverlayTooLarge(u64),
+    Seek(io::Error),
+    SyncAll(io::Error),
+    Transfer(GuestMemoryError),
+    UnsupportedFeature(&'static str),
+}
+
+pub struct OverlayFileEngine {
+    base: File,
+    overlay: File,
+    size: u64,
+    // One bit per chunk, set once the chunk has been copied to the overlay.
+    copied: Vec<u64>,
+}
+
+unsafe impl Send for OverlayFileEngine {}
+
+impl OverlayFileEngine {
+    pub fn from_files(base: File, overlay: File) -> Result<OverlayFileEngine, Error> {
+        let size = base.metadata().map_err(Error::Metadata)?.len();
+        let overlay_len = overlay.metadata().map_err(Error::Metadata)?.len();
+        if overlay_len > size {
+            return Err(Error::OverlayTooLarge(overlay_len));
+        }
+
+        let chunks = (size + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let mut engine = OverlayFileEngine {
+            base,
+            overlay,
+            size,
+            copied: vec![0; ((chunks + 63) / 64) as usize],
+        };
+        // A new overlay doesn't hold any chunk yet.
+        if overlay_len > 0 {
+            engine.find_copied_chunks(overlay_len)?;
+        }
+        Ok(engine)
+    }
+
+    /// The overlay, which is the file specific to the drive.
+    pub fn file(&self) -> &File {
+        &self.overlay
+    }
+
+    /// The size of the disk exposed to the guest, which is the one of the base image.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn read(
+        &mut self,
+        offset: u64,
+        mem: &GuestMemoryMmap,
+        addr: GuestAddress,
+        count: u32,
+    ) -> Result<u32, Error> {
+        let mut done = 0;
+        while done < count {
+            let disk_offset = offset + u64::from(done);
+            let len = Self::chunk_len(disk_offset, count - done);
+            let file = if self.is_copied(disk_offset / CHUNK_SIZE) {
+                &mut self.overlay
+            } else {
+                &mut self.base
+            };
+            file.seek(SeekFrom::Start(disk_offset))
+                .map_err(Error::Seek)?;
+            let res = mem.read_from(addr.unchecked_add(u64::from(done)), file, len as usize);
+            let transferred = match res {
+                Ok(count) => count as u32,
+                // Like for raw images, the transfer stops at the end of the guest memory.
+                Err(_) if done > 0 => break,
+                Err(err) => return Err(Error::Transfer(err)),
+            };
+            done += transferred;
+            if transferred < len {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    pub fn write(
+        &mut self,
+        offset: u64,
+        mem: &GuestMemoryMmap,
+        addr: GuestAddress,
+        count: u32,
+    ) -> Result<u32, Error> {
+        let mut done = 0;
+        while done < count {
+            let disk_offset = offset + u64::from(done);
+            let len = Self::chunk_len(disk_offset, count - done);
+            let chunk = disk_offset / CHUNK_SIZE;
+            if !self.is_copied(chunk) {
+                self.copy_chunk(chunk)?;
+            }
+            self.overlay
+                .seek(SeekFrom::Start(disk_offset))
+                .map_err(Error::Seek)?;
+            let res = mem.write_to(
+                addr.unchecked_add(u64::from(done)),
+                &mut self.overlay,
+                len as usize,
+            );
+            let transferred = match res {
+                Ok(count) => count as u32,
+                Err(_) if done > 0 => break,
+                Err(err) => return Err(Error::Transfer(err)),
+            };
+            done += transferred;
+            if transferred < len {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        // flush() first to force any cached data out of rust buffers.
+        self.overlay.flush().map_err(Error::Flush)?;
+        // Sync data out to physical media on host.
+        self.overlay.sync_all().map_err(Error::SyncAll)
+    }
+
+    // The length of the part of a transfer of `count` bytes at `disk_offset` which fits in the
+    // chunk of `disk_offset`.
+    fn chunk_len(disk_offset: u64, count: u32) -> u32 {
+        let chunk_left = CHUNK_SIZE - disk_offset % CHUNK_SIZE;
+        chunk_left.min(u64::from(count)) as u32
+    }
+
+    fn is_copied(&self, chunk: u64) -> bool {
+        self.copied[(chunk / 64) as usize] & (1 << (chunk % 64)) != 0
+    }
+
+    fn set_copied(&mut self, chunk: u64) {
+        self.copied[(chunk / 64) as usize] |= 1 << (chunk % 64);
+    }
+
+    fn copy_chunk(&mut self, chunk: u64) -> Result<(), Error> {
+        let start = chunk * CHUNK_SIZE;
+        let mut buf = vec![0u8; CHUNK_SIZE.min(self.size - start) as usize];
+        self.base
+            .read_exact_at(&mut buf, start)
+            .map_err(Error::CopyChunk)?;
+        self.overlay
+            .write_all_at(&buf, start)
+            .map_err(Error::CopyChunk)?;
+        self.set_copied(chunk);
+        Ok(())
+    }
+
+    // Marks the chunks overlapping the data extents of the first `len` bytes of the overlay as
+    // copied. Chunks are always copied whole, so any data in a chunk means it was copied.
+    fn find_copied_chunks(&mut self, len: u64) -> Result<(), Error> {
+        let fd = self.overlay.as_raw_fd();
+        let mut offset = 0;
+        while offset < len {
+            // Safe because the file descriptor is valid and the return value is checked.
+            let data = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+            if data < 0 {
+                let err = io::Error::last_os_error();
+                // There is no data past `offset`.
+                if err.raw_os_error() == Some(libc::ENXIO) {
+                    break;
+                }
+                return Err(Error::Extents(err));
+            }
+            // Safe because the file descriptor is valid and the return value is checked.
+            let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+            if hole < 0 {
+                return Err(Error::Extents(io::Error::last_os_error()));
+            }
+            let (data, hole) = (data as u64, hole as u64);
+            for chunk in data / CHUNK_SIZE..(hole + CHUNK_SIZE - 1) / CHUNK_SIZE {
+                self.set_copied(chunk);
+            }
+            offset = hole;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use utils::tempfile::TempFile;
+    use vm_memory::test_utils::create_anon_guest_memory;
+
+    use super::*;
+
+    // Three chunks and a half.
+    pub(crate) const BASE_SIZE: u64 = 3 * CHUNK_SIZE + CHUNK_SIZE / 2;
+    const MEM_LEN: usize = 0x4000;
+
+    // Creates a base image whose bytes all hold the index of their chunk, plus one.
+    pub(crate) fn create_base() -> TempFile {
+        let base = TempFile::new().unwrap();
+        let mut offset = 0;
+        while offset < BASE_SIZE {
+            let len = CHUNK_SIZE.min(BASE_SIZE - offset) as usize;
+            let fill = (offset / CHUNK_SIZE + 1) as u8;
+            base.as_file()
+                .write_all_at(&vec![fill; len], offset)
+                .unwrap();
+            offset += CHUNK_SIZE;
+        }
+        base
+    }
+
+    fn open(base: &TempFile, overlay: &TempFile) -> OverlayFileEngine {
+        OverlayFileEngine::from_files(
+            base.as_file().try_clone().unwrap(),
+            overlay.as_file().try_clone().unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn create_mem() -> GuestMemoryMmap {
+        create_anon_guest_memory(&[(GuestAddress(0), MEM_LEN)], false).unwrap()
+    }
+
+    fn read(engine: &mut OverlayFileEngine, mem: &GuestMemoryMmap, offset: u64) -> Vec<u8> {
+        let mut buf = vec![0u8; 0x200];
+        assert_eq!(
+            engine
+                .read(offset, mem, GuestAddress(0x2000), 0x200)
+                .unwrap(),
+            0x200
+        );
+        mem.read_slice(&mut buf, GuestAddress(0x2000)).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_read_write() {
+        let base = create_base();
+        let overlay = TempFile::new().unwrap();
+        let mut engine = open(&base, &overlay);
+        assert_eq!(engine.size(), BASE_SIZE);
+        let mem = create_mem();
+
+        // Reads fall through to the base image.
+        assert_eq!(read(&mut engine, &mem, CHUNK_SIZE), vec![2u8; 0x200]);
+
+        // A write across two chunks copies both of them to the overlay.
+        let data = vec![0xaau8; 0x200];
+        mem.write_slice(&data, GuestAddress(0)).unwrap();
+        let offset = 2 * CHUNK_SIZE - 0x100;
+        assert_eq!(
+            engine.write(offset, &mem, GuestAddress(0), 0x200).unwrap(),
+            0x200
+        );
+        engine.flush().unwrap();
+        assert_eq!(overlay.as_file().metadata().unwrap().len(), 3 * CHUNK_SIZE);
+        assert!(!engine.is_copied(0));
+        assert!(engine.is_copied(1));
+        assert!(engine.is_copied(2));
+        // The base image is left untouched.
+        let mut buf = vec![0u8; 0x200];
+        base.as_file().read_exact_at(&mut buf, offset).unwrap();
+        assert_eq!(buf[..0x100], [2u8; 0x100]);
+        assert_eq!(buf[0x100..], [3u8; 0x100]);
+
+        // The writes and the rest of the copied chunks read back, with or without the chunks
+        // being found again.
+        for engine in &mut [engine, open(&base, &overlay)] {
+            assert_eq!(read(engine, &mem, offset), data);
+            let buf = read(engine, &mem, offset - 0x100);
+            assert_eq!(buf[..0x100], [2u8; 0x100]);
+            assert_eq!(buf[0x100..], [0xaau8; 0x100]);
+            assert_eq!(read(engine, &mem, 0), vec![1u8; 0x200]);
+            assert_eq!(read(engine, &mem, 3 * CHUNK_SIZE), vec![4u8; 0x200]);
+        }
+
+        // The last chunk is shorter than the others.
+        let mut engine = open(&base, &overlay);
+        assert_eq!(
+            engine
+                .write(BASE_SIZE - 0x200, &mem, GuestAddress(0), 0x200)
+                .unwrap(),
+            0x200
+        );
+        assert_eq!(overlay.as_file().metadata().unwrap().len(), BASE_SIZE);
+        assert_eq!(read(&mut engine, &mem, BASE_SIZE - 0x200), data);
+        assert_eq!(read(&mut engine, &mem, 3 * CHUNK_SIZE), vec![4u8; 0x200]);
+
+        // Transfers stop at the end of the guest memory.
+        let addr = GuestAddress(MEM_LEN as u64 - 100);
+        assert_eq!(engine.read(0, &mem, addr, 200).unwrap(), 100);
+        assert_eq!(engine.write(0, &mem, addr, 200).unwrap(), 100);
+        assert!(engine.is_copied(0));
+    }
+
+    #[test]
+    fn test_zero_chunks() {
+        let base = create_base();
+        let overlay = TempFile::new().unwrap();
+        let mut engine = open(&base, &overlay);
+        let mem = create_mem();
+
+        // A chunk overwritten with zeroes keeps reading as zeroes once the overlay is reopened.
+        let zeroes = vec![0u8; 0x200];
+        mem.write_slice(&zeroes, GuestAddress(0)).unwrap();
+        for chunk_offset in (0..CHUNK_SIZE).step_by(0x200) {
+            engine
+                .write(chunk_offset, &mem, GuestAddress(0), 0x200)
+                .unwrap();
+        }
+        let mut engine = open(&base, &overlay);
+        assert!(engine.is_copied(0));
+        assert!(!engine.is_copied(1));
+        assert_eq!(read(&mut engine, &mem, 0), zeroes);
+        assert_eq!(read(&mut engine, &mem, CHUNK_SIZE), vec![2u8; 0x200]);
+    }
+
+    #[test]
+    fn test_invalid_overlay() {
+        let base = create_base();
+        let overlay = TempFile::new().unwrap();
+        overlay.as_file().set_len(BASE_SIZE + 1).unwrap();
+        assert!(matches!(
+            OverlayFileEngine::from_files(
+                base.as_file().try_clone().unwrap(),
+                overlay.as_file().try_clone().unwrap(),
+            ),
+            Err(Error::OverlayTooLarge(len)) if len == BASE_SIZE + 1
+        ));
+    }
+}
@@ -0,0 +1,908 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A synchronous engine for drives backed by qcow2 images. The guest offsets are mapped to the
+//! clusters of the image through its L1 and L2 tables, and the clusters the guest writes to for
+//! the first time are allocated at the end of the image, along with their refcounts.
+//!
+//! Images with a backing file, encryption, internal snapshots, compressed clusters or any
+//! incompatible feature aren't supported.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::result::Result;
+
+use vm_memory::{Address, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+
+const QCOW_MAGIC: u32 = 0x5146_49fb;
+// The size of the version 2 header, which version 3 headers extend.
+const V2_HEADER_SIZE: usize = 72;
+const V3_HEADER_SIZE: usize = 104;
+// Offset of the autoclear feature bits in version 3 headers.
+const AUTOCLEAR_FEATURES_OFFSET: u64 = 88;
+const MIN_CLUSTER_BITS: u32 = 9;
+const MAX_CLUSTER_BITS: u32 = 21;
+const MAX_REFCOUNT_ORDER: u32 = 6;
+// Version 2 images always use 16 bit refcounts.
+const V2_REFCOUNT_ORDER: u32 = 4;
+// Upper bounds on the size of the tables kept in memory, as enforced by QEMU.
+const MAX_L1_SIZE: u64 = 32 << 20;
+const MAX_REFCOUNT_TABLE_SIZE: u64 = 8 << 20;
+// The largest virtual size QEMU handles, the largest signed 64 bit length aligned to 1GiB.
+const MAX_VIRTUAL_SIZE: u64 = i64::MAX as u64 & !((1 << 30) - 1);
+// Bits 9 to 55 of the L1 and L2 entries hold the offsets of the clusters.
+const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+// Set in the L1 and L2 entries whose cluster has a refcount of exactly one.
+const COPIED_FLAG: u64 = 1 << 63;
+const COMPRESSED_FLAG: u64 = 1 << 62;
+// Set in the L2 entries of the clusters which read as zeroes, in version 3 images.
+const ZERO_FLAG: u64 = 1;
+// The memory used by the cached L2 tables, which holds 16 tables of the default 64KiB clusters.
+const L2_CACHE_BYTES: u64 = 1 << 20;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The image is corrupted.
+    Corrupted(&'static str),
+    Flush(io::Error),
+    /// The header of the image is invalid.
+    InvalidHeader(&'static str),
+    InvalidMagic,
+    /// The L1 table, an L2 table or the refcounts of the image can't be read.
+    ReadMetadata(io::Error),
+    /// There is no room left in the refcount table for the refcounts of new clusters.
+    RefcountTableFull,
+    Seek(io::Error),
+    SyncAll(io::Error),
+    Transfer(GuestMemoryError),
+    UnsupportedFeature(&'static str),
+    UnsupportedVersion(u32),
+    /// The L1 table, an L2 table or the refcounts of the image can't be written.
+    WriteMetadata(io::Error),
+}
+
+// The fields of the header which are used to access the image.
+#[derive(Debug, PartialEq)]
+struct Header {
+    version: u32,
+    cluster_bits: u32,
+    size: u64,
+    l1_size: u32,
+    l1_table_offset: u64,
+    refcount_table_offset: u64,
+    refcount_table_clusters: u32,
+    refcount_order: u32,
+    autoclear_features: u64,
+}
+
+fn be_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn be_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+impl Header {
+    fn read(file: &File) -> Result<Header, Error> {
+        let mut buf = [0u8; V3_HEADER_SIZE];
+        file.read_exact_at(&mut buf[..V2_HEADER_SIZE], 0)
+            .map_err(Error::ReadMetadata)?;
+
+        if be_u32(&buf, 0) != QCOW_MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let version = be_u32(&buf, 4);
+        if version != 2 && version != 3 {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        if be_u64(&buf, 8) != 0 {
+            return Err(Error::UnsupportedFeature("backing file"));
+        }
+        if be_u32(&buf, 32) != 0 {
+            return Err(Error::UnsupportedFeature("encryption"));
+        }
+        if be_u32(&buf, 60) != 0 {
+            return Err(Error::UnsupportedFeature("internal snapshots"));
+        }
+
+        let mut header = Header {
+            version,
+            cluster_bits: be_u32(&buf, 20),
+            size: be_u64(&buf, 24),
+            l1_size: be_u32(&buf, 36),
+            l1_table_offset: be_u64(&buf, 40),
+            refcount_table_offset: be_u64(&buf, 48),
+            refcount_table_clusters: be_u32(&buf, 56),
+            refcount_order: V2_REFCOUNT_ORDER,
+            autoclear_features: 0,
+        };
+
+        if version == 3 {
+            file.read_exact_at(&mut buf[V2_HEADER_SIZE..], V2_HEADER_SIZE as u64)
+                .map_err(Error::ReadMetadata)?;
+            if be_u64(&buf, 72) != 0 {
+                return Err(Error::UnsupportedFeature("incompatible features"));
+            }
+            header.autoclear_features = be_u64(&buf, 88);
+            header.refcount_order = be_u32(&buf, 96);
+            if (be_u32(&buf, 100) as usize) < V3_HEADER_SIZE {
+                return Err(Error::InvalidHeader("header length"));
+            }
+        }
+
+        header.validate()?;
+        Ok(header)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.cluster_bits < MIN_CLUSTER_BITS || self.cluster_bits > MAX_CLUSTER_BITS {
+            return Err(Error::InvalidHeader("cluster size"));
+        }
+        if self.refcount_order > MAX_REFCOUNT_ORDER {
+            return Err(Error::InvalidHeader("refcount order"));
+        }
+        let cluster_size = 1u64 << self.cluster_bits;
+        if self.l1_table_offset % cluster_size != 0
+            || self.refcount_table_offset % cluster_size != 0
+        {
+            return Err(Error::InvalidHeader("table offset"));
+        }
+        if u64::from(self.l1_size) * 8 > MAX_L1_SIZE {
+            return Err(Error::InvalidHeader("L1 table size"));
+        }
+        if u64::from(self.refcount_table_clusters) * cluster_size > MAX_REFCOUNT_TABLE_SIZE {
+            return Err(Error::InvalidHeader("refcount table size"));
+        }
+        if self.size > MAX_VIRTUAL_SIZE {
+            return Err(Error::InvalidHeader("virtual size"));
+        }
+        // Each L2 table maps as many clusters as it holds entries.
+        let l2_coverage = cluster_size * (cluster_size / 8);
+        let l1_entries = self
+            .size
+            .checked_add(l2_coverage - 1)
+            .ok_or(Error::InvalidHeader("virtual size"))?
+            / l2_coverage;
+        if l1_entries > u64::from(self.l1_size) {
+            return Err(Error::InvalidHeader("L1 table size"));
+        }
+        Ok(())
+    }
+}
+
+// Tables read from the image, indexed by their offset. The least recently used table is
+// evicted when the cache is full. The tables are written through, so they are never dirty.
+struct TableCache {
+    capacity: usize,
+    clock: u64,
+    tables: HashMap<u64, (Vec<u64>, u64)>,
+}
+
+impl TableCache {
+    fn new(capacity: usize) -> TableCache {
+        TableCache {
+            capacity,
+            clock: 0,
+            tables: HashMap::new(),
+        }
+    }
+
+    fn contains(&self, offset: u64) -> bool {
+        self.tables.contains_key(&offset)
+    }
+
+    fn get_mut(&mut self, offset: u64) -> Option<&mut Vec<u64>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.tables.get_mut(&offset).map(|(table, last_use)| {
+            *last_use = clock;
+            table
+        })
+    }
+
+    fn insert(&mut self, offset: u64, table: Vec<u64>) {
+        if self.tables.len() >= self.capacity {
+            let lru = self
+                .tables
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(offset, _)| *offset);
+            if let Some(lru) = lru {
+                self.tables.remove(&lru);
+            }
+        }
+        self.clock += 1;
+        self.tables.insert(offset, (table, self.clock));
+    }
+}
+
+pub struct Qcow2FileEngine {
+    file: File,
+    header: Header,
+    cluster_size: u64,
+    l1_table: Vec<u64>,
+    l2_cache: TableCache,
+    refcount_table: Vec<u64>,
+    // Clusters are allocated at the end of the image, so that they read as zeroes.
+    next_cluster_offset: u64,
+}
+
+unsafe impl Send for Qcow2FileEngine {}
+
+impl Qcow2FileEngine {
+    pub fn from_file(file: File, read_only: bool) -> Result<Qcow2FileEngine, Error> {
+        let header = Header::read(&file)?;
+        let cluster_size = 1u64 << header.cluster_bits;
+
+        let l1_table = Self::read_table(
+            &file,
+            header.l1_table_offset,
+            u64::from(header.l1_size) as usize,
+        )?;
+        let refcount_table = Self::read_table(
+            &file,
+            header.refcount_table_offset,
+            (u64::from(header.refcount_table_clusters) * cluster_size / 8) as usize,
+        )?;
+
+        // The autoclear features describe metadata, like dirty bitmaps, which isn't updated
+        // along with the data, so they are cleared as soon as the image may change.
+        if !read_only && header.autoclear_features != 0 {
+            file.write_all_at(&0u64.to_be_bytes(), AUTOCLEAR_FEATURES_OFFSET)
+                .map_err(Error::WriteMetadata)?;
+        }
+
+        let file_len = file.metadata().map_err(Error::ReadMetadata)?.len();
+        let next_cluster_offset = (file_len + cluster_size - 1) / cluster_size * cluster_size;
+
+        Ok(Qcow2FileEngine {
+            file,
+            l2_cache: TableCache::new(std::cmp::max(L2_CACHE_BYTES / cluster_size, 1) as usize),
+            cluster_size,
+            header,
+            l1_table,
+            refcount_table,
+            next_cluster_offset,
+        })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// The size of the disk exposed to the guest.
+    pub fn virtual_size(&self) -> u64 {
+        self.header.size
+    }
+
+    pub fn read(
+        &mut self,
+        offset: u64,
+        mem: &GuestMemoryMmap,
+        addr: GuestAddress,
+        count: u32,
+    ) -> Result<u32, Error> {
+        let mut done = 0;
+        while done < count {
+            let guest_offset = offset + u64::from(done);
+            let len = self.chunk_len(guest_offset, count - done);
+            let chunk_addr = addr.unchecked_add(u64::from(done));
+            let res = match self.cluster_offset(guest_offset)? {
+                Some(host_offset) => {
+                    self.file
+                        .seek(SeekFrom::Start(host_offset))
+                        .map_err(Error::Seek)?;
+                    mem.read_from(chunk_addr, &mut self.file, len as usize)
+                }
+                None => mem.read_from(
+                    chunk_addr,
+                    &mut io::repeat(0).take(u64::from(len)),
+                    len as usize,
+                ),
+            };
+            let transferred = match res {
+                Ok(count) => count as u32,
+                // Like for raw images, the transfer stops at the end of the guest memory.
+                Err(_) if done > 0 => break,
+                Err(err) => return Err(Error::Transfer(err)),
+            };
+            done += transferred;
+            if transferred < len {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    pub fn write(
+        &mut self,
+        offset: u64,
+        mem: &GuestMemoryMmap,
+        addr: GuestAddress,
+        count: u32,
+    ) -> Result<u32, Error> {
+        let mut done = 0;
+        while done < count {
+            let guest_offset = offset + u64::from(done);
+            let len = self.chunk_len(guest_offset, count - done);
+            let host_offset = self.allocated_cluster_offset(guest_offset)?;
+            self.file
+                .seek(SeekFrom::Start(host_offset))
+                .map_err(Error::Seek)?;
+            let res = mem.write_to(
+                addr.unchecked_add(u64::from(done)),
+                &mut self.file,
+                len as usize,
+            );
+            let transferred = match res {
+                Ok(count) => count as u32,
+                Err(_) if done > 0 => break,
+                Err(err) => return Err(Error::Transfer(err)),
+            };
+            done += transferred;
+            if transferred < len {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        // flush() first to force any cached data out of rust buffers.
+        self.file.flush().map_err(Error::Flush)?;
+        // Sync data out to physical media on host.
+        self.file.sync_all().map_err(Error::SyncAll)
+    }
+
+    // The length of the part of a transfer of `count` bytes at `guest_offset` which fits in
+    // the cluster of `guest_offset`.
+    fn chunk_len(&self, guest_offset: u64, count: u32) -> u32 {
+        let cluster_left = self.cluster_size - guest_offset % self.cluster_size;
+        cluster_left.min(u64::from(count)) as u32
+    }
+
+    fn l2_entries(&self) -> u64 {
+        self.cluster_size / 8
+    }
+
+    // The indexes of the L1 and L2 entries mapping `guest_offset`.
+    fn table_indexes(&self, guest_offset: u64) -> Result<(usize, usize), Error> {
+        let cluster = guest_offset >> self.header.cluster_bits;
+        let l1_index = (cluster / self.l2_entries()) as usize;
+        if l1_index >= self.l1_table.len() {
+            return Err(Error::Corrupted("offset beyond the L1 table"));
+        }
+        Ok((l1_index, (cluster % self.l2_entries()) as usize))
+    }
+
+    // Returns the offset in the image of the data at `guest_offset`, or None when it reads
+    // as zeroes.
+    fn cluster_offset(&mut self, guest_offset: u64) -> Result<Option<u64>, Error> {
+        let (l1_index, l2_index) = self.table_indexes(guest_offset)?;
+        let l2_offset = self.l1_table[l1_index] & OFFSET_MASK;
+        if l2_offset == 0 {
+            return Ok(None);
+        }
+        let entry = self.l2_table(l2_offset)?[l2_index];
+        if entry & COMPRESSED_FLAG != 0 {
+            return Err(Error::UnsupportedFeature("compressed clusters"));
+        }
+        let cluster_offset = entry & OFFSET_MASK;
+        if cluster_offset == 0 || (self.header.version == 3 && entry & ZERO_FLAG != 0) {
+            return Ok(None);
+        }
+        Ok(Some(cluster_offset + guest_offset % self.cluster_size))
+    }
+
+    // Returns the offset in the image of the data at `guest_offset`, allocating its cluster
+    // and the L2 table mapping it if needed.
+    fn allocated_cluster_offset(&mut self, guest_offset: u64) -> Result<u64, Error> {
+        let (l1_index, l2_index) = self.table_indexes(guest_offset)?;
+
+        let mut l2_offset = self.l1_table[l1_index] & OFFSET_MASK;
+        if l2_offset == 0 {
+            l2_offset = self.allocate_cluster()?;
+            self.l2_cache
+                .insert(l2_offset, vec![0; self.l2_entries() as usize]);
+            self.l1_table[l1_index] = l2_offset | COPIED_FLAG;
+            self.write_entry(
+                self.header.l1_table_offset + l1_index as u64 * 8,
+                self.l1_table[l1_index],
+            )?;
+        }
+
+        let entry = self.l2_table(l2_offset)?[l2_index];
+        if entry & COMPRESSED_FLAG != 0 {
+            return Err(Error::UnsupportedFeature("compressed clusters"));
+        }
+        let mut cluster_offset = entry & OFFSET_MASK;
+        let zeroed = self.header.version == 3 && entry & ZERO_FLAG != 0;
+        if cluster_offset == 0 || zeroed {
+            if cluster_offset == 0 {
+                cluster_offset = self.allocate_cluster()?;
+            } else {
+                // A zero cluster may keep its preallocated space, whose content is undefined.
+                self.file
+                    .write_all_at(&vec![0; self.cluster_size as usize], cluster_offset)
+                    .map_err(Error::WriteMetadata)?;
+            }
+            let entry = cluster_offset | COPIED_FLAG;
+            self.l2_table(l2_offset)?[l2_index] = entry;
+            self.write_entry(l2_offset + l2_index as u64 * 8, entry)?;
+        }
+
+        Ok(cluster_offset + guest_offset % self.cluster_size)
+    }
+
+    fn l2_table(&mut self, l2_offset: u64) -> Result<&mut Vec<u64>, Error> {
+        if !self.l2_cache.contains(l2_offset) {
+            let table = Self::read_table(&self.file, l2_offset, self.l2_entries() as usize)?;
+            self.l2_cache.insert(l2_offset, table);
+        }
+        Ok(self.l2_cache.get_mut(l2_offset).unwrap())
+    }
+
+    // Allocates a zeroed cluster at the end of the image and sets its refcount.
+    fn allocate_cluster(&mut self) -> Result<u64, Error> {
+        let offset = self.next_cluster_offset;
+        self.next_cluster_offset += self.cluster_size;
+        self.file
+            .set_len(self.next_cluster_offset)
+            .map_err(Error::WriteMetadata)?;
+        self.set_refcount(offset, 1)?;
+        Ok(offset)
+    }
+
+    fn set_refcount(&mut self, cluster_offset: u64, refcount: u64) -> Result<(), Error> {
+        let refcount_bits = 1u64 << self.header.refcount_order;
+        let block_entries = self.cluster_size * 8 / refcount_bits;
+        let cluster = cluster_offset >> self.header.cluster_bits;
+        let table_index = (cluster / block_entries) as usize;
+        let block_index = cluster % block_entries;
+
+        if table_index >= self.refcount_table.len() {
+            return Err(Error::RefcountTableFull);
+        }
+        if self.refcount_table[table_index] & OFFSET_MASK == 0 {
+            // The new refcount block may hold its own refcount, so it has to be reachable
+            // before it's counted.
+            let block_offset = self.next_cluster_offset;
+            self.next_cluster_offset += self.cluster_size;
+            self.file
+                .set_len(self.next_cluster_offset)
+                .map_err(Error::WriteMetadata)?;
+            self.refcount_table[table_index] = block_offset;
+            self.write_entry(
+                self.header.refcount_table_offset + table_index as u64 * 8,
+                block_offset,
+            )?;
+            self.set_refcount(block_offset, 1)?;
+        }
+        let block_offset = self.refcount_table[table_index] & OFFSET_MASK;
+
+        if refcount_bits >= 8 {
+            let bytes = (refcount_bits / 8) as usize;
+            let value = refcount.to_be_bytes();
+            self.file
+                .write_all_at(
+                    &value[8 - bytes..],
+                    block_offset + block_index * bytes as u64,
+                )
+                .map_err(Error::WriteMetadata)
+        } else {
+            // Smaller refcounts are packed in bytes, starting from the least significant bits.
+            let byte_offset = block_offset + block_index * refcount_bits / 8;
+            let shift = block_index * refcount_bits % 8;
+            let mask = ((1u8 << refcount_bits) - 1) << shift;
+            let mut byte = [0u8];
+            self.file
+                .read_exact_at(&mut byte, byte_offset)
+                .map_err(Error::ReadMetadata)?;
+            byte[0] = (byte[0] & !mask) | ((refcount as u8) << shift & mask);
+            self.file
+                .write_all_at(&byte, byte_offset)
+                .map_err(Error::WriteMetadata)
+        }
+    }
+
+    fn read_table(file: &File, offset: u64, entries: usize) -> Result<Vec<u64>, Error> {
+        let mut buf = vec![0u8; entries * 8];
+        file.read_exact_at(&mut buf, offset)
+            .map_err(Error::ReadMetadata)?;
+        Ok(buf
+            .chunks_exact(8)
+            .map(|entry| u64::from_be_bytes(entry.try_into().unwrap()))
+            .collect())
+    }
+
+    fn write_entry(&self, offset: u64, entry: u64) -> Result<(), Error> {
+        self.file
+            .write_all_at(&entry.to_be_bytes(), offset)
+            .map_err(Error::WriteMetadata)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use utils::tempfile::TempFile;
+    use vm_memory::test_utils::create_anon_guest_memory;
+
+    use super::*;
+
+    const CLUSTER_BITS: u32 = 12;
+    const CLUSTER_SIZE: u64 = 1 << CLUSTER_BITS;
+    // Each L2 table maps 512 clusters, so 8 L1 entries are needed.
+    pub(crate) const VIRTUAL_SIZE: u64 = 16 << 20;
+    const L1_SIZE: u32 = 8;
+    const REFCOUNT_TABLE_OFFSET: u64 = CLUSTER_SIZE;
+    const REFCOUNT_BLOCK_OFFSET: u64 = 2 * CLUSTER_SIZE;
+    const L1_TABLE_OFFSET: u64 = 3 * CLUSTER_SIZE;
+    const MEM_LEN: usize = 0x4000;
+
+    // Creates an empty version 3 image. The refcount table, its first refcount block and the
+    // L1 table follow the header, one cluster each.
+    pub(crate) fn create_image(refcount_order: u32) -> TempFile {
+        let image = TempFile::new().unwrap();
+        let file = image.as_file();
+        let mut header = vec![0u8; V3_HEADER_SIZE];
+        header[0..4].copy_from_slice(&QCOW_MAGIC.to_be_bytes());
+        header[4..8].copy_from_slice(&3u32.to_be_bytes());
+        header[20..24].copy_from_slice(&CLUSTER_BITS.to_be_bytes());
+        header[24..32].copy_from_slice(&VIRTUAL_SIZE.to_be_bytes());
+        header[36..40].copy_from_slice(&L1_SIZE.to_be_bytes());
+        header[40..48].copy_from_slice(&L1_TABLE_OFFSET.to_be_bytes());
+        header[48..56].copy_from_slice(&REFCOUNT_TABLE_OFFSET.to_be_bytes());
+        header[56..60].copy_from_slice(&1u32.to_be_bytes());
+        header[96..100].copy_from_slice(&refcount_order.to_be_bytes());
+        header[100..104].copy_from_slice(&(V3_HEADER_SIZE as u32).to_be_bytes());
+        file.write_all_at(&header, 0).unwrap();
+        file.write_all_at(&REFCOUNT_BLOCK_OFFSET.to_be_bytes(), REFCOUNT_TABLE_OFFSET)
+            .unwrap();
+        file.set_len(4 * CLUSTER_SIZE).unwrap();
+        for cluster in 0..4 {
+            write_refcount(file, REFCOUNT_BLOCK_OFFSET, cluster, refcount_order, 1);
+        }
+        image
+    }
+
+    fn write_refcount(file: &File, block_offset: u64, index: u64, order: u32, refcount: u64) {
+        let bits = 1u64 << order;
+        if bits >= 8 {
+            let bytes = (bits / 8) as usize;
+            file.write_all_at(
+                &refcount.to_be_bytes()[8 - bytes..],
+                block_offset + index * bytes as u64,
+            )
+            .unwrap();
+        } else {
+            let offset = block_offset + index * bits / 8;
+            let mut byte = [0u8];
+            file.read_exact_at(&mut byte, offset).unwrap();
+            byte[0] |= (refcount as u8) << (index * bits % 8);
+            file.write_all_at(&byte, offset).unwrap();
+        }
+    }
+
+    fn read_refcount(file: &File, block_offset: u64, index: u64, order: u32) -> u64 {
+        let bits = 1u64 << order;
+        if bits >= 8 {
+            let bytes = (bits / 8) as usize;
+            let mut buf = [0u8; 8];
+            file.read_exact_at(&mut buf[8 - bytes..], block_offset + index * bytes as u64)
+                .unwrap();
+            u64::from_be_bytes(buf)
+        } else {
+            let mut byte = [0u8];
+            file.read_exact_at(&mut byte, block_offset + index * bits / 8)
+                .unwrap();
+            u64::from((byte[0] >> (index * bits % 8)) & ((1 << bits) - 1) as u8)
+        }
+    }
+
+    fn read_entry(file: &File, offset: u64) -> u64 {
+        let mut buf = [0u8; 8];
+        file.read_exact_at(&mut buf, offset).unwrap();
+        u64::from_be_bytes(buf)
+    }
+
+    fn open(image: &TempFile) -> Qcow2FileEngine {
+        Qcow2FileEngine::from_file(image.as_file().try_clone().unwrap(), false).unwrap()
+    }
+
+    fn create_mem() -> GuestMemoryMmap {
+        create_anon_guest_memory(&[(GuestAddress(0), MEM_LEN)], false).unwrap()
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        fn check_open_err(edit: &[(u64, &[u8])]) -> Error {
+            let image = create_image(4);
+            for (offset, bytes) in edit {
+                image.as_file().write_all_at(bytes, *offset).unwrap();
+            }
+            Qcow2FileEngine::from_file(image.as_file().try_clone().unwrap(), true)
+                .err()
+                .unwrap()
+        }
+
+        assert!(matches!(
+            check_open_err(&[(0, &[0; 4])]),
+            Error::InvalidMagic
+        ));
+        assert!(matches!(
+            check_open_err(&[(4, &1u32.to_be_bytes())]),
+            Error::UnsupportedVersion(1)
+        ));
+        assert!(matches!(
+            check_open_err(&[(8, &0x1000u64.to_be_bytes())]),
+            Error::UnsupportedFeature("backing file")
+        ));
+        assert!(matches!(
+            check_open_err(&[(32, &1u32.to_be_bytes())]),
+            Error::UnsupportedFeature("encryption")
+        ));
+        assert!(matches!(
+            check_open_err(&[(60, &1u32.to_be_bytes())]),
+            Error::UnsupportedFeature("internal snapshots")
+        ));
+        assert!(matches!(
+            check_open_err(&[(72, &1u64.to_be_bytes())]),
+            Error::UnsupportedFeature("incompatible features")
+        ));
+        assert!(matches!(
+            check_open_err(&[(20, &8u32.to_be_bytes())]),
+            Error::InvalidHeader("cluster size")
+        ));
+        assert!(matches!(
+            check_open_err(&[(96, &7u32.to_be_bytes())]),
+            Error::InvalidHeader("refcount order")
+        ));
+        assert!(matches!(
+            check_open_err(&[(36, &7u32.to_be_bytes())]),
+            Error::InvalidHeader("L1 table size")
+        ));
+        assert!(matches!(
+            check_open_err(&[(24, &u64::MAX.to_be_bytes())]),
+            Error::InvalidHeader("virtual size")
+        ));
+        assert!(matches!(
+            check_open_err(&[(24, &(MAX_VIRTUAL_SIZE + 1).to_be_bytes())]),
+            Error::InvalidHeader("virtual size")
+        ));
+        // The L1 table of the image only maps a few clusters.
+        assert!(matches!(
+            check_open_err(&[(24, &MAX_VIRTUAL_SIZE.to_be_bytes())]),
+            Error::InvalidHeader("L1 table size")
+        ));
+        assert!(matches!(
+            check_open_err(&[(40, &0x1001u64.to_be_bytes())]),
+            Error::InvalidHeader("table offset")
+        ));
+        assert!(matches!(
+            check_open_err(&[(100, &72u32.to_be_bytes())]),
+            Error::InvalidHeader("header length")
+        ));
+        assert!(matches!(
+            Qcow2FileEngine::from_file(TempFile::new().unwrap().into_file(), true),
+            Err(Error::ReadMetadata(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_write() {
+        let image = create_image(4);
+        let mut engine = open(&image);
+        assert_eq!(engine.virtual_size(), VIRTUAL_SIZE);
+        let mem = create_mem();
+
+        // Unallocated clusters read as zeroes.
+        mem.write_slice(&[0xff; 0x2000], GuestAddress(0)).unwrap();
+        assert_eq!(
+            engine.read(0, &mem, GuestAddress(0), 0x2000).unwrap(),
+            0x2000
+        );
+        let mut buf = [0xffu8; 0x2000];
+        mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
+        assert!(buf.iter().all(|byte| *byte == 0));
+        assert_eq!(image.as_file().metadata().unwrap().len(), 4 * CLUSTER_SIZE);
+
+        // A write across two clusters allocates an L2 table, then the two data clusters.
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        mem.write_slice(&data, GuestAddress(0x1000)).unwrap();
+        let offset = CLUSTER_SIZE - 100;
+        assert_eq!(
+            engine
+                .write(offset, &mem, GuestAddress(0x1000), 200)
+                .unwrap(),
+            200
+        );
+        let file = image.as_file();
+        assert_eq!(file.metadata().unwrap().len(), 7 * CLUSTER_SIZE);
+        assert_eq!(
+            read_entry(file, L1_TABLE_OFFSET),
+            4 * CLUSTER_SIZE | COPIED_FLAG
+        );
+        assert_eq!(
+            read_entry(file, 4 * CLUSTER_SIZE),
+            5 * CLUSTER_SIZE | COPIED_FLAG
+        );
+        assert_eq!(
+            read_entry(file, 4 * CLUSTER_SIZE + 8),
+            6 * CLUSTER_SIZE | COPIED_FLAG
+        );
+        for cluster in 0..7 {
+            assert_eq!(read_refcount(file, REFCOUNT_BLOCK_OFFSET, cluster, 4), 1);
+        }
+        assert_eq!(read_refcount(file, REFCOUNT_BLOCK_OFFSET, 7, 4), 0);
+
+        // The data reads back, with or without the cached tables.
+        for engine in &mut [engine, open(&image)] {
+            let mut buf = vec![0u8; 300];
+            assert_eq!(
+                engine
+                    .read(offset - 50, &mem, GuestAddress(0x2000), 300)
+                    .unwrap(),
+                300
+            );
+            mem.read_slice(&mut buf, GuestAddress(0x2000)).unwrap();
+            assert!(buf[..50].iter().all(|byte| *byte == 0));
+            assert_eq!(buf[50..250], data[..]);
+            assert!(buf[250..].iter().all(|byte| *byte == 0));
+            engine.flush().unwrap();
+        }
+
+        // Transfers stop at the end of the guest memory.
+        let mut engine = open(&image);
+        let addr = GuestAddress(MEM_LEN as u64 - 100);
+        assert_eq!(engine.read(offset, &mem, addr, 200).unwrap(), 100);
+        assert_eq!(engine.write(offset, &mem, addr, 200).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_zero_and_compressed_clusters() {
+        let image = create_image(4);
+        let file = image.as_file();
+        let mem = create_mem();
+        mem.write_slice(&[0xaa; 16], GuestAddress(0)).unwrap();
+        open(&image).write(0, &mem, GuestAddress(0), 16).unwrap();
+
+        // A zero cluster which keeps its preallocated space reads as zeroes, whatever it holds.
+        let l2_entry_offset = 4 * CLUSTER_SIZE;
+        let cluster_offset = 5 * CLUSTER_SIZE;
+        file.write_all_at(
+            &(cluster_offset | COPIED_FLAG | ZERO_FLAG).to_be_bytes(),
+            l2_entry_offset,
+        )
+        .unwrap();
+        let mut engine = open(&image);
+        assert_eq!(engine.read(0, &mem, GuestAddress(0), 16).unwrap(), 16);
+        let mut buf = [0xffu8; 16];
+        mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
+        assert_eq!(buf, [0; 16]);
+
+        // Writing to it reuses its space, with the rest of the cluster zeroed.
+        mem.write_slice(&[0xbb; 4], GuestAddress(0)).unwrap();
+        assert_eq!(engine.write(4, &mem, GuestAddress(0), 4).unwrap(), 4);
+        assert_eq!(
+            read_entry(file, l2_entry_offset),
+            cluster_offset | COPIED_FLAG
+        );
+        assert_eq!(engine.read(0, &mem, GuestAddress(0), 16).unwrap(), 16);
+        mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
+        assert_eq!(
+            buf,
+            [0, 0, 0, 0, 0xbb, 0xbb, 0xbb, 0xbb, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(file.metadata().unwrap().len(), 6 * CLUSTER_SIZE);
+
+        // Compressed clusters can't be accessed.
+        file.write_all_at(
+            &(cluster_offset | COMPRESSED_FLAG).to_be_bytes(),
+            l2_entry_offset,
+        )
+        .unwrap();
+        let mut engine = open(&image);
+        assert!(matches!(
+            engine.read(0, &mem, GuestAddress(0), 16),
+            Err(Error::UnsupportedFeature("compressed clusters"))
+        ));
+        assert!(matches!(
+            engine.write(0, &mem, GuestAddress(0), 16),
+            Err(Error::UnsupportedFeature("compressed clusters"))
+        ));
+    }
+
+    #[test]
+    fn test_refcount_blocks() {
+        // With 64 bit refcounts, each refcount block counts 512 clusters, so writing to 520
+        // clusters needs a second refcount block, and a second L2 table.
+        let image = create_image(6);
+        let file = image.as_file();
+        let mut engine = open(&image);
+        let mem = create_mem();
+        for cluster in 0..520u64 {
+            mem.write_obj(cluster, GuestAddress(0)).unwrap();
+            engine
+                .write(cluster * CLUSTER_SIZE, &mem, GuestAddress(0), 8)
+                .unwrap();
+        }
+        let second_block = read_entry(file, REFCOUNT_TABLE_OFFSET + 8);
+        assert_ne!(second_block, 0);
+        let len = file.metadata().unwrap().len();
+        // The metadata clusters, 520 data clusters, 2 L2 tables and the new refcount block.
+        assert_eq!(len, (4 + 520 + 2 + 1) * CLUSTER_SIZE);
+        for cluster in 512..len / CLUSTER_SIZE {
+            assert_eq!(read_refcount(file, second_block, cluster - 512, 6), 1);
+        }
+
+        let mut engine = open(&image);
+        for cluster in (0..520u64).step_by(37) {
+            engine
+                .read(cluster * CLUSTER_SIZE, &mem, GuestAddress(0x100), 8)
+                .unwrap();
+            assert_eq!(mem.read_obj::<u64>(GuestAddress(0x100)).unwrap(), cluster);
+        }
+
+        // Refcounts smaller than a byte are packed.
+        let image = create_image(0);
+        let mut engine = open(&image);
+        engine.write(0, &mem, GuestAddress(0), 8).unwrap();
+        for cluster in 0..6 {
+            assert_eq!(
+                read_refcount(image.as_file(), REFCOUNT_BLOCK_OFFSET, cluster, 0),
+                1
+            );
+        }
+        assert_eq!(
+            read_refcount(image.as_file(), REFCOUNT_BLOCK_OFFSET, 6, 0),
+            0
+        );
+    }
+
+    #[test]
+    fn test_refcount_table_full() {
+        // The refcount table of the test images has 512 entries, each counting 512 clusters
+        // when the refcounts are 64 bit wide. The image is grown so that the next cluster
+        // is past them.
+        let image = create_image(6);
+        image.as_file().set_len(512 * 512 * CLUSTER_SIZE).unwrap();
+        let mut engine = open(&image);
+        let mem = create_mem();
+        assert!(matches!(
+            engine.write(0, &mem, GuestAddress(0), 8),
+            Err(Error::RefcountTableFull)
+        ));
+    }
+
+    #[test]
+    fn test_autoclear_features() {
+        let image = create_image(4);
+        let file = image.as_file();
+        file.write_all_at(&1u64.to_be_bytes(), AUTOCLEAR_FEATURES_OFFSET)
+            .unwrap();
+
+        Qcow2FileEngine::from_file(file.try_clone().unwrap(), true).unwrap();
+        assert_eq!(read_entry(file, AUTOCLEAR_FEATURES_OFFSET), 1);
+        Qcow2FileEngine::from_file(file.try_clone().unwrap(), false).unwrap();
+        assert_eq!(read_entry(file, AUTOCLEAR_FEATURES_OFFSET), 0);
+    }
+
+    #[test]
+    fn test_table_cache() {
+        let mut cache = TableCache::new(2);
+        cache.insert(0, vec![0]);
+        cache.insert(1, vec![1]);
+        // Using the first table makes the second one the least recently used.
+        cache.get_mut(0).unwrap()[0] = 2;
+        cache.insert(2, vec![3]);
+        assert!(cache.contains(0));
+        assert!(!cache.contains(1));
+        assert!(cache.contains(2));
+        assert_eq!(cache.get_mut(0).unwrap()[0], 2);
+        assert!(cache.get_mut(1).is_none());
+    }
+}
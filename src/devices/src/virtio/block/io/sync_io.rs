@@ -3,12 +3,14 @@
 
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
 use std::result::Result;
 
 use vm_memory::{Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
 #[derive(Debug)]
 pub enum Error {
+    Fallocate(std::io::Error),
     Flush(std::io::Error),
     Seek(std::io::Error),
     SyncAll(std::io::Error),
@@ -26,7 +28,6 @@ impl SyncFileEngine {
         SyncFileEngine { file }
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         &self.file
     }
@@ -61,6 +62,22 @@ impl SyncFileEngine {
             .map_err(Error::Transfer)
     }
 
+    pub fn fallocate(&mut self, mode: u32, offset: u64, len: u64) -> Result<(), Error> {
+        // Safe because the file descriptor is valid and the return value is checked.
+        let ret = unsafe {
+            libc::fallocate(
+                self.file.as_raw_fd(),
+                mode as libc::c_int,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Fallocate(std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
     pub fn flush(&mut self) -> Result<(), Error> {
         // flush() first to force any cached data out of rust buffers.
         self.file.flush().map_err(Error::Flush)?;
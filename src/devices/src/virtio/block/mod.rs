@@ -7,21 +7,32 @@ mod io;
 pub mod persist;
 pub mod request;
 pub mod test_utils;
+mod trace;
 
 use vm_memory::GuestMemoryError;
 
+use crate::virtio::net::VhostError;
+
 pub use self::device::{Block, CacheType};
 pub use self::event_handler::*;
+pub use self::io::nbd::is_nbd_uri;
 pub use self::request::*;
+pub use self::trace::{TraceFormat, TraceRecord, TraceWriter};
 
-pub const CONFIG_SPACE_SIZE: usize = 8;
+pub const CONFIG_SPACE_SIZE: usize = 60;
+// Offset of `num_queues` in `struct virtio_blk_config`.
+pub const NUM_QUEUES_CONFIG_OFFSET: usize = 34;
+// Offset of `max_discard_sectors` in `struct virtio_blk_config`, the first of the discard and
+// write zeroes fields.
+pub const DISCARD_CONFIG_OFFSET: usize = 36;
 pub const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = (0x01_u64) << SECTOR_SHIFT;
 pub const QUEUE_SIZE: u16 = 256;
-pub const NUM_QUEUES: usize = 1;
-pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+// The guest uses at most one queue per vCPU.
+pub const MAX_NUM_QUEUES: u16 = 32;
 // The virtio queue can hold up to 256 descriptors, but 1 request spreads across 2-3 descriptors.
-// So we can use 128 IO_URING entries without ever triggering a FullSq Error.
+// So we can use 128 IO_URING entries without triggering a FullSq Error for a single queue. The
+// requests of devices with several queues are throttled when the submission queue is full.
 pub const IO_URING_NUM_ENTRIES: u16 = 128;
 
 #[derive(Debug)]
@@ -34,8 +45,19 @@ pub enum Error {
     GetFileMetadata(std::io::Error),
     /// Guest gave us bad memory addresses.
     GuestMemory(GuestMemoryError),
+    /// The new size of the disk is smaller than the current one, or not a multiple of the
+    /// sector size.
+    InvalidDiskSize(u64),
+    /// The file descriptor handed over for the backing file can't be used by the drive.
+    InvalidDiskFd(&'static str),
+    /// The read-only mode of the given kind of drive can't be changed.
+    UnsupportedReadOnlyToggle(&'static str),
+    /// The requests served by a vhost-user backend can't be traced.
+    VhostUserTrace,
     /// The data length is invalid.
     InvalidDataLength,
+    /// The flags of a discard or write zeroes request are invalid.
+    InvalidFlags,
     /// The requested operation would cause a seek beyond disk end.
     InvalidOffset,
     /// Guest gave us a read only descriptor that protocol says to write to.
@@ -54,4 +76,6 @@ pub enum Error {
     RateLimiter(std::io::Error),
     // Persistence error.
     Persist(crate::virtio::persist::Error),
+    // Error setting up the vhost-user backend.
+    VhostUser(VhostError),
 }
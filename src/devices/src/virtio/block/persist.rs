@@ -17,7 +17,7 @@ use virtio_gen::virtio_blk::VIRTIO_BLK_F_RO;
 use vm_memory::GuestMemoryMmap;
 
 use super::*;
-use crate::virtio::block::device::FileEngineType;
+use crate::virtio::block::device::{FileEngineType, ImageFormat};
 use crate::virtio::persist::VirtioDeviceState;
 use crate::virtio::{DeviceState, TYPE_BLOCK};
 
@@ -26,6 +26,8 @@ use crate::virtio::{DeviceState, TYPE_BLOCK};
 pub enum CacheTypeState {
     Unsafe,
     Writeback,
+    // Only serialized from BlockState version 4 onwards, see `block_cache_type_ser()`.
+    Writethrough,
 }
 
 impl From<CacheType> for CacheTypeState {
@@ -33,6 +35,7 @@ impl From<CacheType> for CacheTypeState {
         match cache_type {
             CacheType::Unsafe => CacheTypeState::Unsafe,
             CacheType::Writeback => CacheTypeState::Writeback,
+            CacheType::Writethrough => CacheTypeState::Writethrough,
         }
     }
 }
@@ -42,6 +45,7 @@ impl From<CacheTypeState> for CacheType {
         match cache_type_state {
             CacheTypeState::Unsafe => CacheType::Unsafe,
             CacheTypeState::Writeback => CacheType::Writeback,
+            CacheTypeState::Writethrough => CacheType::Writethrough,
         }
     }
 }
@@ -71,6 +75,31 @@ impl From<FileEngineTypeState> for FileEngineType {
     }
 }
 
+#[derive(Clone, Copy, Debug, Versionize, PartialEq)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub enum ImageFormatState {
+    Raw,
+    Qcow2,
+}
+
+impl From<ImageFormat> for ImageFormatState {
+    fn from(image_format: ImageFormat) -> Self {
+        match image_format {
+            ImageFormat::Raw => ImageFormatState::Raw,
+            ImageFormat::Qcow2 => ImageFormatState::Qcow2,
+        }
+    }
+}
+
+impl From<ImageFormatState> for ImageFormat {
+    fn from(image_format_state: ImageFormatState) -> Self {
+        match image_format_state {
+            ImageFormatState::Raw => ImageFormat::Raw,
+            ImageFormatState::Qcow2 => ImageFormat::Qcow2,
+        }
+    }
+}
+
 impl Default for FileEngineTypeState {
     fn default() -> Self {
         // If the snap version does not contain the `FileEngineType`, it must have been snapshotted
@@ -91,7 +120,7 @@ pub struct BlockState {
     )]
     cache_type: CacheTypeState,
     root_device: bool,
-    disk_path: String,
+    pub disk_path: String,
     virtio_state: VirtioDeviceState,
     rate_limiter_state: RateLimiterState,
     #[version(start = 3)]
@@ -99,10 +128,47 @@ pub struct BlockState {
     // v1.0 are incompatible with older FC versions (due to incompatible notification suppression
     // feature).
     file_engine_type: FileEngineTypeState,
+    #[version(
+        start = 4,
+        default_fn = "def_image_format",
+        ser_fn = "ser_image_format"
+    )]
+    image_format: ImageFormatState,
+    #[version(
+        start = 4,
+        default_fn = "def_overlay_path",
+        ser_fn = "ser_overlay_path"
+    )]
+    overlay_path: Option<String>,
+    #[version(
+        start = 4,
+        default_fn = "def_rw_rate_limiter_state",
+        ser_fn = "ser_read_rate_limiter_state"
+    )]
+    read_rate_limiter_state: Option<RateLimiterState>,
+    #[version(
+        start = 4,
+        default_fn = "def_rw_rate_limiter_state",
+        ser_fn = "ser_write_rate_limiter_state"
+    )]
+    write_rate_limiter_state: Option<RateLimiterState>,
+    #[version(start = 4, default_fn = "def_serial", ser_fn = "ser_serial")]
+    serial: Option<String>,
 }
 
 impl BlockState {
+    /// Lists the fields of the state this implementation of the device can't be restored from.
+    pub fn incompatible_fields(&self) -> Vec<String> {
+        self.virtio_state
+            .incompatible_fields(TYPE_BLOCK, QUEUE_SIZE)
+    }
+
     fn block_cache_type_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && self.cache_type == CacheTypeState::Writethrough {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the writethrough cache type.".to_owned(),
+            ));
+        }
         if target_version < 3 && self.cache_type != CacheTypeState::Unsafe {
             warn!(
                 "Target version does not implement the current cache type. Defaulting to \
@@ -116,6 +182,89 @@ impl BlockState {
     fn default_cache_type_flush(_source_version: u16) -> CacheTypeState {
         CacheTypeState::Unsafe
     }
+
+    fn def_image_format(_: u16) -> ImageFormatState {
+        ImageFormatState::Raw
+    }
+
+    fn ser_image_format(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && self.image_format != ImageFormatState::Raw {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement qcow2 block devices.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn def_overlay_path(_: u16) -> Option<String> {
+        None
+    }
+
+    fn ser_overlay_path(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && self.overlay_path.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement block devices with an overlay.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn def_rw_rate_limiter_state(_: u16) -> Option<RateLimiterState> {
+        None
+    }
+
+    fn ser_read_rate_limiter_state(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && self.read_rate_limiter_state.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement read rate limiters.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn ser_write_rate_limiter_state(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && self.write_rate_limiter_state.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement write rate limiters.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn def_serial(_: u16) -> Option<String> {
+        None
+    }
+
+    fn ser_serial(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && self.serial.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement block device serials.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// Only the rate limiters which have a bucket are saved, so that the snapshots of the devices
+// without read or write rate limiters remain compatible with the older versions.
+fn save_rw_rate_limiter(rate_limiter: &RateLimiter) -> Option<RateLimiterState> {
+    if rate_limiter.bandwidth().is_none() && rate_limiter.ops().is_none() {
+        None
+    } else {
+        Some(rate_limiter.save())
+    }
+}
+
+fn restore_rw_rate_limiter(state: &Option<RateLimiterState>) -> Result<RateLimiter, Error> {
+    match state {
+        Some(state) => RateLimiter::restore((), state).map_err(Error::RateLimiter),
+        None => Ok(RateLimiter::default()),
+    }
 }
 
 pub struct BlockConstructorArgs {
@@ -138,6 +287,11 @@ impl Persist<'_> for Block {
             virtio_state: VirtioDeviceState::from_device(self),
             rate_limiter_state: self.rate_limiter.save(),
             file_engine_type: FileEngineTypeState::from(self.file_engine_type()),
+            image_format: ImageFormatState::from(self.image_format()),
+            overlay_path: self.overlay_path().cloned(),
+            read_rate_limiter_state: save_rw_rate_limiter(&self.read_rate_limiter),
+            write_rate_limiter_state: save_rw_rate_limiter(&self.write_rate_limiter),
+            serial: self.serial.clone(),
         }
     }
 
@@ -146,8 +300,15 @@ impl Persist<'_> for Block {
         state: &Self::State,
     ) -> Result<Self, Self::Error> {
         let is_disk_read_only = state.virtio_state.avail_features & (1u64 << VIRTIO_BLK_F_RO) != 0;
+        // The number of queues of the device is the one of the saved queues.
+        let num_queues = state.virtio_state.queues.len() as u16;
+        if num_queues == 0 || num_queues > MAX_NUM_QUEUES {
+            return Err(Error::Persist(crate::virtio::persist::Error::InvalidInput));
+        }
         let rate_limiter =
             RateLimiter::restore((), &state.rate_limiter_state).map_err(Error::RateLimiter)?;
+        let read_rate_limiter = restore_rw_rate_limiter(&state.read_rate_limiter_state)?;
+        let write_rate_limiter = restore_rw_rate_limiter(&state.write_rate_limiter_state)?;
 
         let mut block = Block::new(
             state.id.clone(),
@@ -157,7 +318,12 @@ impl Persist<'_> for Block {
             is_disk_read_only,
             state.root_device,
             rate_limiter,
+            read_rate_limiter,
+            write_rate_limiter,
             state.file_engine_type.into(),
+            state.image_format.into(),
+            state.overlay_path.clone(),
+            num_queues,
         )
         .or_else(|err| match err {
             Error::FileEngine(io::Error::UnsupportedEngine(FileEngineType::Async)) => {
@@ -170,6 +336,8 @@ impl Persist<'_> for Block {
 
                 let rate_limiter = RateLimiter::restore((), &state.rate_limiter_state)
                     .map_err(Error::RateLimiter)?;
+                let read_rate_limiter = restore_rw_rate_limiter(&state.read_rate_limiter_state)?;
+                let write_rate_limiter = restore_rw_rate_limiter(&state.write_rate_limiter_state)?;
                 Block::new(
                     state.id.clone(),
                     state.partuuid.clone(),
@@ -178,7 +346,12 @@ impl Persist<'_> for Block {
                     is_disk_read_only,
                     state.root_device,
                     rate_limiter,
+                    read_rate_limiter,
+                    write_rate_limiter,
                     FileEngineType::Sync,
+                    state.image_format.into(),
+                    state.overlay_path.clone(),
+                    num_queues,
                 )
             }
             other_err => Err(other_err),
@@ -186,8 +359,14 @@ impl Persist<'_> for Block {
 
         block.queues = state
             .virtio_state
-            .build_queues_checked(&constructor_args.mem, TYPE_BLOCK, NUM_QUEUES, QUEUE_SIZE)
+            .build_queues_checked(
+                &constructor_args.mem,
+                TYPE_BLOCK,
+                usize::from(num_queues),
+                QUEUE_SIZE,
+            )
             .map_err(Error::Persist)?;
+        block.set_serial(state.serial.clone());
         block.irq_trigger.irq_status =
             Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         block.avail_features = state.virtio_state.avail_features;
@@ -208,6 +387,8 @@ mod tests {
     use utils::tempfile::TempFile;
 
     use super::*;
+    use crate::virtio::block::io::overlay::tests::{create_base, BASE_SIZE};
+    use crate::virtio::block::io::qcow2::tests::{create_image, VIRTUAL_SIZE};
     use crate::virtio::device::VirtioDevice;
     use crate::virtio::test_utils::default_mem;
 
@@ -221,12 +402,17 @@ mod tests {
             CacheTypeState::Writeback,
             CacheTypeState::from(CacheType::Writeback)
         );
+        assert_eq!(
+            CacheTypeState::Writethrough,
+            CacheTypeState::from(CacheType::Writethrough)
+        );
     }
 
     #[test]
     fn test_cache_type_state_into() {
         assert_eq!(CacheType::Unsafe, CacheTypeState::Unsafe.into());
         assert_eq!(CacheType::Writeback, CacheTypeState::Writeback.into());
+        assert_eq!(CacheType::Writethrough, CacheTypeState::Writethrough.into());
     }
 
     #[test]
@@ -256,7 +442,12 @@ mod tests {
             false,
             false,
             RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
             FileEngineType::default(),
+            ImageFormat::Raw,
+            None,
+            1,
         )
         .unwrap();
 
@@ -273,6 +464,64 @@ mod tests {
             .is_ok());
     }
 
+    #[test]
+    fn test_writethrough_cache_type_state() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let new_block = |cache_type| {
+            Block::new(
+                "test".to_string(),
+                None,
+                cache_type,
+                f.as_path().to_str().unwrap().to_string(),
+                false,
+                false,
+                RateLimiter::default(),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                FileEngineType::default(),
+                ImageFormat::Raw,
+                None,
+                1,
+            )
+            .unwrap()
+        };
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 3)
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        // Version 3 of the state only knows about the unsafe and writeback cache types.
+        let mut mem = vec![0; 4096];
+        assert!(
+            <Block as Persist>::save(&new_block(CacheType::Writethrough))
+                .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+                .is_err()
+        );
+
+        <Block as Persist>::save(&new_block(CacheType::Writeback))
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.cache_type(), CacheType::Writeback);
+
+        <Block as Persist>::save(&new_block(CacheType::Writethrough))
+            .serialize(&mut mem.as_mut_slice(), &version_map, 3)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 3).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.cache_type(), CacheType::Writethrough);
+    }
+
     #[test]
     fn test_file_engine_type() {
         // Test conversions between FileEngineType and FileEngineTypeState.
@@ -308,9 +557,14 @@ mod tests {
                 false,
                 false,
                 RateLimiter::default(),
+                RateLimiter::default(),
+                RateLimiter::default(),
                 // Need to use Sync because it will otherwise return an error.
                 // We'll overwrite the state instead.
                 FileEngineType::Sync,
+                ImageFormat::Raw,
+                None,
+                1,
             )
             .unwrap();
 
@@ -338,6 +592,206 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_image_format_state() {
+        assert_eq!(
+            ImageFormatState::Qcow2,
+            ImageFormatState::from(ImageFormat::Qcow2)
+        );
+        assert_eq!(ImageFormat::Raw, ImageFormatState::Raw.into());
+        assert_eq!(BlockState::def_image_format(3), ImageFormatState::Raw);
+
+        let image = create_image(4);
+        let block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            image.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            FileEngineType::Sync,
+            ImageFormat::Qcow2,
+            None,
+            1,
+        )
+        .unwrap();
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        // Older versions can't describe qcow2 block devices.
+        let mut mem = vec![0; 4096];
+        assert!(<Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.image_format(), ImageFormat::Qcow2);
+        assert_eq!(restored_block.disk.nsectors(), VIRTUAL_SIZE >> SECTOR_SHIFT);
+    }
+
+    #[test]
+    fn test_overlay_path_state() {
+        assert_eq!(BlockState::def_overlay_path(3), None);
+
+        let base = create_base();
+        let overlay = TempFile::new().unwrap();
+        let overlay_path = overlay.as_path().to_str().unwrap().to_string();
+        let block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            base.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            FileEngineType::Sync,
+            ImageFormat::Raw,
+            Some(overlay_path.clone()),
+            1,
+        )
+        .unwrap();
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        // Older versions can't describe block devices with an overlay.
+        let mut mem = vec![0; 4096];
+        assert!(<Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.overlay_path(), Some(&overlay_path));
+        assert_eq!(restored_block.disk.nsectors(), BASE_SIZE >> SECTOR_SHIFT);
+    }
+
+    #[test]
+    fn test_read_write_rate_limiter_state() {
+        assert!(BlockState::def_rw_rate_limiter_state(3).is_none());
+
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::new(0, 0, 0, 10, 0, 100).unwrap(),
+            FileEngineType::Sync,
+            ImageFormat::Raw,
+            None,
+            1,
+        )
+        .unwrap();
+        let state = <Block as Persist>::save(&block);
+        // Only the rate limiters with buckets are saved.
+        assert!(state.read_rate_limiter_state.is_none());
+        assert!(state.write_rate_limiter_state.is_some());
+
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        // Older versions can't describe the rate limiters of the writes.
+        let mut mem = vec![0; 4096];
+        assert!(state
+            .clone()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.read_rate_limiter(), &RateLimiter::default());
+        assert_eq!(
+            restored_block
+                .write_rate_limiter()
+                .ops()
+                .unwrap()
+                .capacity(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_serial_state() {
+        assert_eq!(BlockState::def_serial(3), None);
+
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut block = Block::new(
+            "test".to_string(),
+            None,
+            CacheType::Unsafe,
+            f.as_path().to_str().unwrap().to_string(),
+            false,
+            false,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            FileEngineType::Sync,
+            ImageFormat::Raw,
+            None,
+            1,
+        )
+        .unwrap();
+        block.set_serial(Some("vol-0123456789".to_string()));
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 4);
+
+        // Older versions can't describe the serial of the drive.
+        let mut mem = vec![0; 4096];
+        assert!(<Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Block as Persist>::save(&block)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_block = Block::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.serial(), Some(&"vol-0123456789".to_string()));
+        assert_eq!(&restored_block.disk.image_id()[..14], b"vol-0123456789");
+        assert_eq!(&restored_block.disk.image_id()[14..], &[0; 6]);
+    }
+
     #[test]
     fn test_persistence() {
         // We create the backing file here so that it exists for the whole lifetime of the test.
@@ -353,7 +807,12 @@ mod tests {
             false,
             false,
             RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
             FileEngineType::default(),
+            ImageFormat::Raw,
+            None,
+            1,
         )
         .unwrap();
         let guest_mem = default_mem();
@@ -12,20 +12,28 @@ use logger::{error, IncMetric, METRICS};
 use rate_limiter::{RateLimiter, TokenType};
 pub use virtio_gen::virtio_blk::{
     VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP,
-    VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
+    VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN,
+    VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP,
 };
 use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
 use super::super::DescriptorChain;
-use super::{io as block_io, Error, SECTOR_SHIFT};
+use super::{io as block_io, Error, TraceRecord, SECTOR_SHIFT};
 use crate::virtio::block::device::DiskProperties;
 use crate::virtio::SECTOR_SIZE;
 
+// The `fallocate` modes used to discard sectors and to write zeroes to them, without changing
+// the size of the backing file.
+const PUNCH_HOLE_MODE: u32 = (libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE) as u32;
+const ZERO_RANGE_MODE: u32 = (libc::FALLOC_FL_ZERO_RANGE | libc::FALLOC_FL_KEEP_SIZE) as u32;
+
 #[derive(Debug)]
 pub enum IoErr {
     GetId(GuestMemoryError),
     PartialTransfer { completed: u32, expected: u32 },
     FileEngine(block_io::Error),
+    // The request would modify a disk which was made read-only.
+    ReadOnly,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -34,6 +42,8 @@ pub enum RequestType {
     Out,
     Flush,
     GetDeviceID,
+    Discard,
+    WriteZeroes,
     Unsupported(u32),
 }
 
@@ -44,6 +54,8 @@ impl From<u32> for RequestType {
             VIRTIO_BLK_T_OUT => RequestType::Out,
             VIRTIO_BLK_T_FLUSH => RequestType::Flush,
             VIRTIO_BLK_T_GET_ID => RequestType::GetDeviceID,
+            VIRTIO_BLK_T_DISCARD => RequestType::Discard,
+            VIRTIO_BLK_T_WRITE_ZEROES => RequestType::WriteZeroes,
             t => RequestType::Unsupported(t),
         }
     }
@@ -90,10 +102,36 @@ pub struct PendingRequest {
     r#type: RequestType,
     data_len: u32,
     status_addr: GuestAddress,
+    queue_index: u16,
     desc_idx: u16,
+    // When the request was submitted, in microseconds of the monotonic clock.
+    start_us: u64,
+    // The range of the disk accessed by the request, recorded in the traces.
+    offset: u64,
+    len: u64,
 }
 
 impl PendingRequest {
+    /// Index of the queue the request was popped from.
+    pub fn queue_index(&self) -> usize {
+        usize::from(self.queue_index)
+    }
+
+    /// When the request was submitted, in microseconds of the monotonic clock.
+    pub fn start_us(&self) -> u64 {
+        self.start_us
+    }
+
+    /// Describes the request for the trace of the device, once it took `latency_us` to complete.
+    pub(crate) fn trace_record(&self, latency_us: u64) -> TraceRecord {
+        TraceRecord {
+            r#type: self.r#type,
+            offset: self.offset,
+            len: self.len,
+            latency_us,
+        }
+    }
+
     fn write_status_and_finish(self, status: &Status, mem: &GuestMemoryMmap) -> FinishedRequest {
         let (num_bytes_to_mem, status_code) = match status {
             Status::Ok { num_bytes_to_mem } => (*num_bytes_to_mem, VIRTIO_BLK_S_OK),
@@ -160,6 +198,18 @@ impl PendingRequest {
             (Ok(transferred_data_len), RequestType::GetDeviceID) => {
                 Status::from_data(self.data_len, transferred_data_len, true)
             }
+            (Ok(_), RequestType::Discard) => {
+                METRICS.block.discard_count.inc();
+                Status::Ok {
+                    num_bytes_to_mem: 0,
+                }
+            }
+            (Ok(_), RequestType::WriteZeroes) => {
+                METRICS.block.write_zeroes_count.inc();
+                Status::Ok {
+                    num_bytes_to_mem: 0,
+                }
+            }
             (_, RequestType::Unsupported(op)) => Status::Unsupported { op },
             (Err(err), _) => Status::IoErr {
                 num_bytes_to_mem: 0,
@@ -191,6 +241,29 @@ pub struct RequestHeader {
 // Safe because RequestHeader only contains plain data.
 unsafe impl ByteValued for RequestHeader {}
 
+/// The range of sectors a discard or write zeroes request applies to, as read from its data
+/// descriptor.
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct DiscardWriteZeroesSegment {
+    sector: u64,
+    num_sectors: u32,
+    flags: u32,
+}
+
+// Safe because DiscardWriteZeroesSegment only contains plain data.
+unsafe impl ByteValued for DiscardWriteZeroesSegment {}
+
+impl DiscardWriteZeroesSegment {
+    pub fn new(sector: u64, num_sectors: u32, flags: u32) -> DiscardWriteZeroesSegment {
+        DiscardWriteZeroesSegment {
+            sector,
+            num_sectors,
+            flags,
+        }
+    }
+}
+
 impl RequestHeader {
     pub fn new(request_type: u32, sector: u64) -> RequestHeader {
         RequestHeader {
@@ -220,6 +293,10 @@ pub struct Request {
     pub status_addr: GuestAddress,
     sector: u64,
     data_addr: GuestAddress,
+    // The number of sectors and the flags of discard and write zeroes requests, whose first
+    // sector replaces the one of the header.
+    num_sectors: u32,
+    flags: u32,
 }
 
 impl Request {
@@ -240,6 +317,8 @@ impl Request {
             data_addr: GuestAddress(0),
             data_len: 0,
             status_addr: GuestAddress(0),
+            num_sectors: 0,
+            flags: 0,
         };
 
         let data_desc;
@@ -260,7 +339,12 @@ impl Request {
                 .next_descriptor()
                 .ok_or(Error::DescriptorChainTooShort)?;
 
-            if data_desc.is_write_only() && req.r#type == RequestType::Out {
+            if data_desc.is_write_only()
+                && matches!(
+                    req.r#type,
+                    RequestType::Out | RequestType::Discard | RequestType::WriteZeroes
+                )
+            {
                 return Err(Error::UnexpectedWriteOnlyDescriptor);
             }
             if !data_desc.is_write_only() && req.r#type == RequestType::In {
@@ -295,6 +379,32 @@ impl Request {
                     return Err(Error::InvalidDataLength);
                 }
             }
+            RequestType::Discard | RequestType::WriteZeroes => {
+                // A single segment is advertised in the config space.
+                if req.data_len as usize != std::mem::size_of::<DiscardWriteZeroesSegment>() {
+                    return Err(Error::InvalidDataLength);
+                }
+                let segment: DiscardWriteZeroesSegment =
+                    mem.read_obj(req.data_addr).map_err(Error::GuestMemory)?;
+                let top_sector = segment
+                    .sector
+                    .checked_add(u64::from(segment.num_sectors))
+                    .ok_or(Error::InvalidOffset)?;
+                if top_sector > num_disk_sectors {
+                    return Err(Error::InvalidOffset);
+                }
+                // Unmapping is implied for discard requests.
+                let valid_flags = match req.r#type {
+                    RequestType::WriteZeroes => VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP,
+                    _ => 0,
+                };
+                if segment.flags & !valid_flags != 0 {
+                    return Err(Error::InvalidFlags);
+                }
+                req.sector = segment.sector;
+                req.num_sectors = segment.num_sectors;
+                req.flags = segment.flags;
+            }
             _ => {}
         }
 
@@ -312,17 +422,50 @@ impl Request {
         Ok(req)
     }
 
-    pub(crate) fn rate_limit(&self, rate_limiter: &mut RateLimiter) -> bool {
+    pub(crate) fn rate_limit(
+        &self,
+        rate_limiter: &mut RateLimiter,
+        read_rate_limiter: &mut RateLimiter,
+        write_rate_limiter: &mut RateLimiter,
+    ) -> bool {
+        if self.consume_tokens(rate_limiter) {
+            return true;
+        }
+        // Reads and the requests modifying the disk are also limited on their own.
+        let directional_rate_limiter = match self.r#type {
+            RequestType::In => Some(read_rate_limiter),
+            RequestType::Out | RequestType::Discard | RequestType::WriteZeroes => {
+                Some(write_rate_limiter)
+            }
+            RequestType::Flush | RequestType::GetDeviceID | RequestType::Unsupported(_) => None,
+        };
+        if let Some(directional_rate_limiter) = directional_rate_limiter {
+            if self.consume_tokens(directional_rate_limiter) {
+                // Revert the consume() of the common rate limiter.
+                rate_limiter.manual_replenish(1, TokenType::Ops);
+                if let Some(bytes) = self.rate_limited_bytes() {
+                    rate_limiter.manual_replenish(bytes, TokenType::Bytes);
+                }
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Returns true if `rate_limiter` doesn't have the budget for this request, in which case no
+    // token is consumed.
+    fn consume_tokens(&self, rate_limiter: &mut RateLimiter) -> bool {
         // If limiter.consume() fails it means there is no more TokenType::Ops
         // budget and rate limiting is in effect.
         if !rate_limiter.consume(1, TokenType::Ops) {
             return true;
         }
         // Exercise the rate limiter only if this request is of data transfer type.
-        if self.r#type == RequestType::In || self.r#type == RequestType::Out {
+        if let Some(bytes) = self.rate_limited_bytes() {
             // If limiter.consume() fails it means there is no more TokenType::Bytes
             // budget and rate limiting is in effect.
-            if !rate_limiter.consume(u64::from(self.data_len), TokenType::Bytes) {
+            if !rate_limiter.consume(bytes, TokenType::Bytes) {
                 // Revert the OPS consume().
                 rate_limiter.manual_replenish(1, TokenType::Ops);
                 return true;
@@ -332,26 +475,81 @@ impl Request {
         false
     }
 
+    /// Returns true if the request modifies the disk.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self.r#type,
+            RequestType::Out | RequestType::Discard | RequestType::WriteZeroes
+        )
+    }
+
+    /// Fails the request without touching the disk, which became read-only after the driver
+    /// negotiated the features of the device.
+    pub(crate) fn reject_read_only(
+        self,
+        queue_index: u16,
+        desc_idx: u16,
+        mem: &GuestMemoryMmap,
+    ) -> FinishedRequest {
+        self.to_pending_request(queue_index, desc_idx)
+            .finish(mem, Err(IoErr::ReadOnly))
+    }
+
+    fn rate_limited_bytes(&self) -> Option<u64> {
+        match self.r#type {
+            RequestType::In | RequestType::Out => Some(u64::from(self.data_len)),
+            _ => None,
+        }
+    }
+
     fn offset(&self) -> u64 {
         self.sector << SECTOR_SHIFT
     }
 
-    fn to_pending_request(&self, desc_idx: u16) -> PendingRequest {
+    fn range_len(&self) -> u64 {
+        u64::from(self.num_sectors) << SECTOR_SHIFT
+    }
+
+    // Number of bytes of the disk accessed by the request.
+    fn io_len(&self) -> u64 {
+        match self.r#type {
+            RequestType::In | RequestType::Out => u64::from(self.data_len),
+            RequestType::Discard | RequestType::WriteZeroes => self.range_len(),
+            RequestType::Flush | RequestType::GetDeviceID | RequestType::Unsupported(_) => 0,
+        }
+    }
+
+    /// Describes the request for the trace of the device, once it took `latency_us` to complete.
+    pub(crate) fn trace_record(&self, latency_us: u64) -> TraceRecord {
+        TraceRecord {
+            r#type: self.r#type,
+            offset: self.offset(),
+            len: self.io_len(),
+            latency_us,
+        }
+    }
+
+    fn to_pending_request(&self, queue_index: u16, desc_idx: u16) -> PendingRequest {
         PendingRequest {
             r#type: self.r#type,
             data_len: self.data_len,
             status_addr: self.status_addr,
+            queue_index,
             desc_idx,
+            start_us: utils::time::get_time_us(utils::time::ClockType::Monotonic),
+            offset: self.offset(),
+            len: self.io_len(),
         }
     }
 
     pub(crate) fn process(
         self,
         disk: &mut DiskProperties,
+        queue_index: u16,
         desc_idx: u16,
         mem: &GuestMemoryMmap,
     ) -> ProcessingResult {
-        let pending = self.to_pending_request(desc_idx);
+        let pending = self.to_pending_request(queue_index, desc_idx);
         let res = match self.r#type {
             RequestType::In => disk.file_engine_mut().read(
                 self.offset(),
@@ -368,6 +566,22 @@ impl Request {
                 pending,
             ),
             RequestType::Flush => disk.file_engine_mut().flush(pending),
+            RequestType::Discard => disk.file_engine_mut().fallocate(
+                PUNCH_HOLE_MODE,
+                self.offset(),
+                self.range_len(),
+                pending,
+            ),
+            RequestType::WriteZeroes => {
+                // Punched holes read back as zeroes, and free the space they used.
+                let mode = if self.flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0 {
+                    PUNCH_HOLE_MODE
+                } else {
+                    ZERO_RANGE_MODE
+                };
+                disk.file_engine_mut()
+                    .fallocate(mode, self.offset(), self.range_len(), pending)
+            }
             RequestType::GetDeviceID => {
                 let res = mem
                     .write_slice(disk.image_id(), self.data_addr)
@@ -421,6 +635,8 @@ mod tests {
             VIRTIO_BLK_T_OUT,
             VIRTIO_BLK_T_FLUSH,
             VIRTIO_BLK_T_GET_ID,
+            VIRTIO_BLK_T_DISCARD,
+            VIRTIO_BLK_T_WRITE_ZEROES,
         ];
 
         for request_type in supported_request_types {
@@ -447,6 +663,14 @@ mod tests {
             RequestType::from(VIRTIO_BLK_T_GET_ID),
             RequestType::GetDeviceID
         );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_DISCARD),
+            RequestType::Discard
+        );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_WRITE_ZEROES),
+            RequestType::WriteZeroes
+        );
         assert_eq!(RequestType::from(42), RequestType::Unsupported(42));
     }
 
@@ -693,6 +917,54 @@ mod tests {
         queue.check_parse(true);
     }
 
+    #[test]
+    fn test_parse_discard_write_zeroes() {
+        let mem = &create_anon_guest_memory(&[(GuestAddress(0), 0x10000)], false).unwrap();
+        let mut queue = RequestVirtQueue::new(GuestAddress(0), &mem);
+        let segment_len = std::mem::size_of::<DiscardWriteZeroesSegment>() as u32;
+
+        let request_header = RequestHeader::new(VIRTIO_BLK_T_DISCARD, 0);
+        queue.set_hdr_desc(0x1000, 0x1000, VIRTQ_DESC_F_NEXT, request_header);
+        queue.set_status_desc(0x3000, 0x1000, VIRTQ_DESC_F_WRITE);
+
+        // Write only data descriptor.
+        queue.set_data_desc(0x2000, segment_len, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+        queue.check_parse_err(Error::UnexpectedWriteOnlyDescriptor);
+
+        // More than one segment.
+        queue.set_data_desc(0x2000, 2 * segment_len, VIRTQ_DESC_F_NEXT);
+        queue.check_parse_err(Error::InvalidDataLength);
+
+        // Range beyond the end of the disk.
+        queue.set_data_desc(0x2000, segment_len, VIRTQ_DESC_F_NEXT);
+        let segment = DiscardWriteZeroesSegment::new(NUM_DISK_SECTORS - 1, 2, 0);
+        mem.write_obj(segment, GuestAddress(0x2000)).unwrap();
+        queue.check_parse_err(Error::InvalidOffset);
+
+        // The unmap flag is only valid for write zeroes requests.
+        let segment = DiscardWriteZeroesSegment::new(
+            NUM_DISK_SECTORS - 2,
+            2,
+            VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP,
+        );
+        mem.write_obj(segment, GuestAddress(0x2000)).unwrap();
+        queue.check_parse_err(Error::InvalidFlags);
+
+        queue.mut_hdr().request_type = VIRTIO_BLK_T_WRITE_ZEROES;
+        let mut q = queue.vq.create_queue();
+        let request = Request::parse(&q.pop(mem).unwrap(), mem, NUM_DISK_SECTORS).unwrap();
+        assert_eq!(request.r#type, RequestType::WriteZeroes);
+        assert_eq!(request.sector, NUM_DISK_SECTORS - 2);
+        assert_eq!(request.num_sectors, 2);
+        assert_eq!(request.flags, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP);
+        assert_eq!(request.range_len(), 2 * SECTOR_SIZE);
+
+        // Other flags are invalid.
+        let segment = DiscardWriteZeroesSegment::new(0, 2, 2);
+        mem.write_obj(segment, GuestAddress(0x2000)).unwrap();
+        queue.check_parse_err(Error::InvalidFlags);
+    }
+
     use std::convert::TryInto;
 
     /// -------------------------------------
@@ -735,9 +1007,15 @@ mod tests {
                     1u32,
                     std::sync::Arc::new(Strategy::prop_map(any::<u32>(), |id| {
                         // Random unsupported requests for our implementation start at
-                        // VIRTIO_BLK_T_GET_ID + 1 = 9.
+                        // VIRTIO_BLK_T_GET_ID + 1 = 9, skipping the discard and write zeroes
+                        // ones.
                         // This can be further refined to include unsupported requests ids < 9.
-                        RequestType::Unsupported(id.checked_add(9).unwrap_or(9))
+                        match id.checked_add(9).unwrap_or(9) {
+                            VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
+                                RequestType::Unsupported(9)
+                            }
+                            id => RequestType::Unsupported(id),
+                        }
                     })),
                 ),
             ))
@@ -751,6 +1029,8 @@ mod tests {
                 RequestType::Out => VIRTIO_BLK_T_OUT,
                 RequestType::Flush => VIRTIO_BLK_T_FLUSH,
                 RequestType::GetDeviceID => VIRTIO_BLK_T_GET_ID,
+                RequestType::Discard => VIRTIO_BLK_T_DISCARD,
+                RequestType::WriteZeroes => VIRTIO_BLK_T_WRITE_ZEROES,
                 RequestType::Unsupported(id) => id,
             }
         }
@@ -763,6 +1043,7 @@ mod tests {
             RequestType::Out => VIRTQ_DESC_F_NEXT,
             RequestType::Flush => VIRTQ_DESC_F_NEXT,
             RequestType::GetDeviceID => VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE,
+            RequestType::Discard | RequestType::WriteZeroes => VIRTQ_DESC_F_NEXT,
             RequestType::Unsupported(_) => VIRTQ_DESC_F_NEXT,
         }
     }
@@ -856,6 +1137,8 @@ mod tests {
             status_addr,
             sector: sector & (NUM_DISK_SECTORS - sectors_len),
             data_addr,
+            num_sectors: 0,
+            flags: 0,
         };
         let request_header = RequestHeader::new(virtio_request_id, request.sector);
 
@@ -12,7 +12,7 @@ use rate_limiter::RateLimiter;
 use utils::kernel_version::{min_kernel_version_for_io_uring, KernelVersion};
 use utils::tempfile::TempFile;
 
-use crate::virtio::block::device::FileEngineType;
+use crate::virtio::block::device::{FileEngineType, ImageFormat};
 #[cfg(test)]
 use crate::virtio::block::io::FileEngine;
 #[cfg(test)]
@@ -52,7 +52,12 @@ pub fn default_block_with_path(path: String, file_engine_type: FileEngineType) -
         false,
         false,
         rate_limiter,
+        RateLimiter::default(),
+        RateLimiter::default(),
         file_engine_type,
+        ImageFormat::Raw,
+        None,
+        1,
     )
     .unwrap()
 }
@@ -74,7 +79,7 @@ pub fn simulate_queue_event(b: &mut Block, maybe_expected_irq: Option<bool>) {
     // Trigger the queue event.
     b.queue_evts[0].write(1).unwrap();
     // Handle event.
-    b.process_queue_event();
+    b.process_queue_event(0);
     // Validate the queue operation finished successfully.
     if let Some(expected_irq) = maybe_expected_irq {
         assert_eq!(b.irq_trigger.has_pending_irq(IrqType::Vring), expected_irq);
@@ -103,7 +108,11 @@ pub fn simulate_queue_and_async_completion_events(b: &mut Block, expected_irq: b
             simulate_queue_event(b, None);
             simulate_async_completion_event(b, expected_irq);
         }
-        FileEngine::Sync(_) => {
+        FileEngine::Sync(_)
+        | FileEngine::Qcow2(_)
+        | FileEngine::Overlay(_)
+        | FileEngine::Nbd(_)
+        | FileEngine::VhostUser(_) => {
             simulate_queue_event(b, Some(expected_irq));
         }
     }
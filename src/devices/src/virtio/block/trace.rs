@@ -0,0 +1,258 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writer of the trace of the requests served by a block device, to be replayed or analyzed
+//! offline.
+//!
+//! Each request is recorded once it completes, either as a JSON line or as a fixed size binary
+//! record. The number of records per second is capped, and the records above the cap are
+//! dropped.
+
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use logger::{IncMetric, METRICS};
+use rate_limiter::{BucketReduction, TokenBucket};
+use serde::{Deserialize, Serialize};
+use utils::time::{get_time_us, ClockType};
+
+use super::request::RequestType;
+use super::{
+    VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN,
+    VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES,
+};
+
+// Leads binary traces, followed by the version of the record layout.
+const BINARY_MAGIC: &[u8; 4] = b"FCBT";
+const BINARY_VERSION: u32 = 1;
+const BINARY_HEADER_LEN: usize = 8;
+const BINARY_RECORD_LEN: usize = 32;
+
+/// The encoding of the records of a trace.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TraceFormat {
+    /// One JSON object per line.
+    Jsonl,
+    /// Little-endian records of 32 bytes, after an 8 bytes header.
+    Binary,
+}
+
+impl Default for TraceFormat {
+    fn default() -> Self {
+        TraceFormat::Jsonl
+    }
+}
+
+/// A request served by a block device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceRecord {
+    /// The type of the request.
+    pub r#type: RequestType,
+    /// Offset in bytes on the disk of the first byte the request accesses.
+    pub offset: u64,
+    /// Number of bytes the request accesses.
+    pub len: u64,
+    /// Time between the submission and the completion of the request, in microseconds.
+    pub latency_us: u64,
+}
+
+impl TraceRecord {
+    fn type_name(&self) -> &'static str {
+        match self.r#type {
+            RequestType::In => "In",
+            RequestType::Out => "Out",
+            RequestType::Flush => "Flush",
+            RequestType::GetDeviceID => "GetDeviceID",
+            RequestType::Discard => "Discard",
+            RequestType::WriteZeroes => "WriteZeroes",
+            RequestType::Unsupported(_) => "Unsupported",
+        }
+    }
+
+    fn type_code(&self) -> u32 {
+        match self.r#type {
+            RequestType::In => VIRTIO_BLK_T_IN,
+            RequestType::Out => VIRTIO_BLK_T_OUT,
+            RequestType::Flush => VIRTIO_BLK_T_FLUSH,
+            RequestType::GetDeviceID => VIRTIO_BLK_T_GET_ID,
+            RequestType::Discard => VIRTIO_BLK_T_DISCARD,
+            RequestType::WriteZeroes => VIRTIO_BLK_T_WRITE_ZEROES,
+            RequestType::Unsupported(t) => t,
+        }
+    }
+}
+
+/// Writes the requests of a block device to a trace file.
+#[derive(Debug)]
+pub struct TraceWriter {
+    path: PathBuf,
+    format: TraceFormat,
+    file: File,
+    record: String,
+    // Caps the number of records written per second.
+    records_budget: TokenBucket,
+    dropped_count: u64,
+}
+
+impl TraceWriter {
+    /// Starts a trace in the file at `path`, truncating it, with at most
+    /// `max_records_per_sec` records per second. Fails with `InvalidInput` when that cap is 0.
+    pub fn new(path: &Path, format: TraceFormat, max_records_per_sec: u64) -> io::Result<Self> {
+        let records_budget = TokenBucket::new(max_records_per_sec, 0, 1000)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut file = File::create(path)?;
+        if format == TraceFormat::Binary {
+            let mut header = [0u8; BINARY_HEADER_LEN];
+            header[0..4].copy_from_slice(BINARY_MAGIC);
+            header[4..8].copy_from_slice(&BINARY_VERSION.to_le_bytes());
+            file.write_all(&header)?;
+        }
+
+        Ok(TraceWriter {
+            path: path.to_path_buf(),
+            format,
+            file,
+            record: String::new(),
+            records_budget,
+            dropped_count: 0,
+        })
+    }
+
+    /// Provides the path of the trace file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Provides the format of the trace.
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Number of records dropped because they exceeded the cap.
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped_count
+    }
+
+    /// Appends `record` to the trace, stamped with the current time, unless the cap on the
+    /// records per second is reached.
+    pub fn write_record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if self.records_budget.reduce(1) == BucketReduction::Failure {
+            self.dropped_count += 1;
+            METRICS.block.trace_dropped_count.inc();
+            return Ok(());
+        }
+
+        let timestamp_us = get_time_us(ClockType::Real);
+        match self.format {
+            TraceFormat::Jsonl => {
+                self.record.clear();
+                // Writing to a String can't fail.
+                let _ = writeln!(
+                    self.record,
+                    "{{\"timestamp_us\":{},\"type\":\"{}\",\"offset\":{},\"len\":{},\
+                     \"latency_us\":{}}}",
+                    timestamp_us,
+                    record.type_name(),
+                    record.offset,
+                    record.len,
+                    record.latency_us
+                );
+                self.file.write_all(self.record.as_bytes())
+            }
+            TraceFormat::Binary => {
+                let mut buf = [0u8; BINARY_RECORD_LEN];
+                buf[0..8].copy_from_slice(&timestamp_us.to_le_bytes());
+                buf[8..16].copy_from_slice(&record.offset.to_le_bytes());
+                buf[16..24].copy_from_slice(&record.len.to_le_bytes());
+                // Latencies above an hour and a bit are saturated.
+                let latency_us = u32::try_from(record.latency_us).unwrap_or(u32::MAX);
+                buf[24..28].copy_from_slice(&latency_us.to_le_bytes());
+                buf[28..32].copy_from_slice(&record.type_code().to_le_bytes());
+                self.file.write_all(&buf)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    fn record(r#type: RequestType, offset: u64) -> TraceRecord {
+        TraceRecord {
+            r#type,
+            offset,
+            len: 4096,
+            latency_us: 10,
+        }
+    }
+
+    #[test]
+    fn test_jsonl_trace() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("trace.jsonl");
+        let mut writer = TraceWriter::new(&path, TraceFormat::Jsonl, 100).unwrap();
+        assert_eq!(writer.path(), path.as_path());
+        assert_eq!(writer.format(), TraceFormat::Jsonl);
+
+        writer.write_record(&record(RequestType::In, 0)).unwrap();
+        writer
+            .write_record(&record(RequestType::WriteZeroes, 512))
+            .unwrap();
+
+        let trace = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("{\"timestamp_us\":"));
+        assert!(lines[1]
+            .ends_with("\"type\":\"WriteZeroes\",\"offset\":512,\"len\":4096,\"latency_us\":10}"));
+    }
+
+    #[test]
+    fn test_binary_trace() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("trace.bin");
+        let mut writer = TraceWriter::new(&path, TraceFormat::Binary, 100).unwrap();
+        writer
+            .write_record(&record(RequestType::Out, 1024))
+            .unwrap();
+
+        let trace = fs::read(&path).unwrap();
+        assert_eq!(trace.len(), BINARY_HEADER_LEN + BINARY_RECORD_LEN);
+        assert_eq!(&trace[..4], BINARY_MAGIC);
+        assert_eq!(&trace[4..8], &BINARY_VERSION.to_le_bytes());
+        let rec = &trace[BINARY_HEADER_LEN..];
+        assert_eq!(&rec[8..16], &1024u64.to_le_bytes());
+        assert_eq!(&rec[16..24], &4096u64.to_le_bytes());
+        assert_eq!(&rec[24..28], &10u32.to_le_bytes());
+        assert_eq!(&rec[28..32], &VIRTIO_BLK_T_OUT.to_le_bytes());
+    }
+
+    #[test]
+    fn test_records_cap() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("trace.jsonl");
+        assert_eq!(
+            TraceWriter::new(&path, TraceFormat::Jsonl, 0)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let mut writer = TraceWriter::new(&path, TraceFormat::Jsonl, 2).unwrap();
+        for i in 0..5 {
+            writer
+                .write_record(&record(RequestType::In, i * 512))
+                .unwrap();
+        }
+        assert_eq!(writer.dropped_count(), 3);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+}
@@ -0,0 +1,806 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use logger::{error, IncMetric, METRICS};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{
+    ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
+    GuestMemoryRegion,
+};
+
+use super::super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_MEM};
+use super::{
+    MIN_BLOCK_SIZE, NUM_QUEUES, QUEUE_SIZES, VIRTIO_MEM_REQ_PLUG, VIRTIO_MEM_REQ_STATE,
+    VIRTIO_MEM_REQ_UNPLUG, VIRTIO_MEM_REQ_UNPLUG_ALL, VIRTIO_MEM_RESP_ACK, VIRTIO_MEM_RESP_ERROR,
+    VIRTIO_MEM_RESP_NACK, VIRTIO_MEM_STATE_MIXED, VIRTIO_MEM_STATE_PLUGGED,
+    VIRTIO_MEM_STATE_UNPLUGGED,
+};
+use crate::virtio::mem::Error as MemError;
+use crate::virtio::{DescriptorChain, IrqTrigger, IrqType};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ConfigSpace {
+    // Granularity at which the memory is plugged and unplugged, in bytes.
+    pub block_size: u64,
+    // NUMA node of the memory, unused since VIRTIO_MEM_F_ACPI_PXM isn't offered.
+    pub node_id: u16,
+    pub padding: [u8; 6],
+    // Guest physical address of the hot-pluggable memory.
+    pub addr: u64,
+    // Size of the hot-pluggable memory, in bytes.
+    pub region_size: u64,
+    // Size of the part of the hot-pluggable memory the guest may plug, in bytes.
+    pub usable_region_size: u64,
+    // Size of the memory plugged by the guest, in bytes.
+    pub plugged_size: u64,
+    // Size of the memory the guest is asked to plug, in bytes.
+    pub requested_size: u64,
+}
+
+// Safe because ConfigSpace only contains plain data.
+unsafe impl ByteValued for ConfigSpace {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Request {
+    pub req_type: u16,
+    pub padding: [u16; 3],
+    // Guest physical address of the first block of the request.
+    pub addr: u64,
+    // Number of blocks of the request, unused by unplug all requests.
+    pub nb_blocks: u16,
+    pub padding_1: [u16; 3],
+}
+
+// Safe because Request only contains plain data.
+unsafe impl ByteValued for Request {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Response {
+    pub resp_type: u16,
+    pub padding: [u16; 3],
+    // The state of the blocks, only set in the responses to state requests.
+    pub state: u16,
+}
+
+// Safe because Response only contains plain data.
+unsafe impl ByteValued for Response {}
+
+impl Response {
+    fn new(resp_type: u16) -> Self {
+        Response {
+            resp_type,
+            ..Default::default()
+        }
+    }
+}
+
+const REQUEST_SIZE: usize = std::mem::size_of::<Request>();
+const RESPONSE_SIZE: usize = std::mem::size_of::<Response>();
+
+// Virtio-mem device, exposing memory which the guest plugs and unplugs block by block, up to
+// the size requested by the host.
+pub struct VirtioMem {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: ConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: [EventFd; NUM_QUEUES],
+    pub(crate) device_state: DeviceState,
+    pub(crate) irq_trigger: IrqTrigger,
+
+    // Implementation specific fields.
+    // Whether each block of the hot-pluggable memory is plugged.
+    pub(crate) plugged_blocks: Vec<bool>,
+}
+
+impl VirtioMem {
+    /// Creates a virtio-mem device exposing the `region_size` bytes of guest memory at `addr`,
+    /// plugged and unplugged by blocks of `block_size` bytes.
+    ///
+    /// The block size must be a power of two of at least 2MiB, and the region size a non-zero
+    /// multiple of it. The memory must already be part of the guest memory.
+    pub fn new(addr: GuestAddress, region_size: u64, block_size: u64) -> Result<Self, MemError> {
+        if !block_size.is_power_of_two() || block_size < MIN_BLOCK_SIZE {
+            return Err(MemError::InvalidBlockSize(block_size));
+        }
+        if region_size == 0 || region_size % block_size != 0 {
+            return Err(MemError::InvalidRegionSize(region_size));
+        }
+
+        Ok(VirtioMem {
+            // VIRTIO_MEM_F_UNPLUGGED_INACCESSIBLE isn't offered: the unplugged blocks stay
+            // mapped in the guest, which reads zeroes from them.
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space: ConfigSpace {
+                block_size,
+                addr: addr.0,
+                region_size,
+                usable_region_size: region_size,
+                ..Default::default()
+            },
+            queue_evts: [EventFd::new(libc::EFD_NONBLOCK).map_err(MemError::EventFd)?],
+            queues: QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect(),
+            irq_trigger: IrqTrigger::new().map_err(MemError::EventFd)?,
+            device_state: DeviceState::Inactive,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(MemError::EventFd)?,
+            plugged_blocks: vec![false; (region_size / block_size) as usize],
+        })
+    }
+
+    pub(crate) fn process_queue_event(&mut self) -> Result<(), MemError> {
+        METRICS.memory_hotplug.queue_event_count.inc();
+        self.queue_evts[0].read().map_err(MemError::EventFd)?;
+        self.process_queue()
+    }
+
+    pub(crate) fn process_queue(&mut self) -> Result<(), MemError> {
+        // This is safe since we checked in the event handler that the device is activated.
+        // Cloning the guest memory only clones references to its regions.
+        let mem = self.device_state.mem().unwrap().clone();
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[0].pop(&mem) {
+            let len = Self::parse_request(&mem, &head)
+                .and_then(|(request, response_addr)| {
+                    let response = self.handle_request(&mem, &request);
+                    mem.write_obj(response, response_addr)
+                        .map(|()| RESPONSE_SIZE as u32)
+                        .map_err(MemError::GuestMemory)
+                })
+                .unwrap_or_else(|e| {
+                    error!("virtio-mem: failed to process request: {:?}", e);
+                    METRICS.memory_hotplug.execute_fails.inc();
+                    0
+                });
+            self.queues[0]
+                .add_used(&mem, head.index, len)
+                .map_err(MemError::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
+    // Reads a request from the guest memory. Returns it along with the address of its response.
+    fn parse_request(
+        mem: &GuestMemoryMmap,
+        head: &DescriptorChain,
+    ) -> Result<(Request, GuestAddress), MemError> {
+        if head.is_write_only() || (head.len as usize) < REQUEST_SIZE {
+            return Err(MemError::MalformedDescriptor);
+        }
+        let response_desc = head
+            .next_descriptor()
+            .ok_or(MemError::DescriptorChainTooShort)?;
+        if !response_desc.is_write_only() || (response_desc.len as usize) < RESPONSE_SIZE {
+            return Err(MemError::MalformedDescriptor);
+        }
+
+        let request = mem
+            .read_obj::<Request>(head.addr)
+            .map_err(MemError::GuestMemory)?;
+        Ok((request, response_desc.addr))
+    }
+
+    fn handle_request(&mut self, mem: &GuestMemoryMmap, request: &Request) -> Response {
+        let blocks = self.block_range(request.addr, request.nb_blocks);
+        match (request.req_type, blocks) {
+            (VIRTIO_MEM_REQ_PLUG, Some(blocks)) => self.plug(blocks),
+            (VIRTIO_MEM_REQ_UNPLUG, Some(blocks)) => self.unplug(mem, blocks),
+            (VIRTIO_MEM_REQ_UNPLUG_ALL, _) => self.unplug_all(mem),
+            (VIRTIO_MEM_REQ_STATE, Some(blocks)) => self.state(blocks),
+            (VIRTIO_MEM_REQ_PLUG, None)
+            | (VIRTIO_MEM_REQ_UNPLUG, None)
+            | (VIRTIO_MEM_REQ_STATE, None) => {
+                error!(
+                    "virtio-mem: invalid range of {} blocks at {:#x}",
+                    request.nb_blocks, request.addr
+                );
+                METRICS.memory_hotplug.execute_fails.inc();
+                Response::new(VIRTIO_MEM_RESP_ERROR)
+            }
+            (req_type, _) => {
+                error!("virtio-mem: unsupported request type {}", req_type);
+                METRICS.memory_hotplug.execute_fails.inc();
+                Response::new(VIRTIO_MEM_RESP_ERROR)
+            }
+        }
+    }
+
+    // Returns the indexes of the `nb_blocks` blocks starting at `addr`, unless they aren't all
+    // part of the usable memory.
+    fn block_range(&self, addr: u64, nb_blocks: u16) -> Option<Range<usize>> {
+        let block_size = self.config_space.block_size;
+        let offset = addr.checked_sub(self.config_space.addr)?;
+        if nb_blocks == 0 || offset % block_size != 0 {
+            return None;
+        }
+        let first = offset / block_size;
+        let end = first.checked_add(u64::from(nb_blocks))?;
+        if end.checked_mul(block_size)? > self.config_space.usable_region_size {
+            return None;
+        }
+        Some(first as usize..end as usize)
+    }
+
+    fn plug(&mut self, blocks: Range<usize>) -> Response {
+        if self.plugged_blocks[blocks.clone()]
+            .iter()
+            .any(|&plugged| plugged)
+        {
+            error!("virtio-mem: plugging blocks which are already plugged");
+            METRICS.memory_hotplug.execute_fails.inc();
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+        let size = blocks.len() as u64 * self.config_space.block_size;
+        // The guest can't plug more memory than requested.
+        if self.config_space.plugged_size + size > self.config_space.requested_size {
+            METRICS.memory_hotplug.plug_rejected.inc();
+            return Response::new(VIRTIO_MEM_RESP_NACK);
+        }
+
+        self.plugged_blocks[blocks]
+            .iter_mut()
+            .for_each(|plugged| *plugged = true);
+        self.config_space.plugged_size += size;
+        METRICS.memory_hotplug.plug_count.inc();
+        Response::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn unplug(&mut self, mem: &GuestMemoryMmap, blocks: Range<usize>) -> Response {
+        if !self.plugged_blocks[blocks.clone()]
+            .iter()
+            .all(|&plugged| plugged)
+        {
+            error!("virtio-mem: unplugging blocks which aren't plugged");
+            METRICS.memory_hotplug.execute_fails.inc();
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+        self.release_blocks(mem, blocks)
+    }
+
+    fn unplug_all(&mut self, mem: &GuestMemoryMmap) -> Response {
+        if self.config_space.plugged_size == 0 {
+            return Response::new(VIRTIO_MEM_RESP_ACK);
+        }
+        // Discarding the blocks which aren't plugged is harmless.
+        self.release_blocks(mem, 0..self.plugged_blocks.len())
+    }
+
+    // Discards the memory of `blocks` and marks the plugged ones as unplugged.
+    fn release_blocks(&mut self, mem: &GuestMemoryMmap, blocks: Range<usize>) -> Response {
+        if let Err(e) = self.discard_blocks(mem, blocks.clone()) {
+            error!("virtio-mem: failed to discard unplugged memory: {:?}", e);
+            METRICS.memory_hotplug.unplug_fails.inc();
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+        let block_size = self.config_space.block_size;
+        for plugged in self.plugged_blocks[blocks].iter_mut().filter(|p| **p) {
+            *plugged = false;
+            self.config_space.plugged_size -= block_size;
+        }
+        METRICS.memory_hotplug.unplug_count.inc();
+        Response::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn state(&self, blocks: Range<usize>) -> Response {
+        let plugged_count = self.plugged_blocks[blocks.clone()]
+            .iter()
+            .filter(|&&plugged| plugged)
+            .count();
+        let state = if plugged_count == blocks.len() {
+            VIRTIO_MEM_STATE_PLUGGED
+        } else if plugged_count == 0 {
+            VIRTIO_MEM_STATE_UNPLUGGED
+        } else {
+            VIRTIO_MEM_STATE_MIXED
+        };
+        Response {
+            state,
+            ..Response::new(VIRTIO_MEM_RESP_ACK)
+        }
+    }
+
+    // Frees the host memory backing `blocks`. The guest reads zeroes from them once they are
+    // plugged again.
+    fn discard_blocks(&self, mem: &GuestMemoryMmap, blocks: Range<usize>) -> Result<(), MemError> {
+        let block_size = self.config_space.block_size;
+        let addr = GuestAddress(self.config_space.addr + blocks.start as u64 * block_size);
+        let len = blocks.len() * block_size as usize;
+        let region = mem.find_region(addr).ok_or(MemError::GuestMemory(
+            GuestMemoryError::InvalidGuestAddress(addr),
+        ))?;
+        let host_addr = mem.get_host_address(addr).map_err(MemError::GuestMemory)?;
+        // The pages of guest memory shared through memfds or hugetlbfs files are only freed once
+        // removed from their file. Private file mappings, such as the memory restored from a
+        // snapshot, only drop their copies of the pages, and read the file contents back.
+        let advice = if region.file_offset().is_some() && region.flags() & libc::MAP_SHARED != 0 {
+            libc::MADV_REMOVE
+        } else {
+            libc::MADV_DONTNEED
+        };
+
+        // Safe because the range is part of the guest memory, which stays mapped for as long
+        // as the device is activated.
+        let ret = unsafe { libc::madvise(host_addr as *mut _, len, advice) };
+        if ret < 0 {
+            return Err(MemError::Madvise(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<(), MemError> {
+        self.irq_trigger.trigger_irq(IrqType::Vring).map_err(|e| {
+            METRICS.memory_hotplug.event_fails.inc();
+            MemError::InterruptError(e)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_queue();
+    }
+
+    /// Returns the guest physical address of the hot-pluggable memory.
+    pub fn guest_address(&self) -> GuestAddress {
+        GuestAddress(self.config_space.addr)
+    }
+
+    /// Returns the size of the hot-pluggable memory, in bytes.
+    pub fn region_size(&self) -> u64 {
+        self.config_space.region_size
+    }
+
+    /// Returns the size of the blocks the memory is plugged by, in bytes.
+    pub fn block_size(&self) -> u64 {
+        self.config_space.block_size
+    }
+
+    /// Returns the size of the memory plugged by the guest, in bytes.
+    pub fn plugged_size(&self) -> u64 {
+        self.config_space.plugged_size
+    }
+
+    /// Returns the size of the memory the guest is asked to plug, in bytes.
+    pub fn requested_size(&self) -> u64 {
+        self.config_space.requested_size
+    }
+
+    /// Checks that the guest could be asked to plug `size` bytes, without asking it.
+    pub fn validate_requested_size(&self, size: u64) -> Result<(), MemError> {
+        if size % self.config_space.block_size != 0 || size > self.config_space.usable_region_size {
+            return Err(MemError::InvalidRequestedSize(size));
+        }
+        Ok(())
+    }
+
+    /// Asks the guest to plug or unplug blocks until `size` bytes are plugged.
+    pub fn update_requested_size(&mut self, size: u64) -> Result<(), MemError> {
+        self.validate_requested_size(size)?;
+        self.config_space.requested_size = size;
+        self.irq_trigger
+            .trigger_irq(IrqType::Config)
+            .map_err(MemError::InterruptError)
+    }
+}
+
+impl VirtioDevice for VirtioMem {
+    fn device_type(&self) -> u32 {
+        TYPE_MEM
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.irq_trigger.irq_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            METRICS.memory_hotplug.cfg_fails.inc();
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The configuration space of a virtio-mem device is read-only.
+        error!("Guest attempted to write the virtio-mem config space");
+        METRICS.memory_hotplug.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        self.device_state.is_activated()
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("VirtioMem: Cannot write to activate_evt");
+            METRICS.memory_hotplug.activate_fails.inc();
+            return Err(super::super::ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use utils::tempfile::TempFile;
+    use vm_memory::FileOffset;
+
+    use super::super::{CONFIG_SPACE_SIZE, MEMORY_HOTPLUG_ALIGNMENT};
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::virtio::test_utils::VirtQueue;
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    const REGION_ADDR: u64 = MEMORY_HOTPLUG_ALIGNMENT;
+    const REGION_SIZE: u64 = 4 * MIN_BLOCK_SIZE;
+    const REQUEST_ADDR: u64 = 0x1000;
+    const RESPONSE_ADDR: u64 = 0x2000;
+
+    pub(crate) fn default_virtio_mem() -> VirtioMem {
+        VirtioMem::new(GuestAddress(REGION_ADDR), REGION_SIZE, MIN_BLOCK_SIZE).unwrap()
+    }
+
+    fn mem_with_region() -> GuestMemoryMmap {
+        vm_memory::test_utils::create_anon_guest_memory(
+            &[
+                (GuestAddress(0), 0x10000),
+                (GuestAddress(REGION_ADDR), REGION_SIZE as usize),
+            ],
+            false,
+        )
+        .unwrap()
+    }
+
+    // Sends a request to `virtio_mem` and returns its response.
+    fn send_request(
+        virtio_mem: &mut VirtioMem,
+        mem: &GuestMemoryMmap,
+        vq: &VirtQueue,
+        req_type: u16,
+        addr: u64,
+        nb_blocks: u16,
+    ) -> Response {
+        let request = Request {
+            req_type,
+            addr,
+            nb_blocks,
+            ..Default::default()
+        };
+        mem.write_obj(request, GuestAddress(REQUEST_ADDR)).unwrap();
+        let idx = vq.avail.idx.get();
+        vq.avail.ring[idx as usize].set(0);
+        vq.avail.idx.set(idx + 1);
+        virtio_mem.process_queue().unwrap();
+        vq.check_used_elem(idx, 0, RESPONSE_SIZE as u32);
+        mem.read_obj(GuestAddress(RESPONSE_ADDR)).unwrap()
+    }
+
+    #[test]
+    fn test_new() {
+        let virtio_mem = default_virtio_mem();
+        assert_eq!(virtio_mem.device_type(), TYPE_MEM);
+        assert_eq!(virtio_mem.guest_address(), GuestAddress(REGION_ADDR));
+        assert_eq!(virtio_mem.region_size(), REGION_SIZE);
+        assert_eq!(virtio_mem.block_size(), MIN_BLOCK_SIZE);
+        assert_eq!(virtio_mem.plugged_size(), 0);
+        assert_eq!(virtio_mem.requested_size(), 0);
+        assert_eq!(virtio_mem.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+
+        // The block size must be a power of two of at least 2MiB.
+        assert!(matches!(
+            VirtioMem::new(GuestAddress(REGION_ADDR), REGION_SIZE, MIN_BLOCK_SIZE / 2),
+            Err(MemError::InvalidBlockSize(_))
+        ));
+        assert!(matches!(
+            VirtioMem::new(GuestAddress(REGION_ADDR), REGION_SIZE, 3 * MIN_BLOCK_SIZE),
+            Err(MemError::InvalidBlockSize(_))
+        ));
+        // The region size must be a non-zero multiple of the block size.
+        assert!(matches!(
+            VirtioMem::new(GuestAddress(REGION_ADDR), 0, MIN_BLOCK_SIZE),
+            Err(MemError::InvalidRegionSize(0))
+        ));
+        assert!(matches!(
+            VirtioMem::new(
+                GuestAddress(REGION_ADDR),
+                REGION_SIZE + 4096,
+                MIN_BLOCK_SIZE
+            ),
+            Err(MemError::InvalidRegionSize(_))
+        ));
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let mut virtio_mem = default_virtio_mem();
+
+        let mut actual_config_space = [0u8; CONFIG_SPACE_SIZE];
+        virtio_mem.read_config(0, &mut actual_config_space);
+        let expected_config = ConfigSpace {
+            block_size: MIN_BLOCK_SIZE,
+            addr: REGION_ADDR,
+            region_size: REGION_SIZE,
+            usable_region_size: REGION_SIZE,
+            ..Default::default()
+        };
+        assert_eq!(&actual_config_space[..], expected_config.as_slice());
+
+        // Invalid read.
+        check_metric_after_block!(
+            &METRICS.memory_hotplug.cfg_fails,
+            1,
+            virtio_mem.read_config(CONFIG_SPACE_SIZE as u64, &mut actual_config_space)
+        );
+
+        // Writes are ignored.
+        check_metric_after_block!(
+            &METRICS.memory_hotplug.cfg_fails,
+            1,
+            virtio_mem.write_config(0, &[0xff; CONFIG_SPACE_SIZE])
+        );
+        virtio_mem.read_config(0, &mut actual_config_space);
+        assert_eq!(&actual_config_space[..], expected_config.as_slice());
+    }
+
+    #[test]
+    fn test_update_requested_size() {
+        let mut virtio_mem = default_virtio_mem();
+
+        virtio_mem
+            .update_requested_size(2 * MIN_BLOCK_SIZE)
+            .unwrap();
+        assert_eq!(virtio_mem.requested_size(), 2 * MIN_BLOCK_SIZE);
+        assert!(virtio_mem.irq_trigger.has_pending_irq(IrqType::Config));
+
+        // The requested size must be a multiple of the block size.
+        assert!(matches!(
+            virtio_mem.update_requested_size(MIN_BLOCK_SIZE + 4096),
+            Err(MemError::InvalidRequestedSize(_))
+        ));
+        // And it can't exceed the hot-pluggable memory.
+        assert!(matches!(
+            virtio_mem.validate_requested_size(REGION_SIZE + MIN_BLOCK_SIZE),
+            Err(MemError::InvalidRequestedSize(_))
+        ));
+        assert_eq!(virtio_mem.requested_size(), 2 * MIN_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_process_queue() {
+        let mut virtio_mem = default_virtio_mem();
+        let mem = mem_with_region();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        virtio_mem.queues[0] = vq.create_queue();
+        virtio_mem.activate(mem.clone()).unwrap();
+        vq.dtable[0].set(REQUEST_ADDR, REQUEST_SIZE as u32, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(RESPONSE_ADDR, RESPONSE_SIZE as u32, VIRTQ_DESC_F_WRITE, 0);
+        virtio_mem
+            .update_requested_size(2 * MIN_BLOCK_SIZE)
+            .unwrap();
+
+        // Plug the first 2 blocks.
+        let mut response = Response::default();
+        check_metric_after_block!(
+            &METRICS.memory_hotplug.plug_count,
+            1,
+            response = send_request(
+                &mut virtio_mem,
+                &mem,
+                &vq,
+                VIRTIO_MEM_REQ_PLUG,
+                REGION_ADDR,
+                2
+            )
+        );
+        assert_eq!(response.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert!(virtio_mem.irq_trigger.has_pending_irq(IrqType::Vring));
+        assert_eq!(virtio_mem.plugged_size(), 2 * MIN_BLOCK_SIZE);
+
+        // Plugging more than the requested size is rejected.
+        let last_block = REGION_ADDR + 3 * MIN_BLOCK_SIZE;
+        check_metric_after_block!(
+            &METRICS.memory_hotplug.plug_rejected,
+            1,
+            response = send_request(
+                &mut virtio_mem,
+                &mem,
+                &vq,
+                VIRTIO_MEM_REQ_PLUG,
+                last_block,
+                1
+            )
+        );
+        assert_eq!(response.resp_type, VIRTIO_MEM_RESP_NACK);
+
+        // Plugging blocks which are plugged, or out of the memory, is an error. That includes
+        // ranges whose end would wrap around to the start of the memory.
+        let highest_block = u64::MAX / MIN_BLOCK_SIZE * MIN_BLOCK_SIZE;
+        let wrapping_nb_blocks = (REGION_ADDR / MIN_BLOCK_SIZE + 2) as u16;
+        for &(addr, nb_blocks) in [
+            (REGION_ADDR + MIN_BLOCK_SIZE, 1),
+            (last_block, 2),
+            (REGION_ADDR + 4096, 1),
+            (REGION_ADDR, 0),
+            (highest_block, wrapping_nb_blocks),
+            (highest_block, u16::MAX),
+        ]
+        .iter()
+        {
+            let response = send_request(
+                &mut virtio_mem,
+                &mem,
+                &vq,
+                VIRTIO_MEM_REQ_PLUG,
+                addr,
+                nb_blocks,
+            );
+            assert_eq!(response.resp_type, VIRTIO_MEM_RESP_ERROR);
+        }
+
+        // The state of the blocks.
+        for &(addr, nb_blocks, state) in [
+            (REGION_ADDR, 2, VIRTIO_MEM_STATE_PLUGGED),
+            (REGION_ADDR + MIN_BLOCK_SIZE, 2, VIRTIO_MEM_STATE_MIXED),
+            (last_block, 1, VIRTIO_MEM_STATE_UNPLUGGED),
+        ]
+        .iter()
+        {
+            let response = send_request(
+                &mut virtio_mem,
+                &mem,
+                &vq,
+                VIRTIO_MEM_REQ_STATE,
+                addr,
+                nb_blocks,
+            );
+            assert_eq!(response.resp_type, VIRTIO_MEM_RESP_ACK);
+            assert_eq!(response.state, state);
+        }
+
+        // Unplugging a block discards its memory.
+        let block = GuestAddress(REGION_ADDR + MIN_BLOCK_SIZE);
+        mem.write_obj::<u64>(0xdead_beef, block).unwrap();
+        check_metric_after_block!(
+            &METRICS.memory_hotplug.unplug_count,
+            1,
+            response = send_request(
+                &mut virtio_mem,
+                &mem,
+                &vq,
+                VIRTIO_MEM_REQ_UNPLUG,
+                block.0,
+                1
+            )
+        );
+        assert_eq!(response.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(virtio_mem.plugged_size(), MIN_BLOCK_SIZE);
+        assert_eq!(mem.read_obj::<u64>(block).unwrap(), 0);
+
+        // Unplugging blocks which aren't plugged is an error.
+        let response = send_request(
+            &mut virtio_mem,
+            &mem,
+            &vq,
+            VIRTIO_MEM_REQ_UNPLUG,
+            REGION_ADDR,
+            2,
+        );
+        assert_eq!(response.resp_type, VIRTIO_MEM_RESP_ERROR);
+
+        // Unplug all blocks.
+        let response = send_request(&mut virtio_mem, &mem, &vq, VIRTIO_MEM_REQ_UNPLUG_ALL, 0, 0);
+        assert_eq!(response.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(virtio_mem.plugged_size(), 0);
+
+        // Unsupported request type.
+        check_metric_after_block!(
+            &METRICS.memory_hotplug.execute_fails,
+            1,
+            response = send_request(&mut virtio_mem, &mem, &vq, 4, REGION_ADDR, 1)
+        );
+        assert_eq!(response.resp_type, VIRTIO_MEM_RESP_ERROR);
+
+        // The response descriptor must be write-only.
+        vq.dtable[1].set(RESPONSE_ADDR, RESPONSE_SIZE as u32, 0, 0);
+        let idx = vq.avail.idx.get();
+        vq.avail.ring[idx as usize].set(0);
+        vq.avail.idx.set(idx + 1);
+        check_metric_after_block!(
+            &METRICS.memory_hotplug.execute_fails,
+            1,
+            virtio_mem.process_queue().unwrap()
+        );
+        vq.check_used_elem(idx, 0, 0);
+    }
+
+    #[test]
+    fn test_discard_blocks() {
+        let virtio_mem = default_virtio_mem();
+        let block = GuestAddress(REGION_ADDR + MIN_BLOCK_SIZE);
+
+        // Memory shared through memfds.
+        let mem = vm_memory::create_shared_guest_memory(
+            &[(GuestAddress(REGION_ADDR), REGION_SIZE as usize)],
+            false,
+        )
+        .unwrap();
+        mem.write_obj::<u64>(0xdead_beef, block).unwrap();
+        virtio_mem.discard_blocks(&mem, 1..2).unwrap();
+        assert_eq!(mem.read_obj::<u64>(block).unwrap(), 0);
+
+        // Private file mappings.
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(REGION_SIZE).unwrap();
+        let mem = vm_memory::create_guest_memory(
+            &[(
+                Some(FileOffset::new(file, 0)),
+                GuestAddress(REGION_ADDR),
+                REGION_SIZE as usize,
+            )],
+            false,
+        )
+        .unwrap();
+        mem.write_obj::<u64>(0xdead_beef, block).unwrap();
+        virtio_mem.discard_blocks(&mem, 1..2).unwrap();
+        assert_eq!(mem.read_obj::<u64>(block).unwrap(), 0);
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+pub mod event_handler;
+
+use vm_memory::GuestMemoryError;
+
+pub use self::device::VirtioMem;
+pub use self::event_handler::*;
+
+/// Device ID used in MMIO device identification.
+/// Because the virtio-mem device is unique (as there's only one per microVM),
+/// the same ID can be hardcoded.
+pub const MEM_DEV_ID: &str = "mem";
+pub const CONFIG_SPACE_SIZE: usize = 56;
+pub const QUEUE_SIZE: u16 = 128;
+pub const NUM_QUEUES: usize = 1;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+// The guest adds memory in sections of 128MiB, so the start of the hot-pluggable memory is
+// aligned to it to make all of it usable.
+pub const MEMORY_HOTPLUG_ALIGNMENT: u64 = 128 << 20;
+// The smallest block the memory can be plugged and unplugged by.
+pub const MIN_BLOCK_SIZE: u64 = 2 << 20;
+
+// The request types.
+const VIRTIO_MEM_REQ_PLUG: u16 = 0;
+const VIRTIO_MEM_REQ_UNPLUG: u16 = 1;
+const VIRTIO_MEM_REQ_UNPLUG_ALL: u16 = 2;
+const VIRTIO_MEM_REQ_STATE: u16 = 3;
+// The response types.
+const VIRTIO_MEM_RESP_ACK: u16 = 0;
+const VIRTIO_MEM_RESP_NACK: u16 = 1;
+const VIRTIO_MEM_RESP_ERROR: u16 = 3;
+// The states of a range of blocks, returned by state requests.
+const VIRTIO_MEM_STATE_PLUGGED: u16 = 0;
+const VIRTIO_MEM_STATE_UNPLUGGED: u16 = 1;
+const VIRTIO_MEM_STATE_MIXED: u16 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// No virtio-mem device found.
+    DeviceNotFound,
+    /// Guest gave us too few descriptors in a descriptor chain.
+    DescriptorChainTooShort,
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Received error while sending an interrupt.
+    InterruptError(std::io::Error),
+    /// The block size isn't a power of two of at least 2MiB.
+    InvalidBlockSize(u64),
+    /// The size of the hot-pluggable memory isn't a non-zero multiple of the block size.
+    InvalidRegionSize(u64),
+    /// The requested size isn't a multiple of the block size within the hot-pluggable memory.
+    InvalidRequestedSize(u64),
+    /// Failed to discard the memory of unplugged blocks.
+    Madvise(std::io::Error),
+    /// Guest gave us a malformed descriptor.
+    MalformedDescriptor,
+    /// Error while processing the virt queue.
+    Queue(super::QueueError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
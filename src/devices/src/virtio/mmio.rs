@@ -55,6 +55,8 @@ pub struct MmioTransport {
     pub(crate) config_generation: u32,
     mem: GuestMemoryMmap,
     pub(crate) interrupt_status: Arc<AtomicUsize>,
+    // Set once the device has been unplugged from the guest.
+    unplugged: bool,
 }
 
 impl MmioTransport {
@@ -71,6 +73,7 @@ impl MmioTransport {
             config_generation: 0,
             mem,
             interrupt_status,
+            unplugged: false,
         }
     }
 
@@ -83,6 +86,30 @@ impl MmioTransport {
         self.device.clone()
     }
 
+    /// Detaches the device from the guest. The driver is told through a configuration change
+    /// interrupt that the device needs a reset, and the transport then presents an empty slot.
+    ///
+    /// Virtio over MMIO has no hot-unplug protocol, so the guest is expected to have released
+    /// the device beforehand.
+    pub fn unplug(&mut self) -> std::io::Result<()> {
+        self.unplugged = true;
+        self.device_status |= device_status::DEVICE_NEEDS_RESET;
+        self.notify_config_change()
+    }
+
+    /// Lets the driver know that the configuration space of the device changed.
+    pub fn notify_config_change(&mut self) -> std::io::Result<()> {
+        self.config_generation = self.config_generation.wrapping_add(1);
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_CONFIG as usize, Ordering::SeqCst);
+        self.locked_device().interrupt_evt().write(1)
+    }
+
+    /// Returns true if the device was unplugged from the guest.
+    pub fn is_unplugged(&self) -> bool {
+        self.unplugged
+    }
+
     fn check_device_status(&self, set: u32, clr: u32) -> bool {
         self.device_status & (set | clr) == set
     }
@@ -223,6 +250,8 @@ impl BusDevice for MmioTransport {
                 let v = match offset {
                     0x0 => MMIO_MAGIC_VALUE,
                     0x04 => MMIO_VERSION,
+                    // A device ID of 0 tells the driver that the slot is empty.
+                    0x08 if self.unplugged => 0,
                     0x08 => self.locked_device().device_type(),
                     0x0c => VENDOR_ID, // vendor id
                     0x10 => {
@@ -246,6 +275,8 @@ impl BusDevice for MmioTransport {
                 };
                 byte_order::write_le_u32(data, v);
             }
+            // The device may no longer be able to describe its configuration.
+            0x100..=0xfff if self.unplugged => (),
             0x100..=0xfff => self.locked_device().read_config(offset - 0x100, data),
             _ => {
                 warn!(
@@ -266,6 +297,11 @@ impl BusDevice for MmioTransport {
             *v = (*v & !0xffff_ffff) | u64::from(x)
         }
 
+        // Once unplugged, the device is never driven by the guest again.
+        if self.unplugged {
+            return;
+        }
+
         match offset {
             0x00..=0xff if data.len() == 4 => {
                 let v = byte_order::read_le_u32(data);
@@ -850,6 +886,62 @@ pub(crate) mod tests {
         assert!(d.locked_device().is_activated());
     }
 
+    #[test]
+    fn test_bus_device_notify_config_change() {
+        let m =
+            vm_memory::test_utils::create_anon_guest_memory(&[(GuestAddress(0), 0x1000)], false)
+                .unwrap();
+        let mut d = MmioTransport::new(m, Arc::new(Mutex::new(DummyDevice::new())));
+        let mut buf = vec![0; 4];
+
+        activate_device(&mut d);
+        d.read(0xfc, &mut buf[..]);
+        let config_generation = read_le_u32(&buf[..]);
+
+        d.notify_config_change().unwrap();
+        d.read(0xfc, &mut buf[..]);
+        assert_eq!(read_le_u32(&buf[..]), config_generation + 1);
+        d.read(0x60, &mut buf[..]);
+        assert_eq!(read_le_u32(&buf[..]), VIRTIO_MMIO_INT_CONFIG);
+        assert_eq!(d.locked_device().interrupt_evt().read().unwrap(), 1);
+        // The device is still driven by the guest.
+        assert_eq!(d.device_status & device_status::DEVICE_NEEDS_RESET, 0);
+        assert!(!d.is_unplugged());
+    }
+
+    #[test]
+    fn test_bus_device_unplug() {
+        let m =
+            vm_memory::test_utils::create_anon_guest_memory(&[(GuestAddress(0), 0x1000)], false)
+                .unwrap();
+        let mut d = MmioTransport::new(m, Arc::new(Mutex::new(DummyDevice::new())));
+        let mut buf = vec![0; 4];
+
+        activate_device(&mut d);
+        assert!(!d.is_unplugged());
+        d.read(0x08, &mut buf[..]);
+        assert_eq!(read_le_u32(&buf[..]), 123);
+        let config_generation = d.config_generation;
+
+        d.unplug().unwrap();
+        assert!(d.is_unplugged());
+        assert_eq!(d.config_generation, config_generation + 1);
+        assert_ne!(d.device_status & device_status::DEVICE_NEEDS_RESET, 0);
+        assert_eq!(
+            d.interrupt_status.load(Ordering::SeqCst) as u32 & VIRTIO_MMIO_INT_CONFIG,
+            VIRTIO_MMIO_INT_CONFIG
+        );
+        assert_eq!(d.locked_device().interrupt_evt().read().unwrap(), 1);
+
+        // The slot now looks empty and guest writes are ignored.
+        d.read(0x08, &mut buf[..]);
+        assert_eq!(read_le_u32(&buf[..]), 0);
+        let device_status = d.device_status;
+        write_le_u32(&mut buf[..], 0x0);
+        d.write(0x70, &buf[..]);
+        assert_eq!(d.device_status, device_status);
+    }
+
     #[test]
     fn test_get_avail_features() {
         let dummy_dev = DummyDevice::new();
@@ -12,19 +12,24 @@ use std::io::Error as IOError;
 pub mod balloon;
 pub mod block;
 pub mod device;
+pub mod mem;
 mod mmio;
 pub mod net;
 pub mod persist;
+pub mod pmem;
 mod queue;
 pub mod test_utils;
+mod vhost_user;
 pub mod vsock;
 
 pub use self::balloon::*;
 pub use self::block::*;
 pub use self::device::*;
+pub use self::mem::*;
 pub use self::mmio::*;
 pub use self::net::*;
 pub use self::persist::*;
+pub use self::pmem::*;
 pub use self::queue::*;
 pub use self::vsock::*;
 
@@ -43,6 +48,7 @@ mod device_status {
     pub const FAILED: u32 = 128;
     pub const FEATURES_OK: u32 = 8;
     pub const DRIVER_OK: u32 = 4;
+    pub const DEVICE_NEEDS_RESET: u32 = 64;
 }
 
 /// Types taken from linux/virtio_ids.h.
@@ -50,6 +56,8 @@ mod device_status {
 pub const TYPE_NET: u32 = 1;
 pub const TYPE_BLOCK: u32 = 2;
 pub const TYPE_BALLOON: u32 = 5;
+pub const TYPE_MEM: u32 = 24;
+pub const TYPE_PMEM: u32 = 27;
 
 /// Offset from the base MMIO address of a virtio device used by the guest to notify the device of
 /// queue events.
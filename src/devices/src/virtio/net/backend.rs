@@ -0,0 +1,121 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the host side of the virtio-net device.
+
+use std::io::Result as IoResult;
+use std::os::unix::io::AsRawFd;
+
+use serde::{Deserialize, Serialize};
+
+/// The kinds of host-side backends a net device can be attached to.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetBackendType {
+    /// A TAP interface, opened by name.
+    Tap,
+    /// A queue of a host interface, bound to an AF_XDP socket.
+    Xdp,
+    /// A vhost-user backend listening on a unix socket, which moves the frames itself.
+    #[serde(rename = "vhost_user")]
+    VhostUser,
+    /// A connected `SOCK_SEQPACKET` unix socket, exchanging raw L2 frames.
+    /// Only meant to be used by test harnesses.
+    #[cfg(feature = "net-socketpair")]
+    SocketPair,
+}
+
+impl Default for NetBackendType {
+    fn default() -> Self {
+        Self::Tap
+    }
+}
+
+impl NetBackendType {
+    /// Whether the backend can be handed over as an already opened file descriptor, instead
+    /// of being opened by name.
+    pub fn supports_fd(self) -> bool {
+        match self {
+            Self::Tap => true,
+            Self::Xdp | Self::VhostUser => false,
+            #[cfg(feature = "net-socketpair")]
+            Self::SocketPair => true,
+        }
+    }
+}
+
+/// The way frames are moved between the guest and the host-side backend of a net device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetDatapath {
+    /// Frames are copied by the device model, in userspace.
+    Virtio,
+    /// Frames are moved by the vhost-net kernel module. Only available for TAP backends.
+    Vhost,
+}
+
+impl Default for NetDatapath {
+    fn default() -> Self {
+        Self::Virtio
+    }
+}
+
+/// The checksum and segmentation offloads of a net device, all enabled by default. The
+/// segmentation offloads are only available along with checksum offload.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetOffloads {
+    /// Checksum offload.
+    pub csum: bool,
+    /// TCP segmentation offload, for IPv4 and IPv6.
+    pub tso: bool,
+    /// UDP fragmentation offload.
+    pub ufo: bool,
+}
+
+impl Default for NetOffloads {
+    fn default() -> Self {
+        NetOffloads {
+            csum: true,
+            tso: true,
+            ufo: true,
+        }
+    }
+}
+
+/// Host-side endpoint through which the net device exchanges frames.
+///
+/// The buffers passed in and out of a backend always start with a virtio net header, the same
+/// way the device model handles them. Backends which don't deal with vnet headers natively are
+/// responsible for adding or stripping it.
+pub trait NetBackend: AsRawFd + Send {
+    /// Reads a single frame into `buf`, returning the number of bytes written, vnet header
+    /// included.
+    fn read_frame(&mut self, buf: &mut [u8]) -> IoResult<usize>;
+
+    /// Writes a single frame from `buf`, which must start with a vnet header.
+    fn write_frame(&mut self, buf: &[u8]) -> IoResult<usize>;
+
+    /// Host-side name of the backend (e.g. the TAP interface name).
+    fn if_name(&self) -> String;
+
+    /// The kind of this backend.
+    fn backend_type(&self) -> NetBackendType;
+
+    /// Starts or stops steering host traffic towards this backend. Only meaningful for backends
+    /// serving one of the queue pairs of a multi-queue device.
+    fn set_enabled(&mut self, _enabled: bool) -> IoResult<()> {
+        Ok(())
+    }
+
+    /// Sets the MTU of the host-side interface, for backends which have one.
+    fn set_mtu(&self, _mtu: u16) -> IoResult<()> {
+        Ok(())
+    }
+
+    /// Restricts the offloaded frames the host-side interface hands over to the ones the guest
+    /// is able to receive, for backends which offload anything.
+    fn set_offloads(&self, _offloads: NetOffloads) -> IoResult<()> {
+        Ok(())
+    }
+}
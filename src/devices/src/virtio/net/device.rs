@@ -7,8 +7,9 @@
 
 #[cfg(not(test))]
 use std::io;
-use std::io::{Read, Write};
-use std::net::Ipv4Addr;
+use std::io::Write;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
 use std::{cmp, mem, result};
@@ -18,29 +19,53 @@ use libc::EAGAIN;
 use logger::{error, warn, IncMetric, METRICS};
 use mmds::data_store::Mmds;
 use mmds::ns::MmdsNetworkStack;
-use rate_limiter::{BucketUpdate, RateLimiter, TokenType};
+use rate_limiter::{BucketUpdate, RateLimiter, RateLimiterGroup, TokenType};
+use serde::Serialize;
 use utils::eventfd::EventFd;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
 use virtio_gen::virtio_net::{
-    virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM,
-    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO,
-    VIRTIO_NET_F_MAC,
+    virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_RX,
+    VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO,
+    VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_F_MQ,
+    VIRTIO_NET_F_MTU, VIRTIO_NET_F_STATUS, VIRTIO_NET_S_LINK_UP,
 };
 use virtio_gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
+use crate::virtio::net::backend::{NetBackend, NetBackendType, NetOffloads};
+use crate::virtio::net::l3_filter::L3Filter;
+use crate::virtio::net::rx_filter::{
+    RxFilter, VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_RX,
+};
+#[cfg(feature = "net-socketpair")]
+use crate::virtio::net::socketpair::SocketPair;
 use crate::virtio::net::tap::Tap;
 #[cfg(test)]
 use crate::virtio::net::test_utils::Mocks;
+use crate::virtio::net::vhost::{Error as VhostError, VhostBackend, VhostNet};
+use crate::virtio::net::vhost_user::VhostUserBackend;
+use crate::virtio::net::xdp::XdpSocket;
 use crate::virtio::net::{
-    Error, NetQueue, Result, MAX_BUFFER_SIZE, QUEUE_SIZE, QUEUE_SIZES, RX_INDEX, TX_INDEX,
+    Error, NetFilterDirection, NetFilterRule, PcapWriter, Result, MAX_BUFFER_SIZE, MAX_MTU,
+    MAX_QUEUE_PAIRS, MIN_MTU, QUEUE_SIZE, QUEUE_SIZES, RX_INDEX, TX_INDEX,
 };
+use crate::virtio::vhost_user::VhostUser;
 use crate::virtio::{
     ActivateResult, DescriptorChain, DeviceState, IrqTrigger, IrqType, Queue, VirtioDevice,
     TYPE_NET,
 };
 use crate::{report_net_event_fail, Error as DeviceError};
 
+// Control queue command classes and commands, as defined by the virtio spec.
+const VIRTIO_NET_CTRL_MQ: u8 = 4;
+const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+// Control queue command acknowledgements.
+const VIRTIO_NET_OK: u8 = 0;
+const VIRTIO_NET_ERR: u8 = 1;
+// The longest control queue command we accept, class and command bytes included. MAC table
+// commands are the longest ones, and hold several hundreds of addresses at most.
+const CTRL_CMD_MAX_LEN: usize = 4096;
+
 enum FrontendError {
     AddUsed,
     DescriptorChainTooSmall,
@@ -55,6 +80,25 @@ pub(crate) fn vnet_hdr_len() -> usize {
 
 // Frames being sent/received through the network device model have a VNET header. This
 // function returns a slice which holds the L2 frame bytes without this header.
+// Returns the index of the rx queue of the `queue_pair` pair, in the Net device queues/queue_evts
+// vectors.
+pub(crate) fn rx_queue_index(queue_pair: usize) -> usize {
+    2 * queue_pair + RX_INDEX
+}
+
+// Returns the index of the tx queue of the `queue_pair` pair, in the Net device queues/queue_evts
+// vectors.
+pub(crate) fn tx_queue_index(queue_pair: usize) -> usize {
+    2 * queue_pair + TX_INDEX
+}
+
+fn validate_queue_pairs(num_queue_pairs: usize) -> Result<()> {
+    if num_queue_pairs == 0 || num_queue_pairs > MAX_QUEUE_PAIRS {
+        return Err(Error::InvalidQueuePairs(num_queue_pairs));
+    }
+    Ok(())
+}
+
 fn frame_bytes_from_buf(buf: &[u8]) -> Result<&[u8]> {
     if buf.len() < vnet_hdr_len() {
         Err(Error::VnetHeaderMissing)
@@ -81,39 +125,93 @@ fn init_vnet_hdr(buf: &mut [u8]) {
 }
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct ConfigSpace {
     pub guest_mac: [u8; MAC_ADDR_LEN],
+    pub status: u16,
+    pub max_virtqueue_pairs: u16,
+    pub mtu: u16,
 }
 
 impl Default for ConfigSpace {
     fn default() -> ConfigSpace {
         ConfigSpace {
             guest_mac: [0; MAC_ADDR_LEN],
+            status: 0,
+            max_virtqueue_pairs: 0,
+            mtu: 0,
         }
     }
 }
 
 unsafe impl ByteValued for ConfigSpace {}
 
+/// Traffic statistics of a single net device. Frames exchanged with MMDS are only accounted
+/// on the RX side, like in the aggregate net metrics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct NetStats {
+    /// Number of bytes delivered to the guest, VNET headers included.
+    pub rx_bytes: u64,
+    /// Number of frames delivered to the guest.
+    pub rx_packets: u64,
+    /// Number of guest buffers which couldn't hold an incoming frame.
+    pub rx_fails: u64,
+    /// Number of times receiving was held back by the RX rate limiter.
+    pub rx_rate_limiter_throttled: u64,
+    /// Number of bytes sent to the host, VNET headers included.
+    pub tx_bytes: u64,
+    /// Number of frames sent to the host.
+    pub tx_packets: u64,
+    /// Number of frames sent by the guest which were malformed or couldn't be sent to the host.
+    pub tx_dropped: u64,
+    /// Number of times transmitting was held back by the TX rate limiter.
+    pub tx_rate_limiter_throttled: u64,
+}
+
+/// The host side of a RX/TX queue pair.
+pub(crate) struct QueuePair {
+    pub(crate) backend: Box<dyn NetBackend>,
+
+    pub(crate) rx_deferred_frame: bool,
+
+    rx_bytes_read: usize,
+    rx_frame_buf: [u8; MAX_BUFFER_SIZE],
+
+    // When set, the kernel or a vhost-user backend moves the frames of this pair instead of
+    // the device model.
+    pub(crate) vhost: Option<Box<dyn VhostBackend>>,
+}
+
+impl QueuePair {
+    fn new(backend: Box<dyn NetBackend>) -> Self {
+        QueuePair {
+            backend,
+            rx_deferred_frame: false,
+            rx_bytes_read: 0,
+            rx_frame_buf: [0u8; MAX_BUFFER_SIZE],
+            vhost: None,
+        }
+    }
+}
+
 pub struct Net {
     pub(crate) id: String,
 
-    pub tap: Tap,
+    pub(crate) queue_pairs: Vec<QueuePair>,
+    // The number of queue pairs the host steers traffic towards.
+    pub(crate) active_queue_pairs: usize,
 
     pub(crate) avail_features: u64,
     pub(crate) acked_features: u64,
 
     pub(crate) queues: Vec<Queue>,
     pub(crate) queue_evts: Vec<EventFd>,
+    // Signalled by vhost when it uses buffers of the matching queue in `queues`.
+    pub(crate) vhost_call_evts: Vec<EventFd>,
 
     pub(crate) rx_rate_limiter: RateLimiter,
     pub(crate) tx_rate_limiter: RateLimiter,
 
-    pub(crate) rx_deferred_frame: bool,
-
-    rx_bytes_read: usize,
-    rx_frame_buf: [u8; MAX_BUFFER_SIZE],
-
     tx_iovec: Vec<(GuestAddress, usize)>,
     tx_frame_buf: [u8; MAX_BUFFER_SIZE],
 
@@ -124,25 +222,83 @@ pub struct Net {
 
     pub(crate) device_state: DeviceState,
     pub(crate) activate_evt: EventFd,
+    // Signalled when the device is unplugged, so that it releases its host-side resources.
+    pub(crate) unplug_evt: EventFd,
 
     pub mmds_ns: Option<MmdsNetworkStack>,
 
+    // Mirrors the RX and TX frames to a pcap file when set.
+    pub(crate) capture: Option<PcapWriter>,
+
+    pub(crate) stats: NetStats,
+
+    // Drops the received frames the guest didn't ask for, once it negotiated
+    // VIRTIO_NET_F_CTRL_RX.
+    pub(crate) rx_filter: RxFilter,
+
+    // Drops the frames exchanged with the remote addresses the filter rules don't allow.
+    pub(crate) l3_filter: L3Filter,
+
+    // The network namespace the host-side interfaces were opened in, if not the current one.
+    pub(crate) netns: Option<String>,
+
+    // Whether the device events are processed on a dedicated thread, rather than the VMM one.
+    pub(crate) worker_thread: bool,
+
     #[cfg(test)]
     pub(crate) mocks: Mocks,
 }
 
 impl Net {
-    /// Create a new virtio network device with the given TAP interface.
+    /// Create a new virtio network device with the given TAP interface, with `num_queue_pairs`
+    /// RX/TX queue pairs. A multi-queue TAP interface is used for more than one queue pair.
     pub fn new_with_tap(
         id: String,
         tap_if_name: String,
         guest_mac: Option<&MacAddr>,
         rx_rate_limiter: RateLimiter,
         tx_rate_limiter: RateLimiter,
+        num_queue_pairs: usize,
     ) -> Result<Self> {
-        let tap = Tap::open_named(&tap_if_name).map_err(Error::TapOpen)?;
+        validate_queue_pairs(num_queue_pairs)?;
 
-        // Set offload flags to match the virtio features below.
+        let taps = if num_queue_pairs == 1 {
+            vec![Tap::open_named(&tap_if_name).map_err(Error::TapOpen)?]
+        } else {
+            Tap::open_named_multi_queue(&tap_if_name, num_queue_pairs).map_err(Error::TapOpen)?
+        };
+
+        let mut backends: Vec<Box<dyn NetBackend>> = Vec::with_capacity(taps.len());
+        for tap in taps {
+            backends.push(Self::tap_backend(tap)?);
+        }
+
+        Self::new_with_backends(id, backends, guest_mac, rx_rate_limiter, tx_rate_limiter)
+    }
+
+    /// Create a new virtio network device on top of an already opened TAP device, taking
+    /// ownership of `tap_fd`. The TAP device has to be opened with `IFF_NO_PI` and
+    /// `IFF_VNET_HDR`.
+    pub fn new_with_tap_fd(
+        id: String,
+        tap_fd: RawFd,
+        guest_mac: Option<&MacAddr>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) -> Result<Self> {
+        let tap = Tap::from_fd(tap_fd).map_err(Error::TapOpen)?;
+
+        Self::new_with_backends(
+            id,
+            vec![Self::tap_backend(tap)?],
+            guest_mac,
+            rx_rate_limiter,
+            tx_rate_limiter,
+        )
+    }
+
+    fn tap_backend(tap: Tap) -> Result<Box<dyn NetBackend>> {
+        // Set offload flags to match the virtio features of the device.
         tap.set_offload(
             net_gen::TUN_F_CSUM | net_gen::TUN_F_UFO | net_gen::TUN_F_TSO4 | net_gen::TUN_F_TSO6,
         )
@@ -152,16 +308,139 @@ impl Net {
         tap.set_vnet_hdr_size(vnet_hdr_size)
             .map_err(Error::TapSetVnetHdrSize)?;
 
+        Ok(Box::new(tap))
+    }
+
+    /// Create a new virtio network device connected to the `SOCK_SEQPACKET` unix socket
+    /// listening at `path`.
+    #[cfg(feature = "net-socketpair")]
+    pub fn new_with_socketpair(
+        id: String,
+        path: String,
+        guest_mac: Option<&MacAddr>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) -> Result<Self> {
+        let socket = SocketPair::connect(&path).map_err(Error::SocketPairOpen)?;
+
+        Self::new_with_backends(
+            id,
+            vec![Box::new(socket)],
+            guest_mac,
+            rx_rate_limiter,
+            tx_rate_limiter,
+        )
+    }
+
+    /// Create a new virtio network device on top of a connected `SOCK_SEQPACKET` unix socket
+    /// provided by the test harness, taking ownership of `socket_fd`.
+    #[cfg(feature = "net-socketpair")]
+    pub fn new_with_socketpair_fd(
+        id: String,
+        socket_fd: RawFd,
+        guest_mac: Option<&MacAddr>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) -> Result<Self> {
+        let socket = SocketPair::from_fd(socket_fd).map_err(Error::SocketPairOpen)?;
+
+        Self::new_with_backends(
+            id,
+            vec![Box::new(socket)],
+            guest_mac,
+            rx_rate_limiter,
+            tx_rate_limiter,
+        )
+    }
+
+    /// Create a new virtio network device whose datapath is served by the vhost-user backend
+    /// listening on the unix socket at `path`. The guest memory has to be shared.
+    pub fn new_with_vhost_user(
+        id: String,
+        path: String,
+        guest_mac: Option<&MacAddr>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) -> Result<Self> {
+        let vhost_user = VhostUser::connect(&path, 0).map_err(Error::Vhost)?;
+        let socket = vhost_user.try_clone_socket().map_err(Error::Vhost)?;
+        let backend = VhostUserBackend::new(socket, path);
+
+        let mut net = Self::new_with_backends(
+            id,
+            vec![Box::new(backend)],
+            guest_mac,
+            rx_rate_limiter,
+            tx_rate_limiter,
+        )?;
+        // The offloads are up to the backend, while the configuration space stays with the
+        // device model.
+        net.avail_features &= vhost_user.features()
+            | 1 << VIRTIO_NET_F_MAC
+            | 1 << VIRTIO_NET_F_MTU
+            | 1 << VIRTIO_NET_F_STATUS;
+        net.attach_vhost(vec![Box::new(vhost_user)])?;
+        Ok(net)
+    }
+
+    /// Create a new virtio network device exchanging frames with the first `num_queue_pairs`
+    /// queues of the `if_name` host interface through AF_XDP sockets, which are inserted in
+    /// the `XSKMAP` pinned at `xsks_map_path`.
+    pub fn new_with_xdp(
+        id: String,
+        if_name: String,
+        xsks_map_path: &str,
+        guest_mac: Option<&MacAddr>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+        num_queue_pairs: usize,
+    ) -> Result<Self> {
+        validate_queue_pairs(num_queue_pairs)?;
+
+        let mut backends: Vec<Box<dyn NetBackend>> = Vec::with_capacity(num_queue_pairs);
+        for queue_id in 0..num_queue_pairs {
+            let socket =
+                XdpSocket::new(&if_name, queue_id as u32, xsks_map_path).map_err(Error::XdpOpen)?;
+            backends.push(Box::new(socket));
+        }
+
+        let mut net =
+            Self::new_with_backends(id, backends, guest_mac, rx_rate_limiter, tx_rate_limiter)?;
+        // Frames go to the wire as they are, so the guest has to checksum and segment them.
+        net.set_offloads(NetOffloads {
+            csum: false,
+            tso: false,
+            ufo: false,
+        })?;
+        Ok(net)
+    }
+
+    /// Create a new virtio network device on top of already set up host-side backends, one for
+    /// each RX/TX queue pair.
+    pub fn new_with_backends(
+        id: String,
+        backends: Vec<Box<dyn NetBackend>>,
+        guest_mac: Option<&MacAddr>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) -> Result<Self> {
+        let num_queue_pairs = backends.len();
+        validate_queue_pairs(num_queue_pairs)?;
+
         let mut avail_features = 1 << VIRTIO_NET_F_GUEST_CSUM
             | 1 << VIRTIO_NET_F_CSUM
             | 1 << VIRTIO_NET_F_GUEST_TSO4
             | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
             | 1 << VIRTIO_NET_F_HOST_UFO
+            | 1 << VIRTIO_NET_F_STATUS
             | 1 << VIRTIO_F_VERSION_1
             | 1 << VIRTIO_RING_F_EVENT_IDX;
 
-        let mut config_space = ConfigSpace::default();
+        let mut config_space = ConfigSpace {
+            status: (VIRTIO_NET_S_LINK_UP as u16).to_le(),
+            ..Default::default()
+        };
         if let Some(mac) = guest_mac {
             config_space.guest_mac.copy_from_slice(mac.get_bytes());
             // When this feature isn't available, the driver generates a random MAC address.
@@ -171,30 +450,47 @@ impl Net {
 
         let mut queue_evts = Vec::new();
         let mut queues = Vec::new();
-        for &size in QUEUE_SIZES {
+        for _ in 0..num_queue_pairs {
+            for &size in QUEUE_SIZES {
+                queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+                queues.push(Queue::new(size));
+            }
+        }
+
+        if num_queue_pairs > 1 {
+            // The guest picks the number of queue pairs it uses through the control queue,
+            // which comes after all the RX/TX queues.
+            avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ | 1 << VIRTIO_NET_F_MQ;
+            config_space.max_virtqueue_pairs = (num_queue_pairs as u16).to_le();
             queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
-            queues.push(Queue::new(size));
+            queues.push(Queue::new(QUEUE_SIZE));
         }
 
         Ok(Net {
             id,
-            tap,
+            queue_pairs: backends.into_iter().map(QueuePair::new).collect(),
+            active_queue_pairs: num_queue_pairs,
             avail_features,
             acked_features: 0u64,
             queues,
             queue_evts,
+            vhost_call_evts: Vec::new(),
             rx_rate_limiter,
             tx_rate_limiter,
-            rx_deferred_frame: false,
-            rx_bytes_read: 0,
-            rx_frame_buf: [0u8; MAX_BUFFER_SIZE],
             tx_frame_buf: [0u8; MAX_BUFFER_SIZE],
             tx_iovec: Vec::with_capacity(QUEUE_SIZE as usize),
             irq_trigger: IrqTrigger::new().map_err(Error::EventFd)?,
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            unplug_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
             config_space,
             mmds_ns: None,
+            capture: None,
+            stats: NetStats::default(),
+            rx_filter: RxFilter::default(),
+            l3_filter: L3Filter::default(),
+            netns: None,
+            worker_thread: false,
             guest_mac: guest_mac.copied(),
 
             #[cfg(test)]
@@ -212,9 +508,303 @@ impl Net {
         self.guest_mac.as_ref()
     }
 
+    /// Returns true if the device advertises a MAC address to the guest.
+    pub fn advertises_guest_mac(&self) -> bool {
+        self.avail_features & (1 << VIRTIO_NET_F_MAC) != 0
+    }
+
+    /// Updates the MAC address advertised to the guest. Only devices created with a guest MAC
+    /// address advertise one, otherwise the driver generates its own.
+    pub fn set_guest_mac(&mut self, guest_mac: MacAddr) -> Result<()> {
+        if !self.advertises_guest_mac() {
+            return Err(Error::GuestMacNotAdvertised);
+        }
+        self.config_space
+            .guest_mac
+            .copy_from_slice(guest_mac.get_bytes());
+        self.guest_mac = Some(guest_mac);
+        Ok(())
+    }
+
     /// Provides the host IFACE name of this net device.
     pub fn iface_name(&self) -> String {
-        self.tap.if_name_as_str().to_string()
+        self.queue_pairs[0].backend.if_name()
+    }
+
+    /// Provides the network namespace the host-side interfaces were opened in, if any.
+    pub fn netns(&self) -> Option<&str> {
+        self.netns.as_deref()
+    }
+
+    /// Records the network namespace the host-side interfaces were opened in, so that they
+    /// are opened there again when the device is restored from a snapshot.
+    pub fn set_netns(&mut self, netns: Option<String>) {
+        self.netns = netns;
+    }
+
+    /// Returns true if the device events are processed on a dedicated worker thread.
+    pub fn worker_thread(&self) -> bool {
+        self.worker_thread
+    }
+
+    /// Sets whether the device events are processed on a dedicated worker thread. Only takes
+    /// effect if set before the device is attached to the microVM.
+    pub fn set_worker_thread(&mut self, worker_thread: bool) {
+        self.worker_thread = worker_thread;
+    }
+
+    /// Provides the kind of host-side backend of this net device.
+    pub fn backend_type(&self) -> NetBackendType {
+        self.queue_pairs[0].backend.backend_type()
+    }
+
+    /// Provides the number of RX/TX queue pairs of this net device.
+    pub fn num_queue_pairs(&self) -> usize {
+        self.queue_pairs.len()
+    }
+
+    // Returns the index of the control queue in the queues/queue_evts vectors. Only devices
+    // with more than one queue pair or with RX filtering have a control queue.
+    pub(crate) fn ctrl_queue_index(&self) -> usize {
+        2 * self.queue_pairs.len()
+    }
+
+    pub(crate) fn has_ctrl_queue(&self) -> bool {
+        self.queues.len() > self.ctrl_queue_index()
+    }
+
+    /// Provides the rules filtering the IPv4 traffic of this net device.
+    pub fn filters(&self) -> &[NetFilterRule] {
+        self.l3_filter.rules()
+    }
+
+    /// Replaces the rules filtering the IPv4 traffic of this net device. The frames MMDS
+    /// exchanges with the guest aren't filtered.
+    pub fn set_filters(&mut self, rules: Vec<NetFilterRule>) {
+        self.l3_filter = L3Filter::new(rules);
+    }
+
+    /// Returns true if the guest is offered to filter the frames it receives.
+    pub fn rx_filtering(&self) -> bool {
+        self.avail_features & (1 << VIRTIO_NET_F_CTRL_RX) != 0
+    }
+
+    /// Offers the guest to program which frames it receives through the control queue, so
+    /// that the device drops the other ones instead of delivering them. Must be called before
+    /// the device is activated.
+    pub fn enable_rx_filtering(&mut self) -> Result<()> {
+        if !self.has_ctrl_queue() {
+            self.queue_evts
+                .push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+            self.queues.push(Queue::new(QUEUE_SIZE));
+        }
+        self.avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ | 1 << VIRTIO_NET_F_CTRL_RX;
+        Ok(())
+    }
+
+    // Returns whether the frame of `len` bytes read in the RX buffer of `queue_pair` passes the
+    // filter rules of the device.
+    fn l3_filter_accepts(&self, queue_pair: usize, len: usize) -> bool {
+        match frame_bytes_from_buf(&self.queue_pairs[queue_pair].rx_frame_buf[..len]) {
+            Ok(frame) => self.l3_filter.accepts(NetFilterDirection::Ingress, frame),
+            Err(_) => true,
+        }
+    }
+
+    // Returns whether the frame read in the RX buffer of `queue_pair` passes the filter
+    // programmed by the guest.
+    fn rx_filter_accepts(&self, queue_pair: usize) -> bool {
+        if !self.has_feature(u64::from(VIRTIO_NET_F_CTRL_RX)) {
+            return true;
+        }
+        let pair = &self.queue_pairs[queue_pair];
+        match frame_bytes_from_buf(&pair.rx_frame_buf[..pair.rx_bytes_read]) {
+            Ok(frame) if frame.len() >= MAC_ADDR_LEN => self
+                .rx_filter
+                .accepts(self.guest_mac.as_ref(), &frame[..MAC_ADDR_LEN]),
+            // Frames too short to have a destination are left to the rest of the RX path.
+            _ => true,
+        }
+    }
+
+    /// Asks the device to stop processing events and to close its host-side interfaces. The
+    /// device must have been detached from the guest beforehand.
+    pub fn unplug(&self) -> io::Result<()> {
+        self.unplug_evt.write(1)
+    }
+
+    /// Returns whether the link is reported as up to the guest.
+    pub fn link_up(&self) -> bool {
+        u16::from_le(self.config_space.status) & VIRTIO_NET_S_LINK_UP as u16 != 0
+    }
+
+    /// Reports the link as up or down to the guest, as if a cable was plugged in or pulled.
+    /// The guest driver only notices the change once notified of a config change.
+    pub fn set_link_up(&mut self, link_up: bool) {
+        let mut status = u16::from_le(self.config_space.status);
+        if link_up {
+            status |= VIRTIO_NET_S_LINK_UP as u16;
+        } else {
+            status &= !(VIRTIO_NET_S_LINK_UP as u16);
+        }
+        self.config_space.status = status.to_le();
+    }
+
+    /// Provides the MTU advertised to the guest, if any.
+    pub fn mtu(&self) -> Option<u16> {
+        if self.avail_features & (1 << VIRTIO_NET_F_MTU) != 0 {
+            Some(u16::from_le(self.config_space.mtu))
+        } else {
+            None
+        }
+    }
+
+    /// Sets the MTU of the host-side interface and advertises it to the guest. Must be called
+    /// before the device is activated.
+    pub fn set_mtu(&mut self, mtu: u16) -> Result<()> {
+        if !(MIN_MTU..=MAX_MTU).contains(&mtu) {
+            return Err(Error::InvalidMtu(mtu));
+        }
+        // All the queue pairs share the same host-side interface.
+        self.queue_pairs[0]
+            .backend
+            .set_mtu(mtu)
+            .map_err(Error::SetMtu)?;
+
+        self.config_space.mtu = mtu.to_le();
+        self.avail_features |= 1 << VIRTIO_NET_F_MTU;
+        Ok(())
+    }
+
+    /// Provides the checksum and segmentation offloads offered to the guest.
+    pub fn offloads(&self) -> NetOffloads {
+        NetOffloads {
+            csum: self.avail_features & (1 << VIRTIO_NET_F_CSUM) != 0,
+            tso: self.avail_features & (1 << VIRTIO_NET_F_HOST_TSO4) != 0,
+            ufo: self.avail_features & (1 << VIRTIO_NET_F_HOST_UFO) != 0,
+        }
+    }
+
+    /// Withdraws the offloads disabled in `offloads` from the features offered to the guest
+    /// and from the host-side interface. Disabling checksum offload disables all of them. Must
+    /// be called before the device is activated.
+    pub fn set_offloads(&mut self, offloads: NetOffloads) -> Result<()> {
+        let tso_features = 1 << VIRTIO_NET_F_GUEST_TSO4 | 1 << VIRTIO_NET_F_HOST_TSO4;
+        let ufo_features = 1 << VIRTIO_NET_F_GUEST_UFO | 1 << VIRTIO_NET_F_HOST_UFO;
+        let mut disabled_features = 0u64;
+        if !offloads.csum {
+            disabled_features |=
+                1 << VIRTIO_NET_F_GUEST_CSUM | 1 << VIRTIO_NET_F_CSUM | tso_features | ufo_features;
+        }
+        if !offloads.tso {
+            disabled_features |= tso_features;
+        }
+        if !offloads.ufo {
+            disabled_features |= ufo_features;
+        }
+        self.avail_features &= !disabled_features;
+
+        self.apply_backend_offloads()
+    }
+
+    // Configures the host-side interfaces with the offloads offered to the guest.
+    pub(crate) fn apply_backend_offloads(&self) -> Result<()> {
+        let offloads = self.offloads();
+        for pair in self.queue_pairs.iter() {
+            pair.backend
+                .set_offloads(offloads)
+                .map_err(Error::SetOffloads)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the datapath of this net device is offloaded to vhost-net or to a
+    /// vhost-user backend.
+    pub fn uses_vhost(&self) -> bool {
+        self.queue_pairs[0].vhost.is_some()
+    }
+
+    /// Offloads the datapath of this net device to the vhost-net kernel module, with one
+    /// vhost-net instance for each queue pair. Must be called before the device is activated.
+    pub fn enable_vhost(&mut self) -> Result<()> {
+        if self.backend_type() != NetBackendType::Tap {
+            return Err(Error::VhostUnsupportedBackend);
+        }
+
+        let mut vhosts: Vec<Box<dyn VhostBackend>> = Vec::with_capacity(self.queue_pairs.len());
+        for _ in self.queue_pairs.iter() {
+            vhosts.push(Box::new(VhostNet::new().map_err(Error::Vhost)?));
+        }
+        self.attach_vhost(vhosts)
+    }
+
+    // Hands each queue pair over to the matching vhost instance.
+    fn attach_vhost(&mut self, vhosts: Vec<Box<dyn VhostBackend>>) -> Result<()> {
+        let mut call_evts = Vec::with_capacity(2 * self.queue_pairs.len());
+        for (pair, vhost) in self.queue_pairs.iter_mut().zip(vhosts) {
+            // Without VERSION_1, vhost would expect a shorter vnet header than the backend is
+            // configured with.
+            let missing_features = (1 << VIRTIO_F_VERSION_1) & !vhost.features();
+            if missing_features != 0 {
+                return Err(Error::Vhost(VhostError::UnsupportedFeatures(
+                    missing_features,
+                )));
+            }
+            // The rings are driven by vhost, so it has the last word on the ring features.
+            self.avail_features &= !((1 << VIRTIO_RING_F_EVENT_IDX) & !vhost.features());
+            pair.vhost = Some(vhost);
+
+            for _ in QUEUE_SIZES {
+                call_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+            }
+        }
+        self.vhost_call_evts = call_evts;
+
+        Ok(())
+    }
+
+    // Hands the RX/TX rings over to vhost. Needs to run on the VMM thread, after activation.
+    pub(crate) fn start_vhost(&self) -> result::Result<(), VhostError> {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+
+        for (queue_pair, pair) in self.queue_pairs.iter().enumerate() {
+            let vhost = match pair.vhost.as_ref() {
+                Some(vhost) => vhost,
+                None => continue,
+            };
+            vhost.set_features(self.acked_features & vhost.features())?;
+            vhost.set_mem_table(mem)?;
+            // vhost numbers the rings of a pair the same way the device model does.
+            for (ring, queue_index) in [rx_queue_index(queue_pair), tx_queue_index(queue_pair)]
+                .iter()
+                .enumerate()
+            {
+                vhost.set_vring(
+                    ring,
+                    &self.queues[*queue_index],
+                    mem,
+                    &self.queue_evts[*queue_index],
+                    &self.vhost_call_evts[*queue_index],
+                )?;
+                vhost.start_vring(ring, pair.backend.as_raw_fd())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Relays to the guest the notification vhost sent for the `queue_index` queue.
+    pub fn process_vhost_call_event(&mut self, queue_index: usize) {
+        if let Err(e) = self.vhost_call_evts[queue_index].read() {
+            error!("Failed to get vhost call event: {:?}", e);
+            METRICS.net.event_fails.inc();
+            return;
+        }
+        if let Err(e) = self.irq_trigger.trigger_irq(IrqType::Vring) {
+            error!("Failed to signal used queue: {:?}", e);
+            METRICS.net.event_fails.inc();
+        }
     }
 
     /// Provides the MmdsNetworkStack of this net device.
@@ -222,14 +812,20 @@ impl Net {
         self.mmds_ns.as_ref()
     }
 
-    /// Configures the `MmdsNetworkStack` to allow device to forward MMDS requests.
-    /// If the device already supports MMDS, updates the IPv4 address.
-    pub fn configure_mmds_network_stack(&mut self, ipv4_addr: Ipv4Addr, mmds: Arc<Mutex<Mmds>>) {
-        if let Some(mmds_ns) = self.mmds_ns.as_mut() {
-            mmds_ns.set_ipv4_addr(ipv4_addr);
-        } else {
-            self.mmds_ns = Some(MmdsNetworkStack::new_with_defaults(Some(ipv4_addr), mmds))
-        }
+    /// Configures the `MmdsNetworkStack` to allow device to forward MMDS requests, also over
+    /// IPv6 when `ipv6_addr` is provided.
+    /// If the device already supports MMDS, updates the IP addresses.
+    pub fn configure_mmds_network_stack(
+        &mut self,
+        ipv4_addr: Ipv4Addr,
+        ipv6_addr: Option<Ipv6Addr>,
+        mmds: Arc<Mutex<Mmds>>,
+    ) {
+        let mmds_ns = self
+            .mmds_ns
+            .get_or_insert_with(|| MmdsNetworkStack::new_with_defaults(Some(ipv4_addr), mmds));
+        mmds_ns.set_ipv4_addr(ipv4_addr);
+        mmds_ns.set_ipv6_addr(ipv6_addr);
     }
 
     /// Disables the `MmdsNetworkStack` to prevent device to forward MMDS requests.
@@ -237,6 +833,42 @@ impl Net {
         self.mmds_ns = None
     }
 
+    /// Starts mirroring the RX and TX frames of the device to `capture`, or stops when `None`.
+    /// The frames of the vhost datapath don't go through Firecracker and can't be captured.
+    pub fn set_capture(&mut self, capture: Option<PcapWriter>) -> Result<()> {
+        if capture.is_some() && self.uses_vhost() {
+            return Err(Error::VhostCapture);
+        }
+        self.capture = capture;
+        Ok(())
+    }
+
+    /// Returns true if the frames of the device are being captured.
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    // Mirrors a frame, preceded by its VNET header, to the capture file if there's one. The
+    // capture stops on the first failure.
+    fn capture_frame(capture: &mut Option<PcapWriter>, frame_buf: &[u8]) {
+        if let (Some(writer), Ok(frame)) = (capture.as_mut(), frame_bytes_from_buf(frame_buf)) {
+            if let Err(e) = writer.write_frame(frame) {
+                error!(
+                    "Failed to capture frame to {:?}, stopping capture: {:?}",
+                    writer.path(),
+                    e
+                );
+                *capture = None;
+            }
+        }
+    }
+
+    /// Provides the traffic statistics of this net device. The frames of the vhost datapath
+    /// don't go through Firecracker and aren't accounted.
+    pub fn stats(&self) -> NetStats {
+        self.stats
+    }
+
     /// Provides a reference to the configured RX rate limiter.
     pub fn rx_rate_limiter(&self) -> &RateLimiter {
         &self.rx_rate_limiter
@@ -247,15 +879,11 @@ impl Net {
         &self.tx_rate_limiter
     }
 
-    fn signal_used_queue(&mut self, queue_type: NetQueue) -> result::Result<(), DeviceError> {
+    fn signal_used_queue(&mut self, queue_index: usize) -> result::Result<(), DeviceError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
-        let queue = match queue_type {
-            NetQueue::Rx => &mut self.queues[RX_INDEX],
-            NetQueue::Tx => &mut self.queues[TX_INDEX],
-        };
-
+        let queue = &mut self.queues[queue_index];
         if queue.prepare_kick(mem) {
             self.irq_trigger.trigger_irq(IrqType::Vring).map_err(|e| {
                 METRICS.net.event_fails.inc();
@@ -269,35 +897,49 @@ impl Net {
     // Attempts to copy a single frame into the guest if there is enough
     // rate limiting budget.
     // Returns true on successful frame delivery.
-    fn rate_limited_rx_single_frame(&mut self) -> bool {
+    fn rate_limited_rx_single_frame(&mut self, queue_pair: usize) -> bool {
         // If limiter.consume() fails it means there is no more TokenType::Ops
         // budget and rate limiting is in effect.
         if !self.rx_rate_limiter.consume(1, TokenType::Ops) {
             METRICS.net.rx_rate_limiter_throttled.inc();
+            self.stats.rx_rate_limiter_throttled += 1;
+            return false;
+        }
+        // If limiter.consume() fails it means there is no more TokenType::Packets
+        // budget and rate limiting is in effect.
+        if !self.rx_rate_limiter.consume(1, TokenType::Packets) {
+            // revert the OPS consume()
+            self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
+            METRICS.net.rx_rate_limiter_throttled.inc();
+            self.stats.rx_rate_limiter_throttled += 1;
             return false;
         }
         // If limiter.consume() fails it means there is no more TokenType::Bytes
         // budget and rate limiting is in effect.
+        let rx_bytes_read = self.queue_pairs[queue_pair].rx_bytes_read;
         if !self
             .rx_rate_limiter
-            .consume(self.rx_bytes_read as u64, TokenType::Bytes)
+            .consume(rx_bytes_read as u64, TokenType::Bytes)
         {
-            // revert the OPS consume()
+            // revert the OPS and PACKETS consume()
             self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
+            self.rx_rate_limiter.manual_replenish(1, TokenType::Packets);
             METRICS.net.rx_rate_limiter_throttled.inc();
+            self.stats.rx_rate_limiter_throttled += 1;
             return false;
         }
 
         // Attempt frame delivery.
-        let success = self.write_frame_to_guest();
+        let success = self.write_frame_to_guest(queue_pair);
 
         // Undo the tokens consumption if guest delivery failed.
         if !success {
-            // revert the OPS consume()
+            // revert the OPS and PACKETS consume()
             self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
+            self.rx_rate_limiter.manual_replenish(1, TokenType::Packets);
             // revert the BYTES consume()
             self.rx_rate_limiter
-                .manual_replenish(self.rx_bytes_read as u64, TokenType::Bytes);
+                .manual_replenish(rx_bytes_read as u64, TokenType::Bytes);
         }
         success
     }
@@ -350,12 +992,16 @@ impl Net {
         Err(FrontendError::DescriptorChainTooSmall)
     }
 
-    // Copies a single frame from `self.rx_frame_buf` into the guest.
-    fn do_write_frame_to_guest(&mut self) -> std::result::Result<(), FrontendError> {
+    // Copies a single frame from the `rx_frame_buf` of `queue_pair` into the guest.
+    fn do_write_frame_to_guest(
+        &mut self,
+        queue_pair: usize,
+    ) -> std::result::Result<(), FrontendError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
-        let queue = &mut self.queues[RX_INDEX];
+        let pair = &self.queue_pairs[queue_pair];
+        let queue = &mut self.queues[rx_queue_index(queue_pair)];
         let head_descriptor = queue.pop_or_enable_notification(mem).ok_or_else(|| {
             METRICS.net.no_rx_avail_buffer.inc();
             FrontendError::EmptyQueue
@@ -364,15 +1010,18 @@ impl Net {
 
         let result = Self::write_to_descriptor_chain(
             mem,
-            &self.rx_frame_buf[..self.rx_bytes_read],
+            &pair.rx_frame_buf[..pair.rx_bytes_read],
             head_descriptor,
         );
         // Mark the descriptor chain as used. If an error occurred, skip the descriptor chain.
         let used_len = if result.is_err() {
             METRICS.net.rx_fails.inc();
+            self.stats.rx_fails += 1;
             0
         } else {
-            self.rx_bytes_read as u32
+            self.stats.rx_bytes += pair.rx_bytes_read as u64;
+            self.stats.rx_packets += 1;
+            pair.rx_bytes_read as u32
         };
         queue.add_used(mem, head_index, used_len).map_err(|e| {
             error!("Failed to add available descriptor {}: {}", head_index, e);
@@ -382,12 +1031,12 @@ impl Net {
         result
     }
 
-    // Copies a single frame from `self.rx_frame_buf` into the guest. In case of an error retries
-    // the operation if possible. Returns true if the operation was successfull.
-    fn write_frame_to_guest(&mut self) -> bool {
-        let max_iterations = self.queues[RX_INDEX].actual_size();
+    // Copies a single frame from the `rx_frame_buf` of `queue_pair` into the guest. In case of an
+    // error retries the operation if possible. Returns true if the operation was successfull.
+    fn write_frame_to_guest(&mut self, queue_pair: usize) -> bool {
+        let max_iterations = self.queues[rx_queue_index(queue_pair)].actual_size();
         for _ in 0..max_iterations {
-            match self.do_write_frame_to_guest() {
+            match self.do_write_frame_to_guest(queue_pair) {
                 Ok(()) => return true,
                 Err(FrontendError::EmptyQueue) | Err(FrontendError::AddUsed) => {
                     return false;
@@ -402,7 +1051,8 @@ impl Net {
         false
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP
+    // unless the filter rules drop it.
     //
     // `frame_buf` should contain the frame bytes in a slice of exact length.
     // Returns whether MMDS consumed the frame.
@@ -410,13 +1060,16 @@ impl Net {
         mmds_ns: Option<&mut MmdsNetworkStack>,
         rate_limiter: &mut RateLimiter,
         frame_buf: &[u8],
-        tap: &mut Tap,
+        l3_filter: &L3Filter,
+        tap: &mut dyn NetBackend,
         guest_mac: Option<MacAddr>,
+        stats: &mut NetStats,
     ) -> Result<bool> {
-        let checked_frame = |frame_buf| {
+        let mut checked_frame = |frame_buf| {
             frame_bytes_from_buf(frame_buf).map_err(|e| {
                 error!("VNET header missing in the TX frame.");
                 METRICS.net.tx_malformed_frames.inc();
+                stats.tx_dropped += 1;
                 e
             })
         };
@@ -427,6 +1080,7 @@ impl Net {
                 // MMDS frames are not accounted by the rate limiter.
                 rate_limiter.manual_replenish(frame_buf.len() as u64, TokenType::Bytes);
                 rate_limiter.manual_replenish(1, TokenType::Ops);
+                rate_limiter.manual_replenish(1, TokenType::Packets);
 
                 // MMDS consumed the frame.
                 return Ok(true);
@@ -435,6 +1089,12 @@ impl Net {
 
         // This frame goes to the TAP.
 
+        if !l3_filter.accepts(NetFilterDirection::Egress, checked_frame(frame_buf)?) {
+            METRICS.net.tx_l3_filtered_frames.inc();
+            stats.tx_dropped += 1;
+            return Ok(false);
+        }
+
         // Check for guest MAC spoofing.
         if let Some(mac) = guest_mac {
             let _ = EthernetFrame::from_bytes(checked_frame(frame_buf)?).map(|eth_frame| {
@@ -444,46 +1104,63 @@ impl Net {
             });
         }
 
-        match tap.write(frame_buf) {
+        match tap.write_frame(frame_buf) {
             Ok(_) => {
                 METRICS.net.tx_bytes_count.add(frame_buf.len());
                 METRICS.net.tx_packets_count.inc();
                 METRICS.net.tx_count.inc();
+                stats.tx_bytes += frame_buf.len() as u64;
+                stats.tx_packets += 1;
             }
             Err(e) => {
                 error!("Failed to write to tap: {:?}", e);
                 METRICS.net.tap_write_fails.inc();
+                stats.tx_dropped += 1;
             }
         };
         Ok(false)
     }
 
     // We currently prioritize packets from the MMDS over regular network packets.
-    fn read_from_mmds_or_tap(&mut self) -> Result<usize> {
+    fn read_from_mmds_or_tap(&mut self, queue_pair: usize) -> Result<usize> {
         if let Some(ns) = self.mmds_ns.as_mut() {
-            if let Some(len) =
-                ns.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
-            {
+            let rx_frame_buf = &mut self.queue_pairs[queue_pair].rx_frame_buf[..];
+            if let Some(len) = ns.write_next_frame(frame_bytes_from_buf_mut(rx_frame_buf)?) {
                 let len = len.get();
                 METRICS.mmds.tx_frames.inc();
                 METRICS.mmds.tx_bytes.add(len);
-                init_vnet_hdr(&mut self.rx_frame_buf);
+                init_vnet_hdr(rx_frame_buf);
                 return Ok(vnet_hdr_len() + len);
             }
         }
 
-        self.read_tap().map_err(Error::IO)
+        // The frames the filter rules don't allow are dropped and the next one is read.
+        loop {
+            let len = self.read_tap(queue_pair).map_err(Error::IO)?;
+            if self.l3_filter_accepts(queue_pair, len) {
+                return Ok(len);
+            }
+            METRICS.net.rx_l3_filtered_frames.inc();
+        }
     }
 
-    fn process_rx(&mut self) -> result::Result<(), DeviceError> {
+    fn process_rx(&mut self, queue_pair: usize) -> result::Result<(), DeviceError> {
         // Read as many frames as possible.
         loop {
-            match self.read_from_mmds_or_tap() {
+            match self.read_from_mmds_or_tap(queue_pair) {
                 Ok(count) => {
-                    self.rx_bytes_read = count;
+                    self.queue_pairs[queue_pair].rx_bytes_read = count;
                     METRICS.net.rx_count.inc();
-                    if !self.rate_limited_rx_single_frame() {
-                        self.rx_deferred_frame = true;
+                    if !self.rx_filter_accepts(queue_pair) {
+                        METRICS.net.rx_filtered_frames.inc();
+                        continue;
+                    }
+                    Self::capture_frame(
+                        &mut self.capture,
+                        &self.queue_pairs[queue_pair].rx_frame_buf[..count],
+                    );
+                    if !self.rate_limited_rx_single_frame(queue_pair) {
+                        self.queue_pairs[queue_pair].rx_deferred_frame = true;
                         break;
                     }
                 }
@@ -508,30 +1185,30 @@ impl Net {
 
         // At this point we processed as many Rx frames as possible.
         // We have to wake the guest if at least one descriptor chain has been used.
-        self.signal_used_queue(NetQueue::Rx)
+        self.signal_used_queue(rx_queue_index(queue_pair))
     }
 
     // Process the deferred frame first, then continue reading from tap.
-    fn handle_deferred_frame(&mut self) -> result::Result<(), DeviceError> {
-        if self.rate_limited_rx_single_frame() {
-            self.rx_deferred_frame = false;
+    fn handle_deferred_frame(&mut self, queue_pair: usize) -> result::Result<(), DeviceError> {
+        if self.rate_limited_rx_single_frame(queue_pair) {
+            self.queue_pairs[queue_pair].rx_deferred_frame = false;
             // process_rx() was interrupted possibly before consuming all
             // packets in the tap; try continuing now.
-            return self.process_rx();
+            return self.process_rx(queue_pair);
         }
 
-        self.signal_used_queue(NetQueue::Rx)
+        self.signal_used_queue(rx_queue_index(queue_pair))
     }
 
-    fn resume_rx(&mut self) -> result::Result<(), DeviceError> {
-        if self.rx_deferred_frame {
-            self.handle_deferred_frame()
+    fn resume_rx(&mut self, queue_pair: usize) -> result::Result<(), DeviceError> {
+        if self.queue_pairs[queue_pair].rx_deferred_frame {
+            self.handle_deferred_frame(queue_pair)
         } else {
             Ok(())
         }
     }
 
-    fn process_tx(&mut self) -> result::Result<(), DeviceError> {
+    fn process_tx(&mut self, queue_pair: usize) -> result::Result<(), DeviceError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
@@ -541,7 +1218,7 @@ impl Net {
         // with the MMDS network stack.
         let mut process_rx_for_mmds = false;
         let mut used_any = false;
-        let tx_queue = &mut self.queues[TX_INDEX];
+        let tx_queue = &mut self.queues[tx_queue_index(queue_pair)];
 
         while let Some(head) = tx_queue.pop_or_enable_notification(mem) {
             // If limiter.consume() fails it means there is no more TokenType::Ops
@@ -551,6 +1228,17 @@ impl Net {
                 // avail ring, for later processing.
                 tx_queue.undo_pop();
                 METRICS.net.tx_rate_limiter_throttled.inc();
+                self.stats.tx_rate_limiter_throttled += 1;
+                break;
+            }
+            // If limiter.consume() fails it means there is no more TokenType::Packets
+            // budget and rate limiting is in effect.
+            if !self.tx_rate_limiter.consume(1, TokenType::Packets) {
+                // revert the OPS consume()
+                self.tx_rate_limiter.manual_replenish(1, TokenType::Ops);
+                tx_queue.undo_pop();
+                METRICS.net.tx_rate_limiter_throttled.inc();
+                self.stats.tx_rate_limiter_throttled += 1;
                 break;
             }
 
@@ -575,12 +1263,14 @@ impl Net {
                 .tx_rate_limiter
                 .consume(read_count as u64, TokenType::Bytes)
             {
-                // revert the OPS consume()
+                // revert the OPS and PACKETS consume()
                 self.tx_rate_limiter.manual_replenish(1, TokenType::Ops);
+                self.tx_rate_limiter.manual_replenish(1, TokenType::Packets);
                 // Stop processing the queue and return this descriptor chain to the
                 // avail ring, for later processing.
                 tx_queue.undo_pop();
                 METRICS.net.tx_rate_limiter_throttled.inc();
+                self.stats.tx_rate_limiter_throttled += 1;
                 break;
             }
 
@@ -613,15 +1303,18 @@ impl Net {
                 }
             }
 
+            Self::capture_frame(&mut self.capture, &self.tx_frame_buf[..read_count]);
             let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
                 &mut self.tx_rate_limiter,
                 &self.tx_frame_buf[..read_count],
-                &mut self.tap,
+                &self.l3_filter,
+                self.queue_pairs[queue_pair].backend.as_mut(),
                 self.guest_mac,
+                &mut self.stats,
             )
             .unwrap_or(false);
-            if frame_consumed_by_mmds && !self.rx_deferred_frame {
+            if frame_consumed_by_mmds && !self.queue_pairs[queue_pair].rx_deferred_frame {
                 // MMDS consumed this frame/request, let's also try to process the response.
                 process_rx_for_mmds = true;
             }
@@ -636,49 +1329,194 @@ impl Net {
             METRICS.net.no_tx_avail_buffer.inc();
         }
 
-        self.signal_used_queue(NetQueue::Tx)?;
+        self.signal_used_queue(tx_queue_index(queue_pair))?;
 
         // An incoming frame for the MMDS may trigger the transmission of a new message.
         if process_rx_for_mmds {
-            self.process_rx()
+            self.process_rx(queue_pair)
         } else {
             Ok(())
         }
     }
 
+    // Reads a command out of a control queue descriptor chain. Returns the command, unless it
+    // couldn't be read, along with the address where its acknowledgement should be written.
+    fn read_ctrl_command(
+        mem: &GuestMemoryMmap,
+        head: DescriptorChain,
+    ) -> (Option<Vec<u8>>, Option<GuestAddress>) {
+        let mut command = Some(Vec::new());
+        let mut next_desc = Some(head);
+
+        while let Some(desc) = next_desc {
+            if desc.is_write_only() {
+                // The acknowledgement byte follows the device-readable part of the chain.
+                let ack_addr = if desc.len > 0 { Some(desc.addr) } else { None };
+                return (command, ack_addr);
+            }
+
+            command = command.and_then(|mut command| {
+                let start = command.len();
+                let end = start + desc.len as usize;
+                if end > CTRL_CMD_MAX_LEN {
+                    return None;
+                }
+                command.resize(end, 0);
+                mem.read_slice(&mut command[start..end], desc.addr)
+                    .ok()
+                    .map(|_| command)
+            });
+            next_desc = desc.next_descriptor();
+        }
+
+        (command, None)
+    }
+
+    // Carries out a control queue command, returning its acknowledgement.
+    fn handle_ctrl_command(&mut self, command: &[u8]) -> u8 {
+        match *command {
+            [VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, lo, hi]
+                if self.has_feature(u64::from(VIRTIO_NET_F_MQ)) =>
+            {
+                let num_pairs = usize::from(u16::from_le_bytes([lo, hi]));
+                if num_pairs == 0 || num_pairs > self.queue_pairs.len() {
+                    warn!(
+                        "Net: Invalid number of queue pairs requested: {}",
+                        num_pairs
+                    );
+                    return VIRTIO_NET_ERR;
+                }
+                self.set_active_queue_pairs(num_pairs);
+                VIRTIO_NET_OK
+            }
+            [VIRTIO_NET_CTRL_RX, mode, on] if self.has_feature(u64::from(VIRTIO_NET_F_CTRL_RX)) => {
+                if !self.rx_filter.set_rx_mode(mode, on != 0) {
+                    warn!("Net: Unsupported RX mode: {}", mode);
+                    return VIRTIO_NET_ERR;
+                }
+                VIRTIO_NET_OK
+            }
+            [VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET, ref tables @ ..]
+                if self.has_feature(u64::from(VIRTIO_NET_F_CTRL_RX)) =>
+            {
+                if !self.rx_filter.set_mac_tables(tables) {
+                    warn!("Net: Malformed MAC tables: {:?}", tables);
+                    return VIRTIO_NET_ERR;
+                }
+                VIRTIO_NET_OK
+            }
+            _ => {
+                warn!("Net: Unsupported control queue command: {:?}", command);
+                VIRTIO_NET_ERR
+            }
+        }
+    }
+
+    fn process_ctrl_queue(&mut self) -> result::Result<(), DeviceError> {
+        let ctrl_queue_index = self.ctrl_queue_index();
+        let mut commands = Vec::new();
+        {
+            // This is safe since we checked in the event handler that the device is activated.
+            let mem = self.device_state.mem().unwrap();
+            let ctrl_queue = &mut self.queues[ctrl_queue_index];
+            while let Some(head) = ctrl_queue.pop_or_enable_notification(mem) {
+                commands.push((head.index, Self::read_ctrl_command(mem, head)));
+            }
+        }
+
+        for (head_index, (command, ack_addr)) in commands {
+            let ack = match command {
+                Some(command) => self.handle_ctrl_command(&command),
+                None => {
+                    error!("Net: Failed to read control queue command.");
+                    METRICS.net.event_fails.inc();
+                    VIRTIO_NET_ERR
+                }
+            };
+
+            // This is safe since we checked in the event handler that the device is activated.
+            let mem = self.device_state.mem().unwrap();
+            let used_len = match ack_addr.map(|addr| mem.write_obj(ack, addr)) {
+                Some(Ok(())) => 1,
+                _ => {
+                    error!("Net: Failed to acknowledge control queue command.");
+                    METRICS.net.event_fails.inc();
+                    0
+                }
+            };
+            self.queues[ctrl_queue_index]
+                .add_used(mem, head_index, used_len)
+                .map_err(DeviceError::QueueError)?;
+        }
+
+        self.signal_used_queue(ctrl_queue_index)
+    }
+
+    // Makes the host steer traffic towards the first `num_pairs` queue pairs only.
+    pub(crate) fn set_active_queue_pairs(&mut self, num_pairs: usize) {
+        for (index, pair) in self.queue_pairs.iter_mut().enumerate() {
+            let enabled = index < num_pairs;
+            if enabled == (index < self.active_queue_pairs) {
+                continue;
+            }
+            if let Err(e) = pair.backend.set_enabled(enabled) {
+                error!(
+                    "Net: Failed to set queue pair {} enabled state to {}: {:?}",
+                    index, enabled, e
+                );
+                METRICS.net.event_fails.inc();
+            }
+        }
+        self.active_queue_pairs = num_pairs;
+    }
+
     /// Updates the parameters for the rate limiters
     pub fn patch_rate_limiters(
         &mut self,
         rx_bytes: BucketUpdate,
         rx_ops: BucketUpdate,
+        rx_pps: BucketUpdate,
         tx_bytes: BucketUpdate,
         tx_ops: BucketUpdate,
+        tx_pps: BucketUpdate,
     ) {
         self.rx_rate_limiter.update_buckets(rx_bytes, rx_ops);
+        self.rx_rate_limiter.update_pps(rx_pps);
         self.tx_rate_limiter.update_buckets(tx_bytes, tx_ops);
+        self.tx_rate_limiter.update_pps(tx_pps);
+    }
+
+    /// Makes both rate limiters members of `group`, so that the RX and TX traffic of this
+    /// device is also limited by the buckets shared with the other members.
+    pub fn set_rate_limiter_group(&mut self, group: Option<RateLimiterGroup>) {
+        self.rx_rate_limiter.set_group(group.clone());
+        self.tx_rate_limiter.set_group(group);
     }
 
     #[cfg(not(test))]
-    fn read_tap(&mut self) -> io::Result<usize> {
-        self.tap.read(&mut self.rx_frame_buf)
+    fn read_tap(&mut self, queue_pair: usize) -> io::Result<usize> {
+        let pair = &mut self.queue_pairs[queue_pair];
+        pair.backend.read_frame(&mut pair.rx_frame_buf)
     }
 
-    pub fn process_rx_queue_event(&mut self) {
+    pub fn process_rx_queue_event(&mut self, queue_pair: usize) {
         METRICS.net.rx_queue_event_count.inc();
 
-        if let Err(e) = self.queue_evts[RX_INDEX].read() {
+        if let Err(e) = self.queue_evts[rx_queue_index(queue_pair)].read() {
             // rate limiters present but with _very high_ allowed rate
             error!("Failed to get rx queue event: {:?}", e);
             METRICS.net.event_fails.inc();
         } else if self.rx_rate_limiter.is_blocked() {
             METRICS.net.rx_rate_limiter_throttled.inc();
+            self.stats.rx_rate_limiter_throttled += 1;
         } else {
             // If the limiter is not blocked, resume the receiving of bytes.
-            self.resume_rx().unwrap_or_else(report_net_event_fail);
+            self.resume_rx(queue_pair)
+                .unwrap_or_else(report_net_event_fail);
         }
     }
 
-    pub fn process_tap_rx_event(&mut self) {
+    pub fn process_tap_rx_event(&mut self, queue_pair: usize) {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
         METRICS.net.rx_tap_event_count.inc();
@@ -687,7 +1525,8 @@ impl Net {
         // don't process any more incoming. Otherwise start processing a frame. In the
         // process the deferred_frame flag will be set in order to avoid freezing the
         // RX queue.
-        if self.queues[RX_INDEX].is_empty(mem) && self.rx_deferred_frame {
+        let rx_deferred_frame = self.queue_pairs[queue_pair].rx_deferred_frame;
+        if self.queues[rx_queue_index(queue_pair)].is_empty(mem) && rx_deferred_frame {
             METRICS.net.no_rx_avail_buffer.inc();
             return;
         }
@@ -695,31 +1534,45 @@ impl Net {
         // While limiter is blocked, don't process any more incoming.
         if self.rx_rate_limiter.is_blocked() {
             METRICS.net.rx_rate_limiter_throttled.inc();
+            self.stats.rx_rate_limiter_throttled += 1;
             return;
         }
 
-        if self.rx_deferred_frame
+        if rx_deferred_frame
         // Process a deferred frame first if available. Don't read from tap again
         // until we manage to receive this deferred frame.
         {
-            self.handle_deferred_frame()
+            self.handle_deferred_frame(queue_pair)
                 .unwrap_or_else(report_net_event_fail);
         } else {
-            self.process_rx().unwrap_or_else(report_net_event_fail);
+            self.process_rx(queue_pair)
+                .unwrap_or_else(report_net_event_fail);
         }
     }
 
-    pub fn process_tx_queue_event(&mut self) {
+    pub fn process_tx_queue_event(&mut self, queue_pair: usize) {
         METRICS.net.tx_queue_event_count.inc();
-        if let Err(e) = self.queue_evts[TX_INDEX].read() {
+        if let Err(e) = self.queue_evts[tx_queue_index(queue_pair)].read() {
             error!("Failed to get tx queue event: {:?}", e);
             METRICS.net.event_fails.inc();
         } else if !self.tx_rate_limiter.is_blocked()
         // If the limiter is not blocked, continue transmitting bytes.
         {
-            self.process_tx().unwrap_or_else(report_net_event_fail);
+            self.process_tx(queue_pair)
+                .unwrap_or_else(report_net_event_fail);
         } else {
             METRICS.net.tx_rate_limiter_throttled.inc();
+            self.stats.tx_rate_limiter_throttled += 1;
+        }
+    }
+
+    pub fn process_ctrl_queue_event(&mut self) {
+        if let Err(e) = self.queue_evts[self.ctrl_queue_index()].read() {
+            error!("Failed to get ctrl queue event: {:?}", e);
+            METRICS.net.event_fails.inc();
+        } else {
+            self.process_ctrl_queue()
+                .unwrap_or_else(report_net_event_fail);
         }
     }
 
@@ -730,8 +1583,11 @@ impl Net {
 
         match self.rx_rate_limiter.event_handler() {
             Ok(_) => {
-                // There might be enough budget now to receive the frame.
-                self.resume_rx().unwrap_or_else(report_net_event_fail);
+                // There might be enough budget now to receive the frames.
+                for queue_pair in 0..self.queue_pairs.len() {
+                    self.resume_rx(queue_pair)
+                        .unwrap_or_else(report_net_event_fail);
+                }
             }
             Err(e) => {
                 error!("Failed to get rx rate-limiter event: {:?}", e);
@@ -746,8 +1602,11 @@ impl Net {
         // and restart processing the queue.
         match self.tx_rate_limiter.event_handler() {
             Ok(_) => {
-                // There might be enough budget now to send the frame.
-                self.process_tx().unwrap_or_else(report_net_event_fail);
+                // There might be enough budget now to send the frames.
+                for queue_pair in 0..self.queue_pairs.len() {
+                    self.process_tx(queue_pair)
+                        .unwrap_or_else(report_net_event_fail);
+                }
             }
             Err(e) => {
                 error!("Failed to get tx rate-limiter event: {:?}", e);
@@ -758,8 +1617,16 @@ impl Net {
 
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
-        let _ = self.resume_rx();
-        let _ = self.process_tx();
+        for queue_pair in 0..self.queue_pairs.len() {
+            if self.queue_pairs[queue_pair].vhost.is_some() {
+                continue;
+            }
+            let _ = self.resume_rx(queue_pair);
+            let _ = self.process_tx(queue_pair);
+        }
+        if self.has_ctrl_queue() {
+            let _ = self.process_ctrl_queue();
+        }
     }
 }
 
@@ -801,7 +1668,16 @@ impl VirtioDevice for Net {
     }
 
     fn read_config(&self, offset: u64, mut data: &mut [u8]) {
-        let config_space_bytes = self.config_space.as_slice();
+        // The fields following the MAC address are only part of the config space of devices
+        // offering the features which define them.
+        let config_space_bytes = if self.avail_features
+            & (1 << VIRTIO_NET_F_STATUS | 1 << VIRTIO_NET_F_MQ | 1 << VIRTIO_NET_F_MTU)
+            != 0
+        {
+            self.config_space.as_slice()
+        } else {
+            &self.config_space.as_slice()[..MAC_ADDR_LEN]
+        };
         let config_len = config_space_bytes.len() as u64;
         if offset >= config_len {
             error!("Failed to read config space");
@@ -819,7 +1695,8 @@ impl VirtioDevice for Net {
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
         let data_len = data.len() as u64;
-        let config_space_bytes = self.config_space.as_mut_slice();
+        // Only the MAC address is writable.
+        let config_space_bytes = &mut self.config_space.as_mut_slice()[..MAC_ADDR_LEN];
         let config_len = config_space_bytes.len() as u64;
         if offset + data_len > config_len {
             error!("Failed to write config space");
@@ -869,7 +1746,7 @@ pub mod tests {
     use virtio_gen::virtio_net::{
         virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM,
         VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4,
-        VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
+        VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_F_STATUS,
     };
     use vm_memory::{Address, GuestMemory};
 
@@ -880,26 +1757,29 @@ pub mod tests {
     };
     use crate::virtio::net::test_utils::test::TestHelper;
     use crate::virtio::net::test_utils::{
-        default_net, if_index, inject_tap_tx_frame, set_mac, NetEvent, NetQueue, ReadTapMock,
-        TapTrafficSimulator,
+        default_guest_memory, default_net, if_index, inject_tap_tx_frame, set_mac, NetEvent,
+        NetQueue, ReadTapMock, TapTrafficSimulator,
     };
-    use crate::virtio::net::QUEUE_SIZES;
+    use crate::virtio::net::{Ipv4Cidr, NetFilterAction, QUEUE_SIZES};
+    use crate::virtio::test_utils::VirtQueue;
     use crate::virtio::{
-        Net, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET, VIRTQ_DESC_F_WRITE,
+        Net, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET, VIRTQ_DESC_F_NEXT,
+        VIRTQ_DESC_F_WRITE,
     };
 
     impl Net {
-        pub fn read_tap(&mut self) -> io::Result<usize> {
+        pub fn read_tap(&mut self, queue_pair: usize) -> io::Result<usize> {
+            let pair = &mut self.queue_pairs[queue_pair];
             match &self.mocks.read_tap {
                 ReadTapMock::MockFrame(frame) => {
-                    self.rx_frame_buf[..frame.len()].copy_from_slice(&frame);
+                    pair.rx_frame_buf[..frame.len()].copy_from_slice(&frame);
                     Ok(frame.len())
                 }
                 ReadTapMock::Failure => Err(io::Error::new(
                     io::ErrorKind::Other,
                     "Read tap synthetically failed.",
                 )),
-                ReadTapMock::TapFrame => self.tap.read(&mut self.rx_frame_buf),
+                ReadTapMock::TapFrame => pair.backend.read_frame(&mut pair.rx_frame_buf),
             }
         }
     }
@@ -955,6 +1835,7 @@ pub mod tests {
             | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
             | 1 << VIRTIO_NET_F_HOST_UFO
+            | 1 << VIRTIO_NET_F_STATUS
             | 1 << VIRTIO_F_VERSION_1
             | 1 << VIRTIO_RING_F_EVENT_IDX;
 
@@ -984,7 +1865,7 @@ pub mod tests {
 
         // Invalid read.
         config_mac = [0u8; MAC_ADDR_LEN];
-        net.read_config(MAC_ADDR_LEN as u64 + 1, &mut config_mac);
+        net.read_config(mem::size_of::<ConfigSpace>() as u64, &mut config_mac);
         assert_eq!(config_mac, [0u8, 0u8, 0u8, 0u8, 0u8, 0u8]);
     }
 
@@ -1020,6 +1901,352 @@ pub mod tests {
         assert_eq!(new_config, new_config_read);
     }
 
+    #[test]
+    fn test_mtu() {
+        let mut net = default_net();
+        assert_eq!(net.mtu(), None);
+        assert!(!net.has_feature(u64::from(VIRTIO_NET_F_MTU)));
+
+        assert!(matches!(
+            net.set_mtu(MIN_MTU - 1),
+            Err(Error::InvalidMtu(mtu)) if mtu == MIN_MTU - 1
+        ));
+        assert_eq!(net.mtu(), None);
+
+        net.set_mtu(9000).unwrap();
+        assert_eq!(net.mtu(), Some(9000));
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_MTU), 0);
+
+        // The MTU follows the MAC address, the status and the number of queue pairs.
+        let mut config_mtu = [0u8; 2];
+        net.read_config(MAC_ADDR_LEN as u64 + 4, &mut config_mtu);
+        assert_eq!(u16::from_le_bytes(config_mtu), 9000);
+    }
+
+    #[test]
+    fn test_offloads() {
+        let mut net = default_net();
+        assert_eq!(net.offloads(), NetOffloads::default());
+
+        net.set_offloads(NetOffloads {
+            tso: false,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            net.offloads(),
+            NetOffloads {
+                csum: true,
+                tso: false,
+                ufo: true,
+            }
+        );
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_GUEST_TSO4), 0);
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_HOST_TSO4), 0);
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_GUEST_UFO), 0);
+
+        // Segmentation offloads go along with checksum offload.
+        net.set_offloads(NetOffloads {
+            csum: false,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            net.offloads(),
+            NetOffloads {
+                csum: false,
+                tso: false,
+                ufo: false,
+            }
+        );
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_GUEST_CSUM), 0);
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_GUEST_UFO), 0);
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_HOST_UFO), 0);
+    }
+
+    #[test]
+    fn test_set_guest_mac() {
+        let mut net = default_net();
+        let new_mac = MacAddr::parse_str("11:22:33:44:55:66").unwrap();
+
+        net.set_guest_mac(new_mac).unwrap();
+        assert_eq!(net.guest_mac(), Some(&new_mac));
+        let mut config_mac = [0u8; MAC_ADDR_LEN];
+        net.read_config(0, &mut config_mac);
+        assert_eq!(&config_mac, new_mac.get_bytes());
+
+        // Devices created without a MAC address leave it to the guest driver.
+        let mut net = Net::new_with_tap(
+            "net-no-mac".to_string(),
+            "net-no-mac".to_string(),
+            None,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            1,
+        )
+        .unwrap();
+        assert!(matches!(
+            net.set_guest_mac(new_mac),
+            Err(Error::GuestMacNotAdvertised)
+        ));
+        assert_eq!(net.guest_mac(), None);
+    }
+
+    #[test]
+    fn test_link_up() {
+        let mut net = default_net();
+        assert!(net.link_up());
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_STATUS), 0);
+
+        // The status follows the MAC address.
+        let mut config_status = [0u8; 2];
+        net.read_config(MAC_ADDR_LEN as u64, &mut config_status);
+        assert_eq!(
+            u16::from_le_bytes(config_status),
+            VIRTIO_NET_S_LINK_UP as u16
+        );
+
+        net.set_link_up(false);
+        assert!(!net.link_up());
+        net.read_config(MAC_ADDR_LEN as u64, &mut config_status);
+        assert_eq!(u16::from_le_bytes(config_status), 0);
+
+        net.set_link_up(true);
+        assert!(net.link_up());
+    }
+
+    fn multi_queue_net(tap_if_name: &str, num_queue_pairs: usize) -> Net {
+        Net::new_with_tap(
+            tap_if_name.to_string(),
+            tap_if_name.to_string(),
+            None,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            num_queue_pairs,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_multi_queue_setup() {
+        // The number of queue pairs is validated.
+        assert!(matches!(
+            Net::new_with_tap(
+                "mq-net".to_string(),
+                "mq-net".to_string(),
+                None,
+                RateLimiter::default(),
+                RateLimiter::default(),
+                0,
+            ),
+            Err(Error::InvalidQueuePairs(0))
+        ));
+        assert!(matches!(
+            Net::new_with_tap(
+                "mq-net".to_string(),
+                "mq-net".to_string(),
+                None,
+                RateLimiter::default(),
+                RateLimiter::default(),
+                MAX_QUEUE_PAIRS + 1,
+            ),
+            Err(Error::InvalidQueuePairs(_))
+        ));
+
+        // Single queue pair devices don't offer multi-queue.
+        let net = default_net();
+        assert_eq!(net.num_queue_pairs(), 1);
+        assert_eq!(net.queues().len(), 2);
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_MQ), 0);
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_CTRL_VQ), 0);
+
+        let net = multi_queue_net("mq-net-setup", 4);
+        assert_eq!(net.num_queue_pairs(), 4);
+        // 4 RX/TX queue pairs and the control queue.
+        assert_eq!(net.queues().len(), 9);
+        assert_eq!(net.queue_events().len(), 9);
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_MQ), 0);
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_CTRL_VQ), 0);
+        assert_eq!(net.iface_name(), "mq-net-setup");
+
+        // The number of queue pairs follows the MAC address in the config space.
+        let mut max_virtqueue_pairs = [0u8; 2];
+        net.read_config(MAC_ADDR_LEN as u64 + 2, &mut max_virtqueue_pairs);
+        assert_eq!(u16::from_le_bytes(max_virtqueue_pairs), 4);
+    }
+
+    #[test]
+    fn test_multi_queue_ctrl_queue() {
+        use std::sync::{Arc, Mutex};
+
+        use event_manager::{EventManager, SubscriberOps};
+
+        let mut net = multi_queue_net("mq-net-ctrl", 4);
+        let mem = default_guest_memory();
+        let ctrl_queue_index = net.ctrl_queue_index();
+        let ctrlq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        net.queues[ctrl_queue_index] = ctrlq.create_queue();
+        net.acked_features = net.avail_features;
+
+        // All the queue pairs stay attached to the TAP device until the event manager handles
+        // the activation, which detaches all but the first one.
+        let mut event_manager = EventManager::new().unwrap();
+        let net = Arc::new(Mutex::new(net));
+        event_manager.add_subscriber(net.clone());
+        net.lock().unwrap().activate(mem.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().active_queue_pairs, 4);
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 1);
+        drop(event_manager);
+        let mut net = Arc::try_unwrap(net).ok().unwrap().into_inner().unwrap();
+        assert_eq!(net.active_queue_pairs, 1);
+
+        let mut send_ctrl_command = |index: u16, command: &[u8]| {
+            let cmd_addr = 0x1000 + u64::from(index) * 0x100;
+            let ack_addr = cmd_addr + 0x80;
+            mem.write_slice(command, GuestAddress(cmd_addr)).unwrap();
+            ctrlq.dtable[2 * index as usize].set(
+                cmd_addr,
+                command.len() as u32,
+                VIRTQ_DESC_F_NEXT,
+                2 * index + 1,
+            );
+            ctrlq.dtable[2 * index as usize + 1].set(ack_addr, 1, VIRTQ_DESC_F_WRITE, 0);
+            ctrlq.avail.ring[index as usize].set(2 * index);
+            ctrlq.avail.idx.set(index + 1);
+
+            net.queue_evts[ctrl_queue_index].write(1).unwrap();
+            net.process_ctrl_queue_event();
+
+            assert_eq!(ctrlq.used.idx.get(), index + 1);
+            ctrlq.check_used_elem(index, 2 * index, 1);
+            (
+                mem.read_obj::<u8>(GuestAddress(ack_addr)).unwrap(),
+                net.active_queue_pairs,
+            )
+        };
+
+        // Enable 3 of the 4 queue pairs.
+        assert_eq!(
+            send_ctrl_command(
+                0,
+                &[VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, 3, 0]
+            ),
+            (VIRTIO_NET_OK, 3)
+        );
+        // The device doesn't have 5 queue pairs.
+        assert_eq!(
+            send_ctrl_command(
+                1,
+                &[VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, 5, 0]
+            ),
+            (VIRTIO_NET_ERR, 3)
+        );
+        // At least one queue pair has to be enabled.
+        assert_eq!(
+            send_ctrl_command(
+                2,
+                &[VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, 0, 0]
+            ),
+            (VIRTIO_NET_ERR, 3)
+        );
+        // Other classes of commands aren't supported.
+        assert_eq!(send_ctrl_command(3, &[0, 0, 1]), (VIRTIO_NET_ERR, 3));
+        // Go back to a single queue pair.
+        assert_eq!(
+            send_ctrl_command(
+                4,
+                &[VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, 1, 0]
+            ),
+            (VIRTIO_NET_OK, 1)
+        );
+    }
+
+    #[test]
+    fn test_rx_filtering() {
+        let mut net = default_net();
+        assert!(!net.rx_filtering());
+        net.enable_rx_filtering().unwrap();
+        assert!(net.rx_filtering());
+        // The RX/TX queue pair and the control queue.
+        assert_eq!(net.queues().len(), 3);
+        assert_eq!(net.queue_events().len(), 3);
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_CTRL_VQ), 0);
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_MQ), 0);
+
+        let mem = default_guest_memory();
+        let ctrl_queue_index = net.ctrl_queue_index();
+        let ctrlq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        net.queues[ctrl_queue_index] = ctrlq.create_queue();
+        net.acked_features = net.avail_features;
+        net.activate(mem.clone()).unwrap();
+
+        let mut send_ctrl_command = |index: u16, command: &[u8]| {
+            let cmd_addr = 0x1000 + u64::from(index) * 0x100;
+            let ack_addr = cmd_addr + 0x80;
+            mem.write_slice(command, GuestAddress(cmd_addr)).unwrap();
+            ctrlq.dtable[2 * index as usize].set(
+                cmd_addr,
+                command.len() as u32,
+                VIRTQ_DESC_F_NEXT,
+                2 * index + 1,
+            );
+            ctrlq.dtable[2 * index as usize + 1].set(ack_addr, 1, VIRTQ_DESC_F_WRITE, 0);
+            ctrlq.avail.ring[index as usize].set(2 * index);
+            ctrlq.avail.idx.set(index + 1);
+
+            net.queue_evts[ctrl_queue_index].write(1).unwrap();
+            net.process_ctrl_queue_event();
+            let ack = mem.read_obj::<u8>(GuestAddress(ack_addr)).unwrap();
+
+            // Put a frame for another unicast address in the RX buffer.
+            let mut frame = vec![0u8; vnet_hdr_len() + 14];
+            frame[vnet_hdr_len()..vnet_hdr_len() + MAC_ADDR_LEN]
+                .copy_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+            net.queue_pairs[0].rx_frame_buf[..frame.len()].copy_from_slice(&frame);
+            net.queue_pairs[0].rx_bytes_read = frame.len();
+            (ack, net.rx_filter_accepts(0))
+        };
+
+        // The device delivers all the frames until the guest turns promiscuous mode off.
+        assert_eq!(
+            send_ctrl_command(0, &[VIRTIO_NET_CTRL_RX, 0, 0]),
+            (VIRTIO_NET_OK, false)
+        );
+        // NOUNI and the other extra modes aren't supported.
+        assert_eq!(
+            send_ctrl_command(1, &[VIRTIO_NET_CTRL_RX, 4, 1]),
+            (VIRTIO_NET_ERR, false)
+        );
+        // The guest asks for frames sent to a secondary unicast address, and to no multicast
+        // address.
+        let mut command = vec![
+            VIRTIO_NET_CTRL_MAC,
+            VIRTIO_NET_CTRL_MAC_TABLE_SET,
+            1,
+            0,
+            0,
+            0,
+        ];
+        command.extend_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        command.extend_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(send_ctrl_command(2, &command), (VIRTIO_NET_OK, true));
+        // A table is missing.
+        assert_eq!(
+            send_ctrl_command(
+                3,
+                &[
+                    VIRTIO_NET_CTRL_MAC,
+                    VIRTIO_NET_CTRL_MAC_TABLE_SET,
+                    0,
+                    0,
+                    0,
+                    0
+                ]
+            ),
+            (VIRTIO_NET_ERR, true)
+        );
+    }
+
     #[test]
     fn test_rx_missing_queue_signal() {
         let mut th = TestHelper::default();
@@ -1136,7 +2363,7 @@ pub mod tests {
         th.rxq.check_used_elem(1, 3, 0);
         th.rxq.check_used_elem(2, 4, 0);
         // Check that the frame wasn't deferred.
-        assert!(!th.net().rx_deferred_frame);
+        assert!(!th.net().queue_pairs[0].rx_deferred_frame);
         // Check that the frame has been written successfully to the valid Rx descriptor chain.
         th.rxq.check_used_elem(3, 5, frame.len() as u32);
         th.rxq.dtable[5].check_data(&frame);
@@ -1169,7 +2396,7 @@ pub mod tests {
         );
 
         // Check that the frame wasn't deferred.
-        assert!(!th.net().rx_deferred_frame);
+        assert!(!th.net().queue_pairs[0].rx_deferred_frame);
         // Check that the used queue has advanced.
         assert_eq!(th.rxq.used.idx.get(), 1);
         assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Vring));
@@ -1208,7 +2435,7 @@ pub mod tests {
         );
 
         // Check that the frames weren't deferred.
-        assert!(!th.net().rx_deferred_frame);
+        assert!(!th.net().queue_pairs[0].rx_deferred_frame);
         // Check that the used queue has advanced.
         assert_eq!(th.rxq.used.idx.get(), 2);
         assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Vring));
@@ -1220,13 +2447,53 @@ pub mod tests {
         th.rxq.check_used_elem(1, 2, frame_2.len() as u32);
         th.rxq.dtable[2].check_data(&frame_2);
         th.rxq.dtable[3].check_data(&[0; 500]);
+        // Check the statistics of the device.
+        let stats = th.net().stats();
+        assert_eq!(stats.rx_packets, 2);
+        assert_eq!(stats.rx_bytes, (frame_1.len() + frame_2.len()) as u64);
+    }
+
+    #[test]
+    fn test_capture() {
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let path = dir.as_path().join("capture.pcap");
+        let mut th = TestHelper::default();
+        th.activate_net();
+        th.net().mocks.set_read_tap(ReadTapMock::TapFrame);
+        th.net()
+            .set_capture(Some(PcapWriter::new(&path, None, 1).unwrap()))
+            .unwrap();
+        assert!(th.net().is_capturing());
+
+        let desc_list = [(0, 300, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let tx_frame = th.write_tx_frame(&desc_list, 300);
+        th.event_manager.run_with_timeout(100).unwrap();
+
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 500, VIRTQ_DESC_F_WRITE)]);
+        let rx_frame = inject_tap_tx_frame(&th.net(), 200);
+        th.event_manager.run_with_timeout(100).unwrap();
+
+        // The frames are captured without their VNET header, after the 24 bytes file header
+        // and each behind a 16 bytes record header.
+        let capture = std::fs::read(&path).unwrap();
+        let tx_start = 24 + 16;
+        let rx_start = tx_start + tx_frame.len() - vnet_hdr_len() + 16;
+        assert_eq!(
+            &capture[tx_start..rx_start - 16],
+            &tx_frame[vnet_hdr_len()..]
+        );
+        assert_eq!(&capture[rx_start..], &rx_frame[vnet_hdr_len()..]);
+
+        th.net().set_capture(None).unwrap();
+        assert!(!th.net().is_capturing());
     }
 
     #[test]
     fn test_tx_missing_queue_signal() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().iface_name()));
 
         th.add_desc_chain(NetQueue::Tx, 0, &[(0, 4096, 0)]);
         th.net().queue_evts[TX_INDEX].read().unwrap();
@@ -1246,7 +2513,7 @@ pub mod tests {
     fn test_tx_writeable_descriptor() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().iface_name()));
 
         let desc_list = [(0, 100, 0), (1, 100, VIRTQ_DESC_F_WRITE), (2, 500, 0)];
         th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
@@ -1265,7 +2532,7 @@ pub mod tests {
     fn test_tx_short_frame() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().iface_name()));
 
         // Send an invalid frame (too small, VNET header missing).
         th.add_desc_chain(NetQueue::Tx, 0, &[(0, 1, 0)]);
@@ -1287,7 +2554,7 @@ pub mod tests {
     fn test_tx_partial_read() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().iface_name()));
 
         // The descriptor chain is created so that the last descriptor doesn't fit in the
         // guest memory.
@@ -1315,7 +2582,7 @@ pub mod tests {
     fn test_tx_retry() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().iface_name()));
 
         // Add invalid descriptor chain - writeable descriptor.
         th.add_desc_chain(
@@ -1355,7 +2622,7 @@ pub mod tests {
     fn test_tx_complex_descriptor() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().iface_name()));
 
         // Add gaps between the descriptor ids in order to ensure that we follow
         // the `next` field.
@@ -1383,7 +2650,7 @@ pub mod tests {
     fn test_tx_multiple_frame() {
         let mut th = TestHelper::default();
         th.activate_net();
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().iface_name()));
 
         // Write the first frame to the Tx queue
         let desc_list = [(0, 50, 0), (1, 100, 0), (2, 150, 0)];
@@ -1413,6 +2680,11 @@ pub mod tests {
         let mut buf = vec![0; 600];
         assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
         assert_eq!(&buf[..600], &frame_2[..600]);
+        // Check the statistics of the device.
+        let stats = th.net().stats();
+        assert_eq!(stats.tx_packets, 2);
+        assert_eq!(stats.tx_bytes, 900);
+        assert_eq!(stats.tx_dropped, 0);
     }
 
     fn create_arp_request(
@@ -1467,8 +2739,10 @@ pub mod tests {
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
                 &frame_buf[..frame_len],
-                &mut net.tap,
+                &net.l3_filter,
+                net.queue_pairs[0].backend.as_mut(),
                 Some(src_mac),
+                &mut net.stats,
             )
             .unwrap())
         );
@@ -1477,7 +2751,7 @@ pub mod tests {
         check_metric_after_block!(
             &METRICS.mmds.tx_frames,
             1,
-            net.read_from_mmds_or_tap().unwrap()
+            net.read_from_mmds_or_tap(0).unwrap()
         );
     }
 
@@ -1501,8 +2775,10 @@ pub mod tests {
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
                 &frame_buf[..frame_len],
-                &mut net.tap,
+                &net.l3_filter,
+                net.queue_pairs[0].backend.as_mut(),
                 Some(guest_mac),
+                &mut net.stats,
             )
         );
 
@@ -1514,12 +2790,78 @@ pub mod tests {
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
                 &frame_buf[..frame_len],
-                &mut net.tap,
+                &net.l3_filter,
+                net.queue_pairs[0].backend.as_mut(),
                 Some(not_guest_mac),
+                &mut net.stats,
             )
         );
     }
 
+    #[test]
+    fn test_l3_filter() {
+        let mut net = default_net();
+        let rules = vec![NetFilterRule {
+            action: NetFilterAction::Allow,
+            direction: NetFilterDirection::Egress,
+            cidr: Ipv4Cidr::parse_str("10.1.1.1").unwrap(),
+            protocol: None,
+            port: None,
+        }];
+        net.set_filters(rules.clone());
+        assert_eq!(net.filters(), &rules[..]);
+
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let dst_mac = MacAddr::parse_str("22:22:22:22:22:22").unwrap();
+        let (mut frame_buf, frame_len) = create_arp_request(
+            guest_mac,
+            Ipv4Addr::new(10, 1, 2, 3),
+            dst_mac,
+            Ipv4Addr::new(10, 1, 1, 1),
+        );
+
+        // ARP frames go through.
+        check_metric_after_block!(
+            &METRICS.net.tx_l3_filtered_frames,
+            0,
+            Net::write_to_mmds_or_tap(
+                None,
+                &mut net.tx_rate_limiter,
+                &frame_buf[..frame_len],
+                &net.l3_filter,
+                net.queue_pairs[0].backend.as_mut(),
+                Some(guest_mac),
+                &mut net.stats,
+            )
+        );
+
+        // Turn the frame into an IPv6 one, which the rules don't allow.
+        frame_buf[vnet_hdr_len() + 12..vnet_hdr_len() + 14].copy_from_slice(&[0x86, 0xdd]);
+        check_metric_after_block!(
+            &METRICS.net.tx_l3_filtered_frames,
+            1,
+            Net::write_to_mmds_or_tap(
+                None,
+                &mut net.tx_rate_limiter,
+                &frame_buf[..frame_len],
+                &net.l3_filter,
+                net.queue_pairs[0].backend.as_mut(),
+                Some(guest_mac),
+                &mut net.stats,
+            )
+        );
+        assert_eq!(net.stats.tx_dropped, 1);
+
+        // The rules only apply to their direction.
+        net.queue_pairs[0].rx_frame_buf[..frame_len].copy_from_slice(&frame_buf[..frame_len]);
+        assert!(net.l3_filter_accepts(0, frame_len));
+        net.set_filters(vec![NetFilterRule {
+            direction: NetFilterDirection::Ingress,
+            ..rules[0]
+        }]);
+        assert!(!net.l3_filter_accepts(0, frame_len));
+    }
+
     #[test]
     fn test_process_error_cases() {
         let mut th = TestHelper::default();
@@ -1552,7 +2894,7 @@ pub mod tests {
         th.net().mocks.set_read_tap(ReadTapMock::Failure);
 
         // The RX queue is empty and rx_deffered_frame is set.
-        th.net().rx_deferred_frame = true;
+        th.net().queue_pairs[0].rx_deferred_frame = true;
         check_metric_after_block!(
             &METRICS.net.no_rx_avail_buffer,
             1,
@@ -1562,7 +2904,7 @@ pub mod tests {
         // We need to set this here to false, otherwise the device will try to
         // handle a deferred frame, it will fail and will never try to read from
         // the tap.
-        th.net().rx_deferred_frame = false;
+        th.net().queue_pairs[0].rx_deferred_frame = false;
 
         // Fake an avail buffer; this time, tap reading should error out.
         th.rxq.avail.idx.set(1);
@@ -1590,7 +2932,7 @@ pub mod tests {
         );
         // The frame we read from the tap should be deferred now and
         // no frames should have been transmitted
-        assert!(th.net().rx_deferred_frame);
+        assert!(th.net().queue_pairs[0].rx_deferred_frame);
         assert_eq!(METRICS.net.rx_packets_count.count(), rx_packets_count);
 
         // Let's add a second frame, which should really have the same
@@ -1607,7 +2949,7 @@ pub mod tests {
             th.simulate_event(NetEvent::Tap)
         );
         // We should still have a deferred frame
-        assert!(th.net().rx_deferred_frame);
+        assert!(th.net().queue_pairs[0].rx_deferred_frame);
         // However, we should have delivered the first frame
         assert_eq!(METRICS.net.rx_packets_count.count(), rx_packets_count + 1);
 
@@ -1620,7 +2962,7 @@ pub mod tests {
         );
 
         // We should be done with any deferred frame
-        assert!(!th.net().rx_deferred_frame);
+        assert!(!th.net().queue_pairs[0].rx_deferred_frame);
     }
 
     #[test]
@@ -1688,6 +3030,7 @@ pub mod tests {
                 th.simulate_event(NetEvent::TxQueue);
 
                 assert_eq!(METRICS.net.tx_rate_limiter_throttled.count(), 2);
+                assert_eq!(th.net().stats().tx_rate_limiter_throttled, 2);
             }
 
             // wait for 100ms to give the rate-limiter timer a chance to replenish
@@ -1737,7 +3080,7 @@ pub mod tests {
             th.net().rx_rate_limiter = rl;
 
             // set up RX
-            assert!(!th.net().rx_deferred_frame);
+            assert!(!th.net().queue_pairs[0].rx_deferred_frame);
             th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
 
             // following RX procedure should fail because of bandwidth rate limiting
@@ -1748,7 +3091,7 @@ pub mod tests {
                 // assert that limiter is blocked
                 assert!(th.net().rx_rate_limiter.is_blocked());
                 assert_eq!(METRICS.net.rx_rate_limiter_throttled.count(), 1);
-                assert!(th.net().rx_deferred_frame);
+                assert!(th.net().queue_pairs[0].rx_deferred_frame);
                 // assert that no operation actually completed (limiter blocked it)
                 assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Vring));
                 // make sure the data is still queued for processing
@@ -1761,6 +3104,7 @@ pub mod tests {
                 th.simulate_event(NetEvent::RxQueue);
 
                 assert_eq!(METRICS.net.rx_rate_limiter_throttled.count(), 2);
+                assert_eq!(th.net().stats().rx_rate_limiter_throttled, 2);
             }
 
             // wait for 100ms to give the rate-limiter timer a chance to replenish
@@ -1788,6 +3132,42 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_pps_rate_limiter() {
+        let mut th = TestHelper::default();
+        th.activate_net();
+
+        // create packets rate limiter that allows 10 packets/s with bucket size 1 packet
+        let mut rl = RateLimiter::new(0, 0, 0, 0, 0, 0).unwrap();
+        rl.update_pps(BucketUpdate::Update(TokenBucket::new(1, 0, 100).unwrap()));
+        // use up the budget
+        assert!(rl.consume(1, TokenType::Packets));
+        th.net().tx_rate_limiter = rl;
+
+        // following TX procedure should fail because of packets rate limiting
+        th.add_desc_chain(NetQueue::Tx, 0, &[(0, 4096, 0)]);
+        check_metric_after_block!(
+            METRICS.net.tx_rate_limiter_throttled,
+            1,
+            th.simulate_event(NetEvent::TxQueue)
+        );
+        assert!(th.net().tx_rate_limiter.is_blocked());
+        assert_eq!(th.txq.used.idx.get(), 0);
+
+        // wait for 100ms to give the rate-limiter timer a chance to replenish
+        // wait for an extra 100ms to make sure the timerfd event makes its way from the kernel
+        thread::sleep(Duration::from_millis(200));
+
+        // following TX procedure should succeed because packets should now be available
+        check_metric_after_block!(
+            &METRICS.net.tx_rate_limiter_throttled,
+            0,
+            th.simulate_event(NetEvent::TxRateLimiter)
+        );
+        assert!(!th.net().tx_rate_limiter.is_blocked());
+        assert_eq!(th.txq.used.idx.get(), 1);
+    }
+
     #[test]
     fn test_ops_rate_limiter() {
         let mut th = TestHelper::default();
@@ -1850,7 +3230,7 @@ pub mod tests {
             th.net().rx_rate_limiter = rl;
 
             // set up RX
-            assert!(!th.net().rx_deferred_frame);
+            assert!(!th.net().queue_pairs[0].rx_deferred_frame);
             th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
 
             // following RX procedure should fail because of ops rate limiting
@@ -1865,7 +3245,7 @@ pub mod tests {
                 // assert that limiter is blocked
                 assert!(th.net().rx_rate_limiter.is_blocked());
                 assert!(METRICS.net.rx_rate_limiter_throttled.count() >= 1);
-                assert!(th.net().rx_deferred_frame);
+                assert!(th.net().queue_pairs[0].rx_deferred_frame);
                 // assert that no operation actually completed (limiter blocked it)
                 assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Vring));
                 // make sure the data is still queued for processing
@@ -1909,12 +3289,16 @@ pub mod tests {
         let rx_ops = TokenBucket::new(1003, 1004, 1005).unwrap();
         let tx_bytes = TokenBucket::new(1006, 1007, 1008).unwrap();
         let tx_ops = TokenBucket::new(1009, 1010, 1011).unwrap();
+        let rx_pps = TokenBucket::new(1012, 1013, 1014).unwrap();
+        let tx_pps = TokenBucket::new(1015, 1016, 1017).unwrap();
 
         th.net().patch_rate_limiters(
             BucketUpdate::Update(rx_bytes.clone()),
             BucketUpdate::Update(rx_ops.clone()),
+            BucketUpdate::Update(rx_pps.clone()),
             BucketUpdate::Update(tx_bytes.clone()),
             BucketUpdate::Update(tx_ops.clone()),
+            BucketUpdate::Update(tx_pps.clone()),
         );
         let compare_buckets = |a: &TokenBucket, b: &TokenBucket| {
             assert_eq!(a.capacity(), b.capacity());
@@ -1925,17 +3309,23 @@ pub mod tests {
         compare_buckets(th.net().rx_rate_limiter.ops().unwrap(), &rx_ops);
         compare_buckets(th.net().tx_rate_limiter.bandwidth().unwrap(), &tx_bytes);
         compare_buckets(th.net().tx_rate_limiter.ops().unwrap(), &tx_ops);
+        compare_buckets(th.net().rx_rate_limiter.pps().unwrap(), &rx_pps);
+        compare_buckets(th.net().tx_rate_limiter.pps().unwrap(), &tx_pps);
 
         th.net().patch_rate_limiters(
             BucketUpdate::Disabled,
             BucketUpdate::Disabled,
             BucketUpdate::Disabled,
             BucketUpdate::Disabled,
+            BucketUpdate::Disabled,
+            BucketUpdate::Disabled,
         );
         assert!(th.net().rx_rate_limiter.bandwidth().is_none());
         assert!(th.net().rx_rate_limiter.ops().is_none());
         assert!(th.net().tx_rate_limiter.bandwidth().is_none());
         assert!(th.net().tx_rate_limiter.ops().is_none());
+        assert!(th.net().rx_rate_limiter.pps().is_none());
+        assert!(th.net().tx_rate_limiter.pps().is_none());
     }
 
     #[test]
@@ -1,35 +1,90 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use logger::{debug, error, warn, IncMetric, METRICS};
 use utils::epoll::EventSet;
 
-use crate::virtio::net::device::Net;
-use crate::virtio::{VirtioDevice, RX_INDEX, TX_INDEX};
+use crate::virtio::net::device::{rx_queue_index, tx_queue_index, Net};
+use crate::virtio::VirtioDevice;
 
 impl Net {
-    fn register_runtime_events(&self, ops: &mut EventOps) {
-        if let Err(e) = ops.add(Events::new(&self.queue_evts[RX_INDEX], EventSet::IN)) {
-            error!("Failed to register rx queue event: {}", e);
+    // The events the device listens to once it has been activated.
+    fn runtime_events(&self) -> Vec<Events> {
+        let mut events = Vec::new();
+        for (queue_pair, pair) in self.queue_pairs.iter().enumerate() {
+            if pair.vhost.is_some() {
+                // The queue events and the tap are serviced by vhost, which notifies us
+                // when the guest needs to be interrupted.
+                for queue_index in &[rx_queue_index(queue_pair), tx_queue_index(queue_pair)] {
+                    events.push(Events::new(
+                        &self.vhost_call_evts[*queue_index],
+                        EventSet::IN,
+                    ));
+                }
+                continue;
+            }
+            events.push(Events::new(
+                &self.queue_evts[rx_queue_index(queue_pair)],
+                EventSet::IN,
+            ));
+            events.push(Events::new(
+                &self.queue_evts[tx_queue_index(queue_pair)],
+                EventSet::IN,
+            ));
+            events.push(Events::new(
+                &pair.backend.as_raw_fd(),
+                EventSet::IN | EventSet::EDGE_TRIGGERED,
+            ));
         }
-        if let Err(e) = ops.add(Events::new(&self.queue_evts[TX_INDEX], EventSet::IN)) {
-            error!("Failed to register tx queue event: {}", e);
+        if self.has_ctrl_queue() {
+            events.push(Events::new(
+                &self.queue_evts[self.ctrl_queue_index()],
+                EventSet::IN,
+            ));
         }
-        if let Err(e) = ops.add(Events::new(&self.rx_rate_limiter, EventSet::IN)) {
-            error!("Failed to register rx queue event: {}", e);
+        events.push(Events::new(&self.rx_rate_limiter, EventSet::IN));
+        events.push(Events::new(&self.tx_rate_limiter, EventSet::IN));
+        events
+    }
+
+    fn register_runtime_events(&self, ops: &mut EventOps) {
+        for events in self.runtime_events() {
+            if let Err(e) = ops.add(events) {
+                error!("Failed to register net event: {}", e);
+            }
         }
-        if let Err(e) = ops.add(Events::new(&self.tx_rate_limiter, EventSet::IN)) {
-            error!("Failed to register tx queue event: {}", e);
+    }
+
+    // Dispatches the events coming from the queues and the taps.
+    fn process_queue_event(&mut self, source: RawFd) {
+        for queue_pair in 0..self.num_queue_pairs() {
+            if self.queue_pairs[queue_pair].vhost.is_some() {
+                for queue_index in &[rx_queue_index(queue_pair), tx_queue_index(queue_pair)] {
+                    if source == self.vhost_call_evts[*queue_index].as_raw_fd() {
+                        return self.process_vhost_call_event(*queue_index);
+                    }
+                }
+                continue;
+            }
+            if source == self.queue_evts[rx_queue_index(queue_pair)].as_raw_fd() {
+                return self.process_rx_queue_event(queue_pair);
+            }
+            if source == self.queue_pairs[queue_pair].backend.as_raw_fd() {
+                return self.process_tap_rx_event(queue_pair);
+            }
+            if source == self.queue_evts[tx_queue_index(queue_pair)].as_raw_fd() {
+                return self.process_tx_queue_event(queue_pair);
+            }
         }
-        if let Err(e) = ops.add(Events::new(
-            &self.tap,
-            EventSet::IN | EventSet::EDGE_TRIGGERED,
-        )) {
-            error!("Failed to register tap event: {}", e);
+        if self.has_ctrl_queue() && source == self.queue_evts[self.ctrl_queue_index()].as_raw_fd() {
+            return self.process_ctrl_queue_event();
         }
+
+        warn!("Net: Spurious event received: {:?}", source);
+        METRICS.net.event_fails.inc();
     }
 
     fn register_activate_event(&self, ops: &mut EventOps) {
@@ -38,11 +93,46 @@ impl Net {
         }
     }
 
-    fn process_activate_event(&self, ops: &mut EventOps) {
+    fn register_unplug_event(&self, ops: &mut EventOps) {
+        if let Err(e) = ops.add(Events::new(&self.unplug_evt, EventSet::IN)) {
+            error!("Failed to register unplug event: {}", e);
+        }
+    }
+
+    fn process_unplug_event(&mut self, ops: &mut EventOps) {
+        debug!("net: unplug event");
+        if let Err(e) = self.unplug_evt.read() {
+            error!("Failed to consume net unplug event: {:?}", e);
+        }
+        // Depending on how far the activation went, either the activation event or the
+        // runtime events are registered, so failing to remove the others is expected.
+        let _ = ops.remove(Events::new(&self.activate_evt, EventSet::IN));
+        for events in self.runtime_events() {
+            let _ = ops.remove(events);
+        }
+        if let Err(e) = ops.remove(Events::new(&self.unplug_evt, EventSet::IN)) {
+            error!("Failed to un-register unplug event: {}", e);
+        }
+        // Closes the taps and stops the vhost workers. The device stays registered with the
+        // event manager, without any event left to process.
+        self.queue_pairs.clear();
+    }
+
+    fn process_activate_event(&mut self, ops: &mut EventOps) {
         debug!("net: activate event");
         if let Err(e) = self.activate_evt.read() {
             error!("Failed to consume net activate event: {:?}", e);
         }
+        // Until the guest asks for more through the control queue, only the first queue pair
+        // is used. This is done here rather than on activation, so that the TAP ioctls are
+        // issued from the VMM thread.
+        self.set_active_queue_pairs(1);
+        if self.uses_vhost() {
+            if let Err(e) = self.start_vhost() {
+                error!("Failed to start vhost: {:?}", e);
+                METRICS.net.event_fails.inc();
+            }
+        }
         self.register_runtime_events(ops);
         if let Err(e) = ops.remove(Events::new(&self.activate_evt, EventSet::IN)) {
             error!("Failed to un-register activate event: {}", e);
@@ -66,26 +156,21 @@ impl MutEventSubscriber for Net {
             return;
         }
 
+        if source == self.unplug_evt.as_raw_fd() {
+            return self.process_unplug_event(ops);
+        }
+
         if self.is_activated() {
-            let virtq_rx_ev_fd = self.queue_evts[RX_INDEX].as_raw_fd();
-            let virtq_tx_ev_fd = self.queue_evts[TX_INDEX].as_raw_fd();
             let rx_rate_limiter_fd = self.rx_rate_limiter.as_raw_fd();
             let tx_rate_limiter_fd = self.tx_rate_limiter.as_raw_fd();
-            let tap_fd = self.tap.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
 
             // Looks better than C style if/else if/else.
             match source {
-                _ if source == virtq_rx_ev_fd => self.process_rx_queue_event(),
-                _ if source == tap_fd => self.process_tap_rx_event(),
-                _ if source == virtq_tx_ev_fd => self.process_tx_queue_event(),
                 _ if source == rx_rate_limiter_fd => self.process_rx_rate_limiter_event(),
                 _ if source == tx_rate_limiter_fd => self.process_tx_rate_limiter_event(),
                 _ if activate_fd == source => self.process_activate_event(ops),
-                _ => {
-                    warn!("Net: Spurious event received: {:?}", source);
-                    METRICS.net.event_fails.inc();
-                }
+                _ => self.process_queue_event(source),
             }
         } else {
             warn!(
@@ -105,6 +190,7 @@ impl MutEventSubscriber for Net {
         } else {
             self.register_activate_event(ops);
         }
+        self.register_unplug_event(ops);
     }
 }
 
@@ -142,4 +228,23 @@ pub mod tests {
         // Make sure the data queue advanced.
         assert_eq!(th.txq.used.idx.get(), 1);
     }
+
+    #[test]
+    fn test_unplug_event() {
+        let mut th = TestHelper::default();
+        th.activate_net();
+        assert_eq!(th.net().num_queue_pairs(), 1);
+
+        th.net().unplug().unwrap();
+        let ev_count = th.event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        // The tap was closed.
+        assert_eq!(th.net().num_queue_pairs(), 0);
+
+        // Queue events aren't processed anymore.
+        th.add_desc_chain(NetQueue::Tx, 0, &[(0, 4096, 0)]);
+        let ev_count = th.event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+        assert_eq!(th.txq.used.idx.get(), 0);
+    }
 }
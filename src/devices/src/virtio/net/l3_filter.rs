@@ -0,0 +1,378 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Filtering of the IPv4 traffic of a net device by remote address, protocol and port, as
+//! configured for the device rather than by the guest.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_TCP, PROTOCOL_UDP};
+use serde::de::Error as SerdeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const PROTOCOL_ICMP: u8 = 0x01;
+// The minimum length of an IPv4 header.
+const IPV4_HEADER_LEN: usize = 20;
+
+/// What happens to the packets matched by a filter rule.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetFilterAction {
+    /// The packets go through.
+    Allow,
+    /// The packets are dropped.
+    Deny,
+}
+
+/// The packets a filter rule applies to.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetFilterDirection {
+    /// Packets sent by the guest, matched by destination.
+    Egress,
+    /// Packets sent to the guest, matched by source.
+    Ingress,
+}
+
+/// The IPv4 protocols filter rules can match.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetFilterProtocol {
+    /// Internet Control Message Protocol.
+    Icmp,
+    /// Transmission Control Protocol.
+    Tcp,
+    /// User Datagram Protocol.
+    Udp,
+}
+
+impl NetFilterProtocol {
+    pub(crate) fn number(self) -> u8 {
+        match self {
+            NetFilterProtocol::Icmp => PROTOCOL_ICMP,
+            NetFilterProtocol::Tcp => PROTOCOL_TCP,
+            NetFilterProtocol::Udp => PROTOCOL_UDP,
+        }
+    }
+
+    pub(crate) fn from_number(number: u8) -> Option<Self> {
+        match number {
+            PROTOCOL_ICMP => Some(NetFilterProtocol::Icmp),
+            PROTOCOL_TCP => Some(NetFilterProtocol::Tcp),
+            PROTOCOL_UDP => Some(NetFilterProtocol::Udp),
+            _ => None,
+        }
+    }
+
+    /// Returns whether packets of this protocol have ports.
+    pub fn has_ports(self) -> bool {
+        self != NetFilterProtocol::Icmp
+    }
+}
+
+/// A block of IPv4 addresses, written as `address/prefix_len`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ipv4Cidr {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// Creates the block of the addresses sharing the first `prefix_len` bits of `addr`.
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Option<Self> {
+        if prefix_len > 32 {
+            return None;
+        }
+        Some(Ipv4Cidr { addr, prefix_len })
+    }
+
+    /// Parses a block written as `address/prefix_len`, or as a single address.
+    pub fn parse_str(s: &str) -> Option<Self> {
+        match s.split_once('/') {
+            Some((addr, prefix_len)) => Self::new(addr.parse().ok()?, prefix_len.parse().ok()?),
+            None => Self::new(s.parse().ok()?, 32),
+        }
+    }
+
+    /// Returns the first address of the block.
+    pub fn addr(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    /// Returns the number of leading bits shared by the addresses of the block.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns whether `addr` belongs to the block.
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::MAX
+            .checked_shl(32 - u32::from(self.prefix_len))
+            .unwrap_or(0);
+        u32::from(self.addr) & mask == u32::from(addr) & mask
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl Serialize for Ipv4Cidr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ipv4Cidr {
+    fn deserialize<D>(deserializer: D) -> Result<Ipv4Cidr, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ipv4Cidr::parse_str(&s).ok_or_else(|| D::Error::custom("The provided CIDR is invalid."))
+    }
+}
+
+/// A rule allowing or denying the IPv4 packets exchanged with a block of remote addresses.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetFilterRule {
+    /// Whether the matched packets go through.
+    pub action: NetFilterAction,
+    /// Whether the rule applies to the packets sent or received by the guest.
+    pub direction: NetFilterDirection,
+    /// The remote addresses matched by the rule.
+    pub cidr: Ipv4Cidr,
+    /// The protocol matched by the rule. All protocols are matched when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<NetFilterProtocol>,
+    /// The remote port matched by the rule, for the protocols which have ports. All ports are
+    /// matched when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+impl NetFilterRule {
+    /// Returns whether the rule can match any packet. Ports can only be matched along with a
+    /// protocol which has them.
+    pub fn is_valid(&self) -> bool {
+        self.port.is_none() || self.protocol.map_or(false, NetFilterProtocol::has_ports)
+    }
+
+    fn matches(&self, remote: Ipv4Addr, protocol: u8, port: Option<u16>) -> bool {
+        self.cidr.contains(remote)
+            && self.protocol.map_or(true, |p| p.number() == protocol)
+            && self.port.map_or(true, |p| Some(p) == port)
+    }
+}
+
+/// The filter rules of a net device. The first rule matching a packet decides whether it goes
+/// through, and the packets matching none of the rules of their direction are dropped. Once a
+/// direction has rules, only ARP and IPv4 frames go through in that direction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct L3Filter {
+    rules: Vec<NetFilterRule>,
+}
+
+impl L3Filter {
+    pub(crate) fn new(rules: Vec<NetFilterRule>) -> Self {
+        L3Filter { rules }
+    }
+
+    pub(crate) fn rules(&self) -> &[NetFilterRule] {
+        &self.rules
+    }
+
+    /// Returns whether `frame` can go through in `direction`.
+    pub(crate) fn accepts(&self, direction: NetFilterDirection, frame: &[u8]) -> bool {
+        let mut rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.direction == direction)
+            .peekable();
+        if rules.peek().is_none() {
+            return true;
+        }
+
+        let eth_frame = match EthernetFrame::from_bytes(frame) {
+            Ok(eth_frame) => eth_frame,
+            Err(_) => return false,
+        };
+        match eth_frame.ethertype() {
+            // Neighbours have to be resolved for the allowed endpoints to be reachable.
+            ETHERTYPE_ARP => return true,
+            ETHERTYPE_IPV4 => (),
+            _ => return false,
+        }
+        let packet = match ipv4_packet(eth_frame.payload()) {
+            Some(packet) => packet,
+            None => return false,
+        };
+
+        let (remote, port_offset) = match direction {
+            NetFilterDirection::Egress => (packet.destination_address(), 2),
+            NetFilterDirection::Ingress => (packet.source_address(), 0),
+        };
+        // Only the first fragment of a packet holds the transport header.
+        let port = if packet.flags_and_fragment_offset().1 == 0 {
+            packet
+                .payload()
+                .get(port_offset..port_offset + 2)
+                .map(|port| u16::from_be_bytes([port[0], port[1]]))
+        } else {
+            None
+        };
+
+        rules
+            .find(|rule| rule.matches(remote, packet.protocol(), port))
+            .map_or(false, |rule| rule.action == NetFilterAction::Allow)
+    }
+}
+
+// Returns the IPv4 packet at the start of `payload`, which may be followed by the padding of
+// short Ethernet frames.
+fn ipv4_packet(payload: &[u8]) -> Option<IPv4Packet<&[u8]>> {
+    if payload.len() < IPV4_HEADER_LEN {
+        return None;
+    }
+    let total_len = IPv4Packet::from_bytes_unchecked(payload).total_len() as usize;
+    IPv4Packet::from_bytes(payload.get(..total_len)?, false).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::net::mac::MacAddr;
+
+    use super::*;
+
+    fn frame(
+        ethertype: u16,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        protocol: u8,
+        ports: [u16; 2],
+    ) -> Vec<u8> {
+        let mut buf = vec![0u8; 128];
+        let mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let mut eth_frame =
+            EthernetFrame::write_incomplete(buf.as_mut_slice(), mac, mac, ethertype).unwrap();
+        let mut packet =
+            IPv4Packet::write_header(eth_frame.inner_mut().payload_mut(), protocol, src, dst)
+                .unwrap();
+        let payload = packet.inner_mut().payload_mut();
+        payload[..2].copy_from_slice(&ports[0].to_be_bytes());
+        payload[2..4].copy_from_slice(&ports[1].to_be_bytes());
+        let packet_len = packet.with_payload_len_unchecked(8, true).len();
+        eth_frame.with_payload_len_unchecked(packet_len);
+        // Short frames are padded.
+        buf.truncate(64);
+        buf
+    }
+
+    fn rule(
+        action: NetFilterAction,
+        direction: NetFilterDirection,
+        cidr: &str,
+        protocol: Option<NetFilterProtocol>,
+        port: Option<u16>,
+    ) -> NetFilterRule {
+        NetFilterRule {
+            action,
+            direction,
+            cidr: Ipv4Cidr::parse_str(cidr).unwrap(),
+            protocol,
+            port,
+        }
+    }
+
+    #[test]
+    fn test_ipv4_cidr() {
+        let cidr = Ipv4Cidr::parse_str("10.0.0.0/8").unwrap();
+        assert!(cidr.contains(Ipv4Addr::new(10, 1, 2, 3)));
+        assert!(!cidr.contains(Ipv4Addr::new(11, 0, 0, 0)));
+        assert_eq!(cidr.to_string(), "10.0.0.0/8");
+
+        let cidr = Ipv4Cidr::parse_str("169.254.169.254").unwrap();
+        assert_eq!(cidr.prefix_len(), 32);
+        assert!(cidr.contains(Ipv4Addr::new(169, 254, 169, 254)));
+        assert!(!cidr.contains(Ipv4Addr::new(169, 254, 169, 253)));
+
+        assert!(Ipv4Cidr::parse_str("0.0.0.0/0")
+            .unwrap()
+            .contains(Ipv4Addr::new(1, 2, 3, 4)));
+        assert!(Ipv4Cidr::parse_str("10.0.0.0/33").is_none());
+        assert!(Ipv4Cidr::parse_str("10.0.0/8").is_none());
+        assert!(Ipv4Cidr::parse_str("192.168.0.0/").is_none());
+    }
+
+    #[test]
+    fn test_l3_filter() {
+        use self::NetFilterAction::*;
+        use self::NetFilterDirection::*;
+        use self::NetFilterProtocol::*;
+
+        let guest = Ipv4Addr::new(192, 168, 0, 2);
+        let mmds = Ipv4Addr::new(169, 254, 169, 254);
+        let server = Ipv4Addr::new(10, 0, 0, 1);
+        let other = Ipv4Addr::new(10, 0, 0, 2);
+
+        // Without rules, everything goes through.
+        let filter = L3Filter::default();
+        assert!(filter.accepts(
+            Egress,
+            &frame(ETHERTYPE_IPV4, guest, other, PROTOCOL_TCP, [0; 2])
+        ));
+
+        let filter = L3Filter::new(vec![
+            rule(Deny, Egress, "10.0.0.2", None, None),
+            rule(Allow, Egress, "10.0.0.0/24", Some(Tcp), Some(443)),
+            rule(Allow, Egress, "169.254.169.254/32", None, None),
+        ]);
+        let egress = |dst, protocol, port| {
+            filter.accepts(
+                Egress,
+                &frame(ETHERTYPE_IPV4, guest, dst, protocol, [49152, port]),
+            )
+        };
+        assert!(egress(server, PROTOCOL_TCP, 443));
+        assert!(!egress(server, PROTOCOL_TCP, 80));
+        assert!(!egress(server, PROTOCOL_UDP, 443));
+        assert!(egress(mmds, PROTOCOL_UDP, 53));
+        // The first matching rule wins.
+        assert!(!egress(other, PROTOCOL_TCP, 443));
+        // ARP goes through, other ethertypes don't.
+        assert!(filter.accepts(Egress, &frame(ETHERTYPE_ARP, guest, other, 0, [0; 2])));
+        assert!(!filter.accepts(
+            Egress,
+            &frame(0x86dd, guest, server, PROTOCOL_TCP, [0, 443])
+        ));
+        assert!(!filter.accepts(Egress, &[0u8; 10]));
+
+        // The ingress direction has no rules.
+        assert!(filter.accepts(
+            Ingress,
+            &frame(ETHERTYPE_IPV4, other, guest, PROTOCOL_TCP, [80, 49152])
+        ));
+        let filter = L3Filter::new(vec![rule(Allow, Ingress, "10.0.0.1", Some(Icmp), None)]);
+        assert!(filter.accepts(
+            Ingress,
+            &frame(ETHERTYPE_IPV4, server, guest, PROTOCOL_ICMP, [0; 2])
+        ));
+        assert!(!filter.accepts(
+            Ingress,
+            &frame(ETHERTYPE_IPV4, other, guest, PROTOCOL_ICMP, [0; 2])
+        ));
+
+        // Ports need a protocol which has them.
+        assert!(rule(Allow, Egress, "10.0.0.1", Some(Udp), Some(53)).is_valid());
+        assert!(!rule(Allow, Egress, "10.0.0.1", None, Some(53)).is_valid());
+        assert!(!rule(Allow, Egress, "10.0.0.1", Some(Icmp), Some(53)).is_valid());
+    }
+}
@@ -11,17 +11,39 @@ pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; NUM_QUEUES];
 pub const RX_INDEX: usize = 0;
 // The index of the tx queue from Net device queues/queues_evts vector.
 pub const TX_INDEX: usize = 1;
+// The maximum number of RX/TX queue pairs of a Net device.
+pub const MAX_QUEUE_PAIRS: usize = 16;
+// The MTU bounds accepted by the Linux virtio-net driver.
+pub const MIN_MTU: u16 = 68;
+pub const MAX_MTU: u16 = 65535;
 
+pub mod backend;
 pub mod device;
 pub mod event_handler;
+mod l3_filter;
+mod netns;
+mod pcap;
 pub mod persist;
+mod rx_filter;
+#[cfg(feature = "net-socketpair")]
+mod socketpair;
 mod tap;
 pub mod test_utils;
+pub(crate) mod vhost;
+mod vhost_user;
+mod xdp;
 
 pub use tap::Error as TapError;
+pub use vhost::Error as VhostError;
 
-pub use self::device::Net;
+pub use self::backend::{NetBackend, NetBackendType, NetDatapath, NetOffloads};
+pub use self::device::{Net, NetStats};
 pub use self::event_handler::*;
+pub use self::l3_filter::{
+    Ipv4Cidr, NetFilterAction, NetFilterDirection, NetFilterProtocol, NetFilterRule,
+};
+pub use self::netns::with_netns;
+pub use self::pcap::PcapWriter;
 
 /// Enum representing the Net device queue types
 pub enum NetQueue {
@@ -35,12 +57,35 @@ pub enum NetQueue {
 pub enum Error {
     /// Open tap device failed.
     TapOpen(TapError),
+    /// Connecting to or taking over the socket of the socketpair backend failed.
+    #[cfg(feature = "net-socketpair")]
+    SocketPairOpen(io::Error),
+    /// Binding an AF_XDP socket to the host interface failed.
+    XdpOpen(io::Error),
+    /// Switching to or back from the network namespace of the host interface failed.
+    SetNetns(io::Error),
     /// Setting tap interface offload flags failed.
     TapSetOffload(TapError),
     /// Setting vnet header size failed.
     TapSetVnetHdrSize(TapError),
     /// Enabling tap interface failed.
     TapEnable(TapError),
+    /// Invalid number of RX/TX queue pairs.
+    InvalidQueuePairs(usize),
+    /// Invalid MTU.
+    InvalidMtu(u16),
+    /// Setting the MTU of the host-side backend failed.
+    SetMtu(io::Error),
+    /// Setting the offloads of the host-side backend failed.
+    SetOffloads(io::Error),
+    /// The vhost-net datapath is only available for TAP backends.
+    VhostUnsupportedBackend,
+    /// Setting up vhost-net or the vhost-user backend failed.
+    Vhost(VhostError),
+    /// The device was created without a guest MAC address, so the driver doesn't read one.
+    GuestMacNotAdvertised,
+    /// Frames offloaded to vhost-net can't be captured.
+    VhostCapture,
     /// EventFd error.
     EventFd(io::Error),
     /// IO error.
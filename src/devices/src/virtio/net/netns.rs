@@ -0,0 +1,69 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helper for opening host-side interfaces which live in another network namespace.
+//!
+//! Only the calling thread switches namespaces, and it goes back to its own namespace before
+//! returning. File descriptors of interfaces and sockets opened in the meantime remain bound to
+//! the namespace they were opened in.
+
+use std::fs::File;
+use std::io::Error as IoError;
+use std::os::unix::io::AsRawFd;
+
+use super::{Error, Result};
+
+const THREAD_NETNS_PATH: &str = "/proc/thread-self/ns/net";
+
+fn set_netns(netns: &File) -> Result<()> {
+    // This is safe since we check the return value.
+    let ret = unsafe { libc::setns(netns.as_raw_fd(), libc::CLONE_NEWNET) };
+    if ret < 0 {
+        return Err(Error::SetNetns(IoError::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Runs `f` inside the network namespace at `netns`, e.g. `/var/run/netns/foo`, or in the
+/// current one when `netns` isn't set.
+pub fn with_netns<T, F>(netns: Option<&str>, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let netns = match netns {
+        Some(netns) => netns,
+        None => return f(),
+    };
+
+    let original = File::open(THREAD_NETNS_PATH).map_err(Error::SetNetns)?;
+    let target = File::open(netns).map_err(Error::SetNetns)?;
+    set_netns(&target)?;
+
+    let result = f();
+    // The thread must not be left in the target namespace, whatever `f` returned.
+    set_netns(&original)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_netns() {
+        assert_eq!(with_netns(None, || Ok(1)).unwrap(), 1);
+        assert!(matches!(
+            with_netns(None, || -> Result<()> { Err(Error::VnetHeaderMissing) }),
+            Err(Error::VnetHeaderMissing)
+        ));
+
+        // The closure isn't run when the namespace can't be entered.
+        let mut called = false;
+        let res = with_netns(Some("/var/run/netns/fc-none"), || {
+            called = true;
+            Ok(())
+        });
+        assert!(matches!(res, Err(Error::SetNetns(_))));
+        assert!(!called);
+    }
+}
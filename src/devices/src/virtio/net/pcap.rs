@@ -0,0 +1,201 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writer for the pcap file format, used to mirror the frames going through a net device.
+//!
+//! The capture is spread over a ring of files, which are overwritten in turn once they reach
+//! their size limit.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use utils::time::{get_time_us, ClockType};
+
+use crate::virtio::net::MAX_BUFFER_SIZE;
+
+// https://wiki.wireshark.org/Development/LibpcapFileFormat
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const LINKTYPE_ETHERNET: u32 = 1;
+const GLOBAL_HEADER_LEN: u64 = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+/// Writes Ethernet frames to a ring of pcap files.
+#[derive(Debug)]
+pub struct PcapWriter {
+    path: PathBuf,
+    file: File,
+    // The size above which the next file of the ring is used, if any.
+    max_file_size: Option<u64>,
+    file_count: u32,
+    file_index: u32,
+    file_size: u64,
+    record: Vec<u8>,
+}
+
+impl PcapWriter {
+    /// Starts a capture in the file at `path`. When `max_file_size` is set, the capture moves
+    /// on to the next of `file_count` files once the current one is full. The first file is
+    /// `path`, the following ones have the file index appended to it, e.g. `path.1`.
+    pub fn new(path: &Path, max_file_size: Option<u64>, file_count: u32) -> io::Result<Self> {
+        let file_count = file_count.max(1);
+        let file = Self::create_file(path)?;
+
+        Ok(PcapWriter {
+            path: path.to_path_buf(),
+            file,
+            max_file_size,
+            file_count,
+            file_index: 0,
+            file_size: GLOBAL_HEADER_LEN,
+            record: Vec::with_capacity(RECORD_HEADER_LEN + MAX_BUFFER_SIZE),
+        })
+    }
+
+    /// Provides the path of the first file of the capture.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn file_path(&self, index: u32) -> PathBuf {
+        if index == 0 {
+            self.path.clone()
+        } else {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", index));
+            PathBuf::from(path)
+        }
+    }
+
+    fn create_file(path: &Path) -> io::Result<File> {
+        let mut file = File::create(path)?;
+
+        let mut header = Vec::with_capacity(GLOBAL_HEADER_LEN as usize);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+        // Timestamps are in UTC and their accuracy isn't known.
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(MAX_BUFFER_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        file.write_all(&header)?;
+
+        Ok(file)
+    }
+
+    // Moves the capture to the next file of the ring, overwriting it.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file_index = (self.file_index + 1) % self.file_count;
+        self.file = Self::create_file(&self.file_path(self.file_index))?;
+        self.file_size = GLOBAL_HEADER_LEN;
+        Ok(())
+    }
+
+    /// Appends an Ethernet frame to the capture, stamped with the current time.
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let record_len = (RECORD_HEADER_LEN + frame.len()) as u64;
+        if let Some(max_file_size) = self.max_file_size {
+            // A file always holds at least one record, however large it is.
+            if self.file_size > GLOBAL_HEADER_LEN && self.file_size + record_len > max_file_size {
+                self.rotate()?;
+            }
+        }
+
+        let now_us = get_time_us(ClockType::Real);
+        self.record.clear();
+        self.record
+            .extend_from_slice(&((now_us / 1_000_000) as u32).to_le_bytes());
+        self.record
+            .extend_from_slice(&((now_us % 1_000_000) as u32).to_le_bytes());
+        self.record
+            .extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        self.record
+            .extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        self.record.extend_from_slice(frame);
+        self.file.write_all(&self.record)?;
+        self.file_size += record_len;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    fn read_records(path: &Path) -> Vec<Vec<u8>> {
+        let data = fs::read(path).unwrap();
+        assert_eq!(&data[..4], &PCAP_MAGIC.to_le_bytes());
+        assert_eq!(&data[20..24], &LINKTYPE_ETHERNET.to_le_bytes());
+
+        let mut records = Vec::new();
+        let mut offset = GLOBAL_HEADER_LEN as usize;
+        while offset < data.len() {
+            let incl_len = u32::from_le_bytes([
+                data[offset + 8],
+                data[offset + 9],
+                data[offset + 10],
+                data[offset + 11],
+            ]) as usize;
+            let start = offset + RECORD_HEADER_LEN;
+            records.push(data[start..start + incl_len].to_vec());
+            offset = start + incl_len;
+        }
+        records
+    }
+
+    #[test]
+    fn test_write_frame() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("capture.pcap");
+        let mut writer = PcapWriter::new(&path, None, 1).unwrap();
+        assert_eq!(writer.path(), path.as_path());
+
+        writer.write_frame(&[1u8; 60]).unwrap();
+        writer.write_frame(&[2u8; 100]).unwrap();
+
+        assert_eq!(read_records(&path), vec![vec![1u8; 60], vec![2u8; 100]]);
+    }
+
+    #[test]
+    fn test_file_ring() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("capture.pcap");
+        let path_1 = dir.as_path().join("capture.pcap.1");
+        // Each file has room for two 60 bytes frames.
+        let max_file_size = GLOBAL_HEADER_LEN + 2 * (RECORD_HEADER_LEN as u64 + 60);
+        let mut writer = PcapWriter::new(&path, Some(max_file_size), 2).unwrap();
+
+        for i in 0..5u8 {
+            writer.write_frame(&[i; 60]).unwrap();
+        }
+        // The first file was overwritten by the fifth frame.
+        assert_eq!(read_records(&path), vec![vec![4u8; 60]]);
+        assert_eq!(read_records(&path_1), vec![vec![2u8; 60], vec![3u8; 60]]);
+
+        // Frames larger than the limit still make it to the capture.
+        writer.write_frame(&[5u8; 200]).unwrap();
+        assert_eq!(read_records(&path_1), vec![vec![5u8; 200]]);
+    }
+
+    #[test]
+    fn test_single_file_ring() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("capture.pcap");
+        let max_file_size = GLOBAL_HEADER_LEN + RECORD_HEADER_LEN as u64 + 60;
+        // A file count of 0 is handled as a single file.
+        let mut writer = PcapWriter::new(&path, Some(max_file_size), 0).unwrap();
+
+        writer.write_frame(&[1u8; 60]).unwrap();
+        writer.write_frame(&[2u8; 60]).unwrap();
+        assert_eq!(read_records(&path), vec![vec![2u8; 60]]);
+        assert!(!dir.as_path().join("capture.pcap.1").exists());
+    }
+}
@@ -3,9 +3,9 @@
 
 //! Defines the structures needed for saving/restoring net devices.
 
-use std::io;
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
+use std::{cmp, io};
 
 use mmds::data_store::Mmds;
 use mmds::ns::MmdsNetworkStack;
@@ -14,12 +14,18 @@ use rate_limiter::persist::RateLimiterState;
 use rate_limiter::RateLimiter;
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
+use virtio_gen::virtio_net::VIRTIO_NET_F_CTRL_RX;
 use vm_memory::GuestMemoryMmap;
 
 use super::device::{ConfigSpace, Net};
-use super::{NUM_QUEUES, QUEUE_SIZE};
+use super::l3_filter::Ipv4Cidr;
+use super::netns::with_netns;
+use super::rx_filter::{MacTable, RxFilter};
+use super::{
+    NetFilterAction, NetFilterDirection, NetFilterProtocol, NetFilterRule, NUM_QUEUES, QUEUE_SIZE,
+};
 use crate::virtio::persist::{Error as VirtioStateError, VirtioDeviceState};
 use crate::virtio::{DeviceState, TYPE_NET};
 
@@ -27,18 +33,227 @@ use crate::virtio::{DeviceState, TYPE_NET};
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct NetConfigSpaceState {
     guest_mac: [u8; MAC_ADDR_LEN],
+    // Zero when no MTU is advertised to the guest.
+    #[version(start = 2, default_fn = "def_mtu", ser_fn = "ser_mtu")]
+    mtu: u16,
+    #[version(start = 2, default_fn = "def_link_up", ser_fn = "ser_link_up")]
+    link_up: bool,
+}
+
+impl NetConfigSpaceState {
+    fn def_mtu(_: u16) -> u16 {
+        0
+    }
+
+    fn ser_mtu(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.mtu != 0 {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the net device MTU.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn def_link_up(_: u16) -> bool {
+        true
+    }
+
+    fn ser_link_up(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.link_up {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the net device link state.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct RxFilterState {
+    promisc: bool,
+    all_multi: bool,
+    unicast: Vec<[u8; MAC_ADDR_LEN]>,
+    unicast_overflow: bool,
+    multicast: Vec<[u8; MAC_ADDR_LEN]>,
+    multicast_overflow: bool,
+}
+
+impl From<&RxFilter> for RxFilterState {
+    fn from(filter: &RxFilter) -> Self {
+        RxFilterState {
+            promisc: filter.promisc,
+            all_multi: filter.all_multi,
+            unicast: filter.unicast.addresses.clone(),
+            unicast_overflow: filter.unicast.overflow,
+            multicast: filter.multicast.addresses.clone(),
+            multicast_overflow: filter.multicast.overflow,
+        }
+    }
+}
+
+impl From<&RxFilterState> for RxFilter {
+    fn from(state: &RxFilterState) -> Self {
+        RxFilter {
+            promisc: state.promisc,
+            all_multi: state.all_multi,
+            unicast: MacTable {
+                addresses: state.unicast.clone(),
+                overflow: state.unicast_overflow,
+            },
+            multicast: MacTable {
+                addresses: state.multicast.clone(),
+                overflow: state.multicast_overflow,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct NetFilterRuleState {
+    allow: bool,
+    ingress: bool,
+    addr: u32,
+    prefix_len: u8,
+    // The IP protocol number.
+    protocol: Option<u8>,
+    port: Option<u16>,
+}
+
+impl From<&NetFilterRule> for NetFilterRuleState {
+    fn from(rule: &NetFilterRule) -> Self {
+        NetFilterRuleState {
+            allow: rule.action == NetFilterAction::Allow,
+            ingress: rule.direction == NetFilterDirection::Ingress,
+            addr: u32::from(rule.cidr.addr()),
+            prefix_len: rule.cidr.prefix_len(),
+            protocol: rule.protocol.map(NetFilterProtocol::number),
+            port: rule.port,
+        }
+    }
+}
+
+impl NetFilterRuleState {
+    fn to_rule(&self) -> Option<NetFilterRule> {
+        let protocol = match self.protocol {
+            Some(number) => Some(NetFilterProtocol::from_number(number)?),
+            None => None,
+        };
+        Some(NetFilterRule {
+            action: if self.allow {
+                NetFilterAction::Allow
+            } else {
+                NetFilterAction::Deny
+            },
+            direction: if self.ingress {
+                NetFilterDirection::Ingress
+            } else {
+                NetFilterDirection::Egress
+            },
+            cidr: Ipv4Cidr::new(self.addr.into(), self.prefix_len)?,
+            protocol,
+            port: self.port,
+        })
+    }
 }
 
 #[derive(Clone, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct NetState {
     id: String,
-    tap_if_name: String,
+    pub tap_if_name: String,
     rx_rate_limiter_state: RateLimiterState,
     tx_rate_limiter_state: RateLimiterState,
     pub mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    #[version(
+        start = 2,
+        default_fn = "def_active_queue_pairs",
+        ser_fn = "ser_active_queue_pairs"
+    )]
+    active_queue_pairs: u16,
+    #[version(start = 2, default_fn = "def_netns", ser_fn = "ser_netns")]
+    netns: Option<String>,
+    #[version(start = 2, default_fn = "def_rx_filter", ser_fn = "ser_rx_filter")]
+    rx_filter: RxFilterState,
+    #[version(start = 2, default_fn = "def_filters", ser_fn = "ser_filters")]
+    filters: Vec<NetFilterRuleState>,
+}
+
+impl NetState {
+    /// Lists the fields of the state this implementation of the device can't be restored from.
+    pub fn incompatible_fields(&self) -> Vec<String> {
+        self.virtio_state.incompatible_fields(TYPE_NET, QUEUE_SIZE)
+    }
+
+    fn def_active_queue_pairs(_: u16) -> u16 {
+        1
+    }
+
+    fn ser_active_queue_pairs(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.num_queue_pairs() != 1 {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement multi-queue net devices.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn def_netns(_: u16) -> Option<String> {
+        None
+    }
+
+    fn ser_netns(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.netns.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement net device namespaces.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn def_rx_filter(_: u16) -> RxFilterState {
+        RxFilterState::from(&RxFilter::default())
+    }
+
+    fn ser_rx_filter(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.has_rx_filtering() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement net device RX filtering.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn def_filters(_: u16) -> Vec<NetFilterRuleState> {
+        Vec::new()
+    }
+
+    fn ser_filters(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.filters.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement net device filter rules.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn has_rx_filtering(&self) -> bool {
+        self.virtio_state.avail_features & (1 << VIRTIO_NET_F_CTRL_RX) != 0
+    }
+
+    // Multi-queue devices also have a control queue, which doesn't count towards the pairs.
+    fn num_queue_pairs(&self) -> usize {
+        self.virtio_state.queues.len() / NUM_QUEUES
+    }
 }
 
 pub struct NetConstructorArgs {
@@ -52,6 +267,7 @@ pub enum Error {
     CreateRateLimiter(io::Error),
     VirtioState(VirtioStateError),
     NoMmdsDataStore,
+    InvalidFilterRule,
 }
 
 impl Persist<'_> for Net {
@@ -68,8 +284,18 @@ impl Persist<'_> for Net {
             mmds_ns: self.mmds_ns.as_ref().map(|mmds| mmds.save()),
             config_space: NetConfigSpaceState {
                 guest_mac: self.config_space.guest_mac,
+                mtu: self.mtu().unwrap_or(0),
+                link_up: self.link_up(),
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            active_queue_pairs: self.active_queue_pairs as u16,
+            netns: self.netns.clone(),
+            rx_filter: RxFilterState::from(&self.rx_filter),
+            filters: self
+                .filters()
+                .iter()
+                .map(NetFilterRuleState::from)
+                .collect(),
         }
     }
 
@@ -82,14 +308,31 @@ impl Persist<'_> for Net {
             .map_err(Error::CreateRateLimiter)?;
         let tx_rate_limiter = RateLimiter::restore((), &state.tx_rate_limiter_state)
             .map_err(Error::CreateRateLimiter)?;
-        let mut net = Net::new_with_tap(
-            state.id.clone(),
-            state.tap_if_name.clone(),
-            None,
-            rx_rate_limiter,
-            tx_rate_limiter,
-        )
+        let mut net = with_netns(state.netns.as_deref(), || {
+            Net::new_with_tap(
+                state.id.clone(),
+                state.tap_if_name.clone(),
+                None,
+                rx_rate_limiter,
+                tx_rate_limiter,
+                state.num_queue_pairs(),
+            )
+        })
         .map_err(Error::CreateNet)?;
+        net.netns = state.netns.clone();
+        // The control queue of single queue pair devices only exists with RX filtering.
+        if state.has_rx_filtering() {
+            net.enable_rx_filtering().map_err(Error::CreateNet)?;
+            net.rx_filter = RxFilter::from(&state.rx_filter);
+        }
+        net.set_filters(
+            state
+                .filters
+                .iter()
+                .map(NetFilterRuleState::to_rule)
+                .collect::<Option<_>>()
+                .ok_or(Error::InvalidFilterRule)?,
+        );
 
         // We trust the MMIODeviceManager::restore to pass us an MMDS data store reference if
         // there is at least one net device having the MMDS NS present and/or the mmds version was
@@ -110,21 +353,36 @@ impl Persist<'_> for Net {
 
         net.queues = state
             .virtio_state
-            .build_queues_checked(&constructor_args.mem, TYPE_NET, NUM_QUEUES, QUEUE_SIZE)
+            .build_queues_checked(
+                &constructor_args.mem,
+                TYPE_NET,
+                net.queues.len(),
+                QUEUE_SIZE,
+            )
             .map_err(Error::VirtioState)?;
         net.irq_trigger.irq_status =
             Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         net.avail_features = state.virtio_state.avail_features;
         net.acked_features = state.virtio_state.acked_features;
+        // The host interface was opened with all the offloads, which the guest may not expect.
+        net.apply_backend_offloads().map_err(Error::CreateNet)?;
+        // The MTU of the host interface is left as is, only the guest view is restored.
         net.config_space = ConfigSpace {
             guest_mac: state.config_space.guest_mac,
+            mtu: state.config_space.mtu.to_le(),
+            ..net.config_space
         };
+        net.set_link_up(state.config_space.link_up);
 
         net.guest_mac = Some(MacAddr::from_bytes_unchecked(
             &state.config_space.guest_mac[..MAC_ADDR_LEN],
         ));
 
         if state.virtio_state.activated {
+            net.set_active_queue_pairs(cmp::min(
+                usize::from(state.active_queue_pairs),
+                net.num_queue_pairs(),
+            ));
             net.device_state = DeviceState::Activated(constructor_args.mem);
         }
 
@@ -216,4 +474,219 @@ mod tests {
         // data store. This will return an error.
         validate_save_and_restore(default_net(), None);
     }
+
+    #[test]
+    fn test_persistence_multi_queue() {
+        let net = Net::new_with_tap(
+            "mq-net".to_string(),
+            "mq-net-tap".to_string(),
+            None,
+            RateLimiter::default(),
+            RateLimiter::default(),
+            4,
+        )
+        .unwrap();
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+        let mut mem = vec![0; 4096];
+
+        // Multi-queue devices can't be saved for versions which don't support them.
+        assert!(<Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_guest_memory(),
+                mmds: None,
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.num_queue_pairs(), 4);
+        // 4 RX/TX queue pairs and the control queue.
+        assert_eq!(restored_net.queues().len(), 9);
+        assert_eq!(restored_net.config_space.max_virtqueue_pairs, 4);
+    }
+
+    #[test]
+    fn test_persistence_mtu() {
+        let mut net = default_net();
+        net.set_mtu(1400).unwrap();
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2)
+            .set_type_version(NetConfigSpaceState::type_id(), 2);
+        let mut mem = vec![0; 4096];
+
+        // The MTU can't be saved for versions which don't support it.
+        assert!(<Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_guest_memory(),
+                mmds: None,
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.mtu(), Some(1400));
+    }
+
+    #[test]
+    fn test_persistence_netns() {
+        let mut net = default_net();
+        net.set_netns(Some("/var/run/netns/fc-test".to_string()));
+        assert_eq!(net.netns(), Some("/var/run/netns/fc-test"));
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+        let mut mem = vec![0; 4096];
+
+        // The namespace can't be saved for versions which don't support it.
+        assert!(<Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let state = NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(state.netns.as_deref(), Some("/var/run/netns/fc-test"));
+    }
+
+    #[test]
+    fn test_persistence_rx_filter() {
+        let mut net = default_net();
+        net.enable_rx_filtering().unwrap();
+        net.rx_filter.promisc = false;
+        net.rx_filter
+            .multicast
+            .addresses
+            .push([0x01, 0, 0x5e, 0, 0, 1]);
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+        let mut mem = vec![0; 4096];
+
+        // RX filtering can't be saved for versions which don't support it.
+        assert!(<Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let rx_filter = net.rx_filter.clone();
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_guest_memory(),
+                mmds: None,
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert!(restored_net.rx_filtering());
+        // The RX/TX queue pair and the control queue.
+        assert_eq!(restored_net.queues().len(), 3);
+        assert_eq!(restored_net.rx_filter, rx_filter);
+    }
+
+    #[test]
+    fn test_persistence_link_up() {
+        let mut net = default_net();
+        net.set_link_up(false);
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2)
+            .set_type_version(NetConfigSpaceState::type_id(), 2);
+        let mut mem = vec![0; 4096];
+
+        // A link down can't be saved for versions which don't support it.
+        assert!(<Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_guest_memory(),
+                mmds: None,
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert!(!restored_net.link_up());
+    }
+
+    #[test]
+    fn test_persistence_filters() {
+        let mut net = default_net();
+        let rules = vec![
+            NetFilterRule {
+                action: NetFilterAction::Allow,
+                direction: NetFilterDirection::Egress,
+                cidr: Ipv4Cidr::parse_str("10.0.0.0/8").unwrap(),
+                protocol: Some(NetFilterProtocol::Tcp),
+                port: Some(443),
+            },
+            NetFilterRule {
+                action: NetFilterAction::Deny,
+                direction: NetFilterDirection::Ingress,
+                cidr: Ipv4Cidr::parse_str("0.0.0.0/0").unwrap(),
+                protocol: None,
+                port: None,
+            },
+        ];
+        net.set_filters(rules.clone());
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+        let mut mem = vec![0; 4096];
+
+        // Filter rules can't be saved for versions which don't support them.
+        assert!(<Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Net as Persist>::save(&net)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_guest_memory(),
+                mmds: None,
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.filters(), &rules[..]);
+    }
 }
@@ -0,0 +1,184 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Filtering of the frames received by a net device, as programmed by the guest through the
+//! `VIRTIO_NET_CTRL_RX` and `VIRTIO_NET_CTRL_MAC` control queue commands.
+
+use std::convert::TryInto;
+
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+// Control queue command classes and commands, as defined by the virtio spec.
+pub(crate) const VIRTIO_NET_CTRL_RX: u8 = 0;
+pub(crate) const VIRTIO_NET_CTRL_RX_PROMISC: u8 = 0;
+pub(crate) const VIRTIO_NET_CTRL_RX_ALLMULTI: u8 = 1;
+pub(crate) const VIRTIO_NET_CTRL_MAC: u8 = 1;
+pub(crate) const VIRTIO_NET_CTRL_MAC_TABLE_SET: u8 = 0;
+
+/// The number of addresses of a MAC table above which all the frames of its kind are
+/// accepted, rather than only the ones sent to the addresses of the table.
+pub(crate) const MAC_TABLE_ENTRIES: usize = 64;
+
+/// A table of unicast or multicast MAC addresses the guest wants to receive frames for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct MacTable {
+    pub(crate) addresses: Vec<[u8; MAC_ADDR_LEN]>,
+    // Set when the guest asked for more addresses than the table holds.
+    pub(crate) overflow: bool,
+}
+
+impl MacTable {
+    // Parses a table as sent by the guest, a little endian 32 bit count followed by as many
+    // addresses, and returns it along with the bytes following it.
+    fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        let count = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let len = count.checked_mul(MAC_ADDR_LEN)?;
+        let addresses = data.get(4..)?.get(..len)?;
+        let table = if count > MAC_TABLE_ENTRIES {
+            MacTable {
+                addresses: Vec::new(),
+                overflow: true,
+            }
+        } else {
+            MacTable {
+                addresses: addresses
+                    .chunks_exact(MAC_ADDR_LEN)
+                    .map(|mac| mac.try_into().unwrap())
+                    .collect(),
+                overflow: false,
+            }
+        };
+        Some((table, &data[4 + len..]))
+    }
+
+    fn contains(&self, mac: &[u8]) -> bool {
+        self.overflow || self.addresses.iter().any(|addr| addr[..] == *mac)
+    }
+}
+
+/// The receive filter of a net device. Until the guest programs it, all frames are accepted.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RxFilter {
+    pub(crate) promisc: bool,
+    pub(crate) all_multi: bool,
+    pub(crate) unicast: MacTable,
+    pub(crate) multicast: MacTable,
+}
+
+impl Default for RxFilter {
+    fn default() -> Self {
+        RxFilter {
+            promisc: true,
+            all_multi: false,
+            unicast: MacTable::default(),
+            multicast: MacTable::default(),
+        }
+    }
+}
+
+impl RxFilter {
+    /// Turns the `VIRTIO_NET_CTRL_RX` mode `command` on or off. Returns false for the modes
+    /// which aren't supported.
+    pub(crate) fn set_rx_mode(&mut self, command: u8, on: bool) -> bool {
+        match command {
+            VIRTIO_NET_CTRL_RX_PROMISC => self.promisc = on,
+            VIRTIO_NET_CTRL_RX_ALLMULTI => self.all_multi = on,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Replaces the unicast and multicast MAC tables with the ones of a
+    /// `VIRTIO_NET_CTRL_MAC_TABLE_SET` command. Returns false, leaving the tables unchanged,
+    /// when `data` doesn't hold exactly two tables.
+    pub(crate) fn set_mac_tables(&mut self, data: &[u8]) -> bool {
+        let tables = MacTable::parse(data).and_then(|(unicast, data)| {
+            MacTable::parse(data).map(|(multicast, data)| (unicast, multicast, data))
+        });
+        match tables {
+            Some((unicast, multicast, rest)) if rest.is_empty() => {
+                self.unicast = unicast;
+                self.multicast = multicast;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether a frame sent to `dst` should reach the guest, whose own address is
+    /// `guest_mac`. Unicast frames are all accepted when the guest address is unknown.
+    pub(crate) fn accepts(&self, guest_mac: Option<&MacAddr>, dst: &[u8]) -> bool {
+        if self.promisc {
+            return true;
+        }
+        if dst.iter().all(|byte| *byte == 0xff) {
+            return true;
+        }
+        // The multicast bit is the least significant bit of the first byte.
+        if dst[0] & 1 != 0 {
+            return self.all_multi || self.multicast.contains(dst);
+        }
+        guest_mac.map_or(true, |mac| mac.get_bytes() == dst) || self.unicast.contains(dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac_table(addresses: &[[u8; MAC_ADDR_LEN]]) -> Vec<u8> {
+        let mut table = (addresses.len() as u32).to_le_bytes().to_vec();
+        for mac in addresses {
+            table.extend_from_slice(mac);
+        }
+        table
+    }
+
+    #[test]
+    fn test_rx_filter() {
+        let guest_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let other_mac = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbd];
+        let secondary_mac = [0x02, 0, 0, 0, 0, 1];
+        let multicast_mac = [0x01, 0, 0x5e, 0, 0, 1];
+        let broadcast_mac = [0xff; MAC_ADDR_LEN];
+
+        let mut filter = RxFilter::default();
+        assert!(filter.accepts(Some(&guest_mac), &other_mac));
+        assert!(filter.set_rx_mode(VIRTIO_NET_CTRL_RX_PROMISC, false));
+        assert!(!filter.set_rx_mode(2, true));
+
+        assert!(filter.accepts(Some(&guest_mac), guest_mac.get_bytes()));
+        assert!(filter.accepts(Some(&guest_mac), &broadcast_mac));
+        assert!(!filter.accepts(Some(&guest_mac), &other_mac));
+        assert!(!filter.accepts(Some(&guest_mac), &multicast_mac));
+        // Without a known guest address, unicast frames go through.
+        assert!(filter.accepts(None, &other_mac));
+
+        let mut tables = mac_table(&[secondary_mac]);
+        tables.extend(mac_table(&[multicast_mac]));
+        assert!(filter.set_mac_tables(&tables));
+        assert!(filter.accepts(Some(&guest_mac), &secondary_mac));
+        assert!(filter.accepts(Some(&guest_mac), &multicast_mac));
+        assert!(!filter.accepts(Some(&guest_mac), &[0x01, 0, 0x5e, 0, 0, 2]));
+
+        assert!(filter.set_rx_mode(VIRTIO_NET_CTRL_RX_ALLMULTI, true));
+        assert!(filter.accepts(Some(&guest_mac), &[0x01, 0, 0x5e, 0, 0, 2]));
+
+        // Tables too large to hold accept all the frames of their kind.
+        let mut tables = mac_table(&vec![secondary_mac; MAC_TABLE_ENTRIES + 1]);
+        tables.extend(mac_table(&[]));
+        assert!(filter.set_mac_tables(&tables));
+        assert!(filter.unicast.overflow);
+        assert!(filter.accepts(Some(&guest_mac), &other_mac));
+
+        // Malformed tables are rejected and leave the filter as is.
+        let before = filter.clone();
+        assert!(!filter.set_mac_tables(&mac_table(&[secondary_mac])));
+        let mut tables = mac_table(&[]);
+        tables.extend(mac_table(&[]));
+        tables.push(0);
+        assert!(!filter.set_mac_tables(&tables));
+        assert!(!filter.set_mac_tables(&[1, 0, 0, 0, 1]));
+        assert_eq!(filter, before);
+    }
+}
@@ -0,0 +1,283 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Test-only net backend exchanging raw L2 frames over a unix `SOCK_SEQPACKET` socket.
+
+use std::fs::File;
+use std::io::{Error as IoError, Read, Result as IoResult, Write};
+use std::mem::ManuallyDrop;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::{cmp, mem};
+
+use crate::virtio::net::backend::{NetBackend, NetBackendType};
+use crate::virtio::net::device::vnet_hdr_len;
+
+/// Net backend connected to a `SOCK_SEQPACKET` unix socket owned by a test harness.
+///
+/// Each message on the socket carries exactly one L2 frame, without any vnet header.
+#[derive(Debug)]
+pub struct SocketPair {
+    socket: File,
+    // Empty when the connected socket was handed over by the harness.
+    path: String,
+}
+
+impl SocketPair {
+    /// Takes ownership of `fd`, a connected `SOCK_SEQPACKET` unix socket inherited from the
+    /// harness, e.g. one end of a `socketpair(2)`.
+    pub fn from_fd(fd: RawFd) -> IoResult<SocketPair> {
+        // The fd is only owned once we know it's a seqpacket socket, so that a wrong fd doesn't
+        // end up closing a file we don't own.
+        let socket = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+
+        let mut sock_type: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // This is safe since `sock_type` and `len` outlive the call, and we check the return
+        // value.
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TYPE,
+                &mut sock_type as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(IoError::last_os_error());
+        }
+        if sock_type != libc::SOCK_SEQPACKET {
+            return Err(IoError::from_raw_os_error(libc::EPROTOTYPE));
+        }
+
+        Self::new(ManuallyDrop::into_inner(socket), String::new())
+    }
+
+    /// Connects to the `SOCK_SEQPACKET` unix socket listening at `path`.
+    pub fn connect(path: &str) -> IoResult<SocketPair> {
+        // This is safe since we check the return value.
+        let fd =
+            unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+        // We just checked that the fd is valid.
+        let socket = unsafe { File::from_raw_fd(fd) };
+
+        // This is safe; an all-zero `sockaddr_un` is a valid value.
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        let path_bytes = Path::new(path).as_os_str().as_bytes();
+        // Leave room for the null terminator.
+        if path_bytes.len() >= addr.sun_path.len() {
+            return Err(IoError::from_raw_os_error(libc::ENAMETOOLONG));
+        }
+        for (dst, src) in addr.sun_path.iter_mut().zip(path_bytes) {
+            *dst = *src as libc::c_char;
+        }
+
+        // This is safe since `addr` is a properly initialized `sockaddr_un` and we check the
+        // return value.
+        let ret = unsafe {
+            libc::connect(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Self::new(socket, path.to_string())
+    }
+
+    fn new(socket: File, path: String) -> IoResult<SocketPair> {
+        // The device expects a non-blocking backend, like the TAP.
+        // This is safe since we check the return value.
+        let flags = unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 {
+            return Err(IoError::last_os_error());
+        }
+        // This is safe since we check the return value.
+        let ret =
+            unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) };
+        if ret < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(SocketPair { socket, path })
+    }
+}
+
+impl NetBackend for SocketPair {
+    fn read_frame(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let hdr_len = vnet_hdr_len();
+        let len = self.socket.read(&mut buf[hdr_len..])?;
+        // The harness doesn't deal with offloads, so hand an empty vnet header to the guest.
+        for b in &mut buf[..hdr_len] {
+            *b = 0;
+        }
+        Ok(hdr_len + len)
+    }
+
+    fn write_frame(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let hdr_len = cmp::min(vnet_hdr_len(), buf.len());
+        self.socket.write(&buf[hdr_len..]).map(|len| len + hdr_len)
+    }
+
+    fn if_name(&self) -> String {
+        self.path.clone()
+    }
+
+    fn backend_type(&self) -> NetBackendType {
+        NetBackendType::SocketPair
+    }
+}
+
+impl AsRawFd for SocketPair {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    fn seqpacket_listener(path: &str) -> File {
+        let _ = std::fs::remove_file(path);
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0) };
+        assert!(fd >= 0);
+        let listener = unsafe { File::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, src) in addr.sun_path.iter_mut().zip(path.as_bytes()) {
+            *dst = *src as libc::c_char;
+        }
+        let ret = unsafe {
+            libc::bind(
+                listener.as_raw_fd(),
+                &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(ret, 0);
+        assert_eq!(unsafe { libc::listen(listener.as_raw_fd(), 1) }, 0);
+
+        listener
+    }
+
+    fn accept(listener: &File) -> File {
+        let fd = unsafe {
+            libc::accept(
+                listener.as_raw_fd(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        assert!(fd >= 0);
+        unsafe { File::from_raw_fd(fd) }
+    }
+
+    #[test]
+    fn test_connect_errors() {
+        // Nobody is listening on this path.
+        assert!(SocketPair::connect("/tmp/fc-socketpair-nonexistent.sock").is_err());
+        // A stream socket doesn't accept seqpacket connections.
+        let path = "/tmp/fc-socketpair-stream.sock";
+        let _ = std::fs::remove_file(path);
+        let _listener = UnixListener::bind(path).unwrap();
+        assert!(SocketPair::connect(path).is_err());
+        // Path too long.
+        assert!(SocketPair::connect(&"a".repeat(200)).is_err());
+    }
+
+    #[test]
+    fn test_read_write_frame() {
+        let path = "/tmp/fc-socketpair-rw.sock";
+        let listener = seqpacket_listener(path);
+        let mut backend = SocketPair::connect(path).unwrap();
+        let mut harness = accept(&listener);
+        assert_eq!(backend.if_name(), path);
+        assert_eq!(backend.backend_type(), NetBackendType::SocketPair);
+
+        let hdr_len = vnet_hdr_len();
+
+        // Nothing to read yet.
+        let mut buf = [0xffu8; 128];
+        assert_eq!(
+            backend.read_frame(&mut buf).unwrap_err().raw_os_error(),
+            Some(libc::EAGAIN)
+        );
+
+        // Harness -> guest: the frame gets prefixed by an empty vnet header.
+        harness.write_all(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(backend.read_frame(&mut buf).unwrap(), hdr_len + 4);
+        assert!(buf[..hdr_len].iter().all(|b| *b == 0));
+        assert_eq!(&buf[hdr_len..hdr_len + 4], &[1, 2, 3, 4]);
+
+        // Guest -> harness: the vnet header gets stripped.
+        let mut frame = vec![0xaau8; hdr_len];
+        frame.extend_from_slice(&[5, 6, 7]);
+        assert_eq!(backend.write_frame(&frame).unwrap(), frame.len());
+        let mut recv = [0u8; 16];
+        assert_eq!(harness.read(&mut recv).unwrap(), 3);
+        assert_eq!(&recv[..3], &[5, 6, 7]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn socket_pair(sock_type: libc::c_int) -> (RawFd, File) {
+        let mut fds = [0; 2];
+        assert_eq!(
+            unsafe { libc::socketpair(libc::AF_UNIX, sock_type, 0, fds.as_mut_ptr()) },
+            0
+        );
+        (fds[0], unsafe { File::from_raw_fd(fds[1]) })
+    }
+
+    #[test]
+    fn test_from_fd() {
+        // Not a socket.
+        let file = File::open("/dev/null").unwrap();
+        assert_eq!(
+            SocketPair::from_fd(file.as_raw_fd())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOTSOCK)
+        );
+        // A stream socket doesn't keep the frame boundaries. The fd isn't taken over, so it's
+        // closed by the test.
+        let (fd, _peer) = socket_pair(libc::SOCK_STREAM);
+        let stream = unsafe { File::from_raw_fd(fd) };
+        assert_eq!(
+            SocketPair::from_fd(stream.as_raw_fd())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EPROTOTYPE)
+        );
+
+        let (fd, mut harness) = socket_pair(libc::SOCK_SEQPACKET);
+        let mut backend = SocketPair::from_fd(fd).unwrap();
+        assert_eq!(backend.as_raw_fd(), fd);
+        assert!(backend.if_name().is_empty());
+
+        // The inherited socket is made non-blocking.
+        let mut buf = [0u8; 128];
+        assert_eq!(
+            backend.read_frame(&mut buf).unwrap_err().raw_os_error(),
+            Some(libc::EAGAIN)
+        );
+
+        harness.write_all(&[1, 2, 3]).unwrap();
+        let hdr_len = vnet_hdr_len();
+        assert_eq!(backend.read_frame(&mut buf).unwrap(), hdr_len + 3);
+        assert_eq!(&buf[hdr_len..hdr_len + 3], &[1, 2, 3]);
+    }
+}
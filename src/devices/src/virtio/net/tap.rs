@@ -5,18 +5,25 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
-use std::fs::File;
-use std::io::{Error as IoError, Read, Result as IoResult, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::mem::ManuallyDrop;
 use std::os::raw::*;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
 
 use net_gen::ifreq;
 use utils::ioctl::{ioctl_with_mut_ref, ioctl_with_ref, ioctl_with_val};
-use utils::{ioctl_expr, ioctl_ioc_nr, ioctl_iow_nr};
+use utils::{ioctl_expr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr};
+
+use crate::virtio::net::backend::{NetBackend, NetBackendType, NetOffloads};
 
 // As defined in the Linux UAPI:
 // https://elixir.bootlin.com/linux/v4.17/source/include/uapi/linux/if.h#L33
 const IFACE_NAME_MAX_LEN: usize = 16;
+const TUN_PATH: &str = "/dev/net/tun";
+const SYSFS_NET_PATH: &str = "/sys/class/net";
 
 /// List of errors the tap implementation can throw.
 #[derive(Debug)]
@@ -29,6 +36,14 @@ pub enum Error {
     IoctlError(IoError),
     /// Couldn't open /dev/net/tun.
     OpenTun(IoError),
+    /// Couldn't read the interface index of a macvtap interface.
+    MacvtapIndex(IoError),
+    /// Couldn't open the character device of a macvtap interface.
+    OpenMacvtap(IoError),
+    /// Couldn't create the socket used to configure the interface.
+    CreateSocket(IoError),
+    /// The file descriptor isn't a TAP device opened with the flags the device model needs.
+    InvalidTapFd,
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -36,7 +51,9 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 const TUNTAP: ::std::os::raw::c_uint = 84;
 ioctl_iow_nr!(TUNSETIFF, TUNTAP, 202, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETOFFLOAD, TUNTAP, 208, ::std::os::raw::c_uint);
+ioctl_ior_nr!(TUNGETIFF, TUNTAP, 210, ::std::os::raw::c_uint);
 ioctl_iow_nr!(TUNSETVNETHDRSZ, TUNTAP, 216, ::std::os::raw::c_int);
+ioctl_iow_nr!(TUNSETQUEUE, TUNTAP, 217, ::std::os::raw::c_int);
 
 /// Handle for a network tap interface.
 ///
@@ -51,7 +68,7 @@ pub struct Tap {
 
 // Returns a byte vector representing the contents of a null terminated C string which
 // contains if_name.
-fn build_terminated_if_name(if_name: &str) -> Result<[u8; IFACE_NAME_MAX_LEN]> {
+pub(crate) fn build_terminated_if_name(if_name: &str) -> Result<[u8; IFACE_NAME_MAX_LEN]> {
     // Convert the string slice to bytes, and shadow the variable,
     // since we no longer need the &str version.
     let if_name = if_name.as_bytes();
@@ -66,6 +83,24 @@ fn build_terminated_if_name(if_name: &str) -> Result<[u8; IFACE_NAME_MAX_LEN]> {
     Ok(terminated_if_name)
 }
 
+// Returns the path of the character device backing `if_name` if it's a macvtap interface.
+// Those aren't created through /dev/net/tun, but come with a /dev/tapN device of their own,
+// where N is the interface index.
+fn macvtap_device_path(sysfs_net: &Path, if_name: &str) -> Result<Option<PathBuf>> {
+    let if_dir = sysfs_net.join(if_name);
+    if if_name.is_empty() || !if_dir.join("macvtap").is_dir() {
+        return Ok(None);
+    }
+
+    let if_index = fs::read_to_string(if_dir.join("ifindex"))
+        .map_err(Error::MacvtapIndex)?
+        .trim()
+        .parse::<u32>()
+        .map_err(|e| Error::MacvtapIndex(IoError::new(ErrorKind::InvalidData, e)))?;
+
+    Ok(Some(PathBuf::from(format!("/dev/tap{}", if_index))))
+}
+
 pub struct IfReqBuilder(ifreq);
 
 impl IfReqBuilder {
@@ -89,6 +124,14 @@ impl IfReqBuilder {
         self
     }
 
+    pub(crate) fn mtu(mut self, mtu: i32) -> Self {
+        // Since we don't call as_mut on the same union field more than once, this block is safe.
+        let ifru_mtu = unsafe { self.0.ifr_ifru.ifru_mtu.as_mut() };
+        *ifru_mtu = mtu;
+
+        self
+    }
+
     pub(crate) fn execute<F: AsRawFd>(mut self, socket: &F, ioctl: u64) -> Result<ifreq> {
         // ioctl is safe. Called with a valid socket fd, and we check the return.
         let ret = unsafe { ioctl_with_mut_ref(socket, ioctl, &mut self.0) };
@@ -101,30 +144,93 @@ impl IfReqBuilder {
 }
 
 impl Tap {
-    /// Create a TUN/TAP device given the interface name.
+    /// Create a TUN/TAP device given the interface name. If a macvtap interface with that name
+    /// exists, its character device is opened instead.
     /// # Arguments
     ///
     /// * `if_name` - the name of the interface.
     pub fn open_named(if_name: &str) -> Result<Tap> {
         let terminated_if_name = build_terminated_if_name(if_name)?;
+        let macvtap_path = macvtap_device_path(Path::new(SYSFS_NET_PATH), if_name)?;
 
-        let fd = unsafe {
-            // Open calls are safe because we give a constant null-terminated
-            // string and verify the result.
-            libc::open(
-                b"/dev/net/tun\0".as_ptr() as *const c_char,
-                libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
-            )
-        };
-        if fd < 0 {
-            return Err(Error::OpenTun(IoError::last_os_error()));
+        Self::open_with_flags(
+            &terminated_if_name,
+            macvtap_path.as_deref(),
+            net_gen::IFF_TAP | net_gen::IFF_NO_PI | net_gen::IFF_VNET_HDR,
+        )
+    }
+
+    /// Create a multi-queue TUN/TAP device given the interface name, returning a handle for each
+    /// of its queues.
+    /// # Arguments
+    ///
+    /// * `if_name` - the name of the interface.
+    /// * `num_queues` - the number of queues to attach to the interface.
+    pub fn open_named_multi_queue(if_name: &str, num_queues: usize) -> Result<Vec<Tap>> {
+        let terminated_if_name = build_terminated_if_name(if_name)?;
+        let macvtap_path = macvtap_device_path(Path::new(SYSFS_NET_PATH), if_name)?;
+
+        (0..num_queues)
+            .map(|_| {
+                Self::open_with_flags(
+                    &terminated_if_name,
+                    macvtap_path.as_deref(),
+                    net_gen::IFF_TAP
+                        | net_gen::IFF_NO_PI
+                        | net_gen::IFF_VNET_HDR
+                        | net_gen::IFF_MULTI_QUEUE,
+                )
+            })
+            .collect()
+    }
+
+    /// Create a handle for a TAP device which was opened by someone else, e.g. in another network
+    /// namespace. The handle takes ownership of `fd` if it refers to a suitable TAP device, and
+    /// leaves it untouched otherwise.
+    /// # Arguments
+    ///
+    /// * `fd` - the file descriptor of the TAP device.
+    pub fn from_fd(fd: RawFd) -> Result<Tap> {
+        // The fd is only owned once we know it's a TAP device, so that a wrong fd doesn't end
+        // up closing a file we don't own.
+        let tap_file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+
+        let ifreq = IfReqBuilder::new().execute(&*tap_file, TUNGETIFF())?;
+        // Safe since the kernel filled in the flags.
+        let flags = c_uint::from(unsafe { *ifreq.ifr_ifru.ifru_flags.as_ref() } as u16);
+        let required_flags = net_gen::IFF_TAP | net_gen::IFF_NO_PI | net_gen::IFF_VNET_HDR;
+        if flags & required_flags != required_flags {
+            return Err(Error::InvalidTapFd);
         }
-        // We just checked that the fd is valid.
-        let tuntap = unsafe { File::from_raw_fd(fd) };
+
+        // Safe since only the name is accessed, and it's cloned out.
+        Ok(Tap {
+            tap_file: ManuallyDrop::into_inner(tap_file),
+            if_name: unsafe { *ifreq.ifr_ifrn.ifrn_name.as_ref() },
+        })
+    }
+
+    // Opens a queue of the interface, through its macvtap character device if it has one, or
+    // through /dev/net/tun otherwise. macvtap devices accept the TUN ioctls, but ignore the
+    // interface name.
+    fn open_with_flags(
+        terminated_if_name: &[u8; IFACE_NAME_MAX_LEN],
+        macvtap_path: Option<&Path>,
+        flags: c_uint,
+    ) -> Result<Tap> {
+        let mut options = OpenOptions::new();
+        options
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC);
+        let tuntap = match macvtap_path {
+            Some(path) => options.open(path).map_err(Error::OpenMacvtap)?,
+            None => options.open(TUN_PATH).map_err(Error::OpenTun)?,
+        };
 
         let ifreq = IfReqBuilder::new()
-            .if_name(&terminated_if_name)
-            .flags((net_gen::IFF_TAP | net_gen::IFF_NO_PI | net_gen::IFF_VNET_HDR) as i16)
+            .if_name(terminated_if_name)
+            .flags(flags as i16)
             .execute(&tuntap, TUNSETIFF())?;
 
         // Safe since only the name is accessed, and it's cloned out.
@@ -164,6 +270,48 @@ impl Tap {
 
         Ok(())
     }
+
+    /// Attach or detach this queue of a multi-queue tap interface. The kernel doesn't steer any
+    /// traffic towards detached queues.
+    pub fn set_queue_enabled(&self, enabled: bool) -> Result<()> {
+        let flags = if enabled {
+            net_gen::IFF_ATTACH_QUEUE
+        } else {
+            net_gen::IFF_DETACH_QUEUE
+        };
+        IfReqBuilder::new()
+            .flags(flags as i16)
+            .execute(&self.tap_file, TUNSETQUEUE())?;
+
+        Ok(())
+    }
+
+    /// Set the MTU of the tap interface.
+    pub fn set_mtu(&self, mtu: u16) -> Result<()> {
+        // Interface MTUs can only be changed through a socket, not through the tap fd.
+        // This is safe since we check the return value.
+        let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if sock < 0 {
+            return Err(Error::CreateSocket(IoError::last_os_error()));
+        }
+        // This is safe; nothing else will use or hold onto the raw socket fd.
+        let sock = unsafe { File::from_raw_fd(sock) };
+
+        IfReqBuilder::new()
+            .if_name(&self.if_name)
+            .mtu(i32::from(mtu))
+            .execute(&sock, c_ulong::from(net_gen::sockios::SIOCSIFMTU))?;
+
+        Ok(())
+    }
+}
+
+// Backends report plain IO errors.
+fn to_io_error(e: Error) -> IoError {
+    match e {
+        Error::IoctlError(e) | Error::CreateSocket(e) => e,
+        e => IoError::new(std::io::ErrorKind::Other, format!("{:?}", e)),
+    }
 }
 
 impl Read for Tap {
@@ -182,6 +330,46 @@ impl Write for Tap {
     }
 }
 
+impl NetBackend for Tap {
+    fn read_frame(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.read(buf)
+    }
+
+    fn write_frame(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.write(buf)
+    }
+
+    fn if_name(&self) -> String {
+        self.if_name_as_str().to_string()
+    }
+
+    fn backend_type(&self) -> NetBackendType {
+        NetBackendType::Tap
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> IoResult<()> {
+        self.set_queue_enabled(enabled).map_err(to_io_error)
+    }
+
+    fn set_mtu(&self, mtu: u16) -> IoResult<()> {
+        Tap::set_mtu(self, mtu).map_err(to_io_error)
+    }
+
+    fn set_offloads(&self, offloads: NetOffloads) -> IoResult<()> {
+        let mut flags = 0;
+        if offloads.csum {
+            flags |= net_gen::TUN_F_CSUM;
+            if offloads.tso {
+                flags |= net_gen::TUN_F_TSO4 | net_gen::TUN_F_TSO6;
+            }
+            if offloads.ufo {
+                flags |= net_gen::TUN_F_UFO;
+            }
+        }
+        self.set_offload(flags).map_err(to_io_error)
+    }
+}
+
 impl AsRawFd for Tap {
     fn as_raw_fd(&self) -> RawFd {
         self.tap_file.as_raw_fd()
@@ -191,6 +379,7 @@ impl AsRawFd for Tap {
 #[cfg(test)]
 pub mod tests {
     use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::IntoRawFd;
 
     use net_gen::ETH_HLEN;
 
@@ -232,6 +421,36 @@ pub mod tests {
         assert_eq!(name, tap.if_name_as_str());
     }
 
+    #[test]
+    fn test_macvtap_device_path() {
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        let sysfs_net = tmp_dir.as_path();
+
+        // Regular TAP interfaces, or ones that don't exist yet, go through /dev/net/tun.
+        fs::create_dir(sysfs_net.join("tap0")).unwrap();
+        assert_eq!(macvtap_device_path(sysfs_net, "tap0").unwrap(), None);
+        assert_eq!(macvtap_device_path(sysfs_net, "tap1").unwrap(), None);
+        assert_eq!(macvtap_device_path(sysfs_net, "").unwrap(), None);
+
+        fs::create_dir_all(sysfs_net.join("macvtap0/macvtap/tap12")).unwrap();
+        fs::write(sysfs_net.join("macvtap0/ifindex"), "12\n").unwrap();
+        assert_eq!(
+            macvtap_device_path(sysfs_net, "macvtap0").unwrap(),
+            Some(PathBuf::from("/dev/tap12"))
+        );
+
+        fs::write(sysfs_net.join("macvtap0/ifindex"), "foo\n").unwrap();
+        assert!(matches!(
+            macvtap_device_path(sysfs_net, "macvtap0"),
+            Err(Error::MacvtapIndex(_))
+        ));
+        fs::remove_file(sysfs_net.join("macvtap0/ifindex")).unwrap();
+        assert!(matches!(
+            macvtap_device_path(sysfs_net, "macvtap0"),
+            Err(Error::MacvtapIndex(_))
+        ));
+    }
+
     #[test]
     fn test_tap_exclusive_open() {
         let _tap1 = Tap::open_named("exclusivetap").unwrap();
@@ -239,6 +458,41 @@ pub mod tests {
         Tap::open_named("exclusivetap").unwrap_err();
     }
 
+    #[test]
+    fn test_tap_multi_queue() {
+        let taps = Tap::open_named_multi_queue("multiqueuetap", 4).unwrap();
+        assert_eq!(taps.len(), 4);
+        for tap in &taps {
+            assert_eq!(tap.if_name_as_str(), "multiqueuetap");
+        }
+
+        // A queue can be detached, then attached again.
+        taps[3].set_queue_enabled(false).unwrap();
+        taps[3].set_queue_enabled(true).unwrap();
+
+        // A multi-queue interface can't be opened as a single queue one.
+        Tap::open_named("multiqueuetap").unwrap_err();
+        // Single queue taps can't be detached.
+        let tap = Tap::open_named("").unwrap();
+        tap.set_queue_enabled(false).unwrap_err();
+    }
+
+    #[test]
+    fn test_tap_from_fd() {
+        let tap = Tap::open_named("fdtap").unwrap();
+        let tap = Tap::from_fd(tap.tap_file.into_raw_fd()).unwrap();
+        assert_eq!(tap.if_name_as_str(), "fdtap");
+
+        // Files which aren't TAP devices are rejected, and left open.
+        let file = File::open("/dev/null").unwrap();
+        assert!(matches!(
+            Tap::from_fd(file.as_raw_fd()),
+            Err(Error::IoctlError(_))
+        ));
+        // This is safe since we check the return value.
+        assert!(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFD) } >= 0);
+    }
+
     #[test]
     fn test_set_options() {
         // This line will fail to provide an initialized FD if the test is not run as root.
@@ -263,8 +517,8 @@ pub mod tests {
     #[test]
     fn test_read() {
         let mut tap = Tap::open_named("").unwrap();
-        enable(&tap);
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&tap));
+        enable(tap.if_name_as_str());
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(tap.if_name_as_str()));
 
         let packet = utils::rand::rand_alphanumerics(PAYLOAD_SIZE);
         tap_traffic_simulator.push_tx_packet(packet.as_bytes());
@@ -280,8 +534,8 @@ pub mod tests {
     #[test]
     fn test_write() {
         let mut tap = Tap::open_named("").unwrap();
-        enable(&tap);
-        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&tap));
+        enable(tap.if_name_as_str());
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(tap.if_name_as_str()));
 
         let mut packet = [0u8; PACKET_SIZE];
         let payload = utils::rand::rand_alphanumerics(PAYLOAD_SIZE);
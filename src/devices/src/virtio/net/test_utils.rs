@@ -20,7 +20,7 @@ use vm_memory::{GuestAddress, GuestMemoryMmap};
 
 #[cfg(test)]
 use crate::virtio::net::device::vnet_hdr_len;
-use crate::virtio::net::tap::{Error, IfReqBuilder, Tap};
+use crate::virtio::net::tap::{build_terminated_if_name, Error, IfReqBuilder};
 use crate::virtio::test_utils::VirtQueue;
 use crate::virtio::{Net, Queue, QueueError};
 use crate::Error as DeviceError;
@@ -41,13 +41,15 @@ pub fn default_net() -> Net {
         Some(&guest_mac),
         RateLimiter::default(),
         RateLimiter::default(),
+        1,
     )
     .unwrap();
     net.configure_mmds_network_stack(
         MmdsNetworkStack::default_ipv4_addr(),
+        None,
         Arc::new(Mutex::new(Mmds::default())),
     );
-    enable(&net.tap);
+    enable(&net.iface_name());
 
     net
 }
@@ -64,9 +66,10 @@ pub fn default_net_no_mmds() -> Net {
         Some(&guest_mac),
         RateLimiter::default(),
         RateLimiter::default(),
+        1,
     )
     .unwrap();
-    enable(&net.tap);
+    enable(&net.iface_name());
 
     net
 }
@@ -222,10 +225,10 @@ pub fn virtqueues(mem: &GuestMemoryMmap) -> (VirtQueue, VirtQueue) {
     (rxq, txq)
 }
 
-pub fn if_index(tap: &Tap) -> i32 {
+pub fn if_index(if_name: &str) -> i32 {
     let sock = create_socket();
     let ifreq = IfReqBuilder::new()
-        .if_name(&tap.if_name)
+        .if_name(&build_terminated_if_name(if_name).unwrap())
         .execute(&sock, c_ulong::from(net_gen::sockios::SIOCGIFINDEX))
         .unwrap();
 
@@ -233,20 +236,20 @@ pub fn if_index(tap: &Tap) -> i32 {
 }
 
 /// Enable the tap interface.
-pub fn enable(tap: &Tap) {
+pub fn enable(if_name: &str) {
     // Disable IPv6 router advertisment requests
     Command::new("sh")
         .arg("-c")
         .arg(format!(
             "echo 0 > /proc/sys/net/ipv6/conf/{}/accept_ra",
-            tap.if_name_as_str()
+            if_name
         ))
         .output()
         .unwrap();
 
     let sock = create_socket();
     IfReqBuilder::new()
-        .if_name(&tap.if_name)
+        .if_name(&build_terminated_if_name(if_name).unwrap())
         .flags(
             (net_gen::net_device_flags_IFF_UP
                 | net_gen::net_device_flags_IFF_RUNNING
@@ -259,7 +262,7 @@ pub fn enable(tap: &Tap) {
 #[cfg(test)]
 pub(crate) fn inject_tap_tx_frame(net: &Net, len: usize) -> Vec<u8> {
     assert!(len >= vnet_hdr_len());
-    let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&net.tap));
+    let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&net.iface_name()));
     let mut frame = utils::rand::rand_alphanumerics(len - vnet_hdr_len())
         .as_bytes()
         .to_vec();
@@ -388,10 +391,10 @@ pub mod test {
 
         pub fn simulate_event(&mut self, event: NetEvent) {
             match event {
-                NetEvent::RxQueue => self.net().process_rx_queue_event(),
+                NetEvent::RxQueue => self.net().process_rx_queue_event(0),
                 NetEvent::RxRateLimiter => self.net().process_rx_rate_limiter_event(),
-                NetEvent::Tap => self.net().process_tap_rx_event(),
-                NetEvent::TxQueue => self.net().process_tx_queue_event(),
+                NetEvent::Tap => self.net().process_tap_rx_event(0),
+                NetEvent::TxQueue => self.net().process_tx_queue_event(0),
                 NetEvent::TxRateLimiter => self.net().process_tx_rate_limiter_event(),
             };
         }
@@ -452,7 +455,7 @@ pub mod test {
                 self.event_manager.run_with_timeout(100).unwrap()
             );
             // Check that the frame has been deferred.
-            assert!(self.net().rx_deferred_frame);
+            assert!(self.net().queue_pairs[0].rx_deferred_frame);
             // Check that the descriptor chain has been discarded.
             assert_eq!(self.rxq.used.idx.get(), used_idx + 1);
             assert!(&self.net().irq_trigger.has_pending_irq(IrqType::Vring));
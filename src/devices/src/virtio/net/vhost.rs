@@ -0,0 +1,347 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal wrapper over the vhost kernel interface, used to offload the datapath of a
+//! TAP-backed net device to vhost-net, or the one of a vsock device to vhost-vsock, along with
+//! the interface it shares with the vhost-user implementation.
+
+use std::fs::{File, OpenOptions};
+use std::io::Error as IoError;
+use std::os::raw::{c_int, c_uint};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use utils::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ref};
+use utils::{ioctl_expr, ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr};
+use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+use crate::virtio::Queue;
+
+const VHOST_NET_PATH: &str = "/dev/vhost-net";
+// Upper bound for the number of guest memory regions handed to vhost. Firecracker guests
+// never have more than a couple of them.
+pub(crate) const MAX_MEMORY_REGIONS: usize = 8;
+
+/// List of errors the vhost-net and vhost-user implementations can throw.
+#[derive(Debug)]
+pub enum Error {
+    /// Couldn't open /dev/vhost-net.
+    OpenVhostNet(IoError),
+    /// Couldn't open /dev/vhost-vsock.
+    OpenVhostVsock(IoError),
+    /// ioctl failed.
+    IoctlError(IoError),
+    /// The guest memory has more regions than vhost is given.
+    TooManyMemoryRegions(usize),
+    /// A ring of the queue isn't backed by guest memory.
+    InvalidQueueAddress(GuestAddress),
+    /// Features required by the device model aren't supported by vhost.
+    UnsupportedFeatures(u64),
+    /// Couldn't connect to the socket of the vhost-user backend.
+    VhostUserConnect(IoError),
+    /// Exchanging a message with the vhost-user backend failed.
+    VhostUserSocket(IoError),
+    /// The vhost-user backend sent an unexpected reply to the given request.
+    VhostUserInvalidReply(u32),
+    /// The vhost-user backend doesn't support the given protocol features.
+    VhostUserMissingProtocolFeatures(u64),
+    /// The guest memory region starting at the given address isn't backed by a file, so it
+    /// can't be shared with the vhost-user backend.
+    MemoryNotShared(GuestAddress),
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+pub(crate) const VHOST_VIRTIO: c_uint = 0xAF;
+ioctl_ior_nr!(VHOST_GET_FEATURES, VHOST_VIRTIO, 0x00, u64);
+ioctl_iow_nr!(VHOST_SET_FEATURES, VHOST_VIRTIO, 0x00, u64);
+ioctl_io_nr!(VHOST_SET_OWNER, VHOST_VIRTIO, 0x01);
+ioctl_iow_nr!(VHOST_SET_MEM_TABLE, VHOST_VIRTIO, 0x03, VhostMemoryHeader);
+ioctl_iow_nr!(VHOST_SET_VRING_NUM, VHOST_VIRTIO, 0x10, VhostVringState);
+ioctl_iow_nr!(VHOST_SET_VRING_ADDR, VHOST_VIRTIO, 0x11, VhostVringAddr);
+ioctl_iow_nr!(VHOST_SET_VRING_BASE, VHOST_VIRTIO, 0x12, VhostVringState);
+ioctl_iow_nr!(VHOST_SET_VRING_KICK, VHOST_VIRTIO, 0x20, VhostVringFile);
+ioctl_iow_nr!(VHOST_SET_VRING_CALL, VHOST_VIRTIO, 0x21, VhostVringFile);
+ioctl_iow_nr!(VHOST_NET_SET_BACKEND, VHOST_VIRTIO, 0x30, VhostVringFile);
+
+// The structures below mirror the ones in the Linux UAPI:
+// https://elixir.bootlin.com/linux/v5.10/source/include/uapi/linux/vhost_types.h
+#[repr(C)]
+#[derive(Default)]
+struct VhostVringState {
+    index: c_uint,
+    num: c_uint,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct VhostVringFile {
+    index: c_uint,
+    fd: c_int,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct VhostVringAddr {
+    index: c_uint,
+    flags: c_uint,
+    desc_user_addr: u64,
+    used_user_addr: u64,
+    avail_user_addr: u64,
+    log_guest_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct VhostMemoryRegion {
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    flags_padding: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct VhostMemoryHeader {
+    nregions: u32,
+    padding: u32,
+}
+
+// `struct vhost_memory` ends with a flexible array of regions, which the kernel reads past
+// the header.
+#[repr(C)]
+#[derive(Default)]
+struct VhostMemory {
+    header: VhostMemoryHeader,
+    regions: [VhostMemoryRegion; MAX_MEMORY_REGIONS],
+}
+
+/// The operations through which the device model hands the rings of a queue pair over to a
+/// vhost implementation.
+pub(crate) trait VhostBackend: Send {
+    /// The virtio features implemented by vhost.
+    fn features(&self) -> u64;
+
+    /// Sets the features acked by the guest. Must be a subset of `features()`.
+    fn set_features(&self, features: u64) -> Result<()>;
+
+    /// Describes the guest memory layout, so that vhost can translate the ring addresses.
+    fn set_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()>;
+
+    /// Hands the `index` ring over to vhost, picking up where the device model left it.
+    /// `kick` is signalled by the guest when buffers are made available, and vhost signals
+    /// `call` when buffers are used.
+    fn set_vring(
+        &self,
+        index: usize,
+        queue: &Queue,
+        mem: &GuestMemoryMmap,
+        kick: &dyn AsRawFd,
+        call: &dyn AsRawFd,
+    ) -> Result<()>;
+
+    /// Starts the packet processing for the `index` ring, once it was handed over.
+    /// `backend_fd` is the host-side backend of the queue pair.
+    fn start_vring(&self, index: usize, backend_fd: RawFd) -> Result<()>;
+}
+
+/// Handle for an instance of a vhost kernel module, owned by the calling process. It serves
+/// the rings of a single vhost-net queue pair or vhost-vsock device.
+///
+/// The vhost worker thread is torn down by the kernel when the handle goes out of scope.
+#[derive(Debug)]
+pub(crate) struct VhostKernel {
+    file: File,
+    features: u64,
+}
+
+impl VhostKernel {
+    /// Opens a new instance of the vhost device at `path`, reporting a failure to open it with
+    /// `open_err`.
+    pub(crate) fn open(path: &str, open_err: fn(IoError) -> Error) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC | libc::O_NONBLOCK)
+            .open(path)
+            .map_err(open_err)?;
+
+        // ioctl is safe. Called with a valid vhost fd, and we check the return.
+        let ret = unsafe { ioctl(&file, VHOST_SET_OWNER()) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        let mut features = 0u64;
+        // ioctl is safe. Called with a valid vhost fd, and we check the return.
+        let ret = unsafe { ioctl_with_mut_ref(&file, VHOST_GET_FEATURES(), &mut features) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        Ok(VhostKernel { file, features })
+    }
+
+    pub(crate) fn ioctl_with_ref<T>(&self, req: u64, arg: &T) -> Result<()> {
+        // ioctl is safe. Called with a valid vhost fd and a structure matching the request,
+        // and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, req, arg) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+        Ok(())
+    }
+
+    /// The virtio features implemented by the vhost device.
+    pub(crate) fn features(&self) -> u64 {
+        self.features
+    }
+
+    /// Sets the features acked by the guest. Must be a subset of `features()`.
+    pub(crate) fn set_features(&self, features: u64) -> Result<()> {
+        self.ioctl_with_ref(VHOST_SET_FEATURES(), &features)
+    }
+
+    /// Describes the guest memory layout, so that vhost can translate the ring addresses.
+    pub(crate) fn set_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        let num_regions = mem.num_regions();
+        if num_regions > MAX_MEMORY_REGIONS {
+            return Err(Error::TooManyMemoryRegions(num_regions));
+        }
+
+        let mut table = VhostMemory::default();
+        table.header.nregions = num_regions as u32;
+        for (region, vhost_region) in mem.iter().zip(table.regions.iter_mut()) {
+            vhost_region.guest_phys_addr = region.start_addr().raw_value();
+            vhost_region.memory_size = region.len();
+            // It's safe to unwrap because the guest address is valid.
+            vhost_region.userspace_addr = mem.get_host_address(region.start_addr()).unwrap() as u64;
+        }
+
+        self.ioctl_with_ref(VHOST_SET_MEM_TABLE(), &table)
+    }
+
+    /// Hands the `index` ring over to vhost, picking up where the device model left it.
+    pub(crate) fn set_vring(
+        &self,
+        index: usize,
+        queue: &Queue,
+        mem: &GuestMemoryMmap,
+        kick: &dyn AsRawFd,
+        call: &dyn AsRawFd,
+    ) -> Result<()> {
+        let index = index as c_uint;
+        let host_address = |addr: GuestAddress| {
+            mem.get_host_address(addr)
+                .map(|host_addr| host_addr as u64)
+                .map_err(|_| Error::InvalidQueueAddress(addr))
+        };
+
+        self.ioctl_with_ref(
+            VHOST_SET_VRING_NUM(),
+            &VhostVringState {
+                index,
+                num: c_uint::from(queue.actual_size()),
+            },
+        )?;
+        self.ioctl_with_ref(
+            VHOST_SET_VRING_BASE(),
+            &VhostVringState {
+                index,
+                num: c_uint::from(queue.next_avail.0),
+            },
+        )?;
+        self.ioctl_with_ref(
+            VHOST_SET_VRING_ADDR(),
+            &VhostVringAddr {
+                index,
+                desc_user_addr: host_address(queue.desc_table)?,
+                used_user_addr: host_address(queue.used_ring)?,
+                avail_user_addr: host_address(queue.avail_ring)?,
+                ..Default::default()
+            },
+        )?;
+        self.ioctl_with_ref(
+            VHOST_SET_VRING_KICK(),
+            &VhostVringFile {
+                index,
+                fd: kick.as_raw_fd(),
+            },
+        )?;
+        self.ioctl_with_ref(
+            VHOST_SET_VRING_CALL(),
+            &VhostVringFile {
+                index,
+                fd: call.as_raw_fd(),
+            },
+        )
+    }
+}
+
+impl AsRawFd for VhostKernel {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Handle for a vhost-net instance, serving a single RX/TX queue pair.
+#[derive(Debug)]
+pub struct VhostNet {
+    kernel: VhostKernel,
+}
+
+impl VhostNet {
+    /// Opens a new vhost-net instance, owned by the calling process.
+    pub fn new() -> Result<Self> {
+        Ok(VhostNet {
+            kernel: VhostKernel::open(VHOST_NET_PATH, Error::OpenVhostNet)?,
+        })
+    }
+
+    /// Attaches the `index` ring to the TAP interface behind `backend_fd`, which starts the
+    /// packet processing for that ring.
+    pub fn set_backend(&self, index: usize, backend_fd: RawFd) -> Result<()> {
+        self.kernel.ioctl_with_ref(
+            VHOST_NET_SET_BACKEND(),
+            &VhostVringFile {
+                index: index as c_uint,
+                fd: backend_fd,
+            },
+        )
+    }
+}
+
+impl VhostBackend for VhostNet {
+    fn features(&self) -> u64 {
+        self.kernel.features()
+    }
+
+    fn set_features(&self, features: u64) -> Result<()> {
+        self.kernel.set_features(features)
+    }
+
+    fn set_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        self.kernel.set_mem_table(mem)
+    }
+
+    fn set_vring(
+        &self,
+        index: usize,
+        queue: &Queue,
+        mem: &GuestMemoryMmap,
+        kick: &dyn AsRawFd,
+        call: &dyn AsRawFd,
+    ) -> Result<()> {
+        self.kernel.set_vring(index, queue, mem, kick, call)
+    }
+
+    fn start_vring(&self, index: usize, backend_fd: RawFd) -> Result<()> {
+        self.set_backend(index, backend_fd)
+    }
+}
+
+impl AsRawFd for VhostNet {
+    fn as_raw_fd(&self) -> RawFd {
+        self.kernel.as_raw_fd()
+    }
+}
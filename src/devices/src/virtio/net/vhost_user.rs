@@ -0,0 +1,74 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{Error as IoError, Result as IoResult};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use crate::virtio::net::backend::{NetBackend, NetBackendType};
+
+/// Host-side backend of a net device served by a vhost-user backend. Frames never go through
+/// the device model, so it only identifies the backend by the path of its socket.
+#[derive(Debug)]
+pub struct VhostUserBackend {
+    socket: UnixStream,
+    path: String,
+}
+
+impl VhostUserBackend {
+    /// Creates the backend of the queue pair served through `socket`, named after the `path`
+    /// of the socket.
+    pub fn new(socket: UnixStream, path: String) -> Self {
+        VhostUserBackend { socket, path }
+    }
+}
+
+impl NetBackend for VhostUserBackend {
+    fn read_frame(&mut self, _buf: &mut [u8]) -> IoResult<usize> {
+        Err(IoError::from_raw_os_error(libc::EOPNOTSUPP))
+    }
+
+    fn write_frame(&mut self, _buf: &[u8]) -> IoResult<usize> {
+        Err(IoError::from_raw_os_error(libc::EOPNOTSUPP))
+    }
+
+    fn if_name(&self) -> String {
+        self.path.clone()
+    }
+
+    fn backend_type(&self) -> NetBackendType {
+        NetBackendType::VhostUser
+    }
+}
+
+impl AsRawFd for VhostUserBackend {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vhost_user_backend() {
+        let (socket, _peer) = UnixStream::pair().unwrap();
+        let fd = socket.as_raw_fd();
+        let mut backend = VhostUserBackend::new(socket, "/run/vhost-user.sock".to_string());
+        assert_eq!(backend.if_name(), "/run/vhost-user.sock");
+        assert_eq!(backend.backend_type(), NetBackendType::VhostUser);
+        assert_eq!(backend.as_raw_fd(), fd);
+
+        // The frames are moved by the vhost-user backend.
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            backend.read_frame(&mut buf).unwrap_err().raw_os_error(),
+            Some(libc::EOPNOTSUPP)
+        );
+        assert_eq!(
+            backend.write_frame(&buf).unwrap_err().raw_os_error(),
+            Some(libc::EOPNOTSUPP)
+        );
+    }
+}
@@ -0,0 +1,547 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Net backend exchanging L2 frames with a queue of a host interface through an AF_XDP socket,
+//! bypassing the TAP device and most of the host network stack.
+//!
+//! The socket shares a memory area (the UMEM) with the kernel, split into fixed size frames.
+//! Frames are handed back and forth through four single producer, single consumer rings:
+//! the fill and RX rings for received frames, the TX and completion rings for sent ones.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::{cmp, mem, ptr};
+
+use crate::virtio::net::backend::{NetBackend, NetBackendType};
+use crate::virtio::net::device::vnet_hdr_len;
+
+// As defined in the Linux UAPI:
+// https://elixir.bootlin.com/linux/v5.10/source/include/uapi/linux/if_xdp.h
+const AF_XDP: c_int = 44;
+const SOL_XDP: c_int = 283;
+const XDP_MMAP_OFFSETS: c_int = 1;
+const XDP_RX_RING: c_int = 2;
+const XDP_TX_RING: c_int = 3;
+const XDP_UMEM_REG: c_int = 4;
+const XDP_UMEM_FILL_RING: c_int = 5;
+const XDP_UMEM_COMPLETION_RING: c_int = 6;
+const XDP_PGOFF_RX_RING: libc::off_t = 0;
+const XDP_PGOFF_TX_RING: libc::off_t = 0x8000_0000;
+const XDP_UMEM_PGOFF_FILL_RING: libc::off_t = 0x1_0000_0000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: libc::off_t = 0x1_8000_0000;
+// https://elixir.bootlin.com/linux/v5.10/source/include/uapi/linux/bpf.h
+const BPF_MAP_UPDATE_ELEM: c_int = 2;
+const BPF_OBJ_GET: c_int = 7;
+
+// Number of entries of each ring. The UMEM holds enough frames to keep both the fill and the
+// TX rings full.
+const RING_SIZE: u32 = 256;
+const FRAME_SIZE: u32 = 4096;
+const NUM_FRAMES: u32 = 2 * RING_SIZE;
+
+#[repr(C)]
+struct XdpUmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+}
+
+// The offsets are requested in the layout predating the ring flags, which all the kernels
+// supporting AF_XDP understand.
+#[repr(C)]
+#[derive(Default)]
+struct XdpRingOffset {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct XdpMmapOffsets {
+    rx: XdpRingOffset,
+    tx: XdpRingOffset,
+    fr: XdpRingOffset,
+    cr: XdpRingOffset,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct XdpDesc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+#[repr(C)]
+struct SockaddrXdp {
+    sxdp_family: u16,
+    sxdp_flags: u16,
+    sxdp_ifindex: u32,
+    sxdp_queue_id: u32,
+    sxdp_shared_umem_fd: u32,
+}
+
+#[repr(C)]
+struct BpfObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+#[repr(C)]
+struct BpfMapUpdateAttr {
+    map_fd: u32,
+    pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+// Shared memory area mapped with `mmap`, unmapped when going out of scope.
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn anonymous(len: usize) -> IoResult<Self> {
+        Self::new(len, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+    }
+
+    fn shared(fd: RawFd, len: usize, offset: libc::off_t) -> IoResult<Self> {
+        Self::new(len, libc::MAP_SHARED | libc::MAP_POPULATE, fd, offset)
+    }
+
+    fn new(len: usize, flags: c_int, fd: RawFd, offset: libc::off_t) -> IoResult<Self> {
+        // This is safe since we let the kernel pick the address and check the return value.
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(IoError::last_os_error());
+        }
+        Ok(Mapping {
+            addr: addr as *mut u8,
+            len,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // This is safe since the area was mapped by us and isn't referenced anymore.
+        unsafe { libc::munmap(self.addr as *mut c_void, self.len) };
+    }
+}
+
+// One of the rings shared with the kernel. We are the producer of the fill and TX rings, and
+// the consumer of the RX and completion rings.
+struct Ring<T> {
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    descs: *mut T,
+    mask: u32,
+    _mapping: Option<Mapping>,
+}
+
+impl<T: Copy> Ring<T> {
+    fn map(fd: RawFd, offsets: &XdpRingOffset, pgoff: libc::off_t) -> IoResult<Self> {
+        let len = offsets.desc as usize + RING_SIZE as usize * mem::size_of::<T>();
+        let mapping = Mapping::shared(fd, len, pgoff)?;
+        // This is safe since the kernel laid out the ring according to `offsets`.
+        unsafe { Ok(Self::from_raw(mapping.addr, offsets, Some(mapping))) }
+    }
+
+    // Safe as long as `base` points to a ring laid out according to `offsets`, with
+    // RING_SIZE entries, and outlives the returned value.
+    unsafe fn from_raw(base: *mut u8, offsets: &XdpRingOffset, mapping: Option<Mapping>) -> Self {
+        Ring {
+            producer: base.add(offsets.producer as usize) as *const AtomicU32,
+            consumer: base.add(offsets.consumer as usize) as *const AtomicU32,
+            descs: base.add(offsets.desc as usize) as *mut T,
+            mask: RING_SIZE - 1,
+            _mapping: mapping,
+        }
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        // This is safe since the pointer stays valid for the lifetime of the ring.
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        // This is safe since the pointer stays valid for the lifetime of the ring.
+        unsafe { &*self.consumer }
+    }
+
+    // Appends `desc` to a ring we produce, unless it's full.
+    fn push(&mut self, desc: T) -> bool {
+        let prod = self.producer().load(Ordering::Relaxed);
+        let cons = self.consumer().load(Ordering::Acquire);
+        if prod.wrapping_sub(cons) >= RING_SIZE {
+            return false;
+        }
+        // This is safe since the index is masked to the size of the ring.
+        unsafe { ptr::write_volatile(self.descs.add((prod & self.mask) as usize), desc) };
+        self.producer()
+            .store(prod.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    // Takes the next entry of a ring we consume, if any.
+    fn pop(&mut self) -> Option<T> {
+        let cons = self.consumer().load(Ordering::Relaxed);
+        let prod = self.producer().load(Ordering::Acquire);
+        if cons == prod {
+            return None;
+        }
+        // This is safe since the index is masked to the size of the ring.
+        let desc = unsafe { ptr::read_volatile(self.descs.add((cons & self.mask) as usize)) };
+        self.consumer()
+            .store(cons.wrapping_add(1), Ordering::Release);
+        Some(desc)
+    }
+}
+
+/// Net backend bound to a single queue of a host interface through an AF_XDP socket.
+///
+/// Frames are redirected to the socket by an XDP program attached to the interface, through
+/// an `XSKMAP` the socket is inserted into. Loading that program is left to the host.
+pub struct XdpSocket {
+    socket: File,
+    if_name: String,
+    umem: Mapping,
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    rx: Ring<XdpDesc>,
+    tx: Ring<XdpDesc>,
+    // UMEM frames which aren't owned by the kernel nor in the rings.
+    free_frames: Vec<u64>,
+}
+
+// The raw pointers only refer to memory owned by the socket, which is only accessed through
+// `&mut self` on our side.
+unsafe impl Send for XdpSocket {}
+
+fn setsockopt<T>(socket: &File, name: c_int, value: &T) -> IoResult<()> {
+    // This is safe since `value` is valid for reads of its size, and we check the return value.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            SOL_XDP,
+            name,
+            value as *const T as *const c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+fn bpf<T>(cmd: c_int, attr: &T) -> IoResult<c_int> {
+    // This is safe since `attr` is valid for reads of its size, and we check the return value.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *const T as *const c_void,
+            mem::size_of::<T>() as u32,
+        )
+    };
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(ret as c_int)
+}
+
+impl XdpSocket {
+    /// Binds a new AF_XDP socket to the `queue_id` queue of the `if_name` host interface,
+    /// and inserts it in the `XSKMAP` pinned at `xsks_map_path`, using the queue id as key.
+    pub fn new(if_name: &str, queue_id: u32, xsks_map_path: &str) -> IoResult<XdpSocket> {
+        let c_if_name =
+            CString::new(if_name).map_err(|e| IoError::new(ErrorKind::InvalidInput, e))?;
+        // This is safe since the name is a valid null-terminated string.
+        let if_index = unsafe { libc::if_nametoindex(c_if_name.as_ptr()) };
+        if if_index == 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        // This is safe since we check the return value.
+        let fd = unsafe { libc::socket(AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+        // We just checked that the fd is valid.
+        let socket = unsafe { File::from_raw_fd(fd) };
+
+        let umem = Mapping::anonymous((NUM_FRAMES * FRAME_SIZE) as usize)?;
+        setsockopt(
+            &socket,
+            XDP_UMEM_REG,
+            &XdpUmemReg {
+                addr: umem.addr as u64,
+                len: umem.len as u64,
+                chunk_size: FRAME_SIZE,
+                headroom: 0,
+            },
+        )?;
+        for ring in [
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+            XDP_RX_RING,
+            XDP_TX_RING,
+        ]
+        .iter()
+        {
+            setsockopt(&socket, *ring, &RING_SIZE)?;
+        }
+
+        let mut offsets = XdpMmapOffsets::default();
+        let mut optlen = mem::size_of::<XdpMmapOffsets>() as libc::socklen_t;
+        // This is safe since `offsets` is valid for writes of `optlen` bytes, and we check the
+        // return value.
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                &mut offsets as *mut XdpMmapOffsets as *mut c_void,
+                &mut optlen,
+            )
+        };
+        if ret < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        let mut xdp_socket = XdpSocket {
+            fill: Ring::map(fd, &offsets.fr, XDP_UMEM_PGOFF_FILL_RING)?,
+            completion: Ring::map(fd, &offsets.cr, XDP_UMEM_PGOFF_COMPLETION_RING)?,
+            rx: Ring::map(fd, &offsets.rx, XDP_PGOFF_RX_RING)?,
+            tx: Ring::map(fd, &offsets.tx, XDP_PGOFF_TX_RING)?,
+            socket,
+            if_name: if_name.to_string(),
+            umem,
+            free_frames: (RING_SIZE..NUM_FRAMES)
+                .map(|frame| u64::from(frame * FRAME_SIZE))
+                .collect(),
+        };
+        // Hand half of the frames over to the kernel for receiving.
+        for frame in 0..RING_SIZE {
+            xdp_socket.fill.push(u64::from(frame * FRAME_SIZE));
+        }
+
+        let addr = SockaddrXdp {
+            sxdp_family: AF_XDP as u16,
+            sxdp_flags: 0,
+            sxdp_ifindex: if_index,
+            sxdp_queue_id: queue_id,
+            sxdp_shared_umem_fd: 0,
+        };
+        // This is safe since `addr` is a properly initialized `sockaddr_xdp` and we check the
+        // return value.
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const SockaddrXdp as *const libc::sockaddr,
+                mem::size_of::<SockaddrXdp>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        xdp_socket.insert_in_map(queue_id, xsks_map_path)?;
+        Ok(xdp_socket)
+    }
+
+    fn insert_in_map(&self, queue_id: u32, xsks_map_path: &str) -> IoResult<()> {
+        let path =
+            CString::new(xsks_map_path).map_err(|e| IoError::new(ErrorKind::InvalidInput, e))?;
+        let map_fd = bpf(
+            BPF_OBJ_GET,
+            &BpfObjGetAttr {
+                pathname: path.as_ptr() as u64,
+                bpf_fd: 0,
+                file_flags: 0,
+            },
+        )?;
+        // We just checked that the fd is valid.
+        let map = unsafe { File::from_raw_fd(map_fd) };
+
+        let socket_fd = self.socket.as_raw_fd() as u32;
+        bpf(
+            BPF_MAP_UPDATE_ELEM,
+            &BpfMapUpdateAttr {
+                map_fd: map.as_raw_fd() as u32,
+                pad: 0,
+                key: &queue_id as *const u32 as u64,
+                value: &socket_fd as *const u32 as u64,
+                flags: 0,
+            },
+        )?;
+        Ok(())
+    }
+
+    // Returns the UMEM bytes from `addr` to the end of its frame.
+    fn frame(&mut self, addr: u64) -> &mut [u8] {
+        let addr = addr as usize % self.umem.len;
+        let len = FRAME_SIZE as usize - addr % FRAME_SIZE as usize;
+        // This is safe since the slice lies within a single frame of the UMEM.
+        unsafe { std::slice::from_raw_parts_mut(self.umem.addr.add(addr), len) }
+    }
+}
+
+impl NetBackend for XdpSocket {
+    fn read_frame(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let desc = self
+            .rx
+            .pop()
+            .ok_or_else(|| IoError::from_raw_os_error(libc::EAGAIN))?;
+
+        let hdr_len = vnet_hdr_len();
+        let frame = self.frame(desc.addr);
+        let len = cmp::min(
+            cmp::min(desc.len as usize, frame.len()),
+            buf.len() - hdr_len,
+        );
+        buf[hdr_len..hdr_len + len].copy_from_slice(&frame[..len]);
+        // Frames come straight from the wire, so the guest gets an empty vnet header.
+        for b in &mut buf[..hdr_len] {
+            *b = 0;
+        }
+
+        // The fill ring has room for all the frames used for receiving.
+        self.fill
+            .push(desc.addr - desc.addr % u64::from(FRAME_SIZE));
+        Ok(hdr_len + len)
+    }
+
+    fn write_frame(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let hdr_len = cmp::min(vnet_hdr_len(), buf.len());
+        let frame_len = buf.len() - hdr_len;
+        if frame_len > FRAME_SIZE as usize {
+            return Err(IoError::from_raw_os_error(libc::EMSGSIZE));
+        }
+
+        while let Some(frame) = self.completion.pop() {
+            self.free_frames.push(frame);
+        }
+        let addr = self
+            .free_frames
+            .pop()
+            .ok_or_else(|| IoError::from_raw_os_error(libc::ENOBUFS))?;
+        self.frame(addr)[..frame_len].copy_from_slice(&buf[hdr_len..]);
+        // There are never more frames used for sending than TX ring entries.
+        self.tx.push(XdpDesc {
+            addr,
+            len: frame_len as u32,
+            options: 0,
+        });
+
+        // Without zero-copy support, the kernel only processes the TX ring when asked to.
+        // This is safe since no buffer is passed, and the return value is checked.
+        let ret = unsafe {
+            libc::sendto(
+                self.socket.as_raw_fd(),
+                ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                ptr::null(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let err = IoError::last_os_error();
+            // The frame stays in the ring, and goes out with the next kick.
+            match err.raw_os_error() {
+                Some(libc::EAGAIN) | Some(libc::EBUSY) | Some(libc::ENOBUFS) => (),
+                _ => return Err(err),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn if_name(&self) -> String {
+        self.if_name.clone()
+    }
+
+    fn backend_type(&self) -> NetBackendType {
+        NetBackendType::Xdp
+    }
+}
+
+impl AsRawFd for XdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lays out a ring in plain memory, the way the kernel would.
+    fn test_ring<T: Copy>(mem: &mut Vec<u64>) -> Ring<T> {
+        let offsets = XdpRingOffset {
+            producer: 0,
+            consumer: 8,
+            desc: 16,
+        };
+        mem.resize(2 + RING_SIZE as usize * mem::size_of::<T>() / 8, 0);
+        // This is safe since the ring fits in `mem`, which outlives it.
+        unsafe { Ring::from_raw(mem.as_mut_ptr() as *mut u8, &offsets, None) }
+    }
+
+    #[test]
+    fn test_ring_push_pop() {
+        let mut mem = Vec::new();
+        // Both ends of the ring are driven from the test.
+        let mut producer = test_ring::<XdpDesc>(&mut mem);
+        let mut consumer = test_ring::<XdpDesc>(&mut mem);
+
+        assert_eq!(consumer.pop(), None);
+        // Go around the ring a few times.
+        for round in 0..3u64 {
+            for i in 0..u64::from(RING_SIZE) {
+                assert!(producer.push(XdpDesc {
+                    addr: round * 1000 + i,
+                    len: i as u32,
+                    options: 0,
+                }));
+            }
+            // The ring is full.
+            assert!(!producer.push(XdpDesc::default()));
+
+            for i in 0..u64::from(RING_SIZE) {
+                let desc = consumer.pop().unwrap();
+                assert_eq!(desc.addr, round * 1000 + i);
+                assert_eq!(desc.len, i as u32);
+            }
+            assert_eq!(consumer.pop(), None);
+        }
+    }
+
+    #[test]
+    fn test_new_errors() {
+        // No such interface.
+        assert!(XdpSocket::new("fc-xdp-none", 0, "/sys/fs/bpf/fc-xdp-none").is_err());
+        assert!(XdpSocket::new("lo\0", 0, "/sys/fs/bpf/fc-xdp-none").is_err());
+        // No XSKMAP pinned at the path.
+        assert!(XdpSocket::new("lo", 0, "/sys/fs/bpf/fc-xdp-none").is_err());
+    }
+}
@@ -163,6 +163,38 @@ impl VirtioDeviceState {
         }
         Ok(queues)
     }
+
+    /// Lists the fields of the `self` state a device of `expected_device_type` with queues of
+    /// `expected_queue_max_size` elements can't be restored from, along with their values.
+    pub fn incompatible_fields(
+        &self,
+        expected_device_type: u32,
+        expected_queue_max_size: u16,
+    ) -> Vec<String> {
+        let mut fields = Vec::new();
+        if self.device_type != expected_device_type {
+            fields.push(format!(
+                "virtio_state.device_type: {} instead of {}",
+                self.device_type, expected_device_type
+            ));
+        }
+        let unavailable_features = self.acked_features & !self.avail_features;
+        if unavailable_features != 0 {
+            fields.push(format!(
+                "virtio_state.acked_features: {:#x} acked but not available",
+                unavailable_features
+            ));
+        }
+        for (index, queue) in self.queues.iter().enumerate() {
+            if queue.max_size != expected_queue_max_size {
+                fields.push(format!(
+                    "virtio_state.queues[{}].max_size: {} instead of {}",
+                    index, queue.max_size, expected_queue_max_size
+                ));
+            }
+        }
+        fields
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Versionize)]
@@ -311,6 +343,34 @@ mod tests {
             .unwrap_err();
     }
 
+    #[test]
+    fn test_virtiodev_incompatible_fields() {
+        let max_size = DEFAULT_QUEUE_MAX_SIZE;
+        let mut state = VirtioDeviceState::default();
+        state.queues = vec![QueueState::default(), QueueState::default()];
+        assert!(state.incompatible_fields(0, max_size).is_empty());
+
+        state.acked_features = 0b11;
+        state.avail_features = 0b01;
+        assert_eq!(
+            state.incompatible_fields(1, max_size / 2),
+            vec![
+                "virtio_state.device_type: 0 instead of 1".to_string(),
+                "virtio_state.acked_features: 0x2 acked but not available".to_string(),
+                format!(
+                    "virtio_state.queues[0].max_size: {} instead of {}",
+                    max_size,
+                    max_size / 2
+                ),
+                format!(
+                    "virtio_state.queues[1].max_size: {} instead of {}",
+                    max_size,
+                    max_size / 2
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn test_queue_persistence() {
         let queue = Queue::new(128);
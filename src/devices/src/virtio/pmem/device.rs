@@ -0,0 +1,444 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use logger::{error, IncMetric, METRICS};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{ByteValued, Bytes, FileOffset, GuestAddress, GuestMemoryMmap, MmapRegion};
+
+use super::super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_PMEM};
+use super::{
+    NUM_QUEUES, PMEM_ALIGNMENT, QUEUE_SIZES, VIRTIO_PMEM_REQ_TYPE_FLUSH, VIRTIO_PMEM_RESP_EIO,
+    VIRTIO_PMEM_RESP_OK,
+};
+use crate::virtio::pmem::Error as PmemError;
+use crate::virtio::{DescriptorChain, IrqTrigger, IrqType};
+
+const SIZE_OF_U32: usize = std::mem::size_of::<u32>();
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ConfigSpace {
+    // Guest physical address of the device memory.
+    pub start: u64,
+    // Size of the device memory, in bytes.
+    pub size: u64,
+}
+
+// Safe because ConfigSpace only contains plain data.
+unsafe impl ByteValued for ConfigSpace {}
+
+// Virtio pmem device.
+pub struct Pmem {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: ConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) queue_evts: [EventFd; NUM_QUEUES],
+    pub(crate) device_state: DeviceState,
+    pub(crate) irq_trigger: IrqTrigger,
+
+    // Implementation specific fields.
+    pub(crate) id: String,
+    pub(crate) path_on_host: String,
+    pub(crate) read_only: bool,
+    // The shared mapping of the backing file, which is exposed to the guest as device memory.
+    pub(crate) mapping: MmapRegion,
+}
+
+impl Pmem {
+    /// Creates a pmem device backed by the file at `path_on_host`.
+    ///
+    /// The size of the file must be a non-zero multiple of 2MiB. The guest address of the
+    /// device memory is set with `set_guest_address` once it has been allocated.
+    pub fn new(id: String, path_on_host: String, read_only: bool) -> Result<Pmem, PmemError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .open(&path_on_host)
+            .map_err(PmemError::BackingFile)?;
+        let size = file.metadata().map_err(PmemError::BackingFile)?.len();
+        if size == 0 || size % PMEM_ALIGNMENT != 0 {
+            return Err(PmemError::InvalidSize(size));
+        }
+
+        let prot = if read_only {
+            libc::PROT_READ
+        } else {
+            libc::PROT_READ | libc::PROT_WRITE
+        };
+        let mapping = MmapRegion::build(
+            Some(FileOffset::new(file, 0)),
+            size as usize,
+            prot,
+            libc::MAP_SHARED | libc::MAP_NORESERVE,
+        )
+        .map_err(PmemError::Mmap)?;
+
+        Ok(Pmem {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space: ConfigSpace { start: 0, size },
+            queue_evts: [EventFd::new(libc::EFD_NONBLOCK).map_err(PmemError::EventFd)?],
+            queues: QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect(),
+            irq_trigger: IrqTrigger::new().map_err(PmemError::EventFd)?,
+            device_state: DeviceState::Inactive,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(PmemError::EventFd)?,
+            id,
+            path_on_host,
+            read_only,
+            mapping,
+        })
+    }
+
+    pub(crate) fn process_queue_event(&mut self) -> Result<(), PmemError> {
+        METRICS.pmem.queue_event_count.inc();
+        self.queue_evts[0].read().map_err(PmemError::EventFd)?;
+        self.process_queue()
+    }
+
+    pub(crate) fn process_queue(&mut self) -> Result<(), PmemError> {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[0].pop(mem) {
+            let len = Self::process_request(&self.mapping, mem, &head).unwrap_or_else(|e| {
+                error!("pmem: failed to process request: {:?}", e);
+                METRICS.pmem.execute_fails.inc();
+                0
+            });
+            self.queues[0]
+                .add_used(mem, head.index, len)
+                .map_err(PmemError::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
+    // Handles a request and writes its status in the guest memory. Returns the number of bytes
+    // written.
+    fn process_request(
+        mapping: &MmapRegion,
+        mem: &GuestMemoryMmap,
+        head: &DescriptorChain,
+    ) -> Result<u32, PmemError> {
+        if head.is_write_only() || (head.len as usize) < SIZE_OF_U32 {
+            return Err(PmemError::MalformedDescriptor);
+        }
+        let status_desc = head
+            .next_descriptor()
+            .ok_or(PmemError::DescriptorChainTooShort)?;
+        if !status_desc.is_write_only() || (status_desc.len as usize) < SIZE_OF_U32 {
+            return Err(PmemError::MalformedDescriptor);
+        }
+
+        let request_type = mem
+            .read_obj::<u32>(head.addr)
+            .map_err(PmemError::GuestMemory)?;
+        let status = match request_type {
+            VIRTIO_PMEM_REQ_TYPE_FLUSH => {
+                METRICS.pmem.flush_count.inc();
+                // Writes to a shared mapping land in the page cache, so syncing the file
+                // persists them.
+                match mapping.file_offset().unwrap().file().sync_all() {
+                    Ok(()) => VIRTIO_PMEM_RESP_OK,
+                    Err(e) => {
+                        error!("pmem: failed to flush the backing file: {:?}", e);
+                        METRICS.pmem.flush_fails.inc();
+                        VIRTIO_PMEM_RESP_EIO
+                    }
+                }
+            }
+            _ => {
+                error!("pmem: unsupported request type {}", request_type);
+                METRICS.pmem.execute_fails.inc();
+                VIRTIO_PMEM_RESP_EIO
+            }
+        };
+
+        mem.write_obj(status, status_desc.addr)
+            .map_err(PmemError::GuestMemory)?;
+        Ok(SIZE_OF_U32 as u32)
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<(), PmemError> {
+        self.irq_trigger.trigger_irq(IrqType::Vring).map_err(|e| {
+            METRICS.pmem.event_fails.inc();
+            PmemError::InterruptError(e)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_queue();
+    }
+
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    pub fn path_on_host(&self) -> &String {
+        &self.path_on_host
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns the address at which the backing file is mapped in the Firecracker process.
+    pub fn host_address(&self) -> u64 {
+        self.mapping.as_ptr() as u64
+    }
+
+    /// Returns the size of the device memory, in bytes.
+    pub fn size(&self) -> u64 {
+        self.config_space.size
+    }
+
+    /// Returns the guest physical address of the device memory.
+    pub fn guest_address(&self) -> GuestAddress {
+        GuestAddress(self.config_space.start)
+    }
+
+    /// Sets the guest physical address of the device memory. Must be called before the guest
+    /// driver reads the device configuration.
+    pub fn set_guest_address(&mut self, addr: GuestAddress) {
+        self.config_space.start = addr.0;
+    }
+}
+
+impl VirtioDevice for Pmem {
+    fn device_type(&self) -> u32 {
+        TYPE_PMEM
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.irq_trigger.irq_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            METRICS.pmem.cfg_fails.inc();
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The configuration space of a pmem device is read-only.
+        error!("Guest attempted to write the pmem config space");
+        METRICS.pmem.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        self.device_state.is_activated()
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Pmem: Cannot write to activate_evt");
+            METRICS.pmem.activate_fails.inc();
+            return Err(super::super::ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::super::CONFIG_SPACE_SIZE;
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    pub(crate) fn default_pmem(size: u64) -> (Pmem, TempFile) {
+        let backing_file = TempFile::new().unwrap();
+        backing_file.as_file().set_len(size).unwrap();
+        let pmem = Pmem::new(
+            "pmem0".to_string(),
+            backing_file.as_path().to_str().unwrap().to_string(),
+            false,
+        )
+        .unwrap();
+        (pmem, backing_file)
+    }
+
+    #[test]
+    fn test_new() {
+        let (pmem, _backing_file) = default_pmem(PMEM_ALIGNMENT);
+        assert_eq!(pmem.device_type(), TYPE_PMEM);
+        assert_eq!(pmem.id(), "pmem0");
+        assert_eq!(pmem.size(), PMEM_ALIGNMENT);
+        assert!(!pmem.is_read_only());
+        assert_eq!(pmem.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+
+        // The backing file must not be empty.
+        let backing_file = TempFile::new().unwrap();
+        let path = backing_file.as_path().to_str().unwrap().to_string();
+        assert!(matches!(
+            Pmem::new("pmem1".to_string(), path.clone(), true),
+            Err(PmemError::InvalidSize(0))
+        ));
+
+        // Its size must be a multiple of 2MiB.
+        backing_file
+            .as_file()
+            .set_len(PMEM_ALIGNMENT + 4096)
+            .unwrap();
+        assert!(matches!(
+            Pmem::new("pmem1".to_string(), path, true),
+            Err(PmemError::InvalidSize(_))
+        ));
+
+        // The backing file must exist.
+        assert!(matches!(
+            Pmem::new("pmem1".to_string(), "/invalid/path".to_string(), true),
+            Err(PmemError::BackingFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let (mut pmem, _backing_file) = default_pmem(2 * PMEM_ALIGNMENT);
+        pmem.set_guest_address(GuestAddress(0x1_0000_0000));
+        assert_eq!(pmem.guest_address(), GuestAddress(0x1_0000_0000));
+
+        let mut actual_config_space = [0u8; CONFIG_SPACE_SIZE];
+        pmem.read_config(0, &mut actual_config_space);
+        // The config space is little endian.
+        let expected_config_space: [u8; CONFIG_SPACE_SIZE] = [
+            0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        assert_eq!(actual_config_space, expected_config_space);
+
+        // Invalid read.
+        check_metric_after_block!(
+            &METRICS.pmem.cfg_fails,
+            1,
+            pmem.read_config(CONFIG_SPACE_SIZE as u64, &mut actual_config_space)
+        );
+
+        // Writes are ignored.
+        check_metric_after_block!(
+            &METRICS.pmem.cfg_fails,
+            1,
+            pmem.write_config(0, &[0xff; CONFIG_SPACE_SIZE])
+        );
+        pmem.read_config(0, &mut actual_config_space);
+        assert_eq!(actual_config_space, expected_config_space);
+    }
+
+    #[test]
+    fn test_process_queue() {
+        let (mut pmem, _backing_file) = default_pmem(PMEM_ALIGNMENT);
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        pmem.queues[0] = vq.create_queue();
+        pmem.activate(mem.clone()).unwrap();
+
+        let request_addr = 0x1000;
+        let status_addr = 0x2000;
+        vq.dtable[0].set(request_addr, SIZE_OF_U32 as u32, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(status_addr, SIZE_OF_U32 as u32, VIRTQ_DESC_F_WRITE, 0);
+
+        // Flush request.
+        mem.write_obj::<u32>(VIRTIO_PMEM_REQ_TYPE_FLUSH, GuestAddress(request_addr))
+            .unwrap();
+        mem.write_obj::<u32>(0xff, GuestAddress(status_addr))
+            .unwrap();
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+        check_metric_after_block!(&METRICS.pmem.flush_count, 1, pmem.process_queue().unwrap());
+        assert!(pmem.irq_trigger.has_pending_irq(IrqType::Vring));
+        vq.check_used_elem(0, 0, SIZE_OF_U32 as u32);
+        assert_eq!(
+            mem.read_obj::<u32>(GuestAddress(status_addr)).unwrap(),
+            VIRTIO_PMEM_RESP_OK
+        );
+
+        // Unsupported request type.
+        mem.write_obj::<u32>(1, GuestAddress(request_addr)).unwrap();
+        vq.avail.ring[1].set(0);
+        vq.avail.idx.set(2);
+        check_metric_after_block!(
+            &METRICS.pmem.execute_fails,
+            1,
+            pmem.process_queue().unwrap()
+        );
+        vq.check_used_elem(1, 0, SIZE_OF_U32 as u32);
+        assert_eq!(
+            mem.read_obj::<u32>(GuestAddress(status_addr)).unwrap(),
+            VIRTIO_PMEM_RESP_EIO
+        );
+
+        // The status descriptor must be write-only.
+        vq.dtable[1].set(status_addr, SIZE_OF_U32 as u32, 0, 0);
+        vq.avail.ring[2].set(0);
+        vq.avail.idx.set(3);
+        check_metric_after_block!(
+            &METRICS.pmem.execute_fails,
+            1,
+            pmem.process_queue().unwrap()
+        );
+        vq.check_used_elem(2, 0, 0);
+    }
+}
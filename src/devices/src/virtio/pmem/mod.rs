@@ -0,0 +1,50 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+pub mod event_handler;
+
+use vm_memory::{GuestMemoryError, MmapRegionError};
+
+pub use self::device::Pmem;
+pub use self::event_handler::*;
+
+pub const CONFIG_SPACE_SIZE: usize = 16;
+pub const QUEUE_SIZE: u16 = 256;
+pub const NUM_QUEUES: usize = 1;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+// The guest maps the device memory in 2MiB sections, so the size of the backing file and the
+// guest physical address of the device must be aligned to it.
+pub const PMEM_ALIGNMENT: u64 = 2 << 20;
+
+// The only request type defined by the virtio pmem specification.
+const VIRTIO_PMEM_REQ_TYPE_FLUSH: u32 = 0;
+// The response statuses. The driver treats any non-zero value as an I/O error.
+const VIRTIO_PMEM_RESP_OK: u32 = 0;
+const VIRTIO_PMEM_RESP_EIO: u32 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// Guest gave us too few descriptors in a descriptor chain.
+    DescriptorChainTooShort,
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Error manipulating the backing file.
+    BackingFile(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Received error while sending an interrupt.
+    InterruptError(std::io::Error),
+    /// The size of the backing file is zero or not a multiple of 2MiB.
+    InvalidSize(u64),
+    /// Guest gave us a malformed descriptor.
+    MalformedDescriptor,
+    /// Failed to map the backing file in the Firecracker process.
+    Mmap(MmapRegionError),
+    /// Error while processing the virt queue.
+    Queue(super::QueueError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,570 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Frontend side of the vhost-user protocol, used to offload the datapath of a device to
+//! another process listening on a unix socket, such as a DPDK application or Open vSwitch for
+//! net devices, or SPDK for block devices.
+//!
+//! Only the subset of the protocol needed to hand the rings over to the backend, and to read
+//! the configuration space of the device it emulates, is implemented:
+//! https://qemu.readthedocs.io/en/latest/interop/vhost-user.html
+
+use std::io::{Error as IoError, Read, Result as IoResult};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::{mem, ptr};
+
+use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+use crate::virtio::net::vhost::{Error, Result, VhostBackend, MAX_MEMORY_REGIONS};
+use crate::virtio::Queue;
+
+// Requests sent to the backend.
+pub(crate) const VHOST_USER_GET_FEATURES: u32 = 1;
+pub(crate) const VHOST_USER_SET_FEATURES: u32 = 2;
+pub(crate) const VHOST_USER_SET_OWNER: u32 = 3;
+pub(crate) const VHOST_USER_SET_MEM_TABLE: u32 = 5;
+pub(crate) const VHOST_USER_SET_VRING_NUM: u32 = 8;
+pub(crate) const VHOST_USER_SET_VRING_ADDR: u32 = 9;
+pub(crate) const VHOST_USER_SET_VRING_BASE: u32 = 10;
+pub(crate) const VHOST_USER_SET_VRING_KICK: u32 = 12;
+pub(crate) const VHOST_USER_SET_VRING_CALL: u32 = 13;
+pub(crate) const VHOST_USER_GET_PROTOCOL_FEATURES: u32 = 15;
+pub(crate) const VHOST_USER_SET_PROTOCOL_FEATURES: u32 = 16;
+pub(crate) const VHOST_USER_SET_VRING_ENABLE: u32 = 18;
+pub(crate) const VHOST_USER_GET_CONFIG: u32 = 24;
+
+// Header flags: the protocol version, and the bit set by the backend on replies.
+const VHOST_USER_VERSION: u32 = 0x1;
+const VHOST_USER_REPLY: u32 = 0x4;
+
+// Feature bit through which the backend advertises support for protocol features. Once
+// negotiated, the rings only start once they are explicitly enabled.
+pub(crate) const VHOST_USER_F_PROTOCOL_FEATURES: u64 = 30;
+
+// Protocol feature through which the backend lets the frontend read the configuration space
+// of the device.
+pub(crate) const VHOST_USER_PROTOCOL_F_CONFIG: u64 = 9;
+
+const HEADER_LEN: usize = 12;
+// The configuration space requests start with its offset, size and flags.
+const CONFIG_HEADER_LEN: usize = 12;
+
+/// Connection to a vhost-user backend, serving the rings of a single device, or a single
+/// RX/TX queue pair for net devices.
+///
+/// The backend stops processing the rings when the connection is closed.
+#[derive(Debug)]
+pub struct VhostUser {
+    socket: UnixStream,
+    features: u64,
+    // Whether VHOST_USER_F_PROTOCOL_FEATURES was negotiated.
+    protocol_features: bool,
+    // The protocol features acked by both sides.
+    acked_protocol_features: u64,
+}
+
+impl VhostUser {
+    /// Connects to the vhost-user backend listening at `path`, becomes the owner of the device
+    /// it serves, and acks the `protocol_features` bits the backend supports.
+    pub fn connect(path: &str, protocol_features: u64) -> Result<Self> {
+        let socket = UnixStream::connect(path).map_err(Error::VhostUserConnect)?;
+        let mut vhost_user = VhostUser {
+            socket,
+            features: 0,
+            protocol_features: false,
+            acked_protocol_features: 0,
+        };
+
+        vhost_user.send(VHOST_USER_SET_OWNER, &[], &[])?;
+        let features = vhost_user.get_u64(VHOST_USER_GET_FEATURES)?;
+        if features & (1 << VHOST_USER_F_PROTOCOL_FEATURES) != 0 {
+            // Even when none of the protocol features are used, negotiating them is the only
+            // way to enable the rings explicitly, which some backends expect.
+            let acked_protocol_features =
+                vhost_user.get_u64(VHOST_USER_GET_PROTOCOL_FEATURES)? & protocol_features;
+            vhost_user.send(
+                VHOST_USER_SET_PROTOCOL_FEATURES,
+                &acked_protocol_features.to_ne_bytes(),
+                &[],
+            )?;
+            vhost_user.protocol_features = true;
+            vhost_user.acked_protocol_features = acked_protocol_features;
+        }
+        vhost_user.features = features & !(1 << VHOST_USER_F_PROTOCOL_FEATURES);
+
+        Ok(vhost_user)
+    }
+
+    /// Returns another handle to the socket of this connection.
+    pub fn try_clone_socket(&self) -> Result<UnixStream> {
+        self.socket.try_clone().map_err(Error::VhostUserSocket)
+    }
+
+    /// Closes the connection, even if other handles to its socket are still open, which stops
+    /// the backend.
+    pub fn disconnect(&self) -> Result<()> {
+        self.socket
+            .shutdown(Shutdown::Both)
+            .map_err(Error::VhostUserSocket)
+    }
+
+    /// Reads the first `len` bytes of the configuration space of the device emulated by the
+    /// backend. Requires the `VHOST_USER_PROTOCOL_F_CONFIG` protocol feature.
+    pub fn get_config(&self, len: usize) -> Result<Vec<u8>> {
+        if self.acked_protocol_features & (1 << VHOST_USER_PROTOCOL_F_CONFIG) == 0 {
+            return Err(Error::VhostUserMissingProtocolFeatures(
+                1 << VHOST_USER_PROTOCOL_F_CONFIG,
+            ));
+        }
+
+        // The offset and flags are left to 0, followed by room for the configuration space.
+        let mut payload = vec![0u8; CONFIG_HEADER_LEN + len];
+        payload[4..8].copy_from_slice(&(len as u32).to_ne_bytes());
+        self.send(VHOST_USER_GET_CONFIG, &payload, &[])?;
+
+        let reply = self.recv_reply(VHOST_USER_GET_CONFIG, payload.len())?;
+        Ok(reply[CONFIG_HEADER_LEN..].to_vec())
+    }
+
+    // Sends a message, along with the given file descriptors.
+    fn send(&self, request: u32, payload: &[u8], fds: &[RawFd]) -> Result<()> {
+        let mut msg = Vec::with_capacity(HEADER_LEN + payload.len());
+        msg.extend_from_slice(&request.to_ne_bytes());
+        msg.extend_from_slice(&VHOST_USER_VERSION.to_ne_bytes());
+        msg.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        msg.extend_from_slice(payload);
+
+        let sent = send_with_fds(&self.socket, &msg, fds).map_err(Error::VhostUserSocket)?;
+        if sent != msg.len() {
+            return Err(Error::VhostUserSocket(IoError::from_raw_os_error(
+                libc::EMSGSIZE,
+            )));
+        }
+        Ok(())
+    }
+
+    // Receives the reply to `request`, and returns its payload, which must be `len` bytes long.
+    fn recv_reply(&self, request: u32, len: usize) -> Result<Vec<u8>> {
+        let mut reply = vec![0u8; HEADER_LEN + len];
+        (&self.socket)
+            .read_exact(&mut reply)
+            .map_err(Error::VhostUserSocket)?;
+        let field = |index: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&reply[index * 4..(index + 1) * 4]);
+            u32::from_ne_bytes(bytes)
+        };
+        if field(0) != request || field(1) & VHOST_USER_REPLY == 0 || field(2) as usize != len {
+            return Err(Error::VhostUserInvalidReply(request));
+        }
+
+        reply.drain(..HEADER_LEN);
+        Ok(reply)
+    }
+
+    // Sends a request without payload, and returns the 64 bit value the backend replies with.
+    fn get_u64(&self, request: u32) -> Result<u64> {
+        self.send(request, &[], &[])?;
+
+        let reply = self.recv_reply(request, 8)?;
+        let mut value = [0u8; 8];
+        value.copy_from_slice(&reply);
+        Ok(u64::from_ne_bytes(value))
+    }
+
+    fn set_vring_state(&self, request: u32, index: usize, num: u32) -> Result<()> {
+        let mut payload = Vec::with_capacity(8);
+        payload.extend_from_slice(&(index as u32).to_ne_bytes());
+        payload.extend_from_slice(&num.to_ne_bytes());
+        self.send(request, &payload, &[])
+    }
+}
+
+impl VhostBackend for VhostUser {
+    fn features(&self) -> u64 {
+        self.features
+    }
+
+    fn set_features(&self, mut features: u64) -> Result<()> {
+        if self.protocol_features {
+            features |= 1 << VHOST_USER_F_PROTOCOL_FEATURES;
+        }
+        self.send(VHOST_USER_SET_FEATURES, &features.to_ne_bytes(), &[])
+    }
+
+    fn set_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        let num_regions = mem.num_regions();
+        if num_regions > MAX_MEMORY_REGIONS {
+            return Err(Error::TooManyMemoryRegions(num_regions));
+        }
+
+        // The backend maps the memory regions itself, from the files backing them.
+        let mut payload = Vec::with_capacity(8 + num_regions * 32);
+        payload.extend_from_slice(&(num_regions as u32).to_ne_bytes());
+        payload.extend_from_slice(&0u32.to_ne_bytes());
+        let mut fds = Vec::with_capacity(num_regions);
+        for region in mem.iter() {
+            let file_offset = region
+                .file_offset()
+                .ok_or_else(|| Error::MemoryNotShared(region.start_addr()))?;
+            // It's safe to unwrap because the guest address is valid.
+            let userspace_addr = mem.get_host_address(region.start_addr()).unwrap() as u64;
+
+            payload.extend_from_slice(&region.start_addr().raw_value().to_ne_bytes());
+            payload.extend_from_slice(&region.len().to_ne_bytes());
+            payload.extend_from_slice(&userspace_addr.to_ne_bytes());
+            payload.extend_from_slice(&file_offset.start().to_ne_bytes());
+            fds.push(file_offset.file().as_raw_fd());
+        }
+
+        self.send(VHOST_USER_SET_MEM_TABLE, &payload, &fds)
+    }
+
+    fn set_vring(
+        &self,
+        index: usize,
+        queue: &Queue,
+        mem: &GuestMemoryMmap,
+        kick: &dyn AsRawFd,
+        call: &dyn AsRawFd,
+    ) -> Result<()> {
+        let host_address = |addr: GuestAddress| {
+            mem.get_host_address(addr)
+                .map(|host_addr| host_addr as u64)
+                .map_err(|_| Error::InvalidQueueAddress(addr))
+        };
+
+        self.set_vring_state(
+            VHOST_USER_SET_VRING_NUM,
+            index,
+            u32::from(queue.actual_size()),
+        )?;
+        self.set_vring_state(
+            VHOST_USER_SET_VRING_BASE,
+            index,
+            u32::from(queue.next_avail.0),
+        )?;
+
+        let mut payload = Vec::with_capacity(40);
+        payload.extend_from_slice(&(index as u32).to_ne_bytes());
+        // No flags, since dirty pages aren't logged.
+        payload.extend_from_slice(&0u32.to_ne_bytes());
+        payload.extend_from_slice(&host_address(queue.desc_table)?.to_ne_bytes());
+        payload.extend_from_slice(&host_address(queue.used_ring)?.to_ne_bytes());
+        payload.extend_from_slice(&host_address(queue.avail_ring)?.to_ne_bytes());
+        payload.extend_from_slice(&0u64.to_ne_bytes());
+        self.send(VHOST_USER_SET_VRING_ADDR, &payload, &[])?;
+
+        // The ring index goes in the lowest byte, and the file descriptor along the message.
+        let ring = (index as u64).to_ne_bytes();
+        self.send(VHOST_USER_SET_VRING_KICK, &ring, &[kick.as_raw_fd()])?;
+        self.send(VHOST_USER_SET_VRING_CALL, &ring, &[call.as_raw_fd()])
+    }
+
+    fn start_vring(&self, index: usize, _backend_fd: RawFd) -> Result<()> {
+        // Without protocol features, the backend starts the ring as soon as it gets kicked.
+        if self.protocol_features {
+            self.set_vring_state(VHOST_USER_SET_VRING_ENABLE, index, 1)?;
+        }
+        Ok(())
+    }
+}
+
+// Sends `buf` over `socket`, passing `fds` as `SCM_RIGHTS` ancillary data.
+fn send_with_fds(socket: &UnixStream, buf: &[u8], fds: &[RawFd]) -> IoResult<usize> {
+    let fds_len = mem::size_of_val(fds);
+    // The control buffer is made of u64s, to be suitably aligned for a `cmsghdr`.
+    // This is safe since CMSG_SPACE only computes a length.
+    let cmsg_space = unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize;
+    let mut cmsg_buf = vec![0u64; cmsg_space / 8 + 1];
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // This is safe; an all-zero `msghdr` is a valid value.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !fds.is_empty() {
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = cmsg_space as _;
+        // This is safe since the control buffer is large enough for a header followed by the
+        // file descriptors.
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len as u32) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr() as *const u8, libc::CMSG_DATA(cmsg), fds_len);
+        }
+    }
+
+    // This is safe since `msg` points to valid buffers and we check the return value.
+    let ret = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use utils::eventfd::EventFd;
+
+    use super::*;
+
+    pub(crate) struct Message {
+        pub(crate) request: u32,
+        pub(crate) payload: Vec<u8>,
+        pub(crate) num_fds: usize,
+    }
+
+    // Receives a message sent by the frontend, counting the file descriptors passed along.
+    fn recv_message(socket: &UnixStream) -> Option<Message> {
+        let mut buf = [0u8; 4096];
+        let mut cmsg_buf = [0u64; 64];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: HEADER_LEN,
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&cmsg_buf) as _;
+        let ret = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+        if ret <= 0 {
+            return None;
+        }
+
+        let mut num_fds = 0;
+        let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        if !cmsg.is_null() {
+            let cmsg_len = unsafe { (*cmsg).cmsg_len } as usize;
+            num_fds = (cmsg_len - unsafe { libc::CMSG_LEN(0) } as usize) / 4;
+        }
+
+        let field = |index: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&buf[index * 4..(index + 1) * 4]);
+            u32::from_ne_bytes(bytes)
+        };
+        let request = field(0);
+        let mut payload = vec![0u8; field(2) as usize];
+        (&*socket).read_exact(&mut payload).unwrap();
+        Some(Message {
+            request,
+            payload,
+            num_fds,
+        })
+    }
+
+    fn reply(socket: &UnixStream, request: u32, payload: &[u8]) {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&request.to_ne_bytes());
+        msg.extend_from_slice(&(VHOST_USER_VERSION | VHOST_USER_REPLY).to_ne_bytes());
+        msg.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        msg.extend_from_slice(payload);
+        std::io::Write::write_all(&mut &*socket, &msg).unwrap();
+    }
+
+    // Runs a backend offering `features` and the configuration space `config`, which records
+    // the messages it gets.
+    pub(crate) fn spawn_backend(
+        path: &str,
+        features: u64,
+        config: Vec<u8>,
+    ) -> thread::JoinHandle<Vec<Message>> {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut messages = Vec::new();
+            while let Some(message) = recv_message(&socket) {
+                match message.request {
+                    VHOST_USER_GET_FEATURES => {
+                        reply(&socket, message.request, &features.to_ne_bytes())
+                    }
+                    VHOST_USER_GET_PROTOCOL_FEATURES => {
+                        let protocol_features = 1u64 << VHOST_USER_PROTOCOL_F_CONFIG;
+                        reply(&socket, message.request, &protocol_features.to_ne_bytes())
+                    }
+                    VHOST_USER_GET_CONFIG => {
+                        let mut payload = message.payload[..CONFIG_HEADER_LEN].to_vec();
+                        let len = message.payload.len() - CONFIG_HEADER_LEN;
+                        payload.extend_from_slice(&config[..len]);
+                        reply(&socket, message.request, &payload)
+                    }
+                    _ => (),
+                }
+                messages.push(message);
+            }
+            messages
+        })
+    }
+
+    #[test]
+    fn test_connect_errors() {
+        assert!(matches!(
+            VhostUser::connect("/tmp/fc-vhost-user-nonexistent.sock", 0),
+            Err(Error::VhostUserConnect(_))
+        ));
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let path = "/tmp/fc-vhost-user-negotiation.sock";
+        let features = 1 << VHOST_USER_F_PROTOCOL_FEATURES | 1 << 32;
+        let backend = spawn_backend(path, features, Vec::new());
+
+        let vhost_user = VhostUser::connect(path, 0).unwrap();
+        assert_eq!(vhost_user.features(), 1 << 32);
+        assert!(vhost_user.protocol_features);
+        assert_eq!(vhost_user.acked_protocol_features, 0);
+        // The configuration space can't be read without the matching protocol feature.
+        assert!(matches!(
+            vhost_user.get_config(8),
+            Err(Error::VhostUserMissingProtocolFeatures(_))
+        ));
+
+        vhost_user.set_features(1 << 32).unwrap();
+        vhost_user.start_vring(1, -1).unwrap();
+        // Other handles to the socket don't keep the connection open.
+        let _socket = vhost_user.try_clone_socket().unwrap();
+        vhost_user.disconnect().unwrap();
+
+        let messages = backend.join().unwrap();
+        let requests: Vec<u32> = messages.iter().map(|message| message.request).collect();
+        assert_eq!(
+            requests,
+            vec![
+                VHOST_USER_SET_OWNER,
+                VHOST_USER_GET_FEATURES,
+                VHOST_USER_GET_PROTOCOL_FEATURES,
+                VHOST_USER_SET_PROTOCOL_FEATURES,
+                VHOST_USER_SET_FEATURES,
+                VHOST_USER_SET_VRING_ENABLE,
+            ]
+        );
+        assert_eq!(messages[3].payload, 0u64.to_ne_bytes());
+        // The protocol features bit is acked along with the guest features.
+        assert_eq!(messages[4].payload, features.to_ne_bytes());
+        assert_eq!(messages[5].payload, [1, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_get_config() {
+        let path = "/tmp/fc-vhost-user-config.sock";
+        let features = 1 << VHOST_USER_F_PROTOCOL_FEATURES;
+        let config: Vec<u8> = (0..64).collect();
+        let backend = spawn_backend(path, features, config.clone());
+
+        let vhost_user = VhostUser::connect(path, 1 << VHOST_USER_PROTOCOL_F_CONFIG).unwrap();
+        assert_eq!(
+            vhost_user.acked_protocol_features,
+            1 << VHOST_USER_PROTOCOL_F_CONFIG
+        );
+        assert_eq!(vhost_user.get_config(60).unwrap(), config[..60].to_vec());
+        drop(vhost_user);
+
+        let messages = backend.join().unwrap();
+        let requests: Vec<u32> = messages.iter().map(|message| message.request).collect();
+        assert_eq!(
+            requests,
+            vec![
+                VHOST_USER_SET_OWNER,
+                VHOST_USER_GET_FEATURES,
+                VHOST_USER_GET_PROTOCOL_FEATURES,
+                VHOST_USER_SET_PROTOCOL_FEATURES,
+                VHOST_USER_GET_CONFIG,
+            ]
+        );
+        assert_eq!(
+            messages[3].payload,
+            (1u64 << VHOST_USER_PROTOCOL_F_CONFIG).to_ne_bytes()
+        );
+        // The configuration space is read from its start.
+        assert_eq!(messages[4].payload.len(), CONFIG_HEADER_LEN + 60);
+        assert_eq!(messages[4].payload[..4], 0u32.to_ne_bytes());
+        assert_eq!(messages[4].payload[4..8], 60u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_set_mem_table_and_vring() {
+        let path = "/tmp/fc-vhost-user-mem-table.sock";
+        let backend = spawn_backend(path, 0, Vec::new());
+        let vhost_user = VhostUser::connect(path, 0).unwrap();
+        assert!(!vhost_user.protocol_features);
+
+        // Private guest memory can't be shared with the backend.
+        let mem =
+            vm_memory::test_utils::create_anon_guest_memory(&[(GuestAddress(0), 0x10000)], false)
+                .unwrap();
+        assert!(matches!(
+            vhost_user.set_mem_table(&mem),
+            Err(Error::MemoryNotShared(GuestAddress(0)))
+        ));
+
+        let mem = vm_memory::create_shared_guest_memory(
+            &[(GuestAddress(0), 0x10000), (GuestAddress(0x20000), 0x10000)],
+            false,
+        )
+        .unwrap();
+        vhost_user.set_mem_table(&mem).unwrap();
+
+        let mut queue = Queue::new(16);
+        queue.size = 16;
+        queue.desc_table = GuestAddress(0x1000);
+        queue.avail_ring = GuestAddress(0x2000);
+        queue.used_ring = GuestAddress(0x3000);
+        let kick = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let call = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        vhost_user.set_vring(1, &queue, &mem, &kick, &call).unwrap();
+        // The rings start along with the backend when protocol features aren't negotiated.
+        vhost_user.start_vring(1, -1).unwrap();
+        queue.desc_table = GuestAddress(0x10000);
+        assert!(matches!(
+            vhost_user.set_vring(1, &queue, &mem, &kick, &call),
+            Err(Error::InvalidQueueAddress(GuestAddress(0x10000)))
+        ));
+        drop(vhost_user);
+
+        let messages = backend.join().unwrap();
+        let requests: Vec<u32> = messages.iter().map(|message| message.request).collect();
+        assert_eq!(
+            requests,
+            vec![
+                VHOST_USER_SET_OWNER,
+                VHOST_USER_GET_FEATURES,
+                VHOST_USER_SET_MEM_TABLE,
+                VHOST_USER_SET_VRING_NUM,
+                VHOST_USER_SET_VRING_BASE,
+                VHOST_USER_SET_VRING_ADDR,
+                VHOST_USER_SET_VRING_KICK,
+                VHOST_USER_SET_VRING_CALL,
+                VHOST_USER_SET_VRING_NUM,
+                VHOST_USER_SET_VRING_BASE,
+            ]
+        );
+
+        // One file descriptor and 32 bytes of description per memory region.
+        let mem_table = &messages[2];
+        assert_eq!(mem_table.num_fds, 2);
+        assert_eq!(mem_table.payload.len(), 8 + 2 * 32);
+        assert_eq!(mem_table.payload[..4], 2u32.to_ne_bytes());
+        assert_eq!(mem_table.payload[40..48], 0x20000u64.to_ne_bytes());
+
+        assert_eq!(messages[3].payload, [1, 0, 0, 0, 16, 0, 0, 0]);
+        let host_addr = mem.get_host_address(GuestAddress(0x1000)).unwrap() as u64;
+        assert_eq!(messages[5].payload[8..16], host_addr.to_ne_bytes());
+        assert_eq!(messages[6].payload, 1u64.to_ne_bytes());
+        assert_eq!(messages[6].num_fds, 1);
+        assert_eq!(messages[7].num_fds, 1);
+    }
+}
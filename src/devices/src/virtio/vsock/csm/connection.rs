@@ -84,6 +84,7 @@ use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::{Duration, Instant};
 
 use logger::{debug, error, info, warn, IncMetric, METRICS};
+use serde::Serialize;
 use utils::epoll::EventSet;
 use vm_memory::{GuestMemoryError, GuestMemoryMmap};
 
@@ -95,6 +96,30 @@ use super::{defs, ConnState, Error, PendingRx, PendingRxSet, Result};
 
 /// A self-managing connection object, that handles communication between a guest-side AF_VSOCK
 /// socket and a host-side `Read + Write + AsRawFd` stream.
+/// A point in time view of a connection, used to debug stuck guest-host channels. The byte
+/// counters are the 32-bit wrapping ones of the vsock protocol.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VsockConnectionInfo {
+    /// The connection state, e.g. `Established`.
+    pub state: &'static str,
+    /// The host port.
+    pub local_port: u32,
+    /// The guest port.
+    pub peer_port: u32,
+    /// Number of bytes sent to the guest.
+    pub rx_bytes: u32,
+    /// Number of bytes sent by the guest and written to the host socket.
+    pub tx_bytes: u32,
+    /// Number of bytes sent by the guest, waiting for the host socket to accept them.
+    pub tx_buf_len: usize,
+    /// Capacity of the buffer holding the bytes sent by the guest.
+    pub tx_buf_size: u32,
+    /// Number of bytes sent to the guest which its socket hasn't consumed yet.
+    pub peer_buf_len: u32,
+    /// Capacity of the guest socket buffer, as advertised by the guest.
+    pub peer_buf_alloc: u32,
+}
+
 pub struct VsockConnection<S: Read + Write + AsRawFd> {
     /// The current connection state.
     state: ConnState,
@@ -574,6 +599,21 @@ where
         self.state
     }
 
+    /// Describe the connection, along with the occupancy of its buffers.
+    pub fn info(&self) -> VsockConnectionInfo {
+        VsockConnectionInfo {
+            state: self.state.name(),
+            local_port: self.local_port,
+            peer_port: self.peer_port,
+            rx_bytes: self.rx_cnt.0,
+            tx_bytes: self.fwd_cnt.0,
+            tx_buf_len: self.tx_buf.len(),
+            tx_buf_size: defs::CONN_TX_BUF_SIZE,
+            peer_buf_len: (self.rx_cnt - self.peer_fwd_cnt).0,
+            peer_buf_alloc: self.peer_buf_alloc,
+        }
+    }
+
     /// Send some raw, untracked, data straight to the underlying connected stream.
     /// Returns: number of bytes written, or the error describing the write failure.
     ///
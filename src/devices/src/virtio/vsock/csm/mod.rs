@@ -8,7 +8,7 @@ mod txbuf;
 
 use std::fmt;
 
-pub use connection::VsockConnection;
+pub use connection::{VsockConnection, VsockConnectionInfo};
 
 pub mod defs {
     /// Vsock connection TX buffer capacity.
@@ -76,6 +76,20 @@ pub enum ConnState {
     Killed,
 }
 
+impl ConnState {
+    /// The name of the state, without the guest R/W indication of `PeerClosed`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConnState::LocalInit => "LocalInit",
+            ConnState::PeerInit => "PeerInit",
+            ConnState::Established => "Established",
+            ConnState::LocalClosed => "LocalClosed",
+            ConnState::PeerClosed(..) => "PeerClosed",
+            ConnState::Killed => "Killed",
+        }
+    }
+}
+
 /// An RX indication, used by `VsockConnection` to schedule future `recv_pkt()` responses.
 /// For instance, after being notified that there is available data to be read from the host stream
 /// (via `notify()`), the connection will store a `PendingRx::Rw` to be later inspected by
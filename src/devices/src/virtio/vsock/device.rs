@@ -24,6 +24,7 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use logger::{debug, error, warn, IncMetric, METRICS};
+use rate_limiter::{RateLimiter, TokenType};
 use utils::byte_order;
 use utils::eventfd::EventFd;
 use vm_memory::{Bytes, GuestMemoryMmap};
@@ -31,7 +32,8 @@ use vm_memory::{Bytes, GuestMemoryMmap};
 use super::super::super::Error as DeviceError;
 use super::defs::uapi;
 use super::packet::{VsockPacket, VSOCK_PKT_HDR_SIZE};
-use super::{defs, VsockBackend};
+use super::{defs, VhostVsock, VsockBackend};
+use crate::virtio::net::VhostError;
 use crate::virtio::{
     ActivateError, ActivateResult, DeviceState, IrqTrigger, IrqType, Queue as VirtQueue,
     VirtioDevice, VsockError,
@@ -47,10 +49,13 @@ pub(crate) const VIRTIO_VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
 /// - VIRTIO_F_VERSION_1: the device conforms to at least version 1.0 of the VirtIO spec.
 /// - VIRTIO_F_IN_ORDER: the device returns used buffers in the same order that the driver makes
 ///   them available.
-pub(crate) const AVAIL_FEATURES: u64 =
-    1 << uapi::VIRTIO_F_VERSION_1 as u64 | 1 << uapi::VIRTIO_F_IN_ORDER as u64;
+/// - VIRTIO_VSOCK_F_DGRAM: the device carries datagrams, along with streams.
+pub(crate) const AVAIL_FEATURES: u64 = 1 << uapi::VIRTIO_F_VERSION_1 as u64
+    | 1 << uapi::VIRTIO_F_IN_ORDER as u64
+    | 1 << uapi::VIRTIO_VSOCK_F_DGRAM as u64;
 
 pub struct Vsock<B> {
+    id: String,
     cid: u64,
     pub(crate) queues: Vec<VirtQueue>,
     pub(crate) queue_events: Vec<EventFd>,
@@ -65,6 +70,15 @@ pub struct Vsock<B> {
     // continuous triggers from happening before the device gets activated.
     pub(crate) activate_evt: EventFd,
     pub(crate) device_state: DeviceState,
+    // When set, the kernel moves the packets of the RX and TX queues instead of the backend.
+    pub(crate) vhost: Option<VhostVsock>,
+    // Signalled by vhost when it uses buffers of the RX and TX queues.
+    pub(crate) vhost_call_evts: Vec<EventFd>,
+    // Limit the packets delivered to the guest and the ones it sends, respectively.
+    pub(crate) rx_rate_limiter: RateLimiter,
+    pub(crate) tx_rate_limiter: RateLimiter,
+    // Set while the backend events are unregistered, because the RX rate limiter is blocked.
+    pub(crate) backend_paused: bool,
 }
 
 // TODO: Detect / handle queue deadlock:
@@ -83,6 +97,7 @@ where
         }
 
         Ok(Vsock {
+            id: defs::VSOCK_DEV_ID.to_string(),
             cid,
             queues,
             queue_events,
@@ -92,6 +107,11 @@ where
             irq_trigger: IrqTrigger::new().map_err(VsockError::EventFd)?,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VsockError::EventFd)?,
             device_state: DeviceState::Inactive,
+            vhost: None,
+            vhost_call_evts: Vec::new(),
+            rx_rate_limiter: RateLimiter::default(),
+            tx_rate_limiter: RateLimiter::default(),
+            backend_paused: false,
         })
     }
 
@@ -104,8 +124,16 @@ where
         Self::with_queues(cid, backend, queues)
     }
 
+    /// Create a new virtio-vsock device like `new`, identified by `id` instead of the default
+    /// `VSOCK_DEV_ID`, so that it can be attached along with other vsock devices.
+    pub fn new_with_id(id: String, cid: u64, backend: B) -> super::Result<Vsock<B>> {
+        let mut vsock = Self::new(cid, backend)?;
+        vsock.id = id;
+        Ok(vsock)
+    }
+
     pub fn id(&self) -> &str {
-        defs::VSOCK_DEV_ID
+        &self.id
     }
 
     pub fn cid(&self) -> u64 {
@@ -116,6 +144,109 @@ where
         &self.backend
     }
 
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Delivers the packets the backend has pending to the guest, for when the backend is
+    /// changed outside of the handling of its events.
+    pub fn flush_backend_rx(&mut self) -> result::Result<(), DeviceError> {
+        if self.is_activated() && !self.uses_vhost() && self.process_rx() {
+            self.signal_used_queue()?;
+        }
+        Ok(())
+    }
+
+    /// Limits the packets delivered to the guest with `rx_rate_limiter`, and the ones it sends
+    /// with `tx_rate_limiter`. Each packet takes an operation token and as many bandwidth
+    /// tokens as the bytes of its payload.
+    pub fn set_rate_limiters(
+        &mut self,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) {
+        self.rx_rate_limiter = rx_rate_limiter;
+        self.tx_rate_limiter = tx_rate_limiter;
+    }
+
+    /// Provides a reference to the RX rate limiter.
+    pub fn rx_rate_limiter(&self) -> &RateLimiter {
+        &self.rx_rate_limiter
+    }
+
+    /// Provides a reference to the TX rate limiter.
+    pub fn tx_rate_limiter(&self) -> &RateLimiter {
+        &self.tx_rate_limiter
+    }
+
+    /// Returns true if the RX and TX queues of this vsock device are served by vhost-vsock.
+    pub fn uses_vhost(&self) -> bool {
+        self.vhost.is_some()
+    }
+
+    /// Offloads the RX and TX queues of this vsock device to the vhost-vsock kernel module,
+    /// which connects the guest to the AF_VSOCK sockets of the host. Must be called before the
+    /// device is activated.
+    pub fn enable_vhost(&mut self) -> super::Result<()> {
+        let vhost = VhostVsock::new(self.cid).map_err(VsockError::Vhost)?;
+        let missing_features = (1 << uapi::VIRTIO_F_VERSION_1) & !vhost.features();
+        if missing_features != 0 {
+            return Err(VsockError::Vhost(VhostError::UnsupportedFeatures(
+                missing_features,
+            )));
+        }
+        // The rings are driven by vhost, which doesn't promise to use the buffers in order.
+        self.avail_features &= vhost.features();
+
+        let mut call_evts = Vec::with_capacity(2);
+        for _ in &[RXQ_INDEX, TXQ_INDEX] {
+            call_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(VsockError::EventFd)?);
+        }
+        self.vhost_call_evts = call_evts;
+        self.vhost = Some(vhost);
+
+        Ok(())
+    }
+
+    // Hands the RX and TX rings over to vhost. Needs to run on the VMM thread, after
+    // activation.
+    pub(crate) fn start_vhost(&self) -> result::Result<(), VhostError> {
+        let vhost = match self.vhost.as_ref() {
+            Some(vhost) => vhost,
+            None => return Ok(()),
+        };
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+
+        vhost.set_features(self.acked_features & vhost.features())?;
+        vhost.set_mem_table(mem)?;
+        // vhost-vsock numbers the rings the same way the device model does.
+        for queue_index in &[RXQ_INDEX, TXQ_INDEX] {
+            vhost.set_vring(
+                *queue_index,
+                &self.queues[*queue_index],
+                mem,
+                &self.queue_events[*queue_index],
+                &self.vhost_call_evts[*queue_index],
+            )?;
+        }
+        vhost.set_running(true)
+    }
+
+    /// Relays to the guest the notification vhost sent for the `queue_index` queue.
+    pub fn process_vhost_call_event(&mut self, queue_index: usize) {
+        if let Err(e) = self.vhost_call_evts[queue_index].read() {
+            error!("Failed to get vhost call event: {:?}", e);
+            if queue_index == RXQ_INDEX {
+                METRICS.vsock.rx_queue_event_fails.inc();
+            } else {
+                METRICS.vsock.tx_queue_event_fails.inc();
+            }
+            return;
+        }
+        self.signal_used_queue().unwrap_or_default();
+    }
+
     /// Signal the guest driver that we've used some virtio buffers that it had previously made
     /// available.
     pub fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -138,7 +269,19 @@ where
         while let Some(head) = self.queues[RXQ_INDEX].pop(mem) {
             let used_len = match VsockPacket::from_rx_virtq_head(&head) {
                 Ok(mut pkt) => {
+                    // The size of the packet is only known once the backend fills it in, so the
+                    // tokens of a full buffer are taken, and the unused ones given back after.
+                    let buf_size = pkt.buf_size() as u64;
+                    if !Self::consume_pkt_tokens(&mut self.rx_rate_limiter, buf_size) {
+                        METRICS.vsock.rx_rate_limiter_throttled.inc();
+                        self.queues[RXQ_INDEX].undo_pop();
+                        break;
+                    }
                     if self.backend.recv_pkt(&mut pkt, mem).is_ok() {
+                        self.rx_rate_limiter.manual_replenish(
+                            buf_size.saturating_sub(u64::from(pkt.len())),
+                            TokenType::Bytes,
+                        );
                         match pkt.commit_hdr(mem) {
                             // This addition cannot overflow, because packet length
                             // is previously validated against `MAX_PKT_BUF_SIZE`
@@ -154,6 +297,9 @@ where
                             }
                         }
                     } else {
+                        self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
+                        self.rx_rate_limiter
+                            .manual_replenish(buf_size, TokenType::Bytes);
                         // We are using a consuming iterator over the virtio buffers, so, if we
                         // can't fill in this buffer, we'll need to undo the
                         // last iterator step.
@@ -203,7 +349,15 @@ where
                 }
             };
 
+            let len = u64::from(pkt.len());
+            if !Self::consume_pkt_tokens(&mut self.tx_rate_limiter, len) {
+                METRICS.vsock.tx_rate_limiter_throttled.inc();
+                self.queues[TXQ_INDEX].undo_pop();
+                break;
+            }
             if self.backend.send_pkt(&pkt, mem).is_err() {
+                self.tx_rate_limiter.manual_replenish(1, TokenType::Ops);
+                self.tx_rate_limiter.manual_replenish(len, TokenType::Bytes);
                 self.queues[TXQ_INDEX].undo_pop();
                 break;
             }
@@ -219,6 +373,18 @@ where
         have_used
     }
 
+    // Takes the tokens of a packet with `len` bytes of payload, or none of them.
+    fn consume_pkt_tokens(rate_limiter: &mut RateLimiter, len: u64) -> bool {
+        if !rate_limiter.consume(1, TokenType::Ops) {
+            return false;
+        }
+        if !rate_limiter.consume(len, TokenType::Bytes) {
+            rate_limiter.manual_replenish(1, TokenType::Ops);
+            return false;
+        }
+        true
+    }
+
     // Send TRANSPORT_RESET_EVENT to driver. According to specs, the driver shuts down established
     // connections and the guest_cid configuration field is fetched again. Existing listen sockets
     // remain but their CID is updated to reflect the current guest_cid.
@@ -346,7 +512,26 @@ where
 mod tests {
     use super::*;
     use crate::virtio::vsock::defs::uapi;
-    use crate::virtio::vsock::test_utils::TestContext;
+    use crate::virtio::vsock::test_utils::{TestBackend, TestContext};
+
+    #[test]
+    fn test_device_id() {
+        let vsock = Vsock::new(3, TestBackend::new()).unwrap();
+        assert_eq!(vsock.id(), defs::VSOCK_DEV_ID);
+
+        let vsock = Vsock::new_with_id("vsock-data".to_string(), 4, TestBackend::new()).unwrap();
+        assert_eq!(vsock.id(), "vsock-data");
+        assert_eq!(vsock.cid(), 4);
+    }
+
+    #[test]
+    fn test_vhost_disabled() {
+        let ctx = TestContext::new();
+        assert!(!ctx.device.uses_vhost());
+        assert_eq!(ctx.device.avail_features(), AVAIL_FEATURES);
+        // There is nothing to hand over to vhost.
+        assert!(ctx.device.start_vhost().is_ok());
+    }
 
     #[test]
     fn test_virtio_device() {
@@ -116,7 +116,64 @@ where
         raise_irq
     }
 
+    pub fn handle_rx_rate_limiter_event(&mut self) -> bool {
+        debug!("vsock: RX rate limiter event");
+        METRICS.vsock.rate_limiter_event_count.inc();
+
+        // There might be enough budget now to deliver the packets held back.
+        if self.rx_rate_limiter.event_handler().is_ok() && self.backend.has_pending_rx() {
+            return self.process_rx();
+        }
+        false
+    }
+
+    pub fn handle_tx_rate_limiter_event(&mut self) -> bool {
+        debug!("vsock: TX rate limiter event");
+        METRICS.vsock.rate_limiter_event_count.inc();
+
+        let mut raise_irq = false;
+        if self.tx_rate_limiter.event_handler().is_ok() {
+            raise_irq |= self.process_tx();
+            if self.backend.has_pending_rx() {
+                raise_irq |= self.process_rx();
+            }
+        }
+        raise_irq
+    }
+
+    // While the RX rate limiter is blocked, the backend would keep reporting the host data that
+    // can't be delivered yet, so its events are only listened to again once the limiter unblocks.
+    fn update_backend_registration(&mut self, ops: &mut EventOps) {
+        let paused = self.rx_rate_limiter.is_blocked();
+        if paused == self.backend_paused {
+            return;
+        }
+        let events = Events::new(&self.backend, self.backend.get_polled_evset());
+        let res = if paused {
+            ops.remove(events)
+        } else {
+            ops.add(events)
+        };
+        match res {
+            Ok(()) => self.backend_paused = paused,
+            Err(e) => error!("Failed to update the vsock backend registration: {}", e),
+        }
+    }
+
     fn register_runtime_events(&self, ops: &mut EventOps) {
+        if self.uses_vhost() {
+            // The RX and TX queue events are serviced by vhost, which notifies us when the
+            // guest needs to be interrupted. The backend doesn't get any packet.
+            for call_evt in &self.vhost_call_evts {
+                if let Err(e) = ops.add(Events::new(call_evt, EventSet::IN)) {
+                    error!("Failed to register vhost call event: {}", e);
+                }
+            }
+            if let Err(e) = ops.add(Events::new(&self.queue_events[EVQ_INDEX], EventSet::IN)) {
+                error!("Failed to register ev queue event: {}", e);
+            }
+            return;
+        }
         if let Err(e) = ops.add(Events::new(&self.queue_events[RXQ_INDEX], EventSet::IN)) {
             error!("Failed to register rx queue event: {}", e);
         }
@@ -129,6 +186,12 @@ where
         if let Err(e) = ops.add(Events::new(&self.backend, self.backend.get_polled_evset())) {
             error!("Failed to register vsock backend event: {}", e);
         }
+        if let Err(e) = ops.add(Events::new(&self.rx_rate_limiter, EventSet::IN)) {
+            error!("Failed to register vsock rx rate limiter event: {}", e);
+        }
+        if let Err(e) = ops.add(Events::new(&self.tx_rate_limiter, EventSet::IN)) {
+            error!("Failed to register vsock tx rate limiter event: {}", e);
+        }
     }
 
     fn register_activate_event(&self, ops: &mut EventOps) {
@@ -142,6 +205,10 @@ where
         if let Err(e) = self.activate_evt.read() {
             error!("Failed to consume net activate event: {:?}", e);
         }
+        if let Err(e) = self.start_vhost() {
+            error!("Failed to start vhost-vsock: {:?}", e);
+            METRICS.vsock.activate_fails.inc();
+        }
         self.register_runtime_events(ops);
         if let Err(e) = ops.remove(Events::new(&self.activate_evt, EventSet::IN)) {
             error!("Failed to un-register activate event: {}", e);
@@ -160,9 +227,18 @@ where
         let txq = self.queue_events[TXQ_INDEX].as_raw_fd();
         let evq = self.queue_events[EVQ_INDEX].as_raw_fd();
         let backend = self.backend.as_raw_fd();
+        let rx_rate_limiter = self.rx_rate_limiter.as_raw_fd();
+        let tx_rate_limiter = self.tx_rate_limiter.as_raw_fd();
         let activate_evt = self.activate_evt.as_raw_fd();
 
         if self.is_activated() {
+            if let Some(queue_index) = self
+                .vhost_call_evts
+                .iter()
+                .position(|call_evt| call_evt.as_raw_fd() == source)
+            {
+                return self.process_vhost_call_event(queue_index);
+            }
             let mut raise_irq = false;
             match source {
                 _ if source == rxq => raise_irq = self.handle_rxq_event(evset),
@@ -171,6 +247,8 @@ where
                 _ if source == backend => {
                     raise_irq = self.notify_backend(evset);
                 }
+                _ if source == rx_rate_limiter => raise_irq = self.handle_rx_rate_limiter_event(),
+                _ if source == tx_rate_limiter => raise_irq = self.handle_tx_rate_limiter_event(),
                 _ if source == activate_evt => {
                     self.handle_activate_event(ops);
                 }
@@ -179,6 +257,9 @@ where
             if raise_irq {
                 self.signal_used_queue().unwrap_or_default();
             }
+            if source != activate_evt {
+                self.update_backend_registration(ops);
+            }
         } else {
             warn!(
                 "Vsock: The device is not yet activated. Spurious event received: {:?}",
@@ -203,8 +284,11 @@ where
 #[cfg(test)]
 mod tests {
     use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
 
     use event_manager::{EventManager, SubscriberOps};
+    use rate_limiter::{RateLimiter, TokenType};
     use vm_memory::Bytes;
 
     use super::super::*;
@@ -351,6 +435,64 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rate_limiters() {
+        // Test case: the guest sends a packet while the TX rate limiter has no budget.
+        {
+            let test_ctx = TestContext::new();
+            let mut ctx = test_ctx.create_event_handler_context();
+            ctx.mock_activate(test_ctx.mem.clone());
+            ctx.device.set_rate_limiters(
+                RateLimiter::default(),
+                RateLimiter::new(0, 0, 0, 1, 0, 100).unwrap(),
+            );
+            assert!(ctx.device.tx_rate_limiter.consume(1, TokenType::Ops));
+
+            let throttled = METRICS.vsock.tx_rate_limiter_throttled.count();
+            ctx.device.backend.set_pending_rx(false);
+            ctx.signal_txq_event();
+            // The TX descriptor is left for later.
+            assert_eq!(ctx.guest_txvq.used.idx.get(), 0);
+            assert_eq!(ctx.device.backend.tx_ok_cnt, 0);
+            assert!(ctx.device.tx_rate_limiter.is_blocked());
+            assert_eq!(
+                METRICS.vsock.tx_rate_limiter_throttled.count(),
+                throttled + 1
+            );
+
+            // Once the budget is replenished, the packet goes through.
+            thread::sleep(Duration::from_millis(200));
+            assert!(ctx.device.handle_tx_rate_limiter_event());
+            assert_eq!(ctx.guest_txvq.used.idx.get(), 1);
+            assert_eq!(ctx.device.backend.tx_ok_cnt, 1);
+        }
+
+        // Test case: the backend has a packet for the guest while the RX rate limiter has no
+        // budget.
+        {
+            let test_ctx = TestContext::new();
+            let mut ctx = test_ctx.create_event_handler_context();
+            ctx.mock_activate(test_ctx.mem.clone());
+            ctx.device.set_rate_limiters(
+                RateLimiter::new(0, 0, 0, 1, 0, 100).unwrap(),
+                RateLimiter::default(),
+            );
+            assert!(ctx.device.rx_rate_limiter.consume(1, TokenType::Ops));
+
+            ctx.device.backend.set_pending_rx(true);
+            ctx.signal_rxq_event();
+            // The RX buffer is left for later, and the packet stays in the backend.
+            assert_eq!(ctx.guest_rxvq.used.idx.get(), 0);
+            assert_eq!(ctx.device.backend.rx_ok_cnt, 0);
+            assert!(ctx.device.rx_rate_limiter.is_blocked());
+
+            thread::sleep(Duration::from_millis(200));
+            assert!(ctx.device.handle_rx_rate_limiter_event());
+            assert_eq!(ctx.guest_rxvq.used.idx.get(), 1);
+            assert_eq!(ctx.device.backend.rx_ok_cnt, 1);
+        }
+    }
+
     #[test]
     fn test_evq_event() {
         // Test case: spurious EVQ_EVENT.
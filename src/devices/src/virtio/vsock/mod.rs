@@ -12,17 +12,22 @@ mod packet;
 pub mod persist;
 pub mod test_utils;
 mod unix;
+mod vhost;
 
 use std::os::unix::io::AsRawFd;
 
 use packet::VsockPacket;
+use serde::{Deserialize, Serialize};
 use utils::epoll::EventSet;
 use vm_memory::{GuestMemoryError, GuestMemoryMmap};
 
+pub use self::csm::VsockConnectionInfo;
 pub use self::defs::uapi::VIRTIO_ID_VSOCK as TYPE_VSOCK;
 pub use self::defs::VSOCK_DEV_ID;
 pub use self::device::Vsock;
-pub use self::unix::{Error as VsockUnixBackendError, VsockUnixBackend};
+pub use self::unix::{Error as VsockUnixBackendError, VsockUdsPermissions, VsockUnixBackend};
+pub use self::vhost::VhostVsock;
+use crate::virtio::net::VhostError;
 use crate::virtio::persist::Error as VirtioStateError;
 
 mod defs {
@@ -51,6 +56,8 @@ mod defs {
         pub const VIRTIO_F_IN_ORDER: usize = 35;
         /// The device conforms to the virtio spec version 1.0.
         pub const VIRTIO_F_VERSION_1: u32 = 32;
+        /// The device supports datagram sockets.
+        pub const VIRTIO_VSOCK_F_DGRAM: u32 = 3;
 
         /// Virtio vsock device ID.
         /// Defined in `include/uapi/linux/virtio_ids.h`.
@@ -85,8 +92,11 @@ mod defs {
         /// Vsock packet type.
         /// Defined in `/include/uapi/linux/virtio_vsock.h`.
         ///
-        /// Stream / connection-oriented packet (the only currently valid type).
+        /// Stream / connection-oriented packet.
         pub const VSOCK_TYPE_STREAM: u16 = 1;
+        /// Datagram / connectionless packet, only valid once `VIRTIO_VSOCK_F_DGRAM` is
+        /// negotiated.
+        pub const VSOCK_TYPE_DGRAM: u16 = 3;
 
         pub const VSOCK_HOST_CID: u64 = 2;
     }
@@ -121,10 +131,31 @@ pub enum VsockError {
     /// Invalid virtio configuration.
     VirtioState(VirtioStateError),
     VsockUdsBackend(VsockUnixBackendError),
+    /// Setting up vhost-vsock failed.
+    Vhost(VhostError),
+    /// Restoring a rate limiter failed.
+    RateLimiter(std::io::Error),
 }
 
 type Result<T> = std::result::Result<T, VsockError>;
 
+/// The way packets are moved between the guest and the host.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VsockDatapath {
+    /// Packets are handled by the device model, which forwards the connections to Unix sockets.
+    Virtio,
+    /// Packets are moved by the vhost-vsock kernel module, between the guest and host AF_VSOCK
+    /// sockets.
+    Vhost,
+}
+
+impl Default for VsockDatapath {
+    fn default() -> Self {
+        Self::Virtio
+    }
+}
+
 /// A passive, event-driven object, that needs to be notified whenever an epoll-able event occurs.
 /// An event-polling control loop will use `as_raw_fd()` and `get_polled_evset()` to query
 /// the listener for the file descriptor and the set of events it's interested in. When such an
@@ -6,6 +6,8 @@
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
+use rate_limiter::persist::RateLimiterState;
+use rate_limiter::RateLimiter;
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
@@ -28,6 +30,70 @@ pub struct VsockState {
 pub struct VsockFrontendState {
     pub cid: u64,
     virtio_state: VirtioDeviceState,
+    #[version(
+        start = 2,
+        default_fn = "def_rate_limiter_state",
+        ser_fn = "ser_rx_rate_limiter_state"
+    )]
+    rx_rate_limiter_state: Option<RateLimiterState>,
+    #[version(
+        start = 2,
+        default_fn = "def_rate_limiter_state",
+        ser_fn = "ser_tx_rate_limiter_state"
+    )]
+    tx_rate_limiter_state: Option<RateLimiterState>,
+}
+
+impl VsockState {
+    /// Lists the fields of the state this implementation of the device can't be restored from.
+    pub fn incompatible_fields(&self) -> Vec<String> {
+        self.frontend
+            .virtio_state
+            .incompatible_fields(TYPE_VSOCK, defs::QUEUE_SIZE)
+    }
+}
+
+impl VsockFrontendState {
+    fn def_rate_limiter_state(_: u16) -> Option<RateLimiterState> {
+        None
+    }
+
+    fn ser_rx_rate_limiter_state(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.rx_rate_limiter_state.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement vsock rate limiters.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn ser_tx_rate_limiter_state(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.tx_rate_limiter_state.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement vsock rate limiters.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// Only the rate limiters which have a bucket are saved, so that the snapshots of the devices
+// without rate limiters remain compatible with the older versions.
+fn save_rate_limiter(rate_limiter: &RateLimiter) -> Option<RateLimiterState> {
+    if rate_limiter.bandwidth().is_none() && rate_limiter.ops().is_none() {
+        None
+    } else {
+        Some(rate_limiter.save())
+    }
+}
+
+fn restore_rate_limiter(state: &Option<RateLimiterState>) -> Result<RateLimiter> {
+    match state {
+        Some(state) => RateLimiter::restore((), state).map_err(VsockError::RateLimiter),
+        None => Ok(RateLimiter::default()),
+    }
 }
 
 /// An enum for the serializable backend state types.
@@ -43,12 +109,33 @@ pub enum VsockBackendState {
 pub struct VsockUdsState {
     /// The path for the UDS socket.
     pub(crate) path: String,
+    /// The mode and ownership of the UDS sockets.
+    #[version(start = 2, default_fn = "def_permissions", ser_fn = "ser_permissions")]
+    pub(crate) permissions: VsockUdsPermissions,
+}
+
+impl VsockUdsState {
+    fn def_permissions(_: u16) -> VsockUdsPermissions {
+        VsockUdsPermissions::default()
+    }
+
+    fn ser_permissions(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.permissions != VsockUdsPermissions::default() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement vsock socket permissions.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 /// A helper structure that holds the constructor arguments for VsockUnixBackend
 pub struct VsockConstructorArgs<B> {
     pub mem: GuestMemoryMmap,
     pub backend: B,
+    // The ID of the device isn't part of its state, but of the one of its transport.
+    pub id: String,
 }
 
 /// A helper structure that holds the constructor arguments for VsockUnixBackend
@@ -65,6 +152,7 @@ impl Persist<'_> for VsockUnixBackend {
     fn save(&self) -> Self::State {
         VsockBackendState::Uds(VsockUdsState {
             path: self.host_sock_path.clone(),
+            permissions: self.sock_permissions(),
         })
     }
 
@@ -73,10 +161,12 @@ impl Persist<'_> for VsockUnixBackend {
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
         match state {
-            VsockBackendState::Uds(uds_state) => Ok(VsockUnixBackend::new(
-                constructor_args.cid,
-                uds_state.path.clone(),
-            )?),
+            VsockBackendState::Uds(uds_state) => {
+                let mut backend =
+                    VsockUnixBackend::new(constructor_args.cid, uds_state.path.clone())?;
+                backend.set_sock_permissions(uds_state.permissions)?;
+                Ok(backend)
+            }
         }
     }
 }
@@ -93,6 +183,8 @@ where
         VsockFrontendState {
             cid: self.cid(),
             virtio_state: VirtioDeviceState::from_device(self),
+            rx_rate_limiter_state: save_rate_limiter(&self.rx_rate_limiter),
+            tx_rate_limiter_state: save_rate_limiter(&self.tx_rate_limiter),
         }
     }
 
@@ -111,6 +203,11 @@ where
             )
             .map_err(VsockError::VirtioState)?;
         let mut vsock = Self::with_queues(state.cid, constructor_args.backend, queues)?;
+        vsock.id = constructor_args.id;
+        vsock.set_rate_limiters(
+            restore_rate_limiter(&state.rx_rate_limiter_state)?,
+            restore_rate_limiter(&state.tx_rate_limiter_state)?,
+        );
 
         vsock.acked_features = state.virtio_state.acked_features;
         vsock.avail_features = state.virtio_state.avail_features;
@@ -143,6 +240,7 @@ pub(crate) mod tests {
         fn save(&self) -> Self::State {
             VsockBackendState::Uds(VsockUdsState {
                 path: "test".to_owned(),
+                permissions: VsockUdsPermissions::default(),
             })
         }
 
@@ -194,11 +292,13 @@ pub(crate) mod tests {
                         TestBackend::new()
                     }
                 },
+                id: "vsock-data".to_string(),
             },
             &restored_state.frontend,
         )
         .unwrap();
 
+        assert_eq!(restored_device.id(), "vsock-data");
         assert_eq!(restored_device.device_type(), uapi::VIRTIO_ID_VSOCK);
         assert_eq!(restored_device.avail_features_by_page(0), device_pages[0]);
         assert_eq!(restored_device.avail_features_by_page(1), device_pages[1]);
@@ -236,4 +336,81 @@ pub(crate) mod tests {
         restored_device.read_config(2, &mut data);
         assert_eq!(data, [0u8, 1, 2, 3, 4, 5, 6, 7]);
     }
+
+    #[test]
+    fn test_persist_rate_limiters() {
+        assert!(VsockFrontendState::def_rate_limiter_state(1).is_none());
+
+        let mut ctx = TestContext::new();
+        ctx.device.set_rate_limiters(
+            RateLimiter::default(),
+            RateLimiter::new(0, 0, 0, 10, 0, 100).unwrap(),
+        );
+        let state = ctx.device.save();
+        // Only the rate limiters with buckets are saved.
+        assert!(state.rx_rate_limiter_state.is_none());
+        assert!(state.tx_rate_limiter_state.is_some());
+
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VsockFrontendState::type_id(), 2);
+
+        // Older versions can't describe the rate limiters.
+        let mut mem = vec![0; 4096];
+        assert!(state
+            .clone()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_device = Vsock::restore(
+            VsockConstructorArgs {
+                mem: ctx.mem.clone(),
+                backend: TestBackend::new(),
+                id: "vsock".to_string(),
+            },
+            &VsockFrontendState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_device.rx_rate_limiter(), &RateLimiter::default());
+        assert_eq!(
+            restored_device.tx_rate_limiter().ops().unwrap().capacity(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_persist_uds_permissions() {
+        let permissions = VsockUdsPermissions {
+            mode: Some(0o660),
+            uid: None,
+            gid: Some(1000),
+        };
+        let state = VsockUdsState {
+            path: "test".to_owned(),
+            permissions,
+        };
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VsockUdsState::type_id(), 2);
+
+        // Older versions can't describe the permissions.
+        let mut mem = vec![0; 4096];
+        assert!(state
+            .clone()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state =
+            VsockUdsState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(restored_state.path, "test");
+        assert_eq!(restored_state.permissions, permissions);
+    }
 }
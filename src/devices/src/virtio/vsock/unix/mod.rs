@@ -8,10 +8,19 @@
 /// handling vsock connection states.
 /// Check out `muxer.rs` for a more detailed explanation of the inner workings of this backend.
 mod muxer;
+mod muxer_dgram;
 mod muxer_killq;
 mod muxer_rxq;
 
+use std::ffi::CString;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+
 pub use muxer::VsockMuxer as VsockUnixBackend;
+use serde::{Deserialize, Serialize};
+use utils::syscall::SyscallReturnCode;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
 
 mod defs {
     /// Maximum number of established connections that we can handle.
@@ -22,6 +31,12 @@ mod defs {
 
     /// Size of the muxer connection kill queue.
     pub const MUXER_KILLQ_SIZE: usize = 128;
+
+    /// Number of host datagrams the muxer holds until the guest provides RX buffers.
+    pub const MUXER_DGRAM_RXQ_SIZE: usize = 64;
+
+    /// Maximum number of pooled connections, waiting to be taken by the host.
+    pub const MAX_POOLED_CONNECTIONS: usize = 256;
 }
 
 #[derive(Debug)]
@@ -40,9 +55,55 @@ pub enum Error {
     UnixConnect(std::io::Error),
     /// Error reading from host-side Unix socket.
     UnixRead(std::io::Error),
+    /// Error setting the mode or the ownership of a host-side Unix socket.
+    UnixPermissions(std::io::Error),
     /// Muxer connection limit reached.
     TooManyConnections,
+    /// Error creating the socket pair of a pooled connection.
+    UnixPair(std::io::Error),
+    /// Error passing a pooled connection to the host.
+    UnixSendFd(utils::errno::Error),
+    /// The host asked for a pooled connection to a port that has none ready.
+    NoPooledConnection(u32),
 }
 
 type Result<T> = std::result::Result<T, Error>;
+
+/// The mode and ownership of the host-side Unix sockets bound by the muxer. The ones left out
+/// keep the values the sockets are created with.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct VsockUdsPermissions {
+    /// The permission bits of the sockets, e.g. 0o660.
+    pub mode: Option<u32>,
+    /// The user owning the sockets.
+    pub uid: Option<u32>,
+    /// The group owning the sockets.
+    pub gid: Option<u32>,
+}
+
+impl VsockUdsPermissions {
+    /// Applies the mode and ownership to the socket bound at `path`.
+    fn apply(&self, path: &str) -> io::Result<()> {
+        if self.uid.is_some() || self.gid.is_some() {
+            let c_path =
+                CString::new(path).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+            // An ID of -1 is left unchanged.
+            // Safe because `c_path` is a valid C string.
+            SyscallReturnCode(unsafe {
+                libc::chown(
+                    c_path.as_ptr(),
+                    self.uid.unwrap_or(u32::MAX),
+                    self.gid.unwrap_or(u32::MAX),
+                )
+            })
+            .into_empty_result()?;
+        }
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+}
 type MuxerConnection = super::csm::VsockConnection<std::os::unix::net::UnixStream>;
@@ -30,24 +30,26 @@
 ///    other pollable FDs are then registered under this nested epoll FD.
 ///    To route all these events to their handlers, the muxer uses another `HashMap` object,
 ///    mapping `RawFd`s to `EpollListener`s.
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Read;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 
 use logger::{debug, error, info, warn, IncMetric, METRICS};
 use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use utils::sock_ctrl_msg::ScmSocket;
 use vm_memory::GuestMemoryMmap;
 
-use super::super::csm::ConnState;
+use super::super::csm::{ConnState, VsockConnectionInfo};
 use super::super::defs::uapi;
 use super::super::packet::VsockPacket;
 use super::super::{
     Result as VsockResult, VsockBackend, VsockChannel, VsockEpollListener, VsockError,
 };
+use super::muxer_dgram::MuxerDgramSock;
 use super::muxer_killq::MuxerKillQ;
 use super::muxer_rxq::MuxerRxQ;
-use super::{defs, Error, MuxerConnection, Result};
+use super::{defs, Error, MuxerConnection, Result, VsockUdsPermissions};
 
 /// A unique identifier of a `MuxerConnection` object. Connections are stored in a hash map,
 /// keyed by a `ConnMapKey` object.
@@ -74,11 +76,22 @@ enum EpollListener {
     Connection { key: ConnMapKey, evset: EventSet },
     /// A listener interested in new host-initiated connections.
     HostSock,
-    /// A listener interested in reading host "connect <port>" commands from a freshly
-    /// connected host socket.
+    /// A listener interested in the datagrams sent by the host.
+    DgramSock,
+    /// A listener interested in reading host "connect <port>" or "take <port>" commands from a
+    /// freshly connected host socket.
     LocalStream(UnixStream),
 }
 
+/// A command read from a freshly connected host socket.
+#[derive(Debug, PartialEq)]
+enum HostCommand {
+    /// `CONNECT <port>`: forward the host socket to the guest port.
+    Connect(u32),
+    /// `TAKE <port>`: pass a pooled connection to the guest port to the host.
+    Take(u32),
+}
+
 /// The vsock connection multiplexer.
 pub struct VsockMuxer {
     /// Guest CID.
@@ -94,11 +107,17 @@ pub struct VsockMuxer {
     rxq: MuxerRxQ,
     /// A queue used for terminating connections that are taking too long to shut down.
     killq: MuxerKillQ,
-    /// The Unix socket, through which host-initiated connections are accepted.
-    host_sock: UnixListener,
+    /// The Unix socket, through which host-initiated connections are accepted. Muxers of vsock
+    /// devices served by vhost-vsock have none, since connections don't go through them.
+    host_sock: Option<UnixListener>,
+    /// The Unix datagram socket, through which the host sends datagrams to the guest and the
+    /// guest ones are forwarded. Absent along with `host_sock`.
+    dgram_sock: Option<MuxerDgramSock>,
     /// The file system path of the host-side Unix socket. This is used to figure out the path
     /// to Unix sockets listening on specific ports. I.e. "<this path>_<port number>".
     pub(crate) host_sock_path: String,
+    /// The mode and ownership of `host_sock` and `dgram_sock`.
+    sock_permissions: VsockUdsPermissions,
     /// The nested epoll event set, used to register epoll listeners.
     epoll: Epoll,
     /// A hash set used to keep track of used host-side (local) ports, in order to assign local
@@ -106,6 +125,10 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// The connections initiated ahead of time, keyed by guest port. Each of them holds the
+    /// host end of the socket pair the muxer forwards the connection to, until the host takes
+    /// it over.
+    pool: HashMap<u32, VecDeque<(ConnMapKey, UnixStream)>>,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -176,7 +199,12 @@ impl VsockChannel for VsockMuxer {
             }
         }
 
-        Err(VsockError::NoData)
+        // Datagrams go out once the connections have nothing left to say.
+        let cid = self.cid;
+        match self.dgram_sock.as_mut() {
+            Some(dgram_sock) => dgram_sock.recv_pkt(pkt, mem, cid),
+            None => Err(VsockError::NoData),
+        }
     }
 
     /// Deliver a guest-generated packet to its destination in the vsock backend.
@@ -199,6 +227,19 @@ impl VsockChannel for VsockMuxer {
             pkt.hdr()
         );
 
+        // Datagrams aren't acknowledged, so the ones we can't forward are silently dropped.
+        if pkt.type_() == uapi::VSOCK_TYPE_DGRAM {
+            match self.dgram_sock.as_ref() {
+                Some(dgram_sock)
+                    if pkt.dst_cid() == uapi::VSOCK_HOST_CID && pkt.op() == uapi::VSOCK_OP_RW =>
+                {
+                    dgram_sock.send_pkt(pkt, mem)
+                }
+                _ => METRICS.vsock.dgrams_dropped.inc(),
+            }
+            return Ok(());
+        }
+
         // If this packet has an unsupported type (!=stream), we must send back an RST.
         //
         if pkt.type_() != uapi::VSOCK_TYPE_STREAM {
@@ -250,7 +291,12 @@ impl VsockChannel for VsockMuxer {
     /// Check if the muxer has any pending RX data, with which to fill a guest-provided RX
     /// buffer.
     fn has_pending_rx(&self) -> bool {
-        !self.rxq.is_empty() || !self.rxq.is_synced()
+        !self.rxq.is_empty()
+            || !self.rxq.is_synced()
+            || self
+                .dgram_sock
+                .as_ref()
+                .map_or(false, MuxerDgramSock::has_pending_rx)
     }
 }
 
@@ -308,11 +354,36 @@ impl VsockMuxer {
         let host_sock = UnixListener::bind(&host_sock_path)
             .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
             .map_err(Error::UnixBind)?;
+        let host_sock_fd = host_sock.as_raw_fd();
+        let dgram_sock = MuxerDgramSock::bind(&host_sock_path)?;
+        let dgram_sock_fd = dgram_sock.as_raw_fd();
 
-        let mut muxer = Self {
+        let mut muxer = Self::with_sockets(cid, Some(host_sock), Some(dgram_sock), host_sock_path)?;
+        // Listen on the host initiated socket, for incoming connections.
+        muxer.add_listener(host_sock_fd, EpollListener::HostSock)?;
+        // Listen on the datagram socket, for incoming datagrams.
+        muxer.add_listener(dgram_sock_fd, EpollListener::DgramSock)?;
+        Ok(muxer)
+    }
+
+    /// Creates a muxer without a host socket, for vsock devices served by vhost-vsock, whose
+    /// packets never reach the muxer.
+    pub fn new_detached(cid: u64) -> Result<Self> {
+        Self::with_sockets(cid, None, None, String::new())
+    }
+
+    fn with_sockets(
+        cid: u64,
+        host_sock: Option<UnixListener>,
+        dgram_sock: Option<MuxerDgramSock>,
+        host_sock_path: String,
+    ) -> Result<Self> {
+        Ok(Self {
             cid,
             host_sock,
+            dgram_sock,
             host_sock_path,
+            sock_permissions: VsockUdsPermissions::default(),
             epoll: Epoll::new().map_err(Error::EpollFdCreate)?,
             rxq: MuxerRxQ::new(),
             conn_map: HashMap::with_capacity(defs::MAX_CONNECTIONS),
@@ -320,17 +391,44 @@ impl VsockMuxer {
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
             local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
-        };
-
-        // Listen on the host initiated socket, for incoming connections.
-        muxer.add_listener(muxer.host_sock.as_raw_fd(), EpollListener::HostSock)?;
-        Ok(muxer)
+            pool: HashMap::new(),
+        })
     }
 
     pub fn host_sock_path(&self) -> &str {
         &self.host_sock_path
     }
 
+    /// Provides the mode and ownership of the host-side sockets.
+    pub fn sock_permissions(&self) -> VsockUdsPermissions {
+        self.sock_permissions
+    }
+
+    /// Sets the mode and ownership of the host-side sockets, i.e. the one accepting host-initiated
+    /// connections and the datagram one.
+    pub fn set_sock_permissions(&mut self, permissions: VsockUdsPermissions) -> Result<()> {
+        if self.host_sock.is_some() {
+            permissions
+                .apply(&self.host_sock_path)
+                .map_err(Error::UnixPermissions)?;
+        }
+        if let Some(dgram_sock) = self.dgram_sock.as_ref() {
+            permissions
+                .apply(dgram_sock.path())
+                .map_err(Error::UnixPermissions)?;
+        }
+        self.sock_permissions = permissions;
+        Ok(())
+    }
+
+    /// Describes the active connections, ordered by host port.
+    pub fn connections(&self) -> Vec<VsockConnectionInfo> {
+        let mut conns: Vec<VsockConnectionInfo> =
+            self.conn_map.values().map(MuxerConnection::info).collect();
+        conns.sort_by_key(|conn| (conn.local_port, conn.peer_port));
+        conns
+    }
+
     /// Handle/dispatch an epoll event to its listener.
     fn handle_event(&mut self, fd: RawFd, event_set: EventSet) {
         debug!(
@@ -354,14 +452,19 @@ impl VsockMuxer {
 
             // A new host-initiated connection is ready to be accepted.
             Some(EpollListener::HostSock) => {
+                // This listener is only registered along with the host socket.
+                let host_sock = match self.host_sock.as_ref() {
+                    Some(host_sock) => host_sock,
+                    None => return,
+                };
                 if self.conn_map.len() == defs::MAX_CONNECTIONS {
                     // If we're already maxed-out on connections, we'll just accept and
                     // immediately discard this potentially new one.
                     warn!("vsock: connection limit reached; refusing new host connection");
-                    self.host_sock.accept().map(|_| 0).unwrap_or(0);
+                    host_sock.accept().map(|_| 0).unwrap_or(0);
                     return;
                 }
-                self.host_sock
+                host_sock
                     .accept()
                     .map_err(Error::UnixAccept)
                     .and_then(|(stream, _)| {
@@ -382,13 +485,20 @@ impl VsockMuxer {
                     });
             }
 
+            // The host sent datagrams to the guest.
+            Some(EpollListener::DgramSock) => {
+                if let Some(dgram_sock) = self.dgram_sock.as_mut() {
+                    dgram_sock.recv_host_dgrams();
+                }
+            }
+
             // Data is ready to be read from a host-initiated connection. That would be the
-            // "connect" command that we're expecting.
+            // "connect" or "take" command that we're expecting.
             Some(EpollListener::LocalStream(_)) => {
                 if let Some(EpollListener::LocalStream(mut stream)) = self.remove_listener(fd) {
-                    Self::read_local_stream_port(&mut stream)
-                        .map(|peer_port| (self.allocate_local_port(), peer_port))
-                        .and_then(|(local_port, peer_port)| {
+                    match Self::read_local_stream_cmd(&mut stream) {
+                        Ok(HostCommand::Connect(peer_port)) => {
+                            let local_port = self.allocate_local_port();
                             self.add_connection(
                                 ConnMapKey {
                                     local_port,
@@ -402,10 +512,17 @@ impl VsockMuxer {
                                     peer_port,
                                 ),
                             )
-                        })
-                        .unwrap_or_else(|err| {
-                            info!("vsock: error adding local-init connection: {:?}", err);
-                        })
+                            .unwrap_or_else(|err| {
+                                info!("vsock: error adding local-init connection: {:?}", err);
+                            })
+                        }
+                        Ok(HostCommand::Take(peer_port)) => self
+                            .send_pooled_connection(&stream, peer_port)
+                            .unwrap_or_else(|err| {
+                                info!("vsock: error passing pooled connection: {:?}", err);
+                            }),
+                        Err(err) => info!("vsock: error reading host command: {:?}", err),
+                    }
                 }
             }
 
@@ -419,13 +536,13 @@ impl VsockMuxer {
         }
     }
 
-    /// Parse a host "connect" command, and extract the destination vsock port.
-    fn read_local_stream_port(stream: &mut UnixStream) -> Result<u32> {
+    /// Parse a host "connect" or "take" command, and extract the destination vsock port.
+    fn read_local_stream_cmd(stream: &mut UnixStream) -> Result<HostCommand> {
         let mut buf = [0u8; 32];
 
         // This is the minimum number of bytes that we should be able to read, when parsing a
-        // valid connection request. I.e. `b"connect 0\n".len()`.
-        const MIN_READ_LEN: usize = 10;
+        // valid command. I.e. `b"take 0\n".len()`.
+        const MIN_READ_LEN: usize = 7;
 
         // Bring in the minimum number of bytes that we should be able to read.
         stream
@@ -447,19 +564,116 @@ impl VsockMuxer {
             .map_err(|_| Error::InvalidPortRequest)?
             .split_whitespace();
 
+        let cmd: fn(u32) -> HostCommand = match word_iter.next().map(str::to_lowercase) {
+            Some(word) if word == "connect" => HostCommand::Connect,
+            Some(word) if word == "take" => HostCommand::Take,
+            _ => return Err(Error::InvalidPortRequest),
+        };
         word_iter
             .next()
             .ok_or(Error::InvalidPortRequest)
-            .and_then(|word| {
-                if word.to_lowercase() == "connect" {
-                    Ok(())
-                } else {
-                    Err(Error::InvalidPortRequest)
-                }
-            })
-            .and_then(|_| word_iter.next().ok_or(Error::InvalidPortRequest))
             .and_then(|word| word.parse::<u32>().map_err(|_| Error::InvalidPortRequest))
-            .map_err(|_| Error::InvalidPortRequest)
+            .map(cmd)
+    }
+
+    /// Initiates `count` connections to the guest port `peer_port` ahead of time. Once the
+    /// guest accepts them, the host can take them over with a "take <port>" command, without
+    /// waiting for the guest to accept a new connection.
+    pub fn pool_connections(&mut self, peer_port: u32, count: usize) -> Result<()> {
+        self.purge_pool();
+        let pooled: usize = self.pool.values().map(VecDeque::len).sum();
+        if pooled + count > defs::MAX_POOLED_CONNECTIONS {
+            return Err(Error::TooManyConnections);
+        }
+
+        for _ in 0..count {
+            let (muxer_end, host_end) = UnixStream::pair().map_err(Error::UnixPair)?;
+            muxer_end.set_nonblocking(true).map_err(Error::UnixPair)?;
+            let local_port = self.allocate_local_port();
+            let key = ConnMapKey {
+                local_port,
+                peer_port,
+            };
+            let conn = MuxerConnection::new_local_init(
+                muxer_end,
+                uapi::VSOCK_HOST_CID,
+                self.cid,
+                local_port,
+                peer_port,
+            );
+            if let Err(err) = self.add_connection(key, conn) {
+                self.free_local_port(local_port);
+                return Err(err);
+            }
+            self.pool
+                .entry(peer_port)
+                .or_default()
+                .push_back((key, host_end));
+        }
+        Ok(())
+    }
+
+    /// Provides the number of pooled connections to `peer_port` the guest accepted, and which
+    /// are waiting to be taken by the host.
+    pub fn pooled_connections(&self, peer_port: u32) -> usize {
+        self.pool.get(&peer_port).map_or(0, |conns| {
+            conns
+                .iter()
+                .filter(|(key, _)| {
+                    self.conn_map.get(key).map(MuxerConnection::state)
+                        == Some(ConnState::Established)
+                })
+                .count()
+        })
+    }
+
+    // Drops the pooled connections the guest refused or shut down. Dropping the host end of
+    // their socket pairs shuts down the ones which are still around.
+    fn purge_pool(&mut self) {
+        let conn_map = &self.conn_map;
+        for conns in self.pool.values_mut() {
+            conns.retain(|(key, _)| {
+                matches!(
+                    conn_map.get(key).map(MuxerConnection::state),
+                    Some(ConnState::LocalInit) | Some(ConnState::Established)
+                )
+            });
+        }
+        self.pool.retain(|_, conns| !conns.is_empty());
+    }
+
+    /// Passes the oldest pooled connection to `peer_port` the guest accepted to the host, over
+    /// `stream`. The connection comes as an `SCM_RIGHTS` file descriptor, along with the usual
+    /// "OK <local port>" ack.
+    fn send_pooled_connection(&mut self, stream: &UnixStream, peer_port: u32) -> Result<()> {
+        self.purge_pool();
+        let conns = self
+            .pool
+            .get_mut(&peer_port)
+            .ok_or(Error::NoPooledConnection(peer_port))?;
+        let conn_map = &self.conn_map;
+        let index = conns
+            .iter()
+            .position(|(key, _)| {
+                conn_map.get(key).map(MuxerConnection::state) == Some(ConnState::Established)
+            })
+            .ok_or(Error::NoPooledConnection(peer_port))?;
+        // The index was just found, so the connection is there.
+        let (key, mut conn_stream) = conns.remove(index).unwrap();
+        if conns.is_empty() {
+            self.pool.remove(&peer_port);
+        }
+
+        // The ack was written to the connection when the guest accepted it. It is read back, so
+        // that the host gets the connection ready for its own data.
+        let msg = format!("OK {}\n", key.local_port);
+        let mut ack = vec![0u8; msg.len()];
+        conn_stream.read_exact(&mut ack).map_err(Error::UnixRead)?;
+        stream
+            .send_with_fd(msg.as_bytes(), conn_stream.as_raw_fd())
+            .map_err(Error::UnixSendFd)?;
+        METRICS.vsock.pooled_conns_taken.inc();
+        Ok(())
     }
 
     /// Add a new connection to the active connection pool.
@@ -533,6 +747,7 @@ impl VsockMuxer {
             EpollListener::Connection { evset, .. } => evset,
             EpollListener::LocalStream(_) => EventSet::IN,
             EpollListener::HostSock => EventSet::IN,
+            EpollListener::DgramSock => EventSet::IN,
         };
 
         self.epoll
@@ -769,7 +984,8 @@ impl VsockMuxer {
 mod tests {
     use std::io::{Read, Write};
     use std::ops::Drop;
-    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
     use std::path::{Path, PathBuf};
 
     use utils::tempfile::TempFile;
@@ -997,15 +1213,15 @@ mod tests {
     fn test_bad_peer_pkt() {
         const LOCAL_PORT: u32 = 1026;
         const PEER_PORT: u32 = 1025;
-        const SOCK_DGRAM: u16 = 2;
+        const SOCK_SEQPACKET: u16 = 2;
 
         let mut ctx = MuxerTestContext::new("bad_peer_pkt");
         ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST)
-            .set_type(SOCK_DGRAM);
+            .set_type(SOCK_SEQPACKET);
         ctx.send();
 
-        // The guest sent a SOCK_DGRAM packet. Per the vsock spec, we need to reply with an RST
-        // packet, since vsock only supports stream sockets.
+        // The guest sent a SOCK_SEQPACKET packet. Per the vsock spec, we need to reply with an
+        // RST packet, since the muxer only supports stream and datagram sockets.
         assert!(ctx.muxer.has_pending_rx());
         ctx.recv();
         assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
@@ -1041,6 +1257,86 @@ mod tests {
         assert!(!ctx.muxer.has_pending_rx());
     }
 
+    #[test]
+    fn test_guest_dgram() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("guest_dgram");
+        let host_sock_path = format!("{}_{}", ctx.muxer.host_sock_path, LOCAL_PORT);
+        let host_sock = UnixDatagram::bind(&host_sock_path).unwrap();
+        host_sock.set_nonblocking(true).unwrap();
+
+        let tx_dgrams_count = METRICS.vsock.tx_dgrams_count.count();
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &data)
+            .set_type(uapi::VSOCK_TYPE_DGRAM);
+        ctx.send();
+
+        // The datagram is forwarded as is, and isn't replied to.
+        let mut buf = vec![0u8; 16];
+        let len = host_sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &data);
+        assert_eq!(METRICS.vsock.tx_dgrams_count.count(), tx_dgrams_count + 1);
+        assert!(!ctx.muxer.has_pending_rx());
+
+        // Datagrams for ports nobody listens on are dropped.
+        let dgrams_dropped = METRICS.vsock.dgrams_dropped.count();
+        ctx.init_data_pkt(LOCAL_PORT + 1, PEER_PORT, &data)
+            .set_type(uapi::VSOCK_TYPE_DGRAM);
+        ctx.send();
+        assert_eq!(METRICS.vsock.dgrams_dropped.count(), dgrams_dropped + 1);
+        assert!(!ctx.muxer.has_pending_rx());
+
+        std::fs::remove_file(&host_sock_path).unwrap();
+    }
+
+    #[test]
+    fn test_host_dgram() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("host_dgram");
+        let host_sock_path = format!("{}_{}", ctx.muxer.host_sock_path, LOCAL_PORT);
+        let host_sock = UnixDatagram::bind(&host_sock_path).unwrap();
+        let dgram_sock_path = format!("{}_dgram", ctx.muxer.host_sock_path);
+
+        let data = [5, 6, 7, 8, 9];
+        let mut dgram = format!("SEND {}\n", PEER_PORT).into_bytes();
+        dgram.extend_from_slice(&data);
+        host_sock.send_to(&dgram, &dgram_sock_path).unwrap();
+        // Datagrams without a valid header are dropped.
+        host_sock
+            .send_to(b"CONNECT 1025\n", &dgram_sock_path)
+            .unwrap();
+        let dgrams_dropped = METRICS.vsock.dgrams_dropped.count();
+        ctx.notify_muxer();
+        assert_eq!(METRICS.vsock.dgrams_dropped.count(), dgrams_dropped + 1);
+
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.type_(), uapi::VSOCK_TYPE_DGRAM);
+        assert_eq!(ctx.pkt.src_cid(), uapi::VSOCK_HOST_CID);
+        assert_eq!(ctx.pkt.dst_cid(), PEER_CID);
+        assert_eq!(ctx.pkt.src_port(), LOCAL_PORT);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+        assert_eq!(ctx.pkt.len() as usize, data.len());
+        let mut buf = vec![0u8; data.len()];
+        ctx.pkt
+            .write_from_offset_to(
+                &ctx._vsock_test_ctx.mem,
+                0,
+                &mut buf.as_mut_slice(),
+                data.len(),
+            )
+            .unwrap();
+        assert_eq!(buf, data);
+        assert!(!ctx.muxer.has_pending_rx());
+
+        std::fs::remove_file(&host_sock_path).unwrap();
+    }
+
     #[test]
     fn test_peer_connection() {
         const LOCAL_PORT: u32 = 1026;
@@ -1154,6 +1450,46 @@ mod tests {
         assert_eq!(&buf, &data);
     }
 
+    #[test]
+    fn test_muxer_connections() {
+        let mut ctx = MuxerTestContext::new("muxer_connections");
+        assert!(ctx.muxer.connections().is_empty());
+
+        let peer_port = 1025;
+        let (mut stream, local_port) = ctx.local_connect(peer_port);
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(local_port, peer_port, &data);
+        ctx.send();
+        stream.write_all(&[5, 6, 7, 8, 9, 10]).unwrap();
+        ctx.notify_muxer();
+        ctx.recv();
+
+        let conns = ctx.muxer.connections();
+        assert_eq!(conns.len(), 1);
+        let conn = &conns[0];
+        assert_eq!(conn.state, "Established");
+        assert_eq!(conn.local_port, local_port);
+        assert_eq!(conn.peer_port, peer_port);
+        assert_eq!(conn.tx_bytes, 4);
+        assert_eq!(conn.tx_buf_len, 0);
+        assert_eq!(conn.tx_buf_size, csm_defs::CONN_TX_BUF_SIZE);
+        assert_eq!(conn.rx_bytes, 6);
+        assert_eq!(conn.peer_buf_len, 6);
+        assert_eq!(conn.peer_buf_alloc, PEER_BUF_ALLOC);
+
+        // Connections are listed by host port.
+        let (_stream, other_local_port) = ctx.local_connect(peer_port + 1);
+        let ports: Vec<u32> = ctx
+            .muxer
+            .connections()
+            .iter()
+            .map(|conn| conn.local_port)
+            .collect();
+        let mut expected_ports = vec![local_port, other_local_port];
+        expected_ports.sort_unstable();
+        assert_eq!(ports, expected_ports);
+    }
+
     #[test]
     fn test_local_close() {
         let peer_port = 1025;
@@ -1524,4 +1860,117 @@ mod tests {
         // Check that the connection was removed.
         assert_eq!(METRICS.vsock.conns_removed.count(), conns_removed + 1);
     }
+
+    #[test]
+    fn test_detached_muxer() {
+        let muxer = VsockMuxer::new_detached(PEER_CID).unwrap();
+        assert!(muxer.host_sock.is_none());
+        assert!(muxer.dgram_sock.is_none());
+        assert!(muxer.host_sock_path().is_empty());
+        // Nothing is polled until connections are added.
+        assert!(muxer.listener_map.is_empty());
+        assert!(!muxer.has_pending_rx());
+    }
+
+    #[test]
+    fn test_pooled_connections() {
+        const PEER_PORT: u32 = 1025;
+        let mut ctx = MuxerTestContext::new("pooled_connections");
+        let conns_taken = METRICS.vsock.pooled_conns_taken.count();
+
+        ctx.muxer.pool_connections(PEER_PORT, 2).unwrap();
+        assert!(matches!(
+            ctx.muxer
+                .pool_connections(PEER_PORT, defs::MAX_POOLED_CONNECTIONS - 1),
+            Err(Error::TooManyConnections)
+        ));
+
+        // The guest gets a connection request for each of the pooled connections.
+        let mut local_ports = Vec::new();
+        for _ in 0..2 {
+            ctx.recv();
+            assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_REQUEST);
+            assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+            local_ports.push(ctx.pkt.src_port());
+        }
+        assert!(!ctx.muxer.has_pending_rx());
+        assert_eq!(ctx.muxer.pooled_connections(PEER_PORT), 0);
+
+        // Only the connections the guest accepted are handed out.
+        ctx.init_pkt(local_ports[0], PEER_PORT, uapi::VSOCK_OP_RESPONSE);
+        ctx.send();
+        assert_eq!(ctx.muxer.pooled_connections(PEER_PORT), 1);
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(local_ports[0], PEER_PORT, &data);
+        ctx.send();
+
+        let take = |ctx: &mut MuxerTestContext| {
+            let mut stream = UnixStream::connect(ctx.muxer.host_sock_path.clone()).unwrap();
+            ctx.notify_muxer();
+            stream
+                .write_all(format!("TAKE {}\n", PEER_PORT).as_bytes())
+                .unwrap();
+            ctx.notify_muxer();
+            let mut buf = [0u8; 32];
+            let (len, file) = stream.recv_with_fd(&mut buf).unwrap();
+            (buf[..len].to_vec(), file)
+        };
+        let (ack, file) = take(&mut ctx);
+        assert_eq!(ack, format!("OK {}\n", local_ports[0]).as_bytes());
+        assert_eq!(METRICS.vsock.pooled_conns_taken.count(), conns_taken + 1);
+
+        // The data the guest sent before the connection was taken comes first, without the ack.
+        // Safe because the file descriptor was just received, and nothing else owns it.
+        let mut conn_stream = unsafe { UnixStream::from_raw_fd(file.unwrap().into_raw_fd()) };
+        let mut buf = [0u8; 4];
+        conn_stream.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        // The other connection is still waiting for the guest, so the host gets nothing.
+        let (ack, file) = take(&mut ctx);
+        assert!(ack.is_empty());
+        assert!(file.is_none());
+
+        // The pool can be filled again, up to its limit.
+        ctx.muxer
+            .pool_connections(PEER_PORT, defs::MAX_POOLED_CONNECTIONS - 1)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sock_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let mut ctx = MuxerTestContext::new("sock_permissions");
+        assert_eq!(ctx.muxer.sock_permissions(), VsockUdsPermissions::default());
+
+        // Changing the owner to the current one is allowed without privileges.
+        let permissions = VsockUdsPermissions {
+            mode: Some(0o600),
+            // Safe because these calls have no side effects.
+            uid: Some(unsafe { libc::getuid() }),
+            gid: Some(unsafe { libc::getgid() }),
+        };
+        ctx.muxer.set_sock_permissions(permissions).unwrap();
+        assert_eq!(ctx.muxer.sock_permissions(), permissions);
+
+        let dgram_sock_path = format!("{}_dgram", ctx.muxer.host_sock_path);
+        for path in [ctx.muxer.host_sock_path.clone(), dgram_sock_path].iter() {
+            let metadata = std::fs::metadata(path).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+            assert_eq!(Some(metadata.uid()), permissions.uid);
+            assert_eq!(Some(metadata.gid()), permissions.gid);
+        }
+
+        // Only the mode is changed when the ownership is left out.
+        ctx.muxer
+            .set_sock_permissions(VsockUdsPermissions {
+                mode: Some(0o660),
+                uid: None,
+                gid: None,
+            })
+            .unwrap();
+        let metadata = std::fs::metadata(&ctx.muxer.host_sock_path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+    }
 }
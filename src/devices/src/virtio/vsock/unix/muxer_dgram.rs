@@ -0,0 +1,221 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `MuxerDgramSock` implements the datagram side of the Unix domain sockets vsock backend.
+//!
+//! Datagrams are not connected, so they don't go through `VsockConnection` objects:
+//! - a datagram the guest sends to the host port `<port>` is forwarded as is to the Unix datagram
+//!   socket bound at `<uds_path>_<port>`, if any;
+//! - the host sends datagrams to the guest through the Unix datagram socket the muxer binds at
+//!   `<uds_path>_dgram`. Each of them starts with a `SEND <port>\n` header, naming the
+//!   destination port in the guest. When the host socket is bound at `<uds_path>_<port>`, the
+//!   datagram comes from `<port>` on the guest side, so that the guest can reply to it.
+//!
+//! Datagrams are unreliable, so the ones that can't be delivered are dropped.
+
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+use logger::{debug, warn, IncMetric, METRICS};
+use vm_memory::GuestMemoryMmap;
+
+use super::super::defs::{uapi, MAX_PKT_BUF_SIZE};
+use super::super::packet::VsockPacket;
+use super::super::{Result as VsockResult, VsockError};
+use super::{defs, Error, Result};
+
+// Command leading the datagrams sent by the host, followed by the destination port.
+const SEND_CMD: &str = "send";
+// Upper bound for the length of the `SEND <port>\n` header.
+const MAX_HEADER_LEN: usize = 32;
+// Source port of the datagrams sent from host sockets not bound at `<uds_path>_<port>`.
+const VMADDR_PORT_ANY: u32 = u32::MAX;
+
+/// A datagram sent by the host, waiting for a guest RX buffer.
+struct HostDgram {
+    src_port: u32,
+    dst_port: u32,
+    data: Vec<u8>,
+}
+
+/// The datagram socket of the muxer, along with the datagrams the host sent through it.
+pub struct MuxerDgramSock {
+    sock: UnixDatagram,
+    path: String,
+    /// The path of the host-side Unix socket of the muxer, which the ports are appended to.
+    host_sock_path: String,
+    rxq: VecDeque<HostDgram>,
+    buf: Vec<u8>,
+}
+
+impl MuxerDgramSock {
+    /// Binds the datagram socket of the muxer using `host_sock_path`, replacing the one a
+    /// previous muxer may have left.
+    pub fn bind(host_sock_path: &str) -> Result<Self> {
+        let path = format!("{}_dgram", host_sock_path);
+        // The path is reserved for the muxer, so a socket found there is a stale one.
+        let _ = std::fs::remove_file(&path);
+        let sock = UnixDatagram::bind(&path)
+            .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
+            .map_err(Error::UnixBind)?;
+
+        Ok(Self {
+            sock,
+            path,
+            host_sock_path: host_sock_path.to_owned(),
+            rxq: VecDeque::with_capacity(defs::MUXER_DGRAM_RXQ_SIZE),
+            buf: vec![0u8; MAX_HEADER_LEN + MAX_PKT_BUF_SIZE],
+        })
+    }
+
+    /// Provides the path the socket is bound at.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Forwards a datagram sent by the guest to the host socket bound for its destination port.
+    pub fn send_pkt(&self, pkt: &VsockPacket, mem: &GuestMemoryMmap) {
+        let len = pkt.len() as usize;
+        let mut data = Vec::with_capacity(len);
+        if len > 0 {
+            if let Err(e) = pkt.write_from_offset_to(mem, 0, &mut data, len) {
+                warn!("vsock: error reading guest datagram: {:?}", e);
+                METRICS.vsock.dgrams_dropped.inc();
+                return;
+            }
+        }
+
+        let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+        match self.sock.send_to(&data, &port_path) {
+            Ok(_) => METRICS.vsock.tx_dgrams_count.inc(),
+            Err(e) => {
+                debug!(
+                    "vsock: dropping guest datagram for port {}: {}",
+                    pkt.dst_port(),
+                    e
+                );
+                METRICS.vsock.dgrams_dropped.inc();
+            }
+        }
+    }
+
+    /// Queues the datagrams sent by the host until none is left to read. The ones which don't
+    /// fit in the queue are dropped.
+    pub fn recv_host_dgrams(&mut self) {
+        loop {
+            let (len, addr) = match self.sock.recv_from(&mut self.buf) {
+                Ok(res) => res,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("vsock: error reading host datagram: {}", e);
+                    METRICS.vsock.rx_read_fails.inc();
+                    break;
+                }
+            };
+
+            // A datagram filling the buffer may have been truncated.
+            let dgram = if len < self.buf.len() {
+                self.parse_host_dgram(len, &addr)
+            } else {
+                None
+            };
+            match dgram {
+                Some(dgram) if self.rxq.len() < defs::MUXER_DGRAM_RXQ_SIZE => {
+                    self.rxq.push_back(dgram)
+                }
+                _ => {
+                    debug!("vsock: dropping host datagram of {} bytes", len);
+                    METRICS.vsock.dgrams_dropped.inc();
+                }
+            }
+        }
+    }
+
+    // Splits the `len` bytes of `self.buf` into the `SEND <port>\n` header and the data.
+    fn parse_host_dgram(&self, len: usize, addr: &SocketAddr) -> Option<HostDgram> {
+        let header_len = self.buf[..len.min(MAX_HEADER_LEN)]
+            .iter()
+            .position(|&b| b == b'\n')?
+            + 1;
+        let mut words = std::str::from_utf8(&self.buf[..header_len])
+            .ok()?
+            .split_whitespace();
+        if words.next()?.to_lowercase() != SEND_CMD {
+            return None;
+        }
+        let dst_port = words.next()?.parse::<u32>().ok()?;
+
+        let src_port = addr
+            .as_pathname()
+            .and_then(|path| path.to_str())
+            .and_then(|path| path.strip_prefix(self.host_sock_path.as_str()))
+            .and_then(|suffix| suffix.strip_prefix('_'))
+            .and_then(|port| port.parse::<u32>().ok())
+            .unwrap_or(VMADDR_PORT_ANY);
+
+        Some(HostDgram {
+            src_port,
+            dst_port,
+            data: self.buf[header_len..len].to_vec(),
+        })
+    }
+
+    /// Checks whether there are datagrams from the host to deliver to the guest.
+    pub fn has_pending_rx(&self) -> bool {
+        !self.rxq.is_empty()
+    }
+
+    /// Fills `pkt` with the oldest datagram sent by the host to the guest with the `cid` CID.
+    /// The datagrams which don't fit in the RX buffer are dropped.
+    pub fn recv_pkt(
+        &mut self,
+        pkt: &mut VsockPacket,
+        mem: &GuestMemoryMmap,
+        cid: u64,
+    ) -> VsockResult<()> {
+        while let Some(dgram) = self.rxq.pop_front() {
+            let len = dgram.data.len();
+            if len > pkt.buf_size() {
+                debug!("vsock: dropping host datagram larger than the RX buffer");
+                METRICS.vsock.dgrams_dropped.inc();
+                continue;
+            }
+            if len > 0 {
+                if let Err(e) = pkt.read_at_offset_from(mem, 0, &mut dgram.data.as_slice(), len) {
+                    warn!("vsock: error writing host datagram: {:?}", e);
+                    METRICS.vsock.dgrams_dropped.inc();
+                    continue;
+                }
+            }
+
+            pkt.set_op(uapi::VSOCK_OP_RW)
+                .set_type(uapi::VSOCK_TYPE_DGRAM)
+                .set_src_cid(uapi::VSOCK_HOST_CID)
+                .set_dst_cid(cid)
+                .set_src_port(dgram.src_port)
+                .set_dst_port(dgram.dst_port)
+                .set_len(len as u32)
+                .set_flags(0)
+                .set_buf_alloc(0)
+                .set_fwd_cnt(0);
+            METRICS.vsock.rx_dgrams_count.inc();
+            return Ok(());
+        }
+
+        Err(VsockError::NoData)
+    }
+}
+
+impl AsRawFd for MuxerDgramSock {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
+
+impl Drop for MuxerDgramSock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal wrapper over the vhost-vsock kernel interface, used to offload the RX and TX
+//! queues of a vsock device to the host kernel, so that the host reaches the guest through
+//! native AF_VSOCK sockets.
+
+use std::os::raw::c_int;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use utils::{ioctl_expr, ioctl_ioc_nr, ioctl_iow_nr};
+use vm_memory::GuestMemoryMmap;
+
+use crate::virtio::net::vhost::{Error, Result, VhostKernel, VHOST_VIRTIO};
+use crate::virtio::Queue;
+
+const VHOST_VSOCK_PATH: &str = "/dev/vhost-vsock";
+
+ioctl_iow_nr!(VHOST_VSOCK_SET_GUEST_CID, VHOST_VIRTIO, 0x60, u64);
+ioctl_iow_nr!(VHOST_VSOCK_SET_RUNNING, VHOST_VIRTIO, 0x61, c_int);
+
+/// Handle for a vhost-vsock instance, serving the RX and TX queues of a vsock device. The
+/// event queue is left to the device model.
+#[derive(Debug)]
+pub struct VhostVsock {
+    kernel: VhostKernel,
+}
+
+impl VhostVsock {
+    /// Opens a new vhost-vsock instance, owned by the calling process, for the guest with the
+    /// given CID. Fails if the CID is used by another guest on the host.
+    pub fn new(cid: u64) -> Result<Self> {
+        let vhost = VhostVsock {
+            kernel: VhostKernel::open(VHOST_VSOCK_PATH, Error::OpenVhostVsock)?,
+        };
+        vhost
+            .kernel
+            .ioctl_with_ref(VHOST_VSOCK_SET_GUEST_CID(), &cid)?;
+        Ok(vhost)
+    }
+
+    /// The virtio features implemented by vhost-vsock.
+    pub fn features(&self) -> u64 {
+        self.kernel.features()
+    }
+
+    /// Sets the features acked by the guest. Must be a subset of `features()`.
+    pub fn set_features(&self, features: u64) -> Result<()> {
+        self.kernel.set_features(features)
+    }
+
+    /// Describes the guest memory layout, so that vhost-vsock can translate the ring addresses.
+    pub fn set_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        self.kernel.set_mem_table(mem)
+    }
+
+    /// Hands the `index` ring over to vhost-vsock. `kick` is signalled by the guest when
+    /// buffers are made available, and vhost-vsock signals `call` when buffers are used.
+    pub fn set_vring(
+        &self,
+        index: usize,
+        queue: &Queue,
+        mem: &GuestMemoryMmap,
+        kick: &dyn AsRawFd,
+        call: &dyn AsRawFd,
+    ) -> Result<()> {
+        self.kernel.set_vring(index, queue, mem, kick, call)
+    }
+
+    /// Starts or stops the processing of the rings handed over to vhost-vsock.
+    pub fn set_running(&self, running: bool) -> Result<()> {
+        self.kernel
+            .ioctl_with_ref(VHOST_VSOCK_SET_RUNNING(), &c_int::from(running))
+    }
+}
+
+impl AsRawFd for VhostVsock {
+    fn as_raw_fd(&self) -> RawFd {
+        self.kernel.as_raw_fd()
+    }
+}
@@ -0,0 +1,453 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains logic that helps with handling the ICMPv6 messages of the Neighbor Discovery Protocol,
+//! which IPv6 relies on instead of ARP to resolve link-layer addresses.
+//!
+//! Only neighbor solicitations and neighbor advertisements are supported. A more detailed view of
+//! these messages can be found [here].
+//!
+//! [here]: https://datatracker.ietf.org/doc/html/rfc4861#section-4.3
+use std::convert::From;
+use std::net::Ipv6Addr;
+use std::result::Result;
+
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::ChecksumProto;
+
+/// ICMPv6 type of neighbor solicitations.
+pub const TYPE_NEIGHBOR_SOLICITATION: u8 = 135;
+
+/// ICMPv6 type of neighbor advertisements.
+pub const TYPE_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// The hop limit of the IPv6 packets carrying Neighbor Discovery messages, which guarantees they
+/// did not cross a router.
+pub const NDP_HOP_LIMIT: u8 = 255;
+
+/// Set in advertisements sent in response to a solicitation.
+pub const FLAG_SOLICITED: u8 = 0x40;
+
+/// Set in advertisements which should override the cached link-layer address.
+pub const FLAG_OVERRIDE: u8 = 0x20;
+
+/// The length of a neighbor advertisement carrying the target link-layer address option.
+pub const NEIGHBOR_ADVERTISEMENT_LEN: usize = 32;
+
+const TYPE_OFFSET: usize = 0;
+const CODE_OFFSET: usize = 1;
+const CHECKSUM_OFFSET: usize = 2;
+const FLAGS_OFFSET: usize = 4;
+const TARGET_ADDRESS_OFFSET: usize = 8;
+const OPTIONS_OFFSET: usize = 24;
+
+const OPTION_SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+const OPTION_TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+// Option lengths are expressed in units of 8 bytes.
+const OPTION_LEN_UNIT: usize = 8;
+
+const IPV6_ADDR_LEN: usize = 16;
+
+/// Represents errors which may occur while parsing or writing a message.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Invalid checksum.
+    Checksum,
+    /// Invalid code.
+    Code,
+    /// Invalid message type.
+    MessageType,
+    /// A malformed option.
+    Option,
+    /// The provided slice does not fit the size of a message.
+    SliceExactLen,
+    /// The provided slice is shorter than the message.
+    SliceTooShort,
+    /// The target address is a multicast address.
+    TargetAddress,
+}
+
+/// The inner bytes will be interpreted as a Neighbor Discovery message.
+pub struct NdpMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> NdpMessage<'a, T> {
+    /// Interprets the given bytes as a Neighbor Discovery message, without doing any validity
+    /// checks beforehand.
+    ///
+    ///  # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        NdpMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Tries to interpret a byte slice as a valid neighbor solicitation.
+    ///
+    /// The `verify_checksum` parameter must contain the source and destination addresses from the
+    /// enclosing IPv6 packet if the ICMPv6 checksum must be validated.
+    pub fn solicitation_from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        let maybe = NdpMessage::from_bytes_unchecked(bytes);
+
+        if maybe.message_type() != TYPE_NEIGHBOR_SOLICITATION {
+            return Err(Error::MessageType);
+        }
+
+        if maybe.code() != 0 {
+            return Err(Error::Code);
+        }
+
+        if maybe.target_address().is_multicast() {
+            return Err(Error::TargetAddress);
+        }
+
+        if !maybe.options_are_valid() {
+            return Err(Error::Option);
+        }
+
+        if let Some((src_addr, dst_addr)) = verify_checksum {
+            if maybe.compute_checksum(src_addr, dst_addr) != 0 {
+                return Err(Error::Checksum);
+            }
+        }
+
+        Ok(maybe)
+    }
+
+    /// Returns the ICMPv6 type of the message.
+    #[inline]
+    pub fn message_type(&self) -> u8 {
+        self.bytes[TYPE_OFFSET]
+    }
+
+    /// Returns the ICMPv6 code of the message.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.bytes[CODE_OFFSET]
+    }
+
+    /// Returns the value of the `checksum` field.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.bytes.ntohs_unchecked(CHECKSUM_OFFSET)
+    }
+
+    /// Returns the flags of the message, which are only defined for neighbor advertisements.
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self.bytes[FLAGS_OFFSET]
+    }
+
+    /// Returns the target address of the message.
+    #[inline]
+    pub fn target_address(&self) -> Ipv6Addr {
+        let mut octets = [0u8; IPV6_ADDR_LEN];
+        octets.copy_from_slice(&self.bytes[TARGET_ADDRESS_OFFSET..OPTIONS_OFFSET]);
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns the link-layer address carried by the source link-layer address option, if any.
+    #[inline]
+    pub fn source_link_layer_address(&self) -> Option<MacAddr> {
+        self.link_layer_address(OPTION_SOURCE_LINK_LAYER_ADDRESS)
+    }
+
+    /// Returns the link-layer address carried by the target link-layer address option, if any.
+    #[inline]
+    pub fn target_link_layer_address(&self) -> Option<MacAddr> {
+        self.link_layer_address(OPTION_TARGET_LINK_LAYER_ADDRESS)
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Computes the ICMPv6 checksum of the message.
+    pub fn compute_checksum(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        crate::pdu::compute_checksum(
+            &self.bytes,
+            src_addr.into(),
+            dst_addr.into(),
+            ChecksumProto::Icmpv6,
+        )
+    }
+
+    // Walks through the options, which must all have a non-zero length fitting in the message.
+    fn options_are_valid(&self) -> bool {
+        let mut offset = OPTIONS_OFFSET;
+        while offset < self.len() {
+            if offset + 2 > self.len() {
+                return false;
+            }
+            let option_len = usize::from(self.bytes[offset + 1]) * OPTION_LEN_UNIT;
+            if option_len == 0 || offset + option_len > self.len() {
+                return false;
+            }
+            offset += option_len;
+        }
+        true
+    }
+
+    // Looks up the link-layer address option of the given type. The options must be valid.
+    fn link_layer_address(&self, option_type: u8) -> Option<MacAddr> {
+        let mut offset = OPTIONS_OFFSET;
+        while offset < self.len() {
+            let option_len = usize::from(self.bytes[offset + 1]) * OPTION_LEN_UNIT;
+            if self.bytes[offset] == option_type && option_len >= 2 + MAC_ADDR_LEN {
+                return Some(MacAddr::from_bytes_unchecked(
+                    &self.bytes[offset + 2..offset + 2 + MAC_ADDR_LEN],
+                ));
+            }
+            offset += option_len;
+        }
+        None
+    }
+}
+
+impl<'a, T: NetworkBytesMut> NdpMessage<'a, T> {
+    /// Attempts to write a neighbor advertisement to `buf`, announcing that `target_addr` can be
+    /// reached at `target_mac`. The `src_addr` and `dst_addr` of the enclosing IPv6 packet are
+    /// used to compute the checksum.
+    pub fn write_advertisement(
+        buf: T,
+        flags: u8,
+        target_addr: Ipv6Addr,
+        target_mac: MacAddr,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Self, Error> {
+        if buf.len() != NEIGHBOR_ADVERTISEMENT_LEN {
+            return Err(Error::SliceExactLen);
+        }
+
+        // This is ok, because we've checked the length of the slice.
+        let mut message = NdpMessage::from_bytes_unchecked(buf);
+
+        message
+            .set_message_type(TYPE_NEIGHBOR_ADVERTISEMENT)
+            .set_code(0)
+            .set_checksum(0)
+            .set_flags(flags)
+            .set_target_address(target_addr);
+
+        message.bytes[OPTIONS_OFFSET] = OPTION_TARGET_LINK_LAYER_ADDRESS;
+        message.bytes[OPTIONS_OFFSET + 1] = 1;
+        message.bytes[OPTIONS_OFFSET + 2..].copy_from_slice(target_mac.get_bytes());
+
+        let checksum = message.compute_checksum(src_addr, dst_addr);
+        message.set_checksum(checksum);
+
+        Ok(message)
+    }
+
+    /// Sets the ICMPv6 type of the message.
+    #[inline]
+    pub fn set_message_type(&mut self, value: u8) -> &mut Self {
+        self.bytes[TYPE_OFFSET] = value;
+        self
+    }
+
+    /// Sets the ICMPv6 code of the message.
+    #[inline]
+    pub fn set_code(&mut self, value: u8) -> &mut Self {
+        self.bytes[CODE_OFFSET] = value;
+        self
+    }
+
+    /// Sets the value of the `checksum` field.
+    #[inline]
+    pub fn set_checksum(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(CHECKSUM_OFFSET, value);
+        self
+    }
+
+    /// Sets the flags of the message, also clearing the reserved bits following them.
+    #[inline]
+    pub fn set_flags(&mut self, value: u8) -> &mut Self {
+        self.bytes
+            .htonl_unchecked(FLAGS_OFFSET, u32::from(value) << 24);
+        self
+    }
+
+    /// Sets the target address of the message.
+    #[inline]
+    pub fn set_target_address(&mut self, addr: Ipv6Addr) -> &mut Self {
+        self.bytes[TARGET_ADDRESS_OFFSET..OPTIONS_OFFSET].copy_from_slice(&addr.octets());
+        self
+    }
+}
+
+/// Returns the solicited-node multicast address that the neighbor solicitations looking for
+/// `addr` are sent to.
+#[inline]
+pub fn solicited_node_multicast_addr(addr: Ipv6Addr) -> Ipv6Addr {
+    let octets = addr.octets();
+    Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | u16::from(octets[13]),
+        (u16::from(octets[14]) << 8) | u16::from(octets[15]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for NdpMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(NDP message)")
+        }
+    }
+
+    // Writes a neighbor solicitation for `target_addr`, carrying the `mac` source link-layer
+    // address, and returns its length.
+    fn write_solicitation(
+        buf: &mut [u8],
+        target_addr: Ipv6Addr,
+        mac: MacAddr,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> usize {
+        let len = OPTIONS_OFFSET + OPTION_LEN_UNIT;
+        let mut m = NdpMessage::from_bytes_unchecked(&mut buf[..len]);
+        m.set_message_type(TYPE_NEIGHBOR_SOLICITATION)
+            .set_code(0)
+            .set_checksum(0)
+            .set_flags(0)
+            .set_target_address(target_addr);
+        m.bytes[OPTIONS_OFFSET] = OPTION_SOURCE_LINK_LAYER_ADDRESS;
+        m.bytes[OPTIONS_OFFSET + 1] = 1;
+        m.bytes[OPTIONS_OFFSET + 2..].copy_from_slice(mac.get_bytes());
+        let checksum = m.compute_checksum(src_addr, dst_addr);
+        m.set_checksum(checksum);
+        len
+    }
+
+    #[test]
+    fn test_ndp() {
+        let mut buf = [0u8; 100];
+        let mac = MacAddr::parse_str("06:01:23:45:67:01").unwrap();
+        let remote_mac = MacAddr::parse_str("11:11:11:22:22:22").unwrap();
+        let target_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        let remote_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let multicast_addr = solicited_node_multicast_addr(target_addr);
+        assert_eq!(
+            multicast_addr,
+            Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00, 0x0254)
+        );
+
+        let len = write_solicitation(
+            buf.as_mut(),
+            target_addr,
+            remote_mac,
+            remote_addr,
+            multicast_addr,
+        );
+
+        {
+            let m = NdpMessage::solicitation_from_bytes(
+                &buf[..len],
+                Some((remote_addr, multicast_addr)),
+            )
+            .unwrap();
+            assert_eq!(m.len(), len);
+            assert_eq!(m.message_type(), TYPE_NEIGHBOR_SOLICITATION);
+            assert_eq!(m.code(), 0);
+            assert_eq!(m.target_address(), target_addr);
+            assert_eq!(m.source_link_layer_address(), Some(remote_mac));
+            assert_eq!(m.target_link_layer_address(), None);
+        }
+
+        // Let's check some error conditions.
+        let look_for_error = |buf: &[u8], err: Error| {
+            assert_eq!(
+                NdpMessage::solicitation_from_bytes(buf, Some((remote_addr, multicast_addr)))
+                    .unwrap_err(),
+                err
+            );
+        };
+
+        look_for_error(&buf[..OPTIONS_OFFSET - 1], Error::SliceTooShort);
+        // The option no longer fits.
+        look_for_error(&buf[..len - 1], Error::Option);
+
+        buf[OPTIONS_OFFSET + 1] = 0;
+        look_for_error(&buf[..len], Error::Option);
+        buf[OPTIONS_OFFSET + 1] = 1;
+
+        let checksum = NdpMessage::from_bytes_unchecked(&buf[..len]).checksum();
+        NdpMessage::from_bytes_unchecked(&mut buf[..len]).set_checksum(checksum.wrapping_add(1));
+        look_for_error(&buf[..len], Error::Checksum);
+        // The checksum is not verified when the addresses are missing.
+        assert!(NdpMessage::solicitation_from_bytes(&buf[..len], None).is_ok());
+
+        NdpMessage::from_bytes_unchecked(&mut buf[..len]).set_code(1);
+        look_for_error(&buf[..len], Error::Code);
+
+        NdpMessage::from_bytes_unchecked(&mut buf[..len])
+            .set_message_type(TYPE_NEIGHBOR_ADVERTISEMENT);
+        look_for_error(&buf[..len], Error::MessageType);
+
+        NdpMessage::from_bytes_unchecked(&mut buf[..len])
+            .set_message_type(TYPE_NEIGHBOR_SOLICITATION)
+            .set_code(0)
+            .set_target_address(multicast_addr);
+        look_for_error(&buf[..len], Error::TargetAddress);
+
+        // Now let's write an advertisement in response.
+        assert_eq!(
+            NdpMessage::write_advertisement(
+                &mut buf[..NEIGHBOR_ADVERTISEMENT_LEN - 1],
+                FLAG_SOLICITED,
+                target_addr,
+                mac,
+                target_addr,
+                remote_addr,
+            )
+            .unwrap_err(),
+            Error::SliceExactLen
+        );
+
+        let m = NdpMessage::write_advertisement(
+            &mut buf[..NEIGHBOR_ADVERTISEMENT_LEN],
+            FLAG_SOLICITED | FLAG_OVERRIDE,
+            target_addr,
+            mac,
+            target_addr,
+            remote_addr,
+        )
+        .unwrap();
+        assert_eq!(m.len(), NEIGHBOR_ADVERTISEMENT_LEN);
+        assert_eq!(m.message_type(), TYPE_NEIGHBOR_ADVERTISEMENT);
+        assert_eq!(m.code(), 0);
+        assert_eq!(m.flags(), FLAG_SOLICITED | FLAG_OVERRIDE);
+        assert_eq!(m.target_address(), target_addr);
+        assert_eq!(m.target_link_layer_address(), Some(mac));
+        assert_eq!(m.source_link_layer_address(), None);
+        assert_eq!(m.compute_checksum(target_addr, remote_addr), 0);
+    }
+}
@@ -8,7 +8,7 @@
 //! [Here]: https://en.wikipedia.org/wiki/Transmission_Control_Protocol#TCP_segment_structure
 
 use std::cmp::min;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroU16;
 use std::result::Result;
 
@@ -208,7 +208,22 @@ impl<'a, T: NetworkBytes> TcpSegment<'a, T> {
     ///
     /// [here]: https://en.wikipedia.org/wiki/Transmission_Control_Protocol#Checksum_computation
     pub fn compute_checksum(&self, src_addr: Ipv4Addr, dst_addr: Ipv4Addr) -> u16 {
-        crate::pdu::compute_checksum(&self.bytes, src_addr, dst_addr, ChecksumProto::Tcp)
+        crate::pdu::compute_checksum(
+            &self.bytes,
+            src_addr.into(),
+            dst_addr.into(),
+            ChecksumProto::Tcp,
+        )
+    }
+
+    /// Computes the TCP checksum of the segment, when carried by an IPv6 packet.
+    pub fn compute_checksum_ipv6(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        crate::pdu::compute_checksum(
+            &self.bytes,
+            src_addr.into(),
+            dst_addr.into(),
+            ChecksumProto::Tcp,
+        )
     }
 
     /// Parses TCP header options (only `MSS` is supported for now).
@@ -1,24 +1,25 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Exposes simple TCP over IPv4 listener functionality via the [`TcpIPv4Handler`] structure.
+//! Exposes simple TCP over IPv4 listener functionality via the [`TcpIPv4Handler`] structure,
+//! which can also accept connections over IPv6.
 //!
 //! [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
 
 use std::collections::{HashMap, HashSet};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 
 use micro_http::{Request, Response};
 
 use crate::pdu::bytes::NetworkBytes;
 use crate::pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP};
+use crate::pdu::ipv6::{Error as IPv6PacketError, IPv6Packet};
 use crate::pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpSegment};
+use crate::pdu::Incomplete;
 use crate::tcp::endpoint::Endpoint;
 use crate::tcp::{NextSegmentStatus, RstConfig};
 
-// TODO: This is currently IPv4 specific. Maybe change it to a more generic implementation.
-
 /// Describes events which may occur when the handler receives packets.
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvEvent {
@@ -73,22 +74,25 @@ pub enum RecvError {
 pub enum WriteNextError {
     /// There was an error while writing the contents of the IPv4 packet.
     IPv4Packet(IPv4PacketError),
+    /// There was an error while writing the contents of the IPv6 packet.
+    IPv6Packet(IPv6PacketError),
     /// There was an error while writing the contents of the inner TCP segment.
     TcpSegment(TcpSegmentError),
 }
 
-// Generally speaking, a TCP/IPv4 connection is identified using the four-tuple (src_addr, src_port,
-// dst_addr, dst_port). However, the IPv4 address and TCP port of the MMDS endpoint are fixed, so
+// Generally speaking, a TCP/IP connection is identified using the four-tuple (src_addr, src_port,
+// dst_addr, dst_port). However, the IP addresses and TCP port of the MMDS endpoint are fixed, so
 // we can get away with uniquely identifying connections using just the remote address and port.
+// The version of the remote address is also the one of the packets exchanged over the connection.
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
 struct ConnectionTuple {
-    remote_addr: Ipv4Addr,
+    remote_addr: IpAddr,
     remote_port: u16,
 }
 
 impl ConnectionTuple {
-    fn new(remote_addr: Ipv4Addr, remote_port: u16) -> Self {
+    fn new(remote_addr: IpAddr, remote_port: u16) -> Self {
         ConnectionTuple {
             remote_addr,
             remote_port,
@@ -98,6 +102,10 @@ impl ConnectionTuple {
 
 /// Implements a minimalist TCP over IPv4 listener.
 ///
+/// The listener also accepts connections over IPv6, using the local IPv6 address set by
+/// [`set_local_ipv6_addr`]. The packets sent over a connection have the IP version of the
+/// packets received from the remote endpoint.
+///
 /// Forwards incoming TCP segments to the appropriate connection object, based on the associated
 /// tuple, or attempts to establish new connections (when receiving `SYN` segments). Aside from
 /// constructors, the handler operation is based on three methods:
@@ -120,9 +128,12 @@ impl ConnectionTuple {
 /// [`receive_packet`]: ../handler/struct.TcpIPv4Handler.html#method.receive_packet
 /// [`write_next_packet`]: ../handler/struct.TcpIPv4Handler.html#method.write_next_packet
 /// [`next_segment_status`]: ../handler/struct.TcpIPv4Handler.html#method.next_segment_status
+/// [`set_local_ipv6_addr`]: ../handler/struct.TcpIPv4Handler.html#method.set_local_ipv6_addr
 pub struct TcpIPv4Handler {
-    // Handler IPv4 address used for every connection.
+    // Handler IPv4 address used for every IPv4 connection.
     local_ipv4_addr: Ipv4Addr,
+    // Handler IPv6 address used for every IPv6 connection.
+    local_ipv6_addr: Ipv6Addr,
     // Handler TCP port used for every connection.
     local_port: u16,
     // This map holds the currently active endpoints, identified by their connection tuple.
@@ -166,6 +177,7 @@ impl TcpIPv4Handler {
         let max_pending_resets = max_pending_resets.get();
         TcpIPv4Handler {
             local_ipv4_addr,
+            local_ipv6_addr: Ipv6Addr::UNSPECIFIED,
             local_port,
             connections: HashMap::with_capacity(max_connections),
             max_connections,
@@ -186,6 +198,16 @@ impl TcpIPv4Handler {
         self.local_ipv4_addr
     }
 
+    /// Setter for the local IPv6 address of this TCP handler.
+    pub fn set_local_ipv6_addr(&mut self, ipv6_addr: Ipv6Addr) {
+        self.local_ipv6_addr = ipv6_addr;
+    }
+
+    /// Returns the local IPv6 address of this TCP handler.
+    pub fn local_ipv6_addr(&self) -> Ipv6Addr {
+        self.local_ipv6_addr
+    }
+
     /// Returns the local port of this TCP handler.
     pub fn local_port(&self) -> u16 {
         self.local_port
@@ -208,18 +230,45 @@ impl TcpIPv4Handler {
         &mut self,
         packet: &IPv4Packet<T>,
         callback: F,
+    ) -> Result<RecvEvent, RecvError> {
+        self.receive_segment(
+            IpAddr::V4(packet.source_address()),
+            packet.payload(),
+            callback,
+        )
+    }
+
+    /// Contains logic for handling incoming segments carried by IPv6 packets.
+    ///
+    /// Any changes to the state of the handler are communicated through an `Ok(RecvEvent)`.
+    pub fn receive_ipv6_packet<T: NetworkBytes, F: FnOnce(Request) -> Response>(
+        &mut self,
+        packet: &IPv6Packet<T>,
+        callback: F,
+    ) -> Result<RecvEvent, RecvError> {
+        self.receive_segment(
+            IpAddr::V6(packet.source_address()),
+            packet.payload(),
+            callback,
+        )
+    }
+
+    fn receive_segment<F: FnOnce(Request) -> Response>(
+        &mut self,
+        remote_addr: IpAddr,
+        payload: &[u8],
+        callback: F,
     ) -> Result<RecvEvent, RecvError> {
         // TODO: We skip verifying the checksum, just in case the device model relies on offloading
         // checksum computation from the guest to some other entity. Clear this up at some point!
         // (Issue #520)
-        let segment =
-            TcpSegment::from_bytes(packet.payload(), None).map_err(RecvError::TcpSegment)?;
+        let segment = TcpSegment::from_bytes(payload, None).map_err(RecvError::TcpSegment)?;
 
         if segment.destination_port() != self.local_port {
             return Err(RecvError::InvalidPort);
         }
 
-        let tuple = ConnectionTuple::new(packet.source_address(), segment.source_port());
+        let tuple = ConnectionTuple::new(remote_addr, segment.source_port());
 
         let outcome = if let Some(endpoint) = self.connections.get_mut(&tuple) {
             endpoint.receive_segment(&segment, callback);
@@ -386,49 +435,34 @@ impl TcpIPv4Handler {
         let mut writer_status = None;
         let mut event = WriteEvent::Nothing;
 
-        // Write an incomplete Ipv4 packet and complete it afterwards with missing information.
-        let mut packet =
-            IPv4Packet::write_header(buf, PROTOCOL_TCP, Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST)
-                .map_err(WriteNextError::IPv4Packet)?;
-
-        // We set mss_used to 0, because we don't add any IP options.
-        // TODO: Maybe get this nicely from packet at some point.
-        let mss_reserved = 0;
-
         // We prioritize sending RSTs for now. The 10000 value for window size is just an arbitrary
         // number, and using mss_remaining = 0 is perfectly fine in this case, because we don't add
         // any TCP options, or a payload.
         if let Some((tuple, rst_cfg)) = self.rst_queue.pop() {
             let (seq, ack, flags_after_ns) = rst_cfg.seq_ack_tcp_flags();
-            let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
-                packet.inner_mut().payload_mut(),
-                seq,
-                ack,
-                flags_after_ns,
-                10000,
-                None,
-                0,
-                None,
-            )
-            .map_err(WriteNextError::TcpSegment)?
-            .finalize(
+            let packet_len = Self::write_packet(
+                buf,
                 self.local_port,
-                tuple.remote_port,
-                Some((self.local_ipv4_addr, tuple.remote_addr)),
-            )
-            .len();
-
-            packet
-                .inner_mut()
-                .set_source_address(self.local_ipv4_addr)
-                .set_destination_address(tuple.remote_addr);
-
-            let packet_len = packet.with_payload_len_unchecked(segment_len, true).len();
-            // The unwrap() is safe because packet_len > 0.
-            return Ok((
-                Some(NonZeroUsize::new(packet_len).unwrap()),
-                WriteEvent::Nothing,
-            ));
+                self.local_ipv4_addr,
+                self.local_ipv6_addr,
+                tuple,
+                |payload| {
+                    TcpSegment::write_incomplete_segment::<[u8]>(
+                        payload,
+                        seq,
+                        ack,
+                        flags_after_ns,
+                        10000,
+                        None,
+                        0,
+                        None,
+                    )
+                    .map(Some)
+                    .map_err(WriteNextError::TcpSegment)
+                },
+            )?;
+
+            return Ok((packet_len.and_then(NonZeroUsize::new), WriteEvent::Nothing));
         }
 
         for tuple in self
@@ -439,31 +473,23 @@ impl TcpIPv4Handler {
             // Tuples in self.active_connection or self.next_timeout should also appear as keys
             // in self.connections.
             let endpoint = self.connections.get_mut(tuple).unwrap();
-            // We need this block to clearly delimit the lifetime of the mutable borrow started by
-            // the following packet.inner_mut().
-            let segment_len = {
-                let maybe_segment =
-                    endpoint.write_next_segment(packet.inner_mut().payload_mut(), mss_reserved);
 
-                match maybe_segment {
-                    Some(segment) => segment
-                        .finalize(
-                            self.local_port,
-                            tuple.remote_port,
-                            Some((self.local_ipv4_addr, tuple.remote_addr)),
-                        )
-                        .len(),
-                    None => continue,
-                }
+            // We set mss_reserved to 0, because we don't add any IP options.
+            // TODO: Maybe get this nicely from packet at some point.
+            let maybe_len = Self::write_packet(
+                buf,
+                self.local_port,
+                self.local_ipv4_addr,
+                self.local_ipv6_addr,
+                *tuple,
+                |payload| Ok(endpoint.write_next_segment(payload, 0)),
+            )?;
+
+            let ip_len = match maybe_len {
+                Some(ip_len) => ip_len,
+                None => continue,
             };
 
-            packet
-                .inner_mut()
-                .set_source_address(self.local_ipv4_addr)
-                .set_destination_address(tuple.remote_addr);
-
-            let ip_len = packet.with_payload_len_unchecked(segment_len, true).len();
-
             // The unwrap is safe because ip_len > 0.
             len = Some(NonZeroUsize::new(ip_len).unwrap());
             writer_status = Some((*tuple, endpoint.is_done()));
@@ -488,6 +514,62 @@ impl TcpIPv4Handler {
         Ok((len, event))
     }
 
+    // Writes to `buf` the packet carrying the segment produced by `write_segment` to the remote
+    // endpoint of `tuple`, with the IP version of the remote address. Returns the length of the
+    // packet, or `None` when `write_segment` has nothing to write.
+    fn write_packet<F>(
+        buf: &mut [u8],
+        local_port: u16,
+        local_ipv4_addr: Ipv4Addr,
+        local_ipv6_addr: Ipv6Addr,
+        tuple: ConnectionTuple,
+        write_segment: F,
+    ) -> Result<Option<usize>, WriteNextError>
+    where
+        F: for<'b> FnOnce(
+            &'b mut [u8],
+        ) -> Result<
+            Option<Incomplete<TcpSegment<'b, &'b mut [u8]>>>,
+            WriteNextError,
+        >,
+    {
+        match tuple.remote_addr {
+            IpAddr::V4(remote_addr) => {
+                let mut packet =
+                    IPv4Packet::write_header(buf, PROTOCOL_TCP, local_ipv4_addr, remote_addr)
+                        .map_err(WriteNextError::IPv4Packet)?;
+                let segment_len = match write_segment(packet.inner_mut().payload_mut())? {
+                    Some(segment) => segment
+                        .finalize(
+                            local_port,
+                            tuple.remote_port,
+                            Some((local_ipv4_addr, remote_addr)),
+                        )
+                        .len(),
+                    None => return Ok(None),
+                };
+                Ok(Some(
+                    packet.with_payload_len_unchecked(segment_len, true).len(),
+                ))
+            }
+            IpAddr::V6(remote_addr) => {
+                let mut packet =
+                    IPv6Packet::write_header(buf, PROTOCOL_TCP, local_ipv6_addr, remote_addr)
+                        .map_err(WriteNextError::IPv6Packet)?;
+                let segment_len = match write_segment(packet.inner_mut().payload_mut())? {
+                    Some(segment) => {
+                        let mut segment = segment.finalize(local_port, tuple.remote_port, None);
+                        segment.set_checksum(0);
+                        let checksum = segment.compute_checksum_ipv6(local_ipv6_addr, remote_addr);
+                        segment.set_checksum(checksum).len()
+                    }
+                    None => return Ok(None),
+                };
+                Ok(Some(packet.with_payload_len_unchecked(segment_len).len()))
+            }
+        }
+    }
+
     /// Describes the status of the next segment to be sent by the handler.
     #[inline]
     pub fn next_segment_status(&self) -> NextSegmentStatus {
@@ -709,8 +791,8 @@ mod tests {
         assert_eq!(h.next_segment_status(), NextSegmentStatus::Available);
         assert_eq!(drain_packets(&mut h, local_addr, remote_addr), Ok(1));
 
-        let remote_tuple = ConnectionTuple::new(remote_addr, remote_port);
-        let remote_tuple2 = ConnectionTuple::new(remote_addr, remote_port + 1);
+        let remote_tuple = ConnectionTuple::new(remote_addr.into(), remote_port);
+        let remote_tuple2 = ConnectionTuple::new(remote_addr.into(), remote_port + 1);
 
         // Also, there should be a retransmission timer associated with the previous SYNACK now.
         assert_eq!(h.active_connections.len(), 0);
@@ -771,7 +853,7 @@ mod tests {
         // The timeout associated with the SYNACK of the second connection should be next.
         assert_eq!(h.active_connections.len(), 0);
         if let Some((_, tuple)) = h.next_timeout {
-            assert_ne!(tuple, ConnectionTuple::new(remote_addr, remote_port));
+            assert_ne!(tuple, ConnectionTuple::new(remote_addr.into(), remote_port));
         } else {
             panic!("missing third expected timeout");
         }
@@ -823,4 +905,69 @@ mod tests {
         assert_eq!(h.connections.len(), 1);
         assert_eq!(h.active_connections.len(), 0);
     }
+
+    #[test]
+    fn test_handler_ipv6() {
+        let mut buf = [0u8; 100];
+        let mut buf2 = [0u8; 2000];
+
+        let local_ipv4_addr = Ipv4Addr::new(169, 254, 169, 254);
+        let local_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        let local_port = 80;
+        let remote_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let remote_port = 1012;
+
+        let mut h = TcpIPv4Handler::new(
+            local_ipv4_addr,
+            local_port,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+        h.set_local_ipv6_addr(local_addr);
+        assert_eq!(h.local_ipv6_addr(), local_addr);
+
+        let mut p =
+            IPv6Packet::write_header(buf.as_mut(), PROTOCOL_TCP, remote_addr, local_addr).unwrap();
+        let s_len = TcpSegment::write_segment::<[u8]>(
+            p.inner_mut().payload_mut(),
+            remote_port,
+            local_port,
+            123,
+            456,
+            TcpFlags::SYN,
+            10000,
+            None,
+            100,
+            None,
+            None,
+        )
+        .unwrap()
+        .len();
+        let p = p.with_payload_len_unchecked(s_len);
+
+        assert_eq!(
+            h.receive_ipv6_packet(&p, mock_callback),
+            Ok(RecvEvent::NewConnectionSuccessful)
+        );
+        assert_eq!(h.connections.len(), 1);
+        assert!(h
+            .connections
+            .contains_key(&ConnectionTuple::new(remote_addr.into(), remote_port)));
+
+        // The SYNACK is sent back over IPv6, with a valid checksum.
+        assert_eq!(h.next_segment_status(), NextSegmentStatus::Available);
+        let (len, event) = h.write_next_packet(buf2.as_mut()).unwrap();
+        assert_eq!(event, WriteEvent::Nothing);
+        let len = len.unwrap().get();
+        let packet = IPv6Packet::from_bytes(&buf2[..len]).unwrap();
+        assert_eq!(packet.next_header(), PROTOCOL_TCP);
+        assert_eq!(packet.source_address(), local_addr);
+        assert_eq!(packet.destination_address(), remote_addr);
+
+        let s = TcpSegment::from_bytes(packet.payload(), None).unwrap();
+        assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(s.source_port(), local_port);
+        assert_eq!(s.destination_port(), remote_port);
+        assert_eq!(s.compute_checksum_ipv6(local_addr, remote_addr), 0);
+    }
 }
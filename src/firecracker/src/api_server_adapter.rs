@@ -3,20 +3,21 @@
 
 use std::io::prelude::*;
 use std::os::unix::io::AsRawFd;
-use std::os::unix::net::UnixStream;
+use std::os::unix::net::{UnixDatagram, UnixStream};
 use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-use api_server::{ApiRequest, ApiResponse, ApiServer};
+use api_server::{ApiRequest, ApiResponse, ApiServer, AuditLog, SocketPermissions, VsockForwarder};
 use event_manager::{EventOps, Events, MutEventSubscriber, SubscriberOps};
-use logger::{error, warn, ProcessTimeReporter};
+use logger::{error, warn, ProcessTimeReporter, ThreadCategory, METRICS};
 use seccompiler::BpfThreadMap;
 use utils::epoll::EventSet;
 use utils::eventfd::EventFd;
 use vmm::resources::VmResources;
 use vmm::rpc_interface::{PrebootApiController, RuntimeApiController, VmmAction};
+use vmm::seccomp_filters::SeccompViolations;
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::{EventManager, FcExitCode, Vmm};
 
@@ -49,9 +50,18 @@ impl ApiServerAdapter {
             event_manager
                 .run()
                 .expect("EventManager events driver fatal error");
-            if let Some(exit_code) = vmm.lock().unwrap().shutdown_exit_code() {
+            let mut locked_vmm = vmm.lock().unwrap();
+            if let Some(exit_code) = locked_vmm.shutdown_exit_code() {
                 return exit_code;
             }
+            // The devices attached by the API requests handled above start processing their
+            // events from here on.
+            for block in locked_vmm.take_hotplugged_blocks() {
+                event_manager.add_subscriber(block);
+            }
+            if let Some(balloon) = locked_vmm.take_hotplugged_balloon() {
+                event_manager.add_subscriber(balloon);
+            }
         }
     }
 
@@ -125,6 +135,13 @@ pub(crate) fn run_with_api(
     api_payload_limit: usize,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    socket_permissions: SocketPermissions,
+    audit_log: Option<AuditLog>,
+    vsock_forwarder: Option<VsockForwarder>,
+    metrics_endpoint: bool,
+    request_rate_limit: Option<u64>,
+    ready_notifier: Option<UnixDatagram>,
+    seccomp_violations: Option<SeccompViolations>,
 ) -> FcExitCode {
     // FD to notify of API events. This is a blocking eventfd by design.
     // It is used in the config/pre-boot loop which is a simple blocking loop
@@ -144,11 +161,50 @@ pub(crate) fn run_with_api(
         .remove("api")
         .expect("Missing seccomp filter for API thread.");
 
+    if let Some(mut vsock_forwarder) = vsock_forwarder {
+        let vsock_seccomp_filter = match seccomp_filters.remove("api_vsock") {
+            Some(filter) => filter,
+            None => {
+                error!("Missing seccomp filter for the API vsock thread.");
+                return FcExitCode::BadConfiguration;
+            }
+        };
+        // The thread isn't joined, it relays the connections until the process exits.
+        thread::Builder::new()
+            .name("fc_api_vsock".to_owned())
+            .spawn(move || {
+                METRICS
+                    .cpu_usage
+                    .register_current_thread(ThreadCategory::Api);
+                if let Err(e) = vsock_forwarder.run(&vsock_seccomp_filter) {
+                    error!("The API vsock thread stopped: {}", e);
+                }
+            })
+            .expect("API vsock thread spawn failed.");
+    }
+
     // Start the separate API thread.
     let api_thread = thread::Builder::new()
         .name("fc_api".to_owned())
         .spawn(move || {
-            match ApiServer::new(to_vmm, from_vmm, to_vmm_event_fd).bind_and_run(
+            METRICS
+                .cpu_usage
+                .register_current_thread(ThreadCategory::Api);
+            let mut api_server = ApiServer::new(to_vmm, from_vmm, to_vmm_event_fd);
+            api_server.set_socket_permissions(socket_permissions);
+            if let Some(audit_log) = audit_log {
+                api_server.set_audit_log(audit_log);
+            }
+            if metrics_endpoint {
+                api_server.enable_metrics_endpoint();
+            }
+            if let Some(requests_per_second) = request_rate_limit {
+                api_server.set_request_rate_limit(requests_per_second);
+            }
+            if let Some(ready_notifier) = ready_notifier {
+                api_server.set_ready_notifier(ready_notifier);
+            }
+            match api_server.bind_and_run(
                 api_bind_path,
                 process_time_reporter,
                 &api_seccomp_filter,
@@ -177,6 +233,9 @@ pub(crate) fn run_with_api(
     // Create the firecracker metrics object responsible for periodically printing metrics.
     let firecracker_metrics = Arc::new(Mutex::new(super::metrics::PeriodicMetrics::new()));
     event_manager.add_subscriber(firecracker_metrics.clone());
+    if let Some(seccomp_violations) = seccomp_violations {
+        event_manager.add_subscriber(Arc::new(Mutex::new(seccomp_violations)));
+    }
 
     // Configure, build and start the microVM.
     let build_result = match config_json {
@@ -222,6 +281,8 @@ pub(crate) fn run_with_api(
                 .lock()
                 .expect("Poisoned lock")
                 .start(super::metrics::WRITE_METRICS_PERIOD_MS);
+            // The event loop only runs from here on, so it only gets monitored from here on.
+            event_manager.add_subscriber(Arc::new(Mutex::new(super::health::Heartbeat::new())));
 
             ApiServerAdapter::run_microvm(
                 api_event_fd,
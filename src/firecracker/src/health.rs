@@ -0,0 +1,88 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use logger::{error, warn};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+use vmm::health::{HEALTH, HEARTBEAT_INTERVAL_MS};
+
+/// Reports that the event loop it is registered with is responsive, every
+/// `HEARTBEAT_INTERVAL_MS`, so that a stuck VMM thread shows up in the health checks.
+pub(crate) struct Heartbeat {
+    timer_fd: TimerFd,
+}
+
+impl Heartbeat {
+    /// Heartbeat constructor. Can panic on `TimerFd` creation failure.
+    pub fn new() -> Self {
+        let timer_fd = TimerFd::new_custom(ClockId::Monotonic, true, true)
+            .expect("Cannot create the heartbeat timer fd.");
+        Heartbeat { timer_fd }
+    }
+}
+
+impl MutEventSubscriber for Heartbeat {
+    /// Handle a read event (EPOLLIN).
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        if !EventSet::IN.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if source == self.timer_fd.as_raw_fd() {
+            self.timer_fd.read();
+            HEALTH.beat();
+        } else {
+            error!("Spurious heartbeat event!");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        let interval = Duration::from_millis(HEARTBEAT_INTERVAL_MS);
+        self.timer_fd.set_state(
+            TimerState::Periodic {
+                current: interval,
+                interval,
+            },
+            SetTimeFlags::Default,
+        );
+        HEALTH.beat();
+
+        if let Err(e) = ops.add(Events::new(&self.timer_fd, EventSet::IN)) {
+            error!("Failed to register heartbeat event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use event_manager::{EventManager, SubscriberOps};
+
+    use super::*;
+
+    #[test]
+    fn test_heartbeat() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let heartbeat = Arc::new(Mutex::new(Heartbeat::new()));
+        event_manager.add_subscriber(heartbeat);
+        assert!(HEALTH.report().event_loop);
+
+        // Wait for at most 1.5x period.
+        event_manager
+            .run_with_timeout((HEARTBEAT_INTERVAL_MS + HEARTBEAT_INTERVAL_MS / 2) as i32)
+            .expect("Heartbeat event timeout or error.");
+        assert!(HEALTH.report().event_loop);
+    }
+}
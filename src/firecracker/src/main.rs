@@ -1,22 +1,31 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 mod api_server_adapter;
+mod health;
 mod metrics;
 
-use std::fs::{self, File};
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::{io, panic, process};
 
+use api_server::{AuditLog, SocketPermissions, VsockForwarder};
 use event_manager::SubscriberOps;
-use logger::{error, info, ProcessTimeReporter, StoreMetric, LOGGER, METRICS};
+use logger::{
+    error, info, IncMetric, ProcessTimeReporter, StoreMetric, ThreadCategory, LOGGER, METRICS,
+};
 use seccompiler::BpfThreadMap;
 use snapshot::Snapshot;
 use utils::arg_parser::{ArgParser, Argument};
 use utils::terminal::Terminal;
 use utils::validators::validate_instance_id;
 use vmm::resources::VmResources;
-use vmm::seccomp_filters::{get_filters, SeccompConfig};
+use vmm::seccomp_filters::{
+    get_filters, set_level, SeccompConfig, SeccompLevel, SeccompViolations,
+};
 use vmm::signal_handler::register_signal_handlers;
 use vmm::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
 use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
@@ -111,6 +120,18 @@ fn main_exitable() -> FcExitCode {
                 .default_value(DEFAULT_API_SOCK_PATH)
                 .help("Path to unix domain socket used by the API."),
         )
+        .arg(
+            Argument::new("api-sock-mode")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help("File mode of the API socket, in octal, e.g. 0600."),
+        )
+        .arg(
+            Argument::new("api-sock-owner")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help("User and group IDs owning the API socket, as <uid>:<gid>."),
+        )
         .arg(
             Argument::new("id")
                 .takes_value(true)
@@ -126,10 +147,21 @@ fn main_exitable() -> FcExitCode {
                      filter. For advanced users.",
                 ),
         )
+        .arg(
+            Argument::new("seccomp-level")
+                .takes_value(true)
+                .forbids(vec!["no-seccomp"])
+                .help(
+                    "Optional parameter which sets how the seccomp filters are enforced: \
+                     `enforce` (default) blocks the syscalls they don't allow, while `log` lets \
+                     them through and has the kernel log them. For validating custom filters, \
+                     not recommended in production.",
+                ),
+        )
         .arg(
             Argument::new("no-seccomp")
                 .takes_value(false)
-                .forbids(vec!["seccomp-filter"])
+                .forbids(vec!["seccomp-filter", "seccomp-level"])
                 .help(
                     "Optional parameter which allows starting and using a microVM without seccomp \
                      filtering. Not recommended.",
@@ -148,6 +180,15 @@ fn main_exitable() -> FcExitCode {
         .arg(Argument::new("parent-cpu-time-us").takes_value(true).help(
             "Parent process CPU time (wall clock, microseconds). This parameter is optional.",
         ))
+        .arg(Argument::new("passed-fd").allow_multiple(true).help(
+            "Inherited file descriptor, in the <name>=<fd> format. It can be used in place of a \
+             path in the fd-based device and snapshot configurations. This parameter is \
+             optional, and is set by the jailer for each of its --pass-fd arguments.",
+        ))
+        .arg(Argument::new("notify-fd").takes_value(true).help(
+            "Connected sd_notify socket to report readiness on, once the API socket is up. This \
+             parameter is optional, and is set by the jailer when given --daemonize-notify.",
+        ))
         .arg(
             Argument::new("config-file")
                 .takes_value(true)
@@ -172,6 +213,42 @@ fn main_exitable() -> FcExitCode {
                 .takes_value(true)
                 .help("Path to a fifo or a file used for configuring the logger on startup."),
         )
+        .arg(
+            Argument::new("api-vsock-port")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help(
+                    "Vsock port on which the API is also served, for clients without access to \
+                     the API socket.",
+                ),
+        )
+        .arg(
+            Argument::new("api-vsock-max-connections")
+                .takes_value(true)
+                .requires("api-vsock-port")
+                .help("Maximum number of connections relayed at once from the API vsock port."),
+        )
+        .arg(
+            Argument::new("api-max-request-rate")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help(
+                    "Maximum number of API requests forwarded to the VMM per second, the others \
+                     being rejected.",
+                ),
+        )
+        .arg(
+            Argument::new("audit-log")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help("Path to a fifo or a file where a line is appended for every API request."),
+        )
+        .arg(
+            Argument::new("metrics-endpoint")
+                .takes_value(false)
+                .forbids(vec!["no-api"])
+                .help("Serve the metrics in the Prometheus format on GET /metrics."),
+        )
         .arg(
             Argument::new("level")
                 .takes_value(true)
@@ -206,6 +283,10 @@ fn main_exitable() -> FcExitCode {
                 .takes_value(true)
                 .help("Print the data format version of the provided snapshot state file."),
         )
+        .arg(Argument::new("snapshot-info").takes_value(true).help(
+            "Print the Firecracker version, data format version, vCPU count, memory size, \
+             devices and checksum of the provided snapshot state file, as JSON.",
+        ))
         .arg(
             Argument::new("http-api-max-payload-size")
                 .takes_value(true)
@@ -244,6 +325,11 @@ fn main_exitable() -> FcExitCode {
                 return vmm::FcExitCode::Ok;
             }
 
+            if let Some(snapshot_path) = arg_parser.arguments().single_value("snapshot-info") {
+                print_snapshot_info(snapshot_path);
+                return vmm::FcExitCode::Ok;
+            }
+
             arg_parser.arguments()
         }
     };
@@ -293,17 +379,55 @@ fn main_exitable() -> FcExitCode {
         };
     }
 
+    for passed_fd in arguments.multiple_values("passed-fd").unwrap_or(&[]) {
+        match parse_passed_fd(passed_fd) {
+            Ok((name, fd)) => info!("Inherited fd {} as {}.", fd, name),
+            Err(e) => return generic_error_exit(&e),
+        }
+    }
+
+    let ready_notifier = match arguments
+        .single_value("notify-fd")
+        .map(String::as_str)
+        .map(open_notify_fd)
+    {
+        Some(Ok(notifier)) => Some(notifier),
+        Some(Err(e)) => return generic_error_exit(&e),
+        None => None,
+    };
+
+    let seccomp_level = match SeccompLevel::from_arg(arguments.single_value("seccomp-level")) {
+        Ok(level) => level,
+        Err(e) => {
+            return generic_error_exit(&format!("Seccomp error: {}", e));
+        }
+    };
     let mut seccomp_filters: BpfThreadMap = match SeccompConfig::from_args(
         arguments.flag_present("no-seccomp"),
         arguments.single_value("seccomp-filter"),
     )
     .and_then(get_filters)
+    .map(|filters| set_level(filters, seccomp_level))
     {
         Ok(filters) => filters,
         Err(e) => {
             return generic_error_exit(&format!("Seccomp error: {}", e));
         }
     };
+    // The violations of the filters in log mode are counted from the kernel log, which has to
+    // be opened before the filters are installed.
+    let seccomp_violations = match seccomp_level {
+        SeccompLevel::Enforce => None,
+        SeccompLevel::Log => SeccompViolations::new()
+            .map_err(|e| {
+                METRICS.seccomp.violations_read_fails.inc();
+                error!(
+                    "Cannot read the kernel log, the seccomp violations won't be counted: {}",
+                    e
+                )
+            })
+            .ok(),
+    };
 
     let vmm_config_json = arguments
         .single_value("config-file")
@@ -338,6 +462,11 @@ fn main_exitable() -> FcExitCode {
         })
         .unwrap_or_else(|| api_payload_limit);
 
+    // The main thread goes on to become the VMM thread.
+    METRICS
+        .cpu_usage
+        .register_current_thread(ThreadCategory::Vmm);
+
     if api_enabled {
         let bind_path = arguments
             .single_value("api-sock")
@@ -361,6 +490,56 @@ fn main_exitable() -> FcExitCode {
 
         let process_time_reporter =
             ProcessTimeReporter::new(start_time_us, start_time_cpu_us, parent_cpu_time_us);
+
+        let socket_permissions = match parse_socket_permissions(
+            arguments.single_value("api-sock-mode"),
+            arguments.single_value("api-sock-owner"),
+        ) {
+            Ok(permissions) => permissions,
+            Err(e) => return generic_error_exit(&e),
+        };
+
+        let audit_log = match arguments
+            .single_value("audit-log")
+            .map(String::as_str)
+            .map(open_audit_log)
+        {
+            Some(Ok(audit_log)) => Some(audit_log),
+            Some(Err(e)) => {
+                return generic_error_exit(&format!("Could not open the audit log: {}", e));
+            }
+            None => None,
+        };
+
+        let vsock_forwarder = match arguments.single_value("api-vsock-port").map(|port| {
+            port.parse::<u32>()
+                .expect("'api-vsock-port' parameter expected to be of 'u32' type.")
+        }) {
+            Some(port) => match VsockForwarder::bind(port, bind_path.clone()) {
+                Ok(mut forwarder) => {
+                    if let Some(max_connections) = arguments
+                        .single_value("api-vsock-max-connections")
+                        .map(|max| {
+                            max.parse::<usize>().expect(
+                                "'api-vsock-max-connections' parameter expected to be of 'usize' \
+                                 type.",
+                            )
+                        })
+                    {
+                        forwarder.set_max_connections(max_connections);
+                    }
+                    Some(forwarder)
+                }
+                Err(e) => {
+                    return generic_error_exit(&format!(
+                        "Could not listen on vsock port {}: {}",
+                        port, e
+                    ));
+                }
+            },
+            None => None,
+        };
+
         api_server_adapter::run_with_api(
             &mut seccomp_filters,
             vmm_config_json,
@@ -371,6 +550,16 @@ fn main_exitable() -> FcExitCode {
             api_payload_limit,
             mmds_size_limit,
             metadata_json.as_deref(),
+            socket_permissions,
+            audit_log,
+            vsock_forwarder,
+            arguments.flag_present("metrics-endpoint"),
+            arguments.single_value("api-max-request-rate").map(|rate| {
+                rate.parse::<u64>()
+                    .expect("'api-max-request-rate' parameter expected to be of 'u64' type.")
+            }),
+            ready_notifier,
+            seccomp_violations,
         )
     } else {
         let seccomp_filters: BpfThreadMap = seccomp_filters
@@ -384,10 +573,82 @@ fn main_exitable() -> FcExitCode {
             boot_timer_enabled,
             mmds_size_limit,
             metadata_json.as_deref(),
+            seccomp_violations,
         )
     }
 }
 
+fn parse_socket_permissions(
+    mode: Option<&String>,
+    owner: Option<&String>,
+) -> Result<SocketPermissions, String> {
+    let mode = mode
+        .map(|mode| {
+            u32::from_str_radix(mode, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .ok_or_else(|| format!("Invalid value for the API socket mode: {}", mode))
+        })
+        .transpose()?;
+    let owner = owner
+        .map(|owner| {
+            let mut ids = owner.splitn(2, ':').map(str::parse::<u32>);
+            match (ids.next(), ids.next()) {
+                (Some(Ok(uid)), Some(Ok(gid))) => Ok((uid, gid)),
+                _ => Err(format!(
+                    "Invalid value for the API socket owner: {}. Expected <uid>:<gid>",
+                    owner
+                )),
+            }
+        })
+        .transpose()?;
+    Ok(SocketPermissions { mode, owner })
+}
+
+// Parses a fd inherited from the jailer, in the <name>=<fd> format, and checks it is open.
+fn parse_passed_fd(arg: &str) -> Result<(&str, RawFd), String> {
+    let (name, fd) = arg
+        .split_once('=')
+        .and_then(|(name, fd)| Some((name, fd.parse::<RawFd>().ok()?)))
+        .ok_or_else(|| format!("Invalid value for the passed fd: {}", arg))?;
+    // Safe because the fd is not used as a pointer, and we check the result.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(format!(
+            "Passed fd {} is not open: {}",
+            arg,
+            io::Error::last_os_error()
+        ));
+    }
+    Ok((name, fd))
+}
+
+// Takes over the sd_notify socket inherited from the jailer.
+fn open_notify_fd(fd: &str) -> Result<UnixDatagram, String> {
+    let fd = fd
+        .parse::<RawFd>()
+        .map_err(|_| format!("Invalid value for the notify fd: {}", fd))?;
+    // Safe because the fd is not used as a pointer, and we check the result.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(format!(
+            "Notify fd {} is not open: {}",
+            fd,
+            io::Error::last_os_error()
+        ));
+    }
+    // Safe because the fd is open, and is only used through the returned socket.
+    Ok(unsafe { UnixDatagram::from_raw_fd(fd) })
+}
+
+// Opens the audit log for appending. As for the logger, a fifo is opened with `O_NONBLOCK` so
+// that the API thread is not blocked when nobody reads from it.
+fn open_audit_log(path: &str) -> io::Result<AuditLog> {
+    let file = OpenOptions::new()
+        .custom_flags(libc::O_NONBLOCK)
+        .append(true)
+        .open(path)?;
+    Ok(AuditLog::new(Box::new(io::LineWriter::new(file))))
+}
+
 fn main() {
     // This idiom is the prescribed way to get a clean shutdown of Rust (that will report
     // no leaks in Valgrind or sanitizers).  Calling `unsafe { libc::exit() }` does no
@@ -456,6 +717,20 @@ fn print_snapshot_data_format(snapshot_path: &str) {
     println!("v{}", key);
 }
 
+// Print the description of the snapshot, as served by `GET /snapshot/info`.
+fn print_snapshot_info(snapshot_path: &str) {
+    let snapshot_info =
+        vmm::persist::describe_snapshot(Path::new(snapshot_path), VERSION_MAP.clone())
+            .unwrap_or_else(|err| {
+                process::exit(generic_error_exit(&format!(
+                    "Unable to describe the snapshot: {}",
+                    err
+                )) as i32);
+            });
+    // Serializing plain strings and numbers can't fail.
+    println!("{}", serde_json::to_string_pretty(&snapshot_info).unwrap());
+}
+
 // Configure and start a microVM as described by the command-line JSON.
 fn build_microvm_from_json(
     seccomp_filters: &BpfThreadMap,
@@ -498,12 +773,16 @@ fn run_without_api(
     bool_timer_enabled: bool,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    seccomp_violations: Option<SeccompViolations>,
 ) -> FcExitCode {
     let mut event_manager = EventManager::new().expect("Unable to create EventManager");
 
     // Create the firecracker metrics object responsible for periodically printing metrics.
     let firecracker_metrics = Arc::new(Mutex::new(metrics::PeriodicMetrics::new()));
     event_manager.add_subscriber(firecracker_metrics.clone());
+    if let Some(seccomp_violations) = seccomp_violations {
+        event_manager.add_subscriber(Arc::new(Mutex::new(seccomp_violations)));
+    }
 
     // Build the microVm. We can ignore VmResources since it's not used without api.
     let (_, vmm) = match build_microvm_from_json(
@@ -24,6 +24,9 @@ struct CgroupMountPoint {
     options: String,
 }
 
+// Default period of the CFS bandwidth control, used when only a quota is given.
+const DEFAULT_CFS_PERIOD_US: &str = "100000";
+
 // Allows creation of cgroups on the system for both versions
 pub struct CgroupBuilder {
     version: u8,
@@ -99,6 +102,36 @@ impl CgroupBuilder {
         }
     }
 
+    // Detects the version of the cgroup hierarchy used by the system.
+    // Hybrid systems mount the v1 controllers next to an (usually empty) cgroup2 hierarchy, so
+    // v2 is only picked when the system mounts a unified hierarchy and no v1 controllers.
+    pub fn detect_version() -> Result<u8> {
+        let f =
+            File::open(PROC_MOUNTS).map_err(|e| Error::FileOpen(PathBuf::from(PROC_MOUNTS), e))?;
+
+        let mut v1_found = false;
+        let mut v2_found = false;
+        for l in BufReader::new(f).lines() {
+            let l = l.map_err(|e| Error::ReadLine(PathBuf::from(PROC_MOUNTS), e))?;
+            // The file system type is the third field of a /proc/mounts line.
+            match l.split_whitespace().nth(2) {
+                Some("cgroup") => v1_found = true,
+                Some("cgroup2") => v2_found = true,
+                _ => (),
+            }
+        }
+
+        if v1_found {
+            Ok(1)
+        } else if v2_found {
+            Ok(2)
+        } else {
+            Err(Error::CgroupHierarchyMissing(
+                "No cgroup hierarchy found.".to_string(),
+            ))
+        }
+    }
+
     // Creates a new cggroup and returns it
     pub fn new_cgroup(
         &mut self,
@@ -250,17 +283,126 @@ fn inherit_from_parent(path: &mut PathBuf, file_name: &str, depth: u16) -> Resul
     inherit_from_parent_aux(path, file_name, depth)
 }
 
+// Translates the cgroup v1 files and values passed through `--cgroup` to their cgroup v2
+// counterparts, so that the same arguments can be used on hosts with a unified hierarchy.
+// Files which exist in both versions (e.g cpuset.cpus, pids.max) are kept as they are.
+pub fn translate_v1_to_v2(cgroups: Vec<(String, String)>) -> Result<Vec<(String, String)>> {
+    let mut translated: Vec<(String, String)> = Vec::new();
+    let mut cpu_max = None;
+    let mut cfs_quota = None;
+    let mut cfs_period = None;
+
+    for (file, value) in cgroups {
+        let invalid_value = || Error::CgroupFormat(format!("{}={}", file, value));
+        match file.as_str() {
+            "cpu.shares" => {
+                // Maps the [2, 262144] range of the shares to the [1, 10000] one of the weight.
+                let shares = value
+                    .parse::<u64>()
+                    .map_err(|_| invalid_value())?
+                    .clamp(2, 262_144);
+                let weight = 1 + ((shares - 2) * 9999) / 262_142;
+                translated.push(("cpu.weight".to_string(), weight.to_string()));
+            }
+            "cpu.cfs_quota_us" | "cpu.cfs_period_us" => {
+                let limit = value.parse::<i64>().map_err(|_| invalid_value())?;
+                if file == "cpu.cfs_quota_us" {
+                    cfs_quota = Some(if limit < 0 {
+                        "max".to_string()
+                    } else {
+                        value.clone()
+                    });
+                } else if limit > 0 {
+                    cfs_period = Some(value.clone());
+                } else {
+                    return Err(invalid_value());
+                }
+                // Both values end up in cpu.max, which is filled in once all are known.
+                if cpu_max.is_none() {
+                    cpu_max = Some(translated.len());
+                    translated.push(("cpu.max".to_string(), String::new()));
+                }
+            }
+            "memory.limit_in_bytes" | "memory.soft_limit_in_bytes" => {
+                let limit = value.parse::<i64>().map_err(|_| invalid_value())?;
+                let v2_file = if file == "memory.limit_in_bytes" {
+                    "memory.max"
+                } else {
+                    "memory.low"
+                };
+                let v2_value = if limit < 0 {
+                    "max".to_string()
+                } else {
+                    value.clone()
+                };
+                translated.push((v2_file.to_string(), v2_value));
+            }
+            "blkio.throttle.read_bps_device"
+            | "blkio.throttle.write_bps_device"
+            | "blkio.throttle.read_iops_device"
+            | "blkio.throttle.write_iops_device" => {
+                let key = match file.as_str() {
+                    "blkio.throttle.read_bps_device" => "rbps",
+                    "blkio.throttle.write_bps_device" => "wbps",
+                    "blkio.throttle.read_iops_device" => "riops",
+                    _ => "wiops",
+                };
+                // v1 format: <major>:<minor> <limit>
+                let v: Vec<&str> = value.split_whitespace().collect();
+                if v.len() != 2 || v[1].parse::<u64>().is_err() {
+                    return Err(invalid_value());
+                }
+                // io.max holds a line per device, with all the limits of that device.
+                let device = format!("{} ", v[0]);
+                match translated
+                    .iter_mut()
+                    .find(|(f, val)| f == "io.max" && val.starts_with(&device))
+                {
+                    Some((_, val)) => val.push_str(&format!(" {}={}", key, v[1])),
+                    None => translated
+                        .push(("io.max".to_string(), format!("{}{}={}", device, key, v[1]))),
+                }
+            }
+            _ => translated.push((file, value)),
+        }
+    }
+
+    if let Some(idx) = cpu_max {
+        translated[idx].1 = format!(
+            "{} {}",
+            cfs_quota.unwrap_or_else(|| "max".to_string()),
+            cfs_period.unwrap_or_else(|| DEFAULT_CFS_PERIOD_US.to_string())
+        );
+    }
+
+    Ok(translated)
+}
+
 // Extract the controller name from the cgroup file. The cgroup file must follow
 // this format: <cgroup_controller>.<cgroup_property>.
 fn get_controller_from_filename(file: &str) -> Result<&str> {
-    let v: Vec<&str> = file.split('.').collect();
-
     // Check format <cgroup_controller>.<cgroup_property>
-    if v.len() < 2 {
-        return Err(Error::CgroupInvalidFile(file.to_string()));
+    match file.split_once('.') {
+        Some((controller, property)) if !controller.is_empty() && !property.is_empty() => {
+            Ok(controller)
+        }
+        _ => Err(Error::CgroupInvalidFile(file.to_string())),
     }
+}
+
+// The cpuset files of a new v1 cgroup start out empty, and tasks can't be attached to it until
+// they are populated, so these are the only files which need inheriting the parent values.
+// Other files come with the defaults of the kernel, and some (e.g. blkio.throttle.*) are empty
+// in the parent as well.
+fn needs_inherit(file: &str) -> bool {
+    file == "cpuset.cpus" || file == "cpuset.mems"
+}
 
-    Ok(v[0])
+// Core files of the v2 interface, which are present in every cgroup whether a controller is
+// enabled or not (e.g. cgroup.max.descendants, cgroup.pressure, or the PSI files such as
+// io.pressure).
+fn is_v2_core_file(file: &str) -> bool {
+    file.starts_with("cgroup.") || file.ends_with(".pressure")
 }
 
 impl CgroupV1 {
@@ -301,7 +443,9 @@ impl Cgroup for CgroupV1 {
 
         // Write the corresponding cgroup value. inherit_from_parent is used to
         // correctly propagate the value if not defined.
-        inherit_from_parent(location, &self.base.file, self.cg_parent_depth)?;
+        if needs_inherit(&self.base.file) {
+            inherit_from_parent(location, &self.base.file, self.cg_parent_depth)?;
+        }
         location.push(&self.base.file);
         writeln_special(location, &self.base.value)?;
 
@@ -375,7 +519,7 @@ impl CgroupV2 {
         let controller = get_controller_from_filename(&file)?;
         let mut path = unified_path.to_path_buf();
 
-        if CgroupV2::controller_available(controller, unified_path) {
+        if is_v2_core_file(&file) || CgroupV2::controller_available(controller, unified_path) {
             path.push(parent_cg);
             path.push(id);
             Ok(CgroupV2(CgroupBase {
@@ -398,10 +542,12 @@ impl Cgroup for CgroupV2 {
         fs::create_dir_all(&self.0.location)
             .map_err(|e| Error::CreateDir(self.0.location.clone(), e))?;
 
-        // Ok to unwrap since the path was just created.
-        let parent = location.parent().unwrap();
-        // Enable the controller in all parent directories
-        CgroupV2::write_all_subtree_control(&parent, &controller)?;
+        if !is_v2_core_file(&self.0.file) {
+            // Ok to unwrap since the path was just created.
+            let parent = location.parent().unwrap();
+            // Enable the controller in all parent directories
+            CgroupV2::write_all_subtree_control(&parent, &controller)?;
+        }
 
         location.push(&self.0.file);
         writeln_special(location, &self.0.value)?;
@@ -581,6 +727,81 @@ mod tests {
         assert!(builder.is_err());
     }
 
+    #[test]
+    fn test_cgroup_detect_version() {
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(CgroupBuilder::detect_version().is_err());
+
+        // Unified hierarchy.
+        assert!(!mock_cgroups.add_v2_mounts().is_err());
+        assert_eq!(CgroupBuilder::detect_version().unwrap(), 2);
+
+        // Hybrid hierarchy.
+        assert!(!mock_cgroups.add_v1_mounts().is_err());
+        assert_eq!(CgroupBuilder::detect_version().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_translate_v1_to_v2() {
+        let to_vec = |cgroups: &[(&str, &str)]| -> Vec<(String, String)> {
+            cgroups
+                .iter()
+                .map(|(file, value)| (file.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let translated = translate_v1_to_v2(to_vec(&[
+            ("cpu.shares", "1024"),
+            ("cpuset.cpus", "0-1"),
+            ("cpu.cfs_quota_us", "50000"),
+            ("memory.limit_in_bytes", "1073741824"),
+            ("memory.soft_limit_in_bytes", "-1"),
+            ("blkio.throttle.read_bps_device", "8:0 1048576"),
+            ("blkio.throttle.write_iops_device", "8:16 100"),
+            ("blkio.throttle.write_bps_device", "8:0 2097152"),
+            ("cpu.cfs_period_us", "200000"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            translated,
+            to_vec(&[
+                ("cpu.weight", "39"),
+                ("cpuset.cpus", "0-1"),
+                ("cpu.max", "50000 200000"),
+                ("memory.max", "1073741824"),
+                ("memory.low", "max"),
+                ("io.max", "8:0 rbps=1048576 wbps=2097152"),
+                ("io.max", "8:16 wiops=100"),
+            ])
+        );
+
+        // The shares are clamped to the range supported by v1.
+        assert_eq!(
+            translate_v1_to_v2(to_vec(&[("cpu.shares", "1"), ("cpu.shares", "1000000")])).unwrap(),
+            to_vec(&[("cpu.weight", "1"), ("cpu.weight", "10000")])
+        );
+        // A missing quota or period falls back to the defaults.
+        assert_eq!(
+            translate_v1_to_v2(to_vec(&[("cpu.cfs_quota_us", "-1")])).unwrap(),
+            to_vec(&[("cpu.max", "max 100000")])
+        );
+        assert_eq!(
+            translate_v1_to_v2(to_vec(&[("cpu.cfs_period_us", "50000")])).unwrap(),
+            to_vec(&[("cpu.max", "max 50000")])
+        );
+
+        for invalid in &[
+            ("cpu.shares", "foo"),
+            ("cpu.cfs_quota_us", "foo"),
+            ("cpu.cfs_period_us", "0"),
+            ("memory.limit_in_bytes", "1G"),
+            ("blkio.throttle.read_bps_device", "8:0"),
+            ("blkio.throttle.read_iops_device", "8:0 foo"),
+        ] {
+            assert!(translate_v1_to_v2(to_vec(&[*invalid])).is_err());
+        }
+    }
+
     #[test]
     fn test_cgroup_build() {
         let mut mock_cgroups = MockCgroupFs::new().unwrap();
@@ -676,6 +897,100 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cgroup_v1_write_value() {
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v1_mounts().is_ok());
+
+        let mut builder = CgroupBuilder::new(1).unwrap();
+        let cpuset_root = PathBuf::from(format!("{}/cpuset", MockCgroupFs::MOCK_SYS_CGROUPS_DIR));
+        let memory_root = PathBuf::from(format!("{}/memory", MockCgroupFs::MOCK_SYS_CGROUPS_DIR));
+
+        // The cpuset files are populated from the parent before being written.
+        fs::create_dir_all(cpuset_root.join("fc_test_cgv1/101")).unwrap();
+        MockCgroupFs::create_file_with_contents(cpuset_root.join("cpuset.mems"), "0-1").unwrap();
+        MockCgroupFs::create_file_with_contents(cpuset_root.join("fc_test_cgv1/cpuset.mems"), "")
+            .unwrap();
+        MockCgroupFs::create_file_with_contents(
+            cpuset_root.join("fc_test_cgv1/101/cpuset.mems"),
+            "",
+        )
+        .unwrap();
+        let cg = builder
+            .new_cgroup(
+                "cpuset.mems".to_string(),
+                "1".to_string(),
+                "101",
+                Path::new("fc_test_cgv1"),
+            )
+            .unwrap();
+        assert!(cg.write_value().is_ok());
+        assert_eq!(
+            read_first_line(cpuset_root.join("fc_test_cgv1/cpuset.mems")).unwrap(),
+            "0-1\n"
+        );
+        assert_eq!(
+            read_first_line(cpuset_root.join("fc_test_cgv1/101/cpuset.mems")).unwrap(),
+            "1\n"
+        );
+
+        // Any other file is written as it is, even if the parent one is empty or missing.
+        let cg = builder
+            .new_cgroup(
+                "memory.swappiness".to_string(),
+                "0".to_string(),
+                "101",
+                Path::new("fc_test_cgv1"),
+            )
+            .unwrap();
+        assert!(cg.write_value().is_ok());
+        assert_eq!(
+            read_first_line(memory_root.join("fc_test_cgv1/101/memory.swappiness")).unwrap(),
+            "0\n"
+        );
+        assert!(!memory_root.join("fc_test_cgv1/memory.swappiness").exists());
+    }
+
+    #[test]
+    fn test_cgroup_v2_write_core_file() {
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(mock_cgroups.add_v2_mounts().is_ok());
+
+        let mut builder = CgroupBuilder::new(2).unwrap();
+        let cg_root = PathBuf::from(format!("{}/unified", MockCgroupFs::MOCK_SYS_CGROUPS_DIR));
+
+        // The core files don't depend on any of the controllers.
+        for file in &["cgroup.max.descendants", "cgroup.pressure", "irq.pressure"] {
+            let cg = builder
+                .new_cgroup(
+                    file.to_string(),
+                    "0".to_string(),
+                    "101",
+                    Path::new("fc_test_cgv2_core"),
+                )
+                .unwrap();
+            assert!(cg.write_value().is_ok());
+            assert_eq!(
+                read_first_line(cg_root.join("fc_test_cgv2_core/101").join(file)).unwrap(),
+                "0\n"
+            );
+        }
+        assert_eq!(
+            read_first_line(cg_root.join("cgroup.subtree_control")).unwrap(),
+            "\n"
+        );
+
+        // Unlike the files of a controller which is not available.
+        assert!(builder
+            .new_cgroup(
+                "hugetlb.2MB.max".to_string(),
+                "0".to_string(),
+                "101",
+                Path::new("fc_test_cgv2_core"),
+            )
+            .is_err());
+    }
+
     #[test]
     fn test_inherit_from_parent() {
         // 1. If parent file does not exist, return an error.
@@ -738,5 +1053,9 @@ mod tests {
         result = get_controller_from_filename(file);
         assert!(result.is_err());
         assert!(format!("{:?}", result).contains("CgroupInvalidFile"));
+
+        // Check file without a controller or a property
+        assert!(get_controller_from_filename(".cpus").is_err());
+        assert!(get_controller_from_filename("cpuset.").is_err());
     }
 }
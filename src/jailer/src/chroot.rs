@@ -3,7 +3,8 @@
 
 use std::env;
 use std::ffi::CStr;
-use std::path::Path;
+use std::fs::{self, OpenOptions};
+use std::path::{Component, Path, PathBuf};
 use std::ptr::null;
 
 use utils::syscall::SyscallReturnCode;
@@ -14,9 +15,118 @@ const OLD_ROOT_DIR_NAME_NUL_TERMINATED: &[u8] = b"old_root\0";
 const ROOT_DIR_NUL_TERMINATED: &[u8] = b"/\0";
 const CURRENT_DIR_NUL_TERMINATED: &[u8] = b".\0";
 
+// Host path bind-mounted into the jail.
+#[derive(Debug, PartialEq)]
+pub struct BindMount {
+    host_path: PathBuf,
+    // Relative to the jail root.
+    jail_path: PathBuf,
+    read_only: bool,
+}
+
+impl BindMount {
+    // Parses a bind mount following the <host_path>:<jail_path>[:ro] format.
+    pub fn parse(arg: &str) -> Result<Self> {
+        let invalid = || Error::BindArgument(arg.to_string());
+        let v: Vec<&str> = arg.split(':').collect();
+        let read_only = match v.len() {
+            2 => false,
+            3 if v[2] == "ro" => true,
+            _ => return Err(invalid()),
+        };
+
+        let host_path =
+            fs::canonicalize(v[0]).map_err(|e| Error::Canonicalize(PathBuf::from(v[0]), e))?;
+        // The jail path must stay within the jail.
+        let jail_path = Path::new(v[1]);
+        if !jail_path.is_absolute()
+            || jail_path
+                .components()
+                .any(|c| c == Component::CurDir || c == Component::ParentDir)
+        {
+            return Err(invalid());
+        }
+        let jail_path: PathBuf = jail_path
+            .components()
+            .filter(|c| *c != Component::RootDir)
+            .collect();
+        if jail_path.as_os_str().is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(BindMount {
+            host_path,
+            jail_path,
+            read_only,
+        })
+    }
+
+    /// Returns the path of the mount point, as seen from the jail.
+    pub fn jail_path(&self) -> PathBuf {
+        Path::new("/").join(&self.jail_path)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    // Bind-mounts the host path over the jail one, relative to the current directory.
+    fn mount(&self) -> Result<()> {
+        // The mount point has to be of the same kind as the host path.
+        if self.host_path.is_dir() {
+            fs::create_dir_all(&self.jail_path)
+                .map_err(|e| Error::CreateDir(self.jail_path.clone(), e))?;
+        } else {
+            if let Some(parent) = self.jail_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| Error::CreateDir(parent.to_path_buf(), e))?;
+            }
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&self.jail_path)
+                .map_err(|e| Error::FileOpen(self.jail_path.clone(), e))?;
+        }
+
+        let host_path = to_cstring(&self.host_path)?;
+        let jail_path = to_cstring(&self.jail_path)?;
+        // Safe because we provide valid parameters.
+        SyscallReturnCode(unsafe {
+            libc::mount(
+                host_path.as_ptr(),
+                jail_path.as_ptr(),
+                null(),
+                libc::MS_BIND | libc::MS_REC,
+                null(),
+            )
+        })
+        .into_empty_result()
+        .map_err(|e| Error::MountBindPath(self.host_path.clone(), e))?;
+
+        if self.read_only {
+            // The read-only flag is ignored when creating a bind mount, so remount it.
+            // Safe because we provide valid parameters.
+            SyscallReturnCode(unsafe {
+                libc::mount(
+                    null(),
+                    jail_path.as_ptr(),
+                    null(),
+                    libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                    null(),
+                )
+            })
+            .into_empty_result()
+            .map_err(|e| Error::MountBindPath(self.host_path.clone(), e))?;
+        }
+        Ok(())
+    }
+}
+
 // This uses switching to a new mount namespace + pivot_root(), together with the regular chroot,
 // to provide a hardened jail (at least compared to only relying on chroot).
-pub fn chroot(path: &Path) -> Result<()> {
+// The bind mounts are made in the new mount namespace, so they are only visible from the jail.
+pub fn chroot(path: &Path, bind_mounts: &[BindMount]) -> Result<()> {
     // We unshare into a new mount namespace. The call is safe because we're invoking a C library
     // function with valid parameters.
     SyscallReturnCode(unsafe { libc::unshare(libc::CLONE_NEWNS) })
@@ -61,6 +171,10 @@ pub fn chroot(path: &Path) -> Result<()> {
     // Change current dir to the chroot dir, so we only need to handle relative paths from now on.
     env::set_current_dir(path).map_err(Error::SetCurrentDir)?;
 
+    for bind_mount in bind_mounts {
+        bind_mount.mount()?;
+    }
+
     // We use the CStr conversion to make sure the contents of the byte slice would be a
     // valid C string (and for the as_ptr() method).
     let old_root_dir = CStr::from_bytes_with_nul(OLD_ROOT_DIR_NAME_NUL_TERMINATED)
@@ -100,3 +214,37 @@ pub fn chroot(path: &Path) -> Result<()> {
         .into_empty_result()
         .map_err(Error::RmOldRootDir)
 }
+
+#[cfg(test)]
+mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_bind_mount_parse() {
+        let host_file = TempFile::new().unwrap();
+        let host_path = host_file.as_path().to_str().unwrap();
+
+        assert_eq!(
+            BindMount::parse(&format!("{}:/images/vmlinux", host_path)).unwrap(),
+            BindMount {
+                host_path: fs::canonicalize(host_path).unwrap(),
+                jail_path: PathBuf::from("images/vmlinux"),
+                read_only: false,
+            }
+        );
+        assert!(
+            BindMount::parse(&format!("{}:/vmlinux:ro", host_path))
+                .unwrap()
+                .read_only
+        );
+
+        for jail_path in &["vmlinux", "/", "/../vmlinux", "/images/../../vmlinux"] {
+            assert!(BindMount::parse(&format!("{}:{}", host_path, jail_path)).is_err());
+        }
+        assert!(BindMount::parse(&format!("{}:/vmlinux:rw", host_path)).is_err());
+        assert!(BindMount::parse(host_path).is_err());
+        assert!(BindMount::parse("/inexistent:/vmlinux").is_err());
+    }
+}
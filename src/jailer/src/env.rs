@@ -6,7 +6,8 @@ use std::fs::{self, canonicalize, File, OpenOptions, Permissions};
 use std::io;
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
-use std::os::unix::io::IntoRawFd;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::os::unix::net::UnixDatagram;
 use std::os::unix::process::CommandExt;
 use std::path::{Component, Path, PathBuf};
 use std::process::{Command, Stdio};
@@ -15,9 +16,17 @@ use utils::arg_parser::Error::MissingValue;
 use utils::syscall::SyscallReturnCode;
 use utils::{arg_parser, validators};
 
-use crate::cgroup::{Cgroup, CgroupBuilder};
-use crate::chroot::chroot;
-use crate::resource_limits::{ResourceLimits, FSIZE_ARG, NO_FILE_ARG};
+use crate::cgroup::{translate_v1_to_v2, Cgroup, CgroupBuilder};
+use crate::chroot::{chroot, BindMount};
+use crate::landlock::LandlockConfig;
+use crate::netns::NetNsConfig;
+use crate::pass_fd::PassedFd;
+use crate::resource_limits::{
+    ResourceLimits, CORE_ARG, CPU_ARG, FSIZE_ARG, MEMLOCK_ARG, NO_FILE_ARG,
+};
+use crate::sd_notify::{self, NOTIFY_SOCKET_VAR};
+use crate::security_context::ExecSecurityContext;
+use crate::user_ns::{UserNsConfig, UserNsSync};
 use crate::{Error, Result};
 
 const STDIN_FILENO: libc::c_int = 0;
@@ -45,6 +54,8 @@ const DEV_URANDOM_MINOR: u32 = 9;
 
 const DEV_NULL_WITH_NUL: &[u8] = b"/dev/null\0";
 
+const PROC_DIR: &str = "/proc";
+
 // Relevant folders inside the jail that we create or/and for which we change ownership.
 // We need /dev in order to be able to create /dev/kvm and /dev/net/tun device.
 // We need /run for the default location of the api socket.
@@ -53,8 +64,8 @@ const DEV_NULL_WITH_NUL: &[u8] = b"/dev/null\0";
 const FOLDER_HIERARCHY: [&[u8]; 4] = [b"/\0", b"/dev\0", b"/dev/net\0", b"/run\0"];
 const FOLDER_PERMISSIONS: u32 = 0o700;
 
-// When running with `--new-pid-ns` flag, the PID of the process running the exec_file differs
-// from jailer's and it is stored inside a dedicated file, prefixed with the below extension.
+// When running with `--new-pid-ns` or `--uid-map` flags, the PID of the process running the
+// exec_file differs from jailer's and it is stored inside a dedicated file, prefixed with the below extension.
 const PID_FILE_EXTENSION: &str = ".pid";
 
 // Helper function, since we'll use libc::dup2 a bunch of times for daemonization.
@@ -65,6 +76,14 @@ fn dup2(old_fd: libc::c_int, new_fd: libc::c_int) -> Result<()> {
         .map_err(Error::Dup2)
 }
 
+// Writes `pid` to the file given through `--pidfile`, opened before chrooting.
+fn write_pidfile(pidfile: Option<(PathBuf, File)>, pid: i32) -> Result<()> {
+    match pidfile {
+        Some((path, mut file)) => write!(file, "{}", pid).map_err(|e| Error::Write(path, e)),
+        None => Ok(()),
+    }
+}
+
 // This is a wrapper for the clone system call. When we want to create a new process in a new
 // pid namespace, we will call clone with a NULL stack pointer. We can do this because we will
 // not use the CLONE_VM flag, this will result with the original stack replicated, in a similar
@@ -93,8 +112,18 @@ pub struct Env {
     uid: u32,
     gid: u32,
     netns: Option<String>,
+    new_netns: Option<NetNsConfig>,
     daemonize: bool,
+    daemonize_notify: bool,
+    notify_socket_path: Option<OsString>,
+    notify_socket: Option<UnixDatagram>,
+    pidfile: Option<PathBuf>,
     new_pid_ns: bool,
+    user_ns: Option<UserNsConfig>,
+    bind_mounts: Vec<BindMount>,
+    landlock: Option<LandlockConfig>,
+    security_context: Option<ExecSecurityContext>,
+    passed_fds: Vec<PassedFd>,
     start_time_us: u64,
     start_time_cpu_us: u64,
     jailer_cpu_time_us: u64,
@@ -159,10 +188,31 @@ impl Env {
             .parse::<u32>()
             .map_err(|_| Error::Gid(gid_str.to_owned()))?;
 
+        let user_ns = UserNsConfig::from_args(arguments, uid, gid)?;
+        // In a user namespace, the jail belongs to the host ids the ones of the jailed process
+        // map to.
+        let (uid, gid) = match user_ns {
+            Some(ref user_ns) => (user_ns.host_uid()?, user_ns.host_gid()?),
+            None => (uid, gid),
+        };
+
         let netns = arguments.single_value("netns").cloned();
 
+        let new_netns = NetNsConfig::from_args(arguments)?;
+
         let daemonize = arguments.flag_present("daemonize");
 
+        let daemonize_notify = arguments.flag_present("daemonize-notify");
+        // The environment is cleaned up before running, so the socket path is kept. Nothing is
+        // notified when the service manager doesn't expect it.
+        let notify_socket_path = if daemonize_notify {
+            std::env::var_os(NOTIFY_SOCKET_VAR)
+        } else {
+            None
+        };
+
+        let pidfile = arguments.single_value("pidfile").map(PathBuf::from);
+
         let new_pid_ns = arguments.flag_present("new-pid-ns");
 
         // Optional arguments.
@@ -178,40 +228,66 @@ impl Env {
             return Err(Error::CgroupInvalidParentPath());
         }
 
-        let cgroup_ver = arguments
-            .single_value("cgroup-version")
-            .ok_or_else(|| Error::ArgumentParsing(MissingValue("cgroup-version".to_string())))?;
-        let cgroup_ver = cgroup_ver
-            .parse::<u8>()
-            .map_err(|_| Error::CgroupInvalidVersion(cgroup_ver.to_string()))?;
-
-        let mut cgroup_builder = None;
+        let cgroup_ver = match arguments.single_value("cgroup-version") {
+            Some(ver) => Some(
+                ver.parse::<u8>()
+                    .map_err(|_| Error::CgroupInvalidVersion(ver.to_string()))?,
+            ),
+            None => None,
+        };
 
         // cgroup format: <cgroup_controller>.<cgroup_property>=<value>,...
         if let Some(cgroups_args) = arguments.multiple_values("cgroup") {
-            let builder = cgroup_builder.get_or_insert(CgroupBuilder::new(cgroup_ver)?);
+            let mut cgroup_files = Vec::new();
             for cg in cgroups_args {
-                let aux: Vec<&str> = cg.split('=').collect();
-                if aux.len() != 2 || aux[1].is_empty() {
-                    return Err(Error::CgroupFormat(cg.to_string()));
-                }
-                let file = Path::new(aux[0]);
-                if file.components().any(|c| {
+                // Values may hold '=' themselves (e.g. io.max=8:0 rbps=1048576).
+                let (file, value) = match cg.split_once('=') {
+                    Some((file, value)) if !file.is_empty() && !value.is_empty() => (file, value),
+                    _ => return Err(Error::CgroupFormat(cg.to_string())),
+                };
+                if Path::new(file).components().any(|c| {
                     c == Component::CurDir || c == Component::ParentDir || c == Component::RootDir
                 }) {
                     return Err(Error::CgroupInvalidFile(cg.to_string()));
                 }
+                cgroup_files.push((file.to_string(), value.to_string()));
+            }
+
+            let cgroup_ver = match cgroup_ver {
+                Some(ver) => ver,
+                None => CgroupBuilder::detect_version()?,
+            };
+            // The cgroups are given as v1 files, which need translating on a unified hierarchy.
+            if cgroup_ver == 2 {
+                cgroup_files = translate_v1_to_v2(cgroup_files)?;
+            }
 
-                let cgroup = builder.new_cgroup(
-                    aux[0].to_string(), // cgroup file
-                    aux[1].to_string(), // cgroup value
-                    id,
-                    parent_cgroup,
-                )?;
-                cgroups.push(cgroup);
+            let mut builder = CgroupBuilder::new(cgroup_ver)?;
+            for (file, value) in cgroup_files {
+                cgroups.push(builder.new_cgroup(file, value, id, parent_cgroup)?);
             }
         }
 
+        // bind format: <host_path>:<jail_path>[:ro]
+        let bind_mounts = arguments
+            .multiple_values("bind")
+            .unwrap_or(&[])
+            .iter()
+            .map(|arg| BindMount::parse(arg))
+            .collect::<Result<Vec<_>>>()?;
+
+        // landlock-path format: <jail_path>[:ro]
+        let landlock = LandlockConfig::from_args(
+            arguments,
+            &Path::new("/").join(exec_file_name),
+            &bind_mounts,
+        )?;
+
+        let security_context = ExecSecurityContext::from_args(arguments)?;
+
+        // pass-fd format: <name>=<fd>
+        let passed_fds = PassedFd::from_args(arguments)?;
+
         let mut resource_limits = ResourceLimits::default();
         if let Some(args) = arguments.multiple_values("resource-limit") {
             Env::parse_resource_limits(&mut resource_limits, args)?;
@@ -224,8 +300,18 @@ impl Env {
             uid,
             gid,
             netns,
+            new_netns,
             daemonize,
+            daemonize_notify,
+            notify_socket_path,
+            notify_socket: None,
+            pidfile,
             new_pid_ns,
+            user_ns,
+            bind_mounts,
+            landlock,
+            security_context,
+            passed_fds,
             start_time_us,
             start_time_cpu_us,
             jailer_cpu_time_us: 0,
@@ -247,6 +333,24 @@ impl Env {
         self.uid
     }
 
+    pub fn passed_fds(&self) -> &[PassedFd] {
+        &self.passed_fds
+    }
+
+    // Ids the jailed process switches to, which differ from the host ones owning the jail when
+    // it runs in a user namespace.
+    fn exec_gid(&self) -> u32 {
+        self.user_ns
+            .as_ref()
+            .map_or(self.gid, |user_ns| user_ns.gid)
+    }
+
+    fn exec_uid(&self) -> u32 {
+        self.user_ns
+            .as_ref()
+            .map_or(self.uid, |user_ns| user_ns.uid)
+    }
+
     fn parse_resource_limits(resource_limits: &mut ResourceLimits, args: &[String]) -> Result<()> {
         for arg in args {
             let (name, value) = arg
@@ -259,37 +363,84 @@ impl Env {
             match name {
                 FSIZE_ARG => resource_limits.set_file_size(limit_value),
                 NO_FILE_ARG => resource_limits.set_no_file(limit_value),
+                CORE_ARG => resource_limits.set_core(limit_value),
+                MEMLOCK_ARG => resource_limits.set_memlock(limit_value),
+                CPU_ARG => resource_limits.set_cpu(limit_value),
                 _ => return Err(Error::ResLimitArgument(name.to_string())),
             }
         }
         Ok(())
     }
 
-    fn exec_into_new_pid_ns(&mut self, chroot_exec_file: PathBuf) -> Result<()> {
+    fn exec_into_new_ns(
+        &mut self,
+        chroot_exec_file: PathBuf,
+        proc_dir: Option<File>,
+        pidfile: Option<(PathBuf, File)>,
+    ) -> Result<()> {
         // Compute jailer's total CPU time up to the current time.
         self.jailer_cpu_time_us =
             utils::time::get_time_us(utils::time::ClockType::ProcessCpu) - self.start_time_cpu_us;
 
-        // Duplicate the current process. The child process will belong to the previously created
-        // PID namespace. The current process will not be moved into the newly created namespace,
+        let mut flags = 0;
+        if self.new_pid_ns {
+            flags |= libc::CLONE_NEWPID;
+        }
+        // The child process cannot map the ids of its own user namespace to arbitrary host ids,
+        // so it waits for us to do it.
+        let user_ns_sync = match self.user_ns {
+            Some(_) => {
+                flags |= libc::CLONE_NEWUSER;
+                Some(UserNsSync::new()?)
+            }
+            None => None,
+        };
+
+        // Duplicate the current process. The child process will belong to the newly created
+        // namespaces. The current process will not be moved into the newly created PID namespace,
         // but its first child will assume the role of init(1) in the new namespace.
-        let pid = clone(std::ptr::null_mut(), libc::CLONE_NEWPID)?;
+        let pid = clone(std::ptr::null_mut(), flags)?;
         match pid {
             0 => {
                 // Reset process start time.
                 self.start_time_cpu_us = 0;
 
+                if let Some(sync) = user_ns_sync {
+                    sync.wait()?;
+                }
+                self.restrict_file_accesses()?;
                 Err(Error::Exec(self.exec_command(chroot_exec_file)))
             }
             child_pid => {
+                if let (Some(user_ns), Some(proc_dir), Some(sync)) =
+                    (self.user_ns.as_ref(), proc_dir.as_ref(), user_ns_sync)
+                {
+                    user_ns.write_maps(proc_dir, child_pid)?;
+                    sync.notify()?;
+                }
                 // Save the PID of the process running the exec file provided
                 // inside <chroot_exec_file>.pid file.
                 self.save_exec_file_pid(child_pid, chroot_exec_file)?;
+                write_pidfile(pidfile, child_pid)?;
+                // Stay around as the main process of the service, if any.
+                if self.daemonize_notify {
+                    let exit_code = sd_notify::supervise(child_pid)?;
+                    unsafe { libc::exit(exit_code) }
+                }
                 unsafe { libc::exit(0) }
             }
         }
     }
 
+    // Only done right before the exec, as the jailer itself still needs to reach /proc and the
+    // pid file.
+    fn restrict_file_accesses(&self) -> Result<()> {
+        match self.landlock {
+            Some(ref landlock) => landlock.restrict_self(),
+            None => Ok(()),
+        }
+    }
+
     fn save_exec_file_pid(&mut self, pid: i32, chroot_exec_file: PathBuf) -> Result<()> {
         let chroot_exec_file_str = chroot_exec_file
             .to_str()
@@ -406,11 +557,21 @@ impl Env {
             .args(&["--start-time-us", &self.start_time_us.to_string()])
             .args(&["--start-time-cpu-us", &self.start_time_cpu_us.to_string()])
             .args(&["--parent-cpu-time-us", &self.jailer_cpu_time_us.to_string()])
+            .args(
+                self.notify_socket.iter().flat_map(|socket| {
+                    vec!["--notify-fd".to_string(), socket.as_raw_fd().to_string()]
+                }),
+            )
+            .args(
+                self.passed_fds
+                    .iter()
+                    .flat_map(|passed_fd| vec!["--passed-fd".to_string(), passed_fd.to_arg()]),
+            )
             .stdin(Stdio::inherit())
             .stdout(Stdio::inherit())
             .stderr(Stdio::inherit())
-            .uid(self.uid())
-            .gid(self.gid())
+            .uid(self.exec_uid())
+            .gid(self.exec_gid())
             .args(&self.extra_args)
             .exec()
     }
@@ -503,6 +664,12 @@ impl Env {
     }
 
     pub fn run(mut self) -> Result<()> {
+        // An abstract notification socket is only reachable from the network namespace of the
+        // service manager, so connect to it before anything else.
+        if let Some(ref path) = self.notify_socket_path {
+            self.notify_socket = Some(sd_notify::connect(path)?);
+        }
+
         let exec_file_name = self.copy_exec_to_chroot()?;
         let chroot_exec_file = PathBuf::from("/").join(&exec_file_name);
 
@@ -511,6 +678,11 @@ impl Env {
             Env::join_netns(path)?;
         }
 
+        // Or create a new one and set up its interfaces, while we can still reach the host ones.
+        if let Some(ref netns) = self.new_netns {
+            netns.setup(self.uid(), self.gid())?;
+        }
+
         // Set limits on resources.
         self.resource_limits.install()?;
 
@@ -543,13 +715,36 @@ impl Env {
         } else {
             None
         };
+        // The user namespace is set up through /proc, which is out of reach from the jail.
+        let proc_dir = match self.user_ns {
+            Some(_) => Some(File::open(PROC_DIR).map_err(|e| Error::FileOpen(PROC_DIR.into(), e))?),
+            None => None,
+        };
+        // The pidfile is a host path, so it is opened before chrooting.
+        let pidfile = match self.pidfile {
+            Some(ref path) => Some((
+                path.clone(),
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)
+                    .map_err(|e| Error::FileOpen(path.clone(), e))?,
+            )),
+            None => None,
+        };
+        // The exec security context is set through /proc too. It is kept by the cloned process,
+        // if any, and applies when the jailed binary is exec'd.
+        if let Some(ref security_context) = self.security_context {
+            security_context.set_on_exec()?;
+        }
         #[cfg(target_arch = "aarch64")]
         self.copy_cache_info()?;
         #[cfg(target_arch = "aarch64")]
         self.copy_midr_el1_info()?;
 
         // Jail self.
-        chroot(self.chroot_dir())?;
+        chroot(self.chroot_dir(), &self.bind_mounts)?;
 
         // This will not only create necessary directories, but will also change ownership
         // for all of them.
@@ -598,10 +793,18 @@ impl Env {
                 .map_err(Error::CloseDevNullFd)?;
         }
 
-        // If specified, exec the provided binary into a new PID namespace.
-        if self.new_pid_ns {
-            self.exec_into_new_pid_ns(chroot_exec_file)
+        // Keep the passed fds open across the exec.
+        self.passed_fds
+            .iter()
+            .try_for_each(|passed_fd| passed_fd.unset_cloexec())?;
+
+        // If specified, exec the provided binary into a new PID and/or user namespace.
+        if self.new_pid_ns || self.user_ns.is_some() {
+            self.exec_into_new_ns(chroot_exec_file, proc_dir, pidfile)
         } else {
+            // The jailer becomes the jailed binary.
+            write_pidfile(pidfile, std::process::id() as i32)?;
+            self.restrict_file_accesses()?;
             Err(Error::Exec(self.exec_command(chroot_exec_file)))
         }
     }
@@ -742,6 +945,7 @@ mod tests {
         assert_eq!(format!("{}", good_env.uid()), good_arg_vals.uid);
 
         assert_eq!(good_env.netns, good_arg_vals.netns.map(String::from));
+        assert!(good_env.new_netns.is_none());
         assert!(good_env.daemonize);
         assert!(good_env.new_pid_ns);
 
@@ -1127,6 +1331,183 @@ mod tests {
         assert!(Env::new(&args, 0, 0).is_ok());
     }
 
+    #[test]
+    fn test_cgroups_unified_hierarchy() {
+        let arg_parser = build_arg_parser();
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        assert!(!mock_cgroups.add_v2_mounts().is_err());
+
+        // Without an explicit version, the unified hierarchy is detected and the v1 files are
+        // translated to the v2 ones.
+        let mut args = arg_parser.arguments().clone();
+        let arg_vals = ArgVals {
+            cgroups: vec![
+                "cpu.shares=2",
+                "cpu.cfs_quota_us=50000",
+                "memory.limit_in_bytes=1073741824",
+                "blkio.throttle.read_bps_device=8:0 1048576",
+            ],
+            ..ArgVals::new()
+        };
+        args.parse(&make_args(&arg_vals)).unwrap();
+        assert!(Env::new(&args, 0, 0).is_ok());
+
+        // The v2 files are passed through, including the core ones.
+        let mut args = arg_parser.arguments().clone();
+        let arg_vals = ArgVals {
+            cgroups: vec![
+                "io.max=8:0 rbps=1048576 wiops=100",
+                "io.weight=default 200",
+                "cpuset.cpus.partition=root",
+                "cgroup.pressure=0",
+            ],
+            ..ArgVals::new()
+        };
+        args.parse(&make_args(&arg_vals)).unwrap();
+        assert!(Env::new(&args, 0, 0).is_ok());
+
+        // Values which cannot be translated are rejected.
+        let mut args = arg_parser.arguments().clone();
+        let arg_vals = ArgVals {
+            cgroups: vec!["cpu.shares=foo"],
+            ..ArgVals::new()
+        };
+        args.parse(&make_args(&arg_vals)).unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+
+        // There is no v1 hierarchy to use.
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&ArgVals::new());
+        arg_vec.push("--cgroup-version".to_string());
+        arg_vec.push("1".to_string());
+        args.parse(&arg_vec).unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_bind_mounts_parsing() {
+        let arg_parser = build_arg_parser();
+        let arg_vals = ArgVals {
+            cgroups: Vec::new(),
+            ..ArgVals::new()
+        };
+        let host_file = TempFile::new().unwrap();
+        let host_path = host_file.as_path().to_str().unwrap();
+
+        for (bind, valid) in &[
+            (format!("{}:/vmlinux", host_path), true),
+            (format!("{}:/images/vmlinux:ro", host_path), true),
+            (format!("{}:vmlinux", host_path), false),
+            (format!("{}:/../vmlinux", host_path), false),
+            ("/inexistent:/vmlinux".to_string(), false),
+        ] {
+            let mut args = arg_parser.arguments().clone();
+            let mut arg_vec = make_args(&arg_vals);
+            arg_vec.push("--bind".to_string());
+            arg_vec.push(bind.clone());
+            args.parse(&arg_vec).unwrap();
+            assert_eq!(Env::new(&args, 0, 0).is_ok(), *valid);
+        }
+    }
+
+    #[test]
+    fn test_daemonize_notify() {
+        let arg_parser = build_arg_parser();
+        let arg_vals = ArgVals {
+            cgroups: Vec::new(),
+            ..ArgVals::new()
+        };
+
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_args(&arg_vals)).unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert!(!env.daemonize_notify);
+        assert_eq!(env.pidfile, None);
+
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&arg_vals);
+        arg_vec.extend(
+            ["--daemonize-notify", "--pidfile", "/run/firecracker.pid"]
+                .iter()
+                .map(|arg| arg.to_string()),
+        );
+        args.parse(&arg_vec).unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert!(env.daemonize_notify);
+        assert_eq!(env.pidfile, Some(PathBuf::from("/run/firecracker.pid")));
+    }
+
+    #[test]
+    fn test_passed_fds() {
+        let arg_parser = build_arg_parser();
+        let arg_vals = ArgVals {
+            cgroups: Vec::new(),
+            ..ArgVals::new()
+        };
+        let file = TempFile::new().unwrap();
+        let fd = file.as_file().as_raw_fd();
+
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&arg_vals);
+        arg_vec.push("--pass-fd".to_string());
+        arg_vec.push(format!("rootfs={}", fd));
+        args.parse(&arg_vec).unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert_eq!(env.passed_fds().len(), 1);
+        assert_eq!(env.passed_fds()[0].fd(), fd);
+
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&arg_vals);
+        arg_vec.push("--pass-fd".to_string());
+        arg_vec.push("rootfs".to_string());
+        args.parse(&arg_vec).unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_user_ns() {
+        let arg_parser = build_arg_parser();
+        let arg_vals = ArgVals {
+            uid: "0",
+            gid: "100",
+            cgroups: Vec::new(),
+            ..ArgVals::new()
+        };
+
+        // The jail belongs to the host ids the ones of the jailed process map to.
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&arg_vals);
+        arg_vec.extend(
+            ["--uid-map", "0:100000:65536", "--gid-map", "0:200000:65536"]
+                .iter()
+                .map(|arg| arg.to_string()),
+        );
+        args.parse(&arg_vec).unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert_eq!(env.uid(), 100_000);
+        assert_eq!(env.gid(), 200_100);
+        assert_eq!(env.exec_uid(), 0);
+        assert_eq!(env.exec_gid(), 100);
+
+        // Without a user namespace, both are the same.
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_args(&arg_vals)).unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert_eq!(env.uid(), env.exec_uid());
+        assert_eq!(env.gid(), env.exec_gid());
+
+        // The gid of the jailed process is not mapped.
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&arg_vals);
+        arg_vec.extend(
+            ["--uid-map", "0:100000:65536", "--gid-map", "0:200000:100"]
+                .iter()
+                .map(|arg| arg.to_string()),
+        );
+        args.parse(&arg_vec).unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+    }
+
     #[test]
     fn test_parse_resource_limits() {
         let mut resource_limits = ResourceLimits::default();
@@ -1185,7 +1566,7 @@ mod tests {
         }
 
         // Check valid cases
-        let resources = [FSIZE_ARG, NO_FILE_ARG];
+        let resources = [FSIZE_ARG, NO_FILE_ARG, CORE_ARG, MEMLOCK_ARG, CPU_ARG];
         for resource in resources.iter() {
             let arg = vec![resource.to_string() + &"=4098".to_string()];
             Env::parse_resource_limits(&mut resource_limits, &*arg).unwrap();
@@ -0,0 +1,389 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Landlock ruleset restricting the file accesses of the jailed process.
+//!
+//! It comes on top of the chroot: the jailed process can only reach the paths it needs within
+//! the jail, should the jail hold more than intended. The ruleset is inherited across the exec,
+//! and can't be lifted afterwards.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Component, Path, PathBuf};
+
+use utils::arg_parser;
+use utils::syscall::SyscallReturnCode;
+
+use crate::chroot::BindMount;
+use crate::{Error, Result};
+
+// Landlock syscalls share the same numbers on x86_64 and aarch64.
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+// See include/uapi/linux/landlock.h.
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+// Rights handled since the first version of the ABI, up to ACCESS_FS_MAKE_SYM.
+const ACCESS_FS_ABI_V1: u64 = (1 << 13) - 1;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+// Rights which apply to files, the other ones only apply to directories.
+const ACCESS_FS_FILE: u64 =
+    ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+
+// Devices the jailed process needs, which are created by the jailer.
+const DEV_PATHS: [&str; 3] = ["/dev/kvm", "/dev/net/tun", "/dev/urandom"];
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: libc::c_int,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Access {
+    Execute,
+    ReadOnly,
+    ReadWrite,
+}
+
+impl Access {
+    // Returns the rights granted on a path, among the ones handled by the ruleset.
+    fn rights(self, is_dir: bool, handled: u64) -> u64 {
+        let rights = match self {
+            Access::Execute => ACCESS_FS_READ_FILE | ACCESS_FS_EXECUTE,
+            Access::ReadOnly => ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR,
+            Access::ReadWrite => !ACCESS_FS_EXECUTE,
+        };
+        let rights = if is_dir {
+            rights
+        } else {
+            rights & ACCESS_FS_FILE
+        };
+        rights & handled
+    }
+}
+
+/// Path of the jail the jailed process is allowed to access.
+#[derive(Debug, PartialEq)]
+struct LandlockRule {
+    path: PathBuf,
+    access: Access,
+    // Whether it is an error for the path not to exist.
+    required: bool,
+}
+
+impl LandlockRule {
+    // Parses a rule following the <jail_path>[:ro] format.
+    fn parse(arg: &str) -> Result<Self> {
+        let invalid = || Error::LandlockArgument(arg.to_string());
+        let v: Vec<&str> = arg.split(':').collect();
+        let access = match v.len() {
+            1 => Access::ReadWrite,
+            2 if v[1] == "ro" => Access::ReadOnly,
+            _ => return Err(invalid()),
+        };
+        let path = Path::new(v[0]);
+        if !path.is_absolute()
+            || path
+                .components()
+                .any(|c| c == Component::CurDir || c == Component::ParentDir)
+        {
+            return Err(invalid());
+        }
+        Ok(LandlockRule {
+            path: path.to_path_buf(),
+            access,
+            required: true,
+        })
+    }
+
+    fn add_to(&self, ruleset: &File, handled: u64) -> Result<()> {
+        let path_err = |e| Error::LandlockPath(self.path.clone(), e);
+        let path = CString::new(self.path.as_os_str().as_bytes())
+            .map_err(|_| path_err(io::Error::from_raw_os_error(libc::EINVAL)))?;
+        // Safe because the path is a valid null-terminated string, and we check the result.
+        let fd =
+            SyscallReturnCode(unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) })
+                .into_result();
+        let file = match fd {
+            // Safe because the fd was just opened and nothing else owns it.
+            Ok(fd) => unsafe { File::from_raw_fd(fd) },
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) && !self.required => return Ok(()),
+            Err(e) => return Err(path_err(e)),
+        };
+        let is_dir = file.metadata().map_err(path_err)?.is_dir();
+
+        let attr = PathBeneathAttr {
+            allowed_access: self.access.rights(is_dir, handled),
+            parent_fd: file.as_raw_fd(),
+        };
+        // Safe because the attribute matches the rule type, and we check the result.
+        SyscallReturnCode(unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_ADD_RULE,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            ) as libc::c_int
+        })
+        .into_empty_result()
+        .map_err(path_err)
+    }
+}
+
+/// Landlock ruleset of the jailed process.
+#[derive(Debug, PartialEq)]
+pub struct LandlockConfig {
+    rules: Vec<LandlockRule>,
+}
+
+impl LandlockConfig {
+    /// Builds the ruleset from the jailer arguments, if Landlock is requested.
+    ///
+    /// Besides the paths given through `--landlock-path`, the jailed process is allowed to
+    /// execute its binary, to use the devices created by the jailer and to access the bind mounts.
+    pub fn from_args(
+        arguments: &arg_parser::Arguments,
+        chroot_exec_file: &Path,
+        bind_mounts: &[BindMount],
+    ) -> Result<Option<Self>> {
+        if !arguments.flag_present("landlock") {
+            return Ok(None);
+        }
+
+        let mut rules = vec![LandlockRule {
+            path: chroot_exec_file.to_path_buf(),
+            access: Access::Execute,
+            required: true,
+        }];
+        rules.extend(DEV_PATHS.iter().map(|path| LandlockRule {
+            path: PathBuf::from(path),
+            access: Access::ReadWrite,
+            // /dev/urandom is only created when available on the host.
+            required: false,
+        }));
+        // The cache topology of the host is copied in the jail.
+        #[cfg(target_arch = "aarch64")]
+        rules.push(LandlockRule {
+            path: PathBuf::from("/sys"),
+            access: Access::ReadOnly,
+            required: false,
+        });
+        rules.extend(bind_mounts.iter().map(|bind_mount| LandlockRule {
+            path: bind_mount.jail_path(),
+            access: if bind_mount.read_only() {
+                Access::ReadOnly
+            } else {
+                Access::ReadWrite
+            },
+            required: true,
+        }));
+        for arg in arguments.multiple_values("landlock-path").unwrap_or(&[]) {
+            rules.push(LandlockRule::parse(arg)?);
+        }
+
+        Ok(Some(LandlockConfig { rules }))
+    }
+
+    /// Restricts the file accesses of the current process, and of the ones it spawns, to the
+    /// paths of the ruleset. Paths are resolved from the current root, so this is done once
+    /// jailed.
+    pub fn restrict_self(&self) -> Result<()> {
+        // Safe because a null attribute is expected when querying the ABI version.
+        let abi = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => {
+                    println!(
+                        "Warning! Landlock is not available on this kernel: {}. File accesses \
+                         are only restricted by the jail.",
+                        err
+                    );
+                    Ok(())
+                }
+                _ => Err(Error::LandlockRestrict(err)),
+            };
+        }
+
+        let mut handled = ACCESS_FS_ABI_V1;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // Safe because the attribute is valid and its size is the one of the first version of
+        // the ABI, and we check the result.
+        let fd = SyscallReturnCode(unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            ) as libc::c_int
+        })
+        .into_result()
+        .map_err(Error::LandlockRestrict)?;
+        // Safe because the fd was just created and nothing else owns it.
+        let ruleset = unsafe { File::from_raw_fd(fd) };
+
+        self.rules
+            .iter()
+            .try_for_each(|rule| rule.add_to(&ruleset, handled))?;
+
+        // Required to restrict ourselves without relying on CAP_SYS_ADMIN. Firecracker sets it
+        // anyway before installing its seccomp filters.
+        // Safe because we provide valid parameters, and we check the result.
+        SyscallReturnCode(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })
+            .into_empty_result()
+            .map_err(Error::LandlockRestrict)?;
+        // Safe because the ruleset fd is valid, and we check the result.
+        SyscallReturnCode(unsafe {
+            libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset.as_raw_fd(), 0) as libc::c_int
+        })
+        .into_empty_result()
+        .map_err(Error::LandlockRestrict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::build_arg_parser;
+
+    fn parse_args(extra: &[&str], bind_mounts: &[BindMount]) -> Result<Option<LandlockConfig>> {
+        let arg_parser = build_arg_parser();
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec: Vec<String> = vec![
+            "--binary-name",
+            "--id",
+            "bd65600d-8669-4903-8a14-af88203add38",
+            "--exec-file",
+            "/proc/cpuinfo",
+            "--uid",
+            "0",
+            "--gid",
+            "0",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+        args.parse(&arg_vec).unwrap();
+        LandlockConfig::from_args(&args, Path::new("/firecracker"), bind_mounts)
+    }
+
+    #[test]
+    fn test_landlock_rule_parse() {
+        assert_eq!(
+            LandlockRule::parse("/run").unwrap(),
+            LandlockRule {
+                path: PathBuf::from("/run"),
+                access: Access::ReadWrite,
+                required: true,
+            }
+        );
+        assert_eq!(
+            LandlockRule::parse("/images/rootfs.ext4:ro")
+                .unwrap()
+                .access,
+            Access::ReadOnly
+        );
+
+        for invalid in &["", "run", "/run:rw", "/run:ro:ro", "/images/../run"] {
+            assert!(LandlockRule::parse(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_access_rights() {
+        let handled = ACCESS_FS_ABI_V1 | ACCESS_FS_REFER | ACCESS_FS_TRUNCATE;
+
+        assert_eq!(
+            Access::Execute.rights(false, handled),
+            ACCESS_FS_READ_FILE | ACCESS_FS_EXECUTE
+        );
+        assert_eq!(
+            Access::ReadOnly.rights(true, handled),
+            ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR
+        );
+        assert_eq!(Access::ReadOnly.rights(false, handled), ACCESS_FS_READ_FILE);
+        assert_eq!(
+            Access::ReadWrite.rights(false, handled),
+            ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE
+        );
+        assert_eq!(
+            Access::ReadWrite.rights(true, handled),
+            handled & !ACCESS_FS_EXECUTE
+        );
+        // Rights not handled by the kernel are not requested.
+        assert_eq!(
+            Access::ReadWrite.rights(false, ACCESS_FS_ABI_V1),
+            ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE
+        );
+    }
+
+    #[test]
+    fn test_landlock_config_from_args() {
+        assert_eq!(parse_args(&[], &[]).unwrap(), None);
+
+        let host_file = TempFile::new().unwrap();
+        let bind_mount =
+            BindMount::parse(&format!("{}:/vmlinux:ro", host_file.as_path().display())).unwrap();
+        let config = parse_args(
+            &[
+                "--landlock",
+                "--landlock-path",
+                "/run",
+                "--landlock-path",
+                "/rootfs.ext4:ro",
+            ],
+            &[bind_mount],
+        )
+        .unwrap()
+        .unwrap();
+
+        let rules: Vec<(&Path, Access, bool)> = config
+            .rules
+            .iter()
+            .map(|rule| (rule.path.as_path(), rule.access, rule.required))
+            .collect();
+        assert_eq!(rules[0], (Path::new("/firecracker"), Access::Execute, true));
+        assert!(rules.contains(&(Path::new("/dev/kvm"), Access::ReadWrite, false)));
+        assert!(rules.contains(&(Path::new("/vmlinux"), Access::ReadOnly, true)));
+        assert!(rules.contains(&(Path::new("/run"), Access::ReadWrite, true)));
+        assert!(rules.contains(&(Path::new("/rootfs.ext4"), Access::ReadOnly, true)));
+
+        assert!(parse_args(&["--landlock", "--landlock-path", "run"], &[]).is_err());
+    }
+}
@@ -3,7 +3,13 @@
 mod cgroup;
 mod chroot;
 mod env;
+mod landlock;
+mod netns;
+mod pass_fd;
 mod resource_limits;
+mod sd_notify;
+mod security_context;
+mod user_ns;
 use std::ffi::{CString, NulError, OsString};
 use std::path::{Path, PathBuf};
 use std::{env as p_env, fmt, fs, io, process, result};
@@ -12,11 +18,13 @@ use utils::arg_parser::{ArgParser, Argument, Error as ParsingError};
 use utils::validators;
 
 use crate::env::Env;
+use crate::pass_fd::PassedFd;
 
 const JAILER_VERSION: &str = env!("FIRECRACKER_VERSION");
 #[derive(Debug)]
 pub enum Error {
     ArgumentParsing(ParsingError),
+    BindArgument(String),
     Canonicalize(PathBuf, io::Error),
     CgroupInheritFromParent(PathBuf, String),
     CgroupLineNotFound(String, String),
@@ -35,6 +43,7 @@ pub enum Error {
     CloseDevNullFd(io::Error),
     Copy(PathBuf, PathBuf, io::Error),
     CreateDir(PathBuf, io::Error),
+    CreateNetNs(io::Error),
     CStringParsing(NulError),
     Dup2(io::Error),
     Exec(io::Error),
@@ -43,16 +52,27 @@ pub enum Error {
     FromBytesWithNul(std::ffi::FromBytesWithNulError),
     GetOldFdFlags(io::Error),
     Gid(String),
+    IdMapArgument(String),
     InvalidInstanceId(validators::Error),
+    LandlockArgument(String),
+    LandlockPath(PathBuf, io::Error),
+    LandlockRestrict(io::Error),
     MissingParent(PathBuf),
     MkdirOldRoot(io::Error),
     MknodDev(io::Error, &'static str),
     MountBind(io::Error),
+    MountBindPath(PathBuf, io::Error),
     MountPropagationSlave(io::Error),
+    NetNsAddress(String, io::Error),
+    NetNsArgument(String),
+    NetNsLink(String, io::Error),
     NotAFile(PathBuf),
     NotADirectory(PathBuf),
+    NotifySocket(String, io::Error),
     OpenDevNull(io::Error),
     OsStringParsing(PathBuf, OsString),
+    PassFd(i32, io::Error),
+    PassFdArgument(String),
     PivotRoot(io::Error),
     ReadLine(PathBuf, io::Error),
     ReadToString(PathBuf, io::Error),
@@ -61,15 +81,20 @@ pub enum Error {
     ResLimitFormat(String),
     ResLimitValue(String, String),
     RmOldRootDir(io::Error),
+    SecurityContext(String, io::Error),
+    SecurityContextArgument(String),
     SetCurrentDir(io::Error),
     SetNetNs(io::Error),
     Setrlimit(String),
     SetSid(io::Error),
+    Supervise(io::Error),
     Uid(String),
     UmountOldRoot(io::Error),
     UnexpectedListenerFd(i32),
+    UnmappedId(u32),
     UnshareNewNs(io::Error),
     UnsetCloexec(io::Error),
+    UserNsSync(io::Error),
     Write(PathBuf, io::Error),
 }
 
@@ -79,6 +104,7 @@ impl fmt::Display for Error {
 
         match *self {
             ArgumentParsing(ref err) => write!(f, "Failed to parse arguments: {}", err),
+            BindArgument(ref arg) => write!(f, "Invalid bind mount: {}", arg),
             Canonicalize(ref path, ref io_err) => write!(
                 f,
                 "{}",
@@ -137,6 +163,7 @@ impl fmt::Display for Error {
                 "{}",
                 format!("Failed to create directory {:?}: {}", path, err).replace("\"", "")
             ),
+            CreateNetNs(ref err) => write!(f, "Failed to create network namespace: {}", err),
             CStringParsing(_) => write!(f, "Encountered interior \\0 while parsing a string"),
             Dup2(ref err) => write!(f, "Failed to duplicate fd: {}", err),
             Exec(ref err) => write!(f, "Failed to exec into Firecracker: {}", err),
@@ -155,7 +182,17 @@ impl fmt::Display for Error {
             }
             GetOldFdFlags(ref err) => write!(f, "Failed to get flags from fd: {}", err),
             Gid(ref gid) => write!(f, "Invalid gid: {}", gid),
+            IdMapArgument(ref arg) => write!(f, "Invalid id mapping: {}", arg),
             InvalidInstanceId(ref err) => write!(f, "Invalid instance ID: {}", err),
+            LandlockArgument(ref arg) => write!(f, "Invalid Landlock path: {}", arg),
+            LandlockPath(ref path, ref err) => write!(
+                f,
+                "{}",
+                format!("Failed to add a Landlock rule for {:?}: {}", path, err).replace("\"", "")
+            ),
+            LandlockRestrict(ref err) => {
+                write!(f, "Failed to restrict file accesses with Landlock: {}", err)
+            }
             MissingParent(ref path) => write!(
                 f,
                 "{}",
@@ -174,9 +211,21 @@ impl fmt::Display for Error {
             MountBind(ref err) => {
                 write!(f, "Failed to bind mount the jail root directory: {}", err)
             }
+            MountBindPath(ref path, ref err) => write!(
+                f,
+                "{}",
+                format!("Failed to bind mount {:?} into the jail: {}", path, err).replace("\"", "")
+            ),
             MountPropagationSlave(ref err) => {
                 write!(f, "Failed to change the propagation type to slave: {}", err)
             }
+            NetNsAddress(ref addr, ref err) => {
+                write!(f, "Failed to configure network address {}: {}", addr, err)
+            }
+            NetNsArgument(ref arg) => write!(f, "Invalid network namespace argument: {}", arg),
+            NetNsLink(ref ifname, ref err) => {
+                write!(f, "Failed to set up network interface {}: {}", ifname, err)
+            }
             NotAFile(ref path) => write!(
                 f,
                 "{}",
@@ -187,12 +236,19 @@ impl fmt::Display for Error {
                 "{}",
                 format!("{:?} is not a directory", path).replace("\"", "")
             ),
+            NotifySocket(ref path, ref err) => write!(
+                f,
+                "Failed to connect to the notification socket {}: {}",
+                path, err
+            ),
             OpenDevNull(ref err) => write!(f, "Failed to open /dev/null: {}", err),
             OsStringParsing(ref path, _) => write!(
                 f,
                 "{}",
                 format!("Failed to parse path {:?} into an OsString", path).replace("\"", "")
             ),
+            PassFd(fd, ref err) => write!(f, "Failed to pass fd {}: {}", fd, err),
+            PassFdArgument(ref arg) => write!(f, "Invalid fd to pass: {}", arg),
             PivotRoot(ref err) => write!(f, "Failed to pivot root: {}", err),
             ReadLine(ref path, ref err) => write!(
                 f,
@@ -211,15 +267,25 @@ impl fmt::Display for Error {
                 write!(f, "Invalid limit value for resource: {}: {}", arg, err)
             }
             RmOldRootDir(ref err) => write!(f, "Failed to remove old jail root directory: {}", err),
+            SecurityContext(ref label, ref err) => {
+                write!(
+                    f,
+                    "Failed to set the exec security context {}: {}",
+                    label, err
+                )
+            }
+            SecurityContextArgument(ref arg) => write!(f, "Invalid security context: {}", arg),
             SetCurrentDir(ref err) => write!(f, "Failed to change current directory: {}", err),
             SetNetNs(ref err) => write!(f, "Failed to join network namespace: netns: {}", err),
             Setrlimit(ref err) => write!(f, "Failed to set limit for resource: {}", err),
             SetSid(ref err) => write!(f, "Failed to daemonize: setsid: {}", err),
+            Supervise(ref err) => write!(f, "Failed to supervise the jailed process: {}", err),
             Uid(ref uid) => write!(f, "Invalid uid: {}", uid),
             UmountOldRoot(ref err) => write!(f, "Failed to unmount the old jail root: {}", err),
             UnexpectedListenerFd(fd) => {
                 write!(f, "Unexpected value for the socket listener fd: {}", fd)
             }
+            UnmappedId(id) => write!(f, "Id {} is not mapped in the user namespace", id),
             UnshareNewNs(ref err) => {
                 write!(f, "Failed to unshare into new mount namespace: {}", err)
             }
@@ -228,6 +294,11 @@ impl fmt::Display for Error {
                 "Failed to unset the O_CLOEXEC flag on the socket fd: {}",
                 err
             ),
+            UserNsSync(ref err) => write!(
+                f,
+                "Failed to synchronize with the process in the user namespace: {}",
+                err
+            ),
             Write(ref path, ref err) => write!(
                 f,
                 "{}",
@@ -278,10 +349,60 @@ pub fn build_arg_parser() -> ArgParser<'static> {
                 .takes_value(true)
                 .help("Path to the network namespace this microVM should join."),
         )
+        .arg(
+            Argument::new("new-netns")
+                .takes_value(false)
+                .forbids(vec!["netns"])
+                .help("Create a new network namespace for this microVM."),
+        )
+        .arg(
+            Argument::new("tap-name")
+                .takes_value(true)
+                .requires("new-netns")
+                .help(
+                    "Name of the TAP device to create in the new network namespace, owned by \
+                     the uid and gid of the jailed process.",
+                ),
+        )
+        .arg(
+            Argument::new("veth-bridge")
+                .takes_value(true)
+                .requires("new-netns")
+                .help(
+                    "Host bridge to plumb the new network namespace into, through a veth pair. \
+                     When a TAP device is created as well, it is bridged with the veth inside \
+                     the namespace.",
+                ),
+        )
+        .arg(
+            Argument::new("netns-ip")
+                .takes_value(true)
+                .requires("new-netns")
+                .help(
+                    "Static address of the new network namespace, following this format: \
+                     <address>/<prefix_len> (e.g 192.168.0.2/24).",
+                ),
+        )
+        .arg(
+            Argument::new("netns-gateway")
+                .takes_value(true)
+                .requires("netns-ip")
+                .help("Default gateway of the new network namespace."),
+        )
         .arg(Argument::new("daemonize").takes_value(false).help(
             "Daemonize the jailer before exec, by invoking setsid(), and redirecting the standard \
              I/O file descriptors to /dev/null.",
         ))
+        .arg(Argument::new("daemonize-notify").takes_value(false).help(
+            "Hand the sd_notify socket found in NOTIFY_SOCKET over to the jailed binary, which \
+             reports its readiness once its API socket is up. When the binary is spawned into new \
+             namespaces, stay around and forward the termination signals to it.",
+        ))
+        .arg(
+            Argument::new("pidfile")
+                .takes_value(true)
+                .help("File to write the host PID of the jailed binary to."),
+        )
         .arg(
             Argument::new("new-pid-ns")
                 .takes_value(false)
@@ -292,20 +413,79 @@ pub fn build_arg_parser() -> ArgParser<'static> {
              <cgroup_file>=<value> (e.g cpu.shares=10). This argument can be used multiple times \
              to add multiple cgroups.",
         ))
+        .arg(Argument::new("bind").allow_multiple(true).help(
+            "Host path to bind-mount into the jail. It must follow this format: \
+             <host_path>:<jail_path>[:ro] (e.g /srv/vmlinux:/vmlinux:ro). This argument can be \
+             used multiple times to add multiple bind mounts.",
+        ))
+        .arg(Argument::new("landlock").takes_value(false).help(
+            "Restrict the file accesses of the jailed binary with Landlock, on top of the jail, \
+             to its own binary, the devices created by the jailer, the bind mounts and the paths \
+             given through --landlock-path.",
+        ))
+        .arg(
+            Argument::new("landlock-path")
+                .allow_multiple(true)
+                .requires("landlock")
+                .help(
+                    "Path of the jail the jailed binary is allowed to access with --landlock. It \
+                     must follow this format: <jail_path>[:ro] (e.g /run). This argument can be \
+                     used multiple times to allow multiple paths.",
+                ),
+        )
+        .arg(
+            Argument::new("selinux-context")
+                .takes_value(true)
+                .forbids(vec!["apparmor-profile"])
+                .help(
+                    "SELinux context the jailed binary is executed with (e.g \
+                     system_u:system_r:firecracker_t:s0:c1,c2).",
+                ),
+        )
+        .arg(
+            Argument::new("apparmor-profile")
+                .takes_value(true)
+                .help("AppArmor profile the jailed binary is executed with."),
+        )
+        .arg(Argument::new("pass-fd").allow_multiple(true).help(
+            "Inherited file descriptor to keep open for the jailed binary, which is told about it \
+             through a --passed-fd argument. It must follow this format: <name>=<fd> (e.g \
+             tap0=3). This argument can be used multiple times to pass multiple fds.",
+        ))
         .arg(Argument::new("resource-limit").allow_multiple(true).help(
             "Resource limit values to be set by the jailer. It must follow this format: \
              <resource>=<value> (e.g no-file=1024). This argument can be used multiple times to \
              add multiple resource limits. Current available resource values are:\n\t\tfsize: The \
              maximum size in bytes for files created by the process.\n\t\tno-file: Specifies a \
              value one greater than the maximum file descriptor number that can be opened by this \
-             process.",
+             process.\n\t\tcore: The maximum size in bytes of the core dumps of the process.\
+             \n\t\tmemlock: The maximum size in bytes of memory that may be locked into RAM by \
+             the process.\n\t\tcpu: The maximum CPU time in seconds the process can consume.",
         ))
         .arg(
-            Argument::new("cgroup-version")
-                .takes_value(true)
-                .default_value("1")
-                .help("Select the cgroup version used by the jailer."),
+            Argument::new("uid-map")
+                .allow_multiple(true)
+                .requires("gid-map")
+                .help(
+                    "Launch the jailed binary in a new user namespace, mapping its user ids to \
+                     the host ones. It must follow this format: \
+                     <inside_id>:<outside_id>:<count> (e.g 0:100000:65536). The uid argument \
+                     is then a user id of the namespace.",
+                ),
+        )
+        .arg(
+            Argument::new("gid-map")
+                .allow_multiple(true)
+                .requires("uid-map")
+                .help(
+                    "Group ids mapping of the user namespace, following the same format as \
+                     uid-map. The gid argument is then a group id of the namespace.",
+                ),
         )
+        .arg(Argument::new("cgroup-version").takes_value(true).help(
+            "Select the cgroup version used by the jailer. By default, the version of the cgroup \
+             hierarchy mounted on the host is used.",
+        ))
         .arg(
             Argument::new("parent-cgroup")
                 .takes_value(true)
@@ -339,9 +519,9 @@ pub fn readln_special<T: AsRef<Path>>(file_path: &T) -> Result<String> {
     Ok(line)
 }
 
-fn sanitize_process() {
+fn sanitize_process(passed_fds: &[PassedFd]) {
     // First thing to do is make sure we don't keep any inherited FDs
-    // other that IN, OUT and ERR.
+    // other that IN, OUT and ERR, and the ones passed to the jailed binary.
     if let Ok(paths) = fs::read_dir("/proc/self/fd") {
         for maybe_path in paths {
             if maybe_path.is_err() {
@@ -352,7 +532,7 @@ fn sanitize_process() {
             let fd_str = file_name.to_str().unwrap_or("0");
             let fd = fd_str.parse::<i32>().unwrap_or(0);
 
-            if fd > 2 {
+            if fd > 2 && !passed_fds.iter().any(|passed_fd| passed_fd.fd() == fd) {
                 // Safe because close() cannot fail when passed a valid parameter.
                 unsafe { libc::close(fd) };
             }
@@ -386,8 +566,6 @@ pub fn to_cstring<T: AsRef<Path>>(path: T) -> Result<CString> {
 }
 
 fn main() {
-    sanitize_process();
-
     let mut arg_parser = build_arg_parser();
 
     match arg_parser.parse_from_cmdline() {
@@ -421,6 +599,9 @@ fn main() {
         utils::time::get_time_us(utils::time::ClockType::ProcessCpu),
     )
     .and_then(|env| {
+        // The inherited fds are only closed once we know which ones are passed to the jailed
+        // binary. Nothing is opened or executed until then.
+        sanitize_process(env.passed_fds());
         fs::create_dir_all(env.chroot_dir())
             .map_err(|e| Error::CreateDir(env.chroot_dir().to_owned(), e))?;
         env.run()
@@ -452,12 +633,34 @@ mod tests {
             fds.push(maybe_file.unwrap().into_raw_fd());
         }
 
-        sanitize_process();
+        // The last one is passed to the jailed binary.
+        let arg_parser = build_arg_parser();
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec: Vec<String> = vec![
+            "--binary-name",
+            "--id",
+            "bd65600d-8669-4903-8a14-af88203add38",
+            "--exec-file",
+            "/proc/cpuinfo",
+            "--uid",
+            "0",
+            "--gid",
+            "0",
+            "--pass-fd",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        arg_vec.push(format!("rootfs={}", fds[n - 1]));
+        args.parse(&arg_vec).unwrap();
+        sanitize_process(&PassedFd::from_args(&args).unwrap());
 
-        for fd in fds {
-            let is_fd_opened = unsafe { libc::fcntl(fd, libc::F_GETFD) } == 0;
+        for fd in &fds[..n - 1] {
+            let is_fd_opened = unsafe { libc::fcntl(*fd, libc::F_GETFD) } == 0;
             assert_eq!(is_fd_opened, false);
         }
+        assert!(unsafe { libc::fcntl(fds[n - 1], libc::F_GETFD) } >= 0);
+        unsafe { libc::close(fds[n - 1]) };
 
         assert!(fs::remove_dir_all(tmp_dir_path).is_ok());
     }
@@ -502,6 +705,10 @@ mod tests {
             "Failed to parse arguments: Found argument 'foo' which wasn't expected, or isn't \
              valid in this context."
         );
+        assert_eq!(
+            format!("{}", Error::BindArgument("/foo".to_string())),
+            "Invalid bind mount: /foo",
+        );
         assert_eq!(
             format!(
                 "{}",
@@ -593,6 +800,10 @@ mod tests {
             ),
             format!("Failed to create directory /foo: {}", err2_str)
         );
+        assert_eq!(
+            format!("{}", Error::CreateNetNs(io::Error::from_raw_os_error(42))),
+            "Failed to create network namespace: No message of desired type (os error 42)",
+        );
         assert_eq!(
             format!(
                 "{}",
@@ -633,6 +844,10 @@ mod tests {
             format!("{}", Error::Gid(id.to_string())),
             "Invalid gid: foobar",
         );
+        assert_eq!(
+            format!("{}", Error::IdMapArgument("0:1".to_string())),
+            "Invalid id mapping: 0:1",
+        );
         assert_eq!(
             format!(
                 "{}",
@@ -640,6 +855,24 @@ mod tests {
             ),
             "Invalid instance ID: invalid char (a) at position 1",
         );
+        assert_eq!(
+            format!("{}", Error::LandlockArgument("run".to_string())),
+            "Invalid Landlock path: run",
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::LandlockPath(file_path.clone(), io::Error::from_raw_os_error(2))
+            ),
+            "Failed to add a Landlock rule for /foo/bar: No such file or directory (os error 2)",
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::LandlockRestrict(io::Error::from_raw_os_error(1))
+            ),
+            "Failed to restrict file accesses with Landlock: Operation not permitted (os error 1)",
+        );
         assert_eq!(
             format!("{}", Error::MissingParent(file_path.clone())),
             "File /foo/bar doesn't have a parent",
@@ -662,6 +895,13 @@ mod tests {
             "Failed to bind mount the jail root directory: No message of desired type (os error \
              42)",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::MountBindPath(file_path.clone(), io::Error::from_raw_os_error(42))
+            ),
+            "Failed to bind mount /foo/bar into the jail: No message of desired type (os error 42)",
+        );
         assert_eq!(
             format!(
                 "{}",
@@ -670,6 +910,25 @@ mod tests {
             "Failed to change the propagation type to slave: No message of desired type (os error \
              42)",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::NetNsAddress("10.0.0.1".to_string(), io::Error::from_raw_os_error(42))
+            ),
+            "Failed to configure network address 10.0.0.1: No message of desired type (os error \
+             42)",
+        );
+        assert_eq!(
+            format!("{}", Error::NetNsArgument("foo".to_string())),
+            "Invalid network namespace argument: foo",
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::NetNsLink("tap0".to_string(), io::Error::from_raw_os_error(42))
+            ),
+            "Failed to set up network interface tap0: No message of desired type (os error 42)",
+        );
         assert_eq!(
             format!("{}", Error::NotAFile(file_path.clone())),
             "/foo/bar is not a file",
@@ -678,6 +937,17 @@ mod tests {
             format!("{}", Error::NotADirectory(file_path.clone())),
             "/foo/bar is not a directory",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::NotifySocket(
+                    "/run/systemd/notify".to_string(),
+                    io::Error::from_raw_os_error(2)
+                )
+            ),
+            "Failed to connect to the notification socket /run/systemd/notify: No such file or \
+             directory (os error 2)",
+        );
         assert_eq!(
             format!("{}", Error::OpenDevNull(io::Error::from_raw_os_error(42))),
             "Failed to open /dev/null: No message of desired type (os error 42)",
@@ -689,6 +959,14 @@ mod tests {
             ),
             "Failed to parse path /foo/bar into an OsString",
         );
+        assert_eq!(
+            format!("{}", Error::PassFd(42, io::Error::from_raw_os_error(9))),
+            "Failed to pass fd 42: Bad file descriptor (os error 9)",
+        );
+        assert_eq!(
+            format!("{}", Error::PassFdArgument("tap=foo".to_string())),
+            "Invalid fd to pass: tap=foo",
+        );
         assert_eq!(
             format!("{}", Error::PivotRoot(io::Error::from_raw_os_error(42))),
             "Failed to pivot root: No message of desired type (os error 42)",
@@ -730,6 +1008,17 @@ mod tests {
             format!("{}", Error::RmOldRootDir(io::Error::from_raw_os_error(42))),
             "Failed to remove old jail root directory: No message of desired type (os error 42)",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::SecurityContext("foo".to_string(), io::Error::from_raw_os_error(22))
+            ),
+            "Failed to set the exec security context foo: Invalid argument (os error 22)",
+        );
+        assert_eq!(
+            format!("{}", Error::SecurityContextArgument("foo".to_string())),
+            "Invalid security context: foo",
+        );
         assert_eq!(
             format!("{}", Error::SetCurrentDir(io::Error::from_raw_os_error(2))),
             format!("Failed to change current directory: {}", err2_str),
@@ -746,6 +1035,10 @@ mod tests {
             format!("{}", Error::SetSid(io::Error::from_raw_os_error(42))),
             "Failed to daemonize: setsid: No message of desired type (os error 42)",
         );
+        assert_eq!(
+            format!("{}", Error::Supervise(io::Error::from_raw_os_error(10))),
+            "Failed to supervise the jailed process: No child processes (os error 10)",
+        );
         assert_eq!(
             format!("{}", Error::Uid(id.to_string())),
             "Invalid uid: foobar",
@@ -758,6 +1051,10 @@ mod tests {
             format!("{}", Error::UnexpectedListenerFd(42)),
             "Unexpected value for the socket listener fd: 42",
         );
+        assert_eq!(
+            format!("{}", Error::UnmappedId(42)),
+            "Id 42 is not mapped in the user namespace",
+        );
         assert_eq!(
             format!("{}", Error::UnshareNewNs(io::Error::from_raw_os_error(42))),
             "Failed to unshare into new mount namespace: No message of desired type (os error 42)",
@@ -767,6 +1064,11 @@ mod tests {
             "Failed to unset the O_CLOEXEC flag on the socket fd: No message of desired type (os \
              error 42)",
         );
+        assert_eq!(
+            format!("{}", Error::UserNsSync(io::Error::from_raw_os_error(42))),
+            "Failed to synchronize with the process in the user namespace: No message of desired \
+             type (os error 42)",
+        );
         assert_eq!(
             format!(
                 "{}",
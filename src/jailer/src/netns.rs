@@ -0,0 +1,634 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Creation of a network namespace for the jailed process.
+//!
+//! The namespace is populated through rtnetlink requests, so that no external tool (e.g. `ip`
+//! or a CNI plugin) needs to run before the jailer.
+
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::process;
+
+use utils::arg_parser;
+use utils::syscall::SyscallReturnCode;
+
+use crate::{to_cstring, Error, Result};
+
+// The namespace of the calling thread, which unshare() moves.
+const NETNS_PATH: &str = "/proc/thread-self/ns/net";
+const DEV_NET_TUN: &str = "/dev/net/tun";
+
+// Name of the interfaces created in the new network namespace.
+const NETNS_VETH_NAME: &str = "eth0";
+const NETNS_BRIDGE_NAME: &str = "br0";
+// Interface names are limited to IFNAMSIZ bytes, including the terminating null byte.
+const IFNAMSIZ: usize = 16;
+
+// rtnetlink ABI, from include/uapi/linux/{netlink,rtnetlink,if_link,if_addr,veth}.h.
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+const RTM_NEWLINK: u16 = 16;
+const RTM_NEWADDR: u16 = 20;
+const RTM_NEWROUTE: u16 = 24;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MASTER: u16 = 10;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_NET_NS_FD: u16 = 28;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const VETH_INFO_PEER: u16 = 1;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const RTA_GATEWAY: u16 = 5;
+const RT_TABLE_MAIN: u8 = 254;
+const RTPROT_BOOT: u8 = 3;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RTN_UNICAST: u8 = 1;
+// Size of the nlmsghdr and ifinfomsg structures.
+const NLMSG_HDR_LEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+
+// TUN/TAP ABI, from include/uapi/linux/if_tun.h.
+const IFF_TAP: u16 = 0x0002;
+const IFF_NO_PI: u16 = 0x1000;
+const TUNSETIFF: u64 = 0x4004_54ca;
+const TUNSETPERSIST: u64 = 0x4004_54cb;
+const TUNSETOWNER: u64 = 0x4004_54cc;
+const TUNSETGROUP: u64 = 0x4004_54ce;
+
+/// Network namespace created by the jailer, and the interfaces to set up in it.
+#[derive(Debug, PartialEq)]
+pub struct NetNsConfig {
+    /// TAP device created for the jailed process.
+    pub tap_name: Option<String>,
+    /// Host bridge the namespace is plumbed into, through a veth pair.
+    pub bridge: Option<String>,
+    /// Static address, and prefix length, of the namespace.
+    pub address: Option<(Ipv4Addr, u8)>,
+    /// Default gateway of the namespace.
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl NetNsConfig {
+    /// Builds the configuration from the jailer arguments, if a new network namespace is
+    /// requested.
+    pub fn from_args(arguments: &arg_parser::Arguments) -> Result<Option<Self>> {
+        if !arguments.flag_present("new-netns") {
+            return Ok(None);
+        }
+
+        let tap_name = arguments
+            .single_value("tap-name")
+            .map(|name| validate_ifname(name))
+            .transpose()?;
+        let bridge = arguments
+            .single_value("veth-bridge")
+            .map(|name| validate_ifname(name))
+            .transpose()?;
+        let address = arguments
+            .single_value("netns-ip")
+            .map(|addr| parse_address(addr))
+            .transpose()?;
+        let gateway = arguments
+            .single_value("netns-gateway")
+            .map(|addr| {
+                addr.parse::<Ipv4Addr>()
+                    .map_err(|_| Error::NetNsArgument(addr.to_string()))
+            })
+            .transpose()?;
+
+        // The address is set on the bridge joining the veth and the TAP, or on the only one of
+        // them that exists.
+        if address.is_some() && tap_name.is_none() && bridge.is_none() {
+            return Err(Error::NetNsArgument(
+                "netns-ip requires tap-name or veth-bridge".to_string(),
+            ));
+        }
+
+        Ok(Some(NetNsConfig {
+            tap_name,
+            bridge,
+            address,
+            gateway,
+        }))
+    }
+
+    /// Moves the current process into a new network namespace, and sets up its interfaces.
+    ///
+    /// The TAP device is owned by `uid` and `gid`, for the jailed process to open it.
+    pub fn setup(&self, uid: u32, gid: u32) -> Result<()> {
+        let host_netns =
+            File::open(NETNS_PATH).map_err(|e| Error::FileOpen(NETNS_PATH.into(), e))?;
+
+        // Safe because we are passing valid parameters, and checking the result.
+        SyscallReturnCode(unsafe { libc::unshare(libc::CLONE_NEWNET) })
+            .into_empty_result()
+            .map_err(Error::CreateNetNs)?;
+        let netns = File::open(NETNS_PATH).map_err(|e| Error::FileOpen(NETNS_PATH.into(), e))?;
+
+        if let Some(ref bridge) = self.bridge {
+            // The veth pair is created from the host namespace, which the host end stays in.
+            set_netns(&host_netns)?;
+            let host_veth = format!("fcveth{}", process::id());
+            let netlink = Netlink::new().map_err(|e| Error::NetNsLink(host_veth.clone(), e))?;
+            netlink
+                .create_veth(&host_veth, NETNS_VETH_NAME, netns.as_raw_fd())
+                .map_err(|e| Error::NetNsLink(host_veth.clone(), e))?;
+            let bridge_index = if_index(bridge)?;
+            netlink
+                .set_link_up(if_index(&host_veth)?, Some(bridge_index))
+                .map_err(|e| Error::NetNsLink(host_veth.clone(), e))?;
+            set_netns(&netns)?;
+        }
+
+        // A socket talks to the namespace it was created in, so open it in the new one.
+        let netlink = Netlink::new().map_err(|e| Error::NetNsLink("lo".to_string(), e))?;
+        netlink
+            .set_link_up(if_index("lo")?, None)
+            .map_err(|e| Error::NetNsLink("lo".to_string(), e))?;
+
+        if let Some(ref tap_name) = self.tap_name {
+            create_tap(tap_name, uid, gid).map_err(|e| Error::NetNsLink(tap_name.clone(), e))?;
+        }
+
+        // Pick the interface carrying the traffic of the namespace, joining the veth and the TAP
+        // with a bridge when both exist.
+        let ifname = match (self.bridge.as_ref(), self.tap_name.as_ref()) {
+            (Some(_), Some(tap_name)) => {
+                let err = |e| Error::NetNsLink(NETNS_BRIDGE_NAME.to_string(), e);
+                netlink.create_bridge(NETNS_BRIDGE_NAME).map_err(err)?;
+                let bridge_index = if_index(NETNS_BRIDGE_NAME)?;
+                for port in &[NETNS_VETH_NAME, tap_name.as_str()] {
+                    netlink
+                        .set_link_up(if_index(port)?, Some(bridge_index))
+                        .map_err(|e| Error::NetNsLink(port.to_string(), e))?;
+                }
+                Some(NETNS_BRIDGE_NAME)
+            }
+            (Some(_), None) => Some(NETNS_VETH_NAME),
+            (None, Some(tap_name)) => Some(tap_name.as_str()),
+            (None, None) => None,
+        };
+
+        if let Some(ifname) = ifname {
+            let index = if_index(ifname)?;
+            netlink
+                .set_link_up(index, None)
+                .map_err(|e| Error::NetNsLink(ifname.to_string(), e))?;
+            if let Some((addr, prefix_len)) = self.address {
+                netlink
+                    .add_address(index, addr, prefix_len)
+                    .map_err(|e| Error::NetNsAddress(addr.to_string(), e))?;
+            }
+            if let Some(gateway) = self.gateway {
+                netlink
+                    .add_default_route(gateway)
+                    .map_err(|e| Error::NetNsAddress(gateway.to_string(), e))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_ifname(name: &str) -> Result<String> {
+    if name.is_empty() || name.len() >= IFNAMSIZ || name.contains('/') {
+        return Err(Error::NetNsArgument(name.to_string()));
+    }
+    Ok(name.to_string())
+}
+
+// Parses an address following the <address>/<prefix_len> format.
+fn parse_address(arg: &str) -> Result<(Ipv4Addr, u8)> {
+    let invalid = || Error::NetNsArgument(arg.to_string());
+    let mut parts = arg.splitn(2, '/');
+    let addr = parts
+        .next()
+        .and_then(|addr| addr.parse::<Ipv4Addr>().ok())
+        .ok_or_else(invalid)?;
+    let prefix_len = parts
+        .next()
+        .and_then(|len| len.parse::<u8>().ok())
+        .filter(|len| *len <= 32)
+        .ok_or_else(invalid)?;
+    Ok((addr, prefix_len))
+}
+
+fn set_netns(netns: &File) -> Result<()> {
+    // Safe because we are passing valid parameters, and checking the result.
+    SyscallReturnCode(unsafe { libc::setns(netns.as_raw_fd(), libc::CLONE_NEWNET) })
+        .into_empty_result()
+        .map_err(Error::SetNetNs)
+}
+
+fn if_index(ifname: &str) -> Result<u32> {
+    let name = to_cstring(ifname)?;
+    // Safe because the name is a valid null-terminated string.
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(Error::NetNsLink(
+            ifname.to_string(),
+            io::Error::last_os_error(),
+        )),
+        index => Ok(index),
+    }
+}
+
+// Returns a `struct ifreq` holding the interface name and flags.
+fn ifreq(ifname: &str, flags: u16) -> [u8; 40] {
+    let mut ifreq = [0u8; 40];
+    ifreq[..ifname.len()].copy_from_slice(ifname.as_bytes());
+    ifreq[IFNAMSIZ..IFNAMSIZ + 2].copy_from_slice(&flags.to_ne_bytes());
+    ifreq
+}
+
+// Creates a persistent TAP device, which the jailed process can open without CAP_NET_ADMIN.
+fn create_tap(ifname: &str, uid: u32, gid: u32) -> io::Result<()> {
+    let tun = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(DEV_NET_TUN)?;
+    let ifreq = ifreq(ifname, IFF_TAP | IFF_NO_PI);
+
+    // Safe because the fd is valid, the ifreq outlives the calls, and we check the results.
+    unsafe {
+        SyscallReturnCode(libc::ioctl(tun.as_raw_fd(), TUNSETIFF as _, ifreq.as_ptr()))
+            .into_empty_result()?;
+        SyscallReturnCode(libc::ioctl(
+            tun.as_raw_fd(),
+            TUNSETOWNER as _,
+            uid as libc::c_ulong,
+        ))
+        .into_empty_result()?;
+        SyscallReturnCode(libc::ioctl(
+            tun.as_raw_fd(),
+            TUNSETGROUP as _,
+            gid as libc::c_ulong,
+        ))
+        .into_empty_result()?;
+        SyscallReturnCode(libc::ioctl(
+            tun.as_raw_fd(),
+            TUNSETPERSIST as _,
+            1 as libc::c_ulong,
+        ))
+        .into_empty_result()
+    }
+}
+
+// Builder of a netlink message.
+struct NetlinkMessage {
+    buf: Vec<u8>,
+}
+
+impl NetlinkMessage {
+    fn new(msg_type: u16, flags: u16) -> Self {
+        let mut buf = Vec::with_capacity(256);
+        // The length is filled in when the message is complete.
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(&msg_type.to_ne_bytes());
+        buf.extend_from_slice(&(NLM_F_REQUEST | NLM_F_ACK | flags).to_ne_bytes());
+        // Sequence number and port id, unused since we wait for each ack.
+        buf.extend_from_slice(&[0u8; 8]);
+        NetlinkMessage { buf }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        self.align();
+    }
+
+    fn align(&mut self) {
+        let padding = (4 - self.buf.len() % 4) % 4;
+        self.buf.extend_from_slice(&[0u8; 3][..padding]);
+    }
+
+    // Appends a `struct ifinfomsg`.
+    fn push_ifinfomsg(&mut self, index: u32, flags: u32, change: u32) {
+        let mut ifinfomsg = [0u8; IFINFOMSG_LEN];
+        ifinfomsg[4..8].copy_from_slice(&index.to_ne_bytes());
+        ifinfomsg[8..12].copy_from_slice(&flags.to_ne_bytes());
+        ifinfomsg[12..16].copy_from_slice(&change.to_ne_bytes());
+        self.push(&ifinfomsg);
+    }
+
+    fn push_attr(&mut self, attr_type: u16, payload: &[u8]) {
+        let len = (4 + payload.len()) as u16;
+        self.buf.extend_from_slice(&len.to_ne_bytes());
+        self.buf.extend_from_slice(&attr_type.to_ne_bytes());
+        self.push(payload);
+    }
+
+    fn push_str_attr(&mut self, attr_type: u16, value: &str) {
+        let mut payload = value.as_bytes().to_vec();
+        payload.push(0);
+        self.push_attr(attr_type, &payload);
+    }
+
+    // Starts an attribute nesting others, returning its offset for `end_nested`.
+    fn begin_nested(&mut self, attr_type: u16) -> usize {
+        let offset = self.buf.len();
+        self.push_attr(attr_type, &[]);
+        offset
+    }
+
+    fn end_nested(&mut self, offset: usize) {
+        let len = (self.buf.len() - offset) as u16;
+        self.buf[offset..offset + 2].copy_from_slice(&len.to_ne_bytes());
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let len = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf
+    }
+}
+
+// rtnetlink socket of the network namespace the process was in when it was opened.
+struct Netlink {
+    socket: File,
+}
+
+impl Netlink {
+    fn new() -> io::Result<Self> {
+        // Safe because we are passing valid parameters, and checking the result.
+        let fd = SyscallReturnCode(unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        })
+        .into_result()?;
+        // Safe because the fd was just created and nothing else owns it.
+        Ok(Netlink {
+            socket: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    // Sends a request and waits for the kernel to acknowledge it.
+    fn request(&self, msg: NetlinkMessage) -> io::Result<()> {
+        let msg = msg.finish();
+        // Safe because the kernel address is zero initialized, as the kernel expects.
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+
+        // Safe because the buffers are valid for the lengths passed, and we check the results.
+        SyscallReturnCode(unsafe {
+            libc::sendto(
+                self.socket.as_raw_fd(),
+                msg.as_ptr() as *const libc::c_void,
+                msg.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            ) as libc::c_int
+        })
+        .into_empty_result()?;
+
+        let mut reply = [0u8; 1024];
+        let len = SyscallReturnCode(unsafe {
+            libc::recv(
+                self.socket.as_raw_fd(),
+                reply.as_mut_ptr() as *mut libc::c_void,
+                reply.len(),
+                0,
+            ) as libc::c_int
+        })
+        .into_result()? as usize;
+
+        parse_ack(&reply[..len])
+    }
+
+    fn create_veth(&self, ifname: &str, peer_ifname: &str, peer_netns: RawFd) -> io::Result<()> {
+        let mut msg = NetlinkMessage::new(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL);
+        msg.push_ifinfomsg(0, 0, 0);
+        msg.push_str_attr(IFLA_IFNAME, ifname);
+        let linkinfo = msg.begin_nested(IFLA_LINKINFO);
+        msg.push_str_attr(IFLA_INFO_KIND, "veth");
+        let data = msg.begin_nested(IFLA_INFO_DATA);
+        let peer = msg.begin_nested(VETH_INFO_PEER);
+        msg.push_ifinfomsg(0, 0, 0);
+        msg.push_str_attr(IFLA_IFNAME, peer_ifname);
+        msg.push_attr(IFLA_NET_NS_FD, &(peer_netns as u32).to_ne_bytes());
+        msg.end_nested(peer);
+        msg.end_nested(data);
+        msg.end_nested(linkinfo);
+        self.request(msg)
+    }
+
+    fn create_bridge(&self, ifname: &str) -> io::Result<()> {
+        let mut msg = NetlinkMessage::new(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL);
+        msg.push_ifinfomsg(0, 0, 0);
+        msg.push_str_attr(IFLA_IFNAME, ifname);
+        let linkinfo = msg.begin_nested(IFLA_LINKINFO);
+        msg.push_str_attr(IFLA_INFO_KIND, "bridge");
+        msg.end_nested(linkinfo);
+        self.request(msg)
+    }
+
+    // Brings an interface up, optionally attaching it to a bridge.
+    fn set_link_up(&self, index: u32, master: Option<u32>) -> io::Result<()> {
+        let mut msg = NetlinkMessage::new(RTM_NEWLINK, 0);
+        let up = libc::IFF_UP as u32;
+        msg.push_ifinfomsg(index, up, up);
+        if let Some(master) = master {
+            msg.push_attr(IFLA_MASTER, &master.to_ne_bytes());
+        }
+        self.request(msg)
+    }
+
+    fn add_address(&self, index: u32, addr: Ipv4Addr, prefix_len: u8) -> io::Result<()> {
+        let mut msg = NetlinkMessage::new(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL);
+        // struct ifaddrmsg
+        let mut ifaddrmsg = vec![libc::AF_INET as u8, prefix_len, 0, RT_SCOPE_UNIVERSE];
+        ifaddrmsg.extend_from_slice(&index.to_ne_bytes());
+        msg.push(&ifaddrmsg);
+        msg.push_attr(IFA_LOCAL, &addr.octets());
+        msg.push_attr(IFA_ADDRESS, &addr.octets());
+        self.request(msg)
+    }
+
+    fn add_default_route(&self, gateway: Ipv4Addr) -> io::Result<()> {
+        let mut msg = NetlinkMessage::new(RTM_NEWROUTE, NLM_F_CREATE | NLM_F_EXCL);
+        // struct rtmsg
+        msg.push(&[
+            libc::AF_INET as u8,
+            0,
+            0,
+            0,
+            RT_TABLE_MAIN,
+            RTPROT_BOOT,
+            RT_SCOPE_UNIVERSE,
+            RTN_UNICAST,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        msg.push_attr(RTA_GATEWAY, &gateway.octets());
+        self.request(msg)
+    }
+}
+
+// Checks the reply to a request, which is an error message holding 0 on success, or a negative
+// errno.
+fn parse_ack(reply: &[u8]) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid netlink reply");
+    if reply.len() < NLMSG_HDR_LEN + 4 {
+        return Err(invalid());
+    }
+    let mut msg_type = [0u8; 2];
+    msg_type.copy_from_slice(&reply[4..6]);
+    if u16::from_ne_bytes(msg_type) != NLMSG_ERROR {
+        return Err(invalid());
+    }
+    let mut error = [0u8; 4];
+    error.copy_from_slice(&reply[NLMSG_HDR_LEN..NLMSG_HDR_LEN + 4]);
+    match i32::from_ne_bytes(error) {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(-errno)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_arg_parser;
+
+    fn parse_args(extra: &[&str]) -> Result<Option<NetNsConfig>> {
+        let arg_parser = build_arg_parser();
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec: Vec<String> = vec![
+            "--binary-name",
+            "--id",
+            "bd65600d-8669-4903-8a14-af88203add38",
+            "--exec-file",
+            "/proc/cpuinfo",
+            "--uid",
+            "1001",
+            "--gid",
+            "1002",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+        args.parse(&arg_vec).unwrap();
+        NetNsConfig::from_args(&args)
+    }
+
+    #[test]
+    fn test_netns_config_from_args() {
+        assert_eq!(parse_args(&[]).unwrap(), None);
+        assert_eq!(
+            parse_args(&["--new-netns"]).unwrap(),
+            Some(NetNsConfig {
+                tap_name: None,
+                bridge: None,
+                address: None,
+                gateway: None,
+            })
+        );
+        assert_eq!(
+            parse_args(&[
+                "--new-netns",
+                "--tap-name",
+                "tap0",
+                "--veth-bridge",
+                "fcbr0",
+                "--netns-ip",
+                "192.168.0.2/24",
+                "--netns-gateway",
+                "192.168.0.1",
+            ])
+            .unwrap(),
+            Some(NetNsConfig {
+                tap_name: Some("tap0".to_string()),
+                bridge: Some("fcbr0".to_string()),
+                address: Some((Ipv4Addr::new(192, 168, 0, 2), 24)),
+                gateway: Some(Ipv4Addr::new(192, 168, 0, 1)),
+            })
+        );
+
+        for invalid in &[
+            vec!["--new-netns", "--tap-name", "a_very_long_tap_name"],
+            vec!["--new-netns", "--veth-bridge", "br/0"],
+            vec![
+                "--new-netns",
+                "--tap-name",
+                "tap0",
+                "--netns-ip",
+                "192.168.0.2",
+            ],
+            vec![
+                "--new-netns",
+                "--tap-name",
+                "tap0",
+                "--netns-ip",
+                "192.168.0.2/33",
+            ],
+            vec!["--new-netns", "--tap-name", "tap0", "--netns-ip", "foo/24"],
+            vec!["--new-netns", "--netns-ip", "192.168.0.2/24"],
+            vec![
+                "--new-netns",
+                "--tap-name",
+                "tap0",
+                "--netns-ip",
+                "192.168.0.2/24",
+                "--netns-gateway",
+                "foo",
+            ],
+        ] {
+            assert!(parse_args(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_netlink_message() {
+        let mut msg = NetlinkMessage::new(RTM_NEWLINK, NLM_F_CREATE);
+        msg.push_ifinfomsg(3, 1, 1);
+        let linkinfo = msg.begin_nested(IFLA_LINKINFO);
+        msg.push_str_attr(IFLA_INFO_KIND, "veth");
+        msg.end_nested(linkinfo);
+        let buf = msg.finish();
+
+        // nlmsghdr + ifinfomsg + IFLA_LINKINFO header + IFLA_INFO_KIND padded to 4 bytes.
+        assert_eq!(buf.len(), 16 + 16 + 4 + 12);
+        assert_eq!(buf[..4], (buf.len() as u32).to_ne_bytes());
+        assert_eq!(buf[4..6], RTM_NEWLINK.to_ne_bytes());
+        assert_eq!(
+            buf[6..8],
+            (NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE).to_ne_bytes()
+        );
+        assert_eq!(buf[20..24], 3u32.to_ne_bytes());
+        // The nested attribute covers the one inside it.
+        assert_eq!(buf[32..34], 16u16.to_ne_bytes());
+        assert_eq!(buf[34..36], IFLA_LINKINFO.to_ne_bytes());
+        // The inner attribute length does not include the padding.
+        assert_eq!(buf[36..38], 9u16.to_ne_bytes());
+        assert_eq!(&buf[40..45], b"veth\0");
+    }
+
+    #[test]
+    fn test_parse_ack() {
+        let mut reply = vec![0u8; 36];
+        reply[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        assert!(parse_ack(&reply).is_ok());
+
+        reply[16..20].copy_from_slice(&(-libc::EEXIST).to_ne_bytes());
+        assert_eq!(
+            parse_ack(&reply).unwrap_err().raw_os_error(),
+            Some(libc::EEXIST)
+        );
+
+        assert!(parse_ack(&reply[..8]).is_err());
+        reply[4..6].copy_from_slice(&RTM_NEWLINK.to_ne_bytes());
+        assert!(parse_ack(&reply).is_err());
+    }
+}
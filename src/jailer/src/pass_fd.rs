@@ -0,0 +1,184 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! File descriptors the jailer keeps open across the exec of the jailed process.
+//!
+//! They let the jailed process use resources that are out of reach from the jail, such as a TAP
+//! device, a drive or a memory backend opened beforehand by the orchestrator.
+
+use std::os::unix::io::RawFd;
+
+use utils::arg_parser;
+use utils::syscall::SyscallReturnCode;
+
+use crate::{Error, Result};
+
+/// File descriptor passed to the jailed process, along with the name it is known by.
+#[derive(Debug, PartialEq)]
+pub struct PassedFd {
+    name: String,
+    fd: RawFd,
+}
+
+impl PassedFd {
+    // Parses a fd following the <name>=<fd> format.
+    fn parse(arg: &str) -> Result<Self> {
+        let invalid = || Error::PassFdArgument(arg.to_string());
+        let (name, fd) = arg.split_once('=').ok_or_else(invalid)?;
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(invalid());
+        }
+        let fd = fd.parse::<RawFd>().map_err(|_| invalid())?;
+        // The standard streams are always inherited, and may be replaced when daemonizing.
+        if fd <= libc::STDERR_FILENO {
+            return Err(invalid());
+        }
+        Ok(PassedFd {
+            name: name.to_string(),
+            fd,
+        })
+    }
+
+    /// Builds the list of fds to pass from the jailer arguments, checking they are open.
+    pub fn from_args(arguments: &arg_parser::Arguments) -> Result<Vec<Self>> {
+        let mut passed_fds: Vec<PassedFd> = Vec::new();
+        for arg in arguments.multiple_values("pass-fd").unwrap_or(&[]) {
+            let passed_fd = PassedFd::parse(arg)?;
+            if passed_fds
+                .iter()
+                .any(|other| other.name == passed_fd.name || other.fd == passed_fd.fd)
+            {
+                return Err(Error::PassFdArgument(arg.to_string()));
+            }
+            // Safe because the fd is not used as a pointer, and we check the result.
+            SyscallReturnCode(unsafe { libc::fcntl(passed_fd.fd, libc::F_GETFD) })
+                .into_empty_result()
+                .map_err(|e| Error::PassFd(passed_fd.fd, e))?;
+            passed_fds.push(passed_fd);
+        }
+        Ok(passed_fds)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Returns the argument telling the jailed process about the fd, in the <name>=<fd> format.
+    pub fn to_arg(&self) -> String {
+        format!("{}={}", self.name, self.fd)
+    }
+
+    /// Makes sure the fd is not closed when exec'ing the jailed process.
+    pub fn unset_cloexec(&self) -> Result<()> {
+        // Safe because the fd is not used as a pointer, and we check the results.
+        let flags = SyscallReturnCode(unsafe { libc::fcntl(self.fd, libc::F_GETFD) })
+            .into_result()
+            .map_err(|e| Error::PassFd(self.fd, e))?;
+        SyscallReturnCode(unsafe { libc::fcntl(self.fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) })
+            .into_empty_result()
+            .map_err(|e| Error::PassFd(self.fd, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+    use crate::build_arg_parser;
+
+    fn parse_args(extra: &[String]) -> Result<Vec<PassedFd>> {
+        let arg_parser = build_arg_parser();
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec: Vec<String> = vec![
+            "--binary-name",
+            "--id",
+            "bd65600d-8669-4903-8a14-af88203add38",
+            "--exec-file",
+            "/proc/cpuinfo",
+            "--uid",
+            "0",
+            "--gid",
+            "0",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        arg_vec.extend(extra.iter().cloned());
+        args.parse(&arg_vec).unwrap();
+        PassedFd::from_args(&args)
+    }
+
+    #[test]
+    fn test_passed_fd_parse() {
+        assert_eq!(
+            PassedFd::parse("tap_0=42").unwrap(),
+            PassedFd {
+                name: "tap_0".to_string(),
+                fd: 42
+            }
+        );
+        assert_eq!(PassedFd::parse("rootfs=3").unwrap().to_arg(), "rootfs=3");
+
+        for invalid in &[
+            "", "tap", "=42", "tap=", "tap=foo", "tap=-1", "tap=2", "ta p=42",
+        ] {
+            assert!(PassedFd::parse(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_passed_fds_from_args() {
+        assert_eq!(parse_args(&[]).unwrap(), vec![]);
+
+        let file = File::open("/proc/cpuinfo").unwrap();
+        let other_file = File::open("/proc/cpuinfo").unwrap();
+        let passed_fds = parse_args(&[
+            "--pass-fd".to_string(),
+            format!("rootfs={}", file.as_raw_fd()),
+            "--pass-fd".to_string(),
+            format!("mem={}", other_file.as_raw_fd()),
+        ])
+        .unwrap();
+        assert_eq!(passed_fds.len(), 2);
+        assert_eq!(passed_fds[0].fd(), file.as_raw_fd());
+        assert_eq!(
+            passed_fds[1].to_arg(),
+            format!("mem={}", other_file.as_raw_fd())
+        );
+
+        // The same name or fd is passed twice.
+        assert!(parse_args(&[
+            "--pass-fd".to_string(),
+            format!("rootfs={}", file.as_raw_fd()),
+            "--pass-fd".to_string(),
+            format!("rootfs={}", other_file.as_raw_fd()),
+        ])
+        .is_err());
+        assert!(parse_args(&[
+            "--pass-fd".to_string(),
+            format!("rootfs={}", file.as_raw_fd()),
+            "--pass-fd".to_string(),
+            format!("mem={}", file.as_raw_fd()),
+        ])
+        .is_err());
+
+        // The fd is not open.
+        assert!(parse_args(&["--pass-fd".to_string(), "rootfs=1000000".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_unset_cloexec() {
+        // Rust opens files with O_CLOEXEC.
+        let file = File::open("/proc/cpuinfo").unwrap();
+        let passed_fd = PassedFd::parse(&format!("cpuinfo={}", file.as_raw_fd())).unwrap();
+        passed_fd.unset_cloexec().unwrap();
+        let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, 0);
+    }
+}
@@ -14,6 +14,12 @@ const NO_FILE: u64 = 2048;
 pub(crate) const FSIZE_ARG: &str = "fsize";
 // Number of files resource argument name.
 pub(crate) const NO_FILE_ARG: &str = "no-file";
+// Core file size resource argument name.
+pub(crate) const CORE_ARG: &str = "core";
+// Locked memory resource argument name.
+pub(crate) const MEMLOCK_ARG: &str = "memlock";
+// CPU time resource argument name.
+pub(crate) const CPU_ARG: &str = "cpu";
 
 #[derive(Clone, Copy)]
 pub enum Resource {
@@ -21,6 +27,12 @@ pub enum Resource {
     RlimitFsize,
     // Number of open file descriptors.
     RlimitNoFile,
+    // Size of core dumps.
+    RlimitCore,
+    // Size of memory locked into RAM.
+    RlimitMemlock,
+    // CPU time, in seconds.
+    RlimitCpu,
 }
 
 impl From<Resource> for u32 {
@@ -28,6 +40,9 @@ impl From<Resource> for u32 {
         match resource {
             Resource::RlimitFsize => libc::RLIMIT_FSIZE as u32,
             Resource::RlimitNoFile => libc::RLIMIT_NOFILE as u32,
+            Resource::RlimitCore => libc::RLIMIT_CORE as u32,
+            Resource::RlimitMemlock => libc::RLIMIT_MEMLOCK as u32,
+            Resource::RlimitCpu => libc::RLIMIT_CPU as u32,
         }
     }
 }
@@ -37,6 +52,9 @@ impl Display for Resource {
         match self {
             Resource::RlimitFsize => write!(f, "size of file"),
             Resource::RlimitNoFile => write!(f, "number of file descriptors"),
+            Resource::RlimitCore => write!(f, "size of core dump"),
+            Resource::RlimitMemlock => write!(f, "size of locked memory"),
+            Resource::RlimitCpu => write!(f, "cpu time"),
         }
     }
 }
@@ -45,6 +63,9 @@ impl Display for Resource {
 pub struct ResourceLimits {
     file_size: Option<u64>,
     no_file: u64,
+    core: Option<u64>,
+    memlock: Option<u64>,
+    cpu: Option<u64>,
 }
 
 impl Default for ResourceLimits {
@@ -52,6 +73,9 @@ impl Default for ResourceLimits {
         ResourceLimits {
             file_size: None,
             no_file: NO_FILE,
+            core: None,
+            memlock: None,
+            cpu: None,
         }
     }
 }
@@ -64,6 +88,18 @@ impl ResourceLimits {
         }
         // Set limit on number of file descriptors.
         ResourceLimits::set_limit(Resource::RlimitNoFile, self.no_file)?;
+        if let Some(core) = self.core {
+            // Set core dump size limit.
+            ResourceLimits::set_limit(Resource::RlimitCore, core)?;
+        }
+        if let Some(memlock) = self.memlock {
+            // Set locked memory limit.
+            ResourceLimits::set_limit(Resource::RlimitMemlock, memlock)?;
+        }
+        if let Some(cpu) = self.cpu {
+            // Set CPU time limit.
+            ResourceLimits::set_limit(Resource::RlimitCpu, cpu)?;
+        }
 
         Ok(())
     }
@@ -86,6 +122,18 @@ impl ResourceLimits {
     pub fn set_no_file(&mut self, no_file: u64) {
         self.no_file = no_file;
     }
+
+    pub fn set_core(&mut self, core: u64) {
+        self.core = Some(core);
+    }
+
+    pub fn set_memlock(&mut self, memlock: u64) {
+        self.memlock = Some(memlock);
+    }
+
+    pub fn set_cpu(&mut self, cpu: u64) {
+        self.cpu = Some(cpu);
+    }
 }
 
 #[cfg(test)]
@@ -96,6 +144,12 @@ mod tests {
     fn test_from_resource() {
         assert_eq!(u32::from(Resource::RlimitFsize), libc::RLIMIT_FSIZE as _);
         assert_eq!(u32::from(Resource::RlimitNoFile), libc::RLIMIT_NOFILE as _);
+        assert_eq!(u32::from(Resource::RlimitCore), libc::RLIMIT_CORE as _);
+        assert_eq!(
+            u32::from(Resource::RlimitMemlock),
+            libc::RLIMIT_MEMLOCK as _
+        );
+        assert_eq!(u32::from(Resource::RlimitCpu), libc::RLIMIT_CPU as _);
     }
 
     #[test]
@@ -108,6 +162,15 @@ mod tests {
             Resource::RlimitNoFile.to_string(),
             "number of file descriptors".to_string()
         );
+        assert_eq!(
+            Resource::RlimitCore.to_string(),
+            "size of core dump".to_string()
+        );
+        assert_eq!(
+            Resource::RlimitMemlock.to_string(),
+            "size of locked memory".to_string()
+        );
+        assert_eq!(Resource::RlimitCpu.to_string(), "cpu time".to_string());
     }
 
     #[test]
@@ -120,6 +183,16 @@ mod tests {
         assert_eq!(rlimits.file_size.unwrap(), 1);
         rlimits.set_no_file(1);
         assert_eq!(rlimits.no_file, 1);
+
+        assert!(rlimits.core.is_none());
+        assert!(rlimits.memlock.is_none());
+        assert!(rlimits.cpu.is_none());
+        rlimits.set_core(0);
+        assert_eq!(rlimits.core.unwrap(), 0);
+        rlimits.set_memlock(2);
+        assert_eq!(rlimits.memlock.unwrap(), 2);
+        rlimits.set_cpu(3);
+        assert_eq!(rlimits.cpu.unwrap(), 3);
     }
 
     #[test]
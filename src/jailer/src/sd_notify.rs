@@ -0,0 +1,160 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration with service managers implementing the sd_notify protocol, such as systemd.
+//!
+//! The jailer connects to the notification socket before being jailed, and hands it over to the
+//! jailed binary, which reports its readiness once its API socket is up. When the binary runs in
+//! new namespaces, the jailer stays around as the main process of the service, forwarding the
+//! termination signals to the binary and exiting along with it.
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use libc::{c_int, c_void, siginfo_t};
+use utils::signal::register_signal_handler;
+use utils::syscall::SyscallReturnCode;
+
+use crate::{Error, Result};
+
+/// Environment variable holding the path of the notification socket.
+pub const NOTIFY_SOCKET_VAR: &str = "NOTIFY_SOCKET";
+
+// Signals the service manager uses to stop or reload the service.
+const FORWARDED_SIGNALS: [c_int; 4] = [libc::SIGTERM, libc::SIGINT, libc::SIGHUP, libc::SIGQUIT];
+
+// Process the signals are forwarded to.
+static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+
+/// Connects to the notification socket at `path`, which is in the abstract namespace when it
+/// starts with `@`.
+///
+/// The socket is not closed on exec, so that it can be handed over to the jailed binary.
+pub fn connect(path: &OsStr) -> Result<UnixDatagram> {
+    let socket_err = |e| Error::NotifySocket(path.to_string_lossy().into_owned(), e);
+
+    // Safe because this is a POD struct.
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let bytes = path.as_bytes();
+    // Filesystem paths need room for a terminating null byte, abstract ones start with one.
+    if bytes.is_empty() || bytes.len() >= addr.sun_path.len() {
+        return Err(socket_err(io::Error::from_raw_os_error(libc::EINVAL)));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+    let mut addr_len = std::mem::size_of::<libc::sa_family_t>() + bytes.len();
+    if bytes[0] == b'@' {
+        addr.sun_path[0] = 0;
+    } else {
+        addr_len += 1;
+    }
+
+    // Safe because we provide valid parameters, and we check the result.
+    let fd = SyscallReturnCode(unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM, 0) })
+        .into_result()
+        .map_err(socket_err)?;
+    // Safe because the fd was just created and nothing else owns it.
+    let socket = unsafe { UnixDatagram::from_raw_fd(fd) };
+    // Safe because the address is valid and its length matches it, and we check the result.
+    SyscallReturnCode(unsafe {
+        libc::connect(
+            fd,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            addr_len as libc::socklen_t,
+        )
+    })
+    .into_empty_result()
+    .map_err(socket_err)?;
+    Ok(socket)
+}
+
+extern "C" fn forward_signal(num: c_int, _info: *mut siginfo_t, _unused: *mut c_void) {
+    let pid = CHILD_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        // Safe because kill() is async-signal-safe, and only targets the child process.
+        unsafe { libc::kill(pid, num) };
+    }
+}
+
+// Returns the exit code of a shell for a process which ended with `status`.
+fn exit_code(status: c_int) -> i32 {
+    if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        libc::WEXITSTATUS(status)
+    }
+}
+
+/// Forwards the termination signals the jailer receives to `child_pid` until it ends, and
+/// returns the code to exit with.
+pub fn supervise(child_pid: i32) -> Result<i32> {
+    CHILD_PID.store(child_pid, Ordering::SeqCst);
+    for num in FORWARDED_SIGNALS.iter() {
+        register_signal_handler(*num, forward_signal)
+            .map_err(|e| Error::Supervise(io::Error::from_raw_os_error(e.errno())))?;
+    }
+
+    let mut status = 0;
+    loop {
+        // The child is cloned without an exit signal, so it can only be waited for with __WALL.
+        // Safe because the status is a valid pointer, and we check the result.
+        match SyscallReturnCode(unsafe { libc::waitpid(child_pid, &mut status, libc::__WALL) })
+            .into_empty_result()
+        {
+            Ok(()) => return Ok(exit_code(status)),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Error::Supervise(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::os::unix::io::AsRawFd;
+    use std::process;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_connect() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.as_path().join("notify");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let socket = connect(path.as_os_str()).unwrap();
+        socket.send(b"READY=1").unwrap();
+        let mut buf = [0u8; 16];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        // The socket is handed over to the jailed binary.
+        let flags = unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, 0);
+
+        // Abstract sockets are not bound in this test.
+        let abstract_path = OsString::from(format!("@jailer-test-{}", process::id()));
+        assert!(connect(&abstract_path).is_err());
+
+        assert!(connect(OsStr::new("")).is_err());
+        assert!(connect(tmp_dir.as_path().join("missing").as_os_str()).is_err());
+        let too_long = OsString::from(format!("/{}", "a".repeat(200)));
+        assert!(connect(&too_long).is_err());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        // Exited with 3.
+        assert_eq!(exit_code(3 << 8), 3);
+        // Killed by SIGTERM.
+        assert_eq!(exit_code(libc::SIGTERM), 128 + libc::SIGTERM);
+    }
+}
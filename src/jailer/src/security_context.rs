@@ -0,0 +1,147 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Mandatory access control label the jailed binary is executed with.
+//!
+//! The jailer does what `setexeccon()` (SELinux) and `aa_change_onexec()` (AppArmor) do, without
+//! depending on their libraries: it writes the label to the `exec` attribute of its thread, which
+//! the kernel applies on the next `exec()`. The attribute is carried over to the process cloned
+//! into new namespaces, and it is set before chrooting, as `/proc` is out of reach from the jail.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use utils::arg_parser;
+
+use crate::{Error, Result};
+
+const SELINUX_EXEC_ATTRS: [&str; 1] = ["/proc/thread-self/attr/exec"];
+// Kernels supporting LSM stacking expose the AppArmor attributes in their own directory, older
+// ones only through the shared one.
+const APPARMOR_EXEC_ATTRS: [&str; 2] = [
+    "/proc/thread-self/attr/apparmor/exec",
+    "/proc/thread-self/attr/exec",
+];
+
+/// Security context the jailed binary transitions to when exec'd.
+#[derive(Debug, PartialEq)]
+pub enum ExecSecurityContext {
+    /// SELinux context (e.g. `system_u:system_r:firecracker_t:s0:c1,c2`).
+    SELinux(String),
+    /// AppArmor profile (e.g. `firecracker-vm1`).
+    AppArmor(String),
+}
+
+impl ExecSecurityContext {
+    /// Builds the context from the jailer arguments, if one is requested.
+    pub fn from_args(arguments: &arg_parser::Arguments) -> Result<Option<Self>> {
+        let context = match (
+            arguments.single_value("selinux-context"),
+            arguments.single_value("apparmor-profile"),
+        ) {
+            (Some(context), _) => ExecSecurityContext::SELinux(context.to_string()),
+            (_, Some(profile)) => ExecSecurityContext::AppArmor(profile.to_string()),
+            _ => return Ok(None),
+        };
+        let label = context.label();
+        // The kernel reads the label up to the first null byte or line ending.
+        if label.is_empty() || label.chars().any(char::is_control) {
+            return Err(Error::SecurityContextArgument(label.to_string()));
+        }
+        Ok(Some(context))
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            ExecSecurityContext::SELinux(context) => context,
+            ExecSecurityContext::AppArmor(profile) => profile,
+        }
+    }
+
+    // Returns the attribute files to try, in order, and what to write into them.
+    fn attr(&self) -> (&'static [&'static str], String) {
+        match self {
+            ExecSecurityContext::SELinux(context) => (&SELINUX_EXEC_ATTRS, context.clone()),
+            ExecSecurityContext::AppArmor(profile) => {
+                (&APPARMOR_EXEC_ATTRS, format!("exec {}", profile))
+            }
+        }
+    }
+
+    /// Makes the next `exec()` of the calling thread, and of the processes it clones, transition
+    /// to the context.
+    pub fn set_on_exec(&self) -> Result<()> {
+        let (paths, value) = self.attr();
+        let mut last_err = io::Error::from_raw_os_error(libc::ENOENT);
+        for path in paths {
+            let mut file = match OpenOptions::new().write(true).open(path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    last_err = e;
+                    continue;
+                }
+                Err(e) => return Err(Error::SecurityContext(self.label().to_string(), e)),
+            };
+            // The kernel only accepts the whole attribute in a single write.
+            return file
+                .write_all(value.as_bytes())
+                .map_err(|e| Error::SecurityContext(self.label().to_string(), e));
+        }
+        Err(Error::SecurityContext(self.label().to_string(), last_err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_arg_parser;
+
+    fn parse_args(extra: &[&str]) -> Result<Option<ExecSecurityContext>> {
+        let arg_parser = build_arg_parser();
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec: Vec<String> = vec![
+            "--binary-name",
+            "--id",
+            "bd65600d-8669-4903-8a14-af88203add38",
+            "--exec-file",
+            "/proc/cpuinfo",
+            "--uid",
+            "0",
+            "--gid",
+            "0",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+        args.parse(&arg_vec).unwrap();
+        ExecSecurityContext::from_args(&args)
+    }
+
+    #[test]
+    fn test_security_context_from_args() {
+        assert_eq!(parse_args(&[]).unwrap(), None);
+
+        let context = parse_args(&["--selinux-context", "system_u:system_r:firecracker_t:s0:c1"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            context.attr(),
+            (
+                &SELINUX_EXEC_ATTRS[..],
+                "system_u:system_r:firecracker_t:s0:c1".to_string()
+            )
+        );
+
+        let context = parse_args(&["--apparmor-profile", "firecracker-vm1"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            context.attr(),
+            (&APPARMOR_EXEC_ATTRS[..], "exec firecracker-vm1".to_string())
+        );
+
+        assert!(parse_args(&["--selinux-context", ""]).is_err());
+        assert!(parse_args(&["--apparmor-profile", "vm1\nchangeprofile unconfined"]).is_err());
+    }
+}
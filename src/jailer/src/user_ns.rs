@@ -0,0 +1,277 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! User namespace the jailed process is launched in.
+//!
+//! The namespace is created when cloning the jailed process, and its id mappings are written by
+//! the jailer, which keeps the privileges needed to map arbitrary host ids.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+
+use utils::arg_parser;
+use utils::syscall::SyscallReturnCode;
+
+use crate::{Error, Result};
+
+/// Mapping of a range of ids of the user namespace to a range of host ids.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdMapping {
+    inside: u32,
+    outside: u32,
+    count: u32,
+}
+
+impl IdMapping {
+    // Parses a mapping following the <inside_id>:<outside_id>:<count> format.
+    fn parse(arg: &str) -> Result<Self> {
+        let invalid = || Error::IdMapArgument(arg.to_string());
+        let v = arg
+            .split(':')
+            .map(|id| id.parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<u32>>>()?;
+        if v.len() != 3
+            || v[2] == 0
+            || v[0].checked_add(v[2] - 1).is_none()
+            || v[1].checked_add(v[2] - 1).is_none()
+        {
+            return Err(invalid());
+        }
+        Ok(IdMapping {
+            inside: v[0],
+            outside: v[1],
+            count: v[2],
+        })
+    }
+
+    fn to_outside(self, id: u32) -> Option<u32> {
+        if id >= self.inside && id - self.inside < self.count {
+            Some(self.outside + (id - self.inside))
+        } else {
+            None
+        }
+    }
+}
+
+/// User namespace of the jailed process, and the ids it runs with in it.
+#[derive(Debug, PartialEq)]
+pub struct UserNsConfig {
+    /// User id of the jailed process, in the namespace.
+    pub uid: u32,
+    /// Group id of the jailed process, in the namespace.
+    pub gid: u32,
+    uid_map: Vec<IdMapping>,
+    gid_map: Vec<IdMapping>,
+}
+
+impl UserNsConfig {
+    /// Builds the configuration from the jailer arguments, if a user namespace is requested.
+    pub fn from_args(
+        arguments: &arg_parser::Arguments,
+        uid: u32,
+        gid: u32,
+    ) -> Result<Option<Self>> {
+        let (uid_map, gid_map) = match (
+            arguments.multiple_values("uid-map"),
+            arguments.multiple_values("gid-map"),
+        ) {
+            (Some(uid_map), Some(gid_map)) => (uid_map, gid_map),
+            _ => return Ok(None),
+        };
+
+        let config = UserNsConfig {
+            uid,
+            gid,
+            uid_map: uid_map
+                .iter()
+                .map(|arg| IdMapping::parse(arg))
+                .collect::<Result<_>>()?,
+            gid_map: gid_map
+                .iter()
+                .map(|arg| IdMapping::parse(arg))
+                .collect::<Result<_>>()?,
+        };
+        // The jailed process must be able to switch to its ids.
+        config.host_uid()?;
+        config.host_gid()?;
+        Ok(Some(config))
+    }
+
+    /// Returns the host user id the one of the jailed process maps to.
+    pub fn host_uid(&self) -> Result<u32> {
+        map_to_host(&self.uid_map, self.uid).ok_or(Error::UnmappedId(self.uid))
+    }
+
+    /// Returns the host group id the one of the jailed process maps to.
+    pub fn host_gid(&self) -> Result<u32> {
+        map_to_host(&self.gid_map, self.gid).ok_or(Error::UnmappedId(self.gid))
+    }
+
+    /// Writes the id mappings of the user namespace of the process `pid`.
+    ///
+    /// `proc_dir` is a handle to `/proc`, which is no longer reachable from the jail.
+    pub fn write_maps(&self, proc_dir: &File, pid: i32) -> Result<()> {
+        write_map(proc_dir, pid, "uid_map", &self.uid_map)?;
+        write_map(proc_dir, pid, "gid_map", &self.gid_map)
+    }
+}
+
+fn map_to_host(map: &[IdMapping], id: u32) -> Option<u32> {
+    map.iter().find_map(|mapping| mapping.to_outside(id))
+}
+
+fn write_map(proc_dir: &File, pid: i32, file_name: &str, map: &[IdMapping]) -> Result<()> {
+    let path = format!("{}/{}", pid, file_name);
+    let path_cstr = CString::new(path.clone()).map_err(Error::CStringParsing)?;
+    // Safe because the dir fd and the path are valid, and we check the result.
+    let fd = SyscallReturnCode(unsafe {
+        libc::openat(
+            proc_dir.as_raw_fd(),
+            path_cstr.as_ptr(),
+            libc::O_WRONLY | libc::O_CLOEXEC,
+        )
+    })
+    .into_result()
+    .map_err(|e| Error::FileOpen(PathBuf::from("/proc").join(&path), e))?;
+    // Safe because the fd was just opened and nothing else owns it.
+    let mut file = unsafe { File::from_raw_fd(fd) };
+
+    let contents: String = map
+        .iter()
+        .map(|m| format!("{} {} {}\n", m.inside, m.outside, m.count))
+        .collect();
+    // The kernel only accepts the whole map in a single write.
+    file.write_all(contents.as_bytes())
+        .map_err(|e| Error::Write(PathBuf::from("/proc").join(&path), e))
+}
+
+/// Pipe through which the jailer lets the jailed process go on once its user namespace is set
+/// up.
+pub struct UserNsSync {
+    read: File,
+    write: File,
+}
+
+impl UserNsSync {
+    pub fn new() -> Result<Self> {
+        let mut fds = [0; 2];
+        // Safe because the array is large enough for the two fds, and we check the result.
+        SyscallReturnCode(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })
+            .into_empty_result()
+            .map_err(Error::UserNsSync)?;
+        // Safe because the fds were just created and nothing else owns them.
+        Ok(UserNsSync {
+            read: unsafe { File::from_raw_fd(fds[0]) },
+            write: unsafe { File::from_raw_fd(fds[1]) },
+        })
+    }
+
+    /// Called by the jailed process, blocks until the jailer is done with the namespace.
+    pub fn wait(self) -> Result<()> {
+        let UserNsSync { mut read, write } = self;
+        // Otherwise, we would keep the pipe open if the jailer died.
+        drop(write);
+        let mut buf = [0u8; 1];
+        match read.read(&mut buf).map_err(Error::UserNsSync)? {
+            1 => Ok(()),
+            // The jailer closed the pipe without setting up the namespace.
+            _ => Err(Error::UserNsSync(std::io::Error::from_raw_os_error(
+                libc::ECHILD,
+            ))),
+        }
+    }
+
+    /// Called by the jailer, lets the jailed process go on.
+    pub fn notify(self) -> Result<()> {
+        let UserNsSync { read, mut write } = self;
+        drop(read);
+        write.write_all(&[1]).map_err(Error::UserNsSync)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_arg_parser;
+
+    fn parse_args(extra: &[&str]) -> Result<Option<UserNsConfig>> {
+        let arg_parser = build_arg_parser();
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec: Vec<String> = vec![
+            "--binary-name",
+            "--id",
+            "bd65600d-8669-4903-8a14-af88203add38",
+            "--exec-file",
+            "/proc/cpuinfo",
+            "--uid",
+            "0",
+            "--gid",
+            "0",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        arg_vec.extend(extra.iter().map(|arg| arg.to_string()));
+        args.parse(&arg_vec).unwrap();
+        UserNsConfig::from_args(&args, 0, 0)
+    }
+
+    #[test]
+    fn test_id_mapping() {
+        let mapping = IdMapping::parse("0:100000:65536").unwrap();
+        assert_eq!(mapping.to_outside(0), Some(100_000));
+        assert_eq!(mapping.to_outside(65535), Some(165_535));
+        assert_eq!(mapping.to_outside(65536), None);
+
+        for invalid in &[
+            "",
+            "0:100000",
+            "0:100000:1:1",
+            "0:foo:1",
+            "-1:100000:1",
+            "0:100000:0",
+            "4294967295:0:2",
+        ] {
+            assert!(IdMapping::parse(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_user_ns_config_from_args() {
+        assert_eq!(parse_args(&[]).unwrap(), None);
+
+        let config = parse_args(&[
+            "--uid-map",
+            "0:100000:1000",
+            "--uid-map",
+            "1000:1000:1",
+            "--gid-map",
+            "0:200000:1",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.host_uid().unwrap(), 100_000);
+        assert_eq!(config.host_gid().unwrap(), 200_000);
+        assert_eq!(map_to_host(&config.uid_map, 1000), Some(1000));
+
+        // The ids of the jailed process are not mapped.
+        assert!(parse_args(&["--uid-map", "1:100000:1", "--gid-map", "0:200000:1"]).is_err());
+        assert!(parse_args(&["--uid-map", "0:100000:1", "--gid-map", "1:200000:1"]).is_err());
+        assert!(parse_args(&["--uid-map", "0:100000", "--gid-map", "0:200000:1"]).is_err());
+    }
+
+    #[test]
+    fn test_user_ns_sync() {
+        let sync = UserNsSync::new().unwrap();
+        // Stands for the copy of the pipe the jailer keeps after cloning.
+        let mut write = sync.write.try_clone().unwrap();
+        write.write_all(&[1]).unwrap();
+        assert!(sync.wait().is_ok());
+
+        // The pipe is closed without a notification.
+        assert!(UserNsSync::new().unwrap().wait().is_err());
+    }
+}
@@ -8,6 +8,7 @@
 mod init;
 mod logger;
 mod metrics;
+mod prometheus;
 
 use std::sync::LockResult;
 
@@ -18,8 +19,9 @@ pub use crate::logger::{LoggerError, LOGGER};
 #[cfg(target_arch = "aarch64")]
 pub use crate::metrics::RTCDeviceMetrics;
 pub use crate::metrics::{
-    IncMetric, MetricsError, ProcessTimeReporter, SerialDeviceMetrics, SharedIncMetric,
-    SharedStoreMetric, StoreMetric, METRICS,
+    BlockLatencyMetrics, CpuUsageMetrics, IncMetric, KeyedIncMetric, LatencyHistogram,
+    MetricsError, ProcessTimeReporter, SerialDeviceMetrics, SharedIncMetric, SharedStoreMetric,
+    StoreMetric, ThreadCategory, METRICS,
 };
 
 /// Prefix to be used in log lines for functions/modules in Firecracker
@@ -60,19 +60,28 @@
 //!   (this could be a concern, I guess).
 //! If if turns out this approach is not really what we want, it's pretty easy to resort to
 //! something else, while working behind the same interface.
+//!
+//! # Scraping
+//! The metrics can also be rendered in the Prometheus text exposition format with
+//! `Metrics::scrape`. Since scrapers expect counters to only go up, a scrape reports the totals
+//! since the process started, and leaves the values of the next flush untouched.
 
-use std::fmt;
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::ops::Deref;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::{cmp, fmt};
 
 use lazy_static::lazy_static;
+use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
 #[cfg(target_arch = "aarch64")]
 use vm_superio::rtc_pl031::RtcEvents;
 
 use super::extract_guard;
+use crate::prometheus::{self, Sample};
 #[cfg(target_arch = "aarch64")]
 use crate::warn;
 
@@ -81,6 +90,15 @@ lazy_static! {
     pub static ref METRICS: Metrics<FirecrackerMetrics> = Metrics::new(FirecrackerMetrics::default());
 }
 
+thread_local! {
+    // Set while `Metrics::scrape` serializes the metrics on this thread.
+    static SCRAPING: Cell<bool> = Cell::new(false);
+}
+
+fn is_scraping() -> bool {
+    SCRAPING.with(Cell::get)
+}
+
 /// Metrics system.
 // All member fields have types which are Sync, and exhibit interior mutability, so
 // we can call operations on metrics using a non-mut static global variable.
@@ -178,6 +196,20 @@ impl<T: Serialize> Metrics<T> {
         // metrics were not written.
         Ok(false)
     }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    ///
+    /// The counters and histograms hold the totals since the process started, and aren't reset,
+    /// so scraping doesn't change what the next `write` reports. The metrics don't need to be
+    /// initialized.
+    pub fn scrape(&self) -> Result<String, MetricsError> {
+        SCRAPING.with(|scraping| scraping.set(true));
+        let value = serde_json::to_value(&self.app_metrics);
+        SCRAPING.with(|scraping| scraping.set(false));
+        value
+            .map(|value| prometheus::render(&value))
+            .map_err(|e| MetricsError::Serde(e.to_string()))
+    }
 }
 
 impl<T: Serialize> Deref for Metrics<T> {
@@ -286,6 +318,9 @@ impl Serialize for SharedIncMetric {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         // There's no serializer.serialize_usize() for some reason :(
         let snapshot = self.0.load(Ordering::Relaxed);
+        if is_scraping() {
+            return Sample::Counter(snapshot as u64).serialize(serializer);
+        }
         let res = serializer.serialize_u64(snapshot as u64 - self.1.load(Ordering::Relaxed) as u64);
 
         if res.is_ok() {
@@ -351,6 +386,328 @@ impl ProcessTimeReporter {
     }
 }
 
+/// Kinds of Firecracker threads whose CPU time is accounted for separately.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThreadCategory {
+    /// The API server thread.
+    Api,
+    /// The VMM thread, which also runs the device emulation.
+    Vmm,
+    /// The thread running the vCPU with the given index.
+    Vcpu(u8),
+    /// The worker thread of the net device with the given index.
+    NetWorker(u8),
+}
+
+// CPU clock of a registered thread.
+struct ThreadCpuClock {
+    category: ThreadCategory,
+    clock_id: libc::clockid_t,
+    // Last sampled CPU time, which is still reported after the thread exits.
+    cpu_time_us: u64,
+}
+
+impl ThreadCpuClock {
+    fn sample(&mut self) -> u64 {
+        let mut time_struct = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // Safe because the parameters are valid. The call fails once the thread is gone, in
+        // which case we keep the last sample.
+        if unsafe { libc::clock_gettime(self.clock_id, &mut time_struct) } == 0 {
+            self.cpu_time_us =
+                time_struct.tv_sec as u64 * 1_000_000 + time_struct.tv_nsec as u64 / 1_000;
+        }
+        self.cpu_time_us
+    }
+}
+
+/// Host CPU time consumed by the Firecracker threads, split by thread category.
+///
+/// Threads register themselves once, when they start. Their CPU clocks are only read when the
+/// metrics are flushed, so the accounting adds no overhead to the running threads. All the values
+/// are cumulative, in microseconds.
+#[derive(Default)]
+pub struct CpuUsageMetrics {
+    threads: Mutex<Vec<ThreadCpuClock>>,
+}
+
+impl CpuUsageMetrics {
+    /// Registers the calling thread as belonging to `category`, replacing any thread previously
+    /// registered under the same category.
+    pub fn register_current_thread(&self, category: ThreadCategory) {
+        // This is safe since `gettid` takes no arguments and can't fail.
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::pid_t;
+        // Same encoding as `MAKE_THREAD_CPUCLOCK(tid, CPUCLOCK_SCHED)` in the kernel, which is
+        // what `pthread_getcpuclockid()` returns. Unlike `CLOCK_THREAD_CPUTIME_ID`, this clock can
+        // be read from any thread of the process.
+        let clock_id = ((!tid) << 3) | 6;
+
+        let mut threads = extract_guard(self.threads.lock());
+        threads.retain(|thread| thread.category != category);
+        threads.push(ThreadCpuClock {
+            category,
+            clock_id,
+            cpu_time_us: 0,
+        });
+    }
+}
+
+impl Serialize for CpuUsageMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut api_us = 0;
+        let mut vmm_us = 0;
+        let mut vcpu_us = 0;
+        let mut per_vcpu_us = BTreeMap::new();
+        let mut net_worker_us = 0;
+        for thread in extract_guard(self.threads.lock()).iter_mut() {
+            let cpu_time_us = thread.sample();
+            match thread.category {
+                ThreadCategory::Api => api_us += cpu_time_us,
+                ThreadCategory::Vmm => vmm_us += cpu_time_us,
+                ThreadCategory::Vcpu(index) => {
+                    vcpu_us += cpu_time_us;
+                    per_vcpu_us.insert(index, cpu_time_us);
+                }
+                ThreadCategory::NetWorker(_) => net_worker_us += cpu_time_us,
+            }
+        }
+
+        if is_scraping() {
+            let mut map = serializer.serialize_map(Some(5))?;
+            map.serialize_entry("api_us", &Sample::Counter(api_us))?;
+            map.serialize_entry("vmm_us", &Sample::Counter(vmm_us))?;
+            map.serialize_entry("vcpu_us", &Sample::Counter(vcpu_us))?;
+            let per_vcpu_us = per_vcpu_us
+                .into_iter()
+                .map(|(index, us)| (index.to_string(), Sample::Counter(us)))
+                .collect();
+            map.serialize_entry(
+                "per_vcpu_us",
+                &Sample::Labeled {
+                    label: "vcpu",
+                    values: per_vcpu_us,
+                },
+            )?;
+            map.serialize_entry("net_worker_us", &Sample::Counter(net_worker_us))?;
+            return map.end();
+        }
+
+        let mut map = serializer.serialize_map(Some(5))?;
+        map.serialize_entry("api_us", &api_us)?;
+        map.serialize_entry("vmm_us", &vmm_us)?;
+        map.serialize_entry("vcpu_us", &vcpu_us)?;
+        // Ordered by vCPU index.
+        map.serialize_entry("per_vcpu_us", &per_vcpu_us.values().collect::<Vec<_>>())?;
+        map.serialize_entry("net_worker_us", &net_worker_us)?;
+        map.end()
+    }
+}
+
+/// Upper bounds, in microseconds, of the buckets of the latency histograms. The last bucket holds
+/// the samples above the last bound.
+pub const LATENCY_BUCKETS_US: [u64; 15] = [
+    25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000,
+];
+
+/// Histogram of latencies, which gets reset whenever it is flushed, like `SharedIncMetric`.
+// As for `SharedIncMetric`, the buckets hold the totals, and the flushed totals are kept to
+// compute the increments.
+#[derive(Default)]
+pub struct LatencyHistogram {
+    buckets: [AtomicUsize; LATENCY_BUCKETS_US.len() + 1],
+    flushed: [AtomicUsize; LATENCY_BUCKETS_US.len() + 1],
+    sum_us: AtomicUsize,
+}
+
+impl LatencyHistogram {
+    /// Records a sample, in microseconds.
+    pub fn record(&self, latency_us: u64) {
+        let index = LATENCY_BUCKETS_US
+            .iter()
+            .position(|&bound| latency_us <= bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(latency_us as usize, Ordering::Relaxed);
+    }
+
+    /// Number of samples recorded since the last flush.
+    pub fn count(&self) -> usize {
+        self.buckets
+            .iter()
+            .zip(self.flushed.iter())
+            .map(|(bucket, flushed)| {
+                bucket.load(Ordering::Relaxed) - flushed.load(Ordering::Relaxed)
+            })
+            .sum()
+    }
+
+    fn scraped(&self) -> Sample {
+        Sample::Histogram {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+            sum: self.sum_us.load(Ordering::Relaxed),
+        }
+    }
+
+    // The upper bound of the bucket holding the `percentile`, which is the last bound for the
+    // samples above it, or 0 without samples.
+    fn percentile_us(counts: &[usize], percentile: usize) -> u64 {
+        let total: usize = counts.iter().sum();
+        // The rank of the sample, rounded up.
+        let rank = (total * percentile + 99) / 100;
+        let mut seen = 0;
+        for (index, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank && seen > 0 {
+                return LATENCY_BUCKETS_US[cmp::min(index, LATENCY_BUCKETS_US.len() - 1)];
+            }
+        }
+        0
+    }
+}
+
+impl Serialize for LatencyHistogram {
+    /// Resets the histogram, see `SharedIncMetric`.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if is_scraping() {
+            return self.scraped().serialize(serializer);
+        }
+        let counts: Vec<usize> = self
+            .buckets
+            .iter()
+            .zip(self.flushed.iter())
+            .map(|(bucket, flushed)| {
+                let snapshot = bucket.load(Ordering::Relaxed);
+                snapshot - flushed.swap(snapshot, Ordering::Relaxed)
+            })
+            .collect();
+
+        let mut map = serializer.serialize_map(Some(5))?;
+        map.serialize_entry("count", &counts.iter().sum::<usize>())?;
+        map.serialize_entry("p50_us", &Self::percentile_us(&counts, 50))?;
+        map.serialize_entry("p90_us", &Self::percentile_us(&counts, 90))?;
+        map.serialize_entry("p99_us", &Self::percentile_us(&counts, 99))?;
+        map.serialize_entry("buckets_us", &LatencyBuckets(&counts))?;
+        map.end()
+    }
+}
+
+// The counts of the buckets of a histogram, keyed by their upper bound in increasing order.
+struct LatencyBuckets<'a>(&'a [usize]);
+
+impl Serialize for LatencyBuckets<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (index, count) in self.0.iter().enumerate() {
+            match LATENCY_BUCKETS_US.get(index) {
+                Some(bound) => map.serialize_entry(&format!("le_{}", bound), count)?,
+                None => map.serialize_entry("inf", count)?,
+            }
+        }
+        map.end()
+    }
+}
+
+/// Service time of the requests of each drive, keyed by drive ID.
+///
+/// The devices register themselves when they are created, and only keep recording in the
+/// histogram they got back. The histograms of the drives which are gone are dropped once flushed.
+#[derive(Default)]
+pub struct BlockLatencyMetrics {
+    drives: Mutex<BTreeMap<String, Arc<LatencyHistogram>>>,
+}
+
+impl BlockLatencyMetrics {
+    /// Returns the histogram of the drive `drive_id`, which is shared by the devices created
+    /// with the same ID.
+    pub fn register(&self, drive_id: &str) -> Arc<LatencyHistogram> {
+        extract_guard(self.drives.lock())
+            .entry(drive_id.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+impl Serialize for BlockLatencyMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut drives = extract_guard(self.drives.lock());
+        if is_scraping() {
+            let values = drives
+                .iter()
+                .map(|(drive_id, histogram)| (drive_id.clone(), histogram.scraped()))
+                .collect();
+            return Sample::Labeled {
+                label: "drive_id",
+                values,
+            }
+            .serialize(serializer);
+        }
+        let res = drives.serialize(serializer);
+        // The map holds the last reference to the histograms of the drives which are gone.
+        drives.retain(|_, histogram| Arc::strong_count(histogram) > 1);
+        res
+    }
+}
+
+/// Maximum number of keys a `KeyedIncMetric` keeps track of between two flushes.
+pub const MAX_METRIC_KEYS: usize = 64;
+
+/// Counters keyed by string, which get reset whenever they are flushed, like `SharedIncMetric`.
+///
+/// Since the keys may come from untrusted sources, at most `MAX_METRIC_KEYS` of them are reported
+/// per flush. The increments of the keys beyond that are accumulated under `other`.
+#[derive(Default)]
+pub struct KeyedIncMetric {
+    counters: Mutex<BTreeMap<String, usize>>,
+}
+
+impl KeyedIncMetric {
+    const OTHER_KEY: &'static str = "other";
+
+    /// Adds 1 to the counter of `key`.
+    pub fn inc(&self, key: &str) {
+        let mut counters = extract_guard(self.counters.lock());
+        if let Some(count) = counters.get_mut(key) {
+            *count += 1;
+            return;
+        }
+
+        let key = if counters.len() < MAX_METRIC_KEYS {
+            key
+        } else {
+            Self::OTHER_KEY
+        };
+        *counters.entry(key.to_string()).or_default() += 1;
+    }
+
+    /// Returns the counter of `key` since the last flush.
+    pub fn count(&self, key: &str) -> usize {
+        extract_guard(self.counters.lock())
+            .get(key)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Serialize for KeyedIncMetric {
+    /// Resets the counters, see `SharedIncMetric`.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // The keys may come from the guest, so they aren't turned into labels of the scraped
+        // metrics, whose number would then be up to the guest.
+        if is_scraping() {
+            return serializer.serialize_none();
+        }
+        std::mem::take(&mut *extract_guard(self.counters.lock())).serialize(serializer)
+    }
+}
+
 // The following structs are used to define a certain organization for the set of metrics we
 // are interested in. Whenever the name of a field differs from its ideal textual representation
 // in the serialized form, we can use the #[serde(rename = "name")] attribute to, well, rename it.
@@ -366,17 +723,35 @@ pub struct ApiServerMetrics {
     pub sync_response_fails: SharedIncMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedIncMetric,
+    /// Number of requests which could not be written to the audit log.
+    pub audit_log_fails: SharedIncMetric,
+    /// Number of responses replayed for the retries of requests with an `Idempotency-Key`.
+    pub idempotent_replays_count: SharedIncMetric,
+    /// Number of requests rejected for exceeding the API request rate limit.
+    pub throttled_requests_count: SharedIncMetric,
+    /// Number of vsock API connections rejected for exceeding the connection limit.
+    pub vsock_rejected_connections_count: SharedIncMetric,
 }
 
 /// Metrics specific to GET API Requests for counting user triggered actions and/or failures.
 #[derive(Default, Serialize)]
 pub struct GetRequestsMetrics {
+    /// Number of GETs for polling the lifecycle events of the microVM.
+    pub events_count: SharedIncMetric,
+    /// Number of GETs for checking the health or readiness of the VMM.
+    pub health_count: SharedIncMetric,
     /// Number of GETs for getting information on the instance.
     pub instance_info_count: SharedIncMetric,
     /// Number of GETs for getting status on attaching machine configuration.
     pub machine_cfg_count: SharedIncMetric,
+    /// Number of GETs for scraping the metrics.
+    pub metrics_count: SharedIncMetric,
     /// Number of GETs for getting mmds.
     pub mmds_count: SharedIncMetric,
+    /// Number of GETs for getting the status of an asynchronous operation.
+    pub operations_count: SharedIncMetric,
+    /// Number of GETs for describing a snapshot.
+    pub snapshot_info_count: SharedIncMetric,
     /// Number of GETs for getting the VMM version.
     pub vmm_version_count: SharedIncMetric,
 }
@@ -404,10 +779,18 @@ pub struct PutRequestsMetrics {
     pub machine_cfg_count: SharedIncMetric,
     /// Number of failures in configuring the machine.
     pub machine_cfg_fails: SharedIncMetric,
+    /// Number of PUTs for configuring the hot-pluggable memory.
+    pub memory_hotplug_count: SharedIncMetric,
+    /// Number of failures in configuring the hot-pluggable memory.
+    pub memory_hotplug_fails: SharedIncMetric,
     /// Number of PUTs for initializing the metrics system.
     pub metrics_count: SharedIncMetric,
     /// Number of failures in initializing the metrics system.
     pub metrics_fails: SharedIncMetric,
+    /// Number of PUTs for migrating a microVM.
+    pub migration_count: SharedIncMetric,
+    /// Number of failures in migrating a microVM.
+    pub migration_fails: SharedIncMetric,
     /// Number of PUTs for creating a new network interface.
     pub network_count: SharedIncMetric,
     /// Number of failures in creating a new network interface.
@@ -416,6 +799,18 @@ pub struct PutRequestsMetrics {
     pub mmds_count: SharedIncMetric,
     /// Number of failures in creating a new mmds.
     pub mmds_fails: SharedIncMetric,
+    /// Number of PUTs for attaching a pmem device.
+    pub pmem_count: SharedIncMetric,
+    /// Number of failures in attaching a pmem device.
+    pub pmem_fails: SharedIncMetric,
+    /// Number of PUTs for creating or updating a rate limiter group.
+    pub rate_limiter_group_count: SharedIncMetric,
+    /// Number of failures in creating or updating a rate limiter group.
+    pub rate_limiter_group_fails: SharedIncMetric,
+    /// Number of PUTs for replacing the whole microVM configuration.
+    pub vm_config_count: SharedIncMetric,
+    /// Number of failures in replacing the whole microVM configuration.
+    pub vm_config_fails: SharedIncMetric,
     /// Number of PUTs for creating a vsock device.
     pub vsock_count: SharedIncMetric,
     /// Number of failures in creating a vsock device.
@@ -437,12 +832,29 @@ pub struct PatchRequestsMetrics {
     pub machine_cfg_count: SharedIncMetric,
     /// Number of failures in configuring the machine.
     pub machine_cfg_fails: SharedIncMetric,
+    /// Number of PATCHs for resizing the plugged memory.
+    pub memory_hotplug_count: SharedIncMetric,
+    /// Number of failures in resizing the plugged memory.
+    pub memory_hotplug_fails: SharedIncMetric,
     /// Number of tries to PATCH an mmds.
     pub mmds_count: SharedIncMetric,
     /// Number of failures in PATCHing an mmds.
     pub mmds_fails: SharedIncMetric,
 }
 
+/// Metrics specific to DELETE API Requests for counting user triggered actions and/or failures.
+#[derive(Default, Serialize)]
+pub struct DeleteRequestsMetrics {
+    /// Number of tries to DELETE a block device.
+    pub drive_count: SharedIncMetric,
+    /// Number of failures in DELETEing a block device.
+    pub drive_fails: SharedIncMetric,
+    /// Number of tries to DELETE a net device.
+    pub network_count: SharedIncMetric,
+    /// Number of failures in DELETEing a net device.
+    pub network_fails: SharedIncMetric,
+}
+
 /// Metrics related to deprecated user-facing API calls.
 #[derive(Default, Serialize)]
 pub struct DeprecatedApiMetrics {
@@ -467,6 +879,14 @@ pub struct BalloonDeviceMetrics {
     pub deflate_count: SharedIncMetric,
     /// Number of times when handling events on a balloon device failed.
     pub event_fails: SharedIncMetric,
+    /// Number of free page reports from the driver.
+    pub free_page_report_count: SharedIncMetric,
+    /// Number of bytes of reported free pages that were released.
+    pub free_page_report_freed: SharedIncMetric,
+    /// Number of reported free page ranges that could not be released.
+    pub free_page_report_fails: SharedIncMetric,
+    /// Number of balloon target size updates made by the policy.
+    pub policy_updates: SharedIncMetric,
 }
 
 /// Block Device associated metrics.
@@ -502,11 +922,17 @@ pub struct BlockDeviceMetrics {
     pub read_count: SharedIncMetric,
     /// Number of successful write operations.
     pub write_count: SharedIncMetric,
+    /// Number of successful discard operations.
+    pub discard_count: SharedIncMetric,
+    /// Number of successful write zeroes operations.
+    pub write_zeroes_count: SharedIncMetric,
     /// Number of rate limiter throttling events.
     pub rate_limiter_throttled_events: SharedIncMetric,
     /// Number of virtio events throttled because of the IO engine.
     /// This happens when the io_uring submission queue is full.
     pub io_engine_throttled_events: SharedIncMetric,
+    /// Number of requests left out of the traces because of their cap on records per second.
+    pub trace_dropped_count: SharedIncMetric,
 }
 
 /// Metrics specific to the i8042 device.
@@ -564,6 +990,12 @@ pub struct MmdsMetrics {
     pub connections_created: SharedIncMetric,
     /// The number of connections cleaned up by the MMDS TCP handler.
     pub connections_destroyed: SharedIncMetric,
+    /// The number of successful GET requests, keyed by the requested path.
+    pub path_hits: KeyedIncMetric,
+    /// The number of GET requests for paths missing from the data store.
+    pub not_found: SharedIncMetric,
+    /// The number of requests rejected because of a missing or invalid session token.
+    pub token_failures: SharedIncMetric,
 }
 
 /// Network-related metrics.
@@ -599,6 +1031,10 @@ pub struct NetDeviceMetrics {
     pub rx_fails: SharedIncMetric,
     /// Number of successful read operations while receiving data.
     pub rx_count: SharedIncMetric,
+    /// Number of received frames dropped because the guest filtered them out.
+    pub rx_filtered_frames: SharedIncMetric,
+    /// Number of received frames dropped by the filter rules of the device.
+    pub rx_l3_filtered_frames: SharedIncMetric,
     /// Number of times reading from TAP failed.
     pub tap_read_fails: SharedIncMetric,
     /// Number of times writing to TAP failed.
@@ -623,6 +1059,50 @@ pub struct NetDeviceMetrics {
     pub tx_rate_limiter_throttled: SharedIncMetric,
     /// Number of packets with a spoofed mac, sent by the guest.
     pub tx_spoofed_mac_count: SharedIncMetric,
+    /// Number of transmitted frames dropped by the filter rules of the device.
+    pub tx_l3_filtered_frames: SharedIncMetric,
+}
+
+/// Virtio-mem device associated metrics.
+#[derive(Default, Serialize)]
+pub struct MemoryHotplugDeviceMetrics {
+    /// Number of times when activate failed on the virtio-mem device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of times when interacting with the space config of the virtio-mem device failed.
+    pub cfg_fails: SharedIncMetric,
+    /// Number of times when handling events on the virtio-mem device failed.
+    pub event_fails: SharedIncMetric,
+    /// Number of invalid requests received by the virtio-mem device.
+    pub execute_fails: SharedIncMetric,
+    /// Number of plug requests accepted by the virtio-mem device.
+    pub plug_count: SharedIncMetric,
+    /// Number of plug requests rejected because they exceeded the requested size.
+    pub plug_rejected: SharedIncMetric,
+    /// Number of events triggered on the queue of the virtio-mem device.
+    pub queue_event_count: SharedIncMetric,
+    /// Number of unplug requests served by the virtio-mem device.
+    pub unplug_count: SharedIncMetric,
+    /// Number of failures in discarding the memory of unplugged blocks.
+    pub unplug_fails: SharedIncMetric,
+}
+
+/// Pmem Device associated metrics.
+#[derive(Default, Serialize)]
+pub struct PmemDeviceMetrics {
+    /// Number of times when activate failed on a pmem device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of times when interacting with the space config of a pmem device failed.
+    pub cfg_fails: SharedIncMetric,
+    /// Number of times when handling events on a pmem device failed.
+    pub event_fails: SharedIncMetric,
+    /// Number of invalid requests received for this pmem device.
+    pub execute_fails: SharedIncMetric,
+    /// Number of flush requests received by this pmem device.
+    pub flush_count: SharedIncMetric,
+    /// Number of failures in flushing the backing file of this pmem device.
+    pub flush_fails: SharedIncMetric,
+    /// Number of events triggered on the queue of this pmem device.
+    pub queue_event_count: SharedIncMetric,
 }
 
 /// Performance metrics related for the moment only to snapshots.
@@ -641,20 +1121,32 @@ pub struct PerformanceMetrics {
     pub diff_create_snapshot: SharedStoreMetric,
     /// Measures the snapshot load time, at the API (user) level, in microseconds.
     pub load_snapshot: SharedStoreMetric,
+    /// Measures the snapshot chain merge time, at the API (user) level, in microseconds.
+    pub merge_snapshot: SharedStoreMetric,
     /// Measures the microVM pausing duration, at the API (user) level, in microseconds.
     pub pause_vm: SharedStoreMetric,
     /// Measures the microVM resuming duration, at the API (user) level, in microseconds.
     pub resume_vm: SharedStoreMetric,
+    /// Measures the outgoing migration time, at the API (user) level, in microseconds.
+    pub send_migration: SharedStoreMetric,
+    /// Measures the incoming migration time, at the API (user) level, in microseconds.
+    pub receive_migration: SharedStoreMetric,
     /// Measures the snapshot full create time, at the VMM level, in microseconds.
     pub vmm_full_create_snapshot: SharedStoreMetric,
     /// Measures the snapshot diff create time, at the VMM level, in microseconds.
     pub vmm_diff_create_snapshot: SharedStoreMetric,
     /// Measures the snapshot load time, at the VMM level, in microseconds.
     pub vmm_load_snapshot: SharedStoreMetric,
+    /// Measures the snapshot chain merge time, at the VMM level, in microseconds.
+    pub vmm_merge_snapshot: SharedStoreMetric,
     /// Measures the microVM pausing duration, at the VMM level, in microseconds.
     pub vmm_pause_vm: SharedStoreMetric,
     /// Measures the microVM resuming duration, at the VMM level, in microseconds.
     pub vmm_resume_vm: SharedStoreMetric,
+    /// Measures the outgoing migration time, at the VMM level, in microseconds.
+    pub vmm_send_migration: SharedStoreMetric,
+    /// Measures the incoming migration time, at the VMM level, in microseconds.
+    pub vmm_receive_migration: SharedStoreMetric,
 }
 
 /// Metrics specific to the RTC device.
@@ -689,6 +1181,14 @@ impl RtcEvents for RTCDeviceMetrics {
 pub struct SeccompMetrics {
     /// Number of errors inside the seccomp filtering.
     pub num_faults: SharedStoreMetric,
+    /// Number of syscalls let through in log mode, which the filters would have blocked, as
+    /// read from the kernel log. Counting them requires `/dev/kmsg` in the jail, along with
+    /// `CAP_SYSLOG` or `kernel.dmesg_restrict` set to 0, and the host PID namespace. The records
+    /// collected by an audit daemon never reach the kernel log and aren't counted.
+    pub num_violations: SharedIncMetric,
+    /// Number of failures to open or read the kernel log in log mode. `num_violations` is only
+    /// meaningful while this stays at 0.
+    pub violations_read_fails: SharedIncMetric,
 }
 
 /// Metrics specific to the UART device.
@@ -730,6 +1230,21 @@ pub struct SignalMetrics {
     pub sigill: SharedStoreMetric,
 }
 
+/// Metrics related to the snapshots taken periodically by Firecracker.
+#[derive(Default, Serialize)]
+pub struct SnapshotScheduleMetrics {
+    /// Number of scheduled snapshots taken.
+    pub snapshots: SharedIncMetric,
+    /// Number of scheduled snapshots that failed.
+    pub fails: SharedIncMetric,
+    /// Number of scheduled snapshots skipped because the microVM was not running.
+    pub skipped: SharedIncMetric,
+    /// Number of scheduled snapshots that paused the microVM for longer than allowed.
+    pub pause_overruns: SharedIncMetric,
+    /// Time the microVM was paused for by the last scheduled snapshot, in microseconds.
+    pub pause_us: SharedStoreMetric,
+}
+
 /// Metrics specific to VCPUs' mode of functioning.
 #[derive(Default, Serialize)]
 pub struct VcpuMetrics {
@@ -799,6 +1314,20 @@ pub struct VsockDeviceMetrics {
     pub tx_write_fails: SharedIncMetric,
     /// Number of times read() has failed.
     pub rx_read_fails: SharedIncMetric,
+    /// Number of datagrams received from the host.
+    pub rx_dgrams_count: SharedIncMetric,
+    /// Number of datagrams sent to the host.
+    pub tx_dgrams_count: SharedIncMetric,
+    /// Number of datagrams dropped because they couldn't be delivered.
+    pub dgrams_dropped: SharedIncMetric,
+    /// Number of times receiving was held back by the RX rate limiter.
+    pub rx_rate_limiter_throttled: SharedIncMetric,
+    /// Number of times transmitting was held back by the TX rate limiter.
+    pub tx_rate_limiter_throttled: SharedIncMetric,
+    /// Number of events associated with the rate limiters.
+    pub rate_limiter_event_count: SharedIncMetric,
+    /// Number of pooled connections taken by the host.
+    pub pooled_conns_taken: SharedIncMetric,
 }
 
 // The sole purpose of this struct is to produce an UTC timestamp when an instance is serialized.
@@ -807,6 +1336,10 @@ struct SerializeToUtcTimestampMs;
 
 impl Serialize for SerializeToUtcTimestampMs {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Scrapers timestamp the samples themselves.
+        if is_scraping() {
+            return serializer.serialize_none();
+        }
         serializer.serialize_i64(
             utils::time::get_time_ns(utils::time::ClockType::Real) as i64 / 1_000_000,
         )
@@ -823,6 +1356,12 @@ pub struct FirecrackerMetrics {
     pub balloon: BalloonDeviceMetrics,
     /// A block device's related metrics.
     pub block: BlockDeviceMetrics,
+    /// Service time of the requests of each drive.
+    pub block_latencies_us: BlockLatencyMetrics,
+    /// Host CPU time consumed by the Firecracker threads.
+    pub cpu_usage: CpuUsageMetrics,
+    /// Metrics related to API DELETE requests.
+    pub delete_api_requests: DeleteRequestsMetrics,
     /// Metrics related to deprecated API calls.
     pub deprecated_api: DeprecatedApiMetrics,
     /// Metrics related to API GET requests.
@@ -833,12 +1372,16 @@ pub struct FirecrackerMetrics {
     pub latencies_us: PerformanceMetrics,
     /// Logging related metrics.
     pub logger: LoggerSystemMetrics,
+    /// Metrics related to the virtio-mem device.
+    pub memory_hotplug: MemoryHotplugDeviceMetrics,
     /// Metrics specific to MMDS functionality.
     pub mmds: MmdsMetrics,
     /// A network device's related metrics.
     pub net: NetDeviceMetrics,
     /// Metrics related to API PATCH requests.
     pub patch_api_requests: PatchRequestsMetrics,
+    /// A pmem device's related metrics.
+    pub pmem: PmemDeviceMetrics,
     /// Metrics related to API PUT requests.
     pub put_api_requests: PutRequestsMetrics,
     #[cfg(target_arch = "aarch64")]
@@ -854,6 +1397,8 @@ pub struct FirecrackerMetrics {
     pub uart: Arc<SerialDeviceMetrics>,
     /// Metrics related to signals.
     pub signals: SignalMetrics,
+    /// Metrics related to the snapshots taken periodically.
+    pub snapshot_schedule: SnapshotScheduleMetrics,
     /// Metrics related to virtio-vsockets.
     pub vsock: VsockDeviceMetrics,
 }
@@ -930,6 +1475,178 @@ mod tests {
         assert_eq!(1, m1.fetch());
     }
 
+    #[test]
+    fn test_block_latency_metrics() {
+        let metrics = BlockLatencyMetrics::default();
+        let as_json = |metrics: &BlockLatencyMetrics| -> serde_json::Value {
+            serde_json::from_str(&serde_json::to_string(metrics).unwrap()).unwrap()
+        };
+        assert_eq!(as_json(&metrics), serde_json::json!({}));
+
+        let rootfs = metrics.register("rootfs");
+        let scratch = metrics.register("scratch");
+        // The devices created with the same ID share the histogram.
+        assert!(Arc::ptr_eq(&rootfs, &metrics.register("rootfs")));
+
+        for _ in 0..90 {
+            rootfs.record(80);
+        }
+        for _ in 0..9 {
+            rootfs.record(400);
+        }
+        rootfs.record(2_000_000);
+        assert_eq!(rootfs.count(), 100);
+
+        let json = as_json(&metrics);
+        assert_eq!(json["rootfs"]["count"], 100);
+        assert_eq!(json["rootfs"]["p50_us"], 100);
+        assert_eq!(json["rootfs"]["p90_us"], 100);
+        assert_eq!(json["rootfs"]["p99_us"], 500);
+        assert_eq!(json["rootfs"]["buckets_us"]["le_100"], 90);
+        assert_eq!(json["rootfs"]["buckets_us"]["le_500"], 9);
+        assert_eq!(json["rootfs"]["buckets_us"]["inf"], 1);
+        assert_eq!(json["scratch"]["count"], 0);
+        assert_eq!(json["scratch"]["p99_us"], 0);
+        assert_eq!(rootfs.count(), 0);
+
+        // The samples above the last bound are reported with it.
+        rootfs.record(2_000_000);
+        assert_eq!(as_json(&metrics)["rootfs"]["p50_us"], 1_000_000);
+
+        // The histograms of the drives which are gone are dropped once flushed.
+        drop(scratch);
+        assert!(as_json(&metrics).get("scratch").is_some());
+        assert!(as_json(&metrics).get("scratch").is_none());
+        assert!(as_json(&metrics).get("rootfs").is_some());
+    }
+
+    #[test]
+    fn test_keyed_inc_metric() {
+        let metric = KeyedIncMetric::default();
+        let as_json = |metric: &KeyedIncMetric| -> serde_json::Value {
+            serde_json::from_str(&serde_json::to_string(metric).unwrap()).unwrap()
+        };
+        assert_eq!(as_json(&metric), serde_json::json!({}));
+
+        metric.inc("/a");
+        metric.inc("/a");
+        metric.inc("/b");
+        assert_eq!(metric.count("/a"), 2);
+        assert_eq!(as_json(&metric), serde_json::json!({"/a": 2, "/b": 1}));
+        // The counters are reset when flushed.
+        assert_eq!(metric.count("/a"), 0);
+        assert_eq!(as_json(&metric), serde_json::json!({}));
+
+        // The keys beyond the limit are accumulated together.
+        for index in 0..MAX_METRIC_KEYS + 2 {
+            metric.inc(&format!("/{}", index));
+        }
+        metric.inc("/0");
+        let json = as_json(&metric);
+        assert_eq!(json.as_object().unwrap().len(), MAX_METRIC_KEYS + 1);
+        assert_eq!(json["/0"], 2);
+        assert_eq!(json["other"], 2);
+    }
+
+    #[test]
+    fn test_cpu_usage_metrics() {
+        let metrics = Arc::new(CpuUsageMetrics::default());
+        let as_json = |metrics: &CpuUsageMetrics| -> serde_json::Value {
+            serde_json::from_str(&serde_json::to_string(metrics).unwrap()).unwrap()
+        };
+
+        let json = as_json(&metrics);
+        assert_eq!(json["vcpu_us"], 0);
+        assert_eq!(json["per_vcpu_us"], serde_json::json!([]));
+
+        // Spin for a while, so that the thread has some CPU time to report.
+        let burn_cpu = || {
+            let start = utils::time::get_time_us(utils::time::ClockType::ThreadCpu);
+            while utils::time::get_time_us(utils::time::ClockType::ThreadCpu) - start < 20_000 {}
+        };
+
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let mut vcpu_threads = Vec::new();
+        for index in 0..2 {
+            let m = metrics.clone();
+            let done_tx = done_tx.clone();
+            let (exit_tx, exit_rx) = std::sync::mpsc::channel::<()>();
+            let handle = thread::spawn(move || {
+                m.register_current_thread(ThreadCategory::Vcpu(index));
+                burn_cpu();
+                done_tx.send(()).unwrap();
+                exit_rx.recv().unwrap();
+            });
+            vcpu_threads.push((handle, exit_tx));
+        }
+        metrics.register_current_thread(ThreadCategory::Vmm);
+        burn_cpu();
+        for _ in 0..2 {
+            done_rx.recv().unwrap();
+        }
+
+        let json = as_json(&metrics);
+        let vmm_us = json["vmm_us"].as_u64().unwrap();
+        let vcpu_us = json["vcpu_us"].as_u64().unwrap();
+        let per_vcpu_us = json["per_vcpu_us"].as_array().unwrap();
+        assert!(vmm_us >= 20_000);
+        assert_eq!(json["api_us"], 0);
+        assert_eq!(json["net_worker_us"], 0);
+        assert_eq!(per_vcpu_us.len(), 2);
+        assert!(per_vcpu_us.iter().all(|us| us.as_u64().unwrap() >= 20_000));
+        assert_eq!(
+            vcpu_us,
+            per_vcpu_us
+                .iter()
+                .map(|us| us.as_u64().unwrap())
+                .sum::<u64>()
+        );
+
+        // Once the vCPU threads are gone, their last samples are still reported.
+        for (handle, exit_tx) in vcpu_threads {
+            exit_tx.send(()).unwrap();
+            handle.join().unwrap();
+        }
+
+        // The values are cumulative.
+        burn_cpu();
+        let json = as_json(&metrics);
+        assert!(json["vmm_us"].as_u64().unwrap() >= vmm_us + 20_000);
+        assert!(json["vcpu_us"].as_u64().unwrap() >= vcpu_us);
+    }
+
+    #[test]
+    fn test_scrape() {
+        let metrics = Metrics::new(FirecrackerMetrics::default());
+        metrics.block.read_count.add(5);
+        let rootfs = metrics.block_latencies_us.register("rootfs");
+        rootfs.record(80);
+        metrics.mmds.path_hits.inc("/secret");
+
+        let output = metrics.scrape().unwrap();
+        assert!(output.contains("# TYPE firecracker_block_read_count_total counter\n"));
+        assert!(output.contains("firecracker_block_read_count_total 5\n"));
+        assert!(output.contains("firecracker_block_latencies_us_count{drive_id=\"rootfs\"} 1\n"));
+        assert!(output.contains("firecracker_block_latencies_us_sum{drive_id=\"rootfs\"} 80\n"));
+        assert!(output.contains("# TYPE firecracker_api_server_process_startup_time_us gauge\n"));
+        // The keyed counters and the timestamp aren't scraped.
+        assert!(!output.contains("secret"));
+        assert!(!output.contains("utc_timestamp_ms"));
+
+        // Scraping doesn't reset what gets flushed.
+        let json = serde_json::to_value(&metrics.app_metrics).unwrap();
+        assert_eq!(json["block"]["read_count"], 5);
+        assert_eq!(json["block_latencies_us"]["rootfs"]["count"], 1);
+        assert_eq!(json["mmds"]["path_hits"]["/secret"], 1);
+        assert_eq!(rootfs.count(), 0);
+
+        // And flushing doesn't reset the scraped totals.
+        metrics.block.read_count.inc();
+        let output = metrics.scrape().unwrap();
+        assert!(output.contains("firecracker_block_read_count_total 6\n"));
+        assert!(output.contains("firecracker_block_latencies_us_count{drive_id=\"rootfs\"} 1\n"));
+    }
+
     #[test]
     fn test_serialize() {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
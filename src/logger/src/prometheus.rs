@@ -0,0 +1,231 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Renders the metrics in the Prometheus text exposition format.
+//!
+//! The metrics are first serialized with the same `Serialize` implementations used for
+//! flushing them, in a mode where the counters, histograms and per-device values are tagged with
+//! their kind using `Sample`. The plain numbers which are left are gauges. Each field of the
+//! nested structures adds a component to the metric name, e.g. `net.rx_bytes_count` is exposed as
+//! `firecracker_net_rx_bytes_count_total`.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::metrics::LATENCY_BUCKETS_US;
+
+/// Prefix of the names of all the exposed metrics.
+const NAME_PREFIX: &str = "firecracker";
+
+/// A scraped metric, which isn't a gauge.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Sample {
+    /// The total of a counter since the process started.
+    Counter(u64),
+    /// The totals of the buckets of a histogram, by increasing upper bound, along with the sum
+    /// of all the samples.
+    Histogram { buckets: Vec<usize>, sum: usize },
+    /// Values which only differ by the `label` they are reported with, e.g. the drive they
+    /// belong to.
+    Labeled {
+        label: &'static str,
+        values: BTreeMap<String, Sample>,
+    },
+}
+
+/// Renders the scraped `metrics` in the text exposition format.
+pub(crate) fn render(metrics: &Value) -> String {
+    let mut renderer = Renderer::default();
+    renderer.render(NAME_PREFIX, &[], metrics);
+    renderer.output
+}
+
+#[derive(Default)]
+struct Renderer {
+    output: String,
+    // The names of the metrics whose type was declared, which must happen only once.
+    declared: BTreeSet<String>,
+}
+
+impl Renderer {
+    fn render(&mut self, name: &str, labels: &[(&str, &str)], value: &Value) {
+        match value {
+            Value::Number(number) => {
+                self.declare(name, "gauge");
+                self.sample(name, labels, None, &number.to_string());
+            }
+            Value::Object(fields) => match sample_kind(fields) {
+                Some(("counter", Value::Number(number))) => {
+                    let name = format!("{}_total", name);
+                    self.declare(&name, "counter");
+                    self.sample(&name, labels, None, &number.to_string());
+                }
+                Some(("histogram", histogram)) => self.render_histogram(name, labels, histogram),
+                Some(("labeled", labeled)) => {
+                    let label = labeled["label"].as_str().unwrap_or_default();
+                    if let Some(values) = labeled["values"].as_object() {
+                        for (label_value, value) in values.iter() {
+                            let mut labels = labels.to_vec();
+                            labels.push((label, label_value.as_str()));
+                            self.render(name, &labels, value);
+                        }
+                    }
+                }
+                _ => {
+                    for (field, value) in fields.iter() {
+                        self.render(&format!("{}_{}", name, field), labels, value);
+                    }
+                }
+            },
+            // The metrics which aren't scraped are serialized as null.
+            _ => (),
+        }
+    }
+
+    fn render_histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Value) {
+        let buckets: Vec<u64> = histogram["buckets"]
+            .as_array()
+            .map(|buckets| buckets.iter().filter_map(Value::as_u64).collect())
+            .unwrap_or_default();
+        self.declare(name, "histogram");
+
+        // The buckets of the exposition format also count the samples of the previous ones.
+        let mut count = 0;
+        for (index, bucket) in buckets.iter().enumerate() {
+            count += bucket;
+            let bound = match LATENCY_BUCKETS_US.get(index) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            self.sample(
+                &format!("{}_bucket", name),
+                labels,
+                Some(("le", &bound)),
+                &count.to_string(),
+            );
+        }
+        self.sample(
+            &format!("{}_sum", name),
+            labels,
+            None,
+            &histogram["sum"].to_string(),
+        );
+        self.sample(&format!("{}_count", name), labels, None, &count.to_string());
+    }
+
+    fn declare(&mut self, name: &str, metric_type: &str) {
+        if self.declared.insert(name.to_string()) {
+            // Writing to a `String` can't fail.
+            let _ = writeln!(self.output, "# TYPE {} {}", name, metric_type);
+        }
+    }
+
+    fn sample(
+        &mut self,
+        name: &str,
+        labels: &[(&str, &str)],
+        extra_label: Option<(&str, &str)>,
+        value: &str,
+    ) {
+        let labels: Vec<String> = labels
+            .iter()
+            .chain(extra_label.iter())
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+            .collect();
+        let _ = if labels.is_empty() {
+            writeln!(self.output, "{} {}", name, value)
+        } else {
+            writeln!(self.output, "{}{{{}}} {}", name, labels.join(","), value)
+        };
+    }
+}
+
+// Returns the kind and contents of the `Sample` serialized as `fields`, if it is one.
+fn sample_kind(fields: &Map<String, Value>) -> Option<(&str, &Value)> {
+    if fields.len() != 1 {
+        return None;
+    }
+    fields
+        .iter()
+        .next()
+        .filter(|(kind, _)| ["counter", "histogram", "labeled"].contains(&kind.as_str()))
+        .map(|(kind, value)| (kind.as_str(), value))
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let histogram = Sample::Histogram {
+            buckets: vec![1, 0, 2]
+                .into_iter()
+                .chain(std::iter::repeat(0).take(LATENCY_BUCKETS_US.len() - 3))
+                .chain(std::iter::once(1))
+                .collect(),
+            sum: 2_000_150,
+        };
+        let mut drives = BTreeMap::new();
+        drives.insert("root\"fs".to_string(), histogram);
+        let metrics = json!({
+            "utc_timestamp_ms": null,
+            "api_server": {
+                "process_startup_time_us": 42,
+            },
+            "block": {
+                "read_count": Sample::Counter(7),
+            },
+            "block_latencies_us": Sample::Labeled {
+                label: "drive_id",
+                values: drives,
+            },
+        });
+
+        let output = render(&metrics);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[..4],
+            [
+                "# TYPE firecracker_api_server_process_startup_time_us gauge",
+                "firecracker_api_server_process_startup_time_us 42",
+                "# TYPE firecracker_block_read_count_total counter",
+                "firecracker_block_read_count_total 7",
+            ]
+        );
+        assert_eq!(lines[4], "# TYPE firecracker_block_latencies_us histogram");
+        assert_eq!(
+            lines[5],
+            "firecracker_block_latencies_us_bucket{drive_id=\"root\\\"fs\",le=\"25\"} 1"
+        );
+        assert_eq!(
+            lines[7],
+            "firecracker_block_latencies_us_bucket{drive_id=\"root\\\"fs\",le=\"100\"} 3"
+        );
+        assert_eq!(
+            lines[lines.len() - 3],
+            "firecracker_block_latencies_us_bucket{drive_id=\"root\\\"fs\",le=\"+Inf\"} 4"
+        );
+        assert_eq!(
+            lines[lines.len() - 2],
+            "firecracker_block_latencies_us_sum{drive_id=\"root\\\"fs\"} 2000150"
+        );
+        assert_eq!(
+            lines[lines.len() - 1],
+            "firecracker_block_latencies_us_count{drive_id=\"root\\\"fs\"} 4"
+        );
+        assert_eq!(lines.len(), 5 + LATENCY_BUCKETS_US.len() + 1 + 2);
+    }
+}
@@ -1,17 +1,33 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
 use serde::{Deserialize, Serialize};
 use serde_json::{to_vec, Value};
 
+use crate::identity::{IdentitySigner, IDENTITY_KEY};
 use crate::token::{Error as TokenError, TokenAuthority};
 
+/// Top level key under which the device tags are exposed to the guest.
+pub const DEVICE_TAGS_KEY: &str = "fc-devices";
+/// Top level key under which the identity of a clone of a snapshot is exposed to the guest.
+pub const CLONE_KEY: &str = "fc-clone";
+
 /// The Mmds is the Microvm Metadata Service represented as an untyped json.
 pub struct Mmds {
     data_store: Value,
+    // Read-only subtree managed by Firecracker, exposed to the guest under `DEVICE_TAGS_KEY`.
+    device_tags: Value,
+    // Read-only subtree managed by Firecracker, exposed to the guest under `CLONE_KEY`.
+    clone_identity: Value,
+    // Signs the identity document exposed to the guest under `IDENTITY_KEY`, when configured.
+    identity_signer: Option<IdentitySigner>,
+    // Wall clock time at which the microVM was started, in seconds since the epoch.
+    boot_time_s: Option<u64>,
     // None when MMDS V1 is configured, Some for MMDS V2.
     token_authority: Option<TokenAuthority>,
     is_initialized: bool,
@@ -46,11 +62,28 @@ pub enum OutputFormat {
     Imds,
 }
 
+/// An operation of a JSON Patch document, as defined by RFC 6902.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    /// Adds `value` at `path`, overwriting an existing object member or shifting the
+    /// following array elements.
+    Add { path: String, value: Value },
+    /// Removes the value at `path`, which must exist.
+    Remove { path: String },
+    /// Replaces the value at `path`, which must exist, with `value`.
+    Replace { path: String, value: Value },
+    /// Checks that the value at `path` is equal to `value`.
+    Test { path: String, value: Value },
+}
+
 #[derive(Debug)]
 pub enum Error {
     DataStoreLimitExceeded,
+    InvalidPatchPath(String),
     NotFound,
     NotInitialized,
+    PatchTestFailed(String),
     TokenAuthority(TokenError),
     UnsupportedValueType,
 }
@@ -60,7 +93,15 @@ impl fmt::Display for Error {
         match self {
             Error::DataStoreLimitExceeded => write!(f, "The MMDS patch request doesn't fit."),
             Error::NotFound => write!(f, "The MMDS resource does not exist."),
+            Error::InvalidPatchPath(path) => write!(
+                f,
+                "The JSON Patch path `{}` is malformed or does not point to a valid location.",
+                path
+            ),
             Error::NotInitialized => write!(f, "The MMDS data store is not initialized."),
+            Error::PatchTestFailed(path) => {
+                write!(f, "The JSON Patch test operation on `{}` failed.", path)
+            }
             Error::TokenAuthority(err) => write!(f, "Token Authority error: {}", err),
             Error::UnsupportedValueType => write!(
                 f,
@@ -81,6 +122,10 @@ impl Mmds {
     pub fn default_with_limit(data_store_limit: usize) -> Self {
         Mmds {
             data_store: Value::default(),
+            device_tags: Value::default(),
+            clone_identity: Value::default(),
+            identity_signer: None,
+            boot_time_s: None,
             token_authority: None,
             is_initialized: false,
             data_store_limit,
@@ -98,6 +143,11 @@ impl Mmds {
         }
     }
 
+    /// Whether the data store was given contents, through a PUT request.
+    pub fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+
     /// Set the MMDS version.
     pub fn set_version(&mut self, version: MmdsVersion) -> Result<(), Error> {
         match version {
@@ -179,6 +229,26 @@ impl Mmds {
         Ok(())
     }
 
+    /// Applies the operations of a JSON Patch document to the data store.
+    ///
+    /// The operations are applied in order, and the data store is left untouched if any of
+    /// them fails.
+    pub fn apply_patch_operations(&mut self, operations: Vec<PatchOperation>) -> Result<(), Error> {
+        self.check_data_store_initialized()?;
+        let mut data_store_clone = self.data_store.clone();
+
+        for operation in operations {
+            apply_patch_operation(&mut data_store_clone, operation)?;
+        }
+        // It is safe to unwrap because our data store keys are all strings and
+        // we are using default serializer which does not return error.
+        if to_vec(&data_store_clone).unwrap().len() > self.data_store_limit {
+            return Err(Error::DataStoreLimitExceeded);
+        }
+        self.data_store = data_store_clone;
+        Ok(())
+    }
+
     // We do not check size of data_store before returning a result because due
     // to limit from put/patch the data_store can not be bigger than the limit
     // imposed by the server.
@@ -186,6 +256,104 @@ impl Mmds {
         self.data_store.clone()
     }
 
+    /// Replaces the device tags exposed to the guest under `DEVICE_TAGS_KEY`.
+    ///
+    /// The tags are kept apart from the user provided data store, so they can neither be
+    /// overwritten through PUT/PATCH requests, nor count towards the data store limit.
+    pub fn set_device_tags(&mut self, device_tags: Value) {
+        self.device_tags = device_tags;
+    }
+
+    /// Sets the identity given to the microVM when restored as a clone of a snapshot, exposed to
+    /// the guest under `CLONE_KEY` like the device tags.
+    pub fn set_clone_identity(&mut self, clone_identity: Value) {
+        self.clone_identity = clone_identity;
+    }
+
+    /// Sets the signer of the identity document exposed to the guest under `IDENTITY_KEY`.
+    ///
+    /// Like the device tags, the signed document is not part of the user provided data store.
+    pub fn set_identity_signer(&mut self, identity_signer: IdentitySigner) {
+        self.identity_signer = Some(identity_signer);
+    }
+
+    /// Records the wall clock time at which the microVM was started, in seconds since the epoch.
+    pub fn set_boot_time(&mut self, boot_time_s: u64) {
+        self.boot_time_s = Some(boot_time_s);
+    }
+
+    /// Returns the subtree attached to the data store as seen by the guest under the top level
+    /// `key`, i.e. the device tags, the clone identity or the signed identity document. It hides
+    /// the user data under the same key.
+    fn attached_subtree(&self, key: &str) -> Option<Cow<'_, Value>> {
+        // There is nowhere to attach the subtrees to.
+        if !self.data_store.is_object() && !self.data_store.is_null() {
+            return None;
+        }
+        match key {
+            DEVICE_TAGS_KEY if !self.device_tags.is_null() => {
+                Some(Cow::Borrowed(&self.device_tags))
+            }
+            CLONE_KEY if !self.clone_identity.is_null() => {
+                Some(Cow::Borrowed(&self.clone_identity))
+            }
+            IDENTITY_KEY => self.identity_signer.as_ref().map(|signer| {
+                Cow::Owned(signer.signed_document(self.boot_time_s, &self.data_store))
+            }),
+            _ => None,
+        }
+    }
+
+    // Formats the root of the data store as seen by the guest. Its entries are borrowed from the
+    // data store, rather than copied along with the whole data store.
+    fn format_guest_root(&self, format: OutputFormat) -> Result<String, Error> {
+        let attached: Vec<_> = [DEVICE_TAGS_KEY, CLONE_KEY, IDENTITY_KEY]
+            .iter()
+            .filter_map(|key| self.attached_subtree(key).map(|subtree| (*key, subtree)))
+            .collect();
+        if attached.is_empty() {
+            return Mmds::format_value(&self.data_store, format);
+        }
+
+        let mut root: BTreeMap<&str, Cow<Value>> = BTreeMap::new();
+        if let Some(map) = self.data_store.as_object() {
+            root.extend(
+                map.iter()
+                    .map(|(key, value)| (key.as_str(), Cow::Borrowed(value))),
+            );
+        }
+        root.extend(attached);
+        match format {
+            // It is safe to unwrap because the keys are all strings and we are using the
+            // default serializer which does not return error.
+            OutputFormat::Json => Ok(serde_json::to_string(&root).unwrap()),
+            OutputFormat::Imds => Ok(Mmds::format_imds_keys(
+                root.iter().map(|(key, value)| (*key, value.as_ref())),
+            )),
+        }
+    }
+
+    fn format_value(json: &Value, format: OutputFormat) -> Result<String, Error> {
+        match format {
+            OutputFormat::Json => Ok(json.to_string()),
+            OutputFormat::Imds => Mmds::format_imds(json),
+        }
+    }
+
+    // Lists the keys of an object in IMDS format, with a trailing "/" for the keys of objects.
+    fn format_imds_keys<'a>(entries: impl Iterator<Item = (&'a str, &'a Value)>) -> String {
+        entries
+            .map(|(key, value)| {
+                if value.is_object() {
+                    format!("{}/", key)
+                } else {
+                    key.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     /// Returns the serde::Value in IMDS format plaintext.
     /// Currently, only JSON objects and strings can be IMDS formatted.
     ///
@@ -228,21 +396,10 @@ impl Mmds {
         // If the `dict` is not a dictionary, a Vec with the value corresponding to
         // the key is returned.
         match json.as_object() {
-            Some(map) => {
-                let mut ret = Vec::new();
-                // When the object is a map, push all the keys in the Vec.
-                for key in map.keys() {
-                    let mut key = key.clone();
-                    // If the key corresponds to a dictionary, a "/" is appended
-                    // to the key name.
-                    if map[&key].is_object() {
-                        key.push('/');
-                    }
-
-                    ret.push(key);
-                }
-                Ok(ret.join("\n"))
-            }
+            // When the object is a map, list all its keys.
+            Some(map) => Ok(Mmds::format_imds_keys(
+                map.iter().map(|(key, value)| (key.as_str(), value)),
+            )),
             None => {
                 // When the object is not a map, return the value.
                 // Support only `Value::String`.
@@ -259,25 +416,123 @@ impl Mmds {
     pub fn get_value(&self, path: String, format: OutputFormat) -> Result<String, Error> {
         // The pointer function splits the input by "/". With a trailing "/", pointer does not
         // know how to get the object.
-        let value = if path.ends_with('/') {
-            self.data_store.pointer(&path.as_str()[..(path.len() - 1)])
-        } else {
-            self.data_store.pointer(path.as_str())
+        let path = path.strip_suffix('/').unwrap_or(&path);
+        if path.is_empty() {
+            return self.format_guest_root(format);
+        }
+
+        // The subtrees attached by Firecracker are looked up on their own, so that the data
+        // store isn't copied on each guest read.
+        let attached = path.strip_prefix('/').and_then(|path| {
+            let (key, subpath) = path.split_at(path.find('/').unwrap_or(path.len()));
+            self.attached_subtree(key).map(|subtree| (subtree, subpath))
+        });
+        let value = match attached.as_ref() {
+            Some((subtree, subpath)) => subtree.pointer(subpath),
+            None => self.data_store.pointer(path),
         };
 
-        if let Some(json) = value {
-            match format {
-                OutputFormat::Json => Ok(json.to_string()),
-                OutputFormat::Imds => Mmds::format_imds(json),
+        match value {
+            Some(json) => Mmds::format_value(json, format),
+            None => Err(Error::NotFound),
+        }
+    }
+}
+
+// Applies a single JSON Patch operation to `target`.
+fn apply_patch_operation(target: &mut Value, operation: PatchOperation) -> Result<(), Error> {
+    match operation {
+        PatchOperation::Add { path, value } => add_value(target, &path, value),
+        PatchOperation::Remove { path } => remove_value(target, &path),
+        PatchOperation::Replace { path, value } => match target.pointer_mut(&path) {
+            Some(old_value) => {
+                *old_value = value;
+                Ok(())
             }
-        } else {
-            Err(Error::NotFound)
+            None => Err(Error::InvalidPatchPath(path)),
+        },
+        PatchOperation::Test { path, value } => {
+            if target.pointer(&path) == Some(&value) {
+                Ok(())
+            } else {
+                Err(Error::PatchTestFailed(path))
+            }
+        }
+    }
+}
+
+fn add_value(target: &mut Value, path: &str, value: Value) -> Result<(), Error> {
+    // The empty pointer refers to the whole document.
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+
+    let invalid_path = || Error::InvalidPatchPath(path.to_string());
+    let (parent_path, token) = split_pointer(path).ok_or_else(invalid_path)?;
+    match target.pointer_mut(parent_path) {
+        Some(Value::Object(map)) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => {
+            // "-" refers to the position past the last element of the array.
+            let index = if token == "-" {
+                array.len()
+            } else {
+                array_index(&token, array.len() + 1).ok_or_else(invalid_path)?
+            };
+            array.insert(index, value);
+            Ok(())
         }
+        _ => Err(invalid_path()),
     }
 }
 
+fn remove_value(target: &mut Value, path: &str) -> Result<(), Error> {
+    let invalid_path = || Error::InvalidPatchPath(path.to_string());
+    let (parent_path, token) = split_pointer(path).ok_or_else(invalid_path)?;
+    match target.pointer_mut(parent_path) {
+        Some(Value::Object(map)) => map.remove(&token).map(|_| ()).ok_or_else(invalid_path),
+        Some(Value::Array(array)) => {
+            let index = array_index(&token, array.len()).ok_or_else(invalid_path)?;
+            array.remove(index);
+            Ok(())
+        }
+        _ => Err(invalid_path()),
+    }
+}
+
+// Splits a non-empty JSON pointer into the pointer to the parent value and the unescaped
+// reference token of the child.
+fn split_pointer(path: &str) -> Option<(&str, String)> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    // Safe to unwrap since the path starts with '/'.
+    let index = path.rfind('/').unwrap();
+    let token = path[index + 1..].replace("~1", "/").replace("~0", "~");
+    Some((&path[..index], token))
+}
+
+// Parses an array index reference token, which must be lower than `len`.
+fn array_index(token: &str, len: usize) -> Option<usize> {
+    // Leading zeros and signs are not allowed in array indices.
+    if token.is_empty()
+        || !token.bytes().all(|b| b.is_ascii_digit())
+        || (token.len() > 1 && token.starts_with('0'))
+    {
+        return None;
+    }
+    token.parse::<usize>().ok().filter(|&index| index < len)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+
     use super::*;
 
     impl Mmds {
@@ -565,6 +820,100 @@ mod tests {
         assert_eq!(mmds.get_data_str().len(), 72);
     }
 
+    #[test]
+    fn test_apply_patch_operations() {
+        let mut mmds = Mmds::default();
+        let parse =
+            |operations: &str| -> Vec<PatchOperation> { serde_json::from_str(operations).unwrap() };
+
+        // The data store needs to be initialized first.
+        assert_eq!(
+            mmds.apply_patch_operations(vec![]).unwrap_err().to_string(),
+            Error::NotInitialized.to_string()
+        );
+
+        mmds.put_data(
+            serde_json::from_str(r#"{"a": {"b~c": 1, "d/e": 2}, "f": [1, 2, 3]}"#).unwrap(),
+        )
+        .unwrap();
+        mmds.apply_patch_operations(parse(
+            r#"[
+                {"op": "test", "path": "/a/b~0c", "value": 1},
+                {"op": "remove", "path": "/a/d~1e"},
+                {"op": "replace", "path": "/a/b~0c", "value": "x"},
+                {"op": "add", "path": "/f/0", "value": 0},
+                {"op": "add", "path": "/f/-", "value": 4},
+                {"op": "remove", "path": "/f/2"},
+                {"op": "add", "path": "/g", "value": {"h": null}}
+            ]"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            mmds.data_store_value(),
+            serde_json::from_str::<Value>(
+                r#"{"a": {"b~c": "x"}, "f": [0, 1, 3, 4], "g": {"h": null}}"#
+            )
+            .unwrap()
+        );
+
+        // Operations on missing or malformed paths fail, and none of the operations of the
+        // document get applied.
+        let data_store = mmds.data_store_value();
+        for path in ["/x/y", "/f/4", "/f/01", "/f/-", "a", ""].iter() {
+            let operations = parse(&format!(
+                r#"[{{"op": "add", "path": "/z", "value": 1}}, {{"op": "remove", "path": "{}"}}]"#,
+                path
+            ));
+            assert_eq!(
+                mmds.apply_patch_operations(operations)
+                    .unwrap_err()
+                    .to_string(),
+                Error::InvalidPatchPath(path.to_string()).to_string()
+            );
+            assert_eq!(mmds.data_store_value(), data_store);
+        }
+        assert_eq!(
+            mmds.apply_patch_operations(parse(r#"[{"op": "replace", "path": "/z", "value": 1}]"#))
+                .unwrap_err()
+                .to_string(),
+            Error::InvalidPatchPath("/z".to_string()).to_string()
+        );
+
+        // A failed test aborts the whole document.
+        assert_eq!(
+            mmds.apply_patch_operations(parse(
+                r#"[
+                    {"op": "remove", "path": "/g"},
+                    {"op": "test", "path": "/a/b~0c", "value": 1}
+                ]"#
+            ))
+            .unwrap_err()
+            .to_string(),
+            Error::PatchTestFailed("/a/b~0c".to_string()).to_string()
+        );
+        assert_eq!(mmds.data_store_value(), data_store);
+
+        // The data store size limit still applies.
+        let filling = (0..51300).map(|_| "X").collect::<String>();
+        let operations = vec![PatchOperation::Add {
+            path: "/g/h".to_string(),
+            value: Value::String(filling),
+        }];
+        assert_eq!(
+            mmds.apply_patch_operations(operations)
+                .unwrap_err()
+                .to_string(),
+            Error::DataStoreLimitExceeded.to_string()
+        );
+        assert_eq!(mmds.data_store_value(), data_store);
+
+        // Unsupported operations are rejected when deserializing the document.
+        assert!(serde_json::from_str::<Vec<PatchOperation>>(
+            r#"[{"op": "move", "from": "/a", "path": "/b"}]"#
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_put_size_limit() {
         let mut mmds = Mmds::default();
@@ -581,6 +930,152 @@ mod tests {
         assert_eq!(mmds.get_data_str().len(), 2);
     }
 
+    #[test]
+    fn test_device_tags() {
+        let mut mmds = Mmds::default();
+        let tags = r#"{"0xd0000000": {"type": "block", "id": "rootfs"}}"#;
+        mmds.set_device_tags(serde_json::from_str(tags).unwrap());
+
+        // The tags are visible even with an empty data store.
+        assert_eq!(
+            mmds.get_value("/fc-devices/0xd0000000/id".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "rootfs"
+        );
+        assert_eq!(
+            mmds.get_value("/".to_string(), OutputFormat::Imds).unwrap(),
+            "fc-devices/"
+        );
+
+        // User data can't overwrite the tags, nor is the user data altered by them.
+        mmds.put_data(serde_json::from_str(r#"{"fc-devices": "foo", "bar": "baz"}"#).unwrap())
+            .unwrap();
+        assert_eq!(
+            mmds.get_value(
+                "/fc-devices/0xd0000000/type".to_string(),
+                OutputFormat::Json
+            )
+            .unwrap(),
+            "\"block\""
+        );
+        assert_eq!(
+            mmds.get_value("/bar".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "baz"
+        );
+        assert_eq!(mmds.get_data_str(), r#"{"bar":"baz","fc-devices":"foo"}"#);
+
+        // Clearing the tags makes the user data visible again.
+        mmds.set_device_tags(Value::Null);
+        assert_eq!(
+            mmds.get_value("/fc-devices".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "foo"
+        );
+    }
+
+    #[test]
+    fn test_clone_identity() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::from_str(r#"{"fc-clone": "foo", "bar": "baz"}"#).unwrap())
+            .unwrap();
+        mmds.set_clone_identity(serde_json::from_str(r#"{"instance_id": "clone-1"}"#).unwrap());
+
+        // The identity shadows the user data, which is left as it is.
+        assert_eq!(
+            mmds.get_value("/fc-clone/instance_id".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "clone-1"
+        );
+        assert_eq!(
+            mmds.get_value("/bar".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "baz"
+        );
+        assert_eq!(mmds.get_data_str(), r#"{"bar":"baz","fc-clone":"foo"}"#);
+    }
+
+    #[test]
+    fn test_guest_root() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::from_str(r#"{"fc-devices": "foo", "bar": {"baz": 1}}"#).unwrap())
+            .unwrap();
+        assert_eq!(
+            mmds.get_value("/".to_string(), OutputFormat::Imds).unwrap(),
+            "bar/\nfc-devices"
+        );
+
+        // The attached subtrees are listed along with the user data, which they shadow.
+        mmds.set_device_tags(serde_json::from_str(r#"{"0xd0000000": {"type": "net"}}"#).unwrap());
+        mmds.set_clone_identity(serde_json::from_str(r#"{"instance_id": "clone-1"}"#).unwrap());
+        assert_eq!(
+            mmds.get_value("/".to_string(), OutputFormat::Imds).unwrap(),
+            "bar/\nfc-clone/\nfc-devices/"
+        );
+        assert_eq!(
+            mmds.get_value("".to_string(), OutputFormat::Json).unwrap(),
+            r#"{"bar":{"baz":1},"fc-clone":{"instance_id":"clone-1"},"fc-devices":{"0xd0000000":{"type":"net"}}}"#
+        );
+
+        // The subtrees are only reachable through absolute paths.
+        assert_eq!(
+            mmds.get_value("fc-devices/".to_string(), OutputFormat::Imds)
+                .unwrap_err()
+                .to_string(),
+            Error::NotFound.to_string()
+        );
+        assert_eq!(
+            mmds.get_value("/fc-devices/0xd0000001".to_string(), OutputFormat::Imds)
+                .unwrap_err()
+                .to_string(),
+            Error::NotFound.to_string()
+        );
+        assert_eq!(
+            mmds.get_value("/fc-devices/".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "0xd0000000/"
+        );
+    }
+
+    #[test]
+    fn test_identity_document() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::from_str(r#"{"fc-identity": "foo"}"#).unwrap())
+            .unwrap();
+
+        let key_file = TempFile::new().unwrap();
+        key_file.as_file().write_all(&[0xaa; 32]).unwrap();
+        mmds.set_identity_signer(IdentitySigner::from_key_file(key_file.as_path(), "bar").unwrap());
+        mmds.set_boot_time(42);
+
+        // The signed document shadows the user data, which it covers.
+        assert_eq!(
+            mmds.get_value("/fc-identity".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "document\nsignature"
+        );
+        let document: Value = serde_json::from_str(
+            &mmds
+                .get_value("/fc-identity/document".to_string(), OutputFormat::Imds)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(document["instance_id"], "bar");
+        assert_eq!(document["boot_time_s"], 42);
+        let signature = mmds
+            .get_value("/fc-identity/signature".to_string(), OutputFormat::Imds)
+            .unwrap();
+
+        mmds.put_data(serde_json::from_str(r#"{"foo": "bar"}"#).unwrap())
+            .unwrap();
+        assert_ne!(
+            mmds.get_value("/fc-identity/signature".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            signature
+        );
+        assert_eq!(mmds.get_data_str(), r#"{"foo":"bar"}"#);
+    }
+
     #[test]
     fn test_is_valid() {
         let mut mmds = Mmds::default();
@@ -0,0 +1,151 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::Path;
+use std::{fmt, fs, io};
+
+use hmac::{Hmac, Mac, NewMac};
+use serde_json::{json, to_vec, Value};
+use sha2::{Digest, Sha256};
+
+/// Top level key under which the signed identity document is exposed to the guest.
+pub const IDENTITY_KEY: &str = "fc-identity";
+/// Minimum length of the signing key, in bytes.
+pub const MIN_KEY_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to read the signing key from its file.
+    KeyFile(io::Error),
+    /// The signing key is shorter than `MIN_KEY_LEN`.
+    KeyTooShort(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::KeyFile(err) => write!(f, "Failed to read the signing key: {}.", err),
+            Error::KeyTooShort(len) => write!(
+                f,
+                "The signing key is {} bytes long, it needs at least {} bytes.",
+                len, MIN_KEY_LEN
+            ),
+        }
+    }
+}
+
+/// Generates the identity document of the microVM, and signs it with HMAC-SHA256.
+pub struct IdentitySigner {
+    key: Vec<u8>,
+    instance_id: String,
+}
+
+impl IdentitySigner {
+    /// Creates a signer using the key stored in the file at `key_path`.
+    pub fn from_key_file<P: AsRef<Path>>(key_path: P, instance_id: &str) -> Result<Self, Error> {
+        let key = fs::read(key_path).map_err(Error::KeyFile)?;
+        Self::new(key, instance_id)
+    }
+
+    fn new(key: Vec<u8>, instance_id: &str) -> Result<Self, Error> {
+        if key.len() < MIN_KEY_LEN {
+            return Err(Error::KeyTooShort(key.len()));
+        }
+        Ok(IdentitySigner {
+            key,
+            instance_id: instance_id.to_string(),
+        })
+    }
+
+    /// Returns the identity document along with its base64 encoded signature.
+    ///
+    /// The document is kept as a string, so that the guest gets back the exact bytes which
+    /// were signed.
+    pub fn signed_document(&self, boot_time_s: Option<u64>, user_data: &Value) -> Value {
+        // It is safe to unwrap because the data store keys are all strings and we are using
+        // the default serializer which does not return error.
+        let user_data_sha256 = Sha256::digest(&to_vec(user_data).unwrap())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let document = json!({
+            "instance_id": self.instance_id,
+            "boot_time_s": boot_time_s,
+            "user_data_sha256": user_data_sha256,
+        })
+        .to_string();
+
+        // HMAC accepts keys of any length.
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(document.as_bytes());
+        let signature = base64::encode(mac.finalize().into_bytes());
+
+        json!({
+            "document": document,
+            "signature": signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_signed_document() {
+        let key_file = TempFile::new().unwrap();
+        key_file.as_file().write_all(&[0x0b; 16]).unwrap();
+        assert!(matches!(
+            IdentitySigner::from_key_file(key_file.as_path(), "foo"),
+            Err(Error::KeyTooShort(16))
+        ));
+        key_file.as_file().write_all(&[0x0b; 16]).unwrap();
+        let signer = IdentitySigner::from_key_file(key_file.as_path(), "foo").unwrap();
+
+        let signed = signer.signed_document(Some(42), &json!({}));
+        let document: Value = serde_json::from_str(signed["document"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            document,
+            json!({
+                "instance_id": "foo",
+                "boot_time_s": 42,
+                // SHA-256 of "{}".
+                "user_data_sha256":
+                    "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+            })
+        );
+
+        // Anyone holding the key can check the signature.
+        let mut mac = HmacSha256::new_from_slice(&[0x0b; 32]).unwrap();
+        mac.update(signed["document"].as_str().unwrap().as_bytes());
+        mac.verify(&base64::decode(signed["signature"].as_str().unwrap()).unwrap())
+            .unwrap();
+
+        // The document follows the user data.
+        let other = signer.signed_document(Some(42), &json!({"foo": "bar"}));
+        assert_ne!(other["document"], signed["document"]);
+        assert_ne!(other["signature"], signed["signature"]);
+
+        assert!(matches!(
+            IdentitySigner::from_key_file("/invalid/path", "foo"),
+            Err(Error::KeyFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_error_display() {
+        let errors = [
+            Error::KeyFile(io::Error::from_raw_os_error(0)),
+            Error::KeyTooShort(1),
+        ];
+        for err in errors.iter() {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
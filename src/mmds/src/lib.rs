@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod data_store;
+pub mod identity;
 pub mod ns;
 pub mod persist;
 mod token;
@@ -10,6 +11,7 @@ pub mod token_headers;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
+use logger::{debug, IncMetric, METRICS};
 use micro_http::{
     Body, HttpHeaderError, MediaType, Method, Request, RequestError, Response, StatusCode, Version,
 };
@@ -119,12 +121,39 @@ pub fn convert_to_response(mmds: Arc<Mutex<Mmds>>, request: Request) -> Response
         );
     }
 
+    let method = request.method();
+    let path = sanitize_uri(uri.to_string());
     let mut mmds_guard = mmds.lock().expect("Poisoned lock");
 
-    match mmds_guard.version() {
+    let response = match mmds_guard.version() {
         MmdsVersion::V1 => respond_to_request_mmdsv1(&mmds_guard, request),
         MmdsVersion::V2 => respond_to_request_mmdsv2(&mut mmds_guard, request),
+    };
+    record_request(method, &path, &response);
+    response
+}
+
+// Updates the MMDS metrics with the outcome of a request, and adds it to the access log, which
+// is only emitted at the debug log level.
+fn record_request(method: Method, path: &str, response: &Response) {
+    match response.status() {
+        StatusCode::OK if matches!(method, Method::Get) => {
+            let key = match path.trim_end_matches('/') {
+                "" => "/",
+                key => key,
+            };
+            METRICS.mmds.path_hits.inc(key);
+        }
+        StatusCode::NotFound => METRICS.mmds.not_found.inc(),
+        StatusCode::Unauthorized => METRICS.mmds.token_failures.inc(),
+        _ => (),
     }
+    debug!(
+        "MMDS {:?} request on {:?}: {:?}",
+        method,
+        path,
+        response.status()
+    );
 }
 
 fn respond_to_request_mmdsv1(mmds: &Mmds, request: Request) -> Response {
@@ -449,6 +478,36 @@ mod tests {
         assert_eq!(actual_response, expected_response);
     }
 
+    #[test]
+    fn test_request_metrics() {
+        let mmds = populate_mmds();
+        let get = |path: &str| {
+            let request_bytes = format!("GET {} HTTP/1.0\r\n\r\n", path);
+            let request = Request::try_from(request_bytes.as_bytes(), None).unwrap();
+            convert_to_response(mmds.clone(), request).status()
+        };
+
+        // The hits are keyed by the path without the trailing slash.
+        assert_eq!(get("/phones//mobile/"), StatusCode::OK);
+        assert_eq!(get("/phones/mobile"), StatusCode::OK);
+        assert_eq!(METRICS.mmds.path_hits.count("/phones/mobile"), 2);
+
+        let not_found_count = METRICS.mmds.not_found.count();
+        assert_eq!(get("/phones/fax"), StatusCode::NotFound);
+        assert!(METRICS.mmds.not_found.count() > not_found_count);
+        assert_eq!(METRICS.mmds.path_hits.count("/phones/fax"), 0);
+
+        // Requests without a valid token are rejected once V2 is configured.
+        mmds.lock()
+            .expect("Poisoned lock")
+            .set_version(MmdsVersion::V2)
+            .unwrap();
+        let token_failures_count = METRICS.mmds.token_failures.count();
+        assert_eq!(get("/phones/mobile"), StatusCode::Unauthorized);
+        assert!(METRICS.mmds.token_failures.count() > token_failures_count);
+        assert_eq!(METRICS.mmds.path_hits.count("/phones/mobile"), 2);
+    }
+
     #[test]
     fn test_respond_to_request_mmdsv2() {
         // Populate MMDS with data.
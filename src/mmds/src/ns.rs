@@ -5,7 +5,7 @@
 #![allow(missing_docs)]
 
 use std::convert::From;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 use std::result::Result;
 use std::sync::{Arc, Mutex};
@@ -14,14 +14,19 @@ use dumbo::pdu::arp::{
     test_speculative_tpa, Error as ArpFrameError, EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN,
 };
 use dumbo::pdu::ethernet::{
-    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4,
+    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6,
 };
 use dumbo::pdu::ipv4::{
     test_speculative_dst_addr, Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP,
 };
+use dumbo::pdu::ipv6::{self, Error as IPv6PacketError, IPv6Packet, IPV6_VERSION, PROTOCOL_ICMPV6};
+use dumbo::pdu::ndp::{
+    solicited_node_multicast_addr, Error as NdpMessageError, NdpMessage, FLAG_OVERRIDE,
+    FLAG_SOLICITED, NDP_HOP_LIMIT, NEIGHBOR_ADVERTISEMENT_LEN,
+};
 use dumbo::pdu::tcp::Error as TcpSegmentError;
 use dumbo::pdu::Incomplete;
-use dumbo::tcp::handler::{self, RecvEvent, TcpIPv4Handler, WriteEvent};
+use dumbo::tcp::handler::{self, RecvError, RecvEvent, TcpIPv4Handler, WriteEvent};
 use dumbo::tcp::NextSegmentStatus;
 use logger::{IncMetric, METRICS};
 use utils::net::mac::MacAddr;
@@ -42,9 +47,18 @@ enum WriteArpFrameError {
     Ethernet(EthernetFrameError),
 }
 
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteNdpFrameError {
+    NoPendingNdpReply,
+    Ndp(NdpMessageError),
+    Ethernet(EthernetFrameError),
+    IPv6Packet(IPv6PacketError),
+}
+
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WritePacketError {
     IPv4Packet(IPv4PacketError),
+    IPv6Packet(IPv6PacketError),
     Ethernet(EthernetFrameError),
     TcpSegment(TcpSegmentError),
 }
@@ -53,6 +67,7 @@ impl From<handler::WriteNextError> for WritePacketError {
     fn from(error: handler::WriteNextError) -> Self {
         match error {
             handler::WriteNextError::IPv4Packet(inner) => WritePacketError::IPv4Packet(inner),
+            handler::WriteNextError::IPv6Packet(inner) => WritePacketError::IPv6Packet(inner),
             handler::WriteNextError::TcpSegment(inner) => WritePacketError::TcpSegment(inner),
         }
     }
@@ -69,6 +84,10 @@ pub struct MmdsNetworkStack {
     // It is the Ipv4Addr of the network interface for which the MmdsNetworkStack
     // routes the packets.
     pending_arp_reply_dest: Option<Ipv4Addr>,
+    // MMDS server IPv6 address, when the MMDS is also reachable over IPv6.
+    pub ipv6_addr: Option<Ipv6Addr>,
+    // Neighbor advertisement destination IPv6 address (sender of the neighbor solicitation).
+    pending_ndp_reply_dest: Option<Ipv6Addr>,
     // This handles MMDS<->guest interaction at the TCP level.
     pub(crate) tcp_handler: TcpIPv4Handler,
     // Data store reference shared across all MmdsNetworkStack instances.
@@ -89,6 +108,8 @@ impl MmdsNetworkStack {
             mac_addr,
             ipv4_addr,
             pending_arp_reply_dest: None,
+            ipv6_addr: None,
+            pending_ndp_reply_dest: None,
             tcp_handler: TcpIPv4Handler::new(
                 ipv4_addr,
                 tcp_port,
@@ -128,6 +149,19 @@ impl MmdsNetworkStack {
         Ipv4Addr::from(DEFAULT_IPV4_ADDR)
     }
 
+    // Makes the MMDS reachable at `ipv6_addr` on top of its IPv4 address, or only over IPv4 when
+    // `None`.
+    pub fn set_ipv6_addr(&mut self, ipv6_addr: Option<Ipv6Addr>) {
+        self.ipv6_addr = ipv6_addr;
+        self.pending_ndp_reply_dest = None;
+        self.tcp_handler
+            .set_local_ipv6_addr(ipv6_addr.unwrap_or(Ipv6Addr::UNSPECIFIED));
+    }
+
+    pub fn ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.ipv6_addr
+    }
+
     // This is the entry point into the MMDS network stack. The src slice should hold the contents
     // of an Ethernet frame (of that exact size, without the CRC).
     pub fn detour_frame(&mut self, src: &[u8]) -> bool {
@@ -145,6 +179,23 @@ impl MmdsNetworkStack {
                     }
                     return self.detour_ipv4(eth);
                 }
+                ETHERTYPE_IPV6 => {
+                    // Neighbor solicitations are sent to the solicited-node multicast address
+                    // of the MMDS, which may be shared with other addresses.
+                    let ipv6_addr = match self.ipv6_addr {
+                        Some(ipv6_addr) => ipv6_addr,
+                        None => return false,
+                    };
+                    if !ipv6::test_speculative_dst_addr(src, ipv6_addr)
+                        && !ipv6::test_speculative_dst_addr(
+                            src,
+                            solicited_node_multicast_addr(ipv6_addr),
+                        )
+                    {
+                        return false;
+                    }
+                    return self.detour_ipv6(eth, ipv6_addr);
+                }
                 _ => (),
             };
         } else {
@@ -176,27 +227,9 @@ impl MmdsNetworkStack {
                 // each MmdsNetworkStack routes packets for only one network device.
                 self.remote_mac_addr = eth.src_mac();
                 let mmds_instance = self.mmds.clone();
-                match &mut self.tcp_handler.receive_packet(&ip, move |request| {
+                update_recv_metrics(self.tcp_handler.receive_packet(&ip, move |request| {
                     super::convert_to_response(mmds_instance, request)
-                }) {
-                    Ok(event) => {
-                        METRICS.mmds.rx_count.inc();
-                        match event {
-                            RecvEvent::NewConnectionSuccessful => {
-                                METRICS.mmds.connections_created.inc()
-                            }
-                            RecvEvent::NewConnectionReplacing => {
-                                METRICS.mmds.connections_created.inc();
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            RecvEvent::EndpointDone => {
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            _ => (),
-                        }
-                    }
-                    Err(_) => METRICS.mmds.rx_accepted_err.inc(),
-                }
+                }));
             } else {
                 // A non-TCP IPv4 packet heading towards the MMDS; we consider it unusual.
                 METRICS.mmds.rx_accepted_unusual.inc();
@@ -207,12 +240,68 @@ impl MmdsNetworkStack {
         false
     }
 
+    fn detour_ipv6(&mut self, eth: EthernetFrame<&[u8]>, ipv6_addr: Ipv6Addr) -> bool {
+        if let Ok(ip) = IPv6Packet::from_bytes(eth.payload()) {
+            if ip.next_header() == PROTOCOL_ICMPV6 && self.detour_ndp(&eth, &ip, ipv6_addr) {
+                return true;
+            }
+            // Only neighbor solicitations are expected on the solicited-node multicast address.
+            if ip.destination_address() != ipv6_addr {
+                return false;
+            }
+            if ip.next_header() == PROTOCOL_TCP {
+                // See the notes in detour_ipv4() about the remote MAC address.
+                self.remote_mac_addr = eth.src_mac();
+                let mmds_instance = self.mmds.clone();
+                update_recv_metrics(self.tcp_handler.receive_ipv6_packet(&ip, move |request| {
+                    super::convert_to_response(mmds_instance, request)
+                }));
+            } else {
+                // A non-TCP IPv6 packet heading towards the MMDS; we consider it unusual.
+                METRICS.mmds.rx_accepted_unusual.inc();
+            }
+            return true;
+        }
+
+        false
+    }
+
+    // Looks for neighbor solicitations resolving the MMDS IPv6 address, which is the IPv6
+    // counterpart of answering ARP requests. The ones sent from the unspecified address, as part
+    // of duplicate address detection, are left alone.
+    fn detour_ndp(
+        &mut self,
+        eth: &EthernetFrame<&[u8]>,
+        ip: &IPv6Packet<&[u8]>,
+        ipv6_addr: Ipv6Addr,
+    ) -> bool {
+        let src_addr = ip.source_address();
+        // A hop limit lower than the maximum means the message crossed a router.
+        if ip.hop_limit() != NDP_HOP_LIMIT || src_addr.is_unspecified() {
+            return false;
+        }
+        if let Ok(ns) = NdpMessage::solicitation_from_bytes(
+            ip.payload(),
+            Some((src_addr, ip.destination_address())),
+        ) {
+            if ns.target_address() == ipv6_addr {
+                self.remote_mac_addr = ns
+                    .source_link_layer_address()
+                    .unwrap_or_else(|| eth.src_mac());
+                self.pending_ndp_reply_dest = Some(src_addr);
+                return true;
+            }
+        }
+
+        false
+    }
+
     // Allows the MMDS network stack to write a frame to the specified buffer. Will return:
     // - None, if the MMDS network stack has no frame to send at this point. The buffer can be
     // used for something else by the device model.
     // - Some(len), if a frame of the given length has been written to the specified buffer.
     pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
-        // We try to send ARP replies first.
+        // We try to send ARP replies and neighbor advertisements first.
         if self.pending_arp_reply_dest.is_some() {
             return match self.write_arp_reply(buf) {
                 Ok(something) => {
@@ -225,6 +314,18 @@ impl MmdsNetworkStack {
                     None
                 }
             };
+        } else if self.pending_ndp_reply_dest.is_some() {
+            return match self.write_ndp_reply(buf) {
+                Ok(something) => {
+                    METRICS.mmds.tx_count.inc();
+                    self.pending_ndp_reply_dest = None;
+                    something
+                }
+                Err(_) => {
+                    METRICS.mmds.tx_errors.inc();
+                    None
+                }
+            };
         } else {
             let call_write = match self.tcp_handler.next_segment_status() {
                 NextSegmentStatus::Available => true,
@@ -285,6 +386,53 @@ impl MmdsNetworkStack {
         ))
     }
 
+    fn write_ndp_reply(&self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WriteNdpFrameError> {
+        let ndp_reply_dest = self
+            .pending_ndp_reply_dest
+            .ok_or(WriteNdpFrameError::NoPendingNdpReply)?;
+        // The neighbor solicitation was only accepted because the MMDS has an IPv6 address.
+        let ipv6_addr = self
+            .ipv6_addr
+            .ok_or(WriteNdpFrameError::NoPendingNdpReply)?;
+
+        let mut eth_unsized = self
+            .prepare_eth_unsized(buf, ETHERTYPE_IPV6)
+            .map_err(WriteNdpFrameError::Ethernet)?;
+
+        let packet_len = {
+            let mut packet = IPv6Packet::write_header(
+                eth_unsized.inner_mut().payload_mut(),
+                PROTOCOL_ICMPV6,
+                ipv6_addr,
+                ndp_reply_dest,
+            )
+            .map_err(WriteNdpFrameError::IPv6Packet)?;
+            packet.inner_mut().set_hop_limit(NDP_HOP_LIMIT);
+
+            let payload = packet.inner_mut().payload_mut();
+            if payload.len() < NEIGHBOR_ADVERTISEMENT_LEN {
+                return Err(WriteNdpFrameError::Ndp(NdpMessageError::SliceExactLen));
+            }
+            let ndp_len = NdpMessage::write_advertisement(
+                payload.split_at_mut(NEIGHBOR_ADVERTISEMENT_LEN).0,
+                FLAG_SOLICITED | FLAG_OVERRIDE,
+                ipv6_addr,
+                self.mac_addr,
+                ipv6_addr,
+                ndp_reply_dest,
+            )
+            .map_err(WriteNdpFrameError::Ndp)?
+            .len();
+
+            packet.with_payload_len_unchecked(ndp_len).len()
+        };
+
+        Ok(Some(
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap(),
+        ))
+    }
+
     fn write_packet(&mut self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WritePacketError> {
         let mut eth_unsized = self
             .prepare_eth_unsized(buf, ETHERTYPE_IPV4)
@@ -299,6 +447,10 @@ impl MmdsNetworkStack {
         }
 
         if let Some(packet_len) = maybe_len {
+            // The packets sent over the connections established over IPv6 are IPv6 packets.
+            if eth_unsized.inner_mut().payload_mut()[0] >> 4 == IPV6_VERSION {
+                eth_unsized.inner_mut().set_ethertype(ETHERTYPE_IPV6);
+            }
             return Ok(Some(
                 // The unwrap() is safe because packet_len > 0.
                 NonZeroUsize::new(
@@ -314,10 +466,33 @@ impl MmdsNetworkStack {
     }
 }
 
+// Accounts for the outcome of handing an incoming packet to the TCP handler.
+fn update_recv_metrics(result: Result<RecvEvent, RecvError>) {
+    match result {
+        Ok(event) => {
+            METRICS.mmds.rx_count.inc();
+            match event {
+                RecvEvent::NewConnectionSuccessful => METRICS.mmds.connections_created.inc(),
+                RecvEvent::NewConnectionReplacing => {
+                    METRICS.mmds.connections_created.inc();
+                    METRICS.mmds.connections_destroyed.inc();
+                }
+                RecvEvent::EndpointDone => {
+                    METRICS.mmds.connections_destroyed.inc();
+                }
+                _ => (),
+            }
+        }
+        Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
+    use dumbo::pdu::ethernet::PAYLOAD_OFFSET as ETHERNET_PAYLOAD_OFFSET;
+    use dumbo::pdu::ndp::{TYPE_NEIGHBOR_ADVERTISEMENT, TYPE_NEIGHBOR_SOLICITATION};
     use dumbo::pdu::tcp::{Flags as TcpFlags, TcpSegment};
 
     use super::*;
@@ -329,6 +504,8 @@ mod tests {
     const MMDS_PORT: u16 = 80;
     const REMOTE_PORT: u16 = 1235;
     const SEQ_NUMBER: u32 = 123;
+    const REMOTE_IPV6_ADDR: Ipv6Addr = Ipv6Addr::LOCALHOST;
+    const MMDS_IPV6_ADDR_STR: &str = "fd00:ec2::254";
 
     // Helper methods which only make sense for testing.
     impl MmdsNetworkStack {
@@ -389,6 +566,91 @@ mod tests {
             eth_unsized.with_payload_len_unchecked(packet_len).len()
         }
 
+        fn write_neighbor_solicitation(&self, buf: &mut [u8], target_addr: Ipv6Addr) -> usize {
+            let dst_addr = solicited_node_multicast_addr(target_addr);
+            let remote_mac = MacAddr::parse_str(REMOTE_MAC_STR).unwrap();
+            let mut eth_unsized = self.prepare_eth_unsized(buf, ETHERTYPE_IPV6).unwrap();
+            eth_unsized.inner_mut().set_src_mac(remote_mac);
+            let packet_len = {
+                let mut packet = IPv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_ICMPV6,
+                    REMOTE_IPV6_ADDR,
+                    dst_addr,
+                )
+                .unwrap();
+                packet.inner_mut().set_hop_limit(NDP_HOP_LIMIT);
+
+                // Write an advertisement and then modify it into a solicitation.
+                let mut ndp = NdpMessage::write_advertisement(
+                    packet
+                        .inner_mut()
+                        .payload_mut()
+                        .split_at_mut(NEIGHBOR_ADVERTISEMENT_LEN)
+                        .0,
+                    0,
+                    target_addr,
+                    remote_mac,
+                    REMOTE_IPV6_ADDR,
+                    dst_addr,
+                )
+                .unwrap();
+                ndp.set_message_type(TYPE_NEIGHBOR_SOLICITATION)
+                    .set_checksum(0);
+                let checksum = ndp.compute_checksum(REMOTE_IPV6_ADDR, dst_addr);
+                ndp.set_checksum(checksum);
+
+                packet
+                    .with_payload_len_unchecked(NEIGHBOR_ADVERTISEMENT_LEN)
+                    .len()
+            };
+
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        }
+
+        fn write_incoming_tcp_segment_ipv6(
+            &self,
+            buf: &mut [u8],
+            addr: Ipv6Addr,
+            flags: TcpFlags,
+        ) -> usize {
+            let mut eth_unsized = self.prepare_eth_unsized(buf, ETHERTYPE_IPV6).unwrap();
+            let packet_len = {
+                let mut packet = IPv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_TCP,
+                    REMOTE_IPV6_ADDR,
+                    addr,
+                )
+                .unwrap();
+
+                let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
+                    packet.inner_mut().payload_mut(),
+                    SEQ_NUMBER,
+                    1234,
+                    flags,
+                    10000,
+                    None,
+                    0,
+                    None,
+                )
+                .unwrap()
+                .finalize(REMOTE_PORT, MMDS_PORT, None)
+                .len();
+
+                packet.with_payload_len_unchecked(segment_len).len()
+            };
+
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        }
+
+        fn next_frame_as_ipv6_packet<'a>(&mut self, buf: &'a mut [u8]) -> IPv6Packet<&'a [u8]> {
+            let len = self.write_next_frame(buf).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+            IPv6Packet::from_bytes(&buf[eth.payload_offset()..len]).unwrap()
+        }
+
         fn next_frame_as_ipv4_packet<'a>(&mut self, buf: &'a mut [u8]) -> IPv4Packet<&'a [u8]> {
             let len = self.write_next_frame(buf).unwrap().get();
             let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
@@ -531,6 +793,120 @@ mod tests {
         assert!(ns.write_next_frame(buf.as_mut()).is_none());
     }
 
+    #[test]
+    fn test_ns_ipv6() {
+        let mut ns =
+            MmdsNetworkStack::new_with_defaults(None, Arc::new(Mutex::new(Mmds::default())));
+        let mut buf = [0u8; 2000];
+
+        let remote_mac = MacAddr::parse_str(REMOTE_MAC_STR).unwrap();
+        let mmds_addr = Ipv6Addr::from_str(MMDS_IPV6_ADDR_STR).unwrap();
+        let bad_mmds_addr = Ipv6Addr::from_str("fd00:ec2::255").unwrap();
+
+        // The MMDS isn't reachable over IPv6 until it gets an IPv6 address.
+        {
+            let len = ns.write_neighbor_solicitation(buf.as_mut(), mmds_addr);
+            assert!(!ns.detour_frame(&buf[..len]));
+            let len = ns.write_incoming_tcp_segment_ipv6(buf.as_mut(), mmds_addr, TcpFlags::SYN);
+            assert!(!ns.detour_frame(&buf[..len]));
+            assert!(ns.write_next_frame(buf.as_mut()).is_none());
+        }
+
+        ns.set_ipv6_addr(Some(mmds_addr));
+
+        // Not asking for the MMDS MAC address.
+        {
+            let len = ns.write_neighbor_solicitation(buf.as_mut(), bad_mmds_addr);
+            assert!(!ns.detour_frame(&buf[..len]));
+            assert!(ns.write_next_frame(buf.as_mut()).is_none());
+        }
+
+        // Neighbor solicitations which crossed a router are ignored.
+        {
+            let len = ns.write_neighbor_solicitation(buf.as_mut(), mmds_addr);
+            IPv6Packet::from_bytes_unchecked(&mut buf[ETHERNET_PAYLOAD_OFFSET..len])
+                .set_hop_limit(NDP_HOP_LIMIT - 1);
+            assert!(!ns.detour_frame(&buf[..len]));
+        }
+
+        // Asking for the MMDS MAC address.
+        {
+            let len = ns.write_neighbor_solicitation(buf.as_mut(), mmds_addr);
+            assert!(ns.detour_frame(&buf[..len]));
+            assert_eq!(ns.remote_mac_addr, remote_mac);
+        }
+
+        // There should be a neighbor advertisement to send.
+        {
+            let ip = ns.next_frame_as_ipv6_packet(buf.as_mut());
+            assert_eq!(ip.next_header(), PROTOCOL_ICMPV6);
+            assert_eq!(ip.hop_limit(), NDP_HOP_LIMIT);
+            assert_eq!(ip.source_address(), mmds_addr);
+            assert_eq!(ip.destination_address(), REMOTE_IPV6_ADDR);
+
+            let na = NdpMessage::from_bytes_unchecked(ip.payload());
+            assert_eq!(na.message_type(), TYPE_NEIGHBOR_ADVERTISEMENT);
+            assert_eq!(na.flags(), FLAG_SOLICITED | FLAG_OVERRIDE);
+            assert_eq!(na.target_address(), mmds_addr);
+            assert_eq!(na.target_link_layer_address(), Some(ns.mac_addr));
+            assert_eq!(na.compute_checksum(mmds_addr, REMOTE_IPV6_ADDR), 0);
+        }
+
+        // Nothing to send anymore.
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // A TCP segment heading to the wrong address is rejected.
+        {
+            let len =
+                ns.write_incoming_tcp_segment_ipv6(buf.as_mut(), bad_mmds_addr, TcpFlags::SYN);
+            assert!(!ns.detour_frame(&buf[..len]));
+            assert!(ns.write_next_frame(buf.as_mut()).is_none());
+        }
+
+        // Let's send a TCP SYN into the ns.
+        {
+            let len = ns.write_incoming_tcp_segment_ipv6(buf.as_mut(), mmds_addr, TcpFlags::SYN);
+            let curr_rx_count = METRICS.mmds.rx_count.count();
+            assert!(ns.detour_frame(&buf[..len]));
+            assert_eq!(curr_rx_count + 1, METRICS.mmds.rx_count.count());
+        }
+
+        // We should be getting a SYNACK over IPv6 in response.
+        {
+            let ip = ns.next_frame_as_ipv6_packet(buf.as_mut());
+            assert_eq!(ip.next_header(), PROTOCOL_TCP);
+            assert_eq!(ip.source_address(), mmds_addr);
+            assert_eq!(ip.destination_address(), REMOTE_IPV6_ADDR);
+
+            let s = TcpSegment::from_bytes(ip.payload(), None).unwrap();
+            assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(s.source_port(), MMDS_PORT);
+            assert_eq!(s.destination_port(), REMOTE_PORT);
+            assert_eq!(s.ack_number(), SEQ_NUMBER.wrapping_add(1));
+            assert_eq!(s.compute_checksum_ipv6(mmds_addr, REMOTE_IPV6_ADDR), 0);
+        }
+
+        // Nothing else to send.
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+    }
+
+    #[test]
+    fn test_set_ipv6_addr() {
+        let mut ns =
+            MmdsNetworkStack::new_with_defaults(None, Arc::new(Mutex::new(Mmds::default())));
+        let mmds_addr = Ipv6Addr::from_str(MMDS_IPV6_ADDR_STR).unwrap();
+        assert_eq!(ns.ipv6_addr(), None);
+        assert_eq!(ns.tcp_handler.local_ipv6_addr(), Ipv6Addr::UNSPECIFIED);
+
+        ns.set_ipv6_addr(Some(mmds_addr));
+        assert_eq!(ns.ipv6_addr(), Some(mmds_addr));
+        assert_eq!(ns.tcp_handler.local_ipv6_addr(), mmds_addr);
+
+        ns.set_ipv6_addr(None);
+        assert_eq!(ns.ipv6_addr(), None);
+        assert_eq!(ns.tcp_handler.local_ipv6_addr(), Ipv6Addr::UNSPECIFIED);
+    }
+
     #[test]
     fn test_set_ipv4_addr() {
         let mut ns =
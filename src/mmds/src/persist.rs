@@ -1,14 +1,16 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Defines the structures needed for saving/restoring MmdsNetworkStack.
+//! Defines the structures needed for saving/restoring MmdsNetworkStack and the contents of the
+//! MMDS data store.
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::{Arc, Mutex};
 
+use serde_json::Value;
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
 use super::ns::MmdsNetworkStack;
@@ -23,6 +25,52 @@ pub struct MmdsNetworkStackState {
     tcp_port: u16,
     max_connections: usize,
     max_pending_resets: usize,
+    #[version(start = 2, default_fn = "def_ipv6_addr", ser_fn = "ser_ipv6_addr")]
+    ipv6_addr: Option<[u8; 16]>,
+}
+
+impl MmdsNetworkStackState {
+    fn def_ipv6_addr(_: u16) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn ser_ipv6_addr(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.ipv6_addr.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement MMDS over IPv6.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Contents of the MMDS data store.
+///
+/// The contents are saved apart from the network stack serving them, so that a snapshot can be
+/// taken without them and loaded along with fresh ones.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct MmdsState {
+    // The data store, as a JSON document.
+    data_store: String,
+}
+
+impl MmdsState {
+    /// Saves the contents of the data store of `mmds`, if it has any.
+    pub fn save(mmds: &Mmds) -> Option<Self> {
+        if !mmds.is_initialized() {
+            return None;
+        }
+        Some(MmdsState {
+            data_store: mmds.data_store_value().to_string(),
+        })
+    }
+
+    /// Returns the saved contents, to put in a data store.
+    pub fn data_store(&self) -> serde_json::Result<Value> {
+        serde_json::from_str(&self.data_store)
+    }
 }
 
 impl Persist<'_> for MmdsNetworkStack {
@@ -40,6 +88,7 @@ impl Persist<'_> for MmdsNetworkStack {
             tcp_port: self.tcp_handler.local_port(),
             max_connections: self.tcp_handler.max_connections(),
             max_pending_resets: self.tcp_handler.max_pending_resets(),
+            ipv6_addr: self.ipv6_addr.map(|addr| addr.octets()),
         }
     }
 
@@ -47,14 +96,16 @@ impl Persist<'_> for MmdsNetworkStack {
         mmds: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
-        Ok(MmdsNetworkStack::new(
+        let mut ns = MmdsNetworkStack::new(
             MacAddr::from_bytes_unchecked(&state.mac_addr),
             Ipv4Addr::from(state.ipv4_addr),
             state.tcp_port,
             std::num::NonZeroUsize::new(state.max_connections).unwrap(),
             std::num::NonZeroUsize::new(state.max_pending_resets).unwrap(),
             mmds,
-        ))
+        );
+        ns.set_ipv6_addr(state.ipv6_addr.map(Ipv6Addr::from));
+        Ok(ns)
     }
 }
 
@@ -64,23 +115,39 @@ mod tests {
 
     #[test]
     fn test_persistence() {
-        let ns = MmdsNetworkStack::new_with_defaults(None, Arc::new(Mutex::new(Mmds::default())));
+        let mut ns =
+            MmdsNetworkStack::new_with_defaults(None, Arc::new(Mutex::new(Mmds::default())));
+        ns.set_ipv6_addr(Some(Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254)));
 
         let mut mem = vec![0; 4096];
-        let version_map = VersionMap::new();
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(MmdsNetworkStackState::type_id(), 2);
 
-        ns.save()
+        // The IPv6 address can't be saved in the initial version of the state.
+        assert!(ns
+            .save()
             .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        ns.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
             .unwrap();
 
         let restored_ns = MmdsNetworkStack::restore(
             Arc::new(Mutex::new(Mmds::default())),
-            &MmdsNetworkStackState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+            &MmdsNetworkStackState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
         )
         .unwrap();
 
         assert_eq!(restored_ns.mac_addr, ns.mac_addr);
         assert_eq!(restored_ns.ipv4_addr, ns.ipv4_addr);
+        assert_eq!(restored_ns.ipv6_addr, ns.ipv6_addr);
+        assert_eq!(
+            restored_ns.tcp_handler.local_ipv6_addr(),
+            ns.tcp_handler.local_ipv6_addr()
+        );
         assert_eq!(
             restored_ns.tcp_handler.local_port(),
             ns.tcp_handler.local_port()
@@ -94,4 +161,24 @@ mod tests {
             ns.tcp_handler.max_pending_resets()
         );
     }
+
+    #[test]
+    fn test_mmds_state_persistence() {
+        let mut mmds = Mmds::default();
+        // An empty data store isn't saved.
+        assert!(MmdsState::save(&mmds).is_none());
+
+        let data = serde_json::json!({"latest": {"meta-data": {"ami-id": "ami-12345678"}}});
+        mmds.put_data(data.clone()).unwrap();
+        let state = MmdsState::save(&mmds).unwrap();
+
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_state = MmdsState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap();
+        assert_eq!(restored_state, state);
+        assert_eq!(restored_state.data_store().unwrap(), data);
+    }
 }
@@ -33,6 +33,11 @@
 //! The granularity for 'wake up' events when the rate limiter is blocked is
 //! currently hardcoded to `100 milliseconds`.
 //!
+//! Several rate limiters can also be part of a `RateLimiterGroup`, whose token
+//! buckets are shared by all of its members. Tokens are then consumed both from
+//! the buckets of the limiter and from the ones of its group, which caps the
+//! aggregate rate of the group members.
+//!
 //! ## Limitations
 //!
 //! This rate limiter implementation relies on the *Linux kernel's timerfd* so its
@@ -43,6 +48,7 @@
 //! trait and provides an *event-handler* as part of its API. This *event-handler*
 //! needs to be called by the user on every event on the rate limiter's `AsRawFd` FD.
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{fmt, io};
 
@@ -259,6 +265,8 @@ pub enum TokenType {
     Bytes,
     /// Token type used for operations/second limiting.
     Ops,
+    /// Token type used for packets/second limiting.
+    Packets,
 }
 
 /// Enum that describes the type of token bucket update.
@@ -271,6 +279,101 @@ pub enum BucketUpdate {
     Update(TokenBucket),
 }
 
+// The token buckets shared by the members of a `RateLimiterGroup`.
+#[derive(Debug)]
+struct GroupBuckets {
+    bandwidth: Option<TokenBucket>,
+    ops: Option<TokenBucket>,
+}
+
+impl GroupBuckets {
+    fn bucket_mut(&mut self, token_type: &TokenType) -> Option<&mut TokenBucket> {
+        match token_type {
+            TokenType::Bytes => self.bandwidth.as_mut(),
+            TokenType::Ops => self.ops.as_mut(),
+            // Groups don't limit packets.
+            TokenType::Packets => None,
+        }
+    }
+}
+
+/// Token buckets shared by the rate limiters of several devices, so that the aggregate rate
+/// of these devices is limited on top of their own limits.
+///
+/// Clones of a group share the same token buckets.
+#[derive(Clone, Debug)]
+pub struct RateLimiterGroup {
+    id: String,
+    buckets: Arc<Mutex<GroupBuckets>>,
+}
+
+impl PartialEq for RateLimiterGroup {
+    fn eq(&self, other: &RateLimiterGroup) -> bool {
+        Arc::ptr_eq(&self.buckets, &other.buckets)
+    }
+}
+
+impl RateLimiterGroup {
+    /// Creates a new group identified by `id`, limiting on bytes/s through `bandwidth` and
+    /// on ops/s through `ops`. A group without a bucket for a token type doesn't limit it.
+    pub fn new(id: String, bandwidth: Option<TokenBucket>, ops: Option<TokenBucket>) -> Self {
+        RateLimiterGroup {
+            id,
+            buckets: Arc::new(Mutex::new(GroupBuckets { bandwidth, ops })),
+        }
+    }
+
+    /// Returns the identifier of the group.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns a copy of the shared bandwidth token bucket.
+    pub fn bandwidth(&self) -> Option<TokenBucket> {
+        self.buckets
+            .lock()
+            .expect("Poisoned lock")
+            .bandwidth
+            .clone()
+    }
+
+    /// Returns a copy of the shared ops token bucket.
+    pub fn ops(&self) -> Option<TokenBucket> {
+        self.buckets.lock().expect("Poisoned lock").ops.clone()
+    }
+
+    /// Updates the token buckets of the group, for all of its members.
+    pub fn update_buckets(&self, bytes: BucketUpdate, ops: BucketUpdate) {
+        let mut buckets = self.buckets.lock().expect("Poisoned lock");
+        match bytes {
+            BucketUpdate::Disabled => buckets.bandwidth = None,
+            BucketUpdate::Update(tb) => buckets.bandwidth = Some(tb),
+            BucketUpdate::None => (),
+        };
+        match ops {
+            BucketUpdate::Disabled => buckets.ops = None,
+            BucketUpdate::Update(tb) => buckets.ops = Some(tb),
+            BucketUpdate::None => (),
+        };
+    }
+
+    // Attempts to consume `tokens` from the shared bucket of `token_type`. Returns the refill
+    // time of the bucket along with the outcome, or `None` if the token type isn't limited.
+    fn reduce(&self, tokens: u64, token_type: &TokenType) -> Option<(u64, BucketReduction)> {
+        let mut buckets = self.buckets.lock().expect("Poisoned lock");
+        buckets
+            .bucket_mut(token_type)
+            .map(|bucket| (bucket.refill_time_ms(), bucket.reduce(tokens)))
+    }
+
+    fn force_replenish(&self, tokens: u64, token_type: &TokenType) {
+        let mut buckets = self.buckets.lock().expect("Poisoned lock");
+        if let Some(bucket) = buckets.bucket_mut(token_type) {
+            bucket.force_replenish(tokens);
+        }
+    }
+}
+
 /// Rate Limiter that works on both bandwidth and ops/s limiting.
 ///
 /// Bandwidth (bytes/s) and ops/s limiting can be used at the same time or individually.
@@ -289,6 +392,9 @@ pub enum BucketUpdate {
 pub struct RateLimiter {
     bandwidth: Option<TokenBucket>,
     ops: Option<TokenBucket>,
+    pps: Option<TokenBucket>,
+    // Buckets shared with the rate limiters of other devices, if any.
+    group: Option<RateLimiterGroup>,
 
     timer_fd: TimerFd,
     // Internal flag that quickly determines timer state.
@@ -297,7 +403,10 @@ pub struct RateLimiter {
 
 impl PartialEq for RateLimiter {
     fn eq(&self, other: &RateLimiter) -> bool {
-        self.bandwidth == other.bandwidth && self.ops == other.ops
+        self.bandwidth == other.bandwidth
+            && self.ops == other.ops
+            && self.pps == other.pps
+            && self.group == other.group
     }
 }
 
@@ -330,6 +439,8 @@ impl RateLimiter {
     /// If either bytes/ops *size* or *refill_time* are **zero**, the limiter
     /// is **disabled** for that respective token type.
     ///
+    /// The limiter starts with packets/s limiting disabled, see `update_pps()`.
+    ///
     /// # Errors
     ///
     /// If the timerfd creation fails, an error is returned.
@@ -361,6 +472,8 @@ impl RateLimiter {
         Ok(RateLimiter {
             bandwidth: bytes_token_bucket,
             ops: ops_token_bucket,
+            pps: None,
+            group: None,
             timer_fd,
             timer_active: false,
         })
@@ -373,71 +486,97 @@ impl RateLimiter {
         self.timer_active = true;
     }
 
+    // Identify the token bucket of this limiter for `token_type`.
+    fn token_bucket_mut(&mut self, token_type: &TokenType) -> Option<&mut TokenBucket> {
+        match token_type {
+            TokenType::Bytes => self.bandwidth.as_mut(),
+            TokenType::Ops => self.ops.as_mut(),
+            TokenType::Packets => self.pps.as_mut(),
+        }
+    }
+
+    // Arms the timer as needed by the outcome of a bucket reduction, and returns whether the
+    // tokens were consumed.
+    fn handle_reduction(&mut self, reduction: BucketReduction, refill_time: u64) -> bool {
+        match reduction {
+            // When we report budget is over, there will be no further calls here,
+            // register a timer to replenish the bucket and resume processing;
+            // make sure there is only one running timer for this limiter.
+            BucketReduction::Failure => {
+                if !self.timer_active {
+                    self.activate_timer(TIMER_REFILL_STATE);
+                }
+                false
+            }
+            // The operation succeeded and further calls can be made.
+            BucketReduction::Success => true,
+            // The operation succeeded as the tokens have been consumed
+            // but the timer still needs to be armed.
+            BucketReduction::OverConsumption(ratio) => {
+                // The operation "borrowed" a number of tokens `ratio` times
+                // greater than the size of the bucket, and since it takes
+                // `refill_time` milliseconds to fill an empty bucket, in
+                // order to enforce the bandwidth limit we need to prevent
+                // further calls to the rate limiter for
+                // `ratio * refill_time` milliseconds.
+                self.activate_timer(TimerState::Oneshot(Duration::from_millis(
+                    (ratio * refill_time as f64) as u64,
+                )));
+                true
+            }
+        }
+    }
+
     /// Attempts to consume tokens and returns whether that is possible.
     ///
-    /// If rate limiting is disabled on provided `token_type`, this function will always succeed.
+    /// If rate limiting is disabled on provided `token_type`, both for this limiter and for its
+    /// group, this function will always succeed.
     pub fn consume(&mut self, tokens: u64, token_type: TokenType) -> bool {
         // If the timer is active, we can't consume tokens from any bucket and the function fails.
         if self.timer_active {
             return false;
         }
 
-        // Identify the required token bucket.
-        let token_bucket = match token_type {
-            TokenType::Bytes => self.bandwidth.as_mut(),
-            TokenType::Ops => self.ops.as_mut(),
-        };
-        // Try to consume from the token bucket.
-        if let Some(bucket) = token_bucket {
+        // Try to consume from the token bucket. If bucket is not present rate limiting is
+        // disabled on token type for this limiter.
+        if let Some(bucket) = self.token_bucket_mut(&token_type) {
             let refill_time = bucket.refill_time_ms();
-            match bucket.reduce(tokens) {
-                // When we report budget is over, there will be no further calls here,
-                // register a timer to replenish the bucket and resume processing;
-                // make sure there is only one running timer for this limiter.
-                BucketReduction::Failure => {
-                    if !self.timer_active {
-                        self.activate_timer(TIMER_REFILL_STATE);
-                    }
-                    false
-                }
-                // The operation succeeded and further calls can be made.
-                BucketReduction::Success => true,
-                // The operation succeeded as the tokens have been consumed
-                // but the timer still needs to be armed.
-                BucketReduction::OverConsumption(ratio) => {
-                    // The operation "borrowed" a number of tokens `ratio` times
-                    // greater than the size of the bucket, and since it takes
-                    // `refill_time` milliseconds to fill an empty bucket, in
-                    // order to enforce the bandwidth limit we need to prevent
-                    // further calls to the rate limiter for
-                    // `ratio * refill_time` milliseconds.
-                    self.activate_timer(TimerState::Oneshot(Duration::from_millis(
-                        (ratio * refill_time as f64) as u64,
-                    )));
-                    true
+            let reduction = bucket.reduce(tokens);
+            if !self.handle_reduction(reduction, refill_time) {
+                return false;
+            }
+        }
+
+        // The tokens are also consumed from the bucket shared with the rest of the group.
+        let group_reduction = self
+            .group
+            .as_ref()
+            .and_then(|group| group.reduce(tokens, &token_type));
+        if let Some((refill_time, reduction)) = group_reduction {
+            if reduction == BucketReduction::Failure {
+                // The operation doesn't go through, so it gives back what it took from the
+                // bucket of this limiter.
+                if let Some(bucket) = self.token_bucket_mut(&token_type) {
+                    bucket.force_replenish(tokens);
                 }
             }
-        } else {
-            // If bucket is not present rate limiting is disabled on token type,
-            // consume() will always succeed.
-            true
+            return self.handle_reduction(reduction, refill_time);
         }
+        true
     }
 
-    /// Adds tokens of `token_type` to their respective bucket.
+    /// Adds tokens of `token_type` to their respective bucket, and to the one of the group.
     ///
     /// Can be used to *manually* add tokens to a bucket. Useful for reverting a
     /// `consume()` if needed.
     pub fn manual_replenish(&mut self, tokens: u64, token_type: TokenType) {
-        // Identify the required token bucket.
-        let token_bucket = match token_type {
-            TokenType::Bytes => self.bandwidth.as_mut(),
-            TokenType::Ops => self.ops.as_mut(),
-        };
         // Add tokens to the token bucket.
-        if let Some(bucket) = token_bucket {
+        if let Some(bucket) = self.token_bucket_mut(&token_type) {
             bucket.force_replenish(tokens);
         }
+        if let Some(group) = self.group.as_ref() {
+            group.force_replenish(tokens, &token_type);
+        }
     }
 
     /// Returns whether this rate limiter is blocked.
@@ -482,6 +621,15 @@ impl RateLimiter {
         };
     }
 
+    /// Updates the parameters of the packets/s token bucket associated with this RateLimiter.
+    pub fn update_pps(&mut self, pps: BucketUpdate) {
+        match pps {
+            BucketUpdate::Disabled => self.pps = None,
+            BucketUpdate::Update(tb) => self.pps = Some(tb),
+            BucketUpdate::None => (),
+        };
+    }
+
     /// Returns an immutable view of the inner bandwidth token bucket.
     pub fn bandwidth(&self) -> Option<&TokenBucket> {
         self.bandwidth.as_ref()
@@ -491,6 +639,21 @@ impl RateLimiter {
     pub fn ops(&self) -> Option<&TokenBucket> {
         self.ops.as_ref()
     }
+
+    /// Returns an immutable view of the inner packets/s token bucket.
+    pub fn pps(&self) -> Option<&TokenBucket> {
+        self.pps.as_ref()
+    }
+
+    /// Makes this rate limiter a member of `group`, or of no group at all.
+    pub fn set_group(&mut self, group: Option<RateLimiterGroup>) {
+        self.group = group;
+    }
+
+    /// Returns the group this rate limiter is a member of, if any.
+    pub fn group(&self) -> Option<&RateLimiterGroup> {
+        self.group.as_ref()
+    }
 }
 
 impl AsRawFd for RateLimiter {
@@ -553,6 +716,7 @@ pub(crate) mod tests {
             match token_type {
                 TokenType::Bytes => self.bandwidth.as_ref(),
                 TokenType::Ops => self.ops.as_ref(),
+                TokenType::Packets => self.pps.as_ref(),
             }
         }
     }
@@ -747,6 +911,35 @@ pub(crate) mod tests {
         assert!(l.consume(100, TokenType::Ops));
     }
 
+    #[test]
+    fn test_rate_limiter_pps() {
+        let mut l = RateLimiter::new(0, 0, 0, 0, 0, 0).unwrap();
+        // packets/s limiting is disabled by default
+        assert!(l.pps().is_none());
+        assert!(l.consume(u64::max_value(), TokenType::Packets));
+
+        // limit of 1000 packets/s
+        l.update_pps(BucketUpdate::Update(
+            TokenBucket::new(1000, 0, 1000).unwrap(),
+        ));
+        assert_eq!(l.pps().unwrap().capacity(), 1000);
+        // bytes and ops are accounted independently of packets
+        assert!(l.consume(u64::max_value(), TokenType::Ops));
+        assert!(l.consume(1000, TokenType::Packets));
+        assert!(!l.consume(100, TokenType::Packets));
+        assert!(l.is_blocked());
+        thread::sleep(Duration::from_millis(REFILL_TIMER_INTERVAL_MS));
+        assert!(l.event_handler().is_ok());
+        assert!(!l.is_blocked());
+        assert!(l.consume(100, TokenType::Packets));
+
+        // updates without a pps bucket leave it untouched
+        l.update_pps(BucketUpdate::None);
+        assert!(l.pps().is_some());
+        l.update_pps(BucketUpdate::Disabled);
+        assert!(l.pps().is_none());
+    }
+
     #[test]
     fn test_rate_limiter_full() {
         // rate limiter with limit of 1000 bytes/s and 1000 ops/s
@@ -872,6 +1065,67 @@ pub(crate) mod tests {
         assert_eq!(x.ops, None);
     }
 
+    #[test]
+    fn test_rate_limiter_group() {
+        // group with a limit of 10 bytes/s, shared by two limiters without limits of their own
+        let group = RateLimiterGroup::new(
+            "group".to_string(),
+            Some(TokenBucket::new(1000, 0, 100_000).unwrap()),
+            None,
+        );
+        assert_eq!(group.id(), "group");
+        let mut l1 = RateLimiter::default();
+        let mut l2 = RateLimiter::default();
+        l1.set_group(Some(group.clone()));
+        l2.set_group(Some(group.clone()));
+        assert_eq!(l1.group(), Some(&group));
+        assert_eq!(l1, l2);
+        assert_ne!(l1, RateLimiter::default());
+
+        // the limiters draw from the same budget
+        assert!(l1.consume(600, TokenType::Bytes));
+        assert!(!l2.consume(600, TokenType::Bytes));
+        assert!(l2.is_blocked());
+        assert!(!l1.is_blocked());
+        assert_eq!(group.bandwidth().unwrap().budget(), 400);
+        // the group doesn't limit ops
+        assert!(l1.consume(u64::max_value(), TokenType::Ops));
+
+        // replenishing a limiter replenishes its group
+        l1.manual_replenish(100, TokenType::Bytes);
+        assert_eq!(group.bandwidth().unwrap().budget(), 500);
+
+        // wait for the timer of the blocked limiter
+        thread::sleep(Duration::from_millis(REFILL_TIMER_INTERVAL_MS));
+        assert!(l2.event_handler().is_ok());
+        assert!(!l2.is_blocked());
+        assert!(l2.consume(100, TokenType::Bytes));
+    }
+
+    #[test]
+    fn test_rate_limiter_group_own_buckets() {
+        let group = RateLimiterGroup::new(
+            "group".to_string(),
+            Some(TokenBucket::new(1000, 0, 100_000).unwrap()),
+            None,
+        );
+        // limiter with its own limit of 2000 bytes/s
+        let mut l = RateLimiter::new(2000, 0, 1000, 0, 0, 0).unwrap();
+        l.set_group(Some(group.clone()));
+
+        // the group limit is hit first, and the limiter gets its tokens back
+        assert!(l.consume(800, TokenType::Bytes));
+        assert!(!l.consume(800, TokenType::Bytes));
+        assert_eq!(l.get_token_bucket(TokenType::Bytes).unwrap().budget(), 1200);
+        assert_eq!(group.bandwidth().unwrap().budget(), 200);
+
+        // updating the group updates it for all of its members
+        group.update_buckets(BucketUpdate::Disabled, BucketUpdate::None);
+        assert!(group.bandwidth().is_none());
+        l.set_group(None);
+        assert!(l.group().is_none());
+    }
+
     #[test]
     fn test_rate_limiter_debug() {
         let l = RateLimiter::new(1, 2, 3, 4, 5, 6).unwrap();
@@ -4,7 +4,7 @@
 //! Defines the structures needed for saving/restoring a RateLimiter.
 
 use snapshot::Persist;
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
 use super::*;
@@ -58,6 +58,24 @@ impl Persist<'_> for TokenBucket {
 pub struct RateLimiterState {
     ops: Option<TokenBucketState>,
     bandwidth: Option<TokenBucketState>,
+    #[version(start = 2, default_fn = "def_pps", ser_fn = "ser_pps")]
+    pps: Option<TokenBucketState>,
+}
+
+impl RateLimiterState {
+    fn def_pps(_: u16) -> Option<TokenBucketState> {
+        None
+    }
+
+    fn ser_pps(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.pps.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement packets/s rate limiting.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl Persist<'_> for RateLimiter {
@@ -69,6 +87,7 @@ impl Persist<'_> for RateLimiter {
         RateLimiterState {
             ops: self.ops.as_ref().map(|ops| ops.save()),
             bandwidth: self.bandwidth.as_ref().map(|bw| bw.save()),
+            pps: self.pps.as_ref().map(|pps| pps.save()),
         }
     }
 
@@ -84,6 +103,13 @@ impl Persist<'_> for RateLimiter {
             } else {
                 None
             },
+            pps: if let Some(pps) = state.pps.as_ref() {
+                Some(TokenBucket::restore((), pps)?)
+            } else {
+                None
+            },
+            // Devices whose rate limiters are part of a group can't be snapshotted.
+            group: None,
             timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
             timer_active: false,
         };
@@ -206,4 +232,38 @@ mod tests {
             .unwrap()
             .partial_eq(&restored_rate_limiter.bandwidth().unwrap()));
     }
+
+    #[test]
+    fn test_rate_limiter_pps_persistence() {
+        let mut rate_limiter = RateLimiter::new(0, 0, 0, 0, 0, 0).unwrap();
+        rate_limiter.update_pps(BucketUpdate::Update(
+            TokenBucket::new(100, 0, 100_000).unwrap(),
+        ));
+        rate_limiter.consume(10, TokenType::Packets);
+
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(RateLimiterState::type_id(), 2);
+        let mut mem = vec![0; 4096];
+        rate_limiter
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_rate_limiter = RateLimiter::restore(
+            (),
+            &RateLimiterState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert!(rate_limiter
+            .pps()
+            .unwrap()
+            .partial_eq(&restored_rate_limiter.pps().unwrap()));
+
+        // Older versions can't hold the packets/s bucket.
+        assert!(rate_limiter
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+    }
 }
@@ -291,6 +291,14 @@ pub(crate) struct SeccompFilter {
 }
 
 impl SeccompCondition {
+    /// Returns whether both conditions perform the same check, regardless of their comments.
+    pub fn same_check(&self, other: &SeccompCondition) -> bool {
+        self.arg_number == other.arg_number
+            && self.arg_len == other.arg_len
+            && self.operator == other.operator
+            && self.value == other.value
+    }
+
     /// Validates the SeccompCondition data
     pub fn validate(&self) -> Result<()> {
         // Checks that the given argument number is valid.
@@ -38,6 +38,8 @@ type Result<T> = result::Result<T, Error>;
 pub(crate) enum Error {
     /// Filter and default actions are equal.
     IdenticalActions,
+    /// Rule to remove from the extended filter of a thread, which it doesn't hold.
+    RemovedRuleNotFound(String, String),
     /// Error from the SeccompFilter.
     SeccompFilter(SeccompFilterError),
     /// Invalid syscall name for the given arch.
@@ -50,6 +52,11 @@ impl fmt::Display for Error {
 
         match *self {
             IdenticalActions => write!(f, "`filter_action` and `default_action` are equal."),
+            RemovedRuleNotFound(ref thread_name, ref syscall_name) => write!(
+                f,
+                "No rule to remove for syscall: {} in the extended filter of thread: {}.",
+                syscall_name, thread_name
+            ),
             SeccompFilter(ref err) => write!(f, "{}", err),
             SyscallName(ref syscall_name, ref arch) => write!(
                 f,
@@ -60,8 +67,43 @@ impl fmt::Display for Error {
     }
 }
 
+/// Key of the Json filter file holding the path of the file it extends.
+const EXTENDS_KEY: &str = "extends";
+
 /// Deserializable object that represents the Json filter file.
-pub(crate) struct JsonFile(pub HashMap<String, Filter>);
+pub(crate) struct JsonFile {
+    /// Path of the file whose filters this one extends, relative to this one.
+    pub extends: Option<String>,
+    /// The filters of each thread category.
+    pub filters: HashMap<String, Filter>,
+}
+
+impl JsonFile {
+    /// Resolves the filters of the file on top of the `base` ones, of the file it extends.
+    ///
+    /// The threads missing from the file keep their base filter. The others get the actions of
+    /// the file, and the rules of the base filter, minus the removed ones, followed by their own.
+    pub fn resolve(self, mut base: HashMap<String, Filter>) -> Result<HashMap<String, Filter>> {
+        for (thread_name, mut filter) in self.filters {
+            let mut rules = base
+                .remove(&thread_name)
+                .map(|base_filter| base_filter.filter)
+                .unwrap_or_default();
+            for removed in filter.remove.drain(..) {
+                let len = rules.len();
+                rules.retain(|rule| !rule.is_removed_by(&removed));
+                // The base filter no longer holds the rule, which is likely a mistake.
+                if rules.len() == len {
+                    return Err(Error::RemovedRuleNotFound(thread_name, removed.syscall));
+                }
+            }
+            rules.append(&mut filter.filter);
+            filter.filter = rules;
+            base.insert(thread_name, filter);
+        }
+        Ok(base)
+    }
+}
 
 // Implement a custom deserializer, that returns an error for duplicate thread keys.
 impl<'de> Deserialize<'de> for JsonFile {
@@ -72,7 +114,7 @@ impl<'de> Deserialize<'de> for JsonFile {
         struct JsonFileVisitor;
 
         impl<'d> Visitor<'d> for JsonFileVisitor {
-            type Value = HashMap<String, Filter>;
+            type Value = JsonFile;
 
             fn expecting(&self, f: &mut fmt::Formatter<'_>) -> result::Result<(), fmt::Error> {
                 f.write_str("a map of filters")
@@ -82,18 +124,23 @@ impl<'de> Deserialize<'de> for JsonFile {
             where
                 M: MapAccess<'d>,
             {
-                let mut values = Self::Value::with_capacity(access.size_hint().unwrap_or(0));
-
-                while let Some((key, value)) = access.next_entry()? {
-                    if values.insert(key, value).is_some() {
+                let mut extends = None;
+                let mut filters = HashMap::with_capacity(access.size_hint().unwrap_or(0));
+
+                while let Some(key) = access.next_key::<String>()? {
+                    if key == EXTENDS_KEY {
+                        if extends.replace(access.next_value()?).is_some() {
+                            return Err(M::Error::duplicate_field(EXTENDS_KEY));
+                        }
+                    } else if filters.insert(key, access.next_value()?).is_some() {
                         return Err(M::Error::custom("duplicate filter key"));
                     };
                 }
 
-                Ok(values)
+                Ok(JsonFile { extends, filters })
             }
         }
-        Ok(JsonFile(deserializer.deserialize_map(JsonFileVisitor)?))
+        deserializer.deserialize_map(JsonFileVisitor)
     }
 }
 
@@ -124,6 +171,24 @@ impl SyscallRule {
 
         Ok(())
     }
+
+    /// Returns whether the rule is one of the rules `removed` stands for: all the ones of the
+    /// syscall when it has no conditions, or the ones with the same conditions otherwise.
+    fn is_removed_by(&self, removed: &SyscallRule) -> bool {
+        if self.syscall != removed.syscall {
+            return false;
+        }
+        match (&self.conditions, &removed.conditions) {
+            (_, None) => true,
+            (Some(conditions), Some(removed_conditions)) => {
+                conditions.len() == removed_conditions.len()
+                    && removed_conditions.iter().all(|removed_cond| {
+                        conditions.iter().any(|cond| cond.same_check(removed_cond))
+                    })
+            }
+            (None, Some(_)) => false,
+        }
+    }
 }
 
 /// Deserializable seccomp filter. Refers to one thread category.
@@ -136,6 +201,9 @@ pub(crate) struct Filter {
     filter_action: SeccompAction,
     /// The collection of `SyscallRule`s.
     filter: Vec<SyscallRule>,
+    /// The `SyscallRule`s to remove from the filter being extended.
+    #[serde(default)]
+    remove: Vec<SyscallRule>,
 }
 
 impl Filter {
@@ -271,7 +339,7 @@ mod tests {
     use std::convert::TryInto;
     use std::env::consts::ARCH;
 
-    use super::{Compiler, Error, Filter, SyscallRule};
+    use super::{Compiler, Error, Filter, JsonFile, SyscallRule};
     use crate::backend::SeccompCmpArgLen::*;
     use crate::backend::SeccompCmpOp::*;
     use crate::backend::{
@@ -289,6 +357,7 @@ mod tests {
                 default_action,
                 filter_action,
                 filter,
+                remove: vec![],
             }
         }
     }
@@ -464,6 +533,124 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve() {
+        let ioctl_rule = |val| {
+            SyscallRule::new(
+                "ioctl".to_string(),
+                Some(vec![Cond::new(1, Dword, Eq, val).unwrap()]),
+            )
+        };
+        let mut base = HashMap::new();
+        base.insert(
+            "vmm".to_string(),
+            Filter::new(
+                SeccompAction::Trap,
+                SeccompAction::Allow,
+                vec![
+                    SyscallRule::new("read".to_string(), None),
+                    SyscallRule::new("write".to_string(), None),
+                    SyscallRule::new("write".to_string(), Some(vec![])),
+                    ioctl_rule(1),
+                    ioctl_rule(2),
+                ],
+            ),
+        );
+        base.insert(
+            "api".to_string(),
+            Filter::new(
+                SeccompAction::Trap,
+                SeccompAction::Allow,
+                vec![SyscallRule::new("read".to_string(), None)],
+            ),
+        );
+
+        // A file which doesn't extend any other is resolved on top of no filters.
+        let json_file: JsonFile = serde_json::from_str(
+            r#"{"vcpu": {"default_action": "trap", "filter_action": "allow", "filter": []}}"#,
+        )
+        .unwrap();
+        assert!(json_file.extends.is_none());
+        assert_eq!(json_file.resolve(HashMap::new()).unwrap().len(), 1);
+
+        // The comments don't matter when matching the conditions of the removed rules.
+        let json_file: JsonFile = serde_json::from_str(
+            r#"
+            {
+                "extends": "base.json",
+                "vmm": {
+                    "default_action": "log",
+                    "filter_action": "allow",
+                    "filter": [{"syscall": "close"}],
+                    "remove": [
+                        {"syscall": "write"},
+                        {
+                            "syscall": "ioctl",
+                            "args": [
+                                {"index": 1, "type": "dword", "op": "eq", "val": 2, "comment": "2"}
+                            ]
+                        }
+                    ]
+                },
+                "vcpu": {"default_action": "trap", "filter_action": "allow", "filter": []}
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(json_file.extends, Some("base.json".to_string()));
+        let filters = json_file.resolve(base.clone()).unwrap();
+        assert_eq!(filters.len(), 3);
+        assert_eq!(filters["api"], base["api"]);
+        assert_eq!(
+            filters["vmm"],
+            Filter::new(
+                SeccompAction::Log,
+                SeccompAction::Allow,
+                vec![
+                    SyscallRule::new("read".to_string(), None),
+                    ioctl_rule(1),
+                    SyscallRule::new("close".to_string(), None),
+                ],
+            )
+        );
+        assert_eq!(filters["vcpu"].filter, vec![]);
+
+        // The removed rules must be in the base filter.
+        for removed in &[
+            r#"{"syscall": "close"}"#,
+            r#"{"syscall": "read", "args": []}"#,
+            r#"{"syscall": "ioctl", "args": [{"index": 1, "type": "dword", "op": "eq", "val": 3}]}"#,
+        ] {
+            let json_file: JsonFile = serde_json::from_str(&format!(
+                r#"{{"vmm": {{"default_action": "trap", "filter_action": "allow", "filter": [],
+                    "remove": [{}]}}}}"#,
+                removed
+            ))
+            .unwrap();
+            assert!(matches!(
+                json_file.resolve(base.clone()),
+                Err(Error::RemovedRuleNotFound(..))
+            ));
+        }
+        // Including when the thread has no base filter.
+        let json_file: JsonFile = serde_json::from_str(
+            r#"{"vcpu": {"default_action": "trap", "filter_action": "allow", "filter": [],
+                "remove": [{"syscall": "read"}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            json_file.resolve(base).unwrap_err(),
+            Error::RemovedRuleNotFound("vcpu".to_string(), "read".to_string())
+        );
+
+        // The path of the extended file is a string, given once.
+        assert!(serde_json::from_str::<JsonFile>(r#"{"extends": 1}"#).is_err());
+        assert!(
+            serde_json::from_str::<JsonFile>(r#"{"extends": "a.json", "extends": "b.json"}"#)
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_compile_blob() {
         let compiler = Compiler::new(ARCH.try_into().unwrap());
@@ -541,6 +728,13 @@ mod tests {
             format!("{}", Error::IdenticalActions),
             "`filter_action` and `default_action` are equal."
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::RemovedRuleNotFound("vmm".to_string(), "ioctl".to_string())
+            ),
+            "No rule to remove for syscall: ioctl in the extended filter of thread: vmm."
+        );
         assert_eq!(
             format!(
                 "{}",
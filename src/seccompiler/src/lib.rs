@@ -31,6 +31,15 @@ struct sock_fprog {
 /// Reference to program made up of a sequence of BPF instructions.
 pub type BpfProgramRef<'a> = &'a [sock_filter];
 
+// BPF instruction returning a constant, see /usr/include/linux/filter.h .
+const BPF_RET_K: u16 = 0x06;
+// Seccomp actions, see /usr/include/linux/seccomp.h .
+const SECCOMP_RET_ACTION_FULL: u32 = 0xffff_0000;
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_KILL_THREAD: u32 = 0x0000_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+
 /// Binary filter deserialization errors.
 #[derive(Debug)]
 pub enum DeserializationError {
@@ -100,6 +109,30 @@ pub fn deserialize_binary<R: Read>(
         .collect())
 }
 
+/// Turns a BPF program into one which lets through the syscalls it would kill or trap on.
+///
+/// The kernel logs these syscalls instead, as `SECCOMP_RET_LOG` audit records, provided that
+/// `log` is part of the `/proc/sys/kernel/seccomp/actions_logged` actions.
+pub fn to_log_mode(bpf_filter: BpfProgramRef) -> BpfProgram {
+    bpf_filter
+        .iter()
+        .map(|insn| {
+            let mut insn = insn.clone();
+            let blocks = [
+                SECCOMP_RET_KILL_PROCESS,
+                SECCOMP_RET_KILL_THREAD,
+                SECCOMP_RET_TRAP,
+            ]
+            .iter()
+            .any(|action| insn.code == BPF_RET_K && insn.k & SECCOMP_RET_ACTION_FULL == *action);
+            if blocks {
+                insn.k = SECCOMP_RET_LOG;
+            }
+            insn
+        })
+        .collect()
+}
+
 /// Helper function for installing a BPF filter.
 pub fn apply_filter(bpf_filter: BpfProgramRef) -> std::result::Result<(), InstallationError> {
     // If the program is empty, don't install the filter.
@@ -149,6 +182,40 @@ mod tests {
 
     use super::*;
     use crate::common::BpfProgram;
+
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+    // Filter trapping on `getpid`, and allowing the other syscalls.
+    fn getpid_filter() -> BpfProgram {
+        vec![
+            // Load the syscall number.
+            sock_filter {
+                code: 0x20,
+                jt: 0,
+                jf: 0,
+                k: 0,
+            },
+            sock_filter {
+                code: 0x15,
+                jt: 0,
+                jf: 1,
+                k: libc::SYS_getpid as u32,
+            },
+            sock_filter {
+                code: BPF_RET_K,
+                jt: 0,
+                jf: 0,
+                k: SECCOMP_RET_TRAP,
+            },
+            sock_filter {
+                code: BPF_RET_K,
+                jt: 0,
+                jf: 0,
+                k: SECCOMP_RET_ALLOW,
+            },
+        ]
+    }
+
     #[test]
     fn test_deserialize_binary() {
         // Malformed bincode binary.
@@ -281,4 +348,22 @@ mod tests {
         .join()
         .unwrap();
     }
+
+    #[test]
+    fn test_log_mode() {
+        let filter = to_log_mode(&getpid_filter());
+        assert_eq!(filter[..2], getpid_filter()[..2]);
+        assert_eq!(filter[2].k, SECCOMP_RET_LOG);
+        assert_eq!(filter[3].k, SECCOMP_RET_ALLOW);
+
+        // The syscall is let through.
+        thread::spawn(move || {
+            apply_filter(&filter).unwrap();
+            // Libc may cache the pid.
+            let pid = unsafe { libc::syscall(libc::SYS_getpid) };
+            assert_eq!(pid, i64::from(std::process::id()));
+        })
+        .join()
+        .unwrap();
+    }
 }
@@ -39,15 +39,15 @@ mod syscall_table;
 
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{fmt, io, process};
 
 use backend::{TargetArch, TargetArchError};
 use bincode::Error as BincodeError;
 use common::BpfProgram;
-use compiler::{Compiler, Error as FilterFormatError, JsonFile};
+use compiler::{Compiler, Error as FilterFormatError, Filter, JsonFile};
 use serde_json::error::Error as JSONError;
 use utils::arg_parser::{ArgParser, Argument, Arguments as ArgumentsBag};
 
@@ -58,6 +58,7 @@ const EXIT_CODE_ERROR: i32 = 1;
 #[derive(Debug)]
 enum Error {
     Bincode(BincodeError),
+    ExtendsCycle(PathBuf),
     FileOpen(PathBuf, io::Error),
     FileFormat(FilterFormatError),
     Json(JSONError),
@@ -74,6 +75,11 @@ impl fmt::Display for Error {
 
         match *self {
             Bincode(ref err) => write!(f, "Bincode (de)serialization failed: {}", err),
+            ExtendsCycle(ref path) => write!(
+                f,
+                "{}",
+                format!("Filter file {:?} ends up extending itself.", path).replace("\"", "")
+            ),
             FileFormat(ref err) => write!(f, "{}", err),
             FileOpen(ref path, ref err) => write!(
                 f,
@@ -163,16 +169,41 @@ fn parse_json(reader: &mut dyn Read) -> Result<JsonFile> {
     serde_json::from_reader(reader).map_err(Error::Json)
 }
 
-fn compile(args: &Arguments) -> Result<()> {
-    let input_file = File::open(&args.input_file)
-        .map_err(|err| Error::FileOpen(PathBuf::from(&args.input_file), err))?;
+// Parses the filters of the JSON file at `path`, along with the ones of the files it extends.
+// `extended` holds the files extending this one, to detect cycles.
+fn load_filters(path: &Path, extended: &mut Vec<PathBuf>) -> Result<HashMap<String, Filter>> {
+    let canonical_path =
+        fs::canonicalize(path).map_err(|err| Error::FileOpen(path.to_path_buf(), err))?;
+    if extended.contains(&canonical_path) {
+        return Err(Error::ExtendsCycle(path.to_path_buf()));
+    }
+
+    let input_file = File::open(path).map_err(|err| Error::FileOpen(path.to_path_buf(), err))?;
     let mut input_reader = BufReader::new(input_file);
-    let filters = parse_json(&mut input_reader)?;
+    let json_file = parse_json(&mut input_reader)?;
+
+    let base = match json_file.extends {
+        Some(ref base_path) => {
+            extended.push(canonical_path);
+            // The path of the base file is relative to the one extending it.
+            let base_path = path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(base_path);
+            load_filters(&base_path, extended)?
+        }
+        None => HashMap::new(),
+    };
+    json_file.resolve(base).map_err(Error::FileFormat)
+}
+
+fn compile(args: &Arguments) -> Result<()> {
+    let filters = load_filters(Path::new(&args.input_file), &mut Vec::new())?;
     let compiler = Compiler::new(args.target_arch);
 
     // transform the IR into a Map of BPFPrograms
     let bpf_data: HashMap<String, BpfProgram> = compiler
-        .compile_blob(filters.0, args.is_basic)
+        .compile_blob(filters, args.is_basic)
         .map_err(Error::FileFormat)?;
 
     // serialize the BPF programs & output them to a file
@@ -225,11 +256,12 @@ mod tests {
     use std::path::PathBuf;
 
     use bincode::Error as BincodeError;
+    use utils::tempdir::TempDir;
     use utils::tempfile::TempFile;
 
     use super::compiler::{Error as FilterFormatError, Filter, SyscallRule};
     use super::{
-        build_arg_parser, compile, get_argument_values, parse_json, Arguments, Error,
+        build_arg_parser, compile, get_argument_values, load_filters, parse_json, Arguments, Error,
         DEFAULT_OUTPUT_FILENAME,
     };
     use crate::backend::SeccompCmpArgLen::*;
@@ -350,6 +382,10 @@ mod tests {
                 BincodeError::new(bincode::ErrorKind::SizeLimit)
             )
         );
+        assert_eq!(
+            format!("{}", Error::ExtendsCycle(path.clone())),
+            "Filter file /path ends up extending itself."
+        );
         assert_eq!(
             format!(
                 "{}",
@@ -704,7 +740,10 @@ mod tests {
             let mut json_input = "{}".to_string();
             let json_input = unsafe { json_input.as_bytes_mut() };
 
-            assert_eq!(parse_json(&mut json_input.as_ref()).unwrap().0.len(), 0);
+            assert_eq!(
+                parse_json(&mut json_input.as_ref()).unwrap().filters.len(),
+                0
+            );
 
             // empty Filter
             let mut json_input =
@@ -772,7 +811,7 @@ mod tests {
 
             let mut v2: Vec<_> = parse_json(&mut json_input.as_ref())
                 .unwrap()
-                .0
+                .filters
                 .into_iter()
                 .collect();
             v2.sort_by(|x, y| x.0.cmp(&y.0));
@@ -832,4 +871,68 @@ mod tests {
             assert!(compile(&arguments).is_ok());
         }
     }
+
+    #[test]
+    fn test_load_filters() {
+        let tmp_dir = TempDir::new().unwrap();
+        let base_dir = tmp_dir.as_path().join("base");
+        std::fs::create_dir(&base_dir).unwrap();
+        let write_file = |path: &PathBuf, contents: &str| {
+            std::fs::File::create(path)
+                .unwrap()
+                .write_all(contents.as_bytes())
+                .unwrap()
+        };
+
+        let base_path = base_dir.join("base.json");
+        write_file(&base_path, &get_correct_json_input());
+        let derived_path = tmp_dir.as_path().join("derived.json");
+        write_file(
+            &derived_path,
+            r#"
+            {
+                "extends": "base/base.json",
+                "thread_2": {
+                    "default_action": "trap",
+                    "filter_action": "allow",
+                    "filter": [{"syscall": "read"}],
+                    "remove": [{"syscall": "ioctl"}]
+                }
+            }
+            "#,
+        );
+        let filters = load_filters(&derived_path, &mut Vec::new()).unwrap();
+        assert_eq!(
+            filters["thread_1"],
+            load_filters(&base_path, &mut Vec::new()).unwrap()["thread_1"]
+        );
+        assert_eq!(
+            filters["thread_2"],
+            Filter::new(
+                SeccompAction::Trap,
+                SeccompAction::Allow,
+                vec![SyscallRule::new("read".to_string(), None)],
+            )
+        );
+
+        // The extended file is missing.
+        let other_path = tmp_dir.as_path().join("other.json");
+        write_file(&other_path, r#"{"extends": "missing.json"}"#);
+        match load_filters(&other_path, &mut Vec::new()).unwrap_err() {
+            Error::FileOpen(path, _) => assert_eq!(path, tmp_dir.as_path().join("missing.json")),
+            _ => panic!("Expected FileOpen error."),
+        }
+
+        // The files end up extending themselves.
+        write_file(&other_path, r#"{"extends": "other.json"}"#);
+        assert!(matches!(
+            load_filters(&other_path, &mut Vec::new()),
+            Err(Error::ExtendsCycle(_))
+        ));
+        write_file(&base_path, r#"{"extends": "../derived.json"}"#);
+        assert!(matches!(
+            load_filters(&derived_path, &mut Vec::new()),
+            Err(Error::ExtendsCycle(_))
+        ));
+    }
 }
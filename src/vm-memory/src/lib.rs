@@ -5,9 +5,14 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use std::ffi::CString;
+use std::fs::File;
 use std::io::Error as IoError;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
 
+use utils::tempfile::TempFile;
 use vm_memory_upstream::bitmap::AtomicBitmap;
 pub use vm_memory_upstream::bitmap::Bitmap;
 use vm_memory_upstream::mmap::{check_file_offset, NewBitmap};
@@ -23,6 +28,8 @@ pub type GuestRegionMmap = vm_memory_upstream::GuestRegionMmap<Option<AtomicBitm
 pub type GuestMmapRegion = vm_memory_upstream::MmapRegion<Option<AtomicBitmap>>;
 
 const GUARD_PAGE_COUNT: usize = 1;
+// The `f_type` statfs reports for hugetlbfs mounts.
+const HUGETLBFS_MAGIC: u64 = 0x9584_58f6;
 
 /// Build a `MmapRegion` surrounded by guard pages.
 ///
@@ -37,17 +44,23 @@ const GUARD_PAGE_COUNT: usize = 1;
 /// This results in a border of `GUARD_PAGE_COUNT` pages on either side of the region, which
 /// acts as a safety net for accessing out-of-bounds addresses that are not allocated for the
 /// guest's memory.
+///
+/// The accessible region starts at a multiple of `alignment`, a multiple of the page size, which
+/// the huge pages backing the region require. The guarded range is extended by
+/// `alignment - page_size` bytes to leave room for realigning the region.
 fn build_guarded_region(
     maybe_file_offset: Option<FileOffset>,
     size: usize,
     prot: i32,
     flags: i32,
     track_dirty_pages: bool,
+    alignment: usize,
 ) -> Result<GuestMmapRegion, MmapRegionError> {
     let page_size = utils::get_page_size().expect("Cannot retrieve page size.");
-    // Create the guarded range size (received size + X pages),
+    let alignment = alignment.max(page_size);
+    // Create the guarded range size (received size + X pages + alignment slack),
     // where X is defined as a constant GUARD_PAGE_COUNT.
-    let guarded_size = size + GUARD_PAGE_COUNT * 2 * page_size;
+    let guarded_size = size + GUARD_PAGE_COUNT * 2 * page_size + alignment - page_size;
 
     // Map the guarded range to PROT_NONE
     let guard_addr = unsafe {
@@ -73,7 +86,10 @@ fn build_guarded_region(
         None => (-1, 0),
     };
 
-    let region_start_addr = guard_addr as usize + page_size * GUARD_PAGE_COUNT;
+    let region_start_addr = align_up(
+        guard_addr as usize + page_size * GUARD_PAGE_COUNT,
+        alignment,
+    );
 
     // Inside the protected range, starting with guard_addr + PAGE_SIZE,
     // map the requested range with received protection and flags
@@ -97,13 +113,15 @@ fn build_guarded_region(
         false => None,
     };
 
-    unsafe {
-        MmapRegionBuilder::new_with_bitmap(size, bitmap)
-            .with_raw_mmap_pointer(region_addr as *mut u8)
-            .with_mmap_prot(prot)
-            .with_mmap_flags(flags)
-            .build()
+    let mut builder = MmapRegionBuilder::new_with_bitmap(size, bitmap)
+        .with_raw_mmap_pointer(region_addr as *mut u8)
+        .with_mmap_prot(prot)
+        .with_mmap_flags(flags);
+    if let Some(file_offset) = maybe_file_offset {
+        builder = builder.with_file_offset(file_offset);
     }
+
+    unsafe { builder.build() }
 }
 
 /// Helper for creating the guest memory.
@@ -120,9 +138,15 @@ pub fn create_guest_memory(
             Some(_) => libc::MAP_NORESERVE | libc::MAP_PRIVATE,
         };
 
-        let mmap_region =
-            build_guarded_region(region.0.clone(), region.2, prot, flags, track_dirty_pages)
-                .map_err(Error::MmapRegion)?;
+        let mmap_region = build_guarded_region(
+            region.0.clone(),
+            region.2,
+            prot,
+            flags,
+            track_dirty_pages,
+            0,
+        )
+        .map_err(Error::MmapRegion)?;
 
         mmap_regions.push(GuestRegionMmap::new(mmap_region, region.1)?);
     }
@@ -130,6 +154,123 @@ pub fn create_guest_memory(
     GuestMemoryMmap::from_regions(mmap_regions)
 }
 
+/// Helper for creating guest memory which can be shared with other processes, such as vhost-user
+/// backends. Each region is backed by its own memfd, mapped with `MAP_SHARED`, which can be
+/// retrieved through `GuestMemoryRegion::file_offset`.
+pub fn create_shared_guest_memory(
+    regions: &[(GuestAddress, usize)],
+    track_dirty_pages: bool,
+) -> std::result::Result<GuestMemoryMmap, Error> {
+    let prot = libc::PROT_READ | libc::PROT_WRITE;
+    let flags = libc::MAP_NORESERVE | libc::MAP_SHARED;
+    let mut mmap_regions = Vec::with_capacity(regions.len());
+
+    for (index, region) in regions.iter().enumerate() {
+        let file = create_memfd(&format!("guest_mem_{}", index), region.1)
+            .map_err(|e| Error::MmapRegion(MmapRegionError::Mmap(e)))?;
+        let mmap_region = build_guarded_region(
+            Some(FileOffset::new(file, 0)),
+            region.1,
+            prot,
+            flags,
+            track_dirty_pages,
+            0,
+        )
+        .map_err(Error::MmapRegion)?;
+
+        mmap_regions.push(GuestRegionMmap::new(mmap_region, region.0)?);
+    }
+
+    GuestMemoryMmap::from_regions(mmap_regions)
+}
+
+/// Helper for creating guest memory backed by the huge pages of the hugetlbfs mounted at
+/// `hugetlbfs_path`. Each region is backed by its own file, unlinked from the mount right away,
+/// and mapped with `MAP_SHARED` so that it can also be shared with vhost-user backends.
+///
+/// The huge pages are reserved when the regions are mapped, so the creation fails up front when
+/// the pool of the mount can't back the whole guest memory.
+pub fn create_hugetlbfs_guest_memory(
+    regions: &[(GuestAddress, usize)],
+    hugetlbfs_path: &Path,
+    track_dirty_pages: bool,
+) -> std::result::Result<GuestMemoryMmap, Error> {
+    let to_error = |e| Error::MmapRegion(MmapRegionError::Mmap(e));
+    let huge_page_size = hugetlbfs_page_size(hugetlbfs_path).map_err(to_error)?;
+    let prot = libc::PROT_READ | libc::PROT_WRITE;
+    let flags = libc::MAP_SHARED;
+    let mut mmap_regions = Vec::with_capacity(regions.len());
+
+    for region in regions {
+        if region.1 % huge_page_size != 0 {
+            return Err(to_error(IoError::from_raw_os_error(libc::EINVAL)));
+        }
+        let file = create_hugetlbfs_file(hugetlbfs_path, region.1).map_err(to_error)?;
+        let mmap_region = build_guarded_region(
+            Some(FileOffset::new(file, 0)),
+            region.1,
+            prot,
+            flags,
+            track_dirty_pages,
+            huge_page_size,
+        )
+        .map_err(Error::MmapRegion)?;
+
+        mmap_regions.push(GuestRegionMmap::new(mmap_region, region.0)?);
+    }
+
+    GuestMemoryMmap::from_regions(mmap_regions)
+}
+
+/// Returns the size of the huge pages backing the files of the hugetlbfs mounted at `path`, or
+/// an `InvalidInput` error if `path` isn't a hugetlbfs mount.
+pub fn hugetlbfs_page_size(path: &Path) -> std::result::Result<usize, IoError> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| IoError::from_raw_os_error(libc::EINVAL))?;
+    // This is safe since `statfs` is plain data, fully written by the call on success.
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // This is safe since `path` is a valid C string and we check the return value.
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+    if stat.f_type as u64 != HUGETLBFS_MAGIC {
+        return Err(IoError::new(
+            std::io::ErrorKind::InvalidInput,
+            "not a hugetlbfs mount",
+        ));
+    }
+    Ok(stat.f_bsize as usize)
+}
+
+// Creates a file of `size` bytes in the hugetlbfs mounted at `hugetlbfs_path`, which is unlinked
+// so that its huge pages are released along with the guest memory.
+fn create_hugetlbfs_file(hugetlbfs_path: &Path, size: usize) -> std::result::Result<File, IoError> {
+    let file = TempFile::new_with_prefix(hugetlbfs_path.join("guest_mem_"))
+        .map_err(|e| IoError::from_raw_os_error(e.errno()))?
+        .into_file();
+    file.set_len(size as u64)?;
+    Ok(file)
+}
+
+fn align_up(addr: usize, alignment: usize) -> usize {
+    (addr + alignment - 1) / alignment * alignment
+}
+
+// Creates an anonymous file of `size` bytes, which is closed on exec.
+fn create_memfd(name: &str, size: usize) -> std::result::Result<File, IoError> {
+    // The name only shows up in /proc and can't contain null bytes.
+    let name = CString::new(name).unwrap();
+    // This is safe since `name` is a valid C string and we check the return value.
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_create, name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(IoError::last_os_error());
+    }
+    // We just checked that the fd is valid, and nothing else owns it.
+    let file = unsafe { File::from_raw_fd(fd as i32) };
+    file.set_len(size as u64)?;
+    Ok(file)
+}
+
 pub fn mark_dirty_mem(mem: &GuestMemoryMmap, addr: GuestAddress, len: usize) {
     let _ = mem.try_access(len, addr, |_total, count, caddr, region| {
         if let Some(bitmap) = region.bitmap() {
@@ -298,7 +439,7 @@ mod tests {
             let prot = libc::PROT_READ | libc::PROT_WRITE;
             let flags = libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_PRIVATE;
 
-            let region = build_guarded_region(None, size, prot, flags, false).unwrap();
+            let region = build_guarded_region(None, size, prot, flags, false, 0).unwrap();
 
             // Verify that the region was built correctly
             assert_eq!(region.size(), size);
@@ -326,17 +467,34 @@ mod tests {
                 prot,
                 flags,
                 false,
+                0,
             )
             .unwrap();
 
             // Verify that the region was built correctly
             assert_eq!(region.size(), size);
-            // assert_eq!(region.file_offset().unwrap().start(), offset as u64);
+            assert_eq!(region.file_offset().unwrap().start(), offset as u64);
             assert_eq!(region.prot(), prot);
             assert_eq!(region.flags(), flags);
 
             validate_guard_region(&region);
         }
+
+        // Create a guarded region aligned for huge pages.
+        {
+            let page_size = get_page_size().unwrap();
+            let alignment = 512 * page_size;
+            let size = 2 * alignment;
+            let prot = libc::PROT_READ | libc::PROT_WRITE;
+            let flags = libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_PRIVATE;
+
+            let region = build_guarded_region(None, size, prot, flags, false, alignment).unwrap();
+
+            assert_eq!(region.size(), size);
+            assert_eq!(region.as_ptr() as usize % alignment, 0);
+
+            validate_guard_region(&region);
+        }
     }
 
     #[test]
@@ -391,6 +549,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_create_shared_guest_memory() {
+        let region_size = 0x10000;
+        let regions = vec![
+            (GuestAddress(0x0), region_size),
+            (GuestAddress(0x10000), region_size),
+        ];
+
+        let guest_memory = create_shared_guest_memory(&regions, false).unwrap();
+        guest_memory.iter().for_each(|region| {
+            validate_guard_region(&region);
+            assert_eq!(region.flags() & libc::MAP_SHARED, libc::MAP_SHARED);
+            assert!(region.bitmap().is_none());
+
+            // Writes to the guest memory are visible through the backing file.
+            let file_offset = region.file_offset().unwrap();
+            assert_eq!(file_offset.start(), 0);
+            assert_eq!(
+                file_offset.file().metadata().unwrap().len(),
+                region_size as u64
+            );
+            region.write_obj(0xabu8, MemoryRegionAddress(0x10)).unwrap();
+            let mut byte = [0u8];
+            // This is safe since `byte` is a valid buffer of the given length.
+            let ret = unsafe {
+                libc::pread(
+                    file_offset.file().as_raw_fd(),
+                    byte.as_mut_ptr() as *mut libc::c_void,
+                    1,
+                    0x10,
+                )
+            };
+            assert_eq!(ret, 1);
+            assert_eq!(byte[0], 0xab);
+        });
+
+        let guest_memory = create_shared_guest_memory(&regions, true).unwrap();
+        guest_memory.iter().for_each(|region| {
+            assert!(region.bitmap().is_some());
+        });
+    }
+
+    #[test]
+    fn test_create_hugetlbfs_guest_memory() {
+        // Only hugetlbfs mounts can back the guest memory with huge pages.
+        let dir = std::env::temp_dir();
+        if hugetlbfs_page_size(&dir).is_ok() {
+            return;
+        }
+        assert_eq!(
+            hugetlbfs_page_size(&dir).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        assert!(
+            create_hugetlbfs_guest_memory(&[(GuestAddress(0), 0x20_0000)], &dir, false).is_err()
+        );
+        assert!(hugetlbfs_page_size(Path::new("/inexistent")).is_err());
+    }
+
     #[test]
     fn test_mark_dirty_mem() {
         let page_size = utils::get_page_size().unwrap();
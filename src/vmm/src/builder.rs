@@ -8,16 +8,23 @@ use std::fmt::{Display, Formatter};
 use std::io::{self, Read, Seek, SeekFrom};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::{Arc, Mutex};
+use std::thread;
 
 use arch::InitrdConfig;
 #[cfg(target_arch = "x86_64")]
 use cpuid::common::is_same_model;
 use devices::legacy::serial::ReadableFd;
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::AcpiDevice;
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::RTCDevice;
 use devices::legacy::{EventFdTrigger, SerialDevice, SerialEventsWrapper, SerialWrapper};
-use devices::virtio::{Balloon, Block, MmioTransport, Net, VirtioDevice, Vsock, VsockUnixBackend};
-use event_manager::{MutEventSubscriber, SubscriberOps};
+use devices::virtio::mem::{MEMORY_HOTPLUG_ALIGNMENT, MEM_DEV_ID};
+use devices::virtio::pmem::PMEM_ALIGNMENT;
+use devices::virtio::{
+    Balloon, Block, MmioTransport, Net, Pmem, VirtioDevice, VirtioMem, Vsock, VsockUnixBackend,
+};
+use event_manager::{EventManager as BaseEventManager, MutEventSubscriber, SubscriberOps};
 use libc::EFD_NONBLOCK;
 use linux_loader::cmdline::Cmdline as LoaderKernelCmdline;
 #[cfg(target_arch = "x86_64")]
@@ -25,14 +32,15 @@ use linux_loader::loader::elf::Elf as Loader;
 #[cfg(target_arch = "aarch64")]
 use linux_loader::loader::pe::PE as Loader;
 use linux_loader::loader::KernelLoader;
-use logger::{error, warn, METRICS};
-use seccompiler::BpfThreadMap;
+use logger::{error, warn, ThreadCategory, METRICS};
+use seccompiler::{BpfProgram, BpfThreadMap};
 use snapshot::Persist;
+use timerfd::{ClockId, TimerFd};
 use userfaultfd::Uffd;
 use utils::eventfd::EventFd;
 use utils::terminal::Terminal;
-use utils::time::TimestampUs;
-use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+use utils::time::{get_time_ms, get_time_us, ClockType, TimestampUs};
+use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
 #[cfg(target_arch = "aarch64")]
 use vm_superio::Rtc;
 use vm_superio::Serial;
@@ -43,11 +51,13 @@ use crate::construct_kvm_mpidrs;
 use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::device_manager::persist::MMIODevManagerConstructorArgs;
-use crate::persist::{MicrovmState, MicrovmStateError};
+use crate::persist::{DiffSnapshotParent, MicrovmState, MicrovmStateError};
 use crate::resources::VmResources;
 use crate::vmm_config::boot_source::BootConfig;
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::{VmConfigError, VmUpdateConfig};
+use crate::vmm_config::machine_config::{GuestMemoryBackend, VmConfigError, VmUpdateConfig};
+use crate::vmm_config::memory_hotplug::MemoryHotplugConfig;
+use crate::vmm_config::net::NetBackendType;
 use crate::vstate::system::KvmContext;
 use crate::vstate::vcpu::{Vcpu, VcpuConfig};
 use crate::vstate::vm::Vm;
@@ -60,6 +70,8 @@ pub enum StartMicrovmError {
     AttachBlockDevice(io::Error),
     /// This error is thrown by the minimal boot loader implementation.
     ConfigureSystem(arch::Error),
+    /// Cannot create the virtio-mem device exposing the hot-pluggable memory.
+    CreateMemoryHotplugDevice(devices::virtio::mem::Error),
     /// Internal errors are due to resource exhaustion.
     CreateNetDevice(devices::virtio::net::Error),
     /// Failed to create a `RateLimiter` object.
@@ -72,6 +84,8 @@ pub enum StartMicrovmError {
     InitrdRead(io::Error),
     /// Internal error encountered while starting a microVM.
     Internal(Error),
+    /// The hot-pluggable memory doesn't fit in the guest physical address space.
+    InvalidMemoryHotplugSize(usize),
     /// The kernel command line is invalid.
     KernelCmdline(String),
     /// Cannot load kernel due to invalid memory configuration or invalid kernel image.
@@ -94,6 +108,8 @@ pub enum StartMicrovmError {
     RestoreMicrovmState(MicrovmStateError),
     /// Unable to set VmResources.
     SetVmResources(VmConfigError),
+    /// Cannot start the worker thread of a net device.
+    StartNetWorker(String),
 }
 
 /// It's convenient to automatically convert `linux_loader::cmdline::Error`s
@@ -112,6 +128,9 @@ impl Display for StartMicrovmError {
                 write!(f, "Unable to attach block device to Vmm: {}", err)
             }
             ConfigureSystem(e) => write!(f, "System configuration error: {:?}", e),
+            CreateMemoryHotplugDevice(err) => {
+                write!(f, "Cannot create the memory hot-plug device: {:?}", err)
+            }
             CreateRateLimiter(err) => write!(f, "Cannot create RateLimiter: {}", err),
             CreateNetDevice(err) => {
                 let mut err_msg = format!("{:?}", err);
@@ -131,6 +150,12 @@ impl Display for StartMicrovmError {
             ),
             InitrdRead(err) => write!(f, "Cannot load initrd due to an invalid image: {}", err),
             Internal(err) => write!(f, "Internal error while starting microVM: {}", err),
+            InvalidMemoryHotplugSize(size) => write!(
+                f,
+                "The {} MiB of hot-pluggable memory don't fit in the guest physical address \
+                 space.",
+                size
+            ),
             KernelCmdline(err) => write!(f, "Invalid kernel command line: {}", err),
             KernelLoader(err) => {
                 let mut err_msg = format!("{}", err);
@@ -177,6 +202,9 @@ impl Display for StartMicrovmError {
             }
             RestoreMicrovmState(err) => write!(f, "Cannot restore microvm state. Error: {}", err),
             SetVmResources(err) => write!(f, "Cannot set vm resources. Error: {}", err),
+            StartNetWorker(err) => {
+                write!(f, "Cannot start the worker thread of a net device. {}", err)
+            }
         }
     }
 }
@@ -243,6 +271,9 @@ fn create_vmm_and_vcpus(
     let vcpus_exit_evt = EventFd::new(libc::EFD_NONBLOCK)
         .map_err(Error::EventFd)
         .map_err(Internal)?;
+    let snapshot_timer = TimerFd::new_custom(ClockId::Monotonic, true, true)
+        .map_err(Error::TimerFd)
+        .map_err(Internal)?;
 
     // Instantiate the MMIO device manager.
     // 'mmio_base' address has to be an address which is protected by the kernel
@@ -303,6 +334,12 @@ fn create_vmm_and_vcpus(
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
         pio_device_manager,
+        hotplugged_blocks: Vec::new(),
+        hotplugged_balloon: None,
+        diff_parent: DiffSnapshotParent::Boot,
+        dirty_rate_sample_us: get_time_us(ClockType::Monotonic),
+        snapshot_timer,
+        snapshot_schedule: None,
     };
 
     Ok((vmm, vcpus))
@@ -329,11 +366,44 @@ pub fn build_microvm_for_boot(
     let boot_config = vm_resources.boot_source().ok_or(MissingKernelConfig)?;
 
     let track_dirty_pages = vm_resources.track_dirty_pages();
-    let guest_memory =
-        create_guest_memory(vm_resources.vm_config().mem_size_mib, track_dirty_pages)?;
+    // vhost-user backends map the guest memory in their own address space.
+    let shared_memory =
+        vm_resources.net_builder.iter().any(|net| {
+            net.lock().expect("Poisoned lock").backend_type() == NetBackendType::VhostUser
+        }) || vm_resources.block.list.iter().any(|block| {
+            block
+                .lock()
+                .expect("Poisoned lock")
+                .vhost_user_socket()
+                .is_some()
+        });
+    let mem_size_mib = vm_resources.vm_config().mem_size_mib;
+    let memory_hotplug_region = vm_resources
+        .memory_hotplug
+        .as_ref()
+        .map(|config| memory_hotplug_region(mem_size_mib, config))
+        .transpose()?;
+    let guest_memory = create_guest_memory(
+        mem_size_mib,
+        memory_hotplug_region,
+        track_dirty_pages,
+        shared_memory,
+        &vm_resources.vm_config().mem_backend,
+    )?;
+    // The hot-pluggable memory is announced to the guest by its virtio-mem device, so it's left
+    // out of the memory the guest boots with.
+    let boot_memory = match memory_hotplug_region {
+        Some((addr, size)) => {
+            guest_memory
+                .remove_region(addr, size as u64)
+                .map_err(GuestMemoryMmap)?
+                .0
+        }
+        None => guest_memory.clone(),
+    };
     let vcpu_config = vm_resources.vcpu_config();
-    let entry_addr = load_kernel(boot_config, &guest_memory)?;
-    let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
+    let entry_addr = load_kernel(boot_config, &boot_memory)?;
+    let initrd = load_initrd_from_config(boot_config, &boot_memory)?;
     // Clone the command-line so that a failed boot doesn't pollute the original.
     #[allow(unused_mut)]
     let mut boot_cmdline = linux_loader::cmdline::Cmdline::new(arch::CMDLINE_MAX_SIZE);
@@ -389,10 +459,33 @@ pub fn build_microvm_for_boot(
         &mut boot_cmdline,
         vm_resources.net_builder.iter(),
         event_manager,
+        seccomp_filters,
+    )?;
+    attach_pmem_devices(
+        &mut vmm,
+        &mut boot_cmdline,
+        vm_resources.pmem.list.iter(),
+        event_manager,
     )?;
-    if let Some(unix_vsock) = vm_resources.vsock.get() {
+    for unix_vsock in vm_resources.vsock.devices() {
         attach_unixsock_vsock_device(&mut vmm, &mut boot_cmdline, unix_vsock, event_manager)?;
     }
+    if let (Some(config), Some((addr, size))) =
+        (vm_resources.memory_hotplug.as_ref(), memory_hotplug_region)
+    {
+        attach_memory_hotplug_device(
+            &mut vmm,
+            &mut boot_cmdline,
+            addr,
+            size as u64,
+            config,
+            event_manager,
+        )?;
+    }
+    #[cfg(target_arch = "x86_64")]
+    attach_acpi_device(&mut vmm, event_manager)?;
+    set_mmds_device_tags(&vmm, vm_resources);
+    set_mmds_boot_time(vm_resources);
 
     if let Some(init) = init_params {
         boot_cmdline.insert_str(format!("--{}", init))?;
@@ -403,6 +496,7 @@ pub fn build_microvm_for_boot(
 
     configure_system_for_boot(
         &vmm,
+        &boot_memory,
         vcpus.as_mut(),
         vcpu_config,
         entry_addr,
@@ -470,6 +564,9 @@ pub fn build_microvm_from_snapshot(
         track_dirty_pages,
         vcpu_count,
     )?;
+    // The guest memory wasn't written since boot by this microVM: its diff snapshots need a
+    // parent, which the caller sets if it knows it.
+    vmm.diff_parent = DiffSnapshotParent::Unknown;
 
     #[cfg(target_arch = "x86_64")]
     // Check if we need to scale the TSC.
@@ -528,6 +625,7 @@ pub fn build_microvm_from_snapshot(
             smt: Some(false),
             cpu_template: None,
             track_dirty_pages: Some(track_dirty_pages),
+            mem_backend: None,
         })
         .map_err(SetVmResources)?;
 
@@ -539,12 +637,15 @@ pub fn build_microvm_from_snapshot(
         for_each_restored_device: VmResources::update_from_restored_device,
         vm_resources,
         instance_id: &instance_info.id,
+        exit_evt: &vmm.vcpus_exit_evt,
     };
 
     vmm.mmio_device_manager =
         MMIODeviceManager::restore(mmio_ctor_args, &microvm_state.device_states)
             .map_err(MicrovmStateError::RestoreDevices)
             .map_err(RestoreMicrovmState)?;
+    set_mmds_device_tags(&vmm, vm_resources);
+    set_mmds_boot_time(vm_resources);
     vmm.emulate_serial_init()
         .map_err(StartMicrovmError::Internal)?;
 
@@ -578,13 +679,32 @@ pub fn build_microvm_from_snapshot(
     Ok(vmm)
 }
 
-/// Creates GuestMemory of `mem_size_mib` MiB in size.
+/// Creates GuestMemory of `mem_size_mib` MiB in size. Shared memory can be mapped by other
+/// processes, through the files backing its regions, as can the memory backed by hugetlbfs.
 pub fn create_guest_memory(
     mem_size_mib: usize,
+    memory_hotplug_region: Option<(GuestAddress, usize)>,
     track_dirty_pages: bool,
+    shared: bool,
+    mem_backend: &GuestMemoryBackend,
 ) -> std::result::Result<GuestMemoryMmap, StartMicrovmError> {
     let mem_size = mem_size_mib << 20;
-    let arch_mem_regions = arch::arch_memory_regions(mem_size);
+    let mut arch_mem_regions = arch::arch_memory_regions(mem_size);
+    arch_mem_regions.extend(memory_hotplug_region);
+
+    if let GuestMemoryBackend::Hugetlbfs { path } = mem_backend {
+        return vm_memory::create_hugetlbfs_guest_memory(
+            &arch_mem_regions,
+            path,
+            track_dirty_pages,
+        )
+        .map_err(StartMicrovmError::GuestMemoryMmap);
+    }
+
+    if shared {
+        return vm_memory::create_shared_guest_memory(&arch_mem_regions, track_dirty_pages)
+            .map_err(StartMicrovmError::GuestMemoryMmap);
+    }
 
     vm_memory::create_guest_memory(
         &arch_mem_regions
@@ -596,6 +716,31 @@ pub fn create_guest_memory(
     .map_err(StartMicrovmError::GuestMemoryMmap)
 }
 
+/// Returns the guest physical range of the hot-pluggable memory of a microVM booting with
+/// `mem_size_mib` MiB of memory. It comes after the boot memory and the MMIO gap, so that it
+/// overlaps neither of them, and has to end within the guest physical address space.
+fn memory_hotplug_region(
+    mem_size_mib: usize,
+    config: &MemoryHotplugConfig,
+) -> std::result::Result<(GuestAddress, usize), StartMicrovmError> {
+    let boot_memory_end = arch::arch_memory_regions(mem_size_mib << 20)
+        .last()
+        .map(|(addr, size)| addr.raw_value() + *size as u64)
+        .unwrap_or(0);
+    let memory_end = std::cmp::max(boot_memory_end, arch::MMIO_MEM_START + arch::MMIO_MEM_SIZE);
+    let addr = (memory_end + MEMORY_HOTPLUG_ALIGNMENT - 1) & !(MEMORY_HOTPLUG_ALIGNMENT - 1);
+    let size = (config.total_size_mib as u64)
+        .checked_mul(1 << 20)
+        .filter(|size| {
+            addr.checked_add(*size)
+                .map_or(false, |end| end <= arch::GUEST_MEM_END)
+        })
+        .ok_or(StartMicrovmError::InvalidMemoryHotplugSize(
+            config.total_size_mib,
+        ))?;
+    Ok((GuestAddress(addr), size as usize))
+}
+
 fn load_kernel(
     boot_config: &BootConfig,
     guest_memory: &GuestMemoryMmap,
@@ -811,6 +956,7 @@ fn create_vcpus(vm: &Vm, vcpu_count: u8, exit_evt: &EventFd) -> super::Result<Ve
 #[cfg_attr(target_arch = "aarch64", allow(unused))]
 pub fn configure_system_for_boot(
     vmm: &Vmm,
+    boot_memory: &GuestMemoryMmap,
     vcpus: &mut [Vcpu],
     vcpu_config: VcpuConfig,
     entry_addr: GuestAddress,
@@ -823,7 +969,7 @@ pub fn configure_system_for_boot(
         for vcpu in vcpus.iter_mut() {
             vcpu.kvm_vcpu
                 .configure(
-                    vmm.guest_memory(),
+                    boot_memory,
                     entry_addr,
                     &vcpu_config,
                     vmm.vm.supported_cpuid().clone(),
@@ -835,25 +981,40 @@ pub fn configure_system_for_boot(
         // Write the kernel command line to guest memory. This is x86_64 specific, since on
         // aarch64 the command line will be specified through the FDT.
         linux_loader::loader::load_cmdline::<vm_memory::GuestMemoryMmap>(
-            vmm.guest_memory(),
+            boot_memory,
             GuestAddress(arch::x86_64::layout::CMDLINE_START),
             &boot_cmdline,
         )
         .map_err(LoadCommandline)?;
         arch::x86_64::configure_system(
-            &vmm.guest_memory,
+            boot_memory,
             vm_memory::GuestAddress(arch::x86_64::layout::CMDLINE_START),
             boot_cmdline.as_str().len() + 1,
             initrd,
             vcpus.len() as u8,
         )
         .map_err(ConfigureSystem)?;
+
+        if let Some(acpi) = vmm
+            .mmio_device_manager
+            .get_device_info()
+            .get(&(arch::DeviceType::Acpi, arch::DeviceType::Acpi.to_string()))
+        {
+            arch::x86_64::acpi::setup_acpi_tables(
+                boot_memory,
+                vcpus.len() as u8,
+                acpi.irqs[0],
+                acpi.addr + devices::legacy::SLEEP_CONTROL_OFFSET,
+                acpi.addr + devices::legacy::SLEEP_STATUS_OFFSET,
+            )
+            .map_err(ConfigureSystem)?;
+        }
     }
     #[cfg(target_arch = "aarch64")]
     {
         for vcpu in vcpus.iter_mut() {
             vcpu.kvm_vcpu
-                .configure(vmm.guest_memory(), entry_addr)
+                .configure(boot_memory, entry_addr)
                 .map_err(Error::VcpuConfigure)
                 .map_err(Internal)?;
         }
@@ -863,7 +1024,7 @@ pub fn configure_system_for_boot(
             .map(|cpu| cpu.kvm_vcpu.get_mpidr())
             .collect();
         arch::aarch64::configure_system(
-            &vmm.guest_memory,
+            boot_memory,
             boot_cmdline.as_str(),
             vcpu_mpidr,
             vmm.mmio_device_manager.get_device_info(),
@@ -875,6 +1036,25 @@ pub fn configure_system_for_boot(
     Ok(())
 }
 
+/// Lets the guest map its virtio devices back to their ids by publishing the device tags in
+/// MMDS, if configured.
+fn set_mmds_device_tags(vmm: &Vmm, vm_resources: &VmResources) {
+    if let Some(mmds) = vm_resources.mmds.as_ref() {
+        mmds.lock()
+            .expect("Poisoned lock")
+            .set_device_tags(vmm.device_tags());
+    }
+}
+
+/// Records the boot time reported by the identity document served by MMDS, if configured.
+fn set_mmds_boot_time(vm_resources: &VmResources) {
+    if let Some(mmds) = vm_resources.mmds.as_ref() {
+        mmds.lock()
+            .expect("Poisoned lock")
+            .set_boot_time(get_time_ms(ClockType::Real) / 1000);
+    }
+}
+
 /// Attaches a VirtioDevice device to the device manager and event manager.
 fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber>(
     event_manager: &mut EventManager,
@@ -883,9 +1063,19 @@ fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber>(
     device: Arc<Mutex<T>>,
     cmdline: &mut LoaderKernelCmdline,
 ) -> std::result::Result<(), StartMicrovmError> {
-    use self::StartMicrovmError::*;
-
     event_manager.add_subscriber(device.clone());
+    register_virtio_device(vmm, id, device, cmdline)
+}
+
+/// Attaches a VirtioDevice device to the device manager only, for devices whose events are
+/// processed outside of the main event manager.
+fn register_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber>(
+    vmm: &mut Vmm,
+    id: String,
+    device: Arc<Mutex<T>>,
+    cmdline: &mut LoaderKernelCmdline,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
 
     // The device mutex mustn't be locked here otherwise it will deadlock.
     let device = MmioTransport::new(vmm.guest_memory().clone(), device);
@@ -910,6 +1100,31 @@ pub(crate) fn attach_boot_timer_device(
     Ok(())
 }
 
+/// Attaches the ACPI device, through which the guest is asked to power off.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn attach_acpi_device(
+    vmm: &mut Vmm,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    let exit_evt = vmm
+        .vcpus_exit_evt
+        .try_clone()
+        .map_err(Error::EventFd)
+        .map_err(Internal)?;
+    let acpi = Arc::new(Mutex::new(
+        AcpiDevice::new(exit_evt)
+            .map_err(Error::Acpi)
+            .map_err(Internal)?,
+    ));
+    vmm.mmio_device_manager
+        .register_mmio_acpi(vmm.vm.fd(), acpi.clone(), None)
+        .map_err(RegisterMmioDevice)?;
+    event_manager.add_subscriber(acpi);
+    Ok(())
+}
+
 fn attach_block_devices<'a>(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
@@ -943,15 +1158,133 @@ fn attach_net_devices<'a>(
     cmdline: &mut LoaderKernelCmdline,
     net_devices: impl Iterator<Item = &'a Arc<Mutex<Net>>>,
     event_manager: &mut EventManager,
+    seccomp_filters: &BpfThreadMap,
 ) -> std::result::Result<(), StartMicrovmError> {
-    for net_device in net_devices {
-        let id = net_device.lock().expect("Poisoned lock").id().clone();
+    for (index, net_device) in net_devices.enumerate() {
+        let (id, worker_thread) = {
+            let locked = net_device.lock().expect("Poisoned lock");
+            (locked.id().clone(), locked.worker_thread())
+        };
+        if worker_thread {
+            let seccomp_filter = seccomp_filters
+                .get("net_worker")
+                .ok_or_else(|| StartMicrovmError::MissingSeccompFilters("net_worker".to_string()))?
+                .clone();
+            // There are at most as many net devices as free IRQ lines.
+            start_net_worker(index as u8, net_device.clone(), seccomp_filter)?;
+            // The device mutex mustn't be locked here otherwise it will deadlock.
+            register_virtio_device(vmm, id, net_device.clone(), cmdline)?;
+        } else {
+            // The device mutex mustn't be locked here otherwise it will deadlock.
+            attach_virtio_device(event_manager, vmm, id, net_device.clone(), cmdline)?;
+        }
+    }
+    Ok(())
+}
+
+fn attach_pmem_devices<'a>(
+    vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
+    pmem_devices: impl Iterator<Item = &'a Arc<Mutex<Pmem>>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    // The device memory is mapped after the guest memory and above the MMIO gap, so that it
+    // overlaps neither of them.
+    let memory_end = std::cmp::max(
+        vmm.guest_memory().last_addr().raw_value() + 1,
+        arch::MMIO_MEM_START + arch::MMIO_MEM_SIZE,
+    );
+    let mut next_addr = (memory_end + PMEM_ALIGNMENT - 1) & !(PMEM_ALIGNMENT - 1);
+    // The guest memory regions use the first KVM memory slots.
+    let mut slot = vmm.guest_memory().num_regions() as u32;
+
+    for pmem in pmem_devices {
+        let id = {
+            let mut locked = pmem.lock().expect("Poisoned lock");
+            locked.set_guest_address(GuestAddress(next_addr));
+            vmm.vm
+                .set_device_memory_region(
+                    slot,
+                    GuestAddress(next_addr),
+                    locked.size(),
+                    locked.host_address(),
+                    locked.is_read_only(),
+                )
+                .map_err(Error::Vm)
+                .map_err(Internal)?;
+            // The size of the device memory is a multiple of the alignment.
+            next_addr += locked.size();
+            slot += 1;
+            locked.id().clone()
+        };
         // The device mutex mustn't be locked here otherwise it will deadlock.
-        attach_virtio_device(event_manager, vmm, id, net_device.clone(), cmdline)?;
+        attach_virtio_device(event_manager, vmm, id, pmem.clone(), cmdline)?;
     }
     Ok(())
 }
 
+fn attach_memory_hotplug_device(
+    vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
+    addr: GuestAddress,
+    size: u64,
+    config: &MemoryHotplugConfig,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    let virtio_mem = VirtioMem::new(addr, size, (config.block_size_mib as u64) << 20)
+        .map_err(StartMicrovmError::CreateMemoryHotplugDevice)?;
+    attach_virtio_device(
+        event_manager,
+        vmm,
+        MEM_DEV_ID.to_string(),
+        Arc::new(Mutex::new(virtio_mem)),
+        cmdline,
+    )
+}
+
+/// Processes the events of `net_device` on a thread of its own, which runs until the device is
+/// unplugged.
+fn start_net_worker(
+    index: u8,
+    net_device: Arc<Mutex<Net>>,
+    seccomp_filter: Arc<BpfProgram>,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::StartNetWorker;
+
+    // Unlike the main event manager, this one only holds the net device and can be moved to
+    // the worker thread.
+    let mut event_manager = BaseEventManager::<Arc<Mutex<Net>>>::new()
+        .map_err(|err| StartNetWorker(format!("{:?}", err)))?;
+    event_manager.add_subscriber(net_device.clone());
+
+    thread::Builder::new()
+        .name(format!("fc_net {}", index))
+        .spawn(move || {
+            METRICS
+                .cpu_usage
+                .register_current_thread(ThreadCategory::NetWorker(index));
+            // Execution panics if filters cannot be loaded, use --no-seccomp if skipping filters
+            // altogether is the desired behaviour.
+            if let Err(e) = seccompiler::apply_filter(&seccomp_filter) {
+                panic!(
+                    "Failed to set the requested seccomp filters on net worker {}: Error: {}",
+                    index, e
+                );
+            }
+            // Unplugging the device closes its host-side interfaces and unregisters all its
+            // events, after which there is nothing left to wait for.
+            while net_device.lock().expect("Poisoned lock").num_queue_pairs() > 0 {
+                if let Err(e) = event_manager.run() {
+                    error!("Net worker {} failed to process events: {:?}", index, e);
+                }
+            }
+        })
+        .map(|_| ())
+        .map_err(|err| StartNetWorker(err.to_string()))
+}
+
 fn attach_unixsock_vsock_device(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
@@ -992,18 +1325,22 @@ pub mod tests {
 
     use arch::DeviceType;
     use devices::virtio::vsock::VSOCK_DEV_ID;
-    use devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_VSOCK};
+    use devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_MEM, TYPE_NET, TYPE_PMEM, TYPE_VSOCK};
     use linux_loader::cmdline::Cmdline;
-    use mmds::data_store::{Mmds, MmdsVersion};
+    use mmds::data_store::{Mmds, MmdsVersion, OutputFormat};
     use mmds::ns::MmdsNetworkStack;
     use utils::tempfile::TempFile;
-    use vm_memory::GuestMemory;
+    use vm_memory::{GuestMemory, GuestMemoryRegion};
 
     use super::*;
+    use crate::seccomp_filters::{get_filters, SeccompConfig};
     use crate::vmm_config::balloon::{BalloonBuilder, BalloonDeviceConfig, BALLOON_DEV_ID};
     use crate::vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
-    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, CacheType, FileEngineType};
-    use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    use crate::vmm_config::drive::{
+        BlockBuilder, BlockDeviceConfig, CacheType, FileEngineType, ImageFormat,
+    };
+    use crate::vmm_config::net::{NetBuilder, NetDatapath, NetOffloads, NetworkInterfaceConfig};
+    use crate::vmm_config::pmem::{PmemBuilder, PmemConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::vsock::{VsockBuilder, VsockDeviceConfig};
 
@@ -1068,7 +1405,8 @@ pub mod tests {
     }
 
     pub(crate) fn default_vmm() -> Vmm {
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory =
+            create_guest_memory(128, None, false, false, &GuestMemoryBackend::Anonymous).unwrap();
 
         let vcpus_exit_evt = EventFd::new(libc::EFD_NONBLOCK)
             .map_err(Error::EventFd)
@@ -1102,6 +1440,12 @@ pub mod tests {
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
             pio_device_manager,
+            hotplugged_blocks: Vec::new(),
+            hotplugged_balloon: None,
+            diff_parent: DiffSnapshotParent::Boot,
+            dirty_rate_sample_us: 0,
+            snapshot_timer: TimerFd::new_custom(ClockId::Monotonic, true, true).unwrap(),
+            snapshot_schedule: None,
         }
     }
 
@@ -1124,12 +1468,21 @@ pub mod tests {
                     .to_str()
                     .unwrap()
                     .to_string(),
+                fd: None,
+                serial: None,
                 is_root_device: custom_block_cfg.is_root_device,
                 partuuid: custom_block_cfg.partuuid.clone(),
                 is_read_only: custom_block_cfg.is_read_only,
                 cache_type: custom_block_cfg.cache_type,
                 rate_limiter: None,
+                read_rate_limiter: None,
+                write_rate_limiter: None,
                 file_engine_type: FileEngineType::default(),
+                image_format: ImageFormat::default(),
+                overlay: None,
+                rl_group: None,
+                vhost_user_socket: None,
+                num_queues: None,
             };
             block_dev_configs.insert(block_device_config).unwrap();
         }
@@ -1147,7 +1500,13 @@ pub mod tests {
         let mut net_builder = NetBuilder::new();
         net_builder.build(net_config).unwrap();
 
-        let res = attach_net_devices(vmm, cmdline, net_builder.iter(), event_manager);
+        let res = attach_net_devices(
+            vmm,
+            cmdline,
+            net_builder.iter(),
+            event_manager,
+            &get_filters(SeccompConfig::None).unwrap(),
+        );
         assert!(res.is_ok());
     }
 
@@ -1165,10 +1524,18 @@ pub mod tests {
         mmds.set_version(mmds_version).unwrap();
         net.lock().unwrap().configure_mmds_network_stack(
             MmdsNetworkStack::default_ipv4_addr(),
+            None,
             Arc::new(Mutex::new(mmds)),
         );
 
-        attach_net_devices(vmm, cmdline, net_builder.iter(), event_manager).unwrap();
+        attach_net_devices(
+            vmm,
+            cmdline,
+            net_builder.iter(),
+            event_manager,
+            &get_filters(SeccompConfig::None).unwrap(),
+        )
+        .unwrap();
     }
 
     pub(crate) fn insert_vsock_device(
@@ -1283,21 +1650,58 @@ pub mod tests {
 
         // Case 1: create guest memory without dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, false).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, None, false, false, &GuestMemoryBackend::Anonymous)
+                    .unwrap();
             assert!(!is_dirty_tracking_enabled(&guest_memory));
         }
 
         // Case 2: create guest memory with dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, true).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, None, true, false, &GuestMemoryBackend::Anonymous)
+                    .unwrap();
             assert!(is_dirty_tracking_enabled(&guest_memory));
         }
+
+        // Case 3: create guest memory shared through memfds
+        {
+            let guest_memory =
+                create_guest_memory(mem_size, None, false, true, &GuestMemoryBackend::Anonymous)
+                    .unwrap();
+            assert!(guest_memory
+                .iter()
+                .all(|region| region.file_offset().is_some()));
+            let guest_memory =
+                create_guest_memory(mem_size, None, false, false, &GuestMemoryBackend::Anonymous)
+                    .unwrap();
+            assert!(guest_memory
+                .iter()
+                .all(|region| region.file_offset().is_none()));
+        }
+
+        // Case 4: create guest memory with a hot-pluggable region
+        {
+            let addr = GuestAddress(1 << 32);
+            let guest_memory = create_guest_memory(
+                mem_size,
+                Some((addr, 128 << 20)),
+                false,
+                false,
+                &GuestMemoryBackend::Anonymous,
+            )
+            .unwrap();
+            let region = guest_memory.find_region(addr).unwrap();
+            assert_eq!(region.start_addr(), addr);
+            assert_eq!(region.len(), 128 << 20);
+        }
     }
 
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory =
+            create_guest_memory(128, None, false, false, &GuestMemoryBackend::Anonymous).unwrap();
 
         #[allow(unused_mut)]
         let mut vm = setup_kvm_vm(&guest_memory, false).unwrap();
@@ -1321,6 +1725,18 @@ pub mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1336,6 +1752,70 @@ pub mod tests {
         assert!(net_builder.build(network_interface).is_err());
     }
 
+    #[test]
+    fn test_attach_net_worker() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+
+        let mut net_builder = NetBuilder::new();
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: String::from("worker-host"),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: true,
+        };
+        let net = net_builder.build(network_interface).unwrap();
+
+        // The worker thread can't be started without its seccomp filter.
+        let res = attach_net_devices(
+            &mut vmm,
+            &mut cmdline,
+            net_builder.iter(),
+            &mut event_manager,
+            &BpfThreadMap::new(),
+        );
+        assert!(matches!(
+            res,
+            Err(StartMicrovmError::MissingSeccompFilters(ref category)) if category == "net_worker"
+        ));
+
+        attach_net_devices(
+            &mut vmm,
+            &mut cmdline,
+            net_builder.iter(),
+            &mut event_manager,
+            &get_filters(SeccompConfig::None).unwrap(),
+        )
+        .unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_NET), "netif")
+            .is_some());
+
+        // The unplug event is processed by the worker, without running the main event manager.
+        net.lock().unwrap().unplug().unwrap();
+        let mut retries = 100;
+        while net.lock().unwrap().num_queue_pairs() > 0 {
+            retries -= 1;
+            assert!(retries > 0);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
     #[test]
     fn test_attach_block_devices() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -1513,6 +1993,68 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_hotplug_block_device() {
+        let mut vmm = default_vmm();
+        let block_file = TempFile::new().unwrap();
+        let block = BlockBuilder::create_block(BlockDeviceConfig {
+            drive_id: String::from("scratch"),
+            path_on_host: block_file.as_path().to_str().unwrap().to_string(),
+            fd: None,
+            serial: None,
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            cache_type: CacheType::Unsafe,
+            rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
+            file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
+        })
+        .unwrap();
+
+        vmm.add_block_device(Arc::new(Mutex::new(block))).unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_BLOCK), "scratch")
+            .is_some());
+        // The device is handed over to the event manager only once.
+        assert_eq!(vmm.take_hotplugged_blocks().len(), 1);
+        assert!(vmm.take_hotplugged_blocks().is_empty());
+
+        assert!(vmm.validate_block_device_removal("bogus").is_err());
+        vmm.validate_block_device_removal("scratch").unwrap();
+        vmm.remove_block_device("scratch").unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_BLOCK), "scratch")
+            .is_none());
+        assert!(vmm.remove_block_device("scratch").is_err());
+    }
+
+    #[test]
+    fn test_hotplug_balloon_device() {
+        let mut vmm = default_vmm();
+        assert!(vmm.balloon_config().is_err());
+
+        let balloon = Balloon::new(0, false, 0, false, false).unwrap();
+        vmm.add_balloon_device(Arc::new(Mutex::new(balloon)))
+            .unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+            .is_some());
+        assert!(vmm.balloon_config().is_ok());
+        // The device is handed over to the event manager only once.
+        assert!(vmm.take_hotplugged_balloon().is_some());
+        assert!(vmm.take_hotplugged_balloon().is_none());
+    }
+
     #[test]
     fn test_attach_boot_timer_device() {
         let mut vmm = default_vmm();
@@ -1526,6 +2068,23 @@ pub mod tests {
             .is_some());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_attach_acpi_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        // There is no power button to press without the ACPI device.
+        assert!(vmm.send_acpi_shutdown(None).is_err());
+
+        attach_acpi_device(&mut vmm, &mut event_manager).unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Acpi, &DeviceType::Acpi.to_string())
+            .is_some());
+        vmm.send_acpi_shutdown(Some(std::time::Duration::from_secs(1)))
+            .unwrap();
+    }
+
     #[test]
     fn test_attach_balloon_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -1535,6 +2094,7 @@ pub mod tests {
             amount_mib: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1564,6 +2124,201 @@ pub mod tests {
             .contains("virtio_mmio.device=4K@0xd0000000:5"));
     }
 
+    #[test]
+    fn test_attach_pmem_devices() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+
+        let backing_file = TempFile::new().unwrap();
+        backing_file.as_file().set_len(2 * PMEM_ALIGNMENT).unwrap();
+        let mut builder = PmemBuilder::new();
+        for &(pmem_id, is_read_only) in [("pmem0", false), ("pmem1", true)].iter() {
+            builder
+                .insert(PmemConfig {
+                    pmem_id: pmem_id.to_string(),
+                    path_on_host: backing_file.as_path().to_str().unwrap().to_string(),
+                    is_read_only,
+                })
+                .unwrap();
+        }
+
+        attach_pmem_devices(
+            &mut vmm,
+            &mut cmdline,
+            builder.list.iter(),
+            &mut event_manager,
+        )
+        .unwrap();
+
+        // The device memory is mapped after the guest memory and the MMIO gap, back to back.
+        let first_addr = std::cmp::max(
+            vmm.guest_memory().last_addr().raw_value() + 1,
+            arch::MMIO_MEM_START + arch::MMIO_MEM_SIZE,
+        );
+        for (index, pmem) in builder.list.iter().enumerate() {
+            let locked = pmem.lock().unwrap();
+            assert_eq!(
+                locked.guest_address(),
+                GuestAddress(first_addr + index as u64 * 2 * PMEM_ALIGNMENT)
+            );
+            assert!(vmm
+                .mmio_device_manager
+                .get_device(DeviceType::Virtio(TYPE_PMEM), locked.id())
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn test_attach_memory_hotplug_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        };
+        // The hot-pluggable memory comes after the guest memory and the MMIO gap.
+        let (addr, size) = memory_hotplug_region(128, &config).unwrap();
+        assert!(addr.raw_value() >= arch::MMIO_MEM_START + arch::MMIO_MEM_SIZE);
+        assert_eq!(addr.raw_value() % MEMORY_HOTPLUG_ALIGNMENT, 0);
+        assert_eq!(size, 1024 << 20);
+        // It has to end within the guest physical address space.
+        for &total_size_mib in [(arch::GUEST_MEM_END >> 20) as usize, usize::MAX].iter() {
+            let config = MemoryHotplugConfig {
+                total_size_mib,
+                block_size_mib: 2,
+            };
+            assert!(matches!(
+                memory_hotplug_region(128, &config),
+                Err(StartMicrovmError::InvalidMemoryHotplugSize(size)) if size == total_size_mib
+            ));
+        }
+
+        attach_memory_hotplug_device(
+            &mut vmm,
+            &mut cmdline,
+            addr,
+            size as u64,
+            &config,
+            &mut event_manager,
+        )
+        .unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID)
+            .is_some());
+
+        let status = vmm.memory_hotplug_status().unwrap();
+        assert_eq!(status.total_size_mib, 1024);
+        assert_eq!(status.block_size_mib, 2);
+        assert_eq!(status.plugged_size_mib, 0);
+        assert_eq!(status.requested_size_mib, 0);
+
+        // The block size is validated by the device.
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 3,
+        };
+        assert!(matches!(
+            attach_memory_hotplug_device(
+                &mut vmm,
+                &mut cmdline,
+                addr,
+                size as u64,
+                &config,
+                &mut event_manager,
+            ),
+            Err(StartMicrovmError::CreateMemoryHotplugDevice(_))
+        ));
+    }
+
+    #[test]
+    fn test_set_mmds_device_tags() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+
+        let block_configs = vec![
+            CustomBlockConfig::new(String::from("root"), true, None, true, CacheType::Unsafe),
+            CustomBlockConfig::new(String::from("data"), false, None, false, CacheType::Unsafe),
+        ];
+        let _block_files =
+            insert_block_devices(&mut vmm, &mut cmdline, &mut event_manager, block_configs);
+        let guest_mac = utils::net::mac::MacAddr::parse_str("01:23:45:67:89:0a").unwrap();
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: String::from("hostname"),
+            guest_mac: Some(guest_mac),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
+        };
+        insert_net_device(
+            &mut vmm,
+            &mut cmdline,
+            &mut event_manager,
+            network_interface,
+        );
+
+        // Nothing gets published without MMDS.
+        let mut vm_resources = VmResources::default();
+        set_mmds_device_tags(&vmm, &vm_resources);
+        assert!(vm_resources.mmds.is_none());
+
+        vm_resources.mmds = Some(Arc::new(Mutex::new(Mmds::default())));
+        set_mmds_device_tags(&vmm, &vm_resources);
+        let mmds = vm_resources.mmds.as_ref().unwrap().lock().unwrap();
+
+        // The guest can resolve the id of every attached device from its MMIO address.
+        let device_info = vmm.mmio_device_manager.get_device_info();
+        assert_eq!(device_info.len(), 3);
+        for ((device_type, id), info) in device_info.iter() {
+            let path = format!("/fc-devices/{:#x}", info.addr);
+            let expected_type = match device_type {
+                DeviceType::Virtio(TYPE_BLOCK) => "block",
+                DeviceType::Virtio(TYPE_NET) => "net",
+                _ => unreachable!(),
+            };
+            assert_eq!(
+                mmds.get_value(format!("{}/id", path), OutputFormat::Imds)
+                    .unwrap(),
+                *id
+            );
+            assert_eq!(
+                mmds.get_value(format!("{}/type", path), OutputFormat::Imds)
+                    .unwrap(),
+                expected_type
+            );
+            assert_eq!(
+                mmds.get_value(format!("{}/irq", path), OutputFormat::Imds)
+                    .unwrap(),
+                info.irqs[0].to_string()
+            );
+        }
+        let net_addr = device_info[&(DeviceType::Virtio(TYPE_NET), String::from("netif"))].addr;
+        assert_eq!(
+            mmds.get_value(
+                format!("/fc-devices/{:#x}/mac", net_addr),
+                OutputFormat::Imds
+            )
+            .unwrap(),
+            guest_mac.to_string()
+        );
+    }
+
     #[test]
     fn test_error_messages() {
         use crate::builder::StartMicrovmError::*;
@@ -1575,6 +2330,9 @@ pub mod tests {
         ));
         let _ = format!("{}{:?}", err, err);
 
+        let err = CreateMemoryHotplugDevice(devices::virtio::mem::Error::InvalidBlockSize(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = CreateRateLimiter(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
@@ -13,19 +13,22 @@ use std::{fmt, io};
 use arch::aarch64::DeviceInfoForFDT;
 use arch::DeviceType;
 use arch::DeviceType::Virtio;
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::AcpiDevice;
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::RTCDevice;
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::SerialDevice;
 use devices::pseudo::BootTimer;
 use devices::virtio::{
-    Balloon, Block, MmioTransport, Net, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET,
-    TYPE_VSOCK,
+    Balloon, Block, MmioTransport, Net, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_MEM, TYPE_NET,
+    TYPE_PMEM, TYPE_VSOCK,
 };
 use devices::BusDevice;
 use kvm_ioctls::{IoEventAddress, VmFd};
 use linux_loader::cmdline as kernel_cmdline;
 use logger::info;
+use serde_json::{Map, Value};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_allocator::{AddressAllocator, AllocPolicy, IdAllocator};
@@ -53,6 +56,10 @@ pub enum Error {
     RegisterIoEvent(kvm_ioctls::Error),
     /// Registering an IRQ FD failed.
     RegisterIrqFd(kvm_ioctls::Error),
+    /// Unregistering an IO Event failed.
+    UnregisterIoEvent(kvm_ioctls::Error),
+    /// Unregistering an IRQ FD failed.
+    UnregisterIrqFd(kvm_ioctls::Error),
     /// Failed to update the mmio device.
     UpdateFailed,
     /// Allocation logic error.
@@ -70,6 +77,8 @@ impl fmt::Display for Error {
             Error::InvalidInput => write!(f, "invalid configuration"),
             Error::RegisterIoEvent(e) => write!(f, "failed to register IO event: {}", e),
             Error::RegisterIrqFd(e) => write!(f, "failed to register irqfd: {}", e),
+            Error::UnregisterIoEvent(e) => write!(f, "failed to unregister IO event: {}", e),
+            Error::UnregisterIrqFd(e) => write!(f, "failed to unregister irqfd: {}", e),
             Error::DeviceNotFound => write!(f, "the device couldn't be found"),
             Error::UpdateFailed => write!(f, "failed to update the mmio device"),
             Error::AllocatorError(e) => write!(f, "failed to allocate requested resource: {}", e),
@@ -183,6 +192,55 @@ impl MMIODeviceManager {
         self.register_mmio_device(identifier, slot.clone(), Arc::new(Mutex::new(mmio_device)))
     }
 
+    /// Detaches the virtio device of type `virtio_type` with id `device_id` from the guest and
+    /// frees its IRQ lines, which the next attached devices may reuse. Returns the detached
+    /// device.
+    ///
+    /// The vCPUs keep routing accesses to the slot to the detached transport, which ignores
+    /// them, until they are given the updated bus. The address range of the slot isn't handed
+    /// out again either way.
+    pub fn remove_virtio_device(
+        &mut self,
+        vm: &VmFd,
+        virtio_type: u32,
+        device_id: &str,
+    ) -> Result<Arc<Mutex<dyn VirtioDevice>>> {
+        let identifier = (DeviceType::Virtio(virtio_type), device_id.to_string());
+        let slot = self
+            .id_to_dev_info
+            .remove(&identifier)
+            .ok_or(Error::DeviceNotFound)?;
+        let bus_device = self.bus.remove(slot.addr).ok_or(Error::DeviceNotFound)?;
+
+        let mut locked_bus_device = bus_device.lock().expect("Poisoned lock");
+        let mmio_device = locked_bus_device
+            .as_mut_any()
+            .downcast_mut::<MmioTransport>()
+            .expect("Unexpected BusDevice type");
+        // Let the guest know before its notifications stop reaching the device.
+        mmio_device.unplug().map_err(Error::EventFd)?;
+
+        let device = mmio_device.device();
+        {
+            let locked_device = device.lock().expect("Poisoned lock");
+            for (i, queue_evt) in locked_device.queue_events().iter().enumerate() {
+                let io_addr =
+                    IoEventAddress::Mmio(slot.addr + u64::from(devices::virtio::NOTIFY_REG_OFFSET));
+                vm.unregister_ioevent(queue_evt, &io_addr, i as u32)
+                    .map_err(Error::UnregisterIoEvent)?;
+            }
+            vm.unregister_irqfd(locked_device.interrupt_evt(), slot.irqs[0])
+                .map_err(Error::UnregisterIrqFd)?;
+        }
+        for irq in slot.irqs {
+            self.irq_allocator
+                .free_id(irq)
+                .map_err(Error::AllocatorError)?;
+        }
+
+        Ok(device)
+    }
+
     /// Append a registered virtio-over-MMIO device to the kernel cmdline.
     #[cfg(target_arch = "x86_64")]
     pub fn add_virtio_device_to_cmdline(
@@ -208,13 +266,25 @@ impl MMIODeviceManager {
         mmio_device: MmioTransport,
         _cmdline: &mut kernel_cmdline::Cmdline,
     ) -> Result<MMIODeviceInfo> {
-        let mmio_slot = self.allocate_new_slot(1)?;
-        self.register_mmio_virtio(vm, device_id, mmio_device, &mmio_slot)?;
+        let mmio_slot = self.register_mmio_virtio_for_hotplug(vm, device_id, mmio_device)?;
         #[cfg(target_arch = "x86_64")]
         Self::add_virtio_device_to_cmdline(_cmdline, &mmio_slot)?;
         Ok(mmio_slot)
     }
 
+    /// Allocate slot and register an already created virtio-over-MMIO device, without telling
+    /// the guest about it. The vCPUs only see the device once they are given the updated bus.
+    pub fn register_mmio_virtio_for_hotplug(
+        &mut self,
+        vm: &VmFd,
+        device_id: String,
+        mmio_device: MmioTransport,
+    ) -> Result<MMIODeviceInfo> {
+        let mmio_slot = self.allocate_new_slot(1)?;
+        self.register_mmio_virtio(vm, device_id, mmio_device, &mmio_slot)?;
+        Ok(mmio_slot)
+    }
+
     #[cfg(target_arch = "aarch64")]
     /// Register an early console at the specified MMIO address if given as parameter,
     /// otherwise allocate a new MMIO slot for it.
@@ -286,6 +356,34 @@ impl MMIODeviceManager {
         self.register_mmio_device(identifier, slot, Arc::new(Mutex::new(device)))
     }
 
+    #[cfg(target_arch = "x86_64")]
+    /// Register the ACPI device at the specified MMIO slot if given as parameter, otherwise
+    /// allocate a new MMIO slot for it.
+    pub fn register_mmio_acpi(
+        &mut self,
+        vm: &VmFd,
+        acpi: Arc<Mutex<AcpiDevice>>,
+        dev_info_opt: Option<MMIODeviceInfo>,
+    ) -> Result<MMIODeviceInfo> {
+        // Create a new MMIODeviceInfo object on boot path or unwrap the
+        // existing object on restore path.
+        let slot = if let Some(dev_info) = dev_info_opt {
+            dev_info
+        } else {
+            self.allocate_new_slot(1)?
+        };
+
+        vm.register_irqfd(
+            acpi.lock().expect("Poisoned lock").interrupt_evt(),
+            slot.irqs[0],
+        )
+        .map_err(Error::RegisterIrqFd)?;
+
+        let identifier = (DeviceType::Acpi, DeviceType::Acpi.to_string());
+        self.register_mmio_device(identifier, slot.clone(), acpi)?;
+        Ok(slot)
+    }
+
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
@@ -391,6 +489,52 @@ impl MMIODeviceManager {
         Ok(())
     }
 
+    /// Raises a configuration change interrupt for the virtio device matching `virtio_type`
+    /// and `id`.
+    pub fn signal_config_change(&self, virtio_type: u32, id: &str) -> Result<()> {
+        let busdev = self
+            .get_device(DeviceType::Virtio(virtio_type), id)
+            .ok_or(Error::DeviceNotFound)?;
+        busdev
+            .lock()
+            .expect("Poisoned lock")
+            .as_mut_any()
+            .downcast_mut::<MmioTransport>()
+            .expect("Unexpected BusDevice type")
+            .notify_config_change()
+            .map_err(Error::EventFd)
+    }
+
+    /// Builds the device tags exposed to the guest through MMDS, mapping the MMIO address of
+    /// each virtio device to its type, its IRQ line and the id it was configured with.
+    pub fn device_tags(&self) -> Value {
+        let mut tags = Map::new();
+        let _: Result<()> = self.for_each_virtio_device(|virtio_type, id, info, dev| {
+            let type_name = match virtio_type {
+                TYPE_BALLOON => "balloon",
+                TYPE_BLOCK => "block",
+                TYPE_MEM => "mem",
+                TYPE_NET => "net",
+                TYPE_PMEM => "pmem",
+                TYPE_VSOCK => "vsock",
+                _ => "unknown",
+            };
+            let mut tag = Map::new();
+            tag.insert("type".to_string(), Value::from(type_name));
+            tag.insert("id".to_string(), Value::from(id.as_str()));
+            tag.insert("irq".to_string(), Value::from(info.irqs[0].to_string()));
+            if virtio_type == TYPE_NET {
+                let dev = dev.lock().expect("Poisoned lock");
+                if let Some(mac) = dev.as_any().downcast_ref::<Net>().and_then(Net::guest_mac) {
+                    tag.insert("mac".to_string(), Value::from(mac.to_string()));
+                }
+            }
+            tags.insert(format!("{:#x}", info.addr), Value::Object(tag));
+            Ok(())
+        });
+        Value::Object(tags)
+    }
+
     /// Artificially kick devices as if they had external events.
     pub fn kick_devices(&self) {
         info!("Artificially kick devices.");
@@ -594,6 +738,32 @@ mod tests {
             .is_ok());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_register_mmio_acpi() {
+        let guest_mem =
+            vm_memory::test_utils::create_anon_guest_memory(&[(GuestAddress(0x0), 0x1000)], false)
+                .unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
+        let mut device_manager = MMIODeviceManager::new(
+            0xd000_0000,
+            arch::MMIO_MEM_SIZE,
+            (arch::IRQ_BASE, arch::IRQ_MAX),
+        )
+        .unwrap();
+
+        let exit_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let acpi = Arc::new(Mutex::new(AcpiDevice::new(exit_evt).unwrap()));
+        let slot = device_manager
+            .register_mmio_acpi(vm.fd(), acpi, None)
+            .unwrap();
+        assert_eq!(slot.irqs, vec![arch::IRQ_BASE]);
+        assert!(device_manager
+            .get_device(DeviceType::Acpi, &DeviceType::Acpi.to_string())
+            .is_some());
+    }
+
     #[test]
     fn test_register_too_many_devices() {
         let start_addr1 = GuestAddress(0x0);
@@ -668,6 +838,8 @@ mod tests {
                 Error::InvalidInput => format!("{}{:?}", e, e),
                 Error::RegisterIoEvent(_) => format!("{}{:?}", e, e),
                 Error::RegisterIrqFd(_) => format!("{}{:?}", e, e),
+                Error::UnregisterIoEvent(_) => format!("{}{:?}", e, e),
+                Error::UnregisterIrqFd(_) => format!("{}{:?}", e, e),
                 Error::UpdateFailed => format!("{}{:?}", e, e),
                 Error::AllocatorError(_) => format!("{}{:?}", e, e),
             };
@@ -683,6 +855,8 @@ mod tests {
         check_fmt_err(Error::AllocatorError(vm_allocator::Error::Overflow));
         check_fmt_err(Error::RegisterIoEvent(errno::Error::new(0)));
         check_fmt_err(Error::RegisterIrqFd(errno::Error::new(0)));
+        check_fmt_err(Error::UnregisterIoEvent(errno::Error::new(0)));
+        check_fmt_err(Error::UnregisterIrqFd(errno::Error::new(0)));
         check_fmt_err(Error::UpdateFailed);
     }
 
@@ -756,6 +930,142 @@ mod tests {
         assert_eq!(device_manager.used_irqs_count(), 2);
     }
 
+    #[test]
+    fn test_remove_virtio_device() {
+        let start_addr1 = GuestAddress(0x0);
+        let start_addr2 = GuestAddress(0x1000);
+        let guest_mem = vm_memory::test_utils::create_anon_guest_memory(
+            &[(start_addr1, 0x1000), (start_addr2, 0x1000)],
+            false,
+        )
+        .unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        #[cfg(target_arch = "x86_64")]
+        assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
+        #[cfg(target_arch = "aarch64")]
+        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+
+        let mut device_manager = MMIODeviceManager::new(
+            0xd000_0000,
+            arch::MMIO_MEM_SIZE,
+            (arch::IRQ_BASE, arch::IRQ_MAX),
+        )
+        .unwrap();
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096);
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        let type_id = dummy.lock().unwrap().device_type();
+        let addr = device_manager
+            .register_virtio_test_device(vm.fd(), guest_mem.clone(), dummy, &mut cmdline, "foo")
+            .unwrap();
+
+        assert!(matches!(
+            device_manager.remove_virtio_device(vm.fd(), type_id, "bar"),
+            Err(Error::DeviceNotFound)
+        ));
+        let device = device_manager
+            .remove_virtio_device(vm.fd(), type_id, "foo")
+            .unwrap();
+        assert_eq!(device.lock().unwrap().device_type(), type_id);
+        assert!(device_manager
+            .get_device(DeviceType::Virtio(type_id), "foo")
+            .is_none());
+        assert!(device_manager.get_device_info().is_empty());
+        // The guest was notified of the configuration change.
+        assert_eq!(device.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+
+        // The irq is reused, but not the address range.
+        let new_addr = device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                Arc::new(Mutex::new(DummyDevice::new())),
+                &mut cmdline,
+                "foo",
+            )
+            .unwrap();
+        assert_ne!(new_addr, addr);
+        assert_eq!(
+            device_manager.id_to_dev_info[&(DeviceType::Virtio(type_id), "foo".to_string())].irqs
+                [0],
+            arch::IRQ_BASE
+        );
+    }
+
+    #[test]
+    fn test_register_virtio_device_for_hotplug() {
+        let start_addr1 = GuestAddress(0x0);
+        let start_addr2 = GuestAddress(0x1000);
+        let guest_mem = vm_memory::test_utils::create_anon_guest_memory(
+            &[(start_addr1, 0x1000), (start_addr2, 0x1000)],
+            false,
+        )
+        .unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        #[cfg(target_arch = "x86_64")]
+        assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
+        #[cfg(target_arch = "aarch64")]
+        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+
+        let mut device_manager = MMIODeviceManager::new(
+            0xd000_0000,
+            arch::MMIO_MEM_SIZE,
+            (arch::IRQ_BASE, arch::IRQ_MAX),
+        )
+        .unwrap();
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        let type_id = dummy.lock().unwrap().device_type();
+        let slot = device_manager
+            .register_mmio_virtio_for_hotplug(
+                vm.fd(),
+                "foo".to_string(),
+                MmioTransport::new(guest_mem, dummy),
+            )
+            .unwrap();
+
+        assert_eq!(slot.addr, 0xd000_0000);
+        assert_eq!(slot.irqs, vec![arch::IRQ_BASE]);
+        assert!(device_manager
+            .get_device(DeviceType::Virtio(type_id), "foo")
+            .is_some());
+        assert!(device_manager.bus.get_device(slot.addr).is_some());
+    }
+
+    #[test]
+    fn test_signal_config_change() {
+        let start_addr1 = GuestAddress(0x0);
+        let start_addr2 = GuestAddress(0x1000);
+        let guest_mem = vm_memory::test_utils::create_anon_guest_memory(
+            &[(start_addr1, 0x1000), (start_addr2, 0x1000)],
+            false,
+        )
+        .unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        #[cfg(target_arch = "x86_64")]
+        assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
+        #[cfg(target_arch = "aarch64")]
+        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+
+        let mut device_manager = MMIODeviceManager::new(
+            0xd000_0000,
+            arch::MMIO_MEM_SIZE,
+            (arch::IRQ_BASE, arch::IRQ_MAX),
+        )
+        .unwrap();
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096);
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        let type_id = dummy.lock().unwrap().device_type();
+        device_manager
+            .register_virtio_test_device(vm.fd(), guest_mem, dummy.clone(), &mut cmdline, "foo")
+            .unwrap();
+
+        assert!(matches!(
+            device_manager.signal_config_change(type_id, "bar"),
+            Err(Error::DeviceNotFound)
+        ));
+        device_manager.signal_config_change(type_id, "foo").unwrap();
+        assert_eq!(dummy.lock().unwrap().interrupt_evt().read().unwrap(), 1);
+    }
+
     #[test]
     fn test_slot_irq_allocation() {
         let mut device_manager = MMIODeviceManager::new(
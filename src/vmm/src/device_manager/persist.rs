@@ -6,8 +6,9 @@
 use std::result::Result;
 use std::sync::{Arc, Mutex};
 
-#[cfg(target_arch = "aarch64")]
 use arch::DeviceType;
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::AcpiDevice;
 use devices::virtio::balloon::persist::{BalloonConstructorArgs, BalloonState};
 use devices::virtio::balloon::{Balloon, Error as BalloonError};
 use devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
@@ -25,6 +26,7 @@ use kvm_ioctls::VmFd;
 use logger::{error, warn};
 use mmds::data_store::MmdsVersion;
 use snapshot::Persist;
+use utils::eventfd::EventFd;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_allocator::AllocPolicy;
@@ -38,6 +40,8 @@ use crate::EventManager;
 /// Errors for (de)serialization of the MMIO device manager.
 #[derive(Debug)]
 pub enum Error {
+    #[cfg(target_arch = "x86_64")]
+    Acpi(std::io::Error),
     Balloon(BalloonError),
     Block(BlockError),
     DeviceManager(super::mmio::Error),
@@ -155,12 +159,18 @@ pub struct DeviceStates {
     pub net_devices: Vec<ConnectedNetState>,
     /// Vsock device state.
     pub vsock_device: Option<ConnectedVsockState>,
+    /// States of the vsock devices attached along with the one of `vsock_device`.
+    #[version(start = 4, ser_fn = "vsock_devices_serialize")]
+    pub vsock_devices: Vec<ConnectedVsockState>,
     /// Balloon device state.
     #[version(start = 2, ser_fn = "balloon_serialize")]
     pub balloon_device: Option<ConnectedBalloonState>,
     /// Mmds version.
     #[version(start = 3, ser_fn = "mmds_version_serialize")]
     pub mmds_version: Option<MmdsVersionState>,
+    /// Slot of the ACPI device, which is only attached on x86_64.
+    #[version(start = 4, ser_fn = "acpi_device_serialize")]
+    pub acpi_device: Option<MMIODeviceInfo>,
 }
 
 /// A type used to extract the concrete Arc<Mutex<T>> for each of the device types when restoring
@@ -183,6 +193,16 @@ impl DeviceStates {
         Ok(())
     }
 
+    fn vsock_devices_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && !self.vsock_devices.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not support several vsock devices.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
     fn mmds_version_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
         if target_version < 3 && self.mmds_version.is_some() {
             warn!(
@@ -193,6 +213,17 @@ impl DeviceStates {
 
         Ok(())
     }
+
+    fn acpi_device_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 4 && self.acpi_device.is_some() {
+            warn!(
+                "Target version does not support persisting the ACPI device. The guest won't be \
+                 asked to power off through it when restored."
+            );
+        }
+
+        Ok(())
+    }
 }
 
 pub struct MMIODevManagerConstructorArgs<'a> {
@@ -202,6 +233,7 @@ pub struct MMIODevManagerConstructorArgs<'a> {
     pub for_each_restored_device: fn(&mut VmResources, SharedDeviceType),
     pub vm_resources: &'a mut VmResources,
     pub instance_id: &'a str,
+    pub exit_evt: &'a EventFd,
 }
 
 impl<'a> Persist<'a> for MMIODeviceManager {
@@ -215,16 +247,27 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             block_devices: Vec::new(),
             net_devices: Vec::new(),
             vsock_device: None,
+            vsock_devices: Vec::new(),
             #[cfg(target_arch = "aarch64")]
             legacy_devices: Vec::new(),
             mmds_version: None,
+            acpi_device: None,
         };
         let _: Result<(), ()> = self.for_each_device(|devtype, devid, devinfo, bus_dev| {
-            if *devtype == arch::DeviceType::BootTimer {
+            if *devtype == DeviceType::BootTimer {
                 // No need to save BootTimer state.
                 return Ok(());
             }
 
+            #[cfg(target_arch = "x86_64")]
+            {
+                if *devtype == DeviceType::Acpi {
+                    // The ACPI device has no state besides its slot.
+                    states.acpi_device = Some(devinfo.clone());
+                    return Ok(());
+                }
+            }
+
             #[cfg(target_arch = "aarch64")]
             {
                 if *devtype == DeviceType::Serial || *devtype == DeviceType::Rtc {
@@ -306,12 +349,17 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         });
                     }
 
-                    states.vsock_device = Some(ConnectedVsockState {
+                    let connected_state = ConnectedVsockState {
                         device_id: devid.clone(),
                         device_state: vsock_state,
                         transport_state,
                         mmio_slot: devinfo.clone(),
-                    });
+                    };
+                    if states.vsock_device.is_none() {
+                        states.vsock_device = Some(connected_state);
+                    } else {
+                        states.vsock_devices.push(connected_state);
+                    }
                 }
                 _ => unreachable!(),
             };
@@ -375,6 +423,23 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             }
         }
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            if let Some(slot) = &state.acpi_device {
+                let exit_evt = constructor_args.exit_evt.try_clone().map_err(Error::Acpi)?;
+                let acpi = Arc::new(Mutex::new(AcpiDevice::new(exit_evt).map_err(Error::Acpi)?));
+
+                dev_manager
+                    .address_allocator
+                    .allocate(MMIO_LEN, MMIO_LEN, AllocPolicy::ExactMatch(slot.addr))
+                    .map_err(|e| Error::DeviceManager(super::mmio::Error::AllocatorError(e)))?;
+                dev_manager
+                    .register_mmio_acpi(vm, acpi.clone(), Some(slot.clone()))
+                    .map_err(Error::DeviceManager)?;
+                constructor_args.event_manager.add_subscriber(acpi);
+            }
+        }
+
         let mut restore_helper = |device: Arc<Mutex<dyn VirtioDevice>>,
                                   as_subscriber: Arc<Mutex<dyn MutEventSubscriber>>,
                                   id: &String,
@@ -466,15 +531,6 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 .vm_resources
                 .set_mmds_version(mmds_version.clone().into(), constructor_args.instance_id)
                 .map_err(Error::MmdsConfig)?;
-        } else if state
-            .net_devices
-            .iter()
-            .any(|dev| dev.device_state.mmds_ns.is_some())
-        {
-            // If there's at least one network device having an mmds_ns, it means
-            // that we are restoring from a version that did not persist the `MmdsVersionState`.
-            // Init with the default.
-            constructor_args.vm_resources.mmds_or_default();
         }
 
         for net_state in &state.net_devices {
@@ -509,7 +565,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             )?;
         }
 
-        if let Some(vsock_state) = &state.vsock_device {
+        for vsock_state in state.vsock_device.iter().chain(state.vsock_devices.iter()) {
             let ctor_args = VsockUdsConstructorArgs {
                 cid: vsock_state.device_state.frontend.cid,
             };
@@ -520,6 +576,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                     VsockConstructorArgs {
                         mem: mem.clone(),
                         backend,
+                        id: vsock_state.device_id.clone(),
                     },
                     &vsock_state.device_state.frontend,
                 )
@@ -553,8 +610,10 @@ mod tests {
     use crate::builder::tests::*;
     use crate::resources::VmmConfig;
     use crate::vmm_config::balloon::BalloonDeviceConfig;
-    use crate::vmm_config::net::NetworkInterfaceConfig;
-    use crate::vmm_config::vsock::VsockDeviceConfig;
+    use crate::vmm_config::net::{
+        NetBackendType, NetDatapath, NetOffloads, NetworkInterfaceConfig,
+    };
+    use crate::vmm_config::vsock::{VsockDatapath, VsockDeviceConfig};
 
     impl PartialEq for ConnectedBalloonState {
         fn eq(&self, other: &ConnectedBalloonState) -> bool {
@@ -630,6 +689,7 @@ mod tests {
                 && self.block_devices == other.block_devices
                 && self.net_devices == other.net_devices
                 && self.vsock_device == other.vsock_device
+                && self.vsock_devices == other.vsock_devices
         }
     }
 
@@ -637,8 +697,9 @@ mod tests {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
             write!(
                 f,
-                "DevicesStates {{ block_devices: {:?}, net_devices: {:?}, vsock_device: {:?} }}",
-                self.block_devices, self.net_devices, self.vsock_device
+                "DevicesStates {{ block_devices: {:?}, net_devices: {:?}, vsock_device: {:?}, \
+                 vsock_devices: {:?} }}",
+                self.block_devices, self.net_devices, self.vsock_device, self.vsock_devices
             )
         }
     }
@@ -680,6 +741,52 @@ mod tests {
         }
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_acpi_device_persistence() {
+        let mut buf = vec![0; 1024];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(DeviceStates::type_id(), 4);
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        crate::builder::attach_acpi_device(&mut vmm, &mut event_manager).unwrap();
+        let states = vmm.mmio_device_manager.save();
+        assert!(states.acpi_device.is_some());
+
+        // Older versions can't persist the ACPI device.
+        states
+            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let device_states: DeviceStates =
+            DeviceStates::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap();
+        assert!(device_states.acpi_device.is_none());
+
+        states
+            .serialize(&mut buf.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let device_states: DeviceStates =
+            DeviceStates::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(device_states.acpi_device, states.acpi_device);
+
+        let restored_vmm = default_vmm();
+        let vm_resources = &mut VmResources::default();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: restored_vmm.guest_memory().clone(),
+            vm: restored_vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            for_each_restored_device: VmResources::update_from_restored_device,
+            vm_resources,
+            instance_id: "microvm-id",
+            exit_evt: &restored_vmm.vcpus_exit_evt,
+        };
+        let restored_dev_manager =
+            MMIODeviceManager::restore(restore_args, &device_states).unwrap();
+        assert_eq!(restored_dev_manager, vmm.mmio_device_manager);
+    }
+
     #[test]
     fn test_device_manager_persistence() {
         let mut buf = vec![0; 16384];
@@ -699,6 +806,7 @@ mod tests {
                 amount_mib: 123,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 1,
+                free_page_reporting: false,
             };
             insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_cfg);
             // Add a block device.
@@ -719,6 +827,18 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                backend_type: NetBackendType::default(),
+                num_queues: 1,
+                backend: NetDatapath::Virtio,
+                mtu: None,
+                offloads: NetOffloads::default(),
+                tap_fd: None,
+                xsks_map_path: None,
+                netns: None,
+                rl_group: None,
+                rx_filtering: false,
+                filters: Vec::new(),
+                worker_thread: false,
             };
             insert_net_device_with_mmds(
                 &mut vmm,
@@ -733,6 +853,10 @@ mod tests {
                 vsock_id: Some(vsock_dev_id.to_string()),
                 guest_cid: 3,
                 uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                backend: VsockDatapath::Virtio,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                uds_permissions: None,
             };
             insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
 
@@ -758,8 +882,8 @@ mod tests {
                 .set_type_version(DeviceStates::type_id(), 3);
 
             // For snapshot versions that not support persisting the mmds version, it should be
-            // deserialized as None. The MMIODeviceManager will initialise it as the default if
-            // there's at least one network device having a MMDS NS.
+            // deserialized as None. The snapshot translation sets it to the default if there's
+            // at least one network device having a MMDS NS.
             vmm.mmio_device_manager
                 .save()
                 .serialize(&mut buf.as_mut_slice(), &version_map, 2)
@@ -790,6 +914,7 @@ mod tests {
             for_each_restored_device: VmResources::update_from_restored_device,
             vm_resources,
             instance_id: "microvm-id",
+            exit_evt: &vmm.vcpus_exit_evt,
         };
         let restored_dev_manager =
             MMIODeviceManager::restore(restore_args, &device_states).unwrap();
@@ -799,7 +924,8 @@ mod tests {
   "balloon": {{
     "amount_mib": 123,
     "deflate_on_oom": false,
-    "stats_polling_interval_s": 1
+    "stats_polling_interval_s": 1,
+    "free_page_reporting": false
   }},
   "drives": [
     {{
@@ -830,7 +956,8 @@ mod tests {
     "network_interfaces": [
       "netif"
     ],
-    "ipv4_address": "169.254.169.254"
+    "ipv4_address": "169.254.169.254",
+    "ipv6_address": null
   }},
   "network-interfaces": [
     {{
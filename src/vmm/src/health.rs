@@ -0,0 +1,133 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use serde::Serialize;
+use utils::time::{get_time_us, ClockType};
+
+/// Period at which the event loop of the VMM thread is expected to report that it is
+/// responsive, with `Health::beat`.
+pub const HEARTBEAT_INTERVAL_MS: u64 = 1000;
+// The event loop is considered stalled after missing this many heartbeats.
+const MISSED_HEARTBEATS_LIMIT: u64 = 5;
+
+/// Health of the VMM threads, updated by the threads themselves.
+pub static HEALTH: Health = Health::new();
+
+/// Tracks the health of the VMM threads, so that it can be checked without going through the
+/// VMM thread, which may be the one being stuck.
+pub struct Health {
+    // Time of the last heartbeat of the event loop, or 0 while the event loop, which only runs
+    // once the microVM is started, doesn't beat.
+    last_heartbeat_us: AtomicU64,
+    vcpus_failed: AtomicBool,
+    running: AtomicBool,
+}
+
+impl Health {
+    const fn new() -> Health {
+        Health {
+            last_heartbeat_us: AtomicU64::new(0),
+            vcpus_failed: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// Reports that the event loop of the VMM thread, which serves the devices, is responsive.
+    pub fn beat(&self) {
+        self.last_heartbeat_us
+            .store(get_time_us(ClockType::Monotonic), Ordering::Relaxed);
+    }
+
+    /// Reports that a vCPU exited with an error or stopped responding to the VMM.
+    pub(crate) fn report_vcpu_failure(&self) {
+        self.vcpus_failed.store(true, Ordering::Relaxed);
+    }
+
+    /// Reports whether the vCPUs are running the guest.
+    pub(crate) fn set_running(&self, running: bool) {
+        self.running.store(running, Ordering::Relaxed);
+    }
+
+    /// Returns the current health of the VMM threads.
+    pub fn report(&self) -> HealthReport {
+        self.report_at(get_time_us(ClockType::Monotonic))
+    }
+
+    fn report_at(&self, now_us: u64) -> HealthReport {
+        let last_heartbeat_us = self.last_heartbeat_us.load(Ordering::Relaxed);
+        HealthReport {
+            event_loop: last_heartbeat_us == 0
+                || now_us.saturating_sub(last_heartbeat_us)
+                    < MISSED_HEARTBEATS_LIMIT * HEARTBEAT_INTERVAL_MS * 1000,
+            vcpus: !self.vcpus_failed.load(Ordering::Relaxed),
+            running: self.running.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Outcome of the health checks of the VMM threads.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HealthReport {
+    /// Whether the event loop of the VMM thread, which serves the devices, is responsive.
+    pub event_loop: bool,
+    /// Whether all the vCPUs are healthy.
+    pub vcpus: bool,
+    /// Whether the vCPUs are running the guest.
+    pub running: bool,
+}
+
+impl HealthReport {
+    /// Whether the VMM threads are healthy.
+    pub fn is_healthy(&self) -> bool {
+        self.event_loop && self.vcpus
+    }
+
+    /// Whether the VMM threads are healthy and the guest is running.
+    pub fn is_ready(&self) -> bool {
+        self.is_healthy() && self.running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_report() {
+        let health = Health::new();
+        // The event loop doesn't beat before the microVM is started.
+        let report = health.report_at(1_000_000_000);
+        assert_eq!(
+            report,
+            HealthReport {
+                event_loop: true,
+                vcpus: true,
+                running: false,
+            }
+        );
+        assert!(report.is_healthy());
+        assert!(!report.is_ready());
+
+        health.set_running(true);
+        health.last_heartbeat_us.store(1_000, Ordering::Relaxed);
+        let report = health.report_at(1_000 + 4 * HEARTBEAT_INTERVAL_MS * 1000);
+        assert!(report.is_healthy());
+        assert!(report.is_ready());
+
+        // Missed heartbeats.
+        let report = health.report_at(1_000 + 5 * HEARTBEAT_INTERVAL_MS * 1000);
+        assert!(!report.event_loop);
+        assert!(!report.is_healthy());
+        assert!(!report.is_ready());
+
+        health.beat();
+        assert!(health.report().event_loop);
+
+        health.report_vcpu_failure();
+        let report = health.report();
+        assert!(!report.vcpus);
+        assert!(!report.is_healthy());
+    }
+}
@@ -13,7 +13,13 @@
 /// Handles setup and initialization a `Vmm` object.
 pub mod builder;
 pub(crate) mod device_manager;
+/// Health tracking of the VMM threads.
+pub mod health;
+/// Lifecycle events of the microVM, polled through the API.
+pub mod lifecycle_events;
 pub mod memory_snapshot;
+/// Live migration of the microVM to another Firecracker.
+pub mod migration;
 /// Save/restore utilities.
 pub mod persist;
 /// Resource store for configured microVM resources.
@@ -24,6 +30,11 @@ pub mod rpc_interface;
 pub mod seccomp_filters;
 /// Signal handling utilities.
 pub mod signal_handler;
+mod snapshot_schedule;
+/// Translation of the microVM state of snapshots taken by previous releases.
+pub mod snapshot_translation;
+/// Page fault handler loading the guest memory lazily.
+pub mod uffd_handler;
 /// Utility functions for integration and benchmark testing
 pub mod utilities;
 /// microVM state versions.
@@ -42,28 +53,46 @@ use std::time::Duration;
 
 use arch::DeviceType;
 use devices::legacy::serial::{IER_RDA_BIT, IER_RDA_OFFSET};
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::AcpiDevice;
 use devices::virtio::balloon::Error as BalloonError;
+use devices::virtio::mem::{Error as MemError, MEM_DEV_ID};
 use devices::virtio::{
-    Balloon, BalloonConfig, BalloonStats, Block, MmioTransport, Net, BALLOON_DEV_ID, TYPE_BALLOON,
-    TYPE_BLOCK, TYPE_NET,
+    Balloon, BalloonConfig, BalloonPolicy, BalloonStats, Block, MmioTransport, Net, NetStats,
+    VirtioMem, Vsock, VsockConnectionInfo, VsockUnixBackend, BALLOON_DEV_ID, TYPE_BALLOON,
+    TYPE_BLOCK, TYPE_MEM, TYPE_NET, TYPE_VSOCK,
 };
 use devices::BusDevice;
 use event_manager::{EventManager as BaseEventManager, EventOps, Events, MutEventSubscriber};
 use logger::{error, info, warn, LoggerError, MetricsError, METRICS};
+use mmds::persist::MmdsState;
 use rate_limiter::BucketUpdate;
 use seccompiler::BpfProgram;
 use snapshot::Persist;
+use timerfd::{SetTimeFlags, TimerFd, TimerState};
 use userfaultfd::Uffd;
 use utils::epoll::EventSet;
 use utils::eventfd::EventFd;
-use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use utils::get_page_size;
+use utils::net::mac::MacAddr;
+use utils::time::{get_time_us, ClockType};
+use vm_memory::{Bitmap, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
+use crate::health::HEALTH;
+use crate::lifecycle_events::{LifecycleEventKind, LIFECYCLE_EVENTS};
 use crate::memory_snapshot::SnapshotMemory;
-use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
+use crate::persist::{DiffSnapshotParent, MicrovmState, MicrovmStateError, VmInfo};
+use crate::snapshot_schedule::SnapshotSchedule;
+use crate::vmm_config::drive::DriveTraceConfig;
 use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::memory_hotplug::MemoryHotplugStatus;
+use crate::vmm_config::net::NetworkCaptureConfig;
+use crate::vmm_config::snapshot::{DirtyRate, SnapshotScheduleConfig};
+use crate::vmm_config::vsock::VsockConnectionPoolConfig;
+use crate::vmm_config::RateLimiterUpdate;
 use crate::vstate::vcpu::{Vcpu, VcpuEvent, VcpuHandle, VcpuResponse, VcpuState};
 use crate::vstate::vm::Vm;
 
@@ -118,6 +147,9 @@ pub const HTTP_MAX_PAYLOAD_SIZE: usize = 51200;
 /// have permissions to open the KVM fd).
 #[derive(Debug)]
 pub enum Error {
+    /// The ACPI device failed to be created or to press the power button.
+    #[cfg(target_arch = "x86_64")]
+    Acpi(io::Error),
     /// Legacy devices work with Event file descriptors and the creation can fail because
     /// of resource exhaustion.
     #[cfg(target_arch = "x86_64")]
@@ -142,6 +174,8 @@ pub enum Error {
     Logger(LoggerError),
     /// Internal metrics system error.
     Metrics(MetricsError),
+    /// Cannot fetch the system's page size.
+    PageSize(utils::errno::Error),
     /// Cannot add a device to the MMIO Bus.
     RegisterMMIODevice(device_manager::mmio::Error),
     /// Cannot install seccomp filters.
@@ -184,6 +218,8 @@ impl Display for Error {
         use self::Error::*;
 
         match self {
+            #[cfg(target_arch = "x86_64")]
+            Acpi(e) => write!(f, "ACPI device error: {}", e),
             #[cfg(target_arch = "x86_64")]
             CreateLegacyDevice(e) => write!(f, "Error creating legacy device: {}", e),
             DeviceManager(e) => write!(f, "{}", e),
@@ -196,6 +232,7 @@ impl Display for Error {
             LegacyIOBus(e) => write!(f, "Cannot add devices to the legacy I/O Bus. {}", e),
             Logger(e) => write!(f, "Logger error: {}", e),
             Metrics(e) => write!(f, "Metrics error: {}", e),
+            PageSize(e) => write!(f, "Cannot fetch the system's page size: {}", e),
             RegisterMMIODevice(e) => write!(f, "Cannot add a device to the MMIO Bus. {}", e),
             SeccompFilters(e) => write!(f, "Cannot install seccomp filters: {}", e),
             Serial(e) => write!(f, "Error writing to the serial console: {}", e),
@@ -268,6 +305,17 @@ pub struct Vmm {
     mmio_device_manager: MMIODeviceManager,
     #[cfg(target_arch = "x86_64")]
     pio_device_manager: PortIODeviceManager,
+    // Block devices attached at runtime, waiting to be registered with the event manager.
+    hotplugged_blocks: Vec<Arc<Mutex<Block>>>,
+    // Balloon device attached at runtime, waiting to be registered with the event manager.
+    hotplugged_balloon: Option<Arc<Mutex<Balloon>>>,
+    // Snapshot created or loaded last, which the next diff snapshot is taken on top of.
+    diff_parent: DiffSnapshotParent,
+    // Time of the last dirty page rate sample, or of the start of the microVM.
+    dirty_rate_sample_us: u64,
+    // Fires when the next scheduled snapshot is due.
+    snapshot_timer: TimerFd,
+    snapshot_schedule: Option<SnapshotSchedule>,
 }
 
 impl Vmm {
@@ -326,6 +374,7 @@ impl Vmm {
         self.instance_info.state = VmState::Paused;
         // Wait for vCPUs to initialize their TLS before moving forward.
         barrier.wait();
+        LIFECYCLE_EVENTS.record(LifecycleEventKind::Started);
 
         Ok(())
     }
@@ -347,10 +396,13 @@ impl Vmm {
             .map(|handle| handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC))
             .any(|response| !matches!(response, Ok(VcpuResponse::Resumed)))
         {
+            HEALTH.report_vcpu_failure();
             return Err(Error::VcpuMessage);
         }
 
         self.instance_info.state = VmState::Running;
+        HEALTH.set_running(true);
+        LIFECYCLE_EVENTS.record(LifecycleEventKind::Resumed);
         Ok(())
     }
 
@@ -369,10 +421,13 @@ impl Vmm {
             .map(|handle| handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC))
             .any(|response| !matches!(response, Ok(VcpuResponse::Paused)))
         {
+            HEALTH.report_vcpu_failure();
             return Err(Error::VcpuMessage);
         }
 
         self.instance_info.state = VmState::Paused;
+        HEALTH.set_running(false);
+        LIFECYCLE_EVENTS.record(LifecycleEventKind::Paused);
         Ok(())
     }
 
@@ -430,6 +485,25 @@ impl Vmm {
             .map_err(Error::I8042Error)
     }
 
+    /// Presses the ACPI power button of the guest. If `timeout` is given, the microVM is stopped
+    /// when the guest isn't powered off within it.
+    #[cfg(target_arch = "x86_64")]
+    pub fn send_acpi_shutdown(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let acpi = self
+            .get_bus_device(DeviceType::Acpi, &DeviceType::Acpi.to_string())
+            // Snapshots of older versions don't have the ACPI device.
+            .ok_or(Error::DeviceManager(
+                device_manager::mmio::Error::DeviceNotFound,
+            ))?;
+        acpi.lock()
+            .expect("Poisoned lock")
+            .as_mut_any()
+            .downcast_mut::<AcpiDevice>()
+            .expect("Unexpected BusDevice type")
+            .press_power_button(timeout)
+            .map_err(Error::Acpi)
+    }
+
     /// Saves the state of a paused Microvm.
     pub fn save_state(&mut self) -> std::result::Result<MicrovmState, MicrovmStateError> {
         use self::MicrovmStateError::SaveVmState;
@@ -457,9 +531,35 @@ impl Vmm {
             vm_state,
             vcpu_states,
             device_states,
+            parent: None,
+            mmds: self.save_mmds_state(),
+            compression: None,
         })
     }
 
+    // Saves the contents of the data store served to the guest through MMDS, if any.
+    fn save_mmds_state(&self) -> Option<MmdsState> {
+        let mut mmds_state = None;
+        let _: std::result::Result<(), ()> =
+            self.mmio_device_manager
+                .for_each_virtio_device(|virtio_type, _, _, device| {
+                    if virtio_type != TYPE_NET || mmds_state.is_some() {
+                        return Ok(());
+                    }
+                    let locked_device = device.lock().expect("Poisoned lock");
+                    // All the network interfaces serving MMDS share the same data store.
+                    if let Some(mmds_ns) = locked_device
+                        .as_any()
+                        .downcast_ref::<Net>()
+                        .and_then(|net| net.mmds_ns.as_ref())
+                    {
+                        mmds_state = MmdsState::save(&mmds_ns.mmds.lock().expect("Poisoned lock"));
+                    }
+                    Ok(())
+                });
+        mmds_state
+    }
+
     fn save_vcpu_states(&mut self) -> std::result::Result<Vec<VcpuState>, MicrovmStateError> {
         use self::MicrovmStateError::*;
         for handle in self.vcpus_handles.iter() {
@@ -545,6 +645,58 @@ impl Vmm {
         Ok(bitmap)
     }
 
+    /// Counts the pages dirtied by the guest since the previous sample, through the KVM dirty
+    /// log, and returns the rate they were dirtied at.
+    pub fn sample_dirty_rate(&mut self) -> Result<DirtyRate> {
+        let dirty_bitmap = self.get_dirty_bitmap()?;
+        let sample_us = get_time_us(ClockType::Monotonic);
+        let page_size = get_page_size().map_err(Error::PageSize)?;
+
+        let mut dirty_pages = 0u64;
+        for (slot, region) in self.guest_memory.iter().enumerate() {
+            let kvm_bitmap = dirty_bitmap.get(&slot).map_or(&[][..], Vec::as_slice);
+            for (i, word) in kvm_bitmap.iter().enumerate() {
+                dirty_pages += u64::from(word.count_ones());
+                // Reading the dirty log clears it, the sampled pages are kept in the bitmap of
+                // the region for the next diff snapshot or migration round.
+                for j in (0..64).filter(|j| (word >> j) & 1 != 0) {
+                    region
+                        .bitmap()
+                        .mark_dirty((i * 64 + j) * page_size, page_size);
+                }
+            }
+        }
+
+        let interval_us = sample_us.saturating_sub(self.dirty_rate_sample_us).max(1);
+        self.dirty_rate_sample_us = sample_us;
+        Ok(DirtyRate {
+            dirty_pages,
+            interval_ms: interval_us / 1000,
+            dirty_pages_per_sec: dirty_pages * 1_000_000 / interval_us,
+        })
+    }
+
+    /// Starts taking snapshots periodically, replacing the previous schedule, if any.
+    pub fn schedule_snapshots(&mut self, config: SnapshotScheduleConfig) {
+        let schedule = SnapshotSchedule::new(config);
+        let interval = schedule.interval();
+        self.snapshot_timer.set_state(
+            TimerState::Periodic {
+                current: interval,
+                interval,
+            },
+            SetTimeFlags::Default,
+        );
+        self.snapshot_schedule = Some(schedule);
+    }
+
+    /// Stops taking snapshots periodically.
+    pub fn cancel_snapshot_schedule(&mut self) {
+        self.snapshot_timer
+            .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+        self.snapshot_schedule = None;
+    }
+
     /// Enables or disables KVM dirty page tracking.
     pub fn set_dirty_page_tracking(&mut self, enable: bool) -> Result<()> {
         // This function _always_ results in an ioctl update. The VMM is stateless in the sense
@@ -569,6 +721,68 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Grows the block device with id `drive_id` to `size_bytes`, along with its backing file,
+    /// and lets the guest know about its new capacity.
+    pub fn resize_block_device(&mut self, drive_id: &str, size_bytes: u64) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block.resize(size_bytes).map_err(|e| format!("{:?}", e))
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Makes the block device with id `drive_id` read-only or read-write, reopening its backing
+    /// file accordingly.
+    pub fn set_block_device_read_only(&mut self, drive_id: &str, read_only: bool) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block
+                    .set_read_only(read_only)
+                    .map_err(|e| format!("{:?}", e))
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Starts or stops the trace of the requests of the block device with id `config.drive_id`.
+    pub fn set_block_trace(&mut self, config: &DriveTraceConfig) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, &config.drive_id, |block: &mut Block| {
+                config.apply(block).map_err(|e| e.to_string())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Checks that the block device with id `drive_id` exists and, when `path_on_host` is set,
+    /// that it could be backed by that file, when `size_bytes` is set, that it could be
+    /// resized to it, and when `updates_read_only` is set, that its read-only mode could be
+    /// changed, without changing the device.
+    pub fn validate_block_device_update(
+        &self,
+        drive_id: &str,
+        path_on_host: Option<&str>,
+        size_bytes: Option<u64>,
+        updates_read_only: bool,
+    ) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                if let Some(path) = path_on_host {
+                    block
+                        .validate_disk_image(path)
+                        .map_err(|e| format!("{:?}", e))?;
+                }
+                if let Some(size_bytes) = size_bytes {
+                    block
+                        .validate_resize(size_bytes)
+                        .map_err(|e| format!("{:?}", e))?;
+                }
+                if updates_read_only {
+                    block.validate_read_only().map_err(|e| format!("{:?}", e))?;
+                }
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
     /// Updates the rate limiter parameters for block device with `drive_id` id.
     pub fn update_block_rate_limiter(
         &mut self,
@@ -584,23 +798,328 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Updates the parameters of the rate limiters of the reads and of the writes for block
+    /// device with `drive_id` id.
+    pub fn update_block_read_write_rate_limiters(
+        &mut self,
+        drive_id: &str,
+        read: RateLimiterUpdate,
+        write: RateLimiterUpdate,
+    ) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, move |block: &mut Block| {
+                block.update_read_rate_limiter(read.bandwidth, read.ops);
+                block.update_write_rate_limiter(write.bandwidth, write.ops);
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
     /// Updates the rate limiter parameters for net device with `net_id` id.
     pub fn update_net_rate_limiters(
         &mut self,
         net_id: &str,
-        rx_bytes: BucketUpdate,
-        rx_ops: BucketUpdate,
-        tx_bytes: BucketUpdate,
-        tx_ops: BucketUpdate,
+        rx: RateLimiterUpdate,
+        tx: RateLimiterUpdate,
+    ) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, move |net: &mut Net| {
+                Self::check_net_rate_limiters_supported(net)?;
+                net.patch_rate_limiters(rx.bandwidth, rx.ops, rx.pps, tx.bandwidth, tx.ops, tx.pps);
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Updates the MAC address the net device with id `net_id` advertises to the guest, and
+    /// lets the guest driver know about it.
+    pub fn update_net_guest_mac(&mut self, net_id: &str, guest_mac: MacAddr) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.set_guest_mac(guest_mac)
+                    .map_err(|_| Self::net_guest_mac_unsupported())
+            })
+            .map_err(Error::DeviceManager)?;
+        self.mmio_device_manager
+            .signal_config_change(TYPE_NET, net_id)
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Reports the link of the net device with id `net_id` as up or down to the guest, and lets
+    /// the guest driver know about it.
+    pub fn update_net_link_state(&mut self, net_id: &str, link_up: bool) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.set_link_up(link_up);
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)?;
+        self.mmio_device_manager
+            .signal_config_change(TYPE_NET, net_id)
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Starts or stops the capture of the frames of the net device with id `config.iface_id`.
+    pub fn set_net_capture(&mut self, config: &NetworkCaptureConfig) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, &config.iface_id, |net: &mut Net| {
+                config.apply(net).map_err(|e| e.to_string())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Returns the traffic statistics of the net device with id `net_id`.
+    pub fn net_stats(&self, net_id: &str) -> Result<NetStats> {
+        let mut stats = NetStats::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                if net.uses_vhost() {
+                    return Err(
+                        "Statistics are not available for interfaces using the vhost datapath."
+                            .to_string(),
+                    );
+                }
+                stats = net.stats();
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)?;
+        Ok(stats)
+    }
+
+    /// Returns the active connections of the vsock device with id `vsock_id`.
+    pub fn vsock_connections(&self, vsock_id: &str) -> Result<Vec<VsockConnectionInfo>> {
+        let mut conns = Vec::new();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(
+                TYPE_VSOCK,
+                vsock_id,
+                |vsock: &mut Vsock<VsockUnixBackend>| {
+                    if vsock.uses_vhost() {
+                        return Err(
+                            "Connections are not tracked with the vhost datapath.".to_string()
+                        );
+                    }
+                    conns = vsock.backend().connections();
+                    Ok(())
+                },
+            )
+            .map_err(Error::DeviceManager)?;
+        Ok(conns)
+    }
+
+    /// Initiates connections to a guest port of the vsock device with id `vsock_id`, which the
+    /// host takes over later.
+    pub fn pool_vsock_connections(
+        &self,
+        vsock_id: &str,
+        pool_cfg: &VsockConnectionPoolConfig,
+    ) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(
+                TYPE_VSOCK,
+                vsock_id,
+                |vsock: &mut Vsock<VsockUnixBackend>| {
+                    if vsock.uses_vhost() {
+                        return Err(
+                            "Connections can't be pooled with the vhost datapath.".to_string()
+                        );
+                    }
+                    vsock
+                        .backend_mut()
+                        .pool_connections(pool_cfg.port, pool_cfg.count as usize)
+                        .map_err(|e| format!("{:?}", e))?;
+                    // The connection requests are sent to the guest right away.
+                    vsock.flush_backend_rx().map_err(|e| format!("{:?}", e))
+                },
+            )
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Checks that the net device with id `net_id` exists and can be updated, without changing it.
+    pub fn validate_net_device_update(
+        &self,
+        net_id: &str,
+        update_guest_mac: bool,
+        update_rate_limiters: bool,
     ) -> Result<()> {
         self.mmio_device_manager
             .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
-                net.patch_rate_limiters(rx_bytes, rx_ops, tx_bytes, tx_ops);
+                if update_guest_mac && !net.advertises_guest_mac() {
+                    return Err(Self::net_guest_mac_unsupported());
+                }
+                if update_rate_limiters {
+                    Self::check_net_rate_limiters_supported(net)?;
+                }
                 Ok(())
             })
             .map_err(Error::DeviceManager)
     }
 
+    /// Attaches the block device `block` to the running microVM. The guest isn't notified, it has
+    /// to probe the device itself, at the MMIO address and IRQ line published in the device tags.
+    pub fn add_block_device(&mut self, block: Arc<Mutex<Block>>) -> Result<()> {
+        let id = block.lock().expect("Poisoned lock").id().clone();
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        let device = MmioTransport::new(self.guest_memory.clone(), block.clone());
+        self.hotplug_virtio_device(TYPE_BLOCK, id.clone(), device)?;
+        self.hotplugged_blocks.push(block);
+        LIFECYCLE_EVENTS.record(LifecycleEventKind::DeviceAttached { id });
+        Ok(())
+    }
+
+    /// Attaches the balloon device `balloon` to the running microVM. As for block devices, the
+    /// guest has to probe it itself.
+    pub fn add_balloon_device(&mut self, balloon: Arc<Mutex<Balloon>>) -> Result<()> {
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        let device = MmioTransport::new(self.guest_memory.clone(), balloon.clone());
+        self.hotplug_virtio_device(TYPE_BALLOON, BALLOON_DEV_ID.to_string(), device)?;
+        self.hotplugged_balloon = Some(balloon);
+        LIFECYCLE_EVENTS.record(LifecycleEventKind::DeviceAttached {
+            id: BALLOON_DEV_ID.to_string(),
+        });
+        Ok(())
+    }
+
+    // Registers `device` on the MMIO bus and hands the updated bus to the vCPUs.
+    fn hotplug_virtio_device(
+        &mut self,
+        device_type: u32,
+        id: String,
+        device: MmioTransport,
+    ) -> Result<()> {
+        let slot = self
+            .mmio_device_manager
+            .register_mmio_virtio_for_hotplug(self.vm.fd(), id.clone(), device)
+            .map_err(Error::RegisterMMIODevice)?;
+        if let Err(e) = self.update_vcpus_mmio_bus() {
+            let _ = self
+                .mmio_device_manager
+                .remove_virtio_device(self.vm.fd(), device_type, &id);
+            return Err(e);
+        }
+        info!(
+            "Attached virtio device {} at {:#x}, irq {}.",
+            id, slot.addr, slot.irqs[0]
+        );
+        Ok(())
+    }
+
+    /// Detaches the block device with id `drive_id` from the guest, once its in-flight requests
+    /// are completed and its backing file is flushed.
+    pub fn remove_block_device(&mut self, drive_id: &str) -> Result<()> {
+        let device = self
+            .mmio_device_manager
+            .remove_virtio_device(self.vm.fd(), TYPE_BLOCK, drive_id)
+            .map_err(Error::DeviceManager)?;
+        let mut locked_device = device.lock().expect("Poisoned lock");
+        locked_device
+            .as_mut_any()
+            .downcast_mut::<Block>()
+            .ok_or(Error::DeviceManager(
+                device_manager::mmio::Error::IncorrectDeviceType,
+            ))?
+            .unplug()
+            .map_err(Error::EventFd)?;
+        LIFECYCLE_EVENTS.record(LifecycleEventKind::DeviceDetached {
+            id: drive_id.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Checks that the block device with id `drive_id` exists and can be removed, without
+    /// removing it.
+    pub fn validate_block_device_removal(&self, drive_id: &str) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |_: &mut Block| Ok(()))
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Returns the block devices attached since the last call, which have to be registered with
+    /// the event manager to process their events.
+    pub fn take_hotplugged_blocks(&mut self) -> Vec<Arc<Mutex<Block>>> {
+        std::mem::take(&mut self.hotplugged_blocks)
+    }
+
+    /// Returns the balloon device attached since the last call, if any, which has to be
+    /// registered with the event manager to process its events.
+    pub fn take_hotplugged_balloon(&mut self) -> Option<Arc<Mutex<Balloon>>> {
+        self.hotplugged_balloon.take()
+    }
+
+    // The vCPUs are given a copy of the MMIO bus when they start, so they have to be handed the
+    // current one for the devices attached afterwards to be reachable.
+    fn update_vcpus_mmio_bus(&mut self) -> Result<()> {
+        // Send the events.
+        self.vcpus_handles
+            .iter()
+            .try_for_each(|handle| {
+                handle.send_event(VcpuEvent::UpdateMmioBus(
+                    self.mmio_device_manager.bus.clone(),
+                ))
+            })
+            .map_err(|_| Error::VcpuMessage)?;
+
+        // Check the responses.
+        if self
+            .vcpus_handles
+            .iter()
+            .map(|handle| handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC))
+            .any(|response| !matches!(response, Ok(VcpuResponse::UpdatedMmioBus)))
+        {
+            return Err(Error::VcpuMessage);
+        }
+        Ok(())
+    }
+
+    /// Detaches the net device with id `net_id` from the guest and closes its host-side
+    /// interfaces.
+    pub fn remove_net_device(&mut self, net_id: &str) -> Result<()> {
+        let device = self
+            .mmio_device_manager
+            .remove_virtio_device(self.vm.fd(), TYPE_NET, net_id)
+            .map_err(Error::DeviceManager)?;
+        let locked_device = device.lock().expect("Poisoned lock");
+        locked_device
+            .as_any()
+            .downcast_ref::<Net>()
+            .ok_or(Error::DeviceManager(
+                device_manager::mmio::Error::IncorrectDeviceType,
+            ))?
+            .unplug()
+            .map_err(Error::EventFd)?;
+        LIFECYCLE_EVENTS.record(LifecycleEventKind::DeviceDetached {
+            id: net_id.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Checks that the net device with id `net_id` exists and can be removed, without removing
+    /// it.
+    pub fn validate_net_device_removal(&self, net_id: &str) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |_: &mut Net| Ok(()))
+            .map_err(Error::DeviceManager)
+    }
+
+    fn check_net_rate_limiters_supported(net: &Net) -> std::result::Result<(), String> {
+        if net.uses_vhost() {
+            return Err(
+                "Rate limiters are not supported for interfaces using the vhost datapath."
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    fn net_guest_mac_unsupported() -> String {
+        "The guest MAC address can only be updated for interfaces created with one.".to_string()
+    }
+
+    /// Returns the device tags to be exposed to the guest through MMDS.
+    pub fn device_tags(&self) -> serde_json::Value {
+        self.mmio_device_manager.device_tags()
+    }
+
     /// Returns a reference to the balloon device if present.
     pub fn balloon_config(&self) -> std::result::Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -726,6 +1245,185 @@ impl Vmm {
         }
     }
 
+    /// Sets the policy adjusting the balloon target size from its statistics, or removes it.
+    pub fn set_balloon_policy(
+        &mut self,
+        policy: Option<BalloonPolicy>,
+    ) -> std::result::Result<(), BalloonError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<Balloon>()
+                .unwrap()
+                .set_policy(policy)?;
+            Ok(())
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
+    /// Checks that the balloon device target size could be updated to `amount_mib`,
+    /// without changing it.
+    pub fn validate_balloon_config(
+        &self,
+        amount_mib: u32,
+    ) -> std::result::Result<(), BalloonError> {
+        if amount_mib as u64 > mem_size_mib(self.guest_memory()) {
+            return Err(BalloonError::TooManyPagesRequested);
+        }
+
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<Balloon>()
+                .unwrap()
+                .validate_size(amount_mib)?;
+
+            Ok(())
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
+    /// Checks that the balloon statistics polling interval could be updated to
+    /// `stats_polling_interval_s`, without changing it.
+    pub fn validate_balloon_stats_config(
+        &self,
+        stats_polling_interval_s: u16,
+    ) -> std::result::Result<(), BalloonError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<Balloon>()
+                .unwrap()
+                .validate_stats_polling_interval(stats_polling_interval_s)?;
+
+            Ok(())
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
+    /// Returns the state of the hot-pluggable memory.
+    pub fn memory_hotplug_status(&self) -> std::result::Result<MemoryHotplugStatus, MemError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID) {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            let locked = virtio_device.lock().expect("Poisoned lock");
+            let virtio_mem = locked.as_any().downcast_ref::<VirtioMem>().unwrap();
+
+            Ok(MemoryHotplugStatus {
+                total_size_mib: (virtio_mem.region_size() >> 20) as usize,
+                block_size_mib: (virtio_mem.block_size() >> 20) as usize,
+                plugged_size_mib: (virtio_mem.plugged_size() >> 20) as usize,
+                requested_size_mib: (virtio_mem.requested_size() >> 20) as usize,
+            })
+        } else {
+            Err(MemError::DeviceNotFound)
+        }
+    }
+
+    /// Asks the guest to plug or unplug memory until `requested_size_mib` MiB are plugged.
+    pub fn update_memory_hotplug(
+        &mut self,
+        requested_size_mib: usize,
+    ) -> std::result::Result<(), MemError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID) {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<VirtioMem>()
+                .unwrap()
+                .update_requested_size((requested_size_mib as u64) << 20)?;
+            LIFECYCLE_EVENTS
+                .record(LifecycleEventKind::MemoryResizeRequested { requested_size_mib });
+            Ok(())
+        } else {
+            Err(MemError::DeviceNotFound)
+        }
+    }
+
+    /// Checks that the size of the memory requested from the guest could be updated to
+    /// `requested_size_mib` MiB, without changing it.
+    pub fn validate_memory_hotplug_update(
+        &self,
+        requested_size_mib: usize,
+    ) -> std::result::Result<(), MemError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID) {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                // Only MmioTransport implements BusDevice at this point.
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<VirtioMem>()
+                .unwrap()
+                .validate_requested_size((requested_size_mib as u64) << 20)
+        } else {
+            Err(MemError::DeviceNotFound)
+        }
+    }
+
     /// Signals Vmm to stop and exit.
     pub fn stop(&mut self, exit_code: FcExitCode) {
         // To avoid cycles, all teardown paths take the following route:
@@ -746,6 +1444,7 @@ impl Vmm {
         // Once `vmm.shutdown_exit_code` becomes `Some(exit_code)`, it is the upper layer's
         // responsibility to break main event loop and propagate the exit code value.
         info!("Vmm is stopping.");
+        HEALTH.set_running(false);
 
         // We send a "Finish" event.  If a VCPU has already exited, this is the only
         // message it will accept... but running and paused will take it as well.
@@ -860,6 +1559,9 @@ impl MutEventSubscriber for Vmm {
                 }
             }
             self.stop(exit_code.unwrap_or(FcExitCode::Ok));
+        } else if source == self.snapshot_timer.as_raw_fd() && event_set == EventSet::IN {
+            self.snapshot_timer.read();
+            snapshot_schedule::take_scheduled_snapshot(self);
         } else {
             error!("Spurious EventManager event for handler: Vmm");
         }
@@ -869,5 +1571,8 @@ impl MutEventSubscriber for Vmm {
         if let Err(e) = ops.add(Events::new(&self.vcpus_exit_evt, EventSet::IN)) {
             error!("Failed to register vmm exit event: {}", e);
         }
+        if let Err(e) = ops.add(Events::new(&self.snapshot_timer, EventSet::IN)) {
+            error!("Failed to register snapshot schedule event: {}", e);
+        }
     }
 }
@@ -0,0 +1,215 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use utils::time::{get_time_us, ClockType};
+
+// Number of events kept for the clients polling them, the oldest ones are dropped first.
+const LIFECYCLE_EVENTS_CAPACITY: usize = 1024;
+
+lazy_static! {
+    /// Lifecycle events of the microVM, recorded by the threads where they happen.
+    pub static ref LIFECYCLE_EVENTS: LifecycleEvents = LifecycleEvents::new(LIFECYCLE_EVENTS_CAPACITY);
+}
+
+/// What happened to the microVM.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LifecycleEventKind {
+    /// The vCPUs were started, after a boot or a snapshot load.
+    Started,
+    /// The vCPUs were paused.
+    Paused,
+    /// The vCPUs were resumed.
+    Resumed,
+    /// A device was attached to the running microVM.
+    DeviceAttached {
+        /// ID of the device.
+        id: String,
+    },
+    /// A device was detached from the running microVM.
+    DeviceDetached {
+        /// ID of the device.
+        id: String,
+    },
+    /// The guest was asked to resize its hot-pluggable memory.
+    MemoryResizeRequested {
+        /// Requested size of the hot-pluggable memory, in MiB.
+        requested_size_mib: usize,
+    },
+}
+
+/// An event of the log, with its position in it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LifecycleEvent {
+    /// Position of the event in the log, starting at 1.
+    pub seq: u64,
+    /// Wall clock time the event was recorded at, in microseconds since the epoch.
+    pub timestamp_us: u64,
+    /// What happened.
+    #[serde(flatten)]
+    pub kind: LifecycleEventKind,
+}
+
+/// The events recorded after a cursor, returned by `GET /events`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LifecycleEventsPage {
+    /// The events recorded after the cursor, oldest first.
+    pub events: Vec<LifecycleEvent>,
+    /// Cursor to poll the events recorded after these ones with.
+    pub next_since: u64,
+    /// Number of events recorded after the cursor which were dropped from the log before being
+    /// polled.
+    pub missed: u64,
+}
+
+/// Bounded log of the lifecycle events of the microVM. It is read by the API thread, so that
+/// polling it doesn't involve the VMM thread.
+pub struct LifecycleEvents {
+    capacity: usize,
+    inner: Mutex<EventLog>,
+}
+
+struct EventLog {
+    events: VecDeque<LifecycleEvent>,
+    last_seq: u64,
+}
+
+impl LifecycleEvents {
+    fn new(capacity: usize) -> LifecycleEvents {
+        LifecycleEvents {
+            capacity,
+            inner: Mutex::new(EventLog {
+                events: VecDeque::with_capacity(capacity),
+                last_seq: 0,
+            }),
+        }
+    }
+
+    /// Records that `kind` just happened.
+    pub(crate) fn record(&self, kind: LifecycleEventKind) {
+        let mut log = self.inner.lock().expect("Poisoned lock");
+        if log.events.len() == self.capacity {
+            log.events.pop_front();
+        }
+        log.last_seq += 1;
+        let event = LifecycleEvent {
+            seq: log.last_seq,
+            timestamp_us: get_time_us(ClockType::Real),
+            kind,
+        };
+        log.events.push_back(event);
+    }
+
+    /// Returns the events recorded after the one at position `since`, 0 for all of them.
+    pub fn since(&self, since: u64) -> LifecycleEventsPage {
+        let log = self.inner.lock().expect("Poisoned lock");
+        // A cursor from the future, e.g. from a previous Firecracker process, starts over.
+        let since = if since > log.last_seq { 0 } else { since };
+        let first_seq = log
+            .events
+            .front()
+            .map_or(log.last_seq + 1, |event| event.seq);
+        LifecycleEventsPage {
+            events: log
+                .events
+                .iter()
+                .filter(|event| event.seq > since)
+                .cloned()
+                .collect(),
+            next_since: log.last_seq,
+            missed: first_seq.saturating_sub(since + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifecycle_events() {
+        let log = LifecycleEvents::new(3);
+        assert_eq!(log.since(0), LifecycleEventsPage::default());
+
+        log.record(LifecycleEventKind::Started);
+        log.record(LifecycleEventKind::Resumed);
+        let page = log.since(0);
+        assert_eq!(
+            page.events
+                .iter()
+                .map(|event| (event.seq, event.kind.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, LifecycleEventKind::Started),
+                (2, LifecycleEventKind::Resumed)
+            ]
+        );
+        assert_eq!(page.next_since, 2);
+        assert_eq!(page.missed, 0);
+        assert!(page.events[0].timestamp_us <= page.events[1].timestamp_us);
+
+        // Nothing new.
+        assert_eq!(
+            log.since(2),
+            LifecycleEventsPage {
+                events: vec![],
+                next_since: 2,
+                missed: 0,
+            }
+        );
+
+        // The oldest events are dropped.
+        log.record(LifecycleEventKind::Paused);
+        log.record(LifecycleEventKind::DeviceAttached {
+            id: "drive1".to_string(),
+        });
+        log.record(LifecycleEventKind::DeviceDetached {
+            id: "drive1".to_string(),
+        });
+        let page = log.since(1);
+        assert_eq!(
+            page.events
+                .iter()
+                .map(|event| event.seq)
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(page.next_since, 5);
+        assert_eq!(page.missed, 1);
+        assert_eq!(log.since(0).missed, 2);
+        assert_eq!(log.since(4).events.len(), 1);
+
+        // A cursor past the last event starts over.
+        assert_eq!(log.since(42).events.len(), 3);
+    }
+
+    #[test]
+    fn test_lifecycle_event_serialization() {
+        let event = LifecycleEvent {
+            seq: 7,
+            timestamp_us: 1_000,
+            kind: LifecycleEventKind::MemoryResizeRequested {
+                requested_size_mib: 256,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            "{\"seq\":7,\"timestamp_us\":1000,\"type\":\"memory_resize_requested\",\
+             \"requested_size_mib\":256}"
+        );
+        let event = LifecycleEvent {
+            seq: 1,
+            timestamp_us: 1_000,
+            kind: LifecycleEventKind::Paused,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            "{\"seq\":1,\"timestamp_us\":1000,\"type\":\"paused\"}"
+        );
+    }
+}
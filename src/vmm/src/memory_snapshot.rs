@@ -5,9 +5,12 @@
 
 use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::SeekFrom;
+use std::io::{self, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::thread;
 
 use utils::{errno, get_page_size};
+use versionize::crc::CRC64Writer;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::{
@@ -29,6 +32,15 @@ pub struct GuestMemoryRegionState {
     pub size: usize,
     /// Offset in file/buffer where the region is saved.
     pub offset: u64,
+    /// CRC64 of the region contents, computed when taking a full snapshot.
+    #[version(start = 2, default_fn = "def_checksum")]
+    pub checksum: Option<u64>,
+}
+
+impl GuestMemoryRegionState {
+    fn def_checksum(_: u16) -> Option<u64> {
+        None
+    }
 }
 
 /// Describes guest memory regions and their snapshot file mappings.
@@ -48,12 +60,22 @@ where
     fn describe(&self) -> GuestMemoryState;
     /// Dumps all contents of GuestMemoryMmap to a writer.
     fn dump<T: std::io::Write>(&self, writer: &mut T) -> std::result::Result<(), Error>;
+    /// Dumps all contents of GuestMemoryMmap to a file, in the layout written by `dump`, with
+    /// `writers` threads each writing a disjoint range of the file.
+    fn dump_parallel(&self, file: &File, writers: usize) -> std::result::Result<(), Error>;
     /// Dumps all pages of GuestMemoryMmap present in `dirty_bitmap` to a writer.
     fn dump_dirty<T: std::io::Write + std::io::Seek>(
         &self,
         writer: &mut T,
         dirty_bitmap: &DirtyBitmap,
     ) -> std::result::Result<(), Error>;
+    /// Loads all contents of GuestMemoryMmap from a reader, in the layout written by `dump`.
+    fn load<T: std::io::Read>(&self, reader: &mut T) -> std::result::Result<(), Error>;
+    /// Computes the CRC64 of the contents of each region of GuestMemoryMmap.
+    fn checksums(&self) -> std::result::Result<Vec<u64>, Error>;
+    /// Checks the contents of each region of GuestMemoryMmap against the checksum `state`
+    /// holds for it, if any.
+    fn verify(&self, state: &GuestMemoryState) -> std::result::Result<(), Error>;
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -76,6 +98,14 @@ pub enum Error {
     PageSize(errno::Error),
     /// Cannot dump memory.
     WriteMemory(GuestMemoryError),
+    /// Cannot load memory.
+    ReadMemory(GuestMemoryError),
+    /// Cannot start a memory writer thread.
+    StartWriter(io::Error),
+    /// A memory writer thread panicked.
+    WriterPanicked,
+    /// The contents of the region at the given guest address don't match their checksum.
+    ChecksumMismatch(u64),
 }
 
 impl Display for Error {
@@ -87,6 +117,15 @@ impl Display for Error {
             CreateRegion(err) => write!(f, "Cannot create memory region: {:?}", err),
             PageSize(err) => write!(f, "Cannot fetch system's page size: {:?}", err),
             WriteMemory(err) => write!(f, "Cannot dump memory: {:?}", err),
+            ReadMemory(err) => write!(f, "Cannot load memory: {:?}", err),
+            StartWriter(err) => write!(f, "Cannot start memory writer thread: {}", err),
+            WriterPanicked => write!(f, "A memory writer thread panicked"),
+            ChecksumMismatch(base_address) => write!(
+                f,
+                "The contents of the memory region at {:#x} don't match their checksum, the \
+                 memory file is truncated or corrupted",
+                base_address
+            ),
         }
     }
 }
@@ -101,6 +140,7 @@ impl SnapshotMemory for GuestMemoryMmap {
                 base_address: region.start_addr().0,
                 size: region.len() as usize,
                 offset,
+                checksum: None,
             });
 
             offset += region.len();
@@ -117,6 +157,57 @@ impl SnapshotMemory for GuestMemoryMmap {
             .map_err(Error::WriteMemory)
     }
 
+    /// Dumps all contents of GuestMemoryMmap to a file, in the layout written by `dump`, with
+    /// `writers` threads each writing a disjoint range of the file.
+    fn dump_parallel(&self, file: &File, writers: usize) -> std::result::Result<(), Error> {
+        let page_size = get_page_size().map_err(Error::PageSize)?;
+        let ranges = writer_ranges(&self.describe(), writers.max(1), page_size);
+
+        let mut handles = Vec::with_capacity(ranges.len());
+        let mut result = Ok(());
+        for (index, range) in ranges.into_iter().enumerate() {
+            let guest_memory = self.clone();
+            let file = match file.try_clone() {
+                Ok(file) => file,
+                Err(e) => {
+                    result = Err(Error::FileHandle(e));
+                    break;
+                }
+            };
+            let spawned = thread::Builder::new()
+                .name(format!("fc_mem_writer{}", index))
+                .spawn(move || {
+                    range.into_iter().try_for_each(|(guest_addr, offset, len)| {
+                        guest_memory.write_all_to(
+                            guest_addr,
+                            &mut FileRangeWriter {
+                                file: &file,
+                                offset,
+                            },
+                            len,
+                        )
+                    })
+                });
+            match spawned {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    result = Err(Error::StartWriter(e));
+                    break;
+                }
+            }
+        }
+
+        // The writers already started get to finish, the first error is reported.
+        for handle in handles {
+            let written = match handle.join() {
+                Ok(written) => written.map_err(Error::WriteMemory),
+                Err(_) => Err(Error::WriterPanicked),
+            };
+            result = result.and(written);
+        }
+        result
+    }
+
     /// Dumps all pages of GuestMemoryMmap present in `dirty_bitmap` to a writer.
     fn dump_dirty<T: std::io::Write + std::io::Seek>(
         &self,
@@ -178,6 +269,55 @@ impl SnapshotMemory for GuestMemoryMmap {
             .map_err(Error::WriteMemory)
     }
 
+    /// Loads all contents of GuestMemoryMmap from a reader, in the layout written by `dump`.
+    fn load<T: std::io::Read>(&self, reader: &mut T) -> std::result::Result<(), Error> {
+        self.iter()
+            .try_for_each(|region| {
+                region.read_exact_from(MemoryRegionAddress(0), reader, region.len() as usize)?;
+                // Loading the contents isn't a guest write, so it doesn't dirty the pages.
+                if let Some(bitmap) = region.bitmap() {
+                    bitmap.reset();
+                }
+                Ok(())
+            })
+            .map_err(Error::ReadMemory)
+    }
+
+    /// Computes the CRC64 of the contents of each region of GuestMemoryMmap.
+    fn checksums(&self) -> std::result::Result<Vec<u64>, Error> {
+        self.iter()
+            .map(|region| {
+                let mut crc_writer = CRC64Writer::new(io::sink());
+                region.write_all_to(
+                    MemoryRegionAddress(0),
+                    &mut crc_writer,
+                    region.len() as usize,
+                )?;
+                Ok(crc_writer.checksum())
+            })
+            .collect::<std::result::Result<Vec<_>, GuestMemoryError>>()
+            .map_err(Error::ReadMemory)
+    }
+
+    /// Checks the contents of each region of GuestMemoryMmap against the checksum `state`
+    /// holds for it, if any.
+    fn verify(&self, state: &GuestMemoryState) -> std::result::Result<(), Error> {
+        // Snapshots of older releases, diff snapshots and migrations carry no checksums.
+        if state.regions.iter().all(|region| region.checksum.is_none()) {
+            return Ok(());
+        }
+        let checksums = self.checksums()?;
+        for (region, checksum) in state.regions.iter().zip(checksums) {
+            if region
+                .checksum
+                .map_or(false, |expected| expected != checksum)
+            {
+                return Err(Error::ChecksumMismatch(region.base_address));
+            }
+        }
+        Ok(())
+    }
+
     /// Creates a GuestMemoryMmap backed by a `file` if present, otherwise backed
     /// by anonymous memory. Memory layout and ranges are described in `state` param.
     fn restore(
@@ -202,6 +342,58 @@ impl SnapshotMemory for GuestMemoryMmap {
     }
 }
 
+// Splits the memory file laid out by `state` in `writers` ranges of whole pages, and returns the
+// guest memory chunks each range holds, as `(guest address, file offset, length)`. The ranges
+// which would be empty are left out.
+fn writer_ranges(
+    state: &GuestMemoryState,
+    writers: usize,
+    page_size: usize,
+) -> Vec<Vec<(GuestAddress, u64, usize)>> {
+    let file_len: usize = state.regions.iter().map(|region| region.size).sum();
+    let pages_per_writer = ((file_len + page_size - 1) / page_size + writers - 1) / writers;
+    let range_len = (pages_per_writer * page_size).max(page_size) as u64;
+
+    let mut ranges: Vec<Vec<(GuestAddress, u64, usize)>> = Vec::new();
+    for region in state.regions.iter() {
+        let region_end = region.offset + region.size as u64;
+        let mut offset = region.offset;
+        while offset < region_end {
+            let index = (offset / range_len) as usize;
+            let chunk_end = region_end.min((index as u64 + 1) * range_len);
+            if ranges.len() <= index {
+                ranges.resize_with(index + 1, Vec::new);
+            }
+            ranges[index].push((
+                GuestAddress(region.base_address + offset - region.offset),
+                offset,
+                (chunk_end - offset) as usize,
+            ));
+            offset = chunk_end;
+        }
+    }
+    ranges.retain(|range| !range.is_empty());
+    ranges
+}
+
+// Writes to a file from a given offset, leaving the cursor the clones of the file share alone.
+struct FileRangeWriter<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl Write for FileRangeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write_at(buf, self.offset)?;
+        self.offset += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -230,11 +422,13 @@ mod tests {
                     base_address: 0,
                     size: page_size,
                     offset: 0,
+                    checksum: None,
                 },
                 GuestMemoryRegionState {
                     base_address: page_size as u64 * 2,
                     size: page_size,
                     offset: page_size as u64,
+                    checksum: None,
                 },
             ],
         };
@@ -255,11 +449,13 @@ mod tests {
                     base_address: 0,
                     size: page_size * 3,
                     offset: 0,
+                    checksum: None,
                 },
                 GuestMemoryRegionState {
                     base_address: page_size as u64 * 4,
                     size: page_size * 3,
                     offset: page_size as u64 * 3,
+                    checksum: None,
                 },
             ],
         };
@@ -385,5 +581,146 @@ mod tests {
             reader.read_to_end(&mut diff_file_content).unwrap();
             assert_eq!(expected_first_region, diff_file_content);
         }
+
+        // Case 3: load the full memory from a reader.
+        {
+            let mut dump = Vec::new();
+            guest_memory.dump(&mut dump).unwrap();
+
+            let restored_guest_memory =
+                GuestMemoryMmap::restore(None, &memory_state, true).unwrap();
+            restored_guest_memory.load(&mut dump.as_slice()).unwrap();
+
+            let mut actual_region = vec![0u8; page_size * 2];
+            restored_guest_memory
+                .read(&mut actual_region.as_mut_slice(), GuestAddress(0))
+                .unwrap();
+            assert_eq!(&dump[..page_size * 2], actual_region.as_slice());
+            restored_guest_memory
+                .read(
+                    &mut actual_region.as_mut_slice(),
+                    GuestAddress(page_size as u64 * 3),
+                )
+                .unwrap();
+            assert_eq!(second_region, actual_region);
+            // The loaded pages aren't dirty.
+            restored_guest_memory
+                .iter()
+                .for_each(|r| assert!(!r.bitmap().dirty_at(0)));
+
+            // The reader is too short.
+            assert!(restored_guest_memory.load(&mut &dump[..page_size]).is_err());
+        }
+
+        // Case 4: dump the full memory with several writers.
+        {
+            let mut dump = Vec::new();
+            guest_memory.dump(&mut dump).unwrap();
+
+            for writers in 1..=5 {
+                let memory_file = TempFile::new().unwrap();
+                guest_memory
+                    .dump_parallel(memory_file.as_file(), writers)
+                    .unwrap();
+
+                let mut file_content = Vec::new();
+                memory_file
+                    .as_file()
+                    .read_to_end(&mut file_content)
+                    .unwrap();
+                assert_eq!(dump, file_content);
+            }
+        }
+
+        // Case 5: check the restored memory against the checksums of the regions.
+        {
+            let memory_file = TempFile::new().unwrap();
+            guest_memory.dump(&mut memory_file.as_file()).unwrap();
+
+            let mut checked_state = guest_memory.describe();
+            // Without checksums, there is nothing to check.
+            let restored_guest_memory =
+                GuestMemoryMmap::restore(Some(memory_file.as_file()), &checked_state, false)
+                    .unwrap();
+            assert!(restored_guest_memory.verify(&checked_state).is_ok());
+
+            for (region, checksum) in checked_state
+                .regions
+                .iter_mut()
+                .zip(guest_memory.checksums().unwrap())
+            {
+                region.checksum = Some(checksum);
+            }
+            assert!(restored_guest_memory.verify(&checked_state).is_ok());
+
+            // Flip a byte of the second region in the memory file.
+            memory_file
+                .as_file()
+                .write_all_at(&[0u8], page_size as u64 * 3)
+                .unwrap();
+            let restored_guest_memory =
+                GuestMemoryMmap::restore(Some(memory_file.as_file()), &checked_state, false)
+                    .unwrap();
+            match restored_guest_memory.verify(&checked_state) {
+                Err(Error::ChecksumMismatch(base_address)) => {
+                    assert_eq!(base_address, page_size as u64 * 3)
+                }
+                _ => panic!("Unexpected result."),
+            }
+        }
+    }
+
+    #[test]
+    fn test_writer_ranges() {
+        let page_size: usize = get_page_size().unwrap();
+        let state = GuestMemoryState {
+            regions: vec![
+                GuestMemoryRegionState {
+                    base_address: 0,
+                    size: page_size * 3,
+                    offset: 0,
+                    checksum: None,
+                },
+                GuestMemoryRegionState {
+                    base_address: page_size as u64 * 4,
+                    size: page_size * 2,
+                    offset: page_size as u64 * 3,
+                    checksum: None,
+                },
+            ],
+        };
+        let page = |n: usize| n * page_size;
+
+        // A single writer gets the whole file.
+        assert_eq!(
+            writer_ranges(&state, 1, page_size),
+            vec![vec![
+                (GuestAddress(0), 0, page(3)),
+                (GuestAddress(page(4) as u64), page(3) as u64, page(2)),
+            ]]
+        );
+
+        // The ranges are split at the region boundaries.
+        assert_eq!(
+            writer_ranges(&state, 2, page_size),
+            vec![
+                vec![(GuestAddress(0), 0, page(3))],
+                vec![(GuestAddress(page(4) as u64), page(3) as u64, page(2))],
+            ]
+        );
+        assert_eq!(
+            writer_ranges(&state, 3, page_size),
+            vec![
+                vec![(GuestAddress(0), 0, page(2))],
+                vec![
+                    (GuestAddress(page(2) as u64), page(2) as u64, page(1)),
+                    (GuestAddress(page(4) as u64), page(3) as u64, page(1)),
+                ],
+                vec![(GuestAddress(page(5) as u64), page(4) as u64, page(1))],
+            ]
+        );
+
+        // There are no more writers than pages.
+        assert_eq!(writer_ranges(&state, 16, page_size).len(), 5);
     }
 }
@@ -0,0 +1,653 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Live migration of a running microVM to another Firecracker, over TCP.
+//!
+//! The source copies the whole guest memory while the guest keeps running, then the pages the
+//! guest dirtied meanwhile, round after round, until few enough pages get dirtied during a
+//! round. It then pauses the guest and copies the last dirty pages along with the microVM state,
+//! from which the destination builds the microVM. The source is left paused once the
+//! destination has built the microVM, and resumes the guest otherwise.
+
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use logger::info;
+use seccompiler::BpfThreadMap;
+use snapshot::Snapshot;
+use utils::get_page_size;
+use versionize::{VersionMap, Versionize};
+use vm_memory::{
+    Bitmap, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion, GuestRegionMmap,
+    MemoryRegionAddress,
+};
+
+use crate::builder::{self, StartMicrovmError};
+use crate::memory_snapshot::{self, GuestMemoryState, SnapshotMemory};
+use crate::persist::{
+    restore_mmds_data, snapshot_state_sanity_check, validate_snapshot_devices, CreateSnapshotError,
+    DiffSnapshotParent, LoadSnapshotError, MicrovmState,
+};
+use crate::resources::VmResources;
+use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::migration::{MigrationReceiveConfig, MigrationSendConfig};
+use crate::{DirtyBitmap, Error as VmmError, EventManager, Vmm};
+
+/// Magic number starting a migration stream.
+const MIGRATION_MAGIC: u32 = 0x4643_4d47;
+/// Version of the migration protocol, bumped on any change to the messages.
+const MIGRATION_PROTOCOL_VERSION: u16 = 1;
+/// Message holding the versioned `GuestMemoryState` of the microVM.
+const MEMORY_LAYOUT: u8 = 1;
+/// Message holding a range of guest pages, preceded by its guest address and length.
+const PAGES: u8 = 2;
+/// Message holding the versioned `MicrovmState`, which ends the stream of the source.
+const MICROVM_STATE: u8 = 3;
+/// Sent by the destination once the microVM is built.
+const MICROVM_BUILT: u8 = 4;
+/// Sent by the source once it agrees to leave the microVM paused for good.
+const MIGRATION_COMMITTED: u8 = 5;
+/// Maximum length of the versioned messages.
+const MAX_MESSAGE_LEN: u64 = 16 << 20;
+/// Time given to the destination to build the microVM.
+const DESTINATION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time given to the source to commit the migration once the microVM is built.
+const SOURCE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Errors associated with migrating a microVM.
+#[derive(Debug)]
+pub enum MigrationError {
+    /// Failed to accept the connection of the source Firecracker.
+    Accept(io::Error),
+    /// Failed to build the received microVM.
+    BuildMicroVm(StartMicrovmError),
+    /// Failed to connect to the destination Firecracker.
+    Connect(io::Error),
+    /// The destination Firecracker failed to build the microVM.
+    DestinationFailed,
+    /// The destination Firecracker didn't report in time whether it built the microVM.
+    DestinationTimeout,
+    /// Failed to get the dirty bitmap.
+    DirtyBitmap(VmmError),
+    /// The microVM state received failed the sanity checks.
+    InvalidMicrovmState(LoadSnapshotError),
+    /// The migration stream doesn't follow the migration protocol.
+    InvalidStream(String),
+    /// Failed to listen for the source Firecracker.
+    Listen(io::Error),
+    /// Failed to create, copy or fill the guest memory.
+    Memory(memory_snapshot::Error),
+    /// Failed to pause the microVM.
+    PauseMicroVm(VmmError),
+    /// Failed to resume the microVM.
+    ResumeMicroVm(VmmError),
+    /// Failed to save the microVM state, or the microVM has devices whose state can't be saved.
+    SaveMicrovmState(CreateSnapshotError),
+    /// Failed to serialize or deserialize a versioned message.
+    Serialization(snapshot::Error),
+    /// Failed to read from or write to the migration stream.
+    Stream(io::Error),
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::MigrationError::*;
+        match self {
+            Accept(err) => write!(f, "Cannot accept the source connection: {}", err),
+            BuildMicroVm(err) => write!(f, "Cannot build the received microVM: {}", err),
+            Connect(err) => write!(f, "Cannot connect to the destination: {}", err),
+            DestinationFailed => write!(f, "The destination failed to build the microVM."),
+            DestinationTimeout => write!(
+                f,
+                "The destination didn't acknowledge the microVM build in time, the microVM is \
+                 left paused."
+            ),
+            DirtyBitmap(err) => write!(f, "Cannot get dirty bitmap: {}", err),
+            InvalidMicrovmState(err) => write!(f, "Invalid microVM state: {}", err),
+            InvalidStream(msg) => write!(f, "Invalid migration stream: {}", msg),
+            Listen(err) => write!(f, "Cannot listen for the source: {}", err),
+            Memory(err) => write!(f, "Cannot migrate the guest memory: {}", err),
+            PauseMicroVm(err) => write!(f, "Cannot pause the microVM: {}", err),
+            ResumeMicroVm(err) => write!(f, "Cannot resume the microVM: {}", err),
+            SaveMicrovmState(err) => write!(f, "{}", err),
+            Serialization(err) => write!(f, "Cannot serialize the microVM state: {:?}", err),
+            Stream(err) => write!(f, "Cannot transfer the microVM: {}", err),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, MigrationError>;
+
+/// Migrates the microVM to the destination Firecracker described by `config`. On success, the
+/// microVM is left paused, as it runs at the destination.
+pub fn send_microvm(
+    vmm: &mut Vmm,
+    config: &MigrationSendConfig,
+    version_map: VersionMap,
+) -> Result<()> {
+    use self::MigrationError::*;
+
+    validate_snapshot_devices(vmm).map_err(SaveMicrovmState)?;
+    let mut stream = TcpStream::connect(config.destination).map_err(Connect)?;
+
+    write_header(&mut stream)?;
+    write_versioned(
+        &mut stream,
+        MEMORY_LAYOUT,
+        &vmm.guest_memory().describe(),
+        &version_map,
+    )?;
+
+    // Only the pages dirtied from now on need to be copied again. They are left out of the next
+    // diff snapshot, if the microVM keeps running here.
+    vmm.diff_parent = DiffSnapshotParent::Unknown;
+    vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
+    reset_firecracker_bitmap(vmm.guest_memory());
+    send_all_pages(vmm.guest_memory(), &mut stream)?;
+    for round in 1..=config.max_iterations {
+        let dirty_bitmap = vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
+        let dirty_pages = send_dirty_pages(vmm.guest_memory(), &dirty_bitmap, &mut stream)?;
+        info!(
+            "Migration round {} copied {} dirty pages.",
+            round, dirty_pages
+        );
+        if dirty_pages <= config.max_dirty_pages {
+            break;
+        }
+    }
+
+    let was_running = vmm.instance_info.state == VmState::Running;
+    if was_running {
+        vmm.pause_vm().map_err(PauseMicroVm)?;
+    }
+    let result = send_paused_microvm(vmm, &mut stream, &version_map);
+    match result {
+        // The microVM may have been built at the destination, it mustn't run on both sides.
+        Ok(()) | Err(DestinationTimeout) => (),
+        // The microVM wasn't built at the destination, it keeps running here.
+        Err(_) if was_running => vmm.resume_vm().map_err(ResumeMicroVm)?,
+        Err(_) => (),
+    }
+    result
+}
+
+// Copies the last dirty pages and the state of the paused microVM, then waits for the
+// destination to build it.
+fn send_paused_microvm(
+    vmm: &mut Vmm,
+    stream: &mut TcpStream,
+    version_map: &VersionMap,
+) -> Result<()> {
+    use self::MigrationError::*;
+
+    let dirty_bitmap = vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
+    let dirty_pages = send_dirty_pages(vmm.guest_memory(), &dirty_bitmap, stream)?;
+    info!("Copied the last {} dirty pages.", dirty_pages);
+    let microvm_state = vmm
+        .save_state()
+        .map_err(|e| SaveMicrovmState(CreateSnapshotError::MicrovmState(e)))?;
+    write_versioned(stream, MICROVM_STATE, &microvm_state, version_map)?;
+    stream.flush().map_err(Stream)?;
+
+    stream
+        .set_read_timeout(Some(DESTINATION_TIMEOUT))
+        .map_err(Stream)?;
+    wait_for_build(stream)?;
+    stream.write_all(&[MIGRATION_COMMITTED]).map_err(Stream)
+}
+
+// Waits for the destination to report that it built the microVM, within the read timeout of
+// `reader`.
+fn wait_for_build<R: Read>(reader: &mut R) -> Result<()> {
+    match read_u8(reader) {
+        Ok(MICROVM_BUILT) => Ok(()),
+        Err(MigrationError::Stream(e))
+            if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            Err(MigrationError::DestinationTimeout)
+        }
+        _ => Err(MigrationError::DestinationFailed),
+    }
+}
+
+/// Waits for a microVM migrated by a source Firecracker, as described by `config`, and builds
+/// it. The microVM is resumed once the source has left it paused, if `config` asks for it.
+pub fn receive_microvm(
+    instance_info: &InstanceInfo,
+    event_manager: &mut EventManager,
+    seccomp_filters: &BpfThreadMap,
+    config: &MigrationReceiveConfig,
+    version_map: VersionMap,
+    vm_resources: &mut VmResources,
+) -> Result<Arc<Mutex<Vmm>>> {
+    use self::MigrationError::*;
+
+    let listener = TcpListener::bind(config.listen_address).map_err(Listen)?;
+    info!("Waiting for a microVM on {}.", config.listen_address);
+    let (mut stream, source) = listener.accept().map_err(Accept)?;
+    info!("Receiving a microVM from {}.", source);
+
+    let guest_memory = receive_memory(&mut stream, &version_map, config.track_dirty_pages)?;
+    let mut microvm_state: MicrovmState = read_versioned(&mut stream, &version_map)?;
+    snapshot_state_sanity_check(&microvm_state).map_err(InvalidMicrovmState)?;
+    let mmds_state = microvm_state.mmds.take();
+    let vmm = builder::build_microvm_from_snapshot(
+        instance_info,
+        event_manager,
+        microvm_state,
+        guest_memory,
+        None,
+        config.track_dirty_pages,
+        seccomp_filters,
+        vm_resources,
+    )
+    .map_err(BuildMicroVm)?;
+    restore_mmds_data(vm_resources, mmds_state.as_ref(), None).map_err(InvalidMicrovmState)?;
+
+    // The source only leaves the microVM paused for good once it knows it was built here.
+    stream.write_all(&[MICROVM_BUILT]).map_err(Stream)?;
+    stream
+        .set_read_timeout(Some(SOURCE_TIMEOUT))
+        .map_err(Stream)?;
+    if read_u8(&mut stream)? != MIGRATION_COMMITTED {
+        return Err(InvalidStream(
+            "the source didn't commit the migration".to_string(),
+        ));
+    }
+    if config.resume_vm {
+        vmm.lock()
+            .expect("Poisoned lock")
+            .resume_vm()
+            .map_err(ResumeMicroVm)?;
+    }
+    Ok(vmm)
+}
+
+// Reads the guest memory from the migration stream, up to the microVM state.
+fn receive_memory<R: Read>(
+    reader: &mut R,
+    version_map: &VersionMap,
+    track_dirty_pages: bool,
+) -> Result<GuestMemoryMmap> {
+    use self::MigrationError::*;
+
+    read_header(reader)?;
+    if read_u8(reader)? != MEMORY_LAYOUT {
+        return Err(InvalidStream("the memory layout is missing".to_string()));
+    }
+    let mem_state: GuestMemoryState = read_versioned(reader, version_map)?;
+    let guest_memory =
+        GuestMemoryMmap::restore(None, &mem_state, track_dirty_pages).map_err(Memory)?;
+
+    loop {
+        match read_u8(reader)? {
+            PAGES => {
+                let address = read_u64(reader)?;
+                let len = read_u64(reader)? as usize;
+                guest_memory
+                    .read_exact_from(GuestAddress(address), reader, len)
+                    .map_err(|e| Memory(memory_snapshot::Error::ReadMemory(e)))?;
+            }
+            MICROVM_STATE => break,
+            message => {
+                return Err(InvalidStream(format!(
+                    "unexpected message {} among the guest pages",
+                    message
+                )))
+            }
+        }
+    }
+    // Copying the pages isn't a guest write, so it doesn't dirty them.
+    reset_firecracker_bitmap(&guest_memory);
+    Ok(guest_memory)
+}
+
+// Copies all the guest memory, region after region.
+fn send_all_pages<W: Write>(guest_memory: &GuestMemoryMmap, writer: &mut W) -> Result<()> {
+    guest_memory
+        .iter()
+        .try_for_each(|region| send_pages(region, 0, region.len() as usize, writer))
+}
+
+// Copies the pages marked as dirty by KVM in `dirty_bitmap` or by the devices in the bitmap of
+// the regions, which gets reset. Returns the number of pages copied.
+fn send_dirty_pages<W: Write>(
+    guest_memory: &GuestMemoryMmap,
+    dirty_bitmap: &DirtyBitmap,
+    writer: &mut W,
+) -> Result<u64> {
+    let page_size =
+        get_page_size().map_err(|e| MigrationError::Memory(memory_snapshot::Error::PageSize(e)))?;
+    let mut dirty_pages = 0;
+
+    for (slot, region) in guest_memory.iter().enumerate() {
+        let kvm_bitmap = dirty_bitmap.get(&slot).map_or(&[][..], Vec::as_slice);
+        let firecracker_bitmap = region.bitmap();
+        let page_count = region.len() as usize / page_size;
+        let mut batch_start = None;
+        // The extra iteration ends the last batch of dirty pages.
+        for page in 0..=page_count {
+            let is_dirty = page < page_count
+                && (kvm_bitmap
+                    .get(page / 64)
+                    .map_or(false, |word| (word >> (page % 64)) & 1 != 0)
+                    || firecracker_bitmap.dirty_at(page * page_size));
+            match (is_dirty, batch_start) {
+                (true, None) => batch_start = Some(page),
+                (false, Some(start)) => {
+                    send_pages(
+                        region,
+                        start * page_size,
+                        (page - start) * page_size,
+                        writer,
+                    )?;
+                    dirty_pages += (page - start) as u64;
+                    batch_start = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some(bitmap) = firecracker_bitmap {
+            bitmap.reset();
+        }
+    }
+    Ok(dirty_pages)
+}
+
+fn send_pages<W: Write>(
+    region: &GuestRegionMmap,
+    offset: usize,
+    len: usize,
+    writer: &mut W,
+) -> Result<()> {
+    use self::MigrationError::{Memory, Stream};
+
+    writer.write_all(&[PAGES]).map_err(Stream)?;
+    writer
+        .write_all(&(region.start_addr().0 + offset as u64).to_le_bytes())
+        .map_err(Stream)?;
+    writer
+        .write_all(&(len as u64).to_le_bytes())
+        .map_err(Stream)?;
+    region
+        .write_all_to(MemoryRegionAddress(offset as u64), writer, len)
+        .map_err(|e| Memory(memory_snapshot::Error::WriteMemory(e)))
+}
+
+fn reset_firecracker_bitmap(guest_memory: &GuestMemoryMmap) {
+    guest_memory.iter().for_each(|region| {
+        if let Some(bitmap) = region.bitmap() {
+            bitmap.reset();
+        }
+    });
+}
+
+fn write_header<W: Write>(writer: &mut W) -> Result<()> {
+    writer
+        .write_all(&MIGRATION_MAGIC.to_le_bytes())
+        .and_then(|()| writer.write_all(&MIGRATION_PROTOCOL_VERSION.to_le_bytes()))
+        .map_err(MigrationError::Stream)
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<()> {
+    let mut magic = [0u8; 4];
+    let mut version = [0u8; 2];
+    reader
+        .read_exact(&mut magic)
+        .and_then(|()| reader.read_exact(&mut version))
+        .map_err(MigrationError::Stream)?;
+    if u32::from_le_bytes(magic) != MIGRATION_MAGIC {
+        return Err(MigrationError::InvalidStream(
+            "not a migration stream".to_string(),
+        ));
+    }
+    if u16::from_le_bytes(version) != MIGRATION_PROTOCOL_VERSION {
+        return Err(MigrationError::InvalidStream(format!(
+            "unsupported protocol version {}",
+            u16::from_le_bytes(version)
+        )));
+    }
+    Ok(())
+}
+
+// Writes the `message` made of `object`, serialized in the latest version of `version_map`.
+fn write_versioned<W: Write, O: Versionize>(
+    writer: &mut W,
+    message: u8,
+    object: &O,
+    version_map: &VersionMap,
+) -> Result<()> {
+    let mut bytes = Vec::new();
+    Snapshot::new(version_map.clone(), version_map.latest_version())
+        .save(&mut bytes, object)
+        .map_err(MigrationError::Serialization)?;
+    writer
+        .write_all(&[message])
+        .and_then(|()| writer.write_all(&(bytes.len() as u64).to_le_bytes()))
+        .and_then(|()| writer.write_all(&bytes))
+        .map_err(MigrationError::Stream)
+}
+
+// Reads an object written by `write_versioned`, whose message type was already read.
+fn read_versioned<R: Read, O: Versionize>(reader: &mut R, version_map: &VersionMap) -> Result<O> {
+    let len = read_u64(reader)?;
+    if len > MAX_MESSAGE_LEN {
+        return Err(MigrationError::InvalidStream(format!(
+            "message of {} bytes",
+            len
+        )));
+    }
+    let mut bytes = vec![0u8; len as usize];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(MigrationError::Stream)?;
+    Snapshot::load(&mut bytes.as_slice(), bytes.len(), version_map.clone())
+        .map_err(MigrationError::Serialization)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut byte = [0u8];
+    reader
+        .read_exact(&mut byte)
+        .map_err(MigrationError::Stream)?;
+    Ok(byte[0])
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(MigrationError::Stream)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::builder::tests::default_vmm;
+    use crate::version_map::VERSION_MAP;
+
+    #[test]
+    fn test_migrate_memory() {
+        let page_size = get_page_size().unwrap();
+        let guest_memory = vm_memory::create_guest_memory(
+            &[
+                (None, GuestAddress(0), page_size * 4),
+                (None, GuestAddress(page_size as u64 * 8), page_size * 2),
+            ],
+            true,
+        )
+        .unwrap();
+        guest_memory
+            .write(&vec![1u8; page_size][..], GuestAddress(0))
+            .unwrap();
+
+        let mut stream = Vec::new();
+        write_header(&mut stream).unwrap();
+        write_versioned(
+            &mut stream,
+            MEMORY_LAYOUT,
+            &guest_memory.describe(),
+            &VERSION_MAP,
+        )
+        .unwrap();
+        reset_firecracker_bitmap(&guest_memory);
+        send_all_pages(&guest_memory, &mut stream).unwrap();
+
+        // Pages dirtied by the guest, as reported by KVM, and by a device.
+        guest_memory
+            .write(
+                &vec![2u8; page_size * 2][..],
+                GuestAddress(page_size as u64),
+            )
+            .unwrap();
+        guest_memory
+            .write(
+                &vec![3u8; page_size][..],
+                GuestAddress(page_size as u64 * 9),
+            )
+            .unwrap();
+        let mut dirty_bitmap: DirtyBitmap = HashMap::new();
+        dirty_bitmap.insert(0, vec![0b0110]);
+        dirty_bitmap.insert(1, vec![0]);
+        assert_eq!(
+            send_dirty_pages(&guest_memory, &dirty_bitmap, &mut stream).unwrap(),
+            3
+        );
+        // The device bitmap was reset.
+        assert_eq!(
+            send_dirty_pages(&guest_memory, &dirty_bitmap, &mut stream).unwrap(),
+            2
+        );
+        stream.push(MICROVM_STATE);
+
+        let received = receive_memory(&mut stream.as_slice(), &VERSION_MAP, true).unwrap();
+        assert_eq!(received.describe(), guest_memory.describe());
+        let mut expected = vec![0u8; page_size * 4];
+        let mut actual = vec![0u8; page_size * 4];
+        guest_memory
+            .read(&mut expected[..], GuestAddress(0))
+            .unwrap();
+        received.read(&mut actual[..], GuestAddress(0)).unwrap();
+        assert_eq!(actual, expected);
+        guest_memory
+            .read(
+                &mut expected[..page_size * 2],
+                GuestAddress(page_size as u64 * 8),
+            )
+            .unwrap();
+        received
+            .read(
+                &mut actual[..page_size * 2],
+                GuestAddress(page_size as u64 * 8),
+            )
+            .unwrap();
+        assert_eq!(actual, expected);
+        // Receiving the pages doesn't dirty them.
+        assert!(!received.iter().any(|region| region.bitmap().dirty_at(0)));
+    }
+
+    #[test]
+    fn test_send_microvm_dirty_log() {
+        // The dirty pages aren't tracked, so the migration fails once it reads the dirty log.
+        let mut vmm = default_vmm();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = MigrationSendConfig {
+            destination: listener.local_addr().unwrap(),
+            max_iterations: 1,
+            max_dirty_pages: 0,
+        };
+        assert!(matches!(
+            send_microvm(&mut vmm, &config, VERSION_MAP.clone()),
+            Err(MigrationError::DirtyBitmap(_))
+        ));
+        // The next snapshot has to be a full one.
+        assert_eq!(vmm.diff_parent, DiffSnapshotParent::Unknown);
+    }
+
+    #[test]
+    fn test_wait_for_build() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut source = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut destination, _) = listener.accept().unwrap();
+        source
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+
+        // The destination may still build the microVM.
+        assert!(matches!(
+            wait_for_build(&mut source),
+            Err(MigrationError::DestinationTimeout)
+        ));
+
+        destination.write_all(&[MICROVM_BUILT]).unwrap();
+        wait_for_build(&mut source).unwrap();
+
+        destination.write_all(&[PAGES]).unwrap();
+        assert!(matches!(
+            wait_for_build(&mut source),
+            Err(MigrationError::DestinationFailed)
+        ));
+
+        drop(destination);
+        assert!(matches!(
+            wait_for_build(&mut source),
+            Err(MigrationError::DestinationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_stream() {
+        let mut stream = Vec::new();
+        assert!(matches!(
+            receive_memory(&mut stream.as_slice(), &VERSION_MAP, false),
+            Err(MigrationError::Stream(_))
+        ));
+
+        stream.extend_from_slice(&0u32.to_le_bytes());
+        stream.extend_from_slice(&MIGRATION_PROTOCOL_VERSION.to_le_bytes());
+        assert!(matches!(
+            receive_memory(&mut stream.as_slice(), &VERSION_MAP, false),
+            Err(MigrationError::InvalidStream(_))
+        ));
+
+        stream.clear();
+        write_header(&mut stream).unwrap();
+        stream.push(PAGES);
+        assert!(matches!(
+            receive_memory(&mut stream.as_slice(), &VERSION_MAP, false),
+            Err(MigrationError::InvalidStream(_))
+        ));
+
+        stream.clear();
+        write_header(&mut stream).unwrap();
+        stream.push(MEMORY_LAYOUT);
+        stream.extend_from_slice(&(MAX_MESSAGE_LEN + 1).to_le_bytes());
+        assert!(matches!(
+            receive_memory(&mut stream.as_slice(), &VERSION_MAP, false),
+            Err(MigrationError::InvalidStream(_))
+        ));
+    }
+
+    #[test]
+    fn test_migration_error_display() {
+        use self::MigrationError::*;
+
+        let err = DestinationFailed;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DestinationTimeout;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidStream(String::new());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = Stream(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -3,41 +3,56 @@
 
 //! Defines state structures for saving/restoring a Firecracker microVM.
 
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
-use std::io::{self, Write};
-use std::os::unix::io::AsRawFd;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::net::UnixStream;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 #[cfg(target_arch = "aarch64")]
 use arch::regs::{get_manufacturer_id_from_host, get_manufacturer_id_from_state};
 #[cfg(target_arch = "x86_64")]
 use cpuid::common::{get_vendor_id_from_cpuid, get_vendor_id_from_host};
-use devices::virtio::TYPE_NET;
-use logger::{error, info};
+use devices::virtio::{
+    Block, CacheType, Net, NetBackendType, Vsock, VsockUnixBackend, TYPE_BLOCK, TYPE_MEM, TYPE_NET,
+    TYPE_PMEM, TYPE_VSOCK,
+};
+use logger::{error, info, warn};
+use mmds::persist::MmdsState;
 use seccompiler::BpfThreadMap;
 use serde::Serialize;
 use snapshot::Snapshot;
 use userfaultfd::{FeatureFlags, Uffd, UffdBuilder};
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+use utils::seek_hole::SeekHole;
 use utils::sock_ctrl_msg::ScmSocket;
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
+use virtio_gen::virtio_blk::{VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_WRITE_ZEROES};
 use virtio_gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
-use vm_memory::{GuestMemory, GuestMemoryMmap};
+use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap};
 
 use crate::builder::{self, StartMicrovmError};
 use crate::device_manager::persist::{DeviceStates, Error as DevicePersistError};
 use crate::memory_snapshot::{GuestMemoryState, SnapshotMemory};
 use crate::resources::VmResources;
+use crate::snapshot_translation::{self, translate_microvm_state};
+use crate::uffd_handler::{self, UffdHandler};
 #[cfg(target_arch = "x86_64")]
 use crate::version_map::FC_V0_23_SNAP_VERSION;
-use crate::version_map::{FC_V1_0_SNAP_VERSION, FC_V1_1_SNAP_VERSION, FC_VERSION_TO_SNAP_VERSION};
+use crate::version_map::{
+    FC_V1_0_SNAP_VERSION, FC_V1_1_SNAP_VERSION, FC_V1_2_SNAP_VERSION, FC_VERSION_TO_SNAP_VERSION,
+};
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::MAX_SUPPORTED_VCPUS;
+use crate::vmm_config::machine_config::{GuestMemoryBackend, VmUpdateConfig, MAX_SUPPORTED_VCPUS};
 use crate::vmm_config::snapshot::{
-    CreateSnapshotParams, LoadSnapshotParams, MemBackendType, SnapshotType,
+    CloneConfig, CreateSnapshotParams, DriveOverride, LoadSnapshotParams, MemBackendType,
+    MergeSnapshotParams, NetworkOverride, ResumeMode, SnapshotCompression, SnapshotDeviceInfo,
+    SnapshotInfo, SnapshotType, MAX_MEM_WRITER_THREADS,
 };
 use crate::vstate::vcpu::VcpuState;
 use crate::vstate::vm::VmState;
@@ -45,6 +60,10 @@ use crate::{mem_size_mib, memory_snapshot, vstate, Error as VmmError, EventManag
 
 #[cfg(target_arch = "x86_64")]
 const FC_V0_23_MAX_DEVICES: u32 = 11;
+/// Randomness pool the identity of clones is drawn from.
+const RANDOMNESS_POOL: &str = "/dev/urandom";
+/// Length of the entropy seed published to clones, in bytes.
+const CLONE_ENTROPY_SEED_LEN: usize = 32;
 
 /// Holds information related to the VM that is not part of VmState.
 #[derive(Debug, PartialEq, Versionize)]
@@ -68,6 +87,106 @@ pub struct MicrovmState {
     pub vcpu_states: Vec<VcpuState>,
     /// Device states.
     pub device_states: DeviceStates,
+    /// Snapshot this one was taken on top of, if it is a diff snapshot.
+    #[version(start = 2, ser_fn = "parent_serialize")]
+    pub parent: Option<SnapshotParentState>,
+    /// Contents of the MMDS data store, unless the snapshot was taken without them.
+    #[version(start = 3, ser_fn = "mmds_serialize")]
+    pub mmds: Option<MmdsState>,
+    /// Algorithm the guest memory file was compressed with, if any.
+    #[version(start = 3, ser_fn = "compression_serialize")]
+    pub compression: Option<SnapshotCompressionState>,
+}
+
+impl MicrovmState {
+    fn parent_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.parent.is_some() {
+            warn!(
+                "Target version does not support persisting the parent snapshot. The diff \
+                 snapshot can't be merged through its chain."
+            );
+        }
+
+        Ok(())
+    }
+
+    fn mmds_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 3 && self.mmds.is_some() {
+            warn!(
+                "Target version does not support persisting the MMDS contents. The data store \
+                 will be empty when the snapshot is loaded."
+            );
+        }
+
+        Ok(())
+    }
+
+    fn compression_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 3 && self.compression.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement compressed memory files.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Algorithm the guest memory file of a snapshot was compressed with.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub enum SnapshotCompressionState {
+    /// A single LZ4 frame.
+    Lz4,
+}
+
+impl From<SnapshotCompression> for SnapshotCompressionState {
+    fn from(compression: SnapshotCompression) -> Self {
+        match compression {
+            SnapshotCompression::Lz4 => SnapshotCompressionState::Lz4,
+        }
+    }
+}
+
+impl From<SnapshotCompressionState> for SnapshotCompression {
+    fn from(compression_state: SnapshotCompressionState) -> Self {
+        match compression_state {
+            SnapshotCompressionState::Lz4 => SnapshotCompression::Lz4,
+        }
+    }
+}
+
+/// Locates the snapshot a diff snapshot was taken on top of, the previous link of its chain.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct SnapshotParentState {
+    /// Path to the file that contains the microVM state of the parent snapshot.
+    pub snapshot_path: String,
+    /// Path to the file that contains the guest memory of the parent snapshot.
+    pub mem_file_path: String,
+}
+
+impl SnapshotParentState {
+    pub(crate) fn new(snapshot_path: &Path, mem_file_path: &Path) -> Self {
+        SnapshotParentState {
+            snapshot_path: snapshot_path.to_string_lossy().into_owned(),
+            mem_file_path: mem_file_path.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+/// What the pages held by the next diff snapshot, those written since the dirty log was last
+/// read, are relative to.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum DiffSnapshotParent {
+    /// The start of the microVM: diff snapshots hold all the memory it wrote, and have no parent.
+    Boot,
+    /// The snapshot created or loaded last, from files.
+    Snapshot(SnapshotParentState),
+    /// Memory Firecracker can't refer to, such as a snapshot written to file descriptors or a
+    /// migrated microVM, or the dirty log was read for something else than a snapshot. Only
+    /// full snapshots can be created.
+    Unknown,
 }
 
 /// This describes the mapping between Firecracker base virtual address and
@@ -156,6 +275,45 @@ pub enum CreateSnapshotError {
     #[cfg(target_arch = "x86_64")]
     /// Number of devices exceeds the maximum supported devices for the snapshot data version.
     TooManyDevices(usize),
+    /// The network interface with the given ID uses the vhost datapath, whose state cannot be
+    /// saved.
+    VhostNetDevice(String),
+    /// The network interface with the given ID uses the xdp backend, which cannot be restored.
+    XdpNetDevice(String),
+    /// The network interface with the given ID isn't backed by a TAP device, the only backend
+    /// network interfaces are restored on.
+    NonTapNetDevice(String),
+    /// The device with the given ID is part of a rate limiter group, which isn't saved.
+    RateLimiterGroup(String),
+    /// The network interface with the given ID is processed by a worker thread, which keeps
+    /// running while the microVM is paused.
+    NetWorkerThread(String),
+    /// The drive with the given ID is served by a vhost-user backend, whose state cannot be
+    /// saved.
+    VhostUserDrive(String),
+    /// The drive with the given ID was opened from a file descriptor, so its backing file
+    /// can't be reopened on restore.
+    FdBackedDrive(String),
+    /// The pmem device with the given ID maps a host file in the guest physical memory, which
+    /// isn't saved.
+    PmemDevice(String),
+    /// The memory plugged by the guest through the virtio-mem device isn't tracked by the
+    /// snapshot.
+    MemoryHotplugDevice,
+    /// The vsock device with the given ID is served by vhost-vsock, whose state cannot be saved.
+    VhostVsockDevice(String),
+    /// Diff snapshots rely on seeking over the unmodified pages, which compressed streams can't.
+    CompressedDiffSnapshot,
+    /// Diff snapshots rely on seeking over the unmodified pages, which pipes and sockets can't.
+    StreamedDiffSnapshot,
+    /// The number of memory writer threads is out of range.
+    InvalidMemWriterThreads(usize),
+    /// The memory file can only be written sequentially, by a single thread: diff snapshots
+    /// follow the dirty bitmap, and compressed or streamed memory files aren't seekable.
+    SequentialMemoryFile,
+    /// The pages written since the parent of a diff snapshot aren't all tracked, or its parent
+    /// can't be recorded.
+    UnknownDiffParent,
 }
 
 impl Display for CreateSnapshotError {
@@ -196,6 +354,83 @@ impl Display for CreateSnapshotError {
                  version requested is {}.",
                 val, FC_V0_23_MAX_DEVICES
             ),
+            VhostNetDevice(id) => write!(
+                f,
+                "Cannot snapshot the network interface {}: the vhost datapath does not support \
+                 snapshots.",
+                id
+            ),
+            RateLimiterGroup(id) => write!(
+                f,
+                "Cannot snapshot the device {}: rate limiter groups do not support snapshots.",
+                id
+            ),
+            XdpNetDevice(id) => write!(
+                f,
+                "Cannot snapshot the network interface {}: the xdp backend does not support \
+                 snapshots.",
+                id
+            ),
+            NonTapNetDevice(id) => write!(
+                f,
+                "Cannot snapshot the network interface {}: only interfaces backed by a TAP \
+                 device can be restored.",
+                id
+            ),
+            NetWorkerThread(id) => write!(
+                f,
+                "Cannot snapshot the network interface {}: interfaces processed by a worker \
+                 thread do not support snapshots.",
+                id
+            ),
+            VhostUserDrive(id) => write!(
+                f,
+                "Cannot snapshot the drive {}: vhost-user backends do not support snapshots.",
+                id
+            ),
+            FdBackedDrive(id) => write!(
+                f,
+                "Cannot snapshot the drive {}: its backing file was handed over as a file \
+                 descriptor and has no path to restore it from.",
+                id
+            ),
+            PmemDevice(id) => write!(
+                f,
+                "Cannot snapshot the pmem device {}: pmem devices do not support snapshots.",
+                id
+            ),
+            MemoryHotplugDevice => write!(
+                f,
+                "Cannot snapshot a microVM with hot-pluggable memory: the virtio-mem device does \
+                 not support snapshots."
+            ),
+            VhostVsockDevice(id) => write!(
+                f,
+                "Cannot snapshot the vsock device {}: the vhost datapath does not support \
+                 snapshots.",
+                id
+            ),
+            CompressedDiffSnapshot => write!(f, "Cannot compress the memory of a diff snapshot."),
+            StreamedDiffSnapshot => write!(
+                f,
+                "Cannot stream the memory of a diff snapshot: its memory file descriptor must \
+                 refer to a regular file."
+            ),
+            InvalidMemWriterThreads(threads) => write!(
+                f,
+                "Invalid number of memory writer threads: {}. It must be between 1 and {}.",
+                threads, MAX_MEM_WRITER_THREADS
+            ),
+            SequentialMemoryFile => write!(
+                f,
+                "Cannot write the memory with several threads: only the memory of full, \
+                 uncompressed snapshots written to a regular file can be."
+            ),
+            UnknownDiffParent => write!(
+                f,
+                "Cannot create a diff snapshot: the previous snapshot wasn't created or loaded \
+                 from files, or the dirty pages were consumed since. Create a full snapshot."
+            ),
         }
     }
 }
@@ -205,6 +440,10 @@ impl Display for CreateSnapshotError {
 pub enum LoadSnapshotError {
     /// Failed to build a microVM from snapshot.
     BuildMicroVm(StartMicrovmError),
+    /// Failed to draw the identity of a clone from the randomness pool.
+    CloneEntropy(io::Error),
+    /// Failed to give a clone its guest MAC addresses.
+    CloneGuestMac(VmmError),
     /// Snapshot cpu vendor differs than host cpu vendor.
     CpuVendorCheck(String),
     /// Failed to create an UFFD Builder.
@@ -213,14 +452,25 @@ pub enum LoadSnapshotError {
     DeserializeMemory(memory_snapshot::Error),
     /// Failed to deserialize microVM state.
     DeserializeMicrovmState(snapshot::Error),
+    /// The network interface or drive with the given ID, whose host resource is overridden,
+    /// isn't part of the snapshot.
+    DeviceOverride(String),
     /// Snapshot failed sanity checks.
     InvalidSnapshot(String),
+    /// Failed to set up the lazy loading of the guest memory.
+    LazyResume(uffd_handler::Error),
     /// Failed to open memory backing file.
     MemoryBackingFile(io::Error),
+    /// The memory file, of the given length, is shorter than the guest memory it should hold.
+    MemoryFileTooSmall(u64, u64),
+    /// Failed to put the contents of the MMDS data store.
+    MmdsData(String),
     /// Failed to resume Vm after loading snapshot.
     ResumeMicroVm(VmmError),
     /// Failed to open the snapshot backing file.
     SnapshotBackingFile(&'static str, io::Error),
+    /// Failed to translate the microVM state of a snapshot taken by a previous release.
+    TranslateMicrovmState(snapshot_translation::Error),
     /// Unable to connect to UDS in order to send information regarding
     /// handling guest memory page-fault events.
     UdsConnection(io::Error),
@@ -237,14 +487,32 @@ impl Display for LoadSnapshotError {
         use self::LoadSnapshotError::*;
         match self {
             BuildMicroVm(err) => write!(f, "Cannot build a microVM from snapshot: {}", err),
+            CloneEntropy(err) => write!(f, "Cannot draw the identity of the clone: {}", err),
+            CloneGuestMac(err) => {
+                write!(f, "Cannot give the clone its guest MAC addresses: {}", err)
+            }
             CreateUffdBuilder(err) => write!(f, "Cannot create UFFD builder: {:?}", err),
             CpuVendorCheck(err) => write!(f, "CPU vendor check failed: {}", err),
             DeserializeMemory(err) => write!(f, "Cannot deserialize memory: {}", err),
             DeserializeMicrovmState(err) => {
                 write!(f, "Cannot deserialize the microVM state: {:?}", err)
             }
+            DeviceOverride(id) => write!(
+                f,
+                "Cannot override the host resource of {}: the snapshot has no such network \
+                 interface or drive.",
+                id
+            ),
             InvalidSnapshot(err) => write!(f, "Snapshot sanity check failed: {}", err),
+            LazyResume(err) => write!(f, "Cannot resume lazily: {}", err),
             MemoryBackingFile(err) => write!(f, "Cannot open the memory file: {}", err),
+            MemoryFileTooSmall(file_len, mem_len) => write!(
+                f,
+                "The memory file holds {} bytes, the guest memory needs {}: the file is \
+                 truncated.",
+                file_len, mem_len
+            ),
+            MmdsData(err) => write!(f, "Cannot restore the MMDS contents: {}", err),
             ResumeMicroVm(err) => write!(
                 f,
                 "Failed to resume microVM after loading snapshot: {}",
@@ -255,6 +523,7 @@ impl Display for LoadSnapshotError {
                 "Cannot perform {} on the snapshot backing file: {}",
                 action, err
             ),
+            TranslateMicrovmState(err) => write!(f, "Cannot translate the microVM state: {}", err),
             UdsConnection(err) => write!(
                 f,
                 "Cannot connect to UDS in order to send information on handling guest memory \
@@ -269,43 +538,228 @@ impl Display for LoadSnapshotError {
     }
 }
 
+/// Errors associated with merging a chain of diff snapshots.
+#[derive(Debug)]
+pub enum MergeSnapshotError {
+    /// The chain goes back to the snapshot with the given microVM state file twice.
+    ChainLoop(String),
+    /// The memory file at the given path is compressed, so its pages can't be merged.
+    CompressedMemoryFile(String),
+    /// Failed to load the microVM state of a snapshot of the chain.
+    LoadMicrovmState(LoadSnapshotError),
+    /// Failed to read the memory file at the given path.
+    MemoryFile(String, io::Error),
+    /// Failed to write the merged memory file.
+    OutputMemoryFile(io::Error),
+    /// The merged memory file would overwrite the file at the given path, part of the chain.
+    OutputInChain(String),
+}
+
+impl Display for MergeSnapshotError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::MergeSnapshotError::*;
+        match self {
+            ChainLoop(path) => write!(f, "The snapshot chain loops back to {}.", path),
+            CompressedMemoryFile(path) => {
+                write!(f, "Cannot merge the compressed memory file {}.", path)
+            }
+            LoadMicrovmState(err) => write!(f, "Cannot load the snapshot chain: {}", err),
+            MemoryFile(path, err) => write!(f, "Cannot read the memory file {}: {}", path, err),
+            OutputMemoryFile(err) => write!(f, "Cannot write the merged memory file: {}", err),
+            OutputInChain(path) => write!(
+                f,
+                "Cannot write the merged memory file over {}, which is part of the snapshot \
+                 chain.",
+                path
+            ),
+        }
+    }
+}
+
 /// Creates a Microvm snapshot.
 pub fn create_snapshot(
     vmm: &mut Vmm,
     params: &CreateSnapshotParams,
     version_map: VersionMap,
 ) -> std::result::Result<(), CreateSnapshotError> {
+    // Take ownership of the file descriptors right away, so that they are closed on error.
+    // Safe because the caller hands them over to us.
+    let snapshot_file = params
+        .snapshot_fd
+        .map(|fd| unsafe { File::from_raw_fd(fd) });
+    let mem_file = params
+        .mem_file_fd
+        .map(|fd| unsafe { File::from_raw_fd(fd) });
+
     // Fail early from invalid target version.
     let snapshot_data_version = get_snapshot_data_version(&params.version, &version_map, &vmm)?;
+    let streamed_memory = match mem_file.as_ref() {
+        Some(mem_file) => !is_regular_file(mem_file)
+            .map_err(|e| CreateSnapshotError::MemoryBackingFile("metadata retrieval", e))?,
+        None => false,
+    };
+    if params.snapshot_type == SnapshotType::Diff {
+        if params.compression.is_some() {
+            return Err(CreateSnapshotError::CompressedDiffSnapshot);
+        }
+        if streamed_memory {
+            return Err(CreateSnapshotError::StreamedDiffSnapshot);
+        }
+        if vmm.diff_parent == DiffSnapshotParent::Unknown {
+            return Err(CreateSnapshotError::UnknownDiffParent);
+        }
+    }
+    let mem_writer_threads = params.mem_writer_threads.unwrap_or(1);
+    if mem_writer_threads == 0 || mem_writer_threads > MAX_MEM_WRITER_THREADS {
+        return Err(CreateSnapshotError::InvalidMemWriterThreads(
+            mem_writer_threads,
+        ));
+    }
+    if mem_writer_threads > 1
+        && (params.snapshot_type == SnapshotType::Diff
+            || params.compression.is_some()
+            || streamed_memory)
+    {
+        return Err(CreateSnapshotError::SequentialMemoryFile);
+    }
+
+    validate_snapshot_devices(vmm)?;
 
-    let microvm_state = vmm
+    let mut microvm_state = vmm
         .save_state()
         .map_err(CreateSnapshotError::MicrovmState)?;
+    if params.exclude_mmds {
+        microvm_state.mmds = None;
+    }
+    microvm_state.compression = params.compression.map(SnapshotCompressionState::from);
+    if params.snapshot_type == SnapshotType::Diff {
+        if let DiffSnapshotParent::Snapshot(parent) = &vmm.diff_parent {
+            microvm_state.parent = Some(parent.clone());
+        }
+    } else {
+        // The memory file of a diff snapshot only holds the pages written since the previous
+        // snapshot, only the regions of full snapshots are checked when loaded.
+        let checksums = vmm
+            .guest_memory()
+            .checksums()
+            .map_err(CreateSnapshotError::Memory)?;
+        for (region, checksum) in microvm_state.memory_state.regions.iter_mut().zip(checksums) {
+            region.checksum = Some(checksum);
+        }
+    }
 
     snapshot_state_to_file(
         &microvm_state,
         &params.snapshot_path,
+        snapshot_file,
         snapshot_data_version,
         version_map,
     )?;
 
-    snapshot_memory_to_file(vmm, &params.mem_file_path, &params.snapshot_type)?;
+    // Writing a diff snapshot consumes the dirty log, which a failed one leaves incomplete.
+    vmm.diff_parent = DiffSnapshotParent::Unknown;
+    snapshot_memory_to_file(
+        vmm,
+        &params.mem_file_path,
+        mem_file,
+        &params.snapshot_type,
+        params.compression,
+        mem_writer_threads,
+    )?;
 
+    // A snapshot written to file descriptors can't be referred to by the next diff snapshot.
+    if params.snapshot_fd.is_none() && params.mem_file_fd.is_none() {
+        vmm.diff_parent = DiffSnapshotParent::Snapshot(SnapshotParentState::new(
+            &params.snapshot_path,
+            &params.mem_file_path,
+        ));
+    }
     Ok(())
 }
 
+/// Ensures that the state of all the devices of the microVM can be saved.
+pub(crate) fn validate_snapshot_devices(vmm: &Vmm) -> std::result::Result<(), CreateSnapshotError> {
+    // The ring state of vhost-net and vhost-vsock devices and of vhost-user drives lives outside
+    // of Firecracker, net devices are restored on top of TAP devices, rate limiters are restored
+    // on their own, net worker threads keep writing to the guest memory while it is saved, and
+    // the memory of pmem devices isn't part of the guest memory.
+    vmm.mmio_device_manager
+        .for_each_virtio_device(|virtio_type, id, _info, dev| {
+            let locked_dev = dev.lock().expect("Poisoned lock");
+            match virtio_type {
+                TYPE_NET => {
+                    if let Some(net) = locked_dev.as_any().downcast_ref::<Net>() {
+                        if net.uses_vhost() {
+                            return Err(CreateSnapshotError::VhostNetDevice(id.clone()));
+                        }
+                        if net.backend_type() == NetBackendType::Xdp {
+                            return Err(CreateSnapshotError::XdpNetDevice(id.clone()));
+                        }
+                        if net.backend_type() != NetBackendType::Tap {
+                            return Err(CreateSnapshotError::NonTapNetDevice(id.clone()));
+                        }
+                        if net.rx_rate_limiter().group().is_some() {
+                            return Err(CreateSnapshotError::RateLimiterGroup(id.clone()));
+                        }
+                        if net.worker_thread() {
+                            return Err(CreateSnapshotError::NetWorkerThread(id.clone()));
+                        }
+                    }
+                }
+                TYPE_BLOCK => {
+                    if let Some(block) = locked_dev.as_any().downcast_ref::<Block>() {
+                        if block.rate_limiter().group().is_some() {
+                            return Err(CreateSnapshotError::RateLimiterGroup(id.clone()));
+                        }
+                        if block.vhost_user_socket().is_some() {
+                            return Err(CreateSnapshotError::VhostUserDrive(id.clone()));
+                        }
+                        if block.has_disk_fd() {
+                            return Err(CreateSnapshotError::FdBackedDrive(id.clone()));
+                        }
+                    }
+                }
+                TYPE_MEM => return Err(CreateSnapshotError::MemoryHotplugDevice),
+                TYPE_PMEM => return Err(CreateSnapshotError::PmemDevice(id.clone())),
+                TYPE_VSOCK => {
+                    // Currently, VsockUnixBackend is the only implementation of VsockBackend.
+                    if let Some(vsock) = locked_dev
+                        .as_any()
+                        .downcast_ref::<Vsock<VsockUnixBackend>>()
+                    {
+                        if vsock.uses_vhost() {
+                            return Err(CreateSnapshotError::VhostVsockDevice(id.clone()));
+                        }
+                    }
+                }
+                _ => (),
+            }
+            Ok(())
+        })
+}
+
+// Whether `file` is a regular file, as opposed to a pipe or a socket, which can't be seeked nor
+// synced.
+fn is_regular_file(file: &File) -> io::Result<bool> {
+    Ok(file.metadata()?.file_type().is_file())
+}
+
 fn snapshot_state_to_file(
     microvm_state: &MicrovmState,
     snapshot_path: &Path,
+    snapshot_file: Option<File>,
     snapshot_data_version: u16,
     version_map: VersionMap,
 ) -> std::result::Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
-    let mut snapshot_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(snapshot_path)
-        .map_err(|e| SnapshotBackingFile("open", e))?;
+    let mut snapshot_file = match snapshot_file {
+        Some(file) => file,
+        None => OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(snapshot_path)
+            .map_err(|e| SnapshotBackingFile("open", e))?,
+    };
 
     let mut snapshot = Snapshot::new(version_map, snapshot_data_version);
     snapshot
@@ -314,6 +768,9 @@ fn snapshot_state_to_file(
     snapshot_file
         .flush()
         .map_err(|e| SnapshotBackingFile("flush", e))?;
+    if !is_regular_file(&snapshot_file).map_err(|e| SnapshotBackingFile("metadata retrieval", e))? {
+        return Ok(());
+    }
     snapshot_file
         .sync_all()
         .map_err(|e| SnapshotBackingFile("sync_all", e))
@@ -322,35 +779,77 @@ fn snapshot_state_to_file(
 fn snapshot_memory_to_file(
     vmm: &Vmm,
     mem_file_path: &Path,
+    mem_file: Option<File>,
     snapshot_type: &SnapshotType,
+    compression: Option<SnapshotCompression>,
+    mem_writer_threads: usize,
 ) -> std::result::Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(mem_file_path)
-        .map_err(|e| MemoryBackingFile("open", e))?;
-
-    // Set the length of the file to the full size of the memory area.
-    let mem_size_mib = mem_size_mib(vmm.guest_memory());
-    file.set_len((mem_size_mib * 1024 * 1024) as u64)
-        .map_err(|e| MemoryBackingFile("set_length", e))?;
-
-    match snapshot_type {
-        SnapshotType::Diff => {
-            let dirty_bitmap = vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
-            vmm.guest_memory()
-                .dump_dirty(&mut file, &dirty_bitmap)
-                .map_err(Memory)
-        }
-        SnapshotType::Full => vmm.guest_memory().dump(&mut file).map_err(Memory),
-    }?;
+    let mut file = match mem_file {
+        Some(file) => file,
+        None => OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(mem_file_path)
+            .map_err(|e| MemoryBackingFile("open", e))?,
+    };
+    let is_regular =
+        is_regular_file(&file).map_err(|e| MemoryBackingFile("metadata retrieval", e))?;
+
+    if let Some(compression) = compression {
+        compressed_memory_to_file(vmm.guest_memory(), &file, compression)?;
+    } else if !is_regular {
+        // Pipes and sockets can't be resized, the memory is written sequentially instead. Diff
+        // snapshots, which seek over the unmodified pages, are ruled out by `create_snapshot`.
+        vmm.guest_memory().dump(&mut file).map_err(Memory)?;
+    } else {
+        // Set the length of the file to the full size of the memory area.
+        let mem_size_mib = mem_size_mib(vmm.guest_memory());
+        file.set_len((mem_size_mib * 1024 * 1024) as u64)
+            .map_err(|e| MemoryBackingFile("set_length", e))?;
+
+        match snapshot_type {
+            SnapshotType::Diff => {
+                let dirty_bitmap = vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
+                vmm.guest_memory()
+                    .dump_dirty(&mut file, &dirty_bitmap)
+                    .map_err(Memory)
+            }
+            SnapshotType::Full if mem_writer_threads > 1 => vmm
+                .guest_memory()
+                .dump_parallel(&file, mem_writer_threads)
+                .map_err(Memory),
+            SnapshotType::Full => vmm.guest_memory().dump(&mut file).map_err(Memory),
+        }?;
+    }
     file.flush().map_err(|e| MemoryBackingFile("flush", e))?;
+    if !is_regular {
+        return Ok(());
+    }
     file.sync_all()
         .map_err(|e| MemoryBackingFile("sync_all", e))
 }
 
+// Dumps the whole guest memory to `file`, as a single `compression` frame.
+fn compressed_memory_to_file(
+    guest_memory: &GuestMemoryMmap,
+    file: &File,
+    compression: SnapshotCompression,
+) -> std::result::Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::{Memory, MemoryBackingFile};
+    match compression {
+        SnapshotCompression::Lz4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(file);
+            guest_memory.dump(&mut encoder).map_err(Memory)?;
+            encoder
+                .finish()
+                .map_err(|e| MemoryBackingFile("compression", e.into()))?;
+        }
+    }
+    Ok(())
+}
+
 /// Validate the microVM version and translate it to its corresponding snapshot data format.
 pub fn get_snapshot_data_version(
     maybe_fc_version: &Option<String>,
@@ -390,6 +889,37 @@ pub fn get_snapshot_data_version(
             })?;
     }
 
+    if data_version < FC_V1_2_SNAP_VERSION {
+        vmm.mmio_device_manager
+            .for_each_virtio_device(|virtio_type, _id, _info, dev| {
+                let dev = dev.lock().expect("Poisoned lock");
+                // The discard and write zeroes requests are unknown to older versions.
+                if virtio_type == TYPE_BLOCK
+                    && (dev.has_feature(u64::from(VIRTIO_BLK_F_DISCARD))
+                        || dev.has_feature(u64::from(VIRTIO_BLK_F_WRITE_ZEROES)))
+                {
+                    return Err(CreateSnapshotError::IncompatibleVirtioFeature(
+                        "discard and write zeroes",
+                    ));
+                }
+                // Older versions only restore block devices with a single queue.
+                if virtio_type == TYPE_BLOCK && dev.queues().len() > 1 {
+                    return Err(CreateSnapshotError::IncompatibleVirtioFeature(
+                        "multi-queue",
+                    ));
+                }
+                // Older versions can't restore the Writethrough cache type.
+                if let Some(block) = dev.as_any().downcast_ref::<Block>() {
+                    if block.cache_type() == CacheType::Writethrough {
+                        return Err(CreateSnapshotError::IncompatibleVirtioFeature(
+                            "writethrough caching",
+                        ));
+                    }
+                }
+                Ok(())
+            })?;
+    }
+
     Ok(data_version)
 }
 
@@ -482,6 +1012,155 @@ pub fn snapshot_state_sanity_check(
     Ok(())
 }
 
+/// Replaces the TAP devices and the backing files saved in `device_states` with the ones of
+/// the overridden network interfaces and drives.
+pub fn apply_device_overrides(
+    device_states: &mut DeviceStates,
+    network_overrides: &[NetworkOverride],
+    drive_overrides: &[DriveOverride],
+) -> std::result::Result<(), LoadSnapshotError> {
+    for net_override in network_overrides {
+        let net_state = device_states
+            .net_devices
+            .iter_mut()
+            .find(|net| net.device_id == net_override.iface_id)
+            .ok_or_else(|| LoadSnapshotError::DeviceOverride(net_override.iface_id.clone()))?;
+        info!(
+            "Restoring the network interface {} on top of {} instead of {}.",
+            net_override.iface_id, net_override.host_dev_name, net_state.device_state.tap_if_name
+        );
+        net_state.device_state.tap_if_name = net_override.host_dev_name.clone();
+    }
+    for drive_override in drive_overrides {
+        let block_state = device_states
+            .block_devices
+            .iter_mut()
+            .find(|block| block.device_id == drive_override.drive_id)
+            .ok_or_else(|| LoadSnapshotError::DeviceOverride(drive_override.drive_id.clone()))?;
+        info!(
+            "Restoring the drive {} from {} instead of {}.",
+            drive_override.drive_id,
+            drive_override.path_on_host,
+            block_state.device_state.disk_path
+        );
+        block_state.device_state.disk_path = drive_override.path_on_host.clone();
+    }
+    Ok(())
+}
+
+/// Returns the IDs of the network interfaces saved in `device_states`, after checking that
+/// the MAC addresses `clone` gives to some of them have somewhere to go.
+fn clone_iface_ids(
+    device_states: &DeviceStates,
+    clone: &CloneConfig,
+) -> std::result::Result<Vec<String>, LoadSnapshotError> {
+    let iface_ids: Vec<String> = device_states
+        .net_devices
+        .iter()
+        .map(|net| net.device_id.clone())
+        .collect();
+    match clone
+        .guest_macs
+        .iter()
+        .find(|mac_override| !iface_ids.contains(&mac_override.iface_id))
+    {
+        Some(mac_override) => Err(LoadSnapshotError::DeviceOverride(
+            mac_override.iface_id.clone(),
+        )),
+        None => Ok(iface_ids),
+    }
+}
+
+/// Puts `mmds_data` in the MMDS data store of a restored microVM, or else the contents saved in
+/// `mmds_state`, if any.
+pub(crate) fn restore_mmds_data(
+    vm_resources: &mut VmResources,
+    mmds_state: Option<&MmdsState>,
+    mmds_data: Option<&serde_json::Value>,
+) -> std::result::Result<(), LoadSnapshotError> {
+    use self::LoadSnapshotError::MmdsData;
+    let data = match (mmds_data, mmds_state) {
+        (Some(data), _) => data.clone(),
+        (None, Some(state)) => state.data_store().map_err(|e| MmdsData(e.to_string()))?,
+        (None, None) => return Ok(()),
+    };
+    vm_resources
+        .locked_mmds_or_default()
+        .put_data(data)
+        .map_err(|e| MmdsData(e.to_string()))
+}
+
+// Gives the clone restored in `vmm` its own guest MAC addresses, and publishes its identity in
+// MMDS along with a fresh entropy seed, for the guest to reseed its random number generator.
+fn apply_clone_identity(
+    vmm: &mut Vmm,
+    vm_resources: &VmResources,
+    iface_ids: &[String],
+    clone: &CloneConfig,
+) -> std::result::Result<(), LoadSnapshotError> {
+    use self::LoadSnapshotError::{CloneEntropy, CloneGuestMac};
+    let mut randomness_pool = File::open(RANDOMNESS_POOL).map_err(CloneEntropy)?;
+
+    let mut guest_macs = serde_json::Map::new();
+    for iface_id in iface_ids {
+        let mac_override = clone
+            .guest_macs
+            .iter()
+            .find(|mac_override| &mac_override.iface_id == iface_id);
+        let guest_mac = match mac_override {
+            Some(mac_override) => mac_override.guest_mac,
+            // The driver of an interface without an advertised MAC address generated its own,
+            // which only the guest can change.
+            None if !net_advertises_guest_mac(vmm, iface_id).map_err(CloneGuestMac)? => continue,
+            None => random_guest_mac(&mut randomness_pool).map_err(CloneEntropy)?,
+        };
+        vmm.update_net_guest_mac(iface_id, guest_mac)
+            .map_err(CloneGuestMac)?;
+        guest_macs.insert(
+            iface_id.clone(),
+            serde_json::Value::String(guest_mac.to_string()),
+        );
+    }
+
+    let mut entropy_seed = [0u8; CLONE_ENTROPY_SEED_LEN];
+    randomness_pool
+        .read_exact(&mut entropy_seed)
+        .map_err(CloneEntropy)?;
+    info!("Restoring the microVM as the clone {}.", clone.instance_id);
+    if let Some(mmds) = vm_resources.mmds.as_ref() {
+        mmds.lock()
+            .expect("Poisoned lock")
+            .set_clone_identity(serde_json::json!({
+                "instance_id": clone.instance_id,
+                "entropy_seed": entropy_seed
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<String>(),
+                "guest_macs": guest_macs,
+            }));
+    }
+    Ok(())
+}
+
+fn net_advertises_guest_mac(vmm: &Vmm, iface_id: &str) -> std::result::Result<bool, VmmError> {
+    let mut advertises_guest_mac = false;
+    vmm.mmio_device_manager
+        .with_virtio_device_with_id(TYPE_NET, iface_id, |net: &mut Net| {
+            advertises_guest_mac = net.advertises_guest_mac();
+            Ok(())
+        })
+        .map_err(VmmError::DeviceManager)?;
+    Ok(advertises_guest_mac)
+}
+
+// Draws a random, locally administered, unicast MAC address.
+fn random_guest_mac<R: Read>(randomness_pool: &mut R) -> io::Result<MacAddr> {
+    let mut bytes = [0u8; MAC_ADDR_LEN];
+    randomness_pool.read_exact(&mut bytes)?;
+    bytes[0] = (bytes[0] & 0xfc) | 0x02;
+    Ok(MacAddr::from_bytes_unchecked(&bytes))
+}
+
 /// Loads a Microvm snapshot producing a 'paused' Microvm.
 pub fn restore_from_snapshot(
     instance_info: &InstanceInfo,
@@ -492,20 +1171,62 @@ pub fn restore_from_snapshot(
     vm_resources: &mut VmResources,
 ) -> std::result::Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
     use self::LoadSnapshotError::*;
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    // Safe because the caller hands the file descriptors over to us.
+    let snapshot_file = params
+        .snapshot_fd
+        .map(|fd| unsafe { File::from_raw_fd(fd) });
+    let mem_file = params
+        .mem_file_fd
+        .map(|fd| unsafe { File::from_raw_fd(fd) });
+    let mut microvm_state =
+        snapshot_state_from_file(&params.snapshot_path, snapshot_file, version_map)?;
 
     // Some sanity checks before building the microvm.
     snapshot_state_sanity_check(&microvm_state)?;
+    apply_device_overrides(
+        &mut microvm_state.device_states,
+        &params.network_overrides,
+        &params.drive_overrides,
+    )?;
+    let clone_iface_ids = match params.clone.as_ref() {
+        Some(clone) => clone_iface_ids(&microvm_state.device_states, clone)?,
+        None => Vec::new(),
+    };
+    let mmds_state = microvm_state.mmds.take();
 
     let mem_backend_path = &params.mem_backend.backend_path;
     let mem_state = &microvm_state.memory_state;
     let track_dirty_pages = params.enable_diff_snapshots;
-    let (guest_memory, uffd) = match params.mem_backend.backend_type {
-        MemBackendType::File => (
-            guest_memory_from_file(mem_backend_path, mem_state, track_dirty_pages)?,
+    let (guest_memory, uffd) = match (&params.mem_backend.backend_type, params.resume_mode) {
+        (MemBackendType::File, ResumeMode::Eager) => {
+            let guest_memory = guest_memory_from_file(
+                mem_backend_path,
+                mem_file,
+                microvm_state.compression.map(SnapshotCompression::from),
+                mem_state,
+                track_dirty_pages,
+                &params.guest_mem_backend,
+            )?;
+            // Checking lazily loaded memory would fetch all of it up front, and the memory a
+            // page fault handler serves never goes through Firecracker.
+            if !params.skip_verify {
+                guest_memory.verify(mem_state).map_err(DeserializeMemory)?;
+            }
+            (guest_memory, None)
+        }
+        (MemBackendType::File, ResumeMode::Lazy) => (
+            guest_memory_lazily_from_file(
+                mem_backend_path,
+                mem_file,
+                microvm_state.compression.is_some(),
+                mem_state,
+                track_dirty_pages,
+                microvm_state.device_states.balloon_device.is_some(),
+                seccomp_filters,
+            )?,
             None,
         ),
-        MemBackendType::Uffd => guest_memory_from_uffd(
+        (MemBackendType::Uffd, _) => guest_memory_from_uffd(
             mem_backend_path,
             mem_state,
             track_dirty_pages,
@@ -514,7 +1235,7 @@ pub fn restore_from_snapshot(
             microvm_state.device_states.balloon_device.is_some(),
         )?,
     };
-    builder::build_microvm_from_snapshot(
+    let vmm = builder::build_microvm_from_snapshot(
         instance_info,
         event_manager,
         microvm_state,
@@ -524,33 +1245,360 @@ pub fn restore_from_snapshot(
         seccomp_filters,
         vm_resources,
     )
-    .map_err(BuildMicroVm)
+    .map_err(BuildMicroVm)?;
+    // The machine configuration reports the memory the guest memory was copied in.
+    if !params.guest_mem_backend.is_anonymous() {
+        vm_resources
+            .update_vm_config(&VmUpdateConfig {
+                vcpu_count: None,
+                mem_size_mib: None,
+                smt: None,
+                cpu_template: None,
+                track_dirty_pages: None,
+                mem_backend: Some(params.guest_mem_backend.clone()),
+            })
+            .map_err(|e| BuildMicroVm(StartMicrovmError::SetVmResources(e)))?;
+    }
+    restore_mmds_data(vm_resources, mmds_state.as_ref(), params.mmds_data.as_ref())?;
+    if let Some(clone) = params.clone.as_ref() {
+        apply_clone_identity(
+            &mut vmm.lock().expect("Poisoned lock"),
+            vm_resources,
+            &clone_iface_ids,
+            clone,
+        )?;
+    }
+    // The pages a page fault handler serves, or read from file descriptors, aren't read from a
+    // file Firecracker knows of.
+    if params.mem_backend.backend_type == MemBackendType::File
+        && params.snapshot_fd.is_none()
+        && params.mem_file_fd.is_none()
+    {
+        vmm.lock().expect("Poisoned lock").diff_parent = DiffSnapshotParent::Snapshot(
+            SnapshotParentState::new(&params.snapshot_path, mem_backend_path),
+        );
+    }
+    Ok(vmm)
+}
+
+/// Merges the chain of diff snapshots ending with the one described by `params` into the memory
+/// file of a full snapshot, which goes along with the microVM state file of the last snapshot.
+pub fn merge_snapshot_chain(
+    params: &MergeSnapshotParams,
+    version_map: VersionMap,
+) -> std::result::Result<(), MergeSnapshotError> {
+    use self::MergeSnapshotError::*;
+
+    // Walk the chain back to its base, a snapshot without parent.
+    let mut mem_file_paths = vec![params.mem_file_path.clone()];
+    let mut visited = HashSet::new();
+    let mut snapshot_path = params.snapshot_path.clone();
+    loop {
+        if !visited.insert(snapshot_path.clone()) {
+            return Err(ChainLoop(snapshot_path.to_string_lossy().into_owned()));
+        }
+        let microvm_state = snapshot_state_from_file(&snapshot_path, None, version_map.clone())
+            .map_err(LoadMicrovmState)?;
+        if microvm_state.compression.is_some() {
+            let mem_file_path = mem_file_paths.last().expect("The chain has a memory file");
+            return Err(CompressedMemoryFile(
+                mem_file_path.to_string_lossy().into_owned(),
+            ));
+        }
+        match microvm_state.parent {
+            Some(parent) => {
+                mem_file_paths.push(PathBuf::from(parent.mem_file_path));
+                snapshot_path = PathBuf::from(parent.snapshot_path);
+            }
+            None => break,
+        }
+    }
+    // The output is truncated before the chain is read.
+    if let Some(path) = mem_file_paths
+        .iter()
+        .chain(visited.iter())
+        .find(|path| same_file(path, &params.output_mem_file_path))
+    {
+        return Err(OutputInChain(path.to_string_lossy().into_owned()));
+    }
+
+    let mut output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&params.output_mem_file_path)
+        .map_err(OutputMemoryFile)?;
+    // Apply the base first, then each diff on top of its parent.
+    for mem_file_path in mem_file_paths.iter().rev() {
+        let path = mem_file_path.to_string_lossy().into_owned();
+        let mut mem_file = File::open(mem_file_path).map_err(|e| MemoryFile(path.clone(), e))?;
+        copy_data_segments(&mut mem_file, &mut output).map_err(|e| MemoryFile(path, e))?;
+    }
+    output.flush().map_err(OutputMemoryFile)?;
+    output.sync_all().map_err(OutputMemoryFile)
 }
 
+// Returns whether `path` and `other` are the same file, such as a hard link or a symbolic link
+// to it. A missing file isn't the same as any other.
+fn same_file(path: &Path, other: &Path) -> bool {
+    match (std::fs::metadata(path), std::fs::metadata(other)) {
+        (Ok(metadata), Ok(other_metadata)) => {
+            metadata.dev() == other_metadata.dev() && metadata.ino() == other_metadata.ino()
+        }
+        _ => false,
+    }
+}
+
+// Copies the data segments of the sparse `src` file at the same offsets of `dst`, leaving
+// untouched the holes of `src`, i.e. the pages a diff snapshot doesn't hold.
+fn copy_data_segments(src: &mut File, dst: &mut File) -> io::Result<()> {
+    let src_len = src.metadata()?.len();
+    if dst.metadata()?.len() < src_len {
+        dst.set_len(src_len)?;
+    }
+
+    let mut cursor = 0;
+    while let Some(data_start) = src.seek_data(cursor)? {
+        let data_end = src.seek_hole(data_start)?.unwrap_or(src_len);
+        src.seek(SeekFrom::Start(data_start))?;
+        dst.seek(SeekFrom::Start(data_start))?;
+        io::copy(&mut src.by_ref().take(data_end - data_start), dst)?;
+        cursor = data_end;
+    }
+    Ok(())
+}
+
+/// Describes the snapshot whose microVM state is saved at `snapshot_path`, without loading it.
+pub fn describe_snapshot(
+    snapshot_path: &Path,
+    version_map: VersionMap,
+) -> std::result::Result<SnapshotInfo, LoadSnapshotError> {
+    let snapshot_bytes = read_snapshot_file(snapshot_path, None)?;
+    let (microvm_state, data_version) = deserialize_snapshot_state(&snapshot_bytes, version_map)?;
+
+    let device_states = &microvm_state.device_states;
+    let mut devices = Vec::new();
+    devices.extend(
+        device_states
+            .block_devices
+            .iter()
+            .map(|block| SnapshotDeviceInfo {
+                id: block.device_id.clone(),
+                device_type: "block",
+            }),
+    );
+    devices.extend(
+        device_states
+            .net_devices
+            .iter()
+            .map(|net| SnapshotDeviceInfo {
+                id: net.device_id.clone(),
+                device_type: "net",
+            }),
+    );
+    devices.extend(
+        device_states
+            .vsock_device
+            .iter()
+            .chain(device_states.vsock_devices.iter())
+            .map(|vsock| SnapshotDeviceInfo {
+                id: vsock.device_id.clone(),
+                device_type: "vsock",
+            }),
+    );
+    devices.extend(
+        device_states
+            .balloon_device
+            .iter()
+            .map(|balloon| SnapshotDeviceInfo {
+                id: balloon.device_id.clone(),
+                device_type: "balloon",
+            }),
+    );
+
+    // The checksum takes the last 8 bytes of the state, it was checked when the state was
+    // deserialized.
+    let mut checksum = [0u8; 8];
+    checksum.copy_from_slice(&snapshot_bytes[snapshot_bytes.len() - checksum.len()..]);
+
+    Ok(SnapshotInfo {
+        firecracker_version: FC_VERSION_TO_SNAP_VERSION
+            .iter()
+            .find(|(_, &version)| version == data_version)
+            .map(|(fc_version, _)| fc_version.clone()),
+        data_version,
+        vcpu_count: microvm_state.vcpu_states.len(),
+        mem_size_mib: microvm_state.vm_info.mem_size_mib,
+        devices,
+        checksum: format!("{:016x}", u64::from_le_bytes(checksum)),
+    })
+}
+
+// Loads the microVM state of a snapshot, translated to the layout of the current release.
 fn snapshot_state_from_file(
     snapshot_path: &Path,
+    snapshot_file: Option<File>,
     version_map: VersionMap,
 ) -> std::result::Result<MicrovmState, LoadSnapshotError> {
-    use self::LoadSnapshotError::{DeserializeMicrovmState, SnapshotBackingFile};
-    let mut snapshot_reader =
-        File::open(snapshot_path).map_err(|e| SnapshotBackingFile("open", e))?;
-    let metadata = std::fs::metadata(snapshot_path)
-        .map_err(|e| SnapshotBackingFile("metadata retrieval", e))?;
-    let snapshot_len = metadata.len() as usize;
-    Snapshot::load(&mut snapshot_reader, snapshot_len, version_map).map_err(DeserializeMicrovmState)
+    let snapshot_bytes = read_snapshot_file(snapshot_path, snapshot_file)?;
+    let (mut microvm_state, data_version) =
+        deserialize_snapshot_state(&snapshot_bytes, version_map.clone())?;
+    translate_microvm_state(&mut microvm_state, data_version, &version_map)
+        .map_err(LoadSnapshotError::TranslateMicrovmState)?;
+    Ok(microvm_state)
+}
+
+// Reads the whole microVM state, from `snapshot_file` if any, or else from `snapshot_path`.
+fn read_snapshot_file(
+    snapshot_path: &Path,
+    snapshot_file: Option<File>,
+) -> std::result::Result<Vec<u8>, LoadSnapshotError> {
+    use self::LoadSnapshotError::SnapshotBackingFile;
+    let mut snapshot_reader = match snapshot_file {
+        Some(file) => file,
+        None => File::open(snapshot_path).map_err(|e| SnapshotBackingFile("open", e))?,
+    };
+    // The state is read whole, its data version being needed once it is deserialized. The
+    // length of the state read from a pipe or a socket is only known at its end anyway.
+    let mut snapshot_bytes = Vec::new();
+    snapshot_reader
+        .read_to_end(&mut snapshot_bytes)
+        .map_err(|e| SnapshotBackingFile("read", e))?;
+    Ok(snapshot_bytes)
+}
+
+// Deserializes the microVM state from `snapshot_bytes`, along with its data version.
+fn deserialize_snapshot_state(
+    snapshot_bytes: &[u8],
+    version_map: VersionMap,
+) -> std::result::Result<(MicrovmState, u16), LoadSnapshotError> {
+    use self::LoadSnapshotError::{DeserializeMicrovmState, TranslateMicrovmState};
+    let data_version = match Snapshot::get_data_version(&mut &snapshot_bytes[..], &version_map) {
+        Err(snapshot::Error::InvalidDataVersion(data_version)) => {
+            return Err(TranslateMicrovmState(
+                snapshot_translation::Error::UnsupportedDataVersion(data_version),
+            ))
+        }
+        result => result.map_err(DeserializeMicrovmState)?,
+    };
+    let microvm_state = Snapshot::load(&mut &snapshot_bytes[..], snapshot_bytes.len(), version_map)
+        .map_err(DeserializeMicrovmState)?;
+    Ok((microvm_state, data_version))
 }
 
 fn guest_memory_from_file(
     mem_file_path: &Path,
+    mem_file: Option<File>,
+    compression: Option<SnapshotCompression>,
     mem_state: &GuestMemoryState,
     track_dirty_pages: bool,
+    guest_mem_backend: &GuestMemoryBackend,
 ) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
-    use self::LoadSnapshotError::{DeserializeMemory, MemoryBackingFile};
-    let mem_file = File::open(mem_file_path).map_err(MemoryBackingFile)?;
+    use self::LoadSnapshotError::{DeserializeMemory, MemoryBackingFile, MemoryFileTooSmall};
+    let mem_file = match mem_file {
+        Some(file) => file,
+        None => File::open(mem_file_path).map_err(MemoryBackingFile)?,
+    };
+
+    // A pipe or a socket can't be mapped, and a compressed file has to be decompressed.
+    if compression.is_some() || !is_regular_file(&mem_file).map_err(MemoryBackingFile)? {
+        return load_guest_memory(
+            mem_file,
+            compression,
+            mem_state,
+            track_dirty_pages,
+            guest_mem_backend,
+        );
+    }
+
+    // The pages of a mapping past the end of the file can't be accessed.
+    let file_len = mem_file.metadata().map_err(MemoryBackingFile)?.len();
+    let mem_len = mem_state
+        .regions
+        .iter()
+        .map(|region| region.offset + region.size as u64)
+        .max()
+        .unwrap_or(0);
+    if file_len < mem_len {
+        return Err(MemoryFileTooSmall(file_len, mem_len));
+    }
+    // The memory file can't be mapped in place of the huge pages, it's copied in them.
+    if !guest_mem_backend.is_anonymous() {
+        return load_guest_memory(
+            mem_file,
+            None,
+            mem_state,
+            track_dirty_pages,
+            guest_mem_backend,
+        );
+    }
     GuestMemoryMmap::restore(Some(&mem_file), mem_state, track_dirty_pages)
         .map_err(DeserializeMemory)
 }
 
+// Reads the guest memory from `reader`, compressed with `compression`, in anonymous memory.
+fn load_guest_memory<R: Read>(
+    mut reader: R,
+    compression: Option<SnapshotCompression>,
+    mem_state: &GuestMemoryState,
+    track_dirty_pages: bool,
+    guest_mem_backend: &GuestMemoryBackend,
+) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
+    use self::LoadSnapshotError::{DeserializeMemory, MemoryBackingFile};
+    let guest_memory = match guest_mem_backend {
+        GuestMemoryBackend::Anonymous => {
+            GuestMemoryMmap::restore(None, mem_state, track_dirty_pages)
+        }
+        GuestMemoryBackend::Hugetlbfs { path } => vm_memory::create_hugetlbfs_guest_memory(
+            &mem_state
+                .regions
+                .iter()
+                .map(|region| (GuestAddress(region.base_address), region.size))
+                .collect::<Vec<_>>(),
+            path,
+            track_dirty_pages,
+        )
+        .map_err(memory_snapshot::Error::CreateMemory),
+    }
+    .map_err(DeserializeMemory)?;
+    match compression {
+        Some(SnapshotCompression::Lz4) => {
+            guest_memory.load(&mut lz4_flex::frame::FrameDecoder::new(reader))
+        }
+        None => guest_memory.load(&mut reader),
+    }
+    .map_err(DeserializeMemory)?;
+    Ok(guest_memory)
+}
+
+// Maps the guest memory without loading it, the pages are copied from the memory file on first
+// access by a page fault handler thread.
+fn guest_memory_lazily_from_file(
+    mem_file_path: &Path,
+    mem_file: Option<File>,
+    compressed: bool,
+    mem_state: &GuestMemoryState,
+    track_dirty_pages: bool,
+    enable_balloon: bool,
+    seccomp_filters: &BpfThreadMap,
+) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
+    use self::LoadSnapshotError::{DeserializeMemory, LazyResume, MemoryBackingFile};
+    let mem_file = match mem_file {
+        Some(file) => file,
+        None => File::open(mem_file_path).map_err(MemoryBackingFile)?,
+    };
+    if compressed || !is_regular_file(&mem_file).map_err(MemoryBackingFile)? {
+        return Err(LazyResume(uffd_handler::Error::UnsupportedMemoryFile));
+    }
+
+    let guest_memory =
+        GuestMemoryMmap::restore(None, mem_state, track_dirty_pages).map_err(DeserializeMemory)?;
+    UffdHandler::new(&mem_file, &guest_memory, mem_state, enable_balloon)
+        .and_then(|handler| handler.start(seccomp_filters))
+        .map_err(LazyResume)?;
+    Ok(guest_memory)
+}
+
 fn guest_memory_from_uffd(
     mem_uds_path: &Path,
     mem_state: &GuestMemoryState,
@@ -678,7 +1726,9 @@ mod tests {
     use crate::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
     use crate::vmm_config::balloon::BalloonDeviceConfig;
     use crate::vmm_config::drive::CacheType;
-    use crate::vmm_config::net::NetworkInterfaceConfig;
+    use crate::vmm_config::net::{
+        NetBackendType, NetDatapath, NetOffloads, NetworkInterfaceConfig,
+    };
     use crate::vmm_config::vsock::tests::default_config;
     use crate::Vmm;
 
@@ -695,6 +1745,7 @@ mod tests {
             amount_mib: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
         insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_config);
 
@@ -716,6 +1767,18 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
         };
         insert_net_device(
             &mut vmm,
@@ -734,24 +1797,13 @@ mod tests {
         vmm
     }
 
-    #[test]
-    fn test_microvmstate_versionize() {
-        let vmm = default_vmm_with_devices();
-        let states = vmm.mmio_device_manager.save();
-
-        // Only checking that all devices are saved, actual device state
-        // is tested by that device's tests.
-        assert_eq!(states.block_devices.len(), 1);
-        assert_eq!(states.net_devices.len(), 1);
-        assert!(states.vsock_device.is_some());
-        assert!(states.balloon_device.is_some());
-
+    fn default_microvm_state(vmm: &Vmm) -> MicrovmState {
         let memory_state = vmm.guest_memory().describe();
         let vcpu_states = vec![VcpuState::default()];
         #[cfg(target_arch = "aarch64")]
         let mpidrs = construct_kvm_mpidrs(&vcpu_states);
-        let microvm_state = MicrovmState {
-            device_states: states,
+        MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
             memory_state,
             vcpu_states,
             vm_info: VmInfo { mem_size_mib: 1u64 },
@@ -759,7 +1811,24 @@ mod tests {
             vm_state: vmm.vm.save_state(&mpidrs).unwrap(),
             #[cfg(target_arch = "x86_64")]
             vm_state: vmm.vm.save_state().unwrap(),
-        };
+            parent: None,
+            mmds: None,
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn test_microvmstate_versionize() {
+        let vmm = default_vmm_with_devices();
+        let mut microvm_state = default_microvm_state(&vmm);
+        let states = &microvm_state.device_states;
+
+        // Only checking that all devices are saved, actual device state
+        // is tested by that device's tests.
+        assert_eq!(states.block_devices.len(), 1);
+        assert_eq!(states.net_devices.len(), 1);
+        assert!(states.vsock_device.is_some());
+        assert!(states.balloon_device.is_some());
 
         let mut buf = vec![0; 10000];
         let mut version_map = VersionMap::new();
@@ -782,7 +1851,404 @@ mod tests {
         assert_eq!(
             restored_microvm_state.device_states,
             microvm_state.device_states
+        );
+
+        // The parent snapshot is only saved from v1.2.
+        let parent = SnapshotParentState::new(Path::new("state"), Path::new("mem"));
+        microvm_state.parent = Some(parent.clone());
+        let mut buf = Vec::new();
+        microvm_state
+            .serialize(&mut buf, &VERSION_MAP, FC_V1_2_SNAP_VERSION)
+            .unwrap();
+        let restored_microvm_state =
+            MicrovmState::deserialize(&mut buf.as_slice(), &VERSION_MAP, FC_V1_2_SNAP_VERSION)
+                .unwrap();
+        assert_eq!(restored_microvm_state.parent, Some(parent));
+
+        let mut buf = Vec::new();
+        microvm_state
+            .serialize(&mut buf, &VERSION_MAP, FC_V1_1_SNAP_VERSION)
+            .unwrap();
+        let restored_microvm_state =
+            MicrovmState::deserialize(&mut buf.as_slice(), &VERSION_MAP, FC_V1_1_SNAP_VERSION)
+                .unwrap();
+        assert_eq!(restored_microvm_state.parent, None);
+
+        // The MMDS contents are only saved from v1.2.
+        let mut mmds = mmds::data_store::Mmds::default();
+        mmds.put_data(serde_json::json!({"key": "value"})).unwrap();
+        microvm_state.mmds = MmdsState::save(&mmds);
+        assert!(microvm_state.mmds.is_some());
+        let mut buf = Vec::new();
+        microvm_state
+            .serialize(&mut buf, &VERSION_MAP, FC_V1_2_SNAP_VERSION)
+            .unwrap();
+        let restored_microvm_state =
+            MicrovmState::deserialize(&mut buf.as_slice(), &VERSION_MAP, FC_V1_2_SNAP_VERSION)
+                .unwrap();
+        assert_eq!(restored_microvm_state.mmds, microvm_state.mmds);
+
+        let mut buf = Vec::new();
+        microvm_state
+            .serialize(&mut buf, &VERSION_MAP, FC_V1_1_SNAP_VERSION)
+            .unwrap();
+        let restored_microvm_state =
+            MicrovmState::deserialize(&mut buf.as_slice(), &VERSION_MAP, FC_V1_1_SNAP_VERSION)
+                .unwrap();
+        assert_eq!(restored_microvm_state.mmds, None);
+    }
+
+    #[test]
+    fn test_restore_mmds_data() {
+        use mmds::data_store::Mmds;
+
+        let mut saved_mmds = Mmds::default();
+        saved_mmds
+            .put_data(serde_json::json!({"secret": "saved"}))
+            .unwrap();
+        let mmds_state = MmdsState::save(&saved_mmds).unwrap();
+        let fresh_data = serde_json::json!({"secret": "fresh"});
+        let vm_resources = || VmResources {
+            mmds: Some(Arc::new(Mutex::new(Mmds::default()))),
+            ..Default::default()
+        };
+        let data_store = |vm_resources: &VmResources| {
+            vm_resources
+                .mmds
+                .as_ref()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .data_store_value()
+        };
+
+        // The snapshot was taken without the MMDS contents.
+        let mut resources = vm_resources();
+        restore_mmds_data(&mut resources, None, None).unwrap();
+        assert!(!resources
+            .mmds
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .is_initialized());
+
+        let mut resources = vm_resources();
+        restore_mmds_data(&mut resources, Some(&mmds_state), None).unwrap();
+        assert_eq!(
+            data_store(&resources),
+            serde_json::json!({"secret": "saved"})
+        );
+
+        // Fresh contents replace the saved ones.
+        let mut resources = vm_resources();
+        restore_mmds_data(&mut resources, Some(&mmds_state), Some(&fresh_data)).unwrap();
+        assert_eq!(data_store(&resources), fresh_data);
+
+        // The contents still have to fit in the data store.
+        let mut resources = VmResources {
+            mmds: Some(Arc::new(Mutex::new(Mmds::default_with_limit(4)))),
+            ..Default::default()
+        };
+        match restore_mmds_data(&mut resources, None, Some(&fresh_data)) {
+            Err(LoadSnapshotError::MmdsData(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_apply_device_overrides() {
+        let vmm = default_vmm_with_devices();
+        let mut device_states = default_microvm_state(&vmm).device_states;
+
+        let network_overrides = vec![NetworkOverride {
+            iface_id: "netif".to_string(),
+            host_dev_name: "vmtap01".to_string(),
+        }];
+        let drive_overrides = vec![DriveOverride {
+            drive_id: "root".to_string(),
+            path_on_host: "/srv/images/rootfs.ext4".to_string(),
+        }];
+        apply_device_overrides(&mut device_states, &network_overrides, &drive_overrides).unwrap();
+        assert_eq!(
+            device_states.net_devices[0].device_state.tap_if_name,
+            "vmtap01"
+        );
+        assert_eq!(
+            device_states.block_devices[0].device_state.disk_path,
+            "/srv/images/rootfs.ext4"
+        );
+
+        // Only the devices of the snapshot can be overridden.
+        let network_overrides = vec![NetworkOverride {
+            iface_id: "eth1".to_string(),
+            host_dev_name: "vmtap01".to_string(),
+        }];
+        match apply_device_overrides(&mut device_states, &network_overrides, &[]) {
+            Err(LoadSnapshotError::DeviceOverride(id)) => assert_eq!(id, "eth1"),
+            _ => panic!("Test failed."),
+        }
+        let drive_overrides = vec![DriveOverride {
+            drive_id: "netif".to_string(),
+            path_on_host: "/srv/images/rootfs.ext4".to_string(),
+        }];
+        match apply_device_overrides(&mut device_states, &[], &drive_overrides) {
+            Err(LoadSnapshotError::DeviceOverride(id)) => assert_eq!(id, "netif"),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_clone_identity() {
+        use mmds::data_store::{Mmds, OutputFormat};
+
+        use crate::vmm_config::snapshot::GuestMacOverride;
+
+        let mut vmm = default_vmm_with_devices();
+        let device_states = default_microvm_state(&vmm).device_states;
+        let mut clone = CloneConfig {
+            instance_id: "clone-1".to_string(),
+            guest_macs: vec![],
+        };
+        let iface_ids = clone_iface_ids(&device_states, &clone).unwrap();
+        assert_eq!(iface_ids, vec!["netif".to_string()]);
+
+        // The interface doesn't advertise a MAC address, the guest generated its own.
+        let mut vm_resources = VmResources {
+            mmds: Some(Arc::new(Mutex::new(Mmds::default()))),
+            ..Default::default()
+        };
+        apply_clone_identity(&mut vmm, &vm_resources, &iface_ids, &clone).unwrap();
+        let mmds = vm_resources.mmds.take().unwrap();
+        let mmds = mmds.lock().unwrap();
+        assert_eq!(
+            mmds.get_value("/fc-clone/instance_id".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "clone-1"
+        );
+        let entropy_seed = mmds
+            .get_value("/fc-clone/entropy_seed".to_string(), OutputFormat::Imds)
+            .unwrap();
+        assert_eq!(entropy_seed.len(), CLONE_ENTROPY_SEED_LEN * 2);
+        assert!(mmds
+            .get_value("/fc-clone/guest_macs/netif".to_string(), OutputFormat::Imds)
+            .is_err());
+
+        // Nor can it be given one.
+        clone.guest_macs.push(GuestMacOverride {
+            iface_id: "netif".to_string(),
+            guest_mac: MacAddr::parse_str("06:00:ac:10:00:03").unwrap(),
+        });
+        assert!(matches!(
+            apply_clone_identity(&mut vmm, &vm_resources, &iface_ids, &clone),
+            Err(LoadSnapshotError::CloneGuestMac(_))
+        ));
+
+        // Only the interfaces of the snapshot can be given a MAC address.
+        clone.guest_macs[0].iface_id = "eth1".to_string();
+        match clone_iface_ids(&device_states, &clone) {
+            Err(LoadSnapshotError::DeviceOverride(id)) => assert_eq!(id, "eth1"),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_random_guest_mac() {
+        let guest_mac = random_guest_mac(&mut &[0xffu8; MAC_ADDR_LEN][..]).unwrap();
+        // Locally administered and unicast.
+        assert_eq!(guest_mac.to_string(), "fe:ff:ff:ff:ff:ff");
+        let guest_mac = random_guest_mac(&mut &[0u8; MAC_ADDR_LEN][..]).unwrap();
+        assert_eq!(guest_mac.to_string(), "02:00:00:00:00:00");
+        assert!(random_guest_mac(&mut &[0u8; 2][..]).is_err());
+    }
+
+    #[test]
+    fn test_describe_snapshot() {
+        let vmm = default_vmm_with_devices();
+        let microvm_state = default_microvm_state(&vmm);
+        let state_file = TempFile::new().unwrap();
+        snapshot_state_to_file(
+            &microvm_state,
+            state_file.as_path(),
+            None,
+            FC_V1_1_SNAP_VERSION,
+            VERSION_MAP.clone(),
+        )
+        .unwrap();
+
+        let info = describe_snapshot(state_file.as_path(), VERSION_MAP.clone()).unwrap();
+        assert_eq!(info.firecracker_version, Some("1.1.0".to_string()));
+        assert_eq!(info.data_version, FC_V1_1_SNAP_VERSION);
+        assert_eq!(info.vcpu_count, 1);
+        assert_eq!(info.mem_size_mib, 1);
+        assert_eq!(
+            info.devices
+                .iter()
+                .map(|device| device.device_type)
+                .collect::<Vec<_>>(),
+            vec!["block", "net", "vsock", "balloon"]
+        );
+        assert_eq!(info.devices[0].id, "root");
+        assert_eq!(info.devices[1].id, "netif");
+        let state = std::fs::read(state_file.as_path()).unwrap();
+        let mut checksum = [0u8; 8];
+        checksum.copy_from_slice(&state[state.len() - 8..]);
+        assert_eq!(
+            info.checksum,
+            format!("{:016x}", u64::from_le_bytes(checksum))
+        );
+
+        // The state must be valid.
+        state_file
+            .as_file()
+            .set_len(state.len() as u64 - 1)
+            .unwrap();
+        assert!(describe_snapshot(state_file.as_path(), VERSION_MAP.clone()).is_err());
+        assert!(describe_snapshot(Path::new("/no/such/snapshot"), VERSION_MAP.clone()).is_err());
+    }
+
+    #[test]
+    fn test_merge_snapshot_chain() {
+        use std::os::unix::fs::FileExt;
+
+        let vmm = default_vmm_with_devices();
+        let page_size = utils::get_page_size().unwrap();
+        let pages = |values: &[u8]| {
+            values
+                .iter()
+                .flat_map(|value| vec![*value; page_size])
+                .collect::<Vec<u8>>()
+        };
+
+        // A full snapshot, then two diff snapshots each holding a single page.
+        let mut links: Vec<(TempFile, TempFile)> = Vec::new();
+        for (page, contents) in [
+            (0usize, pages(&[1, 1, 1])),
+            (1, pages(&[2])),
+            (2, pages(&[3])),
+        ]
+        .iter()
+        {
+            let state_file = TempFile::new().unwrap();
+            let mem_file = TempFile::new().unwrap();
+            let mut microvm_state = default_microvm_state(&vmm);
+            microvm_state.parent = links
+                .last()
+                .map(|(state, mem)| SnapshotParentState::new(state.as_path(), mem.as_path()));
+            snapshot_state_to_file(
+                &microvm_state,
+                state_file.as_path(),
+                None,
+                VERSION_MAP.latest_version(),
+                VERSION_MAP.clone(),
+            )
+            .unwrap();
+            mem_file.as_file().set_len(3 * page_size as u64).unwrap();
+            mem_file
+                .as_file()
+                .write_all_at(contents, (page * page_size) as u64)
+                .unwrap();
+            links.push((state_file, mem_file));
+        }
+
+        let (state_file, mem_file) = links.last().unwrap();
+        let output_file = TempFile::new().unwrap();
+        let mut params = MergeSnapshotParams {
+            snapshot_path: state_file.as_path().to_path_buf(),
+            mem_file_path: mem_file.as_path().to_path_buf(),
+            output_mem_file_path: output_file.as_path().to_path_buf(),
+        };
+        merge_snapshot_chain(&params, VERSION_MAP.clone()).unwrap();
+        assert_eq!(
+            std::fs::read(output_file.as_path()).unwrap(),
+            pages(&[1, 2, 3])
+        );
+
+        // The files of the chain can't be overwritten, even through another path.
+        let base_mem_link = TempFile::new().unwrap();
+        std::fs::remove_file(base_mem_link.as_path()).unwrap();
+        std::os::unix::fs::symlink(links[0].1.as_path(), base_mem_link.as_path()).unwrap();
+        for output_path in [links[1].0.as_path(), base_mem_link.as_path()].iter() {
+            params.output_mem_file_path = output_path.to_path_buf();
+            assert!(matches!(
+                merge_snapshot_chain(&params, VERSION_MAP.clone()),
+                Err(MergeSnapshotError::OutputInChain(_))
+            ));
+        }
+        assert_eq!(
+            std::fs::read(links[0].1.as_path()).unwrap(),
+            pages(&[1, 1, 1])
+        );
+        params.output_mem_file_path = output_file.as_path().to_path_buf();
+
+        // A chain going back to one of its snapshots.
+        let looping_state_file = TempFile::new().unwrap();
+        let mut microvm_state = default_microvm_state(&vmm);
+        microvm_state.parent = Some(SnapshotParentState::new(
+            looping_state_file.as_path(),
+            mem_file.as_path(),
+        ));
+        snapshot_state_to_file(
+            &microvm_state,
+            looping_state_file.as_path(),
+            None,
+            VERSION_MAP.latest_version(),
+            VERSION_MAP.clone(),
         )
+        .unwrap();
+        params.snapshot_path = looping_state_file.as_path().to_path_buf();
+        assert!(matches!(
+            merge_snapshot_chain(&params, VERSION_MAP.clone()),
+            Err(MergeSnapshotError::ChainLoop(_))
+        ));
+
+        // The pages of a compressed memory file can't be merged.
+        let compressed_state_file = TempFile::new().unwrap();
+        let mut microvm_state = default_microvm_state(&vmm);
+        microvm_state.compression = Some(SnapshotCompressionState::Lz4);
+        snapshot_state_to_file(
+            &microvm_state,
+            compressed_state_file.as_path(),
+            None,
+            VERSION_MAP.latest_version(),
+            VERSION_MAP.clone(),
+        )
+        .unwrap();
+        params.snapshot_path = compressed_state_file.as_path().to_path_buf();
+        match merge_snapshot_chain(&params, VERSION_MAP.clone()) {
+            Err(MergeSnapshotError::CompressedMemoryFile(path)) => {
+                assert_eq!(path, mem_file.as_path().to_string_lossy())
+            }
+            _ => panic!("Unexpected result."),
+        }
+
+        // Older snapshot versions can't tell the memory file is compressed.
+        microvm_state.compression = None;
+        snapshot_state_to_file(
+            &microvm_state,
+            compressed_state_file.as_path(),
+            None,
+            FC_V1_1_SNAP_VERSION,
+            VERSION_MAP.clone(),
+        )
+        .unwrap();
+        microvm_state.compression = Some(SnapshotCompressionState::Lz4);
+        assert!(matches!(
+            snapshot_state_to_file(
+                &microvm_state,
+                compressed_state_file.as_path(),
+                None,
+                FC_V1_1_SNAP_VERSION,
+                VERSION_MAP.clone(),
+            ),
+            Err(CreateSnapshotError::SerializeMicrovmState(_))
+        ));
+
+        params.snapshot_path = PathBuf::from("/no/such/snapshot");
+        assert!(matches!(
+            merge_snapshot_chain(&params, VERSION_MAP.clone()),
+            Err(MergeSnapshotError::LoadMicrovmState(_))
+        ));
     }
 
     #[test]
@@ -825,6 +2291,238 @@ mod tests {
         assert!(get_snapshot_data_version(&Some("0.24.0".to_string()), &VERSION_MAP, &vmm).is_ok());
     }
 
+    #[test]
+    fn test_compressed_memory_file() {
+        use vm_memory::{Bytes, GuestAddress};
+
+        let page_size = utils::get_page_size().unwrap();
+        let guest_memory =
+            vm_memory::create_guest_memory(&[(None, GuestAddress(0), page_size * 4)], false)
+                .unwrap();
+        guest_memory
+            .write(&vec![0xaau8; page_size][..], GuestAddress(page_size as u64))
+            .unwrap();
+        let mem_state = guest_memory.describe();
+
+        for compression in &[SnapshotCompression::Lz4] {
+            let mem_file = TempFile::new().unwrap();
+            compressed_memory_to_file(&guest_memory, mem_file.as_file(), *compression).unwrap();
+            assert!(mem_file.as_file().metadata().unwrap().len() < page_size as u64);
+
+            let restored = guest_memory_from_file(
+                mem_file.as_path(),
+                None,
+                Some(*compression),
+                &mem_state,
+                false,
+                &GuestMemoryBackend::Anonymous,
+            )
+            .unwrap();
+            let mut page = vec![0u8; page_size];
+            restored
+                .read(&mut page[..], GuestAddress(page_size as u64))
+                .unwrap();
+            assert_eq!(page, vec![0xaau8; page_size]);
+            restored.read(&mut page[..], GuestAddress(0)).unwrap();
+            assert_eq!(page, vec![0u8; page_size]);
+
+            // Compressed pages can't be copied on demand.
+            assert!(matches!(
+                guest_memory_lazily_from_file(
+                    mem_file.as_path(),
+                    None,
+                    true,
+                    &mem_state,
+                    false,
+                    false,
+                    &BpfThreadMap::new()
+                ),
+                Err(LoadSnapshotError::LazyResume(
+                    uffd_handler::Error::UnsupportedMemoryFile
+                ))
+            ));
+        }
+
+        // Uncompressed files are mapped as they are, even when the guest memory starts like a
+        // compressed file.
+        guest_memory
+            .write(&[0x04, 0x22, 0x4d, 0x18], GuestAddress(0))
+            .unwrap();
+        let mem_file = TempFile::new().unwrap();
+        guest_memory.dump(&mut mem_file.as_file()).unwrap();
+        let restored = guest_memory_from_file(
+            mem_file.as_path(),
+            None,
+            None,
+            &mem_state,
+            false,
+            &GuestMemoryBackend::Anonymous,
+        )
+        .unwrap();
+        let mut magic = [0u8; 4];
+        restored.read(&mut magic[..], GuestAddress(0)).unwrap();
+        assert_eq!(magic, [0x04, 0x22, 0x4d, 0x18]);
+
+        // A truncated memory file is rejected before it gets mapped.
+        mem_file.as_file().set_len(page_size as u64 * 3).unwrap();
+        match guest_memory_from_file(
+            mem_file.as_path(),
+            None,
+            None,
+            &mem_state,
+            false,
+            &GuestMemoryBackend::Anonymous,
+        ) {
+            Err(LoadSnapshotError::MemoryFileTooSmall(file_len, mem_len)) => {
+                assert_eq!(file_len, page_size as u64 * 3);
+                assert_eq!(mem_len, page_size as u64 * 4);
+            }
+            _ => panic!("Unexpected result."),
+        }
+
+        // The guest memory is only copied in the huge pages of a hugetlbfs mount.
+        mem_file.as_file().set_len(page_size as u64 * 4).unwrap();
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        assert!(matches!(
+            guest_memory_from_file(
+                mem_file.as_path(),
+                None,
+                None,
+                &mem_state,
+                false,
+                &GuestMemoryBackend::Hugetlbfs {
+                    path: tmp_dir.as_path().to_path_buf()
+                }
+            ),
+            Err(LoadSnapshotError::DeserializeMemory(
+                memory_snapshot::Error::CreateMemory(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_streamed_snapshot() {
+        use std::os::unix::io::IntoRawFd;
+
+        use vm_memory::{Bytes, GuestAddress};
+
+        // Both ends of a socket pair, as files.
+        fn stream() -> (File, File) {
+            let (writer, reader) = UnixStream::pair().unwrap();
+            unsafe {
+                (
+                    File::from_raw_fd(writer.into_raw_fd()),
+                    File::from_raw_fd(reader.into_raw_fd()),
+                )
+            }
+        }
+
+        let mut vmm = default_vmm();
+        let microvm_state = default_microvm_state(&vmm);
+        let (writer, reader) = stream();
+        snapshot_state_to_file(
+            &microvm_state,
+            Path::new(""),
+            Some(writer),
+            VERSION_MAP.latest_version(),
+            VERSION_MAP.clone(),
+        )
+        .unwrap();
+        let restored_state =
+            snapshot_state_from_file(Path::new(""), Some(reader), VERSION_MAP.clone()).unwrap();
+        assert_eq!(restored_state.vm_info, microvm_state.vm_info);
+
+        let page_size = utils::get_page_size().unwrap();
+        let guest_memory =
+            vm_memory::create_guest_memory(&[(None, GuestAddress(0), page_size * 4)], false)
+                .unwrap();
+        guest_memory
+            .write(&vec![0xaau8; page_size][..], GuestAddress(page_size as u64))
+            .unwrap();
+        let mem_state = guest_memory.describe();
+        for compression in &[None, Some(SnapshotCompression::Lz4)] {
+            let (mut writer, reader) = stream();
+            match compression {
+                Some(compression) => {
+                    compressed_memory_to_file(&guest_memory, &writer, *compression).unwrap()
+                }
+                None => guest_memory.dump(&mut writer).unwrap(),
+            }
+            drop(writer);
+
+            let restored = guest_memory_from_file(
+                Path::new(""),
+                Some(reader),
+                *compression,
+                &mem_state,
+                false,
+                &GuestMemoryBackend::Anonymous,
+            )
+            .unwrap();
+            let mut page = vec![0u8; page_size];
+            restored
+                .read(&mut page[..], GuestAddress(page_size as u64))
+                .unwrap();
+            assert_eq!(page, vec![0xaau8; page_size]);
+        }
+
+        // The memory of diff snapshots can't be streamed.
+        let (writer, _reader) = stream();
+        let params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Diff,
+            snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
+            mem_file_path: PathBuf::new(),
+            mem_file_fd: Some(writer.into_raw_fd()),
+            compression: None,
+            mem_writer_threads: None,
+            exclude_mmds: false,
+            version: None,
+        };
+        assert!(matches!(
+            create_snapshot(&mut vmm, &params, VERSION_MAP.clone()),
+            Err(CreateSnapshotError::StreamedDiffSnapshot)
+        ));
+
+        // Nor can diff snapshots be taken on top of memory Firecracker can't refer to, such as
+        // a snapshot written to file descriptors.
+        assert_eq!(vmm.diff_parent, DiffSnapshotParent::Boot);
+        vmm.diff_parent = DiffSnapshotParent::Unknown;
+        let params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Diff,
+            snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
+            mem_file_path: PathBuf::from("bar"),
+            mem_file_fd: None,
+            compression: None,
+            mem_writer_threads: None,
+            exclude_mmds: false,
+            version: None,
+        };
+        assert!(matches!(
+            create_snapshot(&mut vmm, &params, VERSION_MAP.clone()),
+            Err(CreateSnapshotError::UnknownDiffParent)
+        ));
+
+        // Neither can the memory of full snapshots be streamed by several threads.
+        let (writer, _reader) = stream();
+        let params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
+            mem_file_path: PathBuf::new(),
+            mem_file_fd: Some(writer.into_raw_fd()),
+            compression: None,
+            mem_writer_threads: Some(4),
+            exclude_mmds: false,
+            version: None,
+        };
+        assert!(matches!(
+            create_snapshot(&mut vmm, &params, VERSION_MAP.clone()),
+            Err(CreateSnapshotError::SequentialMemoryFile)
+        ));
+    }
+
     #[test]
     fn test_create_snapshot_error_display() {
         use vm_memory::GuestMemoryError;
@@ -857,6 +2555,24 @@ mod tests {
         let err = SnapshotBackingFile("open", io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = CompressedDiffSnapshot;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = StreamedDiffSnapshot;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidMemWriterThreads(0);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SequentialMemoryFile;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UnknownDiffParent;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = NonTapNetDevice(String::from("eth0"));
+        let _ = format!("{}{:?}", err, err);
+
         #[cfg(target_arch = "x86_64")]
         {
             let err = TooManyDevices(0);
@@ -879,14 +2595,49 @@ mod tests {
         let err = DeserializeMicrovmState(snapshot::Error::Io(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = DeviceOverride(String::new());
+        let _ = format!("{}{:?}", err, err);
+
         let err = MemoryBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = MemoryFileTooSmall(0, 0);
+        let _ = format!("{}{:?}", err, err);
+
         let err = SnapshotBackingFile("open", io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
         let err = CpuVendorCheck(String::new());
         let _ = format!("{}{:?}", err, err);
+
+        let err = LazyResume(uffd_handler::Error::MemoryFileTooSmall);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = TranslateMicrovmState(snapshot_translation::Error::UnsupportedDataVersion(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+
+    #[test]
+    fn test_merge_snapshot_error_display() {
+        use crate::persist::MergeSnapshotError::*;
+
+        let err = ChainLoop(String::new());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = CompressedMemoryFile(String::new());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = LoadMicrovmState(LoadSnapshotError::InvalidSnapshot(String::new()));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = MemoryFile(String::new(), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = OutputMemoryFile(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = OutputInChain(String::new());
+        let _ = format!("{}{:?}", err, err);
     }
 
     #[test]
@@ -2,13 +2,16 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::convert::From;
+use std::net::Ipv4Addr;
 use std::sync::{Arc, Mutex, MutexGuard};
 
+use devices::virtio::{Balloon, Block};
 use logger::info;
 use mmds::data_store::{Mmds, MmdsVersion};
 use mmds::ns::MmdsNetworkStack;
 use serde::{Deserialize, Serialize};
 use utils::net::ipv4addr::is_link_local_valid;
+use utils::net::mac::MacAddr;
 
 use crate::device_manager::persist::SharedDeviceType;
 use crate::vmm_config::balloon::*;
@@ -17,9 +20,12 @@ use crate::vmm_config::drive::*;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::logger::{init_logger, LoggerConfig, LoggerConfigError};
 use crate::vmm_config::machine_config::{VmConfig, VmConfigError, VmUpdateConfig};
+use crate::vmm_config::memory_hotplug::{MemoryHotplugConfig, MemoryHotplugConfigError};
 use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::*;
+use crate::vmm_config::pmem::{PmemBuilder, PmemConfig, PmemConfigError};
+use crate::vmm_config::rate_limiter_group::{RateLimiterGroupBuilder, RateLimiterGroupConfig};
 use crate::vmm_config::vsock::*;
 use crate::vstate::vcpu::VcpuConfig;
 
@@ -38,6 +44,8 @@ pub enum Error {
     InvalidJson(serde_json::Error),
     /// Logger configuration error.
     Logger(LoggerConfigError),
+    /// Hot-pluggable memory configuration error.
+    MemoryHotplug(MemoryHotplugConfigError),
     /// Metrics system configuration error.
     Metrics(MetricsConfigError),
     /// MMDS error.
@@ -46,6 +54,8 @@ pub enum Error {
     MmdsConfig(MmdsConfigError),
     /// Net device configuration error.
     NetDevice(NetworkInterfaceError),
+    /// Pmem device configuration error.
+    PmemDevice(PmemConfigError),
     /// microVM vCpus or memory configuration error.
     VmConfig(VmConfigError),
     /// Vsock device configuration error.
@@ -60,10 +70,12 @@ impl std::fmt::Display for Error {
             Error::BootSource(e) => write!(f, "Boot source error: {}", e),
             Error::InvalidJson(e) => write!(f, "Invalid JSON: {}", e),
             Error::Logger(e) => write!(f, "Logger error: {}", e),
+            Error::MemoryHotplug(e) => write!(f, "Memory hot-plug error: {}", e),
             Error::Metrics(e) => write!(f, "Metrics error: {}", e),
             Error::Mmds(e) => write!(f, "MMDS error: {}", e),
             Error::MmdsConfig(e) => write!(f, "MMDS config error: {}", e),
             Error::NetDevice(e) => write!(f, "Network device error: {}", e),
+            Error::PmemDevice(e) => write!(f, "Pmem device error: {}", e),
             Error::VmConfig(e) => write!(f, "VM config error: {}", e),
             Error::VsockDevice(e) => write!(f, "Vsock device error: {}", e),
         }
@@ -83,14 +95,34 @@ pub struct VmmConfig {
     logger: Option<LoggerConfig>,
     #[serde(rename = "machine-config")]
     machine_config: Option<VmConfig>,
+    #[serde(
+        rename = "memory-hotplug",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    memory_hotplug: Option<MemoryHotplugConfig>,
     #[serde(rename = "metrics")]
     metrics: Option<MetricsConfig>,
     #[serde(rename = "mmds-config")]
     mmds_config: Option<MmdsConfig>,
     #[serde(rename = "network-interfaces", default)]
     net_devices: Vec<NetworkInterfaceConfig>,
+    #[serde(rename = "pmem", default, skip_serializing_if = "Vec::is_empty")]
+    pmem_devices: Vec<PmemConfig>,
+    #[serde(
+        rename = "rate-limiter-groups",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    rate_limiter_groups: Vec<RateLimiterGroupConfig>,
     #[serde(rename = "vsock")]
     vsock_device: Option<VsockDeviceConfig>,
+    #[serde(
+        rename = "vsock-devices",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    vsock_devices: Vec<VsockDeviceConfig>,
 }
 
 /// A data structure that encapsulates the device configurations
@@ -109,6 +141,18 @@ pub struct VmResources {
     pub balloon: BalloonBuilder,
     /// The network devices builder.
     pub net_builder: NetBuilder,
+    /// The pmem devices.
+    pub pmem: PmemBuilder,
+    /// The memory the guest can plug after boot, if any.
+    pub memory_hotplug: Option<MemoryHotplugConfig>,
+    /// The rate limiter groups shared by devices.
+    pub rate_limiter_groups: RateLimiterGroupBuilder,
+    /// The logger configuration, if the logger was configured through the API or the
+    /// configuration file.
+    pub logger: Option<LoggerConfig>,
+    /// The metrics configuration, if the metrics were configured through the API or the
+    /// configuration file.
+    pub metrics: Option<MetricsConfig>,
     /// The optional Mmds data store.
     // This is initialised on demand (if ever used), so that we don't allocate it unless it's
     // actually used.
@@ -130,18 +174,142 @@ impl VmResources {
         let vmm_config: VmmConfig = serde_json::from_slice::<VmmConfig>(config_json.as_bytes())
             .map_err(Error::InvalidJson)?;
 
-        if let Some(logger) = vmm_config.logger {
+        if let Some(logger) = vmm_config.logger.clone() {
             init_logger(logger, instance_info).map_err(Error::Logger)?;
         }
 
-        if let Some(metrics) = vmm_config.metrics {
+        if let Some(metrics) = vmm_config.metrics.clone() {
             init_metrics(metrics).map_err(Error::Metrics)?;
         }
 
         let mut resources: Self = Self {
             mmds_size_limit,
+            logger: vmm_config.logger.clone(),
+            metrics: vmm_config.metrics.clone(),
             ..Default::default()
         };
+        let mmds_config = resources.apply_vmm_config(vmm_config)?;
+
+        // Init the data store from file, if present.
+        if let Some(data) = metadata_json {
+            resources
+                .locked_mmds_or_default()
+                .put_data(
+                    serde_json::from_str(&data)
+                        .expect("MMDS error: metadata provided not valid json"),
+                )
+                .map_err(Error::Mmds)?;
+            info!("Successfully added metadata to mmds from file");
+        }
+
+        if let Some(mmds_config) = mmds_config {
+            resources
+                .set_mmds_config(mmds_config, &instance_info.id)
+                .map_err(Error::MmdsConfig)?;
+        }
+
+        Ok(resources)
+    }
+
+    /// Replaces the whole configuration with `vmm_config`, which follows the schema of the
+    /// configuration file. Either all its sections are applied, or the current configuration is
+    /// left untouched.
+    ///
+    /// The MMDS contents aren't part of the configuration, so they are kept. The logger and the
+    /// metrics can't be reconfigured once initialized, so they are set up last, and aren't
+    /// reverted if initializing the metrics fails after the logger.
+    pub fn replace_config(
+        &mut self,
+        vmm_config: VmmConfig,
+        instance_info: &InstanceInfo,
+    ) -> std::result::Result<(), Error> {
+        let logger = vmm_config.logger.clone();
+        let metrics = vmm_config.metrics.clone();
+        let mut resources: Self = Self {
+            mmds_size_limit: self.mmds_size_limit,
+            boot_timer: self.boot_timer,
+            logger: self.logger.clone(),
+            metrics: self.metrics.clone(),
+            ..Default::default()
+        };
+        let mmds_config = resources.apply_vmm_config(vmm_config)?;
+
+        // Nothing is changed on the data store unless the whole MMDS configuration is valid.
+        resources.mmds = self.mmds.clone();
+        if let Some(mmds_config) = mmds_config {
+            resources
+                .set_mmds_config(mmds_config, &instance_info.id)
+                .map_err(Error::MmdsConfig)?;
+        }
+
+        if let Some(logger) = logger {
+            init_logger(logger.clone(), instance_info).map_err(Error::Logger)?;
+            resources.logger = Some(logger);
+        }
+        if let Some(metrics) = metrics {
+            init_metrics(metrics.clone()).map_err(Error::Metrics)?;
+            resources.metrics = Some(metrics);
+        }
+
+        *self = resources;
+        Ok(())
+    }
+
+    /// Checks whether `vmm_config` could replace the whole configuration, without changing
+    /// anything nor opening the host interfaces of the network devices.
+    ///
+    /// The logger and metrics sections are only deserialized, since their files are opened when
+    /// they get initialized.
+    pub fn validate_config(&self, mut vmm_config: VmmConfig) -> std::result::Result<(), Error> {
+        let net_configs = std::mem::take(&mut vmm_config.net_devices);
+        let mmds_config = vmm_config.mmds_config.take();
+        let mut resources: Self = Self {
+            mmds_size_limit: self.mmds_size_limit,
+            ..Default::default()
+        };
+        resources.apply_vmm_config(vmm_config)?;
+
+        for net_config in net_configs.iter() {
+            resources
+                .validate_net_device(net_config)
+                .map_err(Error::NetDevice)?;
+        }
+        NetBuilder::validate_configs(&net_configs).map_err(Error::NetDevice)?;
+
+        if let Some(mmds_config) = mmds_config {
+            validate_mmds_addresses(&mmds_config).map_err(Error::MmdsConfig)?;
+            for iface_id in mmds_config.network_interfaces().iter() {
+                // The last entry with a given ID is the one which ends up configured.
+                match net_configs
+                    .iter()
+                    .rev()
+                    .find(|cfg| &cfg.iface_id == iface_id)
+                {
+                    None => {
+                        return Err(Error::MmdsConfig(
+                            MmdsConfigError::InvalidNetworkInterfaceId,
+                        ))
+                    }
+                    Some(cfg) if cfg.backend == NetDatapath::Vhost => {
+                        return Err(Error::MmdsConfig(MmdsConfigError::VhostNetworkInterface(
+                            iface_id.clone(),
+                        )))
+                    }
+                    Some(_) => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Applies the machine and devices sections of `vmm_config`, and returns its MMDS section,
+    // which has to be applied once the data store is set up.
+    fn apply_vmm_config(
+        &mut self,
+        vmm_config: VmmConfig,
+    ) -> std::result::Result<Option<MmdsConfig>, Error> {
+        let resources = self;
         if let Some(machine_config) = vmm_config.machine_config {
             let machine_config = VmUpdateConfig::from(machine_config);
             resources
@@ -153,6 +321,11 @@ impl VmResources {
             .set_boot_source(vmm_config.boot_source)
             .map_err(Error::BootSource)?;
 
+        // The groups have to exist before the devices referencing them.
+        for group_config in vmm_config.rate_limiter_groups.into_iter() {
+            resources.set_rate_limiter_group(group_config);
+        }
+
         for drive_config in vmm_config.block_devices.into_iter() {
             resources
                 .set_block_device(drive_config)
@@ -165,37 +338,39 @@ impl VmResources {
                 .map_err(Error::NetDevice)?;
         }
 
-        if let Some(vsock_config) = vmm_config.vsock_device {
+        for pmem_config in vmm_config.pmem_devices.into_iter() {
             resources
-                .set_vsock_device(vsock_config)
-                .map_err(Error::VsockDevice)?;
+                .set_pmem_device(pmem_config)
+                .map_err(Error::PmemDevice)?;
         }
 
-        if let Some(balloon_config) = vmm_config.balloon_device {
+        if let Some(memory_hotplug_config) = vmm_config.memory_hotplug {
             resources
-                .set_balloon_device(balloon_config)
-                .map_err(Error::BalloonDevice)?;
+                .set_memory_hotplug_config(memory_hotplug_config)
+                .map_err(Error::MemoryHotplug)?;
         }
 
-        // Init the data store from file, if present.
-        if let Some(data) = metadata_json {
+        if let Some(mut vsock_config) = vmm_config.vsock_device {
+            // As through the API, the deprecated ID of the default device is ignored.
+            vsock_config.vsock_id = None;
             resources
-                .locked_mmds_or_default()
-                .put_data(
-                    serde_json::from_str(&data)
-                        .expect("MMDS error: metadata provided not valid json"),
-                )
-                .map_err(Error::Mmds)?;
-            info!("Successfully added metadata to mmds from file");
+                .set_vsock_device(vsock_config)
+                .map_err(Error::VsockDevice)?;
         }
 
-        if let Some(mmds_config) = vmm_config.mmds_config {
+        for vsock_config in vmm_config.vsock_devices.into_iter() {
             resources
-                .set_mmds_config(mmds_config, &instance_info.id)
-                .map_err(Error::MmdsConfig)?;
+                .set_vsock_device(vsock_config)
+                .map_err(Error::VsockDevice)?;
         }
 
-        Ok(resources)
+        if let Some(balloon_config) = vmm_config.balloon_device {
+            resources
+                .set_balloon_device(balloon_config)
+                .map_err(Error::BalloonDevice)?;
+        }
+
+        Ok(vmm_config.mmds_config)
     }
 
     /// If not initialised, create the mmds data store with the default config.
@@ -260,8 +435,9 @@ impl VmResources {
         &self.vm_config
     }
 
-    /// Update the machine configuration of the microVM.
-    pub fn update_vm_config(&mut self, machine_config: &VmUpdateConfig) -> Result<VmConfigError> {
+    /// Checks whether the machine configuration of the microVM could be updated as described
+    /// by `machine_config`, without updating it.
+    pub fn validate_vm_config(&self, machine_config: &VmUpdateConfig) -> Result<VmConfigError> {
         let vcpu_count = machine_config
             .vcpu_count
             .unwrap_or(self.vm_config.vcpu_count);
@@ -278,9 +454,6 @@ impl VmResources {
             return Err(VmConfigError::InvalidVcpuCount);
         }
 
-        self.vm_config.vcpu_count = vcpu_count;
-        self.vm_config.smt = smt;
-
         let mem_size_mib = machine_config
             .mem_size_mib
             .unwrap_or(self.vm_config.mem_size_mib);
@@ -289,6 +462,12 @@ impl VmResources {
             return Err(VmConfigError::InvalidMemorySize);
         }
 
+        machine_config
+            .mem_backend
+            .as_ref()
+            .unwrap_or(&self.vm_config.mem_backend)
+            .validate(mem_size_mib << 20)?;
+
         // The VM cannot have a memory size smaller than the target size
         // of the balloon device, if present.
         if self.balloon.get().is_some()
@@ -302,7 +481,24 @@ impl VmResources {
             return Err(VmConfigError::IncompatibleBalloonSize);
         }
 
-        self.vm_config.mem_size_mib = mem_size_mib;
+        Ok(())
+    }
+
+    /// Update the machine configuration of the microVM.
+    pub fn update_vm_config(&mut self, machine_config: &VmUpdateConfig) -> Result<VmConfigError> {
+        self.validate_vm_config(machine_config)?;
+
+        if let Some(vcpu_count) = machine_config.vcpu_count {
+            self.vm_config.vcpu_count = vcpu_count;
+        }
+
+        if let Some(smt) = machine_config.smt {
+            self.vm_config.smt = smt;
+        }
+
+        if let Some(mem_size_mib) = machine_config.mem_size_mib {
+            self.vm_config.mem_size_mib = mem_size_mib;
+        }
 
         // Update the CPU template
         if let Some(cpu_template) = machine_config.cpu_template {
@@ -314,6 +510,10 @@ impl VmResources {
             self.vm_config.track_dirty_pages = track_dirty_pages;
         }
 
+        if let Some(mem_backend) = machine_config.mem_backend.as_ref() {
+            self.vm_config.mem_backend = mem_backend.clone();
+        }
+
         Ok(())
     }
 
@@ -336,6 +536,7 @@ impl VmResources {
                 version: mmds.lock().expect("Poisoned lock").version(),
                 network_interfaces: vec![],
                 ipv4_address: None,
+                ipv6_address: None,
             };
 
             for net_dev in net_devs_with_mmds {
@@ -346,6 +547,7 @@ impl VmResources {
                     // Safe to unwrap the mmds_ns as the filter() explicitly checks for
                     // its existence.
                     inner_mmds_config.ipv4_address = Some(net.mmds_ns().unwrap().ipv4_addr());
+                    inner_mmds_config.ipv6_address = net.mmds_ns().unwrap().ipv6_addr();
                 }
             }
 
@@ -364,14 +566,49 @@ impl VmResources {
     pub fn set_balloon_device(
         &mut self,
         config: BalloonDeviceConfig,
+    ) -> Result<BalloonConfigError> {
+        self.validate_balloon_device(&config)?;
+        self.balloon.set(config)
+    }
+
+    /// Sets a balloon device to be attached to the running microVM, and returns it. Unlike
+    /// before boot, the balloon device can't be replaced once attached.
+    pub fn hotplug_balloon_device(
+        &mut self,
+        config: BalloonDeviceConfig,
+    ) -> std::result::Result<Arc<Mutex<Balloon>>, BalloonConfigError> {
+        self.validate_balloon_device_hotplug(&config)?;
+        self.balloon.set(config)?;
+        Ok(self
+            .balloon
+            .get()
+            .expect("The inserted balloon device is missing")
+            .clone())
+    }
+
+    /// Checks whether a balloon device could be attached to the running microVM using
+    /// `config`, without setting it.
+    pub fn validate_balloon_device_hotplug(
+        &self,
+        config: &BalloonDeviceConfig,
+    ) -> Result<BalloonConfigError> {
+        if self.balloon.get().is_some() {
+            return Err(BalloonConfigError::DeviceAlreadyAttached);
+        }
+        self.validate_balloon_device(config)
+    }
+
+    /// Checks whether the balloon device could be set using `config`, without setting it.
+    pub fn validate_balloon_device(
+        &self,
+        config: &BalloonDeviceConfig,
     ) -> Result<BalloonConfigError> {
         // The balloon cannot have a target size greater than the size of
         // the guest memory.
         if config.amount_mib as usize > self.vm_config.mem_size_mib {
             return Err(BalloonConfigError::TooManyPagesRequested);
         }
-
-        self.balloon.set(config)
+        Ok(())
     }
 
     /// Set the guest boot source configuration.
@@ -390,7 +627,108 @@ impl VmResources {
         &mut self,
         block_device_config: BlockDeviceConfig,
     ) -> Result<DriveError> {
-        self.block.insert(block_device_config)
+        self.validate_block_device_rl_group(&block_device_config)?;
+        let drive_id = block_device_config.drive_id.clone();
+        let group = block_device_config
+            .rl_group
+            .as_deref()
+            .and_then(|group_id| self.rate_limiter_groups.get(group_id))
+            .cloned();
+        self.block.insert(block_device_config)?;
+
+        if let Some(block) = self
+            .block
+            .list
+            .iter()
+            .find(|block| block.lock().expect("Poisoned lock").id() == &drive_id)
+        {
+            block
+                .lock()
+                .expect("Poisoned lock")
+                .set_rate_limiter_group(group);
+        }
+        Ok(())
+    }
+
+    /// Checks whether a block device could be inserted using `block_device_config`, without
+    /// inserting it.
+    pub fn validate_block_device(
+        &self,
+        block_device_config: &BlockDeviceConfig,
+    ) -> Result<DriveError> {
+        self.validate_block_device_rl_group(block_device_config)?;
+        self.block.validate(block_device_config)
+    }
+
+    /// Inserts a block to be attached to the running microVM, and returns it. Unlike before
+    /// boot, the drives which are already attached can't be replaced.
+    pub fn hotplug_block_device(
+        &mut self,
+        block_device_config: BlockDeviceConfig,
+    ) -> std::result::Result<Arc<Mutex<Block>>, DriveError> {
+        self.validate_block_device_hotplug(&block_device_config)?;
+        let drive_id = block_device_config.drive_id.clone();
+        self.set_block_device(block_device_config)?;
+        Ok(self
+            .block
+            .list
+            .iter()
+            .find(|block| block.lock().expect("Poisoned lock").id() == &drive_id)
+            .expect("The inserted drive is missing")
+            .clone())
+    }
+
+    /// Checks whether a block device could be attached to the running microVM using
+    /// `block_device_config`, without inserting it.
+    pub fn validate_block_device_hotplug(
+        &self,
+        block_device_config: &BlockDeviceConfig,
+    ) -> Result<DriveError> {
+        let drive_id = &block_device_config.drive_id;
+        if self
+            .block
+            .list
+            .iter()
+            .any(|block| block.lock().expect("Poisoned lock").id() == drive_id)
+        {
+            return Err(DriveError::DriveAlreadyAttached(drive_id.clone()));
+        }
+        // The guest memory may not be shared with the backend.
+        if block_device_config.vhost_user_socket.is_some() {
+            return Err(DriveError::VhostUserHotplug);
+        }
+        self.validate_block_device(block_device_config)
+    }
+
+    /// Removes a block device, so that it isn't attached when the VM starts.
+    pub fn remove_block_device(&mut self, drive_id: &str) -> Result<DriveError> {
+        let _ = self.block.remove(drive_id)?;
+        Ok(())
+    }
+
+    /// Checks whether the block device with id `drive_id` could be removed, without removing it.
+    pub fn validate_block_device_removal(&self, drive_id: &str) -> Result<DriveError> {
+        if !self
+            .block
+            .list
+            .iter()
+            .any(|block| block.lock().expect("Poisoned lock").id() == drive_id)
+        {
+            return Err(DriveError::DriveNotFound(drive_id.to_string()));
+        }
+        Ok(())
+    }
+
+    fn validate_block_device_rl_group(
+        &self,
+        block_device_config: &BlockDeviceConfig,
+    ) -> Result<DriveError> {
+        match block_device_config.rl_group.as_deref() {
+            Some(group_id) if self.rate_limiter_groups.get(group_id).is_none() => {
+                Err(DriveError::RateLimiterGroupNotFound(group_id.to_string()))
+            }
+            _ => Ok(()),
+        }
     }
 
     /// Builds a network device to be attached when the VM starts.
@@ -398,7 +736,102 @@ impl VmResources {
         &mut self,
         body: NetworkInterfaceConfig,
     ) -> Result<NetworkInterfaceError> {
-        let _ = self.net_builder.build(body)?;
+        self.validate_net_device_rl_group(&body)?;
+        let group = body
+            .rl_group
+            .as_deref()
+            .and_then(|group_id| self.rate_limiter_groups.get(group_id))
+            .cloned();
+        let net = self.net_builder.build(body)?;
+        net.lock()
+            .expect("Poisoned lock")
+            .set_rate_limiter_group(group);
+        Ok(())
+    }
+
+    /// Checks whether a network device could be built using `body`, without building it.
+    pub fn validate_net_device(
+        &self,
+        body: &NetworkInterfaceConfig,
+    ) -> Result<NetworkInterfaceError> {
+        self.validate_net_device_rl_group(body)?;
+        self.net_builder.validate(body)
+    }
+
+    fn validate_net_device_rl_group(
+        &self,
+        body: &NetworkInterfaceConfig,
+    ) -> Result<NetworkInterfaceError> {
+        match body.rl_group.as_deref() {
+            Some(group_id) if self.rate_limiter_groups.get(group_id).is_none() => Err(
+                NetworkInterfaceError::RateLimiterGroupNotFound(group_id.to_string()),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds a rate limiter group which devices can be made members of, or updates the one
+    /// that already exists.
+    pub fn set_rate_limiter_group(&mut self, config: RateLimiterGroupConfig) {
+        self.rate_limiter_groups.insert(config);
+    }
+
+    /// Starts or stops the trace of the requests of a block device.
+    pub fn set_block_trace(&mut self, config: &DriveTraceConfig) -> Result<DriveError> {
+        self.block.set_trace(config)
+    }
+
+    /// Starts or stops the capture of the frames of a network device.
+    pub fn set_net_capture(
+        &mut self,
+        config: &NetworkCaptureConfig,
+    ) -> Result<NetworkInterfaceError> {
+        self.net_builder.set_capture(config)
+    }
+
+    /// Checks that no network device other than `iface_id` uses `guest_mac`.
+    pub fn validate_net_guest_mac(
+        &self,
+        iface_id: &str,
+        guest_mac: &MacAddr,
+    ) -> Result<NetworkInterfaceError> {
+        self.net_builder.validate_guest_mac(iface_id, guest_mac)
+    }
+
+    /// Removes a network device, so that it isn't attached when the VM starts.
+    pub fn remove_net_device(&mut self, iface_id: &str) -> Result<NetworkInterfaceError> {
+        let _ = self.net_builder.remove(iface_id)?;
+        Ok(())
+    }
+
+    /// Checks whether the network device with id `iface_id` could be removed, without
+    /// removing it.
+    pub fn validate_net_device_removal(&self, iface_id: &str) -> Result<NetworkInterfaceError> {
+        if !self
+            .net_builder
+            .iter()
+            .any(|net| net.lock().expect("Poisoned lock").id() == iface_id)
+        {
+            return Err(NetworkInterfaceError::InterfaceNotFound(
+                iface_id.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds a pmem device to be attached when the VM starts, or replaces the one with the
+    /// same id.
+    pub fn set_pmem_device(&mut self, config: PmemConfig) -> Result<PmemConfigError> {
+        self.pmem.insert(config)
+    }
+
+    /// Sets the memory the guest can plug after boot, replacing the one that was configured.
+    pub fn set_memory_hotplug_config(
+        &mut self,
+        config: MemoryHotplugConfig,
+    ) -> Result<MemoryHotplugConfigError> {
+        config.validate()?;
+        self.memory_hotplug = Some(config);
         Ok(())
     }
 
@@ -437,18 +870,9 @@ impl VmResources {
     // Updates MMDS Network Stack for network interfaces to allow forwarding
     // requests to MMDS (or not).
     fn set_mmds_network_stack_config(&mut self, config: &MmdsConfig) -> Result<MmdsConfigError> {
-        // Check IPv4 address validity.
-        let ipv4_addr = match config.ipv4_addr() {
-            Some(ipv4_addr) if is_link_local_valid(ipv4_addr) => Ok(ipv4_addr),
-            None => Ok(MmdsNetworkStack::default_ipv4_addr()),
-            _ => Err(MmdsConfigError::InvalidIpv4Addr),
-        }?;
-
+        let ipv4_addr = validate_mmds_addresses(config)?;
+        let ipv6_addr = config.ipv6_addr();
         let network_interfaces = config.network_interfaces();
-        // Ensure that at least one network ID is specified.
-        if network_interfaces.is_empty() {
-            return Err(MmdsConfigError::EmptyNetworkIfaceList);
-        }
 
         // Ensure all interface IDs specified correspond to existing net devices.
         if !network_interfaces.iter().all(|id| {
@@ -460,16 +884,26 @@ impl VmResources {
             return Err(MmdsConfigError::InvalidNetworkInterfaceId);
         }
 
+        // MMDS requests are intercepted by the device model, which vhost bypasses.
+        if let Some(net_device) = self.net_builder.iter().find(|device| {
+            let device = device.lock().expect("Poisoned lock");
+            device.uses_vhost() && network_interfaces.contains(device.id())
+        }) {
+            return Err(MmdsConfigError::VhostNetworkInterface(
+                net_device.lock().expect("Poisoned lock").id().clone(),
+            ));
+        }
+
         // Safe to unwrap because we've just made sure that it's initialised.
         let mmds = self.mmds_or_default().clone();
 
-        // Create `MmdsNetworkStack` and configure the IPv4 address for
+        // Create `MmdsNetworkStack` and configure the IP addresses for
         // existing built network devices whose names are defined in the
         // network interface ID list.
         for net_device in self.net_builder.iter_mut() {
             let mut net_device_lock = net_device.lock().expect("Poisoned lock");
             if network_interfaces.contains(net_device_lock.id()) {
-                net_device_lock.configure_mmds_network_stack(ipv4_addr, mmds.clone());
+                net_device_lock.configure_mmds_network_stack(ipv4_addr, ipv6_addr, mmds.clone());
             } else {
                 net_device_lock.disable_mmds_network_stack();
             }
@@ -479,6 +913,31 @@ impl VmResources {
     }
 }
 
+// Checks the addresses and the presence of network interfaces in `config`, and returns the IPv4
+// address of MMDS.
+fn validate_mmds_addresses(config: &MmdsConfig) -> std::result::Result<Ipv4Addr, MmdsConfigError> {
+    // Check IPv4 address validity.
+    let ipv4_addr = match config.ipv4_addr() {
+        Some(ipv4_addr) if is_link_local_valid(ipv4_addr) => Ok(ipv4_addr),
+        None => Ok(MmdsNetworkStack::default_ipv4_addr()),
+        _ => Err(MmdsConfigError::InvalidIpv4Addr),
+    }?;
+
+    // Check IPv6 address validity.
+    if let Some(addr) = config.ipv6_addr() {
+        if addr.is_unspecified() || addr.is_loopback() || addr.is_multicast() {
+            return Err(MmdsConfigError::InvalidIpv6Addr);
+        }
+    }
+
+    // Ensure that at least one network ID is specified.
+    if config.network_interfaces().is_empty() {
+        return Err(MmdsConfigError::EmptyNetworkIfaceList);
+    }
+
+    Ok(ipv4_addr)
+}
+
 impl From<&VmResources> for VmmConfig {
     fn from(resources: &VmResources) -> Self {
         let boot_source = resources
@@ -491,12 +950,16 @@ impl From<&VmResources> for VmmConfig {
             balloon_device: resources.balloon.get_config().ok(),
             block_devices: resources.block.configs(),
             boot_source,
-            logger: None,
+            logger: resources.logger.clone(),
             machine_config: Some(resources.vm_config.clone()),
-            metrics: None,
+            memory_hotplug: resources.memory_hotplug.clone(),
+            metrics: resources.metrics.clone(),
             mmds_config: resources.mmds_config(),
             net_devices: resources.net_builder.configs(),
+            pmem_devices: resources.pmem.configs(),
+            rate_limiter_groups: resources.rate_limiter_groups.configs(),
             vsock_device: resources.vsock.config(),
+            vsock_devices: resources.vsock.other_configs(),
         }
     }
 }
@@ -504,7 +967,9 @@ impl From<&VmResources> for VmmConfig {
 #[cfg(test)]
 mod tests {
     use std::fs::File;
+    use std::net::Ipv6Addr;
     use std::os::linux::fs::MetadataExt;
+    use std::path::PathBuf;
 
     use devices::virtio::vsock::{VsockError, VSOCK_DEV_ID};
     use logger::{LevelFilter, LOGGER};
@@ -515,9 +980,14 @@ mod tests {
     use super::*;
     use crate::resources::VmResources;
     use crate::vmm_config::boot_source::{BootConfig, BootSourceConfig, DEFAULT_KERNEL_CMDLINE};
-    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, FileEngineType};
-    use crate::vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig, VmConfigError};
-    use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, FileEngineType, ImageFormat};
+    use crate::vmm_config::logger::LoggerLevel;
+    use crate::vmm_config::machine_config::{
+        CpuFeaturesTemplate, GuestMemoryBackend, VmConfig, VmConfigError,
+    };
+    use crate::vmm_config::net::{
+        NetBackendType, NetBuilder, NetDatapath, NetOffloads, NetworkInterfaceConfig,
+    };
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
     use crate::vstate::vcpu::VcpuConfig;
@@ -537,6 +1007,18 @@ mod tests {
             guest_mac: Some(MacAddr::parse_str("01:23:45:67:89:0a").unwrap()),
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
         }
     }
 
@@ -553,12 +1035,21 @@ mod tests {
             BlockDeviceConfig {
                 drive_id: "block1".to_string(),
                 path_on_host: tmp_file.as_path().to_str().unwrap().to_string(),
+                fd: None,
+                serial: None,
                 is_root_device: false,
                 partuuid: Some("0eaa91a0-01".to_string()),
                 cache_type: CacheType::Unsafe,
                 is_read_only: false,
                 rate_limiter: Some(RateLimiterConfig::default()),
+                read_rate_limiter: None,
+                write_rate_limiter: None,
                 file_engine_type: FileEngineType::default(),
+                image_format: ImageFormat::default(),
+                overlay: None,
+                rl_group: None,
+                vhost_user_socket: None,
+                num_queues: None,
             },
             tmp_file,
         )
@@ -591,6 +1082,11 @@ mod tests {
             vsock: Default::default(),
             balloon: Default::default(),
             net_builder: default_net_builder(),
+            pmem: Default::default(),
+            memory_hotplug: None,
+            rate_limiter_groups: Default::default(),
+            logger: None,
+            metrics: None,
             mmds: None,
             boot_timer: false,
             mmds_size_limit: HTTP_MAX_PAYLOAD_SIZE,
@@ -1290,6 +1786,7 @@ mod tests {
             smt: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
             track_dirty_pages: Some(false),
+            mem_backend: Some(GuestMemoryBackend::Anonymous),
         };
 
         assert_ne!(
@@ -1321,6 +1818,19 @@ mod tests {
             vm_resources.update_vm_config(&aux_vm_config),
             Err(VmConfigError::InvalidMemorySize)
         );
+        aux_vm_config.mem_size_mib = Some(512);
+
+        // Invalid memory backend.
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        aux_vm_config.mem_backend = Some(GuestMemoryBackend::Hugetlbfs {
+            path: tmp_dir.as_path().to_path_buf(),
+        });
+        assert!(matches!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMemoryBackend(_))
+        ));
+        assert!(vm_resources.vm_config.mem_backend.is_anonymous());
+        aux_vm_config.mem_backend = None;
 
         // Incompatible mem_size_mib with balloon size.
         vm_resources.vm_config.mem_size_mib = 128;
@@ -1329,6 +1839,7 @@ mod tests {
                 amount_mib: 100,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 0,
+                free_page_reporting: false,
             })
             .unwrap();
         aux_vm_config.mem_size_mib = Some(90);
@@ -1342,6 +1853,49 @@ mod tests {
         assert!(vm_resources.update_vm_config(&aux_vm_config).is_ok());
     }
 
+    #[test]
+    fn test_validate_vm_config() {
+        let mut vm_resources = default_vm_resources();
+        let initial_vm_config = vm_resources.vm_config.clone();
+        let mut aux_vm_config = VmUpdateConfig {
+            vcpu_count: Some(32),
+            mem_size_mib: Some(512),
+            smt: Some(true),
+            cpu_template: Some(CpuFeaturesTemplate::T2),
+            track_dirty_pages: Some(false),
+            mem_backend: None,
+        };
+
+        // A valid config is not applied.
+        assert!(vm_resources.validate_vm_config(&aux_vm_config).is_ok());
+        assert_eq!(vm_resources.vm_config, initial_vm_config);
+
+        // An invalid config doesn't get partially applied either.
+        aux_vm_config.mem_size_mib = Some(0);
+        assert_eq!(
+            vm_resources.validate_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMemorySize)
+        );
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMemorySize)
+        );
+        assert_eq!(vm_resources.vm_config, initial_vm_config);
+
+        // The balloon device is validated against the memory size.
+        let balloon_cfg = BalloonDeviceConfig {
+            amount_mib: vm_resources.vm_config.mem_size_mib as u32 + 1,
+            deflate_on_oom: false,
+            stats_polling_interval_s: 0,
+            free_page_reporting: false,
+        };
+        assert!(matches!(
+            vm_resources.validate_balloon_device(&balloon_cfg),
+            Err(BalloonConfigError::TooManyPagesRequested)
+        ));
+        assert!(vm_resources.balloon.get().is_none());
+    }
+
     #[test]
     fn test_set_balloon_device() {
         let mut vm_resources = default_vm_resources();
@@ -1350,6 +1904,7 @@ mod tests {
             amount_mib: 100,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
         assert!(vm_resources.balloon.get().is_none());
         vm_resources
@@ -1373,6 +1928,49 @@ mod tests {
         assert!(vm_resources.set_balloon_device(new_balloon_cfg).is_err());
     }
 
+    #[test]
+    fn test_hotplug_balloon_device() {
+        let mut vm_resources = default_vm_resources();
+        vm_resources.balloon = BalloonBuilder::new();
+        let balloon_cfg = BalloonDeviceConfig {
+            amount_mib: 100,
+            deflate_on_oom: true,
+            stats_polling_interval_s: 1,
+            free_page_reporting: false,
+        };
+
+        vm_resources
+            .validate_balloon_device_hotplug(&balloon_cfg)
+            .unwrap();
+        let balloon = vm_resources
+            .hotplug_balloon_device(balloon_cfg.clone())
+            .unwrap();
+        assert!(Arc::ptr_eq(&balloon, vm_resources.balloon.get().unwrap()));
+        assert_eq!(vm_resources.balloon.get_config().unwrap(), balloon_cfg);
+
+        // The balloon device can't be replaced once attached.
+        assert!(matches!(
+            vm_resources.validate_balloon_device_hotplug(&balloon_cfg),
+            Err(BalloonConfigError::DeviceAlreadyAttached)
+        ));
+        assert!(matches!(
+            vm_resources.hotplug_balloon_device(balloon_cfg.clone()),
+            Err(BalloonConfigError::DeviceAlreadyAttached)
+        ));
+
+        // The target size can't exceed the guest memory.
+        vm_resources.balloon.clear();
+        let balloon_cfg = BalloonDeviceConfig {
+            amount_mib: 256,
+            ..balloon_cfg
+        };
+        assert!(matches!(
+            vm_resources.hotplug_balloon_device(balloon_cfg),
+            Err(BalloonConfigError::TooManyPagesRequested)
+        ));
+        assert!(vm_resources.balloon.get().is_none());
+    }
+
     #[test]
     fn test_boot_config() {
         let vm_resources = default_vm_resources();
@@ -1437,18 +2035,280 @@ mod tests {
         assert_eq!(vm_resources.block.list.len(), 2);
     }
 
+    #[test]
+    fn test_set_block_device_rl_group() {
+        let mut vm_resources = default_vm_resources();
+        let (mut block_cfg, _file) = default_block_cfg();
+        block_cfg.rl_group = Some("tenant-a".to_string());
+        assert!(matches!(
+            vm_resources.set_block_device(block_cfg.clone()),
+            Err(DriveError::RateLimiterGroupNotFound(group_id)) if group_id == "tenant-a"
+        ));
+
+        vm_resources.set_rate_limiter_group(RateLimiterGroupConfig {
+            group_id: "tenant-a".to_string(),
+            bandwidth: None,
+            ops: None,
+        });
+        vm_resources.validate_block_device(&block_cfg).unwrap();
+        let drive_id = block_cfg.drive_id.clone();
+        vm_resources.set_block_device(block_cfg).unwrap();
+        let block = vm_resources
+            .block
+            .list
+            .iter()
+            .find(|block| block.lock().unwrap().id() == &drive_id)
+            .unwrap()
+            .lock()
+            .unwrap();
+        assert_eq!(block.rate_limiter().group().unwrap().id(), "tenant-a");
+    }
+
     #[test]
     fn test_set_vsock_device() {
         let mut vm_resources = default_vm_resources();
         let mut tmp_sock_file = TempFile::new().unwrap();
         tmp_sock_file.remove().unwrap();
         let new_vsock_cfg = default_config(&tmp_sock_file);
-        assert!(vm_resources.vsock.get().is_none());
+        assert!(vm_resources.vsock.get(VSOCK_DEV_ID).is_none());
         vm_resources.set_vsock_device(new_vsock_cfg).unwrap();
-        let actual_vsock_cfg = vm_resources.vsock.get().unwrap();
+        let actual_vsock_cfg = vm_resources.vsock.get(VSOCK_DEV_ID).unwrap();
         assert_eq!(actual_vsock_cfg.lock().unwrap().id(), VSOCK_DEV_ID);
     }
 
+    #[test]
+    fn test_set_pmem_device() {
+        let mut vm_resources = default_vm_resources();
+        let backing_file = TempFile::new().unwrap();
+        backing_file
+            .as_file()
+            .set_len(devices::virtio::pmem::PMEM_ALIGNMENT)
+            .unwrap();
+        let pmem_config = PmemConfig {
+            pmem_id: "pmem0".to_string(),
+            path_on_host: backing_file.as_path().to_str().unwrap().to_string(),
+            is_read_only: true,
+        };
+        assert!(vm_resources.pmem.list.is_empty());
+        vm_resources.set_pmem_device(pmem_config.clone()).unwrap();
+        assert_eq!(vm_resources.pmem.configs(), vec![pmem_config.clone()]);
+        assert_eq!(
+            VmmConfig::from(&vm_resources).pmem_devices,
+            vec![pmem_config]
+        );
+    }
+
+    #[test]
+    fn test_replace_config() {
+        let kernel_file = TempFile::new().unwrap();
+        let rootfs_file = TempFile::new().unwrap();
+        let instance_info = InstanceInfo::default();
+        let mut vm_resources = default_vm_resources();
+        vm_resources
+            .locked_mmds_or_default()
+            .put_data(serde_json::json!({"foo": "bar"}))
+            .unwrap();
+        let initial_config = VmmConfig::from(&vm_resources);
+
+        // An invalid section leaves the whole configuration untouched.
+        let json = format!(
+            r#"{{
+                "boot-source": {{
+                    "kernel_image_path": "{}"
+                }},
+                "drives": [
+                    {{
+                        "drive_id": "rootfs",
+                        "path_on_host": "/invalid/path",
+                        "is_root_device": true,
+                        "is_read_only": false
+                    }}
+                ]
+            }}"#,
+            kernel_file.as_path().to_str().unwrap()
+        );
+        let vmm_config = serde_json::from_str::<VmmConfig>(&json).unwrap();
+        assert!(matches!(
+            vm_resources.replace_config(vmm_config, &instance_info),
+            Err(Error::BlockDevice(_))
+        ));
+        assert_eq!(VmmConfig::from(&vm_resources), initial_config);
+
+        let json = format!(
+            r#"{{
+                "boot-source": {{
+                    "kernel_image_path": "{}"
+                }},
+                "drives": [
+                    {{
+                        "drive_id": "rootfs",
+                        "path_on_host": "{}",
+                        "is_root_device": true,
+                        "is_read_only": true
+                    }}
+                ],
+                "machine-config": {{
+                    "vcpu_count": 4,
+                    "mem_size_mib": 256
+                }}
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap()
+        );
+        let vmm_config = serde_json::from_str::<VmmConfig>(&json).unwrap();
+        vm_resources
+            .replace_config(vmm_config, &instance_info)
+            .unwrap();
+        assert_eq!(vm_resources.vm_config().vcpu_count, 4);
+        assert_eq!(vm_resources.block.list.len(), 1);
+        assert!(vm_resources.block.list[0].lock().unwrap().is_read_only());
+        // The interfaces which weren't part of the new configuration are gone.
+        assert!(vm_resources.net_builder.configs().is_empty());
+        // The MMDS contents are kept.
+        assert_eq!(
+            vm_resources.locked_mmds_or_default().data_store_value(),
+            serde_json::json!({"foo": "bar"})
+        );
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let kernel_file = TempFile::new().unwrap();
+        let vm_resources = default_vm_resources();
+        let initial_config = VmmConfig::from(&vm_resources);
+        let config_with = |sections: &str| {
+            let json = format!(
+                r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}"
+                    }}{}
+                }}"#,
+                kernel_file.as_path().to_str().unwrap(),
+                sections
+            );
+            serde_json::from_str::<VmmConfig>(&json).unwrap()
+        };
+
+        let network_interfaces = r#",
+            "network-interfaces": [
+                {
+                    "iface_id": "netif1",
+                    "host_dev_name": "hostname1",
+                    "guest_mac": "01:23:45:67:89:0a"
+                },
+                {
+                    "iface_id": "netif2",
+                    "host_dev_name": "hostname2",
+                    "guest_mac": "01:23:45:67:89:0b"
+                }
+            ]"#;
+        vm_resources
+            .validate_config(config_with(network_interfaces))
+            .unwrap();
+        // Nothing is changed.
+        assert_eq!(VmmConfig::from(&vm_resources), initial_config);
+
+        let mac_conflict = network_interfaces.replace("89:0b", "89:0a");
+        assert!(matches!(
+            vm_resources.validate_config(config_with(&mac_conflict)),
+            Err(Error::NetDevice(
+                NetworkInterfaceError::GuestMacAddressInUse(_)
+            ))
+        ));
+        let host_dev_conflict = network_interfaces.replace("hostname2", "hostname1");
+        assert!(matches!(
+            vm_resources.validate_config(config_with(&host_dev_conflict)),
+            Err(Error::NetDevice(
+                NetworkInterfaceError::HostDeviceNameInUse(_)
+            ))
+        ));
+
+        let mmds_config = format!(
+            r#"{},
+            "mmds-config": {{
+                "network_interfaces": ["netif1", "netif3"]
+            }}"#,
+            network_interfaces
+        );
+        assert!(matches!(
+            vm_resources.validate_config(config_with(&mmds_config)),
+            Err(Error::MmdsConfig(
+                MmdsConfigError::InvalidNetworkInterfaceId
+            ))
+        ));
+        let mmds_config = mmds_config.replace(r#", "netif3""#, "");
+        vm_resources
+            .validate_config(config_with(&mmds_config))
+            .unwrap();
+
+        let drives = r#",
+            "drives": [
+                {
+                    "drive_id": "rootfs",
+                    "path_on_host": "/invalid/path",
+                    "is_root_device": true,
+                    "is_read_only": false
+                }
+            ]"#;
+        assert!(matches!(
+            vm_resources.validate_config(config_with(drives)),
+            Err(Error::BlockDevice(_))
+        ));
+    }
+
+    #[test]
+    fn test_logger_and_metrics_config() {
+        let mut vm_resources = default_vm_resources();
+        let vmm_config = VmmConfig::from(&vm_resources);
+        assert!(vmm_config.logger.is_none());
+        assert!(vmm_config.metrics.is_none());
+
+        let logger_config = LoggerConfig {
+            log_path: PathBuf::from("/tmp/fc.log"),
+            level: LoggerLevel::Info,
+            show_level: true,
+            show_log_origin: false,
+        };
+        let metrics_config = MetricsConfig {
+            metrics_path: PathBuf::from("/tmp/fc.metrics"),
+        };
+        vm_resources.logger = Some(logger_config.clone());
+        vm_resources.metrics = Some(metrics_config.clone());
+        let vmm_config = VmmConfig::from(&vm_resources);
+        assert_eq!(vmm_config.logger, Some(logger_config));
+        assert_eq!(vmm_config.metrics, Some(metrics_config));
+    }
+
+    #[test]
+    fn test_set_memory_hotplug_config() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.memory_hotplug.is_none());
+
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        };
+        vm_resources
+            .set_memory_hotplug_config(config.clone())
+            .unwrap();
+        assert_eq!(vm_resources.memory_hotplug, Some(config.clone()));
+        assert_eq!(
+            VmmConfig::from(&vm_resources).memory_hotplug,
+            Some(config.clone())
+        );
+
+        // An invalid configuration doesn't replace the current one.
+        let invalid_config = MemoryHotplugConfig {
+            total_size_mib: 1023,
+            block_size_mib: 2,
+        };
+        assert!(matches!(
+            vm_resources.set_memory_hotplug_config(invalid_config),
+            Err(MemoryHotplugConfigError::InvalidTotalSize(1023))
+        ));
+        assert_eq!(vm_resources.memory_hotplug, Some(config));
+    }
+
     #[test]
     fn test_set_net_device() {
         let mut vm_resources = default_vm_resources();
@@ -1464,6 +2324,109 @@ mod tests {
         assert_eq!(vm_resources.net_builder.len(), 2);
     }
 
+    #[test]
+    fn test_set_mmds_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut mmds_config = MmdsConfig {
+            version: MmdsVersion::V2,
+            network_interfaces: vec![default_net_cfg().iface_id],
+            ipv4_address: None,
+            ipv6_address: Some(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1)),
+        };
+
+        // The MMDS IPv6 address must be a unicast address.
+        assert!(matches!(
+            vm_resources.set_mmds_config(mmds_config.clone(), "instance"),
+            Err(MmdsConfigError::InvalidIpv6Addr)
+        ));
+
+        let ipv6_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        mmds_config.ipv6_address = Some(ipv6_addr);
+        vm_resources
+            .set_mmds_config(mmds_config, "instance")
+            .unwrap();
+        assert_eq!(
+            vm_resources
+                .net_builder
+                .iter()
+                .next()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .mmds_ns()
+                .unwrap()
+                .ipv6_addr(),
+            Some(ipv6_addr)
+        );
+        assert_eq!(
+            vm_resources.mmds_config().unwrap().ipv6_address,
+            Some(ipv6_addr)
+        );
+    }
+
+    #[test]
+    fn test_hotplug_block_device() {
+        let mut vm_resources = default_vm_resources();
+        let (block_cfg, _file) = default_block_cfg();
+
+        // Attached drives can't be replaced.
+        assert_eq!(
+            vm_resources.validate_block_device_hotplug(&block_cfg),
+            Err(DriveError::DriveAlreadyAttached(block_cfg.drive_id.clone()))
+        );
+        assert!(vm_resources.hotplug_block_device(block_cfg).is_err());
+
+        let (mut new_block_cfg, _file) = default_block_cfg();
+        new_block_cfg.drive_id = "block2".to_string();
+        // The guest memory may not be shared with vhost-user backends.
+        let mut vhost_user_cfg = new_block_cfg.clone();
+        vhost_user_cfg.path_on_host = String::new();
+        vhost_user_cfg.vhost_user_socket = Some("/run/vhost-user-blk.sock".to_string());
+        assert_eq!(
+            vm_resources.validate_block_device_hotplug(&vhost_user_cfg),
+            Err(DriveError::VhostUserHotplug)
+        );
+
+        vm_resources
+            .validate_block_device_hotplug(&new_block_cfg)
+            .unwrap();
+        let block = vm_resources.hotplug_block_device(new_block_cfg).unwrap();
+        assert_eq!(block.lock().unwrap().id(), "block2");
+        assert_eq!(vm_resources.block.list.len(), 2);
+    }
+
+    #[test]
+    fn test_remove_block_device() {
+        let mut vm_resources = default_vm_resources();
+        let drive_id = default_block_cfg().0.drive_id;
+        assert_eq!(vm_resources.block.list.len(), 1);
+
+        assert!(vm_resources.validate_block_device_removal("bogus").is_err());
+        assert!(vm_resources
+            .validate_block_device_removal(&drive_id)
+            .is_ok());
+        assert_eq!(vm_resources.block.list.len(), 1);
+
+        vm_resources.remove_block_device(&drive_id).unwrap();
+        assert_eq!(vm_resources.block.list.len(), 0);
+        assert!(vm_resources.remove_block_device(&drive_id).is_err());
+    }
+
+    #[test]
+    fn test_remove_net_device() {
+        let mut vm_resources = default_vm_resources();
+        let iface_id = default_net_cfg().iface_id;
+        assert_eq!(vm_resources.net_builder.len(), 1);
+
+        assert!(vm_resources.validate_net_device_removal("bogus").is_err());
+        assert!(vm_resources.validate_net_device_removal(&iface_id).is_ok());
+        assert_eq!(vm_resources.net_builder.len(), 1);
+
+        vm_resources.remove_net_device(&iface_id).unwrap();
+        assert_eq!(vm_resources.net_builder.len(), 0);
+        assert!(vm_resources.remove_net_device(&iface_id).is_err());
+    }
+
     #[test]
     fn test_error_display() {
         assert_eq!(
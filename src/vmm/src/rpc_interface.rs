@@ -2,45 +2,71 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(target_arch = "x86_64")]
+use std::time::Duration;
 
 use logger::*;
-use mmds::data_store::{self, Mmds};
+use mmds::data_store::{self, Mmds, PatchOperation};
+use mmds::identity::IdentitySigner;
 use seccompiler::BpfThreadMap;
 use serde_json::Value;
 #[cfg(test)]
 use tests::{
-    build_microvm_for_boot, create_snapshot, restore_from_snapshot, MockVmRes as VmResources,
-    MockVmm as Vmm,
+    build_microvm_for_boot, create_snapshot, receive_microvm, restore_from_snapshot, send_microvm,
+    MockVmRes as VmResources, MockVmm as Vmm,
 };
 
 use super::Error as VmmError;
 #[cfg(not(test))]
 use super::{
-    builder::build_microvm_for_boot, persist::create_snapshot, persist::restore_from_snapshot,
-    resources::VmResources, Vmm,
+    builder::build_microvm_for_boot,
+    migration::{receive_microvm, send_microvm},
+    persist::create_snapshot,
+    persist::restore_from_snapshot,
+    resources::VmResources,
+    Vmm,
 };
 use crate::builder::StartMicrovmError;
-use crate::persist::{CreateSnapshotError, LoadSnapshotError};
-use crate::resources::VmmConfig;
+use crate::migration::MigrationError;
+use crate::persist::{
+    describe_snapshot, merge_snapshot_chain, CreateSnapshotError, LoadSnapshotError,
+    MergeSnapshotError,
+};
+use crate::resources::{Error as ResourcesError, VmmConfig};
 use crate::version_map::VERSION_MAP;
 use crate::vmm_config::balloon::{
-    BalloonConfigError, BalloonDeviceConfig, BalloonStats, BalloonUpdateConfig,
+    BalloonConfigError, BalloonDeviceConfig, BalloonPolicy, BalloonStats, BalloonUpdateConfig,
     BalloonUpdateStatsConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
-use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
+use crate::vmm_config::drive::{
+    BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError, DriveTraceConfig,
+};
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::logger::{LoggerConfig, LoggerConfigError};
 use crate::vmm_config::machine_config::{VmConfig, VmConfigError, VmUpdateConfig};
+use crate::vmm_config::memory_hotplug::{
+    MemoryHotplugConfig, MemoryHotplugConfigError, MemoryHotplugSizeUpdate, MemoryHotplugStatus,
+};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
-use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
+use crate::vmm_config::migration::{MigrationReceiveConfig, MigrationSendConfig};
+use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError, MmdsIdentityConfig};
 use crate::vmm_config::net::{
-    NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+    NetStats, NetworkCaptureConfig, NetworkInterfaceConfig, NetworkInterfaceError,
+    NetworkInterfaceUpdateConfig,
+};
+use crate::vmm_config::pmem::{PmemConfig, PmemConfigError};
+use crate::vmm_config::rate_limiter_group::RateLimiterGroupConfig;
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, DirtyRate, LoadSnapshotParams, MergeSnapshotParams, SnapshotInfo,
+    SnapshotScheduleConfig, SnapshotType,
+};
+use crate::vmm_config::vsock::{
+    VsockConfigError, VsockConnectionInfo, VsockConnectionPoolConfig, VsockDeviceConfig,
 };
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
-use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
 use crate::vmm_config::{self, RateLimiterUpdate};
 use crate::{EventManager, FcExitCode};
 
@@ -48,6 +74,9 @@ use crate::{EventManager, FcExitCode};
 /// bits of information (ids, paths, etc.).
 #[derive(PartialEq)]
 pub enum VmmAction {
+    /// Stop taking snapshots periodically. This action can only be called after the microVM has
+    /// booted.
+    CancelSnapshotSchedule,
     /// Configure the boot source of the microVM using as input the `ConfigureBootSource`. This
     /// action can only be called before the microVM has booted.
     ConfigureBootSource(BootSourceConfig),
@@ -60,47 +89,122 @@ pub enum VmmAction {
     /// Create a snapshot using as input the `CreateSnapshotParams`. This action can only be called
     /// after the microVM has booted and only when the microVM is in `Paused` state.
     CreateSnapshot(CreateSnapshotParams),
+    /// Describe the snapshot whose microVM state is saved at the given path, without loading it.
+    /// This action only works on files, so it can be called before and after the microVM has
+    /// booted.
+    DescribeSnapshot(PathBuf),
+    /// Validate the wrapped action against the current microVM state without applying it.
+    /// Only the actions that change a device or the machine configuration support a dry run.
+    DryRun(Box<VmmAction>),
     /// Get the balloon device configuration.
     GetBalloonConfig,
+    /// Get the rate the guest dirtied its memory at since the previous request. This action can
+    /// only be called after the microVM has booted, with dirty page tracking enabled.
+    GetDirtyRate,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
     /// Get complete microVM configuration in JSON format.
     GetFullVmConfig,
     /// Get MMDS contents.
     GetMMDS,
+    /// Get the state of the hot-pluggable memory. This action can only be called after the
+    /// microVM has booted.
+    GetMemoryHotplugStatus,
+    /// Get the traffic statistics of a network interface. This action can only be called after
+    /// the microVM has booted.
+    GetNetworkInterfaceStats(String),
     /// Get the machine configuration of the microVM.
     GetVmMachineConfig,
     /// Get microVM instance information.
     GetVmInstanceInfo,
     /// Get microVM version.
     GetVmmVersion,
+    /// Get the active connections of a vsock device. This action can only be called after the
+    /// microVM has booted.
+    GetVsockConnections(String),
     /// Flush the metrics. This action can only be called after the logger has been configured.
     FlushMetrics,
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
-    /// input. This action can only be called before the microVM has booted.
+    /// input. After boot, new block devices are attached to the running microVM, and the ones
+    /// that already exist can't be updated.
     InsertBlockDevice(BlockDeviceConfig),
     /// Add a new network interface config or update one that already exists using the
     /// `NetworkInterfaceConfig` as input. This action can only be called before the microVM has
     /// booted.
     InsertNetworkDevice(NetworkInterfaceConfig),
+    /// Add a new pmem device or replace the one that already exists using the `PmemConfig` as
+    /// input. This action can only be called before the microVM has booted.
+    InsertPmemDevice(PmemConfig),
     /// Load the microVM state using as input the `LoadSnapshotParams`. This action can only be
     /// called before the microVM has booted. If this action is successful, the loaded microVM will
     /// be in `Paused` state. Should change this state to `Resumed` for the microVM to run.
     LoadSnapshot(LoadSnapshotParams),
+    /// Merge a chain of diff snapshots into a full snapshot using as input the
+    /// `MergeSnapshotParams`. This action can only be called before the microVM has booted, since
+    /// copying the memory files would stall the microVM.
+    MergeSnapshot(MergeSnapshotParams),
     /// Partial update of the MMDS contents.
     PatchMMDS(Value),
+    /// Update of the MMDS contents through the operations of a JSON Patch document.
+    PatchMMDSOperations(Vec<PatchOperation>),
     /// Pause the guest, by pausing the microVM VCPUs.
     Pause,
+    /// Initiate connections to a guest port of a vsock device, which the host takes over
+    /// later. This action can only be called after the microVM has booted.
+    PoolVsockConnections(String, VsockConnectionPoolConfig),
     /// Repopulate the MMDS contents.
     PutMMDS(Value),
+    /// Wait for a microVM migrated by another Firecracker, using as input the
+    /// `MigrationReceiveConfig`, and build it. This action can only be called before the microVM
+    /// has booted.
+    ReceiveMigration(MigrationReceiveConfig),
+    /// Replace the whole microVM configuration, following the configuration file schema, with
+    /// either all or none of its sections applied. This action can only be called before the
+    /// microVM has booted.
+    PutFullVmConfig(Box<VmmConfig>),
+    /// Remove the policy adjusting the balloon size. This action can only be called after the
+    /// microVM has booted.
+    RemoveBalloonPolicy,
+    /// Remove a block device. Before boot, the device is simply not attached to the microVM.
+    /// After boot, it is detached from the guest once its in-flight requests are completed.
+    RemoveBlockDevice(String),
+    /// Remove a network interface. Before boot, the interface is simply not attached to the
+    /// microVM. After boot, it is unplugged from the guest and its host-side interface closed.
+    RemoveNetworkDevice(String),
     /// Resume the guest, by resuming the microVM VCPUs.
     Resume,
+    /// Take snapshots periodically using as input the `SnapshotScheduleConfig`, replacing the
+    /// previous schedule, if any. This action can only be called after the microVM has booted,
+    /// with dirty page tracking enabled.
+    ScheduleSnapshots(SnapshotScheduleConfig),
+    /// Migrate the microVM to another Firecracker using as input the `MigrationSendConfig`.
+    /// This action can only be called after the microVM has booted. If this action is
+    /// successful, the microVM is left in `Paused` state.
+    SendMigration(MigrationSendConfig),
     /// Set the balloon device or update the one that already exists using the
-    /// `BalloonDeviceConfig` as input. This action can only be called before the microVM
-    /// has booted.
+    /// `BalloonDeviceConfig` as input. After boot, the balloon device is attached to the running
+    /// microVM, which mustn't have one already.
     SetBalloonDevice(BalloonDeviceConfig),
+    /// Set the policy adjusting the balloon size from its statistics, or replace the one that
+    /// already exists. This action can only be called after the microVM has booted.
+    SetBalloonPolicy(BalloonPolicy),
+    /// Set the hot-pluggable memory or replace the one that already exists using the
+    /// `MemoryHotplugConfig` as input. This action can only be called before the microVM has
+    /// booted.
+    SetMemoryHotplug(MemoryHotplugConfig),
     /// Set the MMDS configuration.
     SetMmdsConfiguration(MmdsConfig),
+    /// Set the key signing the identity document served by MMDS, or replace the one that
+    /// already exists.
+    SetMmdsIdentity(MmdsIdentityConfig),
+    /// Create a rate limiter group or update the buckets of the one that already exists using
+    /// the `RateLimiterGroupConfig` as input. This action can only be called before the microVM
+    /// has booted.
+    SetRateLimiterGroup(RateLimiterGroupConfig),
+    /// Start or stop recording the requests of a drive to a trace file.
+    SetDriveTrace(DriveTraceConfig),
+    /// Start or stop mirroring the frames of a network interface to pcap files.
+    SetNetworkCapture(NetworkCaptureConfig),
     /// Set the vsock device or update the one that already exists using the
     /// `VsockDeviceConfig` as input. This action can only be called before the microVM has
     /// booted.
@@ -111,14 +215,21 @@ pub enum VmmAction {
     /// driver is listening on the guest end, this can be used to shut down the microVM gracefully.
     #[cfg(target_arch = "x86_64")]
     SendCtrlAltDel,
+    /// Press the ACPI power button of the microVM, so that the guest powers off gracefully. If a
+    /// timeout is given, the microVM is stopped when the guest isn't powered off within it.
+    #[cfg(target_arch = "x86_64")]
+    SendAcpiShutdown(Option<Duration>),
     /// Update the balloon size, after microVM start.
     UpdateBalloon(BalloonUpdateConfig),
     /// Update the balloon statistics polling interval, after microVM start.
     UpdateBalloonStatistics(BalloonUpdateStatsConfig),
     /// Update existing block device properties such as `path_on_host` or `rate_limiter`.
     UpdateBlockDevice(BlockDeviceUpdateConfig),
+    /// Update the size of the hot-pluggable memory the guest is asked to plug, after microVM
+    /// start.
+    UpdateMemoryHotplug(MemoryHotplugSizeUpdate),
     /// Update a network interface, after microVM start. Currently, the only updatable properties
-    /// are the RX and TX rate limiters.
+    /// are the guest MAC address and the RX and TX rate limiters.
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig),
     /// Update the microVM configuration (memory & vcpu) using `VmUpdateConfig` as input. This
     /// action can only be called before the microVM has booted.
@@ -134,6 +245,10 @@ pub enum VmmActionError {
     BootSource(BootSourceConfigError),
     /// The action `CreateSnapshot` failed.
     CreateSnapshot(CreateSnapshotError),
+    /// The action `DescribeSnapshot` failed.
+    DescribeSnapshot(LoadSnapshotError),
+    /// The action `PutFullVmConfig` failed because of bad user input.
+    FullVmConfig(ResourcesError),
     /// One of the actions `InsertBlockDevice` or `UpdateBlockDevicePath`
     /// failed because of bad user input.
     DriveConfig(DriveError),
@@ -148,8 +263,15 @@ pub enum VmmActionError {
     /// One of the actions `GetVmConfiguration` or `UpdateVmConfiguration` failed because of bad
     /// input.
     MachineConfig(VmConfigError),
+    /// One of the actions `SetMemoryHotplug`, `GetMemoryHotplugStatus` or
+    /// `UpdateMemoryHotplug` failed.
+    MemoryHotplugConfig(MemoryHotplugConfigError),
+    /// The action `MergeSnapshot` failed.
+    MergeSnapshot(MergeSnapshotError),
     /// The action `ConfigureMetrics` failed because of bad user input.
     Metrics(MetricsConfigError),
+    /// One of the actions `SendMigration` or `ReceiveMigration` failed.
+    Migration(MigrationError),
     /// One of the `GetMmds`, `PutMmds` or `PatchMmds` actions failed.
     Mmds(data_store::Error),
     /// The action `SetMmdsConfiguration` failed because of bad user input.
@@ -164,6 +286,10 @@ pub enum VmmActionError {
     OperationNotSupportedPostBoot,
     /// The requested operation is not supported before starting the microVM.
     OperationNotSupportedPreBoot,
+    /// The action `InsertPmemDevice` failed because of bad user input.
+    PmemConfig(PmemConfigError),
+    /// The action `ScheduleSnapshots` failed because of bad user input.
+    SnapshotSchedule(String),
     /// The action `StartMicroVm` failed because of an internal error.
     StartMicrovm(StartMicrovmError),
     /// The action `SetVsockDevice` failed because of bad user input.
@@ -181,7 +307,9 @@ impl Display for VmmActionError {
                 BalloonConfig(err) => err.to_string(),
                 BootSource(err) => err.to_string(),
                 CreateSnapshot(err) => err.to_string(),
+                DescribeSnapshot(err) => format!("Describe microVM snapshot error: {}", err),
                 DriveConfig(err) => err.to_string(),
+                FullVmConfig(err) => err.to_string(),
                 InternalVmm(err) => format!("Internal Vmm error: {}", err),
                 LoadSnapshot(err) => format!("Load microVM snapshot error: {}", err),
                 LoadSnapshotNotAllowed => {
@@ -191,7 +319,10 @@ impl Display for VmmActionError {
                 }
                 Logger(err) => err.to_string(),
                 MachineConfig(err) => err.to_string(),
+                MemoryHotplugConfig(err) => err.to_string(),
+                MergeSnapshot(err) => format!("Merge microVM snapshots error: {}", err),
                 Metrics(err) => err.to_string(),
+                Migration(err) => format!("Migration error: {}", err),
                 Mmds(err) => err.to_string(),
                 MmdsConfig(err) => err.to_string(),
                 MmdsLimitExceeded(err) => err.to_string(),
@@ -205,6 +336,8 @@ impl Display for VmmActionError {
                     "The requested operation is not supported before starting the microVM."
                         .to_string()
                 }
+                PmemConfig(err) => err.to_string(),
+                SnapshotSchedule(err) => format!("Invalid snapshot schedule: {}", err),
                 StartMicrovm(err) => err.to_string(),
                 // The action `SetVsockDevice` failed because of bad user input.
                 VsockConfig(err) => err.to_string(),
@@ -221,18 +354,30 @@ pub enum VmmData {
     BalloonConfig(BalloonDeviceConfig),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The dirty page rate of the guest memory.
+    DirtyRate(DirtyRate),
+    /// The dry run of an action succeeded, nothing was applied.
+    DryRun,
     /// No data is sent on the channel.
     Empty,
     /// The complete microVM configuration in JSON format.
     FullVmConfig(VmmConfig),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
+    /// The state of the hot-pluggable memory.
+    MemoryHotplugStatus(MemoryHotplugStatus),
     /// Mmds contents.
     MmdsValue(serde_json::Value),
+    /// The traffic statistics of a network interface.
+    NetworkInterfaceStats(NetStats),
+    /// The description of a snapshot.
+    SnapshotInfo(SnapshotInfo),
     /// The microVM instance information.
     InstanceInformation(InstanceInfo),
     /// The microVM version.
     VmmVersion(String),
+    /// The active connections of a vsock device.
+    VsockConnections(Vec<VsockConnectionInfo>),
 }
 
 /// Shorthand result type for external VMM commands.
@@ -252,24 +397,38 @@ trait MmdsRequestHandler {
         self.mmds()
             .patch_data(value)
             .map(|()| VmmData::Empty)
-            .map_err(|e| match e {
-                data_store::Error::DataStoreLimitExceeded => {
-                    VmmActionError::MmdsLimitExceeded(data_store::Error::DataStoreLimitExceeded)
-                }
-                _ => VmmActionError::Mmds(e),
-            })
+            .map_err(mmds_action_error)
+    }
+
+    fn patch_mmds_operations(&mut self, operations: Vec<PatchOperation>) -> ActionResult {
+        self.mmds()
+            .apply_patch_operations(operations)
+            .map(|()| VmmData::Empty)
+            .map_err(mmds_action_error)
     }
 
     fn put_mmds(&mut self, value: serde_json::Value) -> ActionResult {
         self.mmds()
             .put_data(value)
             .map(|()| VmmData::Empty)
-            .map_err(|e| match e {
-                data_store::Error::DataStoreLimitExceeded => {
-                    VmmActionError::MmdsLimitExceeded(data_store::Error::DataStoreLimitExceeded)
-                }
-                _ => VmmActionError::Mmds(e),
-            })
+            .map_err(mmds_action_error)
+    }
+
+    fn set_mmds_identity(&mut self, config: MmdsIdentityConfig, instance_id: &str) -> ActionResult {
+        let signer = IdentitySigner::from_key_file(&config.signing_key_path, instance_id)
+            .map_err(MmdsConfigError::IdentitySigner)
+            .map_err(VmmActionError::MmdsConfig)?;
+        self.mmds().set_identity_signer(signer);
+        Ok(VmmData::Empty)
+    }
+}
+
+fn mmds_action_error(e: data_store::Error) -> VmmActionError {
+    match e {
+        data_store::Error::DataStoreLimitExceeded => {
+            VmmActionError::MmdsLimitExceeded(data_store::Error::DataStoreLimitExceeded)
+        }
+        _ => VmmActionError::Mmds(e),
     }
 }
 
@@ -391,13 +550,21 @@ impl<'a> PrebootApiController<'a> {
             // Supported operations allowed pre-boot.
             ConfigureBootSource(config) => self.set_boot_source(config),
             ConfigureLogger(logger_cfg) => {
-                vmm_config::logger::init_logger(logger_cfg, &self.instance_info)
-                    .map(|()| VmmData::Empty)
+                vmm_config::logger::init_logger(logger_cfg.clone(), &self.instance_info)
+                    .map(|()| {
+                        // Kept so that `GET /vm/config` reports it.
+                        self.vm_resources.logger = Some(logger_cfg);
+                        VmmData::Empty
+                    })
                     .map_err(VmmActionError::Logger)
             }
-            ConfigureMetrics(metrics_cfg) => vmm_config::metrics::init_metrics(metrics_cfg)
-                .map(|()| VmmData::Empty)
+            ConfigureMetrics(metrics_cfg) => vmm_config::metrics::init_metrics(metrics_cfg.clone())
+                .map(|()| {
+                    self.vm_resources.metrics = Some(metrics_cfg);
+                    VmmData::Empty
+                })
                 .map_err(VmmActionError::Metrics),
+            DryRun(action) => self.dry_run(*action),
             GetBalloonConfig => self.balloon_config(),
             GetFullVmConfig => {
                 warn!(
@@ -414,27 +581,110 @@ impl<'a> PrebootApiController<'a> {
             GetVmmVersion => Ok(VmmData::VmmVersion(self.instance_info.vmm_version.clone())),
             InsertBlockDevice(config) => self.insert_block_device(config),
             InsertNetworkDevice(config) => self.insert_net_device(config),
+            InsertPmemDevice(config) => self.insert_pmem_device(config),
             LoadSnapshot(config) => self.load_snapshot(&config),
+            DescribeSnapshot(snapshot_path) => describe_snapshot_file(&snapshot_path),
+            MergeSnapshot(config) => merge_snapshot(&config),
             PatchMMDS(value) => self.patch_mmds(value),
+            PatchMMDSOperations(operations) => self.patch_mmds_operations(operations),
             PutMMDS(value) => self.put_mmds(value),
+            PutFullVmConfig(config) => self.put_full_vm_config(*config),
+            ReceiveMigration(config) => self.receive_migration(&config),
+            RemoveBlockDevice(drive_id) => self.remove_block_device(&drive_id),
+            RemoveNetworkDevice(iface_id) => self.remove_net_device(&iface_id),
             SetBalloonDevice(config) => self.set_balloon_device(config),
+            SetMemoryHotplug(config) => self.set_memory_hotplug(config),
             SetVsockDevice(config) => self.set_vsock_device(config),
             SetMmdsConfiguration(config) => self.set_mmds_config(config),
+            SetMmdsIdentity(config) => {
+                let instance_id = self.instance_info.id.clone();
+                self.set_mmds_identity(config, &instance_id)
+            }
+            SetRateLimiterGroup(config) => self.set_rate_limiter_group(config),
+            SetDriveTrace(config) => self
+                .vm_resources
+                .set_block_trace(&config)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::DriveConfig),
+            SetNetworkCapture(config) => self
+                .vm_resources
+                .set_net_capture(&config)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::NetworkConfig),
             StartMicroVm => self.start_microvm(),
             UpdateVmConfiguration(config) => self.update_vm_config(config),
             // Operations not allowed pre-boot.
-            CreateSnapshot(_)
+            CancelSnapshotSchedule
+            | CreateSnapshot(_)
             | FlushMetrics
             | Pause
             | Resume
             | GetBalloonStats
+            | GetDirtyRate
+            | GetMemoryHotplugStatus
+            | GetNetworkInterfaceStats(_)
+            | GetVsockConnections(_)
+            | PoolVsockConnections(_, _)
+            | RemoveBalloonPolicy
+            | ScheduleSnapshots(_)
+            | SendMigration(_)
+            | SetBalloonPolicy(_)
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
             | UpdateBlockDevice(_)
+            | UpdateMemoryHotplug(_)
             | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
-            SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
+            SendCtrlAltDel | SendAcpiShutdown(_) => {
+                Err(VmmActionError::OperationNotSupportedPreBoot)
+            }
+        }
+    }
+
+    // Validates `action` against the pre-boot resources without applying it. A dry run
+    // doesn't count as configuring a boot specific resource.
+    fn dry_run(&mut self, action: VmmAction) -> ActionResult {
+        use self::VmmAction::*;
+
+        match action {
+            InsertBlockDevice(config) => self
+                .vm_resources
+                .validate_block_device(&config)
+                .map_err(VmmActionError::DriveConfig),
+            InsertNetworkDevice(config) => self
+                .vm_resources
+                .validate_net_device(&config)
+                .map_err(VmmActionError::NetworkConfig),
+            RemoveBlockDevice(drive_id) => self
+                .vm_resources
+                .validate_block_device_removal(&drive_id)
+                .map_err(VmmActionError::DriveConfig),
+            RemoveNetworkDevice(iface_id) => self
+                .vm_resources
+                .validate_net_device_removal(&iface_id)
+                .map_err(VmmActionError::NetworkConfig),
+            SetBalloonDevice(config) => self
+                .vm_resources
+                .validate_balloon_device(&config)
+                .map_err(VmmActionError::BalloonConfig),
+            UpdateVmConfiguration(config) => self
+                .vm_resources
+                .validate_vm_config(&config)
+                .map_err(VmmActionError::MachineConfig),
+            PutFullVmConfig(config) => self
+                .vm_resources
+                .validate_config(*config)
+                .map_err(VmmActionError::FullVmConfig),
+            UpdateBalloon(_)
+            | UpdateBalloonStatistics(_)
+            | UpdateBlockDevice(_)
+            | UpdateMemoryHotplug(_)
+            | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
+            _ => Err(VmmActionError::NotSupported(
+                "dry run is not available for this request.".to_string(),
+            )),
         }
+        .map(|()| VmmData::DryRun)
     }
 
     fn balloon_config(&mut self) -> ActionResult {
@@ -461,6 +711,38 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::NetworkConfig)
     }
 
+    fn insert_pmem_device(&mut self, cfg: PmemConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_pmem_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::PmemConfig)
+    }
+
+    fn put_full_vm_config(&mut self, cfg: VmmConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .replace_config(cfg, &self.instance_info)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::FullVmConfig)
+    }
+
+    fn remove_block_device(&mut self, drive_id: &str) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .remove_block_device(drive_id)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::DriveConfig)
+    }
+
+    fn remove_net_device(&mut self, iface_id: &str) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .remove_net_device(iface_id)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::NetworkConfig)
+    }
+
     fn set_balloon_device(&mut self, cfg: BalloonDeviceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -469,6 +751,14 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BalloonConfig)
     }
 
+    fn set_memory_hotplug(&mut self, cfg: MemoryHotplugConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_memory_hotplug_config(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::MemoryHotplugConfig)
+    }
+
     fn set_boot_source(&mut self, cfg: BootSourceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -501,6 +791,12 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::VsockConfig)
     }
 
+    fn set_rate_limiter_group(&mut self, cfg: RateLimiterGroupConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources.set_rate_limiter_group(cfg);
+        Ok(VmmData::Empty)
+    }
+
     // On success, this command will end the pre-boot stage and this controller
     // will be replaced by a runtime controller.
     fn start_microvm(&mut self) -> ActionResult {
@@ -534,8 +830,14 @@ impl<'a> PrebootApiController<'a> {
             self.vm_resources.set_track_dirty_pages(true);
         }
 
+        // A clone goes by its own ID from the moment it is restored.
+        let mut instance_info = self.instance_info.clone();
+        if let Some(clone) = load_params.clone.as_ref() {
+            instance_info.id = clone.instance_id.clone();
+        }
+
         let result = restore_from_snapshot(
-            &self.instance_info,
+            &instance_info,
             &mut self.event_manager,
             self.seccomp_filters,
             load_params,
@@ -551,6 +853,10 @@ impl<'a> PrebootApiController<'a> {
 
             ret.map(|()| {
                 self.built_vmm = Some(vmm);
+                if load_params.clone.is_some() {
+                    LOGGER.set_instance_id(instance_info.id.clone());
+                    self.instance_info = instance_info;
+                }
                 VmmData::Empty
             })
             .map_err(LoadSnapshotError::ResumeMicroVm)
@@ -574,6 +880,46 @@ impl<'a> PrebootApiController<'a> {
 
         result
     }
+
+    // On success, this command will end the pre-boot stage and this controller
+    // will be replaced by a runtime controller.
+    fn receive_migration(&mut self, config: &MigrationReceiveConfig) -> ActionResult {
+        let receive_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+
+        if self.boot_path {
+            let err = VmmActionError::LoadSnapshotNotAllowed;
+            info!("{}", err);
+            return Err(err);
+        }
+
+        if config.track_dirty_pages {
+            self.vm_resources.set_track_dirty_pages(true);
+        }
+
+        let vmm = receive_microvm(
+            &self.instance_info,
+            &mut self.event_manager,
+            self.seccomp_filters,
+            config,
+            VERSION_MAP.clone(),
+            self.vm_resources,
+        )
+        .map_err(|e| {
+            // The process is too dirty to recover at this point.
+            self.fatal_error = Some(FcExitCode::BadConfiguration);
+            VmmActionError::Migration(e)
+        })?;
+        self.built_vmm = Some(vmm);
+
+        info!(
+            "'receive migration' VMM action took {} us.",
+            update_metric_with_elapsed_time(
+                &METRICS.latencies_us.vmm_receive_migration,
+                receive_start_us
+            )
+        );
+        Ok(VmmData::Empty)
+    }
 }
 
 /// Enables RPC interaction with a running Firecracker VMM.
@@ -588,13 +934,42 @@ impl MmdsRequestHandler for RuntimeApiController {
     }
 }
 
+// Describes a snapshot, which doesn't involve the microVM.
+fn describe_snapshot_file(snapshot_path: &Path) -> ActionResult {
+    describe_snapshot(snapshot_path, VERSION_MAP.clone())
+        .map(VmmData::SnapshotInfo)
+        .map_err(VmmActionError::DescribeSnapshot)
+}
+
+// Merges a chain of diff snapshots, which doesn't involve the microVM.
+fn merge_snapshot(merge_params: &MergeSnapshotParams) -> ActionResult {
+    log_dev_preview_warning("Virtual machine snapshots", None);
+
+    let merge_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+    merge_snapshot_chain(merge_params, VERSION_MAP.clone())
+        .map_err(VmmActionError::MergeSnapshot)?;
+    let elapsed_time_us =
+        update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_merge_snapshot, merge_start_us);
+    info!("'merge snapshot' VMM action took {} us.", elapsed_time_us);
+
+    Ok(VmmData::Empty)
+}
+
 impl RuntimeApiController {
     /// Handles the incoming runtime `VmmAction` request and provides a response for it.
     pub fn handle_request(&mut self, request: VmmAction) -> ActionResult {
         use self::VmmAction::*;
         match request {
             // Supported operations allowed post-boot.
+            CancelSnapshotSchedule => {
+                self.vmm
+                    .lock()
+                    .expect("Poisoned lock")
+                    .cancel_snapshot_schedule();
+                Ok(VmmData::Empty)
+            }
             CreateSnapshot(snapshot_create_cfg) => self.create_snapshot(&snapshot_create_cfg),
+            DryRun(action) => self.dry_run(*action),
             FlushMetrics => self.flush_metrics(),
             GetBalloonConfig => self
                 .vmm
@@ -610,8 +985,24 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            GetDirtyRate => self.get_dirty_rate(),
             GetFullVmConfig => Ok(VmmData::FullVmConfig((&self.vm_resources).into())),
             GetMMDS => self.get_mmds(),
+            GetMemoryHotplugStatus => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .memory_hotplug_status()
+                .map(VmmData::MemoryHotplugStatus)
+                .map_err(|e| VmmActionError::MemoryHotplugConfig(e.into())),
+            GetNetworkInterfaceStats(iface_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .net_stats(&iface_id)
+                .map(VmmData::NetworkInterfaceStats)
+                .map_err(NetworkInterfaceError::DeviceStats)
+                .map_err(VmmActionError::NetworkConfig),
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
@@ -621,12 +1012,60 @@ impl RuntimeApiController {
             GetVmmVersion => Ok(VmmData::VmmVersion(
                 self.vmm.lock().expect("Poisoned lock").version(),
             )),
+            GetVsockConnections(vsock_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .vsock_connections(&vsock_id)
+                .map(VmmData::VsockConnections)
+                .map_err(VsockConfigError::DeviceConnections)
+                .map_err(VmmActionError::VsockConfig),
+            InsertBlockDevice(config) => self.insert_block_device(config),
+            DescribeSnapshot(snapshot_path) => describe_snapshot_file(&snapshot_path),
             PatchMMDS(value) => self.patch_mmds(value),
+            PatchMMDSOperations(operations) => self.patch_mmds_operations(operations),
             Pause => self.pause(),
+            PoolVsockConnections(vsock_id, pool_cfg) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .pool_vsock_connections(&vsock_id, &pool_cfg)
+                .map(|()| VmmData::Empty)
+                .map_err(VsockConfigError::PoolConnections)
+                .map_err(VmmActionError::VsockConfig),
             PutMMDS(value) => self.put_mmds(value),
+            RemoveBalloonPolicy => self.set_balloon_policy(None),
+            RemoveBlockDevice(drive_id) => self.remove_block_device(&drive_id),
+            RemoveNetworkDevice(iface_id) => self.remove_net_device(&iface_id),
             Resume => self.resume(),
+            ScheduleSnapshots(config) => self.schedule_snapshots(config),
+            SendMigration(config) => self.send_migration(&config),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
+            #[cfg(target_arch = "x86_64")]
+            SendAcpiShutdown(timeout) => self.send_acpi_shutdown(timeout),
+            SetBalloonDevice(config) => self.set_balloon_device(config),
+            SetBalloonPolicy(policy) => self.set_balloon_policy(Some(policy)),
+            SetMmdsIdentity(config) => {
+                let instance_id = self.vmm.lock().expect("Poisoned lock").instance_info().id;
+                self.set_mmds_identity(config, &instance_id)
+            }
+            SetDriveTrace(config) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .set_block_trace(&config)
+                .map(|()| VmmData::Empty)
+                .map_err(DriveError::DeviceUpdate)
+                .map_err(VmmActionError::DriveConfig),
+            SetNetworkCapture(config) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .set_net_capture(&config)
+                .map(|()| VmmData::Empty)
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig),
             UpdateBalloon(balloon_update) => self
                 .vmm
                 .lock()
@@ -642,18 +1081,29 @@ impl RuntimeApiController {
                 .map(|_| VmmData::Empty)
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
             UpdateBlockDevice(new_cfg) => self.update_block_device(new_cfg),
-            UpdateNetworkInterface(netif_update) => self.update_net_rate_limiters(netif_update),
+            UpdateMemoryHotplug(update) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .update_memory_hotplug(update.requested_size_mib)
+                .map(|()| VmmData::Empty)
+                .map_err(|e| VmmActionError::MemoryHotplugConfig(e.into())),
+            UpdateNetworkInterface(netif_update) => self.update_net_device(netif_update),
 
             // Operations not allowed post-boot.
             ConfigureBootSource(_)
             | ConfigureLogger(_)
             | ConfigureMetrics(_)
-            | InsertBlockDevice(_)
             | InsertNetworkDevice(_)
+            | InsertPmemDevice(_)
             | LoadSnapshot(_)
-            | SetBalloonDevice(_)
+            | MergeSnapshot(_)
+            | PutFullVmConfig(_)
+            | ReceiveMigration(_)
+            | SetMemoryHotplug(_)
             | SetVsockDevice(_)
             | SetMmdsConfiguration(_)
+            | SetRateLimiterGroup(_)
             | StartMicroVm
             | UpdateVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
         }
@@ -712,6 +1162,15 @@ impl RuntimeApiController {
             .map_err(VmmActionError::InternalVmm)
     }
 
+    fn set_balloon_policy(&mut self, policy: Option<BalloonPolicy>) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .set_balloon_policy(policy)
+            .map(|()| VmmData::Empty)
+            .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e)))
+    }
+
     /// Injects CTRL+ALT+DEL keystroke combo to the inner Vmm (if present).
     #[cfg(target_arch = "x86_64")]
     fn send_ctrl_alt_del(&mut self) -> ActionResult {
@@ -723,6 +1182,17 @@ impl RuntimeApiController {
             .map_err(VmmActionError::InternalVmm)
     }
 
+    /// Presses the ACPI power button of the inner Vmm (if present).
+    #[cfg(target_arch = "x86_64")]
+    fn send_acpi_shutdown(&mut self, timeout: Option<Duration>) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .send_acpi_shutdown(timeout)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::InternalVmm)
+    }
+
     fn create_snapshot(&mut self, create_params: &CreateSnapshotParams) -> ActionResult {
         log_dev_preview_warning("Virtual machine snapshots", None);
 
@@ -766,10 +1236,142 @@ impl RuntimeApiController {
         Ok(VmmData::Empty)
     }
 
+    fn get_dirty_rate(&mut self) -> ActionResult {
+        // The dirty pages are counted through the dirty log.
+        if !self.vm_resources.track_dirty_pages() {
+            return Err(VmmActionError::NotSupported(
+                "The dirty page rate is not available on uVMs with dirty page tracking disabled."
+                    .to_string(),
+            ));
+        }
+
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .sample_dirty_rate()
+            .map(VmmData::DirtyRate)
+            .map_err(VmmActionError::InternalVmm)
+    }
+
+    fn schedule_snapshots(&mut self, config: SnapshotScheduleConfig) -> ActionResult {
+        log_dev_preview_warning("Virtual machine snapshots", None);
+
+        // All the snapshots of a rotation but the first one are diff snapshots.
+        if !self.vm_resources.track_dirty_pages() {
+            return Err(VmmActionError::NotSupported(
+                "Scheduled snapshots are not allowed on uVMs with dirty page tracking disabled."
+                    .to_string(),
+            ));
+        }
+        if !config.snapshot_dir.is_dir() {
+            return Err(VmmActionError::SnapshotSchedule(format!(
+                "{} is not a directory",
+                config.snapshot_dir.display()
+            )));
+        }
+
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .schedule_snapshots(config);
+        Ok(VmmData::Empty)
+    }
+
+    fn send_migration(&mut self, config: &MigrationSendConfig) -> ActionResult {
+        // The pages dirtied while the guest memory is copied are tracked through the dirty log.
+        if !self.vm_resources.track_dirty_pages() {
+            return Err(VmmActionError::NotSupported(
+                "Migration is not allowed on uVMs with dirty page tracking disabled.".to_string(),
+            ));
+        }
+
+        let mut locked_vmm = self.vmm.lock().unwrap();
+        let send_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+
+        send_microvm(&mut locked_vmm, config, VERSION_MAP.clone())
+            .map_err(VmmActionError::Migration)?;
+
+        info!(
+            "'send migration' VMM action took {} us.",
+            update_metric_with_elapsed_time(
+                &METRICS.latencies_us.vmm_send_migration,
+                send_start_us
+            )
+        );
+        Ok(VmmData::Empty)
+    }
+
+    /// Validates `action` against the running microVM without applying it.
+    fn dry_run(&mut self, action: VmmAction) -> ActionResult {
+        use self::VmmAction::*;
+
+        let vmm = self.vmm.lock().expect("Poisoned lock");
+        match action {
+            UpdateBalloon(balloon_update) => vmm
+                .validate_balloon_config(balloon_update.amount_mib)
+                .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            UpdateBalloonStatistics(balloon_stats_update) => vmm
+                .validate_balloon_stats_config(balloon_stats_update.stats_polling_interval_s)
+                .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            UpdateBlockDevice(new_cfg) => vmm
+                .validate_block_device_update(
+                    &new_cfg.drive_id,
+                    new_cfg.path_on_host.as_deref(),
+                    new_cfg.size_bytes,
+                    new_cfg.is_read_only.is_some(),
+                )
+                .map_err(DriveError::DeviceUpdate)
+                .map_err(VmmActionError::DriveConfig),
+            UpdateMemoryHotplug(update) => vmm
+                .validate_memory_hotplug_update(update.requested_size_mib)
+                .map_err(|e| VmmActionError::MemoryHotplugConfig(e.into())),
+            UpdateNetworkInterface(netif_update) => {
+                if let Some(guest_mac) = netif_update.guest_mac.as_ref() {
+                    self.vm_resources
+                        .validate_net_guest_mac(&netif_update.iface_id, guest_mac)
+                        .map_err(VmmActionError::NetworkConfig)?;
+                }
+                vmm.validate_net_device_update(
+                    &netif_update.iface_id,
+                    netif_update.guest_mac.is_some(),
+                    netif_update.updates_rate_limiters(),
+                )
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig)
+            }
+            RemoveNetworkDevice(iface_id) => vmm
+                .validate_net_device_removal(&iface_id)
+                .map_err(NetworkInterfaceError::DeviceRemoval)
+                .map_err(VmmActionError::NetworkConfig),
+            InsertBlockDevice(config) => self
+                .vm_resources
+                .validate_block_device_hotplug(&config)
+                .map_err(VmmActionError::DriveConfig),
+            RemoveBlockDevice(drive_id) => vmm
+                .validate_block_device_removal(&drive_id)
+                .map_err(DriveError::DeviceRemoval)
+                .map_err(VmmActionError::DriveConfig),
+            SetBalloonDevice(config) => self
+                .vm_resources
+                .validate_balloon_device_hotplug(&config)
+                .map_err(VmmActionError::BalloonConfig),
+            InsertNetworkDevice(_)
+            | PutFullVmConfig(_)
+            | SetMemoryHotplug(_)
+            | UpdateVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            _ => Err(VmmActionError::NotSupported(
+                "dry run is not available for this request.".to_string(),
+            )),
+        }
+        .map(|()| VmmData::DryRun)
+    }
+
     /// Updates block device properties:
     ///  - path of the host file backing the emulated block device, update the disk image on the
     ///    device and its virtio configuration
-    ///  - rate limiter configuration.
+    ///  - size of the device, growing the backing file and updating the virtio configuration
+    ///  - read-only mode of the device, reopening the backing file in the new mode
+    ///  - rate limiter configuration, and the ones of the reads and of the writes.
     fn update_block_device(&mut self, new_cfg: BlockDeviceUpdateConfig) -> ActionResult {
         let mut vmm = self.vmm.lock().expect("Poisoned lock");
         if let Some(new_path) = new_cfg.path_on_host {
@@ -778,6 +1380,16 @@ impl RuntimeApiController {
                 .map_err(DriveError::DeviceUpdate)
                 .map_err(VmmActionError::DriveConfig)?;
         }
+        if let Some(size_bytes) = new_cfg.size_bytes {
+            vmm.resize_block_device(&new_cfg.drive_id, size_bytes)
+                .map_err(DriveError::DeviceUpdate)
+                .map_err(VmmActionError::DriveConfig)?;
+        }
+        if let Some(read_only) = new_cfg.is_read_only {
+            vmm.set_block_device_read_only(&new_cfg.drive_id, read_only)
+                .map_err(DriveError::DeviceUpdate)
+                .map_err(VmmActionError::DriveConfig)?;
+        }
         if new_cfg.rate_limiter.is_some() {
             vmm.update_block_rate_limiter(
                 &new_cfg.drive_id,
@@ -788,43 +1400,168 @@ impl RuntimeApiController {
             .map_err(DriveError::DeviceUpdate)
             .map_err(VmmActionError::DriveConfig)?;
         }
+        if new_cfg.read_rate_limiter.is_some() || new_cfg.write_rate_limiter.is_some() {
+            vmm.update_block_read_write_rate_limiters(
+                &new_cfg.drive_id,
+                RateLimiterUpdate::from(new_cfg.read_rate_limiter),
+                RateLimiterUpdate::from(new_cfg.write_rate_limiter),
+            )
+            .map_err(DriveError::DeviceUpdate)
+            .map_err(VmmActionError::DriveConfig)?;
+        }
         Ok(VmmData::Empty)
     }
 
-    /// Updates configuration for an emulated net device as described in `new_cfg`.
-    fn update_net_rate_limiters(&mut self, new_cfg: NetworkInterfaceUpdateConfig) -> ActionResult {
-        self.vmm
-            .lock()
-            .expect("Poisoned lock")
-            .update_net_rate_limiters(
+    /// Updates net device properties:
+    ///  - guest MAC address, the guest driver is notified through a config change interrupt
+    ///  - link state, also notified through a config change interrupt
+    ///  - rate limiter configuration.
+    fn update_net_device(&mut self, new_cfg: NetworkInterfaceUpdateConfig) -> ActionResult {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        // The guest MAC address is the only updated property which is part of the device tags.
+        let updates_tags = new_cfg.guest_mac.is_some();
+        if let Some(guest_mac) = new_cfg.guest_mac {
+            self.vm_resources
+                .validate_net_guest_mac(&new_cfg.iface_id, &guest_mac)
+                .map_err(VmmActionError::NetworkConfig)?;
+            vmm.update_net_guest_mac(&new_cfg.iface_id, guest_mac)
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig)?;
+        }
+        if let Some(link_up) = new_cfg.link_up {
+            vmm.update_net_link_state(&new_cfg.iface_id, link_up)
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig)?;
+        }
+        if new_cfg.updates_rate_limiters() {
+            vmm.update_net_rate_limiters(
                 &new_cfg.iface_id,
-                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).bandwidth,
-                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).ops,
-                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).bandwidth,
-                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).ops,
+                RateLimiterUpdate::from(new_cfg.rx_rate_limiter),
+                RateLimiterUpdate::from(new_cfg.tx_rate_limiter),
             )
-            .map(|()| VmmData::Empty)
             .map_err(NetworkInterfaceError::DeviceUpdate)
-            .map_err(VmmActionError::NetworkConfig)
+            .map_err(VmmActionError::NetworkConfig)?;
+        }
+        drop(vmm);
+        if updates_tags {
+            self.refresh_device_tags();
+        }
+        Ok(VmmData::Empty)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::path::PathBuf;
+    /// Attaches a new block device to the running microVM.
+    fn insert_block_device(&mut self, cfg: BlockDeviceConfig) -> ActionResult {
+        let drive_id = cfg.drive_id.clone();
+        let block = self
+            .vm_resources
+            .hotplug_block_device(cfg)
+            .map_err(VmmActionError::DriveConfig)?;
+        if let Err(e) = self
+            .vmm
+            .lock()
+            .expect("Poisoned lock")
+            .add_block_device(block)
+        {
+            // The drive isn't attached, so it's forgotten about.
+            let _ = self.vm_resources.remove_block_device(&drive_id);
+            return Err(VmmActionError::DriveConfig(DriveError::DeviceHotplug(e)));
+        }
+        self.refresh_device_tags();
+        Ok(VmmData::Empty)
+    }
 
-    use devices::virtio::balloon::{BalloonConfig, Error as BalloonError};
-    use devices::virtio::VsockError;
-    use mmds::data_store::MmdsVersion;
-    use seccompiler::BpfThreadMap;
+    /// Attaches a balloon device to the running microVM, which has none.
+    fn set_balloon_device(&mut self, cfg: BalloonDeviceConfig) -> ActionResult {
+        let balloon = self
+            .vm_resources
+            .hotplug_balloon_device(cfg)
+            .map_err(VmmActionError::BalloonConfig)?;
+        if let Err(e) = self
+            .vmm
+            .lock()
+            .expect("Poisoned lock")
+            .add_balloon_device(balloon)
+        {
+            // The balloon device isn't attached, so it's forgotten about.
+            self.vm_resources.balloon.clear();
+            return Err(VmmActionError::BalloonConfig(
+                BalloonConfigError::DeviceHotplug(e),
+            ));
+        }
+        self.refresh_device_tags();
+        Ok(VmmData::Empty)
+    }
 
-    use super::*;
-    use crate::vmm_config::balloon::BalloonBuilder;
-    use crate::vmm_config::drive::{CacheType, FileEngineType};
-    use crate::vmm_config::logger::LoggerLevel;
-    use crate::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
-    use crate::vmm_config::vsock::VsockBuilder;
-    use crate::HTTP_MAX_PAYLOAD_SIZE;
+    /// Detaches the block device with id `drive_id` from the guest and forgets about it.
+    fn remove_block_device(&mut self, drive_id: &str) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .remove_block_device(drive_id)
+            .map_err(DriveError::DeviceRemoval)
+            .map_err(VmmActionError::DriveConfig)?;
+        self.vm_resources
+            .remove_block_device(drive_id)
+            .map_err(VmmActionError::DriveConfig)?;
+        self.refresh_device_tags();
+        Ok(VmmData::Empty)
+    }
+
+    /// Unplugs the net device with id `iface_id` from the guest and forgets about it.
+    fn remove_net_device(&mut self, iface_id: &str) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .remove_net_device(iface_id)
+            .map_err(NetworkInterfaceError::DeviceRemoval)
+            .map_err(VmmActionError::NetworkConfig)?;
+        self.vm_resources
+            .remove_net_device(iface_id)
+            .map_err(VmmActionError::NetworkConfig)?;
+        self.refresh_device_tags();
+        Ok(VmmData::Empty)
+    }
+
+    /// Keeps the device tags exposed to the guest through MMDS in sync with the devices. Only
+    /// needed when a device is attached or detached, or when its id or MAC address changes.
+    fn refresh_device_tags(&mut self) {
+        if let Some(mmds) = self.vm_resources.mmds.as_ref() {
+            let device_tags = self.vmm.lock().expect("Poisoned lock").device_tags();
+            mmds.lock()
+                .expect("Poisoned lock")
+                .set_device_tags(device_tags);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use devices::virtio::balloon::{BalloonConfig, Error as BalloonError};
+    use devices::virtio::mem::Error as MemError;
+    use devices::virtio::pmem::Error as PmemError;
+    use devices::virtio::{Balloon, Block, VsockError};
+    use mmds::data_store::MmdsVersion;
+    use seccompiler::BpfThreadMap;
+    use utils::net::mac::MacAddr;
+    use utils::tempdir::TempDir;
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::vmm_config::balloon::BalloonBuilder;
+    use crate::vmm_config::drive::{
+        BlockBuilder, CacheType, FileEngineType, ImageFormat, TraceFormat, TraceState,
+    };
+    use crate::vmm_config::logger::LoggerLevel;
+    use crate::vmm_config::machine_config::GuestMemoryBackend;
+    use crate::vmm_config::net::{CaptureState, NetBackendType, NetDatapath, NetOffloads};
+    use crate::vmm_config::snapshot::{
+        CloneConfig, MemBackendConfig, MemBackendType, ResumeMode, DEFAULT_SCHEDULE_SLOTS,
+    };
+    use crate::vmm_config::vsock::{VsockBuilder, VsockDatapath};
+    use crate::vmm_config::RateLimiterConfig;
+    use crate::HTTP_MAX_PAYLOAD_SIZE;
 
     impl PartialEq for VmmActionError {
         fn eq(&self, other: &VmmActionError) -> bool {
@@ -834,13 +1571,18 @@ mod tests {
                 (BalloonConfig(_), BalloonConfig(_))
                     | (BootSource(_), BootSource(_))
                     | (CreateSnapshot(_), CreateSnapshot(_))
+                    | (DescribeSnapshot(_), DescribeSnapshot(_))
                     | (DriveConfig(_), DriveConfig(_))
+                    | (FullVmConfig(_), FullVmConfig(_))
                     | (InternalVmm(_), InternalVmm(_))
                     | (LoadSnapshot(_), LoadSnapshot(_))
                     | (LoadSnapshotNotAllowed, LoadSnapshotNotAllowed)
                     | (Logger(_), Logger(_))
                     | (MachineConfig(_), MachineConfig(_))
+                    | (MemoryHotplugConfig(_), MemoryHotplugConfig(_))
+                    | (MergeSnapshot(_), MergeSnapshot(_))
                     | (Metrics(_), Metrics(_))
+                    | (Migration(_), Migration(_))
                     | (Mmds(_), Mmds(_))
                     | (MmdsLimitExceeded(_), MmdsLimitExceeded(_))
                     | (MmdsConfig(_), MmdsConfig(_))
@@ -848,6 +1590,8 @@ mod tests {
                     | (NotSupported(_), NotSupported(_))
                     | (OperationNotSupportedPostBoot, OperationNotSupportedPostBoot)
                     | (OperationNotSupportedPreBoot, OperationNotSupportedPreBoot)
+                    | (PmemConfig(_), PmemConfig(_))
+                    | (SnapshotSchedule(_), SnapshotSchedule(_))
                     | (StartMicrovm(_), StartMicrovm(_))
                     | (VsockConfig(_), VsockConfig(_))
             )
@@ -864,11 +1608,21 @@ mod tests {
         balloon_set: bool,
         boot_cfg_set: bool,
         block_set: bool,
+        block_removed: bool,
+        block_trace_set: bool,
+        memory_hotplug_set: bool,
         vsock_set: bool,
         net_set: bool,
+        net_removed: bool,
+        net_capture_set: bool,
+        pmem_set: bool,
+        rl_group_set: bool,
+        config_replaced: bool,
         pub mmds: Option<Arc<Mutex<Mmds>>>,
         pub mmds_size_limit: usize,
         pub boot_timer: bool,
+        pub logger: Option<LoggerConfig>,
+        pub metrics: Option<MetricsConfig>,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
@@ -911,6 +1665,40 @@ mod tests {
             Ok(())
         }
 
+        pub fn validate_vm_config(&self, _: &VmUpdateConfig) -> Result<(), VmConfigError> {
+            if self.force_errors {
+                return Err(VmConfigError::InvalidVcpuCount);
+            }
+            Ok(())
+        }
+
+        pub fn validate_balloon_device(
+            &self,
+            _: &BalloonDeviceConfig,
+        ) -> Result<(), BalloonConfigError> {
+            if self.force_errors {
+                return Err(BalloonConfigError::TooManyPagesRequested);
+            }
+            Ok(())
+        }
+
+        pub fn validate_block_device(&self, _: &BlockDeviceConfig) -> Result<(), DriveError> {
+            if self.force_errors {
+                return Err(DriveError::RootBlockDeviceAlreadyAdded);
+            }
+            Ok(())
+        }
+
+        pub fn validate_net_device(
+            &self,
+            _: &NetworkInterfaceConfig,
+        ) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::GuestMacAddressInUse(String::new()));
+            }
+            Ok(())
+        }
+
         pub fn set_balloon_device(
             &mut self,
             _: BalloonDeviceConfig,
@@ -922,6 +1710,28 @@ mod tests {
             Ok(())
         }
 
+        pub fn hotplug_balloon_device(
+            &mut self,
+            config: BalloonDeviceConfig,
+        ) -> Result<Arc<Mutex<Balloon>>, BalloonConfigError> {
+            if self.force_errors {
+                return Err(BalloonConfigError::DeviceAlreadyAttached);
+            }
+            self.balloon_set = true;
+            self.balloon.set(config)?;
+            Ok(self.balloon.get().unwrap().clone())
+        }
+
+        pub fn validate_balloon_device_hotplug(
+            &self,
+            _: &BalloonDeviceConfig,
+        ) -> Result<(), BalloonConfigError> {
+            if self.force_errors {
+                return Err(BalloonConfigError::DeviceAlreadyAttached);
+            }
+            Ok(())
+        }
+
         pub fn set_boot_source(
             &mut self,
             _: BootSourceConfig,
@@ -943,6 +1753,50 @@ mod tests {
             Ok(())
         }
 
+        pub fn hotplug_block_device(
+            &mut self,
+            config: BlockDeviceConfig,
+        ) -> Result<Arc<Mutex<Block>>, DriveError> {
+            if self.force_errors {
+                return Err(DriveError::DriveAlreadyAttached(String::new()));
+            }
+            self.block_set = true;
+            BlockBuilder::create_block(config).map(|block| Arc::new(Mutex::new(block)))
+        }
+
+        pub fn validate_block_device_hotplug(
+            &self,
+            _: &BlockDeviceConfig,
+        ) -> Result<(), DriveError> {
+            if self.force_errors {
+                return Err(DriveError::DriveAlreadyAttached(String::new()));
+            }
+            Ok(())
+        }
+
+        pub fn remove_block_device(&mut self, _: &str) -> Result<(), DriveError> {
+            if self.force_errors {
+                return Err(DriveError::DriveNotFound(String::new()));
+            }
+            self.block_removed = true;
+            Ok(())
+        }
+
+        pub fn set_block_trace(&mut self, _: &DriveTraceConfig) -> Result<(), DriveError> {
+            if self.force_errors {
+                return Err(DriveError::DriveNotFound(String::new()));
+            }
+            self.block_trace_set = true;
+            Ok(())
+        }
+
+        pub fn validate_block_device_removal(&self, _: &str) -> Result<(), DriveError> {
+            if self.force_errors {
+                return Err(DriveError::DriveNotFound(String::new()));
+            }
+            Ok(())
+        }
+
         pub fn build_net_device(
             &mut self,
             _: NetworkInterfaceConfig,
@@ -954,6 +1808,62 @@ mod tests {
             Ok(())
         }
 
+        pub fn validate_net_guest_mac(
+            &self,
+            _: &str,
+            _: &MacAddr,
+        ) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::GuestMacAddressInUse(String::new()));
+            }
+            Ok(())
+        }
+
+        pub fn validate_net_device_removal(&self, _: &str) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::InterfaceNotFound(String::new()));
+            }
+            Ok(())
+        }
+
+        pub fn set_net_capture(
+            &mut self,
+            _: &NetworkCaptureConfig,
+        ) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::InterfaceNotFound(String::new()));
+            }
+            self.net_capture_set = true;
+            Ok(())
+        }
+
+        pub fn remove_net_device(&mut self, _: &str) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::InterfaceNotFound(String::new()));
+            }
+            self.net_removed = true;
+            Ok(())
+        }
+
+        pub fn set_pmem_device(&mut self, _: PmemConfig) -> Result<(), PmemConfigError> {
+            if self.force_errors {
+                return Err(PmemConfigError::CreatePmemDevice(PmemError::InvalidSize(0)));
+            }
+            self.pmem_set = true;
+            Ok(())
+        }
+
+        pub fn set_memory_hotplug_config(
+            &mut self,
+            _: MemoryHotplugConfig,
+        ) -> Result<(), MemoryHotplugConfigError> {
+            if self.force_errors {
+                return Err(MemoryHotplugConfigError::InvalidTotalSize(0));
+            }
+            self.memory_hotplug_set = true;
+            Ok(())
+        }
+
         pub fn set_vsock_device(&mut self, _: VsockDeviceConfig) -> Result<(), VsockConfigError> {
             if self.force_errors {
                 return Err(VsockConfigError::CreateVsockDevice(
@@ -964,6 +1874,29 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_rate_limiter_group(&mut self, _: RateLimiterGroupConfig) {
+            self.rl_group_set = true;
+        }
+
+        pub fn replace_config(
+            &mut self,
+            _: VmmConfig,
+            _: &InstanceInfo,
+        ) -> Result<(), ResourcesError> {
+            if self.force_errors {
+                return Err(ResourcesError::VmConfig(VmConfigError::InvalidMemorySize));
+            }
+            self.config_replaced = true;
+            Ok(())
+        }
+
+        pub fn validate_config(&self, _: VmmConfig) -> Result<(), ResourcesError> {
+            if self.force_errors {
+                return Err(ResourcesError::VmConfig(VmConfigError::InvalidMemorySize));
+            }
+            Ok(())
+        }
+
         pub fn set_mmds_config(
             &mut self,
             mmds_config: MmdsConfig,
@@ -1009,10 +1942,32 @@ mod tests {
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
         pub send_ctrl_alt_del_called: bool,
+        #[cfg(target_arch = "x86_64")]
+        pub send_acpi_shutdown_called: bool,
         pub update_balloon_config_called: bool,
         pub update_balloon_stats_config_called: bool,
+        pub set_balloon_policy_called: bool,
+        pub memory_hotplug_status_called: bool,
+        pub update_memory_hotplug_called: bool,
         pub update_block_device_path_called: bool,
+        pub resize_block_device_called: bool,
+        pub set_block_device_read_only_called: bool,
+        pub add_block_device_called: bool,
+        pub add_balloon_device_called: bool,
+        pub remove_block_device_called: bool,
+        pub set_block_trace_called: bool,
+        pub update_block_read_write_rate_limiters_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub update_net_guest_mac_called: bool,
+        pub update_net_link_state_called: bool,
+        pub remove_net_device_called: bool,
+        pub set_net_capture_called: bool,
+        pub net_stats_called: bool,
+        pub sample_dirty_rate_called: bool,
+        pub vsock_connections_called: bool,
+        pub pool_vsock_connections_called: bool,
+        pub schedule_snapshots_called: bool,
+        pub cancel_snapshot_schedule_called: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
@@ -1045,6 +2000,17 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(target_arch = "x86_64")]
+        pub fn send_acpi_shutdown(&mut self, _: Option<Duration>) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.send_acpi_shutdown_called = true;
+            Ok(())
+        }
+
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1077,6 +2043,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_balloon_policy(&mut self, _: Option<BalloonPolicy>) -> Result<(), BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::DeviceNotFound);
+            }
+            self.set_balloon_policy_called = true;
+            Ok(())
+        }
+
         pub fn update_block_device_path(&mut self, _: &str, _: String) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -1087,6 +2061,26 @@ mod tests {
             Ok(())
         }
 
+        pub fn resize_block_device(&mut self, _: &str, _: u64) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.resize_block_device_called = true;
+            Ok(())
+        }
+
+        pub fn set_block_device_read_only(&mut self, _: &str, _: bool) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.set_block_device_read_only_called = true;
+            Ok(())
+        }
+
         pub fn update_block_rate_limiter(
             &mut self,
             _: &str,
@@ -1096,13 +2090,26 @@ mod tests {
             Ok(())
         }
 
+        pub fn update_block_read_write_rate_limiters(
+            &mut self,
+            _: &str,
+            _: RateLimiterUpdate,
+            _: RateLimiterUpdate,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_block_read_write_rate_limiters_called = true;
+            Ok(())
+        }
+
         pub fn update_net_rate_limiters(
             &mut self,
             _: &str,
-            _: rate_limiter::BucketUpdate,
-            _: rate_limiter::BucketUpdate,
-            _: rate_limiter::BucketUpdate,
-            _: rate_limiter::BucketUpdate,
+            _: RateLimiterUpdate,
+            _: RateLimiterUpdate,
         ) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -1113,53 +2120,290 @@ mod tests {
             Ok(())
         }
 
-        pub fn instance_info(&self) -> InstanceInfo {
-            InstanceInfo::default()
+        pub fn update_net_guest_mac(&mut self, _: &str, _: MacAddr) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_net_guest_mac_called = true;
+            Ok(())
         }
 
-        pub fn version(&self) -> String {
-            String::default()
+        pub fn update_net_link_state(&mut self, _: &str, _: bool) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_net_link_state_called = true;
+            Ok(())
         }
-    }
 
-    // Need to redefine this since the non-test one uses real VmResources
-    // and real Vmm instead of our mocks.
-    pub fn build_microvm_for_boot(
-        _: &InstanceInfo,
-        _: &VmResources,
-        _: &mut EventManager,
-        _: &BpfThreadMap,
-    ) -> Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
-        Ok(Arc::new(Mutex::new(MockVmm::default())))
-    }
+        pub fn remove_net_device(&mut self, _: &str) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.remove_net_device_called = true;
+            Ok(())
+        }
 
-    // Need to redefine this since the non-test one uses real Vmm
-    // instead of our mocks.
-    pub fn create_snapshot(
-        _: &mut Vmm,
-        _: &CreateSnapshotParams,
-        _: versionize::VersionMap,
-    ) -> std::result::Result<(), CreateSnapshotError> {
-        Ok(())
-    }
+        pub fn add_block_device(&mut self, _: Arc<Mutex<Block>>) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::VcpuMessage);
+            }
+            self.add_block_device_called = true;
+            Ok(())
+        }
 
-    // Need to redefine this since the non-test one uses real Vmm
-    // instead of our mocks.
-    pub fn restore_from_snapshot(
-        _: &InstanceInfo,
-        _: &mut EventManager,
-        _: &BpfThreadMap,
-        _: &LoadSnapshotParams,
-        _: versionize::VersionMap,
-        _: &mut MockVmRes,
-    ) -> Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
-        Ok(Arc::new(Mutex::new(MockVmm::default())))
-    }
+        pub fn add_balloon_device(&mut self, _: Arc<Mutex<Balloon>>) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::VcpuMessage);
+            }
+            self.add_balloon_device_called = true;
+            Ok(())
+        }
 
-    fn default_preboot<'a>(
-        vm_resources: &'a mut VmResources,
-        event_manager: &'a mut EventManager,
-        seccomp_filters: &'a BpfThreadMap,
+        pub fn remove_block_device(&mut self, _: &str) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.remove_block_device_called = true;
+            Ok(())
+        }
+
+        pub fn validate_block_device_removal(&self, _: &str) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            Ok(())
+        }
+
+        pub fn set_block_trace(&mut self, _: &DriveTraceConfig) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.set_block_trace_called = true;
+            Ok(())
+        }
+
+        pub fn set_net_capture(&mut self, _: &NetworkCaptureConfig) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.set_net_capture_called = true;
+            Ok(())
+        }
+
+        pub fn net_stats(&mut self, _: &str) -> Result<NetStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.net_stats_called = true;
+            Ok(NetStats::default())
+        }
+
+        pub fn sample_dirty_rate(&mut self) -> Result<DirtyRate, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DirtyBitmap(kvm_ioctls::Error::new(libc::ENOENT)));
+            }
+            self.sample_dirty_rate_called = true;
+            Ok(DirtyRate::default())
+        }
+
+        pub fn schedule_snapshots(&mut self, _: SnapshotScheduleConfig) {
+            self.schedule_snapshots_called = true;
+        }
+
+        pub fn cancel_snapshot_schedule(&mut self) {
+            self.cancel_snapshot_schedule_called = true;
+        }
+
+        pub fn vsock_connections(&mut self, _: &str) -> Result<Vec<VsockConnectionInfo>, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.vsock_connections_called = true;
+            Ok(Vec::new())
+        }
+
+        pub fn pool_vsock_connections(
+            &mut self,
+            _: &str,
+            _: &VsockConnectionPoolConfig,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.pool_vsock_connections_called = true;
+            Ok(())
+        }
+
+        pub fn validate_net_device_removal(&self, _: &str) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            Ok(())
+        }
+
+        pub fn validate_balloon_config(&self, _: u32) -> Result<(), BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::DeviceNotFound);
+            }
+            Ok(())
+        }
+
+        pub fn validate_balloon_stats_config(&self, _: u16) -> Result<(), BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::DeviceNotFound);
+            }
+            Ok(())
+        }
+
+        pub fn memory_hotplug_status(&mut self) -> Result<MemoryHotplugStatus, MemError> {
+            if self.force_errors {
+                return Err(MemError::DeviceNotFound);
+            }
+            self.memory_hotplug_status_called = true;
+            Ok(MemoryHotplugStatus::default())
+        }
+
+        pub fn update_memory_hotplug(&mut self, _: usize) -> Result<(), MemError> {
+            if self.force_errors {
+                return Err(MemError::DeviceNotFound);
+            }
+            self.update_memory_hotplug_called = true;
+            Ok(())
+        }
+
+        pub fn validate_memory_hotplug_update(&self, _: usize) -> Result<(), MemError> {
+            if self.force_errors {
+                return Err(MemError::InvalidRequestedSize(0));
+            }
+            Ok(())
+        }
+
+        pub fn validate_block_device_update(
+            &self,
+            _: &str,
+            _: Option<&str>,
+            _: Option<u64>,
+            _: bool,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            Ok(())
+        }
+
+        pub fn validate_net_device_update(
+            &self,
+            _: &str,
+            _: bool,
+            _: bool,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            Ok(())
+        }
+
+        pub fn device_tags(&self) -> serde_json::Value {
+            serde_json::Value::Null
+        }
+
+        pub fn instance_info(&self) -> InstanceInfo {
+            InstanceInfo::default()
+        }
+
+        pub fn version(&self) -> String {
+            String::default()
+        }
+    }
+
+    // Need to redefine this since the non-test one uses real VmResources
+    // and real Vmm instead of our mocks.
+    pub fn build_microvm_for_boot(
+        _: &InstanceInfo,
+        _: &VmResources,
+        _: &mut EventManager,
+        _: &BpfThreadMap,
+    ) -> Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
+        Ok(Arc::new(Mutex::new(MockVmm::default())))
+    }
+
+    // Need to redefine this since the non-test one uses real Vmm
+    // instead of our mocks.
+    pub fn create_snapshot(
+        _: &mut Vmm,
+        _: &CreateSnapshotParams,
+        _: versionize::VersionMap,
+    ) -> std::result::Result<(), CreateSnapshotError> {
+        Ok(())
+    }
+
+    // Need to redefine this since the non-test one uses real Vmm
+    // instead of our mocks.
+    pub fn restore_from_snapshot(
+        _: &InstanceInfo,
+        _: &mut EventManager,
+        _: &BpfThreadMap,
+        _: &LoadSnapshotParams,
+        _: versionize::VersionMap,
+        _: &mut MockVmRes,
+    ) -> Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
+        Ok(Arc::new(Mutex::new(MockVmm::default())))
+    }
+
+    // Need to redefine this since the non-test one uses real Vmm
+    // instead of our mocks.
+    pub fn send_microvm(
+        _: &mut Vmm,
+        _: &MigrationSendConfig,
+        _: versionize::VersionMap,
+    ) -> std::result::Result<(), MigrationError> {
+        Ok(())
+    }
+
+    // Need to redefine this since the non-test one uses real VmResources
+    // and real Vmm instead of our mocks.
+    pub fn receive_microvm(
+        _: &InstanceInfo,
+        _: &mut EventManager,
+        _: &BpfThreadMap,
+        _: &MigrationReceiveConfig,
+        _: versionize::VersionMap,
+        _: &mut MockVmRes,
+    ) -> std::result::Result<Arc<Mutex<Vmm>>, MigrationError> {
+        Ok(Arc::new(Mutex::new(MockVmm::default())))
+    }
+
+    fn default_preboot<'a>(
+        vm_resources: &'a mut VmResources,
+        event_manager: &'a mut EventManager,
+        seccomp_filters: &'a BpfThreadMap,
     ) -> PrebootApiController<'a> {
         let instance_info = InstanceInfo::default();
         PrebootApiController::new(seccomp_filters, instance_info, vm_resources, event_manager)
@@ -1287,13 +2531,22 @@ mod tests {
     fn test_preboot_insert_block_dev() {
         let req = VmmAction::InsertBlockDevice(BlockDeviceConfig {
             path_on_host: String::new(),
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::new(),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1302,13 +2555,22 @@ mod tests {
 
         let req = VmmAction::InsertBlockDevice(BlockDeviceConfig {
             path_on_host: String::new(),
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::new(),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         });
         check_preboot_request_err(
             req,
@@ -1324,6 +2586,18 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1336,6 +2610,18 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
         });
         check_preboot_request_err(
             req,
@@ -1346,90 +2632,376 @@ mod tests {
     }
 
     #[test]
-    fn test_preboot_set_vsock_dev() {
-        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
-            vsock_id: Some(String::new()),
-            guest_cid: 0,
-            uds_path: String::new(),
-        });
+    fn test_preboot_remove_net_dev() {
+        let req = VmmAction::RemoveNetworkDevice(String::new());
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vm_res.vsock_set)
+            assert!(vm_res.net_removed)
         });
 
-        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
-            vsock_id: Some(String::new()),
-            guest_cid: 0,
-            uds_path: String::new(),
-        });
+        let req = VmmAction::RemoveNetworkDevice(String::new());
         check_preboot_request_err(
             req,
-            VmmActionError::VsockConfig(VsockConfigError::CreateVsockDevice(
-                VsockError::BufDescMissing,
-            )),
+            VmmActionError::NetworkConfig(NetworkInterfaceError::InterfaceNotFound(String::new())),
         );
     }
 
     #[test]
-    fn test_preboot_set_mmds_config() {
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
-            ipv4_address: None,
-            version: MmdsVersion::V2,
-            network_interfaces: Vec::new(),
-        });
+    fn test_preboot_remove_block_dev() {
+        let req = VmmAction::RemoveBlockDevice(String::new());
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert_eq!(
-                vm_res.mmds.as_ref().unwrap().lock().unwrap().version(),
-                MmdsVersion::V2
-            );
+            assert!(vm_res.block_removed)
         });
 
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
-            ipv4_address: None,
-            version: MmdsVersion::default(),
-            network_interfaces: Vec::new(),
-        });
+        let req = VmmAction::RemoveBlockDevice(String::new());
         check_preboot_request_err(
             req,
-            VmmActionError::MmdsConfig(MmdsConfigError::InvalidIpv4Addr),
+            VmmActionError::DriveConfig(DriveError::DriveNotFound(String::new())),
         );
     }
 
-    #[test]
-    fn test_preboot_get_mmds() {
-        check_preboot_request(VmmAction::GetMMDS, |result, _| {
-            assert_eq!(result, Ok(VmmData::MmdsValue(Value::Null)));
-        });
+    fn trace_config() -> DriveTraceConfig {
+        DriveTraceConfig {
+            drive_id: String::new(),
+            state: TraceState::Started,
+            path_on_host: Some(String::new()),
+            format: TraceFormat::Jsonl,
+            max_records_per_sec: 1,
+        }
     }
 
     #[test]
-    fn test_runtime_get_mmds() {
-        check_runtime_request(VmmAction::GetMMDS, |result, _| {
-            assert_eq!(result, Ok(VmmData::MmdsValue(Value::Null)));
+    fn test_preboot_set_drive_trace() {
+        let req = VmmAction::SetDriveTrace(trace_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.block_trace_set)
         });
+
+        let req = VmmAction::SetDriveTrace(trace_config());
+        check_preboot_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DriveNotFound(String::new())),
+        );
+    }
+
+    fn capture_config() -> NetworkCaptureConfig {
+        NetworkCaptureConfig {
+            iface_id: String::new(),
+            state: CaptureState::Started,
+            path_on_host: Some(String::new()),
+            max_file_size_mib: None,
+            file_count: 1,
+        }
     }
 
     #[test]
-    fn test_preboot_put_mmds() {
-        let mmds = Arc::new(Mutex::new(Mmds::default()));
+    fn test_preboot_set_net_capture() {
+        let req = VmmAction::SetNetworkCapture(capture_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.net_capture_set)
+        });
 
-        check_preboot_request_with_mmds(
-            VmmAction::PutMMDS(Value::String("string".to_string())),
-            mmds.clone(),
-            |result, _| {
-                assert_eq!(result, Ok(VmmData::Empty));
-            },
+        let req = VmmAction::SetNetworkCapture(capture_config());
+        check_preboot_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::InterfaceNotFound(String::new())),
         );
-        check_preboot_request_with_mmds(VmmAction::GetMMDS, mmds.clone(), |result, _| {
-            assert_eq!(
-                result,
-                Ok(VmmData::MmdsValue(Value::String("string".to_string())))
-            );
-        });
+    }
 
-        let filling = (0..51300).map(|_| "X").collect::<String>();
-        let data = "{\"key\": \"".to_string() + &filling + "\"}";
+    fn pmem_config() -> PmemConfig {
+        PmemConfig {
+            pmem_id: String::new(),
+            path_on_host: String::new(),
+            is_read_only: false,
+        }
+    }
+
+    #[test]
+    fn test_preboot_insert_pmem_dev() {
+        let req = VmmAction::InsertPmemDevice(pmem_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.pmem_set)
+        });
+
+        let req = VmmAction::InsertPmemDevice(pmem_config());
+        check_preboot_request_err(
+            req,
+            VmmActionError::PmemConfig(PmemConfigError::CreatePmemDevice(PmemError::InvalidSize(
+                0,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_memory_hotplug() {
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        };
+        let req = VmmAction::SetMemoryHotplug(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.memory_hotplug_set)
+        });
+
+        let req = VmmAction::SetMemoryHotplug(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::InvalidTotalSize(0)),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_vsock_dev() {
+        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
+            vsock_id: Some(String::new()),
+            guest_cid: 0,
+            uds_path: String::new(),
+            backend: VsockDatapath::Virtio,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            uds_permissions: None,
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.vsock_set)
+        });
+
+        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
+            vsock_id: Some(String::new()),
+            guest_cid: 0,
+            uds_path: String::new(),
+            backend: VsockDatapath::Virtio,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            uds_permissions: None,
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::VsockConfig(VsockConfigError::CreateVsockDevice(
+                VsockError::BufDescMissing,
+            )),
+        );
+    }
+
+    fn rl_group_config() -> RateLimiterGroupConfig {
+        RateLimiterGroupConfig {
+            group_id: String::new(),
+            bandwidth: None,
+            ops: None,
+        }
+    }
+
+    #[test]
+    fn test_preboot_set_rate_limiter_group() {
+        let req = VmmAction::SetRateLimiterGroup(rl_group_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.rl_group_set)
+        });
+    }
+
+    #[test]
+    fn test_preboot_put_full_vm_config() {
+        let req = VmmAction::PutFullVmConfig(Box::new(VmmConfig::default()));
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.config_replaced)
+        });
+
+        let req = VmmAction::PutFullVmConfig(Box::new(VmmConfig::default()));
+        check_preboot_request_err(
+            req,
+            VmmActionError::FullVmConfig(ResourcesError::VmConfig(
+                VmConfigError::InvalidMemorySize,
+            )),
+        );
+    }
+
+    #[test]
+    fn test_preboot_dry_run() {
+        let net_cfg = NetworkInterfaceConfig {
+            iface_id: String::new(),
+            host_dev_name: String::new(),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
+        };
+        let block_cfg = BlockDeviceConfig {
+            path_on_host: String::new(),
+            fd: None,
+            serial: None,
+            is_root_device: false,
+            partuuid: None,
+            cache_type: CacheType::Unsafe,
+            is_read_only: false,
+            drive_id: String::new(),
+            rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
+            file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
+        };
+        let dry_run_reqs = vec![
+            VmmAction::InsertBlockDevice(block_cfg),
+            VmmAction::InsertNetworkDevice(net_cfg),
+            VmmAction::RemoveBlockDevice(String::new()),
+            VmmAction::RemoveNetworkDevice(String::new()),
+            VmmAction::SetBalloonDevice(BalloonDeviceConfig::default()),
+            VmmAction::UpdateVmConfiguration(VmUpdateConfig::from(VmConfig::default())),
+            VmmAction::PutFullVmConfig(Box::new(VmmConfig::default())),
+        ];
+
+        for req in dry_run_reqs {
+            let mut vm_resources = MockVmRes::default();
+            let mut evmgr = EventManager::new().unwrap();
+            let seccomp_filters = BpfThreadMap::new();
+            let mut preboot = default_preboot(&mut vm_resources, &mut evmgr, &seccomp_filters);
+            assert_eq!(
+                preboot.handle_preboot_request(VmmAction::DryRun(Box::new(req))),
+                Ok(VmmData::DryRun)
+            );
+            // A dry run doesn't prevent loading a snapshot.
+            assert!(!preboot.boot_path);
+            // Nothing got applied.
+            assert!(!vm_resources.block_set);
+            assert!(!vm_resources.block_removed);
+            assert!(!vm_resources.net_set);
+            assert!(!vm_resources.net_removed);
+            assert!(!vm_resources.balloon_set);
+            assert!(!vm_resources.config_replaced);
+            assert_eq!(vm_resources.vm_config, VmConfig::default());
+        }
+
+        // Validation errors are reported as they would be by the actual request.
+        let req = VmmAction::DryRun(Box::new(VmmAction::SetBalloonDevice(
+            BalloonDeviceConfig::default(),
+        )));
+        check_preboot_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::TooManyPagesRequested),
+        );
+        let req = VmmAction::DryRun(Box::new(VmmAction::UpdateVmConfiguration(
+            VmUpdateConfig::from(VmConfig::default()),
+        )));
+        check_preboot_request_err(
+            req,
+            VmmActionError::MachineConfig(VmConfigError::InvalidVcpuCount),
+        );
+        let req = VmmAction::DryRun(Box::new(VmmAction::PutFullVmConfig(Box::new(
+            VmmConfig::default(),
+        ))));
+        check_preboot_request_err(
+            req,
+            VmmActionError::FullVmConfig(ResourcesError::VmConfig(
+                VmConfigError::InvalidMemorySize,
+            )),
+        );
+
+        // Runtime only requests.
+        let req = VmmAction::DryRun(Box::new(VmmAction::UpdateBalloon(BalloonUpdateConfig {
+            amount_mib: 0,
+        })));
+        check_preboot_request(req, |result, _| {
+            assert_eq!(result, Err(VmmActionError::OperationNotSupportedPreBoot));
+        });
+
+        // Requests without a dry run.
+        let req = VmmAction::DryRun(Box::new(VmmAction::StartMicroVm));
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Err(VmmActionError::NotSupported(String::new())));
+            assert!(!vm_res.boot_cfg_set);
+        });
+        let req = VmmAction::DryRun(Box::new(VmmAction::DryRun(Box::new(
+            VmmAction::StartMicroVm,
+        ))));
+        check_preboot_request(req, |result, _| {
+            assert_eq!(result, Err(VmmActionError::NotSupported(String::new())));
+        });
+    }
+
+    #[test]
+    fn test_preboot_set_mmds_config() {
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V2,
+            network_interfaces: Vec::new(),
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(
+                vm_res.mmds.as_ref().unwrap().lock().unwrap().version(),
+                MmdsVersion::V2
+            );
+        });
+
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::default(),
+            network_interfaces: Vec::new(),
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::MmdsConfig(MmdsConfigError::InvalidIpv4Addr),
+        );
+    }
+
+    #[test]
+    fn test_preboot_get_mmds() {
+        check_preboot_request(VmmAction::GetMMDS, |result, _| {
+            assert_eq!(result, Ok(VmmData::MmdsValue(Value::Null)));
+        });
+    }
+
+    #[test]
+    fn test_runtime_get_mmds() {
+        check_runtime_request(VmmAction::GetMMDS, |result, _| {
+            assert_eq!(result, Ok(VmmData::MmdsValue(Value::Null)));
+        });
+    }
+
+    #[test]
+    fn test_preboot_put_mmds() {
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+
+        check_preboot_request_with_mmds(
+            VmmAction::PutMMDS(Value::String("string".to_string())),
+            mmds.clone(),
+            |result, _| {
+                assert_eq!(result, Ok(VmmData::Empty));
+            },
+        );
+        check_preboot_request_with_mmds(VmmAction::GetMMDS, mmds.clone(), |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MmdsValue(Value::String("string".to_string())))
+            );
+        });
+
+        let filling = (0..51300).map(|_| "X").collect::<String>();
+        let data = "{\"key\": \"".to_string() + &filling + "\"}";
 
         check_preboot_request_with_mmds(
             VmmAction::PutMMDS(serde_json::from_str(&data).unwrap()),
@@ -1548,6 +3120,84 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_preboot_set_mmds_identity() {
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        let key_file = TempFile::new().unwrap();
+        let req = || {
+            VmmAction::SetMmdsIdentity(MmdsIdentityConfig {
+                signing_key_path: key_file.as_path().to_path_buf(),
+            })
+        };
+
+        // The key is too short.
+        check_preboot_request_with_mmds(req(), mmds.clone(), |result, _| {
+            assert!(matches!(
+                result,
+                Err(VmmActionError::MmdsConfig(MmdsConfigError::IdentitySigner(
+                    _
+                )))
+            ));
+        });
+
+        key_file.as_file().write_all(&[0xaa; 32]).unwrap();
+        check_preboot_request_with_mmds(req(), mmds.clone(), |result, _| {
+            assert_eq!(result, Ok(VmmData::Empty));
+        });
+        // The signed document is only exposed to the guest.
+        check_preboot_request_with_mmds(VmmAction::GetMMDS, mmds.clone(), |result, _| {
+            assert_eq!(result, Ok(VmmData::MmdsValue(Value::Null)));
+        });
+        assert!(mmds
+            .lock()
+            .unwrap()
+            .get_value(
+                "/fc-identity/signature".to_string(),
+                mmds::data_store::OutputFormat::Imds
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_preboot_patch_mmds_operations() {
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        check_preboot_request_with_mmds(
+            VmmAction::PutMMDS(serde_json::from_str(r#"{"key1": "value1"}"#).unwrap()),
+            mmds.clone(),
+            |result, _| {
+                assert_eq!(result, Ok(VmmData::Empty));
+            },
+        );
+
+        let operations = |value: &str| {
+            VmmAction::PatchMMDSOperations(
+                serde_json::from_str(&format!(
+                    r#"[
+                        {{"op": "test", "path": "/key1", "value": "{}"}},
+                        {{"op": "remove", "path": "/key1"}}
+                    ]"#,
+                    value
+                ))
+                .unwrap(),
+            )
+        };
+        check_preboot_request_with_mmds(operations("value2"), mmds.clone(), |result, _| {
+            assert!(matches!(
+                result,
+                Err(VmmActionError::Mmds(data_store::Error::PatchTestFailed(_)))
+            ));
+        });
+        check_preboot_request_with_mmds(operations("value1"), mmds.clone(), |result, _| {
+            assert_eq!(result, Ok(VmmData::Empty));
+        });
+        check_preboot_request_with_mmds(VmmAction::GetMMDS, mmds, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MmdsValue(serde_json::from_str("{}").unwrap()))
+            );
+        });
+    }
+
     #[test]
     fn test_runtime_patch_mmds() {
         let mmds = Arc::new(Mutex::new(Mmds::default()));
@@ -1624,12 +3274,21 @@ mod tests {
         // Without resume.
         let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
             snapshot_path: PathBuf::new(),
+            snapshot_fd: None,
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
             },
+            mem_file_fd: None,
             enable_diff_snapshots: false,
             resume_vm: false,
+            resume_mode: ResumeMode::Eager,
+            network_overrides: vec![],
+            drive_overrides: vec![],
+            skip_verify: false,
+            guest_mem_backend: GuestMemoryBackend::Anonymous,
+            clone: None,
+            mmds_data: None,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1640,12 +3299,21 @@ mod tests {
         // With resume.
         let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
             snapshot_path: PathBuf::new(),
+            snapshot_fd: None,
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
             },
+            mem_file_fd: None,
             enable_diff_snapshots: false,
             resume_vm: true,
+            resume_mode: ResumeMode::Eager,
+            network_overrides: vec![],
+            drive_overrides: vec![],
+            skip_verify: false,
+            guest_mem_backend: GuestMemoryBackend::Anonymous,
+            clone: None,
+            mmds_data: None,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1654,35 +3322,169 @@ mod tests {
         assert!(vmm.resume_called);
         // Extra sanity check - pause was never called.
         assert!(!vmm.pause_called);
+        drop(vmm);
+
+        // As a clone, with its own ID.
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr, &seccomp_filters);
+        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            snapshot_fd: None,
+            mem_backend: MemBackendConfig {
+                backend_type: MemBackendType::File,
+                backend_path: PathBuf::new(),
+            },
+            mem_file_fd: None,
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            resume_mode: ResumeMode::Eager,
+            network_overrides: vec![],
+            drive_overrides: vec![],
+            skip_verify: false,
+            guest_mem_backend: GuestMemoryBackend::Anonymous,
+            clone: Some(CloneConfig {
+                instance_id: "clone-1".to_string(),
+                guest_macs: vec![],
+            }),
+            mmds_data: None,
+        });
+        preboot.handle_preboot_request(req).unwrap();
+        assert_eq!(preboot.instance_info.id, "clone-1");
     }
 
     #[test]
-    fn test_preboot_disallowed() {
-        check_preboot_request_err(
-            VmmAction::FlushMetrics,
-            VmmActionError::OperationNotSupportedPreBoot,
-        );
-        check_preboot_request_err(
-            VmmAction::Pause,
-            VmmActionError::OperationNotSupportedPreBoot,
-        );
-        check_preboot_request_err(
-            VmmAction::Resume,
-            VmmActionError::OperationNotSupportedPreBoot,
-        );
-        check_preboot_request_err(
-            VmmAction::GetBalloonStats,
-            VmmActionError::OperationNotSupportedPreBoot,
-        );
-        check_preboot_request_err(
-            VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
-            VmmActionError::OperationNotSupportedPreBoot,
-        );
-        check_preboot_request_err(
-            VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
-                stats_polling_interval_s: 0,
-            }),
-            VmmActionError::OperationNotSupportedPreBoot,
+    fn test_preboot_receive_migration() {
+        let mut vm_resources = MockVmRes::default();
+        let mut evmgr = EventManager::new().unwrap();
+        let seccomp_filters = BpfThreadMap::new();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr, &seccomp_filters);
+
+        let req = VmmAction::ReceiveMigration(MigrationReceiveConfig {
+            listen_address: "0.0.0.0:7070".parse().unwrap(),
+            track_dirty_pages: true,
+            resume_vm: true,
+        });
+        // Request should succeed.
+        preboot.handle_preboot_request(req).unwrap();
+        // Should have built default mock vmm.
+        let vmm = preboot.built_vmm.take().unwrap();
+        assert_eq!(*vmm.lock().unwrap(), MockVmm::default());
+        assert!(vm_resources.track_dirty_pages());
+
+        // Receiving a microVM is not allowed after configuring boot-specific resources.
+        let mut vm_resources = MockVmRes::default();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr, &seccomp_filters);
+        preboot
+            .handle_preboot_request(VmmAction::ConfigureBootSource(BootSourceConfig::default()))
+            .unwrap();
+        let err =
+            preboot.handle_preboot_request(VmmAction::ReceiveMigration(MigrationReceiveConfig {
+                listen_address: "0.0.0.0:7070".parse().unwrap(),
+                track_dirty_pages: false,
+                resume_vm: false,
+            }));
+        assert_eq!(err, Err(VmmActionError::LoadSnapshotNotAllowed));
+    }
+
+    #[test]
+    fn test_merge_snapshot() {
+        let req = || {
+            VmmAction::MergeSnapshot(MergeSnapshotParams {
+                snapshot_path: PathBuf::from("/no/such/snapshot"),
+                mem_file_path: PathBuf::new(),
+                output_mem_file_path: PathBuf::new(),
+            })
+        };
+        let expected_err =
+            || VmmActionError::MergeSnapshot(MergeSnapshotError::ChainLoop(String::new()));
+
+        // The request is only served before boot, the missing files make it fail.
+        check_preboot_request_err(req(), expected_err());
+        check_runtime_request_err(req(), VmmActionError::OperationNotSupportedPostBoot);
+    }
+
+    #[test]
+    fn test_describe_snapshot() {
+        let req = || VmmAction::DescribeSnapshot(PathBuf::from("/no/such/snapshot"));
+        let expected_err = || {
+            VmmActionError::DescribeSnapshot(LoadSnapshotError::SnapshotBackingFile(
+                "open",
+                std::io::Error::from_raw_os_error(0),
+            ))
+        };
+
+        // The request is served before and after boot, the missing file makes it fail.
+        check_preboot_request_err(req(), expected_err());
+        check_runtime_request_err(req(), expected_err());
+    }
+
+    #[test]
+    fn test_preboot_disallowed() {
+        check_preboot_request_err(
+            VmmAction::FlushMetrics,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::Pause,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::Resume,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetBalloonStats,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetDirtyRate,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::CancelSnapshotSchedule,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetMemoryHotplugStatus,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateMemoryHotplug(MemoryHotplugSizeUpdate {
+                requested_size_mib: 0,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetNetworkInterfaceStats(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetVsockConnections(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::PoolVsockConnections(
+                String::new(),
+                VsockConnectionPoolConfig { port: 52, count: 4 },
+            ),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::RemoveBalloonPolicy,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::SetBalloonPolicy(BalloonPolicy::default()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
+                stats_polling_interval_s: 0,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
             VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig::default()),
@@ -1691,6 +3493,8 @@ mod tests {
         check_preboot_request_err(
             VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
                 iface_id: String::new(),
+                guest_mac: None,
+                link_up: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
             }),
@@ -1700,7 +3504,12 @@ mod tests {
             VmmAction::CreateSnapshot(CreateSnapshotParams {
                 snapshot_type: SnapshotType::Full,
                 snapshot_path: PathBuf::new(),
+                snapshot_fd: None,
                 mem_file_path: PathBuf::new(),
+                mem_file_fd: None,
+                compression: None,
+                mem_writer_threads: None,
+                exclude_mmds: false,
                 version: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1710,6 +3519,28 @@ mod tests {
             VmmAction::SendCtrlAltDel,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        #[cfg(target_arch = "x86_64")]
+        check_preboot_request_err(
+            VmmAction::SendAcpiShutdown(None),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::SendMigration(MigrationSendConfig {
+                destination: "127.0.0.1:7070".parse().unwrap(),
+                max_iterations: 10,
+                max_dirty_pages: 1024,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::ScheduleSnapshots(SnapshotScheduleConfig {
+                interval_s: 60,
+                snapshot_dir: PathBuf::from("/snapshots"),
+                slots: DEFAULT_SCHEDULE_SLOTS,
+                max_pause_ms: None,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
     }
 
     #[test]
@@ -1789,195 +3620,865 @@ mod tests {
         };
         let vmm = Arc::new(Mutex::new(MockVmm::default()));
         let mut runtime = RuntimeApiController::new(vm_res, vmm.clone());
-        let res = runtime.handle_request(request);
-        check_success(res, &vmm.lock().unwrap());
-    }
-
-    // Forces error and validates error kind against expected.
-    fn check_runtime_request_err(request: VmmAction, expected_err: VmmActionError) {
-        let vmm = Arc::new(Mutex::new(MockVmm {
-            force_errors: true,
-            ..Default::default()
-        }));
-        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm);
-        let err = runtime.handle_request(request).unwrap_err();
-        assert_eq!(err, expected_err);
+        let res = runtime.handle_request(request);
+        check_success(res, &vmm.lock().unwrap());
+    }
+
+    // Forces error and validates error kind against expected.
+    fn check_runtime_request_err(request: VmmAction, expected_err: VmmActionError) {
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            force_errors: true,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm);
+        let err = runtime.handle_request(request).unwrap_err();
+        assert_eq!(err, expected_err);
+    }
+
+    #[test]
+    fn test_runtime_get_vm_config() {
+        let req = VmmAction::GetVmMachineConfig;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MachineConfiguration(VmConfig::default()))
+            );
+        });
+    }
+
+    #[test]
+    fn test_runtime_pause() {
+        let req = VmmAction::Pause;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.pause_called)
+        });
+
+        let req = VmmAction::Pause;
+        check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuPause));
+    }
+
+    #[test]
+    fn test_runtime_resume() {
+        let req = VmmAction::Resume;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.resume_called)
+        });
+
+        let req = VmmAction::Resume;
+        check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuResume));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_ctrl_alt_del() {
+        let req = VmmAction::SendCtrlAltDel;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.send_ctrl_alt_del_called)
+        });
+
+        let req = VmmAction::SendCtrlAltDel;
+        check_runtime_request_err(
+            req,
+            VmmActionError::InternalVmm(VmmError::I8042Error(
+                devices::legacy::I8042DeviceError::InternalBufferFull,
+            )),
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_acpi_shutdown() {
+        let req = VmmAction::SendAcpiShutdown(Some(Duration::from_secs(10)));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.send_acpi_shutdown_called)
+        });
+
+        let req = VmmAction::SendAcpiShutdown(None);
+        check_runtime_request_err(
+            req,
+            VmmActionError::InternalVmm(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::DeviceNotFound,
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_balloon_config() {
+        let req = VmmAction::GetBalloonConfig;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::BalloonConfig(BalloonDeviceConfig::default()))
+            );
+            assert!(vmm.balloon_config_called)
+        });
+
+        let req = VmmAction::GetBalloonConfig;
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[test]
+    fn test_runtime_latest_balloon_stats() {
+        let req = VmmAction::GetBalloonStats;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::BalloonStats(BalloonStats::default())));
+            assert!(vmm.latest_balloon_stats_called)
+        });
+
+        let req = VmmAction::GetBalloonStats;
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_balloon_config() {
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_balloon_config_called)
+        });
+
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 });
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_balloon_stats_config() {
+        let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
+            stats_polling_interval_s: 0,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_balloon_stats_config_called)
+        });
+
+        let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
+            stats_polling_interval_s: 0,
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[test]
+    fn test_runtime_set_balloon_policy() {
+        let req = VmmAction::SetBalloonPolicy(BalloonPolicy::default());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_balloon_policy_called)
+        });
+
+        let req = VmmAction::RemoveBalloonPolicy;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_balloon_policy_called)
+        });
+
+        let req = VmmAction::SetBalloonPolicy(BalloonPolicy::default());
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_block_device_path() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            path_on_host: Some(String::new()),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_block_device_path_called)
+        });
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            path_on_host: Some(String::new()),
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_resize_block_device() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            size_bytes: Some(0x1000),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.resize_block_device_called);
+            assert!(!vmm.update_block_device_path_called);
+        });
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            size_bytes: Some(0x1000),
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_set_block_device_read_only() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            is_read_only: Some(true),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_block_device_read_only_called);
+            assert!(!vmm.resize_block_device_called);
+        });
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            is_read_only: Some(false),
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_block_read_write_rate_limiters() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            write_rate_limiter: Some(RateLimiterConfig::default()),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_block_read_write_rate_limiters_called);
+        });
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            read_rate_limiter: Some(RateLimiterConfig::default()),
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_dry_run() {
+        let dry_run_reqs = vec![
+            VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
+            VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
+                stats_polling_interval_s: 0,
+            }),
+            VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+                path_on_host: Some(String::new()),
+                ..Default::default()
+            }),
+            VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+                size_bytes: Some(0x1000),
+                ..Default::default()
+            }),
+            VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+                is_read_only: Some(true),
+                ..Default::default()
+            }),
+            VmmAction::UpdateMemoryHotplug(MemoryHotplugSizeUpdate {
+                requested_size_mib: 512,
+            }),
+            VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+                iface_id: String::new(),
+                guest_mac: None,
+                link_up: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+            }),
+            VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+                iface_id: String::new(),
+                guest_mac: Some(MacAddr::parse_str("12:34:56:78:9a:bc").unwrap()),
+                link_up: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+            }),
+            VmmAction::InsertBlockDevice(BlockDeviceConfig {
+                path_on_host: String::new(),
+                fd: None,
+                serial: None,
+                is_root_device: false,
+                partuuid: None,
+                cache_type: CacheType::Unsafe,
+                is_read_only: false,
+                drive_id: String::new(),
+                rate_limiter: None,
+                read_rate_limiter: None,
+                write_rate_limiter: None,
+                file_engine_type: FileEngineType::default(),
+                image_format: ImageFormat::default(),
+                overlay: None,
+                rl_group: None,
+                vhost_user_socket: None,
+                num_queues: None,
+            }),
+            VmmAction::RemoveBlockDevice(String::new()),
+        ];
+
+        for req in dry_run_reqs {
+            check_runtime_request(VmmAction::DryRun(Box::new(req)), |result, vmm| {
+                assert_eq!(result, Ok(VmmData::DryRun));
+                // Nothing got applied.
+                assert_eq!(*vmm, MockVmm::default());
+            });
+        }
+
+        let req = VmmAction::DryRun(Box::new(VmmAction::UpdateBalloon(BalloonUpdateConfig {
+            amount_mib: 0,
+        })));
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+        let req = VmmAction::DryRun(Box::new(VmmAction::UpdateMemoryHotplug(
+            MemoryHotplugSizeUpdate {
+                requested_size_mib: 512,
+            },
+        )));
+        check_runtime_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::InvalidRequestedSize(0)),
+        );
+        let req = VmmAction::DryRun(Box::new(VmmAction::UpdateBlockDevice(
+            BlockDeviceUpdateConfig::default(),
+        )));
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
+        );
+        let req = VmmAction::DryRun(Box::new(VmmAction::UpdateNetworkInterface(
+            NetworkInterfaceUpdateConfig {
+                iface_id: String::new(),
+                guest_mac: None,
+                link_up: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+            },
+        )));
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::IncorrectDeviceType),
+            )),
+        );
+
+        let req = VmmAction::DryRun(Box::new(VmmAction::SetBalloonDevice(
+            BalloonDeviceConfig::default(),
+        )));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::DryRun));
+            assert!(!vmm.add_balloon_device_called);
+        });
+
+        // Pre-boot only requests.
+        let req = VmmAction::DryRun(Box::new(VmmAction::UpdateVmConfiguration(
+            VmUpdateConfig::from(VmConfig::default()),
+        )));
+        check_runtime_request(req, |result, _| {
+            assert_eq!(result, Err(VmmActionError::OperationNotSupportedPostBoot));
+        });
+        let req = VmmAction::DryRun(Box::new(VmmAction::PutFullVmConfig(Box::new(
+            VmmConfig::default(),
+        ))));
+        check_runtime_request(req, |result, _| {
+            assert_eq!(result, Err(VmmActionError::OperationNotSupportedPostBoot));
+        });
+
+        // Requests without a dry run.
+        let req = VmmAction::DryRun(Box::new(VmmAction::Pause));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Err(VmmActionError::NotSupported(String::new())));
+            assert!(!vmm.pause_called);
+        });
+    }
+
+    #[test]
+    fn test_runtime_update_net_rate_limiters() {
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            guest_mac: None,
+            link_up: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_rate_limiters_called);
+            assert!(!vmm.update_net_guest_mac_called);
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            guest_mac: None,
+            link_up: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::IncorrectDeviceType),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_net_guest_mac() {
+        let guest_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            guest_mac: Some(guest_mac),
+            link_up: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_guest_mac_called);
+            // Rate limiters are left alone when they aren't part of the request.
+            assert!(!vmm.update_net_rate_limiters_called);
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            guest_mac: Some(guest_mac),
+            link_up: None,
+            rx_rate_limiter: Some(RateLimiterConfig::default()),
+            tx_rate_limiter: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_guest_mac_called);
+            assert!(vmm.update_net_rate_limiters_called);
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            guest_mac: Some(guest_mac),
+            link_up: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::IncorrectDeviceType),
+            )),
+        );
+
+        // The MAC address is already used by another interface.
+        let vm_res = MockVmRes {
+            force_errors: true,
+            ..Default::default()
+        };
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_res, vmm.clone());
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            guest_mac: Some(guest_mac),
+            link_up: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        });
+        assert_eq!(
+            runtime.handle_request(req),
+            Err(VmmActionError::NetworkConfig(
+                NetworkInterfaceError::GuestMacAddressInUse(String::new())
+            ))
+        );
+        assert!(!vmm.lock().unwrap().update_net_guest_mac_called);
     }
 
     #[test]
-    fn test_runtime_get_vm_config() {
-        let req = VmmAction::GetVmMachineConfig;
-        check_runtime_request(req, |result, _| {
-            assert_eq!(
-                result,
-                Ok(VmmData::MachineConfiguration(VmConfig::default()))
-            );
+    fn test_runtime_update_net_link_state() {
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            guest_mac: None,
+            link_up: Some(false),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
         });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_link_state_called);
+            assert!(!vmm.update_net_guest_mac_called);
+            assert!(!vmm.update_net_rate_limiters_called);
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            guest_mac: None,
+            link_up: Some(true),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::IncorrectDeviceType),
+            )),
+        );
     }
 
     #[test]
-    fn test_runtime_pause() {
-        let req = VmmAction::Pause;
+    fn test_runtime_set_drive_trace() {
+        let req = VmmAction::SetDriveTrace(trace_config());
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.pause_called)
+            assert!(vmm.set_block_trace_called)
         });
 
-        let req = VmmAction::Pause;
-        check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuPause));
+        let req = VmmAction::SetDriveTrace(trace_config());
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::DeviceNotFound,
+            ))),
+        );
     }
 
     #[test]
-    fn test_runtime_resume() {
-        let req = VmmAction::Resume;
+    fn test_runtime_set_net_capture() {
+        let req = VmmAction::SetNetworkCapture(capture_config());
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.resume_called)
+            assert!(vmm.set_net_capture_called)
         });
 
-        let req = VmmAction::Resume;
-        check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuResume));
+        let req = VmmAction::SetNetworkCapture(capture_config());
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::DeviceNotFound),
+            )),
+        );
     }
 
-    #[cfg(target_arch = "x86_64")]
     #[test]
-    fn test_runtime_ctrl_alt_del() {
-        let req = VmmAction::SendCtrlAltDel;
+    fn test_runtime_get_net_stats() {
+        let req = VmmAction::GetNetworkInterfaceStats(String::new());
         check_runtime_request(req, |result, vmm| {
-            assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.send_ctrl_alt_del_called)
+            assert_eq!(
+                result,
+                Ok(VmmData::NetworkInterfaceStats(NetStats::default()))
+            );
+            assert!(vmm.net_stats_called)
         });
 
-        let req = VmmAction::SendCtrlAltDel;
+        let req = VmmAction::GetNetworkInterfaceStats(String::new());
         check_runtime_request_err(
             req,
-            VmmActionError::InternalVmm(VmmError::I8042Error(
-                devices::legacy::I8042DeviceError::InternalBufferFull,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceStats(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::DeviceNotFound),
             )),
         );
     }
 
     #[test]
-    fn test_runtime_balloon_config() {
-        let req = VmmAction::GetBalloonConfig;
+    fn test_runtime_get_dirty_rate() {
+        // The dirty page rate relies on the dirty page tracking.
+        check_runtime_request_err(
+            VmmAction::GetDirtyRate,
+            VmmActionError::NotSupported(String::new()),
+        );
+
+        let mut vm_resources = MockVmRes::default();
+        vm_resources.set_track_dirty_pages(true);
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_resources, vmm.clone());
+        assert_eq!(
+            runtime.handle_request(VmmAction::GetDirtyRate),
+            Ok(VmmData::DirtyRate(DirtyRate::default()))
+        );
+        assert!(vmm.lock().unwrap().sample_dirty_rate_called);
+
+        let mut vm_resources = MockVmRes::default();
+        vm_resources.set_track_dirty_pages(true);
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            force_errors: true,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(vm_resources, vmm);
+        assert_eq!(
+            runtime.handle_request(VmmAction::GetDirtyRate),
+            Err(VmmActionError::InternalVmm(VmmError::DirtyBitmap(
+                kvm_ioctls::Error::new(libc::ENOENT)
+            )))
+        );
+    }
+
+    #[test]
+    fn test_runtime_schedule_snapshots() {
+        let snapshot_dir = TempDir::new().unwrap();
+        let config = |snapshot_dir: &Path| SnapshotScheduleConfig {
+            interval_s: 60,
+            snapshot_dir: snapshot_dir.to_path_buf(),
+            slots: DEFAULT_SCHEDULE_SLOTS,
+            max_pause_ms: None,
+        };
+
+        // All the snapshots of a rotation but the first one are diff snapshots.
+        check_runtime_request_err(
+            VmmAction::ScheduleSnapshots(config(snapshot_dir.as_path())),
+            VmmActionError::NotSupported(String::new()),
+        );
+
+        let mut vm_resources = MockVmRes::default();
+        vm_resources.set_track_dirty_pages(true);
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_resources, vmm.clone());
+        assert_eq!(
+            runtime.handle_request(VmmAction::ScheduleSnapshots(config(Path::new(
+                "/nonexistent"
+            )))),
+            Err(VmmActionError::SnapshotSchedule(String::new()))
+        );
+        assert!(!vmm.lock().unwrap().schedule_snapshots_called);
+
+        assert_eq!(
+            runtime.handle_request(VmmAction::ScheduleSnapshots(config(snapshot_dir.as_path()))),
+            Ok(VmmData::Empty)
+        );
+        assert!(vmm.lock().unwrap().schedule_snapshots_called);
+
+        check_runtime_request(VmmAction::CancelSnapshotSchedule, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.cancel_snapshot_schedule_called);
+        });
+    }
+
+    #[test]
+    fn test_runtime_memory_hotplug() {
+        let req = VmmAction::GetMemoryHotplugStatus;
         check_runtime_request(req, |result, vmm| {
             assert_eq!(
                 result,
-                Ok(VmmData::BalloonConfig(BalloonDeviceConfig::default()))
+                Ok(VmmData::MemoryHotplugStatus(MemoryHotplugStatus::default()))
             );
-            assert!(vmm.balloon_config_called)
+            assert!(vmm.memory_hotplug_status_called)
         });
 
-        let req = VmmAction::GetBalloonConfig;
+        let req = VmmAction::GetMemoryHotplugStatus;
         check_runtime_request_err(
             req,
-            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
+        );
+
+        let update = MemoryHotplugSizeUpdate {
+            requested_size_mib: 512,
+        };
+        let req = VmmAction::UpdateMemoryHotplug(update.clone());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_memory_hotplug_called)
+        });
+
+        let req = VmmAction::UpdateMemoryHotplug(update);
+        check_runtime_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
         );
     }
 
     #[test]
-    fn test_runtime_latest_balloon_stats() {
-        let req = VmmAction::GetBalloonStats;
+    fn test_runtime_get_vsock_connections() {
+        let req = VmmAction::GetVsockConnections(String::new());
         check_runtime_request(req, |result, vmm| {
-            assert_eq!(result, Ok(VmmData::BalloonStats(BalloonStats::default())));
-            assert!(vmm.latest_balloon_stats_called)
+            assert_eq!(result, Ok(VmmData::VsockConnections(Vec::new())));
+            assert!(vmm.vsock_connections_called)
         });
 
-        let req = VmmAction::GetBalloonStats;
+        let req = VmmAction::GetVsockConnections(String::new());
         check_runtime_request_err(
             req,
-            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            VmmActionError::VsockConfig(VsockConfigError::DeviceConnections(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::DeviceNotFound),
+            )),
         );
     }
 
     #[test]
-    fn test_runtime_update_balloon_config() {
-        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 });
+    fn test_runtime_pool_vsock_connections() {
+        let pool_cfg = VsockConnectionPoolConfig { port: 52, count: 4 };
+        let req = VmmAction::PoolVsockConnections(String::new(), pool_cfg.clone());
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.update_balloon_config_called)
+            assert!(vmm.pool_vsock_connections_called)
         });
 
-        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 });
+        let req = VmmAction::PoolVsockConnections(String::new(), pool_cfg);
         check_runtime_request_err(
             req,
-            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            VmmActionError::VsockConfig(VsockConfigError::PoolConnections(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::DeviceNotFound),
+            )),
         );
     }
 
     #[test]
-    fn test_runtime_update_balloon_stats_config() {
-        let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
-            stats_polling_interval_s: 0,
-        });
+    fn test_runtime_remove_net_device() {
+        let req = VmmAction::RemoveNetworkDevice(String::new());
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.update_balloon_stats_config_called)
+            assert!(vmm.remove_net_device_called)
         });
 
-        let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
-            stats_polling_interval_s: 0,
-        });
+        let req = VmmAction::RemoveNetworkDevice(String::new());
         check_runtime_request_err(
             req,
-            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceRemoval(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::DeviceNotFound),
+            )),
         );
+
+        // A dry run doesn't remove the device.
+        let req = VmmAction::DryRun(Box::new(VmmAction::RemoveNetworkDevice(String::new())));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::DryRun));
+            assert!(!vmm.remove_net_device_called)
+        });
     }
 
     #[test]
-    fn test_runtime_update_block_device_path() {
-        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
-            path_on_host: Some(String::new()),
-            ..Default::default()
-        });
+    fn test_runtime_insert_block_device() {
+        let block_file = TempFile::new().unwrap();
+        let block_cfg = BlockDeviceConfig {
+            path_on_host: block_file.as_path().to_str().unwrap().to_string(),
+            fd: None,
+            serial: None,
+            is_root_device: false,
+            partuuid: None,
+            cache_type: CacheType::Unsafe,
+            is_read_only: false,
+            drive_id: String::from("scratch"),
+            rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
+            file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
+        };
+        let req = VmmAction::InsertBlockDevice(block_cfg.clone());
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.update_block_device_path_called)
+            assert!(vmm.add_block_device_called)
         });
 
-        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
-            path_on_host: Some(String::new()),
-            ..Default::default()
-        });
+        let req = VmmAction::InsertBlockDevice(block_cfg);
         check_runtime_request_err(
             req,
-            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
-                crate::device_manager::mmio::Error::IncorrectDeviceType,
-            ))),
+            VmmActionError::DriveConfig(DriveError::DeviceHotplug(VmmError::VcpuMessage)),
         );
     }
 
     #[test]
-    fn test_runtime_update_net_rate_limiters() {
-        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
-            iface_id: String::new(),
-            rx_rate_limiter: None,
-            tx_rate_limiter: None,
-        });
+    fn test_runtime_set_balloon_device() {
+        let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.update_net_rate_limiters_called)
+            assert!(vmm.add_balloon_device_called)
         });
 
-        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
-            iface_id: String::new(),
-            rx_rate_limiter: None,
-            tx_rate_limiter: None,
+        let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceHotplug(VmmError::VcpuMessage)),
+        );
+
+        // The balloon device is forgotten about when it can't be attached.
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            force_errors: true,
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm);
+        let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
+        assert!(runtime.handle_request(req).is_err());
+        assert!(runtime.vm_resources.balloon.get().is_none());
+    }
+
+    #[test]
+    fn test_runtime_remove_block_device() {
+        let req = VmmAction::RemoveBlockDevice(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.remove_block_device_called)
         });
+
+        let req = VmmAction::RemoveBlockDevice(String::new());
         check_runtime_request_err(
             req,
-            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
-                VmmError::DeviceManager(crate::device_manager::mmio::Error::IncorrectDeviceType),
-            )),
+            VmmActionError::DriveConfig(DriveError::DeviceRemoval(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::DeviceNotFound,
+            ))),
         );
     }
 
+    #[test]
+    fn test_runtime_send_migration() {
+        let req = || {
+            VmmAction::SendMigration(MigrationSendConfig {
+                destination: "127.0.0.1:7070".parse().unwrap(),
+                max_iterations: 10,
+                max_dirty_pages: 1024,
+            })
+        };
+
+        // Migration relies on the dirty page tracking.
+        check_runtime_request_err(req(), VmmActionError::NotSupported(String::new()));
+
+        let mut vm_resources = MockVmRes::default();
+        vm_resources.set_track_dirty_pages(true);
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_resources, vmm);
+        assert_eq!(runtime.handle_request(req()), Ok(VmmData::Empty));
+    }
+
     #[test]
     fn test_runtime_disallowed() {
         check_runtime_request_err(
             VmmAction::ConfigureBootSource(BootSourceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::PutFullVmConfig(Box::new(VmmConfig::default())),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
         check_runtime_request_err(
             VmmAction::ConfigureLogger(LoggerConfig {
                 log_path: PathBuf::new(),
@@ -1993,19 +4494,6 @@ mod tests {
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
-        check_runtime_request_err(
-            VmmAction::InsertBlockDevice(BlockDeviceConfig {
-                path_on_host: String::new(),
-                is_root_device: false,
-                partuuid: None,
-                cache_type: CacheType::Unsafe,
-                is_read_only: false,
-                drive_id: String::new(),
-                rate_limiter: None,
-                file_engine_type: FileEngineType::default(),
-            }),
-            VmmActionError::OperationNotSupportedPostBoot,
-        );
         check_runtime_request_err(
             VmmAction::InsertNetworkDevice(NetworkInterfaceConfig {
                 iface_id: String::new(),
@@ -2013,6 +4501,29 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                backend_type: NetBackendType::default(),
+                num_queues: 1,
+                backend: NetDatapath::Virtio,
+                mtu: None,
+                offloads: NetOffloads::default(),
+                tap_fd: None,
+                xsks_map_path: None,
+                netns: None,
+                rl_group: None,
+                rx_filtering: false,
+                filters: Vec::new(),
+                worker_thread: false,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::InsertPmemDevice(pmem_config()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetMemoryHotplug(MemoryHotplugConfig {
+                total_size_mib: 1024,
+                block_size_mib: 2,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2021,11 +4532,15 @@ mod tests {
                 vsock_id: Some(String::new()),
                 guest_cid: 0,
                 uds_path: String::new(),
+                backend: VsockDatapath::Virtio,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                uds_permissions: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
-            VmmAction::SetBalloonDevice(BalloonDeviceConfig::default()),
+            VmmAction::SetRateLimiterGroup(rl_group_config()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
@@ -2033,12 +4548,17 @@ mod tests {
                 vsock_id: Some(String::new()),
                 guest_cid: 0,
                 uds_path: String::new(),
+                backend: VsockDatapath::Virtio,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                uds_permissions: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
             VmmAction::SetMmdsConfiguration(MmdsConfig {
                 ipv4_address: None,
+                ipv6_address: None,
                 version: MmdsVersion::default(),
                 network_interfaces: Vec::new(),
             }),
@@ -2051,12 +4571,29 @@ mod tests {
         check_runtime_request_err(
             VmmAction::LoadSnapshot(LoadSnapshotParams {
                 snapshot_path: PathBuf::new(),
+                snapshot_fd: None,
                 mem_backend: MemBackendConfig {
                     backend_type: MemBackendType::File,
                     backend_path: PathBuf::new(),
                 },
+                mem_file_fd: None,
                 enable_diff_snapshots: false,
                 resume_vm: false,
+                resume_mode: ResumeMode::Eager,
+                network_overrides: vec![],
+                drive_overrides: vec![],
+                skip_verify: false,
+                guest_mem_backend: GuestMemoryBackend::Anonymous,
+                clone: None,
+                mmds_data: None,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::ReceiveMigration(MigrationReceiveConfig {
+                listen_address: "0.0.0.0:7070".parse().unwrap(),
+                track_dirty_pages: false,
+                resume_vm: false,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2073,12 +4610,21 @@ mod tests {
         // Load snapshot should no longer be allowed.
         let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
             snapshot_path: PathBuf::new(),
+            snapshot_fd: None,
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
             },
+            mem_file_fd: None,
             enable_diff_snapshots: false,
             resume_vm: false,
+            resume_mode: ResumeMode::Eager,
+            network_overrides: vec![],
+            drive_overrides: vec![],
+            skip_verify: false,
+            guest_mem_backend: GuestMemoryBackend::Anonymous,
+            clone: None,
+            mmds_data: None,
         });
         let err = preboot.handle_preboot_request(req);
         assert_eq!(
@@ -2097,13 +4643,22 @@ mod tests {
 
         let req = VmmAction::InsertBlockDevice(BlockDeviceConfig {
             path_on_host: String::new(),
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::new(),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertBlockDevice");
 
@@ -2113,16 +4668,41 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
         let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
         verify_load_snap_disallowed_after_boot_resources(req, "SetBalloonDevice");
 
+        let req = VmmAction::InsertPmemDevice(pmem_config());
+        verify_load_snap_disallowed_after_boot_resources(req, "InsertPmemDevice");
+
+        let req = VmmAction::SetMemoryHotplug(MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetMemoryHotplug");
+
         let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
             vsock_id: Some(String::new()),
             guest_cid: 0,
             uds_path: String::new(),
+            backend: VsockDatapath::Virtio,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            uds_permissions: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
 
@@ -2131,9 +4711,13 @@ mod tests {
 
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
             ipv4_address: None,
+            ipv6_address: None,
             version: MmdsVersion::default(),
             network_interfaces: Vec::new(),
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
+
+        let req = VmmAction::SetRateLimiterGroup(rl_group_config());
+        verify_load_snap_disallowed_after_boot_resources(req, "SetRateLimiterGroup");
     }
 }
@@ -1,13 +1,22 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
+mod violations;
+
 use std::fmt;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::sync::Arc;
 
-use seccompiler::{deserialize_binary, BpfThreadMap, DeserializationError, InstallationError};
+use seccompiler::{
+    deserialize_binary, to_log_mode, BpfThreadMap, DeserializationError, InstallationError,
+};
+
+pub use self::violations::SeccompViolations;
 
 const THREAD_CATEGORIES: [&str; 3] = ["vmm", "api", "vcpu"];
+// Categories of the threads which only exist for some configurations. Custom filters lacking
+// them are only rejected when such a thread is started.
+const OPTIONAL_THREAD_CATEGORIES: [&str; 3] = ["net_worker", "api_vsock", "uffd_handler"];
 
 // This byte limit is passed to `bincode` to guard against a potential memory
 // allocation DOS caused by binary filters that are too large.
@@ -82,6 +91,43 @@ impl SeccompConfig {
     }
 }
 
+/// How the seccomp filters are enforced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeccompLevel {
+    /// Default, the syscalls the filters don't allow are blocked.
+    Enforce,
+    /// The syscalls the filters don't allow are let through, and logged by the kernel.
+    Log,
+}
+
+impl SeccompLevel {
+    /// Given the relevant command line arg, return the appropriate level.
+    pub fn from_arg(seccomp_level: Option<&String>) -> Result<Self, FilterError> {
+        match seccomp_level.map(String::as_str) {
+            None | Some("enforce") => Ok(SeccompLevel::Enforce),
+            Some("log") => Ok(SeccompLevel::Log),
+            Some(level) => Err(FilterError::SeccompConfig(format!(
+                "invalid seccomp level: {}",
+                level
+            ))),
+        }
+    }
+}
+
+/// Adapt the filters to the SeccompLevel.
+///
+/// In log mode, the violations are only counted by a `SeccompViolations` subscriber, which has
+/// to be created before installing any filter.
+pub fn set_level(filters: BpfThreadMap, level: SeccompLevel) -> BpfThreadMap {
+    match level {
+        SeccompLevel::Enforce => filters,
+        SeccompLevel::Log => filters
+            .into_iter()
+            .map(|(category, filter)| (category, Arc::new(to_log_mode(&filter))))
+            .collect(),
+    }
+}
+
 /// Retrieve the appropriate filters, based on the SeccompConfig.
 pub fn get_filters(config: SeccompConfig) -> Result<BpfThreadMap, FilterError> {
     match config {
@@ -107,6 +153,9 @@ fn get_empty_filters() -> BpfThreadMap {
     map.insert("vmm".to_string(), Arc::new(vec![]));
     map.insert("api".to_string(), Arc::new(vec![]));
     map.insert("vcpu".to_string(), Arc::new(vec![]));
+    map.insert("net_worker".to_string(), Arc::new(vec![]));
+    map.insert("api_vsock".to_string(), Arc::new(vec![]));
+    map.insert("uffd_handler".to_string(), Arc::new(vec![]));
     map
 }
 
@@ -119,9 +168,11 @@ fn get_custom_filters<R: Read>(reader: R) -> Result<BpfThreadMap, FilterError> {
 
 /// Return an error if the BpfThreadMap contains invalid thread categories.
 fn filter_thread_categories(map: BpfThreadMap) -> Result<BpfThreadMap, FilterError> {
-    let (filters, invalid_filters): (BpfThreadMap, BpfThreadMap) = map
-        .into_iter()
-        .partition(|(k, _)| THREAD_CATEGORIES.contains(&k.as_str()));
+    let (filters, invalid_filters): (BpfThreadMap, BpfThreadMap) =
+        map.into_iter().partition(|(k, _)| {
+            THREAD_CATEGORIES.contains(&k.as_str())
+                || OPTIONAL_THREAD_CATEGORIES.contains(&k.as_str())
+        });
     if !invalid_filters.is_empty() {
         // build the error message
         let mut thread_categories_string =
@@ -156,16 +207,22 @@ mod tests {
     #[test]
     fn test_get_filters() {
         let mut filters = get_filters(SeccompConfig::Advanced).unwrap();
-        assert_eq!(filters.len(), 3);
+        assert_eq!(filters.len(), 6);
         assert!(filters.remove("vmm").is_some());
         assert!(filters.remove("api").is_some());
         assert!(filters.remove("vcpu").is_some());
+        assert!(filters.remove("net_worker").is_some());
+        assert!(filters.remove("api_vsock").is_some());
+        assert!(filters.remove("uffd_handler").is_some());
 
         let mut filters = get_filters(SeccompConfig::None).unwrap();
-        assert_eq!(filters.len(), 3);
+        assert_eq!(filters.len(), 6);
         assert_eq!(filters.remove("vmm").unwrap().len(), 0);
         assert_eq!(filters.remove("api").unwrap().len(), 0);
         assert_eq!(filters.remove("vcpu").unwrap().len(), 0);
+        assert_eq!(filters.remove("net_worker").unwrap().len(), 0);
+        assert_eq!(filters.remove("api_vsock").unwrap().len(), 0);
+        assert_eq!(filters.remove("uffd_handler").unwrap().len(), 0);
 
         let file = TempFile::new().unwrap().into_file();
 
@@ -182,6 +239,17 @@ mod tests {
 
         assert_eq!(filter_thread_categories(map).unwrap().len(), 3);
 
+        // optional categories
+        let mut map = BpfThreadMap::new();
+        map.insert("vcpu".to_string(), Arc::new(vec![]));
+        map.insert("vmm".to_string(), Arc::new(vec![]));
+        map.insert("api".to_string(), Arc::new(vec![]));
+        map.insert("net_worker".to_string(), Arc::new(vec![]));
+        map.insert("api_vsock".to_string(), Arc::new(vec![]));
+        map.insert("uffd_handler".to_string(), Arc::new(vec![]));
+
+        assert_eq!(filter_thread_categories(map).unwrap().len(), 6);
+
         // invalid categories
         let mut map = BpfThreadMap::new();
         map.insert("vcpu".to_string(), Arc::new(vec![]));
@@ -230,4 +298,28 @@ mod tests {
             Ok(SeccompConfig::Advanced)
         ));
     }
+
+    #[test]
+    fn test_seccomp_level() {
+        assert_eq!(SeccompLevel::from_arg(None).unwrap(), SeccompLevel::Enforce);
+        assert_eq!(
+            SeccompLevel::from_arg(Some(&"enforce".to_string())).unwrap(),
+            SeccompLevel::Enforce
+        );
+        assert_eq!(
+            SeccompLevel::from_arg(Some(&"log".to_string())).unwrap(),
+            SeccompLevel::Log
+        );
+        assert!(matches!(
+            SeccompLevel::from_arg(Some(&"kill".to_string())),
+            Err(FilterError::SeccompConfig(_))
+        ));
+
+        let filters = get_filters(SeccompConfig::Advanced).unwrap();
+        assert_eq!(set_level(filters.clone(), SeccompLevel::Enforce), filters);
+        let log_filters = set_level(filters.clone(), SeccompLevel::Log);
+        assert_eq!(log_filters.len(), filters.len());
+        assert_ne!(log_filters["vmm"], filters["vmm"]);
+        assert_eq!(log_filters["vmm"].len(), filters["vmm"].len());
+    }
 }
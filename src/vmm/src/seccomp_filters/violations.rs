@@ -0,0 +1,170 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Counter of the syscalls let through by the seccomp filters in log mode.
+//!
+//! The kernel logs these syscalls as audit records, which are read back from its log by the
+//! thread running the event loop, so that no thread escapes the filters to count them.
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use logger::{error, warn, IncMetric, METRICS};
+use utils::epoll::EventSet;
+
+const KMSG_PATH: &str = "/dev/kmsg";
+// Records are at most 1 KiB long, along with their header.
+const KMSG_RECORD_MAX_LEN: usize = 2048;
+// Type of the audit records of the seccomp actions, see /usr/include/linux/audit.h .
+const AUDIT_SECCOMP_TYPE: &str = "type=1326";
+// `SECCOMP_RET_LOG`, as reported in the audit records.
+const AUDIT_SECCOMP_LOG_CODE: &str = "code=0x7ffc0000";
+
+/// Counts the violations of the seccomp filters in log mode, as reported in the kernel log.
+pub struct SeccompViolations {
+    kmsg: File,
+    // Field identifying the audit records of this process.
+    pid_field: String,
+    // Syscalls whose violations were already reported.
+    reported_syscalls: HashSet<i64>,
+}
+
+impl SeccompViolations {
+    /// Opens the kernel log at its end. This has to happen before the filters are installed,
+    /// since they don't allow opening it.
+    pub fn new() -> io::Result<Self> {
+        // The audit records identify processes by their PID in the host PID namespace, which a
+        // process at the root of another namespace can't know.
+        if std::process::id() == 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Firecracker runs in a new PID namespace",
+            ));
+        }
+        let mut kmsg = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(KMSG_PATH)?;
+        // The records logged before Firecracker started are of no interest.
+        kmsg.seek(SeekFrom::End(0))?;
+        Ok(SeccompViolations {
+            kmsg,
+            pid_field: format!("pid={}", std::process::id()),
+            reported_syscalls: HashSet::new(),
+        })
+    }
+
+    // Returns the syscall number of a record, if it's the audit record of a syscall let through
+    // by the filters of this process.
+    fn parse_violation(&self, record: &str) -> Option<i64> {
+        // The message follows the header of the record.
+        let (_, message) = record.split_once(';')?;
+        let mut fields = message.split_whitespace();
+        if !fields.clone().any(|field| field == AUDIT_SECCOMP_TYPE)
+            || !fields.clone().any(|field| field == AUDIT_SECCOMP_LOG_CODE)
+            || !fields.clone().any(|field| field == self.pid_field)
+        {
+            return None;
+        }
+        fields
+            .find_map(|field| field.strip_prefix("syscall="))
+            .and_then(|syscall| syscall.parse().ok())
+    }
+
+    fn read_records(&mut self) {
+        let mut record = [0u8; KMSG_RECORD_MAX_LEN];
+        loop {
+            let len = match self.kmsg.read(&mut record) {
+                Ok(len) => len,
+                // The oldest records were overwritten before being read, the next read starts
+                // from the oldest one left.
+                Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    METRICS.seccomp.violations_read_fails.inc();
+                    error!("Failed to read the kernel log: {}", e);
+                    return;
+                }
+            };
+            if len == 0 {
+                return;
+            }
+
+            let syscall = match self.parse_violation(&String::from_utf8_lossy(&record[..len])) {
+                Some(syscall) => syscall,
+                None => continue,
+            };
+            METRICS.seccomp.num_violations.inc();
+            if self.reported_syscalls.insert(syscall) {
+                warn!(
+                    "The seccomp filters would have blocked syscall {}.",
+                    syscall
+                );
+            }
+        }
+    }
+}
+
+impl MutEventSubscriber for SeccompViolations {
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        let event_set = event.event_set();
+        if !event_set.contains(EventSet::IN) {
+            warn!("Received unknown event on the kernel log: {:?}", event_set);
+            return;
+        }
+        self.read_records();
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(e) = ops.add(Events::new(&self.kmsg, EventSet::IN)) {
+            error!("Failed to register the kernel log event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_violation() {
+        let violations = SeccompViolations {
+            kmsg: File::open("/dev/null").unwrap(),
+            pid_field: "pid=1234".to_string(),
+            reported_syscalls: HashSet::new(),
+        };
+        let record = |pid: u32, code: &str| {
+            format!(
+                "5,400,23019414807,-;audit: type=1326 audit(1665924000.579:8): auid=4294967295 \
+                 uid=0 gid=0 ses=4294967295 subj=kernel pid={} comm=\"fc_vcpu 0\" \
+                 exe=\"/usr/bin/firecracker\" sig=0 arch=c000003e syscall=16 compat=0 \
+                 ip=0x7f957ee12829 code={}",
+                pid, code
+            )
+        };
+
+        assert_eq!(
+            violations.parse_violation(&record(1234, "0x7ffc0000")),
+            Some(16)
+        );
+        // Other processes.
+        assert_eq!(
+            violations.parse_violation(&record(12345, "0x7ffc0000")),
+            None
+        );
+        // Other seccomp actions.
+        assert_eq!(
+            violations.parse_violation(&record(1234, "0x80000000")),
+            None
+        );
+        // Other records.
+        assert_eq!(
+            violations.parse_violation("6,401,23019414813,-;pid=1234 syscall=16"),
+            None
+        );
+        assert_eq!(violations.parse_violation("no header"), None);
+    }
+}
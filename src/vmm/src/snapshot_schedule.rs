@@ -0,0 +1,319 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use logger::{error, info, update_metric_with_elapsed_time, warn, IncMetric, METRICS};
+use utils::time::{get_time_us, ClockType};
+
+use crate::persist::{create_snapshot, DiffSnapshotParent, SnapshotParentState};
+use crate::version_map::VERSION_MAP;
+use crate::vmm_config::instance_info::VmState;
+use crate::vmm_config::snapshot::{CreateSnapshotParams, SnapshotScheduleConfig, SnapshotType};
+use crate::Vmm;
+
+/// Rotation of the snapshots taken periodically by the VMM.
+///
+/// The first slot of the rotation holds a full snapshot and each following slot a diff snapshot
+/// taken on top of the previous one. Once all the slots are used, the rotation starts over with
+/// a full snapshot in the first slot. It also starts over when another snapshot or a migration
+/// read the dirty log since the previous slot was written.
+pub(crate) struct SnapshotSchedule {
+    config: SnapshotScheduleConfig,
+    // Number of snapshots taken since the start of the current rotation.
+    taken: u64,
+}
+
+impl SnapshotSchedule {
+    pub(crate) fn new(config: SnapshotScheduleConfig) -> Self {
+        SnapshotSchedule { config, taken: 0 }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_s)
+    }
+
+    fn max_pause(&self) -> Option<Duration> {
+        self.config.max_pause_ms.map(Duration::from_millis)
+    }
+
+    fn slot(&self) -> usize {
+        (self.taken % self.config.slots as u64) as usize
+    }
+
+    /// Returns the parameters of the next snapshot of the rotation.
+    fn next_snapshot(&self) -> CreateSnapshotParams {
+        let slot = self.slot();
+        let (snapshot_path, mem_file_path) = slot_paths(&self.config.snapshot_dir, slot);
+        CreateSnapshotParams {
+            snapshot_type: if slot == 0 {
+                SnapshotType::Full
+            } else {
+                SnapshotType::Diff
+            },
+            snapshot_path,
+            snapshot_fd: None,
+            mem_file_path,
+            mem_file_fd: None,
+            compression: None,
+            mem_writer_threads: None,
+            exclude_mmds: false,
+            version: None,
+        }
+    }
+
+    /// Whether the next snapshot continues the chain of the previous slot, which it can only do
+    /// if the previous slot is the last snapshot taken and nothing read the dirty log since.
+    fn continues_chain(&self, diff_parent: &DiffSnapshotParent) -> bool {
+        match self.slot() {
+            0 => true,
+            slot => {
+                let (snapshot_path, mem_file_path) =
+                    slot_paths(&self.config.snapshot_dir, slot - 1);
+                *diff_parent
+                    == DiffSnapshotParent::Snapshot(SnapshotParentState::new(
+                        &snapshot_path,
+                        &mem_file_path,
+                    ))
+            }
+        }
+    }
+
+    // The diff slots are taken on top of the full snapshot about to be rewritten, and would no
+    // longer restore. The VMM isn't allowed to delete files, so empty them instead.
+    fn invalidate_diff_slots(&self) {
+        for slot in 1..self.config.slots {
+            let (snapshot_path, mem_file_path) = slot_paths(&self.config.snapshot_dir, slot);
+            for path in [snapshot_path, mem_file_path].iter() {
+                match OpenOptions::new().write(true).truncate(true).open(path) {
+                    Ok(_) => (),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                    Err(e) => warn!(
+                        "Failed to invalidate scheduled snapshot {}: {}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        self.taken += 1;
+    }
+
+    // A failed snapshot may have consumed the dirty log, so the diff snapshots that follow would
+    // miss pages: start over with a full snapshot.
+    fn restart(&mut self) {
+        self.taken = 0;
+    }
+}
+
+/// Returns the paths of the microVM state and guest memory files of a slot of a schedule.
+fn slot_paths(snapshot_dir: &Path, slot: usize) -> (PathBuf, PathBuf) {
+    (
+        snapshot_dir.join(format!("vmstate_{}", slot)),
+        snapshot_dir.join(format!("mem_{}", slot)),
+    )
+}
+
+/// Pauses the microVM, takes the next snapshot of its schedule and resumes it.
+pub(crate) fn take_scheduled_snapshot(vmm: &mut Vmm) {
+    let (params, max_pause) = match vmm.snapshot_schedule.as_mut() {
+        Some(schedule) => {
+            if !schedule.continues_chain(&vmm.diff_parent) {
+                schedule.restart();
+            }
+            (schedule.next_snapshot(), schedule.max_pause())
+        }
+        None => return,
+    };
+    // The microVM was paused through the API, leave it as it is.
+    if vmm.instance_info.state != VmState::Running {
+        METRICS.snapshot_schedule.skipped.inc();
+        return;
+    }
+    if params.snapshot_type == SnapshotType::Full {
+        if let Some(schedule) = vmm.snapshot_schedule.as_ref() {
+            schedule.invalidate_diff_slots();
+        }
+    }
+
+    let pause_start_us = get_time_us(ClockType::Monotonic);
+    let result = vmm.pause_vm().map_err(|e| e.to_string()).and_then(|_| {
+        create_snapshot(vmm, &params, VERSION_MAP.clone()).map_err(|e| e.to_string())
+    });
+    if let Err(e) = vmm.resume_vm() {
+        error!(
+            "Failed to resume the microVM after a scheduled snapshot: {}",
+            e
+        );
+    }
+    let pause_us =
+        update_metric_with_elapsed_time(&METRICS.snapshot_schedule.pause_us, pause_start_us);
+
+    match result {
+        Ok(()) => {
+            METRICS.snapshot_schedule.snapshots.inc();
+            info!(
+                "Scheduled snapshot {} taken in {} us.",
+                params.snapshot_path.display(),
+                pause_us
+            );
+            if let Some(schedule) = vmm.snapshot_schedule.as_mut() {
+                schedule.advance();
+            }
+        }
+        Err(e) => {
+            METRICS.snapshot_schedule.fails.inc();
+            error!(
+                "Failed to take scheduled snapshot {}: {}",
+                params.snapshot_path.display(),
+                e
+            );
+            if let Some(schedule) = vmm.snapshot_schedule.as_mut() {
+                schedule.restart();
+            }
+        }
+    }
+
+    if let Some(max_pause) = max_pause {
+        if Duration::from_micros(pause_us) > max_pause {
+            METRICS.snapshot_schedule.pause_overruns.inc();
+            warn!(
+                "Scheduled snapshot paused the microVM for {} us, more than the {} ms allowed. \
+                 Cancelling the snapshot schedule.",
+                pause_us,
+                max_pause.as_millis()
+            );
+            vmm.cancel_snapshot_schedule();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempdir::TempDir;
+
+    fn schedule(slots: usize) -> SnapshotSchedule {
+        SnapshotSchedule::new(SnapshotScheduleConfig {
+            interval_s: 60,
+            snapshot_dir: PathBuf::from("/snapshots"),
+            slots,
+            max_pause_ms: Some(100),
+        })
+    }
+
+    #[test]
+    fn test_snapshot_schedule_rotation() {
+        let mut schedule = schedule(3);
+        assert_eq!(schedule.interval(), Duration::from_secs(60));
+        assert_eq!(schedule.max_pause(), Some(Duration::from_millis(100)));
+
+        let mut taken = Vec::new();
+        for _ in 0..4 {
+            let params = schedule.next_snapshot();
+            taken.push((
+                params.snapshot_type,
+                params.snapshot_path,
+                params.mem_file_path,
+            ));
+            schedule.advance();
+        }
+        assert_eq!(
+            taken,
+            vec![
+                (
+                    SnapshotType::Full,
+                    PathBuf::from("/snapshots/vmstate_0"),
+                    PathBuf::from("/snapshots/mem_0")
+                ),
+                (
+                    SnapshotType::Diff,
+                    PathBuf::from("/snapshots/vmstate_1"),
+                    PathBuf::from("/snapshots/mem_1")
+                ),
+                (
+                    SnapshotType::Diff,
+                    PathBuf::from("/snapshots/vmstate_2"),
+                    PathBuf::from("/snapshots/mem_2")
+                ),
+                (
+                    SnapshotType::Full,
+                    PathBuf::from("/snapshots/vmstate_0"),
+                    PathBuf::from("/snapshots/mem_0")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_schedule_restart() {
+        let mut schedule = schedule(4);
+        schedule.advance();
+        schedule.advance();
+        assert_eq!(schedule.next_snapshot().snapshot_type, SnapshotType::Diff);
+
+        schedule.restart();
+        let params = schedule.next_snapshot();
+        assert_eq!(params.snapshot_type, SnapshotType::Full);
+        assert_eq!(params.snapshot_path, PathBuf::from("/snapshots/vmstate_0"));
+    }
+
+    #[test]
+    fn test_snapshot_schedule_chain() {
+        let mut schedule = schedule(3);
+        // The first slot holds a full snapshot, whatever came before.
+        assert!(schedule.continues_chain(&DiffSnapshotParent::Unknown));
+
+        schedule.advance();
+        let slot_0 = DiffSnapshotParent::Snapshot(SnapshotParentState::new(
+            Path::new("/snapshots/vmstate_0"),
+            Path::new("/snapshots/mem_0"),
+        ));
+        assert!(schedule.continues_chain(&slot_0));
+        // A snapshot taken through the API, or a migration, since the previous slot.
+        let other = DiffSnapshotParent::Snapshot(SnapshotParentState::new(
+            Path::new("/other/vmstate"),
+            Path::new("/other/mem"),
+        ));
+        assert!(!schedule.continues_chain(&other));
+        assert!(!schedule.continues_chain(&DiffSnapshotParent::Unknown));
+        assert!(!schedule.continues_chain(&DiffSnapshotParent::Boot));
+
+        schedule.advance();
+        assert!(!schedule.continues_chain(&slot_0));
+    }
+
+    #[test]
+    fn test_snapshot_schedule_invalidate_diff_slots() {
+        let snapshot_dir = TempDir::new().unwrap();
+        let schedule = SnapshotSchedule::new(SnapshotScheduleConfig {
+            interval_s: 60,
+            snapshot_dir: snapshot_dir.as_path().to_path_buf(),
+            slots: 3,
+            max_pause_ms: None,
+        });
+        // Slot 2 was never written.
+        for slot in 0..2 {
+            let (snapshot_path, mem_file_path) = slot_paths(snapshot_dir.as_path(), slot);
+            std::fs::write(snapshot_path, b"vmstate").unwrap();
+            std::fs::write(mem_file_path, b"mem").unwrap();
+        }
+
+        schedule.invalidate_diff_slots();
+        let (snapshot_path, mem_file_path) = slot_paths(snapshot_dir.as_path(), 0);
+        assert_eq!(std::fs::read(snapshot_path).unwrap(), b"vmstate");
+        assert_eq!(std::fs::read(mem_file_path).unwrap(), b"mem");
+        let (snapshot_path, mem_file_path) = slot_paths(snapshot_dir.as_path(), 1);
+        assert!(std::fs::read(snapshot_path).unwrap().is_empty());
+        assert!(std::fs::read(mem_file_path).unwrap().is_empty());
+        let (snapshot_path, _) = slot_paths(snapshot_dir.as_path(), 2);
+        assert!(!snapshot_path.exists());
+    }
+}
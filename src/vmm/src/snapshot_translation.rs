@@ -0,0 +1,261 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Translates the microVM state of the snapshots taken by previous Firecracker releases to the
+//! layout of the current release.
+//!
+//! The versioned deserialization fills in the fields a snapshot predates with default values.
+//! This module carries out the upgrades those defaults can't express, since they depend on
+//! other parts of the state, and rejects the states holding values the current release can't
+//! restore, listing each offending field.
+
+use std::fmt::{Display, Formatter};
+
+use logger::info;
+use versionize::VersionMap;
+
+use crate::device_manager::persist::{DeviceStates, MmdsVersionState};
+use crate::persist::MicrovmState;
+use crate::version_map::FC_V1_1_SNAP_VERSION;
+
+/// Number of previous releases whose snapshots can be loaded.
+pub const TRANSLATED_RELEASES: u16 = 5;
+
+// Upgrades of the device states saved by the releases preceding the snapshot data version they
+// are listed with, applied in order.
+const DEVICE_STATES_UPGRADES: [(u16, fn(&mut DeviceStates)); 1] =
+    [(FC_V1_1_SNAP_VERSION, persist_default_mmds_version)];
+
+/// Errors associated with the translation of the microVM state.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The snapshot data version is neither the current one nor one of the previous
+    /// `TRANSLATED_RELEASES`.
+    UnsupportedDataVersion(u16),
+    /// The microVM state holds fields the current release can't restore.
+    IncompatibleFields(Vec<String>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+        match self {
+            UnsupportedDataVersion(data_version) => write!(
+                f,
+                "Snapshot data version {} cannot be translated, only the snapshots taken by the \
+                 current release and the previous {} ones can be loaded.",
+                data_version, TRANSLATED_RELEASES
+            ),
+            IncompatibleFields(fields) => write!(
+                f,
+                "The microVM state holds fields this release cannot restore: {}.",
+                fields.join(", ")
+            ),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Upgrades in place `microvm_state`, deserialized from a snapshot of `data_version`, to the
+/// latest version of `version_map`.
+pub fn translate_microvm_state(
+    microvm_state: &mut MicrovmState,
+    data_version: u16,
+    version_map: &VersionMap,
+) -> Result<()> {
+    check_data_version(data_version, version_map)?;
+
+    if data_version < version_map.latest_version() {
+        info!(
+            "Translating the microVM state from snapshot data version {} to {}.",
+            data_version,
+            version_map.latest_version()
+        );
+        upgrade_device_states(&mut microvm_state.device_states, data_version);
+    }
+
+    let fields = incompatible_fields(&microvm_state.device_states);
+    if !fields.is_empty() {
+        return Err(Error::IncompatibleFields(fields));
+    }
+    Ok(())
+}
+
+fn check_data_version(data_version: u16, version_map: &VersionMap) -> Result<()> {
+    let latest_version = version_map.latest_version();
+    let oldest_version = latest_version.saturating_sub(TRANSLATED_RELEASES).max(1);
+    if data_version < oldest_version || data_version > latest_version {
+        return Err(Error::UnsupportedDataVersion(data_version));
+    }
+    Ok(())
+}
+
+fn upgrade_device_states(device_states: &mut DeviceStates, data_version: u16) {
+    for (version, upgrade) in DEVICE_STATES_UPGRADES.iter() {
+        if data_version < *version {
+            upgrade(device_states);
+        }
+    }
+}
+
+// The MMDS version is only saved from v1.1, older releases served the MMDS in the default
+// version.
+fn persist_default_mmds_version(device_states: &mut DeviceStates) {
+    if device_states.mmds_version.is_none()
+        && device_states
+            .net_devices
+            .iter()
+            .any(|net| net.device_state.mmds_ns.is_some())
+    {
+        device_states.mmds_version = Some(MmdsVersionState::V1);
+    }
+}
+
+// Lists the fields of the device states the devices of the current release can't be restored
+// from, prefixed by the device they belong to.
+fn incompatible_fields(device_states: &DeviceStates) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut add_fields = |devices: &str, id: &str, device_fields: Vec<String>| {
+        fields.extend(
+            device_fields
+                .into_iter()
+                .map(|field| format!("{}[{}].{}", devices, id, field)),
+        );
+    };
+
+    for block in device_states.block_devices.iter() {
+        add_fields(
+            "block_devices",
+            &block.device_id,
+            block.device_state.incompatible_fields(),
+        );
+    }
+    for net in device_states.net_devices.iter() {
+        add_fields(
+            "net_devices",
+            &net.device_id,
+            net.device_state.incompatible_fields(),
+        );
+    }
+    for vsock in device_states
+        .vsock_device
+        .iter()
+        .chain(device_states.vsock_devices.iter())
+    {
+        add_fields(
+            "vsock_devices",
+            &vsock.device_id,
+            vsock.device_state.incompatible_fields(),
+        );
+    }
+    if let Some(balloon) = device_states.balloon_device.as_ref() {
+        add_fields(
+            "balloon_device",
+            &balloon.device_id,
+            balloon.device_state.incompatible_fields(),
+        );
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use mmds::data_store::MmdsVersion;
+
+    use super::*;
+    use crate::builder::tests::*;
+    use crate::version_map::{FC_V1_0_SNAP_VERSION, VERSION_MAP};
+    use crate::vmm_config::net::{
+        NetBackendType, NetDatapath, NetOffloads, NetworkInterfaceConfig,
+    };
+    use crate::EventManager;
+
+    #[test]
+    fn test_check_data_version() {
+        let latest_version = VERSION_MAP.latest_version();
+        assert!(check_data_version(latest_version, &VERSION_MAP).is_ok());
+        assert!(check_data_version(latest_version - TRANSLATED_RELEASES, &VERSION_MAP).is_ok());
+        assert_eq!(
+            check_data_version(latest_version + 1, &VERSION_MAP),
+            Err(Error::UnsupportedDataVersion(latest_version + 1))
+        );
+        assert_eq!(
+            check_data_version(0, &VERSION_MAP),
+            Err(Error::UnsupportedDataVersion(0))
+        );
+
+        let mut version_map = VERSION_MAP.clone();
+        for _ in 0..TRANSLATED_RELEASES {
+            version_map.new_version();
+        }
+        assert_eq!(
+            check_data_version(latest_version - 1, &version_map),
+            Err(Error::UnsupportedDataVersion(latest_version - 1))
+        );
+    }
+
+    #[test]
+    fn test_upgrade_device_states() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: String::from("hostname"),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            backend_type: NetBackendType::default(),
+            num_queues: 1,
+            backend: NetDatapath::Virtio,
+            mtu: None,
+            offloads: NetOffloads::default(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
+        };
+        insert_net_device_with_mmds(
+            &mut vmm,
+            &mut cmdline,
+            &mut event_manager,
+            network_interface,
+            MmdsVersion::V2,
+        );
+        let mut device_states = vmm.mmio_device_manager.save();
+        assert_eq!(device_states.mmds_version, Some(MmdsVersionState::V2));
+        assert!(incompatible_fields(&device_states).is_empty());
+
+        // A persisted MMDS version is kept.
+        upgrade_device_states(&mut device_states, FC_V1_0_SNAP_VERSION);
+        assert_eq!(device_states.mmds_version, Some(MmdsVersionState::V2));
+
+        // The MMDS version of the releases which didn't save it is the default one.
+        device_states.mmds_version = None;
+        upgrade_device_states(&mut device_states, FC_V1_1_SNAP_VERSION);
+        assert_eq!(device_states.mmds_version, None);
+        upgrade_device_states(&mut device_states, FC_V1_0_SNAP_VERSION);
+        assert_eq!(device_states.mmds_version, Some(MmdsVersionState::V1));
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = Error::IncompatibleFields(vec![
+            "block_devices[root].virtio_state.device_type: 3 instead of 2".to_string(),
+            "net_devices[netif].virtio_state.queues[0].max_size: 128 instead of 256".to_string(),
+        ]);
+        assert_eq!(
+            err.to_string(),
+            "The microVM state holds fields this release cannot restore: \
+             block_devices[root].virtio_state.device_type: 3 instead of 2, \
+             net_devices[netif].virtio_state.queues[0].max_size: 128 instead of 256."
+        );
+
+        let err = Error::UnsupportedDataVersion(0);
+        let _ = format!("{}{:?}", err, err);
+    }
+}
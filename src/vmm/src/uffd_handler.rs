@@ -0,0 +1,372 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Page fault handler populating the guest memory from the memory file of a snapshot on first
+//! access, so that a microVM can be resumed before its memory is loaded.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::{ptr, thread};
+
+use logger::{debug, error, warn};
+use seccompiler::{BpfProgram, BpfThreadMap};
+use userfaultfd::{Event, FeatureFlags, Uffd, UffdBuilder};
+use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+use crate::memory_snapshot::GuestMemoryState;
+
+/// Errors associated with the lazy loading of the guest memory.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to create the UFFD.
+    CreateUffd(userfaultfd::Error),
+    /// Failed to map the memory file.
+    MapMemoryFile(io::Error),
+    /// The memory file doesn't hold the whole guest memory.
+    MemoryFileTooSmall,
+    /// The seccomp filters of the page fault handler thread are missing.
+    MissingSeccompFilter,
+    /// Failed to get the page size.
+    PageSize(utils::errno::Error),
+    /// Failed to register the guest memory regions to the UFFD.
+    RegisterRegion(userfaultfd::Error),
+    /// Failed to populate a guest page.
+    ServePage(userfaultfd::Error),
+    /// Failed to spawn the page fault handler thread.
+    StartThread(io::Error),
+    /// The memory file is compressed, or isn't a regular file, so its pages can't be mapped.
+    UnsupportedMemoryFile,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+        match self {
+            CreateUffd(err) => write!(f, "Cannot create the UFFD: {:?}", err),
+            MapMemoryFile(err) => write!(f, "Cannot map the memory file: {}", err),
+            MemoryFileTooSmall => write!(f, "The memory file is smaller than the guest memory."),
+            MissingSeccompFilter => write!(
+                f,
+                "Missing seccomp filters for the uffd_handler thread category."
+            ),
+            PageSize(err) => write!(f, "Cannot get the page size: {}", err),
+            RegisterRegion(err) => {
+                write!(f, "Cannot register memory regions to UFFD: {:?}", err)
+            }
+            ServePage(err) => write!(f, "Cannot populate a guest page: {:?}", err),
+            StartThread(err) => write!(f, "Cannot start the page fault handler: {}", err),
+            UnsupportedMemoryFile => write!(
+                f,
+                "A lazy resume needs an uncompressed memory file which can be mapped."
+            ),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// Read-only mapping of the memory file, the guest pages are copied from.
+struct MemoryFileMapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+// The mapping is only read, from the thread owning it.
+unsafe impl Send for MemoryFileMapping {}
+
+impl MemoryFileMapping {
+    fn new(file: &File, len: usize) -> Result<Self> {
+        // Safe because the mapping is checked, and is only accessed within `len`.
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE | libc::MAP_NORESERVE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(Error::MapMemoryFile(io::Error::last_os_error()));
+        }
+        Ok(MemoryFileMapping {
+            addr: addr as *mut u8,
+            len,
+        })
+    }
+}
+
+impl Drop for MemoryFileMapping {
+    fn drop(&mut self) {
+        // Safe because the mapping was created by `new` and isn't used anymore.
+        unsafe { libc::munmap(self.addr as *mut libc::c_void, self.len) };
+    }
+}
+
+// Where a guest memory region is mapped in Firecracker, and saved in the memory file.
+struct RegionMapping {
+    host_addr: u64,
+    size: u64,
+    offset: u64,
+}
+
+/// Serves the page faults of the guest memory by copying the faulting pages from the memory
+/// file, through UFFD.
+pub(crate) struct UffdHandler {
+    uffd: Uffd,
+    memory_file: MemoryFileMapping,
+    regions: Vec<RegionMapping>,
+    // Pages given back to the host through the balloon, which are zeroed instead of copied
+    // from the memory file when the guest accesses them again.
+    removed_pages: HashSet<u64>,
+    page_size: u64,
+}
+
+impl UffdHandler {
+    /// Registers `guest_memory`, laid out in `mem_file` as described by `mem_state`, to a new
+    /// UFFD. The pages released through the balloon are tracked when `enable_balloon` is set.
+    pub(crate) fn new(
+        mem_file: &File,
+        guest_memory: &GuestMemoryMmap,
+        mem_state: &GuestMemoryState,
+        enable_balloon: bool,
+    ) -> Result<Self> {
+        let page_size = utils::get_page_size().map_err(Error::PageSize)? as u64;
+        let file_len = mem_file.metadata().map_err(Error::MapMemoryFile)?.len();
+        if mem_state
+            .regions
+            .iter()
+            .any(|region| region.offset + region.size as u64 > file_len)
+        {
+            return Err(Error::MemoryFileTooSmall);
+        }
+        let memory_file = MemoryFileMapping::new(mem_file, file_len as usize)?;
+
+        let mut uffd_builder = UffdBuilder::new();
+        if enable_balloon {
+            // Lets the handler know about the pages released with madvise(MADV_DONTNEED).
+            uffd_builder.require_features(FeatureFlags::EVENT_REMOVE);
+        }
+        // The handler thread waits for the page faults on a blocking read.
+        let uffd = uffd_builder
+            .close_on_exec(true)
+            .non_blocking(false)
+            .create()
+            .map_err(Error::CreateUffd)?;
+
+        let mut regions = Vec::with_capacity(guest_memory.num_regions());
+        for (mem_region, state_region) in guest_memory.iter().zip(mem_state.regions.iter()) {
+            let host_addr = mem_region.as_ptr();
+            uffd.register(host_addr as _, mem_region.size() as _)
+                .map_err(Error::RegisterRegion)?;
+            regions.push(RegionMapping {
+                host_addr: host_addr as u64,
+                size: mem_region.size() as u64,
+                offset: state_region.offset,
+            });
+        }
+
+        Ok(UffdHandler {
+            uffd,
+            memory_file,
+            regions,
+            removed_pages: HashSet::new(),
+            page_size,
+        })
+    }
+
+    /// Serves the page faults on a thread of its own, for as long as Firecracker runs.
+    pub(crate) fn start(self, seccomp_filters: &BpfThreadMap) -> Result<()> {
+        let seccomp_filter: Arc<BpfProgram> = seccomp_filters
+            .get("uffd_handler")
+            .ok_or(Error::MissingSeccompFilter)?
+            .clone();
+
+        thread::Builder::new()
+            .name("fc_uffd".to_string())
+            .spawn(move || {
+                // Execution panics if filters cannot be loaded, use --no-seccomp if skipping
+                // filters altogether is the desired behaviour.
+                if let Err(e) = seccompiler::apply_filter(&seccomp_filter) {
+                    panic!(
+                        "Failed to set the requested seccomp filters on the page fault \
+                         handler: Error: {}",
+                        e
+                    );
+                }
+                self.run();
+            })
+            .map(|_| ())
+            .map_err(Error::StartThread)
+    }
+
+    fn run(mut self) {
+        loop {
+            let event = match self.uffd.read_event() {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(e) => {
+                    // The guest freezes on the next page fault, there is no way to recover.
+                    error!("Cannot read the page faults of the guest memory: {:?}", e);
+                    return;
+                }
+            };
+            if let Err(e) = self.handle_event(event) {
+                error!("{}", e);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Pagefault { addr, .. } => self.serve_page_fault(addr as u64),
+            Event::Remove { start, end } => {
+                let mut page = start as u64;
+                while page < end as u64 {
+                    self.removed_pages.insert(page);
+                    page += self.page_size;
+                }
+                Ok(())
+            }
+            _ => {
+                warn!("Unexpected UFFD event on the guest memory.");
+                Ok(())
+            }
+        }
+    }
+
+    // Populates the page holding `addr`, from the memory file unless the guest released it.
+    fn serve_page_fault(&mut self, addr: u64) -> Result<()> {
+        let page = addr & !(self.page_size - 1);
+        let result = if self.removed_pages.contains(&page) {
+            // Safe because the page belongs to a registered region.
+            unsafe {
+                self.uffd
+                    .zeropage(page as *mut _, self.page_size as usize, true)
+            }
+        } else {
+            let region =
+                match self.regions.iter().find(|region| {
+                    page >= region.host_addr && page < region.host_addr + region.size
+                }) {
+                    Some(region) => region,
+                    None => {
+                        warn!("Page fault outside of the guest memory: {:#x}", addr);
+                        return Ok(());
+                    }
+                };
+            let file_offset = region.offset + (page - region.host_addr);
+            // Safe because the page belongs to a registered region, and the memory file was
+            // checked to hold the whole region.
+            unsafe {
+                self.uffd.copy(
+                    self.memory_file.addr.add(file_offset as usize) as *const _,
+                    page as *mut _,
+                    self.page_size as usize,
+                    true,
+                )
+            }
+        };
+
+        if let Err(e) = result {
+            // Several vCPUs may fault on the same page, which then already got populated for
+            // the first one: the others only need to be woken up.
+            debug!("Cannot populate the guest page {:#x}: {:?}", page, e);
+            self.uffd
+                .wake(page as *mut _, self.page_size as usize)
+                .map_err(Error::ServePage)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+    use vm_memory::{Bytes, GuestAddress};
+
+    use super::*;
+    use crate::memory_snapshot::SnapshotMemory;
+
+    #[test]
+    fn test_lazy_loading() {
+        let page_size = utils::get_page_size().unwrap();
+        let guest_memory = vm_memory::create_guest_memory(
+            &[
+                (None, GuestAddress(0), page_size * 2),
+                (None, GuestAddress(page_size as u64 * 4), page_size * 2),
+            ],
+            false,
+        )
+        .unwrap();
+        let mem_state = guest_memory.describe();
+        let mem_file = TempFile::new().unwrap();
+        for page in 0..4u8 {
+            mem_file
+                .as_file()
+                .write_all(&vec![page + 1; page_size])
+                .unwrap();
+        }
+
+        // The memory file must hold all the guest memory.
+        let small_file = TempFile::new().unwrap();
+        assert!(matches!(
+            UffdHandler::new(small_file.as_file(), &guest_memory, &mem_state, true),
+            Err(Error::MemoryFileTooSmall)
+        ));
+
+        let handler = match UffdHandler::new(mem_file.as_file(), &guest_memory, &mem_state, true) {
+            Ok(handler) => handler,
+            // Creating a UFFD needs privileges the test may not have.
+            Err(Error::CreateUffd(_)) => return,
+            Err(e) => panic!("{}", e),
+        };
+        let mut seccomp_filters = BpfThreadMap::new();
+        seccomp_filters.insert("uffd_handler".to_string(), Arc::new(vec![]));
+        handler.start(&seccomp_filters).unwrap();
+
+        // The pages are copied from the memory file on first access.
+        let mut page = vec![0u8; page_size];
+        guest_memory
+            .read_slice(&mut page, GuestAddress(page_size as u64 * 5))
+            .unwrap();
+        assert_eq!(page, vec![4u8; page_size]);
+        guest_memory.read_slice(&mut page, GuestAddress(0)).unwrap();
+        assert_eq!(page, vec![1u8; page_size]);
+
+        // The pages released by the balloon are zeroed.
+        let host_addr = guest_memory.get_host_address(GuestAddress(0)).unwrap();
+        // Safe because the page belongs to the guest memory.
+        assert_eq!(
+            unsafe { libc::madvise(host_addr as *mut _, page_size, libc::MADV_DONTNEED) },
+            0
+        );
+        guest_memory.read_slice(&mut page, GuestAddress(0)).unwrap();
+        assert_eq!(page, vec![0u8; page_size]);
+        guest_memory
+            .read_slice(&mut page, GuestAddress(page_size as u64))
+            .unwrap();
+        assert_eq!(page, vec![2u8; page_size]);
+    }
+
+    #[test]
+    fn test_error_display() {
+        use self::Error::*;
+
+        let err = MemoryFileTooSmall;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = StartThread(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UnsupportedMemoryFile;
+        let _ = format!("{}{:?}", err, err);
+    }
+}
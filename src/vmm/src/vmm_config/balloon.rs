@@ -5,22 +5,31 @@ use std::fmt;
 use std::sync::{Arc, Mutex};
 
 pub use devices::virtio::balloon::device::BalloonStats;
+pub use devices::virtio::balloon::BalloonPolicy;
 use devices::virtio::balloon::Error as BalloonError;
 pub use devices::virtio::BALLOON_DEV_ID;
 use devices::virtio::{Balloon, BalloonConfig};
 use serde::{Deserialize, Serialize};
 
+use crate::Error as VmmError;
+
 type MutexBalloon = Arc<Mutex<Balloon>>;
 
 /// Errors associated with the operations allowed on the balloon.
 #[derive(Debug)]
 pub enum BalloonConfigError {
+    /// The microVM already has a balloon device, which can't be replaced after boot.
+    DeviceAlreadyAttached,
+    /// Error while attaching the balloon device to the running microVM.
+    DeviceHotplug(VmmError),
     /// The user made a request on an inexistent balloon device.
     DeviceNotFound,
     /// Device not activated yet.
     DeviceNotActive,
     /// The user tried to enable/disable the statistics after boot.
     InvalidStatsUpdate,
+    /// The thresholds of the balloon policy are not ordered or its step is 0.
+    InvalidPolicy,
     /// Amount of pages requested is too large.
     TooManyPagesRequested,
     /// The user polled the statistics of a balloon device that
@@ -36,12 +45,21 @@ impl fmt::Display for BalloonConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> std::fmt::Result {
         use self::BalloonConfigError::*;
         match self {
+            DeviceAlreadyAttached => write!(
+                f,
+                "The balloon device is already attached, it can only be updated through PATCH."
+            ),
+            DeviceHotplug(e) => write!(f, "Error while attaching the balloon device: {}", e),
             DeviceNotFound => write!(f, "No balloon device found."),
             DeviceNotActive => write!(
                 f,
                 "Device is inactive, check if balloon driver is enabled in guest kernel."
             ),
             InvalidStatsUpdate => write!(f, "Cannot enable/disable the statistics after boot."),
+            InvalidPolicy => write!(
+                f,
+                "The balloon policy thresholds must be ordered, and its step must not be 0."
+            ),
             TooManyPagesRequested => write!(f, "Amount of pages requested is too large."),
             StatsNotFound => write!(f, "Statistics for the balloon device are not enabled"),
             CreateFailure(e) => write!(f, "Error creating the balloon device: {:?}", e),
@@ -60,6 +78,7 @@ impl From<BalloonError> for BalloonConfigError {
             BalloonError::DeviceNotFound => Self::DeviceNotFound,
             BalloonError::DeviceNotActive => Self::DeviceNotActive,
             BalloonError::InterruptError(io_error) => Self::UpdateFailure(io_error),
+            BalloonError::InvalidPolicy => Self::InvalidPolicy,
             BalloonError::StatisticsStateChange => Self::InvalidStatsUpdate,
             BalloonError::StatisticsDisabled => Self::StatsNotFound,
             BalloonError::TooManyPagesRequested => Self::TooManyPagesRequested,
@@ -82,6 +101,9 @@ pub struct BalloonDeviceConfig {
     /// Interval in seconds between refreshing statistics.
     #[serde(default)]
     pub stats_polling_interval_s: u16,
+    /// Option to let the guest report its free pages, which are then released.
+    #[serde(default)]
+    pub free_page_reporting: bool,
 }
 
 impl From<BalloonConfig> for BalloonDeviceConfig {
@@ -90,6 +112,7 @@ impl From<BalloonConfig> for BalloonDeviceConfig {
             amount_mib: state.amount_mib,
             deflate_on_oom: state.deflate_on_oom,
             stats_polling_interval_s: state.stats_polling_interval_s,
+            free_page_reporting: state.free_page_reporting,
         }
     }
 }
@@ -140,6 +163,7 @@ impl BalloonBuilder {
                 cfg.amount_mib,
                 cfg.deflate_on_oom,
                 cfg.stats_polling_interval_s,
+                cfg.free_page_reporting,
                 // `restored` flag is false because this code path
                 // is never called by snapshot restore functionality.
                 false,
@@ -150,6 +174,11 @@ impl BalloonBuilder {
         Ok(())
     }
 
+    /// Removes the balloon device from the store.
+    pub fn clear(&mut self) {
+        self.inner = None;
+    }
+
     /// Inserts an existing balloon device.
     pub fn set_device(&mut self, balloon: MutexBalloon) {
         self.inner = Some(balloon);
@@ -178,6 +207,7 @@ pub(crate) mod tests {
             amount_mib: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         }
     }
 
@@ -196,6 +226,7 @@ pub(crate) mod tests {
             amount_mib: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
         assert_eq!(default_balloon_config, balloon_config);
         let mut builder = BalloonBuilder::new();
@@ -217,12 +248,14 @@ pub(crate) mod tests {
             amount_mib: 5,
             deflate_on_oom: false,
             stats_polling_interval_s: 3,
+            free_page_reporting: false,
         };
 
         let actual_balloon_config = BalloonDeviceConfig::from(BalloonConfig {
             amount_mib: 5,
             deflate_on_oom: false,
             stats_polling_interval_s: 3,
+            free_page_reporting: false,
         });
 
         assert_eq!(expected_balloon_config, actual_balloon_config);
@@ -241,12 +274,21 @@ pub(crate) mod tests {
         let err = UpdateFailure(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = DeviceAlreadyAttached;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DeviceHotplug(VmmError::VcpuMessage);
+        let _ = format!("{}{:?}", err, err);
+
         let err = DeviceNotFound;
         let _ = format!("{}{:?}", err, err);
 
         let err = InvalidStatsUpdate;
         let _ = format!("{}{:?}", err, err);
 
+        let err = InvalidPolicy;
+        let _ = format!("{}{:?}", err, err);
+
         let err = TooManyPagesRequested;
         let _ = format!("{}{:?}", err, err);
 
@@ -257,8 +299,10 @@ pub(crate) mod tests {
     #[test]
     fn test_set_device() {
         let mut builder = BalloonBuilder::new();
-        let balloon = Balloon::new(0, true, 0, true).unwrap();
+        let balloon = Balloon::new(0, true, 0, false, true).unwrap();
         builder.set_device(Arc::new(Mutex::new(balloon)));
         assert!(builder.inner.is_some());
+        builder.clear();
+        assert!(builder.get().is_none());
     }
 }
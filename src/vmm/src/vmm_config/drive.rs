@@ -5,15 +5,19 @@ use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::fmt::{Display, Formatter};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::{io, result};
 
-pub use devices::virtio::block::device::FileEngineType;
-use devices::virtio::block::Error as BlockError;
-use devices::virtio::Block;
+pub use devices::virtio::block::device::{FileEngineType, ImageFormat};
+pub use devices::virtio::block::TraceFormat;
+use devices::virtio::block::{is_nbd_uri, Error as BlockError, TraceWriter, MAX_NUM_QUEUES};
 pub use devices::virtio::CacheType;
+use devices::virtio::{Block, VirtioDevice};
+use rate_limiter::RateLimiter;
 use serde::{Deserialize, Serialize};
+use virtio_gen::virtio_blk::VIRTIO_BLK_ID_BYTES;
 
 use super::RateLimiterConfig;
 use crate::Error as VmmError;
@@ -30,14 +34,49 @@ pub enum DriveError {
     CreateBlockDevice(BlockError),
     /// Failed to create a `RateLimiter` object.
     CreateRateLimiter(io::Error),
+    /// Error while attaching the drive to the running microVM.
+    DeviceHotplug(VmmError),
+    /// Error while detaching the drive from the running microVM.
+    DeviceRemoval(VmmError),
     /// Error during drive update (patch).
     DeviceUpdate(VmmError),
+    /// A drive with the given id is already attached to the running microVM.
+    DriveAlreadyAttached(String),
+    /// No drive has the given id.
+    DriveNotFound(String),
+    /// The image format can't be accessed with the Async IO engine.
+    IncompatibleIoEngine(ImageFormat),
+    /// The image of the drive is exported by an NBD server, but the drive has an overlay, isn't
+    /// raw or uses the Async IO engine.
+    IncompatibleNbd,
+    /// The drive has an overlay, but is read-only, isn't raw or uses the Async IO engine.
+    IncompatibleOverlay,
+    /// The drive is served by a vhost-user backend, but has a path, an overlay, a rate
+    /// limiter, a serial or several queues, isn't raw or uses the Async IO engine.
+    IncompatibleVhostUser,
+    /// The backing file is specified both by path and by file descriptor, or the drive opened
+    /// from a file descriptor has an overlay.
+    InvalidDiskSource,
     /// The block device path is invalid.
     InvalidBlockDevicePath(String),
+    /// The number of queues is zero or above the maximum.
+    InvalidNumQueues(u16),
+    /// The serial is longer than the ID of a virtio block device, or contains a NUL byte.
+    InvalidSerial(String),
+    /// A trace needs a path and a non-zero cap on the records per second.
+    InvalidTraceConfig,
     /// Cannot open block device due to invalid permissions or path.
     OpenBlockDevice(io::Error),
+    /// Cannot create a trace file.
+    OpenTraceFile(io::Error),
+    /// No rate limiter group has the given id.
+    RateLimiterGroupNotFound(String),
     /// A root block device was already added.
     RootBlockDeviceAlreadyAdded,
+    /// The requests of the drive can't be traced.
+    TraceUnsupported,
+    /// Drives served by a vhost-user backend can't be attached after boot.
+    VhostUserHotplug,
 }
 
 impl Display for DriveError {
@@ -47,14 +86,74 @@ impl Display for DriveError {
             CreateBlockDevice(e) => write!(f, "Unable to create the block device {:?}", e),
             BlockDeviceUpdateFailed(e) => write!(f, "The update operation failed: {}", e),
             CreateRateLimiter(e) => write!(f, "Cannot create RateLimiter: {}", e),
+            DeviceHotplug(e) => write!(f, "Error while attaching the drive: {}", e),
+            DeviceRemoval(e) => write!(f, "Error while detaching the drive: {}", e),
             DeviceUpdate(e) => write!(f, "Error during drive update (patch): {}", e),
+            DriveAlreadyAttached(drive_id) => write!(
+                f,
+                "The drive {} is already attached, it can only be updated through PATCH.",
+                drive_id
+            ),
+            DriveNotFound(drive_id) => write!(f, "The drive {} does not exist.", drive_id),
+            IncompatibleIoEngine(image_format) => write!(
+                f,
+                "The {:?} image format is only supported by the Sync io_engine.",
+                image_format
+            ),
+            IncompatibleNbd => write!(
+                f,
+                "Drives exported by an NBD server can't have an overlay, and must be raw and use \
+                 the Sync io_engine."
+            ),
+            IncompatibleOverlay => write!(
+                f,
+                "Drives with an overlay must be read-write, have a raw base image and use the \
+                 Sync io_engine."
+            ),
+            IncompatibleVhostUser => write!(
+                f,
+                "Drives served by a vhost-user backend can't have a path_on_host, an overlay, a \
+                 rate limiter, a serial or several queues, and must be raw and use the Sync \
+                 io_engine."
+            ),
+            InvalidDiskSource => write!(
+                f,
+                "Drives opened from a file descriptor can't have a path_on_host or an overlay."
+            ),
             InvalidBlockDevicePath(path) => write!(f, "Invalid block device path: {}", path),
+            InvalidNumQueues(num_queues) => write!(
+                f,
+                "Invalid number of queues {}, drives have between 1 and {} queues.",
+                num_queues, MAX_NUM_QUEUES
+            ),
+            InvalidSerial(serial) => write!(
+                f,
+                "Invalid serial {:?}, it must be at most {} bytes long and can't contain NUL \
+                 bytes.",
+                serial, VIRTIO_BLK_ID_BYTES
+            ),
+            InvalidTraceConfig => write!(
+                f,
+                "Starting a trace requires a path_on_host, and max_records_per_sec cannot be 0."
+            ),
+            OpenTraceFile(e) => write!(f, "Cannot create the trace file: {}", e),
             OpenBlockDevice(e) => write!(
                 f,
                 "Cannot open block device. Invalid permission/path: {}",
                 e
             ),
+            RateLimiterGroupNotFound(group_id) => {
+                write!(f, "The rate limiter group {} does not exist.", group_id)
+            }
             RootBlockDeviceAlreadyAdded => write!(f, "A root block device already exists!"),
+            TraceUnsupported => write!(
+                f,
+                "The requests of drives served by a vhost-user backend cannot be traced."
+            ),
+            VhostUserHotplug => write!(
+                f,
+                "Drives served by a vhost-user backend can't be attached to a running microVM."
+            ),
         }
     }
 }
@@ -65,8 +164,14 @@ impl Display for DriveError {
 pub struct BlockDeviceConfig {
     /// Unique identifier of the drive.
     pub drive_id: String,
-    /// Path of the drive.
+    /// Path of the drive. Also accepted as `base_image`, which is more descriptive for drives
+    /// with an overlay. Left empty when `vhost_user_socket` or `fd` is used.
+    #[serde(default, alias = "base_image")]
     pub path_on_host: String,
+    /// File descriptor of an already opened backing file, used instead of `path_on_host`. The
+    /// device takes ownership of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fd: Option<RawFd>,
     /// If set to true, it makes the current device the root block device.
     /// Setting this flag to true will mount the block device in the
     /// guest under /dev/vda unless the partuuid is present.
@@ -83,24 +188,78 @@ pub struct BlockDeviceConfig {
     pub cache_type: CacheType,
     /// Rate Limiter for I/O operations.
     pub rate_limiter: Option<RateLimiterConfig>,
-    /// The type of IO engine used by the device.
+    /// Rate Limiter for the reads only, applied on top of `rate_limiter`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_rate_limiter: Option<RateLimiterConfig>,
+    /// Rate Limiter for the writes only, discards and write zeroes included, applied on top of
+    /// `rate_limiter`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_rate_limiter: Option<RateLimiterConfig>,
+    /// The type of IO engine used by the device. Also accepted as `file_engine`.
     #[serde(default)]
-    #[serde(rename = "io_engine")]
+    #[serde(rename = "io_engine", alias = "file_engine")]
     pub file_engine_type: FileEngineType,
+    /// The format of the image backing the drive.
+    #[serde(default)]
+    #[serde(rename = "format")]
+    pub image_format: ImageFormat,
+    /// Path of a sparse file, created if needed, holding the writes to the drive, which leave
+    /// the file at `path_on_host` untouched. This allows sharing that file between drives.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlay: Option<String>,
+    /// ID of the rate limiter group whose buckets also limit the I/O operations, along with
+    /// the ones of the other devices in the group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rl_group: Option<String>,
+    /// Path of the unix socket of a vhost-user backend, such as SPDK, which serves the
+    /// requests of the drive instead of Firecracker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vhost_user_socket: Option<String>,
+    /// Number of request queues of the drive, one by default. Several queues let the vCPUs of
+    /// the guest submit requests without contending on a single queue.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_queues: Option<u16>,
+    /// Serial reported to the guest as the ID of the drive, of at most 20 bytes, instead of
+    /// the one derived from the backing file. It identifies the volume in the guest, e.g.
+    /// under `/dev/disk/by-id`, regardless of the attach order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
 }
 
 impl From<&Block> for BlockDeviceConfig {
     fn from(block: &Block) -> Self {
         let rl: RateLimiterConfig = block.rate_limiter().into();
+        let read_rl: RateLimiterConfig = block.read_rate_limiter().into();
+        let write_rl: RateLimiterConfig = block.write_rate_limiter().into();
+        let vhost_user_socket = block.vhost_user_socket().cloned();
         BlockDeviceConfig {
             drive_id: block.id().clone(),
-            path_on_host: block.file_path().clone(),
+            path_on_host: if vhost_user_socket.is_some() {
+                String::new()
+            } else {
+                block.file_path().clone()
+            },
+            fd: None,
             is_root_device: block.is_root_device(),
             partuuid: block.partuuid().cloned(),
             is_read_only: block.is_read_only(),
             cache_type: block.cache_type(),
             rate_limiter: rl.into_option(),
+            read_rate_limiter: read_rl.into_option(),
+            write_rate_limiter: write_rl.into_option(),
             file_engine_type: block.file_engine_type(),
+            image_format: block.image_format(),
+            overlay: block.overlay_path().cloned(),
+            rl_group: block
+                .rate_limiter()
+                .group()
+                .map(|group| group.id().to_string()),
+            vhost_user_socket,
+            num_queues: match block.queues().len() {
+                1 => None,
+                num_queues => Some(num_queues as u16),
+            },
+            serial: block.serial().cloned(),
         }
     }
 }
@@ -114,8 +273,79 @@ pub struct BlockDeviceUpdateConfig {
     pub drive_id: String,
     /// New block file path on the host. Only provided data will be updated.
     pub path_on_host: Option<String>,
+    /// New size of the drive, in bytes. The backing file is grown to it if it's smaller.
+    pub size_bytes: Option<u64>,
+    /// New read-only mode of the drive. The writes the guest keeps sending to a drive made
+    /// read-only fail.
+    pub is_read_only: Option<bool>,
     /// New rate limiter config.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// New config of the rate limiter of the reads.
+    pub read_rate_limiter: Option<RateLimiterConfig>,
+    /// New config of the rate limiter of the writes.
+    pub write_rate_limiter: Option<RateLimiterConfig>,
+}
+
+/// Whether the requests of a drive are traced.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TraceState {
+    /// Requests are recorded to the trace file.
+    Started,
+    /// No requests are recorded.
+    Stopped,
+}
+
+/// The data fed into a trace request for a drive.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriveTraceConfig {
+    /// The drive ID, as provided by the user at creation time.
+    pub drive_id: String,
+    /// Starts or stops the trace.
+    pub state: TraceState,
+    /// Path of the trace file. Required to start a trace.
+    #[serde(default)]
+    pub path_on_host: Option<String>,
+    /// Encoding of the records of the trace.
+    #[serde(default)]
+    pub format: TraceFormat,
+    /// Number of requests recorded per second at most. The requests above it are left out of
+    /// the trace and counted in the `block.trace_dropped_count` metric.
+    #[serde(default = "DriveTraceConfig::default_max_records_per_sec")]
+    pub max_records_per_sec: u64,
+}
+
+impl DriveTraceConfig {
+    fn default_max_records_per_sec() -> u64 {
+        1000
+    }
+
+    /// Starts or stops the trace of the requests of `block`. Starting a trace truncates the
+    /// trace file.
+    pub fn apply(&self, block: &mut Block) -> Result<()> {
+        let trace = match self.state {
+            TraceState::Stopped => None,
+            TraceState::Started => {
+                if block.vhost_user_socket().is_some() {
+                    return Err(DriveError::TraceUnsupported);
+                }
+                let path = self
+                    .path_on_host
+                    .as_ref()
+                    .ok_or(DriveError::InvalidTraceConfig)?;
+                if self.max_records_per_sec == 0 {
+                    return Err(DriveError::InvalidTraceConfig);
+                }
+                Some(
+                    TraceWriter::new(Path::new(path), self.format, self.max_records_per_sec)
+                        .map_err(DriveError::OpenTraceFile)?,
+                )
+            }
+        };
+        block
+            .set_trace(trace)
+            .map_err(|_| DriveError::TraceUnsupported)
+    }
 }
 
 /// Wrapper for the collection that holds all the Block Devices
@@ -163,20 +393,114 @@ impl BlockBuilder {
         }
     }
 
-    /// Inserts a `Block` in the block devices list using the specified configuration.
-    /// If a block with the same id already exists, it will overwrite it.
-    /// Inserting a secondary root block device will fail.
-    pub fn insert(&mut self, config: BlockDeviceConfig) -> Result<()> {
-        let is_root_device = config.is_root_device;
+    /// Checks whether a `Block` could be inserted using the specified configuration, without
+    /// modifying the block devices list.
+    pub fn validate(&self, config: &BlockDeviceConfig) -> Result<()> {
         let position = self.get_index_of_drive_id(&config.drive_id);
-        let has_root_block = self.has_root_device();
 
         // Don't allow adding a second root block device.
         // If the new device cfg is root and not an update to the existing root, fail fast.
-        if is_root_device && has_root_block && position != Some(0) {
+        if config.is_root_device && self.has_root_device() && position != Some(0) {
             return Err(DriveError::RootBlockDeviceAlreadyAdded);
         }
 
+        Self::validate_config(config)
+    }
+
+    // Checks the parts of the configuration which don't depend on the other devices.
+    fn validate_config(config: &BlockDeviceConfig) -> Result<()> {
+        let num_queues = config.num_queues.unwrap_or(1);
+        if num_queues == 0 || num_queues > MAX_NUM_QUEUES {
+            return Err(DriveError::InvalidNumQueues(num_queues));
+        }
+
+        if let Some(serial) = &config.serial {
+            if serial.len() > VIRTIO_BLK_ID_BYTES as usize || serial.contains('\0') {
+                return Err(DriveError::InvalidSerial(serial.clone()));
+            }
+        }
+
+        // The backend owns the disk, and the requests never go through the device model.
+        if config.vhost_user_socket.is_some() {
+            if !config.path_on_host.is_empty()
+                || config.serial.is_some()
+                || config.fd.is_some()
+                || config.overlay.is_some()
+                || config.image_format != ImageFormat::Raw
+                || config.file_engine_type == FileEngineType::Async
+                || config.rate_limiter.is_some()
+                || config.read_rate_limiter.is_some()
+                || config.write_rate_limiter.is_some()
+                || config.rl_group.is_some()
+                || num_queues > 1
+            {
+                return Err(DriveError::IncompatibleVhostUser);
+            }
+            return Ok(());
+        }
+
+        if config.fd.is_some() {
+            // The file is already open, there's no path to check.
+            if !config.path_on_host.is_empty() || config.overlay.is_some() {
+                return Err(DriveError::InvalidDiskSource);
+            }
+        } else if is_nbd_uri(&config.path_on_host) {
+            // The image is only reached through the server.
+            if config.overlay.is_some()
+                || config.image_format != ImageFormat::Raw
+                || config.file_engine_type == FileEngineType::Async
+            {
+                return Err(DriveError::IncompatibleNbd);
+            }
+        } else {
+            // check if the path exists
+            let path_on_host = PathBuf::from(&config.path_on_host);
+            if !path_on_host.exists() {
+                return Err(DriveError::InvalidBlockDevicePath(format!(
+                    "{}",
+                    path_on_host.display()
+                )));
+            }
+        }
+
+        // The qcow2 images are only accessed synchronously.
+        if config.image_format == ImageFormat::Qcow2
+            && config.file_engine_type == FileEngineType::Async
+        {
+            return Err(DriveError::IncompatibleIoEngine(config.image_format));
+        }
+
+        if config.overlay.is_some()
+            && (config.is_read_only
+                || config.image_format != ImageFormat::Raw
+                || config.file_engine_type == FileEngineType::Async)
+        {
+            return Err(DriveError::IncompatibleOverlay);
+        }
+
+        for rate_limiter in [
+            config.rate_limiter,
+            config.read_rate_limiter,
+            config.write_rate_limiter,
+        ] {
+            rate_limiter
+                .map(super::RateLimiterConfig::try_into)
+                .transpose()
+                .map(|_: Option<RateLimiter>| ())
+                .map_err(DriveError::CreateRateLimiter)?;
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a `Block` in the block devices list using the specified configuration.
+    /// If a block with the same id already exists, it will overwrite it.
+    /// Inserting a secondary root block device will fail.
+    pub fn insert(&mut self, config: BlockDeviceConfig) -> Result<()> {
+        self.validate(&config)?;
+
+        let is_root_device = config.is_root_device;
+        let position = self.get_index_of_drive_id(&config.drive_id);
         let block_dev = Arc::new(Mutex::new(Self::create_block(config)?));
         // If the id of the drive already exists in the list, the operation is update/overwrite.
         match position {
@@ -202,35 +526,88 @@ impl BlockBuilder {
         Ok(())
     }
 
+    /// Removes the `Block` with id `drive_id` from the block devices list, and returns it.
+    pub fn remove(&mut self, drive_id: &str) -> Result<Arc<Mutex<Block>>> {
+        let index = self
+            .get_index_of_drive_id(drive_id)
+            .ok_or_else(|| DriveError::DriveNotFound(drive_id.to_string()))?;
+        // The order of the remaining devices is kept, with the root device first.
+        Ok(self.list.remove(index).expect("Invalid drive index"))
+    }
+
+    /// Starts or stops the trace of the requests of a block device.
+    pub fn set_trace(&mut self, config: &DriveTraceConfig) -> Result<()> {
+        let index = self
+            .get_index_of_drive_id(&config.drive_id)
+            .ok_or_else(|| DriveError::DriveNotFound(config.drive_id.clone()))?;
+        config.apply(&mut self.list[index].lock().expect("Poisoned lock"))
+    }
+
     /// Creates a Block device from a BlockDeviceConfig.
     pub fn create_block(block_device_config: BlockDeviceConfig) -> Result<Block> {
-        // check if the path exists
-        let path_on_host = PathBuf::from(&block_device_config.path_on_host);
-        if !path_on_host.exists() {
-            return Err(DriveError::InvalidBlockDevicePath(format!(
-                "{}",
-                path_on_host.display()
-            )));
-        }
+        Self::validate_config(&block_device_config)?;
 
         let rate_limiter = block_device_config
             .rate_limiter
             .map(super::RateLimiterConfig::try_into)
             .transpose()
             .map_err(DriveError::CreateRateLimiter)?;
+        let read_rate_limiter = block_device_config
+            .read_rate_limiter
+            .map(super::RateLimiterConfig::try_into)
+            .transpose()
+            .map_err(DriveError::CreateRateLimiter)?;
+        let write_rate_limiter = block_device_config
+            .write_rate_limiter
+            .map(super::RateLimiterConfig::try_into)
+            .transpose()
+            .map_err(DriveError::CreateRateLimiter)?;
 
-        // Create and return the Block device
-        devices::virtio::Block::new(
-            block_device_config.drive_id,
-            block_device_config.partuuid,
-            block_device_config.cache_type,
-            block_device_config.path_on_host,
-            block_device_config.is_read_only,
-            block_device_config.is_root_device,
-            rate_limiter.unwrap_or_default(),
-            block_device_config.file_engine_type,
-        )
-        .map_err(DriveError::CreateBlockDevice)
+        let mut block = if let Some(socket_path) = block_device_config.vhost_user_socket {
+            devices::virtio::Block::new_with_vhost_user(
+                block_device_config.drive_id,
+                block_device_config.partuuid,
+                block_device_config.cache_type,
+                socket_path,
+                block_device_config.is_read_only,
+                block_device_config.is_root_device,
+            )
+        } else if let Some(disk_fd) = block_device_config.fd {
+            devices::virtio::Block::new_with_fd(
+                block_device_config.drive_id,
+                block_device_config.partuuid,
+                block_device_config.cache_type,
+                disk_fd,
+                block_device_config.is_read_only,
+                block_device_config.is_root_device,
+                rate_limiter.unwrap_or_default(),
+                read_rate_limiter.unwrap_or_default(),
+                write_rate_limiter.unwrap_or_default(),
+                block_device_config.file_engine_type,
+                block_device_config.image_format,
+                block_device_config.num_queues.unwrap_or(1),
+            )
+        } else {
+            devices::virtio::Block::new(
+                block_device_config.drive_id,
+                block_device_config.partuuid,
+                block_device_config.cache_type,
+                block_device_config.path_on_host,
+                block_device_config.is_read_only,
+                block_device_config.is_root_device,
+                rate_limiter.unwrap_or_default(),
+                read_rate_limiter.unwrap_or_default(),
+                write_rate_limiter.unwrap_or_default(),
+                block_device_config.file_engine_type,
+                block_device_config.image_format,
+                block_device_config.overlay,
+                block_device_config.num_queues.unwrap_or(1),
+            )
+        }
+        .map_err(DriveError::CreateBlockDevice)?;
+
+        block.set_serial(block_device_config.serial);
+        Ok(block)
     }
 
     /// Returns a vec with the structures used to configure the devices.
@@ -245,6 +622,9 @@ impl BlockBuilder {
 
 #[cfg(test)]
 mod tests {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::IntoRawFd;
+
     use rate_limiter::RateLimiter;
     use utils::tempfile::TempFile;
 
@@ -262,13 +642,22 @@ mod tests {
         fn clone(&self) -> Self {
             BlockDeviceConfig {
                 path_on_host: self.path_on_host.clone(),
+                fd: self.fd,
                 is_root_device: self.is_root_device,
                 partuuid: self.partuuid.clone(),
                 cache_type: self.cache_type,
                 is_read_only: self.is_read_only,
                 drive_id: self.drive_id.clone(),
                 rate_limiter: None,
+                read_rate_limiter: self.read_rate_limiter,
+                write_rate_limiter: self.write_rate_limiter,
                 file_engine_type: FileEngineType::default(),
+                image_format: self.image_format,
+                overlay: self.overlay.clone(),
+                rl_group: self.rl_group.clone(),
+                vhost_user_socket: self.vhost_user_socket.clone(),
+                num_queues: self.num_queues,
+                serial: self.serial.clone(),
             }
         }
     }
@@ -286,13 +675,22 @@ mod tests {
         let dummy_id = String::from("1");
         let dummy_block_device = BlockDeviceConfig {
             path_on_host: dummy_path,
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Writeback,
             is_read_only: false,
             drive_id: dummy_id.clone(),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -317,13 +715,22 @@ mod tests {
 
         let dummy_block_device = BlockDeviceConfig {
             path_on_host: dummy_path,
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: true,
             drive_id: String::from("1"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -345,34 +752,111 @@ mod tests {
         let dummy_path_1 = dummy_file_1.as_path().to_str().unwrap().to_string();
         let root_block_device_1 = BlockDeviceConfig {
             path_on_host: dummy_path_1,
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
         let dummy_path_2 = dummy_file_2.as_path().to_str().unwrap().to_string();
         let root_block_device_2 = BlockDeviceConfig {
             path_on_host: dummy_path_2,
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let mut block_devs = BlockBuilder::new();
-        assert!(block_devs.insert(root_block_device_1).is_ok());
+        assert!(block_devs.validate(&root_block_device_1).is_ok());
+        assert!(block_devs.list.is_empty());
+        assert!(block_devs.insert(root_block_device_1.clone()).is_ok());
+        // Updating the root device is fine, adding a second one isn't.
+        assert!(block_devs.validate(&root_block_device_1).is_ok());
+        assert_eq!(
+            block_devs.validate(&root_block_device_2).unwrap_err(),
+            DriveError::RootBlockDeviceAlreadyAdded
+        );
         assert_eq!(
             block_devs.insert(root_block_device_2).unwrap_err(),
             DriveError::RootBlockDeviceAlreadyAdded
         );
+        assert_eq!(block_devs.list.len(), 1);
+
+        // The configuration is checked as well.
+        let invalid_block_device = BlockDeviceConfig {
+            path_on_host: String::from("/invalid/path"),
+            fd: None,
+            serial: None,
+            is_root_device: false,
+            partuuid: None,
+            cache_type: CacheType::Unsafe,
+            is_read_only: false,
+            drive_id: String::from("3"),
+            rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
+            file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
+        };
+        assert_eq!(
+            block_devs.validate(&invalid_block_device).unwrap_err(),
+            DriveError::InvalidBlockDevicePath(String::from("/invalid/path"))
+        );
+
+        // The qcow2 images can't be accessed with the Async engine.
+        let qcow2_block_device = BlockDeviceConfig {
+            path_on_host: dummy_file_1.as_path().to_str().unwrap().to_string(),
+            fd: None,
+            serial: None,
+            is_root_device: false,
+            partuuid: None,
+            cache_type: CacheType::Unsafe,
+            is_read_only: false,
+            drive_id: String::from("3"),
+            rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
+            file_engine_type: FileEngineType::Async,
+            image_format: ImageFormat::Qcow2,
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
+        };
+        assert_eq!(
+            block_devs.validate(&qcow2_block_device).unwrap_err(),
+            DriveError::IncompatibleIoEngine(ImageFormat::Qcow2)
+        );
     }
 
     #[test]
@@ -382,39 +866,66 @@ mod tests {
         let dummy_path_1 = dummy_file_1.as_path().to_str().unwrap().to_string();
         let root_block_device = BlockDeviceConfig {
             path_on_host: dummy_path_1,
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
         let dummy_path_2 = dummy_file_2.as_path().to_str().unwrap().to_string();
         let dummy_block_dev_2 = BlockDeviceConfig {
             path_on_host: dummy_path_2,
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
         let dummy_path_3 = dummy_file_3.as_path().to_str().unwrap().to_string();
         let dummy_block_dev_3 = BlockDeviceConfig {
             path_on_host: dummy_path_3,
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("3"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -446,39 +957,66 @@ mod tests {
         let dummy_path_1 = dummy_file_1.as_path().to_str().unwrap().to_string();
         let root_block_device = BlockDeviceConfig {
             path_on_host: dummy_path_1,
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
         let dummy_path_2 = dummy_file_2.as_path().to_str().unwrap().to_string();
         let dummy_block_dev_2 = BlockDeviceConfig {
             path_on_host: dummy_path_2,
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
         let dummy_path_3 = dummy_file_3.as_path().to_str().unwrap().to_string();
         let dummy_block_dev_3 = BlockDeviceConfig {
             path_on_host: dummy_path_3,
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("3"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -511,26 +1049,44 @@ mod tests {
         let dummy_path_1 = dummy_file_1.as_path().to_str().unwrap().to_string();
         let root_block_device = BlockDeviceConfig {
             path_on_host: dummy_path_1.clone(),
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
         let dummy_path_2 = dummy_file_2.as_path().to_str().unwrap().to_string();
         let mut dummy_block_device_2 = BlockDeviceConfig {
             path_on_host: dummy_path_2.clone(),
+            fd: None,
+            serial: None,
             is_root_device: false,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -583,26 +1139,44 @@ mod tests {
 
         let root_block_device = BlockDeviceConfig {
             path_on_host: dummy_path_1,
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
         // Switch roots and add a PARTUUID for the new one.
         let mut root_block_device_old = root_block_device;
         root_block_device_old.is_root_device = false;
         let root_block_device_new = BlockDeviceConfig {
             path_on_host: dummy_path_2,
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: Some("0eaa91a0-01".to_string()),
             cache_type: CacheType::Unsafe,
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
         assert!(block_devs.insert(root_block_device_old).is_ok());
         let root_block_id = root_block_device_new.drive_id.clone();
@@ -618,13 +1192,22 @@ mod tests {
 
         let dummy_block_device = BlockDeviceConfig {
             path_on_host: dummy_file.as_path().to_str().unwrap().to_string(),
+            fd: None,
+            serial: None,
             is_root_device: true,
             partuuid: None,
             cache_type: CacheType::Unsafe,
             is_read_only: true,
             drive_id: String::from("1"),
             rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: None,
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -635,6 +1218,315 @@ mod tests {
         assert_eq!(configs.first().unwrap(), &dummy_block_device);
     }
 
+    #[test]
+    fn test_overlay_config() {
+        let base = TempFile::new().unwrap();
+        base.as_file().set_len(0x1000).unwrap();
+        let overlay = TempFile::new().unwrap();
+
+        let mut block_device = BlockDeviceConfig {
+            path_on_host: base.as_path().to_str().unwrap().to_string(),
+            fd: None,
+            serial: None,
+            is_root_device: false,
+            partuuid: None,
+            cache_type: CacheType::Unsafe,
+            is_read_only: true,
+            drive_id: String::from("1"),
+            rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
+            file_engine_type: FileEngineType::default(),
+            image_format: ImageFormat::default(),
+            overlay: Some(overlay.as_path().to_str().unwrap().to_string()),
+            rl_group: None,
+            vhost_user_socket: None,
+            num_queues: None,
+        };
+        let mut block_devs = BlockBuilder::new();
+
+        // The overlay holds the writes, so the drive can't be read-only.
+        assert_eq!(
+            block_devs.insert(block_device.clone()).unwrap_err(),
+            DriveError::IncompatibleOverlay
+        );
+        block_device.is_read_only = false;
+        block_device.image_format = ImageFormat::Qcow2;
+        assert_eq!(
+            block_devs.insert(block_device.clone()).unwrap_err(),
+            DriveError::IncompatibleOverlay
+        );
+
+        block_device.image_format = ImageFormat::Raw;
+        block_devs.insert(block_device.clone()).unwrap();
+        assert_eq!(block_devs.configs(), vec![block_device]);
+    }
+
+    #[test]
+    fn test_vhost_user_config() {
+        let json = r#"{
+            "drive_id": "vhost_user",
+            "is_root_device": false,
+            "is_read_only": false,
+            "vhost_user_socket": "/run/vhost-user-blk.sock"
+        }"#;
+        let mut block_device: BlockDeviceConfig = serde_json::from_str(json).unwrap();
+        assert!(block_device.path_on_host.is_empty());
+        assert_eq!(
+            block_device.vhost_user_socket.as_deref(),
+            Some("/run/vhost-user-blk.sock")
+        );
+        let block_devs = BlockBuilder::new();
+        block_devs.validate(&block_device).unwrap();
+
+        // The backend owns the disk.
+        block_device.path_on_host = "/dev/null".to_string();
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::IncompatibleVhostUser
+        );
+        block_device.path_on_host = String::new();
+        block_device.image_format = ImageFormat::Qcow2;
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::IncompatibleVhostUser
+        );
+        block_device.image_format = ImageFormat::Raw;
+        block_device.rl_group = Some("group".to_string());
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::IncompatibleVhostUser
+        );
+        block_device.rl_group = None;
+        block_device.num_queues = Some(2);
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::IncompatibleVhostUser
+        );
+        block_device.num_queues = None;
+
+        // Nobody listens on the socket.
+        let mut block_devs = BlockBuilder::new();
+        block_device.vhost_user_socket = Some("/tmp/fc-vhost-user-nonexistent.sock".to_string());
+        assert!(matches!(
+            block_devs.insert(block_device),
+            Err(DriveError::CreateBlockDevice(BlockError::VhostUser(_)))
+        ));
+    }
+
+    #[test]
+    fn test_nbd_config() {
+        let json = r#"{
+            "drive_id": "nbd",
+            "path_on_host": "nbd+unix:///rootfs?socket=/tmp/fc-nbd-nonexistent.sock",
+            "is_root_device": true,
+            "is_read_only": false
+        }"#;
+        let mut block_device: BlockDeviceConfig = serde_json::from_str(json).unwrap();
+        let mut block_devs = BlockBuilder::new();
+        // The export doesn't have to exist on the host.
+        block_devs.validate(&block_device).unwrap();
+
+        block_device.image_format = ImageFormat::Qcow2;
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::IncompatibleNbd
+        );
+        block_device.image_format = ImageFormat::Raw;
+        block_device.file_engine_type = FileEngineType::Async;
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::IncompatibleNbd
+        );
+        block_device.file_engine_type = FileEngineType::Sync;
+        block_device.overlay = Some("/tmp/overlay".to_string());
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::IncompatibleNbd
+        );
+        block_device.overlay = None;
+
+        // Nobody listens on the socket.
+        assert!(matches!(
+            block_devs.insert(block_device),
+            Err(DriveError::CreateBlockDevice(BlockError::FileEngine(_)))
+        ));
+    }
+
+    #[test]
+    fn test_fd_config() {
+        let backing_file = TempFile::new().unwrap();
+        let disk_fd = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(backing_file.as_path())
+            .unwrap()
+            .into_raw_fd();
+        let json = format!(
+            r#"{{
+                "drive_id": "fd",
+                "fd": {},
+                "is_root_device": false,
+                "is_read_only": false
+            }}"#,
+            disk_fd
+        );
+        let mut block_device: BlockDeviceConfig = serde_json::from_str(&json).unwrap();
+        let mut block_devs = BlockBuilder::new();
+        block_devs.validate(&block_device).unwrap();
+
+        // The backing file can't be specified both by path and by fd.
+        block_device.path_on_host = backing_file.as_path().to_str().unwrap().to_string();
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::InvalidDiskSource
+        );
+        block_device.path_on_host = String::new();
+        block_device.overlay = Some("/tmp/overlay".to_string());
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::InvalidDiskSource
+        );
+        block_device.overlay = None;
+        block_device.vhost_user_socket = Some("/tmp/vhost-user-blk.sock".to_string());
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::IncompatibleVhostUser
+        );
+        block_device.vhost_user_socket = None;
+
+        block_devs.insert(block_device).unwrap();
+        let config = &block_devs.configs()[0];
+        assert!(config.path_on_host.is_empty());
+        assert_eq!(config.fd, None);
+        assert!(block_devs.list[0].lock().unwrap().has_disk_fd());
+    }
+
+    #[test]
+    fn test_serial_config() {
+        let backing_file = TempFile::new().unwrap();
+        let json = format!(
+            r#"{{
+                "drive_id": "serial",
+                "path_on_host": "{}",
+                "is_root_device": false,
+                "is_read_only": false,
+                "serial": "vol-0123456789abcdef"
+            }}"#,
+            backing_file.as_path().to_str().unwrap()
+        );
+        let mut block_device: BlockDeviceConfig = serde_json::from_str(&json).unwrap();
+        let mut block_devs = BlockBuilder::new();
+        block_devs.validate(&block_device).unwrap();
+
+        // The serial must fit in the ID of the device.
+        block_device.serial = Some("vol-0123456789abcdefg".to_string());
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::InvalidSerial("vol-0123456789abcdefg".to_string())
+        );
+        block_device.serial = Some("vol\0".to_string());
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::InvalidSerial("vol\0".to_string())
+        );
+
+        block_device.serial = Some("vol-0123456789abcdef".to_string());
+        block_devs.insert(block_device).unwrap();
+        assert_eq!(
+            block_devs.configs()[0].serial.as_deref(),
+            Some("vol-0123456789abcdef")
+        );
+    }
+
+    #[test]
+    fn test_num_queues_config() {
+        let backing_file = TempFile::new().unwrap();
+        let json = format!(
+            r#"{{
+                "drive_id": "multi_queue",
+                "path_on_host": "{}",
+                "is_root_device": false,
+                "is_read_only": false,
+                "num_queues": 4
+            }}"#,
+            backing_file.as_path().to_str().unwrap()
+        );
+        let mut block_device: BlockDeviceConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(block_device.num_queues, Some(4));
+
+        let mut block_devs = BlockBuilder::new();
+        block_devs.insert(block_device.clone()).unwrap();
+        assert_eq!(block_devs.list[0].lock().unwrap().queue_events().len(), 4);
+        assert_eq!(block_devs.configs(), vec![block_device.clone()]);
+
+        block_device.num_queues = Some(0);
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::InvalidNumQueues(0)
+        );
+        block_device.num_queues = Some(MAX_NUM_QUEUES + 1);
+        assert_eq!(
+            block_devs.validate(&block_device).unwrap_err(),
+            DriveError::InvalidNumQueues(MAX_NUM_QUEUES + 1)
+        );
+
+        // A single queue is the default.
+        block_device.num_queues = Some(1);
+        block_devs.insert(block_device).unwrap();
+        assert_eq!(block_devs.configs()[0].num_queues, None);
+    }
+
+    #[test]
+    fn test_read_write_rate_limiters_config() {
+        let backing_file = TempFile::new().unwrap();
+        let json = format!(
+            r#"{{
+                "drive_id": "rw_rate_limiters",
+                "path_on_host": "{}",
+                "is_root_device": false,
+                "is_read_only": false,
+                "read_rate_limiter": {{
+                    "bandwidth": {{ "size": 1048576, "refill_time": 1000 }}
+                }},
+                "write_rate_limiter": {{
+                    "ops": {{ "size": 100, "refill_time": 1000 }}
+                }}
+            }}"#,
+            backing_file.as_path().to_str().unwrap()
+        );
+        let block_device: BlockDeviceConfig = serde_json::from_str(&json).unwrap();
+        assert!(block_device.rate_limiter.is_none());
+
+        let mut block_devs = BlockBuilder::new();
+        block_devs.insert(block_device.clone()).unwrap();
+        {
+            let block = block_devs.list[0].lock().unwrap();
+            assert!(block.rate_limiter().bandwidth().is_none());
+            assert_eq!(
+                block.read_rate_limiter().bandwidth().unwrap().capacity(),
+                1048576
+            );
+            assert!(block.read_rate_limiter().ops().is_none());
+            assert_eq!(block.write_rate_limiter().ops().unwrap().capacity(), 100);
+            assert!(block.write_rate_limiter().bandwidth().is_none());
+        }
+        assert_eq!(block_devs.configs(), vec![block_device.clone()]);
+
+        // The backend serves the requests, so they can't be limited.
+        let vhost_user_device = BlockDeviceConfig {
+            path_on_host: String::new(),
+            fd: None,
+            serial: None,
+            vhost_user_socket: Some("/tmp/vhost-user-blk.sock".to_string()),
+            ..block_device
+        };
+        assert_eq!(
+            block_devs.validate(&vhost_user_device).unwrap_err(),
+            DriveError::IncompatibleVhostUser
+        );
+    }
+
     #[test]
     fn test_add_device() {
         let mut block_devs = BlockBuilder::new();
@@ -648,7 +1540,12 @@ mod tests {
             true,
             true,
             RateLimiter::default(),
+            RateLimiter::default(),
+            RateLimiter::default(),
             FileEngineType::default(),
+            ImageFormat::default(),
+            None,
+            1,
         )
         .unwrap();
 
@@ -666,4 +1563,116 @@ mod tests {
             block_id
         )
     }
+
+    #[test]
+    fn test_remove() {
+        let mut block_devs = BlockBuilder::new();
+        let files: Vec<TempFile> = (0..3).map(|_| TempFile::new().unwrap()).collect();
+        for (i, file) in files.iter().enumerate() {
+            block_devs
+                .insert(BlockDeviceConfig {
+                    path_on_host: file.as_path().to_str().unwrap().to_string(),
+                    fd: None,
+                    serial: None,
+                    is_root_device: i == 0,
+                    partuuid: None,
+                    cache_type: CacheType::Unsafe,
+                    is_read_only: false,
+                    drive_id: i.to_string(),
+                    rate_limiter: None,
+                    read_rate_limiter: None,
+                    write_rate_limiter: None,
+                    file_engine_type: FileEngineType::default(),
+                    image_format: ImageFormat::default(),
+                    overlay: None,
+                    rl_group: None,
+                    vhost_user_socket: None,
+                    num_queues: None,
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            block_devs.remove("bogus").unwrap_err(),
+            DriveError::DriveNotFound("bogus".to_string())
+        );
+        let block = block_devs.remove("1").unwrap();
+        assert_eq!(block.lock().unwrap().id(), "1");
+        let ids: Vec<String> = block_devs
+            .configs()
+            .into_iter()
+            .map(|config| config.drive_id)
+            .collect();
+        assert_eq!(ids, vec!["0".to_string(), "2".to_string()]);
+        assert!(block_devs.remove("1").is_err());
+    }
+
+    #[test]
+    fn test_drive_trace() {
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        let path = tmp_dir.as_path().join("trace.jsonl");
+        let json = format!(
+            r#"{{"drive_id": "trace_id", "state": "Started", "path_on_host": "{}"}}"#,
+            path.display()
+        );
+        let mut trace_cfg: DriveTraceConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(trace_cfg.format, TraceFormat::Jsonl);
+        assert_eq!(trace_cfg.max_records_per_sec, 1000);
+
+        let mut block_devs = BlockBuilder::new();
+        assert_eq!(
+            block_devs.set_trace(&trace_cfg).unwrap_err(),
+            DriveError::DriveNotFound("trace_id".to_string())
+        );
+        let backing_file = TempFile::new().unwrap();
+        block_devs
+            .insert(BlockDeviceConfig {
+                path_on_host: backing_file.as_path().to_str().unwrap().to_string(),
+                fd: None,
+                serial: None,
+                is_root_device: false,
+                partuuid: None,
+                cache_type: CacheType::Unsafe,
+                is_read_only: false,
+                drive_id: "trace_id".to_string(),
+                rate_limiter: None,
+                read_rate_limiter: None,
+                write_rate_limiter: None,
+                file_engine_type: FileEngineType::default(),
+                image_format: ImageFormat::default(),
+                overlay: None,
+                rl_group: None,
+                vhost_user_socket: None,
+                num_queues: None,
+            })
+            .unwrap();
+
+        block_devs.set_trace(&trace_cfg).unwrap();
+        assert!(block_devs.list[0].lock().unwrap().is_tracing());
+        assert!(path.exists());
+
+        trace_cfg.state = TraceState::Stopped;
+        block_devs.set_trace(&trace_cfg).unwrap();
+        assert!(!block_devs.list[0].lock().unwrap().is_tracing());
+
+        // Invalid configs.
+        trace_cfg.state = TraceState::Started;
+        trace_cfg.max_records_per_sec = 0;
+        assert_eq!(
+            block_devs.set_trace(&trace_cfg).unwrap_err(),
+            DriveError::InvalidTraceConfig
+        );
+        trace_cfg.max_records_per_sec = 1;
+        trace_cfg.path_on_host = None;
+        assert_eq!(
+            block_devs.set_trace(&trace_cfg).unwrap_err(),
+            DriveError::InvalidTraceConfig
+        );
+        trace_cfg.path_on_host = Some(String::from("/invalid/path/trace.jsonl"));
+        assert!(matches!(
+            block_devs.set_trace(&trace_cfg),
+            Err(DriveError::OpenTraceFile(_))
+        ));
+        assert!(!block_devs.list[0].lock().unwrap().is_tracing());
+    }
 }
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt;
+use std::path::PathBuf;
 
 use serde::{de, Deserialize, Serialize};
 
@@ -16,6 +17,8 @@ pub const MAX_SUPPORTED_VCPUS: u8 = 32;
 pub enum VmConfigError {
     /// The memory size is smaller than the target size set in the balloon device configuration.
     IncompatibleBalloonSize,
+    /// The guest memory can't be backed by the requested backend.
+    InvalidMemoryBackend(String),
     /// The memory size is invalid. The memory can only be an unsigned integer.
     InvalidMemorySize,
     /// The vcpu count is invalid. When SMT is enabled, the `cpu_count` must be either
@@ -35,6 +38,9 @@ impl fmt::Display for VmConfigError {
                 "The memory size (MiB) is smaller than the previously set balloon device target \
                  size.",
             ),
+            InvalidMemoryBackend(ref msg) => {
+                write!(f, "The guest memory backend is invalid: {}.", msg)
+            }
             InvalidMemorySize => write!(f, "The memory size (MiB) is invalid.",),
             InvalidVcpuCount => write!(
                 f,
@@ -73,6 +79,9 @@ pub struct VmConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// The memory backing the guest memory.
+    #[serde(default, skip_serializing_if = "GuestMemoryBackend::is_anonymous")]
+    pub mem_backend: GuestMemoryBackend,
 }
 
 impl Default for VmConfig {
@@ -83,6 +92,7 @@ impl Default for VmConfig {
             smt: false,
             cpu_template: CpuFeaturesTemplate::None,
             track_dirty_pages: false,
+            mem_backend: GuestMemoryBackend::Anonymous,
         }
     }
 }
@@ -133,6 +143,9 @@ pub struct VmUpdateConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub track_dirty_pages: Option<bool>,
+    /// The memory backing the guest memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_backend: Option<GuestMemoryBackend>,
 }
 
 impl VmUpdateConfig {
@@ -145,6 +158,7 @@ impl VmUpdateConfig {
             && self.cpu_template.is_none()
             && self.smt.is_none()
             && self.track_dirty_pages.is_none()
+            && self.mem_backend.is_none()
         {
             return true;
         }
@@ -161,6 +175,7 @@ impl From<VmConfig> for VmUpdateConfig {
             smt: Some(cfg.smt),
             cpu_template: Some(cfg.cpu_template),
             track_dirty_pages: Some(cfg.track_dirty_pages),
+            mem_backend: Some(cfg.mem_backend),
         }
     }
 }
@@ -234,6 +249,52 @@ where
     T::deserialize(_d)
 }
 
+/// The memory backing the guest memory.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum GuestMemoryBackend {
+    /// Anonymous memory, backed by the regular pages of the host.
+    Anonymous,
+    /// Files created in the hugetlbfs mounted at `path`, backed by its huge pages.
+    Hugetlbfs {
+        /// The path of the hugetlbfs mount.
+        path: PathBuf,
+    },
+}
+
+impl Default for GuestMemoryBackend {
+    fn default() -> Self {
+        GuestMemoryBackend::Anonymous
+    }
+}
+
+impl GuestMemoryBackend {
+    /// Whether the guest memory is anonymous memory.
+    pub fn is_anonymous(&self) -> bool {
+        *self == GuestMemoryBackend::Anonymous
+    }
+
+    /// Checks that the backend can back `mem_size` bytes of guest memory.
+    pub fn validate(&self, mem_size: usize) -> std::result::Result<(), VmConfigError> {
+        if let GuestMemoryBackend::Hugetlbfs { path } = self {
+            let huge_page_size = vm_memory::hugetlbfs_page_size(path).map_err(|e| {
+                VmConfigError::InvalidMemoryBackend(format!(
+                    "{} is not a hugetlbfs mount: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            if mem_size % huge_page_size != 0 {
+                return Err(VmConfigError::InvalidMemoryBackend(format!(
+                    "the memory size is not a multiple of the {} KiB huge pages",
+                    huge_page_size >> 10
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Template types available for configuring the CPU features that map
 /// to EC2 instances.
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -288,4 +349,41 @@ mod tests {
         let expected_str = "The memory size (MiB) is invalid.";
         assert_eq!(VmConfigError::InvalidMemorySize.to_string(), expected_str);
     }
+
+    #[test]
+    fn test_guest_memory_backend() {
+        let backend: GuestMemoryBackend =
+            serde_json::from_str(r#"{"type": "hugetlbfs", "path": "/dev/hugepages"}"#).unwrap();
+        assert_eq!(
+            backend,
+            GuestMemoryBackend::Hugetlbfs {
+                path: PathBuf::from("/dev/hugepages")
+            }
+        );
+        assert!(serde_json::from_str::<GuestMemoryBackend>(r#"{"type": "hugetlbfs"}"#).is_err());
+        assert!(
+            serde_json::from_str::<GuestMemoryBackend>(r#"{"type": "anonymous"}"#)
+                .unwrap()
+                .is_anonymous()
+        );
+
+        // The anonymous backend is left out of the machine configuration.
+        let vm_config = VmConfig::default();
+        assert!(!serde_json::to_string(&vm_config)
+            .unwrap()
+            .contains("mem_backend"));
+        assert!(vm_config.mem_backend.validate(1 << 20).is_ok());
+
+        // Only hugetlbfs mounts can back the guest memory with huge pages.
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let backend = GuestMemoryBackend::Hugetlbfs {
+            path: dir.as_path().to_path_buf(),
+        };
+        match backend.validate(2 << 20) {
+            Err(VmConfigError::InvalidMemoryBackend(msg)) => {
+                assert!(msg.contains("is not a hugetlbfs mount"))
+            }
+            _ => panic!("Test failed."),
+        }
+    }
 }
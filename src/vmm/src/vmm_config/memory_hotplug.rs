@@ -0,0 +1,188 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter};
+
+pub use devices::virtio::mem::MEM_DEV_ID;
+use devices::virtio::mem::{Error as MemError, MIN_BLOCK_SIZE};
+use serde::{Deserialize, Serialize};
+
+/// The default size of the blocks the hot-pluggable memory is plugged by, in MiB.
+pub const DEFAULT_BLOCK_SIZE_MIB: usize = 2;
+
+/// Errors associated with the operations allowed on the hot-pluggable memory.
+#[derive(Debug)]
+pub enum MemoryHotplugConfigError {
+    /// The user made a request on an inexistent virtio-mem device.
+    DeviceNotFound,
+    /// The block size isn't a power of two of at least 2MiB.
+    InvalidBlockSize(usize),
+    /// The requested size isn't a multiple of the block size within the hot-pluggable memory.
+    InvalidRequestedSize(usize),
+    /// The total size isn't a non-zero multiple of the block size.
+    InvalidTotalSize(usize),
+    /// Failed to update the size of the memory requested from the guest.
+    UpdateFailure(MemError),
+}
+
+impl Display for MemoryHotplugConfigError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::MemoryHotplugConfigError::*;
+        match self {
+            DeviceNotFound => write!(f, "No hot-pluggable memory is configured."),
+            InvalidBlockSize(size) => write!(
+                f,
+                "Invalid block size {} MiB, it must be a power of two of at least {} MiB.",
+                size,
+                MIN_BLOCK_SIZE >> 20
+            ),
+            InvalidRequestedSize(size) => write!(
+                f,
+                "Invalid requested size {} MiB, it must be a multiple of the block size no \
+                 larger than the hot-pluggable memory.",
+                size
+            ),
+            InvalidTotalSize(size) => write!(
+                f,
+                "Invalid total size {} MiB, it must be a non-zero multiple of the block size.",
+                size
+            ),
+            UpdateFailure(e) => write!(f, "Error updating the size of the plugged memory: {:?}", e),
+        }
+    }
+}
+
+impl From<MemError> for MemoryHotplugConfigError {
+    fn from(error: MemError) -> Self {
+        match error {
+            MemError::DeviceNotFound => Self::DeviceNotFound,
+            MemError::InvalidRequestedSize(size) => {
+                Self::InvalidRequestedSize((size >> 20) as usize)
+            }
+            e => Self::UpdateFailure(e),
+        }
+    }
+}
+
+fn default_block_size_mib() -> usize {
+    DEFAULT_BLOCK_SIZE_MIB
+}
+
+/// Use this structure to set up the hot-pluggable memory before booting the kernel.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHotplugConfig {
+    /// Size of the memory the guest can plug after boot, on top of the boot memory, in MiB.
+    pub total_size_mib: usize,
+    /// Granularity at which the memory is plugged and unplugged, in MiB.
+    #[serde(default = "default_block_size_mib")]
+    pub block_size_mib: usize,
+}
+
+impl MemoryHotplugConfig {
+    /// Checks that the block size is a power of two of at least 2MiB, and that the total size
+    /// is a non-zero multiple of it.
+    pub fn validate(&self) -> Result<(), MemoryHotplugConfigError> {
+        if !self.block_size_mib.is_power_of_two()
+            || (self.block_size_mib as u64) < MIN_BLOCK_SIZE >> 20
+        {
+            return Err(MemoryHotplugConfigError::InvalidBlockSize(
+                self.block_size_mib,
+            ));
+        }
+        if self.total_size_mib == 0 || self.total_size_mib % self.block_size_mib != 0 {
+            return Err(MemoryHotplugConfigError::InvalidTotalSize(
+                self.total_size_mib,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The data fed into a request resizing the memory plugged by the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHotplugSizeUpdate {
+    /// Size of the memory the guest is asked to plug, in MiB.
+    pub requested_size_mib: usize,
+}
+
+/// The state of the hot-pluggable memory of a running microVM.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct MemoryHotplugStatus {
+    /// Size of the memory the guest can plug, in MiB.
+    pub total_size_mib: usize,
+    /// Granularity at which the memory is plugged and unplugged, in MiB.
+    pub block_size_mib: usize,
+    /// Size of the memory plugged by the guest, in MiB.
+    pub plugged_size_mib: usize,
+    /// Size of the memory the guest is asked to plug, in MiB.
+    pub requested_size_mib: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: DEFAULT_BLOCK_SIZE_MIB,
+        };
+        config.validate().unwrap();
+
+        // The block size must be a power of two of at least 2MiB.
+        for &block_size_mib in [0, 1, 6].iter() {
+            config.block_size_mib = block_size_mib;
+            assert_eq!(
+                config.validate().unwrap_err().to_string(),
+                format!(
+                    "Invalid block size {} MiB, it must be a power of two of at least 2 MiB.",
+                    block_size_mib
+                )
+            );
+        }
+
+        // The total size must be a non-zero multiple of the block size.
+        config.block_size_mib = 128;
+        for &total_size_mib in [0, 192].iter() {
+            config.total_size_mib = total_size_mib;
+            assert!(matches!(
+                config.validate(),
+                Err(MemoryHotplugConfigError::InvalidTotalSize(size)) if size == total_size_mib
+            ));
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: MemoryHotplugConfig =
+            serde_json::from_str(r#"{"total_size_mib": 512}"#).unwrap();
+        assert_eq!(config.block_size_mib, DEFAULT_BLOCK_SIZE_MIB);
+        assert!(serde_json::from_str::<MemoryHotplugConfig>(
+            r#"{"total_size_mib": 512, "requested_size_mib": 256}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::MemoryHotplugConfigError::*;
+        let errors = [
+            DeviceNotFound,
+            InvalidBlockSize(3),
+            InvalidRequestedSize(3),
+            InvalidTotalSize(3),
+            UpdateFailure(MemError::MalformedDescriptor),
+        ];
+        for err in errors.iter() {
+            let _ = format!("{}{:?}", err, err);
+        }
+
+        assert!(matches!(
+            MemoryHotplugConfigError::from(MemError::InvalidRequestedSize(4 << 20)),
+            InvalidRequestedSize(4)
+        ));
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Configurations used in the live migration context.
+
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// Default number of rounds copying the pages dirtied by the running guest.
+const DEFAULT_MAX_ITERATIONS: u32 = 10;
+/// Default number of dirty pages below which the guest is paused.
+const DEFAULT_MAX_DIRTY_PAGES: u64 = 1024;
+
+/// Stores the configuration used to migrate the running microVM to a destination Firecracker.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrationSendConfig {
+    /// Address, as `ip:port`, at which the destination Firecracker waits for the microVM.
+    /// Host names aren't accepted, since resolving them isn't allowed by the seccomp filters.
+    pub destination: SocketAddr,
+    /// Maximum number of rounds copying the pages dirtied by the guest during the previous
+    /// round, while the guest keeps running.
+    #[serde(default = "default_max_iterations")]
+    pub max_iterations: u32,
+    /// Number of pages dirtied during a round below which the guest is paused, to copy the
+    /// remaining pages and the microVM state. It bounds the downtime of the guest.
+    #[serde(default = "default_max_dirty_pages")]
+    pub max_dirty_pages: u64,
+}
+
+fn default_max_iterations() -> u32 {
+    DEFAULT_MAX_ITERATIONS
+}
+
+fn default_max_dirty_pages() -> u64 {
+    DEFAULT_MAX_DIRTY_PAGES
+}
+
+/// Stores the configuration used to receive a microVM migrated by a source Firecracker.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrationReceiveConfig {
+    /// Address, as `ip:port`, on which to wait for the source Firecracker. Host names aren't
+    /// accepted, like for `MigrationSendConfig::destination`.
+    pub listen_address: SocketAddr,
+    /// Setting this flag will enable KVM dirty page tracking on the received microVM, which is
+    /// needed to migrate it again or to take diff snapshots of it.
+    #[serde(default)]
+    pub track_dirty_pages: bool,
+    /// When set to true, the received microVM is resumed. It is left paused otherwise.
+    #[serde(default)]
+    pub resume_vm: bool,
+}
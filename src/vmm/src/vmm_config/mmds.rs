@@ -2,10 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{Display, Formatter, Result};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
 
-use mmds::data_store;
 use mmds::data_store::MmdsVersion;
+use mmds::{data_store, identity};
 use serde::{Deserialize, Serialize};
 
 /// Keeps the MMDS configuration.
@@ -19,6 +20,9 @@ pub struct MmdsConfig {
     pub network_interfaces: Vec<String>,
     /// MMDS IPv4 configured address.
     pub ipv4_address: Option<Ipv4Addr>,
+    /// MMDS IPv6 configured address, which makes the MMDS also reachable over IPv6.
+    #[serde(default)]
+    pub ipv6_address: Option<Ipv6Addr>,
 }
 
 impl MmdsConfig {
@@ -37,6 +41,20 @@ impl MmdsConfig {
     pub fn ipv4_addr(&self) -> Option<Ipv4Addr> {
         self.ipv4_address
     }
+
+    /// Returns the MMDS IPv6 address if one was configured.
+    /// Otherwise returns None.
+    pub fn ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.ipv6_address
+    }
+}
+
+/// Keeps the configuration of the signed identity document served by MMDS.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MmdsIdentityConfig {
+    /// Path to the file holding the HMAC-SHA256 key signing the identity document.
+    pub signing_key_path: PathBuf,
 }
 
 /// MMDS configuration related errors.
@@ -44,11 +62,18 @@ impl MmdsConfig {
 pub enum MmdsConfigError {
     /// The network interfaces list provided is empty.
     EmptyNetworkIfaceList,
+    /// The signer of the identity document could not be created.
+    IdentitySigner(identity::Error),
     /// The provided IPv4 address is not link-local valid.
     InvalidIpv4Addr,
+    /// The provided IPv6 address is not a unicast address.
+    InvalidIpv6Addr,
     /// The network interfaces list provided contains IDs that
     /// does not correspond to any existing network interface.
     InvalidNetworkInterfaceId,
+    /// The network interface with the given ID uses the vhost datapath, which doesn't allow
+    /// forwarding MMDS requests.
+    VhostNetworkInterface(String),
     /// MMDS version could not be configured.
     MmdsVersion(MmdsVersion, data_store::Error),
 }
@@ -63,9 +88,19 @@ impl Display for MmdsConfigError {
                      empty."
                 )
             }
+            MmdsConfigError::IdentitySigner(err) => {
+                write!(
+                    f,
+                    "The MMDS identity document could not be configured: {}",
+                    err
+                )
+            }
             MmdsConfigError::InvalidIpv4Addr => {
                 write!(f, "The MMDS IPv4 address is not link local.")
             }
+            MmdsConfigError::InvalidIpv6Addr => {
+                write!(f, "The MMDS IPv6 address is not a unicast address.")
+            }
             MmdsConfigError::InvalidNetworkInterfaceId => {
                 write!(
                     f,
@@ -73,6 +108,14 @@ impl Display for MmdsConfigError {
                      does not correspond to any existing network interface."
                 )
             }
+            MmdsConfigError::VhostNetworkInterface(iface_id) => {
+                write!(
+                    f,
+                    "The network interface {} uses the vhost datapath and cannot forward MMDS \
+                     requests.",
+                    iface_id
+                )
+            }
             MmdsConfigError::MmdsVersion(version, err) => {
                 write!(
                     f,
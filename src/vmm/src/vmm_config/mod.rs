@@ -23,12 +23,19 @@ pub mod instance_info;
 pub mod logger;
 /// Wrapper for configuring the memory and CPU of the microVM.
 pub mod machine_config;
+/// Wrapper for configuring the hot-pluggable memory of the microVM.
+pub mod memory_hotplug;
 /// Wrapper for configuring the metrics.
 pub mod metrics;
+/// Wrapper for configuring the live migration of the microVM.
+pub mod migration;
 /// Wrapper for configuring the MMDS.
 pub mod mmds;
 /// Wrapper for configuring the network devices attached to the microVM.
 pub mod net;
+/// Wrapper for configuring the pmem devices attached to the microVM.
+pub mod pmem;
+pub mod rate_limiter_group;
 /// Wrapper for configuring microVM snapshots and the microVM state.
 pub mod snapshot;
 /// Wrapper for configuring the vsock devices attached to the microVM.
@@ -78,6 +85,9 @@ pub struct RateLimiterConfig {
     pub bandwidth: Option<TokenBucketConfig>,
     /// Data used to initialize the RateLimiter::ops bucket.
     pub ops: Option<TokenBucketConfig>,
+    /// Data used to initialize the RateLimiter::pps bucket. Only network interfaces consume
+    /// packet tokens.
+    pub pps: Option<TokenBucketConfig>,
 }
 
 /// A public-facing, stateless structure, specifying RateLimiter properties updates.
@@ -86,6 +96,8 @@ pub struct RateLimiterUpdate {
     pub bandwidth: BucketUpdate,
     /// Possible update to the RateLimiter::ops bucket.
     pub ops: BucketUpdate,
+    /// Possible update to the RateLimiter::pps bucket.
+    pub pps: BucketUpdate,
 }
 
 fn get_bucket_update(tb_cfg: &Option<TokenBucketConfig>) -> BucketUpdate {
@@ -113,12 +125,14 @@ impl From<Option<RateLimiterConfig>> for RateLimiterUpdate {
             RateLimiterUpdate {
                 bandwidth: get_bucket_update(&cfg.bandwidth),
                 ops: get_bucket_update(&cfg.ops),
+                pps: get_bucket_update(&cfg.pps),
             }
         } else {
             // No update to the rate-limiter.
             RateLimiterUpdate {
                 bandwidth: BucketUpdate::None,
                 ops: BucketUpdate::None,
+                pps: BucketUpdate::None,
             }
         }
     }
@@ -130,14 +144,18 @@ impl TryInto<RateLimiter> for RateLimiterConfig {
     fn try_into(self) -> std::result::Result<RateLimiter, Self::Error> {
         let bw = self.bandwidth.unwrap_or_default();
         let ops = self.ops.unwrap_or_default();
-        RateLimiter::new(
+        let mut rate_limiter = RateLimiter::new(
             bw.size,
             bw.one_time_burst.unwrap_or(0),
             bw.refill_time,
             ops.size,
             ops.one_time_burst.unwrap_or(0),
             ops.refill_time,
-        )
+        )?;
+        if let BucketUpdate::Update(pps) = get_bucket_update(&self.pps) {
+            rate_limiter.update_pps(BucketUpdate::Update(pps));
+        }
+        Ok(rate_limiter)
     }
 }
 
@@ -146,6 +164,7 @@ impl From<&RateLimiter> for RateLimiterConfig {
         RateLimiterConfig {
             bandwidth: rl.bandwidth().map(TokenBucketConfig::from),
             ops: rl.ops().map(TokenBucketConfig::from),
+            pps: rl.pps().map(TokenBucketConfig::from),
         }
     }
 }
@@ -153,7 +172,7 @@ impl From<&RateLimiter> for RateLimiterConfig {
 impl RateLimiterConfig {
     // Option<T> already implements From<T> so we have to use a custom one.
     fn into_option(self) -> Option<RateLimiterConfig> {
-        if self.bandwidth.is_some() || self.ops.is_some() {
+        if self.bandwidth.is_some() || self.ops.is_some() || self.pps.is_some() {
             Some(self)
         } else {
             None
@@ -202,6 +221,7 @@ mod tests {
                 one_time_burst: None,
                 refill_time: REFILL_TIME * 2,
             }),
+            pps: None,
         };
         let rl: RateLimiter = rlconf.try_into().unwrap();
         assert_eq!(rl.bandwidth().unwrap().capacity(), SIZE);
@@ -210,6 +230,21 @@ mod tests {
         assert_eq!(rl.ops().unwrap().capacity(), SIZE * 2);
         assert_eq!(rl.ops().unwrap().one_time_burst(), 0);
         assert_eq!(rl.ops().unwrap().refill_time_ms(), REFILL_TIME * 2);
+        assert!(rl.pps().is_none());
+
+        let rlconf = RateLimiterConfig {
+            pps: Some(TokenBucketConfig {
+                size: SIZE,
+                one_time_burst: None,
+                refill_time: REFILL_TIME,
+            }),
+            ..Default::default()
+        };
+        let rl: RateLimiter = rlconf.try_into().unwrap();
+        assert!(rl.bandwidth().is_none());
+        assert_eq!(rl.pps().unwrap().capacity(), SIZE);
+        assert_eq!(RateLimiterConfig::from(&rl), rlconf);
+        assert_eq!(rlconf.into_option(), Some(rlconf));
     }
 
     #[test]
@@ -226,6 +261,7 @@ mod tests {
         let rl_conf = RateLimiterConfig {
             bandwidth: Some(bw_tb_cfg),
             ops: None,
+            pps: None,
         };
         let rl: RateLimiter = rl_conf.try_into().unwrap();
         let generated_rl_conf = RateLimiterConfig::from(&rl);
@@ -3,11 +3,18 @@
 
 use std::convert::TryInto;
 use std::ops::Deref;
+use std::os::unix::io::RawFd;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::{fmt, result};
 
-use devices::virtio::net::TapError;
+use devices::virtio::net::{with_netns, PcapWriter, TapError, MAX_MTU, MAX_QUEUE_PAIRS, MIN_MTU};
+pub use devices::virtio::net::{
+    Ipv4Cidr, NetBackendType, NetDatapath, NetFilterAction, NetFilterDirection, NetFilterProtocol,
+    NetFilterRule, NetOffloads, NetStats,
+};
 use devices::virtio::Net;
+use rate_limiter::RateLimiter;
 use serde::{Deserialize, Serialize};
 use utils::net::mac::MacAddr;
 
@@ -21,14 +28,66 @@ use crate::Error as VmmError;
 pub struct NetworkInterfaceConfig {
     /// ID of the guest network interface.
     pub iface_id: String,
-    /// Host level path for the guest network interface.
+    /// Host level path for the guest network interface. For the `socketpair` and `vhost_user`
+    /// backends, this is the path of the unix socket to connect to, and for the `xdp` backend
+    /// the name of the host interface whose queues are used. Left empty when `tap_fd` is used.
+    #[serde(default)]
     pub host_dev_name: String,
+    /// File descriptor of an already opened TAP device, or of a connected socket for the
+    /// `socketpair` backend, used instead of `host_dev_name`. The device takes ownership of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap_fd: Option<RawFd>,
+    /// Path of the pinned `XSKMAP` the AF_XDP sockets of the `xdp` backend are inserted into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xsks_map_path: Option<String>,
+    /// Path of the network namespace the host-side interface is opened in, e.g.
+    /// `/var/run/netns/foo`. The interface is opened in the namespace of Firecracker when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub netns: Option<String>,
+    /// The kind of host-side backend of the interface.
+    #[serde(default)]
+    pub backend_type: NetBackendType,
+    /// Number of RX/TX queue pairs of the interface. More than one queue pair requires a TAP
+    /// interface which supports multiple queues.
+    #[serde(default = "NetworkInterfaceConfig::default_num_queues")]
+    pub num_queues: usize,
+    /// Whether frames are moved by Firecracker or by the vhost-net kernel module.
+    #[serde(default)]
+    pub backend: NetDatapath,
+    /// MTU advertised to the guest, and set on the host interface.
+    pub mtu: Option<u16>,
+    /// Checksum and segmentation offloads offered to the guest.
+    #[serde(default)]
+    pub offloads: NetOffloads,
+    /// Whether the guest can filter the frames it receives by destination address, through
+    /// the control queue of the interface.
+    #[serde(default)]
+    pub rx_filtering: bool,
+    /// Rules allowing or denying the IPv4 traffic exchanged with remote addresses. The first
+    /// rule matching a packet decides whether it goes through, and the packets matching none
+    /// of the rules of their direction are dropped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<NetFilterRule>,
+    /// Whether the frames of the interface are processed on a dedicated worker thread, rather
+    /// than on the thread shared by all the devices.
+    #[serde(default)]
+    pub worker_thread: bool,
     /// Guest MAC address.
     pub guest_mac: Option<MacAddr>,
     /// Rate Limiter for received packages.
     pub rx_rate_limiter: Option<RateLimiterConfig>,
     /// Rate Limiter for transmitted packages.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// ID of the rate limiter group whose buckets also limit the received and transmitted
+    /// packages, along with the ones of the other devices in the group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rl_group: Option<String>,
+}
+
+impl NetworkInterfaceConfig {
+    fn default_num_queues() -> usize {
+        1
+    }
 }
 
 impl From<&Net> for NetworkInterfaceConfig {
@@ -38,20 +97,43 @@ impl From<&Net> for NetworkInterfaceConfig {
         NetworkInterfaceConfig {
             iface_id: net.id().clone(),
             host_dev_name: net.iface_name(),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: net.netns().map(str::to_string),
+            rl_group: net
+                .rx_rate_limiter()
+                .group()
+                .map(|group| group.id().to_string()),
+            backend_type: net.backend_type(),
             guest_mac: net.guest_mac().copied(),
             rx_rate_limiter: rx_rl.into_option(),
             tx_rate_limiter: tx_rl.into_option(),
+            num_queues: net.num_queue_pairs(),
+            backend: if net.uses_vhost() && net.backend_type() == NetBackendType::Tap {
+                NetDatapath::Vhost
+            } else {
+                NetDatapath::Virtio
+            },
+            mtu: net.mtu(),
+            offloads: net.offloads(),
+            rx_filtering: net.rx_filtering(),
+            filters: net.filters().to_vec(),
+            worker_thread: net.worker_thread(),
         }
     }
 }
 
-/// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
-/// can be updated.
+/// The data fed into a network iface update request. Currently, only the guest MAC address, the
+/// link state and the RX and TX rate limiters can be updated.
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceUpdateConfig {
     /// The net iface ID, as provided by the user at iface creation time.
     pub iface_id: String,
+    /// New guest MAC address.
+    pub guest_mac: Option<MacAddr>,
+    /// Whether the link is reported as up or down to the guest.
+    pub link_up: Option<bool>,
     /// New RX rate limiter config. Only provided data will be updated. I.e. if any optional data
     /// is missing, it will not be nullified, but left unchanged.
     pub rx_rate_limiter: Option<RateLimiterConfig>,
@@ -60,6 +142,78 @@ pub struct NetworkInterfaceUpdateConfig {
     pub tx_rate_limiter: Option<RateLimiterConfig>,
 }
 
+impl NetworkInterfaceUpdateConfig {
+    /// Returns true if the request updates the rate limiters. A request that doesn't update
+    /// anything is handled as a rate limiter update, which leaves them unchanged.
+    pub fn updates_rate_limiters(&self) -> bool {
+        (self.guest_mac.is_none() && self.link_up.is_none())
+            || self.rx_rate_limiter.is_some()
+            || self.tx_rate_limiter.is_some()
+    }
+}
+
+/// Whether the frames of a network interface are captured.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CaptureState {
+    /// Frames are mirrored to the capture files.
+    Started,
+    /// No frames are captured.
+    Stopped,
+}
+
+/// The data fed into a packet capture request for a network interface.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkCaptureConfig {
+    /// The net iface ID, as provided by the user at iface creation time.
+    pub iface_id: String,
+    /// Starts or stops the capture.
+    pub state: CaptureState,
+    /// Path of the first capture file. Required to start a capture.
+    #[serde(default)]
+    pub path_on_host: Option<String>,
+    /// Size of a capture file, after which the capture moves on to the next file of the ring.
+    /// No limit is applied when missing.
+    #[serde(default)]
+    pub max_file_size_mib: Option<u32>,
+    /// Number of capture files the capture cycles through.
+    #[serde(default = "NetworkCaptureConfig::default_file_count")]
+    pub file_count: u32,
+}
+
+impl NetworkCaptureConfig {
+    fn default_file_count() -> u32 {
+        1
+    }
+
+    /// Starts or stops the capture of the frames of `net`. Starting a capture truncates the
+    /// capture files.
+    pub fn apply(&self, net: &mut Net) -> Result<()> {
+        let capture = match self.state {
+            CaptureState::Stopped => None,
+            CaptureState::Started => {
+                if net.uses_vhost() {
+                    return Err(NetworkInterfaceError::CaptureUnsupported);
+                }
+                let path = self
+                    .path_on_host
+                    .as_ref()
+                    .ok_or(NetworkInterfaceError::InvalidCaptureConfig)?;
+                if self.max_file_size_mib == Some(0) || self.file_count == 0 {
+                    return Err(NetworkInterfaceError::InvalidCaptureConfig);
+                }
+                let max_file_size = self.max_file_size_mib.map(|mib| u64::from(mib) << 20);
+                Some(
+                    PcapWriter::new(Path::new(path), max_file_size, self.file_count)
+                        .map_err(NetworkInterfaceError::OpenCaptureFile)?,
+                )
+            }
+        };
+        net.set_capture(capture)
+            .map_err(|_| NetworkInterfaceError::CaptureUnsupported)
+    }
+}
+
 /// Errors associated with `NetworkInterfaceConfig`.
 #[derive(Debug)]
 pub enum NetworkInterfaceError {
@@ -69,10 +223,41 @@ pub enum NetworkInterfaceError {
     CreateRateLimiter(std::io::Error),
     /// The MAC address is already in use.
     GuestMacAddressInUse(String),
+    /// The host device name is already in use.
+    HostDeviceNameInUse(String),
     /// Error during interface update (patch).
     DeviceUpdate(VmmError),
+    /// Error during interface removal.
+    DeviceRemoval(VmmError),
+    /// Error while reading the interface statistics.
+    DeviceStats(VmmError),
+    /// No network interface has the given id.
+    InterfaceNotFound(String),
     /// Cannot open/create tap device.
     OpenTap(TapError),
+    /// The number of RX/TX queue pairs isn't supported.
+    InvalidNumQueues(usize),
+    /// The host-side backend is specified both or neither by name and by file descriptor, or
+    /// by file descriptor for a backend which doesn't support it.
+    InvalidTapSource,
+    /// The `XSKMAP` path is missing for the xdp backend, or given for another backend.
+    InvalidXsksMap,
+    /// A network namespace is given along with an already opened TAP device.
+    InvalidNetns,
+    /// A filter rule matches a port without a protocol which has ports.
+    InvalidFilterRule(usize),
+    /// No rate limiter group has the given id.
+    RateLimiterGroupNotFound(String),
+    /// The MTU isn't supported.
+    InvalidMtu(u16),
+    /// The vhost datapath isn't supported with the given configuration.
+    VhostUnsupported(&'static str),
+    /// A capture needs a path and non-zero file sizes and counts.
+    InvalidCaptureConfig,
+    /// Cannot create a capture file.
+    OpenCaptureFile(std::io::Error),
+    /// The frames of the interface can't be captured.
+    CaptureUnsupported,
 }
 
 impl fmt::Display for NetworkInterfaceError {
@@ -86,7 +271,66 @@ impl fmt::Display for NetworkInterfaceError {
                 "{}",
                 format!("The guest MAC address {} is already in use.", mac_addr)
             ),
+            HostDeviceNameInUse(host_dev_name) => write!(
+                f,
+                "The host device name {} is already in use.",
+                host_dev_name
+            ),
             DeviceUpdate(e) => write!(f, "Error during interface update (patch): {}", e),
+            DeviceRemoval(e) => write!(f, "Error during interface removal: {}", e),
+            DeviceStats(e) => write!(f, "Cannot get the interface statistics: {}", e),
+            InterfaceNotFound(iface_id) => {
+                write!(f, "The network interface {} does not exist.", iface_id)
+            }
+            InvalidNumQueues(num_queues) => write!(
+                f,
+                "Invalid number of queue pairs: {}. Interfaces can have between 1 and {} queue \
+                 pairs, and only TAP interfaces opened by name and xdp interfaces can have more \
+                 than one.",
+                num_queues, MAX_QUEUE_PAIRS
+            ),
+            InvalidTapSource => write!(
+                f,
+                "Exactly one of host_dev_name and tap_fd must be specified, and tap_fd is only \
+                 supported by the TAP and socketpair backends."
+            ),
+            InvalidXsksMap => write!(
+                f,
+                "xsks_map_path must be specified for the xdp backend, and only for it."
+            ),
+            RateLimiterGroupNotFound(group_id) => {
+                write!(f, "The rate limiter group {} does not exist.", group_id)
+            }
+            InvalidNetns => write!(
+                f,
+                "netns can't be specified along with tap_fd, which is already opened."
+            ),
+            InvalidFilterRule(index) => write!(
+                f,
+                "Invalid filter rule {}: a port can only be matched along with the tcp or udp \
+                 protocol.",
+                index
+            ),
+            InvalidMtu(mtu) => write!(
+                f,
+                "Invalid MTU: {}. The MTU must be between {} and {}.",
+                mtu, MIN_MTU, MAX_MTU
+            ),
+            VhostUnsupported(reason) => write!(
+                f,
+                "The vhost datapath cannot be used for this interface: {}",
+                reason
+            ),
+            InvalidCaptureConfig => write!(
+                f,
+                "Starting a capture requires a path_on_host, and max_file_size_mib and \
+                 file_count cannot be 0."
+            ),
+            OpenCaptureFile(e) => write!(f, "Cannot create the capture file: {}", e),
+            CaptureUnsupported => write!(
+                f,
+                "The frames of interfaces using the vhost datapath cannot be captured."
+            ),
             OpenTap(e) => {
                 // We are propagating the Tap Error. This error can contain
                 // imbricated quotes which would result in an invalid json.
@@ -136,24 +380,178 @@ impl NetBuilder {
         self.net_devices.push(device);
     }
 
-    /// Builds a network device based on a network interface config. Keeps a device reference
-    /// in the builder's internal list.
-    pub fn build(&mut self, netif_config: NetworkInterfaceConfig) -> Result<Arc<Mutex<Net>>> {
+    /// Checks whether a network device could be built based on a network interface config,
+    /// without creating it. The host side backend is only opened when the device is built.
+    pub fn validate(&self, netif_config: &NetworkInterfaceConfig) -> Result<()> {
+        if let Some(guest_mac) = netif_config.guest_mac.as_ref() {
+            self.validate_guest_mac(&netif_config.iface_id, guest_mac)?;
+        }
+
+        let by_name = !netif_config.host_dev_name.is_empty();
+        let by_fd = netif_config.tap_fd.is_some();
+        if by_name == by_fd || (by_fd && !netif_config.backend_type.supports_fd()) {
+            return Err(NetworkInterfaceError::InvalidTapSource);
+        }
+        if by_name {
+            self.validate_host_dev_name(netif_config)?;
+        }
+        if netif_config.xsks_map_path.is_some()
+            != (netif_config.backend_type == NetBackendType::Xdp)
+        {
+            return Err(NetworkInterfaceError::InvalidXsksMap);
+        }
+        if by_fd && netif_config.netns.is_some() {
+            return Err(NetworkInterfaceError::InvalidNetns);
+        }
+        if let Some(index) = netif_config
+            .filters
+            .iter()
+            .position(|rule| !rule.is_valid())
+        {
+            return Err(NetworkInterfaceError::InvalidFilterRule(index));
+        }
+
+        let max_queues = match netif_config.backend_type {
+            // A TAP file descriptor only serves a single queue.
+            NetBackendType::Tap if by_fd => 1,
+            NetBackendType::Tap => MAX_QUEUE_PAIRS,
+            // Each queue pair is bound to a queue of the host interface.
+            NetBackendType::Xdp => MAX_QUEUE_PAIRS,
+            NetBackendType::VhostUser => 1,
+            #[cfg(feature = "net-socketpair")]
+            NetBackendType::SocketPair => 1,
+        };
+        if netif_config.num_queues == 0 || netif_config.num_queues > max_queues {
+            return Err(NetworkInterfaceError::InvalidNumQueues(
+                netif_config.num_queues,
+            ));
+        }
+
+        if let Some(mtu) = netif_config.mtu {
+            if !(MIN_MTU..=MAX_MTU).contains(&mtu) {
+                return Err(NetworkInterfaceError::InvalidMtu(mtu));
+            }
+        }
+
+        let rate_limiters = [netif_config.rx_rate_limiter, netif_config.tx_rate_limiter];
+        if netif_config.backend == NetDatapath::Vhost
+            && netif_config.backend_type != NetBackendType::Tap
+        {
+            return Err(NetworkInterfaceError::VhostUnsupported(
+                "only TAP interfaces are supported.",
+            ));
+        }
+        // vhost-user backends move the frames themselves, the same way vhost-net does.
+        if netif_config.backend == NetDatapath::Vhost
+            || netif_config.backend_type == NetBackendType::VhostUser
+        {
+            // Frames never go through the device model, so they can't be rate limited.
+            if netif_config.rl_group.is_some()
+                || rate_limiters
+                    .iter()
+                    .flatten()
+                    .any(|rl| *rl != Default::default())
+            {
+                return Err(NetworkInterfaceError::VhostUnsupported(
+                    "rate limiters are not supported.",
+                ));
+            }
+            if netif_config.rx_filtering {
+                return Err(NetworkInterfaceError::VhostUnsupported(
+                    "RX filtering is not supported.",
+                ));
+            }
+            if !netif_config.filters.is_empty() {
+                return Err(NetworkInterfaceError::VhostUnsupported(
+                    "filter rules are not supported.",
+                ));
+            }
+        }
+
+        for rate_limiter in rate_limiters.iter().flatten() {
+            let _: RateLimiter = (*rate_limiter)
+                .try_into()
+                .map_err(NetworkInterfaceError::CreateRateLimiter)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the interfaces of a configuration which aren't built yet don't conflict with
+    /// each other, the same way `validate` does against the built devices.
+    pub fn validate_configs(configs: &[NetworkInterfaceConfig]) -> Result<()> {
+        for (index, config) in configs.iter().enumerate() {
+            let others = configs[..index]
+                .iter()
+                .filter(|other| other.iface_id != config.iface_id);
+            for other in others {
+                if let Some(guest_mac) = config.guest_mac.as_ref() {
+                    if other.guest_mac.as_ref() == Some(guest_mac) {
+                        return Err(NetworkInterfaceError::GuestMacAddressInUse(
+                            guest_mac.to_string(),
+                        ));
+                    }
+                }
+
+                #[cfg(feature = "net-socketpair")]
+                if config.backend_type == NetBackendType::SocketPair {
+                    continue;
+                }
+                if !config.host_dev_name.is_empty()
+                    && other.host_dev_name == config.host_dev_name
+                    && other.backend_type == config.backend_type
+                    && other.netns == config.netns
+                {
+                    return Err(NetworkInterfaceError::HostDeviceNameInUse(
+                        config.host_dev_name.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that no network device other than `iface_id` uses `guest_mac`.
+    pub fn validate_guest_mac(&self, iface_id: &str, guest_mac: &MacAddr) -> Result<()> {
         let mac_conflict = |net: &Arc<Mutex<Net>>| {
             let net = net.lock().expect("Poisoned lock");
-            // Check if another net dev has same MAC.
-            netif_config.guest_mac.is_some()
-                && netif_config.guest_mac.as_ref() == net.guest_mac()
-                && &netif_config.iface_id != net.id()
+            net.guest_mac() == Some(guest_mac) && net.id() != iface_id
         };
-        // Validate there is no Mac conflict.
-        // No need to validate host_dev_name conflict. In such a case,
-        // an error will be thrown during device creation anyway.
         if self.net_devices.iter().any(mac_conflict) {
             return Err(NetworkInterfaceError::GuestMacAddressInUse(
-                netif_config.guest_mac.unwrap().to_string(),
+                guest_mac.to_string(),
             ));
         }
+        Ok(())
+    }
+
+    /// Checks that no network device other than `netif_config.iface_id` uses the same host
+    /// interface, which would otherwise only fail when the interface is opened, with EBUSY.
+    fn validate_host_dev_name(&self, netif_config: &NetworkInterfaceConfig) -> Result<()> {
+        // Several sockets can connect to the same listening socket.
+        #[cfg(feature = "net-socketpair")]
+        if netif_config.backend_type == NetBackendType::SocketPair {
+            return Ok(());
+        }
+
+        let name_conflict = |net: &Arc<Mutex<Net>>| {
+            let net = net.lock().expect("Poisoned lock");
+            net.id() != &netif_config.iface_id
+                && net.backend_type() == netif_config.backend_type
+                && net.netns() == netif_config.netns.as_deref()
+                && net.iface_name() == netif_config.host_dev_name
+        };
+        if self.net_devices.iter().any(name_conflict) {
+            return Err(NetworkInterfaceError::HostDeviceNameInUse(
+                netif_config.host_dev_name.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Builds a network device based on a network interface config. Keeps a device reference
+    /// in the builder's internal list.
+    pub fn build(&mut self, netif_config: NetworkInterfaceConfig) -> Result<Arc<Mutex<Net>>> {
+        self.validate(&netif_config)?;
 
         // If this is an update, just remove the old one.
         if let Some(index) = self
@@ -171,6 +569,27 @@ impl NetBuilder {
         Ok(net)
     }
 
+    /// Removes the network device with id `iface_id` from the builder's internal list and
+    /// returns it.
+    pub fn remove(&mut self, iface_id: &str) -> Result<Arc<Mutex<Net>>> {
+        let index = self
+            .net_devices
+            .iter()
+            .position(|net| net.lock().expect("Poisoned lock").id() == iface_id)
+            .ok_or_else(|| NetworkInterfaceError::InterfaceNotFound(iface_id.to_string()))?;
+        Ok(self.net_devices.remove(index))
+    }
+
+    /// Starts or stops the capture of the frames of a network device.
+    pub fn set_capture(&mut self, config: &NetworkCaptureConfig) -> Result<()> {
+        let net = self
+            .net_devices
+            .iter()
+            .find(|net| net.lock().expect("Poisoned lock").id() == &config.iface_id)
+            .ok_or_else(|| NetworkInterfaceError::InterfaceNotFound(config.iface_id.clone()))?;
+        config.apply(&mut net.lock().expect("Poisoned lock"))
+    }
+
     /// Creates a Net device from a NetworkInterfaceConfig.
     pub fn create_net(cfg: NetworkInterfaceConfig) -> Result<Net> {
         let rx_rate_limiter = cfg
@@ -184,15 +603,93 @@ impl NetBuilder {
             .transpose()
             .map_err(NetworkInterfaceError::CreateRateLimiter)?;
 
-        // Create and return the Net device
-        devices::virtio::net::Net::new_with_tap(
-            cfg.iface_id,
-            cfg.host_dev_name.clone(),
-            cfg.guest_mac.as_ref(),
-            rx_rate_limiter.unwrap_or_default(),
-            tx_rate_limiter.unwrap_or_default(),
-        )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        let datapath = cfg.backend;
+        let mtu = cfg.mtu;
+        let offloads = cfg.offloads;
+        let rx_filtering = cfg.rx_filtering;
+        let filters = cfg.filters.clone();
+        let netns = cfg.netns.clone();
+        let worker_thread = cfg.worker_thread;
+        // The host interface is looked up by name, including when its MTU and offloads are set,
+        // so all of these happen in its namespace.
+        let mut net = with_netns(netns.as_deref(), move || {
+            let mut net = match (cfg.backend_type, cfg.tap_fd) {
+                (NetBackendType::Tap, Some(tap_fd)) => devices::virtio::net::Net::new_with_tap_fd(
+                    cfg.iface_id,
+                    tap_fd,
+                    cfg.guest_mac.as_ref(),
+                    rx_rate_limiter.unwrap_or_default(),
+                    tx_rate_limiter.unwrap_or_default(),
+                ),
+                (NetBackendType::Tap, None) => devices::virtio::net::Net::new_with_tap(
+                    cfg.iface_id,
+                    cfg.host_dev_name.clone(),
+                    cfg.guest_mac.as_ref(),
+                    rx_rate_limiter.unwrap_or_default(),
+                    tx_rate_limiter.unwrap_or_default(),
+                    cfg.num_queues,
+                ),
+                (NetBackendType::Xdp, _) => devices::virtio::net::Net::new_with_xdp(
+                    cfg.iface_id,
+                    cfg.host_dev_name.clone(),
+                    cfg.xsks_map_path.as_deref().unwrap_or_default(),
+                    cfg.guest_mac.as_ref(),
+                    rx_rate_limiter.unwrap_or_default(),
+                    tx_rate_limiter.unwrap_or_default(),
+                    cfg.num_queues,
+                ),
+                (NetBackendType::VhostUser, _) => devices::virtio::net::Net::new_with_vhost_user(
+                    cfg.iface_id,
+                    cfg.host_dev_name.clone(),
+                    cfg.guest_mac.as_ref(),
+                    rx_rate_limiter.unwrap_or_default(),
+                    tx_rate_limiter.unwrap_or_default(),
+                ),
+                #[cfg(feature = "net-socketpair")]
+                (NetBackendType::SocketPair, Some(socket_fd)) => {
+                    devices::virtio::net::Net::new_with_socketpair_fd(
+                        cfg.iface_id,
+                        socket_fd,
+                        cfg.guest_mac.as_ref(),
+                        rx_rate_limiter.unwrap_or_default(),
+                        tx_rate_limiter.unwrap_or_default(),
+                    )
+                }
+                #[cfg(feature = "net-socketpair")]
+                (NetBackendType::SocketPair, None) => {
+                    devices::virtio::net::Net::new_with_socketpair(
+                        cfg.iface_id,
+                        cfg.host_dev_name.clone(),
+                        cfg.guest_mac.as_ref(),
+                        rx_rate_limiter.unwrap_or_default(),
+                        tx_rate_limiter.unwrap_or_default(),
+                    )
+                }
+            }?;
+
+            if let Some(mtu) = mtu {
+                net.set_mtu(mtu)?;
+            }
+            if offloads != NetOffloads::default() {
+                net.set_offloads(offloads)?;
+            }
+            Ok(net)
+        })
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        net.set_netns(netns);
+        net.set_filters(filters);
+        net.set_worker_thread(worker_thread);
+
+        if rx_filtering {
+            net.enable_rx_filtering()
+                .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        }
+
+        if datapath == NetDatapath::Vhost {
+            net.enable_vhost()
+                .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        }
+        Ok(net)
     }
 
     /// Returns a vec with the structures used to configure the net devices.
@@ -212,6 +709,7 @@ mod tests {
     use rate_limiter::RateLimiter;
 
     use super::*;
+    use crate::vmm_config::TokenBucketConfig;
 
     impl NetBuilder {
         pub fn len(&self) -> usize {
@@ -227,9 +725,21 @@ mod tests {
         NetworkInterfaceConfig {
             iface_id: String::from(id),
             host_dev_name: String::from(name),
+            tap_fd: None,
+            xsks_map_path: None,
+            netns: None,
+            rl_group: None,
+            backend_type: NetBackendType::default(),
             guest_mac: Some(MacAddr::parse_str(mac).unwrap()),
             rx_rate_limiter: RateLimiterConfig::default().into_option(),
             tx_rate_limiter: RateLimiterConfig::default().into_option(),
+            num_queues: 1,
+            backend: NetDatapath::default(),
+            mtu: None,
+            offloads: NetOffloads::default(),
+            rx_filtering: false,
+            filters: Vec::new(),
+            worker_thread: false,
         }
     }
 
@@ -238,9 +748,21 @@ mod tests {
             NetworkInterfaceConfig {
                 iface_id: self.iface_id.clone(),
                 host_dev_name: self.host_dev_name.clone(),
+                tap_fd: self.tap_fd,
+                xsks_map_path: self.xsks_map_path.clone(),
+                netns: self.netns.clone(),
+                rl_group: self.rl_group.clone(),
+                backend_type: self.backend_type,
                 guest_mac: self.guest_mac,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                num_queues: self.num_queues,
+                backend: self.backend,
+                mtu: self.mtu,
+                offloads: self.offloads,
+                rx_filtering: self.rx_filtering,
+                filters: self.filters.clone(),
+                worker_thread: self.worker_thread,
             }
         }
     }
@@ -272,6 +794,78 @@ mod tests {
         assert_eq!(net_builder.net_devices.len(), 1);
     }
 
+    #[test]
+    fn test_remove() {
+        let mut net_builder = NetBuilder::new();
+        let netif_1 = create_netif("id_1", "dev1", "01:23:45:67:89:0a");
+        let netif_2 = create_netif("id_2", "dev2", "01:23:45:67:89:0b");
+        assert!(net_builder.build(netif_1).is_ok());
+        assert!(net_builder.build(netif_2).is_ok());
+
+        let net = net_builder.remove("id_1").unwrap();
+        assert_eq!(net.lock().unwrap().id(), "id_1");
+        assert_eq!(net_builder.net_devices.len(), 1);
+        assert_eq!(
+            net_builder.remove("id_1").err().unwrap().to_string(),
+            NetworkInterfaceError::InterfaceNotFound(String::from("id_1")).to_string()
+        );
+
+        // The host device of a removed interface can be used by a new one.
+        drop(net);
+        let netif_3 = create_netif("id_3", "dev1", "01:23:45:67:89:0c");
+        assert!(net_builder.build(netif_3).is_ok());
+        assert_eq!(net_builder.net_devices.len(), 2);
+    }
+
+    #[test]
+    fn test_validate_guest_mac() {
+        let mut net_builder = NetBuilder::new();
+        let netif_1 = create_netif("id_1", "dev1", "01:23:45:67:89:0a");
+        let netif_2 = create_netif("id_2", "dev2", "01:23:45:67:89:0b");
+        assert!(net_builder.build(netif_1).is_ok());
+        assert!(net_builder.build(netif_2).is_ok());
+
+        let mac_1 = MacAddr::parse_str("01:23:45:67:89:0a").unwrap();
+        let new_mac = MacAddr::parse_str("01:23:45:67:89:0c").unwrap();
+        // An interface can keep its own MAC address.
+        assert!(net_builder.validate_guest_mac("id_1", &mac_1).is_ok());
+        assert!(net_builder.validate_guest_mac("id_2", &new_mac).is_ok());
+        assert_eq!(
+            net_builder
+                .validate_guest_mac("id_2", &mac_1)
+                .err()
+                .unwrap()
+                .to_string(),
+            NetworkInterfaceError::GuestMacAddressInUse(mac_1.to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_validate_configs() {
+        let netif_1 = create_netif("id_1", "dev1", "01:23:45:67:89:0a");
+        let netif_2 = create_netif("id_2", "dev2", "01:23:45:67:89:0b");
+        assert!(NetBuilder::validate_configs(&[netif_1.clone(), netif_2]).is_ok());
+        // A later entry with the same ID updates the interface.
+        let netif_1_update = create_netif("id_1", "dev1", "01:23:45:67:89:0a");
+        assert!(NetBuilder::validate_configs(&[netif_1.clone(), netif_1_update]).is_ok());
+
+        let same_mac = create_netif("id_2", "dev2", "01:23:45:67:89:0a");
+        assert_eq!(
+            NetBuilder::validate_configs(&[netif_1.clone(), same_mac])
+                .unwrap_err()
+                .to_string(),
+            NetworkInterfaceError::GuestMacAddressInUse("01:23:45:67:89:0a".to_string())
+                .to_string()
+        );
+        let same_dev = create_netif("id_2", "dev1", "01:23:45:67:89:0b");
+        assert_eq!(
+            NetBuilder::validate_configs(&[netif_1, same_dev])
+                .unwrap_err()
+                .to_string(),
+            NetworkInterfaceError::HostDeviceNameInUse("dev1".to_string()).to_string()
+        );
+    }
+
     #[test]
     fn test_insert_error_cases() {
         let mut net_builder = NetBuilder::new();
@@ -295,21 +889,31 @@ mod tests {
             "The guest MAC address {} is already in use.",
             guest_mac_1.to_string()
         );
+        assert_eq!(
+            net_builder.validate(&netif_2).err().unwrap().to_string(),
+            expected_error
+        );
         assert_eq!(
             net_builder.build(netif_2).err().unwrap().to_string(),
             expected_error
         );
         assert_eq!(net_builder.net_devices.len(), 1);
 
+        // Validating a correct config doesn't build the device.
+        let netif_2 = create_netif(id_2, host_dev_name_2, guest_mac_2);
+        assert!(net_builder.validate(&netif_2).is_ok());
+        assert_eq!(net_builder.net_devices.len(), 1);
+
         // Error Case: Add new network config with the same dev_host_name as netif_1.
         let netif_2 = create_netif(id_2, host_dev_name_1, guest_mac_2);
-        assert_eq!(
-            net_builder.build(netif_2).err().unwrap().to_string(),
-            NetworkInterfaceError::CreateNetworkDevice(devices::virtio::net::Error::TapOpen(
-                TapError::IoctlError(std::io::Error::from_raw_os_error(16))
-            ))
-            .to_string()
-        );
+        assert!(matches!(
+            net_builder.validate(&netif_2),
+            Err(NetworkInterfaceError::HostDeviceNameInUse(ref name)) if name == host_dev_name_1
+        ));
+        assert!(matches!(
+            net_builder.build(netif_2),
+            Err(NetworkInterfaceError::HostDeviceNameInUse(_))
+        ));
         assert_eq!(net_builder.net_devices.len(), 1);
 
         // Adding the second valid network config.
@@ -332,11 +936,16 @@ mod tests {
         let netif_2 = create_netif(id_2, host_dev_name_1, guest_mac_2);
         assert_eq!(
             net_builder.build(netif_2).err().unwrap().to_string(),
-            NetworkInterfaceError::CreateNetworkDevice(devices::virtio::net::Error::TapOpen(
-                TapError::IoctlError(std::io::Error::from_raw_os_error(16))
-            ))
-            .to_string()
+            format!(
+                "The host device name {} is already in use.",
+                host_dev_name_1
+            )
         );
+        assert_eq!(net_builder.net_devices.len(), 2);
+
+        // Updating a config may keep its own host device name.
+        let netif_1 = create_netif(id_1, host_dev_name_1, guest_mac_1);
+        assert!(net_builder.validate(&netif_1).is_ok());
     }
 
     #[test]
@@ -353,6 +962,34 @@ mod tests {
             NetworkInterfaceError::DeviceUpdate(VmmError::VcpuExit),
             NetworkInterfaceError::DeviceUpdate(VmmError::VcpuExit)
         );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::DeviceRemoval(VmmError::VcpuExit),
+            NetworkInterfaceError::DeviceRemoval(VmmError::VcpuExit)
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::DeviceStats(VmmError::VcpuExit),
+            NetworkInterfaceError::DeviceStats(VmmError::VcpuExit)
+        );
+        let err = NetworkInterfaceError::InterfaceNotFound(String::from("id"));
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::InvalidXsksMap;
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::InvalidNetns;
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::InvalidFilterRule(0);
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::RateLimiterGroupNotFound("group".to_string());
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::HostDeviceNameInUse("tap0".to_string());
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::InvalidCaptureConfig;
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::OpenCaptureFile(std::io::Error::from_raw_os_error(2));
+        let _ = format!("{}{:?}", err, err);
+        let err = NetworkInterfaceError::CaptureUnsupported;
+        let _ = format!("{}{:?}", err, err);
         let _ = format!(
             "{}{:?}",
             NetworkInterfaceError::OpenTap(TapError::InvalidIfname),
@@ -381,6 +1018,431 @@ mod tests {
         assert_eq!(configs.first().unwrap(), &net_if_cfg);
     }
 
+    #[test]
+    fn test_net_backend_type() {
+        // The backend type defaults to a TAP when not specified.
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0"}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.backend_type, NetBackendType::Tap);
+
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0", "backend_type": "tap"}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.backend_type, NetBackendType::Tap);
+
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0", "backend_type": "foo"}"#;
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(json).is_err());
+    }
+
+    #[test]
+    fn test_net_num_queues() {
+        // A single queue pair is used when not specified.
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0"}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.num_queues, 1);
+
+        let mut net_builder = NetBuilder::new();
+        let mut netif = create_netif("mq_id", "mq-dev", "01:23:45:67:89:0c");
+
+        netif.num_queues = 0;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidNumQueues(0))
+        ));
+        netif.num_queues = MAX_QUEUE_PAIRS + 1;
+        assert!(matches!(
+            net_builder.build(netif.clone()),
+            Err(NetworkInterfaceError::InvalidNumQueues(_))
+        ));
+        assert!(net_builder.is_empty());
+
+        netif.num_queues = 4;
+        let net = net_builder.build(netif.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().num_queue_pairs(), 4);
+        assert_eq!(net_builder.configs().first().unwrap(), &netif);
+    }
+
+    #[test]
+    fn test_net_tap_fd_validation() {
+        let net_builder = NetBuilder::new();
+        let mut netif = create_netif("fd_id", "fd-dev", "01:23:45:67:89:0f");
+
+        // The TAP can't be specified both by name and by fd.
+        netif.tap_fd = Some(42);
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidTapSource)
+        ));
+
+        netif.host_dev_name = String::new();
+        assert!(net_builder.validate(&netif).is_ok());
+        // A TAP fd only serves a single queue pair.
+        netif.num_queues = 2;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidNumQueues(2))
+        ));
+
+        // The TAP has to be specified in one way or the other.
+        netif.num_queues = 1;
+        netif.tap_fd = None;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidTapSource)
+        ));
+
+        // Only the TAP and socketpair backends can be handed over as fds.
+        netif.tap_fd = Some(42);
+        netif.backend_type = NetBackendType::VhostUser;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidTapSource)
+        ));
+    }
+
+    #[cfg(feature = "net-socketpair")]
+    #[test]
+    fn test_net_socketpair_fd() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut net_builder = NetBuilder::new();
+        let mut netif = create_netif("sp_id", "", "01:23:45:67:89:0e");
+        netif.backend_type = NetBackendType::SocketPair;
+
+        let mut fds = [0; 2];
+        assert_eq!(
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr(),) },
+            0
+        );
+        let _harness = unsafe { File::from_raw_fd(fds[1]) };
+        netif.tap_fd = Some(fds[0]);
+        let net = net_builder.build(netif).unwrap();
+        assert_eq!(
+            net.lock().unwrap().backend_type(),
+            NetBackendType::SocketPair
+        );
+    }
+
+    #[test]
+    fn test_net_xdp_validation() {
+        let json = r#"{
+            "iface_id": "eth0",
+            "host_dev_name": "eth1",
+            "backend_type": "xdp",
+            "xsks_map_path": "/sys/fs/bpf/xsks_map",
+            "num_queues": 4
+        }"#;
+        let mut netif: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(netif.backend_type, NetBackendType::Xdp);
+        let net_builder = NetBuilder::new();
+        assert!(net_builder.validate(&netif).is_ok());
+
+        // The XSKMAP is required for the xdp backend.
+        netif.xsks_map_path = None;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidXsksMap)
+        ));
+        // And only for it.
+        let mut netif = create_netif("xdp_id", "xdp-dev", "01:23:45:67:89:11");
+        netif.xsks_map_path = Some("/sys/fs/bpf/xsks_map".to_string());
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidXsksMap)
+        ));
+    }
+
+    #[test]
+    fn test_net_netns() {
+        let json = r#"{
+            "iface_id": "eth0",
+            "host_dev_name": "tap0",
+            "netns": "/var/run/netns/fc-test"
+        }"#;
+        let mut netif: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(netif.netns.as_deref(), Some("/var/run/netns/fc-test"));
+        let mut net_builder = NetBuilder::new();
+        assert!(net_builder.validate(&netif).is_ok());
+
+        // The TAP device isn't opened when the namespace can't be entered.
+        assert!(matches!(
+            net_builder.build(netif.clone()),
+            Err(NetworkInterfaceError::CreateNetworkDevice(
+                devices::virtio::net::Error::SetNetns(_)
+            ))
+        ));
+        assert!(net_builder.is_empty());
+
+        // An already opened TAP device can't be moved to another namespace.
+        netif.host_dev_name = String::new();
+        netif.tap_fd = Some(0);
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidNetns)
+        ));
+    }
+
+    #[test]
+    fn test_net_mtu() {
+        let mut net_builder = NetBuilder::new();
+        let mut netif = create_netif("mtu_id", "mtu-dev", "01:23:45:67:89:0e");
+
+        netif.mtu = Some(MIN_MTU - 1);
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidMtu(_))
+        ));
+        assert!(net_builder.build(netif.clone()).is_err());
+        assert!(net_builder.is_empty());
+
+        netif.mtu = Some(1400);
+        let net = net_builder.build(netif.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().mtu(), Some(1400));
+        assert_eq!(net_builder.configs().first().unwrap(), &netif);
+    }
+
+    #[test]
+    fn test_net_worker_thread() {
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0"}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert!(!cfg.worker_thread);
+
+        let mut net_builder = NetBuilder::new();
+        let mut netif = create_netif("worker_id", "worker-dev", "01:23:45:67:89:0f");
+        netif.worker_thread = true;
+        let net = net_builder.build(netif.clone()).unwrap();
+        assert!(net.lock().unwrap().worker_thread());
+        assert_eq!(net_builder.configs().first().unwrap(), &netif);
+    }
+
+    #[test]
+    fn test_net_offloads() {
+        // All the offloads are enabled when not specified.
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0"}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.offloads, NetOffloads::default());
+
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0", "offloads": {"tso": false}}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            cfg.offloads,
+            NetOffloads {
+                csum: true,
+                tso: false,
+                ufo: true,
+            }
+        );
+
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0", "offloads": {"gso": false}}"#;
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(json).is_err());
+
+        let mut net_builder = NetBuilder::new();
+        let mut netif = create_netif("offloads_id", "offloads-dev", "01:23:45:67:89:10");
+        netif.offloads = NetOffloads {
+            csum: true,
+            tso: false,
+            ufo: false,
+        };
+        let net = net_builder.build(netif.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().offloads(), netif.offloads);
+        assert_eq!(net_builder.configs().first().unwrap(), &netif);
+    }
+
+    #[test]
+    fn test_net_vhost_validation() {
+        // The userspace datapath is used when not specified.
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0"}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.backend, NetDatapath::Virtio);
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0", "backend": "vhost"}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.backend, NetDatapath::Vhost);
+
+        let net_builder = NetBuilder::new();
+        let mut netif = create_netif("vhost_id", "vhost-dev", "01:23:45:67:89:0d");
+        netif.backend = NetDatapath::Vhost;
+        assert!(net_builder.validate(&netif).is_ok());
+
+        netif.tx_rate_limiter = Some(RateLimiterConfig {
+            bandwidth: Some(TokenBucketConfig {
+                size: 1000,
+                one_time_burst: None,
+                refill_time: 100,
+            }),
+            ops: None,
+            pps: None,
+        });
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::VhostUnsupported(_))
+        ));
+
+        netif.tx_rate_limiter = None;
+        netif.rx_filtering = true;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::VhostUnsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_net_vhost_user_validation() {
+        let json = r#"{
+            "iface_id": "eth0",
+            "host_dev_name": "/run/vhost-user.sock",
+            "backend_type": "vhost_user"
+        }"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.backend_type, NetBackendType::VhostUser);
+
+        let net_builder = NetBuilder::new();
+        let mut netif = create_netif("vhost_user_id", "/run/vhost-user.sock", "01:23:45:67:89:12");
+        netif.backend_type = NetBackendType::VhostUser;
+        assert!(net_builder.validate(&netif).is_ok());
+
+        // The backend serves a single queue pair, and moves the frames itself.
+        netif.num_queues = 2;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidNumQueues(2))
+        ));
+        netif.num_queues = 1;
+        netif.backend = NetDatapath::Vhost;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::VhostUnsupported(_))
+        ));
+        netif.backend = NetDatapath::Virtio;
+        netif.rx_filtering = true;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::VhostUnsupported(_))
+        ));
+        netif.rx_filtering = false;
+
+        // Nobody listens on the socket.
+        let mut net_builder = NetBuilder::new();
+        netif.host_dev_name = "/tmp/fc-vhost-user-nonexistent.sock".to_string();
+        assert!(matches!(
+            net_builder.build(netif),
+            Err(NetworkInterfaceError::CreateNetworkDevice(
+                devices::virtio::net::Error::Vhost(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_net_rx_filtering() {
+        // RX filtering is disabled when not specified.
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0"}"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert!(!cfg.rx_filtering);
+
+        let mut net_builder = NetBuilder::new();
+        let mut netif = create_netif("rx_filter_id", "rx-filter-dev", "01:23:45:67:89:11");
+        netif.rx_filtering = true;
+        let net = net_builder.build(netif).unwrap();
+        assert!(net.lock().unwrap().rx_filtering());
+        assert!(net_builder.configs()[0].rx_filtering);
+    }
+
+    #[test]
+    fn test_net_filters() {
+        let json = r#"{
+            "iface_id": "eth0",
+            "host_dev_name": "tap0",
+            "filters": [
+                {"action": "allow", "direction": "egress", "cidr": "169.254.169.254/32"},
+                {"action": "allow", "direction": "egress", "cidr": "10.0.0.0/8",
+                 "protocol": "tcp", "port": 443}
+            ]
+        }"#;
+        let cfg: NetworkInterfaceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            cfg.filters[1],
+            NetFilterRule {
+                action: NetFilterAction::Allow,
+                direction: NetFilterDirection::Egress,
+                cidr: Ipv4Cidr::parse_str("10.0.0.0/8").unwrap(),
+                protocol: Some(NetFilterProtocol::Tcp),
+                port: Some(443),
+            }
+        );
+        let json = r#"{"iface_id": "eth0", "host_dev_name": "tap0",
+            "filters": [{"action": "allow", "direction": "egress", "cidr": "10.0.0.0/33"}]}"#;
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(json).is_err());
+
+        let mut net_builder = NetBuilder::new();
+        let mut netif = create_netif("filters_id", "filters-dev", "01:23:45:67:89:12");
+        netif.filters = cfg.filters.clone();
+        // A port needs a protocol which has ports.
+        netif.filters[1].protocol = Some(NetFilterProtocol::Icmp);
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::InvalidFilterRule(1))
+        ));
+
+        netif.filters = cfg.filters.clone();
+        let net = net_builder.build(netif.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().filters(), &cfg.filters[..]);
+        assert_eq!(net_builder.configs()[0].filters, cfg.filters);
+
+        netif.backend = NetDatapath::Vhost;
+        assert!(matches!(
+            net_builder.validate(&netif),
+            Err(NetworkInterfaceError::VhostUnsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_net_capture() {
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        let path = tmp_dir.as_path().join("capture.pcap");
+        let json = format!(
+            r#"{{"iface_id": "capture_id", "state": "Started", "path_on_host": "{}"}}"#,
+            path.display()
+        );
+        let mut capture_cfg: NetworkCaptureConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(capture_cfg.file_count, 1);
+        assert_eq!(capture_cfg.max_file_size_mib, None);
+
+        let mut net_builder = NetBuilder::new();
+        assert!(matches!(
+            net_builder.set_capture(&capture_cfg),
+            Err(NetworkInterfaceError::InterfaceNotFound(_))
+        ));
+        let netif = create_netif("capture_id", "capture-dev", "01:23:45:67:89:0e");
+        let net = net_builder.build(netif).unwrap();
+
+        net_builder.set_capture(&capture_cfg).unwrap();
+        assert!(net.lock().unwrap().is_capturing());
+        assert!(path.exists());
+
+        capture_cfg.state = CaptureState::Stopped;
+        net_builder.set_capture(&capture_cfg).unwrap();
+        assert!(!net.lock().unwrap().is_capturing());
+
+        // Invalid configs.
+        capture_cfg.state = CaptureState::Started;
+        capture_cfg.file_count = 0;
+        assert!(matches!(
+            net_builder.set_capture(&capture_cfg),
+            Err(NetworkInterfaceError::InvalidCaptureConfig)
+        ));
+        capture_cfg.file_count = 1;
+        capture_cfg.path_on_host = None;
+        assert!(matches!(
+            net_builder.set_capture(&capture_cfg),
+            Err(NetworkInterfaceError::InvalidCaptureConfig)
+        ));
+        capture_cfg.path_on_host = Some(String::from("/invalid/path/capture.pcap"));
+        assert!(matches!(
+            net_builder.set_capture(&capture_cfg),
+            Err(NetworkInterfaceError::OpenCaptureFile(_))
+        ));
+        assert!(!net.lock().unwrap().is_capturing());
+    }
+
     #[test]
     fn test_add_device() {
         let mut net_builder = NetBuilder::new();
@@ -394,6 +1456,7 @@ mod tests {
             Some(&MacAddr::parse_str(guest_mac).unwrap()),
             RateLimiter::default(),
             RateLimiter::default(),
+            1,
         )
         .unwrap();
 
@@ -0,0 +1,148 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use std::result;
+use std::sync::{Arc, Mutex};
+
+use devices::virtio::pmem::{Error as PmemError, PMEM_ALIGNMENT};
+use devices::virtio::Pmem;
+use serde::{Deserialize, Serialize};
+
+type Result<T> = result::Result<T, PmemConfigError>;
+
+/// Errors associated with the operations allowed on a pmem device.
+#[derive(Debug)]
+pub enum PmemConfigError {
+    /// Cannot open or map the backing file of the pmem device.
+    CreatePmemDevice(PmemError),
+}
+
+impl Display for PmemConfigError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::PmemConfigError::*;
+        match self {
+            CreatePmemDevice(PmemError::InvalidSize(size)) => write!(
+                f,
+                "Invalid pmem backing file size {}, it must be a non-zero multiple of {} bytes.",
+                size, PMEM_ALIGNMENT
+            ),
+            CreatePmemDevice(e) => write!(f, "Unable to create the pmem device: {:?}", e),
+        }
+    }
+}
+
+/// Use this structure to set up a pmem device before booting the kernel.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PmemConfig {
+    /// Unique identifier of the pmem device.
+    pub pmem_id: String,
+    /// Path of the file mapped in the guest physical memory.
+    pub path_on_host: String,
+    /// If set to true, the guest can't write to the device memory.
+    #[serde(default)]
+    pub is_read_only: bool,
+}
+
+impl From<&Pmem> for PmemConfig {
+    fn from(pmem: &Pmem) -> Self {
+        PmemConfig {
+            pmem_id: pmem.id().clone(),
+            path_on_host: pmem.path_on_host().clone(),
+            is_read_only: pmem.is_read_only(),
+        }
+    }
+}
+
+/// Wrapper for the collection that holds all the pmem devices.
+#[derive(Default)]
+pub struct PmemBuilder {
+    /// The list of pmem devices, in the order in which they are mapped in the guest memory.
+    pub list: Vec<Arc<Mutex<Pmem>>>,
+}
+
+impl PmemBuilder {
+    /// Creates an empty list of pmem devices.
+    pub fn new() -> Self {
+        Self { list: Vec::new() }
+    }
+
+    /// Inserts a `Pmem` in the pmem devices list using the specified configuration.
+    /// If a device with the same id already exists, it will overwrite it.
+    pub fn insert(&mut self, config: PmemConfig) -> Result<()> {
+        let position = self
+            .list
+            .iter()
+            .position(|p| p.lock().expect("Poisoned lock").id() == &config.pmem_id);
+        let pmem = Arc::new(Mutex::new(Self::create_pmem(config)?));
+        match position {
+            Some(index) => self.list[index] = pmem,
+            None => self.list.push(pmem),
+        }
+        Ok(())
+    }
+
+    /// Creates a pmem device from a `PmemConfig`.
+    pub fn create_pmem(config: PmemConfig) -> Result<Pmem> {
+        Pmem::new(config.pmem_id, config.path_on_host, config.is_read_only)
+            .map_err(PmemConfigError::CreatePmemDevice)
+    }
+
+    /// Returns a vec with the structures used to configure the devices.
+    pub fn configs(&self) -> Vec<PmemConfig> {
+        self.list
+            .iter()
+            .map(|pmem| PmemConfig::from(pmem.lock().unwrap().deref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    fn pmem_config(pmem_id: &str, backing_file: &TempFile) -> PmemConfig {
+        PmemConfig {
+            pmem_id: pmem_id.to_string(),
+            path_on_host: backing_file.as_path().to_str().unwrap().to_string(),
+            is_read_only: false,
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let backing_file = TempFile::new().unwrap();
+        backing_file.as_file().set_len(PMEM_ALIGNMENT).unwrap();
+        let mut builder = PmemBuilder::new();
+
+        builder.insert(pmem_config("pmem0", &backing_file)).unwrap();
+        builder.insert(pmem_config("pmem1", &backing_file)).unwrap();
+        assert_eq!(builder.list.len(), 2);
+
+        // Inserting a device with the same id overwrites it.
+        let mut config = pmem_config("pmem0", &backing_file);
+        config.is_read_only = true;
+        builder.insert(config.clone()).unwrap();
+        assert_eq!(builder.list.len(), 2);
+        assert_eq!(builder.configs()[0], config);
+
+        // The size of the backing file must be a multiple of 2MiB.
+        backing_file.as_file().set_len(PMEM_ALIGNMENT + 1).unwrap();
+        let err = builder
+            .insert(pmem_config("pmem2", &backing_file))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Invalid pmem backing file size {}, it must be a non-zero multiple of {} bytes.",
+                PMEM_ALIGNMENT + 1,
+                PMEM_ALIGNMENT
+            )
+        );
+        assert_eq!(builder.list.len(), 2);
+    }
+}
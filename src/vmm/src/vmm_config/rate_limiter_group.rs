@@ -0,0 +1,148 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use rate_limiter::{BucketUpdate, RateLimiterGroup, TokenBucket};
+use serde::{Deserialize, Serialize};
+
+use super::TokenBucketConfig;
+
+/// The data fed into a rate limiter group request. The group is referenced by the `rl_group`
+/// field of the devices whose aggregate rate it limits.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimiterGroupConfig {
+    /// ID of the rate limiter group.
+    pub group_id: String,
+    /// Bandwidth limit shared by the devices of the group.
+    pub bandwidth: Option<TokenBucketConfig>,
+    /// Operations limit shared by the devices of the group.
+    pub ops: Option<TokenBucketConfig>,
+}
+
+impl From<&RateLimiterGroup> for RateLimiterGroupConfig {
+    fn from(group: &RateLimiterGroup) -> Self {
+        RateLimiterGroupConfig {
+            group_id: group.id().to_string(),
+            bandwidth: group.bandwidth().as_ref().map(TokenBucketConfig::from),
+            ops: group.ops().as_ref().map(TokenBucketConfig::from),
+        }
+    }
+}
+
+fn token_bucket(tb_cfg: &Option<TokenBucketConfig>) -> Option<TokenBucket> {
+    tb_cfg.and_then(|tb_cfg| {
+        TokenBucket::new(
+            tb_cfg.size,
+            tb_cfg.one_time_burst.unwrap_or(0),
+            tb_cfg.refill_time,
+        )
+    })
+}
+
+fn bucket_update(tb_cfg: &Option<TokenBucketConfig>) -> BucketUpdate {
+    token_bucket(tb_cfg)
+        .map(BucketUpdate::Update)
+        .unwrap_or(BucketUpdate::Disabled)
+}
+
+/// Wrapper for the collection that holds all the rate limiter groups.
+#[derive(Debug, Default)]
+pub struct RateLimiterGroupBuilder {
+    groups: Vec<RateLimiterGroup>,
+}
+
+impl RateLimiterGroupBuilder {
+    /// Creates an empty rate limiter group collection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rate limiter group, or replaces the buckets of the one that already exists, for
+    /// all of the devices in the group.
+    pub fn insert(&mut self, config: RateLimiterGroupConfig) {
+        match self.get(&config.group_id) {
+            Some(group) => {
+                group.update_buckets(bucket_update(&config.bandwidth), bucket_update(&config.ops))
+            }
+            None => self.groups.push(RateLimiterGroup::new(
+                config.group_id,
+                token_bucket(&config.bandwidth),
+                token_bucket(&config.ops),
+            )),
+        }
+    }
+
+    /// Returns the rate limiter group with id `group_id`, if any.
+    pub fn get(&self, group_id: &str) -> Option<&RateLimiterGroup> {
+        self.groups.iter().find(|group| group.id() == group_id)
+    }
+
+    /// Returns a vec with the structures used to configure the rate limiter groups.
+    pub fn configs(&self) -> Vec<RateLimiterGroupConfig> {
+        self.groups
+            .iter()
+            .map(RateLimiterGroupConfig::from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group_config(group_id: &str, size: u64) -> RateLimiterGroupConfig {
+        RateLimiterGroupConfig {
+            group_id: group_id.to_string(),
+            bandwidth: Some(TokenBucketConfig {
+                size,
+                one_time_burst: None,
+                refill_time: 1000,
+            }),
+            ops: None,
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut builder = RateLimiterGroupBuilder::new();
+        assert!(builder.get("tenant-a").is_none());
+
+        builder.insert(group_config("tenant-a", 1000));
+        builder.insert(group_config("tenant-b", 2000));
+        let group = builder.get("tenant-a").unwrap().clone();
+        assert_eq!(group.bandwidth().unwrap().capacity(), 1000);
+        assert!(group.ops().is_none());
+        assert_eq!(
+            builder.configs(),
+            vec![
+                group_config("tenant-a", 1000),
+                group_config("tenant-b", 2000)
+            ]
+        );
+
+        // Updating a group updates the buckets shared by its devices.
+        builder.insert(group_config("tenant-a", 3000));
+        assert_eq!(group.bandwidth().unwrap().capacity(), 3000);
+        assert_eq!(builder.configs().len(), 2);
+
+        let mut config = group_config("tenant-a", 0);
+        config.bandwidth = None;
+        builder.insert(config.clone());
+        assert!(group.bandwidth().is_none());
+        assert_eq!(builder.configs()[0], config);
+    }
+
+    #[test]
+    fn test_group_config_deserialization() {
+        let json = r#"{
+            "group_id": "tenant-a",
+            "bandwidth": {"size": 1000, "refill_time": 100}
+        }"#;
+        let config: RateLimiterGroupConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.group_id, "tenant-a");
+        assert!(config.ops.is_none());
+
+        let json = r#"{"group_id": "tenant-a", "foo": 1}"#;
+        assert!(serde_json::from_str::<RateLimiterGroupConfig>(json).is_err());
+    }
+}
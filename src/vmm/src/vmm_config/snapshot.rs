@@ -3,9 +3,14 @@
 
 //! Configurations used in the snapshotting context.
 
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utils::net::mac::MacAddr;
+
+use crate::vmm_config::machine_config::GuestMemoryBackend;
 
 /// The snapshot type options that are available when
 /// creating a new snapshot.
@@ -23,6 +28,14 @@ impl Default for SnapshotType {
     }
 }
 
+/// The algorithms the guest memory file can be compressed with.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotCompression {
+    /// LZ4, through a pure Rust implementation.
+    Lz4,
+}
+
 /// Specifies the method through which guest memory will get populated when
 /// resuming from a snapshot:
 /// 1) A file that contains the guest memory to be loaded,
@@ -36,6 +49,27 @@ pub enum MemBackendType {
     Uffd,
 }
 
+/// Specifies when the guest memory gets populated from the memory file when resuming from a
+/// snapshot.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResumeMode {
+    /// The whole guest memory is loaded before the microVM is resumed.
+    Eager,
+    /// The guest pages are loaded on first access, by a page fault handler thread of
+    /// Firecracker, through UFFD.
+    Lazy,
+}
+
+impl Default for ResumeMode {
+    fn default() -> Self {
+        ResumeMode::Eager
+    }
+}
+
+/// Maximum number of threads writing the guest memory of a snapshot.
+pub const MAX_MEM_WRITER_THREADS: usize = 16;
+
 /// Stores the configuration that will be used for creating a snapshot.
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -44,40 +78,178 @@ pub struct CreateSnapshotParams {
     /// The default value is `Full`, which means a full snapshot.
     #[serde(default = "SnapshotType::default")]
     pub snapshot_type: SnapshotType,
-    /// Path to the file that will contain the microVM state.
+    /// Path to the file that will contain the microVM state. Left empty when `snapshot_fd` is
+    /// used.
+    #[serde(default)]
     pub snapshot_path: PathBuf,
-    /// Path to the file that will contain the guest memory.
+    /// File descriptor, open in Firecracker, to write the microVM state to. It can be a pipe or
+    /// a socket, to stream the state elsewhere. Firecracker takes ownership of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_fd: Option<RawFd>,
+    /// Path to the file that will contain the guest memory. Left empty when `mem_file_fd` is
+    /// used.
+    #[serde(default)]
     pub mem_file_path: PathBuf,
+    /// File descriptor, open in Firecracker, to write the guest memory to. Diff snapshots need a
+    /// regular file, pipes and sockets are only supported by full snapshots. Firecracker takes
+    /// ownership of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_file_fd: Option<RawFd>,
+    /// Optional algorithm to compress the guest memory file with. Only full snapshots can be
+    /// compressed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<SnapshotCompression>,
+    /// Optional number of threads writing the guest memory of a full, uncompressed snapshot to a
+    /// regular file, each one a disjoint range of the file. Defaults to 1, at most
+    /// `MAX_MEM_WRITER_THREADS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_writer_threads: Option<usize>,
+    /// Leaves the contents of the MMDS data store out of the snapshot, for the snapshot not to
+    /// carry the secrets they may hold.
+    #[serde(default)]
+    pub exclude_mmds: bool,
     /// Optional field for the microVM version. The default
     /// value is the current version.
     pub version: Option<String>,
 }
 
+/// Stores the configuration used for merging a chain of diff snapshots into a full snapshot.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergeSnapshotParams {
+    /// Path to the file that contains the microVM state of the last snapshot of the chain.
+    pub snapshot_path: PathBuf,
+    /// Path to the file that contains the guest memory of the last snapshot of the chain.
+    pub mem_file_path: PathBuf,
+    /// Path to the file that will contain the merged guest memory.
+    pub output_mem_file_path: PathBuf,
+}
+
+/// Number of snapshot slots a schedule rotates through, if not set.
+pub const DEFAULT_SCHEDULE_SLOTS: usize = 4;
+/// Maximum number of snapshot slots a schedule rotates through.
+pub const MAX_SCHEDULE_SLOTS: usize = 64;
+
+fn default_schedule_slots() -> usize {
+    DEFAULT_SCHEDULE_SLOTS
+}
+
+/// Stores the configuration of the snapshots taken periodically by Firecracker, set through
+/// `PUT /snapshot/schedule`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotScheduleConfig {
+    /// Interval between two snapshots, in seconds.
+    pub interval_s: u64,
+    /// Directory the snapshots are written to. Each slot `k` is made of the `vmstate_k` and
+    /// `mem_k` files.
+    pub snapshot_dir: PathBuf,
+    /// Number of slots the snapshots rotate through. The first slot holds a full snapshot, the
+    /// following ones hold diff snapshots taken on top of the previous slot.
+    #[serde(default = "default_schedule_slots")]
+    pub slots: usize,
+    /// Optional bound on the time the microVM is paused for a snapshot, in milliseconds. The
+    /// schedule is cancelled after a snapshot that exceeded it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pause_ms: Option<u64>,
+}
+
+/// Dirty page rate of the guest memory, returned by `GET /vm/dirty-rate`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DirtyRate {
+    /// Number of pages dirtied by the guest since the previous sample.
+    pub dirty_pages: u64,
+    /// Time elapsed since the previous sample, or since the start of the microVM, in
+    /// milliseconds.
+    pub interval_ms: u64,
+    /// Average number of pages dirtied per second over the interval.
+    pub dirty_pages_per_sec: u64,
+}
+
+/// Describes a snapshot without loading it, returned by `GET /snapshot/info`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SnapshotInfo {
+    /// Firecracker release whose data format the microVM state is saved in, if known.
+    pub firecracker_version: Option<String>,
+    /// Data format version of the microVM state.
+    pub data_version: u16,
+    /// Number of vCPUs of the microVM.
+    pub vcpu_count: usize,
+    /// Guest memory size, in MiB.
+    pub mem_size_mib: u64,
+    /// Devices attached to the microVM.
+    pub devices: Vec<SnapshotDeviceInfo>,
+    /// CRC64 checksum of the microVM state file, in hexadecimal.
+    pub checksum: String,
+}
+
+/// Device saved in a snapshot.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SnapshotDeviceInfo {
+    /// ID of the device.
+    pub id: String,
+    /// Type of the device: `block`, `net`, `vsock` or `balloon`.
+    #[serde(rename = "type")]
+    pub device_type: &'static str,
+}
+
 /// Stores the configuration that will be used for loading a snapshot.
 #[derive(Debug, PartialEq)]
 pub struct LoadSnapshotParams {
-    /// Path to the file that contains the microVM state to be loaded.
+    /// Path to the file that contains the microVM state to be loaded. Left empty when
+    /// `snapshot_fd` is used.
     pub snapshot_path: PathBuf,
-    /// Specifies guest memory backend configuration.
+    /// File descriptor to read the microVM state to be loaded from, owned by Firecracker.
+    pub snapshot_fd: Option<RawFd>,
+    /// Specifies guest memory backend configuration. The path of the `File` backend is left
+    /// empty when `mem_file_fd` is used.
     pub mem_backend: MemBackendConfig,
+    /// File descriptor to read the guest memory from, owned by Firecracker.
+    pub mem_file_fd: Option<RawFd>,
     /// Setting this flag will enable KVM dirty page tracking and will
     /// allow taking subsequent incremental snapshots.
     pub enable_diff_snapshots: bool,
     /// When set to true, the vm is also resumed if the snapshot load
     /// is successful.
     pub resume_vm: bool,
+    /// When the guest memory is populated from the `File` backend.
+    pub resume_mode: ResumeMode,
+    /// Host devices of the network interfaces replacing the ones saved in the snapshot.
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Backing files of the drives replacing the ones saved in the snapshot.
+    pub drive_overrides: Vec<DriveOverride>,
+    /// Skips checking the guest memory against the checksums saved in the snapshot.
+    pub skip_verify: bool,
+    /// The memory the guest memory is copied in from the `File` backend.
+    pub guest_mem_backend: GuestMemoryBackend,
+    /// Identity to give to the restored microVM, when it is one of several clones of the
+    /// snapshot.
+    pub clone: Option<CloneConfig>,
+    /// Contents to put in the MMDS data store, instead of the ones saved in the snapshot.
+    pub mmds_data: Option<Value>,
 }
 
 /// Stores the configuration for loading a snapshot that is provided by the user.
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct LoadSnapshotConfig {
-    /// Path to the file that contains the microVM state to be loaded.
+    /// Path to the file that contains the microVM state to be loaded. Left empty when
+    /// `snapshot_fd` is used.
+    #[serde(default)]
     pub snapshot_path: PathBuf,
+    /// File descriptor, open in Firecracker, to read the microVM state to be loaded from. It can
+    /// be a pipe or a socket, to stream the state from elsewhere. Firecracker takes ownership of
+    /// it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot_fd: Option<RawFd>,
     /// Path to the file that contains the guest memory to be loaded. To be used only if
     /// `mem_backend` is not specified.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mem_file_path: Option<PathBuf>,
+    /// File descriptor, open in Firecracker, to read the guest memory from. To be used only if
+    /// `mem_backend` and `mem_file_path` are not specified. Firecracker takes ownership of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_file_fd: Option<RawFd>,
     /// Guest memory backend configuration. Is not to be used in conjunction with `mem_file_path`.
     /// None value is allowed only if `mem_file_path` is present.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -88,6 +260,74 @@ pub struct LoadSnapshotConfig {
     /// Whether or not to resume the vm post snapshot load.
     #[serde(default)]
     pub resume_vm: bool,
+    /// Whether the guest memory is loaded up front or on first access. Only the `File` memory
+    /// backend supports a lazy resume.
+    #[serde(default)]
+    pub resume_mode: ResumeMode,
+    /// Host devices of the network interfaces to use instead of the ones saved in the snapshot,
+    /// e.g. when it is restored on another host.
+    #[serde(default)]
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Backing files of the drives to use instead of the ones saved in the snapshot.
+    #[serde(default)]
+    pub drive_overrides: Vec<DriveOverride>,
+    /// Whether to skip checking the guest memory against the checksums saved in the snapshot.
+    #[serde(default)]
+    pub skip_verify: bool,
+    /// The memory backing the guest memory of the restored microVM, e.g. huge pages. Only an
+    /// eager resume from the `File` memory backend copies the guest memory in it.
+    #[serde(default)]
+    pub guest_mem_backend: GuestMemoryBackend,
+    /// Identity to give to the restored microVM, so that the clones of a snapshot don't share
+    /// the one saved in it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clone: Option<CloneConfig>,
+    /// Fresh contents of the MMDS data store, to use instead of the ones saved in the snapshot,
+    /// if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmds_data: Option<Value>,
+}
+
+/// Host device to back a network interface with when restoring a snapshot.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkOverride {
+    /// ID of the network interface.
+    pub iface_id: String,
+    /// Name of the TAP device to use instead of the one saved in the snapshot.
+    pub host_dev_name: String,
+}
+
+/// Backing file of a drive to use when restoring a snapshot.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DriveOverride {
+    /// ID of the drive.
+    pub drive_id: String,
+    /// Path to the file to use instead of the one saved in the snapshot.
+    pub path_on_host: String,
+}
+
+/// Identity of a clone of the snapshotted microVM.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CloneConfig {
+    /// ID of the clone, replacing the one Firecracker was started with.
+    pub instance_id: String,
+    /// MAC addresses to give to the network interfaces of the clone. The other interfaces which
+    /// advertise a MAC address to the guest get a random one.
+    #[serde(default)]
+    pub guest_macs: Vec<GuestMacOverride>,
+}
+
+/// MAC address to give to a network interface of a clone.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GuestMacOverride {
+    /// ID of the network interface.
+    pub iface_id: String,
+    /// MAC address to advertise to the guest.
+    pub guest_mac: MacAddr,
 }
 
 /// Stores the configuration used for managing snapshot memory.
@@ -1,13 +1,21 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::convert::TryFrom;
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
+use std::io;
 use std::sync::{Arc, Mutex};
 
+pub use devices::virtio::vsock::{
+    VsockConnectionInfo, VsockDatapath, VsockUdsPermissions, VSOCK_DEV_ID,
+};
 use devices::virtio::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
+use rate_limiter::RateLimiter;
 use serde::{Deserialize, Serialize};
 
+use super::RateLimiterConfig;
+use crate::Error as VmmError;
+
 type MutexVsockUnix = Arc<Mutex<Vsock<VsockUnixBackend>>>;
 
 /// Errors associated with `NetworkInterfaceConfig`.
@@ -17,6 +25,22 @@ pub enum VsockConfigError {
     CreateVsockBackend(VsockUnixBackendError),
     /// Failed to create the vsock device.
     CreateVsockDevice(VsockError),
+    /// Another vsock device uses the given guest CID.
+    GuestCidInUse(u32),
+    /// Another vsock device uses the given unix socket path.
+    UdsPathInUse(String),
+    /// The unix socket path is required by the virtio datapath.
+    MissingUdsPath,
+    /// The vhost datapath isn't supported with the given configuration.
+    VhostUnsupported(&'static str),
+    /// Error while listing the connections of the device.
+    DeviceConnections(VmmError),
+    /// Failed to create a `RateLimiter` object.
+    CreateRateLimiter(io::Error),
+    /// The mode of the unix sockets has bits other than the permission ones.
+    InvalidUdsMode(u32),
+    /// Error while pooling connections of the device.
+    PoolConnections(VmmError),
 }
 
 impl fmt::Display for VsockConfigError {
@@ -27,6 +51,38 @@ impl fmt::Display for VsockConfigError {
                 write!(f, "Cannot create backend for vsock device: {:?}", e)
             }
             CreateVsockDevice(ref e) => write!(f, "Cannot create vsock device: {:?}", e),
+            GuestCidInUse(cid) => write!(
+                f,
+                "The guest CID {} is already in use by another vsock device.",
+                cid
+            ),
+            UdsPathInUse(ref path) => write!(
+                f,
+                "The unix socket path {} is already in use by another vsock device.",
+                path
+            ),
+            MissingUdsPath => write!(
+                f,
+                "The unix socket path is required, unless the vhost datapath is used."
+            ),
+            VhostUnsupported(reason) => write!(
+                f,
+                "The vhost datapath cannot be used for this vsock device: {}",
+                reason
+            ),
+            DeviceConnections(ref e) => {
+                write!(f, "Cannot get the vsock device connections: {}", e)
+            }
+            CreateRateLimiter(ref e) => write!(f, "Cannot create RateLimiter: {}", e),
+            PoolConnections(ref e) => {
+                write!(f, "Cannot pool the vsock device connections: {}", e)
+            }
+            InvalidUdsMode(mode) => write!(
+                f,
+                "Invalid mode for the vsock unix sockets: {:#o}. Only the permission bits can be \
+                 set.",
+                mode
+            ),
         }
     }
 }
@@ -40,12 +96,45 @@ type Result<T> = std::result::Result<T, VsockConfigError>;
 pub struct VsockDeviceConfig {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    /// ID of the vsock device.
+    /// ID of the vsock device. The device configured without an ID is the default one, with
+    /// the `VSOCK_DEV_ID` ID. The other ones are attached along with it.
     pub vsock_id: Option<String>,
     /// A 32-bit Context Identifier (CID) used to identify the guest.
     pub guest_cid: u32,
-    /// Path to local unix socket.
+    /// Path to local unix socket. Left empty with the vhost datapath, since the host then
+    /// reaches the guest through AF_VSOCK sockets.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub uds_path: String,
+    /// Whether packets are handled by Firecracker or by the vhost-vsock kernel module.
+    #[serde(default)]
+    pub backend: VsockDatapath,
+    /// Rate Limiter for the packets delivered to the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_rate_limiter: Option<RateLimiterConfig>,
+    /// Rate Limiter for the packets sent by the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// Mode and ownership of the unix sockets Firecracker binds on the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uds_permissions: Option<VsockUdsPermissions>,
+}
+
+/// Connections to a guest port, initiated ahead of time for the host to take them over later.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockConnectionPoolConfig {
+    /// The guest port the connections go to.
+    pub port: u32,
+    /// The number of connections to initiate.
+    pub count: u32,
+}
+
+impl VsockDeviceConfig {
+    /// Returns the ID of the device to configure.
+    pub fn device_id(&self) -> &str {
+        self.vsock_id.as_deref().unwrap_or(VSOCK_DEV_ID)
+    }
 }
 
 struct VsockAndUnixPath {
@@ -53,13 +142,30 @@ struct VsockAndUnixPath {
     uds_path: String,
 }
 
+impl VsockAndUnixPath {
+    fn id(&self) -> String {
+        self.vsock.lock().expect("Poisoned lock").id().to_string()
+    }
+}
+
 impl From<&VsockAndUnixPath> for VsockDeviceConfig {
     fn from(vsock: &VsockAndUnixPath) -> Self {
         let vsock_lock = vsock.vsock.lock().unwrap();
+        let rx_rl: RateLimiterConfig = vsock_lock.rx_rate_limiter().into();
+        let tx_rl: RateLimiterConfig = vsock_lock.tx_rate_limiter().into();
         VsockDeviceConfig {
-            vsock_id: None,
+            vsock_id: Some(vsock_lock.id().to_string()).filter(|id| id != VSOCK_DEV_ID),
             guest_cid: u32::try_from(vsock_lock.cid()).unwrap(),
             uds_path: vsock.uds_path.clone(),
+            backend: if vsock_lock.uses_vhost() {
+                VsockDatapath::Vhost
+            } else {
+                VsockDatapath::Virtio
+            },
+            rx_rate_limiter: rx_rl.into_option(),
+            tx_rate_limiter: tx_rl.into_option(),
+            uds_permissions: Some(vsock_lock.backend().sock_permissions())
+                .filter(|permissions| *permissions != VsockUdsPermissions::default()),
         }
     }
 }
@@ -67,18 +173,24 @@ impl From<&VsockAndUnixPath> for VsockDeviceConfig {
 /// A builder of Vsock with Unix backend from 'VsockDeviceConfig'.
 #[derive(Default)]
 pub struct VsockBuilder {
-    inner: Option<VsockAndUnixPath>,
+    // The devices, in the order they were first inserted, each with its own ID, guest CID
+    // and unix socket path.
+    inner: Vec<VsockAndUnixPath>,
 }
 
 impl VsockBuilder {
     /// Creates an empty Vsock with Unix backend Store.
     pub fn new() -> Self {
-        Self { inner: None }
+        Self { inner: Vec::new() }
+    }
+
+    fn index_of(&self, vsock_id: &str) -> Option<usize> {
+        self.inner.iter().position(|pair| pair.id() == vsock_id)
     }
 
-    /// Inserts an existing vsock device.
+    /// Inserts an existing vsock device. It replaces the device with the same ID, if any.
     pub fn set_device(&mut self, device: Arc<Mutex<Vsock<VsockUnixBackend>>>) {
-        self.inner = Some(VsockAndUnixPath {
+        let pair = VsockAndUnixPath {
             uds_path: device
                 .lock()
                 .expect("Poisoned lock")
@@ -86,41 +198,141 @@ impl VsockBuilder {
                 .host_sock_path()
                 .to_owned(),
             vsock: device.clone(),
-        });
+        };
+        match self.index_of(&pair.id()) {
+            Some(index) => self.inner[index] = pair,
+            None => self.inner.push(pair),
+        }
     }
 
     /// Inserts a Unix backend Vsock in the store.
-    /// If an entry already exists, it will overwrite it.
+    /// If an entry with the same ID already exists, it will overwrite it.
     pub fn insert(&mut self, cfg: VsockDeviceConfig) -> Result<()> {
+        match cfg.backend {
+            VsockDatapath::Virtio if cfg.uds_path.is_empty() => {
+                return Err(VsockConfigError::MissingUdsPath);
+            }
+            VsockDatapath::Vhost if !cfg.uds_path.is_empty() => {
+                return Err(VsockConfigError::VhostUnsupported(
+                    "connections don't go through a unix socket, uds_path must be left out.",
+                ));
+            }
+            VsockDatapath::Vhost
+                if cfg.rx_rate_limiter.is_some() || cfg.tx_rate_limiter.is_some() =>
+            {
+                return Err(VsockConfigError::VhostUnsupported(
+                    "rate limiters are not supported.",
+                ));
+            }
+            VsockDatapath::Vhost if cfg.uds_permissions.is_some() => {
+                return Err(VsockConfigError::VhostUnsupported(
+                    "there are no unix sockets to set uds_permissions on.",
+                ));
+            }
+            _ => (),
+        }
+        if let Some(mode) = cfg.uds_permissions.and_then(|permissions| permissions.mode) {
+            if mode & !0o777 != 0 {
+                return Err(VsockConfigError::InvalidUdsMode(mode));
+            }
+        }
+
+        let index = self.index_of(cfg.device_id());
+        // The other devices can't share the guest CID or the unix socket.
+        for (i, other) in self.inner.iter().enumerate() {
+            if Some(i) == index {
+                continue;
+            }
+            if other.vsock.lock().expect("Poisoned lock").cid() == u64::from(cfg.guest_cid) {
+                return Err(VsockConfigError::GuestCidInUse(cfg.guest_cid));
+            }
+            if !cfg.uds_path.is_empty() && other.uds_path == cfg.uds_path {
+                return Err(VsockConfigError::UdsPathInUse(cfg.uds_path));
+            }
+        }
+
         // Make sure to drop the old one and remove the socket before creating a new one.
-        if let Some(existing) = self.inner.take() {
-            std::fs::remove_file(existing.uds_path)
-                .map_err(VsockUnixBackendError::UnixBind)
-                .map_err(VsockConfigError::CreateVsockBackend)?;
+        if let Some(index) = index {
+            let existing = self.inner.remove(index);
+            if !existing.uds_path.is_empty() {
+                std::fs::remove_file(&existing.uds_path)
+                    .map_err(VsockUnixBackendError::UnixBind)
+                    .map_err(VsockConfigError::CreateVsockBackend)?;
+            }
         }
-        self.inner = Some(VsockAndUnixPath {
+        let pair = VsockAndUnixPath {
             uds_path: cfg.uds_path.clone(),
             vsock: Arc::new(Mutex::new(Self::create_unixsock_vsock(cfg)?)),
-        });
+        };
+        self.inner.insert(index.unwrap_or(self.inner.len()), pair);
         Ok(())
     }
 
-    /// Provides a reference to the Vsock if present.
-    pub fn get(&self) -> Option<&MutexVsockUnix> {
-        self.inner.as_ref().map(|pair| &pair.vsock)
+    /// Provides a reference to the Vsock with id `vsock_id` if present.
+    pub fn get(&self, vsock_id: &str) -> Option<&MutexVsockUnix> {
+        self.index_of(vsock_id)
+            .map(|index| &self.inner[index].vsock)
+    }
+
+    /// Provides a reference to each of the Vsock devices.
+    pub fn devices(&self) -> impl Iterator<Item = &MutexVsockUnix> {
+        self.inner.iter().map(|pair| &pair.vsock)
     }
 
     /// Creates a Vsock device from a VsockDeviceConfig.
     pub fn create_unixsock_vsock(cfg: VsockDeviceConfig) -> Result<Vsock<VsockUnixBackend>> {
-        let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path)
-            .map_err(VsockConfigError::CreateVsockBackend)?;
+        let id = cfg.device_id().to_string();
+        let cid = u64::from(cfg.guest_cid);
+        let rx_rate_limiter: Option<RateLimiter> = cfg
+            .rx_rate_limiter
+            .map(RateLimiterConfig::try_into)
+            .transpose()
+            .map_err(VsockConfigError::CreateRateLimiter)?;
+        let tx_rate_limiter: Option<RateLimiter> = cfg
+            .tx_rate_limiter
+            .map(RateLimiterConfig::try_into)
+            .transpose()
+            .map_err(VsockConfigError::CreateRateLimiter)?;
+        // With vhost, the packets never reach the backend, which doesn't need a socket.
+        let mut backend = match cfg.backend {
+            VsockDatapath::Virtio => VsockUnixBackend::new(cid, cfg.uds_path),
+            VsockDatapath::Vhost => VsockUnixBackend::new_detached(cid),
+        }
+        .map_err(VsockConfigError::CreateVsockBackend)?;
+        if let Some(permissions) = cfg.uds_permissions {
+            backend
+                .set_sock_permissions(permissions)
+                .map_err(VsockConfigError::CreateVsockBackend)?;
+        }
+
+        let mut vsock =
+            Vsock::new_with_id(id, cid, backend).map_err(VsockConfigError::CreateVsockDevice)?;
+        vsock.set_rate_limiters(
+            rx_rate_limiter.unwrap_or_default(),
+            tx_rate_limiter.unwrap_or_default(),
+        );
 
-        Vsock::new(u64::from(cfg.guest_cid), backend).map_err(VsockConfigError::CreateVsockDevice)
+        if cfg.backend == VsockDatapath::Vhost {
+            vsock
+                .enable_vhost()
+                .map_err(VsockConfigError::CreateVsockDevice)?;
+        }
+        Ok(vsock)
     }
 
-    /// Returns the structure used to configure the vsock device.
+    /// Returns the structure used to configure the default vsock device.
     pub fn config(&self) -> Option<VsockDeviceConfig> {
-        self.inner.as_ref().map(VsockDeviceConfig::from)
+        self.index_of(VSOCK_DEV_ID)
+            .map(|index| VsockDeviceConfig::from(&self.inner[index]))
+    }
+
+    /// Returns the structures used to configure the vsock devices other than the default one.
+    pub fn other_configs(&self) -> Vec<VsockDeviceConfig> {
+        self.inner
+            .iter()
+            .map(VsockDeviceConfig::from)
+            .filter(|cfg| cfg.vsock_id.is_some())
+            .collect()
     }
 }
 
@@ -130,12 +342,17 @@ pub(crate) mod tests {
     use utils::tempfile::TempFile;
 
     use super::*;
+    use crate::vmm_config::TokenBucketConfig;
 
     pub(crate) fn default_config(tmp_sock_file: &TempFile) -> VsockDeviceConfig {
         VsockDeviceConfig {
             vsock_id: None,
             guest_cid: 3,
             uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+            backend: VsockDatapath::Virtio,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            uds_permissions: None,
         }
     }
 
@@ -155,14 +372,83 @@ pub(crate) mod tests {
         let mut vsock_config = default_config(&tmp_sock_file);
 
         store.insert(vsock_config.clone()).unwrap();
-        let vsock = store.get().unwrap();
+        let vsock = store.get(VSOCK_DEV_ID).unwrap();
         assert_eq!(vsock.lock().unwrap().id(), VSOCK_DEV_ID);
 
         let new_cid = vsock_config.guest_cid + 1;
         vsock_config.guest_cid = new_cid;
         store.insert(vsock_config).unwrap();
-        let vsock = store.get().unwrap();
+        let vsock = store.get(VSOCK_DEV_ID).unwrap();
         assert_eq!(vsock.lock().unwrap().cid(), new_cid as u64);
+        assert_eq!(store.devices().count(), 1);
+    }
+
+    #[test]
+    fn test_vsock_insert_multiple() {
+        let mut store = VsockBuilder::new();
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut other_sock_file = TempFile::new().unwrap();
+        other_sock_file.remove().unwrap();
+        let vsock_config = default_config(&tmp_sock_file);
+        store.insert(vsock_config.clone()).unwrap();
+
+        // Another device can't share the guest CID or the socket of the first one.
+        let mut other_config = VsockDeviceConfig {
+            vsock_id: Some("data".to_string()),
+            ..vsock_config.clone()
+        };
+        assert_eq!(
+            store.insert(other_config.clone()).unwrap_err().to_string(),
+            VsockConfigError::GuestCidInUse(3).to_string()
+        );
+        other_config.guest_cid = 4;
+        assert_eq!(
+            store.insert(other_config.clone()).unwrap_err().to_string(),
+            VsockConfigError::UdsPathInUse(vsock_config.uds_path.clone()).to_string()
+        );
+        other_config.uds_path = other_sock_file.as_path().to_str().unwrap().to_string();
+        store.insert(other_config.clone()).unwrap();
+
+        assert_eq!(store.devices().count(), 2);
+        assert_eq!(store.get(VSOCK_DEV_ID).unwrap().lock().unwrap().cid(), 3);
+        let vsock = store.get("data").unwrap();
+        assert_eq!(vsock.lock().unwrap().id(), "data");
+        assert_eq!(vsock.lock().unwrap().cid(), 4);
+        assert!(store.get("missing").is_none());
+
+        assert_eq!(store.config().unwrap(), vsock_config);
+        assert_eq!(store.other_configs(), vec![other_config]);
+    }
+
+    #[test]
+    fn test_vsock_datapath_validation() {
+        // The userspace datapath is used when not specified.
+        let json = r#"{"guest_cid": 3, "uds_path": "/tmp/vsock.sock"}"#;
+        let cfg: VsockDeviceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.backend, VsockDatapath::Virtio);
+        let json = r#"{"guest_cid": 3, "backend": "vhost"}"#;
+        let cfg: VsockDeviceConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(cfg.backend, VsockDatapath::Vhost);
+        assert!(cfg.uds_path.is_empty());
+
+        let mut store = VsockBuilder::new();
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.backend = VsockDatapath::Vhost;
+        assert!(matches!(
+            store.insert(vsock_config.clone()),
+            Err(VsockConfigError::VhostUnsupported(_))
+        ));
+
+        vsock_config.backend = VsockDatapath::Virtio;
+        vsock_config.uds_path = String::new();
+        assert!(matches!(
+            store.insert(vsock_config),
+            Err(VsockConfigError::MissingUdsPath)
+        ));
+        assert_eq!(store.devices().count(), 0);
     }
 
     #[test]
@@ -178,6 +464,75 @@ pub(crate) mod tests {
         assert_eq!(config.unwrap(), vsock_config);
     }
 
+    #[test]
+    fn test_vsock_rate_limiters() {
+        let mut vsock_builder = VsockBuilder::new();
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        let bucket = Some(TokenBucketConfig {
+            size: 1000,
+            one_time_burst: None,
+            refill_time: 100,
+        });
+        vsock_config.rx_rate_limiter = Some(RateLimiterConfig {
+            bandwidth: bucket,
+            ops: None,
+            pps: None,
+        });
+        vsock_config.tx_rate_limiter = Some(RateLimiterConfig {
+            bandwidth: None,
+            ops: bucket,
+            pps: None,
+        });
+        vsock_builder.insert(vsock_config.clone()).unwrap();
+        assert_eq!(vsock_builder.config().unwrap(), vsock_config);
+
+        vsock_config.uds_path = String::new();
+        vsock_config.backend = VsockDatapath::Vhost;
+        assert!(matches!(
+            vsock_builder.insert(vsock_config),
+            Err(VsockConfigError::VhostUnsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_vsock_uds_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut vsock_builder = VsockBuilder::new();
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+
+        vsock_config.uds_permissions = Some(VsockUdsPermissions {
+            mode: Some(0o1666),
+            uid: None,
+            gid: None,
+        });
+        assert!(matches!(
+            vsock_builder.insert(vsock_config.clone()),
+            Err(VsockConfigError::InvalidUdsMode(0o1666))
+        ));
+
+        vsock_config.uds_permissions = Some(VsockUdsPermissions {
+            mode: Some(0o666),
+            uid: None,
+            gid: None,
+        });
+        vsock_builder.insert(vsock_config.clone()).unwrap();
+        assert_eq!(vsock_builder.config().unwrap(), vsock_config);
+        let metadata = std::fs::metadata(&vsock_config.uds_path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o666);
+
+        vsock_config.uds_path = String::new();
+        vsock_config.backend = VsockDatapath::Vhost;
+        assert!(matches!(
+            vsock_builder.insert(vsock_config),
+            Err(VsockConfigError::VhostUnsupported(_))
+        ));
+    }
+
     #[test]
     fn test_error_messages() {
         use std::io;
@@ -192,6 +547,30 @@ pub(crate) mod tests {
             io::Error::from_raw_os_error(0),
         ));
         let _ = format!("{}{:?}", err, err);
+
+        let err = GuestCidInUse(3);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UdsPathInUse(String::from("/tmp/vsock.sock"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = MissingUdsPath;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = VhostUnsupported("reason");
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DeviceConnections(crate::Error::VcpuExit);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = CreateRateLimiter(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidUdsMode(0o4755);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = PoolConnections(crate::Error::VcpuExit);
+        let _ = format!("{}{:?}", err, err);
     }
 
     #[test]
@@ -207,9 +586,9 @@ pub(crate) mod tests {
         .unwrap();
 
         vsock_builder.set_device(Arc::new(Mutex::new(vsock)));
-        assert!(vsock_builder.inner.is_some());
+        assert_eq!(vsock_builder.inner.len(), 1);
         assert_eq!(
-            vsock_builder.inner.unwrap().uds_path,
+            vsock_builder.inner[0].uds_path,
             tmp_sock_file.as_path().to_str().unwrap().to_string()
         )
     }
@@ -17,13 +17,14 @@ use std::{io, result, thread};
 use kvm_bindings::{KVM_SYSTEM_EVENT_RESET, KVM_SYSTEM_EVENT_SHUTDOWN};
 use kvm_ioctls::VcpuExit;
 use libc::{c_int, c_void, siginfo_t};
-use logger::{error, info, IncMetric, METRICS};
+use logger::{error, info, IncMetric, ThreadCategory, METRICS};
 use seccompiler::{BpfProgram, BpfProgramRef};
 use utils::errno;
 use utils::eventfd::EventFd;
 use utils::signal::{register_signal_handler, sigrtmin, Killable};
 use utils::sm::StateMachine;
 
+use crate::health::HEALTH;
 use crate::vmm_config::machine_config::CpuFeaturesTemplate;
 use crate::vstate::vm::Vm;
 use crate::FcExitCode;
@@ -237,6 +238,9 @@ impl Vcpu {
         let vcpu_thread = thread::Builder::new()
             .name(format!("fc_vcpu {}", self.kvm_vcpu.index))
             .spawn(move || {
+                METRICS
+                    .cpu_usage
+                    .register_current_thread(ThreadCategory::Vcpu(self.kvm_vcpu.index));
                 let filter = &*seccomp_filter;
                 self.init_thread_local_data()
                     .expect("Cannot cleanly initialize vcpu TLS.");
@@ -324,6 +328,12 @@ impl Vcpu {
                     )))
                     .expect("failed to send save not allowed status");
             }
+            Ok(VcpuEvent::UpdateMmioBus(mmio_bus)) => {
+                self.set_mmio_bus(mmio_bus);
+                self.response_sender
+                    .send(VcpuResponse::UpdatedMmioBus)
+                    .expect("failed to send mmio bus update status");
+            }
             Ok(VcpuEvent::Finish) => return StateMachine::finish(),
             // Unhandled exit of the other end.
             Err(TryRecvError::Disconnected) => {
@@ -388,6 +398,13 @@ impl Vcpu {
 
                 StateMachine::next(Self::paused)
             }
+            Ok(VcpuEvent::UpdateMmioBus(mmio_bus)) => {
+                self.set_mmio_bus(mmio_bus);
+                self.response_sender
+                    .send(VcpuResponse::UpdatedMmioBus)
+                    .expect("vcpu channel unexpectedly closed");
+                StateMachine::next(Self::paused)
+            }
             Ok(VcpuEvent::Finish) => StateMachine::finish(),
             // Unhandled exit of the other end.
             Err(_) => {
@@ -416,6 +433,9 @@ impl Vcpu {
         // Vmm initiated teardown starts from `pub fn Vmm::stop()` (step 4).
         // Once `vmm.shutdown_exit_code` becomes `Some(exit_code)`, it is the upper layer's
         // responsibility to break main event loop and propagate the exit code value.
+        if exit_code != FcExitCode::Ok {
+            HEALTH.report_vcpu_failure();
+        }
         // Signal Vmm of Vcpu exit.
         if let Err(e) = self.exit_evt.write(1) {
             METRICS.vcpu.failures.inc();
@@ -559,6 +579,8 @@ pub enum VcpuEvent {
     RestoreState(Box<VcpuState>),
     /// Event to save the state of a paused Vcpu.
     SaveState,
+    /// Event to replace the MMIO bus of the Vcpu, after devices were attached or detached.
+    UpdateMmioBus(devices::Bus),
 }
 
 /// List of responses that the Vcpu reports.
@@ -577,6 +599,8 @@ pub enum VcpuResponse {
     RestoredState,
     /// Vcpu state is saved.
     SavedState(Box<VcpuState>),
+    /// Vcpu MMIO bus is updated.
+    UpdatedMmioBus,
 }
 
 /// Wrapper over Vcpu that hides the underlying interactions with the Vcpu thread.
@@ -803,14 +827,15 @@ mod tests {
             // Guard match with no wildcard to make sure we catch new enum variants.
             match self {
                 Paused | Resumed | Exited(_) => (),
-                Error(_) | NotAllowed(_) | RestoredState | SavedState(_) => (),
+                Error(_) | NotAllowed(_) | RestoredState | SavedState(_) | UpdatedMmioBus => (),
             };
             match (self, other) {
                 (Paused, Paused) | (Resumed, Resumed) => true,
                 (Exited(code), Exited(other_code)) => code == other_code,
                 (NotAllowed(_), NotAllowed(_))
                 | (RestoredState, RestoredState)
-                | (SavedState(_), SavedState(_)) => true,
+                | (SavedState(_), SavedState(_))
+                | (UpdatedMmioBus, UpdatedMmioBus) => true,
                 (Error(ref err), Error(ref other_err)) => {
                     format!("{:?}", err) == format!("{:?}", other_err)
                 }
@@ -828,6 +853,7 @@ mod tests {
                 Exited(code) => write!(f, "VcpuResponse::Exited({:?})", code),
                 RestoredState => write!(f, "VcpuResponse::RestoredState"),
                 SavedState(_) => write!(f, "VcpuResponse::SavedState"),
+                UpdatedMmioBus => write!(f, "VcpuResponse::UpdatedMmioBus"),
                 Error(ref err) => write!(f, "VcpuResponse::Error({:?})", err),
                 NotAllowed(ref reason) => write!(f, "VcpuResponse::NotAllowed({})", reason),
             }
@@ -1102,6 +1128,28 @@ mod tests {
         vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
     }
 
+    #[test]
+    fn test_vcpu_update_mmio_bus_event() {
+        let (vcpu_handle, _vcpu_exit_evt) = vcpu_configured_for_boot();
+
+        // The bus can be replaced while the vcpu is paused...
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::UpdateMmioBus(devices::Bus::new()),
+            VcpuResponse::UpdatedMmioBus,
+        );
+
+        // ...and while it's running.
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::UpdateMmioBus(devices::Bus::new()),
+            VcpuResponse::UpdatedMmioBus,
+        );
+
+        vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
+    }
+
     #[test]
     fn test_vcpu_rtsig_offset() {
         assert!(validate_signal_num(sigrtmin() + VCPU_RTSIG_OFFSET).is_ok());
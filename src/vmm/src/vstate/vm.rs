@@ -18,11 +18,11 @@ use kvm_bindings::{
     KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE,
     KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
 };
-use kvm_bindings::{kvm_userspace_memory_region, KVM_MEM_LOG_DIRTY_PAGES};
+use kvm_bindings::{kvm_userspace_memory_region, KVM_MEM_LOG_DIRTY_PAGES, KVM_MEM_READONLY};
 use kvm_ioctls::{Kvm, VmFd};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
-use vm_memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug)]
@@ -318,6 +318,29 @@ impl Vm {
             .map_err(Error::SetUserMemoryRegion)?;
         Ok(())
     }
+
+    /// Maps the `size` bytes at `host_addr` in the guest physical memory at `guest_addr`, using
+    /// the KVM memory slot `slot`. These regions aren't part of the guest memory.
+    pub(crate) fn set_device_memory_region(
+        &self,
+        slot: u32,
+        guest_addr: GuestAddress,
+        size: u64,
+        host_addr: u64,
+        read_only: bool,
+    ) -> Result<()> {
+        let memory_region = kvm_userspace_memory_region {
+            slot,
+            guest_phys_addr: guest_addr.raw_value(),
+            memory_size: size,
+            userspace_addr: host_addr,
+            flags: if read_only { KVM_MEM_READONLY } else { 0 },
+        };
+
+        // Safe because the fd is a valid KVM file descriptor and the caller keeps the host
+        // mapping alive for as long as the VM.
+        unsafe { self.fd.set_user_memory_region(memory_region) }.map_err(Error::SetUserMemoryRegion)
+    }
 }
 
 #[cfg(target_arch = "x86_64")]
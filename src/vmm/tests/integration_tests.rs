@@ -120,6 +120,31 @@ fn test_dirty_bitmap_success() {
     vmm.lock().unwrap().stop(FcExitCode::Ok);
 }
 
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_sample_dirty_rate() {
+    use vm_memory::{Bitmap, GuestMemory, GuestMemoryRegion};
+
+    // The vmm will start with dirty page tracking = ON.
+    let (vmm, _) = dirty_tracking_vmm(Some(NOISY_KERNEL_IMAGE));
+
+    // Let it churn for a while and dirty some pages...
+    thread::sleep(Duration::from_millis(100));
+    let dirty_rate = vmm.lock().unwrap().sample_dirty_rate().unwrap();
+    assert!(dirty_rate.dirty_pages > 0);
+    assert!(dirty_rate.interval_ms >= 100);
+    assert!(dirty_rate.dirty_pages_per_sec > 0);
+
+    // The sampled pages are kept for the next diff snapshot.
+    let page_size = utils::get_page_size().unwrap();
+    assert!(vmm.lock().unwrap().guest_memory().iter().any(|region| {
+        (0..region.len() as usize)
+            .step_by(page_size)
+            .any(|offset| region.bitmap().dirty_at(offset))
+    }));
+    vmm.lock().unwrap().stop(FcExitCode::Ok);
+}
+
 #[test]
 fn test_disallow_snapshots_without_pausing() {
     let (vmm, _) = default_vmm(Some(NOISY_KERNEL_IMAGE));
@@ -159,7 +184,12 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
     let snapshot_params = CreateSnapshotParams {
         snapshot_type,
         snapshot_path: snapshot_file.as_path().to_path_buf(),
+        snapshot_fd: None,
         mem_file_path: memory_file.as_path().to_path_buf(),
+        mem_file_fd: None,
+        compression: None,
+        mem_writer_threads: None,
+        exclude_mmds: false,
         version: Some(String::from("0.24.0")),
     };
 
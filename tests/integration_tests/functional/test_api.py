@@ -884,14 +884,15 @@ def _drive_patch(test_microvm):
     # Patches without mandatory fields are not allowed.
     response = test_microvm.drive.patch(drive_id="scratch")
     assert test_microvm.api_session.is_status_bad_request(response.status_code)
-    assert "at least one property to patch: path_on_host, rate_limiter" in response.text
-
-    # Cannot patch drive permissions post boot.
-    response = test_microvm.drive.patch(
-        drive_id="scratch", path_on_host="foo.bar", is_read_only=True
+    assert (
+        "at least one property to patch: path_on_host, size_bytes, is_read_only, "
+        "rate_limiter" in response.text
     )
+
+    # The read-only mode of the root device can't be changed.
+    response = test_microvm.drive.patch(drive_id="rootfs", is_read_only=False)
     assert test_microvm.api_session.is_status_bad_request(response.status_code)
-    assert "unknown field `is_read_only`" in response.text
+    assert "UnsupportedReadOnlyToggle" in response.text
 
     # Cannot patch io_engine post boot.
     response = test_microvm.drive.patch(
@@ -967,6 +968,7 @@ def _drive_patch(test_microvm):
             "is_read_only": False,
             "cache_type": "Unsafe",
             "io_engine": "Sync",
+            "format": "raw",
             "rate_limiter": None,
         },
         {
@@ -977,6 +979,7 @@ def _drive_patch(test_microvm):
             "is_read_only": False,
             "cache_type": "Unsafe",
             "io_engine": "Async" if is_io_uring_supported() else "Sync",
+            "format": "raw",
             "rate_limiter": {
                 "bandwidth": {"size": 5000, "one_time_burst": None, "refill_time": 100},
                 "ops": {"size": 500, "one_time_burst": None, "refill_time": 100},
@@ -1222,6 +1225,7 @@ def test_get_full_config_after_restoring_snapshot(bin_cloner_path):
             "cache_type": "Unsafe",
             "rate_limiter": None,
             "io_engine": "Sync",
+            "format": "raw",
         }
     ]
 
@@ -1341,6 +1345,7 @@ def test_get_full_config(test_microvm_with_api):
             "cache_type": "Unsafe",
             "rate_limiter": None,
             "io_engine": "Sync",
+            "format": "raw",
         }
     ]
 
@@ -122,6 +122,35 @@ def _test_rss_memory_lower(test_microvm):
     assert balloon_rss - init_rss <= 15000
 
 
+def test_hotplug_balloon(test_microvm_with_api, network_config):
+    """
+    Test attaching a balloon device to a running microVM.
+
+    The default seccomp filters are installed, so this also checks that the
+    VMM thread is allowed to set the device up.
+
+    @type: functional
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+    test_microvm.basic_config()
+    _tap, _, _ = test_microvm.ssh_network_config(network_config, "1")
+    test_microvm.start()
+
+    response = test_microvm.balloon.put(
+        amount_mib=0, deflate_on_oom=True, stats_polling_interval_s=1
+    )
+    assert test_microvm.api_session.is_status_no_content(response.status_code)
+
+    # Firecracker is still alive, and serving both the API and the guest.
+    response = test_microvm.balloon.get()
+    assert test_microvm.api_session.is_status_ok(response.status_code)
+    assert response.json()["stats_polling_interval_s"] == 1
+    ssh_connection = net_tools.SSHConnection(test_microvm.ssh_config)
+    exit_code, _, _ = ssh_connection.execute_command("true")
+    assert exit_code == 0
+
+
 # pylint: disable=C0103
 def test_rss_memory_lower(test_microvm_with_api, network_config):
     """
@@ -66,6 +66,33 @@ def test_rescan_file(test_microvm_with_api, network_config):
     _check_block_size(ssh_connection, "/dev/vdb", fs.size())
 
 
+def test_hotplug_drive(test_microvm_with_api, network_config):
+    """
+    Verify that a drive can be attached to a running microVM.
+
+    The default seccomp filters are installed, so this also checks that the
+    VMM thread is allowed to set the device up.
+
+    @type: functional
+    """
+    test_microvm = test_microvm_with_api
+    test_microvm.spawn()
+
+    test_microvm.basic_config()
+    _tap, _, _ = test_microvm.ssh_network_config(network_config, "1")
+    test_microvm.start()
+
+    fs = drive_tools.FilesystemFile(os.path.join(test_microvm.fsfiles, "scratch"))
+    test_microvm.add_drive("scratch", fs.path)
+
+    # Firecracker is still alive, and serving both the API and the guest.
+    response = test_microvm.machine_cfg.get()
+    assert test_microvm.api_session.is_status_ok(response.status_code)
+    ssh_connection = net_tools.SSHConnection(test_microvm.ssh_config)
+    exit_code, _, _ = ssh_connection.execute_command("true")
+    assert exit_code == 0
+
+
 def test_device_ordering(test_microvm_with_api, network_config):
     """
     Verify device ordering.
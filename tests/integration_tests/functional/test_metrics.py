@@ -17,7 +17,7 @@ def test_flush_metrics(test_microvm_with_api):
     """
     microvm = test_microvm_with_api
     microvm.spawn()
-    microvm.basic_config()
+    microvm.basic_config(vcpu_count=2)
 
     # Configure metrics system.
     metrics_fifo_path = os.path.join(microvm.path, "metrics_fifo")
@@ -37,14 +37,19 @@ def test_flush_metrics(test_microvm_with_api):
         "api_server",
         "balloon",
         "block",
+        "block_latencies_us",
+        "cpu_usage",
+        "delete_api_requests",
         "deprecated_api",
         "get_api_requests",
         "i8042",
         "latencies_us",
         "logger",
+        "memory_hotplug",
         "mmds",
         "net",
         "patch_api_requests",
+        "pmem",
         "put_api_requests",
         "seccomp",
         "vcpu",
@@ -59,6 +64,19 @@ def test_flush_metrics(test_microvm_with_api):
 
     assert set(metrics.keys()) == set(exp_keys)
 
+    cpu_usage = metrics["cpu_usage"]
+    assert set(cpu_usage.keys()) == {
+        "api_us",
+        "vmm_us",
+        "vcpu_us",
+        "per_vcpu_us",
+        "net_worker_us",
+    }
+    assert len(cpu_usage["per_vcpu_us"]) == 2
+    assert cpu_usage["vcpu_us"] == sum(cpu_usage["per_vcpu_us"])
+    assert cpu_usage["api_us"] > 0
+    assert cpu_usage["vmm_us"] > 0
+
     utc_time = datetime.datetime.now(datetime.timezone.utc)
     utc_timestamp_ms = math.floor(utc_time.timestamp() * 1000)
 
@@ -5,6 +5,7 @@
 import os
 import platform
 import json
+import stat
 import tempfile
 import time
 import psutil
@@ -224,6 +225,102 @@ def test_failing_filter(test_microvm_with_api):
     assert not psutil.pid_exists(test_microvm.jailer_clone_pid)
 
 
+def test_log_level(test_microvm_with_api):
+    """
+    Test --seccomp-level log, with a filter denying some needed syscalls.
+
+    @type: security
+    """
+    test_microvm = test_microvm_with_api
+
+    _custom_filter_setup(
+        test_microvm,
+        """{
+        "Vmm": {
+            "default_action": "allow",
+            "filter_action": "trap",
+            "filter": []
+        },
+        "Api": {
+            "default_action": "allow",
+            "filter_action": "trap",
+            "filter": []
+        },
+        "Vcpu": {
+            "default_action": "allow",
+            "filter_action": "trap",
+            "filter": [
+                {
+                    "syscall": "ioctl"
+                }
+            ]
+        }
+    }""".encode(
+            "utf-8"
+        ),
+    )
+    test_microvm.jailer.extra_args.update({"seccomp-level": "log"})
+
+    # The violations are counted from the kernel log, which has to be
+    # readable from the jail.
+    jail_dev = os.path.join(test_microvm.jailer.chroot_path(), "dev")
+    os.makedirs(jail_dev, exist_ok=True)
+    os.mknod(os.path.join(jail_dev, "kmsg"), stat.S_IFCHR | 0o444, os.makedev(1, 11))
+    dmesg_restrict_path = "/proc/sys/kernel/dmesg_restrict"
+    with open(dmesg_restrict_path, encoding="utf-8") as dmesg_restrict:
+        dmesg_restrict_value = dmesg_restrict.read()
+    with open(dmesg_restrict_path, "w", encoding="utf-8") as dmesg_restrict:
+        dmesg_restrict.write("0")
+
+    try:
+        _check_log_level(test_microvm)
+    finally:
+        with open(dmesg_restrict_path, "w", encoding="utf-8") as dmesg_restrict:
+            dmesg_restrict.write(dmesg_restrict_value)
+
+
+def _check_log_level(test_microvm):
+    test_microvm.spawn()
+
+    test_microvm.basic_config(vcpu_count=1)
+
+    metrics_fifo_path = os.path.join(test_microvm.path, "metrics_fifo")
+    metrics_fifo = log_tools.Fifo(metrics_fifo_path)
+    response = test_microvm.metrics.put(
+        metrics_path=test_microvm.create_jailed_resource(metrics_fifo.path)
+    )
+    assert test_microvm.api_session.is_status_no_content(response.status_code)
+
+    # The vcpu ioctls are let through, so the VM boots.
+    test_microvm.start()
+
+    ioctl_num = 16 if platform.machine() == "x86_64" else 29
+    # The kernel logs the syscalls let through by the filters.
+    _, stdout, _ = utils.run_cmd("dmesg")
+    assert any(
+        "type=1326" in line
+        and "pid={} ".format(test_microvm.jailer_clone_pid) in line
+        and "syscall={} ".format(ioctl_num) in line
+        and "code=0x7ffc0000" in line
+        for line in stdout.splitlines()
+    )
+
+    test_microvm.check_log_message(
+        "The seccomp filters would have blocked syscall {}.".format(ioctl_num)
+    )
+
+    num_violations = 0
+    violations_read_fails = 0
+    for line in test_microvm.get_all_metrics(metrics_fifo):
+        seccomp_metrics = json.loads(line)["seccomp"]
+        num_violations += seccomp_metrics["num_violations"]
+        violations_read_fails += seccomp_metrics["violations_read_fails"]
+    assert num_violations >= 1
+    assert violations_read_fails == 0
+
+    assert psutil.pid_exists(test_microvm.jailer_clone_pid)
+
+
 @pytest.mark.parametrize("vm_config_file", ["framework/vm_config.json"])
 def test_invalid_bpf(test_microvm_with_api, vm_config_file):
     """